@@ -0,0 +1,680 @@
+// $ soak --soak soak/rpc_bench.toml --duration 600
+//
+// Runs a workload on a single machine against a running daemon for a long time, while injecting
+// faults per a schedule: killing the clients, attaching and detaching addons, upgrading the
+// modules, and taking the link down and up. Along the way, it checks that:
+//
+// - the daemon neither exits nor panics;
+// - the subscriptions of a killed client are torn down, and once all the workers are stopped,
+//   the daemon maps no more shared memory regions than before they started, i.e., the memory
+//   regions of the clients are not leaked;
+// - a worker that reports progress keeps reporting it, i.e., its completions are not stuck.
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde::Deserialize;
+use structopt::StructOpt;
+
+// read from config.toml, as the launcher
+#[derive(Debug, Clone, Deserialize)]
+struct Config {
+    workdir: PathBuf,
+    env: toml::Value,
+}
+
+const fn default_stall_secs() -> u64 {
+    10
+}
+
+const fn default_teardown_secs() -> u64 {
+    10
+}
+
+const fn default_check_interval_secs() -> u64 {
+    1
+}
+
+const fn default_kill_signal() -> i32 {
+    9
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DaemonSpec {
+    /// The name of the process of the daemon, as in /proc/<pid>/comm.
+    comm: String,
+    /// The log of the daemon, scanned for panics.
+    log: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WorkerSpec {
+    name: String,
+    bin: String,
+    args: String,
+    #[serde(default)]
+    start_delay_secs: u64,
+    /// Start the worker again whenever it exits.
+    #[serde(default)]
+    restart: bool,
+    /// The worker prints a line at least every `stall_secs` while its requests complete.
+    #[serde(default)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum FaultKind {
+    /// Sends a signal to a worker, SIGKILL by default.
+    Kill {
+        worker: String,
+        #[serde(default = "default_kill_signal")]
+        signal: i32,
+    },
+    /// Attaches an addon to the subscriptions of a worker, and detaches it after a while. The
+    /// configs are those of addonctl.
+    Addon {
+        worker: String,
+        attach: PathBuf,
+        detach: PathBuf,
+        attached_secs: u64,
+    },
+    /// Upgrades the modules or the addons. The config is that of upgrade.
+    Upgrade { config: PathBuf },
+    /// Takes the link down, and up again after a while, e.g., with `ip link` or `tc netem`.
+    Flap {
+        down: String,
+        up: String,
+        down_secs: u64,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FaultSpec {
+    at_secs: u64,
+    every_secs: Option<u64>,
+    #[serde(flatten)]
+    kind: FaultKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Soak {
+    name: String,
+    description: String,
+    duration_secs: u64,
+    /// A worker that reports progress and prints nothing for this long is stuck.
+    #[serde(default = "default_stall_secs")]
+    stall_secs: u64,
+    /// How long the daemon has to tear down the subscriptions of a killed worker.
+    #[serde(default = "default_teardown_secs")]
+    teardown_secs: u64,
+    #[serde(default = "default_check_interval_secs")]
+    check_interval_secs: u64,
+    /// The directory of the phoenixctl binaries, relative to the workdir.
+    ctl_dir: PathBuf,
+    daemon: DaemonSpec,
+    worker: Vec<WorkerSpec>,
+    #[serde(default)]
+    fault: Vec<FaultSpec>,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "soak",
+    about = "Soak test of the daemon under a schedule of faults."
+)]
+struct Opt {
+    /// The soak test to run
+    #[structopt(short, long)]
+    soak: PathBuf,
+
+    /// configfile
+    #[structopt(short, long, default_value = "config.toml")]
+    configfile: PathBuf,
+
+    /// Override the duration of the soak test, in seconds
+    #[structopt(long)]
+    duration: Option<u64>,
+
+    /// Run the debug builds of the workers
+    #[structopt(long)]
+    debug: bool,
+
+    /// Output directory of log files
+    #[structopt(short, long)]
+    output_dir: Option<PathBuf>,
+}
+
+fn read_toml<T: serde::de::DeserializeOwned, P: AsRef<Path>>(path: P) -> anyhow::Result<T> {
+    let content = fs::read_to_string(path.as_ref())
+        .with_context(|| format!("reading {:?}", path.as_ref()))?;
+    Ok(toml::from_str(&content)?)
+}
+
+struct Worker {
+    spec: WorkerSpec,
+    child: Option<Child>,
+    /// When the worker last printed a line, or was started.
+    last_output: Arc<Mutex<Instant>>,
+}
+
+/// The pid of the process named `comm`.
+fn find_process(comm: &str) -> anyhow::Result<Pid> {
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        if let Ok(name) = fs::read_to_string(entry.path().join("comm")) {
+            if name.trim_end() == comm {
+                return Ok(Pid::from_raw(pid));
+            }
+        }
+    }
+    bail!("no process named {:?}", comm)
+}
+
+/// The number of shared memory regions mapped by `pid`.
+fn count_memfd_mappings(pid: Pid) -> anyhow::Result<usize> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    Ok(maps.lines().filter(|l| l.contains("/memfd:")).count())
+}
+
+/// Copies the lines of a worker to `sink` and the terminal, and records when it last printed.
+fn forward_output<R: Read + Send + 'static>(
+    name: String,
+    r: R,
+    mut sink: Option<fs::File>,
+    last_output: Arc<Mutex<Instant>>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(r).lines().map_while(Result::ok) {
+            *last_output.lock().unwrap() = Instant::now();
+            println!("[{}] {}", name, line);
+            if let Some(sink) = sink.as_mut() {
+                let _ = writeln!(sink, "{}", line);
+            }
+        }
+    });
+}
+
+#[derive(Debug)]
+enum Pending {
+    StartWorker(usize),
+    Detach { worker: String, config: PathBuf },
+    LinkUp(String),
+}
+
+struct Runner {
+    soak: Soak,
+    workdir: PathBuf,
+    envs: Vec<(String, String)>,
+    output_dir: Option<PathBuf>,
+    debug: bool,
+    workers: Vec<Worker>,
+    daemon: Pid,
+    /// The position in the daemon log scanned so far.
+    daemon_log_pos: u64,
+    baseline_mappings: usize,
+    /// The pids of the killed workers, with the deadline for their subscriptions to be gone.
+    killed: Vec<(Pid, Instant)>,
+    pending: Vec<(Instant, Pending)>,
+    violations: Vec<String>,
+    start: Instant,
+}
+
+impl Runner {
+    fn violation(&mut self, what: String) {
+        log::error!("invariant violated: {}", what);
+        self.violations
+            .push(format!("[{:?}] {}", self.start.elapsed(), what));
+    }
+
+    fn command(&self, program: PathBuf) -> Command {
+        let mut cmd = Command::new(program);
+        cmd.current_dir(&self.workdir)
+            .envs(self.envs.iter().cloned());
+        cmd
+    }
+
+    fn start_worker(&mut self, i: usize) -> anyhow::Result<()> {
+        let profile = if self.debug { "debug" } else { "release" };
+        let spec = &self.workers[i].spec;
+        let mut cmd = self.command(Path::new("target").join(profile).join(&spec.bin));
+        cmd.args(spec.args.split_whitespace())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = cmd
+            .spawn()
+            .with_context(|| format!("starting worker {}", spec.name))?;
+        log::info!("worker {} started, pid: {}", spec.name, child.id());
+
+        let sink = |ext: &str| -> Option<fs::File> {
+            let dir = self.output_dir.as_ref()?;
+            let path = dir.join(format!("{}.{}", spec.name, ext));
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .ok()
+        };
+        let worker = &self.workers[i];
+        *worker.last_output.lock().unwrap() = Instant::now();
+        forward_output(
+            spec.name.clone(),
+            child.stdout.take().unwrap(),
+            sink("stdout"),
+            Arc::clone(&worker.last_output),
+        );
+        forward_output(
+            spec.name.clone(),
+            child.stderr.take().unwrap(),
+            sink("stderr"),
+            Arc::clone(&worker.last_output),
+        );
+        self.workers[i].child = Some(child);
+        Ok(())
+    }
+
+    fn worker_index(&self, name: &str) -> anyhow::Result<usize> {
+        self.workers
+            .iter()
+            .position(|w| w.spec.name == name)
+            .ok_or_else(|| anyhow!("no worker named {:?}", name))
+    }
+
+    fn worker_pid(&self, name: &str) -> anyhow::Result<Pid> {
+        let i = self.worker_index(name)?;
+        let child = self.workers[i]
+            .child
+            .as_ref()
+            .ok_or_else(|| anyhow!("worker {} is not running", name))?;
+        Ok(Pid::from_raw(child.id() as _))
+    }
+
+    /// Runs a phoenixctl binary, and fails if it does.
+    fn run_ctl(&self, bin: &str, args: &[&str]) -> anyhow::Result<Vec<u8>> {
+        let output = self
+            .command(self.soak.ctl_dir.join(bin))
+            .args(args)
+            .output()
+            .with_context(|| format!("running {}", bin))?;
+        if !output.status.success() {
+            bail!(
+                "{} {:?} failed: {}",
+                bin,
+                args,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(output.stdout)
+    }
+
+    /// The (pid, sid) of every subscription, from list.
+    fn list_subscriptions(&self) -> anyhow::Result<Vec<(i64, u64)>> {
+        let dump = std::env::temp_dir().join(format!("soak-list-{}.json", std::process::id()));
+        self.run_ctl("list", &["--dump", dump.to_str().unwrap()])?;
+        let subscriptions: Vec<serde_json::Value> =
+            serde_json::from_str(&fs::read_to_string(&dump)?)?;
+        let _ = fs::remove_file(&dump);
+        subscriptions
+            .iter()
+            .map(|s| {
+                let pid = s["pid"]
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("no pid in {}", s))?;
+                let sid = s["sid"]
+                    .as_u64()
+                    .ok_or_else(|| anyhow!("no sid in {}", s))?;
+                Ok((pid, sid))
+            })
+            .collect()
+    }
+
+    fn addonctl(&self, worker: &str, config: &Path) -> anyhow::Result<()> {
+        let pid = self.worker_pid(worker)?;
+        let sids: Vec<u64> = self
+            .list_subscriptions()?
+            .into_iter()
+            .filter(|(p, _)| *p == pid.as_raw() as i64)
+            .map(|(_, sid)| sid)
+            .collect();
+        if sids.is_empty() {
+            bail!("worker {} has no subscription", worker);
+        }
+        let config = config.to_str().unwrap();
+        let pid = pid.to_string();
+        for sid in sids {
+            let sid = sid.to_string();
+            self.run_ctl(
+                "addonctl",
+                &["--config", config, "--pid", &pid, "--sid", &sid, "--wait"],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn shell(&self, script: &str) -> anyhow::Result<()> {
+        let status = self
+            .command(PathBuf::from("sh"))
+            .arg("-c")
+            .arg(script)
+            .status()?;
+        if !status.success() {
+            bail!("{:?} failed: {}", script, status);
+        }
+        Ok(())
+    }
+
+    /// Injects a fault. A failure to inject it is not a violation, e.g., a worker may be
+    /// restarting, but it is logged.
+    fn inject(&mut self, fault: &FaultKind) -> anyhow::Result<()> {
+        let now = Instant::now();
+        log::info!("injecting {:?}", fault);
+        match fault {
+            FaultKind::Kill { worker, signal } => {
+                let pid = self.worker_pid(worker)?;
+                signal::kill(pid, Signal::try_from(*signal)?)?;
+                let deadline = now + Duration::from_secs(self.soak.teardown_secs);
+                self.killed.push((pid, deadline));
+            }
+            FaultKind::Addon {
+                worker,
+                attach,
+                detach,
+                attached_secs,
+            } => {
+                self.addonctl(worker, attach)?;
+                let at = now + Duration::from_secs(*attached_secs);
+                let detach = Pending::Detach {
+                    worker: worker.clone(),
+                    config: detach.clone(),
+                };
+                self.pending.push((at, detach));
+            }
+            FaultKind::Upgrade { config } => {
+                self.run_ctl("upgrade", &["--config", config.to_str().unwrap()])?;
+            }
+            FaultKind::Flap {
+                down,
+                up,
+                down_secs,
+            } => {
+                self.shell(down)?;
+                let at = now + Duration::from_secs(*down_secs);
+                self.pending.push((at, Pending::LinkUp(up.clone())));
+            }
+        }
+        Ok(())
+    }
+
+    fn run_pending(&mut self, action: Pending) -> anyhow::Result<()> {
+        match action {
+            Pending::StartWorker(i) => self.start_worker(i),
+            Pending::Detach { worker, config } => self.addonctl(&worker, &config),
+            // the link must come back, or whatever follows is meaningless
+            Pending::LinkUp(up) => self.shell(&up).context("taking the link up again"),
+        }
+    }
+
+    /// Reaps the workers that exited, and schedules their restart.
+    fn reap_workers(&mut self) {
+        for i in 0..self.workers.len() {
+            let worker = &mut self.workers[i];
+            let child = match worker.child.as_mut() {
+                Some(child) => child,
+                None => continue,
+            };
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    log::warn!("worker {} exited: {}", worker.spec.name, status);
+                    worker.child = None;
+                    if worker.spec.restart {
+                        let at = Instant::now() + Duration::from_secs(1);
+                        self.pending.push((at, Pending::StartWorker(i)));
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("waiting for worker {}: {}", worker.spec.name, e),
+            }
+        }
+    }
+
+    /// Checks the invariants that hold at any time.
+    fn check(&mut self) -> anyhow::Result<()> {
+        // the daemon is alive
+        if !Path::new(&format!("/proc/{}", self.daemon)).exists() {
+            self.violation(format!("the daemon (pid {}) exited", self.daemon));
+            bail!("the daemon is gone");
+        }
+
+        // the daemon has not panicked
+        if let Some(log) = self.soak.daemon.log.clone() {
+            let mut file = fs::File::open(&log)?;
+            let mut tail = String::new();
+            file.seek(SeekFrom::Start(self.daemon_log_pos))?;
+            self.daemon_log_pos += file.read_to_string(&mut tail)? as u64;
+            for line in tail.lines().filter(|l| l.contains("panicked at")) {
+                self.violation(format!("the daemon panicked: {}", line));
+            }
+        }
+
+        // the completions of the workers are not stuck
+        let stall = Duration::from_secs(self.soak.stall_secs);
+        let mut stuck = Vec::new();
+        for worker in &self.workers {
+            if worker.spec.progress && worker.child.is_some() {
+                let silent = worker.last_output.lock().unwrap().elapsed();
+                if silent > stall {
+                    stuck.push(format!(
+                        "worker {} made no progress for {:?}",
+                        worker.spec.name, silent
+                    ));
+                }
+            }
+        }
+        for what in stuck {
+            self.violation(what);
+        }
+
+        // the subscriptions of the killed workers are torn down
+        let now = Instant::now();
+        if self.killed.iter().any(|(_, deadline)| *deadline <= now) {
+            let alive: HashSet<i64> = self
+                .list_subscriptions()?
+                .into_iter()
+                .map(|(pid, _)| pid)
+                .collect();
+            let (due, waiting): (Vec<_>, Vec<_>) = self
+                .killed
+                .drain(..)
+                .partition(|(_, deadline)| *deadline <= now);
+            self.killed = waiting;
+            for (pid, _) in due {
+                if alive.contains(&(pid.as_raw() as i64)) {
+                    self.violation(format!(
+                        "the subscriptions of the killed pid {} are not torn down after {}s",
+                        pid, self.soak.teardown_secs
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, faults: Vec<FaultSpec>, duration: Duration) -> anyhow::Result<()> {
+        let mut schedule: Vec<(Instant, FaultSpec)> = faults
+            .into_iter()
+            .map(|f| (self.start + Duration::from_secs(f.at_secs), f))
+            .collect();
+        for i in 0..self.workers.len() {
+            let at = self.start + Duration::from_secs(self.workers[i].spec.start_delay_secs);
+            self.pending.push((at, Pending::StartWorker(i)));
+        }
+
+        let check_interval = Duration::from_secs(self.soak.check_interval_secs);
+        let mut next_check = self.start + check_interval;
+        while self.start.elapsed() < duration {
+            let now = Instant::now();
+            self.reap_workers();
+
+            let due: Vec<_> = {
+                let (due, later): (Vec<_>, Vec<_>) =
+                    self.pending.drain(..).partition(|(at, _)| *at <= now);
+                self.pending = later;
+                due
+            };
+            for (_, action) in due {
+                if let Err(e) = self.run_pending(action) {
+                    log::warn!("{:#}", e);
+                }
+            }
+
+            for (at, fault) in schedule.iter_mut() {
+                if *at > now {
+                    continue;
+                }
+                if let Err(e) = self.inject(&fault.kind) {
+                    log::warn!("failed to inject {:?}: {:#}", fault.kind, e);
+                }
+                *at = match fault.every_secs {
+                    Some(every) => *at + Duration::from_secs(every),
+                    // never again within the run
+                    None => self.start + duration,
+                };
+            }
+
+            if now >= next_check {
+                next_check = now + check_interval;
+                self.check()?;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+
+    /// Stops the workers, and checks that the daemon gave back their resources.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let pending: Vec<_> = self.pending.drain(..).collect();
+        for (_, action) in pending {
+            if let Pending::LinkUp(up) = action {
+                // the flap in progress ends with the run
+                if let Err(e) = self.shell(&up) {
+                    log::error!("{:#}", e);
+                }
+            }
+        }
+        for worker in &mut self.workers {
+            if let Some(mut child) = worker.child.take() {
+                let _ = signal::kill(Pid::from_raw(child.id() as _), Signal::SIGKILL);
+                let _ = child.wait();
+            }
+        }
+
+        thread::sleep(Duration::from_secs(self.soak.teardown_secs));
+        self.check()?;
+        let subscriptions = self.list_subscriptions()?;
+        if !subscriptions.is_empty() {
+            self.violation(format!(
+                "{} subscriptions are left after all the workers stopped",
+                subscriptions.len()
+            ));
+        }
+        let mappings = count_memfd_mappings(self.daemon)?;
+        if mappings > self.baseline_mappings {
+            self.violation(format!(
+                "the daemon maps {} shared memory regions after all the workers stopped, {} \
+                 before they started",
+                mappings, self.baseline_mappings
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("RUST_LOG", "info")).init();
+
+    let opt = Opt::from_args();
+    log::info!("options: {:?}", opt);
+
+    let config: Config = read_toml(&opt.configfile)?;
+    let soak: Soak = read_toml(&opt.soak)?;
+    log::info!("{}: {}", soak.name, soak.description);
+
+    let envs: Vec<(String, String)> = config
+        .env
+        .as_table()
+        .ok_or_else(|| anyhow!("unexpected config envs: {:?}", config.env))?
+        .iter()
+        .map(|(k, v)| (k.clone(), v.as_str().unwrap().to_owned()))
+        .collect();
+    let workdir = PathBuf::from(shellexpand::tilde(config.workdir.to_str().unwrap()).as_ref());
+
+    let output_dir = opt.output_dir.as_ref().map(|d| d.join(&soak.name));
+    if let Some(dir) = output_dir.as_ref() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let daemon = find_process(&soak.daemon.comm)?;
+    let baseline_mappings = count_memfd_mappings(daemon)?;
+    let daemon_log_pos = match soak.daemon.log.as_ref() {
+        Some(log) => fs::metadata(log)?.len(),
+        None => 0,
+    };
+    log::info!(
+        "daemon pid: {}, shared memory regions: {}",
+        daemon,
+        baseline_mappings
+    );
+
+    let duration = Duration::from_secs(opt.duration.unwrap_or(soak.duration_secs));
+    let faults = soak.fault.clone();
+    let workers = soak
+        .worker
+        .iter()
+        .map(|spec| Worker {
+            spec: spec.clone(),
+            child: None,
+            last_output: Arc::new(Mutex::new(Instant::now())),
+        })
+        .collect();
+    let mut runner = Runner {
+        soak,
+        workdir,
+        envs,
+        output_dir,
+        debug: opt.debug,
+        workers,
+        daemon,
+        daemon_log_pos,
+        baseline_mappings,
+        killed: Vec::new(),
+        pending: Vec::new(),
+        violations: Vec::new(),
+        start: Instant::now(),
+    };
+
+    let result = runner.run(faults, duration).and_then(|()| runner.finish());
+    if let Err(e) = &result {
+        log::error!("soak test aborted: {:#}", e);
+    }
+
+    if runner.violations.is_empty() && result.is_ok() {
+        log::info!("soak test passed after {:?}", runner.start.elapsed());
+        return Ok(());
+    }
+    for v in &runner.violations {
+        println!("VIOLATION {}", v);
+    }
+    bail!("{} invariant violations", runner.violations.len())
+}
@@ -0,0 +1,83 @@
+//! All-reduce benchmark. Each member sums a buffer of f32 with all the others, and checks the
+//! result.
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+
+use clap::{Parser, ValueEnum};
+
+use phoenix_syscalls::collective::{Algorithm, Buffer, Group, ReduceOp};
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Algo {
+    Ring,
+    Tree,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(about = "All-reduce benchmark.")]
+pub struct Opts {
+    /// The addresses of the members, separated by ','. Each is a host:port the backend of the
+    /// member listens on.
+    #[arg(short = 'H', long)]
+    pub hosts: String,
+
+    /// The rank of this member, i.e., its index in the hosts.
+    #[arg(short, long)]
+    pub rank: usize,
+
+    /// Number of f32 in the buffer.
+    #[arg(short, long, default_value = "1048576")]
+    pub count: usize,
+
+    /// Total number of iterations.
+    #[arg(short, long, default_value = "100")]
+    pub total_iters: usize,
+
+    /// The algorithm to use.
+    #[arg(short, long, value_enum, default_value = "ring")]
+    pub algorithm: Algo,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opts = Opts::parse();
+    let hosts: Vec<SocketAddr> = opts
+        .hosts
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            // use the first resolved address
+            x.to_socket_addrs()
+                .unwrap()
+                .next()
+                .expect("resolve address failed")
+        })
+        .collect();
+    let algorithm = match opts.algorithm {
+        Algo::Ring => Algorithm::Ring,
+        Algo::Tree => Algorithm::Tree,
+    };
+
+    let group = Group::new(&hosts, opts.rank)?;
+    eprintln!("Joined the group as {} of {}", group.rank(), group.size());
+
+    let mut buf: Buffer<f32> = Buffer::new(opts.count)?;
+    let n = group.size() as f32;
+    let start = Instant::now();
+    for _ in 0..opts.total_iters {
+        buf.fill(1.0);
+        group.all_reduce(&mut buf, .., ReduceOp::Sum, algorithm)?;
+        assert!(buf.iter().all(|&x| x == n), "unexpected result");
+    }
+    let dura = start.elapsed();
+
+    let bytes = opts.count * std::mem::size_of::<f32>();
+    let algbw = (bytes * opts.total_iters) as f64 / dura.as_secs_f64() / 1e9;
+    println!(
+        "{} bytes, {} iterations, {:.3} ms per iteration, algbw {:.3} GB/s",
+        bytes,
+        opts.total_iters,
+        dura.as_secs_f64() * 1e3 / opts.total_iters as f64,
+        algbw
+    );
+    Ok(())
+}
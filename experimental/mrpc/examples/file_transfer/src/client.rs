@@ -0,0 +1,237 @@
+//! An mRPC client that downloads or uploads a file in chunks, keeping a window of chunks in
+//! flight.
+//!
+//! A downloaded chunk is written to the file straight from the receive buffer, which is handed
+//! back to the backend as soon as the reply is dropped. An uploaded chunk is read from the file
+//! straight into the request in the shared memory heap, from which the backend sends it. The
+//! window bounds both the receive buffers and the heap held by the transfer.
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use structopt::StructOpt;
+
+use mrpc::alloc::Vec;
+
+pub mod file_transfer {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("file_transfer");
+}
+
+use file_transfer::file_transfer_client::FileTransferClient;
+use file_transfer::{ReadRequest, StatRequest, WriteRequest};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "mRPC file transfer client")]
+struct Args {
+    /// The address to connect, can be an IP address or domain name.
+    #[structopt(short = "c", long = "connect", default_value = "localhost")]
+    connect: String,
+
+    /// The port to connect.
+    #[structopt(short, long, default_value = "5000")]
+    port: u16,
+
+    /// The size of a chunk in bytes, at most the `max_chunk_size` of the server.
+    #[structopt(long, default_value = "4194304")]
+    chunk_size: u32,
+
+    /// The number of chunks in flight.
+    #[structopt(short, long, default_value = "8")]
+    window: usize,
+
+    /// The interval in seconds between the progress reports.
+    #[structopt(short, long, default_value = "1")]
+    interval: u64,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Downloads a file from the server.
+    Download {
+        /// The path of the file on the server, relative to its root directory.
+        remote: String,
+        /// The path to write the file to.
+        local: PathBuf,
+    },
+    /// Uploads a file to the server.
+    Upload {
+        /// The path of the file to upload.
+        local: PathBuf,
+        /// The path to write the file to on the server, relative to its root directory.
+        remote: String,
+    },
+}
+
+/// Reports the progress of a transfer.
+#[derive(Debug)]
+struct Progress {
+    size: u64,
+    done: u64,
+    start: Instant,
+    last: Instant,
+    last_done: u64,
+    interval: Duration,
+}
+
+impl Progress {
+    fn new(size: u64, interval: Duration) -> Self {
+        let now = Instant::now();
+        Progress {
+            size,
+            done: 0,
+            start: now,
+            last: now,
+            last_done: 0,
+            interval,
+        }
+    }
+
+    fn advance(&mut self, len: u64) {
+        self.done += len;
+        let now = Instant::now();
+        if now - self.last >= self.interval {
+            eprintln!(
+                "{:.1}%, {:.2} Gb/s",
+                self.done as f64 * 100.0 / self.size as f64,
+                gbps(self.done - self.last_done, now - self.last)
+            );
+            self.last = now;
+            self.last_done = self.done;
+        }
+    }
+
+    fn finish(&self) {
+        let elapsed = self.start.elapsed();
+        eprintln!(
+            "transferred {} bytes in {:?}, {:.2} Gb/s",
+            self.done,
+            elapsed,
+            gbps(self.done, elapsed)
+        );
+    }
+}
+
+fn gbps(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1e9
+}
+
+/// The offsets and lengths of the chunks of a file of `size` bytes.
+fn chunks(size: u64, chunk_size: u32) -> impl Iterator<Item = (u64, u32)> {
+    (0..size)
+        .step_by(chunk_size as usize)
+        .map(move |offset| (offset, (size - offset).min(chunk_size as u64) as u32))
+}
+
+async fn download(
+    client: &FileTransferClient,
+    args: &Args,
+    remote: &str,
+    local: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let req = StatRequest {
+        path: remote.into(),
+    };
+    let size = client.stat(req).await?.size;
+    let file = File::create(local)?;
+    file.set_len(size)?;
+    eprintln!(
+        "downloading {remote} of {size} bytes to {}",
+        local.display()
+    );
+
+    let mut progress = Progress::new(size, Duration::from_secs(args.interval));
+    let mut chunks = chunks(size, args.chunk_size);
+    let mut inflight = FuturesUnordered::new();
+    loop {
+        while inflight.len() < args.window {
+            let Some((offset, len)) = chunks.next() else {
+                break;
+            };
+            let req = ReadRequest {
+                path: remote.into(),
+                offset,
+                len,
+            };
+            inflight.push(async move { (len, client.read(req).await) });
+        }
+        let Some((len, reply)) = inflight.next().await else {
+            break;
+        };
+        let reply = reply?;
+        if reply.data.len() != len as usize {
+            return Err(format!(
+                "short chunk at {}: {} of {} bytes, the file has changed",
+                reply.offset,
+                reply.data.len(),
+                len
+            )
+            .into());
+        }
+        file.write_all_at(&reply.data, reply.offset)?;
+        progress.advance(len as u64);
+        // dropping the reply hands its receive buffer back
+    }
+    progress.finish();
+    Ok(())
+}
+
+async fn upload(
+    client: &FileTransferClient,
+    args: &Args,
+    local: &Path,
+    remote: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = OpenOptions::new().read(true).open(local)?;
+    let size = file.metadata()?.len();
+    eprintln!("uploading {} of {size} bytes to {remote}", local.display());
+
+    let mut progress = Progress::new(size, Duration::from_secs(args.interval));
+    let mut chunks = chunks(size, args.chunk_size);
+    let mut inflight = FuturesUnordered::new();
+    loop {
+        while inflight.len() < args.window {
+            let Some((offset, len)) = chunks.next() else {
+                break;
+            };
+            let mut data = Vec::with_capacity(len as usize);
+            data.resize(len as usize, 0);
+            file.read_exact_at(&mut data, offset)?;
+            let req = WriteRequest {
+                path: remote.into(),
+                offset,
+                data,
+            };
+            inflight.push(async move { (len, client.write(req).await) });
+        }
+        let Some((len, reply)) = inflight.next().await else {
+            break;
+        };
+        let written = reply?.written;
+        if written != len as u64 {
+            return Err(format!("short write: {written} of {len} bytes").into());
+        }
+        progress.advance(len as u64);
+    }
+    progress.finish();
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    eprintln!("args: {:?}", args);
+    assert!(args.chunk_size > 0 && args.window > 0);
+
+    let client = FileTransferClient::connect((args.connect.as_str(), args.port))?;
+    smol::block_on(async {
+        match &args.command {
+            Command::Download { remote, local } => download(&client, &args, remote, local).await,
+            Command::Upload { local, remote } => upload(&client, &args, local, remote).await,
+        }
+    })
+}
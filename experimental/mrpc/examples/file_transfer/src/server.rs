@@ -0,0 +1,176 @@
+//! An mRPC server that reads and writes the files under a root directory, a chunk per call.
+//!
+//! A READ reads the chunk straight into the reply in the shared memory heap, from which the
+//! backend sends it. A WRITE writes the chunk straight from the receive buffer.
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use structopt::StructOpt;
+
+use mrpc::alloc::Vec;
+use mrpc::{RRef, WRef};
+
+pub mod file_transfer {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("file_transfer");
+}
+
+use file_transfer::file_transfer_server::{FileTransfer, FileTransferServer};
+use file_transfer::{
+    ReadRequest, ReadResponse, StatRequest, StatResponse, WriteRequest, WriteResponse,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "mRPC file transfer server")]
+struct Args {
+    /// The port to listen on.
+    #[structopt(short, long, default_value = "5000")]
+    port: u16,
+
+    /// The directory of the files to serve.
+    #[structopt(short, long, default_value = ".")]
+    root: PathBuf,
+
+    /// The maximum size of a chunk in bytes. It should be smaller than the receive buffers of
+    /// the RPC adapter, and than the message size limits of the service, if any.
+    #[structopt(long, default_value = "4194304")]
+    max_chunk_size: u32,
+}
+
+#[derive(Debug)]
+struct MyFileTransfer {
+    root: PathBuf,
+    max_chunk_size: u32,
+    // The files opened so far and whether they are writable, a transfer makes many calls on the
+    // same file.
+    files: Mutex<HashMap<PathBuf, (Arc<File>, bool)>>,
+}
+
+impl MyFileTransfer {
+    fn new(root: PathBuf, max_chunk_size: u32) -> Self {
+        MyFileTransfer {
+            root,
+            max_chunk_size,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf, mrpc::Status> {
+        let path = Path::new(path);
+        // stay under the root
+        if path.as_os_str().is_empty()
+            || !path.components().all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(mrpc::Status::invalid_argument(format!(
+                "invalid path: {}",
+                path.display()
+            )));
+        }
+        Ok(self.root.join(path))
+    }
+
+    fn open(&self, path: &str, create: bool) -> Result<Arc<File>, mrpc::Status> {
+        let path = self.resolve(path)?;
+        let mut files = self.files.lock().unwrap();
+        match files.get(&path) {
+            Some((file, writable)) if *writable || !create => return Ok(Arc::clone(file)),
+            _ => {}
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(create)
+            .create(create)
+            .open(&path)
+            .map_err(|e| io_status(&path, e))?;
+        let file = Arc::new(file);
+        files.insert(path, (Arc::clone(&file), create));
+        Ok(file)
+    }
+}
+
+fn io_status(path: &Path, e: std::io::Error) -> mrpc::Status {
+    let message = format!("{}: {}", path.display(), e);
+    match e.kind() {
+        std::io::ErrorKind::NotFound => mrpc::Status::not_found(message),
+        std::io::ErrorKind::PermissionDenied => mrpc::Status::permission_denied(message),
+        _ => mrpc::Status::internal(message),
+    }
+}
+
+#[mrpc::async_trait]
+impl FileTransfer for MyFileTransfer {
+    async fn stat(&self, request: RRef<StatRequest>) -> Result<WRef<StatResponse>, mrpc::Status> {
+        let path = self.resolve(&request.path)?;
+        let metadata = std::fs::metadata(&path).map_err(|e| io_status(&path, e))?;
+        Ok(WRef::new(StatResponse {
+            size: metadata.len(),
+        }))
+    }
+
+    async fn read(&self, request: RRef<ReadRequest>) -> Result<WRef<ReadResponse>, mrpc::Status> {
+        if request.len > self.max_chunk_size {
+            return Err(mrpc::Status::invalid_argument(format!(
+                "chunk of {} bytes over the maximum of {}",
+                request.len, self.max_chunk_size
+            )));
+        }
+        let file = self.open(&request.path, false)?;
+        let mut data = Vec::with_capacity(request.len as usize);
+        data.resize(request.len as usize, 0);
+        // read until the chunk is full or the end of the file
+        let mut filled = 0;
+        while filled < data.len() {
+            match file.read_at(&mut data[filled..], request.offset + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(io_status(Path::new(request.path.as_str()), e)),
+            }
+        }
+        data.truncate(filled);
+        Ok(WRef::new(ReadResponse {
+            offset: request.offset,
+            data,
+        }))
+    }
+
+    async fn write(
+        &self,
+        request: RRef<WriteRequest>,
+    ) -> Result<WRef<WriteResponse>, mrpc::Status> {
+        if request.data.len() > self.max_chunk_size as usize {
+            return Err(mrpc::Status::invalid_argument(format!(
+                "chunk of {} bytes over the maximum of {}",
+                request.data.len(),
+                self.max_chunk_size
+            )));
+        }
+        let file = self.open(&request.path, true)?;
+        file.write_all_at(&request.data, request.offset)
+            .map_err(|e| io_status(Path::new(request.path.as_str()), e))?;
+        Ok(WRef::new(WriteResponse {
+            written: request.data.len() as u64,
+        }))
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    eprintln!("args: {:?}", args);
+
+    smol::block_on(async {
+        let mut server = mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?;
+        server
+            .add_service(FileTransferServer::new(MyFileTransfer::new(
+                args.root,
+                args.max_chunk_size,
+            )))
+            .serve()
+            .await?;
+        eprintln!("server stopped");
+        Ok(())
+    })
+}
@@ -0,0 +1,326 @@
+//! An mRPC client of the key-value store, which loads the keys, then runs a mix of concurrent
+//! GETs, PUTs and SCANs, and verifies each reply.
+//!
+//! The values fetched by the GETs are cached as they are received, i.e., the cache holds the
+//! receive buffers rather than copies of the values, and a GET that hits the cache reads the value
+//! in place. A cached reply pins its receive buffer until it is evicted, hence the small cache.
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use structopt::StructOpt;
+
+use mrpc::RRef;
+
+pub mod kv_store {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("kv_store");
+}
+
+use kv_store::kv_store_client::KvStoreClient;
+use kv_store::{GetRequest, GetResponse, PutRequest, ScanRequest, ScanResponse};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "mRPC key-value store client")]
+struct Args {
+    /// The address to connect, can be an IP address or domain name.
+    #[structopt(short = "c", long = "connect", default_value = "localhost")]
+    connect: String,
+
+    /// The port to connect.
+    #[structopt(short, long, default_value = "5000")]
+    port: u16,
+
+    /// The number of keys.
+    #[structopt(short = "k", long, default_value = "4096")]
+    num_keys: u64,
+
+    /// The minimum size of a value in bytes.
+    #[structopt(long, default_value = "16")]
+    min_value_size: usize,
+
+    /// The maximum size of a value in bytes.
+    #[structopt(long, default_value = "4096")]
+    max_value_size: usize,
+
+    /// The number of concurrent requests.
+    #[structopt(short = "C", long, default_value = "32")]
+    concurrency: usize,
+
+    /// The number of operations after the keys are loaded.
+    #[structopt(short = "n", long, default_value = "100000")]
+    num_ops: usize,
+
+    /// The percentage of GETs among the operations.
+    #[structopt(long, default_value = "80")]
+    get_ratio: u32,
+
+    /// The percentage of SCANs among the operations, the rest are PUTs.
+    #[structopt(long, default_value = "5")]
+    scan_ratio: u32,
+
+    /// The number of entries a SCAN asks for, at most the `max_scan` of the server.
+    #[structopt(long, default_value = "16")]
+    scan_limit: u32,
+
+    /// The number of GET replies kept in the cache.
+    #[structopt(long, default_value = "64")]
+    cache_size: usize,
+}
+
+/// A value starts with the index of its key and its version, and is filled with a byte derived
+/// from both, so that any value can be checked against its key.
+const VALUE_HEADER: usize = 16;
+
+fn key_of(index: u64) -> String {
+    format!("key{index:08}")
+}
+
+fn index_of(key: &[u8]) -> Option<u64> {
+    std::str::from_utf8(key.strip_prefix(b"key")?)
+        .ok()?
+        .parse()
+        .ok()
+}
+
+fn make_value(index: u64, version: u64, len: usize) -> Vec<u8> {
+    let mut value = Vec::with_capacity(len.max(VALUE_HEADER));
+    value.extend_from_slice(&index.to_le_bytes());
+    value.extend_from_slice(&version.to_le_bytes());
+    value.resize(len.max(VALUE_HEADER), (index ^ version) as u8);
+    value
+}
+
+fn check_value(index: u64, value: &[u8]) -> bool {
+    if value.len() < VALUE_HEADER {
+        return false;
+    }
+    let (header, fill) = value.split_at(VALUE_HEADER);
+    let (key_index, version) = header.split_at(8);
+    let key_index = u64::from_le_bytes(key_index.try_into().unwrap());
+    let version = u64::from_le_bytes(version.try_into().unwrap());
+    key_index == index && fill.iter().all(|&b| b == (index ^ version) as u8)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Get(u64),
+    Put(u64, u64),
+    Scan(u64),
+}
+
+#[derive(Debug)]
+enum Done {
+    Get(u64, RRef<GetResponse>),
+    Put(u64, bool),
+    Scan(u64, RRef<ScanResponse>),
+}
+
+async fn issue(client: &KvStoreClient, op: Op, args: &Args) -> Result<Done, mrpc::Status> {
+    match op {
+        Op::Get(index) => {
+            let req = GetRequest {
+                key: key_of(index).as_bytes().into(),
+            };
+            Ok(Done::Get(index, client.get(req).await?))
+        }
+        Op::Put(index, version) => {
+            let len = fastrand::usize(args.min_value_size..=args.max_value_size);
+            let req = PutRequest {
+                key: key_of(index).as_bytes().into(),
+                value: make_value(index, version, len).as_slice().into(),
+            };
+            Ok(Done::Put(index, client.put(req).await?.replaced))
+        }
+        Op::Scan(index) => {
+            let req = ScanRequest {
+                start: key_of(index).as_bytes().into(),
+                limit: args.scan_limit,
+            };
+            Ok(Done::Scan(index, client.scan(req).await?))
+        }
+    }
+}
+
+/// The GET replies received last, keyed by the index of their keys.
+#[derive(Debug)]
+struct Cache {
+    capacity: usize,
+    replies: HashMap<u64, RRef<GetResponse>>,
+    order: VecDeque<u64>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Cache {
+            capacity,
+            replies: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn get(&self, index: u64) -> Option<&RRef<GetResponse>> {
+        self.replies.get(&index)
+    }
+
+    fn insert(&mut self, index: u64, reply: RRef<GetResponse>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.replies.insert(index, reply).is_none() {
+            if self.order.len() == self.capacity {
+                // dropping the evicted reply releases its receive buffer
+                let evicted = self.order.pop_front().unwrap();
+                self.replies.remove(&evicted);
+            }
+            self.order.push_back(index);
+        }
+    }
+
+    fn invalidate(&mut self, index: u64) {
+        if self.replies.remove(&index).is_some() {
+            self.order.retain(|&i| i != index);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    gets: usize,
+    cache_hits: usize,
+    puts: usize,
+    scans: usize,
+    scanned: usize,
+    errors: usize,
+}
+
+fn check(done: Done, args: &Args, cache: &mut Cache, counters: &mut Counters, loaded: bool) {
+    match done {
+        Done::Get(index, reply) => {
+            counters.gets += 1;
+            if !reply.found || !check_value(index, &reply.value) {
+                eprintln!(
+                    "GET {}: unexpected value, found: {}",
+                    key_of(index),
+                    reply.found
+                );
+                counters.errors += 1;
+            } else {
+                cache.insert(index, reply);
+            }
+        }
+        Done::Put(index, replaced) => {
+            counters.puts += 1;
+            if replaced != loaded {
+                eprintln!("PUT {}: replaced is {replaced}", key_of(index));
+                counters.errors += 1;
+            }
+        }
+        Done::Scan(start, reply) => {
+            counters.scans += 1;
+            counters.scanned += reply.entries.len();
+            let expected = (args.num_keys - start).min(args.scan_limit as u64);
+            let mut ok = reply.entries.len() as u64 == expected;
+            for (i, entry) in reply.entries.iter().enumerate() {
+                let index = start + i as u64;
+                ok &= index_of(&entry.key) == Some(index) && check_value(index, &entry.value);
+            }
+            if !ok {
+                eprintln!(
+                    "SCAN {}: unexpected {} entries",
+                    key_of(start),
+                    reply.entries.len()
+                );
+                counters.errors += 1;
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    eprintln!("args: {:?}", args);
+    assert!(args.num_keys > 0, "num_keys must be positive");
+    assert!(args.min_value_size <= args.max_value_size);
+    assert!(args.get_ratio + args.scan_ratio <= 100);
+
+    let client = KvStoreClient::connect((args.connect.as_str(), args.port))?;
+    let mut cache = Cache::new(args.cache_size);
+    let mut versions = vec![0u64; args.num_keys as usize];
+
+    smol::block_on(async {
+        // load the keys
+        let mut counters = Counters::default();
+        let mut inflight = FuturesUnordered::new();
+        let mut next = 0;
+        while next < args.num_keys || !inflight.is_empty() {
+            while next < args.num_keys && inflight.len() < args.concurrency {
+                inflight.push(issue(&client, Op::Put(next, 0), &args));
+                next += 1;
+            }
+            if let Some(done) = inflight.next().await {
+                check(done?, &args, &mut cache, &mut counters, false);
+            }
+        }
+        eprintln!("loaded {} keys, {} errors", args.num_keys, counters.errors);
+        let load_errors = counters.errors;
+
+        // the mixed workload
+        let mut counters = Counters::default();
+        let start = Instant::now();
+        let mut issued = 0;
+        while issued < args.num_ops || !inflight.is_empty() {
+            while issued < args.num_ops && inflight.len() < args.concurrency {
+                issued += 1;
+                let index = fastrand::u64(..args.num_keys);
+                let dice = fastrand::u32(..100);
+                let op = if dice < args.get_ratio {
+                    if let Some(reply) = cache.get(index) {
+                        // read the value in place, in the receive buffer
+                        counters.gets += 1;
+                        counters.cache_hits += 1;
+                        if !check_value(index, &reply.value) {
+                            eprintln!("GET {}: corrupted cached value", key_of(index));
+                            counters.errors += 1;
+                        }
+                        continue;
+                    }
+                    Op::Get(index)
+                } else if dice < args.get_ratio + args.scan_ratio {
+                    Op::Scan(index)
+                } else {
+                    versions[index as usize] += 1;
+                    cache.invalidate(index);
+                    Op::Put(index, versions[index as usize])
+                };
+                inflight.push(issue(&client, op, &args));
+            }
+            if let Some(done) = inflight.next().await {
+                check(done?, &args, &mut cache, &mut counters, true);
+            }
+        }
+        let elapsed = start.elapsed();
+
+        eprintln!(
+            "{} ops in {:?}, {:.2} Kops/s",
+            args.num_ops,
+            elapsed,
+            args.num_ops as f64 / elapsed.as_secs_f64() / 1e3
+        );
+        eprintln!(
+            "{} GETs ({} cache hits), {} PUTs, {} SCANs ({} entries), {} errors",
+            counters.gets,
+            counters.cache_hits,
+            counters.puts,
+            counters.scans,
+            counters.scanned,
+            counters.errors
+        );
+
+        let errors = load_errors + counters.errors;
+        if errors > 0 {
+            return Err(format!("{errors} replies failed verification").into());
+        }
+        Ok(())
+    })
+}
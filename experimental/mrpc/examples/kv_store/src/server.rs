@@ -0,0 +1,113 @@
+//! An mRPC server of an ordered key-value store.
+//!
+//! The values are kept in the shared memory heap as ready-made replies, so a GET replies with the
+//! stored value without copying it. A PUT copies the value out of the receive buffer once, since
+//! the receive buffer is reclaimed when the request is dropped.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use structopt::StructOpt;
+
+use mrpc::alloc::Vec;
+use mrpc::{RRef, WRef};
+
+pub mod kv_store {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("kv_store");
+}
+
+use kv_store::kv_store_server::{KvStore, KvStoreServer};
+use kv_store::{
+    GetRequest, GetResponse, KeyValue, PutRequest, PutResponse, ScanRequest, ScanResponse,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "mRPC key-value store server")]
+struct Args {
+    /// The port to listen on.
+    #[structopt(short, long, default_value = "5000")]
+    port: u16,
+
+    /// The maximum number of entries a SCAN lists.
+    #[structopt(long, default_value = "128")]
+    max_scan: u32,
+}
+
+#[derive(Debug)]
+struct MyKvStore {
+    // The values, as the replies to the GETs of their keys.
+    entries: Mutex<BTreeMap<std::vec::Vec<u8>, WRef<GetResponse>>>,
+    not_found: WRef<GetResponse>,
+    max_scan: u32,
+}
+
+impl MyKvStore {
+    fn new(max_scan: u32) -> Self {
+        MyKvStore {
+            entries: Mutex::new(BTreeMap::new()),
+            not_found: WRef::new(GetResponse {
+                found: false,
+                value: Vec::new(),
+            }),
+            max_scan,
+        }
+    }
+}
+
+#[mrpc::async_trait]
+impl KvStore for MyKvStore {
+    async fn get(&self, request: RRef<GetRequest>) -> Result<WRef<GetResponse>, mrpc::Status> {
+        let entries = self.entries.lock().unwrap();
+        let reply = entries
+            .get(request.key.as_slice())
+            .unwrap_or(&self.not_found);
+        Ok(WRef::clone(reply))
+    }
+
+    async fn put(&self, request: RRef<PutRequest>) -> Result<WRef<PutResponse>, mrpc::Status> {
+        if request.key.is_empty() {
+            return Err(mrpc::Status::invalid_argument("empty key"));
+        }
+        let value = WRef::new(GetResponse {
+            found: true,
+            value: Vec::from(request.value.as_slice()),
+        });
+        let replaced = self
+            .entries
+            .lock()
+            .unwrap()
+            .insert(request.key.to_vec(), value)
+            .is_some();
+        Ok(WRef::new(PutResponse { replaced }))
+    }
+
+    async fn scan(&self, request: RRef<ScanRequest>) -> Result<WRef<ScanResponse>, mrpc::Status> {
+        let limit = request.limit.min(self.max_scan) as usize;
+        let entries = self.entries.lock().unwrap();
+        let mut reply = ScanResponse {
+            entries: Vec::with_capacity(limit),
+        };
+        for (key, value) in entries.range(request.start.to_vec()..).take(limit) {
+            reply.entries.push(KeyValue {
+                key: Vec::from(key.as_slice()),
+                value: Vec::from(value.value.as_slice()),
+            });
+        }
+        Ok(WRef::new(reply))
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    eprintln!("args: {:?}", args);
+
+    smol::block_on(async {
+        let mut server = mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?;
+        server
+            .add_service(KvStoreServer::new(MyKvStore::new(args.max_scan)))
+            .serve()
+            .await?;
+        eprintln!("server stopped");
+        Ok(())
+    })
+}
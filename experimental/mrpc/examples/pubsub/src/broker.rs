@@ -0,0 +1,66 @@
+//! The broker of the PubSub service. The requests are answered by the PubSub addon attached to
+//! this process; without it, they reach this server, which rejects them.
+pub mod pubsub {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("pubsub");
+}
+
+use structopt::StructOpt;
+
+use pubsub::pub_sub_server::{PubSub, PubSubServer};
+use pubsub::{Ack, Event, SubscribeRequest};
+
+use mrpc::{RRef, WRef};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "PubSub broker")]
+struct Args {
+    /// The port to listen on.
+    #[structopt(short, long, default_value = "5000")]
+    port: u16,
+}
+
+#[derive(Debug, Default)]
+struct Fallback;
+
+fn reject() -> Result<WRef<Ack>, mrpc::Status> {
+    eprintln!("the PubSub addon is not attached, request rejected");
+    Ok(WRef::new(Ack { accepted: false }))
+}
+
+#[mrpc::async_trait]
+impl PubSub for Fallback {
+    async fn subscribe(&self, _request: RRef<SubscribeRequest>) -> Result<WRef<Ack>, mrpc::Status> {
+        reject()
+    }
+
+    async fn unsubscribe(
+        &self,
+        _request: RRef<SubscribeRequest>,
+    ) -> Result<WRef<Ack>, mrpc::Status> {
+        reject()
+    }
+
+    async fn publish(&self, _request: RRef<Event>) -> Result<WRef<Ack>, mrpc::Status> {
+        reject()
+    }
+
+    async fn deliver(&self, _request: RRef<Ack>) -> Result<WRef<Event>, mrpc::Status> {
+        Err(mrpc::Status::unimplemented(
+            "events are only delivered as notifications",
+        ))
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    smol::block_on(async {
+        let mut server = mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?;
+        server
+            .add_service(PubSubServer::new(Fallback::default()))
+            .serve()
+            .await?;
+        eprintln!("server stopped");
+        Ok(())
+    })
+}
@@ -0,0 +1,49 @@
+//! Publishes events to a topic of the broker.
+pub mod pubsub {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("pubsub");
+}
+
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+use pubsub::pub_sub_client::PubSubClient;
+use pubsub::Event;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "PubSub publisher")]
+struct Args {
+    /// The address of the broker.
+    #[structopt(short, long, default_value = "localhost:5000")]
+    connect: String,
+
+    /// The topic to publish to.
+    #[structopt(short, long, default_value = "news")]
+    topic: String,
+
+    /// The number of events to publish.
+    #[structopt(short = "n", long, default_value = "10")]
+    count: usize,
+
+    /// The interval between two events, in milliseconds.
+    #[structopt(short, long, default_value = "100")]
+    interval: u64,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    let client = PubSubClient::connect(&args.connect)?;
+    for i in 0..args.count {
+        let event = Event {
+            topic: args.topic.as_bytes().into(),
+            payload: format!("event {}", i).as_bytes().into(),
+        };
+        let ack = smol::block_on(client.publish(event))?;
+        if !ack.accepted {
+            eprintln!("event {} rejected", i);
+        }
+        std::thread::sleep(Duration::from_millis(args.interval));
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Subscribes to a topic of the broker, and prints the events pushed to it.
+pub mod pubsub {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("pubsub");
+}
+
+use futures::StreamExt;
+use structopt::StructOpt;
+
+use pubsub::pub_sub_client::PubSubClient;
+use pubsub::SubscribeRequest;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "PubSub subscriber")]
+struct Args {
+    /// The address of the broker.
+    #[structopt(short, long, default_value = "localhost:5000")]
+    connect: String,
+
+    /// The topic to subscribe to.
+    #[structopt(short, long, default_value = "news")]
+    topic: String,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    let client = PubSubClient::connect(&args.connect)?;
+    // subscribe to the notifications before the events can be pushed
+    let mut events = client.subscribe_deliver();
+
+    smol::block_on(async {
+        let req = SubscribeRequest {
+            topic: args.topic.as_bytes().into(),
+        };
+        let ack = client.subscribe(req).await?;
+        if !ack.accepted {
+            eprintln!("subscription to {} rejected", args.topic);
+            return Ok(());
+        }
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+            println!(
+                "{}: {}",
+                String::from_utf8_lossy(&event.topic),
+                String::from_utf8_lossy(&event.payload)
+            );
+        }
+        eprintln!("broker disconnected");
+        Ok(())
+    })
+}
@@ -83,6 +83,10 @@ pub struct Args {
     /// Which transport to use, rdma or tcp
     #[structopt(long, default_value = "rdma")]
     pub transport: TransportType,
+
+    /// Encrypt the connections, only with rdma
+    #[structopt(long)]
+    pub encryption: bool,
 }
 
 // mod bench_app;
@@ -291,6 +295,7 @@ fn run_client_thread(tid: usize, args: &Args) -> Result<(), Box<dyn std::error::
     // Set transport type
     let mut setting = mrpc::current_setting();
     setting.transport = args.transport;
+    setting.encryption = args.encryption;
     mrpc::set(&setting);
 
     // bind to NUMA node (tid % num_nodes)
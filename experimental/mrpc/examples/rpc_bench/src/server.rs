@@ -1,6 +1,10 @@
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use hdrhistogram::Histogram;
+use minstant::Instant;
 use structopt::StructOpt;
 
 use mrpc::alloc::Vec;
@@ -38,15 +42,173 @@ pub struct Args {
     #[structopt(long, default_value = "1")]
     pub num_server_threads: usize,
 
+    /// Number of warmup requests on each server thread, not measured.
+    #[structopt(short, long, default_value = "1000")]
+    pub warmup: usize,
+
+    /// Number of requests measured on each server thread after the warmup.
+    #[structopt(short, long, default_value = "16384")]
+    pub total_iters: usize,
+
+    /// Measure for a customized period of seconds after the warmup instead.
+    #[structopt(short = "D", long)]
+    pub duration: Option<f64>,
+
+    /// Seconds between periodic throughput reports.
+    #[structopt(short, long, default_value = "1")]
+    pub interval: f64,
+
     /// Which transport to use, rdma or tcp
     #[structopt(long, default_value = "rdma")]
     pub transport: TransportType,
+
+    /// Encrypt the connections, only with rdma
+    #[structopt(long)]
+    pub encryption: bool,
+}
+
+/// The requests served by a server thread, and the time spent serving them.
+struct Stats {
+    warmup: usize,
+    total_iters: usize,
+    duration: Option<Duration>,
+    inner: Mutex<StatsInner>,
+}
+
+struct StatsInner {
+    /// All the requests received, including those of the warmup.
+    rcnt: usize,
+    /// The bytes of the requests received.
+    nbytes: usize,
+    /// The service times since the last periodic report.
+    interval_hist: Histogram<u64>,
+    /// The service times in the measurement window.
+    hist: Histogram<u64>,
+    measured: usize,
+    measured_nbytes: usize,
+    measure_start: Option<Instant>,
+    measure_end: Option<Instant>,
+}
+
+impl std::fmt::Debug for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stats").finish_non_exhaustive()
+    }
+}
+
+impl Stats {
+    fn new(args: &Args) -> Self {
+        let new_hist = || Histogram::<u64>::new_with_max(60_000_000_000, 5).unwrap();
+        Stats {
+            warmup: args.warmup,
+            total_iters: args.total_iters,
+            duration: args.duration.map(Duration::from_secs_f64),
+            inner: Mutex::new(StatsInner {
+                rcnt: 0,
+                nbytes: 0,
+                interval_hist: new_hist(),
+                hist: new_hist(),
+                measured: 0,
+                measured_nbytes: 0,
+                measure_start: None,
+                measure_end: None,
+            }),
+        }
+    }
+
+    fn record(&self, req_size: usize, service_time: Duration) {
+        let now = Instant::now();
+        let mut s = self.inner.lock().unwrap();
+        s.rcnt += 1;
+        s.nbytes += req_size;
+        let _ = s.interval_hist.record(service_time.as_nanos() as u64);
+        if s.rcnt <= self.warmup || s.measure_end.is_some() {
+            return;
+        }
+
+        let start = *s.measure_start.get_or_insert(now);
+        if self.duration.map_or(false, |d| now - start > d) {
+            s.measure_end = Some(now);
+            return;
+        }
+        s.measured += 1;
+        s.measured_nbytes += req_size;
+        let _ = s.hist.record(service_time.as_nanos() as u64);
+        if self.duration.is_none() && s.measured >= self.total_iters {
+            s.measure_end = Some(now);
+        }
+    }
+
+    /// Prints the throughput every interval, and the summary of the measurement window in JSON
+    /// once it ends.
+    fn report(&self, tid: usize, interval: Duration) {
+        let mut last_ts = Instant::now();
+        let mut last_rcnt = 0;
+        let mut last_nbytes = 0;
+        loop {
+            std::thread::sleep(interval);
+            let mut s = self.inner.lock().unwrap();
+
+            let last_dura = last_ts.elapsed();
+            let rps = (s.rcnt - last_rcnt) as f64 / last_dura.as_secs_f64();
+            let bw = 8e-9 * (s.nbytes - last_nbytes) as f64 / last_dura.as_secs_f64();
+            let phase = if s.rcnt <= self.warmup {
+                " (warmup)"
+            } else {
+                ""
+            };
+            println!(
+                "Thread {}{}, {} rps, {} Gb/s, p50: {:?}, p99: {:?}",
+                tid,
+                phase,
+                rps,
+                bw,
+                Duration::from_nanos(s.interval_hist.value_at_percentile(50.0)),
+                Duration::from_nanos(s.interval_hist.value_at_percentile(99.0)),
+            );
+            s.interval_hist.clear();
+            last_ts = Instant::now();
+            last_rcnt = s.rcnt;
+            last_nbytes = s.nbytes;
+
+            if let (Some(start), None, Some(d)) = (s.measure_start, s.measure_end, self.duration) {
+                if start.elapsed() > d {
+                    s.measure_end = Some(start + d);
+                }
+            }
+            if let (Some(start), Some(end)) = (s.measure_start, s.measure_end) {
+                println!("{}", summary(tid, end - start, &s));
+                return;
+            }
+        }
+    }
+}
+
+/// The summary of the measurement window, with the same figures as the client prints at the end.
+fn summary(tid: usize, dura: Duration, s: &StatsInner) -> serde_json::Value {
+    serde_json::json!({
+        "thread": tid,
+        "duration_ns": dura.as_nanos() as u64,
+        "requests": s.measured,
+        "bytes": s.measured_nbytes,
+        "bandwidth_gbps": 8e-9 * s.measured_nbytes as f64 / dura.as_secs_f64(),
+        "rate_mrps": 1e-6 * s.measured as f64 / dura.as_secs_f64(),
+        "service_time_ns": {
+            "avg": s.hist.mean() as u64,
+            "min": s.hist.min(),
+            "median": s.hist.value_at_percentile(50.0),
+            "p95": s.hist.value_at_percentile(95.0),
+            "p99": s.hist.value_at_percentile(99.0),
+            "max": s.hist.max(),
+        },
+    })
 }
 
 #[derive(Debug)]
 struct MyGreeter {
     replies: Vec<WRef<HelloReply>>,
     count: AtomicUsize,
+    stats: Arc<Stats>,
     args: Args,
 }
 
@@ -54,14 +216,16 @@ struct MyGreeter {
 impl Greeter for MyGreeter {
     async fn say_hello(
         &self,
-        _request: RRef<HelloRequest>,
+        request: RRef<HelloRequest>,
     ) -> Result<WRef<HelloReply>, mrpc::Status> {
         // eprintln!("reply: {:?}", reply);
+        let start = Instant::now();
 
         let my_count = self.count.fetch_add(1, Ordering::AcqRel);
         let ret = Ok(WRef::clone(
             &self.replies[my_count % self.args.provision_count],
         ));
+        self.stats.record(request.name.len(), start.elapsed());
         return ret;
     }
 }
@@ -70,11 +234,19 @@ fn run_server(tid: usize, args: Args) -> Result<(), mrpc::Error> {
     // Set transport type
     let mut setting = mrpc::current_setting();
     setting.transport = args.transport;
+    setting.encryption = args.encryption;
     mrpc::set(&setting);
 
     // bind to NUMA node (tid % num_nodes)
     mrpc::bind_to_node((tid % mrpc::num_numa_nodes()) as u8);
 
+    let stats = Arc::new(Stats::new(&args));
+    {
+        let stats = Arc::clone(&stats);
+        let interval = Duration::from_secs_f64(args.interval);
+        std::thread::spawn(move || stats.report(tid, interval));
+    }
+
     smol::block_on(async {
         let mut replies = Vec::new();
         for _ in 0..args.provision_count {
@@ -88,6 +260,7 @@ fn run_server(tid: usize, args: Args) -> Result<(), mrpc::Error> {
             .add_service(GreeterServer::new(MyGreeter {
                 replies,
                 count: AtomicUsize::new(0),
+                stats,
                 args,
             }))
             .serve()
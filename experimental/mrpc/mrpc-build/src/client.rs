@@ -80,6 +80,10 @@ pub fn generate<T: Service>(
                         stub,
                     })
                 }
+                /// Returns the traffic and the resources of the connection as phoenixd sees them.
+                pub fn connection_stats(&self) -> Result<::mrpc::stub::TransportStats, ::mrpc::Error> {
+                    self.stub.connection_stats()
+                }
                 #methods
             }
 
@@ -111,6 +115,7 @@ fn generate_methods<T: Service>(
         // mRPC current doesn't not support streaming
         // Generate unary
         let ident = quote::format_ident!("{}", method.name());
+        let subscribe_ident = quote::format_ident!("subscribe_{}", method.name());
 
         let (request, response) =
             method.request_response_name(proto_path, compile_well_known_types);
@@ -126,6 +131,11 @@ fn generate_methods<T: Service>(
 
                 self.stub.unary(#service_id, #func_id, call_id, req.into_wref())
             }
+
+            /// Subscribes to the notifications the server pushes under this method.
+            pub fn #subscribe_ident(&self) -> ::mrpc::stub::Notifications<'_, #response> {
+                self.stub.subscribe(#func_id)
+            }
         };
 
         stream.extend(method);
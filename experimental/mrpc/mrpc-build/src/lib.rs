@@ -38,6 +38,23 @@
 //!    Ok(())
 //! }
 //!```
+//!
+//! A tree of proto files, with the imports resolved against several roots
+//!
+//! ```rust,no_run
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     mrpc_build::configure()
+//!         .build_client(false)
+//!         .protos_in("proto/services")
+//!         .proto("third_party/api/common.proto")
+//!         .include("proto")
+//!         .include("third_party")
+//!         .type_attribute(".", "#[derive(serde::Serialize)]")
+//!         .out_dir("src/generated")
+//!         .compile_configured()?;
+//!     Ok(())
+//! }
+//! ```
 
 #![deny(missing_docs)]
 #![deny(rustdoc::broken_intra_doc_links)]
@@ -52,6 +69,8 @@ pub use prost::{compile_protos, configure, Builder};
 
 /// Service code generation for client
 pub mod client;
+/// Test doubles code generation
+pub mod mock;
 /// Service code generation for Server
 pub mod server;
 
@@ -191,16 +210,30 @@ fn naive_snake_case(name: &str) -> String {
 }
 
 fn get_proto_packages<T: Service>(service: &T, proto_path: &str) -> BTreeSet<String> {
+    get_proto_package_items(service, proto_path, "PROTO_SRCS")
+}
+
+fn get_descriptor_sets<T: Service>(service: &T, proto_path: &str) -> BTreeSet<String> {
+    get_proto_package_items(service, proto_path, "FILE_DESCRIPTOR_SET")
+}
+
+// Returns the paths to an item in the `proto` module of the packages the messages of a service
+// come from.
+fn get_proto_package_items<T: Service>(
+    service: &T,
+    proto_path: &str,
+    item: &str,
+) -> BTreeSet<String> {
     let mut proto_packages = BTreeSet::new();
     for method in service.methods() {
         let (input_package, output_package) = method.request_response_package(proto_path);
         if let Some(pkg) = input_package {
-            let pkg_srcs = format!("{}::proto::PROTO_SRCS", pkg);
-            proto_packages.insert(pkg_srcs);
+            let pkg_item = format!("{}::proto::{}", pkg, item);
+            proto_packages.insert(pkg_item);
         }
         if let Some(pkg) = output_package {
-            let pkg_srcs = format!("{}::proto::PROTO_SRCS", pkg);
-            proto_packages.insert(pkg_srcs);
+            let pkg_item = format!("{}::proto::{}", pkg, item);
+            proto_packages.insert(pkg_item);
         }
     }
     proto_packages
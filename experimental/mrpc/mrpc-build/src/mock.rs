@@ -0,0 +1,186 @@
+use proc_macro2::TokenStream;
+
+use crate::{generate_doc_comments, naive_snake_case, Method, Service};
+
+/// Generate test doubles for a service.
+///
+/// This takes some `Service` and will generate a `TokenStream` that contains a public module
+/// with a mock client, and a tester of the service implementations if `with_tester` is set,
+/// which needs the generated server.
+pub fn generate<T: Service>(
+    service: &T,
+    proto_path: &str,
+    compile_well_known_types: bool,
+    with_tester: bool,
+) -> TokenStream {
+    let mock_mod = quote::format_ident!("{}_mock", naive_snake_case(service.name()));
+    let mock_client = generate_client(service, proto_path, compile_well_known_types);
+    let tester = if with_tester {
+        generate_tester(service, proto_path, compile_well_known_types)
+    } else {
+        TokenStream::new()
+    };
+
+    quote::quote! {
+        /// Generated test doubles.
+        pub mod #mock_mod {
+            #mock_client
+
+            #tester
+        }
+    }
+}
+
+fn generate_client<T: Service>(
+    service: &T,
+    proto_path: &str,
+    compile_well_known_types: bool,
+) -> TokenStream {
+    let mock_ident = quote::format_ident!("Mock{}Client", service.name());
+    let client_doc = format!(
+        " A stand-in for `{}Client` in unit tests, answering the calls with the handlers set on it\n \
+          instead of a server. A call without a handler fails with `Code::Unimplemented`.",
+        service.name()
+    );
+
+    let mut fields = TokenStream::new();
+    let mut inits = TokenStream::new();
+    let mut methods = TokenStream::new();
+
+    for method in service.methods() {
+        let ident = quote::format_ident!("{}", method.name());
+        let on_ident = quote::format_ident!("on_{}", method.name());
+        let unimplemented = format!("no handler for {}", method.name());
+
+        let (request, response) =
+            method.request_response_name(proto_path, compile_well_known_types);
+
+        fields.extend(quote::quote! {
+            #ident: Option<Box<dyn Fn(&#request) -> Result<#response, ::mrpc::Status> + Send + Sync>>,
+        });
+        inits.extend(quote::quote! {
+            #ident: None,
+        });
+
+        methods.extend(generate_doc_comments(method.comment()));
+        methods.extend(quote::quote! {
+            pub fn #ident(
+                &self,
+                req: impl ::mrpc::IntoWRef<#request>
+            ) -> impl std::future::Future<
+                Output = Result<::mrpc::RRef<#response>, ::mrpc::Status>
+            > + '_ {
+                let req = req.into_wref();
+                let reply = match &self.#ident {
+                    Some(handler) => handler(&req)
+                        .map(|reply| ::mrpc::testing::into_rref(::mrpc::WRef::new(reply))),
+                    None => Err(::mrpc::Status::unimplemented(#unimplemented)),
+                };
+                std::future::ready(reply)
+            }
+
+            /// Sets the handler answering the calls of this method.
+            pub fn #on_ident(
+                mut self,
+                handler: impl Fn(&#request) -> Result<#response, ::mrpc::Status> + Send + Sync + 'static,
+            ) -> Self {
+                self.#ident = Some(Box::new(handler));
+                self
+            }
+        });
+    }
+
+    let name = mock_ident.to_string();
+
+    quote::quote! {
+        #[doc = #client_doc]
+        pub struct #mock_ident {
+            #fields
+        }
+
+        impl #mock_ident {
+            /// Constructs a mock client without any handler. The messages are allocated on a heap
+            /// of the process itself from then on, see `mrpc::testing::use_local_heap`.
+            pub fn new() -> Self {
+                ::mrpc::testing::use_local_heap();
+                Self {
+                    #inits
+                }
+            }
+
+            #methods
+        }
+
+        impl Default for #mock_ident {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl std::fmt::Debug for #mock_ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct(#name).finish_non_exhaustive()
+            }
+        }
+    }
+}
+
+fn generate_tester<T: Service>(
+    service: &T,
+    proto_path: &str,
+    compile_well_known_types: bool,
+) -> TokenStream {
+    let tester_ident = quote::format_ident!("{}Tester", service.name());
+    let server_trait = quote::format_ident!("{}", service.name());
+    let server_mod = quote::format_ident!("{}_server", naive_snake_case(service.name()));
+    let tester_doc = format!(
+        " Calls a `{}` implementation directly in unit tests, without phoenixd. The requests and\n \
+          the replies are delivered as if they had gone through the backend.",
+        service.name()
+    );
+
+    let mut methods = TokenStream::new();
+
+    for method in service.methods() {
+        let ident = quote::format_ident!("{}", method.name());
+
+        let (request, response) =
+            method.request_response_name(proto_path, compile_well_known_types);
+
+        methods.extend(generate_doc_comments(method.comment()));
+        methods.extend(quote::quote! {
+            pub async fn #ident(
+                &self,
+                req: impl ::mrpc::IntoWRef<#request>
+            ) -> Result<::mrpc::RRef<#response>, ::mrpc::Status> {
+                let req = ::mrpc::testing::into_rref(req.into_wref());
+                let reply = self.inner.#ident(req).await?;
+                Ok(::mrpc::testing::into_rref(reply))
+            }
+        });
+    }
+
+    quote::quote! {
+        #[doc = #tester_doc]
+        #[derive(Debug)]
+        pub struct #tester_ident<T: super::#server_mod::#server_trait> {
+            inner: T,
+        }
+
+        impl<T: super::#server_mod::#server_trait> #tester_ident<T> {
+            /// Constructs a tester of `inner`. The messages are allocated on a heap of the process
+            /// itself from then on, see `mrpc::testing::use_local_heap`.
+            pub fn new(inner: T) -> Self {
+                ::mrpc::testing::use_local_heap();
+                Self { inner }
+            }
+
+            /// Returns the service implementation.
+            pub fn get_ref(&self) -> &T {
+                &self.inner
+            }
+
+            #methods
+        }
+    }
+}
@@ -1,13 +1,16 @@
+use std::collections::BTreeSet;
 use std::ffi::OsString;
-use std::io;
 use std::path::{Path, PathBuf};
+use std::{fs, io, mem};
 
 use proc_macro2::TokenStream;
 use prost_build::Config;
 use quote::quote;
 
 use crate::attribute::Attributes;
-use crate::{client, server};
+use crate::{client, mock, server};
+
+const FILE_DESCRIPTOR_SET_FILENAME: &str = "mrpc_file_descriptor_set.bin";
 
 /// Simple `.proto` compiling. Use [`configure`] instead if you need more options.
 ///
@@ -33,6 +36,7 @@ pub fn configure() -> Builder {
     Builder {
         build_client: true,
         build_server: true,
+        build_mocks: false,
         server_attributes: Attributes::default(),
         client_attributes: Attributes::default(),
         proto_path: "super".to_string(),
@@ -45,6 +49,11 @@ pub fn configure() -> Builder {
         protoc_args: Vec::new(),
         include_file: None,
         out_dir: None,
+        json_transcoding: false,
+        protos: Vec::new(),
+        proto_dirs: Vec::new(),
+        includes: Vec::new(),
+        emit_rerun_if_changed: true,
     }
 }
 
@@ -54,6 +63,7 @@ pub struct Builder {
     // Switches
     pub(crate) build_client: bool,
     pub(crate) build_server: bool,
+    pub(crate) build_mocks: bool,
     // client/server service settings
     pub(crate) server_attributes: Attributes,
     pub(crate) client_attributes: Attributes,
@@ -68,6 +78,12 @@ pub struct Builder {
     pub(crate) protoc_args: Vec<OsString>,
     pub(crate) include_file: Option<PathBuf>,
     out_dir: Option<PathBuf>,
+    pub(crate) json_transcoding: bool,
+    // the files to compile by `compile_configured`
+    protos: Vec<PathBuf>,
+    proto_dirs: Vec<PathBuf>,
+    includes: Vec<PathBuf>,
+    emit_rerun_if_changed: bool,
 }
 
 impl Builder {
@@ -81,10 +97,51 @@ impl Builder {
         self.compile_with_config(Config::new(), protos, includes)
     }
 
+    /// Compile the .proto files added by [`proto`], [`protos`], and [`protos_in`], searching
+    /// the imports in the roots added by [`include`], and execute code generation.
+    ///
+    /// Without any root, the directories given to [`protos_in`] and those the files given to
+    /// [`proto`] reside in are the roots.
+    ///
+    /// [`proto`]: Builder::proto
+    /// [`protos`]: Builder::protos
+    /// [`protos_in`]: Builder::protos_in
+    /// [`include`]: Builder::include
+    pub fn compile_configured(mut self) -> io::Result<()> {
+        let mut protos = mem::take(&mut self.protos);
+        for dir in self.proto_dirs.iter() {
+            collect_protos(dir, &mut protos)?;
+        }
+        if protos.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no .proto file to compile",
+            ));
+        }
+
+        let mut includes = mem::take(&mut self.includes);
+        if includes.is_empty() {
+            includes.extend(self.proto_dirs.iter().cloned());
+            for dir in protos.iter().filter_map(|proto| proto.parent()) {
+                if !includes.iter().any(|root| dir.starts_with(root)) {
+                    includes.push(dir.to_path_buf());
+                }
+            }
+        }
+
+        if self.emit_rerun_if_changed {
+            for dir in self.proto_dirs.iter() {
+                println!("cargo:rerun-if-changed={}", dir.display());
+            }
+        }
+
+        self.compile(&protos, &includes)
+    }
+
     /// Compile the .proto files and execute code generation using a
     /// custom `prost_build::Config`.
     pub fn compile_with_config(
-        self,
+        mut self,
         mut config: Config,
         protos: &[impl AsRef<Path>],
         includes: &[impl AsRef<Path>],
@@ -95,10 +152,13 @@ impl Builder {
             PathBuf::from(std::env::var("OUT_DIR").unwrap())
         };
 
+        // The descriptors are always needed, they are embedded in the generated code for
+        // reflection.
+        let file_descriptor_set_path = self
+            .file_descriptor_set_path
+            .get_or_insert_with(|| out_dir.join(FILE_DESCRIPTOR_SET_FILENAME));
+        config.file_descriptor_set_path(file_descriptor_set_path);
         config.out_dir(out_dir);
-        if let Some(path) = self.file_descriptor_set_path.as_ref() {
-            config.file_descriptor_set_path(path);
-        }
         for (proto_path, rust_path) in self.extern_path.iter() {
             config.extern_path(proto_path, rust_path);
         }
@@ -108,6 +168,13 @@ impl Builder {
         for (prost_path, attr) in self.type_attributes.iter() {
             config.type_attribute(prost_path, attr);
         }
+        if self.json_transcoding {
+            config.type_attribute(
+                ".",
+                "#[derive(::mrpc::serde::Serialize, ::mrpc::serde::Deserialize)]",
+            );
+            config.type_attribute(".", "#[serde(crate = \"::mrpc::serde\")]");
+        }
         if self.compile_well_known_types {
             config.compile_well_known_types();
         }
@@ -119,6 +186,12 @@ impl Builder {
             config.protoc_arg(arg);
         }
 
+        if self.emit_rerun_if_changed {
+            for proto in protos.iter() {
+                println!("cargo:rerun-if-changed={}", proto.as_ref().display());
+            }
+        }
+
         config.service_generator(self.service_generator());
 
         config.compile_protos_mrpc_frontend(protos, includes)?;
@@ -144,13 +217,69 @@ impl Builder {
         self
     }
 
+    /// Enable or disable generating test doubles, see `mrpc::testing`: a mock client for each
+    /// service, and a tester calling a service implementation directly if the server is also
+    /// generated. They run without phoenixd, for the unit tests of applications.
+    ///
+    /// This defaults to `false`. Enabling it does not change the client or the server generated.
+    pub fn build_mocks(mut self, enable: bool) -> Self {
+        self.build_mocks = enable;
+        self
+    }
+
     /// Generate a file containing the encoded `prost_types::FileDescriptorSet` for protocol buffers
-    /// modules. This is required for implementing gRPC Server Reflection.
+    /// modules. The contents are also embedded in the generated code as `proto::FILE_DESCRIPTOR_SET`.
+    ///
+    /// Defaults to a file in the output directory.
     pub fn file_descriptor_set_path(mut self, path: impl AsRef<Path>) -> Self {
         self.file_descriptor_set_path = Some(path.as_ref().to_path_buf());
         self
     }
 
+    /// Add a .proto file to compile by [`compile_configured`].
+    ///
+    /// [`compile_configured`]: Builder::compile_configured
+    pub fn proto(mut self, path: impl AsRef<Path>) -> Self {
+        self.protos.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add the .proto files to compile by [`compile_configured`].
+    ///
+    /// [`compile_configured`]: Builder::compile_configured
+    pub fn protos<P: AsRef<Path>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.protos
+            .extend(paths.into_iter().map(|p| p.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Add all the .proto files under a directory, recursively, to compile by
+    /// [`compile_configured`].
+    ///
+    /// [`compile_configured`]: Builder::compile_configured
+    pub fn protos_in(mut self, dir: impl AsRef<Path>) -> Self {
+        self.proto_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a root directory to search the imports of the .proto files in, for
+    /// [`compile_configured`]. The roots are searched in the order they are added.
+    ///
+    /// [`compile_configured`]: Builder::compile_configured
+    pub fn include(mut self, dir: impl AsRef<Path>) -> Self {
+        self.includes.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Enable or disable printing `cargo:rerun-if-changed` for the .proto files compiled, so that
+    /// the build script runs again when they change.
+    ///
+    /// This defaults to `true`.
+    pub fn emit_rerun_if_changed(mut self, enable: bool) -> Self {
+        self.emit_rerun_if_changed = enable;
+        self
+    }
+
     /// Set the output directory to generate code to.
     ///
     /// Defaults to the `OUT_DIR` environment variable.
@@ -181,7 +310,9 @@ impl Builder {
         self
     }
 
-    /// Add additional attribute to matched messages, enums, and one-offs.
+    /// Add additional attribute to matched messages, enums, and one-offs, e.g.,
+    /// `type_attribute(".my.package", "#[derive(serde::Serialize)]")`. The path `"."` matches
+    /// all types.
     ///
     /// Passed directly to `prost_build::Config.type_attribute`.
     pub fn type_attribute<P: AsRef<str>, A: AsRef<str>>(mut self, path: P, attribute: A) -> Self {
@@ -270,12 +401,27 @@ impl Builder {
         self.include_file = Some(path.as_ref().to_path_buf());
         self
     }
+
+    /// Enable or disable JSON transcoding of the generated messages.
+    ///
+    /// When enabled, all messages derive `serde` traits, and the request and response types of
+    /// the services get `to_json` and `from_json` methods. See `mrpc::json`.
+    ///
+    /// This defaults to `false`.
+    pub fn json_transcoding(mut self, enable: bool) -> Self {
+        self.json_transcoding = enable;
+        self
+    }
 }
 
 struct ServiceGenerator {
     builder: Builder,
     clients: TokenStream,
     servers: TokenStream,
+    mocks: TokenStream,
+    json_methods: TokenStream,
+    // (package, message) that already have the JSON methods
+    json_messages: BTreeSet<(String, String)>,
 }
 
 impl ServiceGenerator {
@@ -284,12 +430,56 @@ impl ServiceGenerator {
             builder,
             clients: TokenStream::default(),
             servers: TokenStream::default(),
+            mocks: TokenStream::default(),
+            json_methods: TokenStream::default(),
+            json_messages: BTreeSet::new(),
+        }
+    }
+
+    fn generate_json_methods(&mut self, service: &prost_build::Service) {
+        for method in service.methods.iter() {
+            let messages = [
+                (&method.input_proto_type, &method.input_type),
+                (&method.output_proto_type, &method.output_type),
+            ];
+            for (proto_type, rust_type) in messages {
+                // Inherent methods can only be added to the types generated in this module.
+                if rust_type.contains("::")
+                    || NON_PATH_TYPE_ALLOWLIST.iter().any(|ty| ty == rust_type)
+                    || (is_google_type(proto_type) && !self.builder.compile_well_known_types)
+                {
+                    continue;
+                }
+                if !self
+                    .json_messages
+                    .insert((service.package.clone(), rust_type.clone()))
+                {
+                    continue;
+                }
+                let ident = quote::format_ident!("{}", rust_type);
+                self.json_methods.extend(quote! {
+                    impl #ident {
+                        /// Renders the message in JSON.
+                        pub fn to_json(&self) -> Result<::std::string::String, ::mrpc::json::Error> {
+                            ::mrpc::json::to_json(self)
+                        }
+                        /// Constructs the message from JSON.
+                        pub fn from_json(json: &str) -> Result<Self, ::mrpc::json::Error> {
+                            ::mrpc::json::from_json(json)
+                        }
+                    }
+                });
+            }
         }
     }
 }
 
 impl prost_build::ServiceGenerator for ServiceGenerator {
     fn generate(&mut self, service: prost_build::Service, _buf: &mut String) {
+        if self.builder.json_transcoding {
+            self.generate_json_methods(&service);
+        }
+
         if self.builder.build_server {
             let server = server::generate(
                 &service,
@@ -301,6 +491,16 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
             self.servers.extend(server);
         }
 
+        if self.builder.build_mocks {
+            let mock = mock::generate(
+                &service,
+                &self.builder.proto_path,
+                self.builder.compile_well_known_types,
+                self.builder.build_server,
+            );
+            self.mocks.extend(mock);
+        }
+
         if self.builder.build_client {
             let client = client::generate(
                 &service,
@@ -314,6 +514,13 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
     }
 
     fn finalize(&mut self, buf: &mut String) {
+        if !self.json_methods.is_empty() {
+            let json_methods = mem::take(&mut self.json_methods);
+            let ast: syn::File = syn::parse2(json_methods).expect("not a valid tokenstream");
+            let code = prettyplease::unparse(&ast);
+            buf.push_str(&code);
+        }
+
         if self.builder.build_client && !self.clients.is_empty() {
             let clients = &self.clients;
 
@@ -341,6 +548,13 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
 
             self.servers = TokenStream::default();
         }
+
+        if self.builder.build_mocks && !self.mocks.is_empty() {
+            let mocks = mem::take(&mut self.mocks);
+            let ast: syn::File = syn::parse2(mocks).expect("not a valid tokenstream");
+            let code = prettyplease::unparse(&ast);
+            buf.push_str(&code);
+        }
     }
 
     fn finalize_package(&mut self, package: prost_build::Package, buf: &mut String) {
@@ -351,9 +565,19 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
             proto_srcs.push(src);
         }
 
+        // The descriptor set is written by prost-build before the generated code is compiled.
+        let file_descriptor_set = match self.builder.file_descriptor_set_path.as_ref() {
+            Some(path) => {
+                let path = path.display().to_string();
+                quote! { include_bytes!(#path) }
+            }
+            None => quote! { &[] },
+        };
+
         let tokens = quote! {
             pub mod #package_mod {
                 pub const PROTO_SRCS: &[&str] = &[#(#proto_srcs),*];
+                pub const FILE_DESCRIPTOR_SET: &[u8] = #file_descriptor_set;
             }
         };
         let ast: syn::File = syn::parse2(tokens).expect("not a valid tokenstream");
@@ -459,3 +683,51 @@ impl crate::Method for prost_build::Method {
 fn is_google_type(ty: &str) -> bool {
     ty.starts_with(".google.protobuf")
 }
+
+// Appends the .proto files under `dir`, recursively, in a deterministic order.
+fn collect_protos(dir: &Path, protos: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_protos(&path, protos)?;
+        } else if path.extension().map_or(false, |ext| ext == "proto") {
+            protos.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_protos_recursively() {
+        let root = std::env::temp_dir().join(format!("mrpc-build-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("b/nested")).unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        for file in ["b/nested/z.proto", "b/y.proto", "a/x.proto", "a/README.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let mut protos = Vec::new();
+        collect_protos(&root, &mut protos).unwrap();
+        let relative = protos
+            .iter()
+            .map(|p| p.strip_prefix(&root).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            relative,
+            ["a/x.proto", "b/nested/z.proto", "b/y.proto"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -2,8 +2,8 @@ use proc_macro2::TokenStream;
 
 use crate::attribute::Attributes;
 use crate::{
-    generate_doc_comments, get_method_path, get_proto_packages, get_service_path, mrpc_get_func_id,
-    mrpc_get_service_id, naive_snake_case, Method, Service,
+    generate_doc_comments, get_descriptor_sets, get_method_path, get_proto_packages,
+    get_service_path, mrpc_get_func_id, mrpc_get_service_id, naive_snake_case, Method, Service,
 };
 
 /// Generate service for server.
@@ -18,6 +18,7 @@ pub fn generate<T: Service>(
     attributes: &Attributes,
 ) -> TokenStream {
     let methods = generate_methods(service, proto_path, compile_well_known_types);
+    let notifiers = generate_notifiers(service, emit_package, proto_path, compile_well_known_types);
 
     let server_service = quote::format_ident!("{}Server", service.name());
     let server_trait = quote::format_ident!("{}", service.name());
@@ -29,6 +30,8 @@ pub fn generate<T: Service>(
         compile_well_known_types,
         server_trait.clone(),
     );
+    let generated_handlers =
+        generate_handlers(service, proto_path, compile_well_known_types, &server_trait);
 
     let service_doc = generate_doc_comments(service.comment());
     let package = if emit_package { service.package() } else { "" };
@@ -45,6 +48,10 @@ pub fn generate<T: Service>(
         .into_iter()
         .map(|x| syn::parse_str::<syn::Path>(&x).unwrap());
 
+    let descriptor_sets = get_descriptor_sets(service, proto_path)
+        .into_iter()
+        .map(|x| syn::parse_str::<syn::Path>(&x).unwrap());
+
     quote::quote! {
         /// Generated server implementations.
         #(#mod_attributes)*
@@ -53,6 +60,8 @@ pub fn generate<T: Service>(
 
             #generated_trait
 
+            #generated_handlers
+
             #service_doc
             #(#struct_attributes)*
             #[derive(Debug)]
@@ -60,18 +69,37 @@ pub fn generate<T: Service>(
             // Translate the reply type to erased message again and put to write shared heap.
             pub struct #server_service<T: #server_trait> {
                 inner: T,
+                size_limit: ::mrpc::stub::MessageSizeLimit,
             }
 
             impl<T: #server_trait> #server_service<T> {
                 fn update_protos() -> Result<(), ::mrpc::Error> {
                     let srcs = [#(#proto_srcs),*].concat();
-                    ::mrpc::stub::update_protos(srcs.as_slice())
+                    ::mrpc::stub::update_protos(srcs.as_slice())?;
+                    // Registers the descriptors for reflection
+                    let descriptor_sets = [#(#descriptor_sets),*];
+                    ::mrpc::stub::update_descriptors(descriptor_sets.as_slice())
                 }
 
                 pub fn new(inner: T) -> Self {
                     // TODO: handle error here
                     Self::update_protos().unwrap();
-                    Self { inner }
+                    Self {
+                        inner,
+                        size_limit: Default::default(),
+                    }
+                }
+
+                /// Limits the size of the marshalled requests of this service, in bytes.
+                pub fn max_request_size(mut self, limit: usize) -> Self {
+                    self.size_limit.max_request_size = Some(limit);
+                    self
+                }
+
+                /// Limits the size of the marshalled responses of this service, in bytes.
+                pub fn max_response_size(mut self, limit: usize) -> Self {
+                    self.size_limit.max_response_size = Some(limit);
+                    self
                 }
             }
 
@@ -86,21 +114,65 @@ pub fn generate<T: Service>(
                     &self,
                     req_opaque: ::mrpc::MessageErased,
                     read_heap: std::sync::Arc<::mrpc::ReadHeap>,
+                    conn: std::sync::Arc<::mrpc::stub::ConnectionContext>,
                 ) -> (::mrpc::WRefOpaque, ::mrpc::MessageErased) {
                     let func_id = req_opaque.meta.func_id;
 
                     match func_id {
                         #methods
                         _ => {
-                            todo!("error handling for unknown func_id: {}", func_id);
+                            let status = ::mrpc::Status::unimplemented(
+                                format!("unknown func_id: {}", func_id)
+                            );
+                            ::mrpc::stub::service_error_handler(status, &req_opaque)
                         }
                     }
                 }
+
+                fn message_size_limit(&self) -> ::mrpc::stub::MessageSizeLimit {
+                    self.size_limit
+                }
             }
+
+            #notifiers
         }
     }
 }
 
+fn generate_notifiers<T: Service>(
+    service: &T,
+    emit_package: bool,
+    proto_path: &str,
+    compile_well_known_types: bool,
+) -> TokenStream {
+    let mut stream = TokenStream::new();
+    // the ids must match those the client subscribes to
+    let package = if emit_package { service.package() } else { "" };
+    let service_id = mrpc_get_service_id(&get_service_path(package, service));
+
+    for method in service.methods() {
+        let func_id = mrpc_get_func_id(&get_method_path(package, service, method));
+        let notify_ident = quote::format_ident!("notify_{}", method.name());
+
+        let (_req_type, res_type) =
+            method.request_response_name(proto_path, compile_well_known_types);
+
+        let notifier = quote::quote! {
+            /// Pushes a notification under this method to the client of `conn`.
+            pub fn #notify_ident(
+                conn: &::mrpc::stub::ConnectionContext,
+                msg: impl ::mrpc::IntoWRef<#res_type>,
+            ) -> Result<(), ::mrpc::Error> {
+                conn.notify(#service_id, #func_id, msg.into_wref())
+            }
+        };
+
+        stream.extend(notifier);
+    }
+
+    stream
+}
+
 fn generate_trait<T: Service>(
     service: &T,
     proto_path: &str,
@@ -117,6 +189,97 @@ fn generate_trait<T: Service>(
     }
 }
 
+fn generate_handlers<T: Service>(
+    service: &T,
+    proto_path: &str,
+    compile_well_known_types: bool,
+    server_trait: &syn::Ident,
+) -> TokenStream {
+    let handlers_ident = quote::format_ident!("{}Handlers", service.name());
+    let handlers_doc = format!(
+        " Implements `{}` with the handlers registered for its methods at runtime, e.g., by\n \
+          plugins. A method without a handler returns `Code::Unimplemented`.",
+        server_trait
+    );
+
+    let mut fields = TokenStream::new();
+    let mut registers = TokenStream::new();
+    let mut trait_methods = TokenStream::new();
+
+    for method in service.methods() {
+        let name = quote::format_ident!("{}", method.name());
+        let on_name = quote::format_ident!("on_{}", method.name());
+        let register_doc = format!(
+            " Registers the handler of `{}`, replacing the one registered before.",
+            method.name()
+        );
+        let unimplemented = format!("{} is not implemented", method.name());
+
+        let (req_type, res_type) =
+            method.request_response_name(proto_path, compile_well_known_types);
+
+        fields.extend(quote::quote! {
+            #name: Option<::mrpc::stub::MethodHandler<#req_type, #res_type>>,
+        });
+
+        registers.extend(quote::quote! {
+            #[doc = #register_doc]
+            pub fn #on_name<F, Fut>(mut self, handler: F) -> Self
+            where
+                F: Fn(::mrpc::RRef<#req_type>) -> Fut + Send + Sync + 'static,
+                Fut: std::future::Future<
+                    Output = Result<::mrpc::WRef<#res_type>, ::mrpc::Status>
+                > + Send + 'static,
+            {
+                self.#name = Some(Box::new(move |request| Box::pin(handler(request))));
+                self
+            }
+        });
+
+        trait_methods.extend(quote::quote! {
+            async fn #name(
+                &self,
+                request: ::mrpc::RRef<#req_type>
+            ) -> Result<::mrpc::WRef<#res_type>, ::mrpc::Status> {
+                match &self.#name {
+                    Some(handler) => handler(request).await,
+                    None => Err(::mrpc::Status::unimplemented(#unimplemented)),
+                }
+            }
+        });
+    }
+
+    let name = handlers_ident.to_string();
+
+    quote::quote! {
+        #[doc = #handlers_doc]
+        #[derive(Default)]
+        pub struct #handlers_ident {
+            #fields
+        }
+
+        impl #handlers_ident {
+            /// Constructs the handlers without any method registered.
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            #registers
+        }
+
+        impl std::fmt::Debug for #handlers_ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct(#name).finish_non_exhaustive()
+            }
+        }
+
+        #[mrpc::async_trait]
+        impl #server_trait for #handlers_ident {
+            #trait_methods
+        }
+    }
+}
+
 fn generate_trait_methods<T: Service>(
     service: &T,
     proto_path: &str,
@@ -131,6 +294,7 @@ fn generate_trait_methods<T: Service>(
             method.request_response_name(proto_path, compile_well_known_types);
 
         let method_doc = generate_doc_comments(method.comment());
+        let unimplemented = format!("{} is not implemented", method.name());
 
         // mRPC does not support streaming
         let method = quote::quote! {
@@ -138,7 +302,10 @@ fn generate_trait_methods<T: Service>(
             async fn #name(
                 &self,
                 request: ::mrpc::RRef<#req_type>
-            ) -> Result<::mrpc::WRef<#res_type>, ::mrpc::Status>;
+            ) -> Result<::mrpc::WRef<#res_type>, ::mrpc::Status> {
+                drop(request);
+                Err(::mrpc::Status::unimplemented(#unimplemented))
+            }
         };
 
         stream.extend(method);
@@ -165,14 +332,14 @@ fn generate_methods<T: Service>(
         let match_branch = quote::quote! {
             #func_id => {
                 // let req_view = ::mrpc::stub::service_pre_handler(&req, reclaim_buffer);
-                let req = ::mrpc::RRef::new(&req_opaque, read_heap);
+                let req = ::mrpc::RRef::with_context(&req_opaque, read_heap, conn);
                 let res = self.inner.#func_ident(req).await;
                 match res {
                     Ok(reply) => {
                         ::mrpc::stub::service_post_handler(reply, &req_opaque)
                     }
-                    Err(_status) => {
-                        todo!();
+                    Err(status) => {
+                        ::mrpc::stub::service_error_handler(status, &req_opaque)
                     }
                 }
             },
@@ -91,16 +91,15 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
 
     let expanded = quote! {
         impl #impl_generics ::mrpc_marshal::RpcMessage for #ident #ty_generics #where_clause {
-            fn marshal(&self) -> std::result::Result<::mrpc_marshal::SgList, mrpc_marshal::MarshalError> {
-                let cap = 1 + self.extent();
-                let mut sgl = ::mrpc_marshal::SgList(std::vec::Vec::with_capacity(cap));
+            fn marshal_into(&self, sgl: &mut ::mrpc_marshal::SgList) -> std::result::Result<(), mrpc_marshal::MarshalError> {
+                sgl.0.clear();
+                sgl.0.reserve(1 + self.extent());
                 let self_sge = ::mrpc_marshal::SgE {
                     ptr: self as *const _ as usize,
                     len: std::mem::size_of::<Self>()
                 };
                 sgl.0.push(self_sge);
-                self.emplace(&mut sgl)?;
-                Ok(sgl)
+                self.emplace(sgl)
             }
 
             unsafe fn unmarshal<'a, A: ::mrpc_marshal::AddressArbiter>(
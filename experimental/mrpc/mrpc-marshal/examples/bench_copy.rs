@@ -0,0 +1,69 @@
+//! Compares `memcpy` with the copy path of mrpc-marshal over a range of field sizes.
+//!
+//! The destination buffers are rotated through a working set larger than the LLC, as the send
+//! buffers of an engine are.
+use std::time::{Duration, Instant};
+
+use mrpc_marshal::copy;
+
+// Total size of the destination buffers.
+const WORKING_SET: usize = 256 * 1024 * 1024;
+const SIZES: [usize; 8] = [
+    256,
+    4096,
+    64 * 1024,
+    256 * 1024,
+    1024 * 1024,
+    4 * 1024 * 1024,
+    16 * 1024 * 1024,
+    64 * 1024 * 1024,
+];
+
+fn print(desc: &str, size: usize, dura: Duration, num: usize) {
+    println!(
+        "{}, size: {}, duration: {:?}, num: {}, latency: {} ns/ops, tput: {:.2} GB/s",
+        desc,
+        size,
+        dura,
+        num,
+        dura.as_nanos() / num as u128,
+        (size * num) as f64 / dura.as_nanos() as f64,
+    );
+}
+
+fn bench<F: Fn(*const u8, *mut u8, usize)>(
+    desc: &str,
+    src: &[u8],
+    dst: &mut [u8],
+    size: usize,
+    f: F,
+) {
+    let slots = dst.len() / size;
+    let num = (WORKING_SET * 4 / size).clamp(16, 1_000_000);
+    let start = Instant::now();
+    for i in 0..num {
+        let off = (i % slots) * size;
+        f(src.as_ptr(), dst[off..].as_mut_ptr(), size);
+    }
+    print(desc, size, start.elapsed(), num);
+}
+
+fn main() {
+    println!("copy kind: {:?}", copy::copy_kind());
+    let src = vec![0x5au8; *SIZES.last().unwrap()];
+    let mut dst = vec![0u8; WORKING_SET];
+
+    for &size in SIZES.iter() {
+        bench("memcpy", &src, &mut dst, size, |s, d, n| unsafe {
+            std::ptr::copy_nonoverlapping(s, d, n);
+        });
+        copy::set_non_temporal_threshold(0);
+        bench("non-temporal", &src, &mut dst, size, |s, d, n| unsafe {
+            copy::copy_nonoverlapping(s, d, n);
+        });
+        copy::set_non_temporal_threshold(copy::DEFAULT_NON_TEMPORAL_THRESHOLD);
+        bench("default", &src, &mut dst, size, |s, d, n| unsafe {
+            copy::copy_nonoverlapping(s, d, n);
+        });
+    }
+}
@@ -0,0 +1,155 @@
+//! Copying of message fields where the copy cannot be avoided, e.g., writing a message into a
+//! send buffer or a heap of another process.
+//!
+//! Small copies are left to `memcpy`, which already uses `rep movsb` on CPUs with ERMS. Above
+//! [`non_temporal_threshold`], the destination is written with non-temporal stores, so that a
+//! large field does not evict the working set of the engine from the cache. The widest vector
+//! extension supported by the CPU is detected at runtime.
+use std::ptr;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// The default size above which the copies bypass the cache.
+pub const DEFAULT_NON_TEMPORAL_THRESHOLD: usize = 1024 * 1024;
+
+static NON_TEMPORAL_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_NON_TEMPORAL_THRESHOLD);
+
+/// The implementation of the copies above the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyKind {
+    /// No suitable vector extension, the copies always use `memcpy`.
+    Memcpy = 1,
+    Avx2 = 2,
+    Avx512 = 3,
+}
+
+// 0 for not detected yet
+static COPY_KIND: AtomicU8 = AtomicU8::new(0);
+
+/// Returns the size above which the copies bypass the cache.
+#[inline]
+pub fn non_temporal_threshold() -> usize {
+    NON_TEMPORAL_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Sets the size above which the copies bypass the cache. `usize::MAX` disables the
+/// non-temporal copies.
+pub fn set_non_temporal_threshold(bytes: usize) {
+    NON_TEMPORAL_THRESHOLD.store(bytes, Ordering::Relaxed);
+}
+
+/// Returns the implementation used on this CPU.
+#[inline]
+pub fn copy_kind() -> CopyKind {
+    match COPY_KIND.load(Ordering::Relaxed) {
+        1 => CopyKind::Memcpy,
+        2 => CopyKind::Avx2,
+        3 => CopyKind::Avx512,
+        _ => {
+            let kind = detect();
+            COPY_KIND.store(kind as u8, Ordering::Relaxed);
+            kind
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect() -> CopyKind {
+    if is_x86_feature_detected!("avx512f") {
+        CopyKind::Avx512
+    } else if is_x86_feature_detected!("avx2") {
+        CopyKind::Avx2
+    } else {
+        CopyKind::Memcpy
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn detect() -> CopyKind {
+    CopyKind::Memcpy
+}
+
+/// Copies `len` bytes from `src` to `dst`.
+///
+/// # Safety
+///
+/// Same as [`std::ptr::copy_nonoverlapping`].
+#[inline]
+pub unsafe fn copy_nonoverlapping(src: *const u8, dst: *mut u8, len: usize) {
+    if len < non_temporal_threshold() {
+        ptr::copy_nonoverlapping(src, dst, len);
+        return;
+    }
+    match copy_kind() {
+        #[cfg(target_arch = "x86_64")]
+        CopyKind::Avx512 => x86::copy_avx512(src, dst, len),
+        #[cfg(target_arch = "x86_64")]
+        CopyKind::Avx2 => x86::copy_avx2(src, dst, len),
+        _ => ptr::copy_nonoverlapping(src, dst, len),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+    use std::ptr;
+
+    /// Copies with 32-byte non-temporal stores.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn copy_avx2(src: *const u8, dst: *mut u8, len: usize) {
+        // the stores must be aligned
+        let head = dst.align_offset(32).min(len);
+        ptr::copy_nonoverlapping(src, dst, head);
+
+        let mut off = head;
+        while off + 128 <= len {
+            let s = src.add(off);
+            let d = dst.add(off);
+            let a = _mm256_loadu_si256(s.cast());
+            let b = _mm256_loadu_si256(s.add(32).cast());
+            let c = _mm256_loadu_si256(s.add(64).cast());
+            let e = _mm256_loadu_si256(s.add(96).cast());
+            _mm256_stream_si256(d.cast(), a);
+            _mm256_stream_si256(d.add(32).cast(), b);
+            _mm256_stream_si256(d.add(64).cast(), c);
+            _mm256_stream_si256(d.add(96).cast(), e);
+            off += 128;
+        }
+        while off + 32 <= len {
+            let a = _mm256_loadu_si256(src.add(off).cast());
+            _mm256_stream_si256(dst.add(off).cast(), a);
+            off += 32;
+        }
+        ptr::copy_nonoverlapping(src.add(off), dst.add(off), len - off);
+        // order the non-temporal stores before the buffer is handed to the NIC or another process
+        _mm_sfence();
+    }
+
+    /// Copies with 64-byte non-temporal stores.
+    #[target_feature(enable = "avx512f")]
+    pub(super) unsafe fn copy_avx512(src: *const u8, dst: *mut u8, len: usize) {
+        let head = dst.align_offset(64).min(len);
+        ptr::copy_nonoverlapping(src, dst, head);
+
+        let mut off = head;
+        while off + 256 <= len {
+            let s = src.add(off);
+            let d = dst.add(off);
+            let a = _mm512_loadu_si512(s.cast());
+            let b = _mm512_loadu_si512(s.add(64).cast());
+            let c = _mm512_loadu_si512(s.add(128).cast());
+            let e = _mm512_loadu_si512(s.add(192).cast());
+            _mm512_stream_si512(d.cast(), a);
+            _mm512_stream_si512(d.add(64).cast(), b);
+            _mm512_stream_si512(d.add(128).cast(), c);
+            _mm512_stream_si512(d.add(192).cast(), e);
+            off += 256;
+        }
+        while off + 64 <= len {
+            let a = _mm512_loadu_si512(src.add(off).cast());
+            _mm512_stream_si512(dst.add(off).cast(), a);
+            off += 64;
+        }
+        ptr::copy_nonoverlapping(src.add(off), dst.add(off), len - off);
+        _mm_sfence();
+    }
+}
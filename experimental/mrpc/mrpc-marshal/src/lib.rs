@@ -5,6 +5,9 @@
 #![feature(core_intrinsics)]
 #![feature(allocator_api)]
 #![feature(alloc_layout_extra)]
+// AVX-512 intrinsics for the copy path
+#![feature(stdsimd)]
+#![feature(avx512_target_feature)]
 
 use std::collections::BTreeMap;
 
@@ -13,7 +16,10 @@ use thiserror::Error;
 
 use shm::ptr::ShmPtr;
 
+pub mod copy;
 pub mod emplacement;
+#[cfg(feature = "seal")]
+pub mod seal;
 pub mod shadow {
     use crate::alloc::PrivateHeap;
 
@@ -62,7 +68,8 @@ pub mod alloc {
 
 #[derive(Error, Debug)]
 pub enum MarshalError {
-    // TBD
+    #[error("unknown func_id: {0}")]
+    UnknownFuncId(u32),
 }
 
 #[derive(Error, Debug)]
@@ -73,14 +80,12 @@ pub enum UnmarshalError {
     SgListUnderflow,
     #[error("query app addr failed: {0}")]
     QueryAppAddr(#[from] AddressNotFound),
+    #[error("unknown func_id: {0}")]
+    UnknownFuncId(u32),
 }
 
-#[derive(Debug)]
-pub struct ShmRecvMr {
-    pub ptr: usize,
-    pub len: usize,
-    pub align: usize,
-}
+/// A receive buffer region as the application has mapped it.
+pub type ShmRecvMr = shm::region::RegionView;
 
 #[derive(Error, Debug, Clone)]
 #[error("address {0} not found")]
@@ -111,7 +116,15 @@ pub struct ExcavateContext<'a, A: AddressArbiter> {
 }
 
 pub trait RpcMessage: Sized {
-    fn marshal(&self) -> Result<SgList, MarshalError>;
+    fn marshal(&self) -> Result<SgList, MarshalError> {
+        let mut sgl = SgList(Vec::with_capacity(1 + self.extent()));
+        self.marshal_into(&mut sgl)?;
+        Ok(sgl)
+    }
+
+    /// Marshals the message into `sgl`, reusing its allocation. The previous content of `sgl` is
+    /// discarded.
+    fn marshal_into(&self, sgl: &mut SgList) -> Result<(), MarshalError>;
 
     /// # Safety
     ///
@@ -142,7 +155,10 @@ pub trait RpcMessage: Sized {
 pub struct AddressExists(pub usize);
 
 // pub type AddressMap = NaiveAddressMap;
+#[cfg(not(feature = "sanitize"))]
 pub type AddressMap = NoopAddressMap;
+#[cfg(feature = "sanitize")]
+pub type AddressMap = SimulatedAddressMap;
 
 #[allow(unused)]
 pub struct NaiveAddressMap(spin::Mutex<BTreeMap<usize, ShmRecvMr>>);
@@ -231,3 +247,46 @@ impl NoopAddressMap {
         Ok(())
     }
 }
+
+/// Maps the receive buffers on the backend to the app at [`SIMULATED_APP_OFFSET`] bytes from
+/// them, as the regions allocated with the `sanitize` feature are simulated to be. The addresses
+/// of the app are not allocated in the backend, so a backend reading one of them in place of its
+/// own is reported by Miri or the sanitizers, instead of reading the same memory.
+///
+/// [`SIMULATED_APP_OFFSET`]: shm::region::SIMULATED_APP_OFFSET
+#[cfg(feature = "sanitize")]
+pub struct SimulatedAddressMap;
+
+#[cfg(feature = "sanitize")]
+impl AddressArbiter for SimulatedAddressMap {
+    #[inline]
+    fn query_app_addr(&self, backend_addr: usize) -> Result<usize, AddressNotFound> {
+        backend_addr
+            .checked_add(shm::region::SIMULATED_APP_OFFSET)
+            .ok_or(AddressNotFound(backend_addr))
+    }
+}
+
+#[cfg(feature = "sanitize")]
+impl Default for SimulatedAddressMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "sanitize")]
+impl SimulatedAddressMap {
+    #[inline]
+    pub fn new() -> Self {
+        SimulatedAddressMap
+    }
+
+    #[inline]
+    pub fn insert_addr_map(
+        &self,
+        _local_addr: usize,
+        _remote_buf: ShmRecvMr,
+    ) -> Result<(), AddressExists> {
+        Ok(())
+    }
+}
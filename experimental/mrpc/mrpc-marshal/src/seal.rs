@@ -0,0 +1,406 @@
+//! Encryption of marshalled messages, for the RPC adapters.
+//!
+//! The keys are negotiated per connection while it is established: each end sends an ephemeral
+//! X25519 public key in the private data of the connection request or reply, and both derive
+//! one AES-256-GCM key for each direction from the shared secret with HKDF-SHA256.
+//!
+//! A sealed message is a single scatter-gather element:
+//!
+//! ```text
+//! | nonce | num_sge | lens[0] ... | value[0] ... | tag |
+//! |   8   |    4    | 4 * num_sge | ...          | 16  |
+//! ```
+//!
+//! Everything between the nonce and the tag is encrypted, the integers are little-endian. The
+//! nonce counts the messages sent in one direction, so a message replayed on the connection is
+//! rejected. The message header is not encrypted, but the caller authenticates the fields that
+//! identify the call with the additional data.
+use std::mem;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce, Tag};
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use sha2::Sha256;
+use thiserror::Error;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::{SgE, SgList};
+
+/// Marks the public key in the private data of a connection.
+pub const KEY_MAGIC: &[u8; 4] = b"phxk";
+/// The length of the private data of [`Handshake::private_data`].
+pub const PRIVATE_DATA_LEN: usize = KEY_MAGIC.len() + 32;
+
+const NONCE_LEN: usize = mem::size_of::<u64>();
+const TAG_LEN: usize = 16;
+const KDF_INFO: &[u8] = b"phoenix-rpc-adapter";
+
+#[derive(Error, Debug)]
+pub enum SealError {
+    #[error("the peer did not offer a key")]
+    NoKey,
+    #[error("the key of the peer is not contributory")]
+    WeakKey,
+    #[error("sealed message of {0} bytes is truncated")]
+    Truncated(usize),
+    #[error("message {nonce} is replayed, expected at least {expected}")]
+    Replayed { nonce: u64, expected: u64 },
+    #[error("message fails to authenticate")]
+    Authentication,
+}
+
+/// The end of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// The end that connects.
+    Initiator,
+    /// The end that accepts.
+    Responder,
+}
+
+/// The key exchange of a connection being established.
+pub struct Handshake {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handshake {
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+        Handshake { secret, public }
+    }
+
+    /// The private data to send to the peer with the connection request or reply.
+    pub fn private_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(PRIVATE_DATA_LEN);
+        data.extend_from_slice(KEY_MAGIC);
+        data.extend_from_slice(self.public.as_bytes());
+        data
+    }
+
+    /// Derives the keys of the connection from the private data received from the peer. The
+    /// private data may be padded by the transport, or carry other data before the key.
+    pub fn finish(self, role: Role, peer_private_data: &[u8]) -> Result<SessionCipher, SealError> {
+        let peer = find_key(peer_private_data).ok_or(SealError::NoKey)?;
+        let shared = self.secret.diffie_hellman(&peer);
+        if !shared.was_contributory() {
+            return Err(SealError::WeakKey);
+        }
+
+        let (initiator, responder) = match role {
+            Role::Initiator => (self.public, peer),
+            Role::Responder => (peer, self.public),
+        };
+        let hkdf = Hkdf::<Sha256>::new(None, shared.as_bytes());
+        let mut okm = [0u8; 64];
+        hkdf.expand_multi_info(
+            &[KDF_INFO, initiator.as_bytes(), responder.as_bytes()],
+            &mut okm,
+        )
+        .expect("64 bytes is a valid length for HKDF-SHA256");
+        let (to_responder, to_initiator) = okm.split_at(32);
+        let (sealer, opener) = match role {
+            Role::Initiator => (to_responder, to_initiator),
+            Role::Responder => (to_initiator, to_responder),
+        };
+        Ok(SessionCipher {
+            sealer: Aes256Gcm::new_from_slice(sealer).unwrap(),
+            opener: Aes256Gcm::new_from_slice(opener).unwrap(),
+            next_nonce: AtomicU64::new(0),
+            next_expected: AtomicU64::new(0),
+        })
+    }
+}
+
+/// Whether the private data of the peer offers a key.
+pub fn offers_key(peer_private_data: &[u8]) -> bool {
+    find_key(peer_private_data).is_some()
+}
+
+fn find_key(private_data: &[u8]) -> Option<PublicKey> {
+    let pos = private_data
+        .windows(KEY_MAGIC.len())
+        .position(|w| w == KEY_MAGIC)?;
+    let key = private_data.get(pos + KEY_MAGIC.len()..pos + PRIVATE_DATA_LEN)?;
+    Some(PublicKey::from(<[u8; 32]>::try_from(key).unwrap()))
+}
+
+/// The keys of a connection, one for each direction.
+pub struct SessionCipher {
+    sealer: Aes256Gcm,
+    opener: Aes256Gcm,
+    next_nonce: AtomicU64,
+    next_expected: AtomicU64,
+}
+
+impl std::fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionCipher")
+            .field("next_nonce", &self.next_nonce)
+            .field("next_expected", &self.next_expected)
+            .finish_non_exhaustive()
+    }
+}
+
+#[inline]
+fn nonce(counter: u64) -> Nonce<U12> {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce.into()
+}
+
+/// The length of `sgl` once sealed.
+#[inline]
+pub fn sealed_len(sgl: &SgList) -> usize {
+    let payload: usize = sgl.0.iter().map(|sge| 4 + sge.len).sum();
+    NONCE_LEN + 4 + payload + TAG_LEN
+}
+
+impl SessionCipher {
+    /// Seals the elements of `sgl` into `buf`, authenticating `aad` with them. The previous
+    /// content of `buf` is discarded.
+    ///
+    /// # Safety
+    ///
+    /// The elements of `sgl` must be valid for reads.
+    pub unsafe fn seal(&self, sgl: &SgList, aad: &[u8], buf: &mut Vec<u8>) {
+        buf.clear();
+        buf.reserve(sealed_len(sgl));
+        let counter = self.next_nonce.fetch_add(1, Ordering::Relaxed);
+        buf.extend_from_slice(&counter.to_le_bytes());
+        buf.extend_from_slice(&(sgl.0.len() as u32).to_le_bytes());
+        for sge in &sgl.0 {
+            buf.extend_from_slice(&(sge.len as u32).to_le_bytes());
+        }
+        for sge in &sgl.0 {
+            buf.extend_from_slice(slice::from_raw_parts(sge.ptr as *const u8, sge.len));
+        }
+        let tag = self
+            .sealer
+            .encrypt_in_place_detached(&nonce(counter), aad, &mut buf[NONCE_LEN..])
+            .expect("the message is within the size limit of AES-GCM");
+        buf.extend_from_slice(&tag);
+    }
+
+    /// Opens the sealed message in `sge` in place, and appends its elements to `sgl`. The
+    /// elements point into `sge`.
+    ///
+    /// # Safety
+    ///
+    /// `sge` must be valid for reads and writes.
+    pub unsafe fn open(&self, sge: SgE, aad: &[u8], sgl: &mut SgList) -> Result<(), SealError> {
+        if sge.len < NONCE_LEN + 4 + TAG_LEN {
+            return Err(SealError::Truncated(sge.len));
+        }
+        let buf = slice::from_raw_parts_mut(sge.ptr as *mut u8, sge.len);
+        let counter = u64::from_le_bytes(buf[..NONCE_LEN].try_into().unwrap());
+        let expected = self.next_expected.load(Ordering::Relaxed);
+        if counter < expected {
+            return Err(SealError::Replayed {
+                nonce: counter,
+                expected,
+            });
+        }
+
+        let (body, tag) = buf[NONCE_LEN..].split_at_mut(sge.len - NONCE_LEN - TAG_LEN);
+        self.opener
+            .decrypt_in_place_detached(&nonce(counter), aad, body, Tag::from_slice(tag))
+            .map_err(|_| SealError::Authentication)?;
+        // only an authentic message moves the window
+        self.next_expected.store(counter + 1, Ordering::Relaxed);
+
+        let num_sge = u32::from_le_bytes(body[..4].try_into().unwrap()) as usize;
+        let lens_end = 4 + 4 * num_sge;
+        if lens_end > body.len() {
+            return Err(SealError::Truncated(sge.len));
+        }
+        let mut value_off = lens_end;
+        for len in body[4..lens_end].chunks_exact(4) {
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            if value_off + len > body.len() {
+                return Err(SealError::Truncated(sge.len));
+            }
+            sgl.0.push(SgE {
+                ptr: body.as_ptr().add(value_off).expose_addr(),
+                len,
+            });
+            value_off += len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (SessionCipher, SessionCipher) {
+        let initiator = Handshake::new();
+        let responder = Handshake::new();
+        let to_responder = initiator.private_data();
+        let to_initiator = responder.private_data();
+        (
+            initiator.finish(Role::Initiator, &to_initiator).unwrap(),
+            responder.finish(Role::Responder, &to_responder).unwrap(),
+        )
+    }
+
+    fn seal(cipher: &SessionCipher, values: &[&[u8]], aad: &[u8]) -> Vec<u8> {
+        let sgl = SgList(
+            values
+                .iter()
+                .map(|v| SgE {
+                    ptr: v.as_ptr() as usize,
+                    len: v.len(),
+                })
+                .collect(),
+        );
+        let mut buf = Vec::new();
+        // SAFETY: the elements point into the slices of `values`
+        unsafe { cipher.seal(&sgl, aad, &mut buf) };
+        assert_eq!(buf.len(), sealed_len(&sgl));
+        buf
+    }
+
+    fn open(cipher: &SessionCipher, buf: &mut [u8], aad: &[u8]) -> Result<Vec<Vec<u8>>, SealError> {
+        let sge = SgE {
+            ptr: buf.as_mut_ptr() as usize,
+            len: buf.len(),
+        };
+        let mut sgl = SgList(Vec::new());
+        // SAFETY: the element points into `buf`
+        unsafe { cipher.open(sge, aad, &mut sgl) }?;
+        Ok(sgl
+            .0
+            .iter()
+            // SAFETY: the elements point into `buf`
+            .map(|sge| unsafe { slice::from_raw_parts(sge.ptr as *const u8, sge.len) }.to_vec())
+            .collect())
+    }
+
+    #[test]
+    fn round_trip() {
+        let (initiator, responder) = pair();
+        let mut buf = seal(&initiator, &[b"hello", b"", b"world"], b"call");
+        assert_eq!(
+            open(&responder, &mut buf, b"call").unwrap(),
+            vec![b"hello".to_vec(), Vec::new(), b"world".to_vec()]
+        );
+
+        let mut buf = seal(&responder, &[b"reply"], b"call");
+        assert_eq!(
+            open(&initiator, &mut buf, b"call").unwrap(),
+            vec![b"reply".to_vec()]
+        );
+    }
+
+    #[test]
+    fn tamper() {
+        let (initiator, responder) = pair();
+        let sealed = seal(&initiator, &[b"hello"], b"call");
+
+        let mut body = sealed.clone();
+        body[NONCE_LEN + 8] ^= 1;
+        assert!(matches!(
+            open(&responder, &mut body, b"call"),
+            Err(SealError::Authentication)
+        ));
+
+        let mut tag = sealed.clone();
+        *tag.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            open(&responder, &mut tag, b"call"),
+            Err(SealError::Authentication)
+        ));
+
+        let mut aad = sealed.clone();
+        assert!(matches!(
+            open(&responder, &mut aad, b"other"),
+            Err(SealError::Authentication)
+        ));
+
+        let mut truncated = sealed[..NONCE_LEN + 4].to_vec();
+        assert!(matches!(
+            open(&responder, &mut truncated, b"call"),
+            Err(SealError::Truncated(_))
+        ));
+
+        // a rejected message does not move the window
+        let mut intact = sealed;
+        assert!(open(&responder, &mut intact, b"call").is_ok());
+    }
+
+    #[test]
+    fn replay() {
+        let (initiator, responder) = pair();
+        let first = seal(&initiator, &[b"first"], b"call");
+        let second = seal(&initiator, &[b"second"], b"call");
+
+        assert!(open(&responder, &mut second.clone(), b"call").is_ok());
+        assert!(matches!(
+            open(&responder, &mut first.clone(), b"call"),
+            Err(SealError::Replayed {
+                nonce: 0,
+                expected: 2
+            })
+        ));
+        assert!(matches!(
+            open(&responder, &mut second.clone(), b"call"),
+            Err(SealError::Replayed {
+                nonce: 1,
+                expected: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn direction_keys() {
+        let (initiator, responder) = pair();
+        // a message is not accepted back by its sender, so it cannot be reflected
+        let mut buf = seal(&initiator, &[b"hello"], b"call");
+        assert!(matches!(
+            open(&initiator, &mut buf, b"call"),
+            Err(SealError::Authentication)
+        ));
+        let mut buf = seal(&responder, &[b"hello"], b"call");
+        assert!(matches!(
+            open(&responder, &mut buf, b"call"),
+            Err(SealError::Authentication)
+        ));
+
+        // nor by another connection
+        let (_, other) = pair();
+        let mut buf = seal(&initiator, &[b"hello"], b"call");
+        assert!(matches!(
+            open(&other, &mut buf, b"call"),
+            Err(SealError::Authentication)
+        ));
+    }
+
+    #[test]
+    fn handshake() {
+        let handshake = Handshake::new();
+        let mut padded = vec![0u8; 7];
+        padded.extend_from_slice(&Handshake::new().private_data());
+        padded.resize(56, 0);
+        assert!(offers_key(&padded));
+        assert!(handshake.finish(Role::Initiator, &padded).is_ok());
+
+        assert!(!offers_key(&[0u8; 56]));
+        assert!(matches!(
+            Handshake::new().finish(Role::Initiator, &[0u8; 56]),
+            Err(SealError::NoKey)
+        ));
+    }
+}
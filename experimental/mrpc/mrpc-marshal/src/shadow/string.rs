@@ -23,3 +23,9 @@ impl ops::Deref for String {
         unsafe { str::from_utf8_unchecked(&self.buf) }
     }
 }
+
+impl serde::Serialize for String {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
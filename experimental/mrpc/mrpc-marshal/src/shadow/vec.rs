@@ -78,3 +78,10 @@ impl<T: std::fmt::Debug> std::fmt::Debug for Vec<T> {
         std::fmt::Debug::fmt(slice, f)
     }
 }
+
+// So that addons can render the messages they see.
+impl<T: serde::Serialize> serde::Serialize for Vec<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
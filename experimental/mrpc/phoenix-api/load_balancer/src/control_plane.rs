@@ -5,9 +5,54 @@ use serde::{Deserialize, Serialize};
 
 type IResult<T> = Result<T, phoenix_api::Error>;
 
+/// Sends the calls of the methods it matches to the backends at these addresses only, e.g.,
+/// the reads to the replicas and the writes to the primary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Match only the calls of this service, by its ID.
+    #[serde(default)]
+    pub service_id: Option<u32>,
+    /// Match only the calls of this function, by its ID.
+    #[serde(default)]
+    pub func_id: Option<u32>,
+    /// The peer addresses of the backend connections.
+    pub backends: Vec<SocketAddr>,
+}
+
+impl Route {
+    #[inline]
+    pub fn matches(&self, service_id: u32, func_id: u32) -> bool {
+        self.service_id.map_or(true, |id| id == service_id)
+            && self.func_id.map_or(true, |id| id == func_id)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
     ListConnection,
+    /// Replaces the routes. The first route that matches a call and has a backend connected
+    /// applies, the calls that no route applies to are spread over all the backends.
+    SetRoutes(Vec<Route>),
+}
+
+/// Queries to LoadBalancerEngine, sent through `EngineQuery`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Query {
+    /// The routes and the backend connections each of them resolves to.
+    Routes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueryResponse {
+    Routes(Vec<RouteStatus>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteStatus {
+    pub route: Route,
+    /// The connections to the backends of the route, empty if none is connected.
+    pub conns: Vec<Handle>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,9 +1,11 @@
 //! mRPC control path commands.
+use std::collections::HashMap;
 use std::{net::SocketAddr, os::unix::prelude::RawFd, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
 use super::control_plane::TransportType;
+use phoenix_api::rpc::RpcMsgType;
 use phoenix_api::Handle;
 
 type IResult<T> = Result<T, phoenix_api::Error>;
@@ -20,6 +22,94 @@ pub enum Command {
     NewMappedAddrs(Handle, Vec<(Handle, usize)>),
     UpdateProtos(Vec<String>),
     UpdateProtosInner(PathBuf),
+    // Encoded FileDescriptorSets of the services, for reflection
+    UpdateDescriptors(Vec<Vec<u8>>),
+    // Limits the size of the messages of a service, or of all services if no service_id is given
+    SetMessageSizeLimit(Option<u32>, MessageSizeLimit),
+    // The statistics of a connection, for the application to look at
+    ConnectionStats(Handle),
+}
+
+/// The maximum sizes of the marshalled messages, in bytes. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageSizeLimit {
+    #[serde(default)]
+    pub max_request_size: Option<usize>,
+    #[serde(default)]
+    pub max_response_size: Option<usize>,
+}
+
+impl MessageSizeLimit {
+    #[inline]
+    pub fn is_unlimited(&self) -> bool {
+        self.max_request_size.is_none() && self.max_response_size.is_none()
+    }
+
+    #[inline]
+    pub fn max_size(&self, msg_type: RpcMsgType) -> Option<usize> {
+        match msg_type {
+            RpcMsgType::Request => self.max_request_size,
+            RpcMsgType::Response | RpcMsgType::Notification => self.max_response_size,
+        }
+    }
+
+    /// Takes the limits set in `other`, keeps the rest.
+    pub fn update(&mut self, other: MessageSizeLimit) {
+        if other.max_request_size.is_some() {
+            self.max_request_size = other.max_request_size;
+        }
+        if other.max_response_size.is_some() {
+            self.max_response_size = other.max_response_size;
+        }
+    }
+}
+
+/// The message size limits of all services, kept by the backend engines that marshal messages.
+#[derive(Debug, Clone, Default)]
+pub struct MessageSizeLimits {
+    default: MessageSizeLimit,
+    services: HashMap<u32, MessageSizeLimit>,
+}
+
+impl MessageSizeLimits {
+    pub fn update(&mut self, service_id: Option<u32>, limit: MessageSizeLimit) {
+        match service_id {
+            Some(service_id) => self.services.entry(service_id).or_default().update(limit),
+            None => self.default.update(limit),
+        }
+    }
+
+    /// The maximum size of a message, a limit of the service takes precedence over the default.
+    #[inline]
+    pub fn max_size(&self, service_id: u32, msg_type: RpcMsgType) -> Option<usize> {
+        self.services
+            .get(&service_id)
+            .and_then(|limit| limit.max_size(msg_type))
+            .or_else(|| self.default.max_size(msg_type))
+    }
+}
+
+/// The traffic and the resources of a connection, as the backend sees them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportStats {
+    /// The bytes of the messages sent, metadata included.
+    pub bytes_sent: u64,
+    pub messages_sent: u64,
+    /// The bytes of the messages received, metadata included.
+    pub bytes_received: u64,
+    pub messages_received: u64,
+    /// The messages received that were dropped because they were malformed.
+    pub messages_dropped: u64,
+    /// The retransmissions of the transport, `None` if it does not expose them. The NIC
+    /// retransmits on an RDMA connection without telling the host.
+    pub retransmits: Option<u64>,
+    /// The credits left to send with, `None` if the transport is not credit-based.
+    pub credits: Option<usize>,
+    /// The bytes of the receive heap of the connection that the application holds.
+    pub recv_heap_in_use: usize,
+    /// The bytes of the receive heap of the connection.
+    pub recv_heap_size: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +124,8 @@ pub struct ReadHeapRegion {
 pub struct ConnectResponse {
     pub conn_handle: Handle,
     pub read_regions: Vec<ReadHeapRegion>,
+    /// The address of the peer, if the transport knows it.
+    pub peer_addr: Option<SocketAddr>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -53,6 +145,9 @@ pub enum CompletionKind {
     // the acknowledgement
     NewMappedAddrs,
     UpdateProtos,
+    UpdateDescriptors,
+    SetMessageSizeLimit,
+    ConnectionStats(TransportStats),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,8 +24,92 @@ impl std::str::FromStr for TransportType {
     }
 }
 
+/// Serving status of a service, as in grpc.health.v1.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ServingStatus {
+    Unknown = 0,
+    Serving = 1,
+    NotServing = 2,
+    /// Used only by Watch in gRPC. mRPC reports it for services there is no status for.
+    ServiceUnknown = 3,
+}
+
+impl std::str::FromStr for ServingStatus {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().replace('-', "_").as_str() {
+            "UNKNOWN" => Ok(Self::Unknown),
+            "SERVING" => Ok(Self::Serving),
+            "NOT_SERVING" => Ok(Self::NotServing),
+            "SERVICE_UNKNOWN" => Ok(Self::ServiceUnknown),
+            _ => Err("Expect SERVING, NOT_SERVING, UNKNOWN or SERVICE_UNKNOWN"),
+        }
+    }
+}
+
+/// Requests to MrpcEngine, sent through `EngineRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Sets the status the built-in Health service reports for a service. The empty service
+    /// name stands for the overall health of the server.
+    SetServingStatus(String, ServingStatus),
+}
+
+/// Queries to MrpcEngine, sent through `EngineQuery`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Request {}
+pub enum Query {
+    /// Lists the services, methods, and message schemas registered by the app.
+    Reflection,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueryResponse {
+    Reflection(ReflectionInfo),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MethodDescriptor {
+    pub name: String,
+    /// Fully qualified name of the request message
+    pub input_type: String,
+    /// Fully qualified name of the response message
+    pub output_type: String,
+    pub func_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceDescriptor {
+    /// Fully qualified name of the service
+    pub name: String,
+    pub service_id: u32,
+    pub methods: Vec<MethodDescriptor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldDescriptor {
+    pub name: String,
+    pub number: i32,
+    /// Either a scalar type, e.g., `int32`, or the fully qualified name of a message or an enum
+    pub type_name: String,
+    pub repeated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageDescriptor {
+    /// Fully qualified name of the message
+    pub name: String,
+    pub fields: Vec<FieldDescriptor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReflectionInfo {
+    pub services: Vec<ServiceDescriptor>,
+    pub messages: Vec<MessageDescriptor>,
+    /// The encoded FileDescriptorSets as registered by the app, for tools that need more
+    pub file_descriptor_sets: Vec<Vec<u8>>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ResponseKind {}
@@ -48,4 +132,13 @@ pub struct Setting {
     pub core_id: Option<usize>,
 
     pub module_config: Option<String>,
+    /// Whether the messages are encrypted on the wire, with keys negotiated for each connection.
+    /// Only supported by the RDMA transport.
+    #[serde(default)]
+    pub encryption: bool,
+    /// The messages up to this size, in bytes, are carried inside the work requests and the
+    /// completions rather than on the shared heap, up to
+    /// [`MAX_INLINE_PAYLOAD`](crate::dp::MAX_INLINE_PAYLOAD). 0 disables it.
+    #[serde(default)]
+    pub max_inline_payload: usize,
 }
@@ -1,13 +1,38 @@
 //! mRPC data path operations.
 use serde::{Deserialize, Serialize};
 
-use phoenix_api::rpc::{CallId, MessageErased, RpcId, TransportStatus};
+use phoenix_api::rpc::{CallId, MessageErased, MessageMeta, RpcId, StageStamps, TransportStatus};
 use phoenix_api::Handle;
 
-pub type WorkRequestSlot = [u8; 64];
+/// The size of a cache line. Each descriptor takes exactly one, so the backend reads a work
+/// request or writes a completion with a single cache line transfer between the cores.
+///
+/// A descriptor points to its message on the shared heap, except for the small messages, which
+/// are carried in the queue right after the descriptor, see [`MAX_INLINE_PAYLOAD`].
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// The largest message that is carried inside the queues, in the slots that follow its
+/// [`WorkRequest::Inline`] or [`Completion::IncomingInline`] descriptor, rather than on the
+/// shared heap. The payload is the struct of the message as is, so it is only carried this way
+/// while the struct fits, and, for a completion, while it does not refer to data elsewhere in
+/// the receive buffer. The threshold actually used is the `max_inline_payload` of the
+/// [`Setting`](crate::control_plane::Setting), up to this.
+///
+/// A work request carried this way is read by the backend from the queue, instead of from the
+/// heap after translating its address. A completion carried this way is copied by the app, and
+/// its receive buffer is given back right away rather than when the app drops the message.
+pub const MAX_INLINE_PAYLOAD: usize = 4 * CACHE_LINE_SIZE;
+
+/// The alignment the struct of a message needs at most to be carried inside the queues, that of
+/// the buffers the backend copies it to.
+pub const INLINE_PAYLOAD_ALIGN: usize = 8;
+
+pub type WorkRequestSlot = [u8; CACHE_LINE_SIZE];
 
 pub const RECV_RECLAIM_BS: usize = 4;
 
+/// The tag is at offset 0 and the payload at offset 8. For a `Call` or a `Reply`, the meta is at
+/// offsets 8..48 and the two addresses of the message at 48..64, all in the same cache line.
 #[repr(C, align(64))]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum WorkRequest {
@@ -16,24 +41,62 @@ pub enum WorkRequest {
     Reply(MessageErased),
     // conn_id and an array of call_id
     ReclaimRecvBuf(Handle, [CallId; RECV_RECLAIM_BS]),
+    // a call or a reply, by the msg_type of the meta, whose struct of the given length follows
+    // in the next slots, see MAX_INLINE_PAYLOAD
+    Inline(MessageMeta, u32),
+    // fills the slots before the end of the queue that an inline payload does not fit in
+    Pad,
 }
 
-pub type CompletionSlot = [u8; 64];
+pub type CompletionSlot = [u8; CACHE_LINE_SIZE];
 
 // Avoid using too much `Send`/`Recv` in the code.
 #[repr(C, align(64))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub enum Completion {
     Incoming(MessageErased),
     Outgoing(RpcId, TransportStatus),
     // (conn_id, status)
     RecvError(Handle, TransportStatus),
+    // the timestamps of a request in the backend, right before its Outgoing, only when the
+    // latency breakdown is enabled
+    Stamps(RpcId, StageStamps),
+    // a message received whose struct of the given length follows in the next slots, see
+    // MAX_INLINE_PAYLOAD
+    IncomingInline(MessageMeta, u32),
+    // fills the slots before the end of the queue that an inline payload does not fit in
+    Pad,
+}
+
+impl WorkRequest {
+    /// The length of the payload that follows the work request in the queue, if any.
+    #[inline]
+    pub fn payload_len(&self) -> Option<usize> {
+        match self {
+            WorkRequest::Inline(_, len) => Some(*len as usize),
+            _ => None,
+        }
+    }
+}
+
+impl Completion {
+    /// The length of the payload that follows the completion in the queue, if any.
+    #[inline]
+    pub fn payload_len(&self) -> Option<usize> {
+        match self {
+            Completion::IncomingInline(_, len) => Some(*len as usize),
+            _ => None,
+        }
+    }
 }
 
 mod sa {
     use super::*;
-    use static_assertions::const_assert;
-    use std::mem::size_of;
-    const_assert!(size_of::<WorkRequest>() <= size_of::<WorkRequestSlot>());
-    const_assert!(size_of::<Completion>() <= size_of::<CompletionSlot>());
+    use static_assertions::{const_assert, const_assert_eq};
+    use std::mem::{align_of, size_of};
+    const_assert_eq!(size_of::<WorkRequest>(), size_of::<WorkRequestSlot>());
+    const_assert_eq!(size_of::<Completion>(), size_of::<CompletionSlot>());
+    const_assert_eq!(align_of::<WorkRequest>(), CACHE_LINE_SIZE);
+    const_assert_eq!(align_of::<Completion>(), CACHE_LINE_SIZE);
+    const_assert!(MAX_INLINE_PAYLOAD <= u32::MAX as usize);
 }
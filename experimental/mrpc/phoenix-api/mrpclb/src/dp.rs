@@ -4,10 +4,16 @@ use serde::{Deserialize, Serialize};
 use phoenix_api::rpc::{CallId, MessageErased, RpcId, TransportStatus};
 use phoenix_api::Handle;
 
-pub type WorkRequestSlot = [u8; 64];
+/// The size of a cache line. Each descriptor takes exactly one, so the backend reads a work
+/// request or writes a completion with a single cache line transfer between the cores.
+pub const CACHE_LINE_SIZE: usize = 64;
+
+pub type WorkRequestSlot = [u8; CACHE_LINE_SIZE];
 
 pub const RECV_RECLAIM_BS: usize = 4;
 
+/// The tag is at offset 0 and the payload at offset 8. For a `Call` or a `Reply`, the meta is at
+/// offsets 8..48 and the two addresses of the message at 48..64, all in the same cache line.
 #[repr(C, align(64))]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum WorkRequest {
@@ -18,7 +24,7 @@ pub enum WorkRequest {
     ReclaimRecvBuf(Handle, [CallId; RECV_RECLAIM_BS]),
 }
 
-pub type CompletionSlot = [u8; 64];
+pub type CompletionSlot = [u8; CACHE_LINE_SIZE];
 
 // Avoid using too much `Send`/`Recv` in the code.
 #[repr(C, align(64))]
@@ -32,8 +38,10 @@ pub enum Completion {
 
 mod sa {
     use super::*;
-    use static_assertions::const_assert;
-    use std::mem::size_of;
-    const_assert!(size_of::<WorkRequest>() <= size_of::<WorkRequestSlot>());
-    const_assert!(size_of::<Completion>() <= size_of::<CompletionSlot>());
+    use static_assertions::const_assert_eq;
+    use std::mem::{align_of, size_of};
+    const_assert_eq!(size_of::<WorkRequest>(), size_of::<WorkRequestSlot>());
+    const_assert_eq!(size_of::<Completion>(), size_of::<CompletionSlot>());
+    const_assert_eq!(align_of::<WorkRequest>(), CACHE_LINE_SIZE);
+    const_assert_eq!(align_of::<Completion>(), CACHE_LINE_SIZE);
 }
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Set the number of payload bytes to capture for each message, 0 to capture headers only.
+    SetSnapLen(usize),
+    /// Close the current capture file and start a new one.
+    Rotate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
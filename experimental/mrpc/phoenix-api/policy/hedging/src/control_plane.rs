@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Replace the hedging policy with that of a TOML config.
+    NewConfig(String),
+}
+
+/// Queries to HedgingEngine, sent through `EngineQuery`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Query {
+    Stats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueryResponse {
+    Stats(HedgingStats),
+}
+
+/// The counters of a HedgingEngine since it was created.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HedgingStats {
+    /// The calls of the hedged methods.
+    pub calls: u64,
+    /// The duplicates sent.
+    pub hedges: u64,
+    /// The calls whose duplicate replied first.
+    pub hedge_wins: u64,
+    /// The duplicates not sent as the budget is spent.
+    pub budget_exhausted: u64,
+    /// The duplicates not sent as no other connection serves the method.
+    pub no_alternate: u64,
+    /// The duplicates not sent as their requests have no hop left.
+    pub hops_exhausted: u64,
+    /// The attempts that lost and were forgotten before their replies arrived.
+    pub losers_expired: u64,
+    /// The replies of the forgotten duplicates that arrived later, and were dropped.
+    pub late_replies: u64,
+    /// The duplicates the budget allows now.
+    pub budget: f64,
+    /// The current hedging delay of each method.
+    pub delays: Vec<MethodDelay>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MethodDelay {
+    pub service_id: u32,
+    pub func_id: u32,
+    /// None until the method has enough samples.
+    pub delay_us: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Set the number of notifications in flight to each subscriber.
+    SetMaxInflight(usize),
+    /// Set the number of notifications queued for each subscriber whose window is full.
+    SetMaxQueued(usize),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
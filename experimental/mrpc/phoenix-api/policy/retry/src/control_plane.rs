@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Replace the retry policy with that of a TOML config.
+    NewConfig(String),
+}
+
+/// Queries to RetryEngine, sent through `EngineQuery`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Query {
+    Stats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueryResponse {
+    Stats(RetryStats),
+}
+
+/// The counters of a RetryEngine since it was created.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RetryStats {
+    /// The calls of the idempotent methods.
+    pub calls: u64,
+    pub retries: u64,
+    /// The calls that succeed after a retry.
+    pub recovered: u64,
+    /// The failed calls not retried as the budget is spent.
+    pub budget_exhausted: u64,
+    /// The failed calls not retried as they have made all their attempts.
+    pub attempts_exhausted: u64,
+    /// The failed calls not retried as their requests have no hop left.
+    pub hops_exhausted: u64,
+    /// The retries the budget allows now.
+    pub budget: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Forget the calls counted so far, which resolves the firing alerts.
+    Reset,
+}
+
+/// Queries to SloEngine, sent through `EngineQuery`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Query {
+    /// The calls of each method over the windows of the alerts.
+    Metrics,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueryResponse {
+    Metrics(Vec<MethodMetrics>),
+}
+
+/// The calls of a method over a window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowMetrics {
+    pub window_secs: u64,
+    pub requests: u64,
+    pub failures: u64,
+    /// The calls slower than the latency threshold of the objective
+    pub slow: u64,
+    /// How fast the error budget of the success objective is spent, 1.0 spends it exactly
+    pub success_burn_rate: f64,
+    /// `None` if the objective has no latency threshold
+    pub latency_burn_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MethodMetrics {
+    pub service_id: u32,
+    pub func_id: u32,
+    pub success_target: f64,
+    pub latency_threshold_us: Option<u64>,
+    pub latency_target: f64,
+    /// From the shortest window to the longest
+    pub windows: Vec<WindowMetrics>,
+    /// The alerts firing, e.g., `success 3600s/300s`
+    pub firing: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
@@ -0,0 +1,283 @@
+//! Looking up services and messages in proto descriptors.
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    FileDescriptorSet, MethodDescriptorProto,
+};
+
+/// The proto files known to the CLI.
+#[derive(Debug, Default)]
+pub struct Descriptors {
+    files: Vec<FileDescriptorProto>,
+}
+
+/// A resolved method.
+#[derive(Debug)]
+pub struct Method<'a> {
+    pub file: &'a FileDescriptorProto,
+    /// The fully qualified service name, e.g., `rpc_hello.Greeter`.
+    pub service: String,
+    pub method: &'a MethodDescriptorProto,
+}
+
+impl<'a> Method<'a> {
+    /// Must match mrpc-build.
+    pub fn service_id(&self) -> u32 {
+        crc32fast::hash(self.service.as_bytes())
+    }
+
+    /// Must match mrpc-build.
+    pub fn func_id(&self) -> u32 {
+        crc32fast::hash(format!("/{}/{}", self.service, self.method.name()).as_bytes())
+    }
+}
+
+impl Descriptors {
+    /// Adds the files in an encoded FileDescriptorSet. A file already known is skipped.
+    pub fn add_encoded_set(&mut self, buf: &[u8]) -> Result<()> {
+        let set = FileDescriptorSet::decode(buf)?;
+        for file in set.file {
+            if !self.files.iter().any(|f| f.name == file.name) {
+                self.files.push(file);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_set_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let buf = std::fs::read(path.as_ref())
+            .map_err(|e| anyhow!("failed to read {:?}: {}", path.as_ref(), e))?;
+        self.add_encoded_set(&buf)
+    }
+
+    /// All the unary methods.
+    pub fn methods(&self) -> Vec<Method<'_>> {
+        let mut methods = Vec::new();
+        for file in self.files.iter() {
+            for s in file.service.iter() {
+                let service = qualify(file.package(), s.name());
+                for m in s.method.iter() {
+                    if m.client_streaming() || m.server_streaming() {
+                        continue;
+                    }
+                    methods.push(Method {
+                        file,
+                        service: service.clone(),
+                        method: m,
+                    });
+                }
+            }
+        }
+        methods
+    }
+
+    /// Finds a method by `package.Service/Method` or `package.Service.Method`.
+    pub fn find_method(&self, name: &str) -> Result<Method<'_>> {
+        let (service, method) = name
+            .rsplit_once('/')
+            .or_else(|| name.rsplit_once('.'))
+            .ok_or_else(|| {
+                anyhow!(
+                    "invalid method name: {}, expect package.Service/Method",
+                    name
+                )
+            })?;
+        for file in self.files.iter() {
+            for s in file.service.iter() {
+                if qualify(file.package(), s.name()) != service {
+                    continue;
+                }
+                if let Some(m) = s.method.iter().find(|m| m.name() == method) {
+                    if m.client_streaming() || m.server_streaming() {
+                        bail!(
+                            "method {} is streaming, mRPC only supports unary RPCs",
+                            name
+                        );
+                    }
+                    return Ok(Method {
+                        file,
+                        service: service.to_owned(),
+                        method: m,
+                    });
+                }
+            }
+        }
+        Err(anyhow!("method {} not found", name))
+    }
+
+    /// Finds a message by its fully qualified name, with or without the leading dot.
+    pub fn find_message(&self, name: &str) -> Result<&DescriptorProto> {
+        let name = name.trim_start_matches('.');
+        self.files
+            .iter()
+            .find_map(|file| {
+                file.message_type
+                    .iter()
+                    .find_map(|m| find_nested(file.package(), m, name))
+            })
+            .ok_or_else(|| anyhow!("message {} not found", name))
+    }
+
+    /// Finds an enum by its fully qualified name, with or without the leading dot.
+    pub fn find_enum(&self, name: &str) -> Result<&EnumDescriptorProto> {
+        let name = name.trim_start_matches('.');
+        for file in self.files.iter() {
+            let package = file.package();
+            if let Some(e) = file
+                .enum_type
+                .iter()
+                .find(|e| qualify(package, e.name()) == name)
+            {
+                return Ok(e);
+            }
+            // an enum nested in a message
+            if let Some((scope, enum_name)) = name.rsplit_once('.') {
+                let found = file
+                    .message_type
+                    .iter()
+                    .find_map(|m| find_nested(package, m, scope))
+                    .and_then(|m| m.enum_type.iter().find(|e| e.name() == enum_name));
+                if let Some(e) = found {
+                    return Ok(e);
+                }
+            }
+        }
+        Err(anyhow!("enum {} not found", name))
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+fn find_nested<'a>(
+    scope: &str,
+    message: &'a DescriptorProto,
+    name: &str,
+) -> Option<&'a DescriptorProto> {
+    let qualified = qualify(scope, message.name());
+    if qualified == name {
+        return Some(message);
+    }
+    if !name.starts_with(qualified.as_str()) {
+        return None;
+    }
+    message
+        .nested_type
+        .iter()
+        .find_map(|m| find_nested(&qualified, m, name))
+}
+
+/// Prints a proto file back to its source, for the mRPC engine to build the marshalling library.
+///
+/// Only what the marshalling library needs is printed: messages, enums and services. Comments and
+/// options other than `map_entry` are lost. The file must not import other files because the
+/// engine compiles each source on its own.
+pub fn to_proto_source(file: &FileDescriptorProto) -> Result<String> {
+    if !file.dependency.is_empty() {
+        bail!(
+            "{} imports {:?}, protos with imports are not supported",
+            file.name(),
+            file.dependency
+        );
+    }
+
+    let mut out = String::new();
+    writeln!(out, "syntax = \"proto3\";")?;
+    if !file.package().is_empty() {
+        writeln!(out, "\npackage {};", file.package())?;
+    }
+    for e in file.enum_type.iter() {
+        writeln!(out)?;
+        print_enum(&mut out, e, 0)?;
+    }
+    for m in file.message_type.iter() {
+        writeln!(out)?;
+        print_message(&mut out, m, 0)?;
+    }
+    for s in file.service.iter() {
+        writeln!(out, "\nservice {} {{", s.name())?;
+        for m in s.method.iter() {
+            writeln!(
+                out,
+                "  rpc {}({}) returns ({});",
+                m.name(),
+                m.input_type(),
+                m.output_type()
+            )?;
+        }
+        writeln!(out, "}}")?;
+    }
+    Ok(out)
+}
+
+fn print_enum(out: &mut String, e: &EnumDescriptorProto, depth: usize) -> std::fmt::Result {
+    let indent = "  ".repeat(depth);
+    writeln!(out, "{}enum {} {{", indent, e.name())?;
+    for v in e.value.iter() {
+        writeln!(out, "{}  {} = {};", indent, v.name(), v.number())?;
+    }
+    writeln!(out, "{}}}", indent)
+}
+
+fn print_message(out: &mut String, m: &DescriptorProto, depth: usize) -> std::fmt::Result {
+    let indent = "  ".repeat(depth);
+    writeln!(out, "{}message {} {{", indent, m.name())?;
+    if m.options.as_ref().map_or(false, |o| o.map_entry()) {
+        writeln!(out, "{}  option map_entry = true;", indent)?;
+    }
+    for e in m.enum_type.iter() {
+        print_enum(out, e, depth + 1)?;
+    }
+    for nested in m.nested_type.iter() {
+        print_message(out, nested, depth + 1)?;
+    }
+    for field in m.field.iter() {
+        writeln!(
+            out,
+            "{}  {}{} {} = {};",
+            indent,
+            if field.label() == Label::Repeated {
+                "repeated "
+            } else {
+                ""
+            },
+            field_type_name(field),
+            field.name(),
+            field.number()
+        )?;
+    }
+    writeln!(out, "{}}}", indent)
+}
+
+/// The type of a field as written in a proto file.
+pub fn field_type_name(field: &FieldDescriptorProto) -> &str {
+    match field.r#type() {
+        // Fully qualified names (with the leading dot) are valid in proto sources.
+        Type::Message | Type::Enum | Type::Group => field.type_name(),
+        Type::Double => "double",
+        Type::Float => "float",
+        Type::Int64 => "int64",
+        Type::Uint64 => "uint64",
+        Type::Int32 => "int32",
+        Type::Fixed64 => "fixed64",
+        Type::Fixed32 => "fixed32",
+        Type::Bool => "bool",
+        Type::String => "string",
+        Type::Bytes => "bytes",
+        Type::Uint32 => "uint32",
+        Type::Sfixed32 => "sfixed32",
+        Type::Sfixed64 => "sfixed64",
+        Type::Sint32 => "sint32",
+        Type::Sint64 => "sint64",
+    }
+}
@@ -0,0 +1,445 @@
+//! Descriptor-driven encoding between JSON and the in-memory layout of mRPC messages.
+//!
+//! mRPC messages are not serialized by the application: the marshalling library reads the
+//! `#[repr(C)]` structs that mrpc-build generates directly from the shared memory heap. Those
+//! structs have one field for each proto field, in declaration order, so the layout can be
+//! reproduced from the descriptor alone.
+//!
+//! Only scalars, enums, strings and bytes (and repeated of them) are supported. For nested
+//! messages, maps, oneofs and proto3 optional fields, the generated layout depends on how rustc
+//! lays out the corresponding `Option`s and collections, and we refuse to guess.
+//!
+//! In JSON, bytes are written as a UTF-8 string or an array of numbers, and enums either by name
+//! or by number. Unset fields get their default value.
+use std::mem;
+
+use anyhow::{anyhow, bail, Context, Result};
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::DescriptorProto;
+use serde_json::{Map, Value};
+
+use mrpc::alloc;
+
+use crate::descriptor::Descriptors;
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Uint32,
+    Bool,
+    /// The values of the enum, by name.
+    Enum(Vec<(String, i32)>),
+    String,
+    Bytes,
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    json_name: String,
+    kind: Kind,
+    repeated: bool,
+    offset: usize,
+}
+
+/// The layout of the struct generated for a message.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    name: String,
+    fields: Vec<Field>,
+    size: usize,
+    align: usize,
+}
+
+impl Kind {
+    fn size_align(&self, repeated: bool) -> (usize, usize) {
+        if repeated {
+            // The layout of a Vec does not depend on its element.
+            return (
+                mem::size_of::<alloc::Vec<u8>>(),
+                mem::align_of::<alloc::Vec<u8>>(),
+            );
+        }
+        match self {
+            Kind::Double | Kind::Int64 | Kind::Uint64 => (8, 8),
+            Kind::Float | Kind::Int32 | Kind::Uint32 | Kind::Enum(_) => (4, 4),
+            Kind::Bool => (1, 1),
+            Kind::String => (
+                mem::size_of::<alloc::String>(),
+                mem::align_of::<alloc::String>(),
+            ),
+            Kind::Bytes => (
+                mem::size_of::<alloc::Vec<u8>>(),
+                mem::align_of::<alloc::Vec<u8>>(),
+            ),
+        }
+    }
+}
+
+impl Layout {
+    /// Computes the layout of `message`, following the rules of `#[repr(C)]`.
+    pub fn of(descriptors: &Descriptors, message: &str) -> Result<Self> {
+        let desc: &DescriptorProto = descriptors.find_message(message)?;
+
+        let mut fields = Vec::with_capacity(desc.field.len());
+        let mut offset = 0;
+        let mut align = 1;
+        for field in desc.field.iter() {
+            if field.oneof_index.is_some() || field.proto3_optional() {
+                bail!(
+                    "{}.{}: oneof and optional fields are not supported",
+                    message,
+                    field.name()
+                );
+            }
+            let kind = match field.r#type() {
+                Type::Double => Kind::Double,
+                Type::Float => Kind::Float,
+                Type::Int64 | Type::Sint64 | Type::Sfixed64 => Kind::Int64,
+                Type::Uint64 | Type::Fixed64 => Kind::Uint64,
+                Type::Int32 | Type::Sint32 | Type::Sfixed32 => Kind::Int32,
+                Type::Uint32 | Type::Fixed32 => Kind::Uint32,
+                Type::Bool => Kind::Bool,
+                Type::String => Kind::String,
+                Type::Bytes => Kind::Bytes,
+                Type::Enum => {
+                    let e = descriptors.find_enum(field.type_name())?;
+                    Kind::Enum(
+                        e.value
+                            .iter()
+                            .map(|v| (v.name().to_owned(), v.number()))
+                            .collect(),
+                    )
+                }
+                Type::Message | Type::Group => bail!(
+                    "{}.{}: nested messages are not supported",
+                    message,
+                    field.name()
+                ),
+            };
+            let repeated = field.label() == Label::Repeated;
+            let (field_size, field_align) = kind.size_align(repeated);
+            offset = round_up(offset, field_align);
+            fields.push(Field {
+                name: field.name().to_owned(),
+                json_name: field.json_name().to_owned(),
+                kind,
+                repeated,
+                offset,
+            });
+            offset += field_size;
+            align = align.max(field_align);
+        }
+
+        Ok(Layout {
+            name: message.trim_start_matches('.').to_owned(),
+            fields,
+            size: round_up(offset, align),
+            align,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Writes the message described by `value` to `dst`. Every field is written, so `dst` may be
+    /// uninitialized.
+    ///
+    /// # Safety
+    ///
+    /// `dst` must be valid for writes of [`size`] bytes and aligned to [`align`].
+    ///
+    /// [`size`]: Layout::size
+    /// [`align`]: Layout::align
+    pub unsafe fn encode(&self, value: &Value, dst: *mut u8) -> Result<()> {
+        let empty = Map::new();
+        let obj = match value {
+            Value::Object(obj) => obj,
+            Value::Null => &empty,
+            _ => bail!("{}: expect a JSON object, found {}", self.name, value),
+        };
+        for key in obj.keys() {
+            if !self
+                .fields
+                .iter()
+                .any(|f| &f.name == key || &f.json_name == key)
+            {
+                bail!("{}: unknown field {}", self.name, key);
+            }
+        }
+        for (i, field) in self.fields.iter().enumerate() {
+            let v = obj
+                .get(&field.name)
+                .or_else(|| obj.get(&field.json_name))
+                .filter(|v| !v.is_null());
+            if let Err(e) = write_field(field, v, dst.add(field.offset)) {
+                // do not leak the fields already written
+                for field in self.fields[..i].iter() {
+                    drop_field(field, dst.add(field.offset));
+                }
+                return Err(e).with_context(|| format!("{}.{}", self.name, field.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the strings and vectors of the message at `dst`.
+    ///
+    /// # Safety
+    ///
+    /// `dst` must point to a message written by [`encode`], which must not be used afterwards.
+    ///
+    /// [`encode`]: Layout::encode
+    pub unsafe fn drop_in_place(&self, dst: *mut u8) {
+        for field in self.fields.iter() {
+            drop_field(field, dst.add(field.offset));
+        }
+    }
+
+    /// Reads the message at `src` into JSON.
+    ///
+    /// # Safety
+    ///
+    /// `src` must point to a valid message of this layout.
+    pub unsafe fn decode(&self, src: *const u8) -> Value {
+        let mut obj = Map::new();
+        for field in self.fields.iter() {
+            obj.insert(field.name.clone(), read_field(field, src.add(field.offset)));
+        }
+        Value::Object(obj)
+    }
+}
+
+#[inline]
+fn round_up(n: usize, align: usize) -> usize {
+    (n + align - 1) / align * align
+}
+
+fn to_i64(v: &Value) -> Result<i64> {
+    match v {
+        Value::Number(n) => n.as_i64(),
+        // proto3 JSON writes 64-bit integers as strings
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("expect an integer, found {}", v))
+}
+
+fn to_u64(v: &Value) -> Result<u64> {
+    match v {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("expect an unsigned integer, found {}", v))
+}
+
+fn to_i32(v: &Value) -> Result<i32> {
+    Ok(i32::try_from(to_i64(v)?)?)
+}
+
+fn to_u32(v: &Value) -> Result<u32> {
+    Ok(u32::try_from(to_u64(v)?)?)
+}
+
+fn to_f64(v: &Value) -> Result<f64> {
+    match v {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Some(f64::NAN),
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            s => s.parse().ok(),
+        },
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("expect a number, found {}", v))
+}
+
+fn to_bool(v: &Value) -> Result<bool> {
+    v.as_bool()
+        .ok_or_else(|| anyhow!("expect a boolean, found {}", v))
+}
+
+fn to_str(v: &Value) -> Result<&str> {
+    v.as_str()
+        .ok_or_else(|| anyhow!("expect a string, found {}", v))
+}
+
+fn to_bytes(v: &Value) -> Result<alloc::Vec<u8>> {
+    match v {
+        Value::String(s) => Ok(alloc::Vec::from(s.as_bytes())),
+        Value::Array(items) => {
+            let mut bytes = alloc::Vec::with_capacity(items.len());
+            for item in items {
+                bytes.push(u8::try_from(to_u64(item)?)?);
+            }
+            Ok(bytes)
+        }
+        _ => bail!("expect a string or an array of bytes, found {}", v),
+    }
+}
+
+fn to_enum(values: &[(String, i32)], v: &Value) -> Result<i32> {
+    match v {
+        Value::String(s) => values
+            .iter()
+            .find_map(|(name, number)| (name == s).then_some(*number))
+            .ok_or_else(|| anyhow!("unknown enum value {}", s)),
+        _ => to_i32(v),
+    }
+}
+
+unsafe fn write_vec<T>(
+    dst: *mut u8,
+    items: &[Value],
+    f: impl Fn(&Value) -> Result<T>,
+) -> Result<()> {
+    let mut vec = alloc::Vec::with_capacity(items.len());
+    for item in items {
+        vec.push(f(item)?);
+    }
+    dst.cast::<alloc::Vec<T>>().write(vec);
+    Ok(())
+}
+
+unsafe fn write_field(field: &Field, value: Option<&Value>, dst: *mut u8) -> Result<()> {
+    if field.repeated {
+        let items = match value {
+            None => &[][..],
+            Some(Value::Array(items)) => items.as_slice(),
+            Some(v) => bail!("expect an array, found {}", v),
+        };
+        return match &field.kind {
+            Kind::Double => write_vec(dst, items, to_f64),
+            Kind::Float => write_vec(dst, items, |v| to_f64(v).map(|x| x as f32)),
+            Kind::Int64 => write_vec(dst, items, to_i64),
+            Kind::Uint64 => write_vec(dst, items, to_u64),
+            Kind::Int32 => write_vec(dst, items, to_i32),
+            Kind::Uint32 => write_vec(dst, items, to_u32),
+            Kind::Bool => write_vec(dst, items, to_bool),
+            Kind::Enum(values) => write_vec(dst, items, |v| to_enum(values, v)),
+            Kind::String => write_vec(dst, items, |v| to_str(v).map(alloc::String::from)),
+            Kind::Bytes => write_vec(dst, items, to_bytes),
+        };
+    }
+
+    match &field.kind {
+        Kind::Double => dst
+            .cast::<f64>()
+            .write(value.map(to_f64).transpose()?.unwrap_or_default()),
+        Kind::Float => dst
+            .cast::<f32>()
+            .write(value.map(to_f64).transpose()?.unwrap_or_default() as f32),
+        Kind::Int64 => dst
+            .cast::<i64>()
+            .write(value.map(to_i64).transpose()?.unwrap_or_default()),
+        Kind::Uint64 => dst
+            .cast::<u64>()
+            .write(value.map(to_u64).transpose()?.unwrap_or_default()),
+        Kind::Int32 => dst
+            .cast::<i32>()
+            .write(value.map(to_i32).transpose()?.unwrap_or_default()),
+        Kind::Uint32 => dst
+            .cast::<u32>()
+            .write(value.map(to_u32).transpose()?.unwrap_or_default()),
+        Kind::Bool => dst
+            .cast::<bool>()
+            .write(value.map(to_bool).transpose()?.unwrap_or_default()),
+        Kind::Enum(values) => dst.cast::<i32>().write(
+            value
+                .map(|v| to_enum(values, v))
+                .transpose()?
+                .unwrap_or_default(),
+        ),
+        Kind::String => dst.cast::<alloc::String>().write(alloc::String::from(
+            value.map(to_str).transpose()?.unwrap_or(""),
+        )),
+        Kind::Bytes => dst.cast::<alloc::Vec<u8>>().write(match value {
+            Some(v) => to_bytes(v)?,
+            None => alloc::Vec::new(),
+        }),
+    }
+    Ok(())
+}
+
+unsafe fn drop_field(field: &Field, dst: *mut u8) {
+    if field.repeated {
+        match &field.kind {
+            Kind::Double => dst.cast::<alloc::Vec<f64>>().drop_in_place(),
+            Kind::Float => dst.cast::<alloc::Vec<f32>>().drop_in_place(),
+            Kind::Int64 => dst.cast::<alloc::Vec<i64>>().drop_in_place(),
+            Kind::Uint64 => dst.cast::<alloc::Vec<u64>>().drop_in_place(),
+            Kind::Int32 | Kind::Enum(_) => dst.cast::<alloc::Vec<i32>>().drop_in_place(),
+            Kind::Uint32 => dst.cast::<alloc::Vec<u32>>().drop_in_place(),
+            Kind::Bool => dst.cast::<alloc::Vec<bool>>().drop_in_place(),
+            Kind::String => dst.cast::<alloc::Vec<alloc::String>>().drop_in_place(),
+            Kind::Bytes => dst.cast::<alloc::Vec<alloc::Vec<u8>>>().drop_in_place(),
+        }
+        return;
+    }
+
+    match &field.kind {
+        Kind::String => dst.cast::<alloc::String>().drop_in_place(),
+        Kind::Bytes => dst.cast::<alloc::Vec<u8>>().drop_in_place(),
+        _ => {}
+    }
+}
+
+fn bytes_to_json(bytes: &[u8]) -> Value {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => Value::from(s),
+        Err(_) => Value::from(bytes),
+    }
+}
+
+fn enum_to_json(values: &[(String, i32)], number: i32) -> Value {
+    values
+        .iter()
+        .find_map(|(name, n)| (*n == number).then(|| Value::from(name.as_str())))
+        .unwrap_or_else(|| Value::from(number))
+}
+
+unsafe fn read_vec<T>(src: *const u8, f: impl Fn(&T) -> Value) -> Value {
+    let vec = &*src.cast::<alloc::Vec<T>>();
+    Value::Array(vec.iter().map(f).collect())
+}
+
+unsafe fn read_field(field: &Field, src: *const u8) -> Value {
+    if field.repeated {
+        return match &field.kind {
+            Kind::Double => read_vec(src, |x: &f64| Value::from(*x)),
+            Kind::Float => read_vec(src, |x: &f32| Value::from(*x)),
+            Kind::Int64 => read_vec(src, |x: &i64| Value::from(*x)),
+            Kind::Uint64 => read_vec(src, |x: &u64| Value::from(*x)),
+            Kind::Int32 => read_vec(src, |x: &i32| Value::from(*x)),
+            Kind::Uint32 => read_vec(src, |x: &u32| Value::from(*x)),
+            Kind::Bool => read_vec(src, |x: &bool| Value::from(*x)),
+            Kind::Enum(values) => read_vec(src, |x: &i32| enum_to_json(values, *x)),
+            Kind::String => read_vec(src, |x: &alloc::String| Value::from(x.as_str())),
+            Kind::Bytes => read_vec(src, |x: &alloc::Vec<u8>| bytes_to_json(x)),
+        };
+    }
+
+    match &field.kind {
+        Kind::Double => Value::from(*src.cast::<f64>()),
+        Kind::Float => Value::from(*src.cast::<f32>()),
+        Kind::Int64 => Value::from(*src.cast::<i64>()),
+        Kind::Uint64 => Value::from(*src.cast::<u64>()),
+        Kind::Int32 => Value::from(*src.cast::<i32>()),
+        Kind::Uint32 => Value::from(*src.cast::<u32>()),
+        Kind::Bool => Value::from(*src.cast::<bool>()),
+        Kind::Enum(values) => enum_to_json(values, *src.cast::<i32>()),
+        Kind::String => Value::from((*src.cast::<alloc::String>()).as_str()),
+        Kind::Bytes => bytes_to_json(&*src.cast::<alloc::Vec<u8>>()),
+    }
+}
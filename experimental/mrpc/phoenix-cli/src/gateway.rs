@@ -0,0 +1,375 @@
+//! An HTTP/1.1 gateway to the unary methods of an mRPC server, so that browsers and curl can
+//! reach it.
+//!
+//! `POST /package.Service/Method` calls the method with the request in the JSON body, and answers
+//! with the reply in JSON. The messages are transcoded with the descriptors, as for
+//! `phoenix-cli call`. `GET /` lists the methods served. A failure is answered with
+//! `{"error": "..."}`, and a call that fails with the status of the call as well:
+//!
+//! ```text
+//! $ curl -d '{"name": "mRPC"}' localhost:8080/rpc_hello.Greeter/SayHello
+//! {"message":"Hello mRPC!"}
+//! ```
+//!
+//! The gateway is an mRPC client like any other. It is not an engine in phoenixd, as an engine
+//! cannot allocate the requests on the heap of an application. All the HTTP connections are
+//! served on one thread, which also drives the stub, so the calls from different connections
+//! are in flight at the same time.
+//!
+//! Only `Content-Length` bodies are accepted. Every response allows any origin, and preflight
+//! requests are answered, so that pages on other origins can call the methods.
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use smol::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use smol::net::{TcpListener, TcpStream};
+use smol::LocalExecutor;
+
+use mrpc::stub::ClientStub;
+use mrpc::Status;
+
+use crate::descriptor::{self, Descriptors, Method};
+use crate::dynamic::Layout;
+use crate::{load_descriptors, unary, GatewayOpts, Opaque, MAX_REQUEST_SIZE};
+
+const MAX_HEADER_LEN: usize = 8192;
+const MAX_BODY_LEN: usize = 1 << 20;
+
+/// A method served by the gateway.
+struct Route {
+    service_id: u32,
+    func_id: u32,
+    request: Arc<Layout>,
+    reply: Layout,
+}
+
+impl Route {
+    /// Returns the route of the method, and the proto source of its file.
+    fn new(descriptors: &Descriptors, method: &Method) -> Result<(Self, String)> {
+        let request = Layout::of(descriptors, method.method.input_type())?;
+        if request.size() > MAX_REQUEST_SIZE {
+            bail!("request message of {} bytes is too large", request.size());
+        }
+        let route = Route {
+            service_id: method.service_id(),
+            func_id: method.func_id(),
+            request: Arc::new(request),
+            reply: Layout::of(descriptors, method.method.output_type())?,
+        };
+        Ok((route, descriptor::to_proto_source(method.file)?))
+    }
+}
+
+struct Gateway {
+    client: ClientStub,
+    /// The routes by their path, i.e., `/package.Service/Method`.
+    routes: BTreeMap<String, Route>,
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+    /// Whether the client closes the connection after the response.
+    close: bool,
+}
+
+struct HttpResponse {
+    status: u16,
+    body: Option<Value>,
+}
+
+impl HttpResponse {
+    fn ok(body: Value) -> Self {
+        HttpResponse {
+            status: 200,
+            body: Some(body),
+        }
+    }
+
+    fn error(status: u16, error: impl ToString) -> Self {
+        HttpResponse {
+            status,
+            body: Some(json!({ "error": error.to_string() })),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        _ => "",
+    }
+}
+
+pub(crate) fn run(opts: GatewayOpts) -> Result<()> {
+    let descriptors = load_descriptors(&opts.descriptors)?;
+
+    let mut routes = BTreeMap::new();
+    let mut protos = BTreeMap::new();
+    for method in descriptors.methods() {
+        let path = format!("/{}/{}", method.service, method.method.name());
+        match Route::new(&descriptors, &method) {
+            Ok((route, proto)) => {
+                routes.insert(path, route);
+                protos.insert(method.file.name().to_owned(), proto);
+            }
+            Err(e) => eprintln!("skipping {}: {}", path, e),
+        }
+    }
+    if routes.is_empty() {
+        bail!("none of the methods in the descriptors can be served");
+    }
+
+    // The engine builds the marshalling library from the proto sources.
+    let protos: Vec<&str> = protos.values().map(String::as_str).collect();
+    mrpc::stub::update_protos(&protos)?;
+    let gateway = Rc::new(Gateway {
+        client: ClientStub::connect(opts.connect.as_str())?,
+        routes,
+    });
+
+    let ex = LocalExecutor::new();
+    smol::block_on(ex.run(accept(&ex, &opts.listen, gateway)))
+}
+
+async fn accept(ex: &LocalExecutor<'_>, addr: &str, gateway: Rc<Gateway>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    eprintln!(
+        "serving {} methods on http://{}",
+        gateway.routes.len(),
+        listener.local_addr()?
+    );
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let gateway = Rc::clone(&gateway);
+        ex.spawn(async move {
+            if let Err(e) = gateway.serve(stream).await {
+                eprintln!("connection from {}: {}", peer, e);
+            }
+        })
+        .detach();
+    }
+}
+
+impl Gateway {
+    /// Serves the requests of one connection, one after another.
+    async fn serve(&self, stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.clone());
+        let mut writer = stream;
+        loop {
+            let (response, close) = match read_request(&mut reader, &mut writer).await {
+                Ok(Some(request)) => (self.handle(&request).await, request.close),
+                // closed by the client
+                Ok(None) => return Ok(()),
+                // the rest of the stream cannot be parsed
+                Err(response) => (response, true),
+            };
+            write_response(&mut writer, &response, close).await?;
+            if close {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("OPTIONS", _) => HttpResponse {
+                status: 204,
+                body: None,
+            },
+            ("GET", "/") => {
+                HttpResponse::ok(json!({ "methods": self.routes.keys().collect::<Vec<_>>() }))
+            }
+            ("POST", path) => match self.routes.get(path) {
+                Some(route) => self.call(route, &request.body).await,
+                None => HttpResponse::error(404, format!("method {} not found", path)),
+            },
+            (method, path) if path == "/" || self.routes.contains_key(path) => {
+                HttpResponse::error(405, format!("{} is not allowed on {}", method, path))
+            }
+            (_, path) => HttpResponse::error(404, format!("{} not found", path)),
+        }
+    }
+
+    async fn call(&self, route: &Route, body: &[u8]) -> HttpResponse {
+        let body = if body.is_empty() {
+            Value::Null
+        } else {
+            match serde_json::from_slice(body) {
+                Ok(body) => body,
+                Err(e) => return HttpResponse::error(400, format!("invalid JSON: {}", e)),
+            }
+        };
+
+        let ids = (route.service_id, route.func_id);
+        match unary(&self.client, ids, &route.request, &body).await {
+            Ok(reply) => {
+                // SAFETY: the reply was marshalled by the engine for this output type.
+                let reply = unsafe { route.reply.decode(&*reply as *const Opaque as *const u8) };
+                HttpResponse::ok(reply)
+            }
+            Err(e) => match e.downcast_ref::<Status>() {
+                Some(status) => HttpResponse {
+                    status: 502,
+                    body: Some(json!({
+                        "error": status.message(),
+                        "code": format!("{:?}", status.code()),
+                    })),
+                },
+                // the request does not fit the message
+                None => HttpResponse::error(400, format!("{:#}", e)),
+            },
+        }
+    }
+}
+
+/// Reads the next request, or `None` if the client has closed the connection. The error is the
+/// response to send before closing the connection.
+async fn read_request(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut TcpStream,
+) -> Result<Option<HttpRequest>, HttpResponse> {
+    let bad_request = |e: std::io::Error| HttpResponse::error(400, e);
+
+    let mut head = String::new();
+    let mut line = String::new();
+    let mut limited = (&mut *reader).take(MAX_HEADER_LEN as u64);
+    loop {
+        line.clear();
+        if limited.read_line(&mut line).await.map_err(bad_request)? == 0 {
+            if head.is_empty() && line.is_empty() {
+                return Ok(None);
+            }
+            if limited.limit() == 0 {
+                return Err(HttpResponse::error(431, "the header is too large"));
+            }
+            return Err(HttpResponse::error(400, "the request is truncated"));
+        }
+        // a blank line ends the header, the ones before the request line are ignored
+        if line.trim().is_empty() {
+            if head.is_empty() {
+                continue;
+            }
+            break;
+        }
+        head.push_str(&line);
+    }
+
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method, target, version)
+        }
+        _ => {
+            return Err(HttpResponse::error(
+                400,
+                format!("invalid request line {:?}", request_line),
+            ))
+        }
+    };
+    let path = target.split('?').next().unwrap_or_default();
+
+    let mut close = version == "HTTP/1.0";
+    let mut content_length = 0;
+    let mut expect_continue = false;
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| HttpResponse::error(400, format!("invalid header {:?}", line)))?;
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value.parse().map_err(|_| {
+                    HttpResponse::error(400, format!("invalid Content-Length {:?}", value))
+                })?;
+            }
+            "transfer-encoding" => {
+                return Err(HttpResponse::error(
+                    501,
+                    "Transfer-Encoding is not supported, send the Content-Length",
+                ));
+            }
+            "connection" => {
+                let value = value.to_ascii_lowercase();
+                if value.contains("close") {
+                    close = true;
+                } else if value.contains("keep-alive") {
+                    close = false;
+                }
+            }
+            "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        return Err(HttpResponse::error(
+            413,
+            format!("the body is larger than {} bytes", MAX_BODY_LEN),
+        ));
+    }
+
+    // curl waits for this before sending a large body
+    if expect_continue && content_length > 0 {
+        writer
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .await
+            .map_err(bad_request)?;
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await.map_err(bad_request)?;
+
+    Ok(Some(HttpRequest {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        body,
+        close,
+    }))
+}
+
+async fn write_response(
+    writer: &mut TcpStream,
+    response: &HttpResponse,
+    close: bool,
+) -> std::io::Result<()> {
+    let body = match response.body.as_ref() {
+        Some(body) => serde_json::to_vec(body).expect("a JSON value is serializable"),
+        None => Vec::new(),
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    head.push_str("Access-Control-Allow-Origin: *\r\n");
+    if response.status == 204 {
+        head.push_str("Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n");
+        head.push_str("Access-Control-Allow-Headers: Content-Type\r\n");
+    } else {
+        head.push_str("Content-Type: application/json\r\n");
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    if close {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await
+}
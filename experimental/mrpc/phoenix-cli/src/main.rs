@@ -0,0 +1,332 @@
+//! Command line tool for poking mRPC services.
+//!
+//! `phoenix-cli call` invokes a unary method ad hoc. The request is given in JSON and encoded into
+//! the message layout using the proto descriptors, either from a FileDescriptorSet file (e.g.,
+//! `mrpc_file_descriptor_set.bin` in the OUT_DIR of a crate built with mrpc-build) or fetched
+//! through the reflection of a local mRPC server.
+//!
+//! `phoenix-cli gateway` serves the methods over HTTP/1.1 and JSON, see [`gateway`].
+//!
+//! `phoenix-cli dissector` generates a Wireshark dissector for the captures written by the
+//! Capture addon, with the names of the services and methods in the descriptors.
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use structopt::StructOpt;
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+use mrpc::stub::ClientStub;
+use mrpc::{RRef, WRef};
+use phoenix_api_mrpc::control_plane::{Query, QueryResponse};
+
+mod descriptor;
+mod dynamic;
+mod gateway;
+
+use descriptor::Descriptors;
+use dynamic::Layout;
+
+const MAX_MSG_LEN: usize = 65536;
+/// The largest request message that can be encoded.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+const MRPC_ENGINE: &str = "MrpcEngine";
+
+const DISSECTOR_TEMPLATE: &str = include_str!("mrpc_capture.lua");
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Command line tool for mRPC services")]
+enum Opts {
+    /// Invoke a unary method.
+    Call(CallOpts),
+    /// Serve the methods over HTTP/1.1 and JSON.
+    Gateway(GatewayOpts),
+    /// Generate a Wireshark dissector for the captures of the Capture addon.
+    Dissector(DissectorOpts),
+}
+
+#[derive(StructOpt, Debug)]
+struct DescriptorOpts {
+    /// Encoded FileDescriptorSets to look up the methods in.
+    #[structopt(short = "d", long = "descriptor-set")]
+    descriptor_sets: Vec<PathBuf>,
+
+    /// Fetch the descriptors through the reflection of a local server, identified by the pid
+    /// and the service subscription id of the server process.
+    #[structopt(long, requires = "sid")]
+    pid: Option<i32>,
+
+    #[structopt(long, requires = "pid")]
+    sid: Option<u64>,
+}
+
+#[derive(StructOpt, Debug)]
+struct CallOpts {
+    /// The address of the server, e.g., localhost:5000.
+    #[structopt(short = "c", long = "connect")]
+    connect: String,
+
+    /// The method to call, in the form of package.Service/Method.
+    method: String,
+
+    /// The request in JSON. Use @<path> to read it from a file, or - for stdin.
+    #[structopt(default_value = "{}")]
+    body: String,
+
+    #[structopt(flatten)]
+    descriptors: DescriptorOpts,
+
+    /// Print the reply on a single line.
+    #[structopt(long)]
+    compact: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct GatewayOpts {
+    /// The address to accept HTTP connections on.
+    #[structopt(short, long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// The address of the server, e.g., localhost:5000.
+    #[structopt(short = "c", long = "connect")]
+    connect: String,
+
+    #[structopt(flatten)]
+    descriptors: DescriptorOpts,
+}
+
+#[derive(StructOpt, Debug)]
+struct DissectorOpts {
+    #[structopt(flatten)]
+    descriptors: DescriptorOpts,
+
+    /// Where to write the dissector, defaults to stdout.
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn control_request(sock: &DomainSocket, req: &Request) -> Result<ResponseKind> {
+    let buf = bincode::serialize(req)?;
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path)?;
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice())?;
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf)?;
+    res.0
+        .map_err(|e| anyhow!("request to phoenix failed: {}", e))
+}
+
+/// Fetches the descriptors registered by a server through the reflection of its MrpcEngine.
+fn fetch_descriptors(pid: i32, sid: u64, descriptors: &mut Descriptors) -> Result<()> {
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path)?;
+    }
+    let sock = DomainSocket::bind(&sock_path)?;
+
+    let subscriptions = match control_request(&sock, &Request::ListSubscription)? {
+        ResponseKind::ListSubscription(subscriptions) => subscriptions,
+        _ => bail!("invalid response"),
+    };
+    let eid = subscriptions
+        .iter()
+        .filter(|s| s.pid == pid && s.sid == sid)
+        .flat_map(|s| s.engines.iter())
+        .find_map(|(eid, ty)| (ty == MRPC_ENGINE).then_some(*eid))
+        .ok_or_else(|| anyhow!("no {} found for pid={}, sid={}", MRPC_ENGINE, pid, sid))?;
+
+    let query = bincode::serialize(&Query::Reflection)?;
+    let answer = match control_request(&sock, &Request::EngineQuery(eid, query))? {
+        ResponseKind::EngineQuery(answer) => answer,
+        _ => bail!("invalid response"),
+    };
+    let QueryResponse::Reflection(info) = bincode::deserialize(&answer)?;
+    for set in info.file_descriptor_sets.iter() {
+        descriptors.add_encoded_set(set)?;
+    }
+
+    std::fs::remove_file(&sock_path)?;
+    Ok(())
+}
+
+fn read_body(body: &str) -> Result<Value> {
+    let text = if body == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else if let Some(path) = body.strip_prefix('@') {
+        std::fs::read_to_string(path)?
+    } else {
+        body.to_owned()
+    };
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// A chunk of memory to hold a request of up to N bytes.
+#[repr(C, align(8))]
+struct Blob<const N: usize>([u8; N]);
+
+/// An encoded request, which drops its strings and vectors with it. The message comes first, so
+/// the request can be marshalled as the message.
+#[repr(C)]
+struct Request<const N: usize> {
+    blob: Blob<N>,
+    layout: Arc<Layout>,
+}
+
+impl<const N: usize> Drop for Request<N> {
+    fn drop(&mut self) {
+        // SAFETY: the blob was written by the layout when the request was created.
+        unsafe { self.layout.drop_in_place(self.blob.0.as_mut_ptr()) };
+    }
+}
+
+/// The reply, only ever accessed through its address.
+#[repr(C, align(8))]
+struct Opaque([u8; 0]);
+
+async fn unary_sized<const N: usize>(
+    client: &ClientStub,
+    ids: (u32, u32),
+    layout: &Arc<Layout>,
+    body: &Value,
+) -> Result<RRef<Opaque>> {
+    let mut blob = Blob([0u8; N]);
+    // SAFETY: the blob is aligned to 8 and large enough, checked by the caller.
+    unsafe { layout.encode(body, blob.0.as_mut_ptr())? };
+    let req = WRef::new(Request {
+        blob,
+        layout: Arc::clone(layout),
+    });
+
+    let call_id = client.initiate_call();
+    let reply = client.unary(ids.0, ids.1, call_id, req).await?;
+    Ok(reply)
+}
+
+/// Calls the method identified by `ids`, i.e., the service id and the function id, with the
+/// request in `body`. A failed call is an error wrapping its [`mrpc::Status`].
+async fn unary(
+    client: &ClientStub,
+    ids: (u32, u32),
+    layout: &Arc<Layout>,
+    body: &Value,
+) -> Result<RRef<Opaque>> {
+    assert!(layout.align() <= 8);
+    match layout.size() {
+        0..=64 => unary_sized::<64>(client, ids, layout, body).await,
+        65..=256 => unary_sized::<256>(client, ids, layout, body).await,
+        257..=1024 => unary_sized::<1024>(client, ids, layout, body).await,
+        1025..=MAX_REQUEST_SIZE => unary_sized::<MAX_REQUEST_SIZE>(client, ids, layout, body).await,
+        size => bail!("request message of {} bytes is too large", size),
+    }
+}
+
+fn load_descriptors(opts: &DescriptorOpts) -> Result<Descriptors> {
+    let mut descriptors = Descriptors::default();
+    for path in opts.descriptor_sets.iter() {
+        descriptors.add_set_file(path)?;
+    }
+    if let (Some(pid), Some(sid)) = (opts.pid, opts.sid) {
+        fetch_descriptors(pid, sid, &mut descriptors)?;
+    }
+    Ok(descriptors)
+}
+
+fn call(opts: CallOpts) -> Result<()> {
+    let descriptors = load_descriptors(&opts.descriptors)?;
+    let method = descriptors.find_method(&opts.method)?;
+    let req_layout = Arc::new(Layout::of(&descriptors, method.method.input_type())?);
+    let res_layout = Layout::of(&descriptors, method.method.output_type())?;
+    let body = read_body(&opts.body)?;
+
+    // The engine builds the marshalling library from the proto sources.
+    let proto = descriptor::to_proto_source(method.file)?;
+    mrpc::stub::update_protos(&[proto.as_str()])?;
+    let client = ClientStub::connect(opts.connect.as_str())?;
+
+    let ids = (method.service_id(), method.func_id());
+    let reply = smol::block_on(unary(&client, ids, &req_layout, &body))?;
+
+    // SAFETY: the reply was marshalled by the engine for this output type.
+    let reply = unsafe { res_layout.decode(&*reply as *const Opaque as *const u8) };
+    if opts.compact {
+        println!("{}", serde_json::to_string(&reply)?);
+    } else {
+        println!("{}", serde_json::to_string_pretty(&reply)?);
+    }
+    Ok(())
+}
+
+fn dissector(opts: DissectorOpts) -> Result<()> {
+    let descriptors = load_descriptors(&opts.descriptors)?;
+
+    let mut services = String::new();
+    let mut methods = String::new();
+    let mut seen = std::collections::BTreeSet::new();
+    for method in descriptors.methods() {
+        if seen.insert(method.service_id()) {
+            writeln!(
+                services,
+                "    [{}] = \"{}\",",
+                method.service_id(),
+                method.service
+            )?;
+        }
+        writeln!(
+            methods,
+            "    [{}] = \"{}/{}\",",
+            method.func_id(),
+            method.service,
+            method.method.name()
+        )?;
+    }
+    let lua = DISSECTOR_TEMPLATE
+        .replace("@SERVICES@", &services)
+        .replace("@METHODS@", &methods);
+
+    match opts.output {
+        Some(path) => std::fs::write(path, lua)?,
+        None => print!("{}", lua),
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    match Opts::from_args() {
+        Opts::Call(opts) => call(opts),
+        Opts::Gateway(opts) => gateway::run(opts),
+        Opts::Dissector(opts) => dissector(opts),
+    }
+}
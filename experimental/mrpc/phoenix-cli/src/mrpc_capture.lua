@@ -0,0 +1,78 @@
+-- Wireshark dissector for the mRPC captures written by the Capture addon.
+--
+-- Generated by `phoenix-cli dissector`, which fills in the names of the services and methods.
+-- Copy it to the Wireshark plugin directory, e.g., ~/.local/lib/wireshark/plugins/, or load it
+-- with `wireshark -X lua_script:mrpc_capture.lua`.
+local mrpc = Proto("mrpc", "mRPC capture")
+
+local HEADER_LEN = 48
+
+local directions = { [0] = "Tx", [1] = "Rx" }
+local kinds = { [0] = "Message", [1] = "Ack", [2] = "RecvError" }
+local msg_types = { [0] = "Request", [1] = "Response", [2] = "Notification" }
+local status_codes = { [0] = "Success", [1] = "AccessDenied", [2] = "Unknown", [3] = "MessageTooLarge" }
+
+-- service_id -> name
+local services = {
+@SERVICES@}
+
+-- func_id -> name
+local methods = {
+@METHODS@}
+
+local f = mrpc.fields
+f.version = ProtoField.uint8("mrpc.version", "Version")
+f.direction = ProtoField.uint8("mrpc.direction", "Direction", base.DEC, directions)
+f.kind = ProtoField.uint8("mrpc.kind", "Kind", base.DEC, kinds)
+f.transport_status = ProtoField.uint32("mrpc.transport_status", "Transport status")
+f.conn_id = ProtoField.uint64("mrpc.conn_id", "Connection")
+f.call_id = ProtoField.uint64("mrpc.call_id", "Call ID")
+f.token = ProtoField.uint64("mrpc.token", "Token", base.HEX)
+f.service_id = ProtoField.uint32("mrpc.service_id", "Service", base.HEX, services)
+f.func_id = ProtoField.uint32("mrpc.func_id", "Method", base.HEX, methods)
+f.msg_type = ProtoField.uint8("mrpc.msg_type", "Message type", base.DEC, msg_types)
+f.status_code = ProtoField.uint8("mrpc.status_code", "Status code", base.DEC, status_codes)
+f.payload_len = ProtoField.uint32("mrpc.payload_len", "Payload length")
+f.payload = ProtoField.bytes("mrpc.payload", "Payload")
+
+function mrpc.dissector(buf, pinfo, tree)
+    if buf:len() < HEADER_LEN then
+        return 0
+    end
+    pinfo.cols.protocol = "mRPC"
+
+    local t = tree:add(mrpc, buf(0, HEADER_LEN))
+    t:add(f.version, buf(0, 1))
+    t:add(f.direction, buf(1, 1))
+    t:add(f.kind, buf(2, 1))
+    t:add_le(f.transport_status, buf(4, 4))
+    t:add_le(f.conn_id, buf(8, 8))
+    t:add_le(f.call_id, buf(16, 8))
+    t:add_le(f.token, buf(24, 8))
+    t:add_le(f.service_id, buf(32, 4))
+    t:add_le(f.func_id, buf(36, 4))
+    t:add(f.msg_type, buf(40, 1))
+    t:add(f.status_code, buf(41, 1))
+    t:add_le(f.payload_len, buf(44, 4))
+
+    local direction = directions[buf(1, 1):uint()] or "?"
+    local kind = buf(2, 1):uint()
+    local call_id = tostring(buf(16, 8):le_uint64())
+    if kind == 0 then
+        local func_id = buf(36, 4):le_uint()
+        local name = methods[func_id] or string.format("0x%08x", func_id)
+        local msg_type = msg_types[buf(40, 1):uint()] or "?"
+        pinfo.cols.info = string.format("%s %s %s call_id=%s", direction, msg_type, name, call_id)
+    else
+        pinfo.cols.info = string.format("%s %s call_id=%s status=%d", direction,
+            kinds[kind] or "?", call_id, buf(4, 4):le_uint())
+    end
+
+    local payload_len = buf(44, 4):le_uint()
+    if payload_len > 0 and buf:len() >= HEADER_LEN + payload_len then
+        t:add(f.payload, buf(HEADER_LEN, payload_len))
+    end
+    return buf:len()
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, mrpc)
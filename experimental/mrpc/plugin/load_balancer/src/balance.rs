@@ -0,0 +1,316 @@
+//! Picks the backend of a call among the backend connections of its route.
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+
+use fnv::{FnvHashMap, FnvHasher};
+
+use phoenix_api::rpc::MessageMeta;
+use phoenix_api::Handle;
+
+use crate::config::{HashKey, LoadBalancerConfig, Strategy};
+
+enum Picker {
+    RoundRobin,
+    /// Smooth weighted round-robin: each pick adds the weights to the current values and takes
+    /// the backend with the largest, which then gives back the total weight.
+    Weighted {
+        weights: Vec<i64>,
+        current: Vec<i64>,
+        total: i64,
+    },
+    /// The points of the backends on the ring, sorted, with the index of their backend.
+    ConsistentHash {
+        key: HashKey,
+        ring: Vec<(u64, usize)>,
+    },
+}
+
+pub(crate) struct Balancer {
+    conns: Vec<Handle>,
+    picker: Picker,
+}
+
+/// Finalizes an FNV hash, whose low bits are poorly mixed, into a point on the ring.
+#[inline]
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^ (x >> 33)
+}
+
+#[inline]
+fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    value.hash(&mut hasher);
+    mix(hasher.finish())
+}
+
+impl Balancer {
+    pub(crate) fn new(
+        conns: Vec<Handle>,
+        peers: &FnvHashMap<Handle, SocketAddr>,
+        config: &LoadBalancerConfig,
+    ) -> Self {
+        let weights: Vec<_> = conns
+            .iter()
+            .map(|conn| config.weight(peers.get(conn)) as i64)
+            .collect();
+        let picker = match config.strategy {
+            Strategy::RoundRobin => Picker::RoundRobin,
+            Strategy::WeightedRoundRobin => Picker::Weighted {
+                total: weights.iter().sum(),
+                current: vec![0; conns.len()],
+                weights,
+            },
+            Strategy::ConsistentHash => {
+                let mut ring = Vec::new();
+                for (i, conn) in conns.iter().enumerate() {
+                    // Place the backend by its address rather than its handle, so it keeps its
+                    // points when it reconnects.
+                    let identity = peers.get(conn).map_or_else(|| hash(conn), hash);
+                    let points = weights[i] as u64 * config.virtual_nodes as u64;
+                    ring.extend((0..points).map(|v| (hash((identity, v)), i)));
+                }
+                ring.sort_unstable();
+                Picker::ConsistentHash {
+                    key: config.hash_key,
+                    ring,
+                }
+            }
+        };
+        Balancer { conns, picker }
+    }
+
+    #[inline]
+    pub(crate) fn conns(&self) -> &[Handle] {
+        &self.conns
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.conns.is_empty()
+    }
+
+    /// Picks the backend of the call among those `admit` accepts, `None` if there is none. If it
+    /// accepts none of them, the backend the call would go to without it is picked.
+    pub(crate) fn pick<F>(&mut self, meta: &MessageMeta, admit: F) -> Option<Handle>
+    where
+        F: Fn(Handle) -> bool,
+    {
+        let n = self.conns.len();
+        if n == 0 {
+            return None;
+        }
+        let mut first = None;
+        if let Picker::ConsistentHash { key, ring } = &self.picker {
+            // the keys of a backend not admitted go to the next backends on the ring
+            let point = hash(key.extract(meta));
+            let start = ring.partition_point(|&(p, _)| p < point);
+            for step in 0..ring.len() {
+                let conn = self.conns[ring[(start + step) % ring.len()].1];
+                if admit(conn) {
+                    return Some(conn);
+                }
+                first.get_or_insert(conn);
+            }
+            return first;
+        }
+        for attempt in 0..n {
+            let index = match &mut self.picker {
+                Picker::RoundRobin => (meta.call_id.0 as usize + attempt) % n,
+                Picker::Weighted {
+                    weights,
+                    current,
+                    total,
+                } => {
+                    let mut best = 0;
+                    for i in 0..weights.len() {
+                        current[i] += weights[i];
+                        if current[i] > current[best] {
+                            best = i;
+                        }
+                    }
+                    current[best] -= *total;
+                    best
+                }
+                Picker::ConsistentHash { .. } => unreachable!(),
+            };
+            let conn = self.conns[index];
+            if admit(conn) {
+                return Some(conn);
+            }
+            first.get_or_insert(conn);
+        }
+        first
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phoenix_api::rpc::{CallId, RpcMsgType, StatusCode};
+
+    use crate::config::BackendWeight;
+
+    fn meta(call_id: u64, token: u64) -> MessageMeta {
+        MessageMeta {
+            conn_id: Handle::MASTER,
+            service_id: 0,
+            func_id: 0,
+            call_id: CallId(call_id),
+            token,
+            msg_type: RpcMsgType::Request,
+            status_code: StatusCode::Success,
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    /// The backends 1 to `n`, at the ports 1 to `n`.
+    fn backends(n: u64) -> (Vec<Handle>, FnvHashMap<Handle, SocketAddr>) {
+        let conns = (1..=n).map(Handle).collect();
+        let peers = (1..=n).map(|i| (Handle(i), addr(i as u16))).collect();
+        (conns, peers)
+    }
+
+    fn config(strategy: Strategy) -> LoadBalancerConfig {
+        LoadBalancerConfig {
+            strategy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_backends() {
+        for strategy in [
+            Strategy::RoundRobin,
+            Strategy::WeightedRoundRobin,
+            Strategy::ConsistentHash,
+        ] {
+            let mut balancer = Balancer::new(Vec::new(), &FnvHashMap::default(), &config(strategy));
+            assert!(balancer.is_empty());
+            assert_eq!(balancer.pick(&meta(0, 0), |_| true), None);
+        }
+    }
+
+    #[test]
+    fn round_robin() {
+        let (conns, peers) = backends(3);
+        let mut balancer = Balancer::new(conns, &peers, &config(Strategy::RoundRobin));
+        let picks: Vec<_> = (0..6)
+            .map(|call_id| balancer.pick(&meta(call_id, 0), |_| true).unwrap().0)
+            .collect();
+        assert_eq!(picks, [1, 2, 3, 1, 2, 3]);
+
+        // a backend not admitted is skipped for the next one
+        let admit = |conn: Handle| conn != Handle(2);
+        assert_eq!(balancer.pick(&meta(1, 0), admit), Some(Handle(3)));
+        assert_eq!(balancer.pick(&meta(2, 0), admit), Some(Handle(3)));
+        // with none admitted, the backend the call would go to
+        assert_eq!(balancer.pick(&meta(1, 0), |_| false), Some(Handle(2)));
+    }
+
+    #[test]
+    fn weighted_round_robin() {
+        let (conns, peers) = backends(3);
+        let mut config = config(Strategy::WeightedRoundRobin);
+        config.weights = vec![BackendWeight {
+            backend: addr(1),
+            weight: 5,
+        }];
+        let mut balancer = Balancer::new(conns, &peers, &config);
+        // the picks of the heavier backend are spread out
+        let picks: Vec<_> = (0..14)
+            .map(|call_id| balancer.pick(&meta(call_id, 0), |_| true).unwrap().0)
+            .collect();
+        assert_eq!(picks, [1, 1, 2, 1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1]);
+
+        // the backends not admitted give their turn to the next
+        let picks: Vec<_> = (0..7)
+            .map(|call_id| {
+                let pick = balancer.pick(&meta(call_id, 0), |conn| conn != Handle(1));
+                pick.unwrap().0
+            })
+            .collect();
+        assert!(picks.iter().all(|&conn| conn != 1));
+        assert!(picks.contains(&2) && picks.contains(&3));
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let (conns, peers) = backends(4);
+        let config = config(Strategy::ConsistentHash);
+        let mut balancer = Balancer::new(conns.clone(), &peers, &config);
+        let picks: Vec<_> = (0..1000)
+            .map(|token| balancer.pick(&meta(token, token), |_| true).unwrap())
+            .collect();
+        // the calls of a key go to the same backend, whatever their call IDs
+        for token in 0..1000 {
+            let pick = balancer.pick(&meta(token + 1, token), |_| true);
+            assert_eq!(pick, Some(picks[token as usize]));
+        }
+        // and the keys are spread over the backends
+        for conn in conns.iter() {
+            let n = picks.iter().filter(|&pick| pick == conn).count();
+            assert!(n > 100, "{:?} gets {} of 1000 keys", conn, n);
+        }
+
+        // only the keys of a backend that leaves move
+        let mut without = Balancer::new(conns[..3].to_vec(), &peers, &config);
+        for token in 0..1000 {
+            let pick = without.pick(&meta(token, token), |_| true).unwrap();
+            let before = picks[token as usize];
+            if before != Handle(4) {
+                assert_eq!(pick, before);
+            }
+        }
+
+        // a backend not admitted passes its keys on, as if it had left
+        for token in 0..1000 {
+            let pick = balancer.pick(&meta(token, token), |conn| conn != Handle(4));
+            let left = without.pick(&meta(token, token), |_| true);
+            assert_eq!(pick, left);
+        }
+        // with none admitted, the backend the call would go to
+        for token in 0..100 {
+            let pick = balancer.pick(&meta(token, token), |_| false);
+            assert_eq!(pick, Some(picks[token as usize]));
+        }
+    }
+
+    #[test]
+    fn consistent_hash_by_address() {
+        let (conns, peers) = backends(3);
+        let config = config(Strategy::ConsistentHash);
+        let mut balancer = Balancer::new(conns, &peers, &config);
+        // backend 3 reconnects as backend 7, at the same address
+        let mut peers = peers;
+        peers.remove(&Handle(3));
+        peers.insert(Handle(7), addr(3));
+        let mut reconnected = Balancer::new(vec![Handle(1), Handle(2), Handle(7)], &peers, &config);
+        for token in 0..1000 {
+            let pick = balancer.pick(&meta(token, token), |_| true).unwrap();
+            let pick = if pick == Handle(3) { Handle(7) } else { pick };
+            assert_eq!(reconnected.pick(&meta(token, token), |_| true), Some(pick));
+        }
+    }
+
+    #[test]
+    fn hash_key() {
+        let (conns, peers) = backends(4);
+        let mut config = config(Strategy::ConsistentHash);
+        config.hash_key = HashKey::FuncId;
+        let mut balancer = Balancer::new(conns, &peers, &config);
+        let mut call = meta(0, 0);
+        call.func_id = 42;
+        let pick = balancer.pick(&call, |_| true);
+        for token in 0..100 {
+            call.token = token;
+            assert_eq!(balancer.pick(&call, |_| true), pick);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::net::SocketAddr;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use phoenix_api::rpc::MessageMeta;
+use phoenix_api_load_balancer::control_plane::Route;
+
+/// How a call picks one of the backends of its route.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Spreads the calls evenly by their call IDs.
+    #[default]
+    RoundRobin,
+    /// Spreads the calls in proportion to the weights of the backends.
+    WeightedRoundRobin,
+    /// Hashes the key of each call onto a ring of the backends, so the calls of a key go to the
+    /// same backend, and only the keys of a backend that leaves or joins move.
+    ConsistentHash,
+}
+
+/// The header field of a call that consistent hashing is keyed on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKey {
+    /// The token the application associates with the call.
+    #[default]
+    Token,
+    ServiceId,
+    FuncId,
+}
+
+impl HashKey {
+    #[inline]
+    pub(crate) fn extract(&self, meta: &MessageMeta) -> u64 {
+        match self {
+            HashKey::Token => meta.token,
+            HashKey::ServiceId => meta.service_id as u64,
+            HashKey::FuncId => meta.func_id as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendWeight {
+    /// The peer address of the backend connection.
+    pub backend: SocketAddr,
+    pub weight: u32,
+}
+
+/// Ejects a backend whose latency or error rate stands out from the other backends, for a
+/// while, then brings it back gradually.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutlierDetection {
+    /// The weight of a new call in the moving averages of the latency and the error rate.
+    #[serde(default = "default_ewma_alpha")]
+    pub ewma_alpha: f64,
+    /// A backend is an outlier if its latency is this many times the median of the others.
+    #[serde(default = "default_latency_factor")]
+    pub latency_factor: f64,
+    /// A backend is an outlier if its error rate is this much above the median of the others.
+    #[serde(default = "default_error_margin")]
+    pub error_margin: f64,
+    /// A backend is judged only after this many calls since it was brought back.
+    #[serde(default = "default_min_requests")]
+    pub min_requests: u64,
+    /// The backends are judged every this many milliseconds.
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// A backend is ejected for this many milliseconds times the number of times it has been
+    /// ejected recently, up to 10 times.
+    #[serde(default = "default_base_ejection_ms")]
+    pub base_ejection_ms: u64,
+    /// A backend brought back gets a growing share of its calls over this many milliseconds.
+    #[serde(default = "default_ramp_ms")]
+    pub ramp_ms: u64,
+    /// At most this fraction of the backends is ejected at once.
+    #[serde(default = "default_max_ejection_fraction")]
+    pub max_ejection_fraction: f64,
+}
+
+fn default_ewma_alpha() -> f64 {
+    0.1
+}
+
+fn default_latency_factor() -> f64 {
+    3.0
+}
+
+fn default_error_margin() -> f64 {
+    0.3
+}
+
+fn default_min_requests() -> u64 {
+    20
+}
+
+fn default_interval_ms() -> u64 {
+    10_000
+}
+
+fn default_base_ejection_ms() -> u64 {
+    30_000
+}
+
+fn default_ramp_ms() -> u64 {
+    10_000
+}
+
+fn default_max_ejection_fraction() -> f64 {
+    0.5
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoadBalancerConfig {
+    /// The routes of the engines created, see `control_plane::Request::SetRoutes`.
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// The weights of the backends, 1 for those not listed. Weighted round-robin sends them
+    /// calls in proportion, and consistent hashing gives them points on the ring in proportion.
+    #[serde(default)]
+    pub weights: Vec<BackendWeight>,
+    #[serde(default)]
+    pub hash_key: HashKey,
+    /// The points each unit of weight puts on the ring of consistent hashing.
+    #[serde(default = "default_virtual_nodes")]
+    pub virtual_nodes: u32,
+    /// No backend is ejected if omitted.
+    #[serde(default)]
+    pub outlier_detection: Option<OutlierDetection>,
+}
+
+fn default_virtual_nodes() -> u32 {
+    160
+}
+
+impl Default for LoadBalancerConfig {
+    fn default() -> Self {
+        LoadBalancerConfig {
+            routes: Vec::new(),
+            strategy: Strategy::default(),
+            weights: Vec::new(),
+            hash_key: HashKey::default(),
+            virtual_nodes: default_virtual_nodes(),
+            outlier_detection: None,
+        }
+    }
+}
+
+impl LoadBalancerConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config: LoadBalancerConfig = toml::from_str(config.unwrap_or(""))?;
+        check_routes(&config.routes)?;
+        for weight in config.weights.iter() {
+            if weight.weight == 0 {
+                bail!("the weight of backend {} must be positive", weight.backend);
+            }
+        }
+        if config.virtual_nodes == 0 {
+            bail!("virtual_nodes must be positive");
+        }
+        if let Some(outlier) = config.outlier_detection {
+            if !(outlier.ewma_alpha > 0.0 && outlier.ewma_alpha <= 1.0) {
+                bail!("ewma_alpha {} is not in (0, 1]", outlier.ewma_alpha);
+            }
+            if !(outlier.latency_factor > 1.0 && outlier.latency_factor.is_finite()) {
+                bail!("latency_factor {} must be above 1", outlier.latency_factor);
+            }
+            if !(outlier.error_margin > 0.0 && outlier.error_margin < 1.0) {
+                bail!(
+                    "error_margin {} is not between 0 and 1",
+                    outlier.error_margin
+                );
+            }
+            if !(outlier.max_ejection_fraction >= 0.0 && outlier.max_ejection_fraction < 1.0) {
+                bail!(
+                    "max_ejection_fraction {} is not in [0, 1)",
+                    outlier.max_ejection_fraction
+                );
+            }
+            if outlier.interval_ms == 0 {
+                bail!("interval_ms must be positive");
+            }
+        }
+        Ok(config)
+    }
+
+    /// The weight of the backend at `peer`.
+    pub(crate) fn weight(&self, peer: Option<&SocketAddr>) -> u32 {
+        peer.and_then(|peer| self.weights.iter().find(|w| w.backend == *peer))
+            .map_or(1, |w| w.weight)
+    }
+}
+
+pub(crate) fn check_routes(routes: &[Route]) -> anyhow::Result<()> {
+    for route in routes {
+        if route.backends.is_empty() {
+            bail!("route {:?} has no backends", route);
+        }
+    }
+    Ok(())
+}
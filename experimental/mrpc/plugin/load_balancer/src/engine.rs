@@ -1,10 +1,12 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::mem;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
 use std::os::unix::prelude::{AsRawFd, RawFd};
 use std::pin::Pin;
 use std::ptr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use fnv::FnvHashMap;
@@ -15,7 +17,7 @@ use mrpc_marshal::{ExcavateContext, SgE, SgList};
 use phoenix_api::buf::Range;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::net::{MappedAddrStatus, WcOpcode, WcStatus};
-use phoenix_api::rpc::{CallId, MessageMeta, RpcId, StatusCode, TransportStatus};
+use phoenix_api::rpc::{CallId, MessageMeta, RpcId, RpcMsgType, StatusCode, TransportStatus};
 use phoenix_api::transport::tcp::dp::Completion;
 use phoenix_api::{AsHandle, Handle};
 use phoenix_api_load_balancer::control_plane;
@@ -34,11 +36,15 @@ use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator,
 use phoenix_common::envelop::ResourceDowncast;
 use phoenix_common::impl_vertex_for_engine;
 use phoenix_common::log;
+use phoenix_common::metrics;
 use phoenix_common::module::{ModuleCollection, Version};
 use phoenix_common::resource::Error as ResourceError;
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 
+use super::balance::Balancer;
+use super::config::{check_routes, LoadBalancerConfig};
 use super::get_ops;
+use super::outlier::OutlierDetector;
 
 use super::{ControlPathError, DatapathError};
 
@@ -56,6 +62,14 @@ pub(crate) struct LoadBalancerEngine {
     pub(crate) p2v: FnvHashMap<Handle, Handle>,
     pub(crate) v2p: FnvHashMap<Handle, Vec<Handle>>,
     pub(crate) buffer: FnvHashMap<CallId, i32>,
+    /// The peer address of each connection, learned when it is connected.
+    pub(crate) peers: FnvHashMap<Handle, SocketAddr>,
+    pub(crate) config: LoadBalancerConfig,
+    /// The backends of the virtual connection.
+    pub(crate) balancer: Balancer,
+    /// The backends of the virtual connection that each route resolves to.
+    pub(crate) routed: Vec<Balancer>,
+    pub(crate) outlier: Option<OutlierDetector>,
     pub(crate) cmd_rx_upstream:
         tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
     pub(crate) cmd_tx_upstream:
@@ -78,8 +92,10 @@ impl Decompose for LoadBalancerEngine {
         // each call to `check_input_queue()` receives at most one message
         let mut work = 0;
         while !self.tx_inputs()[0].is_empty() {
-            if let Progress(n) = self.check_input_queue()? {
-                work += n;
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
             }
         }
         Ok(work)
@@ -99,6 +115,15 @@ impl Decompose for LoadBalancerEngine {
 
         let node = unsafe {
             collections.insert("mode".to_string(), Box::new(ptr::read(&engine._mode)));
+            collections.insert("p2v".to_string(), Box::new(ptr::read(&engine.p2v)));
+            collections.insert("v2p".to_string(), Box::new(ptr::read(&engine.v2p)));
+            collections.insert("buffer".to_string(), Box::new(ptr::read(&engine.buffer)));
+            collections.insert("peers".to_string(), Box::new(ptr::read(&engine.peers)));
+            collections.insert("config".to_string(), Box::new(ptr::read(&engine.config)));
+            collections.insert("outlier".to_string(), Box::new(ptr::read(&engine.outlier)));
+            // the balancers are rebuilt on restore
+            drop(ptr::read(&engine.balancer));
+            drop(ptr::read(&engine.routed));
             collections.insert(
                 "cmd_tx_upstream".to_string(),
                 Box::new(ptr::read(&engine.cmd_tx_upstream)),
@@ -161,6 +186,24 @@ impl LoadBalancerEngine {
             .downcast::<FnvHashMap<CallId, i32>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
 
+        let peers = *local
+            .remove("peers")
+            .unwrap()
+            .downcast::<FnvHashMap<Handle, SocketAddr>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<LoadBalancerConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let outlier = *local
+            .remove("outlier")
+            .unwrap()
+            .downcast::<Option<OutlierDetector>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
         let cmd_tx_upstream = *local
             .remove("cmd_tx_upstream")
             .unwrap()
@@ -191,10 +234,15 @@ impl LoadBalancerEngine {
             .downcast::<Slab<RpcId>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
 
-        let engine = LoadBalancerEngine {
+        let mut engine = LoadBalancerEngine {
             p2v,
             v2p,
             buffer,
+            peers,
+            balancer: Balancer::new(Vec::new(), &Default::default(), &config),
+            routed: Vec::new(),
+            outlier,
+            config,
             cmd_tx_upstream,
             cmd_rx_upstream,
             cmd_tx_downstream,
@@ -204,6 +252,7 @@ impl LoadBalancerEngine {
             indicator: Default::default(), // start: std::time::Instant::now(),
             rpc_ctx,
         };
+        engine.resolve_routes();
         Ok(engine)
     }
 }
@@ -260,10 +309,41 @@ impl Engine for LoadBalancerEngine {
                     );
                 }
             }
+            control_plane::Request::SetRoutes(routes) => {
+                check_routes(&routes)?;
+                log::info!("LoadBalancer routes: {:?}", routes);
+                self.config.routes = routes;
+                self.resolve_routes();
+            }
         }
 
         Ok(())
     }
+
+    fn handle_query(
+        &mut self,
+        query: Vec<u8>,
+        _cred: std::os::unix::ucred::UCred,
+    ) -> Result<Vec<u8>> {
+        let query: control_plane::Query = bincode::deserialize(&query[..])?;
+
+        let response = match query {
+            control_plane::Query::Routes => {
+                let routes = self
+                    .config
+                    .routes
+                    .iter()
+                    .zip(&self.routed)
+                    .map(|(route, balancer)| control_plane::RouteStatus {
+                        route: route.clone(),
+                        conns: balancer.conns().to_vec(),
+                    })
+                    .collect();
+                control_plane::QueryResponse::Routes(routes)
+            }
+        };
+        Ok(bincode::serialize(&response)?)
+    }
 }
 
 impl Drop for LoadBalancerEngine {
@@ -275,7 +355,26 @@ impl Drop for LoadBalancerEngine {
 }
 
 impl LoadBalancerEngine {
+    /// Resolves each route to the backends of the virtual connection at its addresses, and
+    /// rebuilds the balancers.
+    pub(crate) fn resolve_routes(&mut self) {
+        let backends = self.v2p.get(&Handle::MASTER).cloned().unwrap_or_default();
+        self.routed = self
+            .config
+            .routes
+            .iter()
+            .map(|route| {
+                let conns = route_backends(route, &backends, &self.peers);
+                Balancer::new(conns, &self.peers, &self.config)
+            })
+            .collect();
+        self.balancer = Balancer::new(backends, &self.peers, &self.config);
+    }
+
     async fn mainloop(&mut self) -> EngineResult {
+        let source = "LoadBalancerEngine";
+        // the clock is read once every 1024 rounds
+        let mut clock = future::Every::new(1024);
         loop {
             // let mut timer = utils::timer::Timer::new();
 
@@ -307,6 +406,13 @@ impl LoadBalancerEngine {
                 Status::Disconnected => return Ok(()),
             }
 
+            if let Some(outlier) = self.outlier.as_mut() {
+                if clock.tick() {
+                    let backends = self.v2p.get(&Handle::MASTER).map_or(&[][..], |b| &b[..]);
+                    outlier.evaluate(backends, &self.peers, source);
+                }
+            }
+
             self.indicator.set_nwork(work);
 
             // timer.tick();
@@ -318,6 +424,38 @@ impl LoadBalancerEngine {
     }
 }
 
+/// The backends among `backends` whose peer addresses `route` lists.
+fn route_backends(
+    route: &control_plane::Route,
+    backends: &[Handle],
+    peers: &FnvHashMap<Handle, SocketAddr>,
+) -> Vec<Handle> {
+    backends
+        .iter()
+        .copied()
+        .filter(|conn| {
+            peers
+                .get(conn)
+                .map_or(false, |peer| route.backends.contains(peer))
+        })
+        .collect()
+}
+
+/// The balancer of the first route that matches the call and has a backend connected, that of
+/// all the backends if there is none.
+fn route_balancer<'a>(
+    routes: &[control_plane::Route],
+    routed: &'a mut [Balancer],
+    balancer: &'a mut Balancer,
+    meta: &MessageMeta,
+) -> &'a mut Balancer {
+    routes
+        .iter()
+        .zip(routed.iter_mut())
+        .find(|(route, routed)| !routed.is_empty() && route.matches(meta.service_id, meta.func_id))
+        .map_or(balancer, |(_, routed)| routed)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum RpcStrategy {
     /// The entire message is encapuslated into one message, transmitted with one send/recv
@@ -330,56 +468,108 @@ impl LoadBalancerEngine {
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
 
-        match self.tx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineTxMessage::RpcMessage(msg) => {
-                        let conn_id = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() }.conn_id;
-                        let call_id: CallId = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() }.call_id;
-
-                        if conn_id == Handle::MASTER {
-                            self.buffer.insert(call_id, 0);
-                            let rconns = self
-                                .v2p
-                                .get(&conn_id)
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+                            let conn_id = meta.conn_id;
+                            let call_id: CallId = meta.call_id;
+
+                            if conn_id == Handle::MASTER {
+                                // SAFETY: the meta buffer is owned by this message until it is acked
+                                let header = unsafe { &mut (*msg.meta_buf_ptr.0.as_ptr()).header };
+                                if !header.forward() {
+                                    metrics::record_expired_message(&format!(
+                                        "load balancer, call_id={}",
+                                        call_id
+                                    ));
+                                    let rpc_id = RpcId(conn_id, call_id);
+                                    let status = TransportStatus::Error(unsafe {
+                                        NonZeroU32::new_unchecked(508)
+                                    });
+                                    self.rx_outputs()[0]
+                                        .send(EngineRxMessage::Ack(rpc_id, status))?;
+                                    return Ok(Progress(1));
+                                }
+
+                                self.buffer.insert(call_id, 0);
+                                let balancer = route_balancer(
+                                    &self.config.routes,
+                                    &mut self.routed,
+                                    &mut self.balancer,
+                                    meta,
+                                );
+                                let new_conn_id = match self.outlier.as_mut() {
+                                    Some(outlier) => {
+                                        let now = Instant::now();
+                                        let conn =
+                                            balancer.pick(meta, |conn| outlier.admit(conn, now));
+                                        if let Some(conn) = conn {
+                                            outlier.on_request(call_id, conn, now);
+                                        }
+                                        conn
+                                    }
+                                    None => balancer.pick(meta, |_| true),
+                                }
                                 .ok_or(DatapathError::Resource(ResourceError::NotFound))?;
-                            let new_conn_id = rconns[call_id.0 as usize % rconns.len()];
 
-                            unsafe {
-                                (*msg.meta_buf_ptr.as_meta_ptr()).conn_id = new_conn_id;
-                            };
+                                unsafe {
+                                    (*msg.meta_buf_ptr.as_meta_ptr()).conn_id = new_conn_id;
+                                };
+                            }
+                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
                         }
-                        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                        m => self.tx_outputs()[0].send(m)?,
                     }
-                    m => self.tx_outputs()[0].send(m)?,
+                    return Ok(Progress(1));
                 }
-                return Ok(Progress(1));
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
             }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
-        match self.rx_inputs()[0].try_recv() {
-            Ok(m) => {
-                match m {
-                    EngineRxMessage::Ack(rpc_id, status) => {
-                        let call_id = rpc_id.1;
-                        if let Some(_) = self.buffer.get(&call_id) {
-                            let new_rpc_id = RpcId(Handle::MASTER, call_id);
-                            self.buffer.remove(&call_id);
-                            self.rx_outputs()[0].send(EngineRxMessage::Ack(new_rpc_id, status))?;
-                        } else {
-                            self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(m) => {
+                    match m {
+                        EngineRxMessage::RpcMessage(msg) => {
+                            let meta = unsafe { msg.meta.as_ref() };
+                            if let Some(outlier) = self.outlier.as_mut() {
+                                if meta.msg_type == RpcMsgType::Response {
+                                    let success = meta.status_code == StatusCode::Success;
+                                    outlier.on_completion(meta.call_id, success);
+                                }
+                            }
+                            self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                        }
+                        EngineRxMessage::Ack(rpc_id, status) => {
+                            let call_id = rpc_id.1;
+                            if let (Some(outlier), TransportStatus::Error(_)) =
+                                (self.outlier.as_mut(), status)
+                            {
+                                // a request that fails to be sent never gets its reply
+                                outlier.on_completion(call_id, false);
+                            }
+                            if let Some(_) = self.buffer.get(&call_id) {
+                                let new_rpc_id = RpcId(Handle::MASTER, call_id);
+                                self.buffer.remove(&call_id);
+                                self.rx_outputs()[0]
+                                    .send(EngineRxMessage::Ack(new_rpc_id, status))?;
+                            } else {
+                                self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                            }
+                        }
+                        _ => {
+                            self.rx_outputs()[0].send(m)?;
                         }
                     }
-                    _ => {
-                        self.rx_outputs()[0].send(m)?;
-                    }
+                    return Ok(Progress(1));
                 }
-                return Ok(Progress(1));
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
             }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
         Ok(Progress(0))
@@ -399,6 +589,7 @@ impl LoadBalancerEngine {
                         for handle in handles {
                             self.p2v.insert(handle, vid);
                         }
+                        self.resolve_routes();
                         let comp = CompletionKind::MultiConnect(vid);
                         self.cmd_tx_upstream.send(Completion(Ok(comp)))?;
                     }
@@ -418,6 +609,14 @@ impl LoadBalancerEngine {
 
         match self.cmd_rx_downstream.try_recv() {
             Ok(Completion(comp)) => {
+                if let Ok(
+                    CompletionKind::Connect(resp) | CompletionKind::ConnectInternal(resp, _),
+                ) = &comp
+                {
+                    if let Some(peer) = resp.peer_addr {
+                        self.peers.insert(resp.conn_handle, peer);
+                    }
+                }
                 self.cmd_tx_upstream.send(Completion(comp))?;
                 Ok(Status::Progress(1))
             }
@@ -426,3 +625,119 @@ impl LoadBalancerEngine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use control_plane::Route;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn route(service_id: Option<u32>, func_id: Option<u32>, ports: &[u16]) -> Route {
+        Route {
+            service_id,
+            func_id,
+            backends: ports.iter().map(|&port| addr(port)).collect(),
+        }
+    }
+
+    fn meta(service_id: u32, func_id: u32) -> MessageMeta {
+        MessageMeta {
+            conn_id: Handle::MASTER,
+            service_id,
+            func_id,
+            call_id: CallId(1),
+            token: 0,
+            msg_type: RpcMsgType::Request,
+            status_code: StatusCode::Success,
+        }
+    }
+
+    /// The backends 1 to 3 at the ports 1 to 3, and backend 4 whose address is not known.
+    fn backends() -> (Vec<Handle>, FnvHashMap<Handle, SocketAddr>) {
+        let backends = (1..=4).map(Handle).collect();
+        let peers = (1..=3).map(|i| (Handle(i), addr(i as u16))).collect();
+        (backends, peers)
+    }
+
+    #[test]
+    fn resolve_backends() {
+        let (backends, peers) = backends();
+        let resolved = |ports: &[u16]| route_backends(&route(None, None, ports), &backends, &peers);
+        assert_eq!(resolved(&[1, 3]), [Handle(1), Handle(3)]);
+        assert_eq!(resolved(&[3, 2, 9]), [Handle(2), Handle(3)]);
+        assert!(resolved(&[9]).is_empty());
+        assert!(route_backends(&route(None, None, &[1]), &[], &peers).is_empty());
+    }
+
+    #[test]
+    fn first_matching_route() {
+        let (backends, peers) = backends();
+        let config = LoadBalancerConfig::default();
+        let routes = vec![
+            // the backend of this route is not connected
+            route(Some(1), Some(1), &[9]),
+            route(Some(1), Some(1), &[1]),
+            route(Some(1), None, &[2]),
+            route(None, Some(7), &[3]),
+        ];
+        let mut routed: Vec<_> = routes
+            .iter()
+            .map(|route| {
+                let conns = route_backends(route, &backends, &peers);
+                Balancer::new(conns, &peers, &config)
+            })
+            .collect();
+        let mut balancer = Balancer::new(backends.clone(), &peers, &config);
+
+        let mut picked = |service_id, func_id| {
+            let meta = meta(service_id, func_id);
+            route_balancer(&routes, &mut routed, &mut balancer, &meta)
+                .conns()
+                .to_vec()
+        };
+        assert_eq!(picked(1, 1), [Handle(1)]);
+        assert_eq!(picked(1, 2), [Handle(2)]);
+        assert_eq!(picked(1, 7), [Handle(2)]);
+        assert_eq!(picked(2, 7), [Handle(3)]);
+        // the calls no route matches go to all the backends
+        assert_eq!(picked(2, 1), backends);
+    }
+
+    #[test]
+    fn no_routes() {
+        let (backends, peers) = backends();
+        let config = LoadBalancerConfig::default();
+        let mut balancer = Balancer::new(backends.clone(), &peers, &config);
+        let picked = route_balancer(&[], &mut [], &mut balancer, &meta(1, 1));
+        assert_eq!(picked.conns(), backends);
+    }
+
+    #[test]
+    fn reject_empty_route() {
+        assert!(check_routes(&[route(Some(1), None, &[1])]).is_ok());
+        assert!(check_routes(&[route(Some(1), None, &[1]), route(None, None, &[])]).is_err());
+        let config = LoadBalancerConfig::new(Some(
+            r#"
+            [[routes]]
+            service_id = 1
+            backends = []
+            "#,
+        ));
+        assert!(config.is_err());
+        let config = LoadBalancerConfig::new(Some(
+            r#"
+            [[routes]]
+            func_id = 2
+            backends = ["10.0.0.1:1"]
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(config.routes.len(), 1);
+        assert_eq!(config.routes[0].service_id, None);
+        assert_eq!(config.routes[0].func_id, Some(2));
+        assert_eq!(config.routes[0].backends, [addr(1)]);
+    }
+}
@@ -15,7 +15,10 @@ use transport_tcp::{ops, ApiError, TransportError};
 use phoenix_common::resource::Error as ResourceError;
 pub use phoenix_common::{InitFnResult, PhoenixModule};
 
+pub(crate) mod balance;
+pub mod config;
 pub mod module;
+pub(crate) mod outlier;
 
 pub(crate) mod engine;
 
@@ -55,7 +58,11 @@ pub(crate) enum ControlPathError {
 
 impl From<ControlPathError> for phoenix_api::Error {
     fn from(other: ControlPathError) -> Self {
-        phoenix_api::Error::Generic(other.to_string())
+        let code = match &other {
+            ControlPathError::Resource(e) => e.code(),
+            _ => phoenix_api::ErrorCode::Internal,
+        };
+        phoenix_api::Error::from_error(code, &other)
     }
 }
 
@@ -84,10 +91,12 @@ pub(crate) enum DatapathError {
     Rx(#[from] phoenix_common::engine::datapath::SendError<EngineRxMessage>),
 }
 
+use crate::config::LoadBalancerConfig;
 use crate::module::LoadBalancerModule;
 
 #[no_mangle]
-pub fn init_module(_config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
-    let module = LoadBalancerModule::new();
+pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
+    let config = LoadBalancerConfig::new(config_string)?;
+    let module = LoadBalancerModule::new(config);
     Ok(Box::new(module))
 }
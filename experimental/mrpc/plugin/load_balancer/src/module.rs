@@ -22,7 +22,10 @@ use phoenix_common::module::{
 use phoenix_common::state_mgr::SharedStateManager;
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 
+use crate::balance::Balancer;
+use crate::config::LoadBalancerConfig;
 use crate::engine::{LoadBalancerEngine, TlStorage};
+use crate::outlier::OutlierDetector;
 
 pub(crate) struct LoadBalancerEngineBuilder {
     _client_pid: Pid,
@@ -32,6 +35,7 @@ pub(crate) struct LoadBalancerEngineBuilder {
     cmd_rx_downstream: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Completion>,
     cmd_tx_downstream: tokio::sync::mpsc::UnboundedSender<phoenix_api_mrpc::cmd::Command>,
     node: DataPathNode,
+    config: LoadBalancerConfig,
 }
 
 impl LoadBalancerEngineBuilder {
@@ -43,8 +47,8 @@ impl LoadBalancerEngineBuilder {
         cmd_rx_upstream: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
         cmd_tx_downstream: tokio::sync::mpsc::UnboundedSender<phoenix_api_mrpc::cmd::Command>,
         cmd_rx_downstream: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Completion>,
-
         node: DataPathNode,
+        config: LoadBalancerConfig,
     ) -> Self {
         LoadBalancerEngineBuilder {
             _client_pid: client_pid,
@@ -54,14 +58,20 @@ impl LoadBalancerEngineBuilder {
             cmd_tx_downstream,
             cmd_rx_downstream,
             node,
+            config,
         }
     }
 
     fn build(self) -> Result<LoadBalancerEngine> {
-        Ok(LoadBalancerEngine {
+        let mut engine = LoadBalancerEngine {
             p2v: Default::default(),
             v2p: Default::default(),
             buffer: Default::default(),
+            peers: Default::default(),
+            balancer: Balancer::new(Vec::new(), &Default::default(), &self.config),
+            routed: Vec::new(),
+            outlier: self.config.outlier_detection.map(OutlierDetector::new),
+            config: self.config,
             cmd_tx_upstream: self.cmd_tx_upstream,
             cmd_rx_upstream: self.cmd_rx_upstream,
             cmd_tx_downstream: self.cmd_tx_downstream,
@@ -71,11 +81,15 @@ impl LoadBalancerEngineBuilder {
             indicator: Default::default(),
             // start: std::time::Instant::now(),
             rpc_ctx: Default::default(),
-        })
+        };
+        engine.resolve_routes();
+        Ok(engine)
     }
 }
 
-pub struct LoadBalancerModule {}
+pub struct LoadBalancerModule {
+    config: LoadBalancerConfig,
+}
 
 impl LoadBalancerModule {
     pub const LOAD_BALANCER_ENGINE: EngineType = EngineType("LoadBalancerEngine");
@@ -83,15 +97,9 @@ impl LoadBalancerModule {
     pub const DEPENDENCIES: &'static [EnginePair] = &[];
 }
 
-impl Default for LoadBalancerModule {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl LoadBalancerModule {
-    pub fn new() -> Self {
-        LoadBalancerModule {}
+    pub fn new(config: LoadBalancerConfig) -> Self {
+        LoadBalancerModule { config }
     }
 }
 
@@ -121,6 +129,7 @@ impl PhoenixModule for LoadBalancerModule {
     fn migrate(&mut self, prev_module: Box<dyn PhoenixModule>) {
         // NOTE(wyj): we may better call decompose here
         let prev_concrete = unsafe { *prev_module.downcast_unchecked::<Self>() };
+        self.config = prev_concrete.config;
     }
 
     fn create_engine(
@@ -228,6 +237,7 @@ impl LoadBalancerModule {
             cmd_tx_downstream,
             cmd_rx_downstream,
             node,
+            self.config.clone(),
         );
         let engine = builder.build()?;
         Ok(engine)
@@ -0,0 +1,537 @@
+//! Ejects the backends whose latency or error rate stands out from the other backends.
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+
+use phoenix_api::rpc::CallId;
+use phoenix_api::Handle;
+use phoenix_common::event::{self, EventSeverity};
+use phoenix_common::metrics;
+
+use crate::config::OutlierDetection;
+
+const EVENT_KIND: &str = "load_balancer.outlier";
+/// The longest ejection, in multiples of the base ejection time.
+const MAX_EJECTION_MULTIPLIER: u32 = 10;
+/// The share of its calls a backend gets when it is brought back.
+const MIN_RAMP_SHARE: f64 = 0.1;
+/// The calls unanswered for this long are forgotten without a sample.
+const FORGET_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Healthy,
+    Ejected {
+        until: Instant,
+    },
+    /// Brought back, admitted for a share of its calls growing with the time since `since`.
+    Ramping {
+        since: Instant,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Backend {
+    /// The moving average of the latency of the calls, in microseconds.
+    latency_us: f64,
+    /// The moving average of the calls that fail.
+    error_rate: f64,
+    /// The calls since the backend was brought back.
+    calls: u64,
+    /// The number of recent ejections, which drops by one every interval the backend is healthy.
+    ejections: u32,
+    state: State,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend {
+            latency_us: 0.0,
+            error_rate: 0.0,
+            calls: 0,
+            ejections: 0,
+            state: State::Healthy,
+        }
+    }
+}
+
+pub(crate) struct OutlierDetector {
+    config: OutlierDetection,
+    backends: FnvHashMap<Handle, Backend>,
+    /// The backend and the send time of the calls awaiting their replies.
+    inflight: FnvHashMap<CallId, (Handle, Instant)>,
+    last_evaluated: Instant,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+impl OutlierDetector {
+    pub(crate) fn new(config: OutlierDetection) -> Self {
+        OutlierDetector {
+            config,
+            backends: FnvHashMap::default(),
+            inflight: FnvHashMap::default(),
+            last_evaluated: Instant::now(),
+        }
+    }
+
+    /// Whether a call can go to `conn`.
+    #[inline]
+    pub(crate) fn admit(&self, conn: Handle, now: Instant) -> bool {
+        let since = match self.backends.get(&conn).map(|b| b.state) {
+            None | Some(State::Healthy) => return true,
+            Some(State::Ejected { until }) if now < until => return false,
+            Some(State::Ejected { until }) => until,
+            Some(State::Ramping { since }) => since,
+        };
+        let ramp = Duration::from_millis(self.config.ramp_ms);
+        let elapsed = now.saturating_duration_since(since);
+        if elapsed >= ramp {
+            return true;
+        }
+        let share = elapsed.as_secs_f64() / ramp.as_secs_f64();
+        fastrand::f64() < share.max(MIN_RAMP_SHARE)
+    }
+
+    #[inline]
+    pub(crate) fn on_request(&mut self, call_id: CallId, conn: Handle, now: Instant) {
+        self.inflight.insert(call_id, (conn, now));
+    }
+
+    /// Samples the call when its reply arrives, or when it fails to be sent.
+    pub(crate) fn on_completion(&mut self, call_id: CallId, success: bool) {
+        let Some((conn, sent)) = self.inflight.remove(&call_id) else {
+            return;
+        };
+        let alpha = self.config.ewma_alpha;
+        let backend = self.backends.entry(conn).or_default();
+        if success {
+            let latency_us = sent.elapsed().as_secs_f64() * 1e6;
+            backend.latency_us += alpha * (latency_us - backend.latency_us);
+        }
+        let error = if success { 0.0 } else { 1.0 };
+        backend.error_rate += alpha * (error - backend.error_rate);
+        backend.calls += 1;
+    }
+
+    /// Brings back the backends whose ejection is over and ejects the outliers among `conns`,
+    /// once every interval. `peers` names the backends in the events.
+    pub(crate) fn evaluate(
+        &mut self,
+        conns: &[Handle],
+        peers: &FnvHashMap<Handle, SocketAddr>,
+        source: &str,
+    ) {
+        let now = Instant::now();
+        if now.duration_since(self.last_evaluated) < Duration::from_millis(self.config.interval_ms)
+        {
+            return;
+        }
+        self.last_evaluated = now;
+
+        self.inflight
+            .retain(|_, (_, sent)| now.duration_since(*sent) < FORGET_AFTER);
+        for (conn, backend) in self.backends.iter() {
+            if !conns.contains(conn) && matches!(backend.state, State::Ejected { .. }) {
+                metrics::record_backend_returned(&format!("{:?}, disconnected", conn));
+            }
+        }
+        self.backends.retain(|conn, _| conns.contains(conn));
+
+        let describe = |conn: &Handle| match peers.get(conn) {
+            Some(peer) => format!("backend {} ({:?})", peer, conn),
+            None => format!("backend {:?}", conn),
+        };
+
+        let ramp = Duration::from_millis(self.config.ramp_ms);
+        for (conn, backend) in self.backends.iter_mut() {
+            match backend.state {
+                State::Ejected { until } if now >= until => {
+                    backend.state = State::Ramping { since: until };
+                    backend.calls = 0;
+                    let message = format!("{} is brought back", describe(conn));
+                    metrics::record_backend_returned(&message);
+                    event::publish(EventSeverity::Info, source, EVENT_KIND, message);
+                }
+                State::Ramping { since } if now.duration_since(since) >= ramp => {
+                    backend.state = State::Healthy;
+                }
+                State::Healthy => {
+                    backend.ejections = backend.ejections.saturating_sub(1);
+                }
+                _ => {}
+            }
+        }
+
+        // the backends judged, those serving calls with enough of them
+        let judged: Vec<Handle> = self
+            .backends
+            .iter()
+            .filter(|(_, b)| {
+                !matches!(b.state, State::Ejected { .. }) && b.calls >= self.config.min_requests
+            })
+            .map(|(conn, _)| *conn)
+            .collect();
+        if judged.len() < 2 {
+            return;
+        }
+        let max_ejected = (conns.len() as f64 * self.config.max_ejection_fraction) as usize;
+        let mut ejected = self
+            .backends
+            .values()
+            .filter(|b| matches!(b.state, State::Ejected { .. }))
+            .count();
+
+        let mut outliers = Vec::new();
+        for conn in judged.iter() {
+            let backend = &self.backends[conn];
+            let (mut latencies, mut error_rates): (Vec<f64>, Vec<f64>) = judged
+                .iter()
+                .filter(|other| *other != conn)
+                .map(|other| {
+                    let other = &self.backends[other];
+                    (other.latency_us, other.error_rate)
+                })
+                .unzip();
+            let latency_median = median(&mut latencies);
+            let error_median = median(&mut error_rates);
+            let reason = if backend.error_rate > error_median + self.config.error_margin {
+                format!(
+                    "error rate {:.2} against a median of {:.2}",
+                    backend.error_rate, error_median
+                )
+            } else if backend.latency_us > latency_median * self.config.latency_factor {
+                format!(
+                    "latency {:.0}us against a median of {:.0}us",
+                    backend.latency_us, latency_median
+                )
+            } else {
+                continue;
+            };
+            outliers.push((*conn, reason));
+        }
+
+        for (conn, reason) in outliers {
+            if ejected >= max_ejected {
+                break;
+            }
+            ejected += 1;
+            let backend = self.backends.get_mut(&conn).unwrap();
+            backend.ejections = (backend.ejections + 1).min(MAX_EJECTION_MULTIPLIER);
+            let duration = Duration::from_millis(self.config.base_ejection_ms) * backend.ejections;
+            backend.state = State::Ejected {
+                until: now + duration,
+            };
+            let message = format!(
+                "{} is ejected for {}s, {}",
+                describe(&conn),
+                duration.as_secs(),
+                reason
+            );
+            metrics::record_backend_ejected(&message);
+            event::publish(EventSeverity::Warning, source, EVENT_KIND, message);
+        }
+    }
+}
+
+impl Drop for OutlierDetector {
+    fn drop(&mut self) {
+        for (conn, backend) in self.backends.iter() {
+            if matches!(backend.state, State::Ejected { .. }) {
+                metrics::record_backend_returned(&format!("{:?}, load balancer dropped", conn));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> OutlierDetection {
+        OutlierDetection {
+            ewma_alpha: 1.0,
+            latency_factor: 3.0,
+            error_margin: 0.3,
+            min_requests: 5,
+            interval_ms: 1,
+            base_ejection_ms: 30_000,
+            ramp_ms: 10_000,
+            max_ejection_fraction: 0.5,
+        }
+    }
+
+    /// Samples `calls` calls to `conn` that took `latency`.
+    fn sample(
+        detector: &mut OutlierDetector,
+        conn: Handle,
+        calls: u64,
+        latency: Duration,
+        success: bool,
+    ) {
+        for _ in 0..calls {
+            detector.on_request(CallId(0), conn, Instant::now() - latency);
+            detector.on_completion(CallId(0), success);
+        }
+    }
+
+    /// Evaluates the backends now, an interval after the last evaluation.
+    fn evaluate(detector: &mut OutlierDetector, conns: &[Handle]) {
+        let interval = Duration::from_millis(detector.config.interval_ms);
+        detector.last_evaluated = Instant::now() - interval;
+        detector.evaluate(conns, &FnvHashMap::default(), "test");
+    }
+
+    fn ejected(detector: &OutlierDetector, conn: Handle) -> bool {
+        matches!(detector.backends[&conn].state, State::Ejected { .. })
+    }
+
+    fn conns(n: u64) -> Vec<Handle> {
+        (1..=n).map(Handle).collect()
+    }
+
+    #[test]
+    fn medians() {
+        assert_eq!(median(&mut [3.0]), 3.0);
+        assert_eq!(median(&mut [4.0, 1.0]), 2.5);
+        assert_eq!(median(&mut [5.0, 1.0, 3.0]), 3.0);
+        assert_eq!(median(&mut [8.0, 1.0, 2.0, 4.0]), 3.0);
+    }
+
+    #[test]
+    fn moving_averages() {
+        let mut detector = OutlierDetector::new(OutlierDetection {
+            ewma_alpha: 0.5,
+            ..config()
+        });
+        let conn = Handle(1);
+        sample(&mut detector, conn, 1, Duration::ZERO, false);
+        sample(&mut detector, conn, 1, Duration::ZERO, true);
+        sample(&mut detector, conn, 1, Duration::ZERO, true);
+        let backend = &detector.backends[&conn];
+        assert_eq!(backend.error_rate, 0.125);
+        assert_eq!(backend.calls, 3);
+
+        sample(&mut detector, conn, 1, Duration::from_millis(100), true);
+        // half of the latency of the call, the other calls being much faster
+        let latency_us = detector.backends[&conn].latency_us;
+        assert!(
+            latency_us >= 50_000.0 && latency_us < 60_000.0,
+            "{}",
+            latency_us
+        );
+
+        // a call not in flight is not sampled
+        detector.on_completion(CallId(1), false);
+        assert_eq!(detector.backends[&conn].calls, 4);
+    }
+
+    #[test]
+    fn eject_errors() {
+        let mut detector = OutlierDetector::new(OutlierDetection {
+            latency_factor: 1e9,
+            ..config()
+        });
+        let conns = conns(3);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[2], 5, Duration::ZERO, false);
+
+        let now = Instant::now();
+        evaluate(&mut detector, &conns);
+        assert!(!ejected(&detector, conns[0]));
+        assert!(!ejected(&detector, conns[1]));
+        let State::Ejected { until } = detector.backends[&conns[2]].state else {
+            panic!("{:?} is not ejected", conns[2]);
+        };
+        assert!(until >= now + Duration::from_secs(30));
+        assert!(until <= Instant::now() + Duration::from_secs(30));
+        assert!(!detector.admit(conns[2], Instant::now()));
+        assert!(detector.admit(conns[0], Instant::now()));
+        // the backends never sampled are admitted
+        assert!(detector.admit(Handle(4), Instant::now()));
+    }
+
+    #[test]
+    fn eject_latency() {
+        let mut detector = OutlierDetector::new(config());
+        let conns = conns(3);
+        sample(&mut detector, conns[0], 5, Duration::from_millis(1), true);
+        sample(&mut detector, conns[1], 5, Duration::from_millis(1), true);
+        sample(&mut detector, conns[2], 5, Duration::from_millis(100), true);
+        evaluate(&mut detector, &conns);
+        assert!(!ejected(&detector, conns[0]));
+        assert!(!ejected(&detector, conns[1]));
+        assert!(ejected(&detector, conns[2]));
+    }
+
+    #[test]
+    fn judged_after_min_requests() {
+        let mut detector = OutlierDetector::new(config());
+        let conns = conns(3);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[2], 4, Duration::ZERO, false);
+        evaluate(&mut detector, &conns);
+        assert!(!ejected(&detector, conns[2]));
+
+        sample(&mut detector, conns[2], 1, Duration::ZERO, false);
+        evaluate(&mut detector, &conns);
+        assert!(ejected(&detector, conns[2]));
+    }
+
+    #[test]
+    fn not_judged_alone() {
+        let mut detector = OutlierDetector::new(config());
+        let conns = conns(2);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 4, Duration::ZERO, false);
+        evaluate(&mut detector, &conns);
+        assert!(!ejected(&detector, conns[1]));
+    }
+
+    #[test]
+    fn max_ejection_fraction() {
+        let mut detector = OutlierDetector::new(OutlierDetection {
+            latency_factor: 1e9,
+            max_ejection_fraction: 0.25,
+            ..config()
+        });
+        let conns = conns(4);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[2], 5, Duration::ZERO, false);
+        sample(&mut detector, conns[3], 5, Duration::ZERO, false);
+        evaluate(&mut detector, &conns);
+        let count = conns.iter().filter(|&&c| ejected(&detector, c)).count();
+        assert_eq!(count, 1);
+
+        // the other is ejected no sooner than the first is back
+        evaluate(&mut detector, &conns);
+        let count = conns.iter().filter(|&&c| ejected(&detector, c)).count();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn bring_back() {
+        let config = OutlierDetection {
+            latency_factor: 1e9,
+            ..config()
+        };
+        let mut detector = OutlierDetector::new(config);
+        let conns = conns(3);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[2], 5, Duration::ZERO, false);
+        evaluate(&mut detector, &conns);
+        assert!(ejected(&detector, conns[2]));
+
+        // the ejection is over
+        let until = Instant::now() - Duration::from_millis(1);
+        detector.backends.get_mut(&conns[2]).unwrap().state = State::Ejected { until };
+        assert!(detector.admit(conns[2], until + Duration::from_millis(config.ramp_ms)));
+        evaluate(&mut detector, &conns);
+        let backend = &detector.backends[&conns[2]];
+        assert!(matches!(backend.state, State::Ramping { since } if since == until));
+        assert_eq!(backend.calls, 0);
+        assert_eq!(backend.ejections, 1);
+
+        // a growing share of the calls is admitted while ramping
+        let ramp = Duration::from_millis(config.ramp_ms);
+        let admitted = |at: Instant| (0..1000).filter(|_| detector.admit(conns[2], at)).count();
+        let start = admitted(until);
+        let half = admitted(until + ramp / 2);
+        assert!(start > 30 && start < 200, "{}", start);
+        assert!(half > 400 && half < 600, "{}", half);
+        assert_eq!(admitted(until + ramp), 1000);
+
+        // not judged until it has served enough calls again
+        evaluate(&mut detector, &conns);
+        assert!(!ejected(&detector, conns[2]));
+
+        // healthy after the ramp
+        let since = Instant::now() - ramp;
+        detector.backends.get_mut(&conns[2]).unwrap().state = State::Ramping { since };
+        evaluate(&mut detector, &conns);
+        assert!(matches!(detector.backends[&conns[2]].state, State::Healthy));
+        // and forgives an ejection every interval
+        evaluate(&mut detector, &conns);
+        assert_eq!(detector.backends[&conns[2]].ejections, 0);
+    }
+
+    #[test]
+    fn ejections_grow() {
+        let mut detector = OutlierDetector::new(OutlierDetection {
+            latency_factor: 1e9,
+            ..config()
+        });
+        let conns = conns(3);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 5, Duration::ZERO, true);
+        for ejections in 1..=12 {
+            sample(&mut detector, conns[2], 5, Duration::ZERO, false);
+            let now = Instant::now();
+            evaluate(&mut detector, &conns);
+            let backend = &detector.backends[&conns[2]];
+            let multiplier = ejections.min(MAX_EJECTION_MULTIPLIER);
+            assert_eq!(backend.ejections, multiplier);
+            let State::Ejected { until } = backend.state else {
+                panic!("{:?} is not ejected", conns[2]);
+            };
+            assert!(until >= now + Duration::from_secs(30) * multiplier);
+            // brought back right away
+            let until = Instant::now() - Duration::from_millis(1);
+            detector.backends.get_mut(&conns[2]).unwrap().state = State::Ejected { until };
+            evaluate(&mut detector, &conns);
+        }
+    }
+
+    #[test]
+    fn forget_disconnected() {
+        let mut detector = OutlierDetector::new(OutlierDetection {
+            latency_factor: 1e9,
+            ..config()
+        });
+        let conns = conns(3);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[2], 5, Duration::ZERO, false);
+        evaluate(&mut detector, &conns);
+        assert!(ejected(&detector, conns[2]));
+
+        evaluate(&mut detector, &conns[..2]);
+        assert!(!detector.backends.contains_key(&conns[2]));
+        assert!(detector.admit(conns[2], Instant::now()));
+
+        // the calls unanswered for long are forgotten
+        detector.on_request(CallId(1), conns[0], Instant::now() - FORGET_AFTER);
+        detector.on_request(CallId(2), conns[0], Instant::now());
+        evaluate(&mut detector, &conns[..2]);
+        assert!(!detector.inflight.contains_key(&CallId(1)));
+        assert!(detector.inflight.contains_key(&CallId(2)));
+    }
+
+    #[test]
+    fn once_every_interval() {
+        let mut detector = OutlierDetector::new(OutlierDetection {
+            latency_factor: 1e9,
+            interval_ms: 10_000,
+            ..config()
+        });
+        let conns = conns(3);
+        sample(&mut detector, conns[0], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[1], 5, Duration::ZERO, true);
+        sample(&mut detector, conns[2], 5, Duration::ZERO, false);
+        detector.evaluate(&conns, &FnvHashMap::default(), "test");
+        assert!(!ejected(&detector, conns[2]));
+    }
+}
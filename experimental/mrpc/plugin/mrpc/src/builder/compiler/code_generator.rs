@@ -15,7 +15,7 @@ pub fn generate_marshal(method_id: &MethodIdentifier, ty: &str) -> Result<TokenS
             let ptr_backend = addr_backend as *mut #rust_ty;
             assert_eq!(ptr_backend.align_offset(std::mem::align_of::<#rust_ty>()), 0);
             let msg_ref = unsafe { &*ptr_backend };
-            msg_ref.marshal()
+            msg_ref.marshal_into(sgl)
         },
     };
     Ok(marshal)
@@ -76,17 +76,28 @@ pub fn generate(
             meta: &MessageMeta,
             addr_backend: usize,
         ) -> Result<SgList, MarshalError> {
+            let mut sgl = SgList::default();
+            marshal_into(meta, addr_backend, &mut sgl)?;
+            Ok(sgl)
+        }
+
+        #[no_mangle]
+        pub extern "Rust" fn marshal_into(
+            meta: &MessageMeta,
+            addr_backend: usize,
+            sgl: &mut SgList,
+        ) -> Result<(), MarshalError> {
             match meta.msg_type {
                 RpcMsgType::Request => {
                     match meta.func_id {
                         #(#requests_marshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => Err(MarshalError::UnknownFuncId(meta.func_id)),
                     }
                 },
-                RpcMsgType::Response => {
+                RpcMsgType::Response | RpcMsgType::Notification => {
                     match meta.func_id {
                         #(#responses_marshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => Err(MarshalError::UnknownFuncId(meta.func_id)),
                     }
                 }
             }
@@ -101,13 +112,13 @@ pub fn generate(
                 RpcMsgType::Request => {
                     match meta.func_id {
                         #(#requests_unmarshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => return Err(UnmarshalError::UnknownFuncId(meta.func_id)),
                     }
                 },
-                RpcMsgType::Response => {
+                RpcMsgType::Response | RpcMsgType::Notification => {
                     match meta.func_id {
                         #(#response_unmarshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => return Err(UnmarshalError::UnknownFuncId(meta.func_id)),
                     }
                 }
             };
@@ -1,6 +1,9 @@
+use std::io;
 use std::path::PathBuf;
 use std::process::Command;
 use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -108,6 +111,8 @@ pub enum Error {
     ProstBuild(#[from] prost::Error),
     #[error("Marshal Library Compile Error: {0}")]
     LibraryCompile(#[from] compiler::Error),
+    #[error("The build thread has exited")]
+    BuildThreadExited,
 }
 
 pub fn build_serializer_lib(protos: Vec<String>, cache_dir: PathBuf) -> Result<PathBuf, Error> {
@@ -171,3 +176,40 @@ pub fn build_serializer_lib(protos: Vec<String>, cache_dir: PathBuf) -> Result<P
         Ok(dylib_path)
     }
 }
+
+type BuildRequest = (
+    Vec<String>,
+    PathBuf,
+    mpsc::SyncSender<Result<PathBuf, Error>>,
+);
+
+/// Builds the dispatch libraries on a thread of its own. The module starts the thread from the
+/// control thread, so that the runtimes, which may be sandboxed, never run cargo themselves.
+#[derive(Debug, Clone)]
+pub struct BuildThread {
+    tx: mpsc::Sender<BuildRequest>,
+}
+
+impl BuildThread {
+    /// Starts the thread. It exits once every handle has been dropped.
+    pub fn spawn() -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel::<BuildRequest>();
+        thread::Builder::new()
+            .name("mrpc-build".to_string())
+            .spawn(move || {
+                for (protos, cache_dir, reply) in rx {
+                    let _ = reply.send(build_serializer_lib(protos, cache_dir));
+                }
+            })?;
+        Ok(BuildThread { tx })
+    }
+
+    /// Builds the library on the thread, and waits for it.
+    pub fn build(&self, protos: Vec<String>, cache_dir: PathBuf) -> Result<PathBuf, Error> {
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        self.tx
+            .send((protos, cache_dir, reply_tx))
+            .map_err(|_| Error::BuildThreadExited)?;
+        reply_rx.recv().map_err(|_| Error::BuildThreadExited)?
+    }
+}
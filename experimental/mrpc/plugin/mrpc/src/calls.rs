@@ -0,0 +1,51 @@
+//! Sequence checks of the calls on each connection.
+//!
+//! The app allocates the call ids of a connection in increasing order and never reuses them. A
+//! request whose call id is not above the previous ones is a duplicate or has been reordered, and
+//! a response is expected only for a request still outstanding. The checks catch the messages
+//! duplicated or reordered by the addons before they reach the app.
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+
+use phoenix_api::rpc::{CallId, RpcId};
+use phoenix_api::Handle;
+
+#[derive(Debug, Default)]
+pub(crate) struct CallTracker {
+    /// The call id of the last request sent on each connection
+    last_request: HashMap<Handle, CallId>,
+    /// The requests sent whose response has not been received
+    outstanding: HashSet<RpcId>,
+}
+
+impl CallTracker {
+    /// Records a request from the app. Returns false if its call id is not above the call ids of
+    /// the previous requests on the connection.
+    pub(crate) fn send_request(&mut self, rpc_id: RpcId) -> bool {
+        let RpcId(conn_id, call_id) = rpc_id;
+        if let Some(last) = self.last_request.get(&conn_id) {
+            if call_id.0 <= last.0 {
+                return false;
+            }
+        }
+        self.last_request.insert(conn_id, call_id);
+        self.outstanding.insert(rpc_id);
+        true
+    }
+
+    /// Records a response from the peer. Returns false if it does not answer an outstanding
+    /// request, e.g., the request has been answered already.
+    pub(crate) fn recv_response(&mut self, rpc_id: RpcId) -> bool {
+        self.outstanding.remove(&rpc_id)
+    }
+
+    /// Forgets a request that fails before it is answered.
+    pub(crate) fn fail_request(&mut self, rpc_id: RpcId) {
+        self.outstanding.remove(&rpc_id);
+    }
+
+    /// Forgets the calls of a connection that is gone.
+    pub(crate) fn close_connection(&mut self, conn_id: Handle) {
+        self.last_request.remove(&conn_id);
+        self.outstanding.retain(|rpc_id| rpc_id.0 != conn_id);
+    }
+}
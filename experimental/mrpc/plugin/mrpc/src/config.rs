@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+use phoenix_api_mrpc::cmd::MessageSizeLimit;
 use phoenix_api_mrpc::control_plane::TransportType;
 use serde::{Deserialize, Serialize};
 
@@ -20,13 +22,79 @@ pub struct MrpcConfig {
     /// Use NIC 0 by default
     #[serde(default)]
     pub nic_index: usize,
+    /// The maximum size of a marshalled request of any service, in bytes
+    #[serde(default)]
+    pub max_request_size: Option<usize>,
+    /// The maximum size of a marshalled response of any service, in bytes
+    #[serde(default)]
+    pub max_response_size: Option<usize>,
+    /// Message size limits of individual services, by the fully qualified service name
+    #[serde(default)]
+    pub services: HashMap<String, MessageSizeLimit>,
+    /// Record the work requests of each customer, for debugging
+    #[serde(default)]
+    pub record: Option<RecordConfig>,
+    /// Replay a recording once into a summary that is logged, for debugging. The engines and
+    /// transports are not involved
+    #[serde(default)]
+    pub replay: Option<ReplayConfig>,
+    /// Stamp the requests at the stages of the backend, for the latency breakdown reported by
+    /// the apps built with the `breakdown` feature of mrpc
+    #[serde(default)]
+    pub latency_breakdown: bool,
+    /// Number of work requests to read from the application at once
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Number of messages an engine takes from each of its queues in a round, before letting the
+    /// other engines on its runtime run. A smaller quantum is fairer to them, at the cost of the
+    /// latency of the messages left for the next round. Unbounded if omitted
+    #[serde(default)]
+    pub quantum: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordConfig {
+    /// The directory to store the recordings, one file per engine
+    pub dir: PathBuf,
+    /// Number of leading bytes of each message to capture, 0 to disable
+    #[serde(default)]
+    pub payload_snapshot_len: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// The recording to replay
+    pub path: PathBuf,
+    /// Replay at the recorded pace rather than as fast as possible
+    #[serde(default = "default_keep_pace")]
+    pub keep_pace: bool,
 }
 
 impl MrpcConfig {
     pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
+        let config: MrpcConfig = toml::from_str(config.unwrap_or(""))?;
+        if config.batch_size == 0 || config.quantum == Some(0) {
+            anyhow::bail!("batch_size and quantum must be positive");
+        }
         Ok(config)
     }
+
+    /// The message size limits, by service ID. `None` stands for all services.
+    pub fn message_size_limits(&self) -> Vec<(Option<u32>, MessageSizeLimit)> {
+        let default = MessageSizeLimit {
+            max_request_size: self.max_request_size,
+            max_response_size: self.max_response_size,
+        };
+        let mut limits = vec![(None, default)];
+        for (name, limit) in self.services.iter() {
+            // Must match mrpc-build.
+            limits.push((Some(crc32fast::hash(name.as_bytes())), *limit));
+        }
+        limits.retain(|(_, limit)| !limit.is_unlimited());
+        limits
+    }
 }
 
 fn default_build_cache() -> PathBuf {
@@ -34,6 +102,14 @@ fn default_build_cache() -> PathBuf {
     PathBuf::from("build_cache")
 }
 
+fn default_batch_size() -> usize {
+    32
+}
+
+fn default_keep_pace() -> bool {
+    true
+}
+
 fn default_engine_basename() -> String {
     "mrpc-engine".to_owned()
 }
@@ -1,17 +1,21 @@
 use std::mem;
+use std::os::unix::io::RawFd;
+use std::os::unix::ucred::UCred;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::ptr::Unique;
 
 use anyhow::{anyhow, Result};
+use fnv::FnvHashSet as HashSet;
 use futures::future::BoxFuture;
 use std::num::NonZeroU32;
 
 use phoenix_api::engine::SchedulingMode;
-use phoenix_api::rpc::{MessageErased, RpcId, StatusCode};
+use phoenix_api::rpc::{self, MessageErased, RpcId, RpcMsgType, StageStamps, StatusCode};
 use phoenix_api_mrpc::{cmd, control_plane, dp};
 
 use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
-use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+use phoenix_common::engine::datapath::meta_pool::{self, MetaBufferPool};
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{
     future, Decompose, DecomposeResult, Engine, EngineResult, Indicator, Vertex,
@@ -22,8 +26,12 @@ use phoenix_common::module::{ModuleCollection, Version};
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 use phoenix_common::{log, tracing};
 
-use super::builder::build_serializer_lib;
+use super::builder::BuildThread;
+use super::calls::CallTracker;
+use super::health::{self, Health, HealthCheckRequest};
 use super::module::CustomerType;
+use super::record::Recorder;
+use super::reflection;
 use super::state::State;
 use super::{DatapathError, Error};
 
@@ -43,15 +51,41 @@ pub struct MrpcEngine {
     pub(crate) _mode: SchedulingMode,
 
     pub(crate) dispatch_build_cache: PathBuf,
+    /// Builds the dispatch libraries, off the runtime
+    pub(crate) builder: BuildThread,
 
     pub(crate) transport_type: Option<control_plane::TransportType>,
 
     pub(crate) indicator: Indicator,
+    /// Its capacity is the number of work requests read from the customer at once
     pub(crate) wr_read_buffer: Vec<dp::WorkRequest>,
+    /// The payloads of the inline work requests read, in their order
+    pub(crate) wr_payload_buffer: Vec<u8>,
+    /// Messages taken from each queue in a round
+    pub(crate) quantum: usize,
+    /// The largest message received that is carried inside its completion, 0 if none is
+    pub(crate) max_inline_payload: usize,
+
+    /// Serving status reported by the built-in Health service
+    pub(crate) health: Health,
+    /// Replies sent by the engine (health checks and rejected requests), whose acks are not
+    /// forwarded to the app
+    pub(crate) health_replies: HashSet<RpcId>,
+    /// Sequence checks of the calls on each connection
+    pub(crate) calls: CallTracker,
+    /// Encoded FileDescriptorSets registered by the app, for reflection
+    pub(crate) descriptors: Vec<Vec<u8>>,
+
+    // Debugging facilities
+    /// Records the work requests from the customer
+    pub(crate) recorder: Option<Recorder>,
 }
 
 impl_vertex_for_engine!(MrpcEngine, node);
 
+// the struct of an inline work request is copied into the meta buffer of the message
+static_assertions::const_assert!(dp::MAX_INLINE_PAYLOAD <= meta_pool::INLINE_BODY_SIZE);
+
 impl Decompose for MrpcEngine {
     #[inline]
     fn flush(&mut self) -> DecomposeResult<usize> {
@@ -98,6 +132,23 @@ impl Decompose for MrpcEngine {
             "wr_read_buffer".to_string(),
             Box::new(engine.wr_read_buffer),
         );
+        collections.insert(
+            "wr_payload_buffer".to_string(),
+            Box::new(engine.wr_payload_buffer),
+        );
+        collections.insert("quantum".to_string(), Box::new(engine.quantum));
+        collections.insert(
+            "max_inline_payload".to_string(),
+            Box::new(engine.max_inline_payload),
+        );
+        collections.insert("health".to_string(), Box::new(engine.health));
+        collections.insert(
+            "health_replies".to_string(),
+            Box::new(engine.health_replies),
+        );
+        collections.insert("calls".to_string(), Box::new(engine.calls));
+        collections.insert("descriptors".to_string(), Box::new(engine.descriptors));
+        collections.insert("recorder".to_string(), Box::new(engine.recorder));
         (collections, engine.node)
     }
 }
@@ -110,6 +161,7 @@ impl MrpcEngine {
         node: DataPathNode,
         _plugged: &ModuleCollection,
         _prev_version: Version,
+        builder: BuildThread,
     ) -> Result<Self> {
         log::debug!("restoring MrpcEngine states...");
 
@@ -158,6 +210,46 @@ impl MrpcEngine {
             .unwrap()
             .downcast::<Vec<dp::WorkRequest>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let wr_payload_buffer = *local
+            .remove("wr_payload_buffer")
+            .unwrap()
+            .downcast::<Vec<u8>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let quantum = *local
+            .remove("quantum")
+            .unwrap()
+            .downcast::<usize>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let max_inline_payload = *local
+            .remove("max_inline_payload")
+            .unwrap()
+            .downcast::<usize>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let health = *local
+            .remove("health")
+            .unwrap()
+            .downcast::<Health>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let health_replies = *local
+            .remove("health_replies")
+            .unwrap()
+            .downcast::<HashSet<RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let calls = *local
+            .remove("calls")
+            .unwrap()
+            .downcast::<CallTracker>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let descriptors = *local
+            .remove("descriptors")
+            .unwrap()
+            .downcast::<Vec<Vec<u8>>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let recorder = *local
+            .remove("recorder")
+            .unwrap()
+            .downcast::<Option<Recorder>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
 
         let engine = MrpcEngine {
             _state: state,
@@ -168,21 +260,24 @@ impl MrpcEngine {
             meta_buf_pool,
             _mode: mode,
             dispatch_build_cache,
+            builder,
             transport_type,
             indicator: Default::default(),
             wr_read_buffer,
+            wr_payload_buffer,
+            quantum,
+            max_inline_payload,
+            health,
+            health_replies,
+            calls,
+            descriptors,
+            recorder,
         };
         Ok(engine)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Status {
-    Progress(usize),
-    Disconnected,
-}
-
-use Status::Progress;
+use future::Status::{self, Progress};
 
 impl Engine for MrpcEngine {
     fn description(self: Pin<&Self>) -> String {
@@ -197,54 +292,67 @@ impl Engine for MrpcEngine {
     fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
         &mut self.get_mut().indicator
     }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::SetServingStatus(service, status) => {
+                log::info!("Health: service {:?} is now {:?}", service, status);
+                self.health.set(service, status);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_query(&mut self, query: Vec<u8>, _cred: UCred) -> Result<Vec<u8>> {
+        let query: control_plane::Query = bincode::deserialize(&query[..])?;
+
+        let response = match query {
+            control_plane::Query::Reflection => {
+                let info = reflection::reflect(&self.descriptors)?;
+                control_plane::QueryResponse::Reflection(info)
+            }
+        };
+        Ok(bincode::serialize(&response)?)
+    }
+
+    fn doorbells(&self) -> Option<Vec<RawFd>> {
+        Some(self.customer.doorbells().to_vec())
+    }
 }
 
 impl MrpcEngine {
     async fn mainloop(&mut self) -> EngineResult {
+        // the control path is checked once every 100 rounds
+        let mut control = future::Every::new(100);
         loop {
-            // let mut timer = utils::timer::Timer::new();
-            let mut nwork = 0;
-
             // no work 80ns
             // has work: <1us for a batch of 30
-            loop {
-                // no work: 40ns
-                if let Progress(n) = self.check_customer()? {
-                    nwork += n;
-                    if n == 0 {
-                        break;
-                    }
-                }
-            }
-            // timer.tick();
+            let mut nwork = future::drain_at_most(self.quantum, || self.check_customer())?;
 
             // no work: 20ns
             // has work: <2us for a batch of 30
-            loop {
-                match self.check_input_queue()? {
-                    Progress(0) => break,
-                    Progress(n) => nwork += n,
-                    Status::Disconnected => break,
-                }
-            }
-            // timer.tick();
+            nwork += future::drain_at_most(self.quantum, || self.check_input_queue())?;
 
-            if fastrand::usize(..100) < 1 {
+            if control.tick() {
                 // 80-100ns, sometimes 200ns
                 if let Status::Disconnected = self.check_cmd().await? {
                     break;
                 }
-                // timer.tick();
 
                 // 50ns
                 self.check_input_cmd_queue()?;
-                // timer.tick();
-            }
 
-            self.indicator.set_nwork(nwork);
-            // log::info!("mrpc mainloop: {} {}", nwork, timer);
+                if let Some(recorder) = self.recorder.as_mut() {
+                    if let Err(e) = recorder.flush_if_due() {
+                        log::warn!("Failed to flush the recording, recording stopped: {}", e);
+                        self.recorder = None;
+                    }
+                }
+            }
 
-            future::yield_now().await;
+            future::suspend(&mut self.indicator, nwork).await;
         }
 
         self.wait_outstanding_complete().await?;
@@ -258,22 +366,22 @@ impl MrpcEngine {
     // However, we cannot indefinitely wait for it in case of wc errors.
     async fn wait_outstanding_complete(&mut self) -> Result<(), DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
-        while !self.meta_buf_pool.is_full() {
-            match self.rx_inputs()[0].try_recv() {
-                Ok(msg) => match msg {
-                    EngineRxMessage::Ack(rpc_id, _status) => {
-                        // release the buffer whatever the status is.
-                        self.meta_buf_pool.release(rpc_id)?;
-                    }
-                    EngineRxMessage::RpcMessage(_) => {}
-                    EngineRxMessage::RecvError(..) => {}
-                },
-                Err(TryRecvError::Disconnected) => return Ok(()),
-                Err(TryRecvError::Empty) => {}
+        future::wait(self, |engine| {
+            if engine.meta_buf_pool.is_full() {
+                return Some(Ok(()));
             }
-            future::yield_now().await;
-        }
-        Ok(())
+            match engine.rx_inputs()[0].try_recv() {
+                Ok(EngineRxMessage::Ack(rpc_id, _status)) => {
+                    // release the buffer whatever the status is.
+                    engine.health_replies.remove(&rpc_id);
+                    engine.meta_buf_pool.release(rpc_id).err().map(Err)
+                }
+                Ok(EngineRxMessage::RpcMessage(_) | EngineRxMessage::RecvError(..)) => None,
+                Err(TryRecvError::Disconnected) => Some(Ok(())),
+                Err(TryRecvError::Empty) => None,
+            }
+        })
+        .await
     }
 
     async fn check_cmd(&mut self) -> Result<Status, Error> {
@@ -330,13 +438,45 @@ impl MrpcEngine {
                 Ok(None)
             }
             Command::UpdateProtos(protos) => {
-                let dylib_path =
-                    build_serializer_lib(protos.clone(), self.dispatch_build_cache.clone())?;
+                let protos = health::with_health_proto(protos.clone());
+                let dylib_path = self
+                    .builder
+                    .build(protos, self.dispatch_build_cache.clone())?;
+                if let Some(recorder) = self.recorder.as_mut() {
+                    if let Err(e) = recorder.load_dispatch(&dylib_path) {
+                        log::warn!(
+                            "Failed to load {:?}, payloads not recorded: {}",
+                            dylib_path,
+                            e
+                        );
+                    }
+                }
                 self.cmd_tx
                     .send(Command::UpdateProtosInner(dylib_path))
                     .unwrap();
                 Ok(None)
             }
+            Command::UpdateDescriptors(descriptors) => {
+                for desc in descriptors {
+                    if !self.descriptors.contains(desc) {
+                        self.descriptors.push(desc.clone());
+                    }
+                }
+                Ok(Some(CompletionKind::UpdateDescriptors))
+            }
+            Command::SetMessageSizeLimit(service_id, limit) => {
+                // The adapter marshals the messages and enforces the limits.
+                self.cmd_tx
+                    .send(Command::SetMessageSizeLimit(*service_id, *limit))
+                    .unwrap();
+                Ok(Some(CompletionKind::SetMessageSizeLimit))
+            }
+            Command::ConnectionStats(conn_handle) => {
+                self.cmd_tx
+                    .send(Command::ConnectionStats(*conn_handle))
+                    .unwrap();
+                Ok(None)
+            }
             Command::MultiConnect(_) => {
                 panic!("MultiConnect is only used in mrpclb")
             }
@@ -347,10 +487,15 @@ impl MrpcEngine {
     }
 
     fn check_customer(&mut self) -> Result<Status, DatapathError> {
-        use dp::WorkRequest;
         let buffer_cap = self.wr_read_buffer.capacity();
         // let mut timer = crate::timer::Timer::new();
 
+        // Leave the work requests in the shared queue while the tx output is full, the
+        // application sees the queue fill up.
+        if self.tx_outputs_full() {
+            return Ok(Progress(0));
+        }
+
         // 15ns
         // Fetch available work requests. Copy them into a buffer.
         let max_count = buffer_cap.min(self.customer.get_avail_wc_slots()?);
@@ -360,26 +505,20 @@ impl MrpcEngine {
         // timer.tick();
 
         // 300-10us, mostly 300ns (Vec::with_capacity())
-        let mut count = 0;
-        // self.wr_read_buffer.clear();
-        // SAFETY: dp::WorkRequest is Copy and zerocopy
-        unsafe {
-            self.wr_read_buffer.set_len(0);
-        }
+        self.wr_read_buffer.clear();
+        self.wr_payload_buffer.clear();
 
         // timer.tick();
 
         // 60-150ns
-        self.customer
-            .dequeue_wr_with(|ptr, read_count| unsafe {
-                // TODO(cjr): max_count <= read_count always holds
-                count = max_count.min(read_count);
-                for i in 0..count {
-                    self.wr_read_buffer
-                        .push(ptr.add(i).cast::<WorkRequest>().read());
-                }
-                count
-            })
+        let count = self
+            .customer
+            .dequeue_wrs_with_payload(
+                &mut self.wr_read_buffer,
+                &mut self.wr_payload_buffer,
+                max_count,
+                dp::WorkRequest::payload_len,
+            )
             .unwrap_or_else(|e| panic!("check_customer: {}", e));
 
         // Process the work requests.
@@ -388,13 +527,30 @@ impl MrpcEngine {
         // no work: 10ns
         // has work: 100-400ns
         let buffer = mem::take(&mut self.wr_read_buffer);
+        let payloads = mem::take(&mut self.wr_payload_buffer);
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            // SAFETY: the messages are on the shared memory heap, which is mapped in the backend
+            // at shm_addr_backend.
+            let ret = buffer
+                .iter()
+                .try_for_each(|wr| unsafe { recorder.record(wr) });
+            if let Err(e) = ret {
+                log::warn!("Failed to record work requests, recording stopped: {}", e);
+                self.recorder = None;
+            }
+        }
 
+        let mut offset = 0;
         for wr in &buffer {
-            let ret = self.process_dp(wr);
+            let len = wr.payload_len().unwrap_or(0);
+            let ret = self.process_dp(wr, &payloads[offset..offset + len]);
+            offset += len;
             match ret {
                 Ok(()) => {}
                 Err(e) => {
                     self.wr_read_buffer = buffer;
+                    self.wr_payload_buffer = payloads;
                     // TODO(cjr): error handling
                     return Err(e);
                 }
@@ -402,6 +558,7 @@ impl MrpcEngine {
         }
 
         self.wr_read_buffer = buffer;
+        self.wr_payload_buffer = payloads;
 
         // timer.tick();
         // log::info!("check_customer: {} {}", count, timer);
@@ -409,10 +566,57 @@ impl MrpcEngine {
         Ok(Progress(count))
     }
 
-    fn process_dp(&mut self, req: &dp::WorkRequest) -> Result<(), DatapathError> {
+    /// Processes a work request, whose struct is `payload` for an inline one.
+    fn process_dp(&mut self, req: &dp::WorkRequest, payload: &[u8]) -> Result<(), DatapathError> {
         use dp::WorkRequest;
 
         match req {
+            WorkRequest::Inline(meta, _len) => {
+                let rpc_id = RpcId(meta.conn_id, meta.call_id);
+                if payload.len() > self.max_inline_payload {
+                    log::warn!(
+                        "Inline message {:?} of {} bytes, rejected",
+                        rpc_id,
+                        payload.len()
+                    );
+                    let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
+                        NonZeroU32::new_unchecked(413)
+                    });
+                    self.customer
+                        .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
+                    return Ok(());
+                }
+                if meta.msg_type == RpcMsgType::Request && !self.calls.send_request(rpc_id) {
+                    log::warn!("Request {:?} is out of sequence, rejected", rpc_id);
+                    let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
+                        NonZeroU32::new_unchecked(409)
+                    });
+                    self.customer
+                        .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
+                    return Ok(());
+                }
+
+                let meta_buf_ptr = self
+                    .meta_buf_pool
+                    .obtain(rpc_id)
+                    .expect("MessageMeta pool exhausted");
+                // the message is sent from its copy in the meta buffer, which is released with
+                // the meta once the message is acknowledged
+                let addr_backend = unsafe {
+                    std::ptr::write(meta_buf_ptr.as_meta_ptr(), *meta);
+                    meta_buf_ptr.write_inline_body(payload)
+                };
+                if meta.msg_type == RpcMsgType::Request && meta_pool::stamping() {
+                    unsafe { (*meta_buf_ptr.as_stamps_ptr()).engine_dequeue = rpc::tsc() };
+                }
+
+                let msg = RpcMessageTx {
+                    meta_buf_ptr,
+                    addr_backend,
+                };
+                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+            }
+            WorkRequest::Pad => {}
             WorkRequest::Call(erased) | WorkRequest::Reply(erased) => {
                 // let mut timer = crate::timer::Timer::new();
 
@@ -424,8 +628,18 @@ impl MrpcEngine {
 
                 // timer.tick();
 
-                // construct message meta on heap
                 let rpc_id = RpcId(erased.meta.conn_id, erased.meta.call_id);
+                if erased.meta.msg_type == RpcMsgType::Request && !self.calls.send_request(rpc_id) {
+                    log::warn!("Request {:?} is out of sequence, rejected", rpc_id);
+                    let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
+                        NonZeroU32::new_unchecked(409)
+                    });
+                    self.customer
+                        .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
+                    return Ok(());
+                }
+
+                // construct message meta on heap
                 let meta_buf_ptr = self
                     .meta_buf_pool
                     .obtain(rpc_id)
@@ -437,6 +651,9 @@ impl MrpcEngine {
                 unsafe {
                     std::ptr::write(meta_buf_ptr.as_meta_ptr(), erased.meta);
                 }
+                if erased.meta.msg_type == RpcMsgType::Request && meta_pool::stamping() {
+                    unsafe { (*meta_buf_ptr.as_stamps_ptr()).engine_dequeue = rpc::tsc() };
+                }
 
                 let msg = RpcMessageTx {
                     meta_buf_ptr,
@@ -474,6 +691,27 @@ impl MrpcEngine {
                     EngineRxMessage::RpcMessage(msg) => {
                         // let mut timer = crate::timer::Timer::new();
                         let meta = unsafe { *msg.meta.as_ref() };
+                        if meta.status_code == StatusCode::Success
+                            && health::is_check_request(&meta)
+                        {
+                            self.reply_health_check(meta, msg.addr_backend)?;
+                            return Ok(Progress(1));
+                        }
+                        if meta.msg_type == RpcMsgType::Response
+                            && !self.calls.recv_response(RpcId(meta.conn_id, meta.call_id))
+                        {
+                            log::warn!(
+                                "Response to {:?} is unexpected or duplicated, dropped",
+                                RpcId(meta.conn_id, meta.call_id)
+                            );
+                            let msg_call_ids =
+                                [meta.call_id, meta.call_id, meta.call_id, meta.call_id];
+                            self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
+                                meta.conn_id,
+                                msg_call_ids,
+                            ))?;
+                            return Ok(Progress(1));
+                        }
                         tracing::trace!(
                             "mRPC engine send message to App, call_id={}",
                             meta.call_id
@@ -486,22 +724,39 @@ impl MrpcEngine {
                         };
                         // timer.tick();
                         match meta.status_code {
-                            StatusCode::AccessDenied => {
-                                tracing::debug!("Status code: Access denied, meta={:?}", meta);
-                                let mut sent = false;
+                            StatusCode::AccessDenied
+                            | StatusCode::Unimplemented
+                            | StatusCode::Unknown
+                                if meta.msg_type != RpcMsgType::Response =>
+                            {
+                                // the adapter failed to unmarshal it, e.g., the method is unknown
+                                tracing::debug!(
+                                    "Status code: {:?}, meta={:?}",
+                                    meta.status_code,
+                                    meta
+                                );
+                                self.reject_received(meta)?;
+                            }
+                            StatusCode::AccessDenied
+                            | StatusCode::Unimplemented
+                            | StatusCode::Unknown => {
+                                tracing::debug!(
+                                    "Status code: {:?}, meta={:?}",
+                                    meta.status_code,
+                                    meta
+                                );
+                                // the server has replied with an error, fail the call
+                                let code = match meta.status_code {
+                                    StatusCode::AccessDenied => 402,
+                                    StatusCode::Unimplemented => 501,
+                                    _ => 500,
+                                };
                                 let rpc_id = RpcId(meta.conn_id, meta.call_id);
-                                let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
-                                    NonZeroU32::new_unchecked(402)
-                                });
-                                while !sent {
-                                    self.customer.enqueue_wc_with(|ptr, _count| unsafe {
-                                        // self.customer.notify_wc_with(|ptr, _count| unsafe {
-                                        sent = true;
-                                        ptr.cast::<dp::Completion>()
-                                            .write(dp::Completion::Outgoing(rpc_id, status));
-                                        1
-                                    })?;
-                                }
+                                let status = phoenix_api::rpc::TransportStatus::Error(
+                                    NonZeroU32::new(code).unwrap(),
+                                );
+                                self.customer
+                                    .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
                                 let msg_call_ids =
                                     [meta.call_id, meta.call_id, meta.call_id, meta.call_id];
                                 self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
@@ -509,52 +764,69 @@ impl MrpcEngine {
                                     msg_call_ids,
                                 ))?;
                             }
-                            StatusCode::Unknown => {
-                                tracing::error!("Status code: Unknown error, meta={:?}", meta);
+                            StatusCode::MessageTooLarge => {
+                                tracing::debug!("Status code: Message too large, meta={:?}", meta);
+                                self.reject_received(meta)?;
                             }
-                            StatusCode::Success => {
-                                // the following operation takes around 100ns
-                                let mut sent = false;
-                                while !sent {
-                                    self.customer.enqueue_wc_with(|ptr, _count| unsafe {
-                                        // self.customer.notify_wc_with(|ptr, _count| unsafe {
-                                        sent = true;
-                                        ptr.cast::<dp::Completion>()
-                                            .write(dp::Completion::Incoming(erased));
-                                        1
-                                    })?;
+                            StatusCode::Success => match msg
+                                .flat_len
+                                .filter(|&len| len <= self.max_inline_payload)
+                            {
+                                Some(len) => {
+                                    // SAFETY: the adapter has excavated the message in the
+                                    // receive buffer, a struct of len bytes at addr_backend
+                                    let payload = unsafe {
+                                        std::slice::from_raw_parts(
+                                            msg.addr_backend as *const u8,
+                                            len,
+                                        )
+                                    };
+                                    self.customer.enqueue_wc_with_payload(
+                                        dp::Completion::IncomingInline(meta, len as u32),
+                                        payload,
+                                        dp::Completion::Pad,
+                                    )?;
+                                    // the app has its copy, the receive buffer is given back
+                                    let msg_call_ids =
+                                        [meta.call_id, meta.call_id, meta.call_id, meta.call_id];
+                                    self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
+                                        meta.conn_id,
+                                        msg_call_ids,
+                                    ))?;
                                 }
-                            }
+                                None => {
+                                    // the following operation takes around 100ns
+                                    self.customer.enqueue_wc(dp::Completion::Incoming(erased))?;
+                                }
+                            },
                         }
 
                         // timer.tick();
                         // log::info!("MrpcEngine check_input_queue: {}", timer);
                     }
                     EngineRxMessage::Ack(rpc_id, status) => {
+                        let stamps = self.completed_stamps(rpc_id);
                         // release message meta buffer
                         self.meta_buf_pool.release(rpc_id)?;
-                        let mut sent = false;
-                        while !sent {
-                            self.customer.enqueue_wc_with(|ptr, _count| unsafe {
-                                // self.customer.notify_wc_with(|ptr, _count| unsafe {
-                                sent = true;
-                                ptr.cast::<dp::Completion>()
-                                    .write(dp::Completion::Outgoing(rpc_id, status));
-                                1
-                            })?;
+                        if self.health_replies.remove(&rpc_id) {
+                            // the app does not know about the replies sent by us
+                            return Ok(Progress(1));
                         }
+                        if let phoenix_api::rpc::TransportStatus::Error(_) = status {
+                            // no response is coming for a request failed to send
+                            self.calls.fail_request(rpc_id);
+                        }
+                        if let Some(stamps) = stamps {
+                            self.customer
+                                .enqueue_wc(dp::Completion::Stamps(rpc_id, stamps))?;
+                        }
+                        self.customer
+                            .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
                     }
                     EngineRxMessage::RecvError(conn_id, status) => {
-                        let mut sent = false;
-                        while !sent {
-                            self.customer.enqueue_wc_with(|ptr, _count| unsafe {
-                                // self.customer.notify_wc_with(|ptr, _count| unsafe {
-                                sent = true;
-                                ptr.cast::<dp::Completion>()
-                                    .write(dp::Completion::RecvError(conn_id, status));
-                                1
-                            })?;
-                        }
+                        self.calls.close_connection(conn_id);
+                        self.customer
+                            .enqueue_wc(dp::Completion::RecvError(conn_id, status))?;
                     }
                 }
                 Ok(Progress(1))
@@ -564,6 +836,107 @@ impl MrpcEngine {
         }
     }
 
+    /// Returns the timestamps of a request that has been sent, for the latency breakdown. `None`
+    /// if the message is not a request, or stamping is disabled.
+    fn completed_stamps(&self, rpc_id: RpcId) -> Option<StageStamps> {
+        if !meta_pool::stamping() {
+            return None;
+        }
+        let meta_buf_ptr = self.meta_buf_pool.get(rpc_id)?;
+        let mut stamps = unsafe { meta_buf_ptr.as_stamps_ptr().read() };
+        if stamps.engine_dequeue == 0 {
+            return None;
+        }
+        stamps.completion = rpc::tsc();
+        Some(stamps)
+    }
+
+    /// Handles a message that the adapter did not unmarshal, because it exceeds the size limit
+    /// or does not unmarshal.
+    ///
+    /// A request is answered by a meta-only reply with the same status, on behalf of the app. A
+    /// response fails the call with transport status 414. A notification is dropped.
+    fn reject_received(
+        &mut self,
+        mut meta: phoenix_api::rpc::MessageMeta,
+    ) -> Result<(), DatapathError> {
+        let msg_call_ids = [meta.call_id, meta.call_id, meta.call_id, meta.call_id];
+        self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(meta.conn_id, msg_call_ids))?;
+
+        let rpc_id = RpcId(meta.conn_id, meta.call_id);
+        match meta.msg_type {
+            RpcMsgType::Request => {
+                meta.msg_type = RpcMsgType::Response;
+                let meta_buf_ptr = self
+                    .meta_buf_pool
+                    .obtain(rpc_id)
+                    .expect("MessageMeta pool exhausted");
+                unsafe {
+                    std::ptr::write(meta_buf_ptr.as_meta_ptr(), meta);
+                }
+                self.health_replies.insert(rpc_id);
+                let msg = RpcMessageTx {
+                    meta_buf_ptr,
+                    addr_backend: 0,
+                };
+                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+            }
+            RpcMsgType::Response => {
+                let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
+                    NonZeroU32::new_unchecked(414)
+                });
+                self.customer
+                    .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
+            }
+            RpcMsgType::Notification => {
+                log::warn!(
+                    "Notification {:?} is rejected ({:?}), dropped",
+                    rpc_id,
+                    meta.status_code
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a Check request to the Health service on behalf of the app.
+    fn reply_health_check(
+        &mut self,
+        mut meta: phoenix_api::rpc::MessageMeta,
+        addr_backend: usize,
+    ) -> Result<(), DatapathError> {
+        let req_ptr = Unique::new(addr_backend as *mut HealthCheckRequest).unwrap();
+        let req = unsafe { req_ptr.as_ref() };
+        let status = self.health.check(&req.service);
+        tracing::trace!(
+            "Health check for service {:?}: {:?}, call_id={}",
+            &*req.service,
+            status,
+            meta.call_id
+        );
+
+        // The request has been read, give its receive buffer back.
+        let msg_call_ids = [meta.call_id, meta.call_id, meta.call_id, meta.call_id];
+        self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(meta.conn_id, msg_call_ids))?;
+
+        meta.msg_type = RpcMsgType::Response;
+        let rpc_id = RpcId(meta.conn_id, meta.call_id);
+        let meta_buf_ptr = self
+            .meta_buf_pool
+            .obtain(rpc_id)
+            .expect("MessageMeta pool exhausted");
+        unsafe {
+            std::ptr::write(meta_buf_ptr.as_meta_ptr(), meta);
+        }
+        self.health_replies.insert(rpc_id);
+        let msg = RpcMessageTx {
+            meta_buf_ptr,
+            addr_backend: health::response_addr(status),
+        };
+        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        Ok(())
+    }
+
     fn check_input_cmd_queue(&mut self) -> Result<Status, Error> {
         use phoenix_api_mrpc::cmd::{Completion, CompletionKind};
         use tokio::sync::mpsc::error::TryRecvError;
@@ -589,11 +962,19 @@ impl MrpcEngine {
                     c @ Ok(
                         CompletionKind::Bind(..)
                         | CompletionKind::NewMappedAddrs
-                        | CompletionKind::UpdateProtos,
+                        | CompletionKind::UpdateProtos
+                        | CompletionKind::ConnectionStats(..),
                     ) => {
                         self.customer.send_comp(cmd::Completion(c))?;
                         Ok(Status::Progress(1))
                     }
+                    // the app waits for the command, e.g., the stats of an unknown connection
+                    c @ Err(_) => {
+                        self.customer.send_comp(cmd::Completion(c))?;
+                        Ok(Status::Progress(1))
+                    }
+                    // already acknowledged to the app, or set from the config
+                    Ok(CompletionKind::SetMessageSizeLimit) => Ok(Status::Progress(1)),
                     other => panic!("unexpected: {:?}", other),
                 }
             }
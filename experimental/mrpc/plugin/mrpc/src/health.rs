@@ -0,0 +1,124 @@
+//! A grpc.health.v1 style Health service answered by MrpcEngine.
+//!
+//! Probes are answered in the daemon without going through the application, so they tell
+//! whether the service subscription is up even if the application handlers are stuck. Only the
+//! unary Check method is supported; mRPC has no streaming RPCs for Watch.
+//!
+//! The replies are marshaled by the application's dispatch library like any other message, so
+//! the health proto is compiled into every dispatch library the engine builds.
+use std::collections::HashMap;
+
+use phoenix_api::rpc::{MessageMeta, RpcMsgType};
+use phoenix_api_mrpc::control_plane::ServingStatus;
+
+/// The proto of the Health service, compatible with grpc.health.v1.
+pub(crate) const HEALTH_PROTO: &str = r#"
+syntax = "proto3";
+
+package grpc.health.v1;
+
+message HealthCheckRequest {
+  string service = 1;
+}
+
+message HealthCheckResponse {
+  enum ServingStatus {
+    UNKNOWN = 0;
+    SERVING = 1;
+    NOT_SERVING = 2;
+    SERVICE_UNKNOWN = 3;
+  }
+  ServingStatus status = 1;
+}
+
+service Health {
+  rpc Check(HealthCheckRequest) returns (HealthCheckResponse);
+}
+"#;
+
+const HEALTH_PACKAGE: &str = "package grpc.health.v1;";
+
+lazy_static::lazy_static! {
+    // The same as what builder::prost::service computes for the proto above.
+    static ref HEALTH_SERVICE_ID: u32 = crc32fast::hash(b"grpc.health.v1.Health");
+    static ref CHECK_FUNC_ID: u32 = crc32fast::hash(b"/grpc.health.v1.Health/Check");
+}
+
+/// The message layout generated for HealthCheckRequest.
+#[repr(C)]
+pub(crate) struct HealthCheckRequest {
+    pub(crate) service: mrpc_marshal::shadow::String,
+}
+
+/// The message layout generated for HealthCheckResponse.
+#[repr(C)]
+pub(crate) struct HealthCheckResponse {
+    pub(crate) status: i32,
+}
+
+/// One response for each status. The adapters only read the replies when marshaling them.
+static RESPONSES: [HealthCheckResponse; 4] = [
+    HealthCheckResponse {
+        status: ServingStatus::Unknown as i32,
+    },
+    HealthCheckResponse {
+        status: ServingStatus::Serving as i32,
+    },
+    HealthCheckResponse {
+        status: ServingStatus::NotServing as i32,
+    },
+    HealthCheckResponse {
+        status: ServingStatus::ServiceUnknown as i32,
+    },
+];
+
+/// Returns the address of the reply to send for `status`.
+#[inline]
+pub(crate) fn response_addr(status: ServingStatus) -> usize {
+    &RESPONSES[status as i32 as usize] as *const HealthCheckResponse as usize
+}
+
+/// Whether an incoming message is a Check request to the Health service.
+#[inline]
+pub(crate) fn is_check_request(meta: &MessageMeta) -> bool {
+    meta.msg_type == RpcMsgType::Request
+        && meta.service_id == *HEALTH_SERVICE_ID
+        && meta.func_id == *CHECK_FUNC_ID
+}
+
+/// Adds the Health service to the protos of a dispatch library, unless the application has
+/// already brought it.
+pub(crate) fn with_health_proto(mut protos: Vec<String>) -> Vec<String> {
+    if !protos.iter().any(|p| p.contains(HEALTH_PACKAGE)) {
+        protos.push(HEALTH_PROTO.to_owned());
+    }
+    protos
+}
+
+/// The serving status of the services behind an engine.
+#[derive(Debug, Clone)]
+pub(crate) struct Health {
+    statuses: HashMap<String, ServingStatus>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        // The server is serving as long as the engine is alive.
+        let mut statuses = HashMap::default();
+        statuses.insert(String::new(), ServingStatus::Serving);
+        Health { statuses }
+    }
+}
+
+impl Health {
+    pub(crate) fn set(&mut self, service: String, status: ServingStatus) {
+        self.statuses.insert(service, status);
+    }
+
+    pub(crate) fn check(&self, service: &str) -> ServingStatus {
+        self.statuses
+            .get(service)
+            .copied()
+            .unwrap_or(ServingStatus::ServiceUnknown)
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread::JoinHandle;
 
 use anyhow::{bail, Result};
 use uuid::Uuid;
@@ -11,6 +12,7 @@ use phoenix_api_mrpc::control_plane::Setting;
 use phoenix_api_mrpc::control_plane::TransportType;
 use phoenix_api_mrpc::{cmd, dp};
 
+use phoenix_common::capability::Capabilities;
 use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
 use phoenix_common::engine::datapath::node::{ChannelDescriptor, DataPathNode};
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
@@ -23,7 +25,9 @@ use phoenix_common::state_mgr::{Pid, SharedStateManager};
 use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
 use phoenix_common::PhoenixResult;
 
+use crate::builder::BuildThread;
 use crate::config::MrpcConfig;
+use crate::record::{Recorder, Replayer};
 
 use super::engine::MrpcEngine;
 use super::state::{Shared, State};
@@ -39,7 +43,12 @@ pub(crate) struct MrpcEngineBuilder {
     cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Completion>,
     node: DataPathNode,
     serializer_build_cache: PathBuf,
+    builder: BuildThread,
     shared: Arc<Shared>,
+    recorder: Option<Recorder>,
+    batch_size: usize,
+    quantum: usize,
+    max_inline_payload: usize,
 }
 
 impl MrpcEngineBuilder {
@@ -52,7 +61,12 @@ impl MrpcEngineBuilder {
         cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Completion>,
         node: DataPathNode,
         serializer_build_cache: PathBuf,
+        builder: BuildThread,
         shared: Arc<Shared>,
+        recorder: Option<Recorder>,
+        batch_size: usize,
+        quantum: usize,
+        max_inline_payload: usize,
     ) -> Self {
         MrpcEngineBuilder {
             customer,
@@ -62,13 +76,17 @@ impl MrpcEngineBuilder {
             _client_pid: client_pid,
             mode,
             serializer_build_cache,
+            builder,
             shared,
+            recorder,
+            batch_size,
+            quantum,
+            max_inline_payload,
         }
     }
 
     fn build(self) -> Result<MrpcEngine> {
         const META_BUFFER_POOL_CAP: usize = 128;
-        const BUF_LEN: usize = 32;
 
         let state = State::new(self.shared);
 
@@ -81,9 +99,18 @@ impl MrpcEngineBuilder {
             meta_buf_pool: MetaBufferPool::new(META_BUFFER_POOL_CAP),
             _mode: self.mode,
             dispatch_build_cache: self.serializer_build_cache,
+            builder: self.builder,
             transport_type: None,
             indicator: Default::default(),
-            wr_read_buffer: Vec::with_capacity(BUF_LEN),
+            wr_read_buffer: Vec::with_capacity(self.batch_size),
+            wr_payload_buffer: Vec::with_capacity(dp::MAX_INLINE_PAYLOAD),
+            quantum: self.quantum,
+            max_inline_payload: self.max_inline_payload.min(dp::MAX_INLINE_PAYLOAD),
+            health: Default::default(),
+            descriptors: Vec::new(),
+            health_replies: Default::default(),
+            calls: Default::default(),
+            recorder: self.recorder,
         })
     }
 }
@@ -91,6 +118,10 @@ impl MrpcEngineBuilder {
 pub struct MrpcModule {
     config: MrpcConfig,
     pub state_mgr: SharedStateManager<Shared>,
+    /// The thread replaying the recording in the config, started with the first engine
+    replay: Option<JoinHandle<()>>,
+    /// The thread building the dispatch libraries, started with the first engine
+    builder: Option<BuildThread>,
 }
 
 impl MrpcModule {
@@ -134,9 +165,20 @@ impl MrpcModule {
         MrpcModule {
             config,
             state_mgr: SharedStateManager::new(),
+            replay: None,
+            builder: None,
         }
     }
 
+    /// The thread building the dispatch libraries of the engines. It is started on the control
+    /// thread, and the upgraded module starts its own.
+    fn builder(&mut self) -> std::io::Result<BuildThread> {
+        if self.builder.is_none() {
+            self.builder = Some(BuildThread::spawn()?);
+        }
+        Ok(self.builder.clone().unwrap())
+    }
+
     // Returns build_cache if it's already an absolute path. Otherwise returns the path relative
     // to the engine's prefix.
     fn get_build_cache_directory(&self, engine_prefix: &PathBuf) -> PathBuf {
@@ -185,6 +227,21 @@ impl PhoenixModule for MrpcModule {
         }
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // The dispatch libraries are built off the runtimes, which only load them. The cache is
+        // in the prefix of the daemon unless it is elsewhere.
+        let mut caps = Capabilities::default();
+        if self.config.build_cache.is_absolute() {
+            caps.read_paths.push(self.config.build_cache.clone());
+        } else if let Some(prefix) = self.config.prefix.as_ref() {
+            caps.read_paths.push(prefix.join(&self.config.build_cache));
+        }
+        if let Some(record) = self.config.record.as_ref() {
+            caps.write_paths.push(record.dir.clone());
+        }
+        caps
+    }
+
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
@@ -201,6 +258,7 @@ impl PhoenixModule for MrpcModule {
         // NOTE(wyj): we may better call decompose here
         let prev_concrete = unsafe { *prev_module.downcast_unchecked::<Self>() };
         self.state_mgr = prev_concrete.state_mgr;
+        self.replay = prev_concrete.replay;
     }
 
     fn create_engine(
@@ -242,6 +300,25 @@ impl PhoenixModule for MrpcModule {
             let client_pid = Pid::from_raw(cred.pid.unwrap());
             let shared_state = self.state_mgr.get_or_create(client_pid)?;
 
+            let recorder = match &self.config.record {
+                Some(record) => {
+                    std::fs::create_dir_all(&record.dir)?;
+                    let path = record.dir.join(format!("{}-{}.wrlog", client_pid, uuid));
+                    Some(Recorder::create(path, record.payload_snapshot_len)?)
+                }
+                None => None,
+            };
+            // The recording is replayed once for the module, not for each engine.
+            if let Some(replay) = self
+                .config
+                .replay
+                .as_ref()
+                .filter(|_| self.replay.is_none())
+            {
+                let replayer = Replayer::open(&replay.path, replay.keep_pace)?;
+                self.replay = Some(replayer.spawn()?);
+            }
+
             let setting = if let Some(config_string) = config_string {
                 serde_json::from_str(&config_string)?
             } else {
@@ -250,6 +327,8 @@ impl PhoenixModule for MrpcModule {
                     nic_index: self.config.nic_index,
                     core_id: None,
                     module_config: None,
+                    encryption: false,
+                    max_inline_payload: 0,
                 }
             };
             log::debug!("mRPC service setting: {:?}", setting);
@@ -266,6 +345,12 @@ impl PhoenixModule for MrpcModule {
             let cmd_tx = shared.command_path.get_sender(&engine_type)?;
             let cmd_rx = shared.command_path.get_receiver(&engine_type)?;
 
+            // The adapter enforces the limits, their completions are not forwarded to the app.
+            for (service_id, limit) in self.config.message_size_limits() {
+                cmd_tx.send(cmd::Command::SetMessageSizeLimit(service_id, limit))?;
+            }
+
+            let build_thread = self.builder()?;
             let builder = MrpcEngineBuilder::new(
                 customer,
                 client_pid,
@@ -274,7 +359,12 @@ impl PhoenixModule for MrpcModule {
                 cmd_rx,
                 node,
                 build_cache,
+                build_thread,
                 shared_state,
+                recorder,
+                self.config.batch_size,
+                self.config.quantum.unwrap_or(usize::MAX),
+                setting.max_inline_payload,
                 // TODO(cjr): store the setting, not necessary now.
             );
             let engine = builder.build()?;
@@ -298,7 +388,16 @@ impl PhoenixModule for MrpcModule {
         if ty != MrpcModule::MRPC_ENGINE {
             bail!("invalid engine type {:?}", ty)
         }
-        let engine = MrpcEngine::restore(local, shared, global, node, plugged, prev_version)?;
+        let build_thread = self.builder()?;
+        let engine = MrpcEngine::restore(
+            local,
+            shared,
+            global,
+            node,
+            plugged,
+            prev_version,
+            build_thread,
+        )?;
         Ok(Box::new(engine))
     }
 }
@@ -0,0 +1,470 @@
+//! Record and replay of the work request stream of a customer.
+//!
+//! A recording is a stream of bincode encoded [`Record`]s preceded by a small header. It captures
+//! the work requests exactly as MrpcEngine dequeues them from the application, together with the
+//! time they were seen and optionally a snapshot of the leading bytes of the message they point to.
+//!
+//! The replay driver feeds the work requests of a recording to a [`ReplaySink`], either at the
+//! recorded pace or as fast as possible. Replay never goes through the live engines and
+//! transports: the descriptors carry the shared memory addresses of the original process, which
+//! are stale by the time of the replay. The payload snapshots are kept for offline inspection and
+//! are never written back.
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use mrpc_marshal::{MarshalError, SgList};
+use phoenix_api::rpc::{MessageMeta, RpcMsgType};
+use phoenix_api::Handle;
+use phoenix_api_mrpc::dp;
+use phoenix_common::log;
+
+const MAGIC: [u8; 8] = *b"MRPCWRLG";
+const FORMAT_VERSION: u32 = 1;
+
+/// How often the buffered records are written out to the file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Codec error: {0}")]
+    Codec(#[from] bincode::Error),
+    #[error("Not a work request recording")]
+    BadMagic,
+    #[error("Unsupported recording version: {0}")]
+    Version(u32),
+    #[error("Loading dispatch library: {0}")]
+    Dispatch(#[from] libloading::Error),
+}
+
+/// A single recorded work request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Nanoseconds since the recording started.
+    pub timestamp_ns: u64,
+    pub wr: dp::WorkRequest,
+    /// The leading bytes of the marshalled message, empty if not captured.
+    pub payload: Vec<u8>,
+}
+
+type MarshalFn = fn(&MessageMeta, usize) -> Result<SgList, MarshalError>;
+
+/// The marshal function of a dispatch library, which tells the extent of a message.
+struct Marshaler {
+    _library: libloading::Library,
+    // NOTE: Symbol here shall not outlive library.
+    marshal_fn: libloading::os::unix::Symbol<MarshalFn>,
+}
+
+impl Marshaler {
+    fn new<P: AsRef<OsStr>>(lib: P) -> Result<Self, libloading::Error> {
+        let library = unsafe { libloading::Library::new(lib) }?;
+        let marshal_fn = unsafe {
+            let symbol: libloading::Symbol<MarshalFn> = library.get(b"marshal")?;
+            symbol.into_raw()
+        };
+        Ok(Marshaler {
+            _library: library,
+            marshal_fn,
+        })
+    }
+
+    /// Copies at most `len` leading bytes of the marshalled message, never reading past its end.
+    ///
+    /// # Safety
+    ///
+    /// The message must be mapped in this process at `addr_backend`.
+    unsafe fn snapshot(&self, meta: &MessageMeta, addr_backend: usize, len: usize) -> Vec<u8> {
+        let sgl = match (self.marshal_fn)(meta, addr_backend) {
+            Ok(sgl) => sgl,
+            Err(e) => {
+                log::debug!("Failed to marshal {:?} for a snapshot: {}", meta, e);
+                return Vec::new();
+            }
+        };
+        let mut payload = Vec::with_capacity(len);
+        for sge in &sgl.0 {
+            let n = sge.len.min(len - payload.len());
+            if n == 0 {
+                break;
+            }
+            payload.extend_from_slice(std::slice::from_raw_parts(sge.ptr as *const u8, n));
+        }
+        payload
+    }
+}
+
+/// Writes the work requests of a customer to a file.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+    last_flush: Instant,
+    payload_snapshot_len: usize,
+    /// Loaded with the protos of the app, messages are not captured before that.
+    marshaler: Option<Marshaler>,
+    count: u64,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P, payload_snapshot_len: usize) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path.as_ref())?);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        log::info!("Recording work requests to {:?}", path.as_ref());
+        let now = Instant::now();
+        Ok(Recorder {
+            writer,
+            start: now,
+            last_flush: now,
+            payload_snapshot_len,
+            marshaler: None,
+            count: 0,
+        })
+    }
+
+    /// Loads the dispatch library of the app, which is needed to capture the payloads. Does
+    /// nothing if payload snapshot is disabled.
+    pub fn load_dispatch<P: AsRef<OsStr>>(&mut self, dylib_path: P) -> Result<(), Error> {
+        if self.payload_snapshot_len > 0 {
+            self.marshaler = Some(Marshaler::new(dylib_path)?);
+        }
+        Ok(())
+    }
+
+    /// Appends a work request to the recording.
+    ///
+    /// # Safety
+    ///
+    /// If payload snapshot is enabled, the message pointed by a Call or Reply must be mapped in
+    /// this process at `shm_addr_backend`.
+    pub unsafe fn record(&mut self, wr: &dp::WorkRequest) -> Result<(), Error> {
+        if let dp::WorkRequest::Pad = wr {
+            return Ok(());
+        }
+        let payload = match (wr, self.marshaler.as_ref()) {
+            (dp::WorkRequest::Call(erased) | dp::WorkRequest::Reply(erased), Some(marshaler)) => {
+                marshaler.snapshot(
+                    &erased.meta,
+                    erased.shm_addr_backend,
+                    self.payload_snapshot_len,
+                )
+            }
+            _ => Vec::new(),
+        };
+        let record = Record {
+            timestamp_ns: self.start.elapsed().as_nanos() as u64,
+            wr: *wr,
+            payload,
+        };
+        bincode::serialize_into(&mut self.writer, &record)?;
+        self.count += 1;
+        self.flush_if_due()
+    }
+
+    /// Number of work requests recorded so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Writes out the buffered records if they have not been for a while, so a recording is
+    /// mostly complete even if phoenix is killed.
+    pub fn flush_if_due(&mut self) -> Result<(), Error> {
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// Reads [`Record`]s from a recording.
+pub struct RecordReader<R> {
+    reader: R,
+}
+
+impl RecordReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != FORMAT_VERSION {
+            return Err(Error::Version(version));
+        }
+        Ok(RecordReader { reader })
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match bincode::deserialize_from(&mut self.reader) {
+            Ok(record) => Some(Ok(record)),
+            Err(e) => match *e {
+                // A recording may end anywhere, e.g., when phoenix is killed.
+                bincode::ErrorKind::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+                _ => Some(Err(e.into())),
+            },
+        }
+    }
+}
+
+/// Receives the replayed work requests. The descriptors carry the addresses of the recording
+/// process, a sink must not dereference them.
+pub trait ReplaySink {
+    fn submit(&mut self, record: &Record);
+}
+
+/// A sink that tallies the replayed work requests.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub calls: u64,
+    pub replies: u64,
+    pub reclaims: u64,
+    /// Number of calls by (service_id, func_id)
+    pub methods: HashMap<(u32, u32), u64>,
+    pub connections: HashSet<Handle>,
+    /// Total size of the payload snapshots
+    pub snapshot_bytes: u64,
+    /// The recorded time of the first and the last work request, in nanoseconds
+    pub first_ns: Option<u64>,
+    pub last_ns: u64,
+}
+
+impl ReplaySink for Summary {
+    fn submit(&mut self, record: &Record) {
+        match &record.wr {
+            dp::WorkRequest::Call(erased) => {
+                self.calls += 1;
+                let method = (erased.meta.service_id, erased.meta.func_id);
+                *self.methods.entry(method).or_default() += 1;
+                self.connections.insert(erased.meta.conn_id);
+            }
+            dp::WorkRequest::Reply(erased) => {
+                self.replies += 1;
+                self.connections.insert(erased.meta.conn_id);
+            }
+            dp::WorkRequest::ReclaimRecvBuf(conn_id, _) => {
+                self.reclaims += 1;
+                self.connections.insert(*conn_id);
+            }
+            dp::WorkRequest::Inline(meta, _) => {
+                if meta.msg_type == RpcMsgType::Request {
+                    self.calls += 1;
+                    *self
+                        .methods
+                        .entry((meta.service_id, meta.func_id))
+                        .or_default() += 1;
+                } else {
+                    self.replies += 1;
+                }
+                self.connections.insert(meta.conn_id);
+            }
+            dp::WorkRequest::Pad => {}
+        }
+        self.snapshot_bytes += record.payload.len() as u64;
+        self.first_ns.get_or_insert(record.timestamp_ns);
+        self.last_ns = record.timestamp_ns;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = Duration::from_nanos(self.last_ns - self.first_ns.unwrap_or(self.last_ns));
+        write!(
+            f,
+            "{} calls to {} methods, {} replies, {} reclaims on {} connections over {:?}, \
+             {} bytes of payload snapshots",
+            self.calls,
+            self.methods.len(),
+            self.replies,
+            self.reclaims,
+            self.connections.len(),
+            span,
+            self.snapshot_bytes,
+        )
+    }
+}
+
+/// The replay driver. Hands the recorded work requests to a sink once they become due.
+pub struct Replayer<R = BufReader<File>> {
+    records: RecordReader<R>,
+    keep_pace: bool,
+}
+
+impl Replayer {
+    pub fn open<P: AsRef<Path>>(path: P, keep_pace: bool) -> Result<Self, Error> {
+        let records = RecordReader::open(path.as_ref())?;
+        log::info!("Replaying work requests from {:?}", path.as_ref());
+        Ok(Replayer { records, keep_pace })
+    }
+
+    /// Replays the recording into a [`Summary`] on a thread of its own, and logs the summary.
+    pub fn spawn(self) -> io::Result<thread::JoinHandle<()>> {
+        thread::Builder::new()
+            .name("mrpc-replay".to_owned())
+            .spawn(move || {
+                let mut summary = Summary::default();
+                match self.run(&mut summary) {
+                    Ok(count) => {
+                        log::info!("Replay finished, {} work requests: {}", count, summary)
+                    }
+                    Err(e) => log::warn!("Replay stopped: {}, replayed {}", e, summary),
+                }
+            })
+    }
+}
+
+impl<R: Read> Replayer<R> {
+    pub fn new(records: RecordReader<R>, keep_pace: bool) -> Self {
+        Replayer { records, keep_pace }
+    }
+
+    /// Replays the whole recording and returns the number of work requests replayed. When
+    /// keeping the recorded pace, a request is submitted when as much time has passed since
+    /// replaying the first one as it was recorded.
+    pub fn run<S: ReplaySink>(self, sink: &mut S) -> Result<u64, Error> {
+        let mut start = None;
+        let mut count = 0;
+        for record in self.records {
+            let record = record?;
+            if self.keep_pace {
+                let (start, base_ns) =
+                    *start.get_or_insert_with(|| (Instant::now(), record.timestamp_ns));
+                let due = Duration::from_nanos(record.timestamp_ns.saturating_sub(base_ns));
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+            sink.submit(&record);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phoenix_api::rpc::{CallId, MessageErased, RpcMsgType, StatusCode};
+
+    fn call(conn_id: u64, call_id: u64, func_id: u32) -> dp::WorkRequest {
+        dp::WorkRequest::Call(MessageErased {
+            meta: MessageMeta {
+                conn_id: Handle(conn_id),
+                service_id: 1,
+                func_id,
+                call_id: CallId(call_id),
+                token: 0,
+                msg_type: RpcMsgType::Request,
+                status_code: StatusCode::Success,
+            },
+            shm_addr_app: 0x1000,
+            shm_addr_backend: 0x2000,
+        })
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.wrlog", name, std::process::id()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("mrpc-record-round-trip");
+        let wrs = vec![
+            call(1, 0, 7),
+            dp::WorkRequest::ReclaimRecvBuf(Handle(1), [CallId(0); dp::RECV_RECLAIM_BS]),
+            call(2, 1, 8),
+        ];
+        let mut recorder = Recorder::create(&path, 0).unwrap();
+        for wr in &wrs {
+            // SAFETY: payload snapshot is disabled, the messages are not dereferenced.
+            unsafe { recorder.record(wr).unwrap() };
+        }
+        assert_eq!(recorder.count(), 3);
+        drop(recorder);
+
+        let records: Vec<Record> = RecordReader::open(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), wrs.len());
+        for (record, wr) in records.iter().zip(&wrs) {
+            assert_eq!(format!("{:?}", record.wr), format!("{:?}", wr));
+            assert!(record.payload.is_empty());
+        }
+        assert!(records
+            .windows(2)
+            .all(|w| w[0].timestamp_ns <= w[1].timestamp_ns));
+    }
+
+    #[test]
+    fn truncated_recording() {
+        let path = temp_path("mrpc-record-truncated");
+        let mut recorder = Recorder::create(&path, 0).unwrap();
+        unsafe { recorder.record(&call(1, 0, 7)).unwrap() };
+        drop(recorder);
+        let mut buf = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // A record cut short by a crash ends the recording.
+        buf.truncate(buf.len() - 1);
+        let reader = RecordReader::new(&buf[..]).unwrap();
+        assert_eq!(reader.count(), 0);
+
+        buf[0] = b'X';
+        assert!(matches!(RecordReader::new(&buf[..]), Err(Error::BadMagic)));
+    }
+
+    #[test]
+    fn replay_into_summary() {
+        let path = temp_path("mrpc-record-replay");
+        let mut recorder = Recorder::create(&path, 0).unwrap();
+        for wr in [
+            call(1, 0, 7),
+            call(1, 1, 7),
+            call(2, 0, 8),
+            dp::WorkRequest::ReclaimRecvBuf(Handle(2), [CallId(0); dp::RECV_RECLAIM_BS]),
+        ] {
+            unsafe { recorder.record(&wr).unwrap() };
+        }
+        drop(recorder);
+
+        let mut summary = Summary::default();
+        let replayer = Replayer::new(RecordReader::open(&path).unwrap(), false);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replayer.run(&mut summary).unwrap(), 4);
+        assert_eq!(summary.calls, 3);
+        assert_eq!(summary.reclaims, 1);
+        assert_eq!(summary.methods[&(1, 7)], 2);
+        assert_eq!(summary.methods[&(1, 8)], 1);
+        assert_eq!(summary.connections.len(), 2);
+    }
+}
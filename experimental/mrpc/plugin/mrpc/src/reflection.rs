@@ -0,0 +1,109 @@
+//! Reflection over the proto descriptors registered by the app.
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorSet};
+
+use phoenix_api_mrpc::control_plane::{
+    FieldDescriptor, MessageDescriptor, MethodDescriptor, ReflectionInfo, ServiceDescriptor,
+};
+
+/// Builds the reflection of the encoded FileDescriptorSets. A file present in multiple sets is
+/// only listed once.
+pub(crate) fn reflect(descriptor_sets: &[Vec<u8>]) -> Result<ReflectionInfo, prost::DecodeError> {
+    let mut files = Vec::new();
+    for buf in descriptor_sets {
+        let set = FileDescriptorSet::decode(buf.as_slice())?;
+        for file in set.file {
+            if !files
+                .iter()
+                .any(|f: &prost_types::FileDescriptorProto| f.name == file.name)
+            {
+                files.push(file);
+            }
+        }
+    }
+
+    let mut services = Vec::new();
+    let mut messages = Vec::new();
+    for file in files.iter() {
+        let package = file.package();
+        for service in file.service.iter() {
+            let name = qualify(package, service.name());
+            let methods = service
+                .method
+                .iter()
+                .map(|method| MethodDescriptor {
+                    name: method.name().to_owned(),
+                    input_type: method.input_type().trim_start_matches('.').to_owned(),
+                    output_type: method.output_type().trim_start_matches('.').to_owned(),
+                    // Must match mrpc-build
+                    func_id: crc32fast::hash(format!("/{}/{}", name, method.name()).as_bytes()),
+                })
+                .collect();
+            services.push(ServiceDescriptor {
+                service_id: crc32fast::hash(name.as_bytes()),
+                name,
+                methods,
+            });
+        }
+        for message in file.message_type.iter() {
+            collect_message(package, message, &mut messages);
+        }
+    }
+
+    Ok(ReflectionInfo {
+        services,
+        messages,
+        file_descriptor_sets: descriptor_sets.to_vec(),
+    })
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+fn collect_message(scope: &str, message: &DescriptorProto, out: &mut Vec<MessageDescriptor>) {
+    let name = qualify(scope, message.name());
+    let fields = message.field.iter().map(describe_field).collect();
+    for nested in message.nested_type.iter() {
+        // map entries are not interesting to show
+        if nested.options.as_ref().map_or(false, |o| o.map_entry()) {
+            continue;
+        }
+        collect_message(&name, nested, out);
+    }
+    out.push(MessageDescriptor { name, fields });
+}
+
+fn describe_field(field: &FieldDescriptorProto) -> FieldDescriptor {
+    let type_name = match field.r#type() {
+        Type::Message | Type::Enum | Type::Group => {
+            field.type_name().trim_start_matches('.').to_owned()
+        }
+        Type::Double => "double".to_owned(),
+        Type::Float => "float".to_owned(),
+        Type::Int64 => "int64".to_owned(),
+        Type::Uint64 => "uint64".to_owned(),
+        Type::Int32 => "int32".to_owned(),
+        Type::Fixed64 => "fixed64".to_owned(),
+        Type::Fixed32 => "fixed32".to_owned(),
+        Type::Bool => "bool".to_owned(),
+        Type::String => "string".to_owned(),
+        Type::Bytes => "bytes".to_owned(),
+        Type::Uint32 => "uint32".to_owned(),
+        Type::Sfixed32 => "sfixed32".to_owned(),
+        Type::Sfixed64 => "sfixed64".to_owned(),
+        Type::Sint32 => "sint32".to_owned(),
+        Type::Sint64 => "sint64".to_owned(),
+    };
+    FieldDescriptor {
+        name: field.name().to_owned(),
+        number: field.number(),
+        type_name,
+        repeated: field.label() == Label::Repeated,
+    }
+}
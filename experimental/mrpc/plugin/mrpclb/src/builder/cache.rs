@@ -1,5 +1,9 @@
 use std::path::Path;
 
+/// Changes whenever the interface of the generated dispatch library changes, so that the
+/// libraries cached by an older phoenix are rebuilt rather than loaded.
+const DISPATCH_ABI_VERSION: &str = "2";
+
 pub fn check_cache<P: AsRef<Path>>(
     protos: &[String],
     // dir to backend build cache
@@ -10,6 +14,7 @@ pub fn check_cache<P: AsRef<Path>>(
     proto_dir: &str,
 ) -> std::io::Result<(String, bool)> {
     let mut checksum_ctx = md5::Context::new();
+    checksum_ctx.consume(DISPATCH_ABI_VERSION.as_bytes());
     for proto in protos.iter() {
         checksum_ctx.consume(proto.as_bytes());
     }
@@ -20,15 +20,29 @@ pub struct MrpcLBConfig {
     /// Use NIC 0 by default
     #[serde(default)]
     pub nic_index: usize,
+    /// Number of work requests to read from the application at once
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Number of messages an engine takes from each of its queues in a round, before letting the
+    /// other engines on its runtime run. Unbounded if omitted
+    #[serde(default)]
+    pub quantum: Option<usize>,
 }
 
 impl MrpcLBConfig {
     pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
+        let config: MrpcLBConfig = toml::from_str(config.unwrap_or(""))?;
+        if config.batch_size == 0 || config.quantum == Some(0) {
+            anyhow::bail!("batch_size and quantum must be positive");
+        }
         Ok(config)
     }
 }
 
+fn default_batch_size() -> usize {
+    32
+}
+
 fn default_build_cache() -> PathBuf {
     // A path relative to MrpcConfig::prefix if it's non-empty or phoenix_prefix.
     PathBuf::from("build_cache")
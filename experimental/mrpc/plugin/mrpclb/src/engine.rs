@@ -8,11 +8,11 @@ use itertools::Itertools;
 use std::num::NonZeroU32;
 
 use phoenix_api::engine::SchedulingMode;
-use phoenix_api::rpc::{MessageErased, RpcId, StatusCode};
+use phoenix_api::rpc::{MessageErased, RpcId, RpcMsgType, StatusCode, TransportStatus};
 use phoenix_api_mrpc::{cmd, control_plane, dp};
 
 use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
-use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+use phoenix_common::engine::datapath::meta_pool::{MetaBufferPool, MetaBufferPtr};
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{
     future, Decompose, DecomposeResult, Engine, EngineResult, Indicator, Vertex,
@@ -21,10 +21,11 @@ use phoenix_common::envelop::ResourceDowncast;
 use phoenix_common::impl_vertex_for_engine;
 use phoenix_common::module::{ModuleCollection, Version};
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
-use phoenix_common::{log, tracing};
+use phoenix_common::{log, metrics, tracing};
 
-use super::builder::build_serializer_lib;
+use super::builder::BuildThread;
 use super::module::CustomerType;
+use super::serving::Serving;
 use super::state::State;
 use super::{DatapathError, Error};
 
@@ -40,15 +41,25 @@ pub struct MrpcLBEngine {
     // mRPC private buffer pools
     /// Buffer pool for meta and eager message
     pub(crate) meta_buf_pool: MetaBufferPool,
+    /// The requests delivered to the application and not replied to yet, whose hops the calls
+    /// of the application count
+    pub(crate) serving: Serving,
 
     pub(crate) _mode: SchedulingMode,
 
     pub(crate) dispatch_build_cache: PathBuf,
+    /// Builds the dispatch libraries, off the runtime
+    pub(crate) builder: BuildThread,
 
     pub(crate) transport_type: Option<control_plane::TransportType>,
 
     pub(crate) indicator: Indicator,
+    /// Its capacity is the number of work requests read from the customer at once
     pub(crate) wr_read_buffer: Vec<dp::WorkRequest>,
+    /// The payloads of the inline work requests read, in their order
+    pub(crate) wr_payload_buffer: Vec<u8>,
+    /// Messages taken from each queue in a round
+    pub(crate) quantum: usize,
 }
 
 impl_vertex_for_engine!(MrpcLBEngine, node);
@@ -87,6 +98,7 @@ impl Decompose for MrpcLBEngine {
         collections.insert("cmd_tx".to_string(), Box::new(engine.cmd_tx));
         collections.insert("cmd_rx".to_string(), Box::new(engine.cmd_rx));
         collections.insert("meta_buf_pool".to_string(), Box::new(engine.meta_buf_pool));
+        collections.insert("serving".to_string(), Box::new(engine.serving));
         collections.insert(
             "dispatch_build_cache".to_string(),
             Box::new(engine.dispatch_build_cache),
@@ -99,6 +111,11 @@ impl Decompose for MrpcLBEngine {
             "wr_read_buffer".to_string(),
             Box::new(engine.wr_read_buffer),
         );
+        collections.insert(
+            "wr_payload_buffer".to_string(),
+            Box::new(engine.wr_payload_buffer),
+        );
+        collections.insert("quantum".to_string(), Box::new(engine.quantum));
         (collections, engine.node)
     }
 }
@@ -111,6 +128,7 @@ impl MrpcLBEngine {
         node: DataPathNode,
         _plugged: &ModuleCollection,
         _prev_version: Version,
+        builder: BuildThread,
     ) -> Result<Self> {
         log::debug!("restoring MrpcLBEngine states...");
 
@@ -144,6 +162,11 @@ impl MrpcLBEngine {
             .unwrap()
             .downcast::<MetaBufferPool>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let serving = *local
+            .remove("serving")
+            .unwrap()
+            .downcast::<Serving>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let dispatch_build_cache = *local
             .remove("dispatch_build_cache")
             .unwrap()
@@ -159,6 +182,16 @@ impl MrpcLBEngine {
             .unwrap()
             .downcast::<Vec<dp::WorkRequest>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let wr_payload_buffer = *local
+            .remove("wr_payload_buffer")
+            .unwrap()
+            .downcast::<Vec<u8>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let quantum = *local
+            .remove("quantum")
+            .unwrap()
+            .downcast::<usize>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
 
         let engine = MrpcLBEngine {
             _state: state,
@@ -167,11 +200,15 @@ impl MrpcLBEngine {
             cmd_rx,
             node,
             meta_buf_pool,
+            serving,
             _mode: mode,
             dispatch_build_cache,
+            builder,
             transport_type,
             indicator: Default::default(),
             wr_read_buffer,
+            wr_payload_buffer,
+            quantum,
         };
         Ok(engine)
     }
@@ -208,10 +245,12 @@ impl MrpcLBEngine {
 
             // no work 80ns
             // has work: <1us for a batch of 30
-            loop {
+            let mut quantum = self.quantum;
+            while quantum > 0 {
                 // no work: 40ns
                 if let Progress(n) = self.check_customer()? {
                     nwork += n;
+                    quantum = quantum.saturating_sub(n);
                     if n == 0 {
                         break;
                     }
@@ -221,10 +260,14 @@ impl MrpcLBEngine {
 
             // no work: 20ns
             // has work: <2us for a batch of 30
-            loop {
+            let mut quantum = self.quantum;
+            while quantum > 0 {
                 match self.check_input_queue()? {
                     Progress(0) => break,
-                    Progress(n) => nwork += n,
+                    Progress(n) => {
+                        nwork += n;
+                        quantum = quantum.saturating_sub(n);
+                    }
                     Status::Disconnected => break,
                 }
             }
@@ -338,16 +381,33 @@ impl MrpcLBEngine {
                 Ok(None)
             }
             Command::UpdateProtos(protos) => {
-                let dylib_path =
-                    build_serializer_lib(protos.clone(), self.dispatch_build_cache.clone())?;
+                let dylib_path = self
+                    .builder
+                    .build(protos.clone(), self.dispatch_build_cache.clone())?;
                 self.cmd_tx
                     .send(Command::UpdateProtosInner(dylib_path))
                     .unwrap();
                 Ok(None)
             }
+            Command::ConnectionStats(conn_handle) => {
+                self.cmd_tx
+                    .send(Command::ConnectionStats(*conn_handle))
+                    .unwrap();
+                Ok(None)
+            }
             Command::UpdateProtosInner(_) => {
                 panic!("UpdateProtosInner is only used in backend")
             }
+            Command::UpdateDescriptors(_) => {
+                // reflection is not supported by mrpclb yet
+                Ok(Some(CompletionKind::UpdateDescriptors))
+            }
+            Command::SetMessageSizeLimit(service_id, limit) => {
+                self.cmd_tx
+                    .send(Command::SetMessageSizeLimit(*service_id, *limit))
+                    .unwrap();
+                Ok(Some(CompletionKind::SetMessageSizeLimit))
+            }
         }
     }
 
@@ -356,6 +416,12 @@ impl MrpcLBEngine {
         let buffer_cap = self.wr_read_buffer.capacity();
         // let mut timer = crate::timer::Timer::new();
 
+        // Leave the work requests in the shared queue while the tx output is full, the
+        // application sees the queue fill up.
+        if self.tx_outputs_full() {
+            return Ok(Progress(0));
+        }
+
         // 15ns
         // Fetch available work requests. Copy them into a buffer.
         let max_count = buffer_cap.min(self.customer.get_avail_wc_slots()?);
@@ -365,26 +431,20 @@ impl MrpcLBEngine {
         // timer.tick();
 
         // 300-10us, mostly 300ns (Vec::with_capacity())
-        let mut count = 0;
-        // self.wr_read_buffer.clear();
-        // SAFETY: dp::WorkRequest is Copy and zerocopy
-        unsafe {
-            self.wr_read_buffer.set_len(0);
-        }
+        self.wr_read_buffer.clear();
+        self.wr_payload_buffer.clear();
 
         // timer.tick();
 
         // 60-150ns
-        self.customer
-            .dequeue_wr_with(|ptr, read_count| unsafe {
-                // TODO(cjr): max_count <= read_count always holds
-                count = max_count.min(read_count);
-                for i in 0..count {
-                    self.wr_read_buffer
-                        .push(ptr.add(i).cast::<WorkRequest>().read());
-                }
-                count
-            })
+        let count = self
+            .customer
+            .dequeue_wrs_with_payload(
+                &mut self.wr_read_buffer,
+                &mut self.wr_payload_buffer,
+                max_count,
+                WorkRequest::payload_len,
+            )
             .unwrap_or_else(|e| panic!("check_customer: {}", e));
 
         // Process the work requests.
@@ -393,13 +453,18 @@ impl MrpcLBEngine {
         // no work: 10ns
         // has work: 100-400ns
         let buffer = mem::take(&mut self.wr_read_buffer);
+        let payloads = mem::take(&mut self.wr_payload_buffer);
 
+        let mut offset = 0;
         for wr in &buffer {
-            let ret = self.process_dp(wr);
+            let len = wr.payload_len().unwrap_or(0);
+            let ret = self.process_dp(wr, &payloads[offset..offset + len]);
+            offset += len;
             match ret {
                 Ok(()) => {}
                 Err(e) => {
                     self.wr_read_buffer = buffer;
+                    self.wr_payload_buffer = payloads;
                     // TODO(cjr): error handling
                     return Err(e);
                 }
@@ -407,6 +472,7 @@ impl MrpcLBEngine {
         }
 
         self.wr_read_buffer = buffer;
+        self.wr_payload_buffer = payloads;
 
         // timer.tick();
         // log::info!("check_customer: {} {}", count, timer);
@@ -414,10 +480,91 @@ impl MrpcLBEngine {
         Ok(Progress(count))
     }
 
-    fn process_dp(&mut self, req: &dp::WorkRequest) -> Result<(), DatapathError> {
+    /// Obtains the meta buffer of a message of the application.
+    ///
+    /// A request made while the application serves requests is a hop of them, see
+    /// [`Serving`], and is sent with one hop less than the lowest hop limit among them. If no hop
+    /// is left, the call fails with status 508 and `None` is returned. A reply ends the request
+    /// it replies to.
+    fn obtain_meta_buf(
+        &mut self,
+        meta: &phoenix_api::rpc::MessageMeta,
+    ) -> Result<Option<MetaBufferPtr>, DatapathError> {
+        let rpc_id = RpcId(meta.conn_id, meta.call_id);
+        let hop_limit = match meta.msg_type {
+            RpcMsgType::Request => self.serving.hop_limit(),
+            RpcMsgType::Response => {
+                self.serving.remove(rpc_id);
+                None
+            }
+            RpcMsgType::Notification => None,
+        };
+        let Some(hop_limit) = hop_limit else {
+            let meta_buf_ptr = self
+                .meta_buf_pool
+                .obtain(rpc_id)
+                .expect("MessageMeta pool exhausted");
+            return Ok(Some(meta_buf_ptr));
+        };
+        let meta_buf_ptr = self
+            .meta_buf_pool
+            .obtain_forwarded(rpc_id, hop_limit)
+            .expect("MessageMeta pool exhausted");
+        // SAFETY: the buffer is just obtained, nothing else refers to it
+        if unsafe { (*meta_buf_ptr.0.as_ptr()).header.forward() } {
+            return Ok(Some(meta_buf_ptr));
+        }
+        metrics::record_expired_message(&format!("mRPC LB, call_id={}", meta.call_id));
+        self.meta_buf_pool.release(rpc_id)?;
+        let status = TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(508) });
+        self.customer
+            .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
+        Ok(None)
+    }
+
+    /// Processes a work request, whose struct is `payload` for an inline one.
+    fn process_dp(&mut self, req: &dp::WorkRequest, payload: &[u8]) -> Result<(), DatapathError> {
         use dp::WorkRequest;
 
         match req {
+            WorkRequest::Inline(meta, _len) => {
+                tracing::trace!(
+                    "mRPC LB engine got an inline message from App, call_id: {:?}, conn_id: {:?}",
+                    meta.call_id,
+                    meta.conn_id,
+                );
+
+                let rpc_id = RpcId(meta.conn_id, meta.call_id);
+                if payload.len() > dp::MAX_INLINE_PAYLOAD {
+                    log::warn!(
+                        "Inline message {:?} of {} bytes, rejected",
+                        rpc_id,
+                        payload.len()
+                    );
+                    let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
+                        NonZeroU32::new_unchecked(413)
+                    });
+                    self.customer
+                        .enqueue_wc(dp::Completion::Outgoing(rpc_id, status))?;
+                    return Ok(());
+                }
+                let Some(meta_buf_ptr) = self.obtain_meta_buf(meta)? else {
+                    return Ok(());
+                };
+                // the message is sent from its copy in the meta buffer, which is released with
+                // the meta once the message is acknowledged
+                let addr_backend = unsafe {
+                    std::ptr::write(meta_buf_ptr.as_meta_ptr(), *meta);
+                    meta_buf_ptr.write_inline_body(payload)
+                };
+
+                let msg = RpcMessageTx {
+                    meta_buf_ptr,
+                    addr_backend,
+                };
+                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+            }
+            WorkRequest::Pad => {}
             WorkRequest::Call(erased) | WorkRequest::Reply(erased) => {
                 // let mut timer = crate::timer::Timer::new();
 
@@ -431,11 +578,9 @@ impl MrpcLBEngine {
                 // timer.tick();
 
                 // construct message meta on heap
-                let rpc_id = RpcId(erased.meta.conn_id, erased.meta.call_id);
-                let meta_buf_ptr = self
-                    .meta_buf_pool
-                    .obtain(rpc_id)
-                    .expect("MessageMeta pool exhausted");
+                let Some(meta_buf_ptr) = self.obtain_meta_buf(&erased.meta)? else {
+                    return Ok(());
+                };
 
                 // timer.tick();
 
@@ -493,13 +638,25 @@ impl MrpcLBEngine {
                         };
                         // timer.tick();
                         match meta.status_code {
-                            StatusCode::AccessDenied => {
-                                tracing::debug!("Status code: Access denied, meta={:?}", meta);
+                            StatusCode::AccessDenied
+                            | StatusCode::Unimplemented
+                            | StatusCode::Unknown => {
+                                tracing::debug!(
+                                    "Status code: {:?}, meta={:?}",
+                                    meta.status_code,
+                                    meta
+                                );
+                                // the server has replied with an error, fail the call
+                                let code = match meta.status_code {
+                                    StatusCode::AccessDenied => 402,
+                                    StatusCode::Unimplemented => 501,
+                                    _ => 500,
+                                };
                                 let mut sent = false;
                                 let rpc_id = RpcId(meta.conn_id, meta.call_id);
-                                let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
-                                    NonZeroU32::new_unchecked(402)
-                                });
+                                let status = phoenix_api::rpc::TransportStatus::Error(
+                                    NonZeroU32::new(code).unwrap(),
+                                );
                                 while !sent {
                                     self.customer.enqueue_wc_with(|ptr, _count| unsafe {
                                         // self.customer.notify_wc_with(|ptr, _count| unsafe {
@@ -516,10 +673,33 @@ impl MrpcLBEngine {
                                     msg_call_ids,
                                 ))?;
                             }
-                            StatusCode::Unknown => {
-                                tracing::error!("Status code: Unknown error, meta={:?}", meta);
+                            StatusCode::MessageTooLarge => {
+                                tracing::debug!("Status code: Message too large, meta={:?}", meta);
+                                let mut sent = false;
+                                let rpc_id = RpcId(meta.conn_id, meta.call_id);
+                                let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
+                                    NonZeroU32::new_unchecked(414)
+                                });
+                                while !sent {
+                                    self.customer.enqueue_wc_with(|ptr, _count| unsafe {
+                                        sent = true;
+                                        ptr.cast::<dp::Completion>()
+                                            .write(dp::Completion::Outgoing(rpc_id, status));
+                                        1
+                                    })?;
+                                }
+                                let msg_call_ids =
+                                    [meta.call_id, meta.call_id, meta.call_id, meta.call_id];
+                                self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
+                                    meta.conn_id,
+                                    msg_call_ids,
+                                ))?;
                             }
                             StatusCode::Success => {
+                                if meta.msg_type == RpcMsgType::Request {
+                                    self.serving
+                                        .insert(RpcId(meta.conn_id, meta.call_id), msg.hop_limit);
+                                }
                                 // the following operation takes around 100ns
                                 let mut sent = false;
                                 while !sent {
@@ -552,6 +732,8 @@ impl MrpcLBEngine {
                         }
                     }
                     EngineRxMessage::RecvError(conn_id, status) => {
+                        // the requests received on it are never replied to
+                        self.serving.remove_conn(conn_id);
                         let mut sent = false;
                         while !sent {
                             self.customer.enqueue_wc_with(|ptr, _count| unsafe {
@@ -601,11 +783,19 @@ impl MrpcLBEngine {
                     c @ Ok(
                         CompletionKind::Bind(..)
                         | CompletionKind::NewMappedAddrs
-                        | CompletionKind::UpdateProtos,
+                        | CompletionKind::UpdateProtos
+                        | CompletionKind::ConnectionStats(..),
                     ) => {
                         self.customer.send_comp(cmd::Completion(c))?;
                         Ok(Status::Progress(1))
                     }
+                    // the app waits for the command, e.g., the stats of an unknown connection
+                    c @ Err(_) => {
+                        self.customer.send_comp(cmd::Completion(c))?;
+                        Ok(Status::Progress(1))
+                    }
+                    // already acknowledged to the app
+                    Ok(CompletionKind::SetMessageSizeLimit) => Ok(Status::Progress(1)),
                     other => panic!("unexpected: {:?}", other),
                 }
             }
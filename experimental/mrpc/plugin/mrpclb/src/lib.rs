@@ -12,6 +12,7 @@ pub(crate) mod engine;
 // pub mod message;
 // pub mod meta_pool;
 pub mod module;
+pub(crate) mod serving;
 pub mod state;
 pub mod unpack;
 
@@ -34,7 +35,11 @@ pub(crate) enum Error {
 
 impl From<Error> for phoenix_api::Error {
     fn from(other: Error) -> Self {
-        phoenix_api::Error::Generic(other.to_string())
+        let code = match &other {
+            Error::Resource(e) => e.code(),
+            _ => phoenix_api::ErrorCode::Internal,
+        };
+        phoenix_api::Error::from_error(code, &other)
     }
 }
 
@@ -44,10 +49,14 @@ pub(crate) enum DatapathError {
     ShmIpc(#[from] ipc::shmem_ipc::ShmIpcError),
     #[error("Shared memory queue ringbuf error: {0}.")]
     ShmRingbuf(#[from] ipc::shmem_ipc::ShmRingbufError),
+    #[error("Shared memory ring error: {0}.")]
+    ShmRing(#[from] ipc::ring::Error),
     #[error("Resource error: {0}")]
     Resource(#[from] ResourceError),
     #[error("Internal queue send error")]
     InternalQueueSend,
+    #[error("Unexpected error: {0}")]
+    Other(String),
 }
 
 impl From<ipc::Error> for DatapathError {
@@ -55,7 +64,12 @@ impl From<ipc::Error> for DatapathError {
         match other {
             ipc::Error::ShmIpc(e) => DatapathError::ShmIpc(e),
             ipc::Error::ShmRingbuf(e) => DatapathError::ShmRingbuf(e),
-            _ => panic!(),
+            ipc::Error::ShmRing(e) => DatapathError::ShmRing(e),
+            other => {
+                let context = format!("IPC error on the datapath: {}", other);
+                phoenix_common::metrics::record_unexpected_error(&context);
+                DatapathError::Other(context)
+            }
         }
     }
 }
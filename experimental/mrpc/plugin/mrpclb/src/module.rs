@@ -11,6 +11,7 @@ use phoenix_api_mrpc::control_plane::Setting;
 use phoenix_api_mrpc::control_plane::TransportType;
 use phoenix_api_mrpc::{cmd, dp};
 
+use phoenix_common::capability::Capabilities;
 use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
 use phoenix_common::engine::datapath::node::{ChannelDescriptor, DataPathNode};
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
@@ -23,6 +24,7 @@ use phoenix_common::state_mgr::{Pid, SharedStateManager};
 use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
 use phoenix_common::PhoenixResult;
 
+use crate::builder::BuildThread;
 use crate::config::MrpcLBConfig;
 
 use super::engine::MrpcLBEngine;
@@ -39,7 +41,10 @@ pub(crate) struct MrpcLBEngineBuilder {
     cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Completion>,
     node: DataPathNode,
     serializer_build_cache: PathBuf,
+    builder: BuildThread,
     shared: Arc<Shared>,
+    batch_size: usize,
+    quantum: usize,
 }
 
 impl MrpcLBEngineBuilder {
@@ -52,7 +57,10 @@ impl MrpcLBEngineBuilder {
         cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Completion>,
         node: DataPathNode,
         serializer_build_cache: PathBuf,
+        builder: BuildThread,
         shared: Arc<Shared>,
+        batch_size: usize,
+        quantum: usize,
     ) -> Self {
         MrpcLBEngineBuilder {
             customer,
@@ -62,13 +70,15 @@ impl MrpcLBEngineBuilder {
             _client_pid: client_pid,
             mode,
             serializer_build_cache,
+            builder,
             shared,
+            batch_size,
+            quantum,
         }
     }
 
     fn build(self) -> Result<MrpcLBEngine> {
         const META_BUFFER_POOL_CAP: usize = 128;
-        const BUF_LEN: usize = 32;
 
         let state = State::new(self.shared);
 
@@ -79,11 +89,15 @@ impl MrpcLBEngineBuilder {
             cmd_rx: self.cmd_rx,
             node: self.node,
             meta_buf_pool: MetaBufferPool::new(META_BUFFER_POOL_CAP),
+            serving: Default::default(),
             _mode: self.mode,
             dispatch_build_cache: self.serializer_build_cache,
+            builder: self.builder,
             transport_type: Some(TransportType::Tcp),
             indicator: Default::default(),
-            wr_read_buffer: Vec::with_capacity(BUF_LEN),
+            wr_read_buffer: Vec::with_capacity(self.batch_size),
+            wr_payload_buffer: Vec::with_capacity(dp::MAX_INLINE_PAYLOAD),
+            quantum: self.quantum,
         })
     }
 }
@@ -91,6 +105,8 @@ impl MrpcLBEngineBuilder {
 pub struct MrpcLBModule {
     config: MrpcLBConfig,
     pub state_mgr: SharedStateManager<Shared>,
+    /// The thread building the dispatch libraries, started with the first engine
+    builder: Option<BuildThread>,
 }
 
 impl MrpcLBModule {
@@ -163,9 +179,19 @@ impl MrpcLBModule {
         MrpcLBModule {
             config,
             state_mgr: SharedStateManager::new(),
+            builder: None,
         }
     }
 
+    /// The thread building the dispatch libraries of the engines. It is started on the control
+    /// thread, and the upgraded module starts its own.
+    fn builder(&mut self) -> std::io::Result<BuildThread> {
+        if self.builder.is_none() {
+            self.builder = Some(BuildThread::spawn()?);
+        }
+        Ok(self.builder.clone().unwrap())
+    }
+
     // Returns build_cache if it's already an absolute path. Otherwise returns the path relative
     // to the engine's prefix.
     fn get_build_cache_directory(&self, engine_prefix: &PathBuf) -> PathBuf {
@@ -222,6 +248,18 @@ impl PhoenixModule for MrpcLBModule {
         }
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // The dispatch libraries are built off the runtimes, which only load them. The cache is
+        // in the prefix of the daemon unless it is elsewhere.
+        let mut caps = Capabilities::default();
+        if self.config.build_cache.is_absolute() {
+            caps.read_paths.push(self.config.build_cache.clone());
+        } else if let Some(prefix) = self.config.prefix.as_ref() {
+            caps.read_paths.push(prefix.join(&self.config.build_cache));
+        }
+        caps
+    }
+
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
@@ -286,6 +324,8 @@ impl PhoenixModule for MrpcLBModule {
                     nic_index: self.config.nic_index,
                     core_id: None,
                     module_config: None,
+                    encryption: false,
+                    max_inline_payload: 0,
                 }
             };
             log::debug!("mRPCLB service setting: {:?}", setting);
@@ -303,6 +343,7 @@ impl PhoenixModule for MrpcLBModule {
             let cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Completion> =
                 shared.command_path.get_receiver(&MrpcLBModule::LB_ENGINE)?;
 
+            let build_thread = self.builder()?;
             let builder = MrpcLBEngineBuilder::new(
                 customer,
                 client_pid,
@@ -311,7 +352,10 @@ impl PhoenixModule for MrpcLBModule {
                 cmd_rx,
                 node,
                 build_cache,
+                build_thread,
                 shared_state,
+                self.config.batch_size,
+                self.config.quantum.unwrap_or(usize::MAX),
                 // TODO(cjr): store the setting, not necessary now.
             );
             let engine = builder.build()?;
@@ -335,7 +379,16 @@ impl PhoenixModule for MrpcLBModule {
         if ty != MrpcLBModule::MRPCLB_ENGINE {
             bail!("invalid engine type {:?}", ty)
         }
-        let engine = MrpcLBEngine::restore(local, shared, global, node, plugged, prev_version)?;
+        let build_thread = self.builder()?;
+        let engine = MrpcLBEngine::restore(
+            local,
+            shared,
+            global,
+            node,
+            plugged,
+            prev_version,
+            build_thread,
+        )?;
         Ok(Box::new(engine))
     }
 }
@@ -0,0 +1,96 @@
+//! The requests the application is serving, to count the hops of the calls it makes meanwhile.
+//!
+//! The engine cannot tell which received request a call of the application forwards, if any. A
+//! call made while the application has requests it has not replied to is therefore counted as a
+//! hop of the one with the fewest hops left, i.e., it is sent with the hop limit of that request
+//! less one. Applications that forward a request around a loop keep each request of the loop
+//! waiting for the reply of the next hop, so the hop limit goes down around the loop until the
+//! call fails. A call that forwards none of them only gets a lower hop limit than a new one.
+use std::collections::BTreeMap;
+
+use fnv::FnvHashMap;
+
+use phoenix_api::rpc::RpcId;
+use phoenix_api::Handle;
+
+#[derive(Debug, Default)]
+pub(crate) struct Serving {
+    /// The hop limit of each request delivered to the application and not replied to.
+    requests: FnvHashMap<RpcId, u8>,
+    /// The number of those requests of each hop limit.
+    hop_limits: BTreeMap<u8, usize>,
+}
+
+fn forget(hop_limits: &mut BTreeMap<u8, usize>, hop_limit: u8) {
+    let count = hop_limits.get_mut(&hop_limit).unwrap();
+    *count -= 1;
+    if *count == 0 {
+        hop_limits.remove(&hop_limit);
+    }
+}
+
+impl Serving {
+    /// Records a request delivered to the application with `hop_limit` hops left.
+    pub(crate) fn insert(&mut self, rpc_id: RpcId, hop_limit: u8) {
+        if let Some(prev) = self.requests.insert(rpc_id, hop_limit) {
+            forget(&mut self.hop_limits, prev);
+        }
+        *self.hop_limits.entry(hop_limit).or_default() += 1;
+    }
+
+    /// Forgets a request the application has replied to.
+    pub(crate) fn remove(&mut self, rpc_id: RpcId) {
+        if let Some(hop_limit) = self.requests.remove(&rpc_id) {
+            forget(&mut self.hop_limits, hop_limit);
+        }
+    }
+
+    /// Forgets the requests received on a connection that has failed, they are never replied to.
+    pub(crate) fn remove_conn(&mut self, conn_id: Handle) {
+        let hop_limits = &mut self.hop_limits;
+        self.requests.retain(|rpc_id, &mut hop_limit| {
+            if rpc_id.0 != conn_id {
+                return true;
+            }
+            forget(hop_limits, hop_limit);
+            false
+        });
+    }
+
+    /// The lowest hop limit of the requests served, `None` if there is none.
+    #[inline]
+    pub(crate) fn hop_limit(&self) -> Option<u8> {
+        self.hop_limits.keys().next().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use phoenix_api::rpc::CallId;
+
+    use super::*;
+
+    fn id(conn_id: u64, call_id: u64) -> RpcId {
+        RpcId(Handle(conn_id), CallId(call_id))
+    }
+
+    #[test]
+    fn lowest_hop_limit_served() {
+        let mut serving = Serving::default();
+        assert_eq!(serving.hop_limit(), None);
+
+        serving.insert(id(1, 1), 10);
+        serving.insert(id(1, 2), 3);
+        serving.insert(id(2, 1), 3);
+        assert_eq!(serving.hop_limit(), Some(3));
+
+        serving.remove(id(1, 2));
+        assert_eq!(serving.hop_limit(), Some(3));
+        serving.remove_conn(Handle(2));
+        assert_eq!(serving.hop_limit(), Some(10));
+        // replied already
+        serving.remove(id(2, 1));
+        serving.remove(id(1, 1));
+        assert_eq!(serving.hop_limit(), None);
+    }
+}
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Directory to write the capture files to.
+    #[serde(default = "default_dir")]
+    pub dir: PathBuf,
+    /// Number of payload bytes to capture for each message, 0 to capture headers only.
+    #[serde(default)]
+    pub snap_len: usize,
+    /// Capture only the messages of this service, by its ID.
+    #[serde(default)]
+    pub service_id: Option<u32>,
+    /// Capture only the messages of this function, by its ID.
+    #[serde(default)]
+    pub func_id: Option<u32>,
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from("/tmp/phoenix/capture")
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            dir: default_dir(),
+            snap_len: 0,
+            service_id: None,
+            func_id: None,
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config = toml::from_str(config.unwrap_or(""))?;
+        Ok(config)
+    }
+
+    /// Whether the messages of `service_id` and `func_id` are captured. The acks and the errors
+    /// are always captured.
+    pub(crate) fn matches(&self, service_id: u32, func_id: u32) -> bool {
+        self.service_id.map_or(true, |id| id == service_id)
+            && self.func_id.map_or(true, |id| id == func_id)
+    }
+}
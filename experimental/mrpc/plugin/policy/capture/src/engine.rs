@@ -0,0 +1,301 @@
+//! This engine can be placed on either side, it captures what goes through it in both directions.
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use nix::unistd::Pid;
+
+use phoenix_api::rpc::{MessageMeta, RpcMsgType, StatusCode, TransportStatus};
+use phoenix_api_policy_capture::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage};
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::log;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::config::CaptureConfig;
+use crate::module::create_capture_file;
+use crate::pcapng::{Direction, PcapngWriter, RecordHeader, RecordKind};
+
+/// Flush the capture file at least every this many records.
+const FLUSH_INTERVAL: usize = 1024;
+
+pub(crate) struct CaptureEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+    pub(crate) pid: Pid,
+    pub(crate) config: CaptureConfig,
+    pub(crate) writer: PcapngWriter,
+    // records written since the last flush
+    pub(crate) unflushed: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for CaptureEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "CaptureEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::SetSnapLen(snap_len) => {
+                self.config.snap_len = snap_len;
+            }
+            control_plane::Request::Rotate => {
+                self.writer.flush()?;
+                log::info!(
+                    "Rotating capture file after {} records",
+                    self.writer.count()
+                );
+                self.writer = create_capture_file(&self.config, self.pid)?;
+                self.unflushed = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_vertex_for_engine!(CaptureEngine, node);
+
+impl Decompose for CaptureEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
+            }
+        }
+        self.writer.flush()?;
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+        let mut collections = ResourceCollection::with_capacity(4);
+        collections.insert("pid".to_string(), Box::new(engine.pid));
+        collections.insert("config".to_string(), Box::new(engine.config));
+        (collections, engine.node)
+    }
+}
+
+impl CaptureEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let pid = *local
+            .remove("pid")
+            .unwrap()
+            .downcast::<Pid>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<CaptureConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        // The capture continues in a new file.
+        let writer = create_capture_file(&config, pid)?;
+        let engine = CaptureEngine {
+            node,
+            indicator: Default::default(),
+            pid,
+            config,
+            writer,
+            unflushed: 0,
+        };
+        Ok(engine)
+    }
+}
+
+impl CaptureEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => {
+                        self.writer.flush()?;
+                        return Ok(());
+                    }
+                }
+            }
+
+            // Keep the file readable by a live Wireshark when there is nothing else to do.
+            if self.unflushed >= FLUSH_INTERVAL || (work == 0 && self.unflushed > 0) {
+                self.writer.flush()?;
+                self.unflushed = 0;
+            }
+
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+#[inline]
+fn message_header(direction: Direction, meta: &MessageMeta, snap_len: usize) -> RecordHeader {
+    // Only the meta of a rejected message is transmitted.
+    let payload_len = if meta.status_code == StatusCode::Success {
+        snap_len
+    } else {
+        0
+    };
+    RecordHeader {
+        direction,
+        kind: RecordKind::Message,
+        transport_status: 0,
+        conn_id: meta.conn_id.0,
+        call_id: meta.call_id.0,
+        token: meta.token,
+        service_id: meta.service_id,
+        func_id: meta.func_id,
+        msg_type: match meta.msg_type {
+            RpcMsgType::Request => 0,
+            RpcMsgType::Response => 1,
+            RpcMsgType::Notification => 2,
+        },
+        status_code: meta.status_code as u8,
+        payload_len: payload_len as u32,
+    }
+}
+
+#[inline]
+fn status_header(
+    kind: RecordKind,
+    conn_id: u64,
+    call_id: u64,
+    status: TransportStatus,
+) -> RecordHeader {
+    RecordHeader {
+        direction: Direction::Rx,
+        kind,
+        transport_status: status.code(),
+        conn_id,
+        call_id,
+        token: 0,
+        service_id: 0,
+        func_id: 0,
+        msg_type: 0,
+        status_code: 0,
+        payload_len: 0,
+    }
+}
+
+impl CaptureEngine {
+    fn capture(&mut self, header: RecordHeader, payload_addr: usize) -> Result<(), DatapathError> {
+        let payload = if header.payload_len > 0 {
+            // SAFETY: the message is on the shared memory heap mapped in this process. The bytes
+            // past the end of a message smaller than snap_len are within the heap too.
+            unsafe {
+                std::slice::from_raw_parts(payload_addr as *const u8, header.payload_len as usize)
+            }
+        } else {
+            &[]
+        };
+        self.writer.write_packet(&header.to_bytes(), payload)?;
+        self.unflushed += 1;
+        Ok(())
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            if self.config.matches(meta.service_id, meta.func_id) {
+                                let header =
+                                    message_header(Direction::Tx, meta, self.config.snap_len);
+                                self.capture(header, msg.addr_backend)?;
+                            }
+                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                        }
+                        m => self.tx_outputs()[0].send(m)?,
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    return Ok(Status::Disconnected);
+                }
+            }
+        }
+
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineRxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            if self.config.matches(meta.service_id, meta.func_id) {
+                                let header =
+                                    message_header(Direction::Rx, meta, self.config.snap_len);
+                                self.capture(header, msg.addr_backend)?;
+                            }
+                            self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                        }
+                        EngineRxMessage::Ack(rpc_id, status) => {
+                            let header =
+                                status_header(RecordKind::Ack, rpc_id.0 .0, rpc_id.1 .0, status);
+                            self.capture(header, 0)?;
+                            self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                        }
+                        EngineRxMessage::RecvError(conn_id, status) => {
+                            let header = status_header(RecordKind::RecvError, conn_id.0, 0, status);
+                            self.capture(header, 0)?;
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::RecvError(conn_id, status))?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    return Ok(Status::Disconnected);
+                }
+            }
+        }
+
+        Ok(Progress(0))
+    }
+}
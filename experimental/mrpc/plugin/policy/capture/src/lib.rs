@@ -0,0 +1,36 @@
+#![feature(peer_credentials_unix_socket)]
+#![feature(ptr_internals)]
+
+use thiserror::Error;
+
+pub use phoenix_common::{InitFnResult, PhoenixAddon};
+
+pub mod config;
+pub(crate) mod engine;
+pub mod module;
+pub mod pcapng;
+
+#[derive(Error, Debug)]
+pub(crate) enum DatapathError {
+    #[error("Internal queue send error")]
+    InternalQueueSend,
+    #[error("Capture file error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+use phoenix_common::engine::datapath::SendError;
+impl<T> From<SendError<T>> for DatapathError {
+    fn from(_other: SendError<T>) -> Self {
+        DatapathError::InternalQueueSend
+    }
+}
+
+use crate::config::CaptureConfig;
+use crate::module::CaptureAddon;
+
+#[no_mangle]
+pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
+    let config = CaptureConfig::new(config_string)?;
+    let addon = CaptureAddon::new(config);
+    Ok(Box::new(addon))
+}
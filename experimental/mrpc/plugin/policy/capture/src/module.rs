@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::log;
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::CaptureEngine;
+use crate::config::CaptureConfig;
+use crate::pcapng::{PcapngWriter, LINKTYPE_USER0};
+
+/// Creates a new capture file for the process `pid`.
+pub(crate) fn create_capture_file(config: &CaptureConfig, pid: Pid) -> Result<PcapngWriter> {
+    std::fs::create_dir_all(&config.dir)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let path: PathBuf = config.dir.join(format!("mrpc-{}-{}.pcapng", pid, now));
+    log::info!("Capturing mRPC messages of {} to {:?}", pid, path);
+    Ok(PcapngWriter::create(path, LINKTYPE_USER0)?)
+}
+
+pub(crate) struct CaptureEngineBuilder {
+    node: DataPathNode,
+    pid: Pid,
+    config: CaptureConfig,
+}
+
+impl CaptureEngineBuilder {
+    fn new(node: DataPathNode, pid: Pid, config: CaptureConfig) -> Self {
+        CaptureEngineBuilder { node, pid, config }
+    }
+
+    fn build(self) -> Result<CaptureEngine> {
+        let writer = create_capture_file(&self.config, self.pid)?;
+
+        Ok(CaptureEngine {
+            node: self.node,
+            indicator: Default::default(),
+            pid: self.pid,
+            config: self.config,
+            writer,
+            unflushed: 0,
+        })
+    }
+}
+
+pub struct CaptureAddon {
+    config: CaptureConfig,
+}
+
+impl CaptureAddon {
+    pub const CAPTURE_ENGINE: EngineType = EngineType("CaptureEngine");
+    pub const ENGINES: &'static [EngineType] = &[CaptureAddon::CAPTURE_ENGINE];
+}
+
+impl CaptureAddon {
+    pub fn new(config: CaptureConfig) -> Self {
+        CaptureAddon { config }
+    }
+}
+
+impl PhoenixAddon for CaptureAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        CaptureAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = toml::from_str(config)?;
+        Ok(())
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != CaptureAddon::CAPTURE_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = CaptureEngineBuilder::new(node, pid, self.config.clone());
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != CaptureAddon::CAPTURE_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = CaptureEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
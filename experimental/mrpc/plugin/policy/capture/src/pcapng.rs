@@ -0,0 +1,176 @@
+//! A minimal pcapng writer and the record format of captured mRPC messages.
+//!
+//! Each capture file has a single section with a single interface of link type
+//! [`LINKTYPE_USER0`], with timestamps in nanoseconds. Every packet is a [`RecordHeader`]
+//! followed by the captured payload bytes. The Lua dissector generated by
+//! `phoenix-cli dissector` decodes the records in Wireshark.
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The first link type reserved for private use.
+pub const LINKTYPE_USER0: u16 = 147;
+
+/// Version of the record format, bumped on any change to [`RecordHeader`].
+pub const RECORD_VERSION: u8 = 1;
+
+const SHB_TYPE: u32 = 0x0A0D_0D0A;
+const IDB_TYPE: u32 = 0x0000_0001;
+const EPB_TYPE: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_IF_TSRESOL: u16 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Direction {
+    /// From the application to the network.
+    Tx = 0,
+    /// From the network to the application.
+    Rx = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordKind {
+    Message = 0,
+    Ack = 1,
+    RecvError = 2,
+}
+
+/// The header of each captured record, written in little endian.
+///
+/// | offset | size | field                                  |
+/// |--------|------|----------------------------------------|
+/// | 0      | 1    | version, [`RECORD_VERSION`]            |
+/// | 1      | 1    | direction                              |
+/// | 2      | 1    | kind                                   |
+/// | 4      | 4    | transport status                       |
+/// | 8      | 8    | conn_id                                |
+/// | 16     | 8    | call_id                                |
+/// | 24     | 8    | token                                  |
+/// | 32     | 4    | service_id                             |
+/// | 36     | 4    | func_id                                |
+/// | 40     | 1    | msg_type, request 0, reply 1, notify 2 |
+/// | 41     | 1    | status_code                            |
+/// | 44     | 4    | payload_len                            |
+#[derive(Debug, Clone, Copy)]
+pub struct RecordHeader {
+    pub direction: Direction,
+    pub kind: RecordKind,
+    /// The transport status of an ack or a receive error, 0 for messages.
+    pub transport_status: u32,
+    pub conn_id: u64,
+    pub call_id: u64,
+    pub token: u64,
+    pub service_id: u32,
+    pub func_id: u32,
+    pub msg_type: u8,
+    pub status_code: u8,
+    /// Number of payload bytes following the header.
+    pub payload_len: u32,
+}
+
+impl RecordHeader {
+    pub const LEN: usize = 48;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut buf = [0u8; Self::LEN];
+        buf[0] = RECORD_VERSION;
+        buf[1] = self.direction as u8;
+        buf[2] = self.kind as u8;
+        buf[4..8].copy_from_slice(&self.transport_status.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.conn_id.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.call_id.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.token.to_le_bytes());
+        buf[32..36].copy_from_slice(&self.service_id.to_le_bytes());
+        buf[36..40].copy_from_slice(&self.func_id.to_le_bytes());
+        buf[40] = self.msg_type;
+        buf[41] = self.status_code;
+        buf[44..48].copy_from_slice(&self.payload_len.to_le_bytes());
+        buf
+    }
+}
+
+/// Writes packets to a pcapng file.
+pub struct PcapngWriter {
+    writer: BufWriter<File>,
+    count: u64,
+}
+
+#[inline]
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+impl PcapngWriter {
+    pub fn create<P: AsRef<Path>>(path: P, link_type: u16) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        // Section Header Block
+        let len = 28u32;
+        writer.write_all(&SHB_TYPE.to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(&BYTE_ORDER_MAGIC.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        // section length not specified
+        writer.write_all(&(-1i64).to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+
+        // Interface Description Block, with the timestamps in nanoseconds
+        let len = 32u32;
+        writer.write_all(&IDB_TYPE.to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(&link_type.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        // no snap length limit
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&OPT_IF_TSRESOL.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&[9, 0, 0, 0])?;
+        writer.write_all(&OPT_ENDOFOPT.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+
+        Ok(PcapngWriter { writer, count: 0 })
+    }
+
+    /// Writes an Enhanced Packet Block made of `header` and `payload`, timestamped now.
+    pub fn write_packet(&mut self, header: &[u8], payload: &[u8]) -> io::Result<()> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let captured = header.len() + payload.len();
+        let pad = padding(captured);
+        let len = (32 + captured + pad) as u32;
+
+        self.writer.write_all(&EPB_TYPE.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        // interface id
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        self.writer.write_all(&((ts >> 32) as u32).to_le_bytes())?;
+        self.writer.write_all(&(ts as u32).to_le_bytes())?;
+        self.writer.write_all(&(captured as u32).to_le_bytes())?;
+        self.writer.write_all(&(captured as u32).to_le_bytes())?;
+        self.writer.write_all(header)?;
+        self.writer.write_all(payload)?;
+        self.writer.write_all(&[0u8; 3][..pad])?;
+        self.writer.write_all(&len.to_le_bytes())?;
+
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Number of packets written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,318 @@
+//! The breaker of a destination: closed, it counts the calls and opens when too many fail;
+//! open, it fails the calls until the cool-down is over; half-open, it lets a few probe calls
+//! through to decide whether to close or to open again.
+use std::time::{Duration, Instant};
+
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// `probes` calls let through since `since`.
+    HalfOpen {
+        probes: u32,
+        since: Instant,
+    },
+}
+
+/// A change of the state of a breaker worth an event.
+pub(crate) enum Transition {
+    Opened(String),
+    Closed,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Breaker {
+    state: State,
+    consecutive_failures: u32,
+    window_start: Instant,
+    calls: u64,
+    failures: u64,
+}
+
+impl Breaker {
+    pub(crate) fn new(now: Instant) -> Self {
+        Breaker {
+            state: State::Closed,
+            consecutive_failures: 0,
+            window_start: now,
+            calls: 0,
+            failures: 0,
+        }
+    }
+
+    fn reset_counts(&mut self, now: Instant) {
+        self.consecutive_failures = 0;
+        self.window_start = now;
+        self.calls = 0;
+        self.failures = 0;
+    }
+
+    fn open(&mut self, config: &CircuitBreakerConfig, now: Instant) {
+        self.state = State::Open {
+            until: now + Duration::from_millis(config.cooldown_ms),
+        };
+        self.reset_counts(now);
+    }
+
+    /// Whether a call can go to the destination, or should fail fast.
+    #[inline]
+    pub(crate) fn admit(&mut self, config: &CircuitBreakerConfig, now: Instant) -> bool {
+        match self.state {
+            State::Closed => true,
+            State::Open { until } if now < until => false,
+            State::Open { .. } => {
+                self.state = State::HalfOpen {
+                    probes: 1,
+                    since: now,
+                };
+                true
+            }
+            State::HalfOpen { probes, since } if probes < config.half_open_requests => {
+                self.state = State::HalfOpen {
+                    probes: probes + 1,
+                    since,
+                };
+                true
+            }
+            // the probes may never complete, e.g., if they are lost with the connection
+            State::HalfOpen { since, .. }
+                if now.duration_since(since) >= Duration::from_millis(config.cooldown_ms) =>
+            {
+                self.state = State::HalfOpen {
+                    probes: 1,
+                    since: now,
+                };
+                true
+            }
+            State::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Counts a call that completes, returns the transition it causes.
+    pub(crate) fn on_completion(
+        &mut self,
+        config: &CircuitBreakerConfig,
+        success: bool,
+        now: Instant,
+    ) -> Option<Transition> {
+        match self.state {
+            State::HalfOpen { .. } if success => {
+                self.state = State::Closed;
+                self.reset_counts(now);
+                Some(Transition::Closed)
+            }
+            State::HalfOpen { .. } => {
+                self.open(config, now);
+                Some(Transition::Opened("a probe call failed".to_owned()))
+            }
+            // the replies of the calls sent before it opened
+            State::Open { .. } => None,
+            State::Closed => {
+                if now.duration_since(self.window_start) >= Duration::from_millis(config.window_ms)
+                {
+                    self.window_start = now;
+                    self.calls = 0;
+                    self.failures = 0;
+                }
+                self.calls += 1;
+                if success {
+                    self.consecutive_failures = 0;
+                    return None;
+                }
+                self.failures += 1;
+                self.consecutive_failures += 1;
+
+                let reason = if self.consecutive_failures >= config.consecutive_failures {
+                    format!("{} calls failed in a row", self.consecutive_failures)
+                } else if self.calls >= config.min_requests
+                    && self.failures as f64 >= config.error_rate_threshold * self.calls as f64
+                {
+                    format!(
+                        "{} of {} calls failed in {}ms",
+                        self.failures,
+                        self.calls,
+                        now.duration_since(self.window_start).as_millis()
+                    )
+                } else {
+                    return None;
+                };
+                self.open(config, now);
+                Some(Transition::Opened(reason))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            consecutive_failures: 3,
+            error_rate_threshold: 0.5,
+            min_requests: 6,
+            window_ms: 1000,
+            cooldown_ms: 100,
+            half_open_requests: 2,
+        }
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    fn opened(transition: Option<Transition>) -> Option<String> {
+        match transition {
+            Some(Transition::Opened(reason)) => Some(reason),
+            _ => None,
+        }
+    }
+
+    fn closed(transition: Option<Transition>) -> bool {
+        matches!(transition, Some(Transition::Closed))
+    }
+
+    /// Opens the breaker at `now` by failing calls in a row.
+    fn trip(breaker: &mut Breaker, config: &CircuitBreakerConfig, now: Instant) {
+        for _ in 1..config.consecutive_failures {
+            assert!(breaker.on_completion(config, false, now).is_none());
+        }
+        assert!(opened(breaker.on_completion(config, false, now)).is_some());
+    }
+
+    #[test]
+    fn open_on_consecutive_failures() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = Breaker::new(start);
+        assert!(breaker.on_completion(&config, false, start).is_none());
+        assert!(breaker.on_completion(&config, false, start).is_none());
+        // a success breaks the run
+        assert!(breaker.on_completion(&config, true, start).is_none());
+        assert!(breaker.on_completion(&config, false, start).is_none());
+        assert!(breaker.on_completion(&config, false, start).is_none());
+        let reason = opened(breaker.on_completion(&config, false, start)).unwrap();
+        assert_eq!(reason, "3 calls failed in a row");
+        assert!(!breaker.admit(&config, start));
+    }
+
+    #[test]
+    fn open_on_error_rate() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = Breaker::new(start);
+        for _ in 0..2 {
+            assert!(breaker.on_completion(&config, false, start).is_none());
+            assert!(breaker.on_completion(&config, true, start).is_none());
+        }
+        assert!(breaker.on_completion(&config, false, start).is_none());
+        // 3 of 6 calls failed, but a success does not open the breaker
+        assert!(breaker
+            .on_completion(&config, true, ms(start, 10))
+            .is_none());
+
+        let mut breaker = Breaker::new(start);
+        for _ in 0..2 {
+            assert!(breaker.on_completion(&config, true, start).is_none());
+            assert!(breaker.on_completion(&config, false, start).is_none());
+        }
+        assert!(breaker.on_completion(&config, true, start).is_none());
+        let reason = opened(breaker.on_completion(&config, false, ms(start, 10))).unwrap();
+        assert_eq!(reason, "3 of 6 calls failed in 10ms");
+    }
+
+    #[test]
+    fn counts_reset_every_window() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = Breaker::new(start);
+        for _ in 0..2 {
+            assert!(breaker.on_completion(&config, true, start).is_none());
+            assert!(breaker.on_completion(&config, false, start).is_none());
+        }
+        assert!(breaker.on_completion(&config, true, start).is_none());
+        // the failure would be the 3rd of 6 calls, but the window is over
+        let later = ms(start, config.window_ms);
+        assert!(breaker.on_completion(&config, false, later).is_none());
+        assert_eq!((breaker.calls, breaker.failures), (1, 1));
+        assert!(breaker.admit(&config, later));
+    }
+
+    #[test]
+    fn open_fails_fast_until_cooldown() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = Breaker::new(start);
+        trip(&mut breaker, &config, start);
+        assert!(!breaker.admit(&config, start));
+        assert!(!breaker.admit(&config, ms(start, config.cooldown_ms - 1)));
+        // the replies of the calls sent before it opened are ignored
+        assert!(breaker.on_completion(&config, true, start).is_none());
+        assert!(breaker.on_completion(&config, false, start).is_none());
+        assert!(!breaker.admit(&config, ms(start, config.cooldown_ms - 1)));
+
+        // half-open, lets the probes through
+        let cooled = ms(start, config.cooldown_ms);
+        assert!(breaker.admit(&config, cooled));
+        assert!(breaker.admit(&config, cooled));
+        assert!(!breaker.admit(&config, cooled));
+    }
+
+    #[test]
+    fn probe_succeeds() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = Breaker::new(start);
+        trip(&mut breaker, &config, start);
+        let cooled = ms(start, config.cooldown_ms);
+        assert!(breaker.admit(&config, cooled));
+        assert!(closed(breaker.on_completion(&config, true, cooled)));
+        assert!(matches!(breaker.state, State::Closed));
+        // with the counts reset
+        assert!(breaker.admit(&config, cooled));
+        assert!(breaker.on_completion(&config, false, cooled).is_none());
+        assert!(breaker.on_completion(&config, false, cooled).is_none());
+        assert!(opened(breaker.on_completion(&config, false, cooled)).is_some());
+    }
+
+    #[test]
+    fn probe_fails() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = Breaker::new(start);
+        trip(&mut breaker, &config, start);
+        let cooled = ms(start, config.cooldown_ms);
+        assert!(breaker.admit(&config, cooled));
+        let reason = opened(breaker.on_completion(&config, false, cooled)).unwrap();
+        assert_eq!(reason, "a probe call failed");
+        // for another cool-down
+        assert!(!breaker.admit(&config, ms(cooled, config.cooldown_ms - 1)));
+        assert!(breaker.admit(&config, ms(cooled, config.cooldown_ms)));
+    }
+
+    #[test]
+    fn lost_probes() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = Breaker::new(start);
+        trip(&mut breaker, &config, start);
+        let cooled = ms(start, config.cooldown_ms);
+        assert!(breaker.admit(&config, cooled));
+        assert!(breaker.admit(&config, cooled));
+        assert!(!breaker.admit(&config, ms(cooled, config.cooldown_ms - 1)));
+        // the probes never completed, new ones are let through after a cool-down
+        let retried = ms(cooled, config.cooldown_ms);
+        assert!(breaker.admit(&config, retried));
+        assert!(matches!(
+            breaker.state,
+            State::HalfOpen { probes: 1, since } if since == retried
+        ));
+        assert!(breaker.admit(&config, retried));
+        assert!(!breaker.admit(&config, retried));
+    }
+}
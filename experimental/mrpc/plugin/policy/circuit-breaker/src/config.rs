@@ -0,0 +1,84 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// The breaker of a destination opens after this many calls to it fail in a row.
+    #[serde(default = "default_consecutive_failures")]
+    pub consecutive_failures: u32,
+    /// The breaker of a destination opens when this fraction of the calls to it fail in a
+    /// window, ...
+    #[serde(default = "default_error_rate_threshold")]
+    pub error_rate_threshold: f64,
+    /// ... provided the window has at least this many calls.
+    #[serde(default = "default_min_requests")]
+    pub min_requests: u64,
+    /// The calls are counted in windows of this many milliseconds.
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    /// An open breaker fails the calls for this many milliseconds, then lets probe calls
+    /// through.
+    #[serde(default = "default_cooldown_ms")]
+    pub cooldown_ms: u64,
+    /// The probe calls let through at once by a half-open breaker. The breaker closes when one
+    /// succeeds, and opens again when one fails.
+    #[serde(default = "default_half_open_requests")]
+    pub half_open_requests: u32,
+}
+
+fn default_consecutive_failures() -> u32 {
+    5
+}
+
+fn default_error_rate_threshold() -> f64 {
+    0.5
+}
+
+fn default_min_requests() -> u64 {
+    20
+}
+
+fn default_window_ms() -> u64 {
+    10_000
+}
+
+fn default_cooldown_ms() -> u64 {
+    5_000
+}
+
+fn default_half_open_requests() -> u32 {
+    1
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            consecutive_failures: default_consecutive_failures(),
+            error_rate_threshold: default_error_rate_threshold(),
+            min_requests: default_min_requests(),
+            window_ms: default_window_ms(),
+            cooldown_ms: default_cooldown_ms(),
+            half_open_requests: default_half_open_requests(),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config: CircuitBreakerConfig = toml::from_str(config.unwrap_or(""))?;
+        if config.consecutive_failures == 0 {
+            bail!("consecutive_failures must be positive");
+        }
+        if !(config.error_rate_threshold > 0.0 && config.error_rate_threshold <= 1.0) {
+            bail!(
+                "error_rate_threshold {} is not in (0, 1]",
+                config.error_rate_threshold
+            );
+        }
+        if config.window_ms == 0 || config.half_open_requests == 0 {
+            bail!("window_ms and half_open_requests must be positive");
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,264 @@
+//! This engine can only be placed at the sender side for now. It keeps a breaker for each
+//! connection, counting the calls that fail: the replies of status `Unknown`, the requests that
+//! fail in the transport and the errors of the connection. An open breaker fails the requests
+//! to its connection right away, which the mRPC library reports as `Code::Unavailable`, so the
+//! retries of the application do not pile up on a failing destination.
+use std::num::NonZeroU32;
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap;
+use futures::future::BoxFuture;
+use nix::unistd::Pid;
+
+use phoenix_api::rpc::{RpcId, RpcMsgType, StatusCode, TransportStatus};
+use phoenix_api::Handle;
+use phoenix_api_policy_circuit_breaker::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage};
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::event::{self, EventSeverity};
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::breaker::{Breaker, Transition};
+use crate::config::CircuitBreakerConfig;
+
+const EVENT_KIND: &str = "circuit_breaker";
+
+/// The transport status of the requests failed by an open breaker.
+const CIRCUIT_OPEN: TransportStatus =
+    TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(502) });
+
+pub(crate) struct CircuitBreakerEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+    pub(crate) pid: Pid,
+    pub(crate) config: CircuitBreakerConfig,
+    pub(crate) breakers: FnvHashMap<Handle, Breaker>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for CircuitBreakerEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "CircuitBreakerEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::NewConfig(config) => {
+                self.config = CircuitBreakerConfig::new(Some(&config))?;
+            }
+            control_plane::Request::Reset => self.breakers.clear(),
+        }
+        Ok(())
+    }
+}
+
+impl_vertex_for_engine!(CircuitBreakerEngine, node);
+
+impl Decompose for CircuitBreakerEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
+            }
+        }
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        let mut collections = ResourceCollection::with_capacity(3);
+        collections.insert("pid".to_string(), Box::new(engine.pid));
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("breakers".to_string(), Box::new(engine.breakers));
+        (collections, engine.node)
+    }
+}
+
+impl CircuitBreakerEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let pid = *local
+            .remove("pid")
+            .unwrap()
+            .downcast::<Pid>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<CircuitBreakerConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let breakers = *local
+            .remove("breakers")
+            .unwrap()
+            .downcast::<FnvHashMap<Handle, Breaker>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = CircuitBreakerEngine {
+            node,
+            indicator: Default::default(),
+            pid,
+            config,
+            breakers,
+        };
+        Ok(engine)
+    }
+}
+
+impl CircuitBreakerEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+impl CircuitBreakerEngine {
+    fn on_completion(&mut self, conn_id: Handle, success: bool) {
+        let now = Instant::now();
+        let breaker = self
+            .breakers
+            .entry(conn_id)
+            .or_insert_with(|| Breaker::new(now));
+        let transition = breaker.on_completion(&self.config, success, now);
+        let source = || format!("CircuitBreakerEngine pid={}", self.pid);
+        match transition {
+            Some(Transition::Opened(reason)) => {
+                let message = format!(
+                    "breaker of connection {:?} opened for {}ms: {}",
+                    conn_id, self.config.cooldown_ms, reason
+                );
+                event::publish(EventSeverity::Warning, &source(), EVENT_KIND, message);
+            }
+            Some(Transition::Closed) => {
+                let message = format!("breaker of connection {:?} closed", conn_id);
+                event::publish(EventSeverity::Info, &source(), EVENT_KIND, message);
+            }
+            None => {}
+        }
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            let admitted = meta.msg_type != RpcMsgType::Request
+                                || self
+                                    .breakers
+                                    .get_mut(&meta.conn_id)
+                                    .map_or(true, |breaker| {
+                                        breaker.admit(&self.config, Instant::now())
+                                    });
+                            if admitted {
+                                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                            } else {
+                                // fail the request right away, the frontend reclaims its buffer
+                                // on the ack
+                                let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                                self.rx_outputs()[0]
+                                    .send(EngineRxMessage::Ack(rpc_id, CIRCUIT_OPEN))?;
+                            }
+                        }
+                        m => self.tx_outputs()[0].send(m)?,
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineRxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            if meta.msg_type == RpcMsgType::Response {
+                                // the other errors are the faults of the caller, and tell that the
+                                // destination is alive
+                                let success = meta.status_code != StatusCode::Unknown;
+                                self.on_completion(meta.conn_id, success);
+                            }
+                            self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                        }
+                        EngineRxMessage::Ack(rpc_id, status) => {
+                            // a request that fails to be sent never gets its reply, except those
+                            // denied or too large
+                            if let TransportStatus::Error(code) = status {
+                                if !matches!(code.get(), 402 | 413 | 414) {
+                                    self.on_completion(rpc_id.0, false);
+                                }
+                            }
+                            self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                        }
+                        EngineRxMessage::RecvError(conn_id, status) => {
+                            self.on_completion(conn_id, false);
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::RecvError(conn_id, status))?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        Ok(Progress(0))
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::CircuitBreakerEngine;
+use crate::config::CircuitBreakerConfig;
+
+pub(crate) struct CircuitBreakerEngineBuilder {
+    node: DataPathNode,
+    pid: Pid,
+    config: CircuitBreakerConfig,
+}
+
+impl CircuitBreakerEngineBuilder {
+    fn new(node: DataPathNode, pid: Pid, config: CircuitBreakerConfig) -> Self {
+        CircuitBreakerEngineBuilder { node, pid, config }
+    }
+
+    fn build(self) -> Result<CircuitBreakerEngine> {
+        Ok(CircuitBreakerEngine {
+            node: self.node,
+            indicator: Default::default(),
+            pid: self.pid,
+            config: self.config,
+            breakers: Default::default(),
+        })
+    }
+}
+
+pub struct CircuitBreakerAddon {
+    config: CircuitBreakerConfig,
+}
+
+impl CircuitBreakerAddon {
+    pub const CIRCUIT_BREAKER_ENGINE: EngineType = EngineType("CircuitBreakerEngine");
+    pub const ENGINES: &'static [EngineType] = &[CircuitBreakerAddon::CIRCUIT_BREAKER_ENGINE];
+}
+
+impl CircuitBreakerAddon {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        CircuitBreakerAddon { config }
+    }
+}
+
+impl PhoenixAddon for CircuitBreakerAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        CircuitBreakerAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = CircuitBreakerConfig::new(Some(config))?;
+        Ok(())
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != CircuitBreakerAddon::CIRCUIT_BREAKER_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = CircuitBreakerEngineBuilder::new(node, pid, self.config);
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != CircuitBreakerAddon::CIRCUIT_BREAKER_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = CircuitBreakerEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
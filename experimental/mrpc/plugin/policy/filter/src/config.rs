@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// What to do with the requests that a rule matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Send the request on.
+    Pass,
+    /// Drop the request and fail it with the status of the config.
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRule {
+    /// Match only the requests of this service, by its ID.
+    #[serde(default)]
+    pub service_id: Option<u32>,
+    /// Match only the requests of this function, by its ID.
+    #[serde(default)]
+    pub func_id: Option<u32>,
+    pub action: FilterAction,
+}
+
+impl FilterRule {
+    fn matches(&self, service_id: u32, func_id: u32) -> bool {
+        self.service_id.map_or(true, |id| id == service_id)
+            && self.func_id.map_or(true, |id| id == func_id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// The first rule that matches a request decides what to do with it, the requests that no
+    /// rule matches are passed.
+    #[serde(default)]
+    pub rules: Vec<FilterRule>,
+    /// The transport status the rejected requests fail with.
+    #[serde(default = "default_status")]
+    pub status: u32,
+}
+
+fn default_status() -> u32 {
+    403
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            rules: Vec::new(),
+            status: default_status(),
+        }
+    }
+}
+
+impl FilterConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config: FilterConfig = toml::from_str(config.unwrap_or(""))?;
+        if config.status == 0 {
+            anyhow::bail!("the status of the rejected requests cannot be 0");
+        }
+        Ok(config)
+    }
+
+    /// What to do with a request of `service_id` and `func_id`.
+    pub(crate) fn action(&self, service_id: u32, func_id: u32) -> FilterAction {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(service_id, func_id))
+            .map_or(FilterAction::Pass, |rule| rule.action)
+    }
+}
@@ -0,0 +1,183 @@
+//! This engine can only be placed at the sender side for now.
+use std::num::NonZeroU32;
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{RpcId, TransportStatus};
+use phoenix_api_policy_filter::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage};
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::config::{FilterAction, FilterConfig};
+
+pub(crate) struct FilterEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+    pub(crate) config: FilterConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for FilterEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "FilterEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::NewConfig(config) => {
+                self.config = FilterConfig::new(Some(&config))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_vertex_for_engine!(FilterEngine, node);
+
+impl Decompose for FilterEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
+            }
+        }
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        let mut collections = ResourceCollection::with_capacity(1);
+        collections.insert("config".to_string(), Box::new(engine.config));
+        (collections, engine.node)
+    }
+}
+
+impl FilterEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<FilterConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = FilterEngine {
+            node,
+            indicator: Default::default(),
+            config,
+        };
+        Ok(engine)
+    }
+}
+
+impl FilterEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+            // If there's pending receives, there will always be future work to do.
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+impl FilterEngine {
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            match self.config.action(meta.service_id, meta.func_id) {
+                                FilterAction::Pass => {
+                                    self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                                }
+                                FilterAction::Reject => {
+                                    // fail the request right away, the frontend reclaims its buffer
+                                    // on the ack
+                                    let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                                    // the status has been checked to be non-zero with the config
+                                    let status = NonZeroU32::new(self.config.status).unwrap();
+                                    self.rx_outputs()[0].send(EngineRxMessage::Ack(
+                                        rpc_id,
+                                        TransportStatus::Error(status),
+                                    ))?;
+                                }
+                            }
+                        }
+                        m => self.tx_outputs()[0].send(m)?,
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        // forward all rx msgs
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(m) => {
+                    self.rx_outputs()[0].send(m)?;
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        Ok(Progress(0))
+    }
+}
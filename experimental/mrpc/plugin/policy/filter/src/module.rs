@@ -0,0 +1,99 @@
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::FilterEngine;
+use crate::config::FilterConfig;
+
+pub(crate) struct FilterEngineBuilder {
+    node: DataPathNode,
+    config: FilterConfig,
+}
+
+impl FilterEngineBuilder {
+    fn new(node: DataPathNode, config: FilterConfig) -> Self {
+        FilterEngineBuilder { node, config }
+    }
+
+    fn build(self) -> Result<FilterEngine> {
+        Ok(FilterEngine {
+            node: self.node,
+            indicator: Default::default(),
+            config: self.config,
+        })
+    }
+}
+
+pub struct FilterAddon {
+    config: FilterConfig,
+}
+
+impl FilterAddon {
+    pub const FILTER_ENGINE: EngineType = EngineType("FilterEngine");
+    pub const ENGINES: &'static [EngineType] = &[FilterAddon::FILTER_ENGINE];
+}
+
+impl FilterAddon {
+    pub fn new(config: FilterConfig) -> Self {
+        FilterAddon { config }
+    }
+}
+
+impl PhoenixAddon for FilterAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        FilterAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = FilterConfig::new(Some(config))?;
+        Ok(())
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        _pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != FilterAddon::FILTER_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = FilterEngineBuilder::new(node, self.config.clone());
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != FilterAddon::FILTER_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = FilterEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
@@ -0,0 +1,72 @@
+//! The hedging budget of the calls of an application, which bounds the extra load of the
+//! duplicates: the calls add to it and every duplicate spends one.
+use crate::config::HedgingConfig;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HedgeBudget {
+    tokens: f64,
+}
+
+impl HedgeBudget {
+    #[inline]
+    pub(crate) fn tokens(&self) -> f64 {
+        self.tokens
+    }
+
+    #[inline]
+    pub(crate) fn deposit(&mut self, config: &HedgingConfig) {
+        self.tokens = (self.tokens + config.budget_ratio).min(config.max_budget);
+    }
+
+    /// Spends a duplicate, returns false if there is none left.
+    #[inline]
+    pub(crate) fn withdraw(&mut self) -> bool {
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_fund_duplicates() {
+        let config = HedgingConfig {
+            budget_ratio: 0.25,
+            max_budget: 2.0,
+            ..Default::default()
+        };
+        let mut budget = HedgeBudget::default();
+        assert!(!budget.withdraw());
+        for _ in 0..3 {
+            budget.deposit(&config);
+        }
+        assert!(!budget.withdraw());
+        budget.deposit(&config);
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+        assert_eq!(budget.tokens(), 0.0);
+    }
+
+    #[test]
+    fn capped() {
+        let config = HedgingConfig {
+            budget_ratio: 0.5,
+            max_budget: 2.0,
+            ..Default::default()
+        };
+        let mut budget = HedgeBudget::default();
+        for _ in 0..100 {
+            budget.deposit(&config);
+        }
+        assert_eq!(budget.tokens(), 2.0);
+        assert!(budget.withdraw());
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+    }
+}
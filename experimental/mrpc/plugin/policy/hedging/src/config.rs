@@ -0,0 +1,130 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+/// A method whose slow calls are duplicated.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HedgedMethod {
+    /// Match only the calls of this service, by its ID.
+    #[serde(default)]
+    pub service_id: Option<u32>,
+    /// Match only the calls of this function, by its ID.
+    #[serde(default)]
+    pub func_id: Option<u32>,
+}
+
+impl HedgedMethod {
+    fn matches(&self, service_id: u32, func_id: u32) -> bool {
+        self.service_id.map_or(true, |id| id == service_id)
+            && self.func_id.map_or(true, |id| id == func_id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HedgingConfig {
+    /// Only the calls of these methods are hedged, they must be idempotent.
+    #[serde(default)]
+    pub methods: Vec<HedgedMethod>,
+    /// A call is duplicated once it takes longer than this percentile of the latency of its
+    /// method.
+    #[serde(default = "default_percentile")]
+    pub percentile: f64,
+    /// The latencies of the recent calls of a method the percentile is taken over.
+    #[serde(default = "default_window")]
+    pub window: usize,
+    /// The calls of a method are not hedged before it has that many samples.
+    #[serde(default = "default_min_samples")]
+    pub min_samples: usize,
+    /// The hedging delay is never shorter than that.
+    #[serde(default = "default_min_delay_us")]
+    pub min_delay_us: u64,
+    /// Each call adds this fraction of a duplicate to the budget, ...
+    #[serde(default = "default_budget_ratio")]
+    pub budget_ratio: f64,
+    /// ... which holds at most this many.
+    #[serde(default = "default_max_budget")]
+    pub max_budget: f64,
+    /// The reply of an attempt that lost is waited for this long, in milliseconds, to be dropped.
+    #[serde(default = "default_loser_ttl_ms")]
+    pub loser_ttl_ms: u64,
+    /// At most this many replies of the attempts that lost are waited for.
+    #[serde(default = "default_max_losers")]
+    pub max_losers: usize,
+}
+
+fn default_percentile() -> f64 {
+    0.95
+}
+
+fn default_window() -> usize {
+    128
+}
+
+fn default_min_samples() -> usize {
+    20
+}
+
+fn default_min_delay_us() -> u64 {
+    50
+}
+
+fn default_budget_ratio() -> f64 {
+    0.05
+}
+
+fn default_max_budget() -> f64 {
+    20.0
+}
+
+fn default_loser_ttl_ms() -> u64 {
+    10_000
+}
+
+fn default_max_losers() -> usize {
+    4096
+}
+
+impl Default for HedgingConfig {
+    fn default() -> Self {
+        HedgingConfig {
+            methods: Vec::new(),
+            percentile: default_percentile(),
+            window: default_window(),
+            min_samples: default_min_samples(),
+            min_delay_us: default_min_delay_us(),
+            budget_ratio: default_budget_ratio(),
+            max_budget: default_max_budget(),
+            loser_ttl_ms: default_loser_ttl_ms(),
+            max_losers: default_max_losers(),
+        }
+    }
+}
+
+impl HedgingConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config: HedgingConfig = toml::from_str(config.unwrap_or(""))?;
+        if !(config.percentile > 0.0 && config.percentile < 1.0) {
+            bail!("percentile must be in (0, 1), got {}", config.percentile);
+        }
+        if config.min_samples == 0 || config.min_samples > config.window {
+            bail!("min_samples must be positive and at most the window");
+        }
+        if config.max_losers == 0 {
+            bail!("max_losers must be positive");
+        }
+        for value in [config.budget_ratio, config.max_budget] {
+            if !(value >= 0.0 && value.is_finite()) {
+                bail!("the budget settings must be non-negative, got {}", value);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Whether the calls of `service_id` and `func_id` are hedged.
+    pub(crate) fn is_hedged(&self, service_id: u32, func_id: u32) -> bool {
+        self.methods
+            .iter()
+            .any(|method| method.matches(service_id, func_id))
+    }
+}
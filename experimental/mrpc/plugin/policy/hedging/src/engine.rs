@@ -0,0 +1,629 @@
+//! This engine can only be placed at the sender side, right above the RpcAdapter. A call of a
+//! hedged method that has not got its reply after the latency percentile of its method is sent
+//! once more, on another connection that the same service has replied on. The first reply is
+//! passed to the frontend and that of the other attempt is dropped when it arrives. The
+//! acknowledgement of a request is held until the call completes, so that the frontend keeps
+//! the request around for the duplicate.
+//!
+//! A duplicate gets a call ID of its own, in the upper half of the range, and its reply is
+//! rewritten to that of the original call. Sending a duplicate counts a hop of the request, and a
+//! request with no hop left is not duplicated.
+//!
+//! There is no way to cancel a request that has been sent, so the attempt that loses is reclaimed
+//! instead: its reply is dropped and its receive buffer given back when it arrives. The losers
+//! are forgotten after `loser_ttl_ms`, or when more than `max_losers` wait. The late reply of a
+//! forgotten duplicate is still dropped, by its call ID, while that of a forgotten original
+//! request is passed to the frontend, which has completed the call and drops it.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap;
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{CallId, RpcId, RpcMsgType, StatusCode, TransportStatus};
+use phoenix_api::Handle;
+use phoenix_api_mrpc::dp::RECV_RECLAIM_BS;
+use phoenix_api_policy_hedging::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPtr;
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::metrics;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::budget::HedgeBudget;
+use crate::config::HedgingConfig;
+use crate::estimate::LatencyEstimate;
+use crate::losers::Losers;
+
+/// The first call ID of the duplicates.
+pub(crate) const ALTERNATE_CALL_ID_BASE: u64 = 1 << 63;
+
+/// When to duplicate a call: (deadline, conn_id, call_id).
+type Deadline = Reverse<(Instant, u64, u64)>;
+
+/// A send of a call, the original request or its duplicate.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Attempt {
+    id: RpcId,
+    acked: bool,
+    /// The attempt has got its reply, or never will.
+    done: bool,
+}
+
+impl Attempt {
+    fn new(id: RpcId) -> Self {
+        Attempt {
+            id,
+            acked: false,
+            done: false,
+        }
+    }
+}
+
+/// A call of a hedged method that has not completed yet.
+#[derive(Debug)]
+pub(crate) struct Inflight {
+    meta_buf_ptr: MetaBufferPtr,
+    addr_backend: usize,
+    service_id: u32,
+    func_id: u32,
+    sent_at: Instant,
+    primary: Attempt,
+    hedge: Option<Attempt>,
+    /// The first reply has been passed to the frontend.
+    replied: bool,
+    /// The first error of the attempts.
+    error: Option<TransportStatus>,
+}
+
+impl Inflight {
+    fn attempts(&self) -> impl Iterator<Item = &Attempt> {
+        std::iter::once(&self.primary).chain(self.hedge.as_ref())
+    }
+
+    fn attempt_mut(&mut self, id: RpcId) -> Option<&mut Attempt> {
+        if self.primary.id == id {
+            Some(&mut self.primary)
+        } else {
+            self.hedge.as_mut().filter(|attempt| attempt.id == id)
+        }
+    }
+
+    /// Gives up the attempts on `conn_id`, returns whether there was any.
+    fn lose_conn(&mut self, conn_id: Handle, status: TransportStatus) -> bool {
+        let mut lost = false;
+        for attempt in std::iter::once(&mut self.primary).chain(self.hedge.as_mut()) {
+            if attempt.id.0 == conn_id && !attempt.done {
+                attempt.done = true;
+                lost = true;
+            }
+        }
+        if lost {
+            self.error.get_or_insert(status);
+        }
+        lost
+    }
+
+    /// All the sends are acknowledged, and either a reply has arrived or none will.
+    fn is_complete(&self) -> bool {
+        self.attempts().all(|attempt| attempt.acked)
+            && (self.replied || self.attempts().all(|attempt| attempt.done))
+    }
+}
+
+pub(crate) struct HedgingEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+    pub(crate) config: HedgingConfig,
+    pub(crate) budget: HedgeBudget,
+    /// The calls of the hedged methods, by their original ID.
+    pub(crate) inflight: FnvHashMap<RpcId, Inflight>,
+    pub(crate) deadlines: BinaryHeap<Deadline>,
+    /// The duplicates, to the original ID of their calls.
+    pub(crate) aliases: FnvHashMap<RpcId, RpcId>,
+    /// The attempts whose replies are dropped, as the other attempt replied first.
+    pub(crate) losers: Losers,
+    /// The receive buffers of the rewritten replies, from the original ID to that of the
+    /// duplicate.
+    pub(crate) reclaims: FnvHashMap<RpcId, RpcId>,
+    /// The connections that have replied for each service.
+    pub(crate) servers: FnvHashMap<u32, Vec<Handle>>,
+    /// The latency of each method, by (service_id, func_id).
+    pub(crate) estimates: FnvHashMap<(u32, u32), LatencyEstimate>,
+    pub(crate) next_call_id: u64,
+    pub(crate) stats: control_plane::HedgingStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for HedgingEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "HedgingEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::NewConfig(config) => {
+                self.config = HedgingConfig::new(Some(&config))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_query(&mut self, query: Vec<u8>, _cred: UCred) -> Result<Vec<u8>> {
+        let query: control_plane::Query = bincode::deserialize(&query[..])?;
+
+        let response = match query {
+            control_plane::Query::Stats => {
+                let mut stats = self.stats.clone();
+                stats.budget = self.budget.tokens();
+                stats.delays = self
+                    .estimates
+                    .iter()
+                    .map(
+                        |(&(service_id, func_id), estimate)| control_plane::MethodDelay {
+                            service_id,
+                            func_id,
+                            delay_us: estimate
+                                .delay(&self.config)
+                                .map(|delay| delay.as_micros() as u64),
+                        },
+                    )
+                    .collect();
+                control_plane::QueryResponse::Stats(stats)
+            }
+        };
+        Ok(bincode::serialize(&response)?)
+    }
+}
+
+impl_vertex_for_engine!(HedgingEngine, node);
+
+impl Decompose for HedgingEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
+            }
+        }
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        let mut collections = ResourceCollection::with_capacity(11);
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("budget".to_string(), Box::new(engine.budget));
+        collections.insert("inflight".to_string(), Box::new(engine.inflight));
+        collections.insert("deadlines".to_string(), Box::new(engine.deadlines));
+        collections.insert("aliases".to_string(), Box::new(engine.aliases));
+        collections.insert("losers".to_string(), Box::new(engine.losers));
+        collections.insert("reclaims".to_string(), Box::new(engine.reclaims));
+        collections.insert("servers".to_string(), Box::new(engine.servers));
+        collections.insert("estimates".to_string(), Box::new(engine.estimates));
+        collections.insert("next_call_id".to_string(), Box::new(engine.next_call_id));
+        collections.insert("stats".to_string(), Box::new(engine.stats));
+        (collections, engine.node)
+    }
+}
+
+impl HedgingEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<HedgingConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let budget = *local
+            .remove("budget")
+            .unwrap()
+            .downcast::<HedgeBudget>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let inflight = *local
+            .remove("inflight")
+            .unwrap()
+            .downcast::<FnvHashMap<RpcId, Inflight>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let deadlines = *local
+            .remove("deadlines")
+            .unwrap()
+            .downcast::<BinaryHeap<Deadline>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let aliases = *local
+            .remove("aliases")
+            .unwrap()
+            .downcast::<FnvHashMap<RpcId, RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let losers = *local
+            .remove("losers")
+            .unwrap()
+            .downcast::<Losers>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let reclaims = *local
+            .remove("reclaims")
+            .unwrap()
+            .downcast::<FnvHashMap<RpcId, RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let servers = *local
+            .remove("servers")
+            .unwrap()
+            .downcast::<FnvHashMap<u32, Vec<Handle>>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let estimates = *local
+            .remove("estimates")
+            .unwrap()
+            .downcast::<FnvHashMap<(u32, u32), LatencyEstimate>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let next_call_id = *local
+            .remove("next_call_id")
+            .unwrap()
+            .downcast::<u64>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let stats = *local
+            .remove("stats")
+            .unwrap()
+            .downcast::<control_plane::HedgingStats>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = HedgingEngine {
+            node,
+            indicator: Default::default(),
+            config,
+            budget,
+            inflight,
+            deadlines,
+            aliases,
+            losers,
+            reclaims,
+            servers,
+            estimates,
+            next_call_id,
+            stats,
+        };
+        Ok(engine)
+    }
+}
+
+impl HedgingEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+            work += self.check_deadlines()?;
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+impl HedgingEngine {
+    fn check_deadlines(&mut self) -> Result<usize, DatapathError> {
+        let mut work = 0;
+        if self.deadlines.is_empty() && self.losers.is_empty() {
+            return Ok(work);
+        }
+        let now = Instant::now();
+        self.stats.losers_expired += self.losers.expire(now, &self.config) as u64;
+        while let Some(&Reverse((deadline, conn_id, call_id))) = self.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop();
+            work += self.hedge(RpcId::new(Handle(conn_id), CallId(call_id)))?;
+        }
+        Ok(work)
+    }
+
+    /// Duplicates the call of `orig` on another connection if it still waits for its reply.
+    fn hedge(&mut self, orig: RpcId) -> Result<usize, DatapathError> {
+        let call = match self.inflight.get(&orig) {
+            Some(call) => call,
+            None => return Ok(0),
+        };
+        // the meta buffer is rewritten for the duplicate, so the request must have left
+        if call.replied || call.hedge.is_some() || !call.primary.acked || call.primary.done {
+            return Ok(0);
+        }
+
+        let alternate = self.servers.get(&call.service_id).and_then(|conns| {
+            let count = conns.iter().filter(|&&conn| conn != orig.0).count();
+            conns
+                .iter()
+                .filter(|&&conn| conn != orig.0)
+                .nth((self.stats.hedges as usize).checked_rem(count)?)
+                .copied()
+        });
+        let conn_id = match alternate {
+            Some(conn_id) => conn_id,
+            None => {
+                self.stats.no_alternate += 1;
+                return Ok(0);
+            }
+        };
+        if !self.budget.withdraw() {
+            self.stats.budget_exhausted += 1;
+            return Ok(0);
+        }
+        // SAFETY: the frontend keeps the meta buffer until the call is acknowledged
+        let header = unsafe { &mut (*call.meta_buf_ptr.0.as_ptr()).header };
+        if !header.forward() {
+            metrics::record_expired_message(&format!("hedging, call_id={}", orig.1));
+            self.stats.hops_exhausted += 1;
+            return Ok(0);
+        }
+
+        let id = RpcId::new(conn_id, CallId(self.next_call_id));
+        self.next_call_id = self.next_call_id.wrapping_add(1) | ALTERNATE_CALL_ID_BASE;
+        // SAFETY: the frontend keeps the meta buffer until the call is acknowledged
+        let meta = unsafe { &mut *call.meta_buf_ptr.as_meta_ptr() };
+        meta.conn_id = id.0;
+        meta.call_id = id.1;
+        let msg = RpcMessageTx {
+            meta_buf_ptr: call.meta_buf_ptr,
+            addr_backend: call.addr_backend,
+        };
+
+        self.inflight.get_mut(&orig).unwrap().hedge = Some(Attempt::new(id));
+        self.aliases.insert(id, orig);
+        self.stats.hedges += 1;
+        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        Ok(1)
+    }
+
+    /// Acknowledges the call of `orig` to the frontend once it is complete.
+    fn try_complete(&mut self, orig: RpcId) -> Result<(), DatapathError> {
+        if !self
+            .inflight
+            .get(&orig)
+            .map_or(false, Inflight::is_complete)
+        {
+            return Ok(());
+        }
+        let call = self.inflight.remove(&orig).unwrap();
+        for attempt in call.attempts() {
+            if !attempt.done {
+                let forgotten = self.losers.insert(attempt.id, Instant::now(), &self.config);
+                self.stats.losers_expired += forgotten as u64;
+            }
+        }
+        if let Some(hedge) = call.hedge {
+            self.aliases.remove(&hedge.id);
+        }
+        let status = if call.replied {
+            TransportStatus::Success
+        } else {
+            call.error.unwrap_or(TransportStatus::Success)
+        };
+        self.rx_outputs()[0].send(EngineRxMessage::Ack(orig, status))?;
+        Ok(())
+    }
+
+    /// Drops a reply, giving its receive buffer back.
+    fn reclaim(&mut self, id: RpcId) -> Result<(), DatapathError> {
+        let call_ids = [id.1; RECV_RECLAIM_BS];
+        self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(id.0, call_ids))?;
+        Ok(())
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            if meta.msg_type == RpcMsgType::Request
+                                && self.config.is_hedged(meta.service_id, meta.func_id)
+                            {
+                                let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                                let now = Instant::now();
+                                self.budget.deposit(&self.config);
+                                self.stats.calls += 1;
+                                let delay = self
+                                    .estimates
+                                    .get(&(meta.service_id, meta.func_id))
+                                    .and_then(|estimate| estimate.delay(&self.config));
+                                if let Some(delay) = delay {
+                                    let deadline = (now + delay, rpc_id.0 .0, rpc_id.1 .0);
+                                    self.deadlines.push(Reverse(deadline));
+                                }
+                                let call = Inflight {
+                                    meta_buf_ptr: msg.meta_buf_ptr,
+                                    addr_backend: msg.addr_backend,
+                                    service_id: meta.service_id,
+                                    func_id: meta.func_id,
+                                    sent_at: now,
+                                    primary: Attempt::new(rpc_id),
+                                    hedge: None,
+                                    replied: false,
+                                    error: None,
+                                };
+                                self.inflight.insert(rpc_id, call);
+                            }
+                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                        }
+                        EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
+                            // the buffer of a rewritten reply belongs to the connection of the
+                            // duplicate
+                            let msg = match self.reclaims.remove(&RpcId::new(conn_id, call_ids[0]))
+                            {
+                                Some(sent) => EngineTxMessage::ReclaimRecvBuf(
+                                    sent.0,
+                                    call_ids.map(|_| sent.1),
+                                ),
+                                None => EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids),
+                            };
+                            self.tx_outputs()[0].send(msg)?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineRxMessage::RpcMessage(msg) => {
+                            let meta = unsafe { &mut *msg.meta.as_ptr() };
+                            let id = RpcId::new(meta.conn_id, meta.call_id);
+                            if meta.msg_type != RpcMsgType::Response {
+                                self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                                return Ok(Progress(1));
+                            }
+                            if self.losers.remove(&id) {
+                                self.reclaim(id)?;
+                                return Ok(Progress(1));
+                            }
+
+                            let orig = match self.aliases.get(&id) {
+                                Some(&orig) => orig,
+                                // a duplicate that lost, and was forgotten before its reply
+                                None if id.1 .0 & ALTERNATE_CALL_ID_BASE != 0 => {
+                                    self.stats.late_replies += 1;
+                                    self.reclaim(id)?;
+                                    return Ok(Progress(1));
+                                }
+                                None => id,
+                            };
+                            let call = match self.inflight.get_mut(&orig) {
+                                Some(call) if call.attempt_mut(id).is_some() => call,
+                                _ => {
+                                    self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                                    return Ok(Progress(1));
+                                }
+                            };
+                            call.attempt_mut(id).unwrap().done = true;
+                            if call.replied {
+                                // the other attempt replied first
+                                self.reclaim(id)?;
+                                self.try_complete(orig)?;
+                                return Ok(Progress(1));
+                            }
+
+                            call.replied = true;
+                            let latency = call.sent_at.elapsed();
+                            self.estimates
+                                .entry((call.service_id, call.func_id))
+                                .or_default()
+                                .record(latency, &self.config);
+                            if meta.status_code != StatusCode::Unknown {
+                                let servers = self.servers.entry(call.service_id).or_default();
+                                if !servers.contains(&id.0) {
+                                    servers.push(id.0);
+                                }
+                            }
+                            if id != orig {
+                                meta.conn_id = orig.0;
+                                meta.call_id = orig.1;
+                                self.reclaims.insert(orig, id);
+                                self.stats.hedge_wins += 1;
+                            }
+                            self.try_complete(orig)?;
+                            self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                        }
+                        EngineRxMessage::Ack(rpc_id, status) => {
+                            let orig = self.aliases.get(&rpc_id).copied().unwrap_or(rpc_id);
+                            let attempt = self
+                                .inflight
+                                .get_mut(&orig)
+                                .and_then(|call| call.attempt_mut(rpc_id));
+                            match attempt {
+                                Some(attempt) => {
+                                    attempt.acked = true;
+                                    if let TransportStatus::Error(_) = status {
+                                        attempt.done = true;
+                                        let call = self.inflight.get_mut(&orig).unwrap();
+                                        call.error.get_or_insert(status);
+                                    }
+                                    self.try_complete(orig)?;
+                                }
+                                None => {
+                                    self.rx_outputs()[0]
+                                        .send(EngineRxMessage::Ack(rpc_id, status))?;
+                                }
+                            }
+                        }
+                        EngineRxMessage::RecvError(conn_id, status) => {
+                            // the attempts on the connection never get their replies
+                            let lost: Vec<RpcId> = self
+                                .inflight
+                                .iter_mut()
+                                .filter_map(|(orig, call)| {
+                                    call.lose_conn(conn_id, status).then_some(*orig)
+                                })
+                                .collect();
+                            for orig in lost {
+                                self.try_complete(orig)?;
+                            }
+                            self.losers.remove_conn(conn_id);
+                            for servers in self.servers.values_mut() {
+                                servers.retain(|&conn| conn != conn_id);
+                            }
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::RecvError(conn_id, status))?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        Ok(Progress(0))
+    }
+}
@@ -0,0 +1,119 @@
+//! The latency percentile of a method, taken over its recent calls.
+use std::time::Duration;
+
+use crate::config::HedgingConfig;
+
+/// The percentile is taken again after that many samples.
+const REFRESH_SAMPLES: usize = 16;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LatencyEstimate {
+    /// The ring of the recent latencies.
+    samples: Vec<Duration>,
+    next: usize,
+    fresh: usize,
+    percentile: Option<Duration>,
+    scratch: Vec<Duration>,
+}
+
+impl LatencyEstimate {
+    pub(crate) fn record(&mut self, latency: Duration, config: &HedgingConfig) {
+        if self.samples.len() < config.window {
+            self.samples.push(latency);
+        } else {
+            // the window may have shrunk with a new config
+            self.samples.truncate(config.window);
+            self.samples[self.next % config.window] = latency;
+        }
+        self.next = (self.next + 1) % config.window;
+        self.fresh += 1;
+
+        if self.samples.len() >= config.min_samples && self.fresh >= REFRESH_SAMPLES {
+            self.fresh = 0;
+            self.scratch.clear();
+            self.scratch.extend_from_slice(&self.samples);
+            let rank = ((self.scratch.len() as f64 * config.percentile) as usize)
+                .min(self.scratch.len() - 1);
+            let (_, nth, _) = self.scratch.select_nth_unstable(rank);
+            self.percentile = Some(*nth);
+        }
+    }
+
+    /// How long a call waits for its reply before it is duplicated.
+    #[inline]
+    pub(crate) fn delay(&self, config: &HedgingConfig) -> Option<Duration> {
+        self.percentile
+            .map(|p| p.max(Duration::from_micros(config.min_delay_us)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn us(n: u64) -> Duration {
+        Duration::from_micros(n)
+    }
+
+    #[test]
+    fn hedges_after_p95() {
+        let config = HedgingConfig {
+            min_delay_us: 10,
+            ..Default::default()
+        };
+        let mut estimate = LatencyEstimate::default();
+        // the latencies are 100us to 10ms, recorded out of order
+        for i in 0..100 {
+            estimate.record(us((i * 37 % 100 + 1) * 100), &config);
+        }
+        assert_eq!(estimate.delay(&config), Some(us(9600)));
+    }
+
+    #[test]
+    fn not_before_min_samples() {
+        let config = HedgingConfig {
+            min_samples: 20,
+            ..Default::default()
+        };
+        let mut estimate = LatencyEstimate::default();
+        for _ in 0..19 {
+            estimate.record(us(1000), &config);
+        }
+        assert_eq!(estimate.delay(&config), None);
+        estimate.record(us(1000), &config);
+        assert_eq!(estimate.delay(&config), Some(us(1000)));
+    }
+
+    #[test]
+    fn at_least_min_delay() {
+        let config = HedgingConfig {
+            min_delay_us: 50,
+            ..Default::default()
+        };
+        let mut estimate = LatencyEstimate::default();
+        for _ in 0..config.min_samples {
+            estimate.record(us(5), &config);
+        }
+        assert_eq!(estimate.delay(&config), Some(us(50)));
+    }
+
+    #[test]
+    fn follows_recent_calls() {
+        let config = HedgingConfig {
+            window: 32,
+            min_samples: 16,
+            min_delay_us: 1,
+            ..Default::default()
+        };
+        let mut estimate = LatencyEstimate::default();
+        for _ in 0..32 {
+            estimate.record(us(100), &config);
+        }
+        assert_eq!(estimate.delay(&config), Some(us(100)));
+        // the window is taken over by the slower calls
+        for _ in 0..32 {
+            estimate.record(us(900), &config);
+        }
+        assert_eq!(estimate.delay(&config), Some(us(900)));
+    }
+}
@@ -0,0 +1,159 @@
+//! The attempts that lost to the other attempt of their calls. Their replies are dropped when
+//! they arrive, and as a reply may never arrive, a loser is forgotten after `loser_ttl_ms`, or
+//! when more than `max_losers` are waiting.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+
+use phoenix_api::rpc::RpcId;
+use phoenix_api::Handle;
+
+use crate::config::HedgingConfig;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Losers {
+    /// The losers, to when they lost.
+    ids: FnvHashMap<RpcId, Instant>,
+    /// The losers in the order they lost, including some whose replies have arrived since.
+    order: VecDeque<(Instant, RpcId)>,
+}
+
+impl Losers {
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Records that `id` lost at `now`. Returns the number of losers forgotten to make room.
+    pub(crate) fn insert(&mut self, id: RpcId, now: Instant, config: &HedgingConfig) -> usize {
+        self.ids.insert(id, now);
+        self.order.push_back((now, id));
+        let mut forgotten = 0;
+        while self.ids.len() > config.max_losers {
+            forgotten += self.pop_front();
+        }
+        // drop the entries of the replies that arrived, so that they do not pile up either
+        if self.order.len() > 2 * config.max_losers {
+            let ids = &self.ids;
+            self.order
+                .retain(|(lost_at, id)| ids.get(id) == Some(lost_at));
+        }
+        forgotten
+    }
+
+    /// Takes the loser `id` whose reply has arrived. Returns false if it is not a loser.
+    #[inline]
+    pub(crate) fn remove(&mut self, id: &RpcId) -> bool {
+        self.ids.remove(id).is_some()
+    }
+
+    /// Forgets the losers that lost `loser_ttl_ms` before `now`. Returns how many.
+    pub(crate) fn expire(&mut self, now: Instant, config: &HedgingConfig) -> usize {
+        let ttl = Duration::from_millis(config.loser_ttl_ms);
+        let mut forgotten = 0;
+        while let Some(&(lost_at, _)) = self.order.front() {
+            if now.saturating_duration_since(lost_at) < ttl {
+                break;
+            }
+            forgotten += self.pop_front();
+        }
+        forgotten
+    }
+
+    /// Forgets the losers on a connection that is gone.
+    pub(crate) fn remove_conn(&mut self, conn_id: Handle) {
+        self.ids.retain(|id, _| id.0 != conn_id);
+        self.order.retain(|(_, id)| id.0 != conn_id);
+    }
+
+    /// Pops the oldest entry, returns 1 if it was still a loser.
+    fn pop_front(&mut self) -> usize {
+        match self.order.pop_front() {
+            // the entry is stale if the id lost again since, only the latest entry counts
+            Some((lost_at, id)) if self.ids.get(&id) == Some(&lost_at) => {
+                self.ids.remove(&id);
+                1
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phoenix_api::rpc::CallId;
+
+    fn id(conn_id: u64, call_id: u64) -> RpcId {
+        RpcId::new(Handle(conn_id), CallId(call_id))
+    }
+
+    fn config(max_losers: usize, loser_ttl_ms: u64) -> HedgingConfig {
+        HedgingConfig {
+            max_losers,
+            loser_ttl_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reply_takes_loser() {
+        let config = config(4, 1000);
+        let mut losers = Losers::default();
+        losers.insert(id(1, 1), Instant::now(), &config);
+        assert!(losers.remove(&id(1, 1)));
+        assert!(!losers.remove(&id(1, 1)));
+        assert!(losers.is_empty());
+    }
+
+    #[test]
+    fn bounded() {
+        let config = config(2, 1000);
+        let mut losers = Losers::default();
+        let now = Instant::now();
+        assert_eq!(losers.insert(id(1, 1), now, &config), 0);
+        assert_eq!(losers.insert(id(1, 2), now, &config), 0);
+        assert_eq!(losers.insert(id(1, 3), now, &config), 1);
+        assert_eq!(losers.len(), 2);
+        assert!(!losers.remove(&id(1, 1)));
+        assert!(losers.remove(&id(1, 3)));
+
+        // the entries of the replies that arrived do not count
+        assert_eq!(losers.insert(id(1, 4), now, &config), 0);
+        assert_eq!(losers.insert(id(1, 5), now, &config), 1);
+        assert_eq!(losers.len(), 2);
+        assert!(losers.order.len() <= 2 * config.max_losers);
+    }
+
+    #[test]
+    fn aged_out() {
+        let config = config(16, 10);
+        let mut losers = Losers::default();
+        let start = Instant::now();
+        losers.insert(id(1, 1), start, &config);
+        losers.insert(id(1, 2), start + Duration::from_millis(5), &config);
+        assert_eq!(losers.expire(start + Duration::from_millis(9), &config), 0);
+        assert_eq!(losers.expire(start + Duration::from_millis(10), &config), 1);
+        assert!(losers.remove(&id(1, 2)));
+        assert_eq!(losers.expire(start + Duration::from_millis(20), &config), 0);
+        assert!(losers.order.is_empty());
+    }
+
+    #[test]
+    fn conn_gone() {
+        let config = config(16, 1000);
+        let mut losers = Losers::default();
+        let now = Instant::now();
+        losers.insert(id(1, 1), now, &config);
+        losers.insert(id(2, 1), now, &config);
+        losers.remove_conn(Handle(1));
+        assert_eq!(losers.len(), 1);
+        assert!(losers.remove(&id(2, 1)));
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::{HedgingEngine, ALTERNATE_CALL_ID_BASE};
+use crate::config::HedgingConfig;
+
+pub(crate) struct HedgingEngineBuilder {
+    node: DataPathNode,
+    config: HedgingConfig,
+}
+
+impl HedgingEngineBuilder {
+    fn new(node: DataPathNode, config: HedgingConfig) -> Self {
+        HedgingEngineBuilder { node, config }
+    }
+
+    fn build(self) -> Result<HedgingEngine> {
+        Ok(HedgingEngine {
+            node: self.node,
+            indicator: Default::default(),
+            config: self.config,
+            budget: Default::default(),
+            inflight: Default::default(),
+            deadlines: Default::default(),
+            aliases: Default::default(),
+            losers: Default::default(),
+            reclaims: Default::default(),
+            servers: Default::default(),
+            estimates: Default::default(),
+            next_call_id: ALTERNATE_CALL_ID_BASE,
+            stats: Default::default(),
+        })
+    }
+}
+
+pub struct HedgingAddon {
+    config: HedgingConfig,
+}
+
+impl HedgingAddon {
+    pub const HEDGING_ENGINE: EngineType = EngineType("HedgingEngine");
+    pub const ENGINES: &'static [EngineType] = &[HedgingAddon::HEDGING_ENGINE];
+}
+
+impl HedgingAddon {
+    pub fn new(config: HedgingConfig) -> Self {
+        HedgingAddon { config }
+    }
+}
+
+impl PhoenixAddon for HedgingAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        HedgingAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = HedgingConfig::new(Some(config))?;
+        Ok(())
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        _pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != HedgingAddon::HEDGING_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = HedgingEngineBuilder::new(node, self.config.clone());
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != HedgingAddon::HEDGING_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = HedgingEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
@@ -168,61 +168,70 @@ impl HotelAclEngine {
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
 
-        match self.tx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineTxMessage::RpcMessage(msg) => {
-                        // 1 clone
-                        let private_req = materialize(&msg);
-                        // 2 check should block
-                        // yes: ACK with error, drop the data
-                        // no: pass the cloned msg to the next engine, who drops the data?
-                        // Should we Ack right after clone?
-                        let conn_id = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() }.conn_id;
-                        let call_id = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() }.call_id;
-                        let rpc_id = RpcId::new(conn_id, call_id);
-                        if should_block(&private_req) {
-                            let error = EngineRxMessage::Ack(
-                                rpc_id,
-                                TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(403) }),
-                            );
-                            self.rx_outputs()[0].send(error).unwrap_or_else(|e| {
-                                log::warn!("error when bubbling up the error, send failed e: {}", e)
-                            });
-                            drop(private_req);
-                        } else {
-                            // We will release the request on private heap after the RPC adapter
-                            // passes us an Ack.
-                            let raw_ptr: *const reservation::Request = &*private_req;
-                            self.outstanding_req_pool.insert(rpc_id, private_req);
-                            let new_msg = RpcMessageTx {
-                                meta_buf_ptr: msg.meta_buf_ptr,
-                                addr_backend: raw_ptr.addr(),
-                            };
-                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(new_msg))?;
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            // 1 clone
+                            let private_req = materialize(&msg);
+                            // 2 check should block
+                            // yes: ACK with error, drop the data
+                            // no: pass the cloned msg to the next engine, who drops the data?
+                            // Should we Ack right after clone?
+                            let conn_id = msg.meta().conn_id;
+                            let call_id = msg.meta().call_id;
+                            let rpc_id = RpcId::new(conn_id, call_id);
+                            if should_block(&private_req) {
+                                let error = EngineRxMessage::Ack(
+                                    rpc_id,
+                                    TransportStatus::Error(unsafe {
+                                        NonZeroU32::new_unchecked(403)
+                                    }),
+                                );
+                                self.rx_outputs()[0].send(error).unwrap_or_else(|e| {
+                                    log::warn!(
+                                        "error when bubbling up the error, send failed e: {}",
+                                        e
+                                    )
+                                });
+                                drop(private_req);
+                            } else {
+                                // We will release the request on private heap after the RPC adapter
+                                // passes us an Ack.
+                                let raw_ptr: *const reservation::Request = &*private_req;
+                                self.outstanding_req_pool.insert(rpc_id, private_req);
+                                let new_msg = RpcMessageTx {
+                                    meta_buf_ptr: msg.meta_buf_ptr,
+                                    addr_backend: raw_ptr.addr(),
+                                };
+                                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(new_msg))?;
+                            }
                         }
+                        // XXX TODO(cjr): it is best not to reorder the message
+                        m => self.tx_outputs()[0].send(m)?,
                     }
-                    // XXX TODO(cjr): it is best not to reorder the message
-                    m => self.tx_outputs()[0].send(m)?,
+                    return Ok(Progress(1));
                 }
-                return Ok(Progress(1));
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
             }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
         // forward all rx msgs
-        match self.rx_inputs()[0].try_recv() {
-            Ok(m) => {
-                if let EngineRxMessage::Ack(rpc_id, _status) = m {
-                    // remove private_req
-                    self.outstanding_req_pool.remove(&rpc_id);
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(m) => {
+                    if let EngineRxMessage::Ack(rpc_id, _status) = m {
+                        // remove private_req
+                        self.outstanding_req_pool.remove(&rpc_id);
+                    }
+                    self.rx_outputs()[0].send(m)?;
+                    return Ok(Progress(1));
                 }
-                self.rx_outputs()[0].send(m)?;
-                return Ok(Progress(1));
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
             }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
         Ok(Progress(0))
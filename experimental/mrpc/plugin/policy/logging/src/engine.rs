@@ -58,6 +58,22 @@ impl Engine for LoggingEngine {
         }
         Ok(())
     }
+
+    fn process_tx(&mut self, _index: usize, msg: EngineTxMessage) -> Result<(), EngineTxMessage> {
+        if self.tx_outputs_full() {
+            return Err(msg);
+        }
+        self.log_tx(&msg);
+        self.tx_outputs()[0].send(msg).map_err(|e| e.0)
+    }
+
+    fn process_rx(&mut self, _index: usize, msg: EngineRxMessage) -> Result<(), EngineRxMessage> {
+        if self.rx_outputs_full() {
+            return Err(msg);
+        }
+        self.log_rx(&msg);
+        self.rx_outputs()[0].send(msg).map_err(|e| e.0)
+    }
 }
 
 impl_vertex_for_engine!(LoggingEngine, node);
@@ -66,8 +82,10 @@ impl Decompose for LoggingEngine {
     fn flush(&mut self) -> Result<usize> {
         let mut work = 0;
         while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
-            if let Progress(n) = self.check_input_queue()? {
-                work += n;
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
             }
         }
         self.log_file.flush()?;
@@ -132,60 +150,60 @@ impl LoggingEngine {
 }
 
 impl LoggingEngine {
+    fn log_tx(&mut self, msg: &EngineTxMessage) {
+        if let EngineTxMessage::RpcMessage(msg) = msg {
+            let meta_ref = msg.meta();
+            //log::info!("Got message on tx queue: {:?}", meta_ref);
+            self.log_file
+                .write(format!("Got message on tx queue: {:?}", meta_ref).as_bytes())
+                .expect("error writing to log file");
+        }
+    }
+
+    fn log_rx(&mut self, msg: &EngineRxMessage) {
+        if let EngineRxMessage::Ack(rpc_id, status) = msg {
+            self.log_file
+                .write(
+                    format!(
+                        "Got ack on rx queue, rpc_id {:?}, status: {:?}",
+                        rpc_id, status
+                    )
+                    .as_bytes(),
+                )
+                .expect("error writing to log file");
+        }
+    }
+
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
 
-        match self.tx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineTxMessage::RpcMessage(msg) => {
-                        let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
-                        //log::info!("Got message on tx queue: {:?}", meta_ref);
-                        self.log_file
-                            .write(format!("Got message on tx queue: {:?}", meta_ref).as_bytes())
-                            .expect("error writing to log file");
-                        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
-                    }
-                    m => self.tx_outputs()[0].send(m)?,
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    self.log_tx(&msg);
+                    self.tx_outputs()[0].send(msg)?;
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    // log::info!("Disconnected!");
+                    return Ok(Status::Disconnected);
                 }
-                return Ok(Progress(1));
-            }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => {
-                // log::info!("Disconnected!");
-                return Ok(Status::Disconnected);
             }
         }
 
-        match self.rx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineRxMessage::Ack(rpc_id, status) => {
-                        self.log_file
-                            .write(
-                                format!(
-                                    "Got ack on rx queue, rpc_id {:?}, status: {:?}",
-                                    rpc_id, status
-                                )
-                                .as_bytes(),
-                            )
-                            .expect("error writing to log file");
-                        self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
-                    }
-                    EngineRxMessage::RpcMessage(msg) => {
-                        //log::info!("Got msg on rx queue: {:?}", msg);
-                        self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
-                    }
-                    m => self.rx_outputs()[0].send(m)?,
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    self.log_rx(&msg);
+                    self.rx_outputs()[0].send(msg)?;
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    // log::info!("Disconnected!");
+                    return Ok(Status::Disconnected);
                 }
-                //log::info!("Send msg in rx queue");
-                //self.rx_outputs()[0].send(msg)?;
-                return Ok(Progress(1));
-            }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => {
-                // log::info!("Disconnected!");
-                return Ok(Status::Disconnected);
             }
         }
 
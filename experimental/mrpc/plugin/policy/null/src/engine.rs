@@ -56,16 +56,30 @@ impl Engine for NullEngine {
         }
         Ok(())
     }
+
+    fn process_tx(&mut self, _index: usize, msg: EngineTxMessage) -> Result<(), EngineTxMessage> {
+        if self.tx_outputs_full() {
+            return Err(msg);
+        }
+        self.node.tx_output(OUTPUT).route(msg).map_err(|e| e.0)
+    }
 }
 
 impl_vertex_for_engine!(NullEngine, node);
 
+/// The ports of the engine. They span all channels unless declared in the `edges` of the attach
+/// config.
+const INPUT: &str = "in";
+const OUTPUT: &str = "out";
+
 impl Decompose for NullEngine {
     fn flush(&mut self) -> Result<usize> {
         let mut work = 0;
-        while !self.tx_inputs()[0].is_empty() {
-            if let Progress(n) = self.check_input_queue()? {
-                work += n;
+        while !self.node.tx_input(INPUT).is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
             }
         }
         Ok(work)
@@ -129,18 +143,22 @@ impl NullEngine {
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
 
-        match self.tx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineTxMessage::RpcMessage(msg) => {
-                        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        if !self.tx_outputs_full() {
+            match self.node.tx_input(INPUT).try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            self.node
+                                .tx_output(OUTPUT)
+                                .route(EngineTxMessage::RpcMessage(msg))?;
+                        }
+                        m => self.node.tx_output(OUTPUT).route(m)?,
                     }
-                    m => self.tx_outputs()[0].send(m)?,
+                    return Ok(Progress(1));
                 }
-                return Ok(Progress(1));
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
             }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
         Ok(Progress(0))
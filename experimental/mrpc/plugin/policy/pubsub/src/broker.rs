@@ -0,0 +1,243 @@
+//! Topic subscriptions, and the fan-out of the published events under per-subscriber flow control.
+//!
+//! A published event stays in the receive buffers of the publisher's connection until it has
+//! been delivered to, or dropped for, every subscriber. Each subscriber has at most
+//! `max_inflight` deliveries in flight, the rest wait in a queue of at most `max_queued`
+//! entries, which drops its oldest entries when full.
+use std::collections::VecDeque;
+
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+
+use phoenix_api::rpc::{CallId, RpcId};
+use phoenix_api::Handle;
+use phoenix_common::engine::datapath::shared::{PayloadKey, SharedPayloads};
+use phoenix_common::log;
+
+use crate::config::PubSubConfig;
+
+/// The call ids of the deliveries have the two highest bits set. The highest bit alone is taken
+/// by the notifications of the app.
+const DELIVERY_CALL_ID_BITS: u64 = 0b11 << 62;
+
+/// A published event waiting to be delivered, shared by the subscribers it is still to be
+/// delivered or dropped for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Publication {
+    /// The request that published the event.
+    rpc_id: RpcId,
+    /// The receive buffers are given back only if the publisher is still connected.
+    reclaim: bool,
+    /// The hop limit the deliveries are sent with.
+    hop_limit: u8,
+}
+
+/// A notification to send to a subscriber.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Delivery {
+    pub(crate) rpc_id: RpcId,
+    pub(crate) addr_backend: usize,
+    pub(crate) hop_limit: u8,
+}
+
+/// What the engine has to do after a change of the broker.
+#[derive(Debug, Default)]
+pub(crate) struct Outcome {
+    /// The notifications to send.
+    pub(crate) deliveries: Vec<Delivery>,
+    /// The publications whose receive buffers are to be given back.
+    pub(crate) released: Vec<RpcId>,
+}
+
+#[derive(Debug, Default)]
+struct Subscriber {
+    topics: HashSet<Vec<u8>>,
+    inflight: usize,
+    queue: VecDeque<PayloadKey>,
+    dropped: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Broker {
+    config: PubSubConfig,
+    topics: HashMap<Vec<u8>, HashSet<Handle>>,
+    subscribers: HashMap<Handle, Subscriber>,
+    /// The events in the receive buffers, and the deliveries of them in flight
+    publications: SharedPayloads<Publication>,
+    next_seq: u64,
+}
+
+impl Broker {
+    pub(crate) fn new(config: PubSubConfig) -> Self {
+        Broker {
+            config,
+            topics: HashMap::default(),
+            subscribers: HashMap::default(),
+            publications: SharedPayloads::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub(crate) fn set_config(&mut self, config: PubSubConfig) {
+        self.config = config;
+    }
+
+    /// Subscribes `conn_id` to `topic`. Returns false if it is subscribed already.
+    pub(crate) fn subscribe(&mut self, conn_id: Handle, topic: &[u8]) -> bool {
+        let subscriber = self.subscribers.entry(conn_id).or_default();
+        if !subscriber.topics.insert(topic.to_vec()) {
+            return false;
+        }
+        self.topics
+            .entry(topic.to_vec())
+            .or_default()
+            .insert(conn_id);
+        true
+    }
+
+    /// Unsubscribes `conn_id` from `topic`. Returns false if it is not subscribed. The events
+    /// already queued for the subscriber are still delivered.
+    pub(crate) fn unsubscribe(&mut self, conn_id: Handle, topic: &[u8]) -> bool {
+        let removed = self
+            .subscribers
+            .get_mut(&conn_id)
+            .map_or(false, |subscriber| subscriber.topics.remove(topic));
+        if removed {
+            if let Some(conns) = self.topics.get_mut(topic) {
+                conns.remove(&conn_id);
+                if conns.is_empty() {
+                    self.topics.remove(topic);
+                }
+            }
+        }
+        removed
+    }
+
+    /// Publishes the event of the request `rpc_id` to the subscribers of `topic`. The deliveries
+    /// are sent with `hop_limit`, which has the hop through the broker counted already.
+    pub(crate) fn publish(
+        &mut self,
+        rpc_id: RpcId,
+        addr_backend: usize,
+        hop_limit: u8,
+        topic: &[u8],
+        outcome: &mut Outcome,
+    ) {
+        let conns: Vec<Handle> = self
+            .topics
+            .get(topic)
+            .map(|conns| conns.iter().copied().collect())
+            .unwrap_or_default();
+        if conns.is_empty() {
+            outcome.released.push(rpc_id);
+            return;
+        }
+
+        let key = self.publications.insert(
+            Publication {
+                rpc_id,
+                reclaim: true,
+                hop_limit,
+            },
+            addr_backend,
+            conns.len(),
+        );
+        for conn_id in conns {
+            let subscriber = self.subscribers.get_mut(&conn_id).unwrap();
+            if subscriber.inflight < self.config.max_inflight {
+                self.deliver(conn_id, key, outcome);
+                continue;
+            }
+            subscriber.queue.push_back(key);
+            if subscriber.queue.len() > self.config.max_queued {
+                let oldest = subscriber.queue.pop_front().unwrap();
+                subscriber.dropped += 1;
+                self.release(oldest, outcome);
+            }
+        }
+    }
+
+    /// Whether `rpc_id` is a delivery in flight.
+    #[inline]
+    pub(crate) fn is_delivery(&self, rpc_id: &RpcId) -> bool {
+        self.publications.is_send(rpc_id)
+    }
+
+    /// Completes the delivery `rpc_id`, successfully or not, which opens the window of the
+    /// subscriber for the next one.
+    pub(crate) fn complete(&mut self, rpc_id: RpcId, outcome: &mut Outcome) {
+        match self.publications.complete(rpc_id) {
+            Some((_, publication)) => Self::reclaim(publication, outcome),
+            None => return,
+        }
+
+        let conn_id = rpc_id.0;
+        let next = match self.subscribers.get_mut(&conn_id) {
+            Some(subscriber) => {
+                subscriber.inflight -= 1;
+                subscriber.queue.pop_front()
+            }
+            None => None,
+        };
+        if let Some(next) = next {
+            self.deliver(conn_id, next, outcome);
+        }
+    }
+
+    /// Forgets the subscriptions of a connection that is gone, and the events queued for it. The
+    /// events it has published are not given back.
+    pub(crate) fn close_connection(&mut self, conn_id: Handle, outcome: &mut Outcome) {
+        for publication in self.publications.owners_mut() {
+            if publication.rpc_id.0 == conn_id {
+                publication.reclaim = false;
+            }
+        }
+
+        let subscriber = match self.subscribers.remove(&conn_id) {
+            Some(subscriber) => subscriber,
+            None => return,
+        };
+        if subscriber.dropped > 0 {
+            log::info!(
+                "Subscriber {:?} closed, {} events dropped for it",
+                conn_id,
+                subscriber.dropped
+            );
+        }
+        for topic in subscriber.topics {
+            if let Some(conns) = self.topics.get_mut(&topic) {
+                conns.remove(&conn_id);
+                if conns.is_empty() {
+                    self.topics.remove(&topic);
+                }
+            }
+        }
+        for key in subscriber.queue {
+            self.release(key, outcome);
+        }
+    }
+
+    fn deliver(&mut self, conn_id: Handle, key: PayloadKey, outcome: &mut Outcome) {
+        let call_id = CallId(DELIVERY_CALL_ID_BITS | self.next_seq);
+        self.next_seq += 1;
+        let rpc_id = RpcId(conn_id, call_id);
+        self.publications.attach(key, rpc_id);
+        self.subscribers.get_mut(&conn_id).unwrap().inflight += 1;
+        outcome.deliveries.push(Delivery {
+            rpc_id,
+            addr_backend: self.publications.addr_backend(key),
+            hop_limit: self.publications.owner(key).hop_limit,
+        });
+    }
+
+    fn release(&mut self, key: PayloadKey, outcome: &mut Outcome) {
+        let publication = self.publications.release(key);
+        Self::reclaim(publication, outcome);
+    }
+
+    #[inline]
+    fn reclaim(publication: Option<Publication>, outcome: &mut Outcome) {
+        if let Some(publication) = publication.filter(|publication| publication.reclaim) {
+            outcome.released.push(publication.rpc_id);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PubSubConfig {
+    /// Maximum number of notifications in flight to each subscriber.
+    #[serde(default = "default_max_inflight")]
+    pub max_inflight: usize,
+    /// Maximum number of notifications queued for each subscriber whose window is full. The
+    /// oldest notifications are dropped beyond it.
+    #[serde(default = "default_max_queued")]
+    pub max_queued: usize,
+}
+
+fn default_max_inflight() -> usize {
+    32
+}
+
+fn default_max_queued() -> usize {
+    1024
+}
+
+impl Default for PubSubConfig {
+    fn default() -> Self {
+        PubSubConfig {
+            max_inflight: default_max_inflight(),
+            max_queued: default_max_queued(),
+        }
+    }
+}
+
+impl PubSubConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config = toml::from_str(config.unwrap_or(""))?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,401 @@
+//! This engine is placed on the broker side. It answers the PubSub service on behalf of the app,
+//! and pushes the published events to the subscribers as notifications.
+use std::collections::VecDeque;
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+
+use anyhow::{anyhow, bail, Result};
+use fnv::FnvHashSet as HashSet;
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{MessageMeta, RpcId, RpcMsgType, StatusCode};
+use phoenix_api_policy_pubsub::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::log;
+use phoenix_common::metrics;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::broker::{Broker, Delivery, Outcome};
+use crate::config::PubSubConfig;
+use crate::module::META_BUFFER_POOL_CAP;
+use crate::service::{self, Event, Method, SubscribeRequest};
+
+/// The meta buffers kept for the replies, the deliveries wait when only these are left.
+pub(crate) const RESERVED_FOR_REPLIES: usize = 64;
+
+pub(crate) struct PubSubEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+    pub(crate) config: PubSubConfig,
+    pub(crate) broker: Broker,
+    // The meta buffers of the replies and notifications sent by this engine.
+    pub(crate) meta_buf_pool: MetaBufferPool,
+    // The replies sent by this engine, the app does not know about them.
+    pub(crate) replies: HashSet<RpcId>,
+    // The deliveries waiting for a meta buffer.
+    pub(crate) deferred: VecDeque<Delivery>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for PubSubEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "PubSubEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::SetMaxInflight(max_inflight) => {
+                self.config.max_inflight = max_inflight;
+            }
+            control_plane::Request::SetMaxQueued(max_queued) => {
+                self.config.max_queued = max_queued;
+            }
+        }
+        self.broker.set_config(self.config);
+        Ok(())
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<String> {
+        let config: PubSubConfig = toml::from_str(config)?;
+        let previous = toml::to_string(&self.config)?;
+        self.config = config;
+        self.broker.set_config(self.config);
+        Ok(previous)
+    }
+
+    fn check_config(&mut self) -> Result<()> {
+        if self.config.max_inflight == 0 {
+            bail!("max_inflight is 0, no event would ever be delivered");
+        }
+        let available = META_BUFFER_POOL_CAP - RESERVED_FOR_REPLIES;
+        if self.config.max_inflight > available {
+            bail!(
+                "max_inflight {} exceeds the {} meta buffers available for the deliveries",
+                self.config.max_inflight,
+                available
+            );
+        }
+        Ok(())
+    }
+}
+
+impl_vertex_for_engine!(PubSubEngine, node);
+
+impl Decompose for PubSubEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
+            }
+        }
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+        let mut collections = ResourceCollection::with_capacity(8);
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("broker".to_string(), Box::new(engine.broker));
+        collections.insert("meta_buf_pool".to_string(), Box::new(engine.meta_buf_pool));
+        collections.insert("replies".to_string(), Box::new(engine.replies));
+        collections.insert("deferred".to_string(), Box::new(engine.deferred));
+        (collections, engine.node)
+    }
+}
+
+impl PubSubEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<PubSubConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let broker = *local
+            .remove("broker")
+            .unwrap()
+            .downcast::<Broker>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let meta_buf_pool = *local
+            .remove("meta_buf_pool")
+            .unwrap()
+            .downcast::<MetaBufferPool>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let replies = *local
+            .remove("replies")
+            .unwrap()
+            .downcast::<HashSet<RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let deferred = *local
+            .remove("deferred")
+            .unwrap()
+            .downcast::<VecDeque<Delivery>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = PubSubEngine {
+            node,
+            indicator: Default::default(),
+            config,
+            broker,
+            meta_buf_pool,
+            replies,
+            deferred,
+        };
+        Ok(engine)
+    }
+}
+
+impl PubSubEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+
+            work += self.send_deferred()?;
+
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+impl PubSubEngine {
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        // the messages of the app go through untouched
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    self.tx_outputs()[0].send(msg)?;
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    return Ok(Status::Disconnected);
+                }
+            }
+        }
+
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineRxMessage::RpcMessage(msg) => {
+                            let meta = *msg.meta();
+                            // an oversized request is rejected by the MrpcEngine
+                            let method = if meta.status_code == StatusCode::Success {
+                                service::classify(&meta)
+                            } else {
+                                None
+                            };
+                            match method {
+                                Some(method) => {
+                                    self.answer(method, meta, msg.addr_backend, msg.hop_limit)?
+                                }
+                                None => {
+                                    self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?
+                                }
+                            }
+                        }
+                        EngineRxMessage::Ack(rpc_id, status) => {
+                            if self.replies.remove(&rpc_id) {
+                                self.meta_buf_pool.release(rpc_id)?;
+                            } else if self.broker.is_delivery(&rpc_id) {
+                                self.meta_buf_pool.release(rpc_id)?;
+                                if let phoenix_api::rpc::TransportStatus::Error(_) = status {
+                                    log::debug!(
+                                        "Delivery {:?} failed, status: {:?}",
+                                        rpc_id,
+                                        status
+                                    );
+                                }
+                                let mut outcome = Outcome::default();
+                                self.broker.complete(rpc_id, &mut outcome);
+                                self.apply(outcome)?;
+                            } else {
+                                self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                            }
+                        }
+                        EngineRxMessage::RecvError(conn_id, status) => {
+                            let mut outcome = Outcome::default();
+                            // the deliveries to the connection will not be sent
+                            let (gone, deferred): (VecDeque<_>, VecDeque<_>) = self
+                                .deferred
+                                .drain(..)
+                                .partition(|delivery| delivery.rpc_id.0 == conn_id);
+                            self.deferred = deferred;
+                            for delivery in gone.into_iter() {
+                                self.broker.complete(delivery.rpc_id, &mut outcome);
+                            }
+                            self.broker.close_connection(conn_id, &mut outcome);
+                            self.apply(outcome)?;
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::RecvError(conn_id, status))?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    return Ok(Status::Disconnected);
+                }
+            }
+        }
+
+        Ok(Progress(0))
+    }
+
+    /// Answers a request to the PubSub service on behalf of the app. `hop_limit` is the hop
+    /// limit the request was received with, the events are forwarded with one hop less.
+    fn answer(
+        &mut self,
+        method: Method,
+        meta: MessageMeta,
+        addr_backend: usize,
+        hop_limit: u8,
+    ) -> Result<(), DatapathError> {
+        let rpc_id = RpcId(meta.conn_id, meta.call_id);
+        let mut outcome = Outcome::default();
+        // SAFETY: the request has been unmarshaled into the receive buffers, which are kept until
+        // they are given back
+        let accepted = match method {
+            Method::Subscribe => {
+                let req = unsafe { &*(addr_backend as *const SubscribeRequest) };
+                outcome.released.push(rpc_id);
+                self.broker.subscribe(meta.conn_id, &req.topic)
+            }
+            Method::Unsubscribe => {
+                let req = unsafe { &*(addr_backend as *const SubscribeRequest) };
+                outcome.released.push(rpc_id);
+                self.broker.unsubscribe(meta.conn_id, &req.topic)
+            }
+            Method::Publish => match hop_limit.checked_sub(1) {
+                Some(hop_limit) => {
+                    let event = unsafe { &*(addr_backend as *const Event) };
+                    self.broker.publish(
+                        rpc_id,
+                        addr_backend,
+                        hop_limit,
+                        &event.topic,
+                        &mut outcome,
+                    );
+                    true
+                }
+                None => {
+                    metrics::record_expired_message(&format!("pubsub, call_id={}", meta.call_id));
+                    outcome.released.push(rpc_id);
+                    false
+                }
+            },
+        };
+
+        let meta = MessageMeta {
+            msg_type: RpcMsgType::Response,
+            ..meta
+        };
+        let meta_buf_ptr = self
+            .meta_buf_pool
+            .obtain(rpc_id)
+            .expect("MessageMeta pool exhausted");
+        unsafe {
+            std::ptr::write(meta_buf_ptr.as_meta_ptr(), meta);
+        }
+        self.replies.insert(rpc_id);
+        let msg = RpcMessageTx {
+            meta_buf_ptr,
+            addr_backend: service::ack_addr(accepted),
+        };
+        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+
+        self.apply(outcome)
+    }
+
+    /// Sends the deliveries and gives back the receive buffers of an outcome of the broker.
+    fn apply(&mut self, outcome: Outcome) -> Result<(), DatapathError> {
+        self.deferred.extend(outcome.deliveries);
+        self.send_deferred()?;
+        for RpcId(conn_id, call_id) in outcome.released {
+            let msg_call_ids = [call_id, call_id, call_id, call_id];
+            self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(conn_id, msg_call_ids))?;
+        }
+        Ok(())
+    }
+
+    /// Sends the deliveries waiting for a meta buffer, as long as buffers are left for the
+    /// replies.
+    fn send_deferred(&mut self) -> Result<usize, DatapathError> {
+        let mut sent = 0;
+        while !self.deferred.is_empty() && self.meta_buf_pool.free.len() > RESERVED_FOR_REPLIES {
+            let delivery = self.deferred.pop_front().unwrap();
+            let meta = MessageMeta {
+                conn_id: delivery.rpc_id.0,
+                service_id: service::service_id(),
+                func_id: service::deliver_func_id(),
+                call_id: delivery.rpc_id.1,
+                token: 0,
+                msg_type: RpcMsgType::Notification,
+                status_code: StatusCode::Success,
+            };
+            let meta_buf_ptr = self
+                .meta_buf_pool
+                .obtain_forwarded(delivery.rpc_id, delivery.hop_limit)
+                .unwrap();
+            unsafe {
+                std::ptr::write(meta_buf_ptr.as_meta_ptr(), meta);
+            }
+            let msg = RpcMessageTx {
+                meta_buf_ptr,
+                addr_backend: delivery.addr_backend,
+            };
+            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
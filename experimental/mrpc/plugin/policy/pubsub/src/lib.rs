@@ -0,0 +1,37 @@
+#![feature(peer_credentials_unix_socket)]
+#![feature(ptr_internals)]
+
+use thiserror::Error;
+
+pub use phoenix_common::{InitFnResult, PhoenixAddon};
+
+pub(crate) mod broker;
+pub mod config;
+pub(crate) mod engine;
+pub mod module;
+pub(crate) mod service;
+
+#[derive(Error, Debug)]
+pub(crate) enum DatapathError {
+    #[error("Internal queue send error")]
+    InternalQueueSend,
+    #[error("Resource error: {0}")]
+    Resource(#[from] phoenix_common::resource::Error),
+}
+
+use phoenix_common::engine::datapath::SendError;
+impl<T> From<SendError<T>> for DatapathError {
+    fn from(_other: SendError<T>) -> Self {
+        DatapathError::InternalQueueSend
+    }
+}
+
+use crate::config::PubSubConfig;
+use crate::module::PubSubAddon;
+
+#[no_mangle]
+pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
+    let config = PubSubConfig::new(config_string)?;
+    let addon = PubSubAddon::new(config);
+    Ok(Box::new(addon))
+}
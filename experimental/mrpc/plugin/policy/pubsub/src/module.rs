@@ -0,0 +1,107 @@
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::PubSubEngine;
+use crate::broker::Broker;
+use crate::config::PubSubConfig;
+
+pub(crate) const META_BUFFER_POOL_CAP: usize = 1024;
+
+pub(crate) struct PubSubEngineBuilder {
+    node: DataPathNode,
+    config: PubSubConfig,
+}
+
+impl PubSubEngineBuilder {
+    fn new(node: DataPathNode, config: PubSubConfig) -> Self {
+        PubSubEngineBuilder { node, config }
+    }
+
+    fn build(self) -> Result<PubSubEngine> {
+        Ok(PubSubEngine {
+            node: self.node,
+            indicator: Default::default(),
+            config: self.config,
+            broker: Broker::new(self.config),
+            meta_buf_pool: MetaBufferPool::new(META_BUFFER_POOL_CAP),
+            replies: Default::default(),
+            deferred: Default::default(),
+        })
+    }
+}
+
+pub struct PubSubAddon {
+    config: PubSubConfig,
+}
+
+impl PubSubAddon {
+    pub const PUBSUB_ENGINE: EngineType = EngineType("PubSubEngine");
+    pub const ENGINES: &'static [EngineType] = &[PubSubAddon::PUBSUB_ENGINE];
+}
+
+impl PubSubAddon {
+    pub fn new(config: PubSubConfig) -> Self {
+        PubSubAddon { config }
+    }
+}
+
+impl PhoenixAddon for PubSubAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        PubSubAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = toml::from_str(config)?;
+        Ok(())
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        _pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != PubSubAddon::PUBSUB_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = PubSubEngineBuilder::new(node, self.config);
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != PubSubAddon::PUBSUB_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = PubSubEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
@@ -0,0 +1,80 @@
+//! The PubSub service answered by PubSubEngine, as defined in `examples/proto/pubsub`.
+//!
+//! The messages are marshaled by the broker's dispatch library like any other message, so the
+//! broker has to bring the proto, e.g., by adding the generated PubSub server.
+use mrpc_marshal::shadow::Vec;
+use phoenix_api::rpc::{MessageMeta, RpcMsgType};
+
+lazy_static::lazy_static! {
+    // The same as what mrpc-build computes for the proto.
+    static ref PUBSUB_SERVICE_ID: u32 = crc32fast::hash(b"pubsub.PubSub");
+    static ref SUBSCRIBE_FUNC_ID: u32 = crc32fast::hash(b"/pubsub.PubSub/Subscribe");
+    static ref UNSUBSCRIBE_FUNC_ID: u32 = crc32fast::hash(b"/pubsub.PubSub/Unsubscribe");
+    static ref PUBLISH_FUNC_ID: u32 = crc32fast::hash(b"/pubsub.PubSub/Publish");
+    static ref DELIVER_FUNC_ID: u32 = crc32fast::hash(b"/pubsub.PubSub/Deliver");
+}
+
+/// The message layout generated for SubscribeRequest.
+#[repr(C)]
+pub(crate) struct SubscribeRequest {
+    pub(crate) topic: Vec<u8>,
+}
+
+/// The message layout generated for Event.
+#[repr(C)]
+pub(crate) struct Event {
+    pub(crate) topic: Vec<u8>,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// The message layout generated for Ack.
+#[repr(C)]
+pub(crate) struct Ack {
+    pub(crate) accepted: bool,
+}
+
+/// The replies. The adapters only read the replies when marshaling them.
+static ACCEPTED: Ack = Ack { accepted: true };
+static REJECTED: Ack = Ack { accepted: false };
+
+/// Returns the address of the reply to send.
+#[inline]
+pub(crate) fn ack_addr(accepted: bool) -> usize {
+    let ack = if accepted { &ACCEPTED } else { &REJECTED };
+    ack as *const Ack as usize
+}
+
+/// The methods answered by the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Method {
+    Subscribe,
+    Unsubscribe,
+    Publish,
+}
+
+/// Returns the method of an incoming request to the PubSub service, if any.
+#[inline]
+pub(crate) fn classify(meta: &MessageMeta) -> Option<Method> {
+    if meta.msg_type != RpcMsgType::Request || meta.service_id != *PUBSUB_SERVICE_ID {
+        return None;
+    }
+    if meta.func_id == *SUBSCRIBE_FUNC_ID {
+        Some(Method::Subscribe)
+    } else if meta.func_id == *UNSUBSCRIBE_FUNC_ID {
+        Some(Method::Unsubscribe)
+    } else if meta.func_id == *PUBLISH_FUNC_ID {
+        Some(Method::Publish)
+    } else {
+        None
+    }
+}
+
+#[inline]
+pub(crate) fn service_id() -> u32 {
+    *PUBSUB_SERVICE_ID
+}
+
+#[inline]
+pub(crate) fn deliver_func_id() -> u32 {
+    *DELIVER_FUNC_ID
+}
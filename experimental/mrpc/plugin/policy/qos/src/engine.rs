@@ -197,32 +197,34 @@ impl QosEngine {
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
 
-        match self.tx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineTxMessage::RpcMessage(msg) => {
-                        let now = Instant::now();
-                        if self.config.latency_budget_microsecs > 0 {
-                            let deadline =
-                                now + Duration::from_micros(self.config.latency_budget_microsecs);
-                            let tagged = SloTaggedTxMessage {
-                                deadline,
-                                source: self.client_pid,
-                                message: EngineTxMessage::RpcMessage(msg),
-                            };
-                            BUFFER.with_borrow_mut(|buf| {
-                                buf.push(Reverse(tagged));
-                            });
-                        } else {
-                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let now = Instant::now();
+                            if self.config.latency_budget_microsecs > 0 {
+                                let deadline = now
+                                    + Duration::from_micros(self.config.latency_budget_microsecs);
+                                let tagged = SloTaggedTxMessage {
+                                    deadline,
+                                    source: self.client_pid,
+                                    message: EngineTxMessage::RpcMessage(msg),
+                                };
+                                BUFFER.with_borrow_mut(|buf| {
+                                    buf.push(Reverse(tagged));
+                                });
+                            } else {
+                                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                            }
                         }
+                        m => self.tx_outputs()[0].send(m)?,
                     }
-                    m => self.tx_outputs()[0].send(m)?,
+                    return Ok(Progress(1));
                 }
-                return Ok(Progress(1));
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
             }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
         Ok(Progress(0))
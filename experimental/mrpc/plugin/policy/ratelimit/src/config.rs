@@ -5,6 +5,12 @@ use serde::{Deserialize, Serialize};
 pub struct RateLimitConfig {
     pub requests_per_sec: u64,
     pub bucket_size: u64,
+    /// Limit only the requests of this service, by its ID.
+    #[serde(default)]
+    pub service_id: Option<u32>,
+    /// Limit only the requests of this function, by its ID.
+    #[serde(default)]
+    pub func_id: Option<u32>,
 }
 
 impl Default for RateLimitConfig {
@@ -12,6 +18,8 @@ impl Default for RateLimitConfig {
         RateLimitConfig {
             requests_per_sec: 100000,
             bucket_size: 100000,
+            service_id: None,
+            func_id: None,
         }
     }
 }
@@ -21,4 +29,10 @@ impl RateLimitConfig {
         let config = toml::from_str(config.unwrap_or(""))?;
         Ok(config)
     }
+
+    /// Whether the requests of `service_id` and `func_id` are limited.
+    pub(crate) fn matches(&self, service_id: u32, func_id: u32) -> bool {
+        self.service_id.map_or(true, |id| id == service_id)
+            && self.func_id.map_or(true, |id| id == func_id)
+    }
 }
@@ -2,7 +2,7 @@ use std::collections::VecDeque;
 use std::os::unix::ucred::UCred;
 use std::pin::Pin;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use futures::future::BoxFuture;
 use minstant::Instant;
 
@@ -24,10 +24,7 @@ pub(crate) struct RateLimitEngine {
 
     pub(crate) indicator: Indicator,
 
-    // A set of func_ids to apply the rate limit.
-    // TODO(cjr): maybe put this filter in a separate engine like FilterEngine/ClassiferEngine.
-    // pub(crate) filter: FnvHashSet<u32>,
-    // Number of tokens to add for each seconds.
+    // Number of tokens to add for each seconds, and the requests to apply the rate limit to.
     pub(crate) config: RateLimitConfig,
     // The most recent timestamp we add the token to the bucket.
     pub(crate) last_ts: Instant,
@@ -67,11 +64,46 @@ impl Engine for RateLimitEngine {
                 self.config = RateLimitConfig {
                     requests_per_sec,
                     bucket_size,
+                    ..self.config
                 };
             }
         }
         Ok(())
     }
+
+    fn update_config(&mut self, config: &str) -> Result<String> {
+        let config: RateLimitConfig = toml::from_str(config)?;
+        let previous = toml::to_string(&self.config)?;
+        self.config = config;
+        Ok(previous)
+    }
+
+    fn process_tx(&mut self, _index: usize, msg: EngineTxMessage) -> Result<(), EngineTxMessage> {
+        if self.tx_outputs_full() {
+            return Err(msg);
+        }
+        if let EngineTxMessage::RpcMessage(rpc) = &msg {
+            let meta = rpc.meta();
+            if self.config.matches(meta.service_id, meta.func_id) {
+                // the mainloop adds the tokens, and sends the requests held back first
+                if !self.queue.is_empty() || self.num_tokens < 0.1 {
+                    return Err(msg);
+                }
+                self.num_tokens -= 1.0;
+            }
+        }
+        self.tx_outputs()[0].send(msg).map_err(|e| e.0)
+    }
+
+    fn check_config(&mut self) -> Result<()> {
+        if self.config.requests_per_sec == 0 && !self.queue.is_empty() {
+            bail!(
+                "requests_per_sec is 0, the {} requests queued would never be sent",
+                self.queue.len()
+            );
+        }
+        Ok(())
+    }
 }
 
 impl_vertex_for_engine!(RateLimitEngine, node);
@@ -80,8 +112,10 @@ impl Decompose for RateLimitEngine {
     fn flush(&mut self) -> Result<usize> {
         let mut work = 0;
         while !self.tx_inputs()[0].is_empty() {
-            if let Progress(n) = self.check_input_queue()? {
-                work += n;
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
             }
         }
         while !self.queue.is_empty() {
@@ -204,24 +238,29 @@ impl RateLimitEngine {
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
 
-        match self.tx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineTxMessage::RpcMessage(msg) => {
-                        if !self.queue.is_empty() || self.num_tokens < 0.1 {
-                            self.queue.push_back(msg);
-                        } else {
-                            self.num_tokens -= 1.0;
-                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            if !self.config.matches(meta.service_id, meta.func_id) {
+                                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                            } else if !self.queue.is_empty() || self.num_tokens < 0.1 {
+                                self.queue.push_back(msg);
+                            } else {
+                                self.num_tokens -= 1.0;
+                                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                            }
                         }
+                        // XXX TODO(cjr): it is best not to reorder the message
+                        m => self.tx_outputs()[0].send(m)?,
                     }
-                    // XXX TODO(cjr): it is best not to reorder the message
-                    m => self.tx_outputs()[0].send(m)?,
+                    return Ok(Progress(1));
                 }
-                return Ok(Progress(1));
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
             }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
         Ok(Progress(0))
@@ -0,0 +1,85 @@
+//! The retry budget of the calls of an application, so that a failing backend is not flooded
+//! with retries: the calls add to it and every retry spends one.
+use std::time::Instant;
+
+use crate::config::RetryConfig;
+
+#[derive(Debug, Clone)]
+pub(crate) struct RetryBudget {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RetryBudget {
+    pub(crate) fn new(config: &RetryConfig) -> Self {
+        RetryBudget {
+            tokens: config.min_retries_per_sec.min(config.max_budget),
+            last_refill: Instant::now(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn tokens(&self) -> f64 {
+        self.tokens
+    }
+
+    #[inline]
+    pub(crate) fn deposit(&mut self, config: &RetryConfig) {
+        self.tokens = (self.tokens + config.budget_ratio).min(config.max_budget);
+    }
+
+    /// Spends a retry, returns false if there is none left.
+    pub(crate) fn withdraw(&mut self, config: &RetryConfig) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * config.min_retries_per_sec).min(config.max_budget);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_fill_the_budget() {
+        let config = RetryConfig {
+            budget_ratio: 0.25,
+            min_retries_per_sec: 0.0,
+            max_budget: 2.0,
+            ..Default::default()
+        };
+        let mut budget = RetryBudget::new(&config);
+        assert!(!budget.withdraw(&config));
+        for _ in 0..4 {
+            budget.deposit(&config);
+        }
+        assert!(budget.withdraw(&config));
+        assert!(!budget.withdraw(&config));
+
+        // capped at max_budget
+        for _ in 0..100 {
+            budget.deposit(&config);
+        }
+        assert_eq!(budget.tokens(), 2.0);
+    }
+
+    #[test]
+    fn starts_with_a_second_of_retries() {
+        let config = RetryConfig {
+            min_retries_per_sec: 3.0,
+            ..Default::default()
+        };
+        let mut budget = RetryBudget::new(&config);
+        for _ in 0..3 {
+            assert!(budget.withdraw(&config));
+        }
+        assert!(budget.tokens() < 1.0);
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+/// A method whose calls can be retried, as calling it twice has the same effect as once.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdempotentMethod {
+    /// Match only the calls of this service, by its ID.
+    #[serde(default)]
+    pub service_id: Option<u32>,
+    /// Match only the calls of this function, by its ID.
+    #[serde(default)]
+    pub func_id: Option<u32>,
+}
+
+impl IdempotentMethod {
+    fn matches(&self, service_id: u32, func_id: u32) -> bool {
+        self.service_id.map_or(true, |id| id == service_id)
+            && self.func_id.map_or(true, |id| id == func_id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Only the calls of these methods are retried.
+    #[serde(default)]
+    pub idempotent: Vec<IdempotentMethod>,
+    /// The attempts of a call, the first one included.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Each call adds this fraction of a retry to the budget, ...
+    #[serde(default = "default_budget_ratio")]
+    pub budget_ratio: f64,
+    /// ... which also gets this many retries a second, ...
+    #[serde(default = "default_min_retries_per_sec")]
+    pub min_retries_per_sec: f64,
+    /// ... and holds at most this many.
+    #[serde(default = "default_max_budget")]
+    pub max_budget: f64,
+    /// Retry on another connection of the application that the same service has replied on,
+    /// rather than on the connection of the call.
+    #[serde(default)]
+    pub alternate_backend: bool,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_budget_ratio() -> f64 {
+    0.2
+}
+
+fn default_min_retries_per_sec() -> f64 {
+    10.0
+}
+
+fn default_max_budget() -> f64 {
+    100.0
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            idempotent: Vec::new(),
+            max_attempts: default_max_attempts(),
+            budget_ratio: default_budget_ratio(),
+            min_retries_per_sec: default_min_retries_per_sec(),
+            max_budget: default_max_budget(),
+            alternate_backend: false,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config: RetryConfig = toml::from_str(config.unwrap_or(""))?;
+        if config.max_attempts == 0 {
+            bail!("max_attempts must be positive");
+        }
+        for value in [
+            config.budget_ratio,
+            config.min_retries_per_sec,
+            config.max_budget,
+        ] {
+            if !(value >= 0.0 && value.is_finite()) {
+                bail!("the budget settings must be non-negative, got {}", value);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Whether the calls of `service_id` and `func_id` can be retried.
+    pub(crate) fn is_idempotent(&self, service_id: u32, func_id: u32) -> bool {
+        self.idempotent
+            .iter()
+            .any(|method| method.matches(service_id, func_id))
+    }
+}
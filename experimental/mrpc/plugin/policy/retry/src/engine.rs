@@ -0,0 +1,869 @@
+//! This engine can only be placed at the sender side, right above the RpcAdapter. It resends
+//! the requests of the idempotent methods that fail in the transport or get a reply of status
+//! `Unknown`, as long as the call has attempts left and the retry budget of the application
+//! allows it. The acknowledgement of a request is held until its reply arrives, so that the
+//! frontend keeps the request around for a resend.
+//!
+//! A resend on an alternate connection gets a call ID of its own, in the upper half of the
+//! range, and its reply and acknowledgement are rewritten to those of the original call.
+//!
+//! Every resend counts a hop of the request. A request whose hop limit is exhausted, e.g., one
+//! that goes around a loop of engines that resend it, is not resent and its call fails.
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap;
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{CallId, RpcId, RpcMsgType, StatusCode, TransportStatus};
+use phoenix_api::Handle;
+use phoenix_api_mrpc::dp::RECV_RECLAIM_BS;
+use phoenix_api_policy_retry::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPtr;
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::metrics;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::budget::RetryBudget;
+use crate::config::RetryConfig;
+
+/// The first call ID of the resends on alternate connections.
+pub(crate) const ALTERNATE_CALL_ID_BASE: u64 = 1 << 63;
+
+/// A call of an idempotent method that has not completed yet.
+#[derive(Debug)]
+pub(crate) struct Inflight {
+    meta_buf_ptr: MetaBufferPtr,
+    addr_backend: usize,
+    /// The ID of the latest attempt.
+    sent: RpcId,
+    attempts: u32,
+    /// The latest attempt has been acknowledged, the ack is held.
+    acked: bool,
+    /// The reply has been passed to the frontend, waiting for the ack.
+    replied: bool,
+}
+
+pub(crate) struct RetryEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+    pub(crate) config: RetryConfig,
+    pub(crate) budget: RetryBudget,
+    /// The calls of the idempotent methods, by their original ID.
+    pub(crate) inflight: FnvHashMap<RpcId, Inflight>,
+    /// The attempts on alternate connections, to the original ID of their calls.
+    pub(crate) aliases: FnvHashMap<RpcId, RpcId>,
+    /// The receive buffers of the rewritten replies, from the original ID to that of the attempt.
+    pub(crate) reclaims: FnvHashMap<RpcId, RpcId>,
+    /// The connections that have replied for each service.
+    pub(crate) servers: FnvHashMap<u32, Vec<Handle>>,
+    pub(crate) next_call_id: u64,
+    pub(crate) stats: control_plane::RetryStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for RetryEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "RetryEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::NewConfig(config) => {
+                self.config = RetryConfig::new(Some(&config))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_query(&mut self, query: Vec<u8>, _cred: UCred) -> Result<Vec<u8>> {
+        let query: control_plane::Query = bincode::deserialize(&query[..])?;
+
+        let response = match query {
+            control_plane::Query::Stats => {
+                let mut stats = self.stats;
+                stats.budget = self.budget.tokens();
+                control_plane::QueryResponse::Stats(stats)
+            }
+        };
+        Ok(bincode::serialize(&response)?)
+    }
+}
+
+impl_vertex_for_engine!(RetryEngine, node);
+
+impl Decompose for RetryEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
+            }
+        }
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        let mut collections = ResourceCollection::with_capacity(8);
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("budget".to_string(), Box::new(engine.budget));
+        collections.insert("inflight".to_string(), Box::new(engine.inflight));
+        collections.insert("aliases".to_string(), Box::new(engine.aliases));
+        collections.insert("reclaims".to_string(), Box::new(engine.reclaims));
+        collections.insert("servers".to_string(), Box::new(engine.servers));
+        collections.insert("next_call_id".to_string(), Box::new(engine.next_call_id));
+        collections.insert("stats".to_string(), Box::new(engine.stats));
+        (collections, engine.node)
+    }
+}
+
+impl RetryEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<RetryConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let budget = *local
+            .remove("budget")
+            .unwrap()
+            .downcast::<RetryBudget>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let inflight = *local
+            .remove("inflight")
+            .unwrap()
+            .downcast::<FnvHashMap<RpcId, Inflight>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let aliases = *local
+            .remove("aliases")
+            .unwrap()
+            .downcast::<FnvHashMap<RpcId, RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let reclaims = *local
+            .remove("reclaims")
+            .unwrap()
+            .downcast::<FnvHashMap<RpcId, RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let servers = *local
+            .remove("servers")
+            .unwrap()
+            .downcast::<FnvHashMap<u32, Vec<Handle>>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let next_call_id = *local
+            .remove("next_call_id")
+            .unwrap()
+            .downcast::<u64>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let stats = *local
+            .remove("stats")
+            .unwrap()
+            .downcast::<control_plane::RetryStats>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = RetryEngine {
+            node,
+            indicator: Default::default(),
+            config,
+            budget,
+            inflight,
+            aliases,
+            reclaims,
+            servers,
+            next_call_id,
+            stats,
+        };
+        Ok(engine)
+    }
+}
+
+impl RetryEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+/// The requests denied or too large fail the same way on a resend.
+#[inline]
+fn is_retryable(code: u32) -> bool {
+    !matches!(code, 402 | 413 | 414)
+}
+
+impl RetryEngine {
+    /// Resends the call of `orig` if it has attempts left, the budget allows it and the request
+    /// has a hop left.
+    fn retry(&mut self, orig: RpcId) -> Result<bool, DatapathError> {
+        let call = &self.inflight[&orig];
+        if call.attempts >= self.config.max_attempts {
+            self.stats.attempts_exhausted += 1;
+            return Ok(false);
+        }
+        if !self.budget.withdraw(&self.config) {
+            self.stats.budget_exhausted += 1;
+            return Ok(false);
+        }
+        // SAFETY: the frontend keeps the meta buffer until the call is acknowledged
+        let header = unsafe { &mut (*call.meta_buf_ptr.0.as_ptr()).header };
+        if !header.forward() {
+            metrics::record_expired_message(&format!("retry, call_id={}", orig.1));
+            self.stats.hops_exhausted += 1;
+            return Ok(false);
+        }
+
+        // SAFETY: the frontend keeps the meta buffer until the call is acknowledged
+        let meta = unsafe { &mut *call.meta_buf_ptr.as_meta_ptr() };
+        let failed = call.sent.0;
+        let alternate = if self.config.alternate_backend {
+            self.servers.get(&meta.service_id).and_then(|conns| {
+                let count = conns.iter().filter(|&&conn| conn != failed).count();
+                conns
+                    .iter()
+                    .filter(|&&conn| conn != failed)
+                    .nth((call.attempts as usize).checked_rem(count)?)
+                    .copied()
+            })
+        } else {
+            None
+        };
+        let sent = match alternate {
+            Some(conn) if conn != orig.0 => {
+                let call_id = CallId(self.next_call_id);
+                self.next_call_id = self.next_call_id.wrapping_add(1) | ALTERNATE_CALL_ID_BASE;
+                RpcId::new(conn, call_id)
+            }
+            _ => orig,
+        };
+        meta.conn_id = sent.0;
+        meta.call_id = sent.1;
+
+        let call = self.inflight.get_mut(&orig).unwrap();
+        self.aliases.remove(&call.sent);
+        if sent != orig {
+            self.aliases.insert(sent, orig);
+        }
+        call.sent = sent;
+        call.attempts += 1;
+        call.acked = false;
+        call.replied = false;
+        let msg = RpcMessageTx {
+            meta_buf_ptr: call.meta_buf_ptr,
+            addr_backend: call.addr_backend,
+        };
+        self.stats.retries += 1;
+        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        Ok(true)
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            if meta.msg_type == RpcMsgType::Request
+                                && self.config.is_idempotent(meta.service_id, meta.func_id)
+                            {
+                                let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                                self.budget.deposit(&self.config);
+                                self.stats.calls += 1;
+                                let call = Inflight {
+                                    meta_buf_ptr: msg.meta_buf_ptr,
+                                    addr_backend: msg.addr_backend,
+                                    sent: rpc_id,
+                                    attempts: 1,
+                                    acked: false,
+                                    replied: false,
+                                };
+                                self.inflight.insert(rpc_id, call);
+                            }
+                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                        }
+                        EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
+                            // the buffer of a rewritten reply belongs to the connection of its attempt
+                            let msg = match self.reclaims.remove(&RpcId::new(conn_id, call_ids[0]))
+                            {
+                                Some(sent) => EngineTxMessage::ReclaimRecvBuf(
+                                    sent.0,
+                                    call_ids.map(|_| sent.1),
+                                ),
+                                None => EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids),
+                            };
+                            self.tx_outputs()[0].send(msg)?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineRxMessage::RpcMessage(msg) => {
+                            let meta = unsafe { &mut *msg.meta.as_ptr() };
+                            let sent = RpcId::new(meta.conn_id, meta.call_id);
+                            let orig = self.aliases.get(&sent).copied().unwrap_or(sent);
+                            let tracked = meta.msg_type == RpcMsgType::Response
+                                && self.inflight.get(&orig).map_or(false, |c| c.sent == sent);
+                            if !tracked {
+                                self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                                return Ok(Progress(1));
+                            }
+
+                            let failed = meta.status_code == StatusCode::Unknown;
+                            let acked = self.inflight[&orig].acked;
+                            // a resend before the ack of the attempt would reuse its ID
+                            if failed && acked && self.retry(orig)? {
+                                let call_ids = [sent.1; RECV_RECLAIM_BS];
+                                self.tx_outputs()[0]
+                                    .send(EngineTxMessage::ReclaimRecvBuf(sent.0, call_ids))?;
+                                return Ok(Progress(1));
+                            }
+
+                            if !failed {
+                                let servers = self.servers.entry(meta.service_id).or_default();
+                                if !servers.contains(&sent.0) {
+                                    servers.push(sent.0);
+                                }
+                                if self.inflight[&orig].attempts > 1 {
+                                    self.stats.recovered += 1;
+                                }
+                            }
+                            if sent != orig {
+                                meta.conn_id = orig.0;
+                                meta.call_id = orig.1;
+                                self.reclaims.insert(orig, sent);
+                            }
+                            if acked {
+                                self.inflight.remove(&orig);
+                                self.aliases.remove(&sent);
+                                self.rx_outputs()[0]
+                                    .send(EngineRxMessage::Ack(orig, TransportStatus::Success))?;
+                            } else {
+                                self.inflight.get_mut(&orig).unwrap().replied = true;
+                            }
+                            self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                        }
+                        EngineRxMessage::Ack(rpc_id, status) => {
+                            let orig = self.aliases.get(&rpc_id).copied().unwrap_or(rpc_id);
+                            let call = match self.inflight.get_mut(&orig) {
+                                Some(call) if call.sent == rpc_id => call,
+                                _ => {
+                                    self.aliases.remove(&rpc_id);
+                                    self.rx_outputs()[0]
+                                        .send(EngineRxMessage::Ack(orig, status))?;
+                                    return Ok(Progress(1));
+                                }
+                            };
+                            let complete = match status {
+                                TransportStatus::Success if !call.replied => {
+                                    call.acked = true;
+                                    false
+                                }
+                                TransportStatus::Error(code) if is_retryable(code.get()) => {
+                                    !self.retry(orig)?
+                                }
+                                _ => true,
+                            };
+                            if complete {
+                                self.inflight.remove(&orig);
+                                self.aliases.remove(&rpc_id);
+                                self.rx_outputs()[0].send(EngineRxMessage::Ack(orig, status))?;
+                            }
+                        }
+                        EngineRxMessage::RecvError(conn_id, status) => {
+                            // the calls on the connection never get their replies
+                            let lost: Vec<RpcId> = self
+                                .inflight
+                                .iter()
+                                .filter(|(_, call)| call.sent.0 == conn_id && !call.replied)
+                                .map(|(orig, _)| *orig)
+                                .collect();
+                            for orig in lost {
+                                let call = self.inflight.remove(&orig).unwrap();
+                                if call.acked {
+                                    self.aliases.remove(&call.sent);
+                                    self.rx_outputs()[0]
+                                        .send(EngineRxMessage::Ack(orig, status))?;
+                                }
+                            }
+                            for servers in self.servers.values_mut() {
+                                servers.retain(|&conn| conn != conn_id);
+                            }
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::RecvError(conn_id, status))?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        Ok(Progress(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::ptr::Unique;
+
+    use phoenix_api::rpc::MessageMeta;
+    use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+    use phoenix_common::engine::datapath::node::{RxIQueue, RxOQueue, TxIQueue, TxOQueue};
+    use phoenix_common::engine::datapath::{create_channel, ChannelFlavor, RpcMessageRx};
+
+    use super::*;
+    use crate::config::IdempotentMethod;
+
+    const SERVICE_ID: u32 = 1;
+
+    /// A retry engine between a frontend and a backend played by the test.
+    struct Harness {
+        engine: RetryEngine,
+        pool: MetaBufferPool,
+        nbufs: u64,
+        tx_in: TxOQueue,
+        tx_out: TxIQueue,
+        rx_in: RxOQueue,
+        rx_out: RxIQueue,
+    }
+
+    fn error(code: u32) -> TransportStatus {
+        TransportStatus::Error(NonZeroU32::new(code).unwrap())
+    }
+
+    impl Harness {
+        fn new(config: RetryConfig) -> Self {
+            let mut node = DataPathNode::new();
+            let (tx_in, tx_input) = create_channel(ChannelFlavor::Sequential);
+            let (tx_output, tx_out) = create_channel(ChannelFlavor::Sequential);
+            let (rx_in, rx_input) = create_channel(ChannelFlavor::Sequential);
+            let (rx_output, rx_out) = create_channel(ChannelFlavor::Sequential);
+            node.tx_inputs.push(tx_input);
+            node.tx_outputs.push(tx_output);
+            node.rx_inputs.push(rx_input);
+            node.rx_outputs.push(rx_output);
+            let budget = RetryBudget::new(&config);
+            let engine = RetryEngine {
+                node,
+                indicator: Default::default(),
+                config,
+                budget,
+                inflight: FnvHashMap::default(),
+                aliases: FnvHashMap::default(),
+                reclaims: FnvHashMap::default(),
+                servers: FnvHashMap::default(),
+                next_call_id: ALTERNATE_CALL_ID_BASE,
+                stats: Default::default(),
+            };
+            Harness {
+                engine,
+                pool: MetaBufferPool::new(16),
+                nbufs: 0,
+                tx_in,
+                tx_out,
+                rx_in,
+                rx_out,
+            }
+        }
+
+        fn run(&mut self) {
+            while let Progress(1..) = self.engine.check_input_queue().unwrap() {}
+        }
+
+        fn meta(
+            &mut self,
+            rpc_id: RpcId,
+            msg_type: RpcMsgType,
+            status: StatusCode,
+        ) -> MetaBufferPtr {
+            // the buffers are released with the pool
+            self.nbufs += 1;
+            let buf = self
+                .pool
+                .obtain(RpcId::new(Handle(u64::MAX), CallId(self.nbufs)))
+                .unwrap();
+            let meta = MessageMeta {
+                conn_id: rpc_id.0,
+                service_id: SERVICE_ID,
+                func_id: 0,
+                call_id: rpc_id.1,
+                token: 0,
+                msg_type,
+                status_code: status,
+            };
+            // SAFETY: the buffer is obtained, no one else refers to it
+            unsafe { buf.as_meta_ptr().write(meta) };
+            buf
+        }
+
+        /// The frontend sends a request.
+        fn call(&mut self, rpc_id: RpcId) {
+            let meta_buf_ptr = self.meta(rpc_id, RpcMsgType::Request, StatusCode::Success);
+            let msg = RpcMessageTx {
+                meta_buf_ptr,
+                addr_backend: meta_buf_ptr.addr(),
+            };
+            self.tx_in.send(EngineTxMessage::RpcMessage(msg)).unwrap();
+            self.run();
+        }
+
+        /// The backend acknowledges an attempt.
+        fn ack(&mut self, rpc_id: RpcId, status: TransportStatus) {
+            self.rx_in
+                .send(EngineRxMessage::Ack(rpc_id, status))
+                .unwrap();
+            self.run();
+        }
+
+        /// The backend receives a reply.
+        fn reply(&mut self, rpc_id: RpcId, status: StatusCode) {
+            let buf = self.meta(rpc_id, RpcMsgType::Response, status);
+            let msg = RpcMessageRx {
+                meta: Unique::new(buf.as_meta_ptr()).unwrap(),
+                addr_app: 0,
+                addr_backend: buf.addr(),
+                hop_limit: 0,
+                flat_len: None,
+            };
+            self.rx_in.send(EngineRxMessage::RpcMessage(msg)).unwrap();
+            self.run();
+        }
+
+        /// Takes the attempt sent to the backend, returns its ID.
+        fn sent(&mut self) -> RpcId {
+            match self.tx_out.try_recv() {
+                Ok(EngineTxMessage::RpcMessage(msg)) => {
+                    let meta = msg.meta();
+                    RpcId::new(meta.conn_id, meta.call_id)
+                }
+                _ => panic!("expected a request"),
+            }
+        }
+
+        fn reclaimed(&mut self) -> (Handle, CallId) {
+            match self.tx_out.try_recv() {
+                Ok(EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids)) => (conn_id, call_ids[0]),
+                _ => panic!("expected a reclaim"),
+            }
+        }
+
+        fn acked(&mut self) -> (RpcId, TransportStatus) {
+            match self.rx_out.try_recv() {
+                Ok(EngineRxMessage::Ack(rpc_id, status)) => (rpc_id, status),
+                _ => panic!("expected an ack"),
+            }
+        }
+
+        fn replied(&mut self) -> (RpcId, StatusCode) {
+            match self.rx_out.try_recv() {
+                Ok(EngineRxMessage::RpcMessage(msg)) => {
+                    let meta = msg.meta();
+                    (RpcId::new(meta.conn_id, meta.call_id), meta.status_code)
+                }
+                _ => panic!("expected a reply"),
+            }
+        }
+
+        fn quiet(&mut self) -> bool {
+            self.tx_out.try_recv().is_err() && self.rx_out.try_recv().is_err()
+        }
+    }
+
+    fn config(max_attempts: u32) -> RetryConfig {
+        RetryConfig {
+            idempotent: vec![IdempotentMethod {
+                service_id: Some(SERVICE_ID),
+                func_id: None,
+            }],
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    fn id(conn_id: u64, call_id: u64) -> RpcId {
+        RpcId::new(Handle(conn_id), CallId(call_id))
+    }
+
+    #[test]
+    fn retryable_codes() {
+        assert!(is_retryable(1));
+        assert!(is_retryable(500));
+        for code in [402, 413, 414] {
+            assert!(!is_retryable(code));
+        }
+    }
+
+    #[test]
+    fn retry_until_attempts_exhausted() {
+        let mut h = Harness::new(config(2));
+        let call = id(1, 1);
+        h.call(call);
+        assert_eq!(h.sent(), call);
+        assert_eq!(h.engine.stats.calls, 1);
+
+        h.ack(call, error(1));
+        assert_eq!(h.sent(), call);
+        assert!(h.quiet());
+        assert_eq!(h.engine.inflight[&call].attempts, 2);
+        assert_eq!(h.engine.stats.retries, 1);
+
+        h.ack(call, error(1));
+        assert_eq!(h.acked(), (call, error(1)));
+        assert!(h.quiet());
+        assert!(h.engine.inflight.is_empty());
+        assert_eq!(h.engine.stats.attempts_exhausted, 1);
+    }
+
+    #[test]
+    fn not_retryable_error() {
+        let mut h = Harness::new(config(3));
+        let call = id(1, 1);
+        h.call(call);
+        h.sent();
+        h.ack(call, error(413));
+        assert_eq!(h.acked(), (call, error(413)));
+        assert!(h.quiet());
+        assert_eq!(h.engine.stats.retries, 0);
+        assert!(h.engine.inflight.is_empty());
+    }
+
+    #[test]
+    fn not_idempotent_not_tracked() {
+        let mut h = Harness::new(RetryConfig::default());
+        let call = id(1, 1);
+        h.call(call);
+        h.sent();
+        h.ack(call, error(1));
+        assert_eq!(h.acked(), (call, error(1)));
+        assert_eq!(h.engine.stats.calls, 0);
+    }
+
+    #[test]
+    fn retry_spends_budget() {
+        let mut h = Harness::new(RetryConfig {
+            budget_ratio: 0.5,
+            min_retries_per_sec: 0.0,
+            ..config(3)
+        });
+        // half a retry is not enough
+        let first = id(1, 1);
+        h.call(first);
+        h.sent();
+        h.ack(first, error(1));
+        assert_eq!(h.acked(), (first, error(1)));
+        assert_eq!(h.engine.stats.budget_exhausted, 1);
+
+        // two calls make a retry
+        let second = id(1, 2);
+        h.call(second);
+        h.sent();
+        h.ack(second, error(1));
+        assert_eq!(h.sent(), second);
+        assert_eq!(h.engine.stats.retries, 1);
+        assert_eq!(h.engine.budget.tokens(), 0.0);
+    }
+
+    #[test]
+    fn unknown_reply_retried_after_ack() {
+        let mut h = Harness::new(config(3));
+        let call = id(1, 1);
+        h.call(call);
+        h.sent();
+        // the ack is held until the reply
+        h.ack(call, TransportStatus::Success);
+        assert!(h.quiet());
+
+        // the failed reply is dropped and its buffer reclaimed
+        h.reply(call, StatusCode::Unknown);
+        assert_eq!(h.sent(), call);
+        assert_eq!(h.reclaimed(), (call.0, call.1));
+        assert!(h.quiet());
+
+        h.ack(call, TransportStatus::Success);
+        h.reply(call, StatusCode::Success);
+        assert_eq!(h.acked(), (call, TransportStatus::Success));
+        assert_eq!(h.replied(), (call, StatusCode::Success));
+        assert!(h.quiet());
+        assert_eq!(h.engine.stats.recovered, 1);
+        assert!(h.engine.inflight.is_empty());
+    }
+
+    #[test]
+    fn alternate_attempt_rewritten() {
+        let mut h = Harness::new(RetryConfig {
+            alternate_backend: true,
+            ..config(3)
+        });
+        h.engine
+            .servers
+            .insert(SERVICE_ID, vec![Handle(1), Handle(2)]);
+        let call = id(1, 5);
+        h.call(call);
+        h.sent();
+
+        // the retry goes to the other server with a call ID of its own
+        h.ack(call, error(1));
+        let alternate = h.sent();
+        assert_eq!(alternate, id(2, ALTERNATE_CALL_ID_BASE));
+        assert_eq!(h.engine.aliases[&alternate], call);
+        assert_eq!(h.engine.next_call_id, ALTERNATE_CALL_ID_BASE + 1);
+
+        // its ack and reply are rewritten to the call
+        h.ack(alternate, TransportStatus::Success);
+        assert!(h.quiet());
+        h.reply(alternate, StatusCode::Success);
+        assert_eq!(h.acked(), (call, TransportStatus::Success));
+        assert_eq!(h.replied(), (call, StatusCode::Success));
+        assert!(h.engine.aliases.is_empty());
+
+        // and so is the reclaim of the receive buffer of the reply
+        h.tx_in
+            .send(EngineTxMessage::ReclaimRecvBuf(
+                call.0,
+                [call.1; RECV_RECLAIM_BS],
+            ))
+            .unwrap();
+        h.run();
+        assert_eq!(h.reclaimed(), (alternate.0, alternate.1));
+        assert!(h.engine.reclaims.is_empty());
+    }
+
+    #[test]
+    fn recv_error_fails_acked_calls() {
+        let mut h = Harness::new(config(3));
+        let acked = id(1, 1);
+        let pending = id(1, 2);
+        let other = id(2, 1);
+        for call in [acked, pending, other] {
+            h.call(call);
+            h.sent();
+        }
+        h.ack(acked, TransportStatus::Success);
+        h.rx_in
+            .send(EngineRxMessage::RecvError(Handle(1), error(1)))
+            .unwrap();
+        h.run();
+        // only the held ack is released, the pending one is still to come from the backend
+        assert_eq!(h.acked(), (acked, error(1)));
+        assert!(matches!(
+            h.rx_out.try_recv(),
+            Ok(EngineRxMessage::RecvError(Handle(1), _))
+        ));
+        assert_eq!(h.engine.inflight.len(), 1);
+        assert!(h.engine.inflight.contains_key(&other));
+    }
+
+    #[test]
+    fn loop_through_two_engines_expires() {
+        const HOPS: u8 = 5;
+        let loose = RetryConfig {
+            min_retries_per_sec: 100.0,
+            max_budget: 100.0,
+            ..config(100)
+        };
+        // the back engine gives up after a resend, then the front one resends the call to it
+        let mut front = Harness::new(loose.clone());
+        let mut back = Harness::new(RetryConfig {
+            max_attempts: 2,
+            ..loose
+        });
+        let call = id(1, 1);
+        let meta_buf_ptr = front.meta(call, RpcMsgType::Request, StatusCode::Success);
+        // SAFETY: the buffer is obtained, no one else refers to it
+        unsafe { (*meta_buf_ptr.0.as_ptr()).header.hop_limit = HOPS };
+        let msg = RpcMessageTx {
+            meta_buf_ptr,
+            addr_backend: meta_buf_ptr.addr(),
+        };
+        front.tx_in.send(EngineTxMessage::RpcMessage(msg)).unwrap();
+        front.run();
+
+        let mut sends = 0;
+        loop {
+            if let Ok(msg) = front.tx_out.try_recv() {
+                back.tx_in.send(msg).unwrap();
+                back.run();
+            } else if let Ok(msg) = back.rx_out.try_recv() {
+                front.rx_in.send(msg).unwrap();
+                front.run();
+            } else if back.tx_out.try_recv().is_ok() {
+                // the backend fails every attempt
+                sends += 1;
+                assert!(sends <= 2 * HOPS as usize, "resent forever");
+                back.ack(call, error(1));
+            } else {
+                break;
+            }
+        }
+        assert_eq!(front.acked(), (call, error(1)));
+        assert!(front.quiet());
+        // every resend takes a hop, in either engine
+        assert_eq!(sends, HOPS as usize + 1);
+        assert_eq!(
+            front.engine.stats.retries + back.engine.stats.retries,
+            HOPS as u64
+        );
+        assert_eq!(front.engine.stats.hops_exhausted, 1);
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::{RetryEngine, ALTERNATE_CALL_ID_BASE};
+use crate::budget::RetryBudget;
+use crate::config::RetryConfig;
+
+pub(crate) struct RetryEngineBuilder {
+    node: DataPathNode,
+    config: RetryConfig,
+}
+
+impl RetryEngineBuilder {
+    fn new(node: DataPathNode, config: RetryConfig) -> Self {
+        RetryEngineBuilder { node, config }
+    }
+
+    fn build(self) -> Result<RetryEngine> {
+        Ok(RetryEngine {
+            node: self.node,
+            indicator: Default::default(),
+            budget: RetryBudget::new(&self.config),
+            config: self.config,
+            inflight: Default::default(),
+            aliases: Default::default(),
+            reclaims: Default::default(),
+            servers: Default::default(),
+            next_call_id: ALTERNATE_CALL_ID_BASE,
+            stats: Default::default(),
+        })
+    }
+}
+
+pub struct RetryAddon {
+    config: RetryConfig,
+}
+
+impl RetryAddon {
+    pub const RETRY_ENGINE: EngineType = EngineType("RetryEngine");
+    pub const ENGINES: &'static [EngineType] = &[RetryAddon::RETRY_ENGINE];
+}
+
+impl RetryAddon {
+    pub fn new(config: RetryConfig) -> Self {
+        RetryAddon { config }
+    }
+}
+
+impl PhoenixAddon for RetryAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        RetryAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = RetryConfig::new(Some(config))?;
+        Ok(())
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        _pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != RetryAddon::RETRY_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = RetryEngineBuilder::new(node, self.config.clone());
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != RetryAddon::RETRY_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = RetryEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
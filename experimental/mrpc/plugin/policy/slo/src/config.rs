@@ -0,0 +1,185 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use phoenix_common::event::EventSeverity;
+
+/// The objectives of the calls of the methods it matches, each method on its own.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Objective {
+    /// Match only the calls of this service, by its ID.
+    #[serde(default)]
+    pub service_id: Option<u32>,
+    /// Match only the calls of this function, by its ID.
+    #[serde(default)]
+    pub func_id: Option<u32>,
+    /// The fraction of the calls that succeed.
+    #[serde(default = "default_success_target")]
+    pub success_target: f64,
+    /// A call is slow past this many microseconds. No latency objective if omitted.
+    #[serde(default)]
+    pub latency_threshold_us: Option<u64>,
+    /// The fraction of the calls that are not slow.
+    #[serde(default = "default_latency_target")]
+    pub latency_target: f64,
+}
+
+fn default_success_target() -> f64 {
+    0.999
+}
+
+fn default_latency_target() -> f64 {
+    0.99
+}
+
+impl Objective {
+    fn matches(&self, service_id: u32, func_id: u32) -> bool {
+        self.service_id.map_or(true, |id| id == service_id)
+            && self.func_id.map_or(true, |id| id == func_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSeverity {
+    Warning,
+    Critical,
+}
+
+impl From<AlertSeverity> for EventSeverity {
+    fn from(severity: AlertSeverity) -> Self {
+        match severity {
+            AlertSeverity::Warning => EventSeverity::Warning,
+            AlertSeverity::Critical => EventSeverity::Critical,
+        }
+    }
+}
+
+/// Fires when the error budget is spent at least `burn_rate` times as fast as the objective
+/// allows, over both windows. The short window resolves the alert soon after the calls recover.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BurnRateAlert {
+    pub long_window_secs: u64,
+    pub short_window_secs: u64,
+    pub burn_rate: f64,
+    pub severity: AlertSeverity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SloConfig {
+    /// The first objective that matches a method applies to it, the calls of the methods that
+    /// no objective matches are not counted.
+    #[serde(default = "default_objectives")]
+    pub objectives: Vec<Objective>,
+    #[serde(default = "default_alerts")]
+    pub alerts: Vec<BurnRateAlert>,
+    /// The calls are counted in buckets of this many seconds, which the windows are rounded up
+    /// to.
+    #[serde(default = "default_bucket_secs")]
+    pub bucket_secs: u64,
+    /// A long window with fewer calls does not fire.
+    #[serde(default = "default_min_requests")]
+    pub min_requests: u64,
+}
+
+fn default_objectives() -> Vec<Objective> {
+    vec![Objective {
+        service_id: None,
+        func_id: None,
+        success_target: default_success_target(),
+        latency_threshold_us: None,
+        latency_target: default_latency_target(),
+    }]
+}
+
+/// The alerts recommended for a 30-day objective: 2% of the budget spent in an hour pages, and
+/// 5% in six hours opens a ticket.
+fn default_alerts() -> Vec<BurnRateAlert> {
+    vec![
+        BurnRateAlert {
+            long_window_secs: 3600,
+            short_window_secs: 300,
+            burn_rate: 14.4,
+            severity: AlertSeverity::Critical,
+        },
+        BurnRateAlert {
+            long_window_secs: 6 * 3600,
+            short_window_secs: 1800,
+            burn_rate: 6.0,
+            severity: AlertSeverity::Warning,
+        },
+    ]
+}
+
+fn default_bucket_secs() -> u64 {
+    10
+}
+
+fn default_min_requests() -> u64 {
+    10
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        SloConfig {
+            objectives: default_objectives(),
+            alerts: default_alerts(),
+            bucket_secs: default_bucket_secs(),
+            min_requests: default_min_requests(),
+        }
+    }
+}
+
+impl SloConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config: SloConfig = toml::from_str(config.unwrap_or(""))?;
+        if config.bucket_secs == 0 {
+            bail!("bucket_secs must be positive");
+        }
+        for objective in config.objectives.iter() {
+            for target in [objective.success_target, objective.latency_target] {
+                if !(target > 0.0 && target < 1.0) {
+                    bail!("target {} is not between 0 and 1", target);
+                }
+            }
+        }
+        for alert in config.alerts.iter() {
+            if alert.short_window_secs == 0 || alert.short_window_secs > alert.long_window_secs {
+                bail!(
+                    "the short window of {}s must be positive and within the long window of {}s",
+                    alert.short_window_secs,
+                    alert.long_window_secs
+                );
+            }
+            if alert.burn_rate <= 0.0 {
+                bail!("burn rate {} is not positive", alert.burn_rate);
+            }
+        }
+        Ok(config)
+    }
+
+    /// The objective of the calls of `service_id` and `func_id`, by its index.
+    pub(crate) fn objective(&self, service_id: u32, func_id: u32) -> Option<usize> {
+        self.objectives
+            .iter()
+            .position(|o| o.matches(service_id, func_id))
+    }
+
+    /// The number of buckets in `secs`, rounded up.
+    pub(crate) fn buckets(&self, secs: u64) -> u64 {
+        (secs + self.bucket_secs - 1) / self.bucket_secs
+    }
+
+    /// The number of buckets to keep, for the longest window.
+    pub(crate) fn max_buckets(&self) -> u64 {
+        let longest = self
+            .alerts
+            .iter()
+            .map(|a| a.long_window_secs)
+            .max()
+            .unwrap_or(0);
+        self.buckets(longest).max(1)
+    }
+}
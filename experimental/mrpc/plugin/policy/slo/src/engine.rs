@@ -0,0 +1,244 @@
+//! This engine can be placed on either side. It pairs each request with its reply, whichever
+//! direction they go, to count the calls of each method, whether they fail and whether they are
+//! slow. A call fails if its reply has a status other than success, or if it fails in the
+//! transport. Once a second, it evaluates the burn-rate alerts of the objectives and publishes
+//! an event on the event bus when one fires or resolves.
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use nix::unistd::Pid;
+
+use phoenix_api::rpc::{MessageMeta, RpcMsgType, StatusCode, TransportStatus};
+use phoenix_api_policy_slo::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage};
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::config::SloConfig;
+use crate::metrics::SloMetrics;
+
+const EVALUATE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) struct SloEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+    pub(crate) pid: Pid,
+    pub(crate) config: SloConfig,
+    pub(crate) metrics: SloMetrics,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for SloEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "SloEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::Reset => self.metrics.reset(),
+        }
+        Ok(())
+    }
+
+    fn handle_query(&mut self, query: Vec<u8>, _cred: UCred) -> Result<Vec<u8>> {
+        let query: control_plane::Query = bincode::deserialize(&query[..])?;
+
+        let response = match query {
+            control_plane::Query::Metrics => {
+                control_plane::QueryResponse::Metrics(self.metrics.report(&self.config))
+            }
+        };
+        Ok(bincode::serialize(&response)?)
+    }
+}
+
+impl_vertex_for_engine!(SloEngine, node);
+
+impl Decompose for SloEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            match self.check_input_queue()? {
+                Progress(0) => break,
+                Progress(n) => work += n,
+                Status::Disconnected => break,
+            }
+        }
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        let mut collections = ResourceCollection::with_capacity(3);
+        collections.insert("pid".to_string(), Box::new(engine.pid));
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("metrics".to_string(), Box::new(engine.metrics));
+        (collections, engine.node)
+    }
+}
+
+impl SloEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let pid = *local
+            .remove("pid")
+            .unwrap()
+            .downcast::<Pid>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<SloConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let metrics = *local
+            .remove("metrics")
+            .unwrap()
+            .downcast::<SloMetrics>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = SloEngine {
+            node,
+            indicator: Default::default(),
+            pid,
+            config,
+            metrics,
+        };
+        Ok(engine)
+    }
+}
+
+impl SloEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        let source = format!("SloEngine pid={}", self.pid);
+        let mut last_evaluated = Instant::now();
+        // the clock is read once every 1024 rounds
+        let mut clock = future::Every::new(1024);
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+
+            if clock.tick() && last_evaluated.elapsed() >= EVALUATE_INTERVAL {
+                self.metrics.evaluate(&self.config, &source);
+                last_evaluated = Instant::now();
+            }
+
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+impl SloEngine {
+    fn on_message(&mut self, meta: &MessageMeta) {
+        let rpc_id = (meta.conn_id.0, meta.call_id.0);
+        match meta.msg_type {
+            RpcMsgType::Request => {
+                self.metrics
+                    .on_request(&self.config, rpc_id, (meta.service_id, meta.func_id));
+            }
+            RpcMsgType::Response => {
+                let success = meta.status_code == StatusCode::Success;
+                self.metrics.on_completion(&self.config, rpc_id, success);
+            }
+            RpcMsgType::Notification => {}
+        }
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        if !self.tx_outputs_full() {
+            match self.tx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            self.on_message(meta);
+                            self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                        }
+                        m => self.tx_outputs()[0].send(m)?,
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        if !self.rx_outputs_full() {
+            match self.rx_inputs()[0].try_recv() {
+                Ok(msg) => {
+                    match msg {
+                        EngineRxMessage::RpcMessage(msg) => {
+                            let meta = msg.meta();
+                            self.on_message(meta);
+                            self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                        }
+                        EngineRxMessage::Ack(rpc_id, status) => {
+                            // a request that fails to be sent never gets its reply
+                            if let TransportStatus::Error(_) = status {
+                                let rpc_id = (rpc_id.0 .0, rpc_id.1 .0);
+                                self.metrics.on_completion(&self.config, rpc_id, false);
+                            }
+                            self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                        }
+                        EngineRxMessage::RecvError(conn_id, status) => {
+                            self.metrics.on_connection_error(&self.config, conn_id.0);
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::RecvError(conn_id, status))?;
+                        }
+                    }
+                    return Ok(Progress(1));
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+            }
+        }
+
+        Ok(Progress(0))
+    }
+}
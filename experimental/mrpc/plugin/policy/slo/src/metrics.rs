@@ -0,0 +1,481 @@
+//! The calls of each method, counted in time buckets to be summed over the windows of the alerts.
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api_policy_slo::control_plane::{MethodMetrics, WindowMetrics};
+use phoenix_common::event::{self, EventSeverity};
+
+use crate::config::{Objective, SloConfig};
+
+/// The calls in flight tracked at most, the others are not counted.
+const MAX_PENDING: usize = 65536;
+
+/// The event kind of the alerts.
+const EVENT_KIND: &str = "slo.burn_rate";
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    requests: u64,
+    failures: u64,
+    slow: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.requests += other.requests;
+        self.failures += other.failures;
+        self.slow += other.slow;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    epoch: u64,
+    counts: Counts,
+}
+
+/// The indicators an objective is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Indicator {
+    Success,
+    Latency,
+}
+
+impl Indicator {
+    fn name(self) -> &'static str {
+        match self {
+            Indicator::Success => "success",
+            Indicator::Latency => "latency",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Method {
+    objective: usize,
+    /// A ring of the most recent buckets.
+    buckets: Vec<Bucket>,
+    /// Whether each alert is firing, for the success and the latency.
+    firing: Vec<[bool; 2]>,
+}
+
+impl Method {
+    fn new(objective: usize, config: &SloConfig) -> Self {
+        Method {
+            objective,
+            buckets: vec![Bucket::default(); config.max_buckets() as usize],
+            firing: vec![[false; 2]; config.alerts.len()],
+        }
+    }
+
+    /// The bucket of `epoch`, cleared if it held an older one.
+    fn bucket(&mut self, epoch: u64) -> &mut Counts {
+        let len = self.buckets.len() as u64;
+        let bucket = &mut self.buckets[(epoch % len) as usize];
+        if bucket.epoch != epoch {
+            *bucket = Bucket {
+                epoch,
+                counts: Counts::default(),
+            };
+        }
+        &mut bucket.counts
+    }
+
+    /// The sum of the `n` buckets up to `epoch`, included.
+    fn sum(&self, epoch: u64, n: u64) -> Counts {
+        let mut sum = Counts::default();
+        for bucket in self.buckets.iter() {
+            if bucket.epoch <= epoch && epoch - bucket.epoch < n {
+                sum.add(&bucket.counts);
+            }
+        }
+        sum
+    }
+}
+
+/// How fast the error budget is spent over a window, 1.0 spends it exactly by the end of the
+/// period of the objective.
+fn burn_rate(bad: u64, total: u64, target: f64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (bad as f64 / total as f64) / (1.0 - target)
+}
+
+fn burn_rates(counts: &Counts, objective: &Objective) -> (f64, Option<f64>) {
+    let success = burn_rate(counts.failures, counts.requests, objective.success_target);
+    let latency = objective
+        .latency_threshold_us
+        .map(|_| burn_rate(counts.slow, counts.requests, objective.latency_target));
+    (success, latency)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    method: (u32, u32),
+    start: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SloMetrics {
+    start: Instant,
+    /// The calls in flight, by their connection and call IDs.
+    pending: HashMap<(u64, u64), Pending>,
+    /// By their service and function IDs.
+    methods: HashMap<(u32, u32), Method>,
+}
+
+impl SloMetrics {
+    pub(crate) fn new() -> Self {
+        SloMetrics {
+            start: Instant::now(),
+            pending: HashMap::default(),
+            methods: HashMap::default(),
+        }
+    }
+
+    fn epoch(&self, config: &SloConfig, now: Instant) -> u64 {
+        (now - self.start).as_secs() / config.bucket_secs
+    }
+
+    /// Forgets the calls counted so far. The firing alerts are resolved silently.
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+        self.methods.clear();
+    }
+
+    pub(crate) fn on_request(
+        &mut self,
+        config: &SloConfig,
+        rpc_id: (u64, u64),
+        method: (u32, u32),
+    ) {
+        if self.pending.len() >= MAX_PENDING || config.objective(method.0, method.1).is_none() {
+            return;
+        }
+        let start = Instant::now();
+        self.pending.insert(rpc_id, Pending { method, start });
+    }
+
+    /// Counts the call `rpc_id` if it is tracked.
+    pub(crate) fn on_completion(&mut self, config: &SloConfig, rpc_id: (u64, u64), success: bool) {
+        let Some(pending) = self.pending.remove(&rpc_id) else {
+            return;
+        };
+        let now = Instant::now();
+        let epoch = self.epoch(config, now);
+        let Some(objective) = config.objective(pending.method.0, pending.method.1) else {
+            return;
+        };
+        let method = self
+            .methods
+            .entry(pending.method)
+            .or_insert_with(|| Method::new(objective, config));
+        let slow = config.objectives[method.objective]
+            .latency_threshold_us
+            .map_or(false, |threshold| {
+                now - pending.start > Duration::from_micros(threshold)
+            });
+
+        let counts = method.bucket(epoch);
+        counts.requests += 1;
+        counts.failures += !success as u64;
+        counts.slow += slow as u64;
+    }
+
+    /// Fails the calls in flight on the connection `conn_id`.
+    pub(crate) fn on_connection_error(&mut self, config: &SloConfig, conn_id: u64) {
+        let calls: Vec<_> = self
+            .pending
+            .keys()
+            .filter(|rpc_id| rpc_id.0 == conn_id)
+            .copied()
+            .collect();
+        for rpc_id in calls {
+            self.on_completion(config, rpc_id, false);
+        }
+    }
+
+    /// Evaluates the alerts, and publishes an event when one fires or resolves. The calls still
+    /// in flight after the longest window are forgotten.
+    pub(crate) fn evaluate(&mut self, config: &SloConfig, source: &str) {
+        let now = Instant::now();
+        let epoch = self.epoch(config, now);
+        let horizon = Duration::from_secs(config.max_buckets() * config.bucket_secs);
+        self.pending.retain(|_, p| now - p.start < horizon);
+
+        for (&(service_id, func_id), method) in self.methods.iter_mut() {
+            let objective = &config.objectives[method.objective];
+            for (a, alert) in config.alerts.iter().enumerate() {
+                let long = method.sum(epoch, config.buckets(alert.long_window_secs));
+                let short = method.sum(epoch, config.buckets(alert.short_window_secs));
+                let (long_success, long_latency) = burn_rates(&long, objective);
+                let (short_success, short_latency) = burn_rates(&short, objective);
+                let enough = long.requests >= config.min_requests;
+                let firing = &mut method.firing[a];
+
+                let indicators = [
+                    (Indicator::Success, Some((long_success, short_success))),
+                    (Indicator::Latency, long_latency.zip(short_latency)),
+                ];
+                for (i, (indicator, rates)) in indicators.into_iter().enumerate() {
+                    let Some((long_rate, short_rate)) = rates else {
+                        continue;
+                    };
+                    let fires =
+                        enough && long_rate >= alert.burn_rate && short_rate >= alert.burn_rate;
+                    if fires == firing[i] {
+                        continue;
+                    }
+                    firing[i] = fires;
+                    let severity = if fires {
+                        alert.severity.into()
+                    } else {
+                        EventSeverity::Info
+                    };
+                    let message = format!(
+                        "{} objective of service_id={}, func_id={} {}: burn rate {:.1} over {}s \
+                         and {:.1} over {}s, threshold {:.1}",
+                        indicator.name(),
+                        service_id,
+                        func_id,
+                        if fires { "firing" } else { "resolved" },
+                        long_rate,
+                        alert.long_window_secs,
+                        short_rate,
+                        alert.short_window_secs,
+                        alert.burn_rate,
+                    );
+                    event::publish(severity, source, EVENT_KIND, message);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn report(&self, config: &SloConfig) -> Vec<MethodMetrics> {
+        let epoch = self.epoch(config, Instant::now());
+        let mut windows: Vec<u64> = config
+            .alerts
+            .iter()
+            .flat_map(|a| [a.short_window_secs, a.long_window_secs])
+            .collect();
+        windows.sort_unstable();
+        windows.dedup();
+
+        let mut report: Vec<_> = self
+            .methods
+            .iter()
+            .map(|(&(service_id, func_id), method)| {
+                let objective = &config.objectives[method.objective];
+                let windows = windows
+                    .iter()
+                    .map(|&window_secs| {
+                        let counts = method.sum(epoch, config.buckets(window_secs));
+                        let (success_burn_rate, latency_burn_rate) = burn_rates(&counts, objective);
+                        WindowMetrics {
+                            window_secs,
+                            requests: counts.requests,
+                            failures: counts.failures,
+                            slow: counts.slow,
+                            success_burn_rate,
+                            latency_burn_rate,
+                        }
+                    })
+                    .collect();
+                let firing = config
+                    .alerts
+                    .iter()
+                    .zip(method.firing.iter())
+                    .flat_map(|(alert, firing)| {
+                        [Indicator::Success, Indicator::Latency]
+                            .into_iter()
+                            .zip(firing.iter())
+                            .filter(|(_, &f)| f)
+                            .map(move |(indicator, _)| {
+                                format!(
+                                    "{} {}s/{}s",
+                                    indicator.name(),
+                                    alert.long_window_secs,
+                                    alert.short_window_secs
+                                )
+                            })
+                    })
+                    .collect();
+                MethodMetrics {
+                    service_id,
+                    func_id,
+                    success_target: objective.success_target,
+                    latency_threshold_us: objective.latency_threshold_us,
+                    latency_target: objective.latency_target,
+                    windows,
+                    firing,
+                }
+            })
+            .collect();
+        report.sort_by_key(|m| (m.service_id, m.func_id));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        bucket_secs = 10
+        min_requests = 10
+
+        [[objectives]]
+        service_id = 1
+        success_target = 0.99
+        latency_threshold_us = 1000000
+
+        [[alerts]]
+        long_window_secs = 60
+        short_window_secs = 10
+        burn_rate = 10.0
+        severity = "critical"
+    "#;
+
+    fn config() -> SloConfig {
+        SloConfig::new(Some(CONFIG)).unwrap()
+    }
+
+    /// Completes `n` calls of `method` on the connection `conn_id`, `failed` of them failed.
+    fn complete(
+        metrics: &mut SloMetrics,
+        config: &SloConfig,
+        method: (u32, u32),
+        conn_id: u64,
+        n: u64,
+        failed: u64,
+    ) {
+        for call_id in 0..n {
+            metrics.on_request(config, (conn_id, call_id), method);
+            metrics.on_completion(config, (conn_id, call_id), call_id >= failed);
+        }
+    }
+
+    #[test]
+    fn burn_rates() {
+        assert_eq!(burn_rate(0, 0, 0.99), 0.0);
+        assert!((burn_rate(1, 100, 0.99) - 1.0).abs() < 1e-9);
+        assert!((burn_rate(5, 100, 0.99) - 5.0).abs() < 1e-9);
+
+        let config = config();
+        let counts = Counts {
+            requests: 100,
+            failures: 2,
+            slow: 3,
+        };
+        let (success, latency) = super::burn_rates(&counts, &config.objectives[0]);
+        assert!((success - 2.0).abs() < 1e-9);
+        assert!((latency.unwrap() - 3.0).abs() < 1e-9);
+
+        let objective = Objective {
+            latency_threshold_us: None,
+            ..config.objectives[0]
+        };
+        assert_eq!(super::burn_rates(&counts, &objective).1, None);
+    }
+
+    #[test]
+    fn buckets_in_window() {
+        let config = config();
+        assert_eq!(config.max_buckets(), 6);
+        let mut method = Method::new(0, &config);
+        for epoch in 0..10 {
+            method.bucket(epoch).requests += epoch + 1;
+        }
+        // the buckets of epochs 4 to 9 are left in the ring
+        assert_eq!(method.sum(9, 1).requests, 10);
+        assert_eq!(method.sum(9, 2).requests, 10 + 9);
+        assert_eq!(method.sum(9, 6).requests, (5..=10).sum::<u64>());
+        assert_eq!(method.sum(9, 100).requests, (5..=10).sum::<u64>());
+        // a bucket reused by a later epoch starts over
+        method.bucket(10).requests += 1;
+        assert_eq!(method.sum(10, 6).requests, 1 + (6..=10).sum::<u64>());
+        // the buckets after the epoch are not counted
+        assert_eq!(method.sum(7, 2).requests, 8 + 7);
+    }
+
+    #[test]
+    fn counts_matching_methods() {
+        let config = config();
+        let mut metrics = SloMetrics::new();
+        complete(&mut metrics, &config, (1, 1), 1, 4, 1);
+        // no objective matches the methods of service 2
+        complete(&mut metrics, &config, (2, 1), 1, 4, 1);
+        // a completion of a call not in flight is not counted
+        metrics.on_completion(&config, (1, 100), false);
+        assert!(metrics.pending.is_empty());
+
+        let report = metrics.report(&config);
+        assert_eq!(report.len(), 1);
+        assert_eq!((report[0].service_id, report[0].func_id), (1, 1));
+        let windows: Vec<_> = report[0].windows.iter().map(|w| w.window_secs).collect();
+        assert_eq!(windows, [10, 60]);
+        for window in report[0].windows.iter() {
+            assert_eq!((window.requests, window.failures, window.slow), (4, 1, 0));
+            assert!((window.success_burn_rate - 25.0).abs() < 1e-9);
+            assert_eq!(window.latency_burn_rate, Some(0.0));
+        }
+    }
+
+    #[test]
+    fn connection_error_fails_calls() {
+        let config = config();
+        let mut metrics = SloMetrics::new();
+        metrics.on_request(&config, (1, 1), (1, 1));
+        metrics.on_request(&config, (1, 2), (1, 2));
+        metrics.on_request(&config, (2, 1), (1, 1));
+        metrics.on_connection_error(&config, 1);
+        assert_eq!(metrics.pending.len(), 1);
+
+        let report = metrics.report(&config);
+        assert_eq!(report.len(), 2);
+        for method in report.iter() {
+            assert_eq!(method.windows[0].requests, 1);
+            assert_eq!(method.windows[0].failures, 1);
+        }
+    }
+
+    #[test]
+    fn fires_and_resolves() {
+        let config = config();
+        let mut metrics = SloMetrics::new();
+        let firing = |metrics: &SloMetrics| metrics.report(&config)[0].firing.clone();
+
+        // too few calls to fire
+        complete(&mut metrics, &config, (1, 1), 1, 9, 9);
+        metrics.evaluate(&config, "test");
+        assert!(firing(&metrics).is_empty());
+
+        // 9 failures in 10 calls burn at 90 times the objective
+        complete(&mut metrics, &config, (1, 1), 2, 1, 0);
+        metrics.evaluate(&config, "test");
+        assert_eq!(firing(&metrics), ["success 60s/10s"]);
+        metrics.evaluate(&config, "test");
+        assert_eq!(firing(&metrics), ["success 60s/10s"]);
+
+        // 9 failures in 100 calls burn at 9 times the objective, below the threshold
+        complete(&mut metrics, &config, (1, 1), 3, 90, 0);
+        metrics.evaluate(&config, "test");
+        assert!(firing(&metrics).is_empty());
+    }
+
+    #[test]
+    fn reset_forgets_calls() {
+        let config = config();
+        let mut metrics = SloMetrics::new();
+        complete(&mut metrics, &config, (1, 1), 1, 10, 10);
+        metrics.on_request(&config, (1, 100), (1, 1));
+        metrics.evaluate(&config, "test");
+        assert_eq!(metrics.report(&config)[0].firing.len(), 1);
+
+        metrics.reset();
+        assert!(metrics.pending.is_empty());
+        assert!(metrics.report(&config).is_empty());
+    }
+}
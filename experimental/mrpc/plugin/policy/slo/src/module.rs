@@ -0,0 +1,103 @@
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::SloEngine;
+use crate::config::SloConfig;
+use crate::metrics::SloMetrics;
+
+pub(crate) struct SloEngineBuilder {
+    node: DataPathNode,
+    pid: Pid,
+    config: SloConfig,
+}
+
+impl SloEngineBuilder {
+    fn new(node: DataPathNode, pid: Pid, config: SloConfig) -> Self {
+        SloEngineBuilder { node, pid, config }
+    }
+
+    fn build(self) -> Result<SloEngine> {
+        Ok(SloEngine {
+            node: self.node,
+            indicator: Default::default(),
+            pid: self.pid,
+            config: self.config,
+            metrics: SloMetrics::new(),
+        })
+    }
+}
+
+pub struct SloAddon {
+    config: SloConfig,
+}
+
+impl SloAddon {
+    pub const SLO_ENGINE: EngineType = EngineType("SloEngine");
+    pub const ENGINES: &'static [EngineType] = &[SloAddon::SLO_ENGINE];
+}
+
+impl SloAddon {
+    pub fn new(config: SloConfig) -> Self {
+        SloAddon { config }
+    }
+}
+
+impl PhoenixAddon for SloAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        SloAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = SloConfig::new(Some(config))?;
+        Ok(())
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != SloAddon::SLO_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = SloEngineBuilder::new(node, pid, self.config.clone());
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != SloAddon::SLO_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = SloEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
@@ -0,0 +1,72 @@
+//! Obtain and release on the shared pool of receive buffers.
+//!
+//! `held` obtains a buffer while the first ones of the slab are borrowed, `threads` obtains and
+//! releases from several engines at once, the way the engines replenish their receive queues.
+//! Run it with `cargo bench -p phoenix-rpc-adapter --bench pool`.
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use phoenix_rpc_adapter::pool::BufferPool;
+use shm::region::AddressMediator;
+
+/// A pool with a slab already added.
+fn buffer_pool() -> BufferPool {
+    let pool = BufferPool::new(Arc::new(AddressMediator::new()));
+    let buf = pool.obtain();
+    pool.release(buf);
+    pool
+}
+
+fn obtain_release(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pool");
+
+    for num_held in [0usize, 64, 120] {
+        let pool = buffer_pool();
+        let held: Vec<_> = (0..num_held).map(|_| pool.obtain()).collect();
+        group.bench_with_input(BenchmarkId::new("held", num_held), &pool, |b, pool| {
+            b.iter(|| pool.release(criterion::black_box(pool.obtain())))
+        });
+        held.into_iter().for_each(|buf| pool.release(buf));
+    }
+
+    for num_threads in [1usize, 2, 4, 8] {
+        let pool = buffer_pool();
+        group.bench_with_input(
+            BenchmarkId::new("threads", num_threads),
+            &pool,
+            |b, pool| {
+                b.iter_custom(|iters| {
+                    let barrier = Barrier::new(num_threads);
+                    thread::scope(|s| {
+                        let handles: Vec<_> = (0..num_threads)
+                            .map(|_| {
+                                s.spawn(|| {
+                                    barrier.wait();
+                                    let start = Instant::now();
+                                    for _ in 0..iters {
+                                        pool.release(criterion::black_box(pool.obtain()));
+                                    }
+                                    start.elapsed()
+                                })
+                            })
+                            .collect();
+                        // the slowest engine
+                        handles
+                            .into_iter()
+                            .map(|h| h.join().unwrap())
+                            .max()
+                            .unwrap_or(Duration::ZERO)
+                    })
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, obtain_release);
+criterion_main!(benches);
@@ -1,14 +1,116 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct RpcAdapterConfig {
     pub enable_scheduler: bool,
+    /// Sends up to this size, in bytes, are copied into the send queue entry, so that the NIC
+    /// does not read them from the memory. The NIC must support inlining this much data. The
+    /// messages copied into the descriptors between the app and phoenixd are set by
+    /// `max_inline_payload` of the mRPC setting instead.
+    #[serde(default = "default_max_inline_data")]
+    pub max_inline_data: usize,
+    /// A send completion is requested every this many work requests, and at the end of the
+    /// messages queued, and the messages sent before it on the connection are acknowledged in a
+    /// batch. 1 requests one for every message. Must be well below the 128 work requests of a
+    /// send queue, as an unsignaled work request holds its entry until a later one completes.
+    #[serde(default = "default_send_signal_interval")]
+    pub send_signal_interval: usize,
+    /// The receives kept posted on each connection, which is also its receive queue depth.
+    #[serde(default = "default_recv_window")]
+    pub recv_window: usize,
+    /// The receives of a connection are posted again in a batch, back up to `recv_window`, once
+    /// fewer than this are outstanding. The peer gets this many work requests of credit.
+    #[serde(default = "default_recv_low_watermark")]
+    pub recv_low_watermark: usize,
+    /// The receive buffers of 8MB allocated for each connection, at least `recv_window`. Those
+    /// beyond the window refill the receive queue while the application holds the buffers of
+    /// the messages delivered to it.
+    #[serde(default = "default_recv_buffers")]
+    pub recv_buffers: usize,
+    /// Encrypt the connections of all the clients, not only of those that ask for it in their
+    /// setting. The messages are sealed with AES-GCM, under keys negotiated for each connection.
+    #[serde(default)]
+    pub encryption: bool,
+    /// Number of work completions to poll from the NIC at once
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Number of messages or completions an engine takes from each of its queues in a round,
+    /// before letting the other engines on its runtime run. Unbounded if omitted
+    #[serde(default)]
+    pub quantum: Option<usize>,
+    /// The endpoints of each service to connect to when a subscription starts, so that its first
+    /// call there does not wait for the connection. A connection is handed to the first connect
+    /// of the application to its endpoint.
+    #[serde(default)]
+    pub prewarm: BTreeMap<String, Vec<SocketAddr>>,
+    /// The messages waiting for the credits of their connections are sent earliest deadline
+    /// first. The deadline of a request is the time it is queued plus the budget of its method,
+    /// that of the other messages plus `default_send_budget_us`, so that they are sent in order
+    /// when no budget is set.
+    #[serde(default)]
+    pub send_budgets: Vec<SendBudget>,
+    /// The budget of the messages without one, in microseconds.
+    #[serde(default = "default_send_budget_us")]
+    pub default_send_budget_us: u64,
+}
+
+/// The time the requests of a method, or of all methods of a service if `func_id` is omitted,
+/// may wait to be sent before those queued later with a longer budget.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SendBudget {
+    pub service_id: u32,
+    #[serde(default)]
+    pub func_id: Option<u32>,
+    pub budget_us: u64,
+}
+
+fn default_max_inline_data() -> usize {
+    128
+}
+
+fn default_send_signal_interval() -> usize {
+    16
+}
+
+fn default_recv_window() -> usize {
+    128
+}
+
+fn default_recv_low_watermark() -> usize {
+    96
+}
+
+fn default_recv_buffers() -> usize {
+    256
+}
+
+fn default_batch_size() -> usize {
+    32
+}
+
+fn default_send_budget_us() -> u64 {
+    1000
 }
 
 impl RpcAdapterConfig {
     pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
+        let config: RpcAdapterConfig = toml::from_str(config.unwrap_or(""))?;
+        if config.batch_size == 0 || config.quantum == Some(0) {
+            anyhow::bail!("batch_size and quantum must be positive");
+        }
         Ok(config)
     }
+
+    /// The endpoints to prewarm, each once.
+    pub(crate) fn prewarm_endpoints(&self) -> Vec<SocketAddr> {
+        let mut endpoints: Vec<SocketAddr> = self.prewarm.values().flatten().copied().collect();
+        endpoints.sort_unstable();
+        endpoints.dedup();
+        endpoints
+    }
 }
@@ -1,6 +1,8 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::mem;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
 use std::os::unix::prelude::{AsRawFd, RawFd};
 use std::pin::Pin;
 use std::ptr;
@@ -12,18 +14,22 @@ use fnv::FnvHashMap;
 use futures::future::BoxFuture;
 use slab::Slab;
 
-use mrpc_marshal::{ExcavateContext, SgE, SgList};
+use mrpc_marshal::seal::{self, Handshake, Role, SealError};
+use mrpc_marshal::{copy, ExcavateContext, MarshalError, SgE, SgList, UnmarshalError};
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::net;
-use phoenix_api::rpc::{MessageMeta, RpcId, RpcMsgType, TransportStatus};
+use phoenix_api::rpc::{MessageMeta, RpcId, RpcMsgType, StatusCode, TransportStatus};
+use phoenix_api::transport::rdma::control_plane as rdma_control_plane;
+use phoenix_api::wire::{WireError, WireFlags, WireHeader};
 use phoenix_api::{AsHandle, Handle};
 use phoenix_api_mrpc::cmd;
-use phoenix_api_mrpc::cmd::{ConnectResponse, ReadHeapRegion};
+use phoenix_api_mrpc::cmd::{ConnectResponse, MessageSizeLimits, ReadHeapRegion};
 use phoenix_api_rpc_adapter::control_plane;
 use phoenix_mrpc::unpack::UnpackFromSgE;
 use phoenix_salloc::state::State as SallocState;
 use transport_rdma::ops::Ops;
 
+use phoenix_common::engine::datapath::arena::Arena;
 use phoenix_common::engine::datapath::message::{
     EngineRxMessage, EngineTxMessage, RpcMessageRx, RpcMessageTx,
 };
@@ -33,17 +39,19 @@ use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator,
 use phoenix_common::envelop::ResourceDowncast;
 use phoenix_common::impl_vertex_for_engine;
 use phoenix_common::module::{ModuleCollection, Version};
+use phoenix_common::resource::{Error as ResourceError, Versioned};
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 use phoenix_common::{log, tracing};
 
 use super::pool::BufferSlab;
+use super::recv::{RecvReplenisher, RecvWindow};
+use super::sched::{SendBudgets, SendQueue};
 use super::serialization::SerializationEngine;
-use super::state::{ConnectionContext, ReqContext, State, WrContext};
+use super::state::{ConnectionContext, EstablishedConn, ReqContext, StagedCmId, State, WrContext};
 use super::ulib;
+use super::ulib::uverbs::ConnParam;
 use super::{ControlPathError, DatapathError};
 
-pub(crate) const MAX_INLINE_DATA: usize = 128;
-
 thread_local! {
     /// To emulate a thread local storage (TLS). This should be called engine-local-storage (ELS).
     pub(crate) static ELS: RefCell<Option<&'static TlStorage>> = RefCell::new(None);
@@ -61,7 +69,9 @@ pub(crate) struct RpcAdapterEngine {
     pub(crate) tls: Box<TlStorage>,
 
     // shared completion queue model
-    pub(crate) local_buffer: VecDeque<RpcMessageTx>,
+    pub(crate) local_buffer: SendQueue,
+    /// The budgets the deadlines of the messages queued are set from
+    pub(crate) send_budgets: SendBudgets,
 
     // the number of pending receives that are going on. this can avoid the runtime from sleeping
     pub(crate) pending_recv: usize,
@@ -71,9 +81,19 @@ pub(crate) struct RpcAdapterEngine {
     // then we only needs to maintain an additional reference counter for each recv mr, i.e., HashMap<Handle, u64>;
     // if in the future recv mr's addr is directly used as wr_id in post_recv,
     // just change Handle here to usize
-    pub(crate) recv_mr_usage: FnvHashMap<RpcId, Vec<Handle>>,
+    pub(crate) recv_mr_usage: FnvHashMap<RpcId, Vec<Versioned<Handle>>>,
 
     pub(crate) serialization_engine: Option<SerializationEngine>,
+    /// The maximum sizes of the marshalled messages
+    pub(crate) size_limits: MessageSizeLimits,
+    /// Sends up to this size are copied into the send queue entry
+    pub(crate) max_inline_data: usize,
+    /// A send completion is requested every this many work requests
+    pub(crate) send_signal_interval: usize,
+    /// The receives kept posted on each connection
+    pub(crate) recv_window: RecvWindow,
+    /// Whether the connections must be encrypted
+    pub(crate) encryption: bool,
 
     pub(crate) cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Command>,
     pub(crate) cmd_tx: tokio::sync::mpsc::UnboundedSender<cmd::Completion>,
@@ -84,11 +104,26 @@ pub(crate) struct RpcAdapterEngine {
 
     pub(crate) rpc_ctx: Slab<RpcId>,
 
-    // work completion read buffer
+    // work completion read buffer, its capacity is the number of completions polled at once
     pub(crate) wc_read_buffer: Vec<net::WorkCompletion>,
+    /// Messages or completions taken from each queue in a round
+    pub(crate) quantum: usize,
+    // scatter-gather list of the message being sent, reused across messages
+    pub(crate) sgl_buffer: SgList,
+    // the message being sent once sealed, reused across messages
+    pub(crate) seal_buffer: Vec<u8>,
+    // transient allocations of a quantum, e.g., the scatter-gather lists of received messages
+    pub(crate) arena: Arena,
+    // sealed messages posted from their own buffers, by rpc context, until their sends complete
+    pub(crate) sealed_buffers: FnvHashMap<usize, Vec<u8>>,
 
     // NOTE: Hold salloc State to prevent early dropping of send heap.
     pub(crate) salloc: SallocState,
+
+    // the endpoints to connect to before the application asks for it
+    pub(crate) prewarm: Vec<SocketAddr>,
+    // the connections to hand to the first connect to their endpoints
+    pub(crate) prewarmed: FnvHashMap<SocketAddr, EstablishedConn>,
 }
 
 impl_vertex_for_engine!(RpcAdapterEngine, node);
@@ -127,10 +162,30 @@ impl Decompose for RpcAdapterEngine {
                 "local_buffer".to_string(),
                 Box::new(ptr::read(&engine.local_buffer)),
             );
+            collections.insert(
+                "send_budgets".to_string(),
+                Box::new(ptr::read(&engine.send_budgets)),
+            );
             collections.insert(
                 "pending_recv".to_string(),
                 Box::new(ptr::read(&engine.pending_recv)),
             );
+            collections.insert(
+                "max_inline_data".to_string(),
+                Box::new(ptr::read(&engine.max_inline_data)),
+            );
+            collections.insert(
+                "send_signal_interval".to_string(),
+                Box::new(ptr::read(&engine.send_signal_interval)),
+            );
+            collections.insert(
+                "recv_window".to_string(),
+                Box::new(ptr::read(&engine.recv_window)),
+            );
+            collections.insert(
+                "encryption".to_string(),
+                Box::new(ptr::read(&engine.encryption)),
+            );
             collections.insert(
                 "recv_mr_usage".to_string(),
                 Box::new(ptr::read(&engine.recv_mr_usage)),
@@ -139,6 +194,10 @@ impl Decompose for RpcAdapterEngine {
                 "serialization_engine".to_string(),
                 Box::new(ptr::read(&engine.serialization_engine)),
             );
+            collections.insert(
+                "size_limits".to_string(),
+                Box::new(ptr::read(&engine.size_limits)),
+            );
             collections.insert("cmd_tx".to_string(), Box::new(ptr::read(&engine.cmd_tx)));
             collections.insert("cmd_rx".to_string(), Box::new(ptr::read(&engine.cmd_rx)));
             collections.insert("rpc_ctx".to_string(), Box::new(ptr::read(&engine.rpc_ctx)));
@@ -146,7 +205,26 @@ impl Decompose for RpcAdapterEngine {
                 "wc_read_buffer".to_string(),
                 Box::new(ptr::read(&engine.wc_read_buffer)),
             );
+            collections.insert("quantum".to_string(), Box::new(ptr::read(&engine.quantum)));
+            collections.insert(
+                "sgl_buffer".to_string(),
+                Box::new(ptr::read(&engine.sgl_buffer)),
+            );
+            collections.insert(
+                "seal_buffer".to_string(),
+                Box::new(ptr::read(&engine.seal_buffer)),
+            );
+            collections.insert("arena".to_string(), Box::new(ptr::read(&engine.arena)));
+            collections.insert(
+                "sealed_buffers".to_string(),
+                Box::new(ptr::read(&engine.sealed_buffers)),
+            );
             collections.insert("salloc".to_string(), Box::new(ptr::read(&engine.salloc)));
+            collections.insert("prewarm".to_string(), Box::new(ptr::read(&engine.prewarm)));
+            collections.insert(
+                "prewarmed".to_string(),
+                Box::new(ptr::read(&engine.prewarmed)),
+            );
             // don't call the drop function
             ptr::read(&engine.node)
         };
@@ -190,18 +268,28 @@ impl RpcAdapterEngine {
         let local_buffer = *local
             .remove("local_buffer")
             .unwrap()
-            .downcast::<VecDeque<RpcMessageTx>>()
+            .downcast::<SendQueue>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let send_budgets = *local
+            .remove("send_budgets")
+            .unwrap()
+            .downcast::<SendBudgets>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let recv_mr_usage = *local
             .remove("recv_mr_usage")
             .unwrap()
-            .downcast::<FnvHashMap<RpcId, Vec<Handle>>>()
+            .downcast::<FnvHashMap<RpcId, Vec<Versioned<Handle>>>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let serialization_engine = *local
             .remove("serialization_engine")
             .unwrap()
             .downcast::<Option<SerializationEngine>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let size_limits = *local
+            .remove("size_limits")
+            .unwrap()
+            .downcast::<MessageSizeLimits>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let cmd_tx = *local
             .remove("cmd_tx")
             .unwrap()
@@ -217,6 +305,26 @@ impl RpcAdapterEngine {
             .unwrap()
             .downcast::<usize>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let max_inline_data = *local
+            .remove("max_inline_data")
+            .unwrap()
+            .downcast::<usize>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let send_signal_interval = *local
+            .remove("send_signal_interval")
+            .unwrap()
+            .downcast::<usize>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let recv_window = *local
+            .remove("recv_window")
+            .unwrap()
+            .downcast::<RecvWindow>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let encryption = *local
+            .remove("encryption")
+            .unwrap()
+            .downcast::<bool>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let rpc_ctx = *local
             .remove("rpc_ctx")
             .unwrap()
@@ -227,20 +335,61 @@ impl RpcAdapterEngine {
             .unwrap()
             .downcast::<Vec<net::WorkCompletion>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let quantum = *local
+            .remove("quantum")
+            .unwrap()
+            .downcast::<usize>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let sgl_buffer = *local
+            .remove("sgl_buffer")
+            .unwrap()
+            .downcast::<SgList>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let seal_buffer = *local
+            .remove("seal_buffer")
+            .unwrap()
+            .downcast::<Vec<u8>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let arena = *local
+            .remove("arena")
+            .unwrap()
+            .downcast::<Arena>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let sealed_buffers = *local
+            .remove("sealed_buffers")
+            .unwrap()
+            .downcast::<FnvHashMap<usize, Vec<u8>>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let salloc = *local
             .remove("salloc")
             .unwrap()
             .downcast::<SallocState>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let prewarm = *local
+            .remove("prewarm")
+            .unwrap()
+            .downcast::<Vec<SocketAddr>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let prewarmed = *local
+            .remove("prewarmed")
+            .unwrap()
+            .downcast::<FnvHashMap<SocketAddr, EstablishedConn>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
 
         let engine = RpcAdapterEngine {
             state,
             odp_mr,
             tls,
             local_buffer,
+            send_budgets,
             pending_recv,
             recv_mr_usage,
             serialization_engine,
+            size_limits,
+            max_inline_data,
+            send_signal_interval,
+            recv_window,
+            encryption,
             cmd_tx,
             cmd_rx,
             node,
@@ -249,7 +398,14 @@ impl RpcAdapterEngine {
             // TODO(cjr)
             rpc_ctx,
             wc_read_buffer,
+            quantum,
+            sgl_buffer,
+            seal_buffer,
+            arena,
+            sealed_buffers,
             salloc,
+            prewarm,
+            prewarmed,
         };
         Ok(engine)
     }
@@ -337,6 +493,22 @@ impl Engine for RpcAdapterEngine {
         }
         Ok(())
     }
+
+    fn handle_query(
+        &mut self,
+        query: Vec<u8>,
+        _cred: std::os::unix::ucred::UCred,
+    ) -> Result<Vec<u8>> {
+        // the connections are made through the RDMA transport in-process
+        let query: rdma_control_plane::Query = bincode::deserialize(&query[..])?;
+
+        let response = match query {
+            rdma_control_plane::Query::Introspect => {
+                rdma_control_plane::QueryResponse::Introspect(self.tls.ops.introspect())
+            }
+        };
+        Ok(bincode::serialize(&response)?)
+    }
 }
 
 impl Drop for RpcAdapterEngine {
@@ -351,17 +523,24 @@ impl Drop for RpcAdapterEngine {
 
 impl RpcAdapterEngine {
     async fn mainloop(&mut self) -> EngineResult {
+        self.prewarm_connections().await;
         loop {
+            // the allocations of the previous round are gone
+            self.arena.reset();
             // let mut timer = crate::timer::Timer::new();
             let mut work = 0;
             // let mut work2 = 0;
             // no work: 10-100ns
             // has work: ~150-180ns each req on avg
-            loop {
+            let mut quantum = self.quantum;
+            while quantum > 0 {
                 // check input queue, no work 10ns, otherwise 250-350ns
                 match self.check_input_queue()? {
                     Progress(0) => break,
-                    Progress(n) => work += n,
+                    Progress(n) => {
+                        work += n;
+                        quantum = quantum.saturating_sub(n);
+                    }
                     Status::Disconnected => return Ok(()),
                 }
             }
@@ -369,10 +548,12 @@ impl RpcAdapterEngine {
 
             // no work: 80-130ns
             // has work: ~320ns each wc on avg
-            loop {
+            let mut quantum = self.quantum;
+            while quantum > 0 {
                 // ibv_poll_cq, no work: 100-150ns, otherwise 400ns
                 if let Progress(n) = self.check_transport_service()? {
                     work += n;
+                    quantum = quantum.saturating_sub(n);
                     // work2 += n;
                     if n == 0 {
                         break;
@@ -411,6 +592,35 @@ enum RpcStrategy {
     Standard,
 }
 
+/// The total size of a marshalled message, excluding the meta.
+#[inline]
+fn payload_size(sges: &[SgE]) -> usize {
+    sges.iter().map(|sge| sge.len).sum()
+}
+
+/// The status a message is delivered with when it fails to unmarshal.
+fn unmarshal_status(e: &UnmarshalError) -> StatusCode {
+    match e {
+        UnmarshalError::UnknownFuncId(_) => StatusCode::Unimplemented,
+        _ => StatusCode::Unknown,
+    }
+}
+
+/// The size of a receive buffer, which bounds the size of a segment.
+const RECV_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// The fields of the meta a sealed message is authenticated with, so that the payload cannot be
+/// moved under the header of another call.
+#[inline]
+fn seal_aad(meta: &MessageMeta) -> [u8; 20] {
+    let mut aad = [0; 20];
+    aad[0..4].copy_from_slice(&meta.service_id.to_le_bytes());
+    aad[4..8].copy_from_slice(&meta.func_id.to_le_bytes());
+    aad[8..16].copy_from_slice(&meta.call_id.0.to_le_bytes());
+    aad[16..20].copy_from_slice(&(meta.msg_type as u32).to_le_bytes());
+    aad
+}
+
 impl RpcAdapterEngine {
     fn get_or_init_odp_mr(
         &mut self,
@@ -440,11 +650,55 @@ impl RpcAdapterEngine {
         }
     }
 
+    /// Whether to signal the last work request of a message of `nwrs` work requests. It is
+    /// signaled every `send_signal_interval` work requests on the connection, and when no other
+    /// message of the connection is queued, so that the messages sent are acknowledged without
+    /// waiting for more to be sent on it.
+    fn signal_message(&self, conn_ctx: &ConnectionContext, nwrs: usize) -> bool {
+        let unsignaled = conn_ctx.unsignaled_wrs.load(Ordering::Relaxed) + nwrs;
+        if unsignaled >= self.send_signal_interval
+            || !self.local_buffer.has_queued(conn_ctx.conn_id)
+        {
+            conn_ctx.unsignaled_wrs.store(0, Ordering::Relaxed);
+            true
+        } else {
+            conn_ctx.unsignaled_wrs.store(unsignaled, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Acknowledges the message of the rpc context `ctx` with `status`, and the ones sent before
+    /// it on the same connection with success. The send queue completes in order, so the
+    /// unsignaled sends before a completion have completed successfully.
+    fn ack_sends(&mut self, ctx: usize, status: TransportStatus) -> Result<(), DatapathError> {
+        // already acknowledged, e.g., an earlier work request of the message failed
+        let Some(&RpcId(conn_id, _)) = self.rpc_ctx.get(ctx) else {
+            return Ok(());
+        };
+        let conn_ctx = self.state.local_resource().conn(conn_id)?;
+        let mut unacked = conn_ctx.unacked.lock();
+        if !unacked.contains(&ctx) {
+            return Ok(());
+        }
+        while let Some(front) = unacked.pop_front() {
+            let rpc_id = self.rpc_ctx.remove(front);
+            self.sealed_buffers.remove(&front);
+            if front == ctx {
+                self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                break;
+            }
+            let ack = EngineRxMessage::Ack(rpc_id, TransportStatus::Success);
+            self.rx_outputs()[0].send(ack)?;
+        }
+        Ok(())
+    }
+
     fn send_fused(
         &mut self,
         conn_ctx: &ConnectionContext,
         mut meta_buf_ptr: MetaBufferPtr,
         sglist: &SgList,
+        flags: WireFlags,
     ) -> Result<Status, DatapathError> {
         use ulib::uverbs::SendFlags;
 
@@ -452,7 +706,8 @@ impl RpcAdapterEngine {
         let msg_type = unsafe { &*meta_buf_ptr.as_meta_ptr() }.msg_type;
         let cmid = &conn_ctx.cmid;
         // let ctx = RpcId::new(cmid.as_handle(), call_id).encode_u64();
-        let ctx = self.rpc_ctx.insert(RpcId::new(cmid.as_handle(), call_id));
+        let ctx = self.rpc_ctx.insert(RpcId::new(conn_ctx.conn_id, call_id));
+        conn_ctx.unacked.lock().push_back(ctx);
 
         // TODO(cjr): XXX, this credit implementation has big flaws
         if msg_type == RpcMsgType::Request {
@@ -470,41 +725,41 @@ impl RpcAdapterEngine {
         // TODO(cjr): impl Serialize for SgList
         // Serialize the sglist
         // write the lens to MetaBuffer
-        meta_buf.num_sge = sglist.0.len() as u32;
-
         let mut value_len = 0;
         let lens_buf = meta_buf.length_delimited.as_mut_ptr().cast::<u32>();
         let value_buf = unsafe { lens_buf.add(sglist.0.len()).cast::<u8>() };
 
         for (i, sge) in sglist.0.iter().enumerate() {
             // SAFETY: we have done sanity check before in choose_strategy
-            unsafe { lens_buf.add(i).write(sge.len as u32) };
+            unsafe { lens_buf.add(i).write((sge.len as u32).to_le()) };
             unsafe {
-                ptr::copy_nonoverlapping(sge.ptr as *mut u8, value_buf.add(value_len), sge.len);
+                copy::copy_nonoverlapping(sge.ptr as *const u8, value_buf.add(value_len), sge.len);
             }
             value_len += sge.len;
         }
 
         // write the values to MetaBuffer
-        meta_buf.value_len = value_len as u32;
-
-        let odp_mr = self.odp_mr.as_mut().unwrap();
+        meta_buf
+            .header
+            .seal(WireFlags::FUSED | flags, sglist.0.len(), value_len);
+        let post_len = meta_buf.len();
+        meta_buf.stamp_post_send();
+        // SAFETY: the header is not read after it is encoded
+        unsafe { WireHeader::encode(&mut meta_buf.header) };
 
         // post send with imm
         // tracing::trace!("send_fused, meta_buf={:?}, post_len: {}", meta_buf, meta_buf.len());
-        let send_flags = if meta_buf.len() <= MAX_INLINE_DATA {
+        let mut send_flags = if post_len <= self.max_inline_data {
             SendFlags::INLINE
         } else {
             SendFlags::empty()
         };
+        if self.signal_message(conn_ctx, 1) {
+            send_flags |= SendFlags::SIGNALED;
+        }
+        let odp_mr = self.odp_mr.as_mut().unwrap();
         unsafe {
-            cmid.post_send_with_imm(
-                odp_mr,
-                off..off + meta_buf.len(),
-                ctx as u64,
-                send_flags | SendFlags::SIGNALED,
-                0,
-            )?;
+            cmid.post_send_with_imm(odp_mr, off..off + post_len, ctx as u64, send_flags, 0)?;
         }
 
         Ok(Progress(1))
@@ -513,11 +768,17 @@ impl RpcAdapterEngine {
     fn send_standard(
         &mut self,
         conn_ctx: &ConnectionContext,
-        meta_ref: &MessageMeta,
+        mut meta_buf_ptr: MetaBufferPtr,
         sglist: &SgList,
+        flags: WireFlags,
     ) -> Result<Status, DatapathError> {
         use ulib::uverbs::SendFlags;
 
+        let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
+        meta_buf
+            .header
+            .seal(flags, sglist.0.len(), payload_size(&sglist.0));
+        let meta_ref = &meta_buf.header.meta;
         let call_id = meta_ref.call_id;
         let cmid = &conn_ctx.cmid;
 
@@ -535,14 +796,38 @@ impl RpcAdapterEngine {
 
         // Sender posts send requests from the SgList
         // let ctx = RpcId::new(cmid.as_handle(), call_id).encode_u64();
-        let ctx = self.rpc_ctx.insert(RpcId::new(cmid.as_handle(), call_id));
+        let ctx = self.rpc_ctx.insert(RpcId::new(conn_ctx.conn_id, call_id));
+        conn_ctx.unacked.lock().push_back(ctx);
+        if flags.contains(WireFlags::SEALED) {
+            // the sealed message is posted from its buffer, which is kept until it is sent
+            let buf = mem::take(&mut self.seal_buffer);
+            self.sealed_buffers.insert(ctx, buf);
+        }
+        // only the last work request of the message may be signaled, its completion tells that
+        // the ones before it completed
+        let signaled = if self.signal_message(conn_ctx, sglist.0.len() + 1) {
+            SendFlags::SIGNALED
+        } else {
+            SendFlags::empty()
+        };
 
+        meta_buf.stamp_post_send();
+        // SAFETY: the header is not read after it is encoded
+        unsafe { WireHeader::encode(&mut meta_buf.header) };
         let meta_sge = SgE {
-            ptr: (meta_ref as *const MessageMeta).expose_addr(),
-            len: mem::size_of::<MessageMeta>(),
+            ptr: (&meta_buf.header as *const WireHeader).expose_addr(),
+            len: WireHeader::SIZE,
         };
 
         // TODO(cjr): credit handle logic for response
+        let max_inline_data = self.max_inline_data;
+        let inline_flag = |len: usize| {
+            if len <= max_inline_data {
+                SendFlags::INLINE
+            } else {
+                SendFlags::empty()
+            }
+        };
         let odp_mr = self.odp_mr.as_mut().unwrap();
         // timer.tick();
 
@@ -552,7 +837,7 @@ impl RpcAdapterEngine {
                 odp_mr,
                 meta_sge.ptr..meta_sge.ptr + meta_sge.len,
                 ctx as u64,
-                SendFlags::SIGNALED,
+                inline_flag(meta_sge.len),
             )?;
         }
 
@@ -562,7 +847,7 @@ impl RpcAdapterEngine {
             if i + 1 < sglist.0.len() {
                 // post send
                 unsafe {
-                    cmid.post_send(odp_mr, off..off + sge.len, ctx as u64, SendFlags::SIGNALED)?;
+                    cmid.post_send(odp_mr, off..off + sge.len, ctx as u64, inline_flag(sge.len))?;
                 }
             } else {
                 // post send with imm
@@ -572,7 +857,7 @@ impl RpcAdapterEngine {
                         odp_mr,
                         off..off + sge.len,
                         ctx as u64,
-                        SendFlags::SIGNALED,
+                        inline_flag(sge.len) | signaled,
                         0,
                     )?;
                 }
@@ -588,11 +873,22 @@ impl RpcAdapterEngine {
         match self.tx_inputs()[0].try_recv() {
             Ok(msg) => {
                 match msg {
-                    EngineTxMessage::RpcMessage(msg) => self.local_buffer.push_back(msg),
+                    EngineTxMessage::RpcMessage(msg) => {
+                        // SAFETY: the meta stays valid until the message is acknowledged
+                        let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+                        let budget = self.send_budgets.budget(meta_ref);
+                        let conn_id = meta_ref.conn_id;
+                        self.local_buffer.push(msg, conn_id, budget);
+                    }
                     EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
                         // let mut timer = crate::timer::Timer::new();
-                        let conn_ctx = self.state.local_resource().cmid_table.get(&conn_id)?;
-                        let cmid = &conn_ctx.cmid;
+                        // the buffers of a connection that has been replaced are not posted on
+                        // the one that took its handle
+                        let conn_ctx = match self.state.local_resource().conn(conn_id) {
+                            Ok(conn_ctx) => Some(conn_ctx),
+                            Err(ResourceError::Stale) => None,
+                            Err(e) => return Err(e.into()),
+                        };
                         // timer.tick();
 
                         // TODO(cjr): only handle the first element, fix it later
@@ -601,7 +897,12 @@ impl RpcAdapterEngine {
                                 .recv_mr_usage
                                 .remove(&RpcId(conn_id, *call_id))
                                 .expect("invalid WR identifier");
-                            self.reclaim_recv_buffers(cmid, &recv_buffer_handles)?;
+                            if let Some(conn_ctx) = &conn_ctx {
+                                conn_ctx.recv.lock().reclaim(&recv_buffer_handles);
+                            }
+                        }
+                        if let Some(conn_ctx) = conn_ctx {
+                            self.replenish_recv_buffers(&conn_ctx)?;
                         }
                         // timer.tick();
                         // log::info!("ReclaimRecvBuf: {}", timer);
@@ -613,33 +914,92 @@ impl RpcAdapterEngine {
             Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
         }
 
-        if let Some(msg) = self.local_buffer.pop_front() {
+        if let Some(queued) = self.local_buffer.pop() {
             // SAFETY: don't know what kind of UB can be triggered
-            let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
-            let cmid_handle = meta_ref.conn_id;
+            let meta_ref = unsafe { &*queued.msg.meta_buf_ptr.as_meta_ptr() };
 
             // get cmid from conn_id
-            let conn_ctx = self.state.local_resource().cmid_table.get(&cmid_handle)?;
+            let conn_ctx = match self.state.local_resource().conn(meta_ref.conn_id) {
+                Ok(conn_ctx) => conn_ctx,
+                Err(ResourceError::Stale) => return self.reject_stale(queued.msg),
+                Err(e) => return Err(e.into()),
+            };
 
             if conn_ctx.credit.load(Ordering::Acquire) <= 5 {
                 // some random number for now TODO(cjr): update this
-                self.local_buffer.push_front(msg);
+                self.local_buffer.requeue(queued);
                 return Ok(Progress(0));
             }
+            let msg = queued.msg;
             // let mut timer = crate::timer::Timer::new();
 
-            let sglist = if let Some(ref module) = self.serialization_engine {
-                module.marshal(meta_ref, msg.addr_backend).unwrap()
+            let mut sglist = mem::take(&mut self.sgl_buffer);
+            if meta_ref.status_code != StatusCode::Success {
+                // only the meta is sent
+                sglist.0.clear();
+            } else if let Some(ref module) = self.serialization_engine {
+                if let Err(e) = module.marshal_into(meta_ref, msg.addr_backend, &mut sglist) {
+                    log::warn!(
+                        "failed to marshal {:?}, service_id={}, call_id={}: {}",
+                        meta_ref.msg_type,
+                        meta_ref.service_id,
+                        meta_ref.call_id,
+                        e
+                    );
+                    self.sgl_buffer = sglist;
+                    let (status_code, code) = match e {
+                        MarshalError::UnknownFuncId(_) => (StatusCode::Unimplemented, 501),
+                    };
+                    return self.reject(&conn_ctx, msg, status_code, code);
+                }
             } else {
                 panic!("dispatch module not loaded");
-            };
+            }
             // timer.tick();
 
+            if meta_ref.status_code == StatusCode::Success {
+                let size = payload_size(&sglist.0);
+                let max_size = self
+                    .size_limits
+                    .max_size(meta_ref.service_id, meta_ref.msg_type);
+                if max_size.map_or(false, |max_size| size > max_size) {
+                    self.sgl_buffer = sglist;
+                    return self.reject_too_large(&conn_ctx, msg, size);
+                }
+            }
+
+            // on an encrypted connection, the payload is sealed into a single element
+            let mut flags = WireFlags::empty();
+            let cipher = conn_ctx.cipher.as_ref().filter(|_| !sglist.0.is_empty());
+            if let Some(cipher) = cipher {
+                let aad = seal_aad(meta_ref);
+                // SAFETY: the elements are in the heaps of the message until it is acked
+                unsafe { cipher.seal(&sglist, &aad, &mut self.seal_buffer) };
+                let size = self.seal_buffer.len();
+                if size > RECV_BUFFER_SIZE {
+                    self.sgl_buffer = sglist;
+                    return self.reject_too_large(&conn_ctx, msg, size);
+                }
+                sglist.0.clear();
+                sglist.0.push(SgE {
+                    ptr: self.seal_buffer.as_ptr().expose_addr(),
+                    len: size,
+                });
+                flags = WireFlags::SEALED;
+            }
+
+            let bytes = mem::size_of::<MessageMeta>() + payload_size(&sglist.0);
             // TODO(cjr): Examine the SgList and optimize for small messages
             let status = match Self::choose_strategy(&sglist) {
-                RpcStrategy::Fused => self.send_fused(&conn_ctx, msg.meta_buf_ptr, &sglist)?,
-                RpcStrategy::Standard => self.send_standard(&conn_ctx, meta_ref, &sglist)?,
+                RpcStrategy::Fused => {
+                    self.send_fused(&conn_ctx, msg.meta_buf_ptr, &sglist, flags)?
+                }
+                RpcStrategy::Standard => {
+                    self.send_standard(&conn_ctx, msg.meta_buf_ptr, &sglist, flags)?
+                }
             };
+            conn_ctx.traffic.sent(bytes);
+            self.sgl_buffer = sglist;
 
             // timer.tick();
             // log::info!("check_input_queue: {}", timer);
@@ -649,7 +1009,127 @@ impl RpcAdapterEngine {
         Ok(Progress(0))
     }
 
-    fn reshape_fused_sg_list(sg_list: &mut SgList) {
+    /// Fails a message sent on the `conn_id` of a connection that has been replaced with
+    /// transport status 410. It is not sent on the connection that took its handle.
+    fn reject_stale(&mut self, msg: RpcMessageTx) -> Result<Status, DatapathError> {
+        // SAFETY: the meta buffer is owned by this message until it is acked
+        let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+        log::warn!(
+            "{:?} on stale connection {:?}, call_id={}, rejected",
+            meta.msg_type,
+            meta.conn_id,
+            meta.call_id
+        );
+        let rpc_id = RpcId(meta.conn_id, meta.call_id);
+        let status = TransportStatus::Error(NonZeroU32::new(410).unwrap());
+        self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+        Ok(Progress(1))
+    }
+
+    /// Drops a message larger than the limit instead of sending it.
+    ///
+    /// The call of a request fails locally with transport status 413. A response is replaced by
+    /// its meta, which tells the client that the reply is too large.
+    fn reject_too_large(
+        &mut self,
+        conn_ctx: &ConnectionContext,
+        msg: RpcMessageTx,
+        size: usize,
+    ) -> Result<Status, DatapathError> {
+        // SAFETY: the meta buffer is owned by this message until it is acked
+        let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+        log::warn!(
+            "{:?} of {} bytes exceeds the size limit, service_id={}, call_id={}",
+            meta.msg_type,
+            size,
+            meta.service_id,
+            meta.call_id
+        );
+        self.reject(conn_ctx, msg, StatusCode::MessageTooLarge, 413)
+    }
+
+    /// Fails a message that cannot be sent. A request fails locally with the transport status
+    /// `code`. A response is sent without its payload and with `status_code`, which fails the
+    /// call on the other side.
+    fn reject(
+        &mut self,
+        conn_ctx: &ConnectionContext,
+        msg: RpcMessageTx,
+        status_code: StatusCode,
+        code: u32,
+    ) -> Result<Status, DatapathError> {
+        // SAFETY: the meta buffer is owned by this message until it is acked
+        let meta = unsafe { &mut *msg.meta_buf_ptr.as_meta_ptr() };
+        match meta.msg_type {
+            // nobody waits for a notification, it fails locally as a request does
+            RpcMsgType::Request | RpcMsgType::Notification => {
+                let rpc_id = RpcId(meta.conn_id, meta.call_id);
+                let status = TransportStatus::Error(NonZeroU32::new(code).unwrap());
+                self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                Ok(Progress(1))
+            }
+            RpcMsgType::Response => {
+                meta.status_code = status_code;
+                self.send_fused(
+                    conn_ctx,
+                    msg.meta_buf_ptr,
+                    &SgList(Vec::new()),
+                    WireFlags::empty(),
+                )
+            }
+        }
+    }
+
+    /// Checks the header of a received message, and splits an eager (fused) message into its
+    /// segments. Returns the flags of the message.
+    fn parse_sg_list(sg_list: &mut SgList) -> Result<WireFlags, DatapathError> {
+        // SAFETY: the first segment is in a receive buffer, which is aligned and at least
+        // as long as its length
+        let header = unsafe { WireHeader::parse(sg_list.0[0].ptr, sg_list.0[0].len) }?;
+        header.check_segments(sg_list.0.len())?;
+        let flags = header.flags();
+        if header.is_fused() {
+            header.check_fused(sg_list.0[0].len, MetaBuffer::capacity())?;
+            Self::reshape_fused_sg_list(sg_list)?;
+        } else {
+            // the first segment is unpacked as a MessageMeta
+            sg_list.0[0].len = mem::size_of::<MessageMeta>();
+        }
+        Ok(flags)
+    }
+
+    /// Opens a sealed message in place, into its elements. On an encrypted connection, only the
+    /// messages without payload, e.g., errors, are not sealed.
+    fn open_sg_list(
+        conn_ctx: &ConnectionContext,
+        flags: WireFlags,
+        sg_list: &mut SgList,
+    ) -> Result<(), DatapathError> {
+        let sealed = flags.contains(WireFlags::SEALED);
+        match conn_ctx.cipher.as_ref() {
+            Some(cipher) if sealed => {
+                // the meta is followed by the sealed element
+                if sg_list.0.len() != 2 {
+                    return Err(WireError::SegmentCount {
+                        expected: 2,
+                        actual: sg_list.0.len(),
+                    }
+                    .into());
+                }
+                // SAFETY: the header has been parsed in place
+                let aad = seal_aad(unsafe { &*(sg_list.0[0].ptr as *const MessageMeta) });
+                let sge = sg_list.0.pop().unwrap();
+                // SAFETY: the element is in a receive buffer, which is held by this message
+                unsafe { cipher.open(sge, &aad, sg_list) }?;
+            }
+            Some(_) if sg_list.0.len() > 1 => return Err(DatapathError::NotSealed),
+            None if sealed => return Err(SealError::NoKey.into()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn reshape_fused_sg_list(sg_list: &mut SgList) -> Result<(), WireError> {
         use std::ptr::Unique;
 
         assert_eq!(sg_list.0.len(), 1);
@@ -661,40 +1141,44 @@ impl RpcAdapterEngine {
         // modify the first sge in place
         sg_list.0[0].len = mem::size_of::<MessageMeta>();
 
-        let num_sge = meta_buf.num_sge as usize;
+        let num_sge = meta_buf.header.num_sge as usize;
         let (_prefix, lens, _suffix): (_, &[u32], _) = unsafe { meta_buf.lens_buffer().align_to() };
         debug_assert!(_prefix.is_empty() && _suffix.is_empty());
+        meta_buf.header.check_fused_lens(lens)?;
 
         let value_buf_base = meta_buf.value_buffer().as_ptr().expose_addr();
         let mut value_offset = 0;
 
         #[allow(clippy::needless_range_loop)]
         for i in 0..num_sge {
+            let len = u32::from_le(lens[i]) as usize;
             sg_list.0.push(SgE {
                 ptr: value_buf_base + value_offset,
-                len: lens[i] as usize,
+                len,
             });
 
-            value_offset += lens[i] as usize;
+            value_offset += len;
         }
 
         // tracing::trace!("reshape_fused_sg_list: sg_list: {:?}", sg_list);
+        Ok(())
     }
 
     fn unmarshal_and_deliver_up(
         &mut self,
-        sgl: SgList,
+        sgl: &[SgE],
         conn_ctx: Arc<ConnectionContext>,
     ) -> Result<RpcId, DatapathError> {
         // log::debug!("unmarshal_and_deliver_up, sgl: {:0x?}", sgl);
 
         // let mut timer = crate::timer::Timer::new();
 
-        let mut meta_ptr = unsafe { MessageMeta::unpack(&sgl.0[0]) }.unwrap();
+        let mut meta_ptr = unsafe { MessageMeta::unpack(&sgl[0]) }.unwrap();
         let meta = unsafe { meta_ptr.as_mut() };
-        meta.conn_id = conn_ctx.cmid.as_handle();
+        meta.conn_id = conn_ctx.conn_id;
 
         let recv_id = RpcId(meta.conn_id, meta.call_id);
+        conn_ctx.traffic.received(payload_size(sgl));
 
         // timer.tick();
         // replenish the credits
@@ -710,21 +1194,62 @@ impl RpcAdapterEngine {
         // timer.tick();
 
         let mut excavate_ctx = ExcavateContext {
-            sgl: sgl.0[1..].iter(),
+            sgl: sgl[1..].iter(),
             addr_arbiter: &self.state.local_resource().addr_map,
         };
 
-        let (addr_app, addr_backend) = if let Some(ref module) = self.serialization_engine {
-            module.unmarshal(meta, &mut excavate_ctx).unwrap()
+        // An oversized message is delivered without its payload, the MrpcEngine rejects it.
+        if meta.status_code == StatusCode::Success {
+            let size = payload_size(&sgl[1..]);
+            let max_size = self.size_limits.max_size(meta.service_id, meta.msg_type);
+            if max_size.map_or(false, |max_size| size > max_size) {
+                log::warn!(
+                    "received {:?} of {} bytes exceeds the size limit, service_id={}, call_id={}",
+                    meta.msg_type,
+                    size,
+                    meta.service_id,
+                    meta.call_id
+                );
+                meta.status_code = StatusCode::MessageTooLarge;
+            }
+        }
+
+        let (addr_app, addr_backend) = if meta.status_code != StatusCode::Success {
+            (0, 0)
+        } else if let Some(ref module) = self.serialization_engine {
+            match module.unmarshal(meta, &mut excavate_ctx) {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    // delivered without its payload, the MrpcEngine rejects it
+                    log::warn!(
+                        "failed to unmarshal {:?}, service_id={}, call_id={}: {}",
+                        meta.msg_type,
+                        meta.service_id,
+                        meta.call_id,
+                        e
+                    );
+                    meta.status_code = unmarshal_status(&e);
+                    (0, 0)
+                }
+            }
         } else {
             panic!("dispatch module not loaded");
         };
         // timer.tick();
 
+        // a message that is its struct alone can be copied by value
+        let flat_len = match &sgl[1..] {
+            [sge] if meta.status_code == StatusCode::Success => Some(sge.len),
+            _ => None,
+        };
+        // SAFETY: the header is decoded in place by parse_sg_list
+        let hop_limit = unsafe { (*(sgl[0].ptr as *const WireHeader)).hop_limit };
         let msg = RpcMessageRx {
             meta: meta_ptr,
             addr_backend,
             addr_app,
+            hop_limit,
+            flat_len,
         };
 
         self.rx_outputs()[0]
@@ -755,6 +1280,7 @@ impl RpcAdapterEngine {
         cq.poll(&mut self.wc_read_buffer)?;
 
         let comps = mem::take(&mut self.wc_read_buffer);
+        let arena = mem::take(&mut self.arena);
 
         let mut progress = 0;
         for wc in &comps {
@@ -765,11 +1291,7 @@ impl RpcAdapterEngine {
                             // send completed,  do nothing
                             if wc.wc_flags.contains(WcFlags::WITH_IMM) {
                                 tracing::trace!("post_send_imm completed, wr_id={}", wc.wr_id);
-                                // let rpc_id = RpcId::decode_u64(wc.wr_id);
-                                let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
-                                self.rx_outputs()[0]
-                                    .send(EngineRxMessage::Ack(rpc_id, TransportStatus::Success))
-                                    .unwrap();
+                                self.ack_sends(wc.wr_id as usize, TransportStatus::Success)?;
                             }
                         }
                         WcOpcode::Recv => {
@@ -787,10 +1309,12 @@ impl RpcAdapterEngine {
                                 };
                                 let mut recv_ctx = conn_ctx.receiving_ctx.lock();
                                 recv_ctx.sg_list.0.push(sge);
-                                recv_ctx.recv_buffer_handles.push(Handle(wc.wr_id as u64));
+                                recv_ctx.recv_buffer_handles.push(wr_ctx.buffer);
                                 drop(recv_ctx);
                                 conn_ctx
                             };
+                            conn_ctx.recv.lock().completed();
+                            self.replenish_recv_buffers(&conn_ctx)?;
 
                             if wc.wc_flags.contains(WcFlags::WITH_IMM) {
                                 // received an entire RPC message
@@ -800,54 +1324,67 @@ impl RpcAdapterEngine {
                                 );
                                 // let mut timer = crate::timer::Timer::new();
 
-                                use std::ops::DerefMut;
-                                let mut recv_ctx =
-                                    mem::take(conn_ctx.receiving_ctx.lock().deref_mut());
+                                let mut recv_ctx = conn_ctx.receiving_ctx.lock();
+                                let recv_buffer_handles =
+                                    mem::take(&mut recv_ctx.recv_buffer_handles);
+                                // a message that fails to open, e.g., forged or replayed, is
+                                // dropped like a malformed one
+                                let sgl = Self::parse_sg_list(&mut recv_ctx.sg_list)
+                                    .and_then(|flags| {
+                                        Self::open_sg_list(&conn_ctx, flags, &mut recv_ctx.sg_list)
+                                    })
+                                    .map(|()| arena.alloc_slice_copy(&recv_ctx.sg_list.0));
+                                // the list of the connection is kept for its next message, this
+                                // one is delivered from its copy without holding the lock
+                                recv_ctx.sg_list.0.clear();
+                                drop(recv_ctx);
 
-                                // check if it is an eager message
-                                if recv_ctx.sg_list.0.len() == 1 {
-                                    // got an eager message
-                                    Self::reshape_fused_sg_list(&mut recv_ctx.sg_list);
-                                }
+                                let sgl = match sgl {
+                                    Ok(sgl) => sgl,
+                                    Err(e) => {
+                                        self.drop_received(&conn_ctx, &recv_buffer_handles, e)?;
+                                        progress += 1;
+                                        continue;
+                                    }
+                                };
 
                                 // timer.tick();
                                 // 200-500ns
-                                let recv_id = self.unmarshal_and_deliver_up(
-                                    recv_ctx.sg_list,
-                                    Arc::clone(&conn_ctx),
-                                )?;
+                                let recv_id =
+                                    self.unmarshal_and_deliver_up(sgl, Arc::clone(&conn_ctx))?;
                                 // timer.tick();
 
                                 // 60-70ns
                                 // keep them outstanding because they will be used by the user
-                                self.recv_mr_usage
-                                    .insert(recv_id, recv_ctx.recv_buffer_handles);
+                                self.recv_mr_usage.insert(recv_id, recv_buffer_handles);
                                 // timer.tick();
                                 // log::info!("check_transport_service: {}", timer);
                             }
                             progress += 1;
                         }
-                        // The below two are probably errors in impl logic, so assert them
-                        WcOpcode::Invalid => panic!("invalid wc: {:?}", wc),
-                        _ => panic!("Unhandled wc opcode: {:?}", wc),
+                        // probably an error in impl logic
+                        _ => return Err(DatapathError::UnexpectedCompletion(format!("{:?}", wc))),
                     }
                 }
                 WcStatus::Error(code) => {
                     log::debug!("wc failed: {:?}", wc);
                     // TODO(cjr): bubble up the error, close the connection, and return an error
                     // to the user.
-                    let msg = if let Ok(wr_ctx) =
+                    let sent = if let Ok(wr_ctx) =
                         self.state.local_resource().wr_contexts.get(&wc.wr_id)
                     {
                         // this is a recv operation. don't know the rpc_id
-                        let conn_id = wr_ctx.conn_id;
-                        EngineRxMessage::RecvError(conn_id, TransportStatus::Error(code))
+                        let mut conn_id = wr_ctx.conn_id;
+                        if let Ok(conn_ctx) = self.state.local_resource().cmid_table.get(&conn_id) {
+                            conn_ctx.recv.lock().completed();
+                            conn_id = conn_ctx.conn_id;
+                        }
+                        let msg = EngineRxMessage::RecvError(conn_id, TransportStatus::Error(code));
+                        self.rx_outputs()[0].send(msg).map_err(DatapathError::from)
                     } else {
-                        // let rpc_id = RpcId::decode_u64(wc.wr_id);
-                        let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
-                        EngineRxMessage::Ack(rpc_id, TransportStatus::Error(code))
+                        self.ack_sends(wc.wr_id as usize, TransportStatus::Error(code))
                     };
-                    self.rx_outputs()[0].send(msg).unwrap_or_else(|e| {
+                    sent.unwrap_or_else(|e| {
                         log::warn!("error when bubbling up the error, send failed e: {}", e)
                     });
                     // the error is caused by unexpected shutdown of mrpc engine
@@ -856,27 +1393,69 @@ impl RpcAdapterEngine {
         }
 
         self.wc_read_buffer = comps;
+        self.arena = arena;
 
         // COMMENT(cjr): Progress(0) here is okay for now because we haven't use the progress as
         // any indicator.
         Ok(Status::Progress(progress))
     }
 
-    fn reclaim_recv_buffers(
+    /// Drops a received message that is malformed or fails to open. Its receive buffers are posted
+    /// again, and the drop is counted in the stats of the connection.
+    fn drop_received(
         &mut self,
-        cmid: &ulib::ucm::CmId,
-        mr_handles: &[Handle],
+        conn_ctx: &ConnectionContext,
+        recv_buffer_handles: &[Versioned<Handle>],
+        err: DatapathError,
+    ) -> Result<(), DatapathError> {
+        log::warn!(
+            "dropped a message received on {:?}: {}",
+            conn_ctx.cmid.as_handle(),
+            err
+        );
+        conn_ctx.traffic.dropped();
+        conn_ctx.recv.lock().reclaim(recv_buffer_handles);
+        self.replenish_recv_buffers(conn_ctx)
+    }
+
+    /// Posts a batch of receives on the connection, back up to the window, once the receives
+    /// outstanding have dropped below the low watermark.
+    fn replenish_recv_buffers(
+        &mut self,
+        conn_ctx: &ConnectionContext,
     ) -> Result<(), DatapathError> {
-        for handle in mr_handles {
-            let recv_buffer = self.state.local_resource().recv_buffer_table.get(handle)?;
+        let batch = conn_ctx.recv.lock().take_batch();
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut posted = 0;
+        for handle in &batch {
+            let recv_buffer = match self
+                .state
+                .local_resource()
+                .recv_buffer_table
+                .get_versioned(handle)
+            {
+                Ok(recv_buffer) => recv_buffer,
+                Err(ResourceError::Stale) => {
+                    log::warn!("skipped posting a stale receive buffer {:?}", handle);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
             let off = recv_buffer.addr();
             let len = recv_buffer.len();
 
             let odp_mr = self.odp_mr.as_mut().unwrap();
             unsafe {
-                cmid.post_recv(odp_mr, off..off + len, handle.0 as u64)?;
+                conn_ctx
+                    .cmid
+                    .post_recv(odp_mr, off..off + len, handle.key.0 as u64)?;
             }
+            posted += 1;
         }
+        conn_ctx.recv.lock().add_posted(posted);
         Ok(())
     }
 
@@ -897,22 +1476,54 @@ impl RpcAdapterEngine {
                     .set_send_cq(cq)
                     .set_recv_cq(cq)
                     .set_max_send_wr(128)
-                    .set_max_recv_wr(128)
-                    .set_max_inline_data(MAX_INLINE_DATA as _)
+                    .set_max_recv_wr(self.recv_window.window as u32)
+                    .set_max_inline_data(self.max_inline_data as _)
                     .build()?;
 
+                // answer the key the peer offers. A connection that must be encrypted is refused
+                // without one, by dropping its CmId.
+                let peer_addr = pre_id.get_peer_addr().ok();
+                let sealing = match pre_id.take_peer_private_data() {
+                    Some(data) if seal::offers_key(&data) => {
+                        let handshake = Handshake::new();
+                        let private_data = handshake.private_data();
+                        match handshake.finish(Role::Responder, &data) {
+                            Ok(cipher) => Some((private_data, cipher)),
+                            Err(e) => {
+                                log::warn!("refusing the connection from {:?}: {}", peer_addr, e);
+                                return Ok(Status::Progress(1));
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                if self.encryption && sealing.is_none() {
+                    log::warn!(
+                        "refusing the connection from {:?}: no key offered",
+                        peer_addr
+                    );
+                    return Ok(Status::Progress(1));
+                }
+
                 // prepare and post receive buffers
-                let (read_regions, fds) = self.prepare_recv_buffers(&mut pre_id)?;
+                let (read_regions, fds, recv) = self.prepare_recv_buffers(&mut pre_id)?;
                 let handle = pre_id.as_handle();
                 // move pre_cm_id to staging
+                let staged = StagedCmId {
+                    pre_id,
+                    recv,
+                    sealing,
+                };
                 self.state
                     .resource()
                     .staging_pre_cmid_table
-                    .insert(handle, pre_id)?;
+                    .insert(handle, staged)?;
                 // pass these resources back to the user
+                let conn_id = self.state.local_resource().insert_conn_id(handle);
                 let conn_resp = ConnectResponse {
-                    conn_handle: handle,
+                    conn_handle: conn_id,
                     read_regions,
+                    peer_addr,
                 };
                 let comp = cmd::Completion(Ok(cmd::CompletionKind::NewConnectionInternal(
                     conn_resp, fds,
@@ -926,43 +1537,49 @@ impl RpcAdapterEngine {
     fn prepare_recv_buffers(
         &mut self,
         pre_id: &mut ulib::ucm::PreparedCmId,
-    ) -> Result<(Vec<ReadHeapRegion>, Vec<RawFd>), ControlPathError> {
-        // create 128 receive mrs, post recv requests
+    ) -> Result<(Vec<ReadHeapRegion>, Vec<RawFd>, RecvReplenisher), ControlPathError> {
+        // create the receive mrs, post recv requests for the window and keep the rest free
         let slab = BufferSlab::new(
-            128,
-            8 * 1024 * 1024,
-            8 * 1024 * 1024,
+            self.recv_window.num_buffers,
+            RECV_BUFFER_SIZE,
+            RECV_BUFFER_SIZE,
             &self.salloc.addr_mediator,
         )?;
+        let mut recv = RecvReplenisher::new(self.recv_window);
 
-        // post receives
-        for _ in 0..128 {
-            let odp_mr = self.get_or_init_odp_mr(pre_id);
-
-            // This is fine because we just allocated 128 buffers there
+        for i in 0..self.recv_window.num_buffers {
+            // This is fine because we just allocated num_buffers buffers there
             let recv_buffer = slab.obtain().unwrap();
 
             let handle = recv_buffer.as_handle();
             let wr_id = handle.0 as u64;
-            let wr_ctx = WrContext {
-                conn_id: pre_id.as_handle(),
-                buffer_addr: recv_buffer.addr(),
-            };
-
             let off = recv_buffer.addr();
             let len = recv_buffer.len();
 
-            unsafe {
-                pre_id.post_recv(odp_mr, off..off + len, wr_id)?;
-            }
+            let buffer = self
+                .state
+                .local_resource()
+                .recv_buffer_table
+                .insert_versioned(handle, recv_buffer)?;
+            let wr_ctx = WrContext {
+                conn_id: pre_id.as_handle(),
+                buffer_addr: off,
+                buffer,
+            };
             self.state
                 .local_resource()
                 .wr_contexts
                 .insert(wr_id, wr_ctx)?;
-            self.state
-                .local_resource()
-                .recv_buffer_table
-                .insert(handle, recv_buffer)?;
+
+            if i < self.recv_window.window {
+                let odp_mr = self.get_or_init_odp_mr(pre_id);
+                unsafe {
+                    pre_id.post_recv(odp_mr, off..off + len, wr_id)?;
+                }
+                recv.add_posted(1);
+            } else {
+                recv.give(buffer);
+            }
         }
 
         let region = slab.storage();
@@ -977,7 +1594,7 @@ impl RpcAdapterEngine {
         // don't forget this
         self.state.resource().recv_buffer_pool.replenish(slab);
 
-        Ok((read_regions, fds))
+        Ok((read_regions, fds, recv))
     }
 
     async fn check_input_cmd_queue(&mut self) -> Result<Status, ControlPathError> {
@@ -996,6 +1613,61 @@ impl RpcAdapterEngine {
         }
     }
 
+    /// Connects to `addr`, with the receives of the connection posted.
+    async fn establish(&mut self, addr: &SocketAddr) -> Result<EstablishedConn, ControlPathError> {
+        // create CmIdBuilder
+        let mut builder = ulib::ucm::CmIdBuilder::new()
+            .set_max_send_wr(128)
+            .set_max_recv_wr(self.recv_window.window as u32)
+            .set_max_inline_data(self.max_inline_data as u32)
+            .resolve_route(addr)
+            .await?;
+
+        // create or get CQ
+        let cq = self.state.get_or_init_cq(2048, 0, &builder)?;
+
+        builder.set_send_cq(cq).set_recv_cq(cq);
+        let mut pre_id = builder.build()?;
+
+        // prepare and post receive buffers
+        let (read_regions, fds, recv) = self.prepare_recv_buffers(&mut pre_id)?;
+        // connect, offering a key if the connection is to be encrypted
+        let handshake = self.encryption.then(Handshake::new);
+        let private_data = handshake.as_ref().map(Handshake::private_data);
+        let conn_param = private_data.as_deref().map(ConnParam::with_private_data);
+        let id = pre_id.connect(conn_param.as_ref()).await?;
+        let cipher = match handshake {
+            Some(handshake) => {
+                let peer_data = id.take_peer_private_data().unwrap_or_default();
+                Some(handshake.finish(Role::Initiator, &peer_data)?)
+            }
+            None => None,
+        };
+        Ok(EstablishedConn {
+            id,
+            read_regions,
+            fds,
+            recv,
+            cipher,
+        })
+    }
+
+    /// Connects to the endpoints of the `prewarm` config, ahead of the application.
+    async fn prewarm_connections(&mut self) {
+        for addr in mem::take(&mut self.prewarm) {
+            if self.prewarmed.contains_key(&addr) {
+                continue;
+            }
+            match self.establish(&addr).await {
+                Ok(conn) => {
+                    log::debug!("Prewarmed a connection to {:?}", addr);
+                    self.prewarmed.insert(addr, conn);
+                }
+                Err(e) => log::warn!("fail to prewarm a connection to {:?}: {}", addr, e),
+            }
+        }
+    }
+
     async fn process_cmd(
         &mut self,
         req: &cmd::Command,
@@ -1006,33 +1678,30 @@ impl RpcAdapterEngine {
             }
             cmd::Command::Connect(addr) => {
                 log::debug!("Connect, addr: {:?}", addr);
-                // create CmIdBuilder
-                let mut builder = ulib::ucm::CmIdBuilder::new()
-                    .set_max_send_wr(128)
-                    .set_max_recv_wr(128)
-                    .set_max_inline_data(MAX_INLINE_DATA as u32)
-                    .resolve_route(addr)
-                    .await?;
-
-                // create or get CQ
-                let cq = self.state.get_or_init_cq(2048, 0, &builder)?;
-
-                builder.set_send_cq(cq).set_recv_cq(cq);
-                let mut pre_id = builder.build()?;
-
-                // prepare and post receive buffers
-                let (read_regions, fds) = self.prepare_recv_buffers(&mut pre_id)?;
-                // connect
-                let id = pre_id.connect(None).await?;
-                let handle = id.as_handle();
+                let conn = match self.prewarmed.remove(addr) {
+                    Some(conn) => conn,
+                    None => self.establish(addr).await?,
+                };
+                let conn_id = self
+                    .state
+                    .local_resource()
+                    .insert_conn_id(conn.id.as_handle());
 
                 // insert resources after connection establishment
-                self.state.local_resource().insert_cmid(id, 128)?;
+                let credit = self.recv_window.low_watermark;
+                self.state.local_resource().insert_cmid(
+                    conn.id,
+                    conn_id,
+                    credit,
+                    conn.recv,
+                    conn.cipher,
+                )?;
                 let conn_resp = ConnectResponse {
-                    conn_handle: handle,
-                    read_regions,
+                    conn_handle: conn_id,
+                    read_regions: conn.read_regions,
+                    peer_addr: Some(*addr),
                 };
-                Ok(cmd::CompletionKind::ConnectInternal(conn_resp, fds))
+                Ok(cmd::CompletionKind::ConnectInternal(conn_resp, conn.fds))
             }
             cmd::Command::Bind(addr) => {
                 // create CmIdBuilder
@@ -1047,28 +1716,39 @@ impl RpcAdapterEngine {
             cmd::Command::NewMappedAddrs(conn_handle, app_vaddrs) => {
                 for (mr_handle, app_vaddr) in app_vaddrs.iter() {
                     let region = self.state.resource().recv_buffer_pool.find(mr_handle)?;
-                    let mr_local_addr = region.as_ptr().expose_addr();
-                    let mr_remote_mapped = mrpc_marshal::ShmRecvMr {
-                        ptr: *app_vaddr,
-                        len: region.len(),
-                        align: region.align(),
-                    };
+                    let mr_local_addr = region.addr();
+                    let mr_remote_mapped = region.map_app(*app_vaddr);
                     self.state
                         .local_resource()
                         .addr_map
                         .insert_addr_map(mr_local_addr, mr_remote_mapped)?;
                 }
                 // finish the last step to establish a connection
-                if let Ok(Some(pre_id)) = self
+                let cmid_handle = self.state.local_resource().cmid_handle(*conn_handle)?;
+                if let Ok(Some(staged)) = self
                     .state
                     .resource()
                     .staging_pre_cmid_table
-                    .close_resource(conn_handle)
+                    .close_resource(&cmid_handle)
                 {
+                    let StagedCmId {
+                        pre_id,
+                        recv,
+                        sealing,
+                    } = Arc::try_unwrap(staged).unwrap();
+                    let (private_data, cipher) = sealing.unzip();
+                    let conn_param = private_data.as_deref().map(ConnParam::with_private_data);
                     // accept connection after we get the AddrMap updated
-                    let id = Arc::try_unwrap(pre_id).unwrap().accept(None).await?;
+                    let id = pre_id.accept(conn_param.as_ref()).await?;
                     // insert resources after connection establishment
-                    self.state.local_resource().insert_cmid(id, 128)?;
+                    let credit = self.recv_window.low_watermark;
+                    self.state.local_resource().insert_cmid(
+                        id,
+                        *conn_handle,
+                        credit,
+                        recv,
+                        cipher,
+                    )?;
                 }
                 Ok(cmd::CompletionKind::NewMappedAddrs)
             }
@@ -1084,6 +1764,36 @@ impl RpcAdapterEngine {
             cmd::Command::MultiConnect(_) => {
                 unreachable!();
             }
+            cmd::Command::UpdateDescriptors(_) => {
+                unreachable!();
+            }
+            cmd::Command::SetMessageSizeLimit(service_id, limit) => {
+                log::debug!(
+                    "SetMessageSizeLimit, service_id: {:?}, {:?}",
+                    service_id,
+                    limit
+                );
+                self.size_limits.update(*service_id, *limit);
+                Ok(cmd::CompletionKind::SetMessageSizeLimit)
+            }
+            cmd::Command::ConnectionStats(conn_handle) => {
+                let conn_ctx = self.state.local_resource().conn(*conn_handle)?;
+                let traffic = &conn_ctx.traffic;
+                let recv = conn_ctx.recv.lock();
+                let stats = cmd::TransportStats {
+                    bytes_sent: traffic.bytes_sent.load(Ordering::Relaxed),
+                    messages_sent: traffic.messages_sent.load(Ordering::Relaxed),
+                    bytes_received: traffic.bytes_received.load(Ordering::Relaxed),
+                    messages_received: traffic.messages_received.load(Ordering::Relaxed),
+                    messages_dropped: traffic.messages_dropped.load(Ordering::Relaxed),
+                    // the NIC retransmits on its own
+                    retransmits: None,
+                    credits: Some(conn_ctx.credit.load(Ordering::Acquire)),
+                    recv_heap_in_use: recv.in_use() * RECV_BUFFER_SIZE,
+                    recv_heap_size: recv.num_buffers() * RECV_BUFFER_SIZE,
+                };
+                Ok(cmd::CompletionKind::ConnectionStats(stats))
+            }
         }
     }
 }
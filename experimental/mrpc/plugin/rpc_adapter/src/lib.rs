@@ -20,11 +20,14 @@ pub mod state;
 pub(crate) mod acceptor;
 pub mod config;
 pub(crate) mod engine;
+pub(crate) mod recv;
+pub(crate) mod sched;
 pub(crate) mod serialization;
 pub(crate) mod ulib;
 
-#[allow(unused)]
-pub(crate) mod pool;
+// public for the benchmarks only
+#[doc(hidden)]
+pub mod pool;
 
 #[derive(Error, Debug)]
 #[error("rpc-adapter control path error")]
@@ -40,6 +43,8 @@ pub(crate) enum ControlPathError {
     SharedRegion(#[from] region::Error),
     #[error("{0}")]
     InsertAddrMap(#[from] mrpc_marshal::AddressExists),
+    #[error("Key exchange error: {0}")]
+    Seal(#[from] mrpc_marshal::seal::SealError),
 
     // Below are errors that does not return to the user.
     #[error("Send command error")]
@@ -52,12 +57,16 @@ pub(crate) enum ControlPathError {
 
 impl From<ControlPathError> for phoenix_api::Error {
     fn from(other: ControlPathError) -> Self {
-        phoenix_api::Error::Generic(other.to_string())
+        let code = match &other {
+            ControlPathError::Resource(e) => e.code(),
+            _ => phoenix_api::ErrorCode::Internal,
+        };
+        phoenix_api::Error::from_error(code, &other)
     }
 }
 
 // use crate::engine::graph::SendError;
-use phoenix_common::engine::datapath::EngineTxMessage;
+use phoenix_common::engine::datapath::{EngineRxMessage, EngineTxMessage};
 use tokio::sync::mpsc::error::SendError;
 
 impl<T> From<SendError<T>> for ControlPathError {
@@ -74,6 +83,16 @@ pub(crate) enum DatapathError {
     Ulib(#[from] ulib::Error),
     #[error("Tx queue send error: {0}")]
     Tx(#[from] phoenix_common::engine::datapath::SendError<EngineTxMessage>),
+    #[error("Rx queue send error: {0}")]
+    Rx(#[from] phoenix_common::engine::datapath::SendError<EngineRxMessage>),
+    #[error("Wire format error: {0}")]
+    Wire(#[from] phoenix_api::wire::WireError),
+    #[error("Sealed message error: {0}")]
+    Seal(#[from] mrpc_marshal::seal::SealError),
+    #[error("Message is not sealed on an encrypted connection")]
+    NotSealed,
+    #[error("Unexpected work completion: {0}")]
+    UnexpectedCompletion(String),
 }
 
 use crate::config::RpcAdapterConfig;
@@ -1,11 +1,15 @@
 use anyhow::{anyhow, bail, Result};
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use nix::unistd::Pid;
 
+use mrpc_marshal::SgList;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api_mrpc::cmd;
+use phoenix_api_mrpc::control_plane::Setting;
 
 use phoenix_salloc::module::SallocModule;
 use phoenix_salloc::region::AddressMediator;
@@ -13,6 +17,8 @@ use phoenix_salloc::state::{Shared as SallocShared, State as SallocState};
 use transport_rdma::module::RdmaTransportModule;
 use transport_rdma::ops::Ops;
 
+use phoenix_common::capability::Capabilities;
+use phoenix_common::engine::datapath::arena::Arena;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
 use phoenix_common::log;
@@ -25,6 +31,8 @@ use phoenix_common::storage::{ResourceCollection, SharedStorage};
 use crate::acceptor::engine::AcceptorEngine;
 use crate::config::RpcAdapterConfig;
 use crate::engine::{RpcAdapterEngine, TlStorage};
+use crate::recv::RecvWindow;
+use crate::sched::{SendBudgets, SendQueue};
 use crate::state::{Shared, State};
 
 pub(crate) struct AcceptorEngineBuilder {
@@ -53,6 +61,13 @@ impl AcceptorEngineBuilder {
 
 pub(crate) struct RpcAdapterEngineBuilder {
     _client_pid: Pid,
+    max_inline_data: usize,
+    send_signal_interval: usize,
+    recv_window: RecvWindow,
+    send_budgets: SendBudgets,
+    encryption: bool,
+    batch_size: usize,
+    quantum: usize,
     mode: SchedulingMode,
     cmd_tx: tokio::sync::mpsc::UnboundedSender<phoenix_api_mrpc::cmd::Completion>,
     cmd_rx: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
@@ -61,6 +76,7 @@ pub(crate) struct RpcAdapterEngineBuilder {
     shared: Arc<Shared>,
     salloc_shared: Arc<SallocShared>,
     addr_mediator: Arc<AddressMediator>,
+    prewarm: Vec<SocketAddr>,
 }
 
 impl RpcAdapterEngineBuilder {
@@ -68,6 +84,13 @@ impl RpcAdapterEngineBuilder {
     fn new(
         client_pid: Pid,
         _enable_scheduler: bool,
+        max_inline_data: usize,
+        send_signal_interval: usize,
+        recv_window: RecvWindow,
+        send_budgets: SendBudgets,
+        encryption: bool,
+        batch_size: usize,
+        quantum: usize,
         mode: SchedulingMode,
         cmd_tx: tokio::sync::mpsc::UnboundedSender<phoenix_api_mrpc::cmd::Completion>,
         cmd_rx: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
@@ -76,9 +99,17 @@ impl RpcAdapterEngineBuilder {
         shared: Arc<Shared>,
         salloc_shared: Arc<SallocShared>,
         addr_mediator: Arc<AddressMediator>,
+        prewarm: Vec<SocketAddr>,
     ) -> Self {
         RpcAdapterEngineBuilder {
             _client_pid: client_pid,
+            max_inline_data,
+            send_signal_interval,
+            recv_window,
+            send_budgets,
+            encryption,
+            batch_size,
+            quantum,
             mode,
             cmd_tx,
             cmd_rx,
@@ -87,6 +118,7 @@ impl RpcAdapterEngineBuilder {
             shared,
             salloc_shared,
             addr_mediator,
+            prewarm,
         }
     }
 
@@ -100,7 +132,8 @@ impl RpcAdapterEngineBuilder {
             odp_mr: None,
             tls: Box::new(TlStorage { ops: self.ops }),
             pending_recv: 0,
-            local_buffer: VecDeque::new(),
+            local_buffer: SendQueue::default(),
+            send_budgets: self.send_budgets,
             cmd_tx: self.cmd_tx,
             cmd_rx: self.cmd_rx,
             node: self.node,
@@ -108,9 +141,21 @@ impl RpcAdapterEngineBuilder {
             indicator: Default::default(),
             recv_mr_usage: fnv::FnvHashMap::default(),
             serialization_engine: None,
+            size_limits: Default::default(),
+            max_inline_data: self.max_inline_data,
+            send_signal_interval: self.send_signal_interval.max(1),
+            recv_window: self.recv_window,
+            encryption: self.encryption,
             rpc_ctx: slab::Slab::with_capacity(128),
-            wc_read_buffer: Vec::with_capacity(BUF_LEN),
+            wc_read_buffer: Vec::with_capacity(self.batch_size),
+            quantum: self.quantum,
+            sgl_buffer: SgList(Vec::with_capacity(BUF_LEN)),
+            seal_buffer: Vec::new(),
+            arena: Arena::new(),
+            sealed_buffers: fnv::FnvHashMap::default(),
             salloc: salloc_state,
+            prewarm: self.prewarm,
+            prewarmed: fnv::FnvHashMap::default(),
         })
     }
 }
@@ -174,6 +219,36 @@ impl PhoenixModule for RpcAdapterModule {
         Self::DEPENDENCIES
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // The engines resolve the addresses to connect to and bind, talk to the devices through
+        // the verbs and the connection manager, and share the memfds of the regions allocated by
+        // the applications.
+        Capabilities {
+            read_paths: [
+                "/etc/hosts",
+                "/etc/host.conf",
+                "/etc/nsswitch.conf",
+                "/etc/resolv.conf",
+                "/etc/gai.conf",
+                "/sys/class/infiniband",
+                "/sys/devices",
+            ]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+            devices: vec!["/dev/infiniband".into()],
+            syscalls: vec![
+                libc::SYS_memfd_create,
+                libc::SYS_ftruncate,
+                libc::SYS_socket,
+                libc::SYS_bind,
+                libc::SYS_connect,
+                libc::SYS_getsockname,
+            ],
+            ..Default::default()
+        }
+    }
+
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
@@ -306,11 +381,18 @@ impl RpcAdapterModule {
         node: DataPathNode,
         salloc: &mut SallocModule,
         rdma_transport: &mut RdmaTransportModule,
-        _config_string: Option<String>,
+        config_string: Option<String>,
     ) -> Result<RpcAdapterEngine> {
         // Acceptor engine should already been created at this moment
         let ops = rdma_transport.create_ops(client_pid)?;
 
+        // the client may ask for encryption in its setting
+        let setting: Option<Setting> = config_string
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()?;
+        let encryption = self.config.encryption || setting.map_or(false, |s| s.encryption);
+
         // Get salloc state
         let addr_mediator = salloc.get_addr_mediator();
         let addr_mediator_clone = Arc::clone(&addr_mediator);
@@ -322,6 +404,20 @@ impl RpcAdapterModule {
         let builder = RpcAdapterEngineBuilder::new(
             client_pid,
             self.config.enable_scheduler,
+            self.config.max_inline_data,
+            self.config.send_signal_interval,
+            RecvWindow::new(
+                self.config.recv_window,
+                self.config.recv_low_watermark,
+                self.config.recv_buffers,
+            ),
+            SendBudgets::new(
+                &self.config.send_budgets,
+                self.config.default_send_budget_us,
+            ),
+            encryption,
+            self.config.batch_size,
+            self.config.quantum.unwrap_or(usize::MAX),
             mode,
             cmd_tx,
             cmd_rx,
@@ -330,6 +426,7 @@ impl RpcAdapterModule {
             shared,
             salloc_shared,
             addr_mediator,
+            self.config.prewarm_endpoints(),
         );
         let engine = builder.build()?;
         Ok(engine)
@@ -1,13 +1,13 @@
 //! A pool of receive buffers. The buffers are shared among connections.
 use std::alloc::Layout;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use bitvec::bitvec;
-use bitvec::vec::BitVec;
+use arc_swap::ArcSwap;
 
 use phoenix_api::{AsHandle, Handle};
 
-use phoenix_salloc::region::{AddressMediator, SharedRegion};
+use shm::region::{AddressMediator, BufferSlice, ShmRegion};
 
 use phoenix_common::resource::Error as ResourceError;
 
@@ -15,50 +15,22 @@ use super::ControlPathError;
 
 /// A reference handed by `BufferPool`, pointed to one particular memory segment in one of the
 /// backing storage of `BufferPool`. Multiple `RecvBuffer`s cannot overlap with each other.
-pub(crate) struct RecvBuffer {
-    offset: usize,
-    len: usize,
-    align: usize,
-    /// The backing storage. A `RecvBuffer` can only belong to one SharedRegion.
-    storage: Arc<SharedRegion>,
-}
-
-impl AsHandle for RecvBuffer {
-    fn as_handle(&self) -> Handle {
-        let high = self.storage.as_handle().0;
-        let low = self.offset / self.len;
-        assert!(high < (1 << 16), "Please consider reduce the number of underlying storage or widen the Handle type to 64-bit");
-        assert!(low < (1 << 16), "Please consider reduce the number of recv buffers inside a slab or widen the Handle type to 64-bit");
-        Handle(((high * (1 << 16)) as u32 + low as u32) as u64)
-    }
-}
-
-impl RecvBuffer {
-    #[inline]
-    pub(crate) fn addr(&self) -> usize {
-        self.storage.as_ptr().expose_addr() + self.offset
-    }
-
-    #[inline]
-    pub(crate) fn len(&self) -> usize {
-        self.len
-    }
-
-    #[inline]
-    pub(crate) fn align(&self) -> usize {
-        self.align
-    }
-}
+pub type RecvBuffer = BufferSlice;
 
 /// A thread-safe buffer slab.
-pub(crate) struct BufferSlab {
+///
+/// The buffers are obtained and released by the engines on the datapath without locking. Each bit
+/// of the bitmap is claimed with a compare-and-swap on its word, so two engines racing for the
+/// same buffer retry on the next free bit instead of waiting on each other.
+pub struct BufferSlab {
     num_buffers: usize,
     buffer_size: usize,
     buffer_align: usize,
     /// The list of backing storage.
-    storage: Arc<SharedRegion>,
-    /// Record which index is borrowed. 1 used, 0 unused.
-    bitmap: spin::Mutex<BitVec>,
+    storage: Arc<ShmRegion>,
+    /// Record which index is borrowed. 1 used, 0 unused. The bits past `num_buffers` in the
+    /// last word are always 1.
+    bitmap: Box<[AtomicU64]>,
 }
 
 impl BufferSlab {
@@ -79,68 +51,97 @@ impl BufferSlab {
         let buffer_size = buffer_size.max(buffer_align);
         let total_size = num_buffers * buffer_size;
 
-        // allocate a SharedRegion
+        // allocate a ShmRegion
         let layout = Layout::from_size_align(total_size, buffer_align)?;
-        let region = Arc::new(SharedRegion::new(layout, addr_mediator)?);
+        let region = Arc::new(ShmRegion::new(layout, addr_mediator)?);
+
+        let bitmap = (0..(num_buffers + 63) / 64)
+            .map(|i| {
+                let used = (num_buffers - i * 64).min(64);
+                AtomicU64::new(if used == 64 { 0 } else { !0u64 << used })
+            })
+            .collect();
 
         Ok(Self {
             num_buffers,
             buffer_size,
             buffer_align,
             storage: region,
-            bitmap: spin::Mutex::new(bitvec![0; num_buffers]),
+            bitmap,
         })
     }
 
     #[inline]
-    pub(crate) fn storage(&self) -> Arc<SharedRegion> {
+    pub(crate) fn storage(&self) -> Arc<ShmRegion> {
         Arc::clone(&self.storage)
     }
 
-    pub(crate) fn obtain(&self) -> Option<RecvBuffer> {
-        let mut bitmap = self.bitmap.lock();
-        if let Some(unused) = bitmap.iter_zeros().next() {
-            let offset = unused * self.buffer_size;
-            let len = self.buffer_size;
-            bitmap.set(offset / len, true);
-            Some(RecvBuffer {
-                offset,
-                len,
-                align: self.buffer_align,
-                storage: Arc::clone(&self.storage),
-            })
-        } else {
-            None
+    /// Claims the first unused buffer, returns `None` if all are borrowed.
+    pub fn obtain(&self) -> Option<RecvBuffer> {
+        for (i, word) in self.bitmap.iter().enumerate() {
+            let mut current = word.load(Ordering::Relaxed);
+            while current != !0 {
+                let bit = (!current).trailing_zeros() as usize;
+                match word.compare_exchange_weak(
+                    current,
+                    current | (1 << bit),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let index = i * 64 + bit;
+                        debug_assert!(index < self.num_buffers);
+                        return Some(BufferSlice::new(
+                            Arc::clone(&self.storage),
+                            index * self.buffer_size,
+                            self.buffer_size,
+                            self.buffer_align,
+                        ));
+                    }
+                    Err(actual) => current = actual,
+                }
+            }
         }
+        None
     }
 
-    pub(crate) fn release(&self, recv_buf: RecvBuffer) {
-        self.bitmap
-            .lock()
-            .set(recv_buf.offset / recv_buf.len, false);
+    /// Gives back a buffer obtained from this slab.
+    pub fn release(&self, recv_buf: RecvBuffer) {
+        let index = recv_buf.offset() / self.buffer_size;
+        debug_assert!(index < self.num_buffers);
+        let prev = self.bitmap[index / 64].fetch_and(!(1 << (index % 64)), Ordering::Release);
+        debug_assert!(prev & (1 << (index % 64)) != 0, "double release of {index}");
     }
 }
 
-/// A thread-safe buffer slab.
-pub(crate) struct BufferPool {
-    slabs: spin::Mutex<Vec<BufferSlab>>,
+/// A thread-safe buffer pool.
+///
+/// The pool is shared by the engines and read on the datapath, while slabs are rarely added. The
+/// list of slabs is therefore copied on write and read without locking.
+pub struct BufferPool {
+    slabs: ArcSwap<Vec<Arc<BufferSlab>>>,
     addr_mediator: Arc<AddressMediator>,
 }
 
 impl BufferPool {
-    pub(crate) fn new(addr_mediator: Arc<AddressMediator>) -> Self {
+    pub fn new(addr_mediator: Arc<AddressMediator>) -> Self {
         Self {
-            slabs: spin::Mutex::new(Vec::new()),
+            slabs: ArcSwap::from_pointee(Vec::new()),
             addr_mediator,
         }
     }
 
-    pub(crate) fn replenish(&self, slab: BufferSlab) {
-        self.slabs.lock().push(slab);
+    pub fn replenish(&self, slab: BufferSlab) {
+        let slab = Arc::new(slab);
+        self.slabs.rcu(|slabs| {
+            let mut slabs = Vec::clone(slabs);
+            slabs.push(Arc::clone(&slab));
+            slabs
+        });
     }
 
-    pub(crate) fn obtain(&self) -> RecvBuffer {
-        for slab in self.slabs.lock().iter() {
+    pub fn obtain(&self) -> RecvBuffer {
+        for slab in self.slabs.load().iter() {
             if let Some(ret) = slab.obtain() {
                 return ret;
             }
@@ -153,10 +154,10 @@ impl BufferPool {
         self.obtain()
     }
 
-    pub(crate) fn release(&self, recv_buf: RecvBuffer) {
+    pub fn release(&self, recv_buf: RecvBuffer) {
         // TODO(cjr): update the impl
-        for slab in self.slabs.lock().iter() {
-            if Arc::ptr_eq(&slab.storage, &recv_buf.storage) {
+        for slab in self.slabs.load().iter() {
+            if Arc::ptr_eq(&slab.storage, recv_buf.region()) {
                 slab.release(recv_buf);
                 return;
             }
@@ -164,9 +165,9 @@ impl BufferPool {
         unreachable!()
     }
 
-    pub(crate) fn find(&self, handle: &Handle) -> Result<Arc<SharedRegion>, ControlPathError> {
+    pub(crate) fn find(&self, handle: &Handle) -> Result<Arc<ShmRegion>, ControlPathError> {
         self.slabs
-            .lock()
+            .load()
             .iter()
             .find_map(|s| {
                 if &s.storage.as_handle() == handle {
@@ -178,3 +179,23 @@ impl BufferPool {
             .map_or_else(|| Err(ResourceError::NotFound.into()), Ok)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obtain_every_buffer_once() {
+        // the last word of the bitmap is partly used
+        let slab = BufferSlab::new(70, 4096, 4096, &AddressMediator::new()).unwrap();
+        let mut bufs: Vec<_> = std::iter::from_fn(|| slab.obtain()).collect();
+        assert_eq!(bufs.len(), 70);
+        let offsets: Vec<_> = bufs.iter().map(|b| b.offset()).collect();
+        assert_eq!(offsets, (0..70).map(|i| i * 4096).collect::<Vec<_>>());
+
+        let buf = bufs.swap_remove(65);
+        slab.release(buf);
+        assert_eq!(slab.obtain().map(|b| b.offset()), Some(65 * 4096));
+        assert!(slab.obtain().is_none());
+    }
+}
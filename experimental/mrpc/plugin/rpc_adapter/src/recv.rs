@@ -0,0 +1,101 @@
+//! Keeping the receive queue of a connection stocked.
+//!
+//! Each connection gets [`RecvWindow::num_buffers`] receive buffers, more than the
+//! [`RecvWindow::window`] receives it keeps posted, so that the receive queue can be refilled
+//! while the application still holds the buffers of the messages delivered to it. The receives
+//! are not posted again one by one as the buffers come back, but in a batch back up to the
+//! window once the ones outstanding drop below [`RecvWindow::low_watermark`].
+use phoenix_api::Handle;
+use phoenix_common::resource::Versioned;
+
+/// The receive flow-control window of the connections, see [`RpcAdapterConfig`].
+///
+/// [`RpcAdapterConfig`]: crate::config::RpcAdapterConfig
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RecvWindow {
+    /// The receives posted on a connection after a batch, and its receive queue depth.
+    pub(crate) window: usize,
+    /// A batch is posted when fewer receives than this are outstanding. It is also the credit
+    /// of the peer, i.e., the work requests it may have in flight.
+    pub(crate) low_watermark: usize,
+    /// The receive buffers allocated for a connection.
+    pub(crate) num_buffers: usize,
+}
+
+impl RecvWindow {
+    pub(crate) fn new(window: usize, low_watermark: usize, num_buffers: usize) -> Self {
+        let window = window.max(1);
+        RecvWindow {
+            window,
+            low_watermark: low_watermark.clamp(1, window),
+            num_buffers: num_buffers.max(window),
+        }
+    }
+}
+
+/// The receives outstanding on a connection and the buffers free to post.
+///
+/// A buffer is referred to by its versioned handle in the receive buffer table, so a handle
+/// kept past the buffer never posts the buffer allocated at the same address later.
+#[derive(Debug)]
+pub(crate) struct RecvReplenisher {
+    window: RecvWindow,
+    posted: usize,
+    free: Vec<Versioned<Handle>>,
+}
+
+impl RecvReplenisher {
+    pub(crate) fn new(window: RecvWindow) -> Self {
+        RecvReplenisher {
+            window,
+            posted: 0,
+            free: Vec::with_capacity(window.num_buffers),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn add_posted(&mut self, n: usize) {
+        self.posted += n;
+    }
+
+    /// A receive has completed, in success or in error.
+    #[inline]
+    pub(crate) fn completed(&mut self) {
+        self.posted = self.posted.saturating_sub(1);
+    }
+
+    /// The application has returned the buffers.
+    #[inline]
+    pub(crate) fn reclaim(&mut self, handles: &[Versioned<Handle>]) {
+        self.free.extend_from_slice(handles);
+    }
+
+    #[inline]
+    pub(crate) fn give(&mut self, handle: Versioned<Handle>) {
+        self.free.push(handle);
+    }
+
+    /// The receive buffers of the connection.
+    #[inline]
+    pub(crate) fn num_buffers(&self) -> usize {
+        self.window.num_buffers
+    }
+
+    /// The receive buffers neither posted nor free, which the application holds.
+    #[inline]
+    pub(crate) fn in_use(&self) -> usize {
+        self.window
+            .num_buffers
+            .saturating_sub(self.posted + self.free.len())
+    }
+
+    /// Takes the buffers to post receives with, nothing while the receives outstanding are
+    /// not below the low watermark.
+    pub(crate) fn take_batch(&mut self) -> Vec<Versioned<Handle>> {
+        if self.posted >= self.window.low_watermark {
+            return Vec::new();
+        }
+        let n = (self.window.window - self.posted).min(self.free.len());
+        self.free.split_off(self.free.len() - n)
+    }
+}
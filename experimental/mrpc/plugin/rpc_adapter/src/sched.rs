@@ -0,0 +1,140 @@
+//! Sending the messages queued for credits earliest deadline first.
+//!
+//! A message waits in the engine while its connection is out of credits. Taking the messages
+//! in order lets a short call wait behind the bulk transfers queued before it, so each message
+//! gets a deadline when it is queued, from the budget of its method, and the one with the
+//! earliest deadline is sent first. The messages of the same budget keep their order, so do the
+//! replies on a connection, which the client matches with its requests in order.
+//!
+//! The queue also counts the messages of each connection, so that the last message queued for a
+//! connection is signaled when sent, see `RpcAdapterEngine::signal_message`.
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+
+use phoenix_api::rpc::{MessageMeta, RpcMsgType};
+use phoenix_api::Handle;
+use phoenix_common::engine::datapath::message::RpcMessageTx;
+
+use crate::config::SendBudget;
+
+/// The budgets of the requests, see [`RpcAdapterConfig`].
+///
+/// [`RpcAdapterConfig`]: crate::config::RpcAdapterConfig
+#[derive(Debug, Clone)]
+pub(crate) struct SendBudgets {
+    methods: FnvHashMap<(u32, u32), Duration>,
+    services: FnvHashMap<u32, Duration>,
+    default: Duration,
+}
+
+impl SendBudgets {
+    pub(crate) fn new(budgets: &[SendBudget], default_us: u64) -> Self {
+        let mut methods = FnvHashMap::default();
+        let mut services = FnvHashMap::default();
+        for budget in budgets {
+            let duration = Duration::from_micros(budget.budget_us);
+            match budget.func_id {
+                Some(func_id) => methods.insert((budget.service_id, func_id), duration),
+                None => services.insert(budget.service_id, duration),
+            };
+        }
+        SendBudgets {
+            methods,
+            services,
+            default: Duration::from_micros(default_us),
+        }
+    }
+
+    /// The budget of a message, that of its method takes precedence over that of its service.
+    pub(crate) fn budget(&self, meta: &MessageMeta) -> Duration {
+        if meta.msg_type != RpcMsgType::Request {
+            return self.default;
+        }
+        self.methods
+            .get(&(meta.service_id, meta.func_id))
+            .or_else(|| self.services.get(&meta.service_id))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// A message waiting to be sent.
+#[derive(Debug)]
+pub(crate) struct Queued {
+    deadline: Instant,
+    // breaks the ties in the order the messages are queued
+    seq: u64,
+    conn_id: Handle,
+    pub(crate) msg: RpcMessageTx,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        (self.deadline, self.seq) == (other.deadline, other.seq)
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+/// The messages waiting to be sent, earliest deadline first.
+#[derive(Debug, Default)]
+pub(crate) struct SendQueue {
+    heap: BinaryHeap<Reverse<Queued>>,
+    next_seq: u64,
+    /// The number of messages queued for each connection
+    per_conn: FnvHashMap<Handle, usize>,
+}
+
+impl SendQueue {
+    pub(crate) fn push(&mut self, msg: RpcMessageTx, conn_id: Handle, budget: Duration) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.requeue(Queued {
+            deadline: Instant::now() + budget,
+            seq,
+            conn_id,
+            msg,
+        });
+    }
+
+    /// Takes the message of the earliest deadline.
+    #[inline]
+    pub(crate) fn pop(&mut self) -> Option<Queued> {
+        let Reverse(queued) = self.heap.pop()?;
+        if let Some(count) = self.per_conn.get_mut(&queued.conn_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_conn.remove(&queued.conn_id);
+            }
+        }
+        Some(queued)
+    }
+
+    /// Puts back a message popped but not sent, ahead of those queued after it.
+    #[inline]
+    pub(crate) fn requeue(&mut self, queued: Queued) {
+        *self.per_conn.entry(queued.conn_id).or_default() += 1;
+        self.heap.push(Reverse(queued));
+    }
+
+    /// Whether a message of the connection is waiting.
+    #[inline]
+    pub(crate) fn has_queued(&self, conn_id: Handle) -> bool {
+        self.per_conn.contains_key(&conn_id)
+    }
+}
@@ -1,23 +1,28 @@
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::io;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use dashmap::DashMap;
 use fnv::FnvBuildHasher;
 use nix::unistd::Pid;
 
+use mrpc_marshal::seal::SessionCipher;
 use mrpc_marshal::SgList;
 use phoenix_api::rpc::CallId;
 use phoenix_api::AsHandle;
+use phoenix_api_mrpc::cmd::ReadHeapRegion;
 
 use phoenix_salloc::region::AddressMediator;
 
 use phoenix_common::local_resource::{LocalResourceTable, LocalResourceTableGeneric};
-use phoenix_common::resource::{Error as ResourceError, ResourceTable};
+use phoenix_common::resource::{Error as ResourceError, ResourceTable, Versioned, VersionedSlab};
 use phoenix_common::state_mgr::ProcessShared;
 
 use super::pool::{BufferPool, RecvBuffer};
+use super::recv::RecvReplenisher;
 use super::serialization::AddressMap;
 use super::ulib;
 
@@ -80,6 +85,8 @@ impl Shared {
 pub(crate) struct WrContext {
     pub(crate) conn_id: phoenix_api::Handle,
     pub(crate) buffer_addr: usize,
+    // the receive buffer in the recv_buffer_table
+    pub(crate) buffer: Versioned<phoenix_api::Handle>,
 }
 
 #[derive(Debug)]
@@ -94,31 +101,86 @@ pub(crate) struct RecvContext {
     // buffer for recevied sges
     pub(crate) sg_list: SgList,
     // recv mrs that received sges are on
-    pub(crate) recv_buffer_handles: Vec<phoenix_api::Handle>,
+    pub(crate) recv_buffer_handles: Vec<Versioned<phoenix_api::Handle>>,
+}
+
+/// The messages and the bytes sent and received on a connection.
+#[derive(Debug, Default)]
+pub(crate) struct Traffic {
+    pub(crate) bytes_sent: AtomicU64,
+    pub(crate) messages_sent: AtomicU64,
+    pub(crate) bytes_received: AtomicU64,
+    pub(crate) messages_received: AtomicU64,
+    pub(crate) messages_dropped: AtomicU64,
+}
+
+impl Traffic {
+    #[inline]
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn dropped(&self) {
+        self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct ConnectionContext {
     pub(crate) cmid: ulib::ucm::CmId,
+    // the versioned handle the application refers to the connection by
+    pub(crate) conn_id: phoenix_api::Handle,
     pub(crate) credit: AtomicUsize,
     // call_id, sg_len
     pub(crate) outstanding_req: spin::Mutex<VecDeque<ReqContext>>,
     pub(crate) receiving_ctx: spin::Mutex<RecvContext>,
+    // work requests posted unsignaled since the last signaled one
+    pub(crate) unsignaled_wrs: AtomicUsize,
+    // rpc contexts of the messages sent, in order, until their sends complete
+    pub(crate) unacked: spin::Mutex<VecDeque<usize>>,
+    // receives outstanding and receive buffers free to post
+    pub(crate) recv: spin::Mutex<RecvReplenisher>,
+    // the keys the messages are sealed with, if the connection is encrypted
+    pub(crate) cipher: Option<SessionCipher>,
+    pub(crate) traffic: Traffic,
 }
 
 impl ConnectionContext {
-    pub(crate) fn new(cmid: ulib::ucm::CmId, credit: usize) -> Self {
+    pub(crate) fn new(
+        cmid: ulib::ucm::CmId,
+        conn_id: phoenix_api::Handle,
+        credit: usize,
+        recv: RecvReplenisher,
+        cipher: Option<SessionCipher>,
+    ) -> Self {
         Self {
             cmid,
+            conn_id,
             credit: AtomicUsize::new(credit),
             outstanding_req: spin::Mutex::new(VecDeque::new()),
             receiving_ctx: spin::Mutex::new(RecvContext::default()),
+            unsignaled_wrs: AtomicUsize::new(0),
+            unacked: spin::Mutex::new(VecDeque::new()),
+            recv: spin::Mutex::new(recv),
+            cipher,
+            traffic: Traffic::default(),
         }
     }
 }
 
 pub struct LocalResource {
     pub(crate) cmid_table: LocalResourceTable<ConnectionContext>,
+    // the conn_id the application refers to a connection by -> cmid handle, see `conn`
+    pub(crate) conn_ids: RefCell<VersionedSlab<phoenix_api::Handle>>,
     // wr_id -> WrContext
     pub(crate) wr_contexts: LocalResourceTableGeneric<u64, WrContext>,
     // TODO(wyj): redesign these states
@@ -133,6 +195,7 @@ impl LocalResource {
     fn new() -> Self {
         Self {
             cmid_table: LocalResourceTable::default(),
+            conn_ids: RefCell::new(VersionedSlab::default()),
             wr_contexts: LocalResourceTableGeneric::default(),
             recv_buffer_table: LocalResourceTable::default(),
             addr_map: AddressMap::new(),
@@ -140,17 +203,70 @@ impl LocalResource {
         }
     }
 
+    /// Gives out the `conn_id` the application refers to the connection on `cmid_handle` by.
+    ///
+    /// The application is given a versioned handle rather than the handle of the cmid, so that
+    /// a handle kept past its connection, e.g., across a reconnect, is rejected with
+    /// [`ResourceError::Stale`] instead of addressing another connection.
+    #[inline]
+    pub(crate) fn insert_conn_id(&self, cmid_handle: phoenix_api::Handle) -> phoenix_api::Handle {
+        self.conn_ids.borrow_mut().insert(cmid_handle)
+    }
+
+    /// The handle of the cmid of the connection `conn_id` refers to.
+    #[inline]
+    pub(crate) fn cmid_handle(
+        &self,
+        conn_id: phoenix_api::Handle,
+    ) -> Result<phoenix_api::Handle, ResourceError> {
+        self.conn_ids.borrow().get(conn_id).copied()
+    }
+
+    /// The connection `conn_id` refers to.
+    #[inline]
+    pub(crate) fn conn(
+        &self,
+        conn_id: phoenix_api::Handle,
+    ) -> Result<Arc<ConnectionContext>, ResourceError> {
+        self.cmid_table.get(&self.cmid_handle(conn_id)?)
+    }
+
     #[inline]
     pub(crate) fn insert_cmid(
         &self,
         cmid: ulib::ucm::CmId,
+        conn_id: phoenix_api::Handle,
         credit: usize,
+        recv: RecvReplenisher,
+        cipher: Option<SessionCipher>,
     ) -> Result<(), ResourceError> {
-        self.cmid_table
-            .insert(cmid.as_handle(), ConnectionContext::new(cmid, credit))
+        self.cmid_table.insert(
+            cmid.as_handle(),
+            ConnectionContext::new(cmid, conn_id, credit, recv, cipher),
+        )
     }
 }
 
+/// A connection request being accepted.
+#[derive(Debug)]
+pub(crate) struct StagedCmId {
+    pub(crate) pre_id: ulib::ucm::PreparedCmId,
+    pub(crate) recv: RecvReplenisher,
+    /// The private data that offers the key of this side, and the keys of the connection, if
+    /// the peer has offered a key.
+    pub(crate) sealing: Option<(Vec<u8>, SessionCipher)>,
+}
+
+/// A connection established, with its receives posted, before it is handed to the application.
+#[derive(Debug)]
+pub(crate) struct EstablishedConn {
+    pub(crate) id: ulib::ucm::CmId,
+    pub(crate) read_regions: Vec<ReadHeapRegion>,
+    pub(crate) fds: Vec<RawFd>,
+    pub(crate) recv: RecvReplenisher,
+    pub(crate) cipher: Option<SessionCipher>,
+}
+
 // NOTE: Pay attention to the drop order.
 pub struct Resource {
     // rpc_adapter_id -> Queue of pre_cmid
@@ -159,7 +275,8 @@ pub struct Resource {
         VecDeque<ulib::ucm::CmIdBuilder<'static, 'static, 'static, 'static, 'static>>,
         FnvBuildHasher,
     >,
-    pub(crate) staging_pre_cmid_table: ResourceTable<ulib::ucm::PreparedCmId>,
+    // pre_cmid with its receives posted, until the application has mapped the receive buffers
+    pub(crate) staging_pre_cmid_table: ResourceTable<StagedCmId>,
     // (rpc_adapter_id, CmIdListener)
     pub(crate) listener_table: ResourceTable<(usize, ulib::ucm::CmIdListener)>,
 
@@ -319,6 +319,16 @@ impl AsHandle for PreparedCmId {
 }
 
 impl PreparedCmId {
+    pub(crate) fn get_peer_addr(&self) -> Result<SocketAddr, Error> {
+        let addr = get_ops().get_peer_addr(&self.inner.handle)?;
+        Ok(addr)
+    }
+
+    /// The private data of the connection request.
+    pub(crate) fn take_peer_private_data(&self) -> Option<Vec<u8>> {
+        get_ops().take_peer_private_data(self.inner.handle.0)
+    }
+
     pub(crate) async fn accept<'a>(
         self,
         conn_param: Option<&'a ConnParam<'a>>,
@@ -367,6 +377,11 @@ impl CmId {
         let addr = get_ops().get_peer_addr(&self.inner.handle)?;
         Ok(addr)
     }
+
+    /// The private data the peer accepted the connection with.
+    pub(crate) fn take_peer_private_data(&self) -> Option<Vec<u8>> {
+        get_ops().take_peer_private_data(self.inner.handle.0)
+    }
 }
 impl CmId {
     pub(crate) fn disconnect(&self) -> Result<(), Error> {
@@ -313,6 +313,22 @@ pub(crate) struct ConnParam<'priv_data> {
     pub(crate) qp_num: u32,
 }
 
+impl<'priv_data> ConnParam<'priv_data> {
+    /// The default parameters of the connection manager, with `private_data`.
+    pub(crate) fn with_private_data(private_data: &'priv_data [u8]) -> Self {
+        ConnParam {
+            private_data: Some(private_data),
+            responder_resources: 1,
+            initiator_depth: 1,
+            flow_control: 0,
+            retry_count: 7,
+            rnr_retry_count: 7,
+            srq: 0,
+            qp_num: 0,
+        }
+    }
+}
+
 impl<'priv_data> FromBorrow<ConnParam<'priv_data>> for net::ConnParam {
     fn from_borrow<T: Borrow<ConnParam<'priv_data>>>(borrow: &T) -> Self {
         let b = borrow.borrow();
@@ -1,6 +1,7 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::mem;
+use std::num::NonZeroU32;
 use std::os::unix::prelude::{AsRawFd, RawFd};
 use std::pin::Pin;
 use std::ptr;
@@ -11,20 +12,22 @@ use fnv::FnvHashMap;
 use futures::future::BoxFuture;
 use slab::Slab;
 
-use mrpc_marshal::{ExcavateContext, SgE, SgList};
+use mrpc_marshal::{copy, ExcavateContext, MarshalError, SgE, SgList, UnmarshalError};
 use phoenix_api::buf::Range;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::net::{MappedAddrStatus, WcOpcode, WcStatus};
-use phoenix_api::rpc::{MessageMeta, RpcId, StatusCode, TransportStatus};
+use phoenix_api::rpc::{MessageMeta, RpcId, RpcMsgType, StatusCode, TransportStatus};
 use phoenix_api::transport::tcp::dp::Completion;
+use phoenix_api::wire::{WireError, WireFlags, WireHeader};
 use phoenix_api::{AsHandle, Handle};
-use phoenix_api_mrpc::cmd::{ConnectResponse, ReadHeapRegion};
+use phoenix_api_mrpc::cmd::{ConnectResponse, MessageSizeLimits, ReadHeapRegion, TransportStats};
 use phoenix_api_tcp_rpc_adapter::control_plane;
 use phoenix_mrpc::unpack::UnpackFromSgE;
 use phoenix_salloc::state::State as SallocState;
 use transport_tcp::ops::Ops;
 use transport_tcp::ApiError;
 
+use phoenix_common::engine::datapath::arena::Arena;
 use phoenix_common::engine::datapath::message::{
     EngineRxMessage, EngineTxMessage, RpcMessageRx, RpcMessageTx,
 };
@@ -41,7 +44,7 @@ use phoenix_common::storage::{ResourceCollection, SharedStorage};
 use super::get_ops;
 use super::pool::BufferSlab;
 use super::serialization::SerializationEngine;
-use super::state::{ConnectionContext, State};
+use super::state::State;
 use super::{ControlPathError, DatapathError};
 
 thread_local! {
@@ -71,6 +74,8 @@ pub(crate) struct TcpRpcAdapterEngine {
     pub(crate) recv_mr_usage: FnvHashMap<RpcId, Vec<Handle>>,
 
     pub(crate) serialization_engine: Option<SerializationEngine>,
+    /// The maximum sizes of the marshalled messages
+    pub(crate) size_limits: MessageSizeLimits,
 
     pub(crate) node: DataPathNode,
     pub(crate) cmd_rx: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
@@ -80,6 +85,10 @@ pub(crate) struct TcpRpcAdapterEngine {
     pub(crate) indicator: Indicator,
     // pub(crate) start: std::time::Instant,
     pub(crate) rpc_ctx: Slab<RpcId>,
+    // scatter-gather list of the message being sent, reused across messages
+    pub(crate) sgl_buffer: SgList,
+    // transient allocations of a quantum, e.g., the scatter-gather lists of received messages
+    pub(crate) arena: Arena,
 }
 
 impl_vertex_for_engine!(TcpRpcAdapterEngine, node);
@@ -125,10 +134,19 @@ impl Decompose for TcpRpcAdapterEngine {
                 "serialization_engine".to_string(),
                 Box::new(ptr::read(&engine.serialization_engine)),
             );
+            collections.insert(
+                "size_limits".to_string(),
+                Box::new(ptr::read(&engine.size_limits)),
+            );
             collections.insert("cmd_tx".to_string(), Box::new(ptr::read(&engine.cmd_tx)));
             collections.insert("cmd_rx".to_string(), Box::new(ptr::read(&engine.cmd_rx)));
             collections.insert("salloc".to_string(), Box::new(ptr::read(&engine.salloc)));
             collections.insert("rpc_ctx".to_string(), Box::new(ptr::read(&engine.rpc_ctx)));
+            collections.insert(
+                "sgl_buffer".to_string(),
+                Box::new(ptr::read(&engine.sgl_buffer)),
+            );
+            collections.insert("arena".to_string(), Box::new(ptr::read(&engine.arena)));
             // don't call the drop function
             ptr::read(&engine.node)
         };
@@ -179,6 +197,11 @@ impl TcpRpcAdapterEngine {
             .unwrap()
             .downcast::<Option<SerializationEngine>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let size_limits = *local
+            .remove("size_limits")
+            .unwrap()
+            .downcast::<MessageSizeLimits>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let cmd_tx = *local
             .remove("cmd_tx")
             .unwrap()
@@ -199,6 +222,16 @@ impl TcpRpcAdapterEngine {
             .unwrap()
             .downcast::<Slab<RpcId>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let sgl_buffer = *local
+            .remove("sgl_buffer")
+            .unwrap()
+            .downcast::<SgList>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let arena = *local
+            .remove("arena")
+            .unwrap()
+            .downcast::<Arena>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
 
         let engine = TcpRpcAdapterEngine {
             state,
@@ -206,6 +239,7 @@ impl TcpRpcAdapterEngine {
             local_buffer,
             recv_mr_usage,
             serialization_engine,
+            size_limits,
             cmd_tx,
             cmd_rx,
             node,
@@ -214,6 +248,8 @@ impl TcpRpcAdapterEngine {
             salloc,
             // start: std::time::Instant::now(),
             rpc_ctx,
+            sgl_buffer,
+            arena,
         };
         Ok(engine)
     }
@@ -252,6 +288,14 @@ impl Engine for TcpRpcAdapterEngine {
         ELS.with_borrow_mut(|els| *els = unsafe { Some(&*tls) });
     }
 
+    fn doorbells(&self) -> Option<Vec<RawFd>> {
+        // the queued messages are sent without waiting for any socket event
+        if !self.local_buffer.is_empty() {
+            return None;
+        }
+        Some(vec![self.tls.ops.poll_fd()])
+    }
+
     fn handle_request(
         &mut self,
         request: Vec<u8>,
@@ -299,6 +343,8 @@ impl Drop for TcpRpcAdapterEngine {
 impl TcpRpcAdapterEngine {
     async fn mainloop(&mut self) -> EngineResult {
         loop {
+            // the allocations of the previous round are gone
+            self.arena.reset();
             // let mut timer = utils::timer::Timer::new();
 
             let mut work = 0;
@@ -346,6 +392,26 @@ enum RpcStrategy {
     Standard,
 }
 
+/// The total size of a marshalled message, excluding the meta.
+#[inline]
+fn payload_size(sges: &[SgE]) -> usize {
+    sges.iter().map(|sge| sge.len).sum()
+}
+
+/// The status a message is delivered with when it fails to unmarshal.
+fn unmarshal_status(e: &UnmarshalError) -> StatusCode {
+    match e {
+        UnmarshalError::UnknownFuncId(_) => StatusCode::Unimplemented,
+        _ => StatusCode::Unknown,
+    }
+}
+
+/// The receive buffers of a connection.
+const RECV_BUFFERS: usize = 128;
+
+/// The size of a receive buffer.
+const RECV_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
 impl TcpRpcAdapterEngine {
     #[inline]
     fn choose_strategy(sglist: &SgList) -> RpcStrategy {
@@ -368,15 +434,11 @@ impl TcpRpcAdapterEngine {
         sglist: &SgList,
     ) -> Result<Status, DatapathError> {
         let meta_ref = unsafe { &*meta_buf_ptr.as_meta_ptr() };
-        let table = self.state.conn_table.borrow();
-        let conn_ctx = table
-            .get(&meta_ref.conn_id)
-            .ok_or(ResourceError::NotFound)?;
+        let sock_handle = self.state.sock_handle(meta_ref.conn_id)?;
 
         let call_id = meta_ref.call_id;
-        let sock_handle = conn_ctx.sock_handle;
         // let ctx = RpcId::new(sock_handle, call_id, 0).encode_u64();
-        let ctx = self.rpc_ctx.insert(RpcId::new(sock_handle, call_id));
+        let ctx = self.rpc_ctx.insert(RpcId::new(meta_ref.conn_id, call_id));
 
         let off = meta_buf_ptr.0.as_ptr().expose_addr();
         let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
@@ -384,28 +446,32 @@ impl TcpRpcAdapterEngine {
         // TODO(cjr): impl Serialize for SgList
         // Serialize the sglist
         // write the lens to MetaBuffer
-        meta_buf.num_sge = sglist.0.len() as u32;
-
         let mut value_len = 0;
         let lens_buf = meta_buf.length_delimited.as_mut_ptr().cast::<u32>();
         let value_buf = unsafe { lens_buf.add(sglist.0.len()).cast::<u8>() };
         for (i, sge) in sglist.0.iter().enumerate() {
             // SAFETY: we have done sanity check before in choose_strategy
-            unsafe { lens_buf.add(i).write(sge.len as u32) };
+            unsafe { lens_buf.add(i).write((sge.len as u32).to_le()) };
             unsafe {
-                ptr::copy_nonoverlapping(sge.ptr as *mut u8, value_buf.add(value_len), sge.len);
+                copy::copy_nonoverlapping(sge.ptr as *const u8, value_buf.add(value_len), sge.len);
             }
             value_len += sge.len;
         }
         // write the values to MetaBuffer
-        meta_buf.value_len = value_len as u32;
+        meta_buf
+            .header
+            .seal(WireFlags::FUSED, sglist.0.len(), value_len);
+        let post_len = meta_buf.len();
+        meta_buf.stamp_post_send();
+        // SAFETY: the header is not read after it is encoded
+        unsafe { WireHeader::encode(&mut meta_buf.header) };
 
         get_ops().post_send(
             sock_handle,
             ctx as u64,
             Range {
                 offset: off as _,
-                len: meta_buf.len() as _,
+                len: post_len as _,
             },
             1,
         )?;
@@ -415,25 +481,29 @@ impl TcpRpcAdapterEngine {
 
     fn send_standard(
         &mut self,
-        meta_ref: &MessageMeta,
+        mut meta_buf_ptr: MetaBufferPtr,
         sglist: &SgList,
     ) -> Result<Status, DatapathError> {
         log::debug!("start send_standard!");
-        let table = self.state.conn_table.borrow();
-        let conn_ctx = table
-            .get(&meta_ref.conn_id)
-            .ok_or(ResourceError::NotFound)?;
+        let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
+        meta_buf
+            .header
+            .seal(WireFlags::empty(), sglist.0.len(), payload_size(&sglist.0));
+        let meta_ref = &meta_buf.header.meta;
+        let sock_handle = self.state.sock_handle(meta_ref.conn_id)?;
 
         let call_id = meta_ref.call_id;
-        let sock_handle = conn_ctx.sock_handle;
 
         // Sender posts send requests from the SgList
         // let ctx = RpcId::new(sock_handle, call_id, 0).encode_u64();
-        let ctx = self.rpc_ctx.insert(RpcId::new(sock_handle, call_id));
+        let ctx = self.rpc_ctx.insert(RpcId::new(meta_ref.conn_id, call_id));
 
+        meta_buf.stamp_post_send();
+        // SAFETY: the header is not read after it is encoded
+        unsafe { WireHeader::encode(&mut meta_buf.header) };
         let meta_sge = SgE {
-            ptr: (meta_ref as *const MessageMeta).expose_addr(),
-            len: mem::size_of::<MessageMeta>(),
+            ptr: (&meta_buf.header as *const WireHeader).expose_addr(),
+            len: WireHeader::SIZE,
         };
         log::debug!("send_standard start! meta_sge: {:?}", meta_sge);
 
@@ -471,10 +541,12 @@ impl TcpRpcAdapterEngine {
             Ok(msg) => match msg {
                 EngineTxMessage::RpcMessage(msg) => self.local_buffer.push_back(msg),
                 EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
-                    let sock_handle = {
-                        let table = self.state.conn_table.borrow_mut();
-                        let conn_ctx = table.get(&conn_id).ok_or(ResourceError::NotFound)?;
-                        conn_ctx.sock_handle
+                    // the buffers of a connection that has been replaced are not posted on the
+                    // one that reuses its socket
+                    let sock_handle = match self.state.sock_handle(conn_id) {
+                        Ok(sock_handle) => Some(sock_handle),
+                        Err(ResourceError::Stale) => None,
+                        Err(e) => return Err(e.into()),
                     };
                     // TODO(cjr): only handle the first element, fix it later
                     for call_id in &call_ids[..1] {
@@ -482,7 +554,9 @@ impl TcpRpcAdapterEngine {
                             .recv_mr_usage
                             .remove(&RpcId::new(conn_id, *call_id))
                             .expect("invalid WR identifier");
-                        self.reclaim_recv_buffers(sock_handle, &recv_mrs[..])?;
+                        if let Some(sock_handle) = sock_handle {
+                            self.reclaim_recv_buffers(sock_handle, &recv_mrs[..])?;
+                        }
                     }
                 }
             },
@@ -493,41 +567,150 @@ impl TcpRpcAdapterEngine {
         if let Some(msg) = self.local_buffer.pop_front() {
             // SAFETY: don't know what kind of UB can be triggered
             let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
-            // let table = self.state.conn_table.borrow_mut();
-            // let conn_ctx = table
-            //     .get(&meta_ref.conn_id)
-            //     .ok_or(ResourceError::NotFound)?;
+            let sock_handle = match self.state.sock_handle(meta_ref.conn_id) {
+                Ok(sock_handle) => sock_handle,
+                Err(ResourceError::Stale) => return self.reject_stale(msg),
+                Err(e) => return Err(e.into()),
+            };
             // log::info!("dispatching message: {:?}", meta_ref);
-            let sglist = match meta_ref.status_code {
-                StatusCode::AccessDenied => SgList { 0: Vec::new() },
+            let mut sglist = mem::take(&mut self.sgl_buffer);
+            match meta_ref.status_code {
+                StatusCode::AccessDenied
+                | StatusCode::MessageTooLarge
+                | StatusCode::Unimplemented
+                | StatusCode::Unknown => sglist.0.clear(),
                 StatusCode::Success => {
                     if let Some(ref module) = self.serialization_engine {
-                        match module.marshal(meta_ref, msg.addr_backend) {
-                            Ok(sglist) => sglist,
-                            Err(e) => {
-                                panic!("marshal error: {:?}", e);
-                            }
+                        if let Err(e) = module.marshal_into(meta_ref, msg.addr_backend, &mut sglist)
+                        {
+                            log::warn!(
+                                "failed to marshal {:?}, service_id={}, call_id={}: {}",
+                                meta_ref.msg_type,
+                                meta_ref.service_id,
+                                meta_ref.call_id,
+                                e
+                            );
+                            self.sgl_buffer = sglist;
+                            let (status_code, code) = match e {
+                                MarshalError::UnknownFuncId(_) => (StatusCode::Unimplemented, 501),
+                            };
+                            return self.reject(msg, status_code, code);
                         }
                     } else {
                         panic!("dispatch module not loaded");
                     }
                 }
-                _ => {
-                    panic!("unexpected status code: {:?}", meta_ref.status_code);
+            }
+
+            if meta_ref.status_code == StatusCode::Success {
+                let size = payload_size(&sglist.0);
+                let max_size = self
+                    .size_limits
+                    .max_size(meta_ref.service_id, meta_ref.msg_type);
+                if max_size.map_or(false, |max_size| size > max_size) {
+                    self.sgl_buffer = sglist;
+                    return self.reject_too_large(msg, size);
                 }
-            };
+            }
 
+            let bytes = mem::size_of::<MessageMeta>() + payload_size(&sglist.0);
             let status = match Self::choose_strategy(&sglist) {
                 RpcStrategy::Fused => self.send_fused(msg.meta_buf_ptr, &sglist)?,
-                RpcStrategy::Standard => self.send_standard(meta_ref, &sglist)?,
+                RpcStrategy::Standard => self.send_standard(msg.meta_buf_ptr, &sglist)?,
             };
+            if let Some(conn_ctx) = self.state.conn_table.borrow_mut().get_mut(&sock_handle) {
+                conn_ctx.traffic.sent(bytes);
+            }
+            self.sgl_buffer = sglist;
             return Ok(status);
         }
 
         Ok(Progress(0))
     }
 
-    fn reshape_fused_sg_list(sg_list: &mut SgList) {
+    /// Fails a message sent on the `conn_id` of a connection that has been replaced, e.g., by a
+    /// reconnect that reuses its socket, with transport status 410. It is not sent on the
+    /// connection that reuses the socket.
+    fn reject_stale(&mut self, msg: RpcMessageTx) -> Result<Status, DatapathError> {
+        // SAFETY: the meta buffer is owned by this message until it is acked
+        let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+        log::warn!(
+            "{:?} on stale connection {:?}, call_id={}, rejected",
+            meta.msg_type,
+            meta.conn_id,
+            meta.call_id
+        );
+        let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+        let status = TransportStatus::Error(NonZeroU32::new(410).unwrap());
+        self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+        Ok(Progress(1))
+    }
+
+    /// Drops a message larger than the limit instead of sending it.
+    ///
+    /// The call of a request fails locally with transport status 413. A response is replaced by
+    /// its meta, which tells the client that the reply is too large.
+    fn reject_too_large(
+        &mut self,
+        msg: RpcMessageTx,
+        size: usize,
+    ) -> Result<Status, DatapathError> {
+        // SAFETY: the meta buffer is owned by this message until it is acked
+        let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+        log::warn!(
+            "{:?} of {} bytes exceeds the size limit, service_id={}, call_id={}",
+            meta.msg_type,
+            size,
+            meta.service_id,
+            meta.call_id
+        );
+        self.reject(msg, StatusCode::MessageTooLarge, 413)
+    }
+
+    /// Fails a message that cannot be sent. A request fails locally with the transport status
+    /// `code`. A response is sent without its payload and with `status_code`, which fails the
+    /// call on the other side.
+    fn reject(
+        &mut self,
+        msg: RpcMessageTx,
+        status_code: StatusCode,
+        code: u32,
+    ) -> Result<Status, DatapathError> {
+        // SAFETY: the meta buffer is owned by this message until it is acked
+        let meta = unsafe { &mut *msg.meta_buf_ptr.as_meta_ptr() };
+        match meta.msg_type {
+            // nobody waits for a notification, it fails locally as a request does
+            RpcMsgType::Request | RpcMsgType::Notification => {
+                let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                let status = TransportStatus::Error(NonZeroU32::new(code).unwrap());
+                self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                Ok(Progress(1))
+            }
+            RpcMsgType::Response => {
+                meta.status_code = status_code;
+                self.send_fused(msg.meta_buf_ptr, &SgList(Vec::new()))
+            }
+        }
+    }
+
+    /// Checks the header of a received message, and splits an eager (fused) message into its
+    /// segments.
+    fn parse_sg_list(sg_list: &mut SgList) -> Result<(), DatapathError> {
+        // SAFETY: the first segment is in a receive buffer, which is aligned and at least
+        // as long as its length
+        let header = unsafe { WireHeader::parse(sg_list.0[0].ptr, sg_list.0[0].len) }?;
+        header.check_segments(sg_list.0.len())?;
+        if header.is_fused() {
+            header.check_fused(sg_list.0[0].len, MetaBuffer::capacity())?;
+            Self::reshape_fused_sg_list(sg_list)?;
+        } else {
+            // the first segment is unpacked as a MessageMeta
+            sg_list.0[0].len = mem::size_of::<MessageMeta>();
+        }
+        Ok(())
+    }
+
+    fn reshape_fused_sg_list(sg_list: &mut SgList) -> Result<(), WireError> {
         use std::ptr::Unique;
         assert_eq!(sg_list.0.len(), 1);
 
@@ -537,14 +720,15 @@ impl TcpRpcAdapterEngine {
         // modify the first sge in place
         sg_list.0[0].len = mem::size_of::<MessageMeta>();
 
-        let num_sge = meta_buf.num_sge as usize;
+        let num_sge = meta_buf.header.num_sge as usize;
         let (_prefix, lens, _suffix): (_, &[u32], _) = unsafe { meta_buf.lens_buffer().align_to() };
         debug_assert!(_prefix.is_empty() && _suffix.is_empty());
+        meta_buf.header.check_fused_lens(lens)?;
 
         let value_buf_base = meta_buf.value_buffer().as_ptr().expose_addr();
         let mut value_offset = 0;
 
-        for len in lens.iter().take(num_sge).map(|x| *x as usize) {
+        for len in lens.iter().take(num_sge).map(|x| u32::from_le(*x) as usize) {
             sg_list.0.push(SgE {
                 ptr: value_buf_base + value_offset,
                 len,
@@ -554,37 +738,77 @@ impl TcpRpcAdapterEngine {
         }
 
         // tracing::trace!("reshape_fused_sg_list: sg_list: {:?}", sg_list);
+        Ok(())
     }
 
-    fn unmarshal_and_deliver_up(&mut self, sgl: SgList, sock_handle: Handle) -> RpcId {
-        let mut meta_ptr = unsafe { MessageMeta::unpack(&sgl.0[0]) }.unwrap();
+    fn unmarshal_and_deliver_up(&mut self, sgl: &[SgE], conn_id: Handle) -> RpcId {
+        let mut meta_ptr = unsafe { MessageMeta::unpack(&sgl[0]) }.unwrap();
         let meta = unsafe { meta_ptr.as_mut() };
-        meta.conn_id = sock_handle;
+        meta.conn_id = conn_id;
 
         let recv_id = RpcId::new(meta.conn_id, meta.call_id);
         let mut excavate_ctx = ExcavateContext {
-            sgl: sgl.0[1..].iter(),
+            sgl: sgl[1..].iter(),
             addr_arbiter: &self.state.resource().addr_map,
         };
 
+        // An oversized message is delivered without its payload, the MrpcEngine rejects it.
+        if meta.status_code == StatusCode::Success {
+            let size = payload_size(&sgl[1..]);
+            let max_size = self.size_limits.max_size(meta.service_id, meta.msg_type);
+            if max_size.map_or(false, |max_size| size > max_size) {
+                log::warn!(
+                    "received {:?} of {} bytes exceeds the size limit, service_id={}, call_id={}",
+                    meta.msg_type,
+                    size,
+                    meta.service_id,
+                    meta.call_id
+                );
+                meta.status_code = StatusCode::MessageTooLarge;
+            }
+        }
+
         let (addr_app, addr_backend) = match meta.status_code {
             StatusCode::Success => {
                 if let Some(ref module) = self.serialization_engine {
-                    module.unmarshal(meta, &mut excavate_ctx).unwrap()
+                    match module.unmarshal(meta, &mut excavate_ctx) {
+                        Ok(addrs) => addrs,
+                        Err(e) => {
+                            // delivered without its payload, the MrpcEngine rejects it
+                            log::warn!(
+                                "failed to unmarshal {:?}, service_id={}, call_id={}: {}",
+                                meta.msg_type,
+                                meta.service_id,
+                                meta.call_id,
+                                e
+                            );
+                            meta.status_code = unmarshal_status(&e);
+                            (0, 0)
+                        }
+                    }
                 } else {
                     panic!("dispatch module not loaded");
                 }
             }
-            StatusCode::AccessDenied => (0usize, 0usize),
-            _ => {
-                panic!("unexpected status code: {:?}", meta.status_code);
-            }
+            StatusCode::AccessDenied
+            | StatusCode::MessageTooLarge
+            | StatusCode::Unimplemented
+            | StatusCode::Unknown => (0usize, 0usize),
         };
 
+        // a message that is its struct alone can be copied by value
+        let flat_len = match &sgl[1..] {
+            [sge] if meta.status_code == StatusCode::Success => Some(sge.len),
+            _ => None,
+        };
+        // SAFETY: the header is decoded in place by parse_sg_list
+        let hop_limit = unsafe { (*(sgl[0].ptr as *const WireHeader)).hop_limit };
         let msg = RpcMessageRx {
             meta: meta_ptr,
             addr_backend,
             addr_app,
+            hop_limit,
+            flat_len,
         };
 
         self.rx_outputs()[0]
@@ -597,9 +821,18 @@ impl TcpRpcAdapterEngine {
     fn process_new_connection(&mut self, handle: &Handle) -> usize {
         (|| -> Result<(), ControlPathError> {
             let (read_regions, fds) = self.prepare_recv_buffers(*handle)?;
+            // its receives wait until the app has mapped the buffers, see NewMappedAddrs
+            let conn_id = self.state.insert_conn(*handle);
+            let peer_addr = get_ops()
+                .state
+                .sock_table
+                .borrow()
+                .get(handle)
+                .and_then(|(sock, _status)| sock.peer_addr().ok());
             let conn_resp = ConnectResponse {
-                conn_handle: *handle,
+                conn_handle: conn_id,
                 read_regions,
+                peer_addr,
             };
             let comp = phoenix_api_mrpc::cmd::Completion(Ok(
                 phoenix_api_mrpc::cmd::CompletionKind::NewConnectionInternal(conn_resp, fds),
@@ -610,7 +843,11 @@ impl TcpRpcAdapterEngine {
         .is_ok() as usize
     }
 
-    fn process_completion(&mut self, wc: &Completion) -> usize {
+    fn process_completion(
+        &mut self,
+        wc: &Completion,
+        arena: &Arena,
+    ) -> Result<usize, DatapathError> {
         match wc.status {
             WcStatus::Success => {
                 match wc.opcode {
@@ -627,7 +864,7 @@ impl TcpRpcAdapterEngine {
                         let mut table = self.state.conn_table.borrow_mut();
                         let conn_ctx = table.get_mut(&Handle(wc.conn_id));
                         if conn_ctx.is_none() {
-                            return 0;
+                            return Ok(0);
                         }
                         let conn_ctx = conn_ctx.unwrap();
 
@@ -642,24 +879,33 @@ impl TcpRpcAdapterEngine {
                         if wc.imm != 0 {
                             // received an entire RPC message
                             let sock_handle = conn_ctx.sock_handle;
-                            let mut recv_ctx = mem::take(&mut conn_ctx.receiving_ctx);
+                            let conn_id = conn_ctx.conn_id;
+                            let recv_ctx = &mut conn_ctx.receiving_ctx;
+                            let recv_mrs = mem::take(&mut recv_ctx.recv_mrs);
+                            conn_ctx.traffic.received(payload_size(&recv_ctx.sg_list.0));
+                            let sgl = Self::parse_sg_list(&mut recv_ctx.sg_list)
+                                .map(|()| arena.alloc_slice_copy(&recv_ctx.sg_list.0));
+                            // the list of the connection is kept for its next message, this one
+                            // is delivered from its copy once the table is released
+                            recv_ctx.sg_list.0.clear();
                             drop(table);
 
-                            // check if it is an eager message
-                            if recv_ctx.sg_list.0.len() == 1 {
-                                // got an eager message
-                                Self::reshape_fused_sg_list(&mut recv_ctx.sg_list);
-                            }
+                            let sgl = match sgl {
+                                Ok(sgl) => sgl,
+                                Err(e) => {
+                                    self.drop_received(sock_handle, &recv_mrs, e)?;
+                                    return Ok(1);
+                                }
+                            };
 
-                            let recv_id =
-                                self.unmarshal_and_deliver_up(recv_ctx.sg_list, sock_handle);
+                            let recv_id = self.unmarshal_and_deliver_up(sgl, conn_id);
 
                             // keep them outstanding because they will be used by the user
-                            self.recv_mr_usage.insert(recv_id, recv_ctx.recv_mrs);
+                            self.recv_mr_usage.insert(recv_id, recv_mrs);
                         }
                     }
-                    // The below two are probably errors in impl logic, so assert them
-                    _ => panic!("invalid wc: {:?}", wc),
+                    // probably an error in impl logic
+                    _ => return Err(DatapathError::UnexpectedCompletion(format!("{:?}", wc))),
                 }
             }
             WcStatus::Error(code) => {
@@ -668,21 +914,21 @@ impl TcpRpcAdapterEngine {
                 get_ops().state.listener_table.borrow_mut().remove(&handle);
                 get_ops().state.sock_table.borrow_mut().remove(&handle);
                 get_ops().state.cq_table.borrow_mut().remove(&handle);
-                self.state.conn_table.borrow_mut().remove(&handle);
+                let conn_id = self.state.remove_conn(handle).unwrap_or(handle);
                 let msg = if wc.opcode == WcOpcode::Send {
                     // let rpc_id = RpcId::decode_u64(wc.wr_id);
                     let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
                     EngineRxMessage::Ack(rpc_id, TransportStatus::Error(code))
                 } else if wc.opcode == WcOpcode::Recv {
-                    EngineRxMessage::RecvError(handle, TransportStatus::Error(code))
+                    EngineRxMessage::RecvError(conn_id, TransportStatus::Error(code))
                 } else {
-                    panic!("invalid wc: {:?}", wc);
+                    return Err(DatapathError::UnexpectedCompletion(format!("{:?}", wc)));
                 };
                 self.rx_outputs()[0].send(msg).unwrap();
             }
         }
 
-        1
+        Ok(1)
     }
 
     fn check_transport_service(&mut self) -> Result<Status, DatapathError> {
@@ -691,19 +937,36 @@ impl TcpRpcAdapterEngine {
         let (conns, wcs) = get_ops().poll_io(Duration::from_micros(0))?;
         // let (conns, wcs) = get_ops().poll_io(Duration::from_micros(5))?;
 
+        let arena = mem::take(&mut self.arena);
         let mut progress = 0;
         for conn in &conns {
             progress += self.process_new_connection(conn);
         }
         for wc in &wcs {
-            progress += self.process_completion(wc);
+            progress += self.process_completion(wc, &arena)?;
         }
+        self.arena = arena;
 
         // COMMENT(cjr): Progress(0) here is okay for now because we haven't use the progress as
         // any indicator.
         Ok(Status::Progress(progress))
     }
 
+    /// Drops a received message that is malformed. Its receive buffers are posted again, and the
+    /// drop is counted in the stats of the connection.
+    fn drop_received(
+        &mut self,
+        sock_handle: Handle,
+        recv_mrs: &[Handle],
+        err: DatapathError,
+    ) -> Result<(), DatapathError> {
+        log::warn!("dropped a message received on {:?}: {}", sock_handle, err);
+        if let Some(conn_ctx) = self.state.conn_table.borrow_mut().get_mut(&sock_handle) {
+            conn_ctx.traffic.dropped();
+        }
+        self.reclaim_recv_buffers(sock_handle, recv_mrs)
+    }
+
     fn reclaim_recv_buffers(
         &mut self,
         sock_handle: Handle,
@@ -730,13 +993,13 @@ impl TcpRpcAdapterEngine {
         sock_handle: Handle,
     ) -> Result<(Vec<ReadHeapRegion>, Vec<RawFd>), ControlPathError> {
         let slab = BufferSlab::new(
-            128,
-            8 * 1024 * 1024,
-            8 * 1024 * 1024,
+            RECV_BUFFERS,
+            RECV_BUFFER_SIZE,
+            RECV_BUFFER_SIZE,
             &self.salloc.addr_mediator,
         )?;
         // create 128 receive mrs and post recv requests
-        for _ in 0..128 {
+        for _ in 0..RECV_BUFFERS {
             let recv_buffer = slab.obtain().unwrap();
             let wr_id = recv_buffer.as_handle().0 as u64;
             let offset = recv_buffer.addr() as u64;
@@ -791,15 +1054,12 @@ impl TcpRpcAdapterEngine {
             Command::SetTransport(_) => {
                 unreachable!();
             }
-            Command::NewMappedAddrs(sock_handle, app_vaddrs) => {
+            Command::NewMappedAddrs(conn_id, app_vaddrs) => {
+                let sock_handle = self.state.sock_handle(*conn_id)?;
                 for (mr_handle, app_vaddr) in app_vaddrs.iter() {
                     let region = self.state.resource().recv_buffer_pool.find(mr_handle)?;
-                    let mr_local_addr = region.as_ptr().expose_addr();
-                    let mr_remote_mapped = mrpc_marshal::ShmRecvMr {
-                        ptr: *app_vaddr,
-                        len: region.len(),
-                        align: region.align(),
-                    };
+                    let mr_local_addr = region.addr();
+                    let mr_remote_mapped = region.map_app(*app_vaddr);
                     self.state
                         .resource()
                         .addr_map
@@ -808,13 +1068,8 @@ impl TcpRpcAdapterEngine {
 
                 //Marked socket as addresses mapped
                 let mut table = get_ops().state.sock_table.borrow_mut();
-                let value = table.get_mut(sock_handle).ok_or(ApiError::NotFound)?;
+                let value = table.get_mut(&sock_handle).ok_or(ApiError::NotFound)?;
                 value.1 = MappedAddrStatus::Mapped;
-                // insert resources after connection establishment
-                self.state
-                    .conn_table
-                    .borrow_mut()
-                    .insert(*sock_handle, ConnectionContext::new(*sock_handle));
 
                 Ok(CompletionKind::NewMappedAddrs)
             }
@@ -822,13 +1077,11 @@ impl TcpRpcAdapterEngine {
                 log::debug!("Connect, addr: {:?}", addr);
                 let sock_handle = get_ops().connect(addr)?;
                 let (read_regions, fds) = self.prepare_recv_buffers(sock_handle)?;
-                self.state
-                    .conn_table
-                    .borrow_mut()
-                    .insert(sock_handle, ConnectionContext::new(sock_handle));
+                let conn_id = self.state.insert_conn(sock_handle);
                 let conn_resp = ConnectResponse {
-                    conn_handle: sock_handle,
+                    conn_handle: conn_id,
                     read_regions,
+                    peer_addr: Some(*addr),
                 };
                 Ok(CompletionKind::ConnectInternal(conn_resp, fds))
             }
@@ -850,6 +1103,42 @@ impl TcpRpcAdapterEngine {
             Command::MultiConnect(_) => {
                 unreachable!();
             }
+            Command::UpdateDescriptors(_) => {
+                unreachable!();
+            }
+            Command::SetMessageSizeLimit(service_id, limit) => {
+                log::debug!(
+                    "SetMessageSizeLimit, service_id: {:?}, {:?}",
+                    service_id,
+                    limit
+                );
+                self.size_limits.update(*service_id, *limit);
+                Ok(CompletionKind::SetMessageSizeLimit)
+            }
+            Command::ConnectionStats(conn_id) => {
+                let sock_handle = self.state.sock_handle(*conn_id)?;
+                let table = self.state.conn_table.borrow();
+                let traffic = &table.get(&sock_handle).ok_or(ApiError::NotFound)?.traffic;
+                // the buffers of the messages delivered until the app returns them
+                let in_use: usize = self
+                    .recv_mr_usage
+                    .iter()
+                    .filter(|(rpc_id, _)| rpc_id.0 == *conn_id)
+                    .map(|(_, recv_mrs)| recv_mrs.len())
+                    .sum();
+                let stats = TransportStats {
+                    bytes_sent: traffic.bytes_sent,
+                    messages_sent: traffic.messages_sent,
+                    bytes_received: traffic.bytes_received,
+                    messages_received: traffic.messages_received,
+                    messages_dropped: traffic.messages_dropped,
+                    retransmits: Some(get_ops().retransmits(sock_handle)?),
+                    credits: None,
+                    recv_heap_in_use: in_use * RECV_BUFFER_SIZE,
+                    recv_heap_size: RECV_BUFFERS * RECV_BUFFER_SIZE,
+                };
+                Ok(CompletionKind::ConnectionStats(stats))
+            }
         }
     }
 }
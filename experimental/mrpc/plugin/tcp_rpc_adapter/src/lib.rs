@@ -57,7 +57,11 @@ pub(crate) enum ControlPathError {
 
 impl From<ControlPathError> for phoenix_api::Error {
     fn from(other: ControlPathError) -> Self {
-        phoenix_api::Error::Generic(other.to_string())
+        let code = match &other {
+            ControlPathError::Resource(e) => e.code(),
+            _ => phoenix_api::ErrorCode::Internal,
+        };
+        phoenix_api::Error::from_error(code, &other)
     }
 }
 
@@ -75,6 +79,12 @@ pub(crate) enum DatapathError {
 
     #[error("TCP transport error: {0}")]
     TransportError(#[from] TransportError),
+
+    #[error("Wire format error: {0}")]
+    Wire(#[from] phoenix_api::wire::WireError),
+
+    #[error("Unexpected work completion: {0}")]
+    UnexpectedCompletion(String),
 }
 
 use crate::module::TcpRpcAdapterModule;
@@ -1,9 +1,11 @@
 use anyhow::{anyhow, bail, Result};
 use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use nix::unistd::Pid;
 
+use mrpc_marshal::SgList;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api_mrpc::cmd;
 
@@ -13,6 +15,8 @@ use phoenix_salloc::state::{Shared as SallocShared, State as SallocState};
 use transport_tcp::module::TcpTransportModule;
 use transport_tcp::ops::Ops;
 
+use phoenix_common::capability::Capabilities;
+use phoenix_common::engine::datapath::arena::Arena;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
 use phoenix_common::module::{
@@ -77,9 +81,12 @@ impl RpcAdapterEngineBuilder {
             indicator: Default::default(),
             recv_mr_usage: fnv::FnvHashMap::default(),
             serialization_engine: None,
+            size_limits: Default::default(),
             salloc: salloc_state,
             // start: std::time::Instant::now(),
             rpc_ctx: Default::default(),
+            sgl_buffer: SgList(Vec::with_capacity(32)),
+            arena: Arena::new(),
         })
     }
 }
@@ -121,6 +128,38 @@ impl PhoenixModule for TcpRpcAdapterModule {
         Self::DEPENDENCIES
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // The engines resolve the addresses, drive the sockets through the TCP transport, and
+        // share the memfds of the regions allocated by the applications.
+        Capabilities {
+            read_paths: [
+                "/etc/hosts",
+                "/etc/host.conf",
+                "/etc/nsswitch.conf",
+                "/etc/resolv.conf",
+                "/etc/gai.conf",
+            ]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+            syscalls: vec![
+                libc::SYS_memfd_create,
+                libc::SYS_ftruncate,
+                libc::SYS_socket,
+                libc::SYS_bind,
+                libc::SYS_listen,
+                libc::SYS_accept4,
+                libc::SYS_connect,
+                libc::SYS_setsockopt,
+                libc::SYS_getsockopt,
+                libc::SYS_getsockname,
+                libc::SYS_getpeername,
+                libc::SYS_shutdown,
+            ],
+            ..Default::default()
+        }
+    }
+
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
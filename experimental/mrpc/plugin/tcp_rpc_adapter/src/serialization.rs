@@ -3,10 +3,12 @@ use std::ffi::OsStr;
 use mrpc_marshal::{ExcavateContext, SgList};
 use mrpc_marshal::{MarshalError, UnmarshalError};
 use phoenix_api::rpc::MessageMeta;
+use phoenix_common::metrics;
 
 pub(crate) use mrpc_marshal::AddressMap;
 
 pub(crate) type MarshalFn = fn(&MessageMeta, usize) -> Result<SgList, MarshalError>;
+pub(crate) type MarshalIntoFn = fn(&MessageMeta, usize, &mut SgList) -> Result<(), MarshalError>;
 pub(crate) type UnmarshalFn =
     fn(&MessageMeta, &mut ExcavateContext<AddressMap>) -> Result<(usize, usize), UnmarshalError>;
 
@@ -17,6 +19,11 @@ pub(crate) struct SerializationEngine {
     marshal_fn: libloading::os::unix::Symbol<MarshalFn>,
     #[cfg(windows)]
     marshal_fn: libloading::os::windows::Symbol<MarshalFn>,
+    // NOTE: None for the dispatch libraries cached before marshal_into is generated.
+    #[cfg(unix)]
+    marshal_into_fn: Option<libloading::os::unix::Symbol<MarshalIntoFn>>,
+    #[cfg(windows)]
+    marshal_into_fn: Option<libloading::os::windows::Symbol<MarshalIntoFn>>,
     #[cfg(unix)]
     unmarshal_fn: libloading::os::unix::Symbol<UnmarshalFn>,
     #[cfg(windows)]
@@ -32,6 +39,13 @@ impl SerializationEngine {
             symbol.into_raw()
         };
 
+        let marshal_into_fn = unsafe {
+            library
+                .get::<MarshalIntoFn>(b"marshal_into")
+                .ok()
+                .map(|symbol| symbol.into_raw())
+        };
+
         let unmarshal_fn = unsafe {
             let symbol: libloading::Symbol<UnmarshalFn> = library.get(b"unmarshal")?;
             symbol.into_raw()
@@ -40,18 +54,34 @@ impl SerializationEngine {
         let module = SerializationEngine {
             _library: library,
             marshal_fn,
+            marshal_into_fn,
             unmarshal_fn,
         };
         Ok(module)
     }
 
+    /// Marshals the message into `sgl`, reusing its allocation.
     #[inline]
-    pub(crate) fn marshal(
+    pub(crate) fn marshal_into(
         &self,
         meta: &MessageMeta,
         addr_backend: usize,
-    ) -> Result<SgList, MarshalError> {
-        (self.marshal_fn)(meta, addr_backend)
+        sgl: &mut SgList,
+    ) -> Result<(), MarshalError> {
+        match self.marshal_into_fn {
+            Some(ref marshal_into_fn) => {
+                let capacity = sgl.0.capacity();
+                marshal_into_fn(meta, addr_backend, sgl)?;
+                if sgl.0.capacity() != capacity {
+                    metrics::record_datapath_allocation();
+                }
+            }
+            None => {
+                *sgl = (self.marshal_fn)(meta, addr_backend)?;
+                metrics::record_datapath_allocation();
+            }
+        }
+        Ok(())
     }
 
     #[inline]
@@ -9,6 +9,7 @@ use nix::unistd::Pid;
 use phoenix_api::Handle;
 use phoenix_salloc::region::AddressMediator;
 
+use phoenix_common::resource::{Error as ResourceError, VersionedSlab};
 use phoenix_common::state_mgr::ProcessShared;
 
 use super::pool::{BufferPool, RecvBuffer};
@@ -19,7 +20,10 @@ pub(crate) struct State {
     pub(crate) _rpc_adapter_id: usize,
     // shared among all engines of a user process
     pub(crate) shared: Arc<Shared>,
+    // sock_handle -> ConnectionContext
     pub(crate) conn_table: RefCell<HashMap<Handle, ConnectionContext>>,
+    // the conn_id the application refers to a connection by -> sock_handle, see `insert_conn`
+    pub(crate) conn_ids: RefCell<VersionedSlab<Handle>>,
     pub(crate) recv_buffer_table: RefCell<HashMap<Handle, RecvBuffer>>,
 }
 // SAFETY: State in tcp will not be shared by multiple threads
@@ -33,6 +37,7 @@ impl State {
             _rpc_adapter_id: rpc_adapter_id,
             shared,
             conn_table: RefCell::new(HashMap::default()),
+            conn_ids: RefCell::new(VersionedSlab::default()),
             recv_buffer_table: RefCell::new(HashMap::default()),
         }
     }
@@ -45,12 +50,41 @@ impl Clone for State {
             _rpc_adapter_id,
             shared: Arc::clone(&self.shared),
             conn_table: RefCell::new(HashMap::default()),
+            conn_ids: RefCell::new(VersionedSlab::default()),
             recv_buffer_table: RefCell::new(HashMap::default()),
         }
     }
 }
 
 impl State {
+    /// Adds the connection on the socket. Returns the `conn_id` the application refers to it by.
+    ///
+    /// The socket handle is a file descriptor, which is reused once the connection is closed, so
+    /// the application is given a versioned handle instead. A handle kept past its connection
+    /// is rejected with [`ResourceError::Stale`] rather than addressing the connection that
+    /// reuses the socket.
+    pub(crate) fn insert_conn(&self, sock_handle: Handle) -> Handle {
+        let conn_id = self.conn_ids.borrow_mut().insert(sock_handle);
+        self.conn_table
+            .borrow_mut()
+            .insert(sock_handle, ConnectionContext::new(sock_handle, conn_id));
+        conn_id
+    }
+
+    /// Removes the connection on the socket. Returns the `conn_id` it was referred to by.
+    pub(crate) fn remove_conn(&self, sock_handle: Handle) -> Option<Handle> {
+        let conn_ctx = self.conn_table.borrow_mut().remove(&sock_handle)?;
+        // the handle is only removed along with the connection
+        self.conn_ids.borrow_mut().remove(conn_ctx.conn_id).unwrap();
+        Some(conn_ctx.conn_id)
+    }
+
+    /// The socket of the connection `conn_id` refers to.
+    #[inline]
+    pub(crate) fn sock_handle(&self, conn_id: Handle) -> Result<Handle, ResourceError> {
+        self.conn_ids.borrow().get(conn_id).copied()
+    }
+
     #[inline]
     #[allow(dead_code)]
     pub(crate) fn resource(&self) -> &Resource {
@@ -108,17 +142,51 @@ pub(crate) struct RecvContext {
     pub(crate) recv_mrs: Vec<Handle>,
 }
 
+/// The messages and the bytes sent and received on a connection.
+#[derive(Debug, Default)]
+pub(crate) struct Traffic {
+    pub(crate) bytes_sent: u64,
+    pub(crate) messages_sent: u64,
+    pub(crate) bytes_received: u64,
+    pub(crate) messages_received: u64,
+    pub(crate) messages_dropped: u64,
+}
+
+impl Traffic {
+    #[inline]
+    pub(crate) fn sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as u64;
+        self.messages_sent += 1;
+    }
+
+    #[inline]
+    pub(crate) fn received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
+        self.messages_received += 1;
+    }
+
+    #[inline]
+    pub(crate) fn dropped(&mut self) {
+        self.messages_dropped += 1;
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct ConnectionContext {
     pub(crate) sock_handle: Handle,
+    /// The versioned handle the application refers to the connection by
+    pub(crate) conn_id: Handle,
     pub(crate) receiving_ctx: RecvContext,
+    pub(crate) traffic: Traffic,
 }
 
 impl ConnectionContext {
-    pub(crate) fn new(sock_handle: Handle) -> Self {
+    pub(crate) fn new(sock_handle: Handle, conn_id: Handle) -> Self {
         Self {
             sock_handle,
+            conn_id,
             receiving_ctx: RecvContext::default(),
+            traffic: Traffic::default(),
         }
     }
 }
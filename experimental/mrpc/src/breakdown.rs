@@ -0,0 +1,235 @@
+//! Latency breakdown of the calls, with the `breakdown` feature.
+//!
+//! When `latency_breakdown` is enabled in the config of the mRPC plugin, the backend stamps each
+//! request when the mRPC engine dequeues it, when the RPC adapter posts it to the transport, and
+//! when the mRPC engine learns that it has been sent, and hands the stamps back to the
+//! application. The client stub adds the stamps of the enqueueing of the request and of the
+//! delivery of the reply. All the stamps are read from the time stamp counter, so that those of
+//! the application and of the backend can be compared.
+//!
+//! The durations of the [`Stage`]s of the calls made on the current thread are kept until
+//! [`reset`], and [`report`] summarizes them in percentiles. A call is left out if the backend
+//! does not stamp it, or if it fails.
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api::rpc::{tsc, RpcId, StageStamps};
+
+/// Calls stamped in the application but not by the backend are dropped past this many.
+const MAX_PENDING: usize = 65536;
+
+/// A stage of a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// From the enqueueing of the request by the stub to its dequeueing by the mRPC engine,
+    /// i.e., the shared memory queue.
+    Queue,
+    /// From the dequeueing of the request by the mRPC engine to its posting to the transport,
+    /// i.e., the engines of the backend.
+    Engine,
+    /// From the posting of the request to the transport to the mRPC engine learning that it has
+    /// been sent, i.e., the NIC. The send completions may be signaled in batches, which is
+    /// included. This stage overlaps with [`Stage::Reply`].
+    Send,
+    /// From the posting of the request to the transport to the delivery of the reply, i.e., the
+    /// network, the server, and the way back.
+    Reply,
+    /// From the enqueueing of the request to the delivery of the reply.
+    Total,
+}
+
+impl Stage {
+    /// All the stages, in the order they are reported.
+    pub const ALL: [Stage; 5] = [
+        Stage::Queue,
+        Stage::Engine,
+        Stage::Send,
+        Stage::Reply,
+        Stage::Total,
+    ];
+}
+
+/// The percentiles of the durations of a stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageReport {
+    pub stage: Stage,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+/// The latency breakdown of the calls made on a thread, see [`report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The number of calls broken down.
+    pub calls: usize,
+    /// The percentiles of each stage, empty if no call has been broken down.
+    pub stages: Vec<StageReport>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "latency breakdown of {} calls:", self.calls)?;
+        for s in &self.stages {
+            writeln!(
+                f,
+                "{:>8}: p50 {:?}, p90 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+                format!("{:?}", s.stage),
+                s.p50,
+                s.p90,
+                s.p99,
+                s.p999,
+                s.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    enqueue: u64,
+    backend: Option<StageStamps>,
+    delivery: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Recorder {
+    pending: HashMap<RpcId, Pending>,
+    // the durations in cycles, by stage
+    samples: [Vec<u64>; Stage::ALL.len()],
+}
+
+impl Recorder {
+    fn complete(&mut self, rpc_id: RpcId) {
+        let Some(Pending {
+            enqueue,
+            backend: Some(backend),
+            delivery: Some(delivery),
+        }) = self.pending.get(&rpc_id)
+        else {
+            return;
+        };
+        let durations = [
+            backend.engine_dequeue.saturating_sub(*enqueue),
+            backend.post_send.saturating_sub(backend.engine_dequeue),
+            backend.completion.saturating_sub(backend.post_send),
+            delivery.saturating_sub(backend.post_send),
+            delivery.saturating_sub(*enqueue),
+        ];
+        for (samples, d) in self.samples.iter_mut().zip(durations) {
+            samples.push(d);
+        }
+        self.pending.remove(&rpc_id);
+    }
+}
+
+thread_local! {
+    static RECORDER: RefCell<Recorder> = RefCell::new(Recorder::default());
+}
+
+lazy_static::lazy_static! {
+    static ref NANOS_PER_CYCLE: f64 = {
+        let (start, start_tsc) = (Instant::now(), tsc());
+        std::thread::sleep(Duration::from_millis(10));
+        let cycles = tsc().saturating_sub(start_tsc);
+        if cycles == 0 {
+            0.0
+        } else {
+            start.elapsed().as_nanos() as f64 / cycles as f64
+        }
+    };
+}
+
+/// The request of the call has been enqueued for the backend.
+#[inline]
+pub(crate) fn record_enqueue(rpc_id: RpcId) {
+    RECORDER.with_borrow_mut(|r| {
+        if r.pending.len() >= MAX_PENDING {
+            // the backend does not stamp the requests
+            r.pending.clear();
+        }
+        r.pending.insert(
+            rpc_id,
+            Pending {
+                enqueue: tsc(),
+                ..Default::default()
+            },
+        );
+    });
+}
+
+/// The backend has handed back the stamps of the request of the call.
+#[inline]
+pub(crate) fn record_backend(rpc_id: RpcId, stamps: StageStamps) {
+    RECORDER.with_borrow_mut(|r| {
+        if let Some(pending) = r.pending.get_mut(&rpc_id) {
+            pending.backend = Some(stamps);
+            r.complete(rpc_id);
+        }
+    });
+}
+
+/// The reply of the call has been delivered.
+#[inline]
+pub(crate) fn record_delivery(rpc_id: RpcId) {
+    let now = tsc();
+    RECORDER.with_borrow_mut(|r| {
+        if let Some(pending) = r.pending.get_mut(&rpc_id) {
+            pending.delivery = Some(now);
+            r.complete(rpc_id);
+        }
+    });
+}
+
+/// The call has failed.
+#[inline]
+pub(crate) fn discard(rpc_id: RpcId) {
+    RECORDER.with_borrow_mut(|r| r.pending.remove(&rpc_id));
+}
+
+fn percentile(sorted: &[u64], p: f64) -> Duration {
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    Duration::from_nanos((sorted[index] as f64 * *NANOS_PER_CYCLE) as u64)
+}
+
+/// Summarizes the latency breakdown of the calls made on the current thread since the last
+/// [`reset`].
+pub fn report() -> Report {
+    RECORDER.with_borrow(|r| {
+        let calls = r.samples[0].len();
+        if calls == 0 {
+            return Report {
+                calls,
+                stages: Vec::new(),
+            };
+        }
+        let stages = Stage::ALL
+            .iter()
+            .zip(&r.samples)
+            .map(|(&stage, samples)| {
+                let mut sorted = samples.clone();
+                sorted.sort_unstable();
+                StageReport {
+                    stage,
+                    p50: percentile(&sorted, 0.5),
+                    p90: percentile(&sorted, 0.9),
+                    p99: percentile(&sorted, 0.99),
+                    p999: percentile(&sorted, 0.999),
+                    max: percentile(&sorted, 1.0),
+                }
+            })
+            .collect();
+        Report { calls, stages }
+    })
+}
+
+/// Forgets the calls made on the current thread so far.
+pub fn reset() {
+    RECORDER.with_borrow_mut(|r| *r = Recorder::default());
+}
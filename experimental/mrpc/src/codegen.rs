@@ -112,12 +112,13 @@ pub mod greeter_server {
             &self,
             req_opaque: mrpc::MessageErased,
             read_heap: std::sync::Arc<mrpc::ReadHeap>,
+            conn: std::sync::Arc<mrpc::stub::ConnectionContext>,
         ) -> (mrpc::WRefOpaque, mrpc::MessageErased) {
             let func_id = req_opaque.meta.func_id;
             match func_id {
                 // TODO(cjr): fill this with the right func_id
                 3687134534u32 => {
-                    let req = ::mrpc::RRef::new(&req_opaque, read_heap);
+                    let req = ::mrpc::RRef::with_context(&req_opaque, read_heap, conn);
                     let res = self.inner.say_hello(req).await;
                     match res {
                         Ok(reply) => ::mrpc::stub::service_post_handler(reply, &req_opaque),
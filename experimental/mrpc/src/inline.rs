@@ -0,0 +1,65 @@
+//! Small messages carried inside the work requests and the completions rather than on the shared
+//! heap, see [`dp::MAX_INLINE_PAYLOAD`].
+//!
+//! A message sent is copied into the queue after its work request while its struct fits in the
+//! `max_inline_payload` of the [`Setting`] of the thread. The data of its fields outside of the
+//! struct stays on the heap, so the message is still held until it is acknowledged.
+//!
+//! A message received in a completion is copied to a buffer of the app, which its [`RRef`]
+//! refers to. The backend gives the receive buffer back as soon as it has written the completion,
+//! so dropping the [`RRef`] does not send a work request.
+//!
+//! [`Setting`]: phoenix_api_mrpc::control_plane::Setting
+//! [`RRef`]: crate::RRef
+use std::alloc::Layout;
+
+use phoenix_api::rpc::{MessageErased, MessageMeta};
+use phoenix_api_mrpc::dp;
+
+use crate::MRPC_CTX;
+
+/// The copy of a message received in a completion.
+#[derive(Debug)]
+#[repr(C, align(64))]
+pub(crate) struct InlineCopy([u8; dp::MAX_INLINE_PAYLOAD]);
+
+/// Returns the number of bytes to copy after the work request of a message whose struct is of
+/// `layout`, `None` if the message is sent from the shared heap.
+#[inline]
+pub(crate) fn payload_len(layout: Layout) -> Option<usize> {
+    let max = MRPC_CTX.with(|ctx| ctx.max_inline_payload());
+    (layout.size() <= max && layout.align() <= dp::INLINE_PAYLOAD_ALIGN).then_some(layout.size())
+}
+
+/// Copies the struct of a message received in a completion to a buffer of the app. The message
+/// returned refers to the copy, which is owned by the first [`RRef`](crate::RRef) made of it.
+///
+/// # Panics
+///
+/// Panics if `payload` is longer than [`dp::MAX_INLINE_PAYLOAD`].
+pub(crate) fn receive(meta: MessageMeta, payload: &[u8]) -> MessageErased {
+    let mut copy = Box::new(InlineCopy([0; dp::MAX_INLINE_PAYLOAD]));
+    copy.0[..payload.len()].copy_from_slice(payload);
+    MessageErased {
+        meta,
+        shm_addr_app: Box::into_raw(copy) as usize,
+        // there is no copy in the backend
+        shm_addr_backend: 0,
+    }
+}
+
+/// Returns whether the message has been received in a completion, see [`receive`].
+#[inline]
+pub(crate) fn is_inline(msg: &MessageErased) -> bool {
+    msg.shm_addr_backend == 0
+}
+
+/// Takes the copy of a message received in a completion.
+///
+/// # Safety
+///
+/// The message must have been returned by [`receive`], and its copy not taken before.
+#[inline]
+pub(crate) unsafe fn take(msg: &MessageErased) -> Box<InlineCopy> {
+    Box::from_raw(msg.shm_addr_app as *mut InlineCopy)
+}
@@ -0,0 +1,27 @@
+//! JSON transcoding of messages.
+//!
+//! Messages generated by `mrpc-build` with [`json_transcoding`] enabled implement
+//! [`serde::Serialize`] and [`serde::Deserialize`], and have `to_json` and `from_json` methods
+//! that forward to the functions here. A message read from JSON has its strings and vectors
+//! allocated on the shared memory heap, so it can be sent as is.
+//!
+//! [`json_transcoding`]: ../../mrpc_build/struct.Builder.html#method.json_transcoding
+pub use serde_json::Error;
+
+/// Renders a message in JSON.
+#[inline]
+pub fn to_json<T: serde::Serialize>(msg: &T) -> Result<String, Error> {
+    serde_json::to_string(msg)
+}
+
+/// Renders a message in pretty-printed JSON.
+#[inline]
+pub fn to_json_pretty<T: serde::Serialize>(msg: &T) -> Result<String, Error> {
+    serde_json::to_string_pretty(msg)
+}
+
+/// Constructs a message from JSON.
+#[inline]
+pub fn from_json<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, Error> {
+    serde_json::from_str(json)
+}
@@ -26,8 +26,27 @@
 //!
 //! # Max Message Size
 //!
-//! Currently, both servers and clients are using a fixed `8MB` as the limit for maximal message size.
-//! This fixed limit will be removed or made configurable in the future.
+//! Both servers and clients are bound by a fixed `8MB` limit for maximal message size. Lower
+//! limits can be set for all or individual services in the config of the mRPC plugin
+//! (`max_request_size` and `max_response_size`), by a server through the builder methods of the
+//! generated server, or at runtime with [`stub::set_message_size_limit`]. A message over the limit
+//! is rejected by the backend before it is transmitted, and the call fails with
+//! [`Code::ResourceExhausted`].
+//!
+//! # Shared Memory Heap
+//!
+//! Messages are allocated on a heap shared with the backend, which is grown on demand. To keep
+//! the first calls from paying for growing it, set [`HeapWarmup`] in the [`SallocSetting`] of the
+//! thread with [`set_salloc_setting`] before creating any stub. The setting also chooses what
+//! happens to an allocation when the heap of the process reaches the limit of the backend.
+//!
+//! # Daemon Restarts
+//!
+//! When phoenixd restarts, a thread registers with the new daemon on its next call and a stub
+//! created by [`stub::ClientStub::connect`] connects to its server again. The calls in flight at
+//! that time fail with [`Code::Unavailable`]. The shared memory heap is not carried over:
+//! messages allocated before the restart can no longer be sent, and sending one fails with
+//! [`Code::Unavailable`] too.
 //!
 //! [`mRPC`]: https://github.com/phoenix-dataplane/phoenix/tree/main/experimental/mrpc
 //! [`Phoenix`]: https://github.com/phoenix-dataplane/phoenix
@@ -50,14 +69,16 @@
 // WRef
 #![feature(get_mut_unchecked)]
 
-use std::cell::RefCell;
+use std::cell::{Cell, Ref, RefCell};
 use std::collections::BTreeSet;
 use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
 
 use ipc::service::ShmService;
-pub use phoenix_api::engine::SchedulingHint;
+pub use phoenix_api::engine::{SchedulingClass, SchedulingHint};
 use phoenix_api_mrpc::control_plane::Setting;
 use phoenix_api_mrpc::{cmd, dp};
 use phoenix_syscalls::_rx_recv_impl as rx_recv_impl;
@@ -71,6 +92,13 @@ pub use rheap::ReadHeap;
 
 use shmalloc::backend::SA_CTX;
 
+#[doc(inline)]
+pub use phoenix_api::salloc::control_plane::{
+    HeapFullPolicy, HeapWarmup, Setting as SallocSetting,
+};
+#[doc(inline)]
+pub use shmalloc::backend::{current_setting as salloc_setting, set_setting as set_salloc_setting};
+
 /// Returns the current mRPC [`Setting`].
 pub fn current_setting() -> Setting {
     SETTING.with_borrow(|s| s.clone())
@@ -111,15 +139,45 @@ thread_local! {
     }
 }
 
+type MrpcService =
+    ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>;
+
+/// How often to check whether the backend is still alive.
+const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// The longest time to wait for a restarted backend before giving up.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+/// The longest delay between two attempts to reconnect.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
 pub(crate) struct Context {
     protos: RefCell<BTreeSet<String>>,
-    service: ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>,
+    descriptors: RefCell<BTreeSet<&'static [u8]>>,
+    // the size limits set at runtime, to be set again on the restarted backend
+    size_limits: RefCell<Vec<(Option<u32>, cmd::MessageSizeLimit)>>,
+    service: RefCell<MrpcService>,
+    // the largest message carried inside its work request, see inline
+    max_inline_payload: usize,
+    // the number of times the backend has been replaced
+    generation: Cell<u64>,
+    last_check: Cell<Instant>,
 }
 
 impl Context {
     fn register(setting: &Setting) -> Result<Context, Error> {
-        let protos = RefCell::new(BTreeSet::new());
         println!("mrpc register: {:?}", setting);
+        let service = Self::register_service(setting)?;
+        Ok(Self {
+            protos: RefCell::new(BTreeSet::new()),
+            descriptors: RefCell::new(BTreeSet::new()),
+            size_limits: RefCell::new(Vec::new()),
+            service: RefCell::new(service),
+            max_inline_payload: setting.max_inline_payload.min(dp::MAX_INLINE_PAYLOAD),
+            generation: Cell::new(0),
+            last_check: Cell::new(Instant::now()),
+        })
+    }
+
+    fn register_service(setting: &Setting) -> Result<MrpcService, Error> {
         let setting_str = serde_json::to_string(setting)?;
         let mut service = "Mrpc".to_string();
         if let Some(name) = &setting.module_config {
@@ -132,7 +190,93 @@ impl Context {
             SCHEDULING_HINT.with_borrow(|h| *h),
             Some(&setting_str),
         )?;
-        Ok(Self { protos, service })
+        Ok(service)
+    }
+
+    #[inline]
+    pub(crate) fn service(&self) -> Ref<'_, MrpcService> {
+        self.service.borrow()
+    }
+
+    /// Returns the largest message carried inside its work request.
+    #[inline]
+    pub(crate) fn max_inline_payload(&self) -> usize {
+        self.max_inline_payload
+    }
+
+    /// Returns the number of times the backend has been replaced.
+    #[inline]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+
+    /// Checks whether the backend is still alive. If it has exited, waits for phoenixd to come
+    /// back and registers with it again, with the protos, descriptors and message size limits
+    /// of the thread. Returns the generation of the backend.
+    pub(crate) fn ensure_backend(&self) -> Result<u64, Error> {
+        if self.last_check.get().elapsed() < LIVENESS_CHECK_INTERVAL {
+            return Ok(self.generation());
+        }
+        self.last_check.set(Instant::now());
+        if self.service().is_peer_alive() {
+            return Ok(self.generation());
+        }
+
+        log::warn!("phoenixd has exited, reconnecting");
+        let start = Instant::now();
+        let mut backoff = Duration::from_millis(10);
+        loop {
+            match self.reconnect() {
+                Ok(()) => break,
+                Err(e) if start.elapsed() >= RECONNECT_TIMEOUT => {
+                    log::error!("Failed to reconnect to phoenixd: {}", e);
+                    return Err(Error::Disconnected);
+                }
+                Err(e) => {
+                    log::debug!(
+                        "Failed to reconnect to phoenixd: {}, retry in {:?}",
+                        e,
+                        backoff
+                    );
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                }
+            }
+        }
+        self.generation.set(self.generation() + 1);
+        self.last_check.set(Instant::now());
+        log::info!("Reconnected to phoenixd");
+        Ok(self.generation())
+    }
+
+    fn reconnect(&self) -> Result<(), Error> {
+        SA_CTX.with(|ctx| ctx.reconnect())?;
+        let service = Self::register_service(&current_setting())?;
+        *self.service.borrow_mut() = service;
+
+        let protos = self.protos.borrow().iter().cloned().collect::<Vec<_>>();
+        if !protos.is_empty() {
+            self.service()
+                .send_cmd(cmd::Command::UpdateProtos(protos))?;
+            rx_recv_impl!(self.service(), cmd::CompletionKind::UpdateProtos)?;
+        }
+        let descriptors = self
+            .descriptors
+            .borrow()
+            .iter()
+            .map(|d| d.to_vec())
+            .collect::<Vec<_>>();
+        if !descriptors.is_empty() {
+            let req = cmd::Command::UpdateDescriptors(descriptors);
+            self.service().send_cmd(req)?;
+            rx_recv_impl!(self.service(), cmd::CompletionKind::UpdateDescriptors)?;
+        }
+        for (service_id, limit) in self.size_limits.borrow().iter() {
+            let req = cmd::Command::SetMessageSizeLimit(*service_id, *limit);
+            self.service().send_cmd(req)?;
+            rx_recv_impl!(self.service(), cmd::CompletionKind::SetMessageSizeLimit)?;
+        }
+        Ok(())
     }
 
     fn update_protos(&self, protos: &[&str]) -> Result<(), Error> {
@@ -142,8 +286,33 @@ impl Context {
         if used_protos.len() > orig {
             let protos = used_protos.iter().cloned().collect::<Vec<_>>();
             let req = cmd::Command::UpdateProtos(protos);
-            self.service.send_cmd(req)?;
-            rx_recv_impl!(self.service, cmd::CompletionKind::UpdateProtos)?;
+            self.service().send_cmd(req)?;
+            rx_recv_impl!(self.service(), cmd::CompletionKind::UpdateProtos)?;
+        }
+        Ok(())
+    }
+
+    fn set_message_size_limit(
+        &self,
+        service_id: Option<u32>,
+        limit: cmd::MessageSizeLimit,
+    ) -> Result<(), Error> {
+        let req = cmd::Command::SetMessageSizeLimit(service_id, limit);
+        self.service().send_cmd(req)?;
+        rx_recv_impl!(self.service(), cmd::CompletionKind::SetMessageSizeLimit)?;
+        self.size_limits.borrow_mut().push((service_id, limit));
+        Ok(())
+    }
+
+    fn update_descriptors(&self, descriptors: &[&'static [u8]]) -> Result<(), Error> {
+        let mut used_descriptors = self.descriptors.borrow_mut();
+        let orig = used_descriptors.len();
+        used_descriptors.extend(descriptors.iter().copied().filter(|d| !d.is_empty()));
+        if used_descriptors.len() > orig {
+            let descriptors = used_descriptors.iter().map(|d| d.to_vec()).collect();
+            let req = cmd::Command::UpdateDescriptors(descriptors);
+            self.service().send_cmd(req)?;
+            rx_recv_impl!(self.service(), cmd::CompletionKind::UpdateDescriptors)?;
         }
         Ok(())
     }
@@ -178,6 +347,8 @@ pub use phoenix_api::rpc::MessageErased;
 mod rref;
 pub use rref::RRef;
 
+pub(crate) mod inline;
+
 mod wref;
 pub use wref::{IntoWRef, WRef, WRefOpaque};
 
@@ -188,6 +359,9 @@ pub use status::{Code, Status};
 #[cfg(feature = "timing")]
 pub(crate) mod timing;
 
+#[cfg(feature = "breakdown")]
+pub mod breakdown;
+
 pub mod sched;
 #[doc(inline)]
 pub use sched::{bind_to_node, num_numa_nodes};
@@ -195,6 +369,14 @@ pub use sched::{bind_to_node, num_numa_nodes};
 /// A re-export of [`async-trait`](https://docs.rs/async-trait) for use with codegen.
 pub use async_trait::async_trait;
 
+/// A re-export of [`serde`](https://docs.rs/serde) for use with codegen.
+#[doc(hidden)]
+pub use serde;
+
+pub mod json;
+
+pub mod testing;
+
 /// The error type for operations interacting with the mRPC service.
 #[derive(Error, Debug)]
 pub enum Error {
@@ -219,4 +401,13 @@ pub enum Error {
     /// Connection has been closed.
     #[error("Connection closed.")]
     ConnectionClosed,
+    /// Errors from the shared memory heap.
+    #[error("Salloc error: {0}")]
+    Salloc(#[from] shmalloc::backend::Error),
+    /// phoenixd has exited and did not come back in time.
+    #[error("Disconnected from phoenixd")]
+    Disconnected,
+    /// The message is allocated on the heap shared with phoenixd before it restarted.
+    #[error("Message allocated before phoenixd restarted")]
+    StaleMessage,
 }
@@ -7,9 +7,13 @@ use std::ops::Deref;
 use std::sync::Arc;
 
 use phoenix_api::rpc::{CallId, MessageErased, RpcId, Token};
+use phoenix_api::Handle;
 use phoenix_api_mrpc::dp::{WorkRequest, RECV_RECLAIM_BS};
 use shm::ptr::ShmPtr;
 
+use crate::inline::{self, InlineCopy};
+use crate::stub::{ConnectionContext, RpcData};
+use crate::wref::{WRef, WRefOpaque};
 use crate::ReadHeap;
 use crate::MRPC_CTX;
 
@@ -20,6 +24,15 @@ struct RRefInner<T> {
     token: Token,
     read_heap: Arc<ReadHeap>,
     data: ShmPtr<T>,
+    /// The generation of the backend that received the message.
+    generation: u64,
+    /// The connection a request arrives on at a server.
+    context: Option<Arc<ConnectionContext>>,
+    /// The message this refers to when it is not received from the backend, see
+    /// [`RRef::from_local`].
+    local: Option<WRefOpaque>,
+    /// The copy of the message when it is received in a completion, see [`inline`].
+    inline: Option<Box<InlineCopy>>,
 }
 
 /// A thread-safe reference-counting pointer to objects on the read-only shared memory heap.
@@ -29,6 +42,12 @@ pub struct RRef<T>(Arc<RRefInner<T>>);
 // but the shared memory should be properly recycled by the backend
 impl<T> Drop for RRefInner<T> {
     fn drop(&mut self) {
+        if self.local.is_some() || self.inline.is_some() {
+            // there is no receive buffer to give back
+            self.read_heap.decrement_refcnt();
+            return;
+        }
+
         let msgs: [MaybeUninit<CallId>; RECV_RECLAIM_BS] = MaybeUninit::uninit_array();
         let mut msgs = unsafe { MaybeUninit::array_assume_init(msgs) };
         msgs[0] = self.rpc_id.1;
@@ -36,9 +55,13 @@ impl<T> Drop for RRefInner<T> {
         let conn_id = self.rpc_id.0;
         let reclaim_wr = WorkRequest::ReclaimRecvBuf(conn_id, msgs);
         MRPC_CTX.with(move |ctx| {
+            // the backend that owns the buffer has exited
+            if ctx.generation() != self.generation {
+                return;
+            }
             let mut sent = false;
             while !sent {
-                ctx.service
+                ctx.service()
                     .enqueue_wr_with(|ptr, _count| unsafe {
                         ptr.cast::<WorkRequest>().write(reclaim_wr);
                         sent = true;
@@ -58,8 +81,33 @@ impl<T> RRef<T> {
     #[must_use]
     #[inline]
     pub fn new(msg: &MessageErased, read_heap: Arc<ReadHeap>) -> Self {
+        Self::new_inner(msg, read_heap, None)
+    }
+
+    /// Constructs an `RRef<T>` for a request received by a server on the connection of `context`.
+    #[must_use]
+    #[inline]
+    pub fn with_context(
+        msg: &MessageErased,
+        read_heap: Arc<ReadHeap>,
+        context: Arc<ConnectionContext>,
+    ) -> Self {
+        Self::new_inner(msg, read_heap, Some(context))
+    }
+
+    #[inline]
+    fn new_inner(
+        msg: &MessageErased,
+        read_heap: Arc<ReadHeap>,
+        context: Option<Arc<ConnectionContext>>,
+    ) -> Self {
         let ptr_app = msg.shm_addr_app as *mut T;
-        let ptr_backend = ptr_app.with_addr(msg.shm_addr_backend);
+        let (inline, ptr_backend) = if inline::is_inline(msg) {
+            // SAFETY: a message received is made into a single RRef, which owns its copy
+            (Some(unsafe { inline::take(msg) }), ptr_app)
+        } else {
+            (None, ptr_app.with_addr(msg.shm_addr_backend))
+        };
         let backend_owned = ShmPtr::new(ptr_app, ptr_backend).unwrap();
 
         let rpc_id = RpcId::new(msg.meta.conn_id, msg.meta.call_id);
@@ -72,6 +120,38 @@ impl<T> RRef<T> {
             token: Token(msg.meta.token as usize),
             read_heap,
             data: backend_owned,
+            generation: MRPC_CTX.with(|ctx| ctx.generation()),
+            context,
+            local: None,
+            inline,
+        }))
+    }
+
+    /// Constructs an `RRef<T>` that reads a message on the writable heap in place, as if it had
+    /// been received, without the backend. This is how the test doubles in [`testing`] deliver
+    /// their requests and replies.
+    ///
+    /// [`testing`]: crate::testing
+    #[must_use]
+    pub fn from_local(msg: WRef<T>) -> Self
+    where
+        T: RpcData,
+    {
+        let (ptr_app, ptr_backend) = msg.clone().into_shmptr().to_raw_parts();
+        let data = ShmPtr::new(ptr_app.as_ptr(), ptr_backend.as_ptr()).unwrap();
+
+        let read_heap = Arc::new(ReadHeap::default());
+        read_heap.increment_refcnt();
+
+        RRef(Arc::new(RRefInner {
+            rpc_id: RpcId::new(Handle::INVALID, CallId(0)),
+            token: msg.token(),
+            read_heap,
+            data,
+            generation: 0,
+            context: None,
+            local: Some(msg.into_opaque()),
+            inline: None,
         }))
     }
 
@@ -81,6 +161,14 @@ impl<T> RRef<T> {
     pub fn token(&self) -> Token {
         self.0.token
     }
+
+    /// Returns the context of the connection a request arrives on. Returns `None` for a reply or
+    /// a notification received by a client.
+    #[must_use]
+    #[inline]
+    pub fn context(&self) -> Option<&Arc<ConnectionContext>> {
+        self.0.context.as_ref()
+    }
 }
 
 impl<T> Clone for RRef<T> {
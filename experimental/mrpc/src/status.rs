@@ -315,6 +315,14 @@ impl Status {
             TransportStatus::Success => Status::ok(""),
             TransportStatus::Error(code) => match code.get() {
                 402 => Status::permission_denied("Access Denied from server ACL engine"),
+                409 => Status::aborted("The call id is out of sequence on the connection"),
+                413 => Status::resource_exhausted("Request exceeds the maximum message size"),
+                414 => Status::resource_exhausted("Message exceeds the maximum message size"),
+                500 => Status::unknown("The server failed the call"),
+                501 => Status::unimplemented("The method is not implemented by the server"),
+                502 => Status::unavailable("The circuit breaker of the destination is open"),
+                503 => Status::unavailable("The call is lost as phoenixd has restarted"),
+                508 => Status::aborted("The request exceeds its hop limit in a forwarding loop"),
                 _ => Status::data_loss(format!("receiving wc error: {code}")),
             },
         }
@@ -412,16 +420,37 @@ impl From<std::io::Error> for Status {
     }
 }
 
+impl From<phoenix_api::ErrorCode> for Code {
+    fn from(code: phoenix_api::ErrorCode) -> Self {
+        use phoenix_api::ErrorCode;
+        match code {
+            ErrorCode::Unknown => Code::Unknown,
+            ErrorCode::InvalidArgument => Code::InvalidArgument,
+            ErrorCode::NotFound => Code::NotFound,
+            ErrorCode::AlreadyExists => Code::AlreadyExists,
+            ErrorCode::PermissionDenied => Code::PermissionDenied,
+            ErrorCode::ResourceExhausted => Code::ResourceExhausted,
+            ErrorCode::Unavailable => Code::Unavailable,
+            ErrorCode::Timeout => Code::DeadlineExceeded,
+            ErrorCode::Unimplemented => Code::Unimplemented,
+            ErrorCode::Internal => Code::Internal,
+        }
+    }
+}
+
 impl From<crate::Error> for Status {
     fn from(err: crate::Error) -> Self {
         use crate::Error::*;
         // TODO(cjr): This mapping doesn't make sense at all.
         let code = match err {
-            Service(..) | Interface(..) | Io(..) => Code::Internal,
+            Interface(_, ref e) => e.code().into(),
+            Service(..) | Io(..) => Code::Internal,
             Serde(..) => Code::InvalidArgument,
             NoAddrResolved => Code::NotFound,
-            Connect(..) => Code::Unavailable,
+            Connect(..) | Disconnected | StaleMessage => Code::Unavailable,
             ConnectionClosed => Code::Cancelled,
+            Salloc(ref e) if e.is_heap_full() => Code::ResourceExhausted,
+            Salloc(..) => Code::Internal,
         };
         Status::new(code, err.to_string())
     }
@@ -1,17 +1,22 @@
 //! Client implementation.
-use std::collections::HashMap;
+use std::alloc::Layout;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::hash::Hash;
 use std::marker::PhantomData;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::num::NonZeroU32;
 use std::pin::Pin;
+use std::slice;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 
+use futures::Stream;
 use ipc::channel::{Receiver, TryRecvError};
 use phoenix_api::rpc::{CallId, MessageErased, MessageMeta, RpcId, RpcMsgType, TransportStatus};
 use phoenix_api::{AsHandle, Handle};
-use phoenix_api_mrpc::cmd::{Command, CompletionKind};
+use phoenix_api_mrpc::cmd::{Command, CompletionKind, TransportStats};
 use phoenix_api_mrpc::dp;
 use phoenix_syscalls::_rx_recv_impl as rx_recv_impl;
 
@@ -19,7 +24,7 @@ use super::conn::Connection;
 use super::reply_cache::ReplyCache;
 use super::RpcData;
 use super::LOCAL_REACTOR;
-use crate::{Error, RRef, ReadHeap, Status, WRef, MRPC_CTX};
+use crate::{inline, Error, RRef, ReadHeap, Status, WRef, MRPC_CTX};
 
 #[cfg(feature = "timing")]
 use crate::timing::{SampleKind, Timer};
@@ -43,16 +48,17 @@ impl<'a, T: Unpin> Future for ReqFuture<'a, T> {
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
 
+        this.client.ensure_connected()?;
         futures::ready!(LOCAL_REACTOR.with_borrow_mut(|r| r.poll(cx)))?;
 
         this.client.dispatch()?;
         // let inner = this.client.inner.borrow();
-        let inner = this.client.inner.lock();
+        let mut inner = this.client.inner.lock();
 
         // Poll::Pending
         if let Some(reply) = inner
             .reply_cache
-            .get(this.rpc_id.1)
+            .take(this.rpc_id.1)
             .expect("Expect an entry")
         {
             let ret = match reply {
@@ -64,22 +70,102 @@ impl<'a, T: Unpin> Future for ReqFuture<'a, T> {
                     let read_heap = this
                         .client
                         .conns
+                        .borrow()
                         .get(&reply.meta.conn_id)
                         .unwrap()
                         .map_alive(|alive| Arc::clone(&alive.read_heap))
                         .expect("TODO: return an error when connection is dead rather than panic");
-                    Ok(RRef::new(reply, read_heap))
+                    #[cfg(feature = "breakdown")]
+                    crate::breakdown::record_delivery(this.rpc_id);
+                    Ok(RRef::new(&reply, read_heap))
+                }
+                Err(status) => {
+                    #[cfg(feature = "breakdown")]
+                    crate::breakdown::discard(this.rpc_id);
+                    Err(Status::from_incoming_transport(status))
                 }
-                Err(status) => Err(Status::from_incoming_transport(*status)),
             };
+            LOCAL_REACTOR.with_borrow_mut(|r| r.unpark(this.client.stub_id, this.rpc_id.1));
             return Poll::Ready(ret);
         }
 
+        // wait for the reactor to steer the completion of the call here, unless no other task
+        // is polling the completion queue
+        let stub_id = this.client.stub_id;
+        if LOCAL_REACTOR.with_borrow_mut(|r| r.park(stub_id, this.rpc_id.1, cx.waker())) {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+impl<'a, T> Drop for ReqFuture<'a, T> {
+    fn drop(&mut self) {
+        LOCAL_REACTOR.with_borrow_mut(|r| r.unpark(self.client.stub_id, self.rpc_id.1));
+    }
+}
+
+/// Stream of the notifications pushed by the server for a func_id, returned by
+/// [`ClientStub::subscribe`]. Yields read-only [`RRef<T>`]s, and ends when the connection is
+/// closed.
+///
+/// Dropping the stream unsubscribes from the notifications.
+pub struct Notifications<'a, T> {
+    func_id: u32,
+    client: &'a ClientStub,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: Unpin> Stream for Notifications<'a, T> {
+    type Item = Result<RRef<T>, Status>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        this.client.ensure_connected()?;
+        futures::ready!(LOCAL_REACTOR.with_borrow_mut(|r| r.poll(cx)))?;
+
+        this.client.dispatch()?;
+        let msg = this
+            .client
+            .inner
+            .lock()
+            .subscriptions
+            .get_mut(&this.func_id)
+            .and_then(VecDeque::pop_front);
+
+        if let Some(msg) = msg {
+            tracing::trace!(
+                "Notifications receive a notification from mRPC engine, call_id={}",
+                msg.meta.call_id
+            );
+            let read_heap = this.client.read_heap(msg.meta.conn_id)?;
+            return Poll::Ready(Some(Ok(RRef::new(&msg, read_heap))));
+        }
+
+        if this.client.master_conn().map_alive(|_| ()).is_err() {
+            return Poll::Ready(None);
+        }
+
         cx.waker().wake_by_ref();
         Poll::Pending
     }
 }
 
+impl<'a, T> Drop for Notifications<'a, T> {
+    fn drop(&mut self) {
+        let queued = self.client.inner.lock().subscriptions.remove(&self.func_id);
+        // give the receive buffers of the notifications not taken back
+        for msg in queued.into_iter().flatten() {
+            self.client.reclaim(&msg);
+        }
+    }
+}
+
+/// The status of the calls lost when phoenixd restarts.
+const CALL_LOST: TransportStatus =
+    TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(503) });
+
 impl !Send for ClientStub {}
 impl !Sync for ClientStub {}
 
@@ -88,12 +174,18 @@ impl !Sync for ClientStub {}
 /// [`mrpc-build`]: ../../../doc/mrpc_build/index.html
 #[derive(Debug)]
 pub struct ClientStub {
-    vconn: Connection,
+    vconn: RefCell<Connection>,
     // A connection could go into error state, in that case, all subsequent operations over this
     // connection would return an error.
-    conns: HashMap<Handle, Connection>,
+    conns: RefCell<HashMap<Handle, Connection>>,
     // inner: RefCell<Inner>,
     inner: spin::Mutex<Inner>,
+    // The address to connect to again after phoenixd restarts. `None` if the stub is created by
+    // `multi_connect`, which cannot be reconnected.
+    addr: Option<SocketAddr>,
+    stub_id: usize,
+    // The generation of the backend the connections are established on.
+    generation: Cell<u64>,
 }
 
 #[derive(Debug)]
@@ -103,6 +195,8 @@ struct Inner {
     // Reply cache records whether a reply has been received for RPC client.  Each reply cache
     // should be assoicated to a connection.
     reply_cache: ReplyCache,
+    // The notifications received and not yet taken, for each func_id subscribed to.
+    subscriptions: HashMap<u32, VecDeque<MessageErased>>,
 }
 
 impl ClientStub {
@@ -118,6 +212,9 @@ impl ClientStub {
         Req: RpcData,
         Res: Unpin + RpcData,
     {
+        if let Err(e) = self.ensure_connected() {
+            self.fail_call(call_id, e);
+        }
         let conn_id = self.master_conn().handle();
 
         // construct meta
@@ -131,7 +228,9 @@ impl ClientStub {
             status_code: phoenix_api::rpc::StatusCode::Success,
         };
 
-        self.post_request(req, meta).unwrap();
+        if let Err(e) = self.post_request(req, meta) {
+            self.fail_call(call_id, e);
+        }
 
         ReqFuture {
             rpc_id: RpcId(conn_id, call_id),
@@ -142,12 +241,80 @@ impl ClientStub {
 
     /// Prepare to make an RPC.
     ///
-    /// Allocating an entry to the ongoing RPC slab.
+    /// Allocates the call id of the RPC. The call ids of a stub increase monotonically and are
+    /// never reused.
     #[inline]
     pub fn initiate_call(&self) -> CallId {
+        // reconnect before the call is made, so that it is not failed together with the calls
+        // on the old connection
+        if let Err(e) = self.ensure_connected() {
+            log::warn!("Failed to reconnect: {}", e);
+        }
         // self.inner.borrow_mut().reply_cache.initiate_call()
         self.inner.lock().reply_cache.initiate_call()
     }
+
+    /// Subscribes to the notifications the server pushes under `func_id`, see
+    /// [`ConnectionContext::notify`][crate::stub::ConnectionContext::notify].
+    ///
+    /// The notifications received before subscribing are dropped. So are those received while
+    /// phoenixd restarts, the server has to be asked for them again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `func_id` has been subscribed to by a stream not dropped yet.
+    pub fn subscribe<T: Unpin>(&self, func_id: u32) -> Notifications<'_, T> {
+        let prev = self
+            .inner
+            .lock()
+            .subscriptions
+            .insert(func_id, VecDeque::new());
+        assert!(
+            prev.is_none(),
+            "func_id {} is subscribed to already",
+            func_id
+        );
+        Notifications {
+            func_id,
+            client: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the traffic and the resources of the connection as phoenixd sees them, for the
+    /// application to diagnose itself. Those of the connections of a stub created by
+    /// `multi_connect` are summed up.
+    pub fn connection_stats(&self) -> Result<TransportStats, Error> {
+        self.ensure_connected()?;
+        let handles: Vec<Handle> = if self.vconn.borrow().handle().is_master() {
+            self.conns.borrow().keys().copied().collect()
+        } else {
+            vec![self.vconn.borrow().handle()]
+        };
+        let mut total = TransportStats::default();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let stats = MRPC_CTX.with(|ctx| {
+                ctx.service().send_cmd(Command::ConnectionStats(handle))?;
+                rx_recv_impl!(ctx.service(), CompletionKind::ConnectionStats, stats, {
+                    Ok(stats)
+                })
+            })?;
+            if i == 0 {
+                total = stats;
+                continue;
+            }
+            total.bytes_sent += stats.bytes_sent;
+            total.messages_sent += stats.messages_sent;
+            total.bytes_received += stats.bytes_received;
+            total.messages_received += stats.messages_received;
+            total.messages_dropped += stats.messages_dropped;
+            total.retransmits = total.retransmits.zip(stats.retransmits).map(|(a, b)| a + b);
+            total.credits = total.credits.zip(stats.credits).map(|(a, b)| a + b);
+            total.recv_heap_in_use += stats.recv_heap_in_use;
+            total.recv_heap_size += stats.recv_heap_size;
+        }
+        Ok(total)
+    }
 }
 
 impl ClientStub {
@@ -163,7 +330,22 @@ impl ClientStub {
                     }
                     RpcMsgType::Response => {
                         // client receives responses, update the ReplyCache
-                        inner.reply_cache.update(call_id, Ok(msg)).unwrap();
+                        if let Err(e) = inner.reply_cache.update(call_id, Ok(msg)) {
+                            log::warn!("Dropping the response: {}", e);
+                        }
+                    }
+                    RpcMsgType::Notification => {
+                        // client receives notifications, queue them for the subscriber
+                        match inner.subscriptions.get_mut(&msg.meta.func_id) {
+                            Some(queue) => queue.push_back(msg),
+                            None => {
+                                log::debug!(
+                                    "Dropping the notification of func_id {} not subscribed to",
+                                    msg.meta.func_id
+                                );
+                                self.reclaim(&msg);
+                            }
+                        }
                     }
                 }
             }
@@ -175,9 +357,12 @@ impl ClientStub {
                 // A success ack is returned by when the request is sent
                 // and 402 is returned when ACL denies the request
                 // in that case we must not remove the pending request twice!
+                // Similarly, 414 is returned when the request or its reply exceeds the
+                // maximum message size after the request has been sent, and 500 and 501 are
+                // returned when the server replies with an error.
                 match status {
                     TransportStatus::Error(code) => match code.get() {
-                        402 => {}
+                        402 | 414 | 500 | 501 => {}
                         _ => {
                             self.master_conn()
                                 .map_alive(|alive| alive.pending.remove(&rpc_id))?;
@@ -191,7 +376,9 @@ impl ClientStub {
 
                 if let TransportStatus::Error(_) = status {
                     // Update the ReplyCache with error
-                    inner.reply_cache.update(rpc_id.1, Err(status)).unwrap();
+                    if let Err(e) = inner.reply_cache.update(rpc_id.1, Err(status)) {
+                        log::warn!("Dropping the error {:?}: {}", status, e);
+                    }
                 }
             }
             dp::Completion::RecvError(conn_id, status) => {
@@ -205,6 +392,12 @@ impl ClientStub {
                 );
                 self.master_conn().close();
             }
+            dp::Completion::Stamps(..) => {
+                // taken by the reactor
+            }
+            dp::Completion::IncomingInline(..) | dp::Completion::Pad => {
+                // turned into an Incoming or skipped by the reactor
+            }
         }
 
         Ok(())
@@ -230,6 +423,52 @@ impl ClientStub {
         Ok(())
     }
 
+    /// Resolves the call with `Code::Unavailable` if `err` is caused by a restart of phoenixd.
+    fn fail_call(&self, call_id: CallId, err: Error) {
+        match err {
+            Error::Disconnected | Error::StaleMessage => {
+                log::warn!("Call {:?} failed: {}", call_id, err);
+                // the call may have been failed already
+                let _ = self
+                    .inner
+                    .lock()
+                    .reply_cache
+                    .update(call_id, Err(CALL_LOST));
+            }
+            err => panic!("{}", err),
+        }
+    }
+
+    /// Connects to the server again if phoenixd has restarted since the connection was
+    /// established. The calls in flight are failed.
+    fn ensure_connected(&self) -> Result<(), Error> {
+        let generation = MRPC_CTX.with(|ctx| ctx.ensure_backend())?;
+        if generation == self.generation.get() {
+            return Ok(());
+        }
+
+        {
+            let mut inner = self.inner.lock();
+            // the completions left are for the old connections
+            while inner.receiver.try_recv().is_ok() {}
+            inner.reply_cache.resolve_pending(|| Err(CALL_LOST));
+            LOCAL_REACTOR.with_borrow_mut(|r| r.wake_stub(self.stub_id));
+            // so are the notifications not taken, their buffers are gone with the old backend
+            inner.subscriptions.values_mut().for_each(VecDeque::clear);
+        }
+
+        let addr = self.addr.ok_or(Error::Disconnected)?;
+        let conn = Self::establish(addr)?;
+        LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(self.stub_id, &conn));
+        *self.vconn.borrow_mut() = Connection::vconn(conn.handle());
+        let mut conns = self.conns.borrow_mut();
+        conns.clear();
+        conns.insert(conn.handle(), conn);
+        self.generation.set(generation);
+        log::info!("Reconnected to {}", addr);
+        Ok(())
+    }
+
     pub(crate) fn post_request<T: RpcData>(
         &self,
         msg: WRef<T>,
@@ -240,6 +479,12 @@ impl ClientStub {
             meta.call_id
         );
 
+        let (ptr_app, ptr_backend) = WRef::clone(&msg).into_shmptr().to_raw_parts();
+        // the restarted backend cannot read a message allocated on the old heap
+        if self.generation.get() > 0 && !shmalloc::is_backed(ptr_app.addr().get()) {
+            return Err(Error::StaleMessage);
+        }
+
         // track the msg as pending
         // self.conn
         //     .hold_rpc(RpcId::new(meta.conn_id, meta.call_id), WRef::clone(&msg))?;
@@ -248,11 +493,10 @@ impl ClientStub {
             .map_alive(|alive: &crate::stub::conn::AliveConnection| {
                 alive
                     .pending
-                    .insert(RpcId::new(meta.conn_id, meta.call_id), WRef::clone(&msg))
+                    .insert(RpcId::new(meta.conn_id, meta.call_id), msg)
             })?;
 
         // construct the request
-        let (ptr_app, ptr_backend) = msg.into_shmptr().to_raw_parts();
         let erased = MessageErased {
             meta,
             shm_addr_app: ptr_app.addr().get(),
@@ -260,6 +504,7 @@ impl ClientStub {
         };
 
         let req = dp::WorkRequest::Call(erased);
+        let inline_len = inline::payload_len(Layout::new::<T>());
 
         #[cfg(feature = "timing")]
         TIMER.with_borrow_mut(|timer| {
@@ -269,11 +514,27 @@ impl ClientStub {
             );
         });
 
+        #[cfg(feature = "breakdown")]
+        crate::breakdown::record_enqueue(RpcId::new(meta.conn_id, meta.call_id));
+
         // notify the backend
         MRPC_CTX.with(|ctx| {
+            if let Some(len) = inline_len {
+                // SAFETY: the message is held until it is acknowledged, the struct is read as
+                // bytes for the backend to copy
+                let payload = unsafe { slice::from_raw_parts(ptr_app.as_ptr().cast::<u8>(), len) };
+                let wr = dp::WorkRequest::Inline(meta, len as u32);
+                while !ctx
+                    .service()
+                    .enqueue_wr_with_payload(wr, payload, dp::WorkRequest::Pad)?
+                {
+                    std::hint::spin_loop();
+                }
+                return Ok(());
+            }
             let mut sent = false;
             while !sent {
-                ctx.service.enqueue_wr_with(|ptr, _count| unsafe {
+                ctx.service().enqueue_wr_with(|ptr, _count| unsafe {
                     ptr.cast::<dp::WorkRequest>().write(req);
                     sent = true;
                     1
@@ -283,26 +544,40 @@ impl ClientStub {
         })
     }
 
-    fn master_conn(&self) -> &Connection {
-        if self.vconn.handle().is_master() {
-            &self.vconn
+    /// Returns the read-only heap of the connection `conn_id`.
+    fn read_heap(&self, conn_id: Handle) -> Result<Arc<ReadHeap>, Error> {
+        self.conns
+            .borrow()
+            .get(&conn_id)
+            .ok_or(Error::ConnectionClosed)?
+            .map_alive(|alive| Arc::clone(&alive.read_heap))
+    }
+
+    /// Gives the receive buffer of a message nobody takes back to the backend.
+    fn reclaim(&self, msg: &MessageErased) {
+        if let Ok(read_heap) = self.read_heap(msg.meta.conn_id) {
+            // the buffer is given back once the RRef is dropped
+            drop(RRef::<()>::new(msg, read_heap));
+        }
+    }
+
+    fn master_conn(&self) -> Ref<'_, Connection> {
+        let vconn = self.vconn.borrow();
+        if vconn.handle().is_master() {
+            vconn
         } else {
-            self.conns.get(&self.vconn.handle()).unwrap()
+            let handle = vconn.handle();
+            Ref::map(self.conns.borrow(), |conns| conns.get(&handle).unwrap())
         }
     }
-    /// Creates an RPC client by connecting to a given socket address.
-    // TODO(cjr): Change this to async too
-    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
-        let connect_addr = addr
-            .to_socket_addrs()?
-            .next()
-            .ok_or(Error::NoAddrResolved)?;
-        let req = Command::Connect(connect_addr);
 
+    /// Connects to `addr` and maps the read-only heap of the connection.
+    fn establish(addr: SocketAddr) -> Result<Connection, Error> {
+        let req = Command::Connect(addr);
         MRPC_CTX.with(|ctx| {
-            ctx.service.send_cmd(req)?;
-            let fds = ctx.service.recv_fd()?;
-            rx_recv_impl!(ctx.service, CompletionKind::Connect, conn_resp, {
+            ctx.service().send_cmd(req)?;
+            let fds = ctx.service().recv_fd()?;
+            rx_recv_impl!(ctx.service(), CompletionKind::Connect, conn_resp, {
                 // use memfd::Memfd;
                 assert_eq!(fds.len(), conn_resp.read_regions.len());
 
@@ -317,30 +592,53 @@ impl ClientStub {
 
                 // return the mapped addr back
                 let req = Command::NewMappedAddrs(conn_handle, vaddrs);
-                ctx.service.send_cmd(req)?;
+                ctx.service().send_cmd(req)?;
                 // wait for the reply!
-                rx_recv_impl!(ctx.service, CompletionKind::NewMappedAddrs)?;
-
-                // register the stub with the reactor
-                let conn = Connection::new(conn_handle, read_heap);
-                let (stub_id, receiver) = LOCAL_REACTOR.with_borrow_mut(|r| r.register_stub());
-                LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(stub_id, &conn));
-
-                let mut conns = HashMap::new();
-                conns.insert(conn.handle().clone(), conn);
-                Ok(Self {
-                    vconn: Connection::vconn(conn_handle),
-                    conns: conns,
-                    // inner: RefCell::new(Inner {
-                    inner: spin::Mutex::new(Inner {
-                        receiver,
-                        reply_cache: ReplyCache::new(),
-                    }),
-                })
+                rx_recv_impl!(ctx.service(), CompletionKind::NewMappedAddrs)?;
+
+                Ok(Connection::new(
+                    conn_handle,
+                    read_heap,
+                    conn_resp.peer_addr,
+                    None,
+                ))
             })
         })
     }
 
+    /// Creates an RPC client by connecting to a given socket address.
+    // TODO(cjr): Change this to async too
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        let connect_addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or(Error::NoAddrResolved)?;
+        shmalloc::warm_up(&crate::salloc_setting().warmup)?;
+        let generation = MRPC_CTX.with(|ctx| ctx.ensure_backend())?;
+        let conn = Self::establish(connect_addr)?;
+
+        // register the stub with the reactor
+        let (stub_id, receiver) = LOCAL_REACTOR.with_borrow_mut(|r| r.register_stub());
+        LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(stub_id, &conn));
+
+        let conn_handle = conn.handle();
+        let mut conns = HashMap::new();
+        conns.insert(conn_handle, conn);
+        Ok(Self {
+            vconn: RefCell::new(Connection::vconn(conn_handle)),
+            conns: RefCell::new(conns),
+            // inner: RefCell::new(Inner {
+            inner: spin::Mutex::new(Inner {
+                receiver,
+                reply_cache: ReplyCache::new(),
+                subscriptions: HashMap::new(),
+            }),
+            addr: Some(connect_addr),
+            stub_id,
+            generation: Cell::new(generation),
+        })
+    }
+
     /// Creates an RPC client by connecting to multiple socket address.
     pub fn multi_connect<A: ToSocketAddrs>(addrs: Vec<A>) -> Result<Self, Error> {
         let connect_addrs: Vec<SocketAddr> = addrs
@@ -350,15 +648,17 @@ impl ClientStub {
             .into_iter()
             .flatten()
             .collect();
+        shmalloc::warm_up(&crate::salloc_setting().warmup)?;
+        let generation = MRPC_CTX.with(|ctx| ctx.ensure_backend())?;
         let mut conns = Vec::new();
         let mut handles = Vec::new();
         let mut vconn = None;
         for addr in connect_addrs {
             let cmd = Command::Connect(addr);
             MRPC_CTX.with(|ctx| {
-                ctx.service.send_cmd(cmd).unwrap();
-                let fds = ctx.service.recv_fd().unwrap();
-                match ctx.service.recv_comp().unwrap().0 {
+                ctx.service().send_cmd(cmd).unwrap();
+                let fds = ctx.service().recv_fd().unwrap();
+                match ctx.service().recv_comp().unwrap().0 {
                     Ok(CompletionKind::Connect(conn_resp)) => {
                         assert_eq!(fds.len(), conn_resp.read_regions.len());
 
@@ -373,16 +673,17 @@ impl ClientStub {
 
                         // return the mapped addr back
                         let req = Command::NewMappedAddrs(conn_handle, vaddrs);
-                        ctx.service.send_cmd(req).unwrap();
+                        ctx.service().send_cmd(req).unwrap();
                         // wait for the reply!
-                        match ctx.service.recv_comp().unwrap().0 {
+                        match ctx.service().recv_comp().unwrap().0 {
                             Ok(CompletionKind::NewMappedAddrs) => {}
                             Err(e) => panic!("{:?}", e),
                             _ => panic!("unmatched branch"),
                         }
 
                         // register the stub with the reactor
-                        let conn = Connection::new(conn_handle, read_heap);
+                        let conn =
+                            Connection::new(conn_handle, read_heap, conn_resp.peer_addr, None);
                         handles.push(conn.handle().clone());
                         conns.push(conn);
                     }
@@ -395,8 +696,8 @@ impl ClientStub {
         }
         MRPC_CTX.with(|ctx| {
             let cmd = Command::MultiConnect(handles);
-            ctx.service.send_cmd(cmd).unwrap();
-            match ctx.service.recv_comp().unwrap().0 {
+            ctx.service().send_cmd(cmd).unwrap();
+            match ctx.service().recv_comp().unwrap().0 {
                 Ok(CompletionKind::MultiConnect(handle)) => {
                     //assert!(handle == Handle::MASTER);
                     _ = vconn.insert(Connection::vconn(handle));
@@ -416,12 +717,16 @@ impl ClientStub {
             conn_map.insert(conn.handle().clone(), conn);
         }
         Ok(Self {
-            vconn: vconn.unwrap(),
-            conns: conn_map,
+            vconn: RefCell::new(vconn.unwrap()),
+            conns: RefCell::new(conn_map),
             inner: spin::Mutex::new(Inner {
                 receiver,
                 reply_cache: ReplyCache::new(),
+                subscriptions: HashMap::new(),
             }),
+            addr: None,
+            stub_id,
+            generation: Cell::new(generation),
         })
     }
 }
@@ -1,8 +1,10 @@
 use std::cell::RefCell;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use phoenix_api::Handle;
 
+use super::context::{ConnectionContext, Outbox};
 use super::pending::PendingWRef;
 use crate::{Error, ReadHeap};
 
@@ -24,6 +26,7 @@ pub(crate) struct AliveConnection {
     pub(crate) handle: Handle,
     pub(crate) read_heap: Arc<ReadHeap>,
     pub(crate) pending: PendingWRef,
+    pub(crate) context: Arc<ConnectionContext>,
 }
 
 #[derive(Debug)]
@@ -48,9 +51,16 @@ impl std::hash::Hash for Connection {
 
 impl Connection {
     #[inline]
-    pub(crate) fn new(handle: Handle, read_heap: ReadHeap) -> Self {
+    pub(crate) fn new(
+        handle: Handle,
+        read_heap: ReadHeap,
+        peer_addr: Option<SocketAddr>,
+        outbox: Option<Outbox>,
+    ) -> Self {
         Connection {
-            inner: RefCell::new(Inner::Alive(AliveConnection::new(handle, read_heap))),
+            inner: RefCell::new(Inner::Alive(AliveConnection::new(
+                handle, read_heap, peer_addr, outbox,
+            ))),
         }
     }
 
@@ -59,6 +69,8 @@ impl Connection {
             inner: RefCell::new(Inner::Alive(AliveConnection::new(
                 handle,
                 ReadHeap::default(),
+                None,
+                None,
             ))),
         }
     }
@@ -92,15 +104,22 @@ impl Connection {
 
 impl AliveConnection {
     #[inline]
-    pub(crate) fn new(handle: Handle, read_heap: ReadHeap) -> Self {
+    pub(crate) fn new(
+        handle: Handle,
+        read_heap: ReadHeap,
+        peer_addr: Option<SocketAddr>,
+        outbox: Option<Outbox>,
+    ) -> Self {
         Self {
             handle,
             read_heap: Arc::new(read_heap),
             pending: PendingWRef::new(),
+            context: Arc::new(ConnectionContext::new(handle, peer_addr, outbox)),
         }
     }
 
     pub(crate) fn close(&mut self) -> DeadConnection {
+        self.context.close();
         DeadConnection {
             handle: self.handle,
         }
@@ -0,0 +1,196 @@
+//! Per-connection state shared by the handlers of a server.
+use std::any::{Any, TypeId};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use fnv::FnvHashMap as HashMap;
+use spin::Mutex;
+
+use phoenix_api::rpc::{CallId, MessageErased, MessageMeta, RpcMsgType, StatusCode};
+use phoenix_api::Handle;
+
+use super::RpcData;
+use crate::{Error, WRef, WRefOpaque};
+
+/// The call ids of notifications have the highest bit set, so that they never collide with the
+/// call ids of the requests from the client.
+const NOTIFICATION_CALL_ID_BIT: u64 = 1 << 63;
+
+/// The notifications waiting to be posted by a server, shared by its connections.
+pub(crate) type Outbox = Arc<Mutex<Vec<(WRefOpaque, MessageErased)>>>;
+
+/// The context of a connection accepted by a server.
+///
+/// A handler reaches the context of the connection a request arrives on by
+/// [`RRef::context`][crate::RRef::context]. The context lives as long as the connection, so a
+/// server can keep per-peer state in it, e.g., the identity of the peer once authenticated, or
+/// push [notifications][ConnectionContext::notify] to the peer.
+pub struct ConnectionContext {
+    conn_id: Handle,
+    peer_addr: Option<SocketAddr>,
+    identity: Mutex<Option<String>>,
+    requests: AtomicU64,
+    replies: AtomicU64,
+    notifications: AtomicU64,
+    values: Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+    outbox: Option<Outbox>,
+    closed: AtomicBool,
+}
+
+/// The counters of a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// The number of requests received.
+    pub requests: u64,
+    /// The number of replies sent.
+    pub replies: u64,
+    /// The number of notifications sent.
+    pub notifications: u64,
+}
+
+impl fmt::Debug for ConnectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionContext")
+            .field("conn_id", &self.conn_id)
+            .field("peer_addr", &self.peer_addr)
+            .field("identity", &*self.identity.lock())
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ConnectionContext {
+    pub(crate) fn new(
+        conn_id: Handle,
+        peer_addr: Option<SocketAddr>,
+        outbox: Option<Outbox>,
+    ) -> Self {
+        ConnectionContext {
+            conn_id,
+            peer_addr,
+            identity: Mutex::new(None),
+            requests: AtomicU64::new(0),
+            replies: AtomicU64::new(0),
+            notifications: AtomicU64::new(0),
+            values: Mutex::new(HashMap::default()),
+            outbox,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the handle of the connection.
+    #[inline]
+    pub fn conn_id(&self) -> Handle {
+        self.conn_id
+    }
+
+    /// Returns the address of the peer, if the transport knows it.
+    #[inline]
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Returns the identity of the peer, if it has been set.
+    pub fn identity(&self) -> Option<String> {
+        self.identity.lock().clone()
+    }
+
+    /// Sets the identity of the peer, once the application has authenticated it.
+    pub fn set_identity<S: Into<String>>(&self, identity: S) {
+        *self.identity.lock() = Some(identity.into());
+    }
+
+    /// Returns the counters of the connection.
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            replies: self.replies.load(Ordering::Relaxed),
+            notifications: self.notifications.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Pushes `msg` to the peer outside of any call, e.g., to invalidate a cache entry of the
+    /// client.
+    ///
+    /// `func_id` names a method of the service `service_id` whose reply type is `T`, the client
+    /// receives the notification from the stream returned by
+    /// [`ClientStub::subscribe`][crate::stub::ClientStub::subscribe] for `func_id`. The
+    /// notification is posted by the server loop, a notification the client does not subscribe to
+    /// is dropped by the client.
+    ///
+    /// Returns [`Error::ConnectionClosed`] if the connection has been closed.
+    pub fn notify<T: RpcData>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        msg: WRef<T>,
+    ) -> Result<(), Error> {
+        let outbox = self.outbox.as_ref().ok_or(Error::ConnectionClosed)?;
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::ConnectionClosed);
+        }
+        let seq = self.notifications.fetch_add(1, Ordering::Relaxed);
+        let meta = MessageMeta {
+            conn_id: self.conn_id,
+            service_id,
+            func_id,
+            call_id: CallId(NOTIFICATION_CALL_ID_BIT | seq),
+            token: msg.token().0 as u64,
+            msg_type: RpcMsgType::Notification,
+            status_code: StatusCode::Success,
+        };
+
+        let msg_opaque = WRef::clone(&msg).into_opaque();
+        let (ptr_app, ptr_backend) = msg.into_shmptr().to_raw_parts();
+        let erased = MessageErased {
+            meta,
+            shm_addr_app: ptr_app.addr().get(),
+            shm_addr_backend: ptr_backend.addr().get(),
+        };
+        outbox.lock().push((msg_opaque, erased));
+        Ok(())
+    }
+
+    /// Stores a value of type `T`, and returns the value of that type stored before.
+    pub fn insert<T: Send + Sync + 'static>(&self, val: T) -> Option<T> {
+        self.values
+            .lock()
+            .insert(TypeId::of::<T>(), Box::new(val))
+            .and_then(|prev| prev.downcast().ok().map(|prev| *prev))
+    }
+
+    /// Returns a copy of the value of type `T`, if any.
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.values
+            .lock()
+            .get(&TypeId::of::<T>())
+            .and_then(|val| val.downcast_ref::<T>())
+            .cloned()
+    }
+
+    /// Removes the value of type `T`, and returns it.
+    pub fn remove<T: Send + Sync + 'static>(&self) -> Option<T> {
+        self.values
+            .lock()
+            .remove(&TypeId::of::<T>())
+            .and_then(|val| val.downcast().ok().map(|val| *val))
+    }
+
+    #[inline]
+    pub(crate) fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn record_reply(&self) {
+        self.replies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fails the notifications sent afterwards.
+    #[inline]
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+}
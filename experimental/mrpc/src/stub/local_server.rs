@@ -2,6 +2,7 @@
 use std::cell::RefCell;
 use std::future::Future;
 use std::net::ToSocketAddrs;
+use std::slice;
 use std::sync::Arc;
 use std::task::Poll;
 
@@ -13,17 +14,18 @@ use futures::task::LocalFutureObj;
 use futures::FutureExt;
 
 use ipc::channel::{Receiver, TryRecvError};
-use phoenix_api::rpc::{MessageErased, RpcId, RpcMsgType, TransportStatus};
+use phoenix_api::rpc::{MessageErased, RpcId, RpcMsgType, StatusCode, TransportStatus};
 use phoenix_api::{AsHandle, Handle};
 use phoenix_api_mrpc::cmd::{Command, CompletionKind, ConnectResponse};
 use phoenix_api_mrpc::dp;
 use phoenix_syscalls::_rx_recv_impl as rx_recv_impl;
 
 use super::conn::Connection;
+use super::context::Outbox;
 use super::service::{NamedService, Service};
-use super::LOCAL_REACTOR;
+use super::{MessageSizeLimit, LOCAL_REACTOR};
 use crate::wref::WRefOpaque;
-use crate::{Error, ReadHeap, MRPC_CTX};
+use crate::{inline, Error, ReadHeap, MRPC_CTX};
 
 #[cfg(feature = "timing")]
 use crate::timing::{SampleKind, Timer};
@@ -38,6 +40,10 @@ pub struct LocalServer {
     stub_id: usize,
     listener_handle: Handle,
     routes: HashMap<u32, Box<dyn Service>>,
+    // message size limits to register with the backend before serving
+    size_limits: Vec<(u32, MessageSizeLimit)>,
+    // notifications pushed by the handlers, posted together with the replies
+    outbox: Outbox,
     inner: RefCell<Inner>,
 }
 
@@ -69,7 +75,9 @@ impl Inner {
     }
 
     fn close_connection(&mut self, conn_id: Handle) {
-        self.connections.remove(&conn_id);
+        if let Some(conn) = self.connections.remove(&conn_id) {
+            let _ = conn.map_alive(|alive| alive.context.close());
+        }
     }
 }
 
@@ -83,15 +91,18 @@ impl LocalServer {
             .next()
             .ok_or(Error::NoAddrResolved)?;
         let req = Command::Bind(bind_addr);
+        shmalloc::warm_up(&crate::salloc_setting().warmup)?;
         MRPC_CTX.with(|ctx| {
-            ctx.service.send_cmd(req)?;
-            rx_recv_impl!(ctx.service, CompletionKind::Bind, listener_handle, {
+            ctx.service().send_cmd(req)?;
+            rx_recv_impl!(ctx.service(), CompletionKind::Bind, listener_handle, {
                 let (stub_id, receiver) = LOCAL_REACTOR.with_borrow_mut(|r| r.register_stub());
 
                 Ok(Self {
                     stub_id,
                     listener_handle,
                     routes: HashMap::default(),
+                    size_limits: Vec::new(),
+                    outbox: Outbox::default(),
                     inner: RefCell::new(Inner {
                         connections: HashMap::default(),
                         receiver,
@@ -107,6 +118,10 @@ impl LocalServer {
     ///
     /// Panics on duplicate [`NamedService::SERVICE_ID`].
     pub fn add_service<S: Service + NamedService + 'static>(&mut self, svc: S) -> &mut Self {
+        let limit = svc.message_size_limit();
+        if !limit.is_unlimited() {
+            self.size_limits.push((S::SERVICE_ID, limit));
+        }
         if self.routes.insert(S::SERVICE_ID, Box::new(svc)).is_some() {
             panic!("Hash collisions in func_id: {}", S::SERVICE_ID);
         }
@@ -118,6 +133,10 @@ impl LocalServer {
     /// Returns an [`Future`] that should be run by an `Executor`. The [`Future`] resolves to a
     /// `Result` indicating any error during serving.
     pub async fn serve(&mut self) -> Result<(), Error> {
+        for (service_id, limit) in self.size_limits.drain(..) {
+            super::set_message_size_limit(Some(service_id), limit)?;
+        }
+
         // running tasks
         let mut running = FuturesUnordered::new();
         running.push(LocalFutureObj::new(Box::new(std::future::pending())));
@@ -140,6 +159,7 @@ impl LocalServer {
                     }
                     default => {
                         // TODO(cjr): Having the default branch is not cpu efficient
+                        self.collect_notifications(&mut reply_buffer);
                         if !reply_buffer.is_empty() {
                             self.post_replies(&mut reply_buffer)?;
                         }
@@ -194,6 +214,7 @@ impl LocalServer {
                     }
                     default => {
                         // TODO(cjr): Having the default branch is not cpu efficient
+                        self.collect_notifications(&mut reply_buffer);
                         if !reply_buffer.is_empty() {
                             self.post_replies(&mut reply_buffer)?;
                         }
@@ -219,7 +240,7 @@ impl LocalServer {
         conn_resp: ConnectResponse,
         ctx: &crate::Context,
     ) -> Result<(), Error> {
-        match ctx.service.recv_fd() {
+        match ctx.service().recv_fd() {
             Ok(fds) => {
                 let conn_handle = conn_resp.conn_handle;
                 assert_eq!(fds.len(), conn_resp.read_regions.len());
@@ -232,7 +253,12 @@ impl LocalServer {
                     .collect();
 
                 // register connection to the reactor
-                let conn = Connection::new(conn_handle, read_heap);
+                let conn = Connection::new(
+                    conn_handle,
+                    read_heap,
+                    conn_resp.peer_addr,
+                    Some(Arc::clone(&self.outbox)),
+                );
                 LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(self.stub_id, &conn));
 
                 // update connection set
@@ -246,7 +272,7 @@ impl LocalServer {
 
                 // update backend addr mapping
                 let req = Command::NewMappedAddrs(conn_handle, vaddrs);
-                ctx.service.send_cmd(req)?;
+                ctx.service().send_cmd(req)?;
                 // NO NEED TO WAIT
                 Ok(())
             }
@@ -256,7 +282,7 @@ impl LocalServer {
 
     fn check_cm_event(&self) -> Result<(), Error> {
         MRPC_CTX.with(|ctx| {
-            match ctx.service.try_recv_comp().map(|comp| comp.0) {
+            match ctx.service().try_recv_comp().map(|comp| comp.0) {
                 Err(ipc::Error::TryRecv(ipc::TryRecvError::Empty)) => {}
                 Err(e) => return Err(e.into()),
                 Ok(compkind) => {
@@ -276,6 +302,30 @@ impl LocalServer {
         })
     }
 
+    /// Moves the notifications pushed by the handlers to `msg_buffer`. The notifications to the
+    /// connections closed since are dropped.
+    fn collect_notifications(&self, msg_buffer: &mut Vec<(WRefOpaque, MessageErased)>) {
+        let mut outbox = self.outbox.lock();
+        if outbox.is_empty() {
+            return;
+        }
+        let inner = self.inner.borrow();
+        for m in outbox.drain(..) {
+            let alive = inner
+                .get_connection(m.1.meta.conn_id)
+                .and_then(|conn| conn.map_alive(|_| ()))
+                .is_ok();
+            if alive {
+                msg_buffer.push(m);
+            } else {
+                log::debug!(
+                    "Dropping the notification to closed connection {:?}",
+                    m.1.meta.conn_id
+                );
+            }
+        }
+    }
+
     fn post_replies(&self, msg_buffer: &mut Vec<(WRefOpaque, MessageErased)>) -> Result<(), Error> {
         // track the msg as pending
 
@@ -284,6 +334,9 @@ impl LocalServer {
             let inner = self.inner.borrow();
             let conn = inner.get_connection(conn_id)?;
             conn.map_alive(|alive| {
+                if m.1.meta.msg_type == RpcMsgType::Response {
+                    alive.context.record_reply();
+                }
                 alive
                     .pending
                     .insert_opaque(RpcId::new(conn.handle(), m.1.meta.call_id), m.0.clone())
@@ -294,11 +347,32 @@ impl LocalServer {
         let mut sent = 0;
         MRPC_CTX.with(|ctx| {
             while sent < num {
-                ctx.service.enqueue_wr_with(|ptr, count| unsafe {
-                    let to_send = (num - sent).min(count);
-                    for i in 0..to_send {
-                        let wr = dp::WorkRequest::Reply(msg_buffer[sent + i].1);
-                        ptr.add(i).cast::<dp::WorkRequest>().write(wr);
+                let erased = &msg_buffer[sent].1;
+                if let Some(len) = Self::inline_len(&msg_buffer[sent]) {
+                    // SAFETY: the message is held until it is acknowledged, the struct is read
+                    // as bytes for the backend to copy
+                    let payload =
+                        unsafe { slice::from_raw_parts(erased.shm_addr_app as *const u8, len) };
+                    let wr = dp::WorkRequest::Inline(erased.meta, len as u32);
+                    if ctx
+                        .service()
+                        .enqueue_wr_with_payload(wr, payload, dp::WorkRequest::Pad)?
+                    {
+                        sent += 1;
+                    }
+                    continue;
+                }
+                ctx.service().enqueue_wr_with(|ptr, count| unsafe {
+                    // the replies up to the next inline one
+                    let mut to_send = 0;
+                    while to_send < (num - sent).min(count) {
+                        let m = &msg_buffer[sent + to_send];
+                        if to_send > 0 && Self::inline_len(m).is_some() {
+                            break;
+                        }
+                        let wr = dp::WorkRequest::Reply(m.1);
+                        ptr.add(to_send).cast::<dp::WorkRequest>().write(wr);
+                        to_send += 1;
                     }
                     sent += to_send;
                     to_send
@@ -311,6 +385,14 @@ impl LocalServer {
         Ok(())
     }
 
+    /// The length of the struct of a reply or a notification to copy after its work request, see
+    /// [`inline`].
+    fn inline_len((opaque, erased): &(WRefOpaque, MessageErased)) -> Option<usize> {
+        // an error reply is sent without its message
+        inline::payload_len(opaque.layout())
+            .filter(|_| erased.meta.status_code == StatusCode::Success)
+    }
+
     fn dispatch_one_request<'s>(
         &'s self,
         comp: &dp::Completion,
@@ -329,9 +411,11 @@ impl LocalServer {
                                 let conn = inner.get_connection(request.meta.conn_id)?;
                                 // the connection has disappeared, do nothing
 
-                                let read_heap =
-                                    conn.map_alive(|alive| Arc::clone(&alive.read_heap))?;
-                                let task = LocalFutureObj::new(s.call(request, read_heap));
+                                let (read_heap, context) = conn.map_alive(|alive| {
+                                    alive.context.record_request();
+                                    (Arc::clone(&alive.read_heap), Arc::clone(&alive.context))
+                                })?;
+                                let task = LocalFutureObj::new(s.call(request, read_heap, context));
                                 running.push(task);
                             }
                             None => {
@@ -339,8 +423,8 @@ impl LocalServer {
                             }
                         }
                     }
-                    RpcMsgType::Response => {
-                        // client receives responses, update the ReplyCache
+                    RpcMsgType::Response | RpcMsgType::Notification => {
+                        // client receives responses and notifications
                         panic!("impossible, something is wrong")
                     }
                 }
@@ -370,6 +454,12 @@ impl LocalServer {
                 );
                 inner.close_connection(conn_id);
             }
+            dp::Completion::Stamps(..) => {
+                // taken by the reactor
+            }
+            dp::Completion::IncomingInline(..) | dp::Completion::Pad => {
+                // turned into an Incoming or skipped by the reactor
+            }
         }
 
         Ok(())
@@ -5,13 +5,20 @@ use crate::{Error, MRPC_CTX};
 
 // Re-exports
 pub use phoenix_api::rpc::{MessageErased, MessageMeta, RpcMsgType};
+pub use phoenix_api_mrpc::cmd::{MessageSizeLimit, TransportStats};
 pub use phoenix_api_mrpc::control_plane::TransportType;
 
 mod service;
-pub use service::{service_post_handler, service_pre_handler, NamedService, Service};
+pub use service::{
+    service_error_handler, service_post_handler, service_pre_handler, MethodHandler, NamedService,
+    Service,
+};
+
+mod context;
+pub use context::{ConnectionContext, ConnectionStats};
 
 mod client;
-pub use client::{ClientStub, ReqFuture};
+pub use client::{ClientStub, Notifications, ReqFuture};
 
 mod local_server;
 pub mod server;
@@ -20,6 +27,7 @@ pub use local_server::LocalServer;
 pub(crate) mod conn;
 pub(crate) mod pending;
 pub(crate) mod reply_cache;
+pub(crate) mod waker;
 
 // We can make RpcData a private trait, and only mark it for compiler generated types.
 // This seems impossible.
@@ -38,3 +46,22 @@ thread_local! {
 pub fn update_protos(protos: &[&str]) -> Result<(), Error> {
     MRPC_CTX.with(|ctx| ctx.update_protos(protos))
 }
+
+/// Limits the size of the marshalled messages of a service, or of all services of this process
+/// if `service_id` is `None`.
+///
+/// A request over the limit fails with [`Code::ResourceExhausted`] without being sent. So does a
+/// call whose reply is over the limit of either side.
+///
+/// [`Code::ResourceExhausted`]: crate::Code::ResourceExhausted
+pub fn set_message_size_limit(
+    service_id: Option<u32>,
+    limit: MessageSizeLimit,
+) -> Result<(), Error> {
+    MRPC_CTX.with(|ctx| ctx.set_message_size_limit(service_id, limit))
+}
+
+#[doc(hidden)]
+pub fn update_descriptors(descriptors: &[&'static [u8]]) -> Result<(), Error> {
+    MRPC_CTX.with(|ctx| ctx.update_descriptors(descriptors))
+}
@@ -1,16 +1,25 @@
 //! Local I/O reactor fetch completions from the backend mRPC service and
 //! dispatch the completions to corresponding completion queue.
-use std::task::{Context, Poll};
+//!
+//! Each application thread has a completion queue of its own, the one of its backend mRPC
+//! engine, and a reactor of its own. Within a thread, a completion is steered to the task
+//! waiting on the call it completes, by the stub and the call id it carries, and only that task
+//! is woken, see [`WakerTable`]. A single waiting task at a time, the driver, keeps polling the
+//! completion queue for the others.
+use std::collections::VecDeque;
+use std::task::{Context, Poll, Waker};
 
 use fnv::FnvHashMap as HashMap;
 use slab::Slab;
 
 use ipc::channel::{create_channel, ChannelFlavor, Receiver, Sender};
+use phoenix_api::rpc::{CallId, RpcMsgType, TransportStatus};
 use phoenix_api::Handle;
 use phoenix_api_mrpc::dp;
 
 use super::conn::Connection;
-use crate::{Error, MRPC_CTX};
+use super::waker::WakerTable;
+use crate::{inline, Error, MRPC_CTX};
 
 /// Provides functions to fetch or wait on the work completions in the shared memory queue
 /// generated by the corresponding backend mRPC engine.
@@ -18,8 +27,17 @@ use crate::{Error, MRPC_CTX};
 pub struct Reactor {
     senders: Slab<Sender<dp::Completion>>,
     buffer: Vec<dp::Completion>,
+    // the payloads of the inline completions in buffer, in their order
+    payload: Vec<u8>,
     // conn_id -> stub_id
     conn_to_stub: HashMap<Handle, usize>,
+    // stub_id -> the tasks waiting for the calls of the stub to complete
+    waiters: Vec<WakerTable>,
+    // the waiting tasks in the order they started to wait, to hand the driver over to, which
+    // may include the calls completed since
+    parked: VecDeque<(usize, CallId)>,
+    // the waiting task polling the completion queue
+    driver: Option<(usize, CallId)>,
 }
 
 // After all, someone is going to do the mapping from conn_id to stub_id
@@ -36,7 +54,11 @@ impl Reactor {
         Reactor {
             senders: Slab::new(),
             buffer: Vec::with_capacity(32),
+            payload: Vec::with_capacity(dp::MAX_INLINE_PAYLOAD),
             conn_to_stub: HashMap::default(),
+            waiters: Vec::new(),
+            parked: VecDeque::new(),
+            driver: None,
         }
     }
 
@@ -44,6 +66,9 @@ impl Reactor {
     pub(crate) fn register_stub(&mut self) -> (usize, Receiver<dp::Completion>) {
         let (sender, receiver) = create_channel(ChannelFlavor::Sequential);
         let stub_id = self.senders.insert(sender);
+        if self.waiters.len() <= stub_id {
+            self.waiters.resize_with(stub_id + 1, WakerTable::new);
+        }
         (stub_id, receiver)
     }
 
@@ -57,24 +82,34 @@ impl Reactor {
     /// Attempt to resolve to the number of ready work completions.
     pub fn poll(&mut self, _cx: &mut Context<'_>) -> Poll<Result<usize, Error>> {
         MRPC_CTX.with(|ctx| {
-            // let has_work = futures::ready!(ctx.service.poll_wc_readable(cx))?;
+            // let has_work = futures::ready!(ctx.service().poll_wc_readable(cx))?;
             // if !has_work {
             //     return Poll::Pending;
             // }
 
-            unsafe { self.buffer.set_len(0) };
+            self.buffer.clear();
+            self.payload.clear();
 
             // read completions into a local buffer
-            ctx.service
-                .dequeue_wc_with(|ptr, count| unsafe {
-                    for i in 0..count {
-                        let c = ptr.add(i).cast::<dp::Completion>().read();
-                        self.buffer.push(c);
-                    }
-                    count
-                })
+            ctx.service()
+                .dequeue_wcs_with_payload(
+                    &mut self.buffer,
+                    &mut self.payload,
+                    dp::Completion::payload_len,
+                )
                 .map_err(Error::Service)?;
 
+            // the messages received inside their completions are copied out, the stubs take them
+            // as the others
+            let mut offset = 0;
+            for c in &mut self.buffer {
+                if let dp::Completion::IncomingInline(meta, len) = *c {
+                    let payload = &self.payload[offset..offset + len as usize];
+                    *c = dp::Completion::Incoming(inline::receive(meta, payload));
+                    offset += len as usize;
+                }
+            }
+
             // dispatch newly arrived completions
             let ret = self.buffer.len();
 
@@ -84,6 +119,13 @@ impl Reactor {
                     dp::Completion::Incoming(msg) => msg.meta.conn_id,
                     dp::Completion::Outgoing(rpc_id, _status) => rpc_id.0,
                     dp::Completion::RecvError(conn_id, _status) => *conn_id,
+                    dp::Completion::IncomingInline(..) | dp::Completion::Pad => continue,
+                    // the stamps are kept by the reactor rather than the stub
+                    dp::Completion::Stamps(_rpc_id, _stamps) => {
+                        #[cfg(feature = "breakdown")]
+                        crate::breakdown::record_backend(*_rpc_id, *_stamps);
+                        continue;
+                    }
                 };
 
                 // find the stub and push the completion to that stub
@@ -93,9 +135,91 @@ impl Reactor {
                     .get_mut(stub_id)
                     .unwrap_or_else(|| panic!("unknown stub_id {}", stub_id));
                 sender.send(c.clone()).unwrap();
+
+                // wake the task waiting for it
+                match c {
+                    dp::Completion::Incoming(msg) if msg.meta.msg_type == RpcMsgType::Response => {
+                        self.wake((stub_id, msg.meta.call_id));
+                    }
+                    dp::Completion::Outgoing(rpc_id, TransportStatus::Error(_)) => {
+                        self.wake((stub_id, rpc_id.1));
+                    }
+                    dp::Completion::RecvError(..) => self.wake_stub(stub_id),
+                    _ => {}
+                }
             }
 
             Poll::Ready(Ok(ret))
         })
     }
+
+    /// Registers the task waiting for the call `call_id` of the stub `stub_id` to complete, to be
+    /// woken when a completion for the call arrives. Returns whether the task is to drive the
+    /// reactor, i.e., to wake itself to poll it again, as no other waiting task does.
+    pub(crate) fn park(&mut self, stub_id: usize, call_id: CallId, waker: &Waker) -> bool {
+        let key = (stub_id, call_id);
+        if self.waiters[stub_id].insert(call_id, waker) {
+            self.parked.push_back(key);
+            self.compact();
+        }
+        match self.driver {
+            Some(driver) => driver == key,
+            None => {
+                self.driver = Some(key);
+                true
+            }
+        }
+    }
+
+    /// Deregisters the task waiting for the call, once it has completed or been given up. If the
+    /// task was driving the reactor, another waiting task is woken to take over.
+    pub(crate) fn unpark(&mut self, stub_id: usize, call_id: CallId) {
+        self.waiters[stub_id].remove(call_id);
+        if self.driver == Some((stub_id, call_id)) {
+            self.resign();
+        }
+    }
+
+    fn wake(&mut self, key: (usize, CallId)) {
+        if let Some(waker) = self.waiters[key.0].remove(key.1) {
+            waker.wake();
+            if self.driver == Some(key) {
+                self.resign();
+            }
+        }
+    }
+
+    /// Wakes all tasks waiting on the calls of the stub, e.g., after its connection is gone.
+    pub(crate) fn wake_stub(&mut self, stub_id: usize) {
+        self.waiters[stub_id].drain().for_each(Waker::wake);
+        if self.driver.map_or(false, |driver| driver.0 == stub_id) {
+            self.resign();
+        }
+    }
+
+    /// Hands the polling of the completion queue over to the task waiting the longest, if any.
+    fn resign(&mut self) {
+        self.driver = None;
+        while let Some((stub_id, call_id)) = self.parked.pop_front() {
+            if let Some(waker) = self.waiters[stub_id].get(call_id) {
+                waker.wake_by_ref();
+                // still waiting, to take over later if another task takes over now
+                self.parked.push_back((stub_id, call_id));
+                return;
+            }
+        }
+    }
+
+    /// Drops the calls completed from the queue of waiting tasks once they dominate it.
+    fn compact(&mut self) {
+        if self.parked.len() <= 64 {
+            return;
+        }
+        let waiting: usize = self.waiters.iter().map(WakerTable::len).sum();
+        if self.parked.len() > 2 * waiting + 64 {
+            let waiters = &self.waiters;
+            self.parked
+                .retain(|(stub_id, call_id)| waiters[*stub_id].contains(*call_id));
+        }
+    }
 }
@@ -1,18 +1,23 @@
+use std::collections::HashMap;
+
 use phoenix_api::rpc::{CallId, MessageErased, TransportStatus};
 use thiserror::Error;
 
-use slab::Slab;
-
 #[derive(Error, Debug)]
 pub(crate) enum Error {
     #[error("CallId {0} not found")]
     NotFound(CallId),
+    #[error("CallId {0} has already been resolved")]
+    Duplicate(CallId),
 }
 
 #[derive(Debug)]
 pub(crate) struct ReplyCacheT<T> {
     // Each RPC identified by a call_id resolves to a Result<MessageErased, TransportStatus>
-    slab: Slab<Option<T>>,
+    calls: HashMap<CallId, Option<T>>,
+    // Call ids are allocated in increasing order and never reused, so that a late completion
+    // cannot be mistaken for the reply of a newer call.
+    next_call_id: u64,
 }
 
 impl<T> Default for ReplyCacheT<T> {
@@ -23,17 +28,25 @@ impl<T> Default for ReplyCacheT<T> {
 
 impl<T> ReplyCacheT<T> {
     pub(crate) fn new() -> Self {
-        ReplyCacheT { slab: Slab::new() }
+        ReplyCacheT {
+            calls: HashMap::new(),
+            next_call_id: 0,
+        }
     }
 
     #[inline]
     pub(crate) fn initiate_call(&mut self) -> CallId {
-        self.slab.insert(None).into()
+        let call_id = CallId(self.next_call_id);
+        self.next_call_id += 1;
+        self.calls.insert(call_id, None);
+        call_id
     }
 
+    /// Resolves the call. Each call is resolved at most once, the later values are rejected.
     #[inline]
     pub(crate) fn update(&mut self, call_id: CallId, val: T) -> Result<(), Error> {
-        match self.slab.get_mut(call_id.0 as usize) {
+        match self.calls.get_mut(&call_id) {
+            Some(Some(_)) => Err(Error::Duplicate(call_id)),
             Some(entry) => {
                 entry.replace(val);
                 Ok(())
@@ -42,12 +55,72 @@ impl<T> ReplyCacheT<T> {
         }
     }
 
+    /// Removes the call if it has been resolved, and returns its value.
     #[inline]
-    pub(crate) fn get(&self, call_id: CallId) -> Result<&Option<T>, Error> {
-        self.slab
-            .get(call_id.0 as usize)
-            .ok_or(Error::NotFound(call_id))
+    pub(crate) fn take(&mut self, call_id: CallId) -> Result<Option<T>, Error> {
+        match self.calls.get(&call_id) {
+            Some(Some(_)) => Ok(self.calls.remove(&call_id).flatten()),
+            Some(None) => Ok(None),
+            None => Err(Error::NotFound(call_id)),
+        }
+    }
+
+    /// Resolves all the calls that are still waiting for a reply with the value made by `f`.
+    pub(crate) fn resolve_pending<F: FnMut() -> T>(&mut self, mut f: F) {
+        for entry in self.calls.values_mut() {
+            if entry.is_none() {
+                entry.replace(f());
+            }
+        }
     }
 }
 
 pub(crate) type ReplyCache = ReplyCacheT<Result<MessageErased, TransportStatus>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_ids_are_not_reused() {
+        let mut cache = ReplyCacheT::<u32>::new();
+        let first = cache.initiate_call();
+        cache.update(first, 1).unwrap();
+        assert_eq!(cache.take(first).unwrap(), Some(1));
+
+        let second = cache.initiate_call();
+        assert!(second.0 > first.0);
+        assert!(matches!(cache.take(first), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn resolved_once() {
+        let mut cache = ReplyCacheT::<u32>::new();
+        let call_id = cache.initiate_call();
+        cache.update(call_id, 1).unwrap();
+        assert!(matches!(cache.update(call_id, 2), Err(Error::Duplicate(_))));
+        assert_eq!(cache.take(call_id).unwrap(), Some(1));
+        assert!(matches!(cache.update(call_id, 3), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn out_of_order_completions() {
+        let mut cache = ReplyCacheT::<u64>::new();
+        let calls = (0..8).map(|_| cache.initiate_call()).collect::<Vec<_>>();
+        for call_id in calls.iter().rev() {
+            cache.update(*call_id, call_id.0).unwrap();
+        }
+        for call_id in calls.iter() {
+            assert_eq!(cache.take(*call_id).unwrap(), Some(call_id.0));
+        }
+    }
+
+    #[test]
+    fn take_pending() {
+        let mut cache = ReplyCacheT::<u32>::new();
+        let call_id = cache.initiate_call();
+        assert_eq!(cache.take(call_id).unwrap(), None);
+        cache.resolve_pending(|| 7);
+        assert_eq!(cache.take(call_id).unwrap(), Some(7));
+    }
+}
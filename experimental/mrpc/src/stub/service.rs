@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
-use phoenix_api::rpc::{MessageErased, MessageMeta, RpcMsgType};
+use futures::future::BoxFuture;
 
-use super::RpcData;
-use crate::{RRef, ReadHeap, WRef, WRefOpaque};
+use phoenix_api::rpc::{MessageErased, MessageMeta, RpcMsgType, StatusCode};
+
+use super::{ConnectionContext, MessageSizeLimit, RpcData};
+use crate::{Code, RRef, ReadHeap, Status, WRef, WRefOpaque};
 
 /// A trait to provide a static reference to the service's name and ID.
 /// This is used for routing requests to service within the server.
@@ -18,15 +20,28 @@ pub trait NamedService {
     const NAME: &'static str = "";
 }
 
+/// The handler of a method registered at runtime, see the `<Service>Handlers` generated for a
+/// service.
+pub type MethodHandler<Req, Res> =
+    Box<dyn Fn(RRef<Req>) -> BoxFuture<'static, Result<WRef<Res>, Status>> + Send + Sync>;
+
 /// A trait implemented by generated code.
 #[crate::async_trait]
 pub trait Service {
     /// Resolves to a type-erased [`WRef`] and the [type-erased RPC descriptor][MessageErased] for the reply.
+    ///
+    /// `conn` is the context of the connection the request arrives on.
     async fn call(
         &self,
         req: MessageErased,
         read_heap: Arc<ReadHeap>,
+        conn: Arc<ConnectionContext>,
     ) -> (WRefOpaque, MessageErased);
+
+    /// The message size limits of the service, unlimited by default.
+    fn message_size_limit(&self) -> MessageSizeLimit {
+        MessageSizeLimit::default()
+    }
 }
 
 #[doc(hidden)]
@@ -56,3 +71,28 @@ pub fn service_post_handler<T: RpcData>(
 
     (reply_opaque, erased)
 }
+
+/// Replies to a request with the error a handler has returned. Only the code is sent back: the
+/// client receives [`Code::Unimplemented`] as is, and [`Code::Unknown`] for any other error.
+#[doc(hidden)]
+pub fn service_error_handler(
+    status: Status,
+    req_opaque: &MessageErased,
+) -> (WRefOpaque, MessageErased) {
+    let status_code = match status.code() {
+        Code::Unimplemented => StatusCode::Unimplemented,
+        _ => StatusCode::Unknown,
+    };
+    log::debug!(
+        "Replying to call {} with error: {}",
+        req_opaque.meta.call_id,
+        status
+    );
+
+    // the backend only sends the meta of the reply, but the message is kept until the reply is
+    // acknowledged as the others
+    let (reply_opaque, mut erased) =
+        service_post_handler(WRef::new(status_code as u32), req_opaque);
+    erased.meta.status_code = status_code;
+    (reply_opaque, erased)
+}
@@ -0,0 +1,246 @@
+//! The wakers of the tasks waiting for their calls to complete.
+//!
+//! The completions from the backend carry nothing but the call id to find the waiting task by,
+//! and the wakers never leave the address space of the application. A table holds the wakers of
+//! the calls of a stub in slots indexed by the call id. As the call ids of a stub are allocated
+//! in increasing order, the calls outstanding at a time fall into distinct slots, unless they
+//! span more call ids than slots, in which case the table doubles. As a call may stay outstanding
+//! for long while the later ones complete, the span is not bounded by the number of calls; past
+//! `MAX_CAPACITY` slots, the calls that collide are kept in a map instead.
+use std::task::Waker;
+
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api::rpc::CallId;
+
+const INITIAL_CAPACITY: usize = 64;
+const MAX_CAPACITY: usize = 4096;
+
+#[derive(Debug)]
+pub(crate) struct WakerTable {
+    // the length is a power of two
+    slots: Vec<Option<(CallId, Waker)>>,
+    // the number of calls in the slots
+    len: usize,
+    // the calls that collide in the slots once they cannot grow
+    overflow: HashMap<CallId, Waker>,
+}
+
+impl Default for WakerTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WakerTable {
+    pub(crate) fn new() -> Self {
+        WakerTable {
+            slots: (0..INITIAL_CAPACITY).map(|_| None).collect(),
+            len: 0,
+            overflow: HashMap::default(),
+        }
+    }
+
+    #[inline]
+    fn index(&self, call_id: CallId) -> usize {
+        call_id.0 as usize & (self.slots.len() - 1)
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len + self.overflow.len()
+    }
+
+    /// Stores the waker of the call, replacing the one stored before unless it wakes the same
+    /// task. Returns whether the call was not waited on before.
+    pub(crate) fn insert(&mut self, call_id: CallId, waker: &Waker) -> bool {
+        // a call in the overflow stays there until it completes
+        if !self.overflow.is_empty() {
+            if let Some(w) = self.overflow.get_mut(&call_id) {
+                update(w, waker);
+                return false;
+            }
+        }
+        loop {
+            let full = self.slots.len() >= MAX_CAPACITY;
+            let index = self.index(call_id);
+            match &mut self.slots[index] {
+                Some((id, w)) if *id == call_id => {
+                    update(w, waker);
+                    return false;
+                }
+                Some(_) if !full => self.grow(),
+                Some(_) => {
+                    self.overflow.insert(call_id, waker.clone());
+                    return true;
+                }
+                slot @ None => {
+                    *slot = Some((call_id, waker.clone()));
+                    self.len += 1;
+                    return true;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn contains(&self, call_id: CallId) -> bool {
+        self.get(call_id).is_some()
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, call_id: CallId) -> Option<&Waker> {
+        match &self.slots[self.index(call_id)] {
+            Some((id, waker)) if *id == call_id => Some(waker),
+            _ => self.overflow.get(&call_id),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn remove(&mut self, call_id: CallId) -> Option<Waker> {
+        let index = self.index(call_id);
+        match &self.slots[index] {
+            Some((id, _)) if *id == call_id => {
+                self.len -= 1;
+                self.slots[index].take().map(|(_, waker)| waker)
+            }
+            _ => self.overflow.remove(&call_id),
+        }
+    }
+
+    /// Takes the wakers of all the calls. The table is empty as soon as this returns, and
+    /// shrinks back to its initial capacity.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Waker> {
+        self.len = 0;
+        let slots = std::mem::replace(
+            &mut self.slots,
+            (0..INITIAL_CAPACITY).map(|_| None).collect(),
+        );
+        let overflow = std::mem::take(&mut self.overflow);
+        slots
+            .into_iter()
+            .flatten()
+            .map(|(_, waker)| waker)
+            .chain(overflow.into_values())
+    }
+
+    /// Doubles the slots. The call ids in distinct slots stay in distinct slots.
+    fn grow(&mut self) {
+        let capacity = self.slots.len() * 2;
+        let old = std::mem::replace(&mut self.slots, (0..capacity).map(|_| None).collect());
+        for (call_id, waker) in old.into_iter().flatten() {
+            let index = self.index(call_id);
+            debug_assert!(self.slots[index].is_none());
+            self.slots[index] = Some((call_id, waker));
+        }
+    }
+}
+
+#[inline]
+fn update(w: &mut Waker, waker: &Waker) {
+    if !w.will_wake(waker) {
+        *w = waker.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::task::noop_waker;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut table = WakerTable::new();
+        let waker = noop_waker();
+        assert!(table.insert(CallId(3), &waker));
+        assert!(!table.insert(CallId(3), &waker));
+        assert!(table.contains(CallId(3)));
+        assert!(!table.contains(CallId(4)));
+        assert_eq!(table.len(), 1);
+        assert!(table.remove(CallId(3)).is_some());
+        assert!(table.remove(CallId(3)).is_none());
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn colliding_calls_grow_the_table() {
+        let mut table = WakerTable::new();
+        let waker = noop_waker();
+        let calls = (0..4 * INITIAL_CAPACITY as u64)
+            .map(CallId)
+            .collect::<Vec<_>>();
+        for call_id in calls.iter() {
+            assert!(table.insert(*call_id, &waker));
+        }
+        assert_eq!(table.len(), calls.len());
+        for call_id in calls.iter() {
+            assert!(table.contains(*call_id));
+        }
+        // a later call in the slot of a completed one
+        let later = CallId(calls.len() as u64 * 2);
+        assert!(table.remove(CallId(0)).is_some());
+        assert!(table.insert(later, &waker));
+        assert!(table.contains(later));
+        assert!(!table.contains(CallId(0)));
+    }
+
+    #[test]
+    fn drain_all() {
+        let mut table = WakerTable::new();
+        let waker = noop_waker();
+        for call_id in 0..10 {
+            table.insert(CallId(call_id), &waker);
+        }
+        let wakers = table.drain();
+        // the table is empty before the wakers are consumed
+        assert_eq!(table.len(), 0);
+        assert!(!table.contains(CallId(0)));
+        assert_eq!(wakers.count(), 10);
+    }
+
+    #[test]
+    fn long_outstanding_call_bounds_the_slots() {
+        let mut table = WakerTable::new();
+        let waker = noop_waker();
+        // call 0 stays outstanding while the later calls complete one at a time
+        assert!(table.insert(CallId(0), &waker));
+        for call_id in 1..64 * MAX_CAPACITY as u64 {
+            assert!(table.insert(CallId(call_id), &waker));
+            assert!(table.contains(CallId(0)));
+            assert!(table.remove(CallId(call_id)).is_some());
+        }
+        assert_eq!(table.slots.len(), MAX_CAPACITY);
+        assert!(table.overflow.is_empty());
+        assert_eq!(table.len(), 1);
+
+        // a colliding call goes to the overflow
+        let collide = CallId(64 * MAX_CAPACITY as u64);
+        assert!(table.insert(collide, &waker));
+        assert!(!table.insert(collide, &waker));
+        assert_eq!(table.overflow.len(), 1);
+        assert_eq!(table.len(), 2);
+        assert!(table.get(collide).is_some());
+
+        // and stays there after the slot is freed
+        assert!(table.remove(CallId(0)).is_some());
+        assert!(!table.insert(collide, &waker));
+        assert!(table.slots.iter().all(Option::is_none));
+        assert!(table.remove(collide).is_some());
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn drain_overflow() {
+        let mut table = WakerTable::new();
+        let waker = noop_waker();
+        for call_id in 0..4 {
+            table.insert(CallId(call_id * MAX_CAPACITY as u64), &waker);
+        }
+        assert_eq!(table.overflow.len(), 3);
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.drain().count(), 4);
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.slots.len(), INITIAL_CAPACITY);
+    }
+}
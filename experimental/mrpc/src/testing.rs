@@ -0,0 +1,26 @@
+//! Running the generated stubs without phoenixd, in unit tests of applications.
+//!
+//! With [`build_mocks`] enabled, `mrpc-build` generates a `<service>_mock` module next to the
+//! client and the server of a service, e.g., `greeter_mock` for `Greeter`, holding
+//!
+//! - `MockGreeterClient`, which has the methods of `GreeterClient` and answers the calls with
+//!   the handlers set on it, e.g., by `on_say_hello`, to test the code that makes the calls;
+//! - `GreeterTester`, which calls a `Greeter` implementation directly with the requests given
+//!   to it, to test the service.
+//!
+//! Both allocate their messages on a heap the process creates itself, see [`use_local_heap`], so
+//! they need neither phoenixd nor RDMA hardware. The messages delivered to the application are
+//! [`RRef`]s reading them in place, see [`RRef::from_local`].
+//!
+//! [`build_mocks`]: ../../mrpc_build/struct.Builder.html#method.build_mocks
+use crate::stub::RpcData;
+use crate::{RRef, WRef};
+
+#[doc(inline)]
+pub use shmalloc::{is_local_heap, use_local_heap};
+
+/// Delivers a message as if it had been received.
+#[inline]
+pub fn into_rref<T: RpcData>(msg: WRef<T>) -> RRef<T> {
+    RRef::from_local(msg)
+}
@@ -1,4 +1,5 @@
 //! An owned, writable reference on shared heap.
+use std::alloc::Layout;
 use std::mem;
 use std::ops::Deref;
 use std::sync::Arc;
@@ -58,6 +59,8 @@ impl WRefOpaqueVTable {
 pub struct WRefOpaque {
     data: *const (),
     vtable: WRefOpaqueVTable,
+    /// The layout of the message.
+    layout: Layout,
 }
 
 impl Unpin for WRefOpaque {}
@@ -68,8 +71,12 @@ impl WRefOpaque {
     /// Creates a new [`WRefOpaque`] from the provided `data` pointer and `vtable`.
     #[inline]
     #[must_use]
-    pub(crate) const fn new(data: *const (), vtable: WRefOpaqueVTable) -> Self {
-        WRefOpaque { data, vtable }
+    pub(crate) const fn new(data: *const (), vtable: WRefOpaqueVTable, layout: Layout) -> Self {
+        WRefOpaque {
+            data,
+            vtable,
+            layout,
+        }
     }
 
     /// Get the `data` pointer used to create this `WRefOpaque`.
@@ -88,6 +95,13 @@ impl WRefOpaque {
         &self.vtable
     }
 
+    /// Get the layout of the message, of its struct on the heap.
+    #[inline]
+    #[must_use]
+    pub(crate) fn layout(&self) -> Layout {
+        self.layout
+    }
+
     /// Constructs a [`WRefOpaque`] from a [`WRef<T>`], erasing its inner generic argument.
     #[inline]
     pub(crate) fn from_wref<T: RpcData>(wref: WRef<T>) -> Self {
@@ -106,7 +120,7 @@ impl WRefOpaque {
 
         let vtable = WRefOpaqueVTable::new(clone_func, drop_func);
 
-        Self::new(data, vtable)
+        Self::new(data, vtable, Layout::new::<T>())
     }
 }
 
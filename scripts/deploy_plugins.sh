@@ -16,3 +16,10 @@ fi
 for plugin in `find "${TARGETDIR}"/release/ -maxdepth 1 -type f -name "libphoenix_*.rlib" -o -name "libphoenix_*.d"`; do
     install -v -Dm755 "${plugin}" -t "${PHOENIX_PREFIX}"/plugins/
 done
+
+# The manifests of the plugins, see ipc::control::PluginManifest
+for manifest in `find "${WORKDIR}"/../src/plugin -mindepth 2 -maxdepth 2 -type f -name "libphoenix_*.toml"`; do
+    if [[ -f "${TARGETDIR}/release/$(basename "${manifest}" .toml).rlib" ]]; then
+        install -v -Dm644 "${manifest}" -t "${PHOENIX_PREFIX}"/plugins/
+    fi
+done
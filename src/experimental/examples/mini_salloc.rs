@@ -81,7 +81,7 @@ impl SAContext {
     }
 }
 
-fn allocate_shm(len: usize) -> Result<usize, Error> {
+fn allocate_shm(len: usize) -> Result<(usize, u64), Error> {
     assert!(len > 0);
     SA_CTX.with(|ctx| {
         // TODO(cjr): use a correct align
@@ -97,16 +97,18 @@ fn allocate_shm(len: usize) -> Result<usize, Error> {
         assert!(file_len >= len);
 
         match ctx.service.recv_comp().unwrap().0 {
-            Ok(cmd::CompletionKind::AllocShm(remote_addr, _file_off)) => Ok(remote_addr),
+            Ok(cmd::CompletionKind::AllocShm(remote_addr, _file_off, generation)) => {
+                Ok((remote_addr, generation))
+            }
             Err(e) => Err(Error::Interface("AllocShm", e)),
             otherwise => panic!("Expect AllocShm, found {:?}", otherwise),
         }
     })
 }
 
-fn dealloc_shm(remote_addr: usize) {
+fn dealloc_shm(remote_addr: usize, generation: u64) {
     SA_CTX.with(|ctx| {
-        let req = cmd::Command::DeallocShm(remote_addr);
+        let req = cmd::Command::DeallocShm(remote_addr, generation);
         ctx.service.send_cmd(req).expect("fail to dealloc");
         rx_recv_impl!(ctx.service, cmd::CompletionKind::DeallocShm).expect("fail to dealloc");
     })
@@ -127,8 +129,8 @@ fn main() {
     let size = 1024 * 1024 * 64;
     loop {
         let start = std::time::Instant::now();
-        let addr = allocate_shm(size).unwrap();
-        dealloc_shm(addr);
+        let (addr, generation) = allocate_shm(size).unwrap();
+        dealloc_shm(addr, generation);
         let elapsed = start.elapsed().as_millis();
         println!("Alloc and dealloc 64MB, latency={}ms", elapsed);
     }
@@ -0,0 +1,129 @@
+//! Direct calls in place of a channel.
+//!
+//! The receiving side of a channel can be given a function that takes a message right away, see
+//! [`Receiver::set_direct`](super::Receiver::set_direct). A sender on the thread that set it then
+//! calls it instead of queueing the message, as long as the channel is empty, so that the message
+//! does not overtake the ones queued before it. The function may give the message back, which is
+//! then queued as usual. A sender on any other thread always queues.
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Takes a message in place of a channel, or gives it back to be queued.
+pub type DirectCall<T> = Box<dyn FnMut(T) -> Result<(), T>>;
+
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Identifies the current thread, never 0.
+    static THREAD: u64 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline]
+fn current_thread() -> u64 {
+    THREAD.with(|t| *t)
+}
+
+/// The direct call of a channel, shared by its sides.
+pub(crate) struct Direct<T> {
+    /// The thread the call is made on, 0 if there is none.
+    owner: AtomicU64,
+    /// Only touched by the `owner` thread, or by any thread when there is no owner.
+    call: UnsafeCell<Option<DirectCall<T>>>,
+}
+
+// The call is only used on the thread that set it, see `owner`.
+unsafe impl<T: Send> Send for Direct<T> {}
+unsafe impl<T: Send> Sync for Direct<T> {}
+
+impl<T> fmt::Debug for Direct<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Direct")
+            .field("owner", &self.owner.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl<T> Direct<T> {
+    pub(crate) fn new() -> Self {
+        Direct {
+            owner: AtomicU64::new(0),
+            call: UnsafeCell::new(None),
+        }
+    }
+
+    /// Sets `call` for the current thread. Returns false if another thread has one set.
+    pub(crate) fn set(&self, call: DirectCall<T>) -> bool {
+        let thread = current_thread();
+        let owner = self.owner.load(Ordering::Acquire);
+        if owner != 0 && owner != thread {
+            return false;
+        }
+        // SAFETY: no other thread touches the call without being the owner
+        unsafe { *self.call.get() = Some(call) };
+        self.owner.store(thread, Ordering::Release);
+        true
+    }
+
+    /// Drops the call. The thread that set it must not be calling it, e.g., when it gives the
+    /// receiver away.
+    pub(crate) fn clear(&self) {
+        if self.owner.swap(0, Ordering::AcqRel) != 0 {
+            // SAFETY: the owner is not calling it, and no other thread does
+            drop(unsafe { (*self.call.get()).take() });
+        }
+    }
+
+    /// Calls the function set for the current thread with `t` if `is_empty` holds, gives `t`
+    /// back otherwise.
+    #[inline]
+    pub(crate) fn try_call(&self, t: T, is_empty: impl FnOnce() -> bool) -> Result<(), T> {
+        let owner = self.owner.load(Ordering::Acquire);
+        if owner == 0 || owner != current_thread() || !is_empty() {
+            return Err(t);
+        }
+        // SAFETY: this is the owner thread. The call is taken out while it runs, so that a call
+        // back to the channel from within queues.
+        let Some(mut call) = (unsafe { (*self.call.get()).take() }) else {
+            return Err(t);
+        };
+        let ret = call(t);
+        // SAFETY: as above
+        unsafe { *self.call.get() = Some(call) };
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_call_on_the_owner_thread_only() {
+        let direct = std::sync::Arc::new(Direct::<u32>::new());
+        assert_eq!(direct.try_call(1, || true), Err(1));
+
+        let taken = std::rc::Rc::new(std::cell::Cell::new(0));
+        let sink = std::rc::Rc::clone(&taken);
+        assert!(direct.set(Box::new(move |t| {
+            sink.set(t);
+            Ok(())
+        })));
+        assert_eq!(direct.try_call(2, || true), Ok(()));
+        assert_eq!(taken.get(), 2);
+        // a message queued before goes first
+        assert_eq!(direct.try_call(3, || false), Err(3));
+
+        let other = std::sync::Arc::clone(&direct);
+        std::thread::spawn(move || {
+            assert_eq!(other.try_call(4, || true), Err(4));
+            assert!(!other.set(Box::new(Err)));
+        })
+        .join()
+        .unwrap();
+        assert_eq!(taken.get(), 2);
+
+        direct.clear();
+        assert_eq!(direct.try_call(5, || true), Err(5));
+    }
+}
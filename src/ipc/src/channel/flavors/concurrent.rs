@@ -1,7 +1,417 @@
-//! Concurrent channel, encapsulated over crossbeam channel.
+//! Concurrent channel, a bounded single producer single consumer ring.
+//!
+//! Sending to and receiving from the ring are wait-free: each side owns one index and only reads
+//! the other's. When the ring is full, `try_send` fails, while `send` spills the message to an
+//! unbounded overflow queue behind a lock. Once a message spills, the following ones spill too
+//! until the receiver drains the overflow, so the messages are still received in order.
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-pub(crate) use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::utils::CachePadded;
 
-pub(crate) fn create_channel<T>() -> (Sender<T>, Receiver<T>) {
-    unbounded()
+use super::super::{ChannelStats, SendError, TryRecvError, TrySendError};
+
+struct Shared<T> {
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    /// The next slot to receive from, written only by the receiver.
+    head: CachePadded<AtomicUsize>,
+    /// The next slot to send to, written only by the sender.
+    tail: CachePadded<AtomicUsize>,
+    /// The messages sent after the ring was found full, in order.
+    overflow: Mutex<VecDeque<T>>,
+    /// The length of the overflow, so that neither side takes the lock when it is empty.
+    noverflow: AtomicUsize,
+    /// Set when either side is dropped.
+    disconnected: AtomicBool,
+    /// The most messages in the ring at once, written only by the sender.
+    high_watermark: AtomicUsize,
+    /// The sends that found the ring full, written only by the sender.
+    nfull: AtomicUsize,
+}
+
+// The slots between head and tail are only accessed by the receiver, the others only by the
+// sender.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while head != tail {
+            unsafe { self.buffer[head & self.mask].get_mut().assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn len(&self) -> usize {
+        // load the head first, so that the tail is not behind it
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head) + self.noverflow.load(Ordering::Acquire)
+    }
+
+    fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            len: self.len(),
+            capacity: self.capacity(),
+            high_watermark: self.high_watermark.load(Ordering::Relaxed),
+            nfull: self.nfull.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+    tail: usize,
+    /// The head last seen, the ring has at least `capacity - (tail - head)` free slots.
+    head: usize,
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("stats", &self.shared.stats())
+            .finish()
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.disconnected.store(true, Ordering::Release);
+    }
+}
+
+impl<T> Sender<T> {
+    fn push(&mut self, t: T) -> Result<(), T> {
+        let shared = &*self.shared;
+        if self.tail.wrapping_sub(self.head) == shared.capacity() {
+            self.head = shared.head.load(Ordering::Acquire);
+            if self.tail.wrapping_sub(self.head) == shared.capacity() {
+                return Err(t);
+            }
+        }
+        unsafe { (*shared.buffer[self.tail & shared.mask].get()).write(t) };
+        self.tail = self.tail.wrapping_add(1);
+        shared.tail.store(self.tail, Ordering::Release);
+
+        let len = self.tail.wrapping_sub(self.head);
+        if len > shared.high_watermark.load(Ordering::Relaxed) {
+            shared.high_watermark.store(len, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn count_full(&self) {
+        let nfull = self.shared.nfull.load(Ordering::Relaxed);
+        self.shared.nfull.store(nfull + 1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn try_send(&mut self, t: T) -> Result<(), TrySendError<T>> {
+        if self.shared.disconnected.load(Ordering::Acquire) {
+            return Err(TrySendError::Disconnected(t));
+        }
+        // the spilled messages go first
+        if self.shared.noverflow.load(Ordering::Acquire) > 0 {
+            self.count_full();
+            return Err(TrySendError::Full(t));
+        }
+        self.push(t).map_err(|t| {
+            self.count_full();
+            TrySendError::Full(t)
+        })
+    }
+
+    pub(crate) fn send(&mut self, t: T) -> Result<(), SendError<T>> {
+        if self.shared.disconnected.load(Ordering::Acquire) {
+            return Err(crossbeam::channel::SendError(t));
+        }
+        // only the sender grows the overflow, so it is really empty when it looks so
+        let t = if self.shared.noverflow.load(Ordering::Acquire) == 0 {
+            match self.push(t) {
+                Ok(()) => return Ok(()),
+                Err(t) => t,
+            }
+        } else {
+            t
+        };
+        self.count_full();
+        let mut overflow = self.shared.overflow.lock().unwrap();
+        overflow.push_back(t);
+        self.shared
+            .noverflow
+            .store(overflow.len(), Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether the channel holds at least `capacity` messages, so that `try_send` would fail.
+    #[inline]
+    pub(crate) fn is_full(&self) -> bool {
+        self.shared.len() >= self.shared.capacity()
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shared.len() == 0
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+}
+
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    head: usize,
+    /// The tail last seen, the ring has at least `tail - head` messages.
+    tail: usize,
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("stats", &self.shared.stats())
+            .finish()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.disconnected.store(true, Ordering::Release);
+    }
+}
+
+impl<T> Receiver<T> {
+    fn pop(&mut self) -> Option<T> {
+        let shared = &*self.shared;
+        if self.head == self.tail {
+            self.tail = shared.tail.load(Ordering::Acquire);
+            if self.head == self.tail {
+                return None;
+            }
+        }
+        let t = unsafe { (*shared.buffer[self.head & shared.mask].get()).assume_init_read() };
+        self.head = self.head.wrapping_add(1);
+        shared.head.store(self.head, Ordering::Release);
+        Some(t)
+    }
+
+    fn pop_overflow(&mut self) -> Option<T> {
+        if self.shared.noverflow.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        let shared = Arc::clone(&self.shared);
+        let mut overflow = shared.overflow.lock().unwrap();
+        // the messages sent to the ring before the first spilled one may only be visible now
+        if let Some(t) = self.pop() {
+            return Some(t);
+        }
+        let t = overflow.pop_front();
+        shared.noverflow.store(overflow.len(), Ordering::Release);
+        t
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(t) = self.pop().or_else(|| self.pop_overflow()) {
+            return Ok(t);
+        }
+        if self.shared.disconnected.load(Ordering::Acquire) {
+            // the messages sent before the sender was dropped are visible now
+            return self
+                .pop()
+                .or_else(|| self.pop_overflow())
+                .ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+
+    #[inline]
+    pub(crate) fn stats(&self) -> ChannelStats {
+        self.shared.stats()
+    }
+}
+
+/// Creates a ring of at least `capacity` slots, rounded up to a power of two.
+pub(crate) fn create_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let shared = Arc::new(Shared {
+        buffer: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        mask: capacity - 1,
+        head: CachePadded::new(AtomicUsize::new(0)),
+        tail: CachePadded::new(AtomicUsize::new(0)),
+        overflow: Mutex::new(VecDeque::new()),
+        noverflow: AtomicUsize::new(0),
+        disconnected: AtomicBool::new(false),
+        high_watermark: AtomicUsize::new(0),
+        nfull: AtomicUsize::new(0),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+            tail: 0,
+            head: 0,
+        },
+        Receiver {
+            shared,
+            head: 0,
+            tail: 0,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_pong() {
+        let (mut tx, mut rx) = create_channel(4);
+        assert_eq!(tx.send(42), Ok(()));
+        assert_eq!(rx.try_recv(), Ok(42));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn closed_tx() {
+        let (mut tx, mut rx) = create_channel(4);
+        tx.send(42).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(42));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn closed_rx() {
+        let (mut tx, rx) = create_channel(4);
+        drop(rx);
+        assert_eq!(tx.send(42), Err(crossbeam::channel::SendError(42)));
+        assert_eq!(tx.try_send(42), Err(TrySendError::Disconnected(42)));
+    }
+
+    #[test]
+    fn try_send_full() {
+        let (mut tx, mut rx) = create_channel(3);
+        assert_eq!(tx.capacity(), 4);
+        for i in 0..4 {
+            assert_eq!(tx.try_send(i), Ok(()));
+        }
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(tx.try_send(4), Ok(()));
+        assert_eq!(rx.stats().high_watermark, 4);
+        assert_eq!(rx.stats().nfull, 1);
+    }
+
+    #[test]
+    fn spill_in_order() {
+        let (mut tx, mut rx) = create_channel(4);
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.len(), 5);
+        // the overflow is not drained yet, the ring must not overtake it
+        assert_eq!(tx.try_send(5), Err(TrySendError::Full(5)));
+        assert_eq!(rx.try_recv(), Ok(0));
+        tx.send(5).unwrap();
+        for i in 1..6 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert!(rx.is_empty());
+        assert_eq!(tx.try_send(6), Ok(()));
+        assert_eq!(rx.try_recv(), Ok(6));
+    }
+
+    #[test]
+    fn send_past_capacity() {
+        let (mut tx, mut rx) = create_channel(2);
+        assert!(!tx.is_full());
+        // a full channel is not a disconnected one, send never gives the message back
+        for i in 0..8 {
+            assert_eq!(tx.send(i), Ok(()));
+            assert_eq!(tx.is_full(), i >= 1);
+        }
+        assert_eq!(rx.len(), 8);
+        assert_eq!(rx.stats().nfull, 6);
+        for i in 0..7 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert!(!tx.is_full());
+        assert_eq!(rx.try_recv(), Ok(7));
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn drop_pending() {
+        let msg = Arc::new(());
+        let (mut tx, rx) = create_channel(2);
+        for _ in 0..3 {
+            tx.send(Arc::clone(&msg)).unwrap();
+        }
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&msg), 1);
+    }
+
+    #[test]
+    fn across_threads() {
+        const N: usize = 100_000;
+        let (mut tx, mut rx) = create_channel(16);
+        let sender = std::thread::spawn(move || {
+            for i in 0..N {
+                let mut msg = i;
+                if i % 2 == 0 {
+                    tx.send(msg).unwrap();
+                } else {
+                    while let Err(TrySendError::Full(m)) = tx.try_send(msg) {
+                        msg = m;
+                        std::thread::yield_now();
+                    }
+                }
+            }
+        });
+        let mut expected = 0;
+        loop {
+            match rx.try_recv() {
+                Ok(i) => {
+                    assert_eq!(i, expected);
+                    expected += 1;
+                }
+                Err(TryRecvError::Empty) => std::thread::yield_now(),
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        assert_eq!(expected, N);
+        sender.join().unwrap();
+    }
 }
@@ -1,2 +1,3 @@
 pub(crate) mod concurrent;
+pub(crate) mod mpsc;
 pub(crate) mod sequential;
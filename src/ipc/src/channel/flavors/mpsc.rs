@@ -0,0 +1,448 @@
+//! Multi-producer channel, a bounded multiple producer single consumer ring.
+//!
+//! Each slot carries a sequence number that tells whether it is free for the sender of the
+//! current lap or holds a message for the receiver, so the senders only contend on the tail they
+//! claim the slots from. Like the concurrent channel, `send` spills to an unbounded overflow queue
+//! when the ring is full. The messages of each sender are received in order, the messages of
+//! different senders in any order.
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crossbeam::utils::CachePadded;
+
+use super::super::{ChannelStats, SendError, TryRecvError, TrySendError};
+
+struct Slot<T> {
+    /// The slot is free for the sender of `tail == seq`, and holds a message for the receiver of
+    /// `head + 1 == seq`.
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Shared<T> {
+    buffer: Box<[Slot<T>]>,
+    mask: usize,
+    /// The next slot to receive from, written only by the receiver.
+    head: CachePadded<AtomicUsize>,
+    /// The next slot to claim, written by the senders.
+    tail: CachePadded<AtomicUsize>,
+    /// The messages sent after the ring was found full.
+    overflow: Mutex<VecDeque<T>>,
+    /// The length of the overflow, so that neither side takes the lock when it is empty.
+    noverflow: AtomicUsize,
+    /// The number of senders alive.
+    senders: AtomicUsize,
+    /// Set when the receiver is dropped.
+    receiver_gone: AtomicBool,
+    high_watermark: AtomicUsize,
+    nfull: AtomicUsize,
+}
+
+// A slot is accessed by the sender that claimed it until it is filled, and by the receiver after.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // every claimed slot is filled, a sender writes right after it claims
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while head != tail {
+            unsafe {
+                self.buffer[head & self.mask]
+                    .value
+                    .get_mut()
+                    .assume_init_drop()
+            };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn len(&self) -> usize {
+        // load the head first, so that the tail is not behind it
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head) + self.noverflow.load(Ordering::Acquire)
+    }
+
+    fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            len: self.len(),
+            capacity: self.capacity(),
+            high_watermark: self.high_watermark.load(Ordering::Relaxed),
+            nfull: self.nfull.load(Ordering::Relaxed),
+        }
+    }
+
+    fn push(&self, t: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[tail & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match seq.wrapping_sub(tail) as isize {
+                0 => match self.tail.compare_exchange_weak(
+                    tail,
+                    tail.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(t) };
+                        slot.seq.store(tail.wrapping_add(1), Ordering::Release);
+                        let len = tail
+                            .wrapping_add(1)
+                            .wrapping_sub(self.head.load(Ordering::Relaxed));
+                        self.high_watermark.fetch_max(len, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    Err(current) => tail = current,
+                },
+                // the slot still holds the message of the previous lap
+                diff if diff < 0 => return Err(t),
+                // another sender claimed the slot
+                _ => tail = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("stats", &self.shared.stats())
+            .finish()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> Sender<T> {
+    pub(crate) fn try_send(&mut self, t: T) -> Result<(), TrySendError<T>> {
+        let shared = &*self.shared;
+        if shared.receiver_gone.load(Ordering::Acquire) {
+            return Err(TrySendError::Disconnected(t));
+        }
+        // the spilled messages go first
+        if shared.noverflow.load(Ordering::Acquire) > 0 {
+            shared.nfull.fetch_add(1, Ordering::Relaxed);
+            return Err(TrySendError::Full(t));
+        }
+        shared.push(t).map_err(|t| {
+            shared.nfull.fetch_add(1, Ordering::Relaxed);
+            TrySendError::Full(t)
+        })
+    }
+
+    pub(crate) fn send(&mut self, t: T) -> Result<(), SendError<T>> {
+        let shared = &*self.shared;
+        if shared.receiver_gone.load(Ordering::Acquire) {
+            return Err(crossbeam::channel::SendError(t));
+        }
+        // another sender may spill meanwhile, which only reorders the messages of different
+        // senders
+        let t = if shared.noverflow.load(Ordering::Acquire) == 0 {
+            match shared.push(t) {
+                Ok(()) => return Ok(()),
+                Err(t) => t,
+            }
+        } else {
+            t
+        };
+        shared.nfull.fetch_add(1, Ordering::Relaxed);
+        let mut overflow = shared.overflow.lock().unwrap();
+        overflow.push_back(t);
+        shared.noverflow.store(overflow.len(), Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether the channel holds at least `capacity` messages, so that `try_send` would fail.
+    #[inline]
+    pub(crate) fn is_full(&self) -> bool {
+        self.shared.len() >= self.shared.capacity()
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shared.len() == 0
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+}
+
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    head: usize,
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("stats", &self.shared.stats())
+            .finish()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_gone.store(true, Ordering::Release);
+    }
+}
+
+impl<T> Receiver<T> {
+    fn pop(&mut self) -> Option<T> {
+        let shared = &*self.shared;
+        let slot = &shared.buffer[self.head & shared.mask];
+        if slot.seq.load(Ordering::Acquire) != self.head.wrapping_add(1) {
+            return None;
+        }
+        let t = unsafe { (*slot.value.get()).assume_init_read() };
+        slot.seq
+            .store(self.head.wrapping_add(shared.capacity()), Ordering::Release);
+        self.head = self.head.wrapping_add(1);
+        shared.head.store(self.head, Ordering::Release);
+        Some(t)
+    }
+
+    fn pop_overflow(&mut self) -> Option<T> {
+        if self.shared.noverflow.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        let shared = Arc::clone(&self.shared);
+        let mut overflow = shared.overflow.lock().unwrap();
+        if let Some(t) = self.pop() {
+            return Some(t);
+        }
+        // a slot claimed before a message spilled may not be filled yet, it may hold an earlier
+        // message of the same sender
+        if shared.tail.load(Ordering::Acquire) != self.head {
+            return None;
+        }
+        let t = overflow.pop_front();
+        shared.noverflow.store(overflow.len(), Ordering::Release);
+        t
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(t) = self.pop().or_else(|| self.pop_overflow()) {
+            return Ok(t);
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            // the messages sent before the last sender was dropped are visible now
+            return self
+                .pop()
+                .or_else(|| self.pop_overflow())
+                .ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+
+    #[inline]
+    pub(crate) fn stats(&self) -> ChannelStats {
+        self.shared.stats()
+    }
+}
+
+/// Creates a ring of at least `capacity` slots, rounded up to a power of two. The ring has at
+/// least two slots, a slot of a single slot ring could not tell a message from a free slot.
+pub(crate) fn create_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let capacity = capacity.max(2).next_power_of_two();
+    let shared = Arc::new(Shared {
+        buffer: (0..capacity)
+            .map(|i| Slot {
+                seq: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect(),
+        mask: capacity - 1,
+        head: CachePadded::new(AtomicUsize::new(0)),
+        tail: CachePadded::new(AtomicUsize::new(0)),
+        overflow: Mutex::new(VecDeque::new()),
+        noverflow: AtomicUsize::new(0),
+        senders: AtomicUsize::new(1),
+        receiver_gone: AtomicBool::new(false),
+        high_watermark: AtomicUsize::new(0),
+        nfull: AtomicUsize::new(0),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared, head: 0 },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_pong() {
+        let (mut tx, mut rx) = create_channel(4);
+        assert_eq!(tx.send(42), Ok(()));
+        assert_eq!(rx.try_recv(), Ok(42));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn closed_tx() {
+        let (mut tx, mut rx) = create_channel(4);
+        let mut tx2 = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        tx2.send(2).unwrap();
+        drop(tx2);
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn closed_rx() {
+        let (mut tx, rx) = create_channel(4);
+        drop(rx);
+        assert_eq!(tx.send(42), Err(crossbeam::channel::SendError(42)));
+        assert_eq!(tx.try_send(42), Err(TrySendError::Disconnected(42)));
+    }
+
+    #[test]
+    fn try_send_full() {
+        let (mut tx, mut rx) = create_channel(3);
+        let mut tx2 = tx.clone();
+        assert_eq!(tx.capacity(), 4);
+        for i in 0..2 {
+            assert_eq!(tx.try_send(i), Ok(()));
+            assert_eq!(tx2.try_send(i + 2), Ok(()));
+        }
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(tx2.try_send(4), Ok(()));
+        for i in [2, 1, 3, 4] {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert_eq!(rx.stats().high_watermark, 4);
+        assert_eq!(rx.stats().nfull, 1);
+    }
+
+    #[test]
+    fn send_past_capacity() {
+        let (mut tx, mut rx) = create_channel(2);
+        let tx2 = tx.clone();
+        for i in 0..6 {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        assert!(tx2.is_full());
+        assert_eq!(rx.len(), 6);
+        assert_eq!(rx.stats().nfull, 4);
+        // the ring must not overtake the overflow
+        assert_eq!(tx.try_send(6), Err(TrySendError::Full(6)));
+        for i in 0..5 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert!(!tx.is_full());
+        assert_eq!(tx.send(6), Ok(()));
+        assert_eq!(rx.try_recv(), Ok(5));
+        assert_eq!(rx.try_recv(), Ok(6));
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn drop_pending() {
+        let msg = Arc::new(());
+        let (mut tx, rx) = create_channel(2);
+        for _ in 0..3 {
+            tx.send(Arc::clone(&msg)).unwrap();
+        }
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&msg), 1);
+    }
+
+    #[test]
+    fn across_threads() {
+        const SENDERS: usize = 4;
+        const N: usize = 50_000;
+        let (tx, mut rx) = create_channel(16);
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|id| {
+                let mut tx = tx.clone();
+                std::thread::spawn(move || {
+                    for i in 0..N {
+                        let mut msg = (id, i);
+                        if i % 2 == 0 {
+                            tx.send(msg).unwrap();
+                        } else {
+                            while let Err(TrySendError::Full(m)) = tx.try_send(msg) {
+                                msg = m;
+                                std::thread::yield_now();
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut expected = [0; SENDERS];
+        loop {
+            match rx.try_recv() {
+                Ok((id, i)) => {
+                    assert_eq!(i, expected[id], "out of order from sender {}", id);
+                    expected[id] += 1;
+                }
+                Err(TryRecvError::Empty) => std::thread::yield_now(),
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        assert_eq!(expected, [N; SENDERS]);
+        for sender in senders {
+            sender.join().unwrap();
+        }
+    }
+}
@@ -1,9 +1,12 @@
 //! Single producer single consumer queue. Non-thread-safe.
+//!
+//! The queue is bounded for `try_send` only. `send` never fails on a full queue, since the
+//! receiver runs on the same thread and cannot drain it while the sender waits.
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
-use super::super::{SendError, TryRecvError};
+use super::super::{ChannelStats, SendError, TryRecvError, TrySendError};
 
 #[derive(Debug)]
 pub(crate) struct Sender<T> {
@@ -28,6 +31,9 @@ unsafe impl<T: Send> Send for Receiver<T> {}
 #[derive(Debug)]
 struct Inner<T> {
     queue: VecDeque<T>,
+    capacity: usize,
+    high_watermark: usize,
+    nfull: usize,
 }
 
 #[derive(Debug)]
@@ -42,10 +48,41 @@ impl<T> Sender<T> {
             return Err(crossbeam::channel::SendError(t));
         }
         let mut inner = self.shared.inner.borrow_mut();
+        if inner.queue.len() >= inner.capacity {
+            inner.nfull += 1;
+        }
         inner.queue.push_back(t);
+        inner.high_watermark = inner.high_watermark.max(inner.queue.len());
         drop(inner);
         Ok(())
     }
+
+    pub(crate) fn try_send(&mut self, t: T) -> Result<(), TrySendError<T>> {
+        if Rc::strong_count(&self.shared) == 1 {
+            return Err(TrySendError::Disconnected(t));
+        }
+        let mut inner = self.shared.inner.borrow_mut();
+        if inner.queue.len() >= inner.capacity {
+            inner.nfull += 1;
+            return Err(TrySendError::Full(t));
+        }
+        inner.queue.push_back(t);
+        inner.high_watermark = inner.high_watermark.max(inner.queue.len());
+        Ok(())
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        let inner = self.shared.inner.borrow();
+        inner.queue.len() >= inner.capacity
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shared.inner.borrow().queue.is_empty()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.shared.inner.borrow().capacity
+    }
 }
 
 impl<T> Receiver<T> {
@@ -64,11 +101,33 @@ impl<T> Receiver<T> {
         let inner = self.shared.inner.borrow_mut();
         inner.queue.is_empty()
     }
+
+    pub(crate) fn len(&self) -> usize {
+        let inner = self.shared.inner.borrow();
+        inner.queue.len()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.shared.inner.borrow().capacity
+    }
+
+    pub(crate) fn stats(&self) -> ChannelStats {
+        let inner = self.shared.inner.borrow();
+        ChannelStats {
+            len: inner.queue.len(),
+            capacity: inner.capacity,
+            high_watermark: inner.high_watermark,
+            nfull: inner.nfull,
+        }
+    }
 }
 
-pub(crate) fn create_channel<T>() -> (Sender<T>, Receiver<T>) {
+pub(crate) fn create_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
     let inner = Inner {
         queue: VecDeque::new(),
+        capacity: capacity.max(1),
+        high_watermark: 0,
+        nfull: 0,
     };
     let shared = Shared {
         inner: RefCell::new(inner),
@@ -87,22 +146,51 @@ mod tests {
     use super::*;
     #[test]
     fn ping_pong() {
-        let (mut tx, mut rx) = create_channel();
+        let (mut tx, mut rx) = create_channel(4);
         assert_eq!(tx.send(42), Ok(()));
         assert_eq!(rx.try_recv(), Ok(42));
     }
 
     #[test]
     fn closed_tx() {
-        let (tx, mut rx) = create_channel::<()>();
+        let (tx, mut rx) = create_channel::<()>(4);
         drop(tx);
         assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
     }
 
     #[test]
     fn closed_rx() {
-        let (mut tx, rx) = create_channel();
+        let (mut tx, rx) = create_channel(4);
         drop(rx);
         assert_eq!(tx.send(42), Err(crossbeam::channel::SendError(42)));
     }
+
+    #[test]
+    fn try_send_full() {
+        let (mut tx, mut rx) = create_channel(2);
+        assert_eq!(tx.try_send(0), Ok(()));
+        assert_eq!(tx.try_send(1), Ok(()));
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        // send goes beyond the capacity
+        assert_eq!(tx.send(2), Ok(()));
+        assert_eq!(rx.stats().high_watermark, 3);
+        assert_eq!(rx.stats().nfull, 2);
+        for i in 0..3 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+    }
+
+    #[test]
+    fn send_past_capacity() {
+        let (mut tx, mut rx) = create_channel(2);
+        for i in 0..8 {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        assert!(tx.is_full());
+        assert_eq!(rx.len(), 8);
+        for i in 0..7 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert!(!tx.is_full());
+    }
 }
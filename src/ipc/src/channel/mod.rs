@@ -1,20 +1,61 @@
 //! Channel implementations.
+//!
+//! The channels are bounded. `try_send` fails on a full channel with [`TrySendError::Full`].
+//! `send` fails only on a disconnected channel: when the channel is full, it queues the message
+//! past the capacity, on a slower path counted in [`ChannelStats::nfull`]. An engine applies
+//! backpressure by leaving its input queued while [`Sender::is_full`] holds for the output it
+//! forwards to, and retries on its next quantum.
+//!
+//! A message can also skip the channel when its receiver has a direct call set on the thread of
+//! the sender, see [`direct`].
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
+pub mod direct;
 pub(crate) mod flavors;
 
+use direct::Direct;
+pub use direct::DirectCall;
+
 pub type SendError<T> = crossbeam::channel::SendError<T>;
+pub type TrySendError<T> = crossbeam::channel::TrySendError<T>;
 pub type TryRecvError = crossbeam::channel::TryRecvError;
 
+/// The capacity of the channels created by [`create_channel`].
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+static CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_CAPACITY);
+
+/// Sets the capacity of the channels created by [`create_channel`] from now on.
+pub fn set_default_capacity(capacity: usize) {
+    CAPACITY.store(capacity.max(1), Ordering::Relaxed);
+}
+
+/// The occupancy of a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChannelStats {
+    /// The number of messages in the channel.
+    pub len: usize,
+    pub capacity: usize,
+    /// The most messages in the channel at once, up to the capacity for a concurrent channel.
+    pub high_watermark: usize,
+    /// The number of sends that found the channel full.
+    pub nfull: usize,
+}
+
 /// The sending of a channel.
 #[derive(Debug)]
 pub struct Sender<T> {
     flavor: SenderFlavor<T>,
+    direct: Arc<Direct<T>>,
 }
 
 #[derive(Debug)]
 pub(crate) enum SenderFlavor<T> {
-    /// Crossbeam MPMC channel.
+    /// Bounded SPSC ring.
     Concurrent(flavors::concurrent::Sender<T>),
+    /// Bounded MPSC ring.
+    Mpsc(flavors::mpsc::Sender<T>),
     /// Sequential single-threaded queue. Not concurrent safe. Must be used with special
     /// scheduling policy.
     Sequential(flavors::sequential::Sender<T>),
@@ -24,6 +65,7 @@ macro_rules! choose_sender_flavor {
     ($flavor:expr, $func:ident $(, $args:tt)*) => {
         match $flavor {
             SenderFlavor::Concurrent(c) => c.$func($($args)*),
+            SenderFlavor::Mpsc(c) => c.$func($($args)*),
             SenderFlavor::Sequential(c) => c.$func($($args)*),
         }
     };
@@ -32,20 +74,68 @@ macro_rules! choose_sender_flavor {
 impl<T> Sender<T> {
     #[inline]
     pub fn send(&mut self, t: T) -> Result<(), SendError<T>> {
+        let t = match self.try_direct(t) {
+            Ok(()) => return Ok(()),
+            Err(t) => t,
+        };
         choose_sender_flavor!(&mut self.flavor, send, t)
     }
+
+    /// Sends a message unless the channel is full or disconnected.
+    #[inline]
+    pub fn try_send(&mut self, t: T) -> Result<(), TrySendError<T>> {
+        let t = match self.try_direct(t) {
+            Ok(()) => return Ok(()),
+            Err(t) => t,
+        };
+        choose_sender_flavor!(&mut self.flavor, try_send, t)
+    }
+
+    /// Hands the message to the direct call of the receiver, see [`direct`].
+    #[inline]
+    fn try_direct(&mut self, t: T) -> Result<(), T> {
+        let flavor = &self.flavor;
+        self.direct
+            .try_call(t, || choose_sender_flavor!(flavor, is_empty))
+    }
+
+    /// Returns true if the channel holds at least `capacity` messages.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        choose_sender_flavor!(&self.flavor, is_full)
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        choose_sender_flavor!(&self.flavor, capacity)
+    }
+
+    /// Returns another sender of the channel, or `None` if the channel has a single producer.
+    /// Only a [`ChannelFlavor::Mpsc`] channel has multiple producers.
+    pub fn try_clone(&self) -> Option<Self> {
+        match &self.flavor {
+            SenderFlavor::Mpsc(c) => Some(Sender {
+                flavor: SenderFlavor::Mpsc(c.clone()),
+                direct: Arc::clone(&self.direct),
+            }),
+            SenderFlavor::Concurrent(_) | SenderFlavor::Sequential(_) => None,
+        }
+    }
 }
 
 /// The sending of a channel.
 #[derive(Debug)]
 pub struct Receiver<T> {
     flavor: ReceiverFlavor<T>,
+    direct: Arc<Direct<T>>,
 }
 
 #[derive(Debug)]
 pub(crate) enum ReceiverFlavor<T> {
-    /// Crossbeam MPMC channel.
+    /// Bounded SPSC ring.
     Concurrent(flavors::concurrent::Receiver<T>),
+    /// Bounded MPSC ring.
+    Mpsc(flavors::mpsc::Receiver<T>),
     /// Sequential single-threaded queue. Not concurrent safe. Must be used with special
     /// scheduling policy.
     Sequential(flavors::sequential::Receiver<T>),
@@ -55,6 +145,7 @@ macro_rules! choose_receiver_flavor {
     ($flavor:expr, $func:ident $(, $args:tt)*) => {
         match $flavor {
             ReceiverFlavor::Concurrent(c) => c.$func($($args)*),
+            ReceiverFlavor::Mpsc(c) => c.$func($($args)*),
             ReceiverFlavor::Sequential(c) => c.$func($($args)*),
         }
     };
@@ -70,35 +161,102 @@ impl<T> Receiver<T> {
     pub fn is_empty(&self) -> bool {
         choose_receiver_flavor!(&self.flavor, is_empty)
     }
+
+    /// Returns the number of messages in the channel.
+    #[inline]
+    pub fn len(&self) -> usize {
+        choose_receiver_flavor!(&self.flavor, len)
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        choose_receiver_flavor!(&self.flavor, capacity)
+    }
+
+    #[inline]
+    pub fn stats(&self) -> ChannelStats {
+        choose_receiver_flavor!(&self.flavor, stats)
+    }
+
+    /// Has the senders on the current thread call `call` in place of the channel while it is
+    /// empty, see [`direct`]. Returns false if another thread has a call set.
+    pub fn set_direct(&self, call: DirectCall<T>) -> bool {
+        self.direct.set(call)
+    }
+
+    /// Drops the direct call, the messages are queued again. Must not be called from within the
+    /// call.
+    pub fn clear_direct(&self) {
+        self.direct.clear()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // the call may refer to the owner of the receiver
+        self.direct.clear();
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChannelFlavor {
     Concurrent,
+    /// Concurrent, with senders cloned by [`Sender::try_clone`].
+    Mpsc,
     Sequential,
 }
 
+/// Creates a channel of the default capacity, see [`set_default_capacity`].
 pub fn create_channel<T>(flavor: ChannelFlavor) -> (Sender<T>, Receiver<T>) {
+    create_bounded_channel(flavor, CAPACITY.load(Ordering::Relaxed))
+}
+
+/// Creates a channel of at least `capacity` messages. The capacity of a concurrent or MPSC
+/// channel is rounded up to a power of two.
+pub fn create_bounded_channel<T>(
+    flavor: ChannelFlavor,
+    capacity: usize,
+) -> (Sender<T>, Receiver<T>) {
     match flavor {
         ChannelFlavor::Concurrent => {
-            let (sender, receiver) = flavors::concurrent::create_channel();
+            let (sender, receiver) = flavors::concurrent::create_channel(capacity);
+            let direct = Arc::new(Direct::new());
             (
                 Sender {
                     flavor: SenderFlavor::Concurrent(sender),
+                    direct: Arc::clone(&direct),
                 },
                 Receiver {
                     flavor: ReceiverFlavor::Concurrent(receiver),
+                    direct,
+                },
+            )
+        }
+        ChannelFlavor::Mpsc => {
+            let (sender, receiver) = flavors::mpsc::create_channel(capacity);
+            let direct = Arc::new(Direct::new());
+            (
+                Sender {
+                    flavor: SenderFlavor::Mpsc(sender),
+                    direct: Arc::clone(&direct),
+                },
+                Receiver {
+                    flavor: ReceiverFlavor::Mpsc(receiver),
+                    direct,
                 },
             )
         }
         ChannelFlavor::Sequential => {
-            let (sender, receiver) = flavors::sequential::create_channel();
+            let (sender, receiver) = flavors::sequential::create_channel(capacity);
+            let direct = Arc::new(Direct::new());
             (
                 Sender {
                     flavor: SenderFlavor::Sequential(sender),
+                    direct: Arc::clone(&direct),
                 },
                 Receiver {
                     flavor: ReceiverFlavor::Sequential(receiver),
+                    direct,
                 },
             )
         }
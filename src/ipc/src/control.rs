@@ -1,12 +1,16 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 pub use libc::pid_t;
 use serde::{Deserialize, Serialize};
 
-use phoenix_api::engine::{SchedulingHint, SchedulingMode};
+use phoenix_api::engine::{SchedulingClass, SchedulingHint, SchedulingMode};
 
 type IResult<T> = Result<T, phoenix_api::Error>;
 
+/// The labels of a service subscription, e.g., its team, app or env.
+pub type Labels = BTreeMap<String, String>;
+
 /// Description for loading/upgrading a plugin.
 #[derive(Debug, Clone, Serialize, Deserialize, Eq)]
 pub struct PluginDescriptor {
@@ -35,6 +39,66 @@ impl PartialEq for PluginDescriptor {
     }
 }
 
+/// The manifest of a plugin, a TOML file next to its library with the extension `toml`, e.g.,
+/// `libphoenix_mrpc.toml`. The daemon checks a plugin against its manifest before loading it, so
+/// that a plugin built for another daemon, or a config it does not take, is refused with an
+/// error rather than failing once loaded. A plugin without a manifest is loaded unchecked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginManifest {
+    /// The name of the plugin, as in its `PluginDescriptor`
+    pub name: String,
+    pub kind: PluginType,
+    /// The semantic version of the plugin, e.g., `0.1.0`
+    pub version: String,
+    /// The engine types the plugin provides
+    pub engines: Vec<String>,
+    /// The oldest version of the daemon the plugin works with
+    #[serde(default)]
+    pub min_core_version: Option<String>,
+    /// The keys of the config of the plugin
+    #[serde(default)]
+    pub config: BTreeMap<String, ConfigField>,
+}
+
+/// A key of the config of a plugin.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigField {
+    #[serde(rename = "type")]
+    pub ty: ConfigType,
+    /// Whether the config must set the key
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// The type of the value of a key of the config of a plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Array,
+    Table,
+}
+
+/// A plugin loaded in the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub kind: PluginType,
+    pub lib_path: PathBuf,
+    /// The version the plugin reports
+    pub version: String,
+    pub engines: Vec<String>,
+    /// The manifest the plugin was loaded with, if any
+    pub manifest: Option<PluginManifest>,
+}
+
 /// Request for upgrading plugins.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpgradeRequest {
@@ -48,9 +112,42 @@ pub struct UpgradeRequest {
     /// whether to suspend all engines
     /// within the same service subscription
     pub detach_subscription: bool,
+    /// only check the request and answer with the plan,
+    /// without loading the plugins or touching the engines
+    pub dry_run: bool,
+    /// Only upgrade the engines of the subscriptions whose labels match this selector, e.g.,
+    /// `app=search`, instead of those of all the subscriptions
+    #[serde(default)]
+    pub selector: Option<String>,
 }
 
+/// Request for loading a second version of a module side by side with the one running, and
+/// assigning it a share of the new application processes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanaryRequest {
+    /// The canary version of the module
+    pub plugin: PluginDescriptor,
+    /// The share of the new processes whose subscriptions run on the canary, in percent.
+    /// Sending the request again for the same library changes the share of its canary.
+    pub percent: u8,
+    /// Only assign the processes whose labels match this selector, e.g., `env=staging`
+    #[serde(default)]
+    pub selector: Option<String>,
+    /// only check the request and answer with the plan,
+    /// without loading the plugin
+    pub dry_run: bool,
+}
+
+/// How a canary ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CanaryOutcome {
+    /// Upgrade the module to the canary version, which moves all the subscriptions to it.
+    Promote,
+    /// Stop assigning processes to the canary, it is unloaded once they are gone.
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PluginType {
     Module,
     Addon,
@@ -75,6 +172,98 @@ pub struct AddonRequest {
     pub config_path: Option<PathBuf>,
     /// The configuration string.
     pub config_string: Option<String>,
+    /// Named ports of the addon engine when attaching an addon
+    pub ports: Vec<PortDescriptor>,
+    /// Only check the request and answer with the plan, without touching the subscription
+    pub dry_run: bool,
+    /// Target the subscriptions whose labels match this selector, e.g., `app=search,env!=dev`,
+    /// one after another, instead of the one of `pid` and `sid`
+    #[serde(default)]
+    pub selector: Option<String>,
+}
+
+/// An addon of a chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainedAddon {
+    /// Addon engine type
+    pub addon_engine: String,
+    /// The path of the configuration file of this addon. Should be a toml file.
+    pub config_path: Option<PathBuf>,
+    /// The configuration string.
+    pub config_string: Option<String>,
+    /// Whether the addon only handles the tx messages, and stays off the rx channel
+    #[serde(default)]
+    pub tx_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainRequest {
+    /// Target user process
+    pub pid: pid_t,
+    /// Target service subscription
+    pub sid: u64,
+    /// The engine on the sender end of the tx channel to insert the chain into
+    pub upstream: String,
+    /// The engine on the receiver end of that channel,
+    /// the rx channel from it back to `upstream` is replaced as well, if any
+    pub downstream: String,
+    /// The addons, in the order the tx messages go through them
+    pub addons: Vec<ChainedAddon>,
+    /// Which scheduling group should the addons belong to, identified as a set of engines;
+    /// the addons get a new group of their own if empty
+    pub group: Vec<String>,
+    /// Only check the request and answer with the plan, without touching the subscription
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyRequest {
+    /// Target user process
+    pub pid: pid_t,
+    /// Target service subscription
+    pub sid: u64,
+    /// The policy, in TOML, compiled into a chain of the standard policy addons
+    pub policy: String,
+    /// Only check the request and answer with the plan, without touching the subscription
+    pub dry_run: bool,
+}
+
+/// The channels of an engine of one direction and end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortEndpoint {
+    TxInput,
+    TxOutput,
+    RxInput,
+    RxOutput,
+}
+
+/// How the messages sent to an output port spread over its channels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FanOut {
+    /// Each message goes to one channel, chosen by the engine or round robin.
+    #[default]
+    Route,
+    /// Each message goes to all channels.
+    Duplicate,
+}
+
+/// Names some channels of an engine as a port. The messages received from an input port are
+/// merged from its channels in a round robin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortDescriptor {
+    /// The engine type
+    pub engine: String,
+    pub endpoint: PortEndpoint,
+    /// The name of the port
+    pub name: String,
+    /// The indices of the channels in the endpoint
+    pub channels: Vec<usize>,
+    /// Only for the output ports
+    #[serde(default)]
+    pub fan_out: FanOut,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,14 +272,72 @@ pub enum Request {
     NewClient(SchedulingHint, String, Option<String>),
     /// Send a request to a specified engine, identified by the EngineId
     EngineRequest(u64, Vec<u8>),
+    /// Send a query to a specified engine, identified by the EngineId, and wait for its answer
+    EngineQuery(u64, Vec<u8>),
+    /// Replace the configuration of a specified engine, identified by the EngineId, with a TOML
+    /// string of its config type. The update is rolled back if the engine rejects it. Answered
+    /// with a `ResponseKind::EngineConfig` or an error.
+    UpdateEngineConfig(u64, String),
     /// List all service subscriptions
     ListSubscription,
-    /// Attach an addon to a service subscription
+    /// Attach an addon to a service subscription.
+    /// A dry run is answered with a `ResponseKind::Plan` or an error.
     AttachAddon(SchedulingMode, AddonRequest),
-    /// Detach an addon from a service subscription
+    /// Attach a chain of addons to a service subscription, all at once.
+    /// A dry run is answered with a `ResponseKind::Plan` or an error.
+    AttachChain(SchedulingMode, ChainRequest),
+    /// Compile a policy into a chain of addons and attach it to a service subscription.
+    /// A dry run is answered with a `ResponseKind::Plan` or an error.
+    ApplyPolicy(SchedulingMode, PolicyRequest),
+    /// Detach an addon from a service subscription.
+    /// A dry run is answered with a `ResponseKind::Plan` or an error.
     DetachAddon(AddonRequest),
-    /// Upgrade modules or plugins
+    /// Upgrade modules or plugins.
+    /// A dry run is answered with a `ResponseKind::Plan` or an error.
     Upgrade(UpgradeRequest),
+    /// Dump the datapath graph of a service subscription, identified by an optional pid and
+    /// the subscription ID. The pid can be omitted if the subscription ID is unambiguous.
+    DataPathGraph(Option<pid_t>, u64),
+    /// Cap the CPU used by the engines of a service subscription, identified by the pid and the
+    /// subscription ID, in number of cores, e.g., 0.5 for half a core. `None` removes the cap.
+    SetCpuShare(pid_t, u64, Option<f64>),
+    /// Change the scheduling class of a service subscription, identified by the pid and the
+    /// subscription ID. Its scheduling groups are moved to runtimes of the new class.
+    SetSchedulingClass(pid_t, u64, SchedulingClass),
+    /// List the recent failures of the engines and what their supervisors did about them.
+    ListEngineFailures,
+    /// Replace the labels of a service subscription, identified by the pid and the subscription
+    /// ID. The subscriptions can then be targeted by label selectors, e.g., `app=search`.
+    SetLabels(pid_t, u64, Labels),
+    /// Tear down a service subscription, identified by the pid and the subscription ID, e.g.,
+    /// once its failed engines kept for inspection have been inspected.
+    TearDownSubscription(pid_t, u64),
+    /// List the most recent requests of the audit log, up to the given number
+    ListAuditLog(usize),
+    /// List the events published on the event bus after the given sequence number, oldest
+    /// first, up to the given number
+    ListEvents(u64, usize),
+    /// Dispatch a long-running request, i.e., an Upgrade, AttachAddon, AttachChain, ApplyPolicy,
+    /// DetachAddon or EndCanary, and stream its progress back: a `ResponseKind::Progress` for each step, and
+    /// finally either a `ResponseKind::Completed` or an error. Dry runs cannot be streamed.
+    Streaming(Box<Request>),
+    /// Hand the control socket over to the new daemon sending this request. The socket is sent
+    /// back as an fd, and the old daemon exits after its current clients are gone.
+    Handoff,
+    /// New service subscription with the labels of the process, e.g., from `PHOENIX_LABELS`,
+    /// which the subscription is created with and which decide whether it runs on a canary.
+    NewLabeledClient(SchedulingHint, String, Option<String>, Labels),
+    /// Load a canary version of a module, or change the share of the processes assigned to it.
+    /// A dry run is answered with a `ResponseKind::Plan` or an error.
+    StartCanary(CanaryRequest),
+    /// End the canary of a module, identified by its name.
+    EndCanary(String, CanaryOutcome),
+    /// List the canaries of the modules.
+    ListCanaries,
+    /// List the plugins loaded, with their manifests.
+    ListPlugins,
+    /// Take a snapshot of the counters of the events worth watching in the daemon.
+    ListMetrics,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +347,211 @@ pub struct ServiceSubscriptionInfo {
     pub service: String,
     pub engines: Vec<(u64, String)>,
     pub addons: Vec<String>,
+    /// CPU time accounting of the engines
+    pub engine_times: Vec<EngineTimeInfo>,
+    /// The CPU cap in number of cores, `None` if not capped
+    pub cpu_share: Option<f64>,
+    #[serde(default)]
+    pub labels: Labels,
+    /// The modules whose engines run on their canary version for this subscription
+    #[serde(default)]
+    pub canaries: Vec<String>,
+}
+
+/// A canary version of a module, loaded side by side with the one running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanaryInfo {
+    pub name: String,
+    pub lib_path: PathBuf,
+    /// The share of the new processes assigned to the canary, in percent
+    pub percent: u8,
+    pub selector: Option<String>,
+    /// Whether the canary no longer takes new processes, and waits for its engines to be gone
+    /// before it is unloaded
+    pub draining: bool,
+    /// The processes whose subscriptions run on the canary
+    pub processes: Vec<pid_t>,
+}
+
+/// A snapshot of the counters of the events worth watching, since the daemon started.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MetricsInfo {
+    /// Errors the datapath of a plugin does not expect
+    pub unexpected_errors: u64,
+    /// Heap allocations on the per-message path of the engines
+    pub datapath_allocations: u64,
+    /// Messages dropped by a forwarding engine at their hop limit
+    pub expired_messages: u64,
+    /// Times a port went down
+    pub link_down_events: u64,
+    /// Times a port came back up
+    pub link_up_events: u64,
+    /// Ports that are down now
+    pub ports_down: u64,
+    /// Times a load balancer ejected a backend
+    pub backend_ejections: u64,
+    /// Backends that are ejected now
+    pub backends_ejected: u64,
+    /// Processes torn down while the plugins still held some of their resources
+    pub leaks: u64,
+}
+
+/// Time-slicing accounting of an engine since it was last (re)started.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EngineTimeInfo {
+    /// EngineId
+    pub eid: u64,
+    /// Total time its runtime spent in resuming the engine, in nanoseconds
+    pub cpu_time_ns: u64,
+    /// Number of times the engine was resumed
+    pub resumes: u64,
+    /// Average time of a resume, in nanoseconds
+    pub avg_quantum_ns: u64,
+    /// Number of times the engine was resumed right after the sender of a fused edge
+    pub fused_resumes: u64,
+    /// Total latency saved by the fused resumes and the direct calls, in nanoseconds
+    pub fused_saved_ns: u64,
+    /// Number of messages the engine took from direct calls on fused edges
+    pub direct_calls: u64,
+}
+
+/// What the supervisor of a subscription did about a failed engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SupervisorAction {
+    /// The engine is restarted after a backoff, in milliseconds.
+    Restart(u64),
+    /// All the engines of the subscription are shut down.
+    Teardown,
+    /// The engine is no longer resumed, but kept for inspection with engine queries.
+    KeepFailed,
+}
+
+/// A failure of an engine, i.e., a panic or an error returned by the engine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineFailureInfo {
+    pub pid: pid_t,
+    pub sid: u64,
+    /// EngineId
+    pub eid: u64,
+    pub engine_type: String,
+    /// The error, or the message of the panic
+    pub error: String,
+    /// Number of times the engines of the subscription have been restarted before
+    pub restarts: u32,
+    pub action: SupervisorAction,
+    /// When the engine failed, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+}
+
+/// A request received on the control socket, as recorded in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// The ID given to the request by the daemon, increasing
+    pub id: u64,
+    /// When the request was dispatched, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// The credentials of the sender
+    pub pid: Option<pid_t>,
+    pub uid: u32,
+    pub gid: u32,
+    /// The request, truncated
+    pub request: String,
+    /// `None` if the request succeeded
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EventSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+/// An event published on the event bus of the daemon, e.g., an alert raised by an addon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventInfo {
+    /// The sequence number of the event, increasing
+    pub seq: u64,
+    /// When the event was published, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub severity: EventSeverity,
+    /// What published the event, e.g., the name of an engine and its subscription
+    pub source: String,
+    /// What the event is about, e.g., `slo.burn_rate`, for the clients to filter on
+    pub kind: String,
+    pub message: String,
+}
+
+/// A step of an upgrade, or of an addon attach or detach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProgressStage {
+    /// The engine has been suspended from its runtime
+    Suspended,
+    /// The queues of the engine have been flushed
+    Flushed,
+    /// The states of the engine have been dumped
+    Dumped,
+    /// The engine has been restored from its dumped states
+    Restored,
+    /// The addon engine has been created
+    Created,
+    /// The addon engine has been removed
+    Removed,
+    /// The engines of the subscription have been handed back to the runtimes
+    Resubmitted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressEvent {
+    pub pid: pid_t,
+    pub sid: u64,
+    /// `None` if the step is about the whole subscription
+    pub engine_type: Option<String>,
+    pub stage: ProgressStage,
+}
+
+/// Direction of a datapath channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelDirection {
+    /// From the application towards the network.
+    Tx,
+    /// From the network towards the application.
+    Rx,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphEngineInfo {
+    /// EngineId
+    pub eid: u64,
+    pub engine_type: String,
+    /// The runtime the engine is running on
+    pub rid: u64,
+    /// The scheduling group the engine belongs to
+    pub gid: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphChannelInfo {
+    pub direction: ChannelDirection,
+    pub sender: String,
+    /// Index of the channel in the sender's output queues
+    pub sender_index: usize,
+    pub receiver: String,
+    /// Index of the channel in the receiver's input queues
+    pub receiver_index: usize,
+    /// Number of messages in the queue when sampled, `None` if the receiver
+    /// did not respond in time
+    pub queue_depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataPathGraphInfo {
+    pub pid: pid_t,
+    pub sid: u64,
+    pub service: String,
+    pub addons: Vec<String>,
+    pub engines: Vec<GraphEngineInfo>,
+    pub channels: Vec<GraphChannelInfo>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,6 +559,23 @@ pub enum ResponseKind {
     /// path of the engine's domain socket
     NewClient(PathBuf),
     ListSubscription(Vec<ServiceSubscriptionInfo>),
+    DataPathGraph(DataPathGraphInfo),
+    /// The encoded answer of an engine to an EngineQuery
+    EngineQuery(Vec<u8>),
+    /// The configuration replaced by an UpdateEngineConfig, as a TOML string
+    EngineConfig(String),
+    /// The recent failures of the engines, oldest first
+    EngineFailures(Vec<EngineFailureInfo>),
+    /// The most recent requests of the audit log, oldest first
+    AuditLog(Vec<AuditEntry>),
+    /// The events of the event bus, oldest first
+    Events(Vec<EventInfo>),
+    /// A step of a streamed request
+    Progress(ProgressEvent),
+    /// The streamed request has completed
+    Completed,
+    /// The steps a dry run would take, in order
+    Plan(Vec<String>),
     /// .0: the requested scheduling mode
     /// .1: name of the OneShotServer
     /// .2: data path work queue capacity in bytes
@@ -116,6 +585,12 @@ pub enum ResponseKind {
         wq_cap: usize,
         cq_cap: usize,
     },
+    /// The canaries of the modules
+    Canaries(Vec<CanaryInfo>),
+    /// The plugins loaded, by name
+    Plugins(Vec<PluginInfo>),
+    /// A snapshot of the counters
+    Metrics(MetricsInfo),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
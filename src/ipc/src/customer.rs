@@ -1,7 +1,8 @@
 //! Shared memory Customer implementation.
-use std::fs;
+use std::fs::{self, File};
+use std::io;
 use std::mem;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
@@ -42,6 +43,17 @@ pub struct Customer<Command, Completion, WorkRequest, WorkCompletion> {
     dp_cq: ShmSender<WorkCompletion>,
     timer: Instant,
     fd_notifier: ShmObject<AtomicUsize>,
+    /// An eventfd the client writes after sending a command.
+    cmd_doorbell: File,
+}
+
+/// Creates a nonblocking eventfd.
+fn new_doorbell() -> io::Result<File> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
 }
 
 impl<Command, Completion, WorkRequest, WorkCompletion>
@@ -120,6 +132,7 @@ where
         let cmd_tx_entries = ShmObject::new(AtomicUsize::new(0))?;
         let cmd_rx_entries = ShmObject::new(AtomicUsize::new(0))?;
         let fd_notifier = ShmObject::new(AtomicUsize::new(0))?;
+        let cmd_doorbell = new_doorbell()?;
 
         // 8. send the file descriptors back to let the client attach to these shared memory queues
         engine_sock.send_fd(
@@ -134,6 +147,7 @@ where
                 ShmObject::memfd(&cmd_tx_entries).as_raw_fd(),
                 ShmObject::memfd(&cmd_rx_entries).as_raw_fd(),
                 ShmObject::memfd(&fd_notifier).as_raw_fd(),
+                cmd_doorbell.as_raw_fd(),
             ],
         )?;
 
@@ -148,9 +162,20 @@ where
             dp_cq,
             timer: Instant::now(),
             fd_notifier,
+            cmd_doorbell,
         })
     }
 
+    /// The eventfds that become readable when the client sends a command or enqueues a work
+    /// request to an empty queue. They are never read by the customer itself.
+    #[inline]
+    pub fn doorbells(&self) -> [RawFd; 2] {
+        [
+            self.cmd_doorbell.as_raw_fd(),
+            self.dp_wq.empty_signal().as_raw_fd(),
+        ]
+    }
+
     #[inline]
     pub fn has_control_command(&mut self) -> bool {
         static TIMEOUT: Duration = Duration::from_millis(100);
@@ -222,4 +247,69 @@ where
         self.dp_cq.sender_mut().send(f)?;
         Ok(())
     }
+
+    /// Appends up to `max` work requests to `buf`, each read as a `T` from its slot, and
+    /// returns the number of work requests received.
+    #[inline]
+    pub fn dequeue_wrs<T: Copy>(&mut self, buf: &mut Vec<T>, max: usize) -> Result<usize, Error> {
+        // SAFETY: the application writes its work requests in the slots, which the backend
+        // trusts as it does the descriptors they point to
+        let count = unsafe { self.dp_wq.receiver_mut().recv_typed_into(buf, max)? };
+        Ok(count)
+    }
+
+    /// Appends up to `max` work requests to `buf` as [`dequeue_wrs`](Self::dequeue_wrs), and the
+    /// payloads that follow some of them to `payload`, see
+    /// [`Receiver::recv_typed_with_payload_into`](crate::ring::Receiver::recv_typed_with_payload_into).
+    #[inline]
+    pub fn dequeue_wrs_with_payload<T: Copy, L: Fn(&T) -> Option<usize>>(
+        &mut self,
+        buf: &mut Vec<T>,
+        payload: &mut Vec<u8>,
+        max: usize,
+        payload_len: L,
+    ) -> Result<usize, Error> {
+        // SAFETY: the application writes its work requests and their payloads in the slots,
+        // which the backend trusts as it does the descriptors
+        let count = unsafe {
+            self.dp_wq.receiver_mut().recv_typed_with_payload_into(
+                buf,
+                payload,
+                max,
+                payload_len,
+            )?
+        };
+        Ok(count)
+    }
+
+    /// Writes a completion as a `T` followed by `payload`, spinning while the queue does not
+    /// have room. The slots left before the end of the queue are filled with `pad` if the
+    /// payload does not fit there. This bypasses the eventfd, as
+    /// [`enqueue_wc_with`](Self::enqueue_wc_with).
+    #[inline]
+    pub fn enqueue_wc_with_payload<T: Copy>(
+        &mut self,
+        wc: T,
+        payload: &[u8],
+        pad: T,
+    ) -> Result<(), Error> {
+        while !self
+            .dp_cq
+            .sender_mut()
+            .try_send_typed_with_payload(wc, payload, pad)?
+        {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Writes a completion as a `T` in a slot, spinning while the queue is full. This bypasses
+    /// the eventfd, as [`enqueue_wc_with`](Self::enqueue_wc_with).
+    #[inline]
+    pub fn enqueue_wc<T: Copy>(&mut self, wc: T) -> Result<(), Error> {
+        while !self.dp_cq.sender_mut().try_send_typed(wc)? {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
 }
@@ -10,7 +10,13 @@ use thiserror::Error;
 pub mod ipc_channel;
 /// Re-exports shmem_ipc
 pub mod shmem_ipc;
-pub(crate) use crate::shmem_ipc::{ShmReceiver, ShmSender};
+
+/// Provides the shared memory rings of the data path
+pub mod ring;
+pub(crate) use crate::ring::{ShmReceiver, ShmSender};
+
+/// Provides ShmChannel, typed channels over the shared memory rings
+pub mod shm_channel;
 
 /// Common data structures passed between client and server
 pub mod control;
@@ -22,6 +28,9 @@ pub mod unix;
 pub(crate) mod shmobj;
 pub(crate) use shmobj::ShmObject;
 
+/// Provides ShmHeap, an allocator in shared memory
+pub mod shmalloc;
+
 /// Provides Customer and Service
 pub mod customer;
 pub mod service;
@@ -90,8 +99,12 @@ pub enum Error {
     ShmIpc(#[from] shmem_ipc::ShmIpcError),
     #[error("Shared memory queue ringbuf error: {0}")]
     ShmRingbuf(#[from] shmem_ipc::ShmRingbufError),
+    #[error("Shared memory ring error: {0}")]
+    ShmRing(#[from] ring::Error),
     #[error("ShmObject error: {0}")]
     ShmObj(#[from] shmobj::Error),
+    #[error("Shared heap error: {0}")]
+    ShmAlloc(#[from] shmalloc::Error),
     #[error("Expect a credential from the peer")]
     EmptyCredential,
     #[error("Credential mismatch {0:?} vs {1:?}")]
@@ -0,0 +1,466 @@
+//! Shared memory rings of the data path, between a single producer and a single consumer.
+//!
+//! The producer index and the consumer index are each on a cache line of their own, or on a page
+//! of their own with [`Placement::Page`], and the slots start on the next line. Each side keeps
+//! the index of the other side it has last seen, and only reloads it when the ring looks full, or
+//! empty. So the two cores only pull the line of an index when the other core has moved it. With
+//! the two indices in the same line, the line bounces between the cores on every enqueue and
+//! every dequeue, which `perf c2c` reports as HITM events on the queue header.
+//!
+//! The layout of the shared memory, `stride` being the size of a cache line or of a page:
+//!
+//! | offset       | content                                           |
+//! |--------------|---------------------------------------------------|
+//! | 0            | header, written once by the side creating the ring |
+//! | stride       | producer index                                    |
+//! | 2 * stride   | consumer index                                    |
+//! | 3 * stride   | slots                                             |
+use std::fs::File;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use memfd::{Memfd, MemfdOptions};
+use memmap2::{MmapOptions, MmapRaw};
+use thiserror::Error;
+use uuid::Uuid;
+
+const CACHE_LINE_SIZE: usize = 64;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"PHXRING1");
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Memfd: {0}.")]
+    Memfd(#[from] memfd::Error),
+    #[error("IO: {0}.")]
+    Io(#[from] io::Error),
+    #[error("Buffer too small.")]
+    BufTooSmall,
+    #[error("Not a ring of {0} slots of {1} bytes.")]
+    Mismatch(usize, usize),
+    #[error("The callback took {0} slots, but only {1} were given.")]
+    TooManySlots(usize, usize),
+}
+
+/// Where to place the indices of a ring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Placement {
+    /// On a cache line of their own.
+    #[default]
+    CacheLine,
+    /// On a page of their own, in case the adjacent line prefetcher pulls the line of the other
+    /// index together.
+    Page,
+}
+
+impl Placement {
+    fn stride(self) -> usize {
+        match self {
+            Placement::CacheLine => CACHE_LINE_SIZE,
+            Placement::Page => page_size(),
+        }
+    }
+}
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    capacity: u64,
+    slot_size: u64,
+    stride: u64,
+}
+
+struct Ring<T> {
+    mmap: MmapRaw,
+    memfd: Memfd,
+    capacity: usize,
+    stride: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Ring<T> {
+    fn nbytes(capacity: usize, stride: usize) -> usize {
+        3 * stride + capacity * size_of::<T>()
+    }
+
+    fn create(capacity: usize, placement: Placement) -> Result<Self, Error> {
+        assert!(capacity > 0, "a ring needs at least a slot");
+        assert!(align_of::<T>() <= CACHE_LINE_SIZE);
+        let stride = placement.stride();
+        let opts = MemfdOptions::default()
+            .allow_sealing(true)
+            .close_on_exec(false);
+        let name = format!("shmring-{}", Uuid::new_v4());
+        let memfd = opts.create(name)?;
+        memfd
+            .as_file()
+            .set_len(Self::nbytes(capacity, stride) as u64)?;
+
+        let mmap = MmapOptions::new().map_raw(memfd.as_file())?;
+        // SAFETY: the mapping is page aligned and large enough, and no one else maps it yet
+        unsafe {
+            mmap.as_mut_ptr().cast::<Header>().write(Header {
+                magic: MAGIC,
+                capacity: capacity as u64,
+                slot_size: size_of::<T>() as u64,
+                stride: stride as u64,
+            });
+        }
+        Ok(Ring {
+            mmap,
+            memfd,
+            capacity,
+            stride,
+            _marker: PhantomData,
+        })
+    }
+
+    fn open(capacity: usize, file: File) -> Result<Self, Error> {
+        let memfd = Memfd::try_from_file(file).map_err(|_| io::Error::last_os_error())?;
+        let mmap = MmapOptions::new().map_raw(memfd.as_file())?;
+        if mmap.len() < size_of::<Header>() {
+            return Err(Error::BufTooSmall);
+        }
+        // SAFETY: the mapping is page aligned and holds a header
+        let header = unsafe { mmap.as_ptr().cast::<Header>().read() };
+        if header.magic != MAGIC
+            || header.capacity != capacity as u64
+            || header.slot_size != size_of::<T>() as u64
+        {
+            return Err(Error::Mismatch(capacity, size_of::<T>()));
+        }
+        let stride = header.stride as usize;
+        if stride < CACHE_LINE_SIZE || mmap.len() < Self::nbytes(capacity, stride) {
+            return Err(Error::BufTooSmall);
+        }
+        Ok(Ring {
+            mmap,
+            memfd,
+            capacity,
+            stride,
+            _marker: PhantomData,
+        })
+    }
+
+    #[inline]
+    fn producer(&self) -> &AtomicUsize {
+        // SAFETY: the index is within the mapping, aligned, and only accessed atomically
+        unsafe { &*self.mmap.as_ptr().add(self.stride).cast::<AtomicUsize>() }
+    }
+
+    #[inline]
+    fn consumer(&self) -> &AtomicUsize {
+        // SAFETY: the index is within the mapping, aligned, and only accessed atomically
+        unsafe {
+            &*self
+                .mmap
+                .as_ptr()
+                .add(2 * self.stride)
+                .cast::<AtomicUsize>()
+        }
+    }
+
+    #[inline]
+    fn slots(&self) -> *mut T {
+        // SAFETY: the slots are within the mapping
+        unsafe { self.mmap.as_mut_ptr().add(3 * self.stride).cast::<T>() }
+    }
+}
+
+/// The producer side of a ring.
+pub struct Sender<T> {
+    ring: Ring<T>,
+    head: usize,
+    /// The consumer index last seen.
+    tail: usize,
+}
+
+/// The consumer side of a ring.
+pub struct Receiver<T> {
+    ring: Ring<T>,
+    tail: usize,
+    /// The producer index last seen.
+    head: usize,
+}
+
+// SAFETY: the ring is only accessed through the mapping, which is Send, and each side is owned
+// by a single thread at a time
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+impl<T> Sender<T> {
+    fn new(ring: Ring<T>) -> Self {
+        let head = ring.producer().load(Ordering::Relaxed);
+        let tail = ring.consumer().load(Ordering::Acquire);
+        Sender { ring, head, tail }
+    }
+
+    /// The number of free slots.
+    #[inline]
+    pub fn write_count(&mut self) -> Result<usize, Error> {
+        self.tail = self.ring.consumer().load(Ordering::Acquire);
+        Ok(self.ring.capacity - self.head.wrapping_sub(self.tail))
+    }
+
+    /// Calls `f` with the free slots that are contiguous in memory, and publishes the number of
+    /// slots it returns as written.
+    #[inline]
+    pub fn send<F: FnOnce(*mut T, usize) -> usize>(&mut self, f: F) -> Result<usize, Error> {
+        let capacity = self.ring.capacity;
+        let mut free = capacity - self.head.wrapping_sub(self.tail);
+        if free == 0 {
+            free = self.write_count()?;
+        }
+        let start = self.head % capacity;
+        let count = free.min(capacity - start);
+        // SAFETY: the consumer does not touch these slots until they are published
+        let written = f(unsafe { self.ring.slots().add(start) }, count);
+        if written > count {
+            return Err(Error::TooManySlots(written, count));
+        }
+        if written > 0 {
+            self.head = self.head.wrapping_add(written);
+            self.ring.producer().store(self.head, Ordering::Release);
+        }
+        Ok(written)
+    }
+
+    /// Calls `f` with `n` free slots that are contiguous in memory, and publishes them as
+    /// written. If fewer than `n` slots are left before the end of the ring, `pad` is called with
+    /// each of them first and they are published with the others, so that the `n` slots start at
+    /// the beginning of the ring. Returns the number of slots published, 0 if the ring does not
+    /// have room.
+    #[inline]
+    pub fn send_contiguous<P, F>(&mut self, n: usize, mut pad: P, f: F) -> Result<usize, Error>
+    where
+        P: FnMut(*mut T),
+        F: FnOnce(*mut T),
+    {
+        let capacity = self.ring.capacity;
+        if n > capacity {
+            return Err(Error::TooManySlots(n, capacity));
+        }
+        let start = self.head % capacity;
+        let padding = if capacity - start < n {
+            capacity - start
+        } else {
+            0
+        };
+        let free = capacity - self.head.wrapping_sub(self.tail);
+        if free < padding + n && self.write_count()? < padding + n {
+            return Ok(0);
+        }
+        for i in start..start + padding {
+            // SAFETY: the consumer does not touch these slots until they are published
+            pad(unsafe { self.ring.slots().add(i) });
+        }
+        // SAFETY: as above, and the slots do not wrap around
+        f(unsafe { self.ring.slots().add((start + padding) % capacity) });
+        self.head = self.head.wrapping_add(padding + n);
+        self.ring.producer().store(self.head, Ordering::Release);
+        Ok(padding + n)
+    }
+}
+
+impl<T> Receiver<T> {
+    fn new(ring: Ring<T>) -> Self {
+        let tail = ring.consumer().load(Ordering::Relaxed);
+        let head = ring.producer().load(Ordering::Acquire);
+        Receiver { ring, tail, head }
+    }
+
+    /// The number of slots to read.
+    #[inline]
+    pub fn read_count(&mut self) -> Result<usize, Error> {
+        self.head = self.ring.producer().load(Ordering::Acquire);
+        Ok(self.head.wrapping_sub(self.tail))
+    }
+
+    /// Calls `f` with the slots to read that are contiguous in memory, and releases the number
+    /// of slots it returns as read.
+    #[inline]
+    pub fn recv<F: FnOnce(*const T, usize) -> usize>(&mut self, f: F) -> Result<usize, Error> {
+        let capacity = self.ring.capacity;
+        let mut avail = self.head.wrapping_sub(self.tail);
+        if avail == 0 {
+            avail = self.read_count()?;
+        }
+        let start = self.tail % capacity;
+        let count = avail.min(capacity - start);
+        // SAFETY: the producer does not touch these slots until they are released
+        let read = f(unsafe { self.ring.slots().add(start) }, count);
+        if read > count {
+            return Err(Error::TooManySlots(read, count));
+        }
+        if read > 0 {
+            self.tail = self.tail.wrapping_add(read);
+            self.ring.consumer().store(self.tail, Ordering::Release);
+        }
+        Ok(read)
+    }
+}
+
+fn new_signal() -> io::Result<File> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// A [`Sender`] in shared memory, with the eventfds to wake up the consumer. `empty_signal`
+/// becomes readable when the producer sends to an empty ring. `full_signal` is kept for the
+/// consumer to wake up a producer waiting on a full ring.
+pub struct ShmSender<T> {
+    sender: Sender<T>,
+    empty_signal: File,
+    full_signal: File,
+}
+
+/// A [`Receiver`] in shared memory, with the eventfds of the ring, see [`ShmSender`].
+pub struct ShmReceiver<T> {
+    receiver: Receiver<T>,
+    empty_signal: File,
+    full_signal: File,
+}
+
+impl<T> ShmSender<T> {
+    /// Creates a ring of `capacity` slots, with the indices on their own cache lines.
+    pub fn new(capacity: usize) -> Result<Self, Error> {
+        Self::with_placement(capacity, Placement::CacheLine)
+    }
+
+    pub fn with_placement(capacity: usize, placement: Placement) -> Result<Self, Error> {
+        Ok(ShmSender {
+            sender: Sender::new(Ring::create(capacity, placement)?),
+            empty_signal: new_signal()?,
+            full_signal: new_signal()?,
+        })
+    }
+
+    /// Attaches to a ring of `capacity` slots created by the other side.
+    pub fn open(
+        capacity: usize,
+        memfd: File,
+        empty_signal: File,
+        full_signal: File,
+    ) -> Result<Self, Error> {
+        Ok(ShmSender {
+            sender: Sender::new(Ring::open(capacity, memfd)?),
+            empty_signal,
+            full_signal,
+        })
+    }
+
+    #[inline]
+    pub fn memfd(&self) -> &Memfd {
+        &self.sender.ring.memfd
+    }
+
+    #[inline]
+    pub fn empty_signal(&self) -> &File {
+        &self.empty_signal
+    }
+
+    #[inline]
+    pub fn full_signal(&self) -> &File {
+        &self.full_signal
+    }
+
+    /// Sends without signaling the consumer.
+    #[inline]
+    pub fn sender_mut(&mut self) -> &mut Sender<T> {
+        &mut self.sender
+    }
+
+    /// Sends, and signals the consumer if it may have seen the ring empty.
+    pub fn send_raw<F: FnOnce(*mut T, usize) -> usize>(&mut self, f: F) -> Result<usize, Error> {
+        let prev_head = self.sender.head;
+        let written = self.sender.send(f)?;
+        if written == 0 {
+            return Ok(0);
+        }
+        // Pairs with the consumer checking the producer index after arming its wait on the
+        // eventfd. Either the consumer sees the new slots, or we see it has read everything.
+        fence(Ordering::SeqCst);
+        if self.sender.ring.consumer().load(Ordering::Relaxed) == prev_head {
+            (&self.empty_signal).write_all(&1u64.to_ne_bytes())?;
+        }
+        Ok(written)
+    }
+
+    /// Sends `n` contiguous slots as [`Sender::send_contiguous`], and signals the consumer as
+    /// [`send_raw`](Self::send_raw).
+    pub fn send_contiguous_raw<P, F>(&mut self, n: usize, pad: P, f: F) -> Result<usize, Error>
+    where
+        P: FnMut(*mut T),
+        F: FnOnce(*mut T),
+    {
+        let prev_head = self.sender.head;
+        let written = self.sender.send_contiguous(n, pad, f)?;
+        if written == 0 {
+            return Ok(0);
+        }
+        fence(Ordering::SeqCst);
+        if self.sender.ring.consumer().load(Ordering::Relaxed) == prev_head {
+            (&self.empty_signal).write_all(&1u64.to_ne_bytes())?;
+        }
+        Ok(written)
+    }
+}
+
+impl<T> ShmReceiver<T> {
+    /// Creates a ring of `capacity` slots, with the indices on their own cache lines.
+    pub fn new(capacity: usize) -> Result<Self, Error> {
+        Self::with_placement(capacity, Placement::CacheLine)
+    }
+
+    pub fn with_placement(capacity: usize, placement: Placement) -> Result<Self, Error> {
+        Ok(ShmReceiver {
+            receiver: Receiver::new(Ring::create(capacity, placement)?),
+            empty_signal: new_signal()?,
+            full_signal: new_signal()?,
+        })
+    }
+
+    /// Attaches to a ring of `capacity` slots created by the other side.
+    pub fn open(
+        capacity: usize,
+        memfd: File,
+        empty_signal: File,
+        full_signal: File,
+    ) -> Result<Self, Error> {
+        Ok(ShmReceiver {
+            receiver: Receiver::new(Ring::open(capacity, memfd)?),
+            empty_signal,
+            full_signal,
+        })
+    }
+
+    #[inline]
+    pub fn memfd(&self) -> &Memfd {
+        &self.receiver.ring.memfd
+    }
+
+    #[inline]
+    pub fn empty_signal(&self) -> &File {
+        &self.empty_signal
+    }
+
+    #[inline]
+    pub fn full_signal(&self) -> &File {
+        &self.full_signal
+    }
+
+    #[inline]
+    pub fn receiver_mut(&mut self) -> &mut Receiver<T> {
+        &mut self.receiver
+    }
+}
@@ -3,6 +3,7 @@ use std::cell::RefCell;
 use std::env;
 use std::fs;
 use std::fs::File;
+use std::io::Write;
 use std::os::unix::io::FromRawFd;
 use std::os::unix::io::RawFd;
 use std::os::unix::net::UCred;
@@ -34,6 +35,22 @@ pub type ShmService<A, B, C, D> = Service<A, B, C, D>;
 
 unsafe impl<A: Sync, B: Sync, C: Sync, D: Sync> Sync for Service<A, B, C, D> {}
 
+/// The labels of this process, from `PHOENIX_LABELS`, e.g., `app=search,env=staging`. The
+/// daemon attaches them to the subscriptions of the process.
+fn process_labels() -> Option<control::Labels> {
+    let labels = env::var("PHOENIX_LABELS").ok()?;
+    let labels = labels
+        .split(',')
+        .filter(|label| !label.trim().is_empty())
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) => (key.trim().to_owned(), value.trim().to_owned()),
+            // left for the daemon to refuse
+            None => (label.trim().to_owned(), String::new()),
+        })
+        .collect::<control::Labels>();
+    (!labels.is_empty()).then_some(labels)
+}
+
 /// A `Service` sends Command (contorl path) and WorkRequest (datapath)
 /// and reply with Completion (control path) and WorkCompletion (datapath).
 ///
@@ -47,6 +64,7 @@ pub struct Service<Command, Completion, WorkRequest, WorkCompletion> {
     timer: AtomicCell<Instant>,
     cmd_rx_entries: ShmObject<AtomicUsize>,
     fd_notifier: ShmObject<AtomicUsize>,
+    cmd_doorbell: File,
     #[cfg(feature = "customer")]
     dp_cq_eventfd: async_io::Async<RawFd>,
 }
@@ -87,15 +105,20 @@ where
         }
         let mut sock = DomainSocket::bind(sock_path)?;
 
-        let req = control::Request::NewClient(hint, service, config_str.map(|s| s.to_string()));
+        let config_str = config_str.map(|s| s.to_string());
+        let req = match process_labels() {
+            Some(labels) => control::Request::NewLabeledClient(hint, service, config_str, labels),
+            None => control::Request::NewClient(hint, service, config_str),
+        };
         let buf = bincode::serialize(&req)?;
         assert!(buf.len() < MAX_MSG_LEN);
 
         let service_path = phoenix_prefix.as_ref().join(control_path);
         sock.send_to(&buf, &service_path)?;
 
-        // receive NewClient response
-        let mut buf = vec![0u8; 128];
+        // receive NewClient response, which may be an error, e.g., the subscription is not
+        // admitted
+        let mut buf = vec![0u8; 1024];
         let (_, sender) = sock.recv_from(buf.as_mut_slice())?;
         assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
         let res: control::Response = bincode::deserialize(&buf)?;
@@ -137,7 +160,7 @@ where
                 // receive file descriptors to attach to the shared memory queues
                 let (fds, cred) = sock.recv_fd()?;
                 Self::check_credential(&sock, cred)?;
-                assert_eq!(fds.len(), 9);
+                assert_eq!(fds.len(), 10);
                 let (wq_memfd, wq_empty_signal, wq_full_signal) = unsafe {
                     (
                         File::from_raw_fd(fds[0]),
@@ -155,6 +178,7 @@ where
                 let cmd_rx_notify_memfd = unsafe { File::from_raw_fd(fds[6]) };
                 let cmd_tx_notify_memfd = unsafe { File::from_raw_fd(fds[7]) };
                 let fd_notifier_memfd = unsafe { File::from_raw_fd(fds[8]) };
+                let cmd_doorbell = unsafe { File::from_raw_fd(fds[9]) };
 
                 // attach to the shared memories
                 let dp_wq = ShmSender::<WorkRequest>::open(
@@ -186,6 +210,7 @@ where
                     timer: AtomicCell::new(Instant::now()),
                     cmd_rx_entries,
                     fd_notifier,
+                    cmd_doorbell,
                     #[cfg(feature = "customer")]
                     dp_cq_eventfd,
                 })
@@ -194,6 +219,24 @@ where
         }
     }
 
+    /// The pid of the process serving this service, i.e., phoenixd.
+    #[inline]
+    pub fn peer_pid(&self) -> Option<i32> {
+        self.sock.peer_cred().ok().and_then(|cred| cred.pid)
+    }
+
+    /// Returns false if the process serving this service has exited, e.g., phoenixd has
+    /// crashed or restarted.
+    pub fn is_peer_alive(&self) -> bool {
+        match self.peer_pid() {
+            Some(pid) => {
+                let ret = unsafe { libc::kill(pid, 0) };
+                ret == 0 || std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+            }
+            None => true,
+        }
+    }
+
     #[inline]
     pub fn recv_fd(&self) -> Result<Vec<RawFd>, Error> {
         let (fds, cred) = self.sock.recv_fd()?;
@@ -215,7 +258,10 @@ where
 
     #[inline]
     pub fn send_cmd(&self, cmd: Command) -> Result<(), Error> {
-        Ok(self.cmd_tx.send(cmd)?)
+        self.cmd_tx.send(cmd)?;
+        // wake up the backend in case it is idle
+        (&self.cmd_doorbell).write_all(&1u64.to_ne_bytes())?;
+        Ok(())
     }
 
     #[inline]
@@ -246,15 +292,34 @@ where
         Ok(comp)
     }
 
+    /// This will trigger the eventfd when the queue was empty, to wake up an idle backend.
     #[inline]
     pub fn enqueue_wr_with<F: FnOnce(*mut WorkRequest, usize) -> usize>(
         &self,
         f: F,
     ) -> Result<(), Error> {
-        self.dp_wq.borrow_mut().sender_mut().send(f)?;
+        self.dp_wq.borrow_mut().send_raw(f)?;
         Ok(())
     }
 
+    /// Writes a work request as a `T` followed by `payload`, see
+    /// [`Sender::try_send_typed_with_payload`](crate::ring::Sender::try_send_typed_with_payload).
+    /// Returns false if the queue does not have room. This will trigger the eventfd as
+    /// [`enqueue_wr_with`](Self::enqueue_wr_with).
+    #[inline]
+    pub fn enqueue_wr_with_payload<T: Copy>(
+        &self,
+        wr: T,
+        payload: &[u8],
+        pad: T,
+    ) -> Result<bool, Error> {
+        let sent = self
+            .dp_wq
+            .borrow_mut()
+            .try_send_typed_with_payload(wr, payload, pad)?;
+        Ok(sent)
+    }
+
     #[inline]
     pub fn dequeue_wc_with<F: FnOnce(*const WorkCompletion, usize) -> usize>(
         &self,
@@ -264,6 +329,27 @@ where
         Ok(())
     }
 
+    /// Appends the completions to `buf`, and the payloads that follow some of them to
+    /// `payload`, see
+    /// [`Receiver::recv_typed_with_payload_into`](crate::ring::Receiver::recv_typed_with_payload_into).
+    #[inline]
+    pub fn dequeue_wcs_with_payload<T: Copy, L: Fn(&T) -> Option<usize>>(
+        &self,
+        buf: &mut Vec<T>,
+        payload: &mut Vec<u8>,
+        payload_len: L,
+    ) -> Result<usize, Error> {
+        // SAFETY: the backend writes its completions and their payloads in the slots, which the
+        // application trusts as it does the rest of the data path
+        let count = unsafe {
+            self.dp_cq
+                .borrow_mut()
+                .receiver_mut()
+                .recv_typed_with_payload_into(buf, payload, usize::MAX, payload_len)?
+        };
+        Ok(count)
+    }
+
     /// For CPU efficient scenarios.
     #[cfg(feature = "customer")]
     pub fn poll_wc_readable(&self, cx: &mut Context<'_>) -> Poll<Result<bool, Error>> {
@@ -0,0 +1,418 @@
+//! Typed channels over the shared memory rings.
+//!
+//! The rings of [`crate::ring`] hand out raw slots to a closure, and each user of them casts the
+//! slots to its messages, checks by hand that the messages fit, and loops while the ring is full.
+//! A [`ShmChannel`] does it once: a message `T` is checked at compile time to fit in a slot and
+//! to be aligned in it, and is sent and received by value.
+//!
+//! The slot type `S` defaults to `T`. The data path rings keep slots of bytes, e.g., a cache line,
+//! shared by the messages of several versions of the descriptors, and take any `T` that fits.
+//!
+//! As for the rest of the data path, the receiving side trusts the process on the other side of
+//! the ring to only write `T`s in the slots. Opening the receiving side of a ring created by
+//! another process is therefore `unsafe`.
+use std::fs::File;
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+
+use memfd::Memfd;
+
+use crate::ring::{Error, Placement, Receiver, Sender, ShmReceiver, ShmSender};
+
+/// The alignment of the slots of the rings, whose slots start on a cache line.
+const SLOT_ALIGN: usize = 64;
+
+/// The layout checks of a message `T` in a slot `S`, evaluated at compile time when a channel of
+/// them is used.
+struct Layout<T, S>(PhantomData<(T, S)>);
+
+impl<T, S> Layout<T, S> {
+    const CHECK: () = {
+        assert!(
+            size_of::<T>() <= size_of::<S>(),
+            "the message does not fit in a slot"
+        );
+        assert!(
+            align_of::<T>() <= SLOT_ALIGN && size_of::<S>() % align_of::<T>() == 0,
+            "the message is not aligned in every slot"
+        );
+    };
+}
+
+impl<S> Sender<S> {
+    /// Writes `msg` in a free slot. Returns false if the ring is full.
+    #[inline]
+    pub fn try_send_typed<T: Copy>(&mut self, msg: T) -> Result<bool, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Layout::<T, S>::CHECK;
+        let written = self.send(|ptr, count| {
+            if count == 0 {
+                return 0;
+            }
+            // SAFETY: the slot is free, and checked to hold a `T`
+            unsafe { ptr.cast::<T>().write(msg) };
+            1
+        })?;
+        Ok(written == 1)
+    }
+
+    /// Writes `msg` in a free slot and `payload` in the slots right after it, contiguous in
+    /// memory, so that the receiver finds them together, see
+    /// [`recv_typed_with_payload_into`](Receiver::recv_typed_with_payload_into). The slots left
+    /// before the end of the ring are filled with `pad` if the payload does not fit there.
+    /// Returns false if the ring does not have room.
+    #[inline]
+    pub fn try_send_typed_with_payload<T: Copy>(
+        &mut self,
+        msg: T,
+        payload: &[u8],
+        pad: T,
+    ) -> Result<bool, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Layout::<T, S>::CHECK;
+        let n = 1 + payload_slots::<S>(payload.len());
+        let written = self.send_contiguous(
+            n,
+            // SAFETY: the slot is free, and checked to hold a `T`
+            |ptr| unsafe { ptr.cast::<T>().write(pad) },
+            // SAFETY: the `n` slots are free and contiguous
+            |ptr| unsafe { write_with_payload(ptr, msg, payload) },
+        )?;
+        Ok(written > 0)
+    }
+}
+
+impl<S> ShmSender<S> {
+    /// Sends `msg` followed by `payload` as
+    /// [`Sender::try_send_typed_with_payload`], and signals the consumer if it may have seen the
+    /// ring empty.
+    #[inline]
+    pub fn try_send_typed_with_payload<T: Copy>(
+        &mut self,
+        msg: T,
+        payload: &[u8],
+        pad: T,
+    ) -> Result<bool, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Layout::<T, S>::CHECK;
+        let n = 1 + payload_slots::<S>(payload.len());
+        let written = self.send_contiguous_raw(
+            n,
+            // SAFETY: the slot is free, and checked to hold a `T`
+            |ptr| unsafe { ptr.cast::<T>().write(pad) },
+            // SAFETY: the `n` slots are free and contiguous
+            |ptr| unsafe { write_with_payload(ptr, msg, payload) },
+        )?;
+        Ok(written > 0)
+    }
+}
+
+/// The number of slots `S` taken by a payload of `len` bytes.
+#[inline]
+pub const fn payload_slots<S>(len: usize) -> usize {
+    (len + size_of::<S>() - 1) / size_of::<S>()
+}
+
+/// Writes `msg` in the slot at `ptr`, and `payload` from the next slot on.
+///
+/// # Safety
+///
+/// The slots must be writable, `1 + payload_slots::<S>(payload.len())` of them.
+#[inline]
+unsafe fn write_with_payload<T: Copy, S>(ptr: *mut S, msg: T, payload: &[u8]) {
+    ptr.cast::<T>().write(msg);
+    std::ptr::copy_nonoverlapping(payload.as_ptr(), ptr.add(1).cast::<u8>(), payload.len());
+}
+
+impl<S> Receiver<S> {
+    /// Appends up to `max` messages to `buf`, and returns the number of messages received.
+    ///
+    /// # Safety
+    ///
+    /// The slots must have been written as `T`s.
+    #[inline]
+    pub unsafe fn recv_typed_into<T: Copy>(
+        &mut self,
+        buf: &mut Vec<T>,
+        max: usize,
+    ) -> Result<usize, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Layout::<T, S>::CHECK;
+        self.recv(|ptr, count| {
+            let count = count.min(max);
+            buf.reserve(count);
+            for i in 0..count {
+                // the other side has just written the next slot on another core, ask for its
+                // line while copying this one
+                if i + 1 < count {
+                    phoenix_api::rpc::prefetch(ptr.add(i + 1));
+                }
+                buf.push(ptr.add(i).cast::<T>().read());
+            }
+            count
+        })
+    }
+
+    /// Appends up to `max` messages to `buf` as [`recv_typed_into`](Self::recv_typed_into),
+    /// where a message for which `payload_len` returns a length is followed by a payload of that
+    /// many bytes, sent by [`try_send_typed_with_payload`](Sender::try_send_typed_with_payload).
+    /// The payloads are appended to `payload` in the order of their messages. Returns the number
+    /// of messages received.
+    ///
+    /// # Safety
+    ///
+    /// The slots must have been written as `T`s, each followed by its payload if `payload_len`
+    /// tells it has one.
+    #[inline]
+    pub unsafe fn recv_typed_with_payload_into<T: Copy, L: Fn(&T) -> Option<usize>>(
+        &mut self,
+        buf: &mut Vec<T>,
+        payload: &mut Vec<u8>,
+        max: usize,
+        payload_len: L,
+    ) -> Result<usize, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Layout::<T, S>::CHECK;
+        let mut received = 0;
+        self.recv(|ptr, count| {
+            let mut i = 0;
+            while i < count && received < max {
+                let msg = ptr.add(i).cast::<T>().read();
+                let len = payload_len(&msg).unwrap_or(0);
+                let n = 1 + payload_slots::<S>(len);
+                // a message and its payload are published together
+                if i + n > count {
+                    break;
+                }
+                // the other side has just written the next message on another core, ask for its
+                // line while copying this one
+                if i + n < count {
+                    phoenix_api::rpc::prefetch(ptr.add(i + n));
+                }
+                payload.extend_from_slice(std::slice::from_raw_parts(
+                    ptr.add(i + 1).cast::<u8>(),
+                    len,
+                ));
+                buf.push(msg);
+                received += 1;
+                i += n;
+            }
+            i
+        })?;
+        Ok(received)
+    }
+}
+
+/// Builds the sides of a typed channel.
+#[derive(Debug, Clone, Copy)]
+pub struct ShmChannel<T: Copy, S = T> {
+    capacity: usize,
+    placement: Placement,
+    signal: bool,
+    _marker: PhantomData<(T, S)>,
+}
+
+impl<T: Copy, S> Default for ShmChannel<T, S> {
+    fn default() -> Self {
+        ShmChannel {
+            capacity: Self::DEFAULT_CAPACITY,
+            placement: Placement::CacheLine,
+            signal: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Copy, S> ShmChannel<T, S> {
+    pub const DEFAULT_CAPACITY: usize = 32;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of slots of the ring.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Where to place the indices of the ring.
+    pub fn placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Whether the sender writes the eventfd of the ring when it sends to an empty ring, to wake
+    /// up a receiver waiting on it.
+    pub fn signal(mut self, signal: bool) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Creates a ring and its sending side. The other process opens the receiving side from the
+    /// memfd and the eventfds.
+    pub fn sender(self) -> Result<ShmChannelSender<T, S>, Error> {
+        let inner = ShmSender::with_placement(self.capacity, self.placement)?;
+        Ok(ShmChannelSender::new(inner, self.signal))
+    }
+
+    /// Creates a ring and its receiving side. The other process opens the sending side from the
+    /// memfd and the eventfds.
+    pub fn receiver(self) -> Result<ShmChannelReceiver<T, S>, Error> {
+        let inner = ShmReceiver::with_placement(self.capacity, self.placement)?;
+        Ok(ShmChannelReceiver::new(inner))
+    }
+
+    /// Opens the sending side of a ring created by another process.
+    pub fn open_sender(
+        self,
+        memfd: File,
+        empty_signal: File,
+        full_signal: File,
+    ) -> Result<ShmChannelSender<T, S>, Error> {
+        let inner = ShmSender::open(self.capacity, memfd, empty_signal, full_signal)?;
+        Ok(ShmChannelSender::new(inner, self.signal))
+    }
+
+    /// Opens the receiving side of a ring created by another process.
+    ///
+    /// # Safety
+    ///
+    /// The other process must only write `T`s in the slots.
+    pub unsafe fn open_receiver(
+        self,
+        memfd: File,
+        empty_signal: File,
+        full_signal: File,
+    ) -> Result<ShmChannelReceiver<T, S>, Error> {
+        let inner = ShmReceiver::open(self.capacity, memfd, empty_signal, full_signal)?;
+        Ok(ShmChannelReceiver::new(inner))
+    }
+}
+
+/// The sending side of a typed channel.
+pub struct ShmChannelSender<T: Copy, S = T> {
+    inner: ShmSender<S>,
+    signal: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy, S> ShmChannelSender<T, S> {
+    /// Types the sending side of a ring.
+    pub fn new(inner: ShmSender<S>, signal: bool) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Layout::<T, S>::CHECK;
+        ShmChannelSender {
+            inner,
+            signal,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sends `msg` unless the ring is full. Returns whether it was sent.
+    #[inline]
+    pub fn try_send(&mut self, msg: T) -> Result<bool, Error> {
+        if !self.signal {
+            return self.inner.sender_mut().try_send_typed(msg);
+        }
+        let written = self.inner.send_raw(|ptr, count| {
+            if count == 0 {
+                return 0;
+            }
+            // SAFETY: the slot is free, and checked to hold a `T`
+            unsafe { ptr.cast::<T>().write(msg) };
+            1
+        })?;
+        Ok(written == 1)
+    }
+
+    /// Sends `msg`, spinning while the ring is full.
+    #[inline]
+    pub fn send(&mut self, msg: T) -> Result<(), Error> {
+        while !self.try_send(msg)? {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// The number of free slots.
+    #[inline]
+    pub fn write_count(&mut self) -> Result<usize, Error> {
+        self.inner.sender_mut().write_count()
+    }
+
+    #[inline]
+    pub fn memfd(&self) -> &Memfd {
+        self.inner.memfd()
+    }
+
+    #[inline]
+    pub fn empty_signal(&self) -> &File {
+        self.inner.empty_signal()
+    }
+
+    #[inline]
+    pub fn full_signal(&self) -> &File {
+        self.inner.full_signal()
+    }
+}
+
+/// The receiving side of a typed channel.
+pub struct ShmChannelReceiver<T: Copy, S = T> {
+    inner: ShmReceiver<S>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy, S> ShmChannelReceiver<T, S> {
+    fn new(inner: ShmReceiver<S>) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Layout::<T, S>::CHECK;
+        ShmChannelReceiver {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Receives a message, `None` if the ring is empty.
+    #[inline]
+    pub fn try_recv(&mut self) -> Result<Option<T>, Error> {
+        let mut msg = None;
+        self.inner.receiver_mut().recv(|ptr, count| {
+            if count == 0 {
+                return 0;
+            }
+            // SAFETY: the slot is written, and the other side only writes `T`s
+            msg = Some(unsafe { ptr.cast::<T>().read() });
+            1
+        })?;
+        Ok(msg)
+    }
+
+    /// Appends up to `max` messages to `buf`, and returns the number of messages received.
+    #[inline]
+    pub fn recv_into(&mut self, buf: &mut Vec<T>, max: usize) -> Result<usize, Error> {
+        // SAFETY: the other side only writes `T`s
+        unsafe { self.inner.receiver_mut().recv_typed_into(buf, max) }
+    }
+
+    /// The number of messages to read.
+    #[inline]
+    pub fn read_count(&mut self) -> Result<usize, Error> {
+        self.inner.receiver_mut().read_count()
+    }
+
+    #[inline]
+    pub fn memfd(&self) -> &Memfd {
+        self.inner.memfd()
+    }
+
+    #[inline]
+    pub fn empty_signal(&self) -> &File {
+        self.inner.empty_signal()
+    }
+
+    #[inline]
+    pub fn full_signal(&self) -> &File {
+        self.inner.full_signal()
+    }
+}
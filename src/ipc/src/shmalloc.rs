@@ -0,0 +1,646 @@
+//! A heap in shared memory, for the structures several processes share, e.g., counters, caches
+//! or waker tables, so each of them does not lay out a mapping of its own.
+//!
+//! The heap is a memfd mapped by each process, whatever its address. Allocations are therefore
+//! named by their offsets in the heap, [`ShmBox`] and [`ShmSlice`], which stay valid across the
+//! processes, and are only turned into references through the heap. The allocator takes a
+//! process-shared robust mutex. A process dying with the mutex held does not wedge the others:
+//! the next one to take the mutex finds the owner gone and recovers it. Each update of the
+//! allocator state is published by a single store at its end, so a process dying in the middle
+//! of one at most leaks the block it was taking or giving back.
+//!
+//! The blocks come in size classes of powers of two, from 16 bytes on. A freed block goes back
+//! to the free list of its class, for the next allocation of that class, and the blocks are
+//! neither split nor coalesced. It suits a few long-lived structures of similar sizes, not a
+//! general-purpose heap.
+//!
+//! The layout of the shared memory:
+//!
+//! | offset      | content                                                  |
+//! |-------------|----------------------------------------------------------|
+//! | 0           | header: mutex, allocator state, written by the creator  |
+//! | HEAP_START  | blocks, each a 16-byte block header followed by the data |
+use std::cell::UnsafeCell;
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::ptr;
+use std::sync::atomic::{self, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use memfd::{Memfd, MemfdOptions};
+use memmap2::{MmapOptions, MmapRaw};
+use thiserror::Error;
+use uuid::Uuid;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"PHXHEAP1");
+const BLOCK_MAGIC: u32 = u32::from_le_bytes(*b"PHXB");
+
+/// The alignment of the data of the blocks, and the size of the block header.
+const BLOCK_ALIGN: usize = 16;
+const MIN_CLASS_SHIFT: u32 = 4;
+const NUM_CLASSES: usize = 40;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Memfd: {0}.")]
+    Memfd(#[from] memfd::Error),
+    #[error("IO: {0}.")]
+    Io(#[from] io::Error),
+    #[error("Buffer too small.")]
+    BufTooSmall,
+    #[error("Not a shared heap.")]
+    Mismatch,
+    #[error("Out of shared memory for {0} bytes.")]
+    OutOfMemory(usize),
+    #[error("Alignment of {0} bytes is not supported.")]
+    Alignment(usize),
+    #[error("Mutex: {0}.")]
+    Mutex(io::Error),
+}
+
+/// Types that can be placed in the shared heap and accessed by several processes at once.
+///
+/// # Safety
+///
+/// The type must be valid for any bit pattern, including all zeros, be safe to access
+/// concurrently through shared references, e.g., atomics, and must not hold pointers or any
+/// resource of a process, as the other processes map the heap at other addresses. It is never
+/// dropped.
+pub unsafe trait ShmSafe: Sync + Send {}
+
+macro_rules! impl_shm_safe {
+    ($($t:ty),*) => {
+        $(unsafe impl ShmSafe for $t {})*
+    };
+}
+
+impl_shm_safe!(
+    atomic::AtomicBool,
+    atomic::AtomicU8,
+    atomic::AtomicU16,
+    atomic::AtomicU32,
+    atomic::AtomicU64,
+    atomic::AtomicUsize,
+    atomic::AtomicI8,
+    atomic::AtomicI16,
+    atomic::AtomicI32,
+    atomic::AtomicI64,
+    atomic::AtomicIsize
+);
+
+unsafe impl<T: ShmSafe, const N: usize> ShmSafe for [T; N] {}
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    len: u64,
+    mutex: UnsafeCell<libc::pthread_mutex_t>,
+    /// The times the mutex was recovered from a process that died holding it
+    recoveries: AtomicU64,
+    /// The offset of the first block never allocated, guarded by the mutex
+    top: UnsafeCell<u64>,
+    /// The offset of the first free block of each class, 0 if none, guarded by the mutex
+    free: UnsafeCell<[u64; NUM_CLASSES]>,
+}
+
+const HEAP_START: usize = (size_of::<Header>() + BLOCK_ALIGN - 1) / BLOCK_ALIGN * BLOCK_ALIGN;
+
+#[repr(C)]
+struct BlockHeader {
+    magic: u32,
+    class: u32,
+    /// The offset of the next free block of the class while the block is free
+    next: u64,
+}
+
+const _: () = assert!(size_of::<BlockHeader>() == BLOCK_ALIGN);
+
+fn class_of(size: usize) -> Option<usize> {
+    let block = size.checked_add(BLOCK_ALIGN)?.max(1 << MIN_CLASS_SHIFT);
+    let class = block.checked_next_power_of_two()?.trailing_zeros() - MIN_CLASS_SHIFT;
+    ((class as usize) < NUM_CLASSES).then_some(class as usize)
+}
+
+#[inline]
+fn class_size(class: usize) -> usize {
+    1 << (class as u32 + MIN_CLASS_SHIFT)
+}
+
+struct Inner {
+    mmap: MmapRaw,
+    memfd: Memfd,
+}
+
+/// A heap in shared memory. The clones share the mapping.
+#[derive(Clone)]
+pub struct ShmHeap {
+    inner: Arc<Inner>,
+}
+
+// SAFETY: the state of the heap is guarded by its process-shared mutex
+unsafe impl Send for ShmHeap {}
+unsafe impl Sync for ShmHeap {}
+
+/// An allocation of a `T` in a [`ShmHeap`], valid in every process that maps the heap.
+#[derive(Debug)]
+pub struct ShmBox<T> {
+    offset: u64,
+    _marker: PhantomData<T>,
+}
+
+/// An allocation of `len` `T`s in a [`ShmHeap`], valid in every process that maps the heap.
+#[derive(Debug)]
+pub struct ShmSlice<T> {
+    offset: u64,
+    len: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for ShmBox<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ShmBox<T> {}
+
+impl<T> Clone for ShmSlice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ShmSlice<T> {}
+
+impl<T> ShmBox<T> {
+    /// The offset of the allocation in the heap, to pass it to another process.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Names the allocation at `offset`, e.g., received from another process. The heap checks
+    /// it when accessed.
+    #[inline]
+    pub fn from_offset(offset: u64) -> Self {
+        ShmBox {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> ShmSlice<T> {
+    /// The offset of the allocation in the heap, to pass it to another process.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Names the allocation of `len` `T`s at `offset`, e.g., received from another process. The
+    /// heap checks it when accessed.
+    #[inline]
+    pub fn from_offset(offset: u64, len: usize) -> Self {
+        ShmSlice {
+            offset,
+            len: len as u64,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Holds the mutex of the heap, and releases it when dropped.
+struct Guard<'a> {
+    header: &'a Header,
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        // SAFETY: the mutex is initialized and held by this thread
+        unsafe { libc::pthread_mutex_unlock(self.header.mutex.get()) };
+    }
+}
+
+impl ShmHeap {
+    /// Creates a heap of about `nbytes` bytes, headers included.
+    pub fn new(nbytes: usize) -> Result<Self, Error> {
+        if nbytes <= HEAP_START {
+            return Err(Error::BufTooSmall);
+        }
+        let opts = MemfdOptions::default()
+            .allow_sealing(true)
+            .close_on_exec(false);
+        let name = format!("shmheap-{}", Uuid::new_v4());
+        let memfd = opts.create(name)?;
+        memfd.as_file().set_len(nbytes as u64)?;
+
+        let mmap = MmapOptions::new().map_raw(memfd.as_file())?;
+        let header = mmap.as_mut_ptr().cast::<Header>();
+        // SAFETY: the mapping is page aligned and large enough, and no one else maps it yet. The
+        // memfd is zero-filled, so are the fields not written here.
+        unsafe {
+            ptr::addr_of_mut!((*header).len).write(nbytes as u64);
+            ptr::addr_of_mut!((*header).top).write(UnsafeCell::new(HEAP_START as u64));
+            init_robust_mutex((*header).mutex.get())?;
+            // the magic goes last, a heap without it is not opened
+            ptr::addr_of_mut!((*header).magic).write(MAGIC);
+        }
+        Ok(ShmHeap {
+            inner: Arc::new(Inner { mmap, memfd }),
+        })
+    }
+
+    /// Maps a heap created by another process.
+    pub fn open(file: File) -> Result<Self, Error> {
+        let memfd = Memfd::try_from_file(file).map_err(|_| io::Error::last_os_error())?;
+        let mmap = MmapOptions::new().map_raw(memfd.as_file())?;
+        if mmap.len() <= HEAP_START {
+            return Err(Error::BufTooSmall);
+        }
+        let heap = ShmHeap {
+            inner: Arc::new(Inner { mmap, memfd }),
+        };
+        let header = heap.header();
+        if header.magic != MAGIC {
+            return Err(Error::Mismatch);
+        }
+        if heap.inner.mmap.len() < header.len as usize {
+            return Err(Error::BufTooSmall);
+        }
+        Ok(heap)
+    }
+
+    pub fn memfd(&self) -> &Memfd {
+        &self.inner.memfd
+    }
+
+    /// The times the mutex of the heap was recovered from a process that died holding it.
+    pub fn recoveries(&self) -> u64 {
+        self.header().recoveries.load(Ordering::Relaxed)
+    }
+
+    /// Allocates a `T`, zeroed.
+    pub fn alloc<T: ShmSafe>(&self) -> Result<ShmBox<T>, Error> {
+        let offset = self.alloc_zeroed(size_of::<T>(), align_of::<T>())?;
+        Ok(ShmBox::from_offset(offset))
+    }
+
+    /// Allocates `len` `T`s, zeroed.
+    pub fn alloc_slice<T: ShmSafe>(&self, len: usize) -> Result<ShmSlice<T>, Error> {
+        let size = size_of::<T>()
+            .checked_mul(len)
+            .ok_or(Error::OutOfMemory(usize::MAX))?;
+        let offset = self.alloc_zeroed(size, align_of::<T>())?;
+        Ok(ShmSlice::from_offset(offset, len))
+    }
+
+    /// The `T` of an allocation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `b` is not an allocation of the heap large enough for a `T`.
+    #[inline]
+    pub fn get<T: ShmSafe>(&self, b: ShmBox<T>) -> &T {
+        let ptr = self.data(b.offset, size_of::<T>());
+        // SAFETY: the block is within the mapping, aligned, large enough, and `T` is valid for
+        // any bit pattern and only accessed through shared references
+        unsafe { &*ptr.cast::<T>() }
+    }
+
+    /// The `T`s of an allocation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not an allocation of the heap large enough for its `T`s.
+    #[inline]
+    pub fn get_slice<T: ShmSafe>(&self, s: ShmSlice<T>) -> &[T] {
+        let ptr = self.data(s.offset, size_of::<T>() * s.len());
+        // SAFETY: as in `get`
+        unsafe { std::slice::from_raw_parts(ptr.cast::<T>(), s.len()) }
+    }
+
+    /// Gives an allocation back to the heap.
+    ///
+    /// # Safety
+    ///
+    /// No process may access the allocation afterwards, through the references it got from
+    /// [`get`](Self::get) or otherwise.
+    pub unsafe fn free<T: ShmSafe>(&self, b: ShmBox<T>) -> Result<(), Error> {
+        self.free_block(b.offset, size_of::<T>())
+    }
+
+    /// Gives an allocation of `T`s back to the heap.
+    ///
+    /// # Safety
+    ///
+    /// As in [`free`](Self::free).
+    pub unsafe fn free_slice<T: ShmSafe>(&self, s: ShmSlice<T>) -> Result<(), Error> {
+        self.free_block(s.offset, size_of::<T>() * s.len())
+    }
+
+    #[inline]
+    fn header(&self) -> &Header {
+        // SAFETY: the mapping starts with the header, checked or written when mapped
+        unsafe { &*self.inner.mmap.as_ptr().cast::<Header>() }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.header().len as usize
+    }
+
+    #[inline]
+    fn block(&self, offset: usize) -> *mut BlockHeader {
+        // SAFETY: the callers check that the block is within the mapping
+        unsafe {
+            self.inner
+                .mmap
+                .as_mut_ptr()
+                .add(offset)
+                .cast::<BlockHeader>()
+        }
+    }
+
+    /// The data of the allocation at `offset`, checking that it is one of at least `size` bytes.
+    fn data(&self, offset: u64, size: usize) -> *mut u8 {
+        let offset = offset as usize;
+        assert!(
+            offset >= HEAP_START + BLOCK_ALIGN
+                && offset % BLOCK_ALIGN == 0
+                && offset
+                    .checked_add(size)
+                    .map_or(false, |end| end <= self.len()),
+            "offset {} is not an allocation of {} bytes in the heap",
+            offset,
+            size
+        );
+        // SAFETY: the block header is within the mapping
+        let block = unsafe { &*self.block(offset - BLOCK_ALIGN) };
+        assert!(
+            block.magic == BLOCK_MAGIC
+                && (block.class as usize) < NUM_CLASSES
+                && class_size(block.class as usize) >= size + BLOCK_ALIGN,
+            "offset {} is not an allocation of {} bytes in the heap",
+            offset,
+            size
+        );
+        // SAFETY: checked above
+        unsafe { self.inner.mmap.as_mut_ptr().add(offset) }
+    }
+
+    fn lock(&self) -> Result<Guard<'_>, Error> {
+        let header = self.header();
+        // SAFETY: the mutex is initialized by the creator of the heap
+        match unsafe { libc::pthread_mutex_lock(header.mutex.get()) } {
+            0 => {}
+            libc::EOWNERDEAD => {
+                // the state is consistent, the process only died before or after its store
+                // SAFETY: the mutex is held by this thread
+                unsafe { libc::pthread_mutex_consistent(header.mutex.get()) };
+                header.recoveries.fetch_add(1, Ordering::Relaxed);
+            }
+            err => return Err(Error::Mutex(io::Error::from_raw_os_error(err))),
+        }
+        Ok(Guard { header })
+    }
+
+    fn alloc_zeroed(&self, size: usize, align: usize) -> Result<u64, Error> {
+        if align > BLOCK_ALIGN {
+            return Err(Error::Alignment(align));
+        }
+        let class = class_of(size).ok_or(Error::OutOfMemory(size))?;
+        let block_size = class_size(class);
+
+        let guard = self.lock()?;
+        // SAFETY: the state is guarded by the mutex held
+        let free = unsafe { &mut *guard.header.free.get() };
+        let top = unsafe { &mut *guard.header.top.get() };
+        let offset = if free[class] != 0 {
+            let offset = free[class] as usize;
+            // SAFETY: the free lists only hold blocks of the heap
+            let next = unsafe { (*self.block(offset)).next };
+            free[class] = next;
+            offset
+        } else {
+            let offset = *top as usize;
+            if block_size > self.len() - offset {
+                return Err(Error::OutOfMemory(size));
+            }
+            // SAFETY: the block is within the mapping and never allocated
+            unsafe {
+                self.block(offset).write(BlockHeader {
+                    magic: BLOCK_MAGIC,
+                    class: class as u32,
+                    next: 0,
+                })
+            };
+            *top = (offset + block_size) as u64;
+            offset
+        };
+        drop(guard);
+
+        // SAFETY: the block is taken by this allocation alone
+        unsafe {
+            self.inner
+                .mmap
+                .as_mut_ptr()
+                .add(offset + BLOCK_ALIGN)
+                .write_bytes(0, block_size - BLOCK_ALIGN)
+        };
+        Ok((offset + BLOCK_ALIGN) as u64)
+    }
+
+    fn free_block(&self, offset: u64, size: usize) -> Result<(), Error> {
+        self.data(offset, size);
+        let offset = offset as usize - BLOCK_ALIGN;
+        let guard = self.lock()?;
+        // SAFETY: the state is guarded by the mutex held, and the block is of the heap
+        unsafe {
+            let free = &mut *guard.header.free.get();
+            let block = self.block(offset);
+            let class = (*block).class as usize;
+            (*block).next = free[class];
+            free[class] = offset as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Initializes a process-shared robust mutex in place.
+unsafe fn init_robust_mutex(mutex: *mut libc::pthread_mutex_t) -> Result<(), Error> {
+    let check = |ret: libc::c_int| match ret {
+        0 => Ok(()),
+        err => Err(Error::Mutex(io::Error::from_raw_os_error(err))),
+    };
+    let mut attr = std::mem::MaybeUninit::<libc::pthread_mutexattr_t>::uninit();
+    check(libc::pthread_mutexattr_init(attr.as_mut_ptr()))?;
+    let ret = (|| {
+        check(libc::pthread_mutexattr_setpshared(
+            attr.as_mut_ptr(),
+            libc::PTHREAD_PROCESS_SHARED,
+        ))?;
+        check(libc::pthread_mutexattr_setrobust(
+            attr.as_mut_ptr(),
+            libc::PTHREAD_MUTEX_ROBUST,
+        ))?;
+        check(libc::pthread_mutex_init(mutex, attr.as_ptr()))
+    })();
+    libc::pthread_mutexattr_destroy(attr.as_mut_ptr());
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(align(32))]
+    struct Aligned(AtomicU64);
+
+    unsafe impl ShmSafe for Aligned {}
+
+    /// Maps the heap once more, as another process would.
+    fn reopen(heap: &ShmHeap) -> ShmHeap {
+        ShmHeap::open(heap.memfd().as_file().try_clone().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn size_classes() {
+        assert_eq!(class_of(0), Some(0));
+        assert_eq!(class_of(1), Some(1));
+        assert_eq!(class_of(16), Some(1));
+        assert_eq!(class_of(17), Some(2));
+        assert_eq!(class_of(4096 - BLOCK_ALIGN), Some(8));
+        assert_eq!(class_of(4096), Some(9));
+        assert_eq!(class_size(9), 8192);
+        assert_eq!(class_of(usize::MAX), None);
+        assert_eq!(
+            class_of(class_size(NUM_CLASSES - 1) - BLOCK_ALIGN),
+            Some(39)
+        );
+        assert_eq!(class_of(class_size(NUM_CLASSES - 1)), None);
+    }
+
+    #[test]
+    fn shared_across_mappings() {
+        let heap = ShmHeap::new(1 << 16).unwrap();
+        let counter = heap.alloc::<AtomicU64>().unwrap();
+        let array = heap.alloc_slice::<atomic::AtomicU32>(10).unwrap();
+        assert_eq!(array.len(), 10);
+        assert_eq!(heap.get(counter).load(Ordering::Relaxed), 0);
+        assert!(heap
+            .get_slice(array)
+            .iter()
+            .all(|x| x.load(Ordering::Relaxed) == 0));
+        heap.get(counter).store(42, Ordering::Relaxed);
+        heap.get_slice(array)[9].store(7, Ordering::Relaxed);
+
+        // the other mapping names the allocations by their offsets
+        let other = reopen(&heap);
+        let counter = ShmBox::<AtomicU64>::from_offset(counter.offset());
+        let array = ShmSlice::<atomic::AtomicU32>::from_offset(array.offset(), array.len());
+        assert_eq!(other.get(counter).load(Ordering::Relaxed), 42);
+        assert_eq!(other.get_slice(array)[9].load(Ordering::Relaxed), 7);
+        other.get(counter).fetch_add(1, Ordering::Relaxed);
+        assert_eq!(heap.get(counter).load(Ordering::Relaxed), 43);
+
+        // and allocates from the same heap
+        let b = other.alloc::<AtomicU64>().unwrap();
+        assert_ne!(b.offset(), counter.offset());
+    }
+
+    #[test]
+    fn reuse_freed() {
+        let heap = ShmHeap::new(1 << 16).unwrap();
+        let a = heap.alloc::<AtomicU64>().unwrap();
+        let b = heap.alloc::<AtomicU64>().unwrap();
+        let c = heap.alloc_slice::<AtomicU64>(100).unwrap();
+        assert_eq!(a.offset() as usize % BLOCK_ALIGN, 0);
+        assert_eq!(
+            b.offset() - a.offset(),
+            class_size(class_of(8).unwrap()) as u64
+        );
+        heap.get(a).store(1, Ordering::Relaxed);
+
+        unsafe { heap.free(a).unwrap() };
+        unsafe { heap.free_slice(c).unwrap() };
+        // the blocks go back to the free lists of their classes, and are zeroed again
+        let d = heap.alloc::<atomic::AtomicU32>().unwrap();
+        assert_eq!(d.offset(), a.offset());
+        assert_eq!(heap.get(d).load(Ordering::Relaxed), 0);
+        let e = heap.alloc_slice::<AtomicU64>(90).unwrap();
+        assert_eq!(e.offset(), c.offset());
+        let f = heap.alloc::<AtomicU64>().unwrap();
+        assert!(f.offset() > c.offset());
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let heap = ShmHeap::new(HEAP_START + 4 * 32).unwrap();
+        for _ in 0..4 {
+            heap.alloc::<AtomicU64>().unwrap();
+        }
+        assert!(matches!(
+            heap.alloc::<AtomicU64>(),
+            Err(Error::OutOfMemory(8))
+        ));
+        assert!(matches!(
+            heap.alloc_slice::<AtomicU64>(usize::MAX),
+            Err(Error::OutOfMemory(_))
+        ));
+        assert!(matches!(heap.alloc::<Aligned>(), Err(Error::Alignment(32))));
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert!(matches!(ShmHeap::new(HEAP_START), Err(Error::BufTooSmall)));
+        let memfd = MemfdOptions::default().create("not-a-heap").unwrap();
+        memfd.as_file().set_len(4096).unwrap();
+        assert!(matches!(
+            ShmHeap::open(memfd.into_file()),
+            Err(Error::Mismatch)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an allocation")]
+    fn reject_foreign_offset() {
+        let heap = ShmHeap::new(1 << 16).unwrap();
+        let b = heap.alloc::<AtomicU64>().unwrap();
+        heap.get(ShmBox::<AtomicU64>::from_offset(b.offset() + 8));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an allocation")]
+    fn reject_larger_type() {
+        let heap = ShmHeap::new(1 << 16).unwrap();
+        let b = heap.alloc::<AtomicU64>().unwrap();
+        heap.get(ShmBox::<[AtomicU64; 4]>::from_offset(b.offset()));
+    }
+
+    #[test]
+    fn recover_dead_owner() {
+        let heap = ShmHeap::new(1 << 16).unwrap();
+        let other = reopen(&heap);
+        // a thread exiting with the mutex held, as would a process dying
+        std::thread::spawn(move || std::mem::forget(other.lock().unwrap()))
+            .join()
+            .unwrap();
+        assert_eq!(heap.recoveries(), 0);
+        heap.alloc::<AtomicU64>().unwrap();
+        assert_eq!(heap.recoveries(), 1);
+        heap.alloc::<AtomicU64>().unwrap();
+        assert_eq!(heap.recoveries(), 1);
+    }
+}
@@ -43,6 +43,8 @@ pub struct DomainSocket {
     local_cred: UCred,
     // only exists when this socket is not a listener
     peer_cred: Option<UCred>,
+    // whether the socket file is ours to remove
+    owns_path: bool,
 }
 
 impl AsRef<UnixDatagram> for DomainSocket {
@@ -61,6 +63,9 @@ impl Deref for DomainSocket {
 
 impl Drop for DomainSocket {
     fn drop(&mut self) {
+        if !self.owns_path {
+            return;
+        }
         if let Ok(local_addr) = self.sock.local_addr() {
             if let Some(path) = local_addr.as_pathname() {
                 let _ = std::fs::remove_file(path);
@@ -87,9 +92,29 @@ impl DomainSocket {
             sock,
             local_cred: cred,
             peer_cred: None,
+            owns_path: true,
+        })
+    }
+
+    /// Wraps a socket bound by someone else, e.g., passed in by the service manager or
+    /// another process. The socket file is only removed on drop if `owns_path` is true.
+    pub fn from_bound(sock: UnixDatagram, owns_path: bool) -> io::Result<DomainSocket> {
+        sock.set_passcred(true)?;
+        let cred = get_ucred();
+        Ok(DomainSocket {
+            sock,
+            local_cred: cred,
+            peer_cred: None,
+            owns_path,
         })
     }
 
+    /// Leaves the socket file in place when dropped, e.g., after the socket is handed over to
+    /// another process.
+    pub fn release_path(&mut self) {
+        self.owns_path = false;
+    }
+
     pub fn connect<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
         let path = path.as_ref();
         self.sock.connect(path)?;
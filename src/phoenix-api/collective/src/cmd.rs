@@ -0,0 +1,82 @@
+//! collective control path commands.
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+use phoenix_api::buf::Range;
+use phoenix_api::Handle;
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+/// How the data of an operation moves between the members of a group.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    /// Each member exchanges data with its two neighbours on a ring. Uses the bandwidth of every
+    /// link, at the cost of a latency linear in the size of the group.
+    #[default]
+    Ring,
+    /// The data flows along a binary tree. The latency is logarithmic in the size of the group.
+    Tree,
+}
+
+/// The type of the elements of a buffer to reduce.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DataType {
+    I32,
+    I64,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl DataType {
+    /// The size of an element in bytes.
+    #[inline]
+    pub const fn size(&self) -> usize {
+        match self {
+            DataType::I32 | DataType::U32 | DataType::F32 => 4,
+            DataType::I64 | DataType::U64 | DataType::F64 => 8,
+        }
+    }
+}
+
+/// How the elements of all members are combined.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReduceOp {
+    Sum,
+    Prod,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+    /// Joins the group of `members` as the member `rank`. Every member has to create the group
+    /// with the same addresses, the engine of each member listens on its own address.
+    CreateGroup(Vec<SocketAddr>, usize),
+    DestroyGroup(Handle),
+    /// Creates a buffer of the given size in bytes shared with the engine.
+    RegBuffer(usize),
+    DeregBuffer(Handle),
+    /// (group, buffer, range in the buffer, root rank, algorithm)
+    Broadcast(Handle, Handle, Range, usize, Algorithm),
+    /// (group, buffer, range in the buffer, element type, operator, algorithm)
+    AllReduce(Handle, Handle, Range, DataType, ReduceOp, Algorithm),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CompletionKind {
+    // group handle, size of the group
+    CreateGroup(Handle, usize),
+    DestroyGroup,
+    // buffer handle, file_len
+    RegBuffer(Handle, usize),
+    DeregBuffer,
+    Broadcast,
+    AllReduce,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Completion(pub IResult<CompletionKind>);
@@ -0,0 +1,3 @@
+pub mod cmd;
+pub mod control_plane;
+pub mod dp;
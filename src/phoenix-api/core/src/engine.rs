@@ -39,6 +39,35 @@ impl From<CustomSchedulingSpec> for SchedulingMode {
     }
 }
 
+/// The class of service of a subscription, which decides how its engines are run.
+///
+/// A class takes precedence over the scheduling mode of the [`SchedulingHint`] and over the
+/// scheduling policies of phoenixd. It only applies to the engines whose mode is not specified
+/// by their plugin. The class of a subscription can be changed while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SchedulingClass {
+    /// Each scheduling group runs on a runtime of its own, which keeps spinning and takes a whole
+    /// core. A subscription is admitted only if phoenixd has cores left for its groups.
+    LatencyCritical,
+    /// The scheduling groups share runtimes with the groups of other throughput subscriptions.
+    /// The runtimes keep spinning.
+    Throughput,
+    /// The scheduling groups share runtimes with the groups of other background subscriptions.
+    /// The runtimes sleep when they have no work, so a request may wait for up to the latency
+    /// budget of the hint, or that of phoenixd if the hint has none.
+    Background,
+}
+
+impl SchedulingClass {
+    /// The scheduling mode the engines of the class are submitted with.
+    pub fn mode(self) -> SchedulingMode {
+        match self {
+            SchedulingClass::LatencyCritical => SchedulingMode::Dedicate,
+            SchedulingClass::Throughput | SchedulingClass::Background => SchedulingMode::Compact,
+        }
+    }
+}
+
 /// The user submits this hint to the backend. The content of this hint is subject to change.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SchedulingHint {
@@ -46,4 +75,11 @@ pub struct SchedulingHint {
     pub mode: SchedulingMode,
     /// The numa node the user thread affinites to.
     pub numa_node_affinity: Option<u8>,
+    /// The extra latency in microseconds the user tolerates for the engines to sleep when they
+    /// have no work, instead of spinning. Only honored in the `Compact` mode and the `Background`
+    /// class. `None` keeps the engines spinning, except in the `Background` class.
+    pub latency_budget_us: Option<u32>,
+    /// The class of service, which takes precedence over `mode`. `None` leaves the scheduling to
+    /// `mode`.
+    pub class: Option<SchedulingClass>,
 }
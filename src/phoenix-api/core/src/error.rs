@@ -1,8 +1,241 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Debug, Clone, Error, Serialize, Deserialize)]
-pub enum Error {
-    #[error("{0}")]
-    Generic(String),
+/// The category of an [`Error`].
+///
+/// The numeric values are part of the control plane protocol. They are never reused or
+/// renumbered, new codes are only appended. A code unknown to the receiver, e.g., from a newer
+/// phoenixd, is read as [`ErrorCode::Unknown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u16", into = "u16")]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// The error does not fall in any other category.
+    Unknown = 0,
+    /// The request is malformed or its arguments are invalid. Retrying does not help.
+    InvalidArgument = 1,
+    /// The resource the request refers to does not exist.
+    NotFound = 2,
+    /// The resource the request creates already exists.
+    AlreadyExists = 3,
+    /// The caller is not allowed to make the request.
+    PermissionDenied = 4,
+    /// A resource, e.g., the shared memory heap, is used up. The request may succeed later.
+    ResourceExhausted = 5,
+    /// The service is temporarily unable to serve the request, e.g., it is being upgraded or
+    /// the connection is down. The request may succeed later.
+    Unavailable = 6,
+    /// The request did not complete in time. The request may succeed later.
+    Timeout = 7,
+    /// The request is not supported by the service.
+    Unimplemented = 8,
+    /// An invariant of the service is broken.
+    Internal = 9,
+}
+
+impl ErrorCode {
+    /// Whether a request that failed with this code may succeed if it is made again unchanged.
+    #[inline]
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::ResourceExhausted | ErrorCode::Unavailable | ErrorCode::Timeout
+        )
+    }
+
+    /// A short description of the code.
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorCode::Unknown => "Unknown error",
+            ErrorCode::InvalidArgument => "Invalid argument",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::AlreadyExists => "Already exists",
+            ErrorCode::PermissionDenied => "Permission denied",
+            ErrorCode::ResourceExhausted => "Resource exhausted",
+            ErrorCode::Unavailable => "Unavailable",
+            ErrorCode::Timeout => "Timeout",
+            ErrorCode::Unimplemented => "Unimplemented",
+            ErrorCode::Internal => "Internal error",
+        }
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => ErrorCode::InvalidArgument,
+            2 => ErrorCode::NotFound,
+            3 => ErrorCode::AlreadyExists,
+            4 => ErrorCode::PermissionDenied,
+            5 => ErrorCode::ResourceExhausted,
+            6 => ErrorCode::Unavailable,
+            7 => ErrorCode::Timeout,
+            8 => ErrorCode::Unimplemented,
+            9 => ErrorCode::Internal,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    #[inline]
+    fn from(code: ErrorCode) -> Self {
+        code as u16
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// The error returned by the control plane of the services.
+///
+/// Besides its message, an error carries a stable [`ErrorCode`] to make decisions on, and the
+/// errors that caused it, which are kept through serialization as errors of their own.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[error("{code}: {message}")]
+pub struct Error {
+    code: ErrorCode,
+    message: String,
+    #[source]
+    source: Option<Box<Error>>,
+}
+
+impl Error {
+    /// Creates an error without a source.
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Error {
+            code,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Creates an error from another error, keeping the chain of its sources.
+    ///
+    /// The sources are converted to errors of code [`ErrorCode::Unknown`], unless they are
+    /// errors of this type.
+    pub fn from_error(code: ErrorCode, error: &(dyn std::error::Error + 'static)) -> Self {
+        Error {
+            code,
+            message: error.to_string(),
+            source: error.source().map(|e| Box::new(Self::from_source(e))),
+        }
+    }
+
+    fn from_source(error: &(dyn std::error::Error + 'static)) -> Self {
+        match error.downcast_ref::<Error>() {
+            Some(e) => e.clone(),
+            None => Self::from_error(ErrorCode::Unknown, error),
+        }
+    }
+
+    /// Sets the error that caused this one.
+    pub fn with_source(mut self, source: &(dyn std::error::Error + 'static)) -> Self {
+        self.source = Some(Box::new(Self::from_source(source)));
+        self
+    }
+
+    /// Creates an error of code [`ErrorCode::Unknown`].
+    #[inline]
+    pub fn unknown(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Unknown, message)
+    }
+
+    /// Creates an error of code [`ErrorCode::InvalidArgument`].
+    #[inline]
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidArgument, message)
+    }
+
+    /// Creates an error of code [`ErrorCode::NotFound`].
+    #[inline]
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    /// Creates an error of code [`ErrorCode::ResourceExhausted`].
+    #[inline]
+    pub fn resource_exhausted(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::ResourceExhausted, message)
+    }
+
+    /// Creates an error of code [`ErrorCode::Unavailable`].
+    #[inline]
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Unavailable, message)
+    }
+
+    /// Creates an error of code [`ErrorCode::Internal`].
+    #[inline]
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+
+    /// The code of the error.
+    #[inline]
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// The message of the error, without its code and its sources.
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Whether the request may succeed if it is made again unchanged.
+    #[inline]
+    pub fn retryable(&self) -> bool {
+        self.code.retryable()
+    }
+
+    /// The error and the errors that caused it, outermost first.
+    pub fn chain(&self) -> impl Iterator<Item = &Error> {
+        std::iter::successors(Some(self), |e| e.source.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_roundtrip() {
+        for raw in 0..=9u16 {
+            assert_eq!(u16::from(ErrorCode::from(raw)), raw);
+        }
+        assert_eq!(ErrorCode::from(1000), ErrorCode::Unknown);
+    }
+
+    #[test]
+    fn retryable() {
+        assert!(Error::resource_exhausted("heap is full").retryable());
+        assert!(Error::unavailable("upgrading").retryable());
+        assert!(!Error::invalid_argument("bad").retryable());
+        assert!(!Error::internal("bug").retryable());
+    }
+
+    #[test]
+    fn source_chain() {
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "disk on fire");
+        let inner = Error::from_error(ErrorCode::Unavailable, &io);
+        let outer = Error::internal("failed to open").with_source(&inner);
+        let chain: Vec<_> = outer.chain().map(|e| (e.code(), e.message())).collect();
+        assert_eq!(
+            chain,
+            [
+                (ErrorCode::Internal, "failed to open"),
+                (ErrorCode::Unavailable, "disk on fire"),
+            ]
+        );
+        assert_eq!(
+            std::error::Error::source(&outer).unwrap().to_string(),
+            "Unavailable: disk on fire"
+        );
+    }
 }
@@ -5,7 +5,7 @@ pub mod handle;
 pub use handle::{AsHandle, Handle};
 
 pub mod error;
-pub use error::Error;
+pub use error::{Error, ErrorCode};
 
 pub mod buf;
 pub mod net;
@@ -15,6 +15,8 @@ pub mod engine;
 
 #[cfg(feature = "mrpc")]
 pub mod rpc;
+#[cfg(feature = "mrpc")]
+pub mod wire;
 
 #[cfg(feature = "salloc")]
 pub use salloc;
@@ -27,6 +27,18 @@ pub struct SharedReceiveQueue(pub Handle);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct QueuePair(pub Handle);
 
+/// A DC target, which receives from any DC initiator presenting its key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DcTarget(pub Handle);
+
+/// A DC initiator, which sends to any DC target it has a peer for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DcInitiator(pub Handle);
+
+/// The address of a DC target a DC initiator sends to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DcPeer(pub Handle);
+
 pub mod returned {
     use serde::{Deserialize, Serialize};
 
@@ -129,6 +141,25 @@ pub struct RemoteKey {
     pub rkey: u32,
 }
 
+/// A memory region exposed to peers for one-sided operations. The owner hands it to the peers it
+/// grants access to, which then read, write, or compare-and-swap into the region through it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteRegion {
+    pub key: RemoteKey,
+    pub len: u64,
+    /// The remote access the region is registered with.
+    pub access: AccessFlags,
+}
+
+impl RemoteRegion {
+    /// Returns whether an operation that needs `access` to `len` bytes at `offset` is permitted
+    /// by the region.
+    #[inline]
+    pub fn permits(&self, access: AccessFlags, offset: u64, len: u64) -> bool {
+        self.access.contains(access) && offset.checked_add(len).map_or(false, |end| end <= self.len)
+    }
+}
+
 // NOTE(cjr): do not annotate this structure with any repr, use repr(Rust)
 // and static assert.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
@@ -157,6 +188,8 @@ pub enum WcOpcode {
     Send = 0,
     RdmaWrite = 1,
     RdmaRead = 2,
+    CompSwap = 3,
+    FetchAdd = 4,
     Recv = 128,
     RecvRdmaWithImm = 129,
     Invalid = 255,
@@ -203,6 +236,8 @@ bitflags! {
         const REMOTE_READ = 0b00000100;
         /// Enables Remote Atomic Operation Access.
         const REMOTE_ATOMIC = 0b00001000;
+        /// Allows binding memory windows to the region.
+        const MW_BIND = 0b00010000;
     }
 }
 
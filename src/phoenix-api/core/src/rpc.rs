@@ -30,6 +30,8 @@ impl From<Token> for usize {
 pub enum RpcMsgType {
     Request,
     Response,
+    /// A message pushed by the server to a client, not in reply to a request.
+    Notification,
 }
 
 /// An `u64` associated with an RPC.
@@ -100,6 +102,10 @@ pub enum StatusCode {
     Success = 0,
     AccessDenied = 1,
     Unknown = 2,
+    /// The message exceeds the maximum message size, only the meta is transmitted.
+    MessageTooLarge = 3,
+    /// The server does not implement the method called, only the meta is transmitted.
+    Unimplemented = 4,
 }
 
 #[repr(C)]
@@ -123,6 +129,48 @@ pub struct MessageMeta {
     pub status_code: StatusCode,
 }
 
+/// The timestamps of a request at the stages it goes through in the backend, for the latency
+/// breakdown of the calls. The timestamps are read from the time stamp counter by [`tsc`], and a
+/// stage not stamped is 0.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StageStamps {
+    /// The mRPC engine dequeues the request from the application.
+    pub engine_dequeue: u64,
+    /// The RPC adapter posts the request to the transport.
+    pub post_send: u64,
+    /// The mRPC engine learns that the request has been sent.
+    pub completion: u64,
+}
+
+/// Reads the time stamp counter. The counter is synchronized across the cores on CPUs with an
+/// invariant TSC, so the readings of the application and the backend can be compared. Returns 0
+/// on other architectures.
+#[inline]
+pub fn tsc() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: RDTSC has no side effects
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    0
+}
+
+/// Hints the CPU to bring the cache line at `ptr` into L1, so that a later read of it does not
+/// stall. A no-op on other architectures than x86_64.
+#[inline]
+pub fn prefetch<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: PREFETCH does not fault, even on an invalid address
+    unsafe {
+        use core::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>(ptr.cast());
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = ptr;
+}
+
 /// An RPC descriptor.
 ///
 /// Contains the metadata of the RPC message and a group of pointer that points to the location of
@@ -147,4 +195,5 @@ mod sa {
     const_assert_eq!(size_of::<RpcId>(), 16);
     const_assert_eq!(size_of::<MessageMeta>(), 40);
     const_assert_eq!(size_of::<MessageErased>(), 56);
+    const_assert_eq!(size_of::<StageStamps>(), 24);
 }
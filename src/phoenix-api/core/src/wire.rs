@@ -0,0 +1,508 @@
+//! The wire format of mRPC messages, shared by the RPC adapters of all transports.
+//!
+//! Every message starts with a [`WireHeader`]. A message is sent in one of two forms:
+//!
+//! - Fused ([`WireFlags::FUSED`]): a single segment, where the header is followed by `num_sge`
+//!   lengths as `u32`, and then by the scatter-gather elements back to back.
+//! - Segmented: the header is sent in a segment of its own, followed by one segment per
+//!   scatter-gather element.
+//!
+//! The elements of a message are encrypted into a single one when it is sealed
+//! ([`WireFlags::SEALED`]), which is then sent in either form. The header stays in the clear.
+//!
+//! Only the last segment of a message is marked by the transport, i.e., sent with immediate data
+//! on RDMA, or flagged as the end of the message on TCP.
+//!
+//! ```text
+//! header:    | meta | version | flags | num_sge | value_len | hop_limit | reserved |
+//!            |  40  |    1    |   1   |    2    |     4     |     1     |    7     |
+//! fused:     | header | lens[0] ... | value[0] ... |
+//!            |   56   | 4 * num_sge | value_len    |
+//! segmented: | header |  |value[0]|  |value[1]|  ...
+//! ```
+//!
+//! The hop limit bounds the number of times a message is forwarded between engines, e.g., by a
+//! load balancer or a proxy, so that a forwarding loop cannot keep a message alive forever. A
+//! message starts with [`DEFAULT_HOP_LIMIT`], and every engine that forwards it calls
+//! [`WireHeader::forward`]. An engine that sends again a message it has received must carry over
+//! the hop limit of the received header.
+//!
+//! All the fields of the header, and the lengths that follow a fused header, are little-endian on
+//! the wire. In memory, the header is kept in the byte order of the host, and converted at the
+//! transport boundary: by [`WireHeader::encode`] right before the message is posted, and by
+//! [`WireHeader::parse`] when it is received. The conversions are no-ops on little-endian hosts.
+//! The body of the message is not converted.
+use std::mem;
+
+use bitflags::bitflags;
+use thiserror::Error;
+
+use crate::rpc::{CallId, MessageMeta, RpcMsgType, StatusCode};
+use crate::Handle;
+
+/// The version of the wire format. Bump it on any change of the layout.
+pub const WIRE_VERSION: u8 = 2;
+
+/// The hop limit of a new message.
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+bitflags! {
+    /// Flags of a message on the wire.
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct WireFlags: u8 {
+        /// The message is sent in a single segment.
+        const FUSED = 0b00000001;
+        /// The scatter-gather elements are encrypted into a single one.
+        const SEALED = 0b00000010;
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum WireError {
+    #[error("Wire format version {0} is not supported, expected {}", WIRE_VERSION)]
+    Version(u8),
+    #[error("Segment of {0} bytes is too short for a message header")]
+    Truncated(usize),
+    #[error("Header announces {expected} segments, {actual} received")]
+    SegmentCount { expected: usize, actual: usize },
+    #[error("Invalid {field} {value} in the message header")]
+    InvalidField { field: &'static str, value: u32 },
+    #[error("Fused message of {required} bytes overflows its {available} bytes")]
+    Overflow { required: usize, available: usize },
+    #[error("Header announces {expected} bytes of elements, their lengths add up to {actual}")]
+    ValueLength { expected: usize, actual: usize },
+}
+
+/// The header of every mRPC message on the wire.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WireHeader {
+    /// The metadata of the RPC message.
+    pub meta: MessageMeta,
+    /// The [`WIRE_VERSION`] of the sender.
+    pub version: u8,
+    /// The [`WireFlags`] of the message.
+    pub flags: u8,
+    /// The number of scatter-gather elements the message is made of.
+    pub num_sge: u16,
+    /// The total length of the scatter-gather elements, in bytes.
+    pub value_len: u32,
+    /// The number of times the message can still be forwarded.
+    pub hop_limit: u8,
+    _reserved: [u8; 7],
+}
+
+impl WireHeader {
+    /// The size of the header on the wire.
+    pub const SIZE: usize = mem::size_of::<WireHeader>();
+
+    /// Fills in the header of a message of `num_sge` elements and `value_len` bytes. The meta and
+    /// the hop limit are left untouched.
+    #[inline]
+    pub fn seal(&mut self, flags: WireFlags, num_sge: usize, value_len: usize) {
+        assert!(
+            num_sge <= u16::MAX as usize,
+            "too many segments: {}",
+            num_sge
+        );
+        self.version = WIRE_VERSION;
+        self.flags = flags.bits();
+        self.num_sge = num_sge as u16;
+        self.value_len = value_len as u32;
+    }
+
+    #[inline]
+    pub fn flags(&self) -> WireFlags {
+        WireFlags::from_bits_truncate(self.flags)
+    }
+
+    #[inline]
+    pub fn is_fused(&self) -> bool {
+        self.flags().contains(WireFlags::FUSED)
+    }
+
+    #[inline]
+    pub fn is_sealed(&self) -> bool {
+        self.flags().contains(WireFlags::SEALED)
+    }
+
+    /// Counts a hop of the message when an engine forwards it. Returns `false` if the hop limit
+    /// is exhausted, in which case the message must be dropped.
+    #[inline]
+    pub fn forward(&mut self) -> bool {
+        if self.hop_limit == 0 {
+            return false;
+        }
+        self.hop_limit -= 1;
+        true
+    }
+
+    /// Converts the header to the byte order of the wire in place, right before the message is
+    /// posted to the transport.
+    ///
+    /// # Safety
+    ///
+    /// `header` must be valid for reads and writes. The header must not be read as a `WireHeader`
+    /// afterwards, until it is decoded by [`WireHeader::parse`].
+    #[inline]
+    pub unsafe fn encode(header: *mut WireHeader) {
+        let raw = RawHeader::from_header(&*header).swap_le();
+        header.cast::<RawHeader>().write(raw);
+    }
+
+    /// Decodes in place the header at the beginning of a received segment of `len` bytes, and
+    /// returns it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to at least `len` readable and writable bytes, aligned for `WireHeader`.
+    #[inline]
+    pub unsafe fn parse<'a>(ptr: usize, len: usize) -> Result<&'a WireHeader, WireError> {
+        if len < Self::SIZE {
+            return Err(WireError::Truncated(len));
+        }
+        let raw = (ptr as *const RawHeader).read().swap_le();
+        if raw.version != WIRE_VERSION {
+            return Err(WireError::Version(raw.version));
+        }
+        let header = ptr as *mut WireHeader;
+        header.write(raw.into_header()?);
+        Ok(&*header)
+    }
+
+    /// Checks that the lengths and the elements announced by a fused header fit in the segment
+    /// of `segment_len` bytes it is received in, and in the `capacity` bytes a buffer has for
+    /// them after the header.
+    #[inline]
+    pub fn check_fused(&self, segment_len: usize, capacity: usize) -> Result<(), WireError> {
+        let body = self.num_sge as usize * mem::size_of::<u32>() + self.value_len as usize;
+        let available = segment_len.saturating_sub(Self::SIZE).min(capacity);
+        if body > available {
+            return Err(WireError::Overflow {
+                required: Self::SIZE + body,
+                available: Self::SIZE + available,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the lengths that follow a fused header, as they are on the wire, add up to
+    /// the length of the elements it announces.
+    #[inline]
+    pub fn check_fused_lens(&self, lens: &[u32]) -> Result<(), WireError> {
+        let actual: u64 = lens.iter().map(|len| u32::from_le(*len) as u64).sum();
+        if lens.len() != self.num_sge as usize || actual != self.value_len as u64 {
+            return Err(WireError::ValueLength {
+                expected: self.value_len as usize,
+                actual: actual as usize,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the number of segments received for the message, including the one of the header.
+    #[inline]
+    pub fn check_segments(&self, num_segments: usize) -> Result<(), WireError> {
+        let expected = if self.is_fused() {
+            1
+        } else {
+            self.num_sge as usize + 1
+        };
+        if num_segments != expected {
+            return Err(WireError::SegmentCount {
+                expected,
+                actual: num_segments,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The header as it is on the wire. The enums of the meta are plain integers, so that any bytes
+/// received are a valid `RawHeader`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawHeader {
+    conn_id: u64,
+    service_id: u32,
+    func_id: u32,
+    call_id: u64,
+    token: u64,
+    msg_type: u32,
+    status_code: u32,
+    version: u8,
+    flags: u8,
+    num_sge: u16,
+    value_len: u32,
+    hop_limit: u8,
+    reserved: [u8; 7],
+}
+
+impl RawHeader {
+    fn from_header(header: &WireHeader) -> Self {
+        let meta = &header.meta;
+        RawHeader {
+            conn_id: meta.conn_id.0,
+            service_id: meta.service_id,
+            func_id: meta.func_id,
+            call_id: meta.call_id.0,
+            token: meta.token,
+            msg_type: meta.msg_type as u32,
+            status_code: meta.status_code as u32,
+            version: header.version,
+            flags: header.flags,
+            num_sge: header.num_sge,
+            value_len: header.value_len,
+            hop_limit: header.hop_limit,
+            reserved: [0; 7],
+        }
+    }
+
+    fn into_header(self) -> Result<WireHeader, WireError> {
+        let msg_type = match self.msg_type {
+            0 => RpcMsgType::Request,
+            1 => RpcMsgType::Response,
+            2 => RpcMsgType::Notification,
+            value => {
+                return Err(WireError::InvalidField {
+                    field: "msg_type",
+                    value,
+                })
+            }
+        };
+        // keep in sync with StatusCode
+        let status_code = match self.status_code {
+            0 => StatusCode::Success,
+            1 => StatusCode::AccessDenied,
+            2 => StatusCode::Unknown,
+            3 => StatusCode::MessageTooLarge,
+            4 => StatusCode::Unimplemented,
+            value => {
+                return Err(WireError::InvalidField {
+                    field: "status_code",
+                    value,
+                })
+            }
+        };
+        Ok(WireHeader {
+            meta: MessageMeta {
+                conn_id: Handle(self.conn_id),
+                service_id: self.service_id,
+                func_id: self.func_id,
+                call_id: CallId(self.call_id),
+                token: self.token,
+                msg_type,
+                status_code,
+            },
+            version: self.version,
+            flags: self.flags,
+            num_sge: self.num_sge,
+            value_len: self.value_len,
+            hop_limit: self.hop_limit,
+            _reserved: [0; 7],
+        })
+    }
+
+    /// Converts between the byte order of the host and little-endian, in either direction.
+    fn swap_le(self) -> Self {
+        RawHeader {
+            conn_id: self.conn_id.to_le(),
+            service_id: self.service_id.to_le(),
+            func_id: self.func_id.to_le(),
+            call_id: self.call_id.to_le(),
+            token: self.token.to_le(),
+            msg_type: self.msg_type.to_le(),
+            status_code: self.status_code.to_le(),
+            version: self.version,
+            flags: self.flags,
+            num_sge: self.num_sge.to_le(),
+            value_len: self.value_len.to_le(),
+            hop_limit: self.hop_limit,
+            reserved: self.reserved,
+        }
+    }
+}
+
+mod sa {
+    use super::*;
+    use static_assertions::const_assert_eq;
+    use std::mem::{align_of, size_of};
+
+    // the header must not change unless WIRE_VERSION is bumped
+    const_assert_eq!(size_of::<MessageMeta>(), 40);
+    const_assert_eq!(size_of::<WireFlags>(), 1);
+    const_assert_eq!(size_of::<WireHeader>(), 56);
+    const_assert_eq!(align_of::<WireHeader>(), 8);
+    const_assert_eq!(size_of::<RawHeader>(), size_of::<WireHeader>());
+    const_assert_eq!(align_of::<RawHeader>(), align_of::<WireHeader>());
+    // the lengths that follow a fused header are aligned
+    const_assert_eq!(size_of::<WireHeader>() % align_of::<u32>(), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The tests do not depend on the byte order of the host. Run them on a big-endian target with
+    // `cross test -p phoenix-api-core --features mrpc --target powerpc64-unknown-linux-gnu`.
+
+    fn header() -> WireHeader {
+        let mut header = WireHeader {
+            meta: MessageMeta {
+                conn_id: Handle(0x0102030405060708),
+                service_id: 0x11121314,
+                func_id: 0x21222324,
+                call_id: CallId(0x3132333435363738),
+                token: 0x4142434445464748,
+                msg_type: RpcMsgType::Response,
+                status_code: StatusCode::MessageTooLarge,
+            },
+            version: 0,
+            flags: 0,
+            num_sge: 0,
+            value_len: 0,
+            hop_limit: DEFAULT_HOP_LIMIT,
+            _reserved: [0; 7],
+        };
+        header.seal(WireFlags::FUSED, 0x0102, 0x51525354);
+        header
+    }
+
+    #[repr(C, align(8))]
+    struct Segment([u8; WireHeader::SIZE]);
+
+    fn encode(header: WireHeader) -> Segment {
+        let mut seg = Segment([0; WireHeader::SIZE]);
+        let ptr = seg.0.as_mut_ptr().cast::<WireHeader>();
+        unsafe {
+            ptr.write(header);
+            WireHeader::encode(ptr);
+        }
+        seg
+    }
+
+    #[test]
+    fn encoded_little_endian() {
+        let seg = encode(header());
+        let bytes = &seg.0;
+        assert_eq!(bytes[0..8], 0x0102030405060708u64.to_le_bytes());
+        assert_eq!(bytes[8..12], 0x11121314u32.to_le_bytes());
+        assert_eq!(bytes[12..16], 0x21222324u32.to_le_bytes());
+        assert_eq!(bytes[16..24], 0x3132333435363738u64.to_le_bytes());
+        assert_eq!(bytes[24..32], 0x4142434445464748u64.to_le_bytes());
+        assert_eq!(bytes[32..36], 1u32.to_le_bytes());
+        assert_eq!(bytes[36..40], 3u32.to_le_bytes());
+        assert_eq!(bytes[40], WIRE_VERSION);
+        assert_eq!(bytes[41], WireFlags::FUSED.bits());
+        assert_eq!(bytes[42..44], 0x0102u16.to_le_bytes());
+        assert_eq!(bytes[44..48], 0x51525354u32.to_le_bytes());
+        assert_eq!(bytes[48], DEFAULT_HOP_LIMIT);
+        assert_eq!(bytes[49..56], [0; 7]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let expected = header();
+        let mut seg = encode(expected);
+        let parsed = unsafe { WireHeader::parse(seg.0.as_mut_ptr() as usize, seg.0.len()) };
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.meta, expected.meta);
+        assert_eq!(parsed.version, WIRE_VERSION);
+        assert!(parsed.is_fused());
+        assert!(!parsed.is_sealed());
+        assert_eq!(parsed.num_sge, expected.num_sge);
+        assert_eq!(parsed.value_len, expected.value_len);
+        assert_eq!(parsed.hop_limit, expected.hop_limit);
+    }
+
+    #[test]
+    fn sealed_flag() {
+        let mut header = header();
+        header.seal(WireFlags::SEALED, 1, 0x100);
+        let mut seg = encode(header);
+        assert_eq!(seg.0[41], WireFlags::SEALED.bits());
+        let parsed = unsafe { WireHeader::parse(seg.0.as_mut_ptr() as usize, seg.0.len()) };
+        let parsed = parsed.unwrap();
+        assert!(parsed.is_sealed());
+        assert!(!parsed.is_fused());
+        assert!(parsed.check_segments(2).is_ok());
+    }
+
+    #[test]
+    fn hop_limit_expires() {
+        let mut header = header();
+        for _ in 0..DEFAULT_HOP_LIMIT {
+            assert!(header.forward());
+        }
+        assert_eq!(header.hop_limit, 0);
+        assert!(!header.forward());
+        assert_eq!(header.hop_limit, 0);
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let mut seg = encode(header());
+        seg.0[36..40].copy_from_slice(&7u32.to_le_bytes());
+        let parsed = unsafe { WireHeader::parse(seg.0.as_mut_ptr() as usize, seg.0.len()) };
+        assert!(matches!(
+            parsed,
+            Err(WireError::InvalidField {
+                field: "status_code",
+                value: 7
+            })
+        ));
+
+        let mut seg = encode(header());
+        seg.0[40] = WIRE_VERSION + 1;
+        let parsed = unsafe { WireHeader::parse(seg.0.as_mut_ptr() as usize, seg.0.len()) };
+        assert!(matches!(parsed, Err(WireError::Version(_))));
+
+        let mut seg = encode(header());
+        let parsed =
+            unsafe { WireHeader::parse(seg.0.as_mut_ptr() as usize, WireHeader::SIZE - 1) };
+        assert!(matches!(parsed, Err(WireError::Truncated(_))));
+    }
+
+    #[test]
+    fn fused_bounds() {
+        let mut header = header();
+        header.seal(WireFlags::FUSED, 2, 100);
+        let len = WireHeader::SIZE + 2 * 4 + 100;
+        assert!(header.check_fused(len, 1024).is_ok());
+        // a receive buffer may be larger than the message
+        assert!(header.check_fused(len + 8, 1024).is_ok());
+        assert!(matches!(
+            header.check_fused(len - 1, 1024),
+            Err(WireError::Overflow {
+                required,
+                available,
+            }) if required == len && available == len - 1
+        ));
+        // the segment may be larger than the buffer the message is copied into
+        assert!(header.check_fused(4096, 107).is_err());
+        assert!(header.check_fused(4, 1024).is_err());
+
+        header.seal(WireFlags::FUSED, u16::MAX as usize, u32::MAX as usize);
+        assert!(header.check_fused(usize::MAX, 16384).is_err());
+    }
+
+    #[test]
+    fn fused_lens() {
+        let mut header = header();
+        header.seal(WireFlags::FUSED, 2, 100);
+        let lens = [40u32.to_le(), 60u32.to_le()];
+        assert!(header.check_fused_lens(&lens).is_ok());
+        assert!(matches!(
+            header.check_fused_lens(&[40u32.to_le(), 61u32.to_le()]),
+            Err(WireError::ValueLength {
+                expected: 100,
+                actual: 101
+            })
+        ));
+        assert!(header.check_fused_lens(&lens[..1]).is_err());
+        // lengths that wrap around a u32 do not add up
+        let lens = [u32::MAX.to_le(), 101u32.to_le()];
+        assert!(header.check_fused_lens(&lens).is_err());
+    }
+}
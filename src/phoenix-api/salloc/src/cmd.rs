@@ -7,14 +7,14 @@ type IResult<T> = Result<T, phoenix_api::Error>;
 pub enum Command {
     // Layout: (size, align)
     AllocShm(usize, usize),
-    // addr: usize
-    DeallocShm(usize),
+    // addr: usize, generation: u64
+    DeallocShm(usize, u64),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum CompletionKind {
-    // remote_addr, file_off
-    AllocShm(usize, i64),
+    // remote_addr, file_off, generation
+    AllocShm(usize, i64, u64),
     DeallocShm,
 }
 
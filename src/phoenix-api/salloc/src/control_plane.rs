@@ -2,6 +2,62 @@ use serde::{Deserialize, Serialize};
 
 type IResult<T> = Result<T, phoenix_api::Error>;
 
+/// What to do with an allocation that does not fit in the heap of the process.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HeapFullPolicy {
+    /// Fail the allocation with [`phoenix_api::ErrorCode::ResourceExhausted`] right away.
+    #[default]
+    Reject,
+    /// Hold the allocation until other threads of the process free enough memory, fail it if
+    /// that does not happen within the timeout.
+    Block,
+    /// Fail the allocation, the client then returns its cached empty pages to the backend and
+    /// retries once.
+    Reclaim,
+}
+
+/// How much heap memory to pre-allocate when the first stub of the process is created, in bytes
+/// of each kind of page. Each size is rounded up to whole pages.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeapWarmup {
+    /// 4 KiB pages, for objects up to 256 bytes.
+    pub small: usize,
+    /// 2 MiB pages, for objects up to 128 KiB.
+    pub large: usize,
+    /// 1 GiB pages, for objects up to 64 MiB.
+    pub huge: usize,
+}
+
+impl HeapWarmup {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.small == 0 && self.large == 0 && self.huge == 0
+    }
+}
+
+/// The setting of a salloc subscription, passed as the config string in JSON.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Setting {
+    pub heap_full_policy: HeapFullPolicy,
+    /// How long an allocation waits under [`HeapFullPolicy::Block`], in milliseconds.
+    pub block_timeout_ms: u64,
+    /// Only used by the client.
+    pub warmup: HeapWarmup,
+}
+
+impl Default for Setting {
+    fn default() -> Self {
+        Setting {
+            heap_full_policy: HeapFullPolicy::Reject,
+            block_timeout_ms: 1000,
+            warmup: HeapWarmup::default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {}
 
@@ -3,5 +3,8 @@ pub use core::*;
 #[cfg(feature = "salloc")]
 pub use salloc;
 
+#[cfg(feature = "collective")]
+pub use collective;
+
 #[cfg(feature = "transport")]
 pub use transport;
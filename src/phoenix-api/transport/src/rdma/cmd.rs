@@ -11,6 +11,20 @@ use phoenix_api::Handle;
 
 type IResult<T> = Result<T, phoenix_api::Error>;
 
+/// A change of the state of a connection that phoenix recovers by itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnEvent {
+    /// The queue pair went into the error state or the peer disconnected. The work requests
+    /// posted since are held back until the connection is restored.
+    Degraded,
+    /// The connection is re-established on a new queue pair, and the work requests without a
+    /// completion have been posted again.
+    Restored,
+    /// The connection could not be re-established. The work requests without a completion are
+    /// completed with flush errors.
+    Failed,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
     // rdmacm
@@ -38,6 +52,7 @@ pub enum Command {
 
     Disconnect(net::CmId),
     DestroyId(net::CmId),
+    TryGetConnEvent(net::CmId),
 
     // reference counting
     OpenPd(net::ProtectionDomain),
@@ -54,6 +69,19 @@ pub enum Command {
 
     DeregMr(net::MemoryRegion),
 
+    // dynamically connected transport, when built with it
+    CreateDcTarget(net::ProtectionDomain, net::CompletionQueue, u32),
+    CreateDcInitiator(net::ProtectionDomain, net::CompletionQueue, u32),
+    /// Advertises the DC target to the peer in the private data of the connect or accept of the
+    /// `CmId`.
+    SetDcTarget(net::CmId, net::DcTarget),
+    /// Creates the peer to reach the DC target the other end of the `CmId` advertised. The peer
+    /// stays valid after the `CmId` is destroyed.
+    CreateDcPeer(net::DcInitiator, net::CmId),
+    DestroyDcTarget(net::DcTarget),
+    DestroyDcInitiator(net::DcInitiator),
+    DestroyDcPeer(net::DcPeer),
+
     // Other
     GetDefaultPds,
     GetDefaultContexts,
@@ -79,6 +107,7 @@ pub enum CompletionKind {
 
     Disconnect,
     DestroyId,
+    TryGetConnEvent(Option<ConnEvent>),
 
     // reference counting
     OpenPd,
@@ -95,6 +124,15 @@ pub enum CompletionKind {
 
     DeregMr,
 
+    // dynamically connected transport
+    CreateDcTarget(net::DcTarget),
+    CreateDcInitiator(net::DcInitiator),
+    SetDcTarget,
+    CreateDcPeer(net::DcPeer),
+    DestroyDcTarget,
+    DestroyDcInitiator,
+    DestroyDcPeer,
+
     // Other
     GetDefaultPds(Vec<returned::ProtectionDomain>),
     GetDefaultContexts(Vec<returned::VerbsContext>),
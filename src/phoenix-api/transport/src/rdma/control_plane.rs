@@ -1,5 +1,9 @@
+use std::net::SocketAddr;
+
 use serde::{Deserialize, Serialize};
 
+use phoenix_api::net::{CmId, CompletionQueue, DcTarget, QpCapability};
+
 type IResult<T> = Result<T, phoenix_api::Error>;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +15,73 @@ pub enum ResponseKind {}
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Response(pub IResult<ResponseKind>);
 
+/// Queries to the RDMA TransportEngine, or to an engine using the transport in-process, sent
+/// through `EngineQuery`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Query {
+    /// Dumps the attributes of the queue pairs, completion queues, and shared receive queues of
+    /// the process, as the device negotiated them.
+    Introspect,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueryResponse {
+    Introspect(IntrospectInfo),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QpInfo {
+    pub qp_num: u32,
+    /// E.g., `RTS`
+    pub state: String,
+    /// The path MTU in bytes
+    pub path_mtu: u32,
+    /// The local ACK timeout, 4.096 * 2^timeout usec, 0 for infinite
+    pub timeout: u8,
+    pub retry_cnt: u8,
+    /// 7 for infinite
+    pub rnr_retry: u8,
+    pub min_rnr_timer: u8,
+    pub max_rd_atomic: u8,
+    pub max_dest_rd_atomic: u8,
+    /// Including the inline data the device granted, which may be more than asked for
+    pub cap: QpCapability,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CqInfo {
+    pub handle: CompletionQueue,
+    /// The entries the device granted, which may be more than asked for
+    pub cqe: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub cmid: CmId,
+    pub local_addr: SocketAddr,
+    pub peer_addr: SocketAddr,
+    /// `None` if the queue pair could not be queried
+    pub qp: Option<QpInfo>,
+    pub send_cq: CqInfo,
+    pub recv_cq: CqInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SrqInfo {
+    /// The DC target the shared receive queue belongs to
+    pub dct: DcTarget,
+    pub max_wr: u32,
+    pub max_sge: u32,
+    pub srq_limit: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IntrospectInfo {
+    pub connections: Vec<ConnectionInfo>,
+    /// Only with the dynamically connected transport
+    pub srqs: Vec<SrqInfo>,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Setting {
     /// The NIC to use.
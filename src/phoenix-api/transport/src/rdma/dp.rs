@@ -17,6 +17,64 @@ pub enum WorkRequest {
     PostSendWithImm(Handle, u64, Range, Handle, SendFlags, u32),
     PostWrite(Handle, Handle, u64, Range, u64, RemoteKey, SendFlags),
     PostRead(Handle, Handle, u64, Range, u64, RemoteKey, SendFlags),
+    /// Compares the 8 bytes at the remote offset with the first u64 and swaps them with the
+    /// second one if equal. The original remote value is written to the 8-byte local range.
+    PostCompareAndSwap(
+        Handle,
+        Handle,
+        u64,
+        Range,
+        u64,
+        RemoteKey,
+        SendFlags,
+        u64,
+        u64,
+    ),
+    /// Adds the first u64 to the field of the remote 8 bytes selected by the mask, the second
+    /// u64, which is a contiguous run of bits. The original remote value is written to the 8-byte
+    /// local range.
+    PostFetchAndAdd(
+        Handle,
+        Handle,
+        u64,
+        Range,
+        u64,
+        RemoteKey,
+        SendFlags,
+        u64,
+        u64,
+    ),
+    /// Compares the bits of the remote 8 bytes in the second u64 with the first one and, if
+    /// equal, replaces the bits in the fourth u64 with the third one. The original remote value is
+    /// written to the 8-byte local range.
+    PostMaskedCompareAndSwap(
+        Handle,
+        Handle,
+        u64,
+        Range,
+        u64,
+        RemoteKey,
+        SendFlags,
+        u64,
+        u64,
+        u64,
+        u64,
+    ),
+    /// Posts a receive to the shared receive queue of a DC target.
+    PostDcRecv(Handle, u64, Range, Handle),
+    /// Sends from a DC initiator to a DC peer.
+    PostDcSend(Handle, Handle, u64, Range, Handle, SendFlags),
+    /// Writes from a DC initiator to the remote offset of a memory region of a DC peer.
+    PostDcWrite(
+        Handle,
+        Handle,
+        u64,
+        Range,
+        Handle,
+        u64,
+        RemoteKey,
+        SendFlags,
+    ),
     PollCq(CompletionQueue),
 }
 
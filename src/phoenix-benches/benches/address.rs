@@ -0,0 +1,58 @@
+//! Translation of the addresses of the receive buffers from the backend to the application.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use mrpc_marshal::{AddressArbiter, NaiveAddressMap, NoopAddressMap, ShmRecvMr};
+
+const REGION_START: usize = 0x6000_0000_0000;
+const REGION_SIZE: usize = 2 * 1024 * 1024;
+
+/// The map of `num_regions` receive buffer regions, mapped at the same addresses in both.
+fn address_map(num_regions: usize) -> NaiveAddressMap {
+    let map = NaiveAddressMap::new();
+    for i in 0..num_regions {
+        let start = REGION_START + i * REGION_SIZE;
+        map.insert_addr_map(
+            start,
+            ShmRecvMr {
+                ptr: start,
+                len: REGION_SIZE,
+                align: REGION_SIZE,
+            },
+        )
+        .unwrap();
+    }
+    map
+}
+
+fn query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("address");
+
+    group.bench_function("noop", |b| {
+        let map = NoopAddressMap;
+        b.iter(|| map.query_app_addr(criterion::black_box(REGION_START + 4096)))
+    });
+
+    for num_regions in [1usize, 16, 256] {
+        let map = address_map(num_regions);
+        // addresses spread over the regions
+        let addrs: Vec<usize> = (0..num_regions)
+            .map(|i| REGION_START + i * REGION_SIZE + (i * 4096) % REGION_SIZE)
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("naive", num_regions),
+            &addrs,
+            |b, addrs| {
+                let mut i = 0;
+                b.iter(|| {
+                    i = (i + 1) % addrs.len();
+                    map.query_app_addr(criterion::black_box(addrs[i])).unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, query);
+criterion_main!(benches);
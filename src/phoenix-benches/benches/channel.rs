@@ -0,0 +1,41 @@
+//! Send and receive on the channels between the engines.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use ipc::channel::{create_bounded_channel, ChannelFlavor};
+
+/// About the size of an RPC message descriptor.
+type Message = [u64; 8];
+
+const CAPACITY: usize = 1024;
+
+fn send_recv(c: &mut Criterion) {
+    let mut group = c.benchmark_group("channel");
+    for flavor in [
+        ChannelFlavor::Concurrent,
+        ChannelFlavor::Mpsc,
+        ChannelFlavor::Sequential,
+    ] {
+        for batch in [1usize, 32] {
+            let (mut sender, mut receiver) = create_bounded_channel::<Message>(flavor, CAPACITY);
+            group.throughput(Throughput::Elements(batch as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{flavor:?}"), batch),
+                &batch,
+                |b, &batch| {
+                    b.iter(|| {
+                        for i in 0..batch {
+                            sender.send([i as u64; 8]).unwrap();
+                        }
+                        for _ in 0..batch {
+                            criterion::black_box(receiver.try_recv().unwrap());
+                        }
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send_recv);
+criterion_main!(benches);
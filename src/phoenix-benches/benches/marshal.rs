@@ -0,0 +1,41 @@
+//! Marshalling and unmarshalling of representative messages.
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use mrpc_marshal::{ExcavateContext, NoopAddressMap, RpcMessage, SgList};
+
+use phoenix_benches::messages;
+
+fn bench_message<M: RpcMessage>(c: &mut Criterion, name: &str, msg: M) {
+    let mut group = c.benchmark_group(format!("marshal/{name}"));
+    // the message stays in place, unmarshalling only rewrites its pointers
+    let msg = Box::new(msg);
+    let mut sgl = SgList::default();
+
+    group.bench_function("marshal", |b| {
+        b.iter(|| msg.marshal_into(criterion::black_box(&mut sgl)).unwrap())
+    });
+
+    msg.marshal_into(&mut sgl).unwrap();
+    let addr_arbiter = NoopAddressMap;
+    group.bench_function("unmarshal", |b| {
+        b.iter(|| {
+            let mut ctx = ExcavateContext {
+                sgl: sgl.0.iter(),
+                addr_arbiter: &addr_arbiter,
+            };
+            criterion::black_box(unsafe { M::unmarshal(&mut ctx) }.unwrap())
+        })
+    });
+
+    group.finish();
+}
+
+fn marshal(c: &mut Criterion) {
+    bench_message(c, "small", messages::small());
+    bench_message(c, "key_value_64", messages::key_value(64));
+    bench_message(c, "key_value_4k", messages::key_value(4096));
+    bench_message(c, "batch_32x64", messages::batch(32, 64));
+}
+
+criterion_group!(benches, marshal);
+criterion_main!(benches);
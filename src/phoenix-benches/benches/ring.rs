@@ -0,0 +1,195 @@
+//! Enqueue and dequeue on the shared memory rings of the data path.
+//!
+//! `cross_core` streams slots to a consumer spinning on another core, the way the application
+//! and the backend use the rings. `packed` is the ring of shmem-ipc the data path used before,
+//! the others are `ipc::ring` with each index on a cache line or on a page of its own. Run it
+//! under `perf c2c record` to compare the HITM events on the lines of the indices.
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use ipc::ring::Placement;
+use ipc::shmem_ipc::{ShmReceiver, ShmSender};
+
+/// A work request or a completion slot.
+type Slot = [u8; 64];
+
+const CAPACITY: usize = 1024;
+
+fn ring_pair() -> (ShmSender<Slot>, ShmReceiver<Slot>) {
+    let receiver = ShmReceiver::<Slot>::new(CAPACITY).unwrap();
+    let dup = |file: &File| file.try_clone().unwrap();
+    let sender = ShmSender::<Slot>::open(
+        CAPACITY,
+        dup(receiver.memfd().as_file()),
+        dup(receiver.empty_signal()),
+        dup(receiver.full_signal()),
+    )
+    .unwrap();
+    (sender, receiver)
+}
+
+fn padded_ring_pair(
+    placement: Placement,
+) -> (ipc::ring::ShmSender<Slot>, ipc::ring::ShmReceiver<Slot>) {
+    let receiver = ipc::ring::ShmReceiver::<Slot>::with_placement(CAPACITY, placement).unwrap();
+    let dup = |file: &File| file.try_clone().unwrap();
+    let sender = ipc::ring::ShmSender::<Slot>::open(
+        CAPACITY,
+        dup(receiver.memfd().as_file()),
+        dup(receiver.empty_signal()),
+        dup(receiver.full_signal()),
+    )
+    .unwrap();
+    (sender, receiver)
+}
+
+/// The producer side of either ring.
+trait Produce: Send + 'static {
+    /// Writes at most `n` slots, returns how many.
+    fn produce(&mut self, n: usize) -> usize;
+}
+
+/// The consumer side of either ring.
+trait Consume: Send + 'static {
+    /// Reads all the slots available, returns how many.
+    fn consume(&mut self) -> usize;
+}
+
+macro_rules! impl_ring {
+    ($sender:ty, $receiver:ty) => {
+        impl Produce for $sender {
+            fn produce(&mut self, n: usize) -> usize {
+                let mut written = 0;
+                self.sender_mut()
+                    .send(|ptr, count| {
+                        written = n.min(count);
+                        for i in 0..written {
+                            unsafe { ptr.add(i).write([i as u8; 64]) };
+                        }
+                        written
+                    })
+                    .unwrap();
+                written
+            }
+        }
+
+        impl Consume for $receiver {
+            fn consume(&mut self) -> usize {
+                let mut read = 0;
+                self.receiver_mut()
+                    .recv(|ptr, count| {
+                        for i in 0..count {
+                            criterion::black_box(unsafe { ptr.add(i).read() });
+                        }
+                        read = count;
+                        count
+                    })
+                    .unwrap();
+                read
+            }
+        }
+    };
+}
+
+impl_ring!(ShmSender<Slot>, ShmReceiver<Slot>);
+impl_ring!(ipc::ring::ShmSender<Slot>, ipc::ring::ShmReceiver<Slot>);
+
+fn enqueue_dequeue(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ring");
+    for batch in [1usize, 8, 32] {
+        let (mut sender, mut receiver) = padded_ring_pair(Placement::CacheLine);
+        group.throughput(Throughput::Elements(batch as u64));
+        group.bench_with_input(
+            BenchmarkId::new("enqueue_dequeue", batch),
+            &batch,
+            |b, &batch| {
+                b.iter(|| {
+                    sender
+                        .send_raw(|ptr, count| {
+                            let n = batch.min(count);
+                            for i in 0..n {
+                                unsafe { ptr.add(i).write([i as u8; 64]) };
+                            }
+                            n
+                        })
+                        .unwrap();
+                    receiver
+                        .receiver_mut()
+                        .recv(|ptr, count| {
+                            for i in 0..count {
+                                criterion::black_box(unsafe { ptr.add(i).read() });
+                            }
+                            count
+                        })
+                        .unwrap();
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_cross_core<P: Produce, C: Consume>(
+    group: &mut criterion::BenchmarkGroup<'_, criterion::measurement::WallTime>,
+    name: &str,
+    batch: usize,
+    (mut sender, mut receiver): (P, C),
+) {
+    let consumed = Arc::new(AtomicU64::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let consumer = {
+        let consumed = Arc::clone(&consumed);
+        let stop = Arc::clone(&stop);
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let n = receiver.consume();
+                if n > 0 {
+                    consumed.fetch_add(n as u64, Ordering::Release);
+                }
+            }
+        })
+    };
+
+    group.bench_with_input(BenchmarkId::new(name, batch), &batch, |b, &batch| {
+        b.iter_custom(|iters| {
+            let target = consumed.load(Ordering::Acquire) + iters * batch as u64;
+            let start = Instant::now();
+            for _ in 0..iters {
+                let mut sent = 0;
+                while sent < batch {
+                    sent += sender.produce(batch - sent);
+                }
+            }
+            while consumed.load(Ordering::Acquire) < target {
+                std::hint::spin_loop();
+            }
+            start.elapsed()
+        })
+    });
+
+    stop.store(true, Ordering::Relaxed);
+    consumer.join().unwrap();
+}
+
+fn cross_core(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ring_cross_core");
+    for batch in [1usize, 8, 32] {
+        group.throughput(Throughput::Elements(batch as u64));
+        bench_cross_core(&mut group, "packed", batch, ring_pair());
+        bench_cross_core(
+            &mut group,
+            "cache_line",
+            batch,
+            padded_ring_pair(Placement::CacheLine),
+        );
+        bench_cross_core(&mut group, "page", batch, padded_ring_pair(Placement::Page));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, enqueue_dequeue, cross_core);
+criterion_main!(benches);
@@ -0,0 +1,76 @@
+//! Sealing and opening of marshalled messages, the cost the RPC adapter adds to the datapath of
+//! an encrypted connection. `copy` is the plain copy of a fused message, for reference.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use mrpc_marshal::seal::{Handshake, Role};
+use mrpc_marshal::{RpcMessage, SgE, SgList};
+
+use phoenix_benches::messages;
+
+fn bench_message<M: RpcMessage>(c: &mut Criterion, name: &str, msg: M) {
+    let initiator = Handshake::new();
+    let responder = Handshake::new();
+    let initiator_data = initiator.private_data();
+    let sealer = initiator
+        .finish(Role::Initiator, &responder.private_data())
+        .unwrap();
+    let opener = responder.finish(Role::Responder, &initiator_data).unwrap();
+
+    let msg = Box::new(msg);
+    let mut sgl = SgList::default();
+    msg.marshal_into(&mut sgl).unwrap();
+    let size: usize = sgl.0.iter().map(|sge| sge.len).sum();
+    let aad = [0u8; 20];
+
+    let mut group = c.benchmark_group(format!("seal/{name}"));
+    group.throughput(Throughput::Bytes(size as u64));
+
+    let mut buf = Vec::new();
+    group.bench_function("copy", |b| {
+        b.iter(|| {
+            buf.clear();
+            for sge in &sgl.0 {
+                buf.extend_from_slice(unsafe {
+                    std::slice::from_raw_parts(sge.ptr as *const u8, sge.len)
+                });
+            }
+            criterion::black_box(&buf);
+        })
+    });
+
+    group.bench_function("seal", |b| {
+        b.iter(|| unsafe { sealer.seal(&sgl, &aad, criterion::black_box(&mut buf)) })
+    });
+
+    // a message is opened in place, and only once
+    group.bench_function("open", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut buf = Vec::new();
+                unsafe { sealer.seal(&sgl, &aad, &mut buf) };
+                (buf, SgList::default())
+            },
+            |(buf, opened)| {
+                let sge = SgE {
+                    ptr: buf.as_mut_ptr() as usize,
+                    len: buf.len(),
+                };
+                unsafe { opener.open(sge, &aad, opened) }.unwrap();
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn seal(c: &mut Criterion) {
+    bench_message(c, "small", messages::small());
+    bench_message(c, "key_value_64", messages::key_value(64));
+    bench_message(c, "key_value_4k", messages::key_value(4096));
+    bench_message(c, "key_value_1m", messages::key_value(1 << 20));
+    bench_message(c, "batch_32x64", messages::batch(32, 64));
+}
+
+criterion_group!(benches, seal);
+criterion_main!(benches);
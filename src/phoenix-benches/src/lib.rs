@@ -0,0 +1,14 @@
+//! Microbenchmarks of the datapath primitives.
+//!
+//! The benchmarks cover the shared memory rings between the applications and the backend, the
+//! channels between the engines, marshalling of RPC messages, and the translation of the
+//! addresses of the receive buffers. Run them with
+//!
+//! ```bash
+//! cargo bench -p phoenix-benches
+//! ```
+//!
+//! or a single one with, e.g., `cargo bench -p phoenix-benches --bench marshal`. Criterion keeps
+//! the results of the last run under `target/criterion` and reports the change against them.
+
+pub mod messages;
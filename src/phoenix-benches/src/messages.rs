@@ -0,0 +1,61 @@
+//! Representative messages, as the backend sees them.
+//!
+//! The messages are built with exact capacities, because excavating a string or a vector sets
+//! its capacity to its length.
+use mrpc_marshal::shadow::{String, Vec};
+
+/// A message of scalars only, marshalled into a single segment.
+#[repr(C)]
+#[derive(Debug, ::mrpc_derive::Message)]
+pub struct Small {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(uint32, tag = "2")]
+    pub flags: u32,
+}
+
+/// A key-value pair, with a segment for each of the key and the value.
+#[repr(C)]
+#[derive(Debug, ::mrpc_derive::Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+/// A batch of key-value pairs, with nested messages.
+#[repr(C)]
+#[derive(Debug, ::mrpc_derive::Message)]
+pub struct Batch {
+    #[prost(message, repeated, tag = "1")]
+    pub items: Vec<KeyValue>,
+    #[prost(string, tag = "2")]
+    pub tag: String,
+}
+
+pub fn small() -> Small {
+    Small { id: 42, flags: 7 }
+}
+
+/// A key-value pair with a value of `value_len` bytes.
+pub fn key_value(value_len: usize) -> KeyValue {
+    let mut value = Vec::with_capacity(value_len);
+    value.extend_from_slice(&vec![0xab; value_len]);
+    KeyValue {
+        key: String::from("benchmark-key"),
+        value,
+    }
+}
+
+/// A batch of `num_items` key-value pairs with values of `value_len` bytes.
+pub fn batch(num_items: usize, value_len: usize) -> Batch {
+    let mut items = Vec::with_capacity(num_items);
+    for _ in 0..num_items {
+        items.push(key_value(value_len));
+    }
+    Batch {
+        items,
+        tag: String::from("benchmark-batch"),
+    }
+}
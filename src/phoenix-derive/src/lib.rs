@@ -0,0 +1,378 @@
+//! Derive macros for the engines.
+//!
+//! `#[derive(EngineState)]` implements `phoenix_common::engine::EngineState`, which dumps the
+//! fields of an engine into a `ResourceCollection` when it is decomposed and restores them when
+//! its module restores the engine. The fields are controlled by `#[state(...)]`:
+//!
+//! - `#[state(node)]` marks the `DataPathNode` of the engine, which is returned separately and
+//!   passed back on restore. Exactly one field must be marked.
+//! - `#[state(skip)]` leaves the field out of the dump, e.g., raw pointers, channel ends or the
+//!   `Indicator`. It is restored by `Default::default()`, or by the expression given by
+//!   `#[state(skip, with = "expr")]`.
+//! - `#[state(since = N)]` marks a field added in version `N` of the state. It is restored by
+//!   `Default::default()`, or by `#[state(since = N, default = "expr")]`, when the dumped state
+//!   is older.
+//!
+//! The version of the state is given by `#[state(version = N)]` on the struct, and defaults to 1.
+//! The expressions of `with` and `default` may use the arguments of `EngineState::restore`,
+//! i.e., `shared`, `global`, `plugged` and `prev_version`, and the fields declared before.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Attribute, Data, DataStruct, DeriveInput, Error, Expr, Fields, Ident, Lit, Meta, NestedMeta,
+    Type,
+};
+
+enum Kind {
+    Dumped,
+    Node,
+    Skipped(Option<Expr>),
+    Since(u32, Option<Expr>),
+}
+
+struct Field {
+    ident: Ident,
+    ty: Type,
+    kind: Kind,
+}
+
+fn state_args(attrs: &[Attribute]) -> Result<Vec<NestedMeta>, Error> {
+    let mut args = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path.is_ident("state")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => args.extend(list.nested),
+            meta => return Err(Error::new_spanned(meta, "expected #[state(...)]")),
+        }
+    }
+    Ok(args)
+}
+
+fn parse_expr(lit: &Lit) -> Result<Expr, Error> {
+    match lit {
+        Lit::Str(s) => s.parse(),
+        _ => Err(Error::new_spanned(
+            lit,
+            "expected an expression in a string",
+        )),
+    }
+}
+
+fn parse_version(lit: &Lit) -> Result<u32, Error> {
+    match lit {
+        Lit::Int(i) => i.base10_parse(),
+        _ => Err(Error::new_spanned(lit, "expected an integer")),
+    }
+}
+
+fn parse_field(field: syn::Field) -> Result<Field, Error> {
+    let ident = field.ident.clone().unwrap();
+    let (mut node, mut skip, mut since, mut expr) = (false, false, None, None);
+    for arg in state_args(&field.attrs)? {
+        match arg {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("node") => node = true,
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => skip = true,
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("since") => {
+                since = Some(parse_version(&nv.lit)?);
+            }
+            NestedMeta::Meta(Meta::NameValue(nv))
+                if nv.path.is_ident("with") || nv.path.is_ident("default") =>
+            {
+                expr = Some((nv.path.is_ident("with"), parse_expr(&nv.lit)?));
+            }
+            arg => return Err(Error::new_spanned(arg, "unknown state attribute")),
+        }
+    }
+
+    let kind = match (node, skip, since, expr) {
+        (true, false, None, None) => Kind::Node,
+        (false, true, None, None) => Kind::Skipped(None),
+        (false, true, None, Some((true, expr))) => Kind::Skipped(Some(expr)),
+        (false, false, Some(since), None) => Kind::Since(since, None),
+        (false, false, Some(since), Some((false, expr))) => Kind::Since(since, Some(expr)),
+        (false, false, None, None) => Kind::Dumped,
+        _ => {
+            return Err(Error::new_spanned(
+                &field,
+                "expected one of node, skip, skip with, since or since with default",
+            ))
+        }
+    };
+    Ok(Field {
+        ident,
+        ty: field.ty,
+        kind,
+    })
+}
+
+fn try_engine_state(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let ident = input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut version = 1;
+    for arg in state_args(&input.attrs)? {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("version") => {
+                version = parse_version(&nv.lit)?;
+            }
+            arg => return Err(Error::new_spanned(arg, "unknown state attribute")),
+        }
+    }
+
+    let fields = match input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => fields.named,
+        _ => {
+            return Err(Error::new_spanned(
+                &ident,
+                "EngineState can only be derived for a struct with named fields",
+            ))
+        }
+    };
+    let fields = fields
+        .into_iter()
+        .map(parse_field)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut nodes = fields.iter().filter(|f| matches!(f.kind, Kind::Node));
+    let node = match (nodes.next(), nodes.next()) {
+        (Some(node), None) => &node.ident,
+        _ => {
+            return Err(Error::new_spanned(
+                &ident,
+                "exactly one field must be marked #[state(node)]",
+            ))
+        }
+    };
+
+    for field in &fields {
+        if let Kind::Since(since, _) = field.kind {
+            if since > version {
+                return Err(Error::new_spanned(
+                    &field.ident,
+                    "a field cannot be added after the version of the state",
+                ));
+            }
+        }
+    }
+
+    let dumped: Vec<_> = fields
+        .iter()
+        .filter(|f| matches!(f.kind, Kind::Dumped | Kind::Since(..)))
+        .map(|f| {
+            let ident = &f.ident;
+            let key = ident.to_string();
+            quote! {
+                collections.insert(#key.to_string(), ::std::boxed::Box::new(engine.#ident));
+            }
+        })
+        .collect();
+    let capacity = dumped.len() + 1;
+
+    let restored: Vec<_> = fields
+        .iter()
+        .map(|f| {
+            let ident = &f.ident;
+            let ty = &f.ty;
+            let key = ident.to_string();
+            let take = quote! {
+                ::phoenix_common::engine::decompose::take_state::<#ty>(&mut __local, #key)?
+            };
+            let default = |expr: &Option<Expr>| match expr {
+                Some(expr) => quote!(#expr),
+                None => quote!(::std::default::Default::default()),
+            };
+            let value = match &f.kind {
+                Kind::Dumped => take,
+                Kind::Node => quote!(node),
+                Kind::Skipped(with) => default(with),
+                Kind::Since(since, expr) => {
+                    let default = default(expr);
+                    quote! {
+                        if __state_version >= #since { #take } else { #default }
+                    }
+                }
+            };
+            quote! {
+                let #ident: #ty = #value;
+            }
+        })
+        .collect();
+    let idents: Vec<_> = fields.iter().map(|f| &f.ident).collect();
+    let name = ident.to_string();
+
+    Ok(quote! {
+        impl #impl_generics ::phoenix_common::engine::EngineState for #ident #ty_generics #where_clause {
+            const STATE_VERSION: u32 = #version;
+
+            fn dump(
+                self,
+            ) -> (
+                ::phoenix_common::storage::ResourceCollection,
+                ::phoenix_common::engine::datapath::DataPathNode,
+            ) {
+                ::phoenix_common::tracing::trace!("dumping {} states...", #name);
+                let engine = self;
+                let mut collections =
+                    ::phoenix_common::storage::ResourceCollection::with_capacity(#capacity);
+                collections.insert(
+                    ::phoenix_common::engine::decompose::STATE_VERSION_KEY.to_string(),
+                    ::std::boxed::Box::new(Self::STATE_VERSION),
+                );
+                #(#dumped)*
+                (collections, engine.#node)
+            }
+
+            #[allow(unused_variables)]
+            fn restore(
+                mut __local: ::phoenix_common::storage::ResourceCollection,
+                shared: &mut ::phoenix_common::storage::SharedStorage,
+                global: &mut ::phoenix_common::storage::ResourceCollection,
+                node: ::phoenix_common::engine::datapath::DataPathNode,
+                plugged: &::phoenix_common::module::ModuleCollection,
+                prev_version: ::phoenix_common::module::Version,
+            ) -> ::phoenix_common::PhoenixResult<Self> {
+                ::phoenix_common::tracing::trace!("restoring {} states...", #name);
+                let __state_version =
+                    ::phoenix_common::engine::decompose::state_version(&mut __local, Self::STATE_VERSION)?;
+                #(#restored)*
+                Ok(Self { #(#idents),* })
+            }
+        }
+    })
+}
+
+/// Derives `EngineState`, see the crate documentation for the attributes.
+#[proc_macro_derive(EngineState, attributes(state))]
+pub fn engine_state(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    try_engine_state(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use syn::parse_quote;
+
+    fn expand_err(input: DeriveInput) -> String {
+        match try_engine_state(input) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn expands_versioned_state() {
+        let input: DeriveInput = parse_quote! {
+            #[state(version = 3)]
+            struct Engine {
+                #[state(node)]
+                node: DataPathNode,
+                count: u64,
+                #[state(skip)]
+                indicator: Indicator,
+                #[state(skip, with = "count + 1")]
+                next: u64,
+                #[state(since = 2, default = "5")]
+                added: u32,
+            }
+        };
+        let expanded = try_engine_state(input).unwrap().to_string();
+        assert!(
+            expanded.contains("const STATE_VERSION : u32 = 3u32"),
+            "{expanded}"
+        );
+        // only the dumped fields and the version are inserted
+        assert_eq!(expanded.matches("collections . insert").count(), 3);
+        assert!(expanded.contains("\"count\""));
+        assert!(expanded.contains("\"added\""));
+        assert!(!expanded.contains("\"indicator\""));
+        assert!(!expanded.contains("\"next\""));
+        assert!(expanded.contains("let next : u64 = count + 1"));
+        assert!(expanded.contains("if __state_version >= 2u32"));
+    }
+
+    #[test]
+    fn defaults_to_version_one() {
+        let input: DeriveInput = parse_quote! {
+            struct Engine {
+                #[state(node)]
+                node: DataPathNode,
+            }
+        };
+        let expanded = try_engine_state(input).unwrap().to_string();
+        assert!(
+            expanded.contains("const STATE_VERSION : u32 = 1u32"),
+            "{expanded}"
+        );
+    }
+
+    #[test]
+    fn rejects_field_after_version() {
+        let input: DeriveInput = parse_quote! {
+            #[state(version = 2)]
+            struct Engine {
+                #[state(node)]
+                node: DataPathNode,
+                #[state(since = 3)]
+                added: u32,
+            }
+        };
+        assert!(expand_err(input).contains("after the version"));
+    }
+
+    #[test]
+    fn rejects_node_count() {
+        let none: DeriveInput = parse_quote! {
+            struct Engine {
+                count: u64,
+            }
+        };
+        assert!(expand_err(none).contains("exactly one field"));
+        let two: DeriveInput = parse_quote! {
+            struct Engine {
+                #[state(node)]
+                a: DataPathNode,
+                #[state(node)]
+                b: DataPathNode,
+            }
+        };
+        assert!(expand_err(two).contains("exactly one field"));
+    }
+
+    #[test]
+    fn rejects_conflicting_attributes() {
+        let input: DeriveInput = parse_quote! {
+            struct Engine {
+                #[state(node)]
+                node: DataPathNode,
+                #[state(skip, since = 2)]
+                added: u32,
+            }
+        };
+        assert!(expand_err(input).contains("expected one of"));
+        let input: DeriveInput = parse_quote! {
+            struct Engine {
+                #[state(node)]
+                node: DataPathNode,
+                #[state(skip, default = "1")]
+                added: u32,
+            }
+        };
+        assert!(expand_err(input).contains("expected one of"));
+        let input: DeriveInput = parse_quote! {
+            struct Engine {
+                #[state(node)]
+                node: DataPathNode,
+                #[state(renamed)]
+                added: u32,
+            }
+        };
+        assert!(expand_err(input).contains("unknown state attribute"));
+    }
+}
@@ -0,0 +1,249 @@
+//! Collective operations across a group of processes, e.g., to synchronize the parameters of a
+//! model among its trainers.
+//!
+//! The operations run in the Collective engine of each member, on the data of a [`Buffer`]
+//! shared with the engine, so the data is not copied between the app and the backend.
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::slice::{self, SliceIndex};
+
+use memfd::Memfd;
+use memmap2::{MmapOptions, MmapRaw};
+
+use ipc::service::ShmService;
+use phoenix_api::buf::Range;
+pub use phoenix_api::collective::cmd::{Algorithm, DataType, ReduceOp};
+use phoenix_api::collective::cmd::{Command, CompletionKind};
+use phoenix_api::collective::{cmd, dp};
+use phoenix_api::Handle;
+
+use crate::transport::SCHEDULING_HINT;
+use crate::{rx_recv_impl, Error, PHOENIX_CONTROL_SOCK, PHOENIX_PREFIX};
+
+thread_local! {
+    pub(crate) static COLL_CTX: Context = Context::register().expect("phoenix collective register failed");
+}
+
+pub(crate) struct Context {
+    service: ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>,
+}
+
+impl Context {
+    fn register() -> Result<Context, Error> {
+        let service = ShmService::register(
+            &*PHOENIX_PREFIX,
+            &*PHOENIX_CONTROL_SOCK,
+            "Collective".to_string(),
+            SCHEDULING_HINT.with_borrow(|h| *h),
+            None,
+        )?;
+        Ok(Self { service })
+    }
+}
+
+/// The types of the elements that can be reduced.
+pub trait Element: Copy {
+    const DATA_TYPE: DataType;
+}
+
+macro_rules! impl_element {
+    ($($ty:ty => $dtype:ident),*) => {$(
+        impl Element for $ty {
+            const DATA_TYPE: DataType = DataType::$dtype;
+        }
+    )*};
+}
+
+impl_element!(i32 => I32, i64 => I64, u32 => U32, u64 => U64, f32 => F32, f64 => F64);
+
+/// A buffer of `T` shared with the backend. The collective operations work on the buffers in
+/// place.
+#[derive(Debug)]
+pub struct Buffer<T> {
+    handle: Handle,
+    mmap: MmapRaw,
+    _memfd: Memfd,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Deref for Buffer<T> {
+    type Target = [T];
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            slice::from_raw_parts(
+                self.mmap.as_ptr().cast(),
+                self.mmap.len() / mem::size_of::<T>(),
+            )
+        }
+    }
+}
+
+impl<T> DerefMut for Buffer<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.mmap.as_mut_ptr().cast(),
+                self.mmap.len() / mem::size_of::<T>(),
+            )
+        }
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        (|| {
+            COLL_CTX.with(|ctx| {
+                let req = Command::DeregBuffer(self.handle);
+                ctx.service.send_cmd(req)?;
+                rx_recv_impl!(ctx.service, CompletionKind::DeregBuffer)
+            })
+        })()
+        .unwrap_or_else(|e| eprintln!("Dropping Buffer: {}", e));
+    }
+}
+
+impl<T: Copy> Buffer<T> {
+    /// Creates a buffer of `len` elements, all bytes zero.
+    pub fn new(len: usize) -> Result<Self, Error> {
+        let nbytes = len * mem::size_of::<T>();
+        let req = Command::RegBuffer(nbytes);
+        COLL_CTX.with(|ctx| {
+            ctx.service.send_cmd(req)?;
+            let fds = ctx.service.recv_fd()?;
+            if fds.is_empty() {
+                // the buffer is not created, the completion carries the error
+                return match ctx.service.recv_comp()?.0 {
+                    Err(e) => Err(Error::Interface("CompletionKind::RegBuffer", e)),
+                    otherwise => panic!(
+                        "Expect an error for a Buffer without fd, found {:?}",
+                        otherwise
+                    ),
+                };
+            }
+
+            assert_eq!(fds.len(), 1);
+
+            let memfd = Memfd::try_from_fd(fds[0]).map_err(|_| io::Error::last_os_error())?;
+            let file_len = memfd.as_file().metadata()?.len() as usize;
+            assert!(file_len >= nbytes);
+
+            match ctx.service.recv_comp()?.0 {
+                Ok(CompletionKind::RegBuffer(handle, _len)) => {
+                    let mmap = MmapOptions::new().map_raw(memfd.as_file())?;
+                    Ok(Buffer {
+                        handle,
+                        mmap,
+                        _memfd: memfd,
+                        _marker: PhantomData,
+                    })
+                }
+                Err(e) => Err(Error::Interface("CompletionKind::RegBuffer", e)),
+                otherwise => panic!("Expect RegBuffer, found {:?}", otherwise),
+            }
+        })
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+/// A group of processes taking part in collective operations. Every member has to run the same
+/// operations on the group in the same order.
+#[derive(Debug)]
+pub struct Group {
+    handle: Handle,
+    rank: usize,
+    size: usize,
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        (|| {
+            COLL_CTX.with(|ctx| {
+                let req = Command::DestroyGroup(self.handle);
+                ctx.service.send_cmd(req)?;
+                rx_recv_impl!(ctx.service, CompletionKind::DestroyGroup)
+            })
+        })()
+        .unwrap_or_else(|e| eprintln!("Dropping Group: {}", e));
+    }
+}
+
+impl Group {
+    /// Joins the group of `members` as the member `rank`, and waits for all the other members
+    /// to join. Every member passes the same addresses, the backend of the member `rank` listens
+    /// on `members[rank]`.
+    pub fn new(members: &[SocketAddr], rank: usize) -> Result<Self, Error> {
+        let req = Command::CreateGroup(members.to_vec(), rank);
+        COLL_CTX.with(|ctx| {
+            ctx.service.send_cmd(req)?;
+            match ctx.service.recv_comp()?.0 {
+                Ok(CompletionKind::CreateGroup(handle, size)) => Ok(Group { handle, rank, size }),
+                Err(e) => Err(Error::Interface("CompletionKind::CreateGroup", e)),
+                otherwise => panic!("Expect CreateGroup, found {:?}", otherwise),
+            }
+        })
+    }
+
+    #[inline]
+    pub fn rank(&self) -> usize {
+        self.rank
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Copies `range` of the buffer of the member `root` to the same range of the buffers of
+    /// all members.
+    pub fn broadcast<T, R>(
+        &self,
+        buf: &mut Buffer<T>,
+        range: R,
+        root: usize,
+        algorithm: Algorithm,
+    ) -> Result<(), Error>
+    where
+        T: Copy,
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let range = Range::new(buf.as_slice(), range);
+        let req = Command::Broadcast(self.handle, buf.handle, range, root, algorithm);
+        COLL_CTX.with(|ctx| {
+            ctx.service.send_cmd(req)?;
+            rx_recv_impl!(ctx.service, CompletionKind::Broadcast)
+        })
+    }
+
+    /// Combines `range` of the buffers of all members by `op`, and leaves the result in that
+    /// range of every buffer.
+    pub fn all_reduce<T, R>(
+        &self,
+        buf: &mut Buffer<T>,
+        range: R,
+        op: ReduceOp,
+        algorithm: Algorithm,
+    ) -> Result<(), Error>
+    where
+        T: Element,
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let range = Range::new(buf.as_slice(), range);
+        let req = Command::AllReduce(self.handle, buf.handle, range, T::DATA_TYPE, op, algorithm);
+        COLL_CTX.with(|ctx| {
+            ctx.service.send_cmd(req)?;
+            rx_recv_impl!(ctx.service, CompletionKind::AllReduce)
+        })
+    }
+}
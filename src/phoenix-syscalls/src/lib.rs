@@ -3,6 +3,7 @@ use std::borrow::Borrow;
 use std::env;
 use std::path::PathBuf;
 
+pub mod collective;
 pub mod transport;
 
 // Re-exports
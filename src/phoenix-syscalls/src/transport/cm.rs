@@ -14,6 +14,7 @@ use verbs::{ConnParam, ProtectionDomain, QpInitAttr};
 
 // Re-exports
 pub use phoenix_api::addrinfo::{AddrFamily, AddrInfo, AddrInfoFlags, AddrInfoHints, PortSpace};
+pub use phoenix_api::transport::rdma::cmd::ConnEvent;
 
 /// Address and route resolution service.
 pub fn getaddrinfo(
@@ -341,6 +342,18 @@ impl CmId {
     ) -> Result<CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq>, Error> {
         CmIdBuilder::new().resolve_route(addr)
     }
+
+    /// Returns the next change of the connection, if any, when it is recovered by the transport
+    /// from failures of the queue pair.
+    pub fn try_get_event(&self) -> Result<Option<ConnEvent>, Error> {
+        KL_CTX.with(|ctx| {
+            let req = Command::TryGetConnEvent(self.inner.handle);
+            ctx.service.send_cmd(req)?;
+            rx_recv_impl!(ctx.service, CompletionKind::TryGetConnEvent, event, {
+                Ok(event)
+            })
+        })
+    }
 }
 
 macro_rules! impl_for_cmid {
@@ -0,0 +1,223 @@
+//! Dynamically connected transport, available when the transport is built with the `dc` feature.
+//!
+//! A DC target is set on a `PreparedCmId` before it connects or accepts, and a DC peer is created
+//! for a DC initiator from the `CmId` afterwards. The `CmId` can be dropped then.
+use std::slice::SliceIndex;
+
+use phoenix_api::transport::rdma::cmd::{Command, CompletionKind};
+use phoenix_api::transport::rdma::dp::WorkRequest;
+use phoenix_api::{buf, net};
+
+use crate::rx_recv_impl;
+use crate::transport::cm::{CmId, PreparedCmId};
+use crate::transport::verbs::{self, CompletionQueue, ProtectionDomain};
+use crate::transport::{Error, KL_CTX};
+
+/// The receiving side, whose receives complete on the completion queue it is created with.
+#[derive(Debug)]
+pub struct DcTarget {
+    pub(crate) inner: net::DcTarget,
+}
+
+impl Drop for DcTarget {
+    fn drop(&mut self) {
+        (|| {
+            let req = Command::DestroyDcTarget(self.inner);
+            KL_CTX.with(|ctx| {
+                ctx.service.send_cmd(req)?;
+                rx_recv_impl!(ctx.service, CompletionKind::DestroyDcTarget)
+            })
+        })()
+        .unwrap_or_else(|e| eprintln!("Dropping DcTarget: {}", e));
+    }
+}
+
+impl DcTarget {
+    pub fn new(
+        pd: &ProtectionDomain,
+        cq: &CompletionQueue,
+        max_recv_wr: u32,
+    ) -> Result<Self, Error> {
+        KL_CTX.with(|ctx| {
+            let req = Command::CreateDcTarget(pd.inner, cq.inner, max_recv_wr);
+            ctx.service.send_cmd(req)?;
+            rx_recv_impl!(ctx.service, CompletionKind::CreateDcTarget, inner, {
+                Ok(DcTarget { inner })
+            })
+        })
+    }
+
+    /// # Safety
+    ///
+    /// See [`CmId::post_recv`].
+    #[inline]
+    pub unsafe fn post_recv<T, R>(
+        &self,
+        mr: &mut verbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let req = WorkRequest::PostDcRecv(
+            self.inner.0,
+            context,
+            buf::Range::new(mr, range),
+            mr.inner.0,
+        );
+        post(req)
+    }
+}
+
+/// The sending side, which reaches any number of peers.
+#[derive(Debug)]
+pub struct DcInitiator {
+    pub(crate) inner: net::DcInitiator,
+}
+
+impl Drop for DcInitiator {
+    fn drop(&mut self) {
+        (|| {
+            let req = Command::DestroyDcInitiator(self.inner);
+            KL_CTX.with(|ctx| {
+                ctx.service.send_cmd(req)?;
+                rx_recv_impl!(ctx.service, CompletionKind::DestroyDcInitiator)
+            })
+        })()
+        .unwrap_or_else(|e| eprintln!("Dropping DcInitiator: {}", e));
+    }
+}
+
+impl DcInitiator {
+    pub fn new(
+        pd: &ProtectionDomain,
+        cq: &CompletionQueue,
+        max_send_wr: u32,
+    ) -> Result<Self, Error> {
+        KL_CTX.with(|ctx| {
+            let req = Command::CreateDcInitiator(pd.inner, cq.inner, max_send_wr);
+            ctx.service.send_cmd(req)?;
+            rx_recv_impl!(ctx.service, CompletionKind::CreateDcInitiator, inner, {
+                Ok(DcInitiator { inner })
+            })
+        })
+    }
+
+    /// Creates a peer for the DC target the other end of `cmid` advertised.
+    pub fn create_peer(&self, cmid: &CmId) -> Result<DcPeer, Error> {
+        KL_CTX.with(|ctx| {
+            let req = Command::CreateDcPeer(self.inner, cmid.inner.handle);
+            ctx.service.send_cmd(req)?;
+            rx_recv_impl!(ctx.service, CompletionKind::CreateDcPeer, inner, {
+                Ok(DcPeer { inner })
+            })
+        })
+    }
+
+    /// # Safety
+    ///
+    /// See [`CmId::post_send`].
+    #[inline]
+    pub unsafe fn post_send<T, R>(
+        &self,
+        peer: &DcPeer,
+        mr: &verbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+        flags: verbs::SendFlags,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let req = WorkRequest::PostDcSend(
+            self.inner.0,
+            peer.inner.0,
+            context,
+            buf::Range::new(mr, range),
+            mr.inner.0,
+            flags,
+        );
+        post(req)
+    }
+
+    /// # Safety
+    ///
+    /// See [`CmId::post_write`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_write<T, R>(
+        &self,
+        peer: &DcPeer,
+        mr: &verbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+        flags: verbs::SendFlags,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let req = WorkRequest::PostDcWrite(
+            self.inner.0,
+            peer.inner.0,
+            context,
+            buf::Range::new(mr, range),
+            mr.inner.0,
+            remote_offset,
+            rkey,
+            flags,
+        );
+        post(req)
+    }
+}
+
+/// A DC target reached by a DC initiator.
+#[derive(Debug)]
+pub struct DcPeer {
+    pub(crate) inner: net::DcPeer,
+}
+
+impl Drop for DcPeer {
+    fn drop(&mut self) {
+        (|| {
+            let req = Command::DestroyDcPeer(self.inner);
+            KL_CTX.with(|ctx| {
+                ctx.service.send_cmd(req)?;
+                rx_recv_impl!(ctx.service, CompletionKind::DestroyDcPeer)
+            })
+        })()
+        .unwrap_or_else(|e| eprintln!("Dropping DcPeer: {}", e));
+    }
+}
+
+impl PreparedCmId {
+    /// Advertises `dct` to the other end when connecting or accepting.
+    pub fn set_dc_target(&self, dct: &DcTarget) -> Result<(), Error> {
+        KL_CTX.with(|ctx| {
+            let req = Command::SetDcTarget(self.inner.handle, dct.inner);
+            ctx.service.send_cmd(req)?;
+            rx_recv_impl!(ctx.service, CompletionKind::SetDcTarget)
+        })
+    }
+}
+
+fn post(req: WorkRequest) -> Result<(), Error> {
+    KL_CTX.with(|ctx| {
+        // This WR must be successfully sent.
+        let mut sent = false;
+        while !sent {
+            ctx.service.enqueue_wr_with(|ptr, count| {
+                debug_assert!(count >= 1);
+                unsafe { ptr.cast::<WorkRequest>().write(req) };
+                sent = true;
+                1
+            })?;
+            if !sent {
+                ctx.progress()?;
+            }
+        }
+        Ok(())
+    })
+}
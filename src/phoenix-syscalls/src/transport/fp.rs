@@ -216,6 +216,292 @@ impl CmId {
         })
     }
 
+    /// Compares the u64 at `remote_offset` with `compare`, and replaces it by `swap` if equal. The
+    /// original remote value is written to `range`, which must span 8 bytes.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `CompletionQueue::poll_cq` returns a completion for this send).
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_compare_and_swap<T, R>(
+        &self,
+        mr: &mut verbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+        flags: verbs::SendFlags,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+        compare: u64,
+        swap: u64,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let req = WorkRequest::PostCompareAndSwap(
+            self.inner.handle.0,
+            mr.inner.0,
+            context,
+            buf::Range::new(mr, range),
+            remote_offset,
+            rkey,
+            flags,
+            compare,
+            swap,
+        );
+        KL_CTX.with(|ctx| {
+            let mut sent = false;
+            while !sent {
+                ctx.service.enqueue_wr_with(|ptr, count| {
+                    debug_assert!(count >= 1);
+                    ptr.cast::<WorkRequest>().write(req);
+                    sent = true;
+                    1
+                })?;
+                if !sent {
+                    ctx.progress()?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Adds `add` to the field of the u64 at `remote_offset` selected by `mask`, a contiguous run
+    /// of bits, e.g., `0xffff_ffff` for a 4-byte operand in the low half. Carries out of the field
+    /// are dropped. The original remote value is written to `range`, which must span 8 bytes.
+    ///
+    /// Only a full mask is a native fetch-and-add, others are done by the service as a sequence
+    /// of compare-and-swaps and are not atomic with respect to RDMA writes.
+    ///
+    /// # Safety
+    ///
+    /// See [`CmId::post_compare_and_swap`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_fetch_and_add<T, R>(
+        &self,
+        mr: &mut verbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+        flags: verbs::SendFlags,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+        add: u64,
+        mask: u64,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let req = WorkRequest::PostFetchAndAdd(
+            self.inner.handle.0,
+            mr.inner.0,
+            context,
+            buf::Range::new(mr, range),
+            remote_offset,
+            rkey,
+            flags,
+            add,
+            mask,
+        );
+        KL_CTX.with(|ctx| {
+            let mut sent = false;
+            while !sent {
+                ctx.service.enqueue_wr_with(|ptr, count| {
+                    debug_assert!(count >= 1);
+                    ptr.cast::<WorkRequest>().write(req);
+                    sent = true;
+                    1
+                })?;
+                if !sent {
+                    ctx.progress()?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Compares the bits of `compare_mask` of the u64 at `remote_offset` with `compare`, and
+    /// replaces the bits of `swap_mask` by `swap` if equal. The original remote value is written
+    /// to `range`, which must span 8 bytes.
+    ///
+    /// Only full masks are a native compare-and-swap, others are done by the service as a
+    /// sequence of compare-and-swaps and are not atomic with respect to RDMA writes.
+    ///
+    /// # Safety
+    ///
+    /// See [`CmId::post_compare_and_swap`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_masked_compare_and_swap<T, R>(
+        &self,
+        mr: &mut verbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+        flags: verbs::SendFlags,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+        compare: u64,
+        compare_mask: u64,
+        swap: u64,
+        swap_mask: u64,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        let req = WorkRequest::PostMaskedCompareAndSwap(
+            self.inner.handle.0,
+            mr.inner.0,
+            context,
+            buf::Range::new(mr, range),
+            remote_offset,
+            rkey,
+            flags,
+            compare,
+            compare_mask,
+            swap,
+            swap_mask,
+        );
+        KL_CTX.with(|ctx| {
+            let mut sent = false;
+            while !sent {
+                ctx.service.enqueue_wr_with(|ptr, count| {
+                    debug_assert!(count >= 1);
+                    ptr.cast::<WorkRequest>().write(req);
+                    sent = true;
+                    1
+                })?;
+                if !sent {
+                    ctx.progress()?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Reads `range.len()` bytes at `offset` of a region exposed by the peer into `range`.
+    ///
+    /// Returns [`Error::RemoteAccess`] if the region does not permit the read.
+    ///
+    /// # Safety
+    ///
+    /// See [`CmId::post_read`].
+    #[inline]
+    pub unsafe fn read_remote<T: Copy>(
+        &self,
+        mr: &mut verbs::MemoryRegion<T>,
+        range: std::ops::Range<usize>,
+        context: u64,
+        flags: verbs::SendFlags,
+        region: &net::RemoteRegion,
+        offset: u64,
+    ) -> Result<(), Error> {
+        let len = (range.len() * mem::size_of::<T>()) as u64;
+        if !region.permits(net::AccessFlags::REMOTE_READ, offset, len) {
+            return Err(Error::RemoteAccess);
+        }
+        self.post_read(mr, range, context, flags, region.key, offset)
+    }
+
+    /// Writes `range` to `offset` of a region exposed by the peer.
+    ///
+    /// Returns [`Error::RemoteAccess`] if the region does not permit the write.
+    ///
+    /// # Safety
+    ///
+    /// See [`CmId::post_write`].
+    #[inline]
+    pub unsafe fn write_remote<T: Copy>(
+        &self,
+        mr: &verbs::MemoryRegion<T>,
+        range: std::ops::Range<usize>,
+        context: u64,
+        flags: verbs::SendFlags,
+        region: &net::RemoteRegion,
+        offset: u64,
+    ) -> Result<(), Error> {
+        let len = (range.len() * mem::size_of::<T>()) as u64;
+        if !region.permits(net::AccessFlags::REMOTE_WRITE, offset, len) {
+            return Err(Error::RemoteAccess);
+        }
+        self.post_write(mr, range, context, flags, region.key, offset)
+    }
+
+    /// Compares-and-swaps the u64 at `offset` of a region exposed by the peer, the original value
+    /// is written to `mr[index]`.
+    ///
+    /// Returns [`Error::RemoteAccess`] if the region does not permit atomics at `offset`.
+    ///
+    /// # Safety
+    ///
+    /// See [`CmId::post_compare_and_swap`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn compare_and_swap_remote(
+        &self,
+        mr: &mut verbs::MemoryRegion<u64>,
+        index: usize,
+        context: u64,
+        flags: verbs::SendFlags,
+        region: &net::RemoteRegion,
+        offset: u64,
+        compare: u64,
+        swap: u64,
+    ) -> Result<(), Error> {
+        let len = mem::size_of::<u64>() as u64;
+        if !region.permits(net::AccessFlags::REMOTE_ATOMIC, offset, len) {
+            return Err(Error::RemoteAccess);
+        }
+        self.post_compare_and_swap(
+            mr,
+            index..index + 1,
+            context,
+            flags,
+            region.key,
+            offset,
+            compare,
+            swap,
+        )
+    }
+
+    /// Adds `add` to the field of `mask` of the u64 at `offset` of a region exposed by the peer,
+    /// the original value is written to `mr[index]`.
+    ///
+    /// Returns [`Error::RemoteAccess`] if the region does not permit atomics at `offset`.
+    ///
+    /// # Safety
+    ///
+    /// See [`CmId::post_fetch_and_add`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn fetch_and_add_remote(
+        &self,
+        mr: &mut verbs::MemoryRegion<u64>,
+        index: usize,
+        context: u64,
+        flags: verbs::SendFlags,
+        region: &net::RemoteRegion,
+        offset: u64,
+        add: u64,
+        mask: u64,
+    ) -> Result<(), Error> {
+        let len = mem::size_of::<u64>() as u64;
+        if !region.permits(net::AccessFlags::REMOTE_ATOMIC, offset, len) {
+            return Err(Error::RemoteAccess);
+        }
+        self.post_fetch_and_add(
+            mr,
+            index..index + 1,
+            context,
+            flags,
+            region.key,
+            offset,
+            add,
+            mask,
+        )
+    }
+
     #[inline]
     pub fn get_send_comp(&self) -> Result<verbs::WorkCompletion, Error> {
         let mut wc = Vec::with_capacity(1);
@@ -245,7 +531,7 @@ impl CmId {
 
 impl Context {
     #[inline]
-    fn progress(&self) -> Result<(), Error> {
+    pub(crate) fn progress(&self) -> Result<(), Error> {
         // Poll the shared memory queue, and put into the local buffer. This is called progress
         // because if the dp_cq is full, it may cause the program to hang unnecessarily.
         self.service.dequeue_wc_with(|ptr, count| unsafe {
@@ -6,13 +6,14 @@ use lazy_static::lazy_static;
 use thiserror::Error;
 
 use ipc::service::ShmService;
-pub use phoenix_api::engine::SchedulingHint;
+pub use phoenix_api::engine::{SchedulingClass, SchedulingHint};
 use phoenix_api::transport::rdma::control_plane::Setting;
 use phoenix_api::transport::rdma::{cmd, dp};
 
 use crate::{PHOENIX_CONTROL_SOCK, PHOENIX_PREFIX};
 
 pub mod cm;
+pub mod dc;
 mod fp;
 pub mod verbs;
 
@@ -73,4 +74,6 @@ pub enum Error {
     NoAddrResolved,
     #[error("Connect failed: {0}")]
     Connect(phoenix_api::Error),
+    #[error("Remote access not permitted by the region")]
+    RemoteAccess,
 }
@@ -22,7 +22,7 @@ use crate::{rx_recv_impl, FromBorrow};
 
 // Re-exports
 pub use phoenix_api::net::{AccessFlags, SendFlags, WcFlags, WcOpcode, WcStatus, WorkCompletion};
-pub use phoenix_api::net::{QpCapability, QpType, RemoteKey};
+pub use phoenix_api::net::{QpCapability, QpType, RemoteKey, RemoteRegion};
 
 lazy_static! {
     pub static ref DEFAULT_PDS: Vec<ProtectionDomain> =
@@ -134,6 +134,12 @@ impl ProtectionDomain {
         KL_CTX.with(|ctx| {
             ctx.service.send_cmd(req)?;
             let fds = ctx.service.recv_fd()?;
+            if fds.is_empty() {
+                // the registration is refused, the completion carries the error
+                return rx_recv_impl!(ctx.service, CompletionKind::RegMr, _mr, {
+                    panic!("Expect an error for a MemoryRegion without fd")
+                });
+            }
 
             assert_eq!(fds.len(), 1);
 
@@ -142,7 +148,7 @@ impl ProtectionDomain {
             assert!(file_len >= nbytes);
 
             rx_recv_impl!(ctx.service, CompletionKind::RegMr, mr, {
-                MemoryRegion::new(self.inner, mr.handle, mr.rkey, memfd)
+                MemoryRegion::new(self.inner, mr.handle, mr.rkey, access, memfd)
             })
         })
     }
@@ -245,6 +251,7 @@ pub struct MemoryRegion<T> {
     pub(crate) inner: net::MemoryRegion,
     mmap: MmapRaw,
     rkey: RemoteKey,
+    access: AccessFlags,
     // offset between the remote mapped shared memory address and the local shared memory in bytes
     _memfd: Memfd,
     _pd: ProtectionDomain,
@@ -292,12 +299,14 @@ impl<T: Sized + Copy> MemoryRegion<T> {
         pd: net::ProtectionDomain,
         inner: net::MemoryRegion,
         rkey: RemoteKey,
+        access: AccessFlags,
         memfd: Memfd,
     ) -> Result<Self, Error> {
         let mmap = MmapOptions::new().map_raw(memfd.as_file())?;
         Ok(MemoryRegion {
             inner,
             rkey,
+            access,
             mmap,
             _pd: ProtectionDomain::open(returned::ProtectionDomain { handle: pd })?,
             _memfd: memfd,
@@ -320,6 +329,18 @@ impl<T: Sized + Copy> MemoryRegion<T> {
         self.rkey
     }
 
+    /// Returns the handle to give to the peers that access the region by one-sided operations.
+    #[inline]
+    pub fn remote_region(&self) -> RemoteRegion {
+        let remote =
+            AccessFlags::REMOTE_READ | AccessFlags::REMOTE_WRITE | AccessFlags::REMOTE_ATOMIC;
+        RemoteRegion {
+            key: self.rkey,
+            len: self.mmap.len() as u64,
+            access: self.access & remote,
+        }
+    }
+
     #[inline]
     pub fn as_ptr(&self) -> *const T {
         self.mmap.as_ptr() as *const T
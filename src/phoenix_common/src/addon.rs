@@ -4,6 +4,7 @@ pub use semver::Version;
 
 use phoenix_api::engine::SchedulingMode;
 
+use crate::capability::Capabilities;
 use crate::engine::datapath::node::DataPathNode;
 use crate::engine::{Engine, EngineType};
 use crate::envelop::TypeTagged;
@@ -37,6 +38,12 @@ pub trait PhoenixAddon: TypeTagged + Send + Sync + 'static {
         &[]
     }
 
+    /// What the engines need from the system, for the sandbox of the runtimes
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     /// Live update addon's (RPC policy's) configuration.
     fn update_config(&mut self, config: &str) -> Result<()>;
 
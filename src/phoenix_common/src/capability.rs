@@ -0,0 +1,115 @@
+//! What the engines of a plugin need from the system, beyond what the runtimes themselves need.
+//!
+//! When the sandbox is enabled in the config, each runtime thread only opens the paths and only
+//! makes the system calls declared by the plugins loaded when the daemon starts (Landlock and
+//! seccomp). A plugin loaded later must not declare more.
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Files and directories, recursively, the engines read.
+    pub read_paths: Vec<PathBuf>,
+    /// Files and directories, recursively, the engines read, write, and create or remove
+    /// entries in.
+    pub write_paths: Vec<PathBuf>,
+    /// Device files or directories of them the engines open, e.g., `/dev/infiniband`. Declaring
+    /// a device also allows `ioctl`.
+    pub devices: Vec<PathBuf>,
+    /// The system calls the engines make, by number, e.g., `libc::SYS_socket`.
+    pub syscalls: Vec<i64>,
+}
+
+fn merge_into<T: PartialEq + Clone>(into: &mut Vec<T>, from: &[T]) {
+    for x in from {
+        if !into.contains(x) {
+            into.push(x.clone());
+        }
+    }
+}
+
+fn is_beneath(path: &Path, dirs: &[PathBuf]) -> bool {
+    dirs.iter().any(|dir| path.starts_with(dir))
+}
+
+impl Capabilities {
+    /// Adds the capabilities of `other`.
+    pub fn merge(&mut self, other: &Capabilities) {
+        merge_into(&mut self.read_paths, &other.read_paths);
+        merge_into(&mut self.write_paths, &other.write_paths);
+        merge_into(&mut self.devices, &other.devices);
+        merge_into(&mut self.syscalls, &other.syscalls);
+    }
+
+    /// Whether `other` asks for nothing more.
+    pub fn covers(&self, other: &Capabilities) -> bool {
+        let readable: Vec<_> = self
+            .read_paths
+            .iter()
+            .chain(&self.write_paths)
+            .cloned()
+            .collect();
+        other.read_paths.iter().all(|p| is_beneath(p, &readable))
+            && other
+                .write_paths
+                .iter()
+                .all(|p| is_beneath(p, &self.write_paths))
+            && other.devices.iter().all(|p| is_beneath(p, &self.devices))
+            && other.syscalls.iter().all(|nr| self.syscalls.contains(nr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(read: &[&str], write: &[&str], devices: &[&str], syscalls: &[i64]) -> Capabilities {
+        Capabilities {
+            read_paths: read.iter().map(PathBuf::from).collect(),
+            write_paths: write.iter().map(PathBuf::from).collect(),
+            devices: devices.iter().map(PathBuf::from).collect(),
+            syscalls: syscalls.to_vec(),
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut merged = caps(&["/etc"], &["/tmp/phoenix"], &[], &[1, 2]);
+        merged.merge(&caps(&["/etc", "/sys"], &[], &["/dev/infiniband"], &[2, 3]));
+        assert_eq!(
+            merged,
+            caps(
+                &["/etc", "/sys"],
+                &["/tmp/phoenix"],
+                &["/dev/infiniband"],
+                &[1, 2, 3]
+            )
+        );
+
+        // merging again adds nothing
+        let before = merged.clone();
+        merged.merge(&before);
+        assert_eq!(merged, before);
+    }
+
+    #[test]
+    fn covers() {
+        let sandbox = caps(&["/sys"], &["/tmp/phoenix"], &["/dev/infiniband"], &[1, 2]);
+        assert!(sandbox.covers(&Capabilities::default()));
+        assert!(sandbox.covers(&sandbox));
+
+        // beneath the declared directories
+        assert!(sandbox.covers(&caps(&["/sys/class/infiniband"], &[], &[], &[])));
+        assert!(sandbox.covers(&caps(&[], &["/tmp/phoenix/cache"], &[], &[1])));
+        assert!(sandbox.covers(&caps(&[], &[], &["/dev/infiniband/uverbs0"], &[])));
+        // a writable path is readable
+        assert!(sandbox.covers(&caps(&["/tmp/phoenix/cache"], &[], &[], &[])));
+
+        // a readable path is not writable
+        assert!(!sandbox.covers(&caps(&[], &["/sys"], &[], &[])));
+        // components are compared, not strings
+        assert!(!sandbox.covers(&caps(&["/system"], &[], &[], &[])));
+        assert!(!sandbox.covers(&caps(&["/"], &[], &[], &[])));
+        assert!(!sandbox.covers(&caps(&[], &[], &["/dev/kvm"], &[])));
+        assert!(!sandbox.covers(&caps(&[], &[], &[], &[2, 3])));
+    }
+}
@@ -0,0 +1,172 @@
+//! A bump arena for the transient allocations of an engine on the datapath.
+//!
+//! An engine allocates from its arena what only lives while it processes a message, e.g., the
+//! scatter-gather list of a received message, and resets the arena at the start of each quantum,
+//! i.e., each round of its mainloop. Allocating bumps an offset in the current chunk. The chunks
+//! are kept across resets, so once the arena has grown to what a quantum needs, the engine no
+//! longer calls into the global allocator. Each chunk added is counted by
+//! [`metrics::record_datapath_allocation`].
+use std::alloc::{self, Layout};
+use std::cell::{Cell, UnsafeCell};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+use crate::metrics;
+
+/// The alignment of the chunks, and the largest alignment the arena can allocate.
+const CHUNK_ALIGN: usize = 64;
+
+/// The default size of a chunk.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+struct Chunk {
+    ptr: NonNull<u8>,
+    size: usize,
+}
+
+impl Chunk {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, CHUNK_ALIGN).unwrap();
+        // SAFETY: the size is not zero
+        let ptr = unsafe { alloc::alloc(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        metrics::record_datapath_allocation();
+        Chunk { ptr, size }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.size, CHUNK_ALIGN).unwrap();
+        // SAFETY: allocated in Chunk::new with the same layout
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), layout) };
+    }
+}
+
+/// A bump arena, reset by its engine at the start of each quantum.
+///
+/// Allocations borrow the arena, and [`reset`](Arena::reset) takes it mutably, so nothing
+/// allocated outlives the quantum it is allocated in. Only `Copy` types are allocated, as
+/// nothing is dropped on reset.
+pub struct Arena {
+    /// Only touched within one call of `alloc_layout`.
+    chunks: UnsafeCell<Vec<Chunk>>,
+    /// The index of the chunk allocated from.
+    current: Cell<usize>,
+    /// The offset of the next allocation in the current chunk.
+    offset: Cell<usize>,
+    chunk_size: usize,
+}
+
+// SAFETY: the arena owns its chunks, and is not Sync, so no allocation is shared with another
+// thread.
+unsafe impl Send for Arena {}
+
+impl std::fmt::Debug for Arena {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Arena")
+            .field("capacity", &self.capacity())
+            .field("current", &self.current.get())
+            .field("offset", &self.offset.get())
+            .finish()
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    /// Creates an arena of chunks of [`DEFAULT_CHUNK_SIZE`]. No chunk is allocated until the
+    /// first allocation.
+    pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Creates an arena of chunks of `chunk_size` bytes. An allocation larger than that gets a
+    /// chunk of its own size.
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk_size: {chunk_size}");
+        Arena {
+            chunks: UnsafeCell::new(Vec::new()),
+            current: Cell::new(0),
+            offset: Cell::new(0),
+            chunk_size,
+        }
+    }
+
+    /// The total size of the chunks allocated.
+    pub fn capacity(&self) -> usize {
+        // SAFETY: not borrowed outside of alloc_layout
+        unsafe { &*self.chunks.get() }.iter().map(|c| c.size).sum()
+    }
+
+    /// Frees all the allocations at once, keeping the chunks for the next quantum.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.current.set(0);
+        self.offset.set(0);
+    }
+
+    /// Copies `src` into the arena.
+    #[inline]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        let layout = Layout::for_value(src);
+        let dst = self.alloc_layout(layout).cast::<T>().as_ptr();
+        // SAFETY: dst is valid for the layout of src, and does not overlap it
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+            slice::from_raw_parts_mut(dst, src.len())
+        }
+    }
+
+    fn alloc_layout(&self, layout: Layout) -> NonNull<u8> {
+        assert!(layout.align() <= CHUNK_ALIGN, "layout: {layout:?}");
+        // SAFETY: the vector is not borrowed elsewhere. The allocations point into the chunks,
+        // which do not move when it grows.
+        let chunks = unsafe { &mut *self.chunks.get() };
+        loop {
+            if let Some(chunk) = chunks.get(self.current.get()) {
+                let start = (self.offset.get() + layout.align() - 1) & !(layout.align() - 1);
+                if start + layout.size() <= chunk.size {
+                    self.offset.set(start + layout.size());
+                    // SAFETY: start is within the chunk
+                    return unsafe { NonNull::new_unchecked(chunk.ptr.as_ptr().add(start)) };
+                }
+                if self.current.get() + 1 < chunks.len() {
+                    // the next chunk kept from a previous quantum
+                    self.current.set(self.current.get() + 1);
+                    self.offset.set(0);
+                    continue;
+                }
+            }
+            chunks.push(Chunk::new(self.chunk_size.max(layout.size())));
+            self.current.set(chunks.len() - 1);
+            self.offset.set(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_the_chunks_after_reset() {
+        let mut arena = Arena::with_chunk_size(256);
+        for _ in 0..2 {
+            let a = arena.alloc_slice_copy(&[1u8, 2, 3]);
+            let b = arena.alloc_slice_copy(&[4u64; 20]);
+            assert_eq!(a, [1, 2, 3]);
+            assert_eq!(b, [4; 20]);
+            assert_eq!(b.as_ptr() as usize % std::mem::align_of::<u64>(), 0);
+            // larger than a chunk
+            let c = arena.alloc_slice_copy(&[5u32; 100]);
+            assert_eq!(c, [5; 100]);
+            assert_eq!(arena.capacity(), 256 + 400);
+            arena.reset();
+        }
+    }
+}
@@ -1,3 +1,13 @@
+//! The messages passed between the engines on the data path.
+//!
+//! A message passed between the engines owns its meta and its payload, which are left where the
+//! application or the transport put them. An engine that only inspects or routes a message reads
+//! its meta through [`RpcMessageTx::meta`] and sends the message on, which moves the pointers and
+//! copies nothing. Changing the meta takes [`RpcMessageTx::meta_mut`], and so the message by value
+//! or by a unique borrow. Sending the message gives it up, so an engine cannot read it once the
+//! next engine may change it. A message is not `Clone`; an engine that sends the same payload
+//! again, e.g., to retry a call, builds a new message over the meta buffer and keeps the buffer
+//! alive until the calls complete.
 use std::ptr::Unique;
 
 use phoenix_api::rpc::{CallId, MessageMeta, RpcId, TransportStatus};
@@ -6,6 +16,7 @@ use phoenix_api_mrpc::dp::RECV_RECLAIM_BS;
 
 // use crate::mrpc::meta_pool::MetaBufferPtr;
 use super::meta_pool::MetaBufferPtr;
+use super::token;
 
 // TODO(cjr): Should be repr(C)
 
@@ -16,6 +27,32 @@ pub struct RpcMessageTx {
     pub addr_backend: usize,
 }
 
+impl RpcMessageTx {
+    /// Borrows the meta of the message, to inspect or route it.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the meta buffer has been released, see [`token`].
+    #[inline]
+    pub fn meta(&self) -> &MessageMeta {
+        token::check_not_reclaimed(self.meta_buf_ptr.addr());
+        // SAFETY: the meta buffer is valid and owned by the message until it is acknowledged
+        unsafe { &*self.meta_buf_ptr.as_meta_ptr() }
+    }
+
+    /// Borrows the meta of the message to change it.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the meta buffer has been released, see [`token`].
+    #[inline]
+    pub fn meta_mut(&mut self) -> &mut MessageMeta {
+        token::check_not_reclaimed(self.meta_buf_ptr.addr());
+        // SAFETY: the meta buffer is valid and owned by the message until it is acknowledged
+        unsafe { &mut *self.meta_buf_ptr.as_meta_ptr() }
+    }
+}
+
 #[derive(Debug)]
 pub enum EngineTxMessage {
     RpcMessage(RpcMessageTx),
@@ -27,6 +64,30 @@ pub struct RpcMessageRx {
     pub meta: Unique<MessageMeta>,
     pub addr_app: usize,
     pub addr_backend: usize,
+    /// The hop limit the message was received with. An engine that forwards the message sends
+    /// it on with this hop limit, see [`MetaBufferPool::obtain_forwarded`].
+    ///
+    /// [`MetaBufferPool::obtain_forwarded`]: super::meta_pool::MetaBufferPool::obtain_forwarded
+    pub hop_limit: u8,
+    /// The size of the message, if it is its struct alone at `addr_backend`, without data
+    /// elsewhere in the receive buffer, so that a copy of the struct is the whole message.
+    pub flat_len: Option<usize>,
+}
+
+impl RpcMessageRx {
+    /// Borrows the meta of the message, to inspect or route it.
+    #[inline]
+    pub fn meta(&self) -> &MessageMeta {
+        // SAFETY: the meta is in the receive buffer, owned by the message until it is reclaimed
+        unsafe { self.meta.as_ref() }
+    }
+
+    /// Borrows the meta of the message to change it.
+    #[inline]
+    pub fn meta_mut(&mut self) -> &mut MessageMeta {
+        // SAFETY: the meta is in the receive buffer, owned by the message until it is reclaimed
+        unsafe { self.meta.as_mut() }
+    }
 }
 
 #[derive(Debug)]
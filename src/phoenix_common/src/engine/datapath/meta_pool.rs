@@ -1,34 +1,61 @@
 use std::fmt;
 use std::mem;
-use std::ptr::Unique;
+use std::ptr::{self, Unique};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use fnv::FnvHashMap as HashMap;
 
-use phoenix_api::rpc::{MessageMeta, RpcId};
+use phoenix_api::rpc::{self, MessageMeta, RpcId, StageStamps};
+use phoenix_api::wire::{WireHeader, DEFAULT_HOP_LIMIT};
 
 use crate::resource::Error as ResourceError;
 
+use super::token::{AddressSpace, MessageToken};
+
 /// The size of the [`MetaBuffer`] struct.
 pub const META_BUFFER_SIZE: usize = 16384; // TODO(cjr): try 4096 or 256
 
-/// A buffer that holds the room for [`MessageMeta`] and optionally the body of the message.
+/// The size of the room in a [`MetaBuffer`] for the struct of a message the app sends inside its
+/// work request.
+pub const INLINE_BODY_SIZE: usize = 256;
+
+/// A buffer that holds the room for the [`WireHeader`] and optionally the body of the message.
 ///
-/// Format:
+/// A fused message is sent as is, see [`phoenix_api::wire`] for the format. The inline body and
+/// the stamps at the end of the buffer are never sent as part of it.
 /// ```text
-/// | meta | num_sge | value_len | lens[0] | lens[1] | ... | value[0] | value[1] | ... |
-/// |  40  |    4    |     4     |                 META_BUFFER_SIZE - 48               |
+/// | header | lens[0] | lens[1] | ... | value[0] | value[1] | ... | inline body | stamps |
+/// |   56   |         META_BUFFER_SIZE - 56 - 256 - 24          |     256     |   24   |
 /// ```
 #[repr(C)]
 #[derive(Clone)]
 pub struct MetaBuffer {
-    /// The metadata of an RPC message.
-    pub meta: MessageMeta,
-    /// The number of disaggregated segments (or scatter-gather elements) the RPC message has.
-    pub num_sge: u32,
-    /// The length of the body of the RPC message inside this `MetaBuffer`.
-    pub value_len: u32,
+    /// The header of the RPC message on the wire, starting with its [`MessageMeta`].
+    pub header: WireHeader,
     /// The remaining raw bytes of the struct.
-    pub length_delimited: [u8; META_BUFFER_SIZE - (mem::size_of::<MessageMeta>() + 8)],
+    pub length_delimited: [u8; META_BUFFER_SIZE
+        - WireHeader::SIZE
+        - INLINE_BODY_SIZE
+        - mem::size_of::<StageStamps>()],
+    /// The struct of a message sent by the app inside its work request, see
+    /// [`MetaBufferPtr::write_inline_body`]. Aligned to 8 bytes.
+    pub inline_body: [u64; INLINE_BODY_SIZE / 8],
+    /// The timestamps of a request in the backend, only written while [`stamping`] is enabled.
+    pub stamps: StageStamps,
+}
+
+static STAMPING: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables the stamping of the requests at the stages of the backend, for the latency
+/// breakdown of the calls, see [`StageStamps`].
+pub fn set_stamping(enabled: bool) {
+    STAMPING.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether the requests are stamped at the stages of the backend.
+#[inline]
+pub fn stamping() -> bool {
+    STAMPING.load(Ordering::Relaxed)
 }
 
 mod sa {
@@ -44,9 +71,7 @@ impl fmt::Debug for MetaBuffer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let print_count = self.value_buffer().len().min(64);
         f.debug_struct("MetaBuffer")
-            .field("meta", &self.meta)
-            .field("num_sge", &self.num_sge)
-            .field("value_len", &self.value_len)
+            .field("header", &self.header)
             .field("lens", &self.lens_buffer())
             .field("value", &(&self.value_buffer()[..print_count]))
             .finish()
@@ -57,17 +82,13 @@ impl MetaBuffer {
     /// Returns the number of bytes contained in this `MetaBuffer`.
     #[inline]
     pub fn len(&self) -> usize {
-        mem::size_of::<MessageMeta>()
-            + mem::size_of_val(&self.num_sge)
-            + mem::size_of_val(&self.value_len)
-            + self.value_start()
-            + self.value_len as usize
+        WireHeader::SIZE + self.value_start() + self.header.value_len as usize
     }
 
     /// Returns the number of bytes the `MetaBuffer` can hold.
     #[inline]
     pub const fn capacity() -> usize {
-        META_BUFFER_SIZE - 40
+        META_BUFFER_SIZE - WireHeader::SIZE - INLINE_BODY_SIZE - mem::size_of::<StageStamps>()
     }
 
     /// Returns the offset in bytes of the message to the beginning of `length_delimited`.
@@ -77,7 +98,7 @@ impl MetaBuffer {
     /// Panics if the starting offset of the message exceeds the end of the buffer.
     #[inline]
     pub const fn value_start(&self) -> usize {
-        let start = self.num_sge as usize * mem::size_of::<u32>();
+        let start = self.header.num_sge as usize * mem::size_of::<u32>();
         assert!(start <= Self::capacity());
         start
     }
@@ -89,11 +110,19 @@ impl MetaBuffer {
         &self.length_delimited[..self.value_start()]
     }
 
+    /// Stamps the message as posted to the transport, while [`stamping`] is enabled.
+    #[inline]
+    pub fn stamp_post_send(&mut self) {
+        if stamping() {
+            self.stamps.post_send = rpc::tsc();
+        }
+    }
+
     /// Returns a byte slice that represents buffer to the RPC message.
     #[inline]
     pub fn value_buffer(&self) -> &[u8] {
         let base = self.value_start();
-        let len = self.value_len as usize;
+        let len = self.header.value_len as usize;
 
         assert!(
             base + len <= self.length_delimited.len(),
@@ -118,19 +147,51 @@ impl MetaBufferPtr {
         MetaBufferPtr(ptr)
     }
 
+    /// Returns the address of the buffer in the backend.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+
     /// Returns an unsafe mutable pointer to the [RPC descriptor][`MessageMeta`].
     #[inline]
     pub fn as_meta_ptr(&self) -> *mut MessageMeta {
         self.0.as_ptr().cast()
     }
+
+    /// Copies the struct of a message into the inline body of the buffer, and returns its
+    /// address, which the message is sent from in place of the one on the shared heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `body` is longer than [`INLINE_BODY_SIZE`].
+    ///
+    /// # Safety
+    ///
+    /// The buffer must be obtained, and its inline body not referred to by a message in flight.
+    #[inline]
+    pub unsafe fn write_inline_body(&self, body: &[u8]) -> usize {
+        assert!(body.len() <= INLINE_BODY_SIZE);
+        let dst = ptr::addr_of_mut!((*self.0.as_ptr()).inline_body).cast::<u8>();
+        ptr::copy_nonoverlapping(body.as_ptr(), dst, body.len());
+        dst as usize
+    }
+
+    /// Returns an unsafe mutable pointer to the [`StageStamps`] of the message.
+    #[inline]
+    pub fn as_stamps_ptr(&self) -> *mut StageStamps {
+        // SAFETY: the pointer is in bounds of the buffer, and no reference is created
+        unsafe { ptr::addr_of_mut!((*self.0.as_ptr()).stamps) }
+    }
 }
 
-/// A pool of [`MetaBuffer`]s.
+/// A pool of [`MetaBuffer`]s. Each buffer obtained is owned by a [`MessageToken`] until it is
+/// released.
 pub struct MetaBufferPool {
     #[allow(unused_variables)]
     buffer: Vec<MetaBuffer>,
     pub free: Vec<MetaBufferPtr>,
-    used: HashMap<RpcId, MetaBufferPtr>,
+    used: HashMap<RpcId, (MetaBufferPtr, MessageToken)>,
 }
 
 impl MetaBufferPool {
@@ -159,23 +220,154 @@ impl MetaBufferPool {
         self.free.len() == self.buffer.capacity()
     }
 
-    /// Attempt to obtain a free [`MetaBuffer`] for a given `rpc_id`.
+    /// Attempt to obtain a free [`MetaBuffer`] for a given `rpc_id`. The hop limit of the
+    /// message is reset to [`DEFAULT_HOP_LIMIT`], and so are the stamps while [`stamping`] is
+    /// enabled.
     ///
     /// Returns a [`MetaBufferPtr`] on success. Returns [`None`] if there is no free slots.
     #[inline]
     pub fn obtain(&mut self, rpc_id: RpcId) -> Option<MetaBufferPtr> {
+        self.obtain_forwarded(rpc_id, DEFAULT_HOP_LIMIT)
+    }
+
+    /// Like [`obtain`], for a message that forwards a received one. The hop limit of the
+    /// message is set to `hop_limit`, the hop limit the received message came with, and the
+    /// forwarding engine counts its hop with [`WireHeader::forward`].
+    ///
+    /// [`obtain`]: Self::obtain
+    #[inline]
+    pub fn obtain_forwarded(&mut self, rpc_id: RpcId, hop_limit: u8) -> Option<MetaBufferPtr> {
         self.free.pop().map(|buf| {
-            self.used.insert(rpc_id, buf);
+            // SAFETY: the buffer is free, and the fields are written without reading the buffer
+            unsafe {
+                ptr::addr_of_mut!((*buf.0.as_ptr()).header.hop_limit).write(hop_limit);
+                if stamping() {
+                    buf.as_stamps_ptr().write(StageStamps::default());
+                }
+            }
+            // SAFETY: the buffer is free, no other token owns it
+            let token = unsafe { MessageToken::issue(buf.addr(), AddressSpace::Backend) };
+            self.used.insert(rpc_id, (buf, token));
             buf
         })
     }
 
+    /// Returns the [`MetaBuffer`] allocated for the `rpc_id`, if any.
+    #[inline]
+    pub fn get(&self, rpc_id: RpcId) -> Option<MetaBufferPtr> {
+        self.used.get(&rpc_id).map(|(buf, _)| *buf)
+    }
+
     /// Release the [`MetaBuffer`] allocated for the `rpc_id`, making it available for
     /// future allocations.
     #[inline]
     pub fn release(&mut self, rpc_id: RpcId) -> Result<(), ResourceError> {
-        let buf = self.used.remove(&rpc_id).ok_or(ResourceError::NotFound)?;
+        let (buf, token) = self.used.remove(&rpc_id).ok_or(ResourceError::NotFound)?;
+        token.reclaim();
         self.free.push(buf);
         Ok(())
     }
 }
+
+impl Drop for MetaBufferPool {
+    fn drop(&mut self) {
+        // the buffers go away with the pool, their addresses may be taken again by another one
+        for (_, (_, token)) in self.used.drain() {
+            token.reclaim();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use phoenix_api::rpc::{CallId, RpcMsgType, StatusCode};
+    use phoenix_api::wire::WireFlags;
+    use phoenix_api::Handle;
+
+    use super::*;
+
+    fn pool(cap: usize) -> MetaBufferPool {
+        let pool = MetaBufferPool::new(cap);
+        for buf in &pool.free {
+            // SAFETY: the buffers are free, and zero bytes are a valid header
+            unsafe { buf.0.as_ptr().write_bytes(0, 1) };
+        }
+        pool
+    }
+
+    fn fill(buf: MetaBufferPtr, rpc_id: RpcId) {
+        let meta = MessageMeta {
+            conn_id: rpc_id.0,
+            service_id: 0,
+            func_id: 0,
+            call_id: rpc_id.1,
+            token: 0,
+            msg_type: RpcMsgType::Request,
+            status_code: StatusCode::Success,
+        };
+        // SAFETY: the buffer is obtained, no one else refers to it
+        unsafe {
+            buf.as_meta_ptr().write(meta);
+            (*buf.0.as_ptr()).header.seal(WireFlags::empty(), 0, 0);
+        }
+    }
+
+    /// Sends the message over the wire, and returns the hop limit it is received with.
+    fn send(buf: MetaBufferPtr) -> u8 {
+        let header = buf.0.as_ptr().cast::<WireHeader>();
+        // SAFETY: the buffer is obtained, and it is aligned and long enough for a header
+        unsafe {
+            WireHeader::encode(header);
+            WireHeader::parse(header as usize, WireHeader::SIZE)
+                .unwrap()
+                .hop_limit
+        }
+    }
+
+    #[test]
+    fn forwarding_loop_expires() {
+        let mut pool = pool(1);
+        let rpc_id = RpcId(Handle(1), CallId(1));
+
+        let buf = pool.obtain(rpc_id).unwrap();
+        fill(buf, rpc_id);
+        let mut received = send(buf);
+        pool.release(rpc_id).unwrap();
+        assert_eq!(received, DEFAULT_HOP_LIMIT);
+
+        // engines that forward the message to each other, each in a buffer of its own
+        let mut forwarded = 0;
+        loop {
+            let buf = pool.obtain_forwarded(rpc_id, received).unwrap();
+            fill(buf, rpc_id);
+            // SAFETY: the buffer is obtained, no one else refers to it
+            if !unsafe { (*buf.0.as_ptr()).header.forward() } {
+                pool.release(rpc_id).unwrap();
+                break;
+            }
+            received = send(buf);
+            pool.release(rpc_id).unwrap();
+            forwarded += 1;
+            assert!(forwarded <= DEFAULT_HOP_LIMIT as usize, "forwarded forever");
+        }
+        assert_eq!(forwarded, DEFAULT_HOP_LIMIT as usize);
+        assert_eq!(received, 0);
+    }
+
+    #[test]
+    fn obtain_resets_hop_limit() {
+        let mut pool = pool(1);
+        let rpc_id = RpcId(Handle(1), CallId(1));
+        let buf = pool.obtain_forwarded(rpc_id, 3).unwrap();
+        // SAFETY: the buffer is obtained, no one else refers to it
+        assert_eq!(unsafe { (*buf.0.as_ptr()).header.hop_limit }, 3);
+        pool.release(rpc_id).unwrap();
+
+        let buf = pool.obtain(rpc_id).unwrap();
+        // SAFETY: the buffer is obtained, no one else refers to it
+        assert_eq!(
+            unsafe { (*buf.0.as_ptr()).header.hop_limit },
+            DEFAULT_HOP_LIMIT
+        );
+    }
+}
@@ -1,12 +1,21 @@
-pub use channel::{create_channel, ChannelFlavor, SendError, TryRecvError};
+pub use channel::{
+    create_bounded_channel, create_channel, ChannelFlavor, ChannelStats, SendError, TryRecvError,
+    TrySendError,
+};
 pub use ipc::channel;
 
+pub mod arena;
 pub mod message;
 pub mod node;
+pub mod port;
+pub mod shared;
+pub mod token;
 
 pub use message::{EngineRxMessage, EngineTxMessage, RpcMessageRx, RpcMessageTx};
 pub use node::DataPathNode;
 pub use node::{ChannelDescriptor, RxIQueue, RxOQueue, TxIQueue, TxOQueue, Vertex};
+pub use port::{FanOut, InPort, OutPort, PortEndpoint, PortError};
+pub use token::{AddressSpace, MessageToken};
 
 #[allow(clippy::len_without_is_empty)]
 pub mod meta_pool;
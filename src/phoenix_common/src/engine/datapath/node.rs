@@ -1,5 +1,6 @@
 use super::channel::{Receiver, Sender};
 use super::message::{EngineRxMessage, EngineTxMessage};
+use super::port::{FanOut, InPort, OutPort, PortEndpoint, PortError, Ports};
 use crate::engine::EngineType;
 
 pub type TxIQueue = Receiver<EngineTxMessage>;
@@ -13,10 +14,23 @@ pub trait Vertex {
     fn tx_outputs(&mut self) -> &mut Vec<TxOQueue>;
     fn rx_inputs(&mut self) -> &mut Vec<RxIQueue>;
     fn rx_outputs(&mut self) -> &mut Vec<RxOQueue>;
+
+    /// Returns true if any tx output is full. The engine then leaves its tx inputs queued, so
+    /// that the upstream engine sees them fill up, and takes them on a later quantum.
+    #[inline]
+    fn tx_outputs_full(&mut self) -> bool {
+        self.tx_outputs().iter().any(|q| q.is_full())
+    }
+
+    /// Returns true if any rx output is full, see [`Vertex::tx_outputs_full`].
+    #[inline]
+    fn rx_outputs_full(&mut self) -> bool {
+        self.rx_outputs().iter().any(|q| q.is_full())
+    }
 }
 
 /// A descriptor to describe channel
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ChannelDescriptor(pub EngineType, pub EngineType, pub usize, pub usize);
 
 pub struct DataPathNode {
@@ -24,6 +38,8 @@ pub struct DataPathNode {
     pub tx_outputs: Vec<TxOQueue>,
     pub rx_inputs: Vec<RxIQueue>,
     pub rx_outputs: Vec<RxOQueue>,
+    /// Named groups of the channels above
+    pub ports: Ports,
 }
 
 impl DataPathNode {
@@ -33,8 +49,51 @@ impl DataPathNode {
             tx_outputs: Vec::new(),
             rx_inputs: Vec::new(),
             rx_outputs: Vec::new(),
+            ports: Ports::default(),
         }
     }
+
+    /// Names the `channels` of `endpoint` as a port.
+    pub fn declare_port(
+        &mut self,
+        endpoint: PortEndpoint,
+        name: String,
+        channels: Vec<usize>,
+        fan_out: FanOut,
+    ) -> Result<(), PortError> {
+        let nchannels = match endpoint {
+            PortEndpoint::TxInput => self.tx_inputs.len(),
+            PortEndpoint::TxOutput => self.tx_outputs.len(),
+            PortEndpoint::RxInput => self.rx_inputs.len(),
+            PortEndpoint::RxOutput => self.rx_outputs.len(),
+        };
+        self.ports
+            .declare(endpoint, name, channels, fan_out, nchannels)
+    }
+
+    #[inline]
+    pub fn tx_input(&mut self, port: &str) -> InPort<'_, EngineTxMessage> {
+        self.ports
+            .input(PortEndpoint::TxInput, port, &mut self.tx_inputs)
+    }
+
+    #[inline]
+    pub fn tx_output(&mut self, port: &str) -> OutPort<'_, EngineTxMessage> {
+        self.ports
+            .output(PortEndpoint::TxOutput, port, &mut self.tx_outputs)
+    }
+
+    #[inline]
+    pub fn rx_input(&mut self, port: &str) -> InPort<'_, EngineRxMessage> {
+        self.ports
+            .input(PortEndpoint::RxInput, port, &mut self.rx_inputs)
+    }
+
+    #[inline]
+    pub fn rx_output(&mut self, port: &str) -> OutPort<'_, EngineRxMessage> {
+        self.ports
+            .output(PortEndpoint::RxOutput, port, &mut self.rx_outputs)
+    }
 }
 
 #[macro_export]
@@ -0,0 +1,225 @@
+//! Named ports of a data path node.
+//!
+//! A port names some channels of one endpoint of a node, e.g., the tx outputs to the backends of
+//! a load balancer. The ports are declared in the `edges` section of the config when an addon is
+//! attached. A port that is not declared spans all the channels of its endpoint, so an engine
+//! with a single successor can still use a named port.
+//!
+//! An input port merges its channels in a round robin. An output port routes each message to
+//! one channel, chosen by the engine or in a round robin, or duplicates it to all of them.
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+pub use ipc::control::{FanOut, PortEndpoint};
+
+use super::channel::{Receiver, SendError, Sender, TryRecvError, TrySendError};
+
+#[derive(Debug, Error)]
+pub enum PortError {
+    #[error("Port {0:?} is declared twice")]
+    Duplicated(String),
+    #[error("Port {0:?} refers to channel {1}, but the endpoint has only {2} channels")]
+    ChannelOutOfRange(String, usize, usize),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Port {
+    /// `None` for a port that is not declared, which spans all channels.
+    channels: Option<Vec<usize>>,
+    fan_out: FanOut,
+    /// The branch to try first in the round robin.
+    cursor: usize,
+}
+
+impl Port {
+    #[inline]
+    fn width(&self, nchannels: usize) -> usize {
+        self.channels.as_ref().map_or(nchannels, |c| c.len())
+    }
+
+    #[inline]
+    fn channel(&self, branch: usize) -> usize {
+        self.channels.as_ref().map_or(branch, |c| c[branch])
+    }
+}
+
+/// The ports of the four endpoints of a node.
+#[derive(Debug, Default)]
+pub struct Ports {
+    ports: HashMap<PortEndpoint, HashMap<String, Port>>,
+}
+
+impl Ports {
+    pub fn declare(
+        &mut self,
+        endpoint: PortEndpoint,
+        name: String,
+        channels: Vec<usize>,
+        fan_out: FanOut,
+        nchannels: usize,
+    ) -> Result<(), PortError> {
+        if let Some(&c) = channels.iter().find(|&&c| c >= nchannels) {
+            return Err(PortError::ChannelOutOfRange(name, c, nchannels));
+        }
+        let ports = self.ports.entry(endpoint).or_default();
+        if ports.get(&name).map_or(false, |p| p.channels.is_some()) {
+            return Err(PortError::Duplicated(name));
+        }
+        let port = Port {
+            channels: Some(channels),
+            fan_out,
+            cursor: 0,
+        };
+        ports.insert(name, port);
+        Ok(())
+    }
+
+    fn get_mut(&mut self, endpoint: PortEndpoint, name: &str) -> &mut Port {
+        let ports = self.ports.entry(endpoint).or_default();
+        // the name is only copied the first time the port is used
+        if !ports.contains_key(name) {
+            ports.insert(name.to_owned(), Default::default());
+        }
+        ports.get_mut(name).unwrap()
+    }
+
+    pub(crate) fn input<'a, T>(
+        &'a mut self,
+        endpoint: PortEndpoint,
+        name: &str,
+        queues: &'a mut [Receiver<T>],
+    ) -> InPort<'a, T> {
+        InPort {
+            port: self.get_mut(endpoint, name),
+            queues,
+        }
+    }
+
+    pub(crate) fn output<'a, T>(
+        &'a mut self,
+        endpoint: PortEndpoint,
+        name: &str,
+        queues: &'a mut [Sender<T>],
+    ) -> OutPort<'a, T> {
+        OutPort {
+            port: self.get_mut(endpoint, name),
+            queues,
+        }
+    }
+}
+
+/// An input port, borrowed from a node.
+#[derive(Debug)]
+pub struct InPort<'a, T> {
+    port: &'a mut Port,
+    queues: &'a mut [Receiver<T>],
+}
+
+impl<'a, T> InPort<'a, T> {
+    /// The number of channels of the port.
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.port.width(self.queues.len())
+    }
+
+    /// Receives a message from the channels in a round robin, starting from the one after the
+    /// channel of the last message. Returns `Disconnected` only if all channels are.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let width = self.width();
+        let mut disconnected = 0;
+        for k in 0..width {
+            let branch = (self.port.cursor + k) % width;
+            match self.queues[self.port.channel(branch)].try_recv() {
+                Ok(msg) => {
+                    self.port.cursor = (branch + 1) % width;
+                    return Ok(msg);
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => disconnected += 1,
+            }
+        }
+        if disconnected == width {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..self.width()).all(|b| self.queues[self.port.channel(b)].is_empty())
+    }
+
+    /// Returns the number of messages in the channels of the port.
+    pub fn len(&self) -> usize {
+        (0..self.width())
+            .map(|b| self.queues[self.port.channel(b)].len())
+            .sum()
+    }
+}
+
+/// An output port, borrowed from a node.
+#[derive(Debug)]
+pub struct OutPort<'a, T> {
+    port: &'a mut Port,
+    queues: &'a mut [Sender<T>],
+}
+
+impl<'a, T> OutPort<'a, T> {
+    /// The number of channels of the port.
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.port.width(self.queues.len())
+    }
+
+    #[inline]
+    pub fn fan_out(&self) -> FanOut {
+        self.port.fan_out
+    }
+
+    /// Sends a message to the `branch`-th channel of the port.
+    pub fn send_to(&mut self, branch: usize, msg: T) -> Result<(), SendError<T>> {
+        if branch >= self.width() {
+            return Err(SendError(msg));
+        }
+        self.queues[self.port.channel(branch)].send(msg)
+    }
+
+    /// Sends a message to the `branch`-th channel of the port, unless it is full.
+    pub fn try_send_to(&mut self, branch: usize, msg: T) -> Result<(), TrySendError<T>> {
+        if branch >= self.width() {
+            return Err(TrySendError::Disconnected(msg));
+        }
+        self.queues[self.port.channel(branch)].try_send(msg)
+    }
+
+    /// Sends a message to the next channel in a round robin, whatever the fan-out of the port.
+    pub fn route(&mut self, msg: T) -> Result<(), SendError<T>> {
+        let width = self.width();
+        if width == 0 {
+            return Err(SendError(msg));
+        }
+        let branch = self.port.cursor % width;
+        self.port.cursor = (branch + 1) % width;
+        self.queues[self.port.channel(branch)].send(msg)
+    }
+}
+
+impl<'a, T: Clone> OutPort<'a, T> {
+    /// Sends a message by the fan-out of the port.
+    pub fn send(&mut self, msg: T) -> Result<(), SendError<T>> {
+        match self.port.fan_out {
+            FanOut::Route => self.route(msg),
+            FanOut::Duplicate => {
+                let width = self.width();
+                if width == 0 {
+                    return Err(SendError(msg));
+                }
+                for branch in 1..width {
+                    self.queues[self.port.channel(branch)].send(msg.clone())?;
+                }
+                self.queues[self.port.channel(0)].send(msg)
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Payloads sent to several destinations without being copied, for fan-out and mirroring.
+//!
+//! A payload, e.g., a message in the receive buffers of a connection, is sent to each destination
+//! by a message of its own that points to the same payload. [`SharedPayloads`] counts the
+//! references to each payload, i.e., the sends of it not completed yet and the destinations it
+//! is still to be sent to, and hands back the owner of the payload once the last reference is
+//! gone, for the engine to give its buffers back.
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api::rpc::RpcId;
+
+/// Identifies a payload in [`SharedPayloads`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PayloadKey(u64);
+
+#[derive(Debug)]
+struct Shared<T> {
+    owner: T,
+    addr_backend: usize,
+    refs: usize,
+}
+
+/// The payloads shared by several sends, and the sends in flight.
+#[derive(Debug)]
+pub struct SharedPayloads<T> {
+    payloads: HashMap<PayloadKey, Shared<T>>,
+    /// The sends in flight, and the payloads they are of
+    sends: HashMap<RpcId, PayloadKey>,
+    next_key: u64,
+}
+
+impl<T> Default for SharedPayloads<T> {
+    fn default() -> Self {
+        SharedPayloads {
+            payloads: HashMap::default(),
+            sends: HashMap::default(),
+            next_key: 0,
+        }
+    }
+}
+
+impl<T> SharedPayloads<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shares the payload at `addr_backend` among `refs` destinations. `owner` tells the engine
+    /// how to give the payload back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `refs` is 0.
+    pub fn insert(&mut self, owner: T, addr_backend: usize, refs: usize) -> PayloadKey {
+        assert!(
+            refs > 0,
+            "a payload must be shared by at least one destination"
+        );
+        let key = PayloadKey(self.next_key);
+        self.next_key += 1;
+        self.payloads.insert(
+            key,
+            Shared {
+                owner,
+                addr_backend,
+                refs,
+            },
+        );
+        key
+    }
+
+    /// The address of the payload, to point the message of a send to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload has been given back.
+    #[inline]
+    pub fn addr_backend(&self, key: PayloadKey) -> usize {
+        self.payloads[&key].addr_backend
+    }
+
+    /// The owner of the payload.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload has been given back.
+    #[inline]
+    pub fn owner(&self, key: PayloadKey) -> &T {
+        &self.payloads[&key].owner
+    }
+
+    /// Records that the message `send` of the payload is in flight. Its completion drops the
+    /// reference of its destination.
+    #[inline]
+    pub fn attach(&mut self, key: PayloadKey, send: RpcId) {
+        debug_assert!(self.payloads.contains_key(&key));
+        self.sends.insert(send, key);
+    }
+
+    /// Whether `send` is a message of a shared payload in flight.
+    #[inline]
+    pub fn is_send(&self, send: &RpcId) -> bool {
+        self.sends.contains_key(send)
+    }
+
+    /// Completes the message `send`, successfully or not. Returns the key of its payload and the
+    /// owner of the payload if it was the last reference, `None` if `send` is not in flight.
+    pub fn complete(&mut self, send: RpcId) -> Option<(PayloadKey, Option<T>)> {
+        let key = self.sends.remove(&send)?;
+        Some((key, self.release(key)))
+    }
+
+    /// Drops a reference without sending the payload, e.g., when its destination is gone.
+    /// Returns the owner of the payload if it was the last reference.
+    pub fn release(&mut self, key: PayloadKey) -> Option<T> {
+        let shared = self.payloads.get_mut(&key)?;
+        shared.refs -= 1;
+        if shared.refs > 0 {
+            return None;
+        }
+        self.payloads.remove(&key).map(|shared| shared.owner)
+    }
+
+    /// The owners of the payloads not given back yet.
+    pub fn owners_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.payloads.values_mut().map(|shared| &mut shared.owner)
+    }
+
+    /// The number of payloads not given back yet.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+}
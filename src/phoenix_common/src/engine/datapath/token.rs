@@ -0,0 +1,202 @@
+//! Ownership of the buffers the messages point to.
+//!
+//! A message passed between the engines is a pair of raw addresses, and nothing stops an engine
+//! from keeping a copy of them, sending the same buffer twice, or reading a buffer after it has
+//! been given back. A [`MessageToken`] stands for the ownership of a buffer, from the time it is
+//! taken to the time it is reclaimed, and records in which [`AddressSpace`] its address is valid.
+//! A token is neither `Clone` nor `Copy`, and reclaiming it takes it by value.
+//!
+//! In debug builds, the live and the reclaimed buffers are also recorded in a registry, keyed by
+//! their backend addresses, which panics on the memory bugs that otherwise show up much later:
+//! a buffer taken while another token still owns it, e.g., a message sent twice; a buffer
+//! reclaimed twice; and a buffer read through a message after it was reclaimed, see
+//! [`check_not_reclaimed`]. A buffer taken again after it was reclaimed is live again, so a stale
+//! message is only caught until then. The registry takes a lock on every check, and the release
+//! builds keep none of it.
+use std::fmt;
+
+/// Where the address of a buffer is valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressSpace {
+    /// The memory of the backend alone, e.g., the meta buffers of the engines.
+    Backend,
+    /// The shared memory heap of an application, at its address in the backend.
+    Shared,
+}
+
+/// The ownership of a buffer handed off with the messages.
+pub struct MessageToken {
+    addr: usize,
+    space: AddressSpace,
+    #[cfg(debug_assertions)]
+    generation: u64,
+}
+
+impl fmt::Debug for MessageToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageToken")
+            .field("addr", &format_args!("{:#x}", self.addr))
+            .field("space", &self.space)
+            .finish()
+    }
+}
+
+impl MessageToken {
+    /// Takes the ownership of the buffer at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be the address of a buffer valid in `space`, that no other token owns.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if another token owns the buffer.
+    #[inline]
+    pub unsafe fn issue(addr: usize, space: AddressSpace) -> Self {
+        MessageToken {
+            addr,
+            space,
+            #[cfg(debug_assertions)]
+            generation: registry::issue(addr, space),
+        }
+    }
+
+    /// The address of the buffer, valid in [`space`](Self::space).
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    #[inline]
+    pub fn space(&self) -> AddressSpace {
+        self.space
+    }
+
+    /// The address of the buffer, to access it from `space`.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the address is not valid in `space`, or if the buffer has been
+    /// reclaimed through another token.
+    #[inline]
+    pub fn addr_in(&self, space: AddressSpace) -> usize {
+        debug_assert_eq!(
+            self.space, space,
+            "buffer {:#x} is accessed from the wrong address space",
+            self.addr
+        );
+        #[cfg(debug_assertions)]
+        registry::check(self.addr, self.generation);
+        self.addr
+    }
+
+    /// Gives the buffer back, and returns its address.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the buffer has already been reclaimed.
+    #[inline]
+    pub fn reclaim(self) -> usize {
+        #[cfg(debug_assertions)]
+        registry::reclaim(self.addr, self.generation);
+        self.addr
+    }
+}
+
+/// Checks that the buffer at the backend address `addr` has not been reclaimed, before reading it
+/// through a message. A no-op in release builds, and for the buffers not owned by tokens.
+///
+/// # Panics
+///
+/// In debug builds, panics if the buffer has been reclaimed and not taken again.
+#[inline]
+pub fn check_not_reclaimed(addr: usize) {
+    #[cfg(debug_assertions)]
+    registry::check_not_reclaimed(addr);
+    #[cfg(not(debug_assertions))]
+    let _ = addr;
+}
+
+/// The number of buffers owned by tokens, e.g., to check that an engine gave back all of them
+/// when it shuts down. Always 0 in release builds.
+pub fn outstanding() -> usize {
+    #[cfg(debug_assertions)]
+    return registry::outstanding();
+    #[cfg(not(debug_assertions))]
+    0
+}
+
+#[cfg(debug_assertions)]
+mod registry {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    use super::AddressSpace;
+
+    #[derive(Debug)]
+    enum Entry {
+        Live(u64, AddressSpace),
+        Reclaimed,
+    }
+
+    struct Registry {
+        entries: BTreeMap<usize, Entry>,
+        live: usize,
+        next_generation: u64,
+    }
+
+    static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+        entries: BTreeMap::new(),
+        live: 0,
+        next_generation: 0,
+    });
+
+    fn lock() -> std::sync::MutexGuard<'static, Registry> {
+        // a panic on a check below poisons the lock, the registry itself is still consistent
+        REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(super) fn issue(addr: usize, space: AddressSpace) -> u64 {
+        let mut registry = lock();
+        let generation = registry.next_generation;
+        registry.next_generation += 1;
+        if let Some(Entry::Live(_, owner_space)) = registry
+            .entries
+            .insert(addr, Entry::Live(generation, space))
+        {
+            panic!(
+                "buffer {:#x} ({:?}) is taken while another message owns it, sent twice?",
+                addr, owner_space
+            );
+        }
+        registry.live += 1;
+        generation
+    }
+
+    pub(super) fn check(addr: usize, generation: u64) {
+        match lock().entries.get(&addr) {
+            Some(Entry::Live(g, _)) if *g == generation => {}
+            _ => panic!("buffer {:#x} is used after it was reclaimed", addr),
+        }
+    }
+
+    pub(super) fn reclaim(addr: usize, generation: u64) {
+        let mut registry = lock();
+        match registry.entries.get(&addr) {
+            Some(Entry::Live(g, _)) if *g == generation => {}
+            _ => panic!("buffer {:#x} is reclaimed twice", addr),
+        }
+        registry.entries.insert(addr, Entry::Reclaimed);
+        registry.live -= 1;
+    }
+
+    pub(super) fn check_not_reclaimed(addr: usize) {
+        if let Some(Entry::Reclaimed) = lock().entries.get(&addr) {
+            panic!("buffer {:#x} is read after it was reclaimed", addr);
+        }
+    }
+
+    pub(super) fn outstanding() -> usize {
+        lock().live
+    }
+}
@@ -1,5 +1,12 @@
+use anyhow::{anyhow, bail};
+
+pub use phoenix_derive::EngineState;
+
 use super::datapath::node::DataPathNode;
+use crate::envelop::{AnyResource, ResourceDowncast};
+use crate::module::{ModuleCollection, Version};
 use crate::storage::{ResourceCollection, SharedStorage};
+use crate::PhoenixResult;
 
 pub type DecomposeResult<T> = anyhow::Result<T>;
 
@@ -16,3 +23,60 @@ pub trait Decompose {
         global: &mut ResourceCollection,
     ) -> (ResourceCollection, DataPathNode);
 }
+
+/// The state of an engine that outlives the engine across an upgrade or a migration. It is
+/// dumped by [`Decompose::decompose`] and restored by the module of the engine, usually by the
+/// code generated by `#[derive(EngineState)]`.
+pub trait EngineState: Sized {
+    /// The version of the layout of the dumped state. A state is restored from the same or an
+    /// older version.
+    const STATE_VERSION: u32;
+
+    /// Dumps the state into a collection, and extracts the data path node.
+    fn dump(self) -> (ResourceCollection, DataPathNode);
+
+    /// Restores the engine from a dumped state.
+    fn restore(
+        local: ResourceCollection,
+        shared: &mut SharedStorage,
+        global: &mut ResourceCollection,
+        node: DataPathNode,
+        plugged: &ModuleCollection,
+        prev_version: Version,
+    ) -> PhoenixResult<Self>;
+}
+
+/// The key of the version of a dumped state. The states dumped by hand have no version, and are
+/// taken as version 1.
+pub const STATE_VERSION_KEY: &str = "__state_version";
+
+/// Takes the version of a dumped state, and checks that it can be restored by `current`.
+#[doc(hidden)]
+pub fn state_version(local: &mut ResourceCollection, current: u32) -> PhoenixResult<u32> {
+    let version = match local.remove(STATE_VERSION_KEY) {
+        Some(version) => *version
+            .downcast::<u32>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+        None => 1,
+    };
+    if version > current {
+        bail!(
+            "cannot restore a state of version {} with version {}",
+            version,
+            current
+        );
+    }
+    Ok(version)
+}
+
+/// Takes a field of a dumped state.
+#[doc(hidden)]
+pub fn take_state<T: AnyResource>(local: &mut ResourceCollection, key: &str) -> PhoenixResult<T> {
+    let resource = local
+        .remove(key)
+        .ok_or_else(|| anyhow!("missing state {:?}", key))?;
+    let value = resource
+        .downcast::<T>()
+        .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+    Ok(*value)
+}
@@ -1,7 +1,28 @@
+//! Building blocks for the futures of the engines.
+//!
+//! An engine is an async fn, usually a `mainloop` returned by [`Engine::activate`], that the
+//! runtime resumes over and over. Each `.await` on the helpers here is a suspension point, where
+//! the runtime gets the control back.
+//!
+//! Engines were already futures, so there is no new engine trait: the helpers here replace the
+//! hand-rolled parts of the loops, i.e., ending a round ([`suspend`]), waiting on a condition
+//! ([`wait`]), draining a queue ([`drain`], [`drain_at_most`]) and interleaving the control path
+//! ([`Every`]). `MrpcEngine` is ported to them; the other engines keep their loops until they are
+//! touched.
+//!
+//! The runtime may detach an engine at any suspension point, to move it to another runtime or to
+//! upgrade it. The future is then dropped and a new one is created by `activate`, which starts
+//! from the top of the async fn. Everything that must outlive a suspension point therefore lives
+//! in the engine, not in the locals of the future, and the engine must be able to start over
+//! from any suspension point.
+//!
+//! [`Engine::activate`]: super::Engine::activate
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+use super::{Engine, Indicator};
+
 pub async fn yield_now() {
     /// Yield implementation
     struct YieldNow {
@@ -24,3 +45,231 @@ pub async fn yield_now() {
 
     YieldNow { yielded: false }.await
 }
+
+/// Ends a round of the engine. Reports `nwork` to the runtime and suspends the engine until it is
+/// resumed again.
+#[inline]
+pub async fn suspend(tracker: &mut Indicator, nwork: usize) {
+    tracker.set_nwork(nwork);
+    yield_now().await
+}
+
+/// Suspends `engine` until `ready` returns `Some`, e.g., until a completion it waits for
+/// arrives. The engine reports no work at each suspension while waiting, so its runtime can save
+/// energy.
+///
+/// As with any suspension point, the engine may be detached while waiting. What `ready` checks
+/// must be in the engine, so that the new future can wait on it again.
+pub async fn wait<E, T, F>(engine: &mut E, mut ready: F) -> T
+where
+    E: Engine,
+    F: FnMut(&mut E) -> Option<T>,
+{
+    loop {
+        if let Some(value) = ready(engine) {
+            return value;
+        }
+        Pin::new(&mut *engine).tracker().set_nwork(0);
+        yield_now().await;
+    }
+}
+
+/// The result of checking a source of work of an engine, e.g., a queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+/// Checks a source of work until it has no more, and returns the work done. Stops early if the
+/// source is disconnected, which is left to the engine to detect on its control path.
+#[inline]
+pub fn drain<E, F>(check: F) -> Result<usize, E>
+where
+    F: FnMut() -> Result<Status, E>,
+{
+    drain_at_most(usize::MAX, check)
+}
+
+/// Like [`drain`], but stops once `quantum` work is done, leaving the rest to the next round.
+/// This bounds how long the engine keeps its runtime in a round, so that the engines sharing the
+/// runtime get their turn, at the cost of the latency of the work left over.
+#[inline]
+pub fn drain_at_most<E, F>(quantum: usize, mut check: F) -> Result<usize, E>
+where
+    F: FnMut() -> Result<Status, E>,
+{
+    let mut nwork = 0;
+    while nwork < quantum {
+        match check()? {
+            Status::Progress(0) | Status::Disconnected => return Ok(nwork),
+            Status::Progress(n) => nwork += n,
+        }
+    }
+    Ok(nwork)
+}
+
+/// Interleaves an infrequent check with the rounds of an engine, e.g., the control path with the
+/// data path. [`Every::tick`] returns true once every `period` calls.
+#[derive(Debug, Clone)]
+pub struct Every {
+    period: u32,
+    count: u32,
+}
+
+impl Every {
+    pub const fn new(period: u32) -> Self {
+        Every { period, count: 0 }
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> bool {
+        self.count += 1;
+        if self.count >= self.period {
+            self.count = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future::BoxFuture;
+    use futures::task::noop_waker;
+
+    use crate::engine::datapath::node::{DataPathNode, RxIQueue, RxOQueue, TxIQueue, TxOQueue};
+    use crate::engine::{Decompose, DecomposeResult, EngineResult, Vertex};
+    use crate::storage::{ResourceCollection, SharedStorage};
+
+    /// An engine that is ready once its countdown reaches zero.
+    struct CountdownEngine {
+        node: DataPathNode,
+        indicator: Indicator,
+        countdown: usize,
+    }
+
+    impl Decompose for CountdownEngine {
+        fn flush(&mut self) -> DecomposeResult<usize> {
+            Ok(0)
+        }
+
+        fn decompose(
+            self: Box<Self>,
+            _shared: &mut SharedStorage,
+            _global: &mut ResourceCollection,
+        ) -> (ResourceCollection, DataPathNode) {
+            (ResourceCollection::new(), self.node)
+        }
+    }
+
+    impl Vertex for CountdownEngine {
+        fn tx_inputs(&mut self) -> &mut Vec<TxIQueue> {
+            &mut self.node.tx_inputs
+        }
+        fn tx_outputs(&mut self) -> &mut Vec<TxOQueue> {
+            &mut self.node.tx_outputs
+        }
+        fn rx_inputs(&mut self) -> &mut Vec<RxIQueue> {
+            &mut self.node.rx_inputs
+        }
+        fn rx_outputs(&mut self) -> &mut Vec<RxOQueue> {
+            &mut self.node.rx_outputs
+        }
+    }
+
+    impl Engine for CountdownEngine {
+        fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn description(self: Pin<&Self>) -> String {
+            "CountdownEngine".to_owned()
+        }
+
+        fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+            &mut self.get_mut().indicator
+        }
+    }
+
+    fn poll_once<F: Future + Unpin>(fut: &mut F) -> Poll<F::Output> {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(fut).poll(&mut cx)
+    }
+
+    #[test]
+    fn suspend_reports_work() {
+        let mut tracker = Indicator::default();
+        let mut fut = Box::pin(suspend(&mut tracker, 3));
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(poll_once(&mut fut).is_ready());
+        drop(fut);
+        assert_eq!(tracker.nwork(), 3);
+    }
+
+    #[test]
+    fn wait_reports_no_work() {
+        let mut engine = CountdownEngine {
+            node: DataPathNode::new(),
+            indicator: Indicator::default(),
+            countdown: 2,
+        };
+        // as if a previous round reported work
+        engine.indicator.set_busy();
+        let mut fut = Box::pin(wait(&mut engine, |engine| {
+            if engine.countdown == 0 {
+                return Some(42);
+            }
+            engine.countdown -= 1;
+            None
+        }));
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(poll_once(&mut fut), Poll::Ready(42));
+        drop(fut);
+        assert_eq!(engine.indicator.nwork(), 0);
+    }
+
+    #[test]
+    fn drain_until_empty() {
+        let mut queue = vec![
+            Status::Progress(0),
+            Status::Progress(2),
+            Status::Progress(1),
+        ];
+        let nwork = drain::<(), _>(|| Ok(queue.pop().unwrap()));
+        assert_eq!(nwork, Ok(3));
+
+        let mut queue = vec![
+            Status::Progress(1),
+            Status::Disconnected,
+            Status::Progress(1),
+        ];
+        let nwork = drain::<(), _>(|| Ok(queue.pop().unwrap()));
+        assert_eq!(nwork, Ok(1));
+
+        assert_eq!(drain(|| Err("failed")), Err("failed"));
+    }
+
+    #[test]
+    fn drain_at_most_quantum() {
+        let mut checks = 0;
+        let nwork = drain_at_most::<(), _>(4, || {
+            checks += 1;
+            Ok(Status::Progress(1))
+        });
+        assert_eq!(nwork, Ok(4));
+        assert_eq!(checks, 4);
+    }
+
+    #[test]
+    fn every_period() {
+        let mut every = Every::new(3);
+        let ticks: Vec<_> = (0..7).map(|_| every.tick()).collect();
+        assert_eq!(ticks, [false, false, true, false, false, true, false]);
+    }
+}
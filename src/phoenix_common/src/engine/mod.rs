@@ -1,3 +1,4 @@
+use std::os::unix::io::RawFd;
 use std::os::unix::ucred::UCred;
 use std::pin::Pin;
 
@@ -9,9 +10,12 @@ pub mod future;
 
 pub mod datapath;
 pub use datapath::node::Vertex;
+use datapath::{EngineRxMessage, EngineTxMessage};
 
 pub mod decompose;
-pub use decompose::{Decompose, DecomposeResult};
+pub use decompose::{Decompose, DecomposeResult, EngineState};
+
+pub mod timer;
 
 pub type EngineResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -51,6 +55,59 @@ pub trait Engine: Decompose + Send + Vertex + Unpin + 'static {
         Ok(())
     }
 
+    /// Handle query sent by the network operator, returns the encoded answer.
+    #[inline]
+    fn handle_query(&mut self, _query: Vec<u8>, _cred: UCred) -> PhoenixResult<Vec<u8>> {
+        anyhow::bail!("the engine does not answer queries")
+    }
+
+    /// Replaces the configuration of the engine with `config`, a TOML string parsed against the
+    /// config type of the engine. The new configuration must be applied as a whole or not at
+    /// all. Returns the replaced configuration as a TOML string, which is applied back if
+    /// [`Engine::check_config`] rejects the new one.
+    #[inline]
+    fn update_config(&mut self, _config: &str) -> PhoenixResult<String> {
+        anyhow::bail!("the engine does not take configuration updates")
+    }
+
+    /// Checks the configuration just updated against the state of the engine, e.g., the work it
+    /// has queued. An error rolls the update back.
+    #[inline]
+    fn check_config(&mut self) -> PhoenixResult<()> {
+        Ok(())
+    }
+
+    /// Returns the file descriptors that become readable when the engine gets new work, e.g.,
+    /// the doorbells of its customer. The runtime waits on them in the idle mode, and drains the
+    /// readable ones with an 8-byte read. They must be eventfds or epoll fds.
+    ///
+    /// Returns `None` if the engine can get work without any of them becoming readable, e.g.,
+    /// from a NIC it polls. This keeps its runtime out of the idle mode. The timers armed through
+    /// [`timer::Timers`] need no doorbell, the runtime wakes up for them.
+    #[inline]
+    fn doorbells(&self) -> Option<Vec<RawFd>> {
+        None
+    }
+
+    /// Processes a message sent to its tx input `index` right away, in place of the channel.
+    /// The sender of a fused edge calls it on the runtime of the engine, between two resumes of
+    /// the engine, see `runtime::fusion` of phoenixos. The engine-local storage of the engine is
+    /// not set for the call.
+    ///
+    /// Returns the message back to have it queued in the channel instead, e.g., if the engine
+    /// does not take messages this way, or if its outputs are full.
+    #[inline]
+    fn process_tx(&mut self, _index: usize, msg: EngineTxMessage) -> Result<(), EngineTxMessage> {
+        Err(msg)
+    }
+
+    /// Processes a message sent to its rx input `index` right away, see
+    /// [`Engine::process_tx`].
+    #[inline]
+    fn process_rx(&mut self, _index: usize, msg: EngineRxMessage) -> Result<(), EngineRxMessage> {
+        Err(msg)
+    }
+
     /// NOTE(wyj): temporary API
     /// engines should not have thread/runtime local states in the fugture
     /// Preform preparatory work before detaching the engine from runtime
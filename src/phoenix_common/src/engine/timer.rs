@@ -0,0 +1,467 @@
+//! Timers for the engines, e.g., for deadlines, keepalives, retransmissions and rate limiters.
+//!
+//! Each runtime keeps a hierarchical timing wheel, which it advances before resuming its engines.
+//! An engine arms timers through its own [`Timers`], on the wheel of the runtime it runs on, and
+//! takes the expired ones when it is resumed. The runtime does not wait in the idle mode past the
+//! next expiry of its wheel, so an engine that waits on timers can still return its doorbells.
+//!
+//! A timer stays on the wheel it was armed on. After its engine moves to another runtime, the
+//! timer still expires on time, but the engine may notice it only when its new runtime resumes
+//! it, which in the idle mode is at most `max_park_ms` later.
+use std::cell::RefCell;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashSet as HashSet;
+use thiserror::Error;
+
+/// The resolution of the timers. A timer never expires early, and at most a tick late plus the
+/// time to resume the engine.
+pub const TICK: Duration = Duration::from_micros(100);
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+/// Six levels of 64 slots cover 2^36 ticks, about 80 days. Later deadlines wait in the overflow.
+const LEVELS: usize = 6;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Timers can only be armed by an engine running on a runtime")]
+    NoRuntime,
+}
+
+/// Identifies a timer among the timers of an engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(pub u64);
+
+#[derive(Debug, Default)]
+struct State {
+    armed: HashSet<u64>,
+    expired: Vec<TimerId>,
+}
+
+/// The state shared by the timers of an engine and the wheels they are armed on.
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+}
+
+impl Shared {
+    /// Moves the timer to the expired ones, unless it has been cancelled.
+    fn expire(&self, token: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.armed.remove(&token) {
+            state.expired.push(TimerId(token));
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The timers of an engine.
+#[derive(Debug, Default)]
+pub struct Timers {
+    shared: Arc<Shared>,
+    next_token: u64,
+}
+
+impl Drop for Timers {
+    fn drop(&mut self) {
+        // the entries left on the wheels expire as no-ops
+        self.shared.state.lock().unwrap().armed.clear();
+    }
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Arms a timer expiring at `deadline`. Must be called from the engine's future, so that the
+    /// timer goes to the wheel of the runtime that resumes the engine.
+    pub fn register(&mut self, deadline: Instant) -> Result<TimerId, Error> {
+        let token = self.next_token;
+        WHEEL.with(|wheel| {
+            let mut wheel = wheel.borrow_mut();
+            let wheel = wheel.as_mut().ok_or(Error::NoRuntime)?;
+            self.shared.state.lock().unwrap().armed.insert(token);
+            wheel.insert(deadline, token, Arc::clone(&self.shared));
+            Ok(())
+        })?;
+        self.next_token += 1;
+        Ok(TimerId(token))
+    }
+
+    /// Arms a timer expiring after `delay`.
+    #[inline]
+    pub fn register_after(&mut self, delay: Duration) -> Result<TimerId, Error> {
+        self.register(Instant::now() + delay)
+    }
+
+    /// Cancels a timer. Returns false if it has expired or been cancelled already.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        self.shared.state.lock().unwrap().armed.remove(&id.0)
+    }
+
+    /// Takes the timers expired since the last call, in the order they expired.
+    pub fn expired(&mut self) -> Vec<TimerId> {
+        mem::take(&mut self.shared.state.lock().unwrap().expired)
+    }
+
+    /// The number of timers armed, neither expired nor cancelled.
+    pub fn armed(&self) -> usize {
+        self.shared.state.lock().unwrap().armed.len()
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// In ticks since the origin of the wheel.
+    deadline: u64,
+    token: u64,
+    timers: Arc<Shared>,
+}
+
+/// A hierarchical timing wheel. The slots of level `l` are `64^l` ticks wide, a timer sits on
+/// the level of the highest 6 bits its deadline differs from the current tick in. When the
+/// current tick reaches the start of a slot, the timers of the slot cascade to the lower levels.
+#[derive(Debug)]
+pub struct TimerWheel {
+    origin: Instant,
+    /// The ticks processed.
+    elapsed: u64,
+    levels: Vec<Vec<Vec<Entry>>>,
+    overflow: Vec<Entry>,
+    /// The entries already due when inserted, they expire on the next advance.
+    due: Vec<Entry>,
+    /// The number of entries in the levels and the overflow.
+    len: usize,
+}
+
+impl TimerWheel {
+    pub fn new() -> Self {
+        TimerWheel {
+            origin: Instant::now(),
+            elapsed: 0,
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            overflow: Vec::new(),
+            due: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Whether there is no entry, including those of the cancelled timers.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && self.due.is_empty()
+    }
+
+    /// The ticks from the origin to `t`, rounded up.
+    fn ticks_ceil(&self, t: Instant) -> u64 {
+        let nanos = t.saturating_duration_since(self.origin).as_nanos();
+        let tick = TICK.as_nanos();
+        ((nanos + tick - 1) / tick) as u64
+    }
+
+    fn insert(&mut self, deadline: Instant, token: u64, timers: Arc<Shared>) {
+        let entry = Entry {
+            deadline: self.ticks_ceil(deadline),
+            token,
+            timers,
+        };
+        if entry.deadline <= self.elapsed {
+            self.due.push(entry);
+        } else {
+            self.place(entry);
+        }
+    }
+
+    /// Puts an entry whose deadline is not before the current tick on its level.
+    fn place(&mut self, entry: Entry) {
+        let significant = (entry.deadline ^ self.elapsed) | SLOT_MASK;
+        let level = ((63 - significant.leading_zeros()) / SLOT_BITS) as usize;
+        if level >= LEVELS {
+            self.overflow.push(entry);
+        } else {
+            let slot = ((entry.deadline >> (level as u32 * SLOT_BITS)) & SLOT_MASK) as usize;
+            self.levels[level][slot].push(entry);
+        }
+        self.len += 1;
+    }
+
+    /// Moves the wheel to `now`, and returns the number of timers expired.
+    pub fn advance(&mut self, now: Instant) -> usize {
+        let mut nexpired = 0;
+        for entry in mem::take(&mut self.due) {
+            nexpired += entry.timers.expire(entry.token) as usize;
+        }
+
+        let target = now.saturating_duration_since(self.origin).as_nanos() / TICK.as_nanos();
+        let target = target as u64;
+        while self.elapsed < target {
+            if self.len == 0 {
+                self.elapsed = target;
+                break;
+            }
+            self.elapsed += 1;
+            let tick = self.elapsed;
+
+            // the overflow is checked once the top level wraps around
+            let top = (LEVELS as u32) * SLOT_BITS;
+            if tick & ((1 << top) - 1) == 0 {
+                for entry in mem::take(&mut self.overflow) {
+                    self.len -= 1;
+                    self.place(entry);
+                }
+            }
+            for level in (1..LEVELS).rev() {
+                let shift = level as u32 * SLOT_BITS;
+                if tick & ((1 << shift) - 1) == 0 {
+                    let slot = ((tick >> shift) & SLOT_MASK) as usize;
+                    for entry in mem::take(&mut self.levels[level][slot]) {
+                        self.len -= 1;
+                        self.place(entry);
+                    }
+                }
+            }
+            let slot = (tick & SLOT_MASK) as usize;
+            for entry in mem::take(&mut self.levels[0][slot]) {
+                self.len -= 1;
+                nexpired += entry.timers.expire(entry.token) as usize;
+            }
+        }
+        nexpired
+    }
+
+    /// The earliest deadline on the wheel, `None` if it is empty.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        if !self.due.is_empty() {
+            return Some(self.origin);
+        }
+        self.levels
+            .iter()
+            .flatten()
+            .flatten()
+            .chain(self.overflow.iter())
+            .map(|entry| entry.deadline)
+            .min()
+            .map(|deadline| self.origin + Duration::from_nanos(deadline * TICK.as_nanos() as u64))
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+thread_local! {
+    /// The wheel of the runtime on this thread.
+    static WHEEL: RefCell<Option<TimerWheel>> = RefCell::new(None);
+}
+
+/// Gives the calling runtime thread a wheel. Called by the runtime before resuming any engine.
+pub fn install_wheel() {
+    WHEEL.with(|wheel| {
+        wheel.borrow_mut().get_or_insert_with(TimerWheel::new);
+    });
+}
+
+/// Moves the wheel of this thread to `now`. Returns the number of timers expired. Called by the
+/// runtime before resuming its engines.
+pub fn advance_wheel(now: Instant) -> usize {
+    WHEEL.with(|wheel| {
+        wheel
+            .borrow_mut()
+            .as_mut()
+            .map_or(0, |wheel| wheel.advance(now))
+    })
+}
+
+/// The earliest deadline on the wheel of this thread. The runtime does not sleep past it.
+pub fn next_expiry() -> Option<Instant> {
+    WHEEL.with(|wheel| {
+        wheel
+            .borrow()
+            .as_ref()
+            .and_then(|wheel| wheel.next_expiry())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arms a timer on `wheel` directly, as `Timers::register` does on the wheel of the runtime.
+    fn arm(wheel: &mut TimerWheel, timers: &mut Timers, deadline: Instant) -> TimerId {
+        let token = timers.next_token;
+        timers.next_token += 1;
+        timers.shared.state.lock().unwrap().armed.insert(token);
+        wheel.insert(deadline, token, Arc::clone(&timers.shared));
+        TimerId(token)
+    }
+
+    fn ticks(n: u64) -> Duration {
+        Duration::from_nanos(TICK.as_nanos() as u64 * n)
+    }
+
+    #[test]
+    fn expire_on_time() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        // on each level, and on the boundaries of the slots
+        let deadlines = [
+            1,
+            2,
+            63,
+            64,
+            65,
+            100,
+            4095,
+            4096,
+            4097,
+            300_000,
+            262_144 * 2,
+        ];
+        let ids: Vec<_> = deadlines
+            .iter()
+            .map(|&n| arm(&mut wheel, &mut timers, origin + ticks(n)))
+            .collect();
+        assert_eq!(timers.armed(), deadlines.len());
+        for (&n, &id) in deadlines.iter().zip(ids.iter()) {
+            assert_eq!(wheel.advance(origin + ticks(n - 1)), 0, "{} ticks", n);
+            assert!(timers.expired().is_empty());
+            assert_eq!(wheel.advance(origin + ticks(n)), 1, "{} ticks", n);
+            assert_eq!(timers.expired(), [id]);
+        }
+        assert!(wheel.is_empty());
+        assert_eq!(timers.armed(), 0);
+    }
+
+    #[test]
+    fn expire_in_order() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        let deadlines = [5000, 10, 70, 10, 9];
+        let ids: Vec<_> = deadlines
+            .iter()
+            .map(|&n| arm(&mut wheel, &mut timers, origin + ticks(n)))
+            .collect();
+        assert_eq!(wheel.advance(origin + ticks(10_000)), deadlines.len());
+        assert_eq!(timers.expired(), [ids[4], ids[1], ids[3], ids[2], ids[0]]);
+        // taken once
+        assert!(timers.expired().is_empty());
+    }
+
+    #[test]
+    fn round_up_to_tick() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        let id = arm(&mut wheel, &mut timers, origin + ticks(3) + TICK / 2);
+        assert_eq!(wheel.advance(origin + ticks(3) + TICK / 2), 0);
+        assert_eq!(wheel.advance(origin + ticks(4)), 1);
+        assert_eq!(timers.expired(), [id]);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        let first = arm(&mut wheel, &mut timers, origin + ticks(10));
+        let second = arm(&mut wheel, &mut timers, origin + ticks(20));
+        assert!(timers.cancel(first));
+        assert!(!timers.cancel(first));
+        assert_eq!(timers.armed(), 1);
+        // the entry stays on the wheel, and expires as a no-op
+        assert!(!wheel.is_empty());
+        assert_eq!(wheel.advance(origin + ticks(30)), 1);
+        assert_eq!(timers.expired(), [second]);
+        assert!(!timers.cancel(second));
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn already_due() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        assert_eq!(wheel.advance(origin + ticks(100)), 0);
+        let id = arm(&mut wheel, &mut timers, origin + ticks(50));
+        assert_eq!(wheel.next_expiry(), Some(origin));
+        // on the next advance, even if the wheel does not move
+        assert_eq!(wheel.advance(origin + ticks(100)), 1);
+        assert_eq!(timers.expired(), [id]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn next_expiry() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        assert_eq!(wheel.next_expiry(), None);
+        arm(&mut wheel, &mut timers, origin + ticks(5000));
+        arm(&mut wheel, &mut timers, origin + ticks(70));
+        assert_eq!(wheel.next_expiry(), Some(origin + ticks(70)));
+        wheel.advance(origin + ticks(70));
+        assert_eq!(wheel.next_expiry(), Some(origin + ticks(5000)));
+        wheel.advance(origin + ticks(5000));
+        assert_eq!(wheel.next_expiry(), None);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        let far = origin + Duration::from_secs(100 * 86400);
+        arm(&mut wheel, &mut timers, far);
+        assert_eq!(wheel.overflow.len(), 1);
+        assert_eq!(wheel.next_expiry(), Some(far));
+        arm(&mut wheel, &mut timers, origin + ticks(1 << 35));
+        assert_eq!(wheel.overflow.len(), 1);
+    }
+
+    #[test]
+    fn dropped_timers() {
+        let mut wheel = TimerWheel::new();
+        let origin = wheel.origin;
+        let mut timers = Timers::new();
+        arm(&mut wheel, &mut timers, origin + ticks(10));
+        let shared = Arc::clone(&timers.shared);
+        drop(timers);
+        assert_eq!(wheel.advance(origin + ticks(10)), 0);
+        assert!(shared.state.lock().unwrap().expired.is_empty());
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn wheel_of_runtime() {
+        let mut timers = Timers::new();
+        assert!(matches!(
+            timers.register_after(Duration::ZERO),
+            Err(Error::NoRuntime)
+        ));
+        assert_eq!(advance_wheel(Instant::now()), 0);
+        assert_eq!(super::next_expiry(), None);
+
+        install_wheel();
+        let deadline = Instant::now() + Duration::from_millis(1);
+        let id = timers.register(deadline).unwrap();
+        let expiry = super::next_expiry().unwrap();
+        assert!(expiry >= deadline && expiry < deadline + TICK);
+        assert_eq!(advance_wheel(deadline - TICK), 0);
+        assert_eq!(advance_wheel(deadline + TICK), 1);
+        assert_eq!(timers.expired(), [id]);
+        assert_eq!(super::next_expiry(), None);
+    }
+}
@@ -0,0 +1,72 @@
+//! The event bus of the daemon, where the engines publish what the operators should know about,
+//! e.g., the alerts raised by an addon. The most recent events are kept in memory for the control
+//! clients to list with `ListEvents`, and every event is logged as well.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use ipc::control::{EventInfo, EventSeverity};
+
+/// The events older than this many are forgotten.
+const MAX_EVENTS: usize = 1024;
+/// So that the events listed fit in a response on the control socket.
+const MAX_LISTED: usize = 100;
+const MAX_MESSAGE_LEN: usize = 300;
+
+struct Bus {
+    next_seq: u64,
+    events: VecDeque<EventInfo>,
+}
+
+static BUS: Mutex<Bus> = Mutex::new(Bus {
+    next_seq: 1,
+    events: VecDeque::new(),
+});
+
+/// Publishes an event, and returns its sequence number. The message is truncated.
+pub fn publish(severity: EventSeverity, source: &str, kind: &str, mut message: String) -> u64 {
+    match severity {
+        EventSeverity::Info => tracing::info!("[{}] {}: {}", kind, source, message),
+        EventSeverity::Warning => tracing::warn!("[{}] {}: {}", kind, source, message),
+        EventSeverity::Critical => tracing::error!("[{}] {}: {}", kind, source, message),
+    }
+    if message.len() > MAX_MESSAGE_LEN {
+        let mut end = MAX_MESSAGE_LEN;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let mut bus = BUS.lock().unwrap();
+    let seq = bus.next_seq;
+    bus.next_seq += 1;
+    if bus.events.len() >= MAX_EVENTS {
+        bus.events.pop_front();
+    }
+    bus.events.push_back(EventInfo {
+        seq,
+        timestamp_ms,
+        severity,
+        source: source.to_owned(),
+        kind: kind.to_owned(),
+        message,
+    });
+    seq
+}
+
+/// Returns the events published after the sequence number `after`, oldest first, up to `limit`
+/// and at most `MAX_LISTED`.
+pub fn list(after: u64, limit: usize) -> Vec<EventInfo> {
+    let bus = BUS.lock().unwrap();
+    bus.events
+        .iter()
+        .filter(|e| e.seq > after)
+        .take(limit.min(MAX_LISTED))
+        .cloned()
+        .collect()
+}
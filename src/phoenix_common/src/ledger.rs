@@ -0,0 +1,167 @@
+//! Accounting of the resources the plugins hold on behalf of an application process.
+//!
+//! A plugin keeps an [`Account`] next to the resources of a process, e.g., in its per-process
+//! shared state, and records on it the memory regions it registers, the memfds it creates, and
+//! the bytes of shared memory it hands out, as well as their release. The account is dropped with
+//! the resources, which gives back whatever is still recorded on it.
+//!
+//! When the last subscription of a process is torn down, all its engines are gone and so should
+//! be its resources. [`check_released`] then reports any resource still held, with the
+//! [`metrics`](crate::metrics) and a warning, and fails a debug assertion in debug builds, e.g.,
+//! the tests. A leak is otherwise only noticed once the daemon runs out of file descriptors or
+//! of MTT entries, days later.
+use std::fmt;
+use std::sync::Mutex;
+
+use nix::libc::pid_t;
+use nix::unistd::Pid;
+
+/// An amount of resources.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// The memory regions registered to the NICs.
+    pub memory_regions: u64,
+    /// The memfds of the shared memory regions.
+    pub memfds: u64,
+    /// The bytes of shared memory allocated for the process.
+    pub heap_bytes: u64,
+}
+
+impl Usage {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == Usage::default()
+    }
+
+    fn add(&mut self, other: Usage) {
+        self.memory_regions += other.memory_regions;
+        self.memfds += other.memfds;
+        self.heap_bytes += other.heap_bytes;
+    }
+
+    fn sub(&mut self, other: Usage) {
+        // a release that was never acquired is a bug of the accounting, not worth a panic
+        self.memory_regions = self.memory_regions.saturating_sub(other.memory_regions);
+        self.memfds = self.memfds.saturating_sub(other.memfds);
+        self.heap_bytes = self.heap_bytes.saturating_sub(other.heap_bytes);
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} memory regions, {} memfds, {} heap bytes",
+            self.memory_regions, self.memfds, self.heap_bytes
+        )
+    }
+}
+
+// the processes with resources recorded, there are only a handful of them
+static LEDGER: Mutex<Vec<(pid_t, Usage)>> = Mutex::new(Vec::new());
+
+fn update<F: FnOnce(&mut Usage)>(pid: Pid, f: F) {
+    let mut ledger = LEDGER.lock().unwrap_or_else(|e| e.into_inner());
+    match ledger.iter_mut().find(|(p, _)| *p == pid.as_raw()) {
+        Some((_, usage)) => f(usage),
+        None => {
+            let mut usage = Usage::default();
+            f(&mut usage);
+            ledger.push((pid.as_raw(), usage));
+        }
+    }
+}
+
+/// The resources held for a process by a plugin.
+pub struct Account {
+    pid: Pid,
+    held: Mutex<Usage>,
+}
+
+impl fmt::Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("pid", &self.pid)
+            .field("held", &self.held())
+            .finish()
+    }
+}
+
+impl Account {
+    pub fn new(pid: Pid) -> Self {
+        Account {
+            pid,
+            held: Mutex::new(Usage::default()),
+        }
+    }
+
+    #[inline]
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// What is recorded on the account.
+    pub fn held(&self) -> Usage {
+        *self.held.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn acquire(&self, usage: Usage) {
+        self.held
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .add(usage);
+        update(self.pid, |u| u.add(usage));
+    }
+
+    pub fn release(&self, usage: Usage) {
+        self.held
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .sub(usage);
+        update(self.pid, |u| u.sub(usage));
+    }
+}
+
+impl Drop for Account {
+    fn drop(&mut self) {
+        // the resources still recorded are dropped with the account
+        let held = self.held();
+        if !held.is_empty() {
+            update(self.pid, |u| u.sub(held));
+        }
+    }
+}
+
+/// The resources held for `pid` by all the plugins.
+pub fn outstanding(pid: Pid) -> Usage {
+    let ledger = LEDGER.lock().unwrap_or_else(|e| e.into_inner());
+    ledger
+        .iter()
+        .find(|(p, _)| *p == pid.as_raw())
+        .map_or_else(Usage::default, |(_, usage)| *usage)
+}
+
+/// Checks that the resources of `pid` have all been released, once its last subscription is
+/// torn down, and forgets about the process. Returns what is still held.
+///
+/// # Panics
+///
+/// In debug builds, panics if any resource is still held.
+pub fn check_released(pid: Pid) -> Usage {
+    let leaked = {
+        let mut ledger = LEDGER.lock().unwrap_or_else(|e| e.into_inner());
+        match ledger.iter().position(|(p, _)| *p == pid.as_raw()) {
+            Some(pos) => ledger.swap_remove(pos).1,
+            None => Usage::default(),
+        }
+    };
+    if !leaked.is_empty() {
+        crate::metrics::record_leak(&format!("process {} torn down with {}", pid, leaked));
+        debug_assert!(
+            false,
+            "process {} is torn down with {} still held",
+            pid, leaked
+        );
+    }
+    leaked
+}
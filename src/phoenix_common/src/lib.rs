@@ -10,10 +10,14 @@ pub mod addon;
 #[allow(clippy::missing_safety_doc)]
 pub mod module;
 
+pub mod capability;
 pub mod engine;
 #[allow(clippy::missing_safety_doc)]
 pub mod envelop;
+pub mod event;
+pub mod ledger;
 pub mod local_resource;
+pub mod metrics;
 
 pub mod page_padded;
 pub mod resource;
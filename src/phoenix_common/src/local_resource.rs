@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::hash_map;
 use std::mem;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -8,11 +8,12 @@ use fnv::FnvHashMap as HashMap;
 
 use phoenix_api::Handle;
 
-use super::resource::Error;
+use super::resource::{Error, Versioned};
 
 #[derive(Debug)]
 pub struct LocalResourceTableGeneric<K: Eq + std::hash::Hash, R> {
     table: RefCell<HashMap<K, Entry<R>>>,
+    next_generation: Cell<u64>,
 }
 
 pub type LocalResourceTable<R> = LocalResourceTableGeneric<Handle, R>;
@@ -21,6 +22,7 @@ impl<K: Eq + std::hash::Hash, R> Default for LocalResourceTableGeneric<K, R> {
     fn default() -> Self {
         LocalResourceTableGeneric {
             table: RefCell::new(HashMap::default()),
+            next_generation: Cell::new(1),
         }
     }
 }
@@ -32,13 +34,15 @@ pub struct Entry<R> {
     // NOTE(cjr): either the data held here is Arc, or the resource table takes a closure to modify
     // operates on the resource.
     data: Arc<R>,
+    generation: u64,
 }
 
 impl<R> Entry<R> {
-    fn new(data: R, refcnt: usize) -> Self {
+    fn new(data: R, refcnt: usize, generation: u64) -> Self {
         Entry {
             refcnt: AtomicUsize::new(refcnt),
             data: Arc::new(data),
+            generation,
         }
     }
 
@@ -47,6 +51,20 @@ impl<R> Entry<R> {
         Arc::clone(&self.data)
     }
 
+    /// The generation the resource was inserted with.
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    #[inline]
+    fn check(&self, generation: u64) -> Result<&Self, Error> {
+        if self.generation != generation {
+            return Err(Error::Stale);
+        }
+        Ok(self)
+    }
+
     /// `Open` means to increment the reference count.
     #[inline]
     pub fn open(&self) {
@@ -65,13 +83,58 @@ impl<K: Eq + std::hash::Hash, R> LocalResourceTableGeneric<K, R> {
         &self.table
     }
 
+    #[inline]
+    fn next_generation(&self) -> u64 {
+        let generation = self.next_generation.get();
+        self.next_generation.set(generation + 1);
+        generation
+    }
+
     pub fn insert(&self, h: K, r: R) -> Result<(), Error> {
-        match self.table.borrow_mut().insert(h, Entry::new(r, 1)) {
+        let entry = Entry::new(r, 1, self.next_generation());
+        match self.table.borrow_mut().insert(h, entry) {
             Some(_) => Err(Error::Exists),
             None => Ok(()),
         }
     }
 
+    /// Inserts the resource and returns the versioned key to refer to it.
+    pub fn insert_versioned(&self, h: K, r: R) -> Result<Versioned<K>, Error>
+    where
+        K: Clone,
+    {
+        let generation = self.next_generation();
+        let entry = Entry::new(r, 1, generation);
+        match self.table.borrow_mut().insert(h.clone(), entry) {
+            Some(_) => Err(Error::Exists),
+            None => Ok(Versioned { key: h, generation }),
+        }
+    }
+
+    /// Returns the versioned key of the resource currently in the table under `h`.
+    pub fn versioned(&self, h: &K) -> Result<Versioned<K>, Error>
+    where
+        K: Clone,
+    {
+        let generation = self
+            .table
+            .borrow()
+            .get(h)
+            .ok_or(Error::NotFound)?
+            .generation();
+        Ok(Versioned {
+            key: h.clone(),
+            generation,
+        })
+    }
+
+    /// Returns the resource if it is still the one `v` refers to.
+    pub fn get_versioned(&self, v: &Versioned<K>) -> Result<Arc<R>, Error> {
+        let table = self.table.borrow();
+        let entry = table.get(&v.key).ok_or(Error::NotFound)?;
+        entry.check(v.generation).map(|e| e.data())
+    }
+
     pub fn get(&self, h: &K) -> Result<Arc<R>, Error> {
         self.table
             .borrow()
@@ -96,7 +159,7 @@ impl<K: Eq + std::hash::Hash, R> LocalResourceTableGeneric<K, R> {
                 mem::forget(r);
             }
             hash_map::Entry::Vacant(e) => {
-                e.insert(Entry::new(r, 0));
+                e.insert(Entry::new(r, 0, self.next_generation()));
             }
         }
     }
@@ -109,7 +172,7 @@ impl<K: Eq + std::hash::Hash, R> LocalResourceTableGeneric<K, R> {
                 mem::forget(r);
             }
             hash_map::Entry::Vacant(e) => {
-                e.insert(Entry::new(r, 1));
+                e.insert(Entry::new(r, 1, self.next_generation()));
             }
         }
     }
@@ -138,4 +201,70 @@ impl<K: Eq + std::hash::Hash, R> LocalResourceTableGeneric<K, R> {
         }
         Ok(None)
     }
+
+    /// Like [`close_resource`], but fails with [`Error::Stale`] if the resource is no longer the
+    /// one `v` refers to.
+    ///
+    /// [`close_resource`]: Self::close_resource
+    pub fn close_resource_versioned(&self, v: &Versioned<K>) -> Result<Option<Arc<R>>, Error> {
+        let mut table = self.table.borrow_mut();
+        let close = table
+            .get(&v.key)
+            .ok_or(Error::NotFound)?
+            .check(v.generation)?
+            .close();
+        if close {
+            return Ok(table.remove(&v.key).map(|r| r.data()));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versioned_get() {
+        let table = LocalResourceTable::default();
+        let v = table.insert_versioned(Handle(1), "a").unwrap();
+        assert_eq!(*table.get_versioned(&v).unwrap(), "a");
+        assert_eq!(table.versioned(&Handle(1)).unwrap(), v);
+        assert!(matches!(
+            table.insert_versioned(Handle(1), "b"),
+            Err(Error::Exists)
+        ));
+    }
+
+    #[test]
+    fn stale_after_reuse() {
+        let table = LocalResourceTable::default();
+        let old = table.insert_versioned(Handle(1), "old").unwrap();
+        assert_eq!(
+            *table.close_resource_versioned(&old).unwrap().unwrap(),
+            "old"
+        );
+        let new = table.insert_versioned(Handle(1), "new").unwrap();
+        assert_ne!(old.generation, new.generation);
+
+        assert!(matches!(table.get_versioned(&old), Err(Error::Stale)));
+        assert!(matches!(
+            table.close_resource_versioned(&old),
+            Err(Error::Stale)
+        ));
+        assert_eq!(*table.get_versioned(&new).unwrap(), "new");
+    }
+
+    #[test]
+    fn versioned_close_counts_references() {
+        let table = LocalResourceTable::default();
+        let v = table.insert_versioned(Handle(1), 7).unwrap();
+        table.open_resource(&Handle(1)).unwrap();
+        assert!(table.close_resource_versioned(&v).unwrap().is_none());
+        assert_eq!(*table.close_resource_versioned(&v).unwrap().unwrap(), 7);
+        assert!(matches!(
+            table.close_resource_versioned(&v),
+            Err(Error::NotFound)
+        ));
+    }
 }
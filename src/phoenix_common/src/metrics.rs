@@ -0,0 +1,122 @@
+//! Counters of events worth watching in a running daemon.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static UNEXPECTED_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Records an error that the datapath of a plugin does not expect, e.g., an IPC error other than
+/// those of the shared memory queues. Such an error fails the operation rather than the daemon.
+pub fn record_unexpected_error(context: &str) {
+    let count = UNEXPECTED_ERRORS.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::warn!("Unexpected error ({} so far): {}", count, context);
+}
+
+/// Returns the number of unexpected errors recorded since the daemon started.
+pub fn unexpected_errors() -> u64 {
+    UNEXPECTED_ERRORS.load(Ordering::Relaxed)
+}
+
+static DATAPATH_ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Records a heap allocation on the per-message path of an engine, e.g., when a reused buffer has
+/// to grow or an [`Arena`](crate::engine::datapath::arena::Arena) adds a chunk. Such allocations
+/// are expected to stop once the buffers reach their working size.
+#[inline]
+pub fn record_datapath_allocation() {
+    DATAPATH_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of datapath allocations recorded since the daemon started.
+pub fn datapath_allocations() -> u64 {
+    DATAPATH_ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+static EXPIRED_MESSAGES: AtomicU64 = AtomicU64::new(0);
+
+/// Records a message dropped by a forwarding engine as its hop limit is exhausted, which usually
+/// means that the message is caught in a forwarding loop.
+pub fn record_expired_message(context: &str) {
+    let count = EXPIRED_MESSAGES.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::warn!(
+        "Message dropped at hop limit ({} so far): {}",
+        count,
+        context
+    );
+}
+
+/// Returns the number of messages dropped at their hop limit since the daemon started.
+pub fn expired_messages() -> u64 {
+    EXPIRED_MESSAGES.load(Ordering::Relaxed)
+}
+
+static LINK_DOWN_EVENTS: AtomicU64 = AtomicU64::new(0);
+static LINK_UP_EVENTS: AtomicU64 = AtomicU64::new(0);
+static PORTS_DOWN: AtomicU64 = AtomicU64::new(0);
+
+/// Records a port of a device going down, or the device being removed.
+pub fn record_link_down(context: &str) {
+    let count = LINK_DOWN_EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+    PORTS_DOWN.fetch_add(1, Ordering::Relaxed);
+    tracing::warn!("Link down ({} so far): {}", count, context);
+}
+
+/// Records a port of a device that was down coming back up.
+pub fn record_link_up(context: &str) {
+    LINK_UP_EVENTS.fetch_add(1, Ordering::Relaxed);
+    PORTS_DOWN.fetch_sub(1, Ordering::Relaxed);
+    tracing::info!("Link up: {}", context);
+}
+
+/// Returns the number of times a port went down since the daemon started.
+pub fn link_down_events() -> u64 {
+    LINK_DOWN_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Returns the number of times a port came back up since the daemon started.
+pub fn link_up_events() -> u64 {
+    LINK_UP_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Returns the number of ports that are down now.
+pub fn ports_down() -> u64 {
+    PORTS_DOWN.load(Ordering::Relaxed)
+}
+
+static BACKEND_EJECTIONS: AtomicU64 = AtomicU64::new(0);
+static BACKENDS_EJECTED: AtomicU64 = AtomicU64::new(0);
+
+/// Records a backend ejected by a load balancer as an outlier among its backends.
+pub fn record_backend_ejected(context: &str) {
+    let count = BACKEND_EJECTIONS.fetch_add(1, Ordering::Relaxed) + 1;
+    BACKENDS_EJECTED.fetch_add(1, Ordering::Relaxed);
+    tracing::warn!("Backend ejected ({} so far): {}", count, context);
+}
+
+/// Records an ejected backend being reintroduced, or forgotten with its load balancer.
+pub fn record_backend_returned(context: &str) {
+    BACKENDS_EJECTED.fetch_sub(1, Ordering::Relaxed);
+    tracing::info!("Backend returned: {}", context);
+}
+
+/// Returns the number of times a backend was ejected since the daemon started.
+pub fn backend_ejections() -> u64 {
+    BACKEND_EJECTIONS.load(Ordering::Relaxed)
+}
+
+/// Returns the number of backends that are ejected now.
+pub fn backends_ejected() -> u64 {
+    BACKENDS_EJECTED.load(Ordering::Relaxed)
+}
+
+static LEAKS: AtomicU64 = AtomicU64::new(0);
+
+/// Records a process torn down while the plugins still hold some of its resources, e.g., a
+/// memory region that was never deregistered.
+pub fn record_leak(context: &str) {
+    let count = LEAKS.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::warn!("Resources leaked ({} so far): {}", count, context);
+}
+
+/// Returns the number of processes torn down with leaked resources since the daemon started.
+pub fn leaks() -> u64 {
+    LEAKS.load(Ordering::Relaxed)
+}
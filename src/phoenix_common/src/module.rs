@@ -7,6 +7,7 @@ use nix::unistd::Pid;
 use phoenix_api::engine::SchedulingMode;
 pub use semver::Version;
 
+use crate::capability::Capabilities;
 use crate::engine::datapath::node::{ChannelDescriptor, DataPathNode};
 use crate::engine::{Engine, EnginePair, EngineType};
 use crate::envelop::TypeTagged;
@@ -72,6 +73,12 @@ pub trait PhoenixModule: TypeTagged + Send + Sync + 'static {
         &[]
     }
 
+    /// What the engines need from the system, for the sandbox of the runtimes
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     /// Dependencies between the engines
     /// It may include external engines
     /// Dependencies should not include other services' engines
@@ -1,5 +1,5 @@
 use std::mem;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use dashmap::mapref::entry;
@@ -8,7 +8,7 @@ use fnv::FnvBuildHasher;
 
 use thiserror::Error;
 
-use phoenix_api::Handle;
+use phoenix_api::{ErrorCode, Handle};
 
 #[derive(Error, Debug, Clone)]
 pub enum Error {
@@ -18,11 +18,126 @@ pub enum Error {
     Exists,
     #[error("Slab is full or the maximum number of shards has been reached, please adjust the slab's configuration")]
     SlabFull,
+    #[error("Resource has been replaced in the table")]
+    Stale,
+}
+
+impl Error {
+    /// The code to report the error to the user with.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::NotFound | Error::Stale => ErrorCode::NotFound,
+            Error::Exists => ErrorCode::AlreadyExists,
+            Error::SlabFull => ErrorCode::ResourceExhausted,
+        }
+    }
+}
+
+/// A key together with the generation of the resource it refers to.
+///
+/// A key is often reused after its resource is removed, e.g., a file descriptor, but a
+/// generation never is, so a versioned key of a removed resource does not address the resource
+/// inserted with the same key later. A generation is unique within its table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Versioned<K> {
+    pub key: K,
+    pub generation: u64,
+}
+
+impl Versioned<Handle> {
+    /// Packs the versioned handle into a plain one, for an application that refers to the
+    /// resource by a plain handle, e.g., the `conn_id` of a message. The key takes the lower 32
+    /// bits and the generation the upper 32 bits, so both must fit in 32 bits.
+    #[inline]
+    pub fn pack(&self) -> Handle {
+        assert!(
+            self.key.0 <= u32::MAX as u64 && self.generation <= u32::MAX as u64,
+            "{self:?} does not fit in a handle"
+        );
+        Handle(self.generation << 32 | self.key.0)
+    }
+
+    /// Unpacks a handle packed by [`pack`](Self::pack).
+    #[inline]
+    pub fn unpack(handle: Handle) -> Self {
+        Versioned {
+            key: Handle(handle.0 & u32::MAX as u64),
+            generation: handle.0 >> 32,
+        }
+    }
+}
+
+/// Packed versioned handles for the resources an engine hands to the application, e.g., the
+/// connections of mRPC, whose own keys are reused or too wide to be packed with a generation.
+///
+/// A handle is a slot of the table and the generation the slot was filled with, see
+/// [`Versioned::pack`]. An emptied slot is filled again with the next generation, so a handle
+/// kept past its resource, e.g., across a reconnect, is rejected with [`Error::Stale`] rather
+/// than addressing the resource that took its slot.
+#[derive(Debug)]
+pub struct VersionedSlab<R> {
+    /// The generation of each slot and the resource in it.
+    slots: Vec<(u32, Option<R>)>,
+    /// The empty slots.
+    free: Vec<usize>,
+}
+
+impl<R> Default for VersionedSlab<R> {
+    fn default() -> Self {
+        VersionedSlab {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<R> VersionedSlab<R> {
+    /// Inserts the resource and returns the handle to refer to it.
+    pub fn insert(&mut self, r: R) -> Handle {
+        let slot = self.free.pop().unwrap_or_else(|| {
+            self.slots.push((0, None));
+            self.slots.len() - 1
+        });
+        let (generation, entry) = &mut self.slots[slot];
+        *generation = generation.wrapping_add(1);
+        *entry = Some(r);
+        Versioned {
+            key: Handle(slot as u64),
+            generation: *generation as u64,
+        }
+        .pack()
+    }
+
+    /// Returns the resource `handle` refers to.
+    pub fn get(&self, handle: Handle) -> Result<&R, Error> {
+        let (slot, generation) = self.slot(handle)?;
+        if generation != Versioned::unpack(handle).generation {
+            return Err(Error::Stale);
+        }
+        self.slots[slot].1.as_ref().ok_or(Error::NotFound)
+    }
+
+    /// Removes the resource `handle` refers to.
+    pub fn remove(&mut self, handle: Handle) -> Result<R, Error> {
+        self.get(handle)?;
+        let (slot, _) = self.slot(handle)?;
+        let r = self.slots[slot].1.take().unwrap();
+        self.free.push(slot);
+        Ok(r)
+    }
+
+    #[inline]
+    fn slot(&self, handle: Handle) -> Result<(usize, u64), Error> {
+        let slot = Versioned::unpack(handle).key.0 as usize;
+        let (generation, _) = self.slots.get(slot).ok_or(Error::NotFound)?;
+        Ok((slot, *generation as u64))
+    }
 }
 
 #[derive(Debug)]
 pub struct ResourceTableGeneric<K: Eq + std::hash::Hash, R> {
     table: DashMap<K, Entry<R>, FnvBuildHasher>,
+    next_generation: AtomicU64,
 }
 
 pub type ResourceTable<R> = ResourceTableGeneric<Handle, R>;
@@ -31,6 +146,7 @@ impl<K: Eq + std::hash::Hash, R> Default for ResourceTableGeneric<K, R> {
     fn default() -> Self {
         ResourceTableGeneric {
             table: DashMap::default(),
+            next_generation: AtomicU64::new(1),
         }
     }
 }
@@ -42,13 +158,15 @@ pub struct Entry<R> {
     // NOTE(cjr): either the data held here is Arc, or the resource table takes a closure to modify
     // operates on the resource.
     data: Arc<R>,
+    generation: u64,
 }
 
 impl<R> Entry<R> {
-    fn new(data: R, refcnt: usize) -> Self {
+    fn new(data: R, refcnt: usize, generation: u64) -> Self {
         Entry {
             refcnt: AtomicUsize::new(refcnt),
             data: Arc::new(data),
+            generation,
         }
     }
 
@@ -57,6 +175,20 @@ impl<R> Entry<R> {
         Arc::clone(&self.data)
     }
 
+    /// The generation the resource was inserted with.
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    #[inline]
+    fn check(&self, generation: u64) -> Result<&Self, Error> {
+        if self.generation != generation {
+            return Err(Error::Stale);
+        }
+        Ok(self)
+    }
+
     /// `Open` means to increment the reference count.
     #[inline]
     pub fn open(&self) {
@@ -75,13 +207,49 @@ impl<K: Eq + std::hash::Hash, R> ResourceTableGeneric<K, R> {
         &self.table
     }
 
+    #[inline]
+    fn next_generation(&self) -> u64 {
+        self.next_generation.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub fn insert(&self, h: K, r: R) -> Result<(), Error> {
-        match self.table.insert(h, Entry::new(r, 1)) {
+        let entry = Entry::new(r, 1, self.next_generation());
+        match self.table.insert(h, entry) {
             Some(_) => Err(Error::Exists),
             None => Ok(()),
         }
     }
 
+    /// Inserts the resource and returns the versioned key to refer to it.
+    pub fn insert_versioned(&self, h: K, r: R) -> Result<Versioned<K>, Error>
+    where
+        K: Clone,
+    {
+        let generation = self.next_generation();
+        match self.table.insert(h.clone(), Entry::new(r, 1, generation)) {
+            Some(_) => Err(Error::Exists),
+            None => Ok(Versioned { key: h, generation }),
+        }
+    }
+
+    /// Returns the versioned key of the resource currently in the table under `h`.
+    pub fn versioned(&self, h: &K) -> Result<Versioned<K>, Error>
+    where
+        K: Clone,
+    {
+        let generation = self.table.get(h).ok_or(Error::NotFound)?.generation();
+        Ok(Versioned {
+            key: h.clone(),
+            generation,
+        })
+    }
+
+    /// Returns the resource if it is still the one `v` refers to.
+    pub fn get_versioned(&self, v: &Versioned<K>) -> Result<Arc<R>, Error> {
+        let entry = self.table.get(&v.key).ok_or(Error::NotFound)?;
+        entry.check(v.generation).map(|e| e.data())
+    }
+
     pub fn get(&self, h: &K) -> Result<Arc<R>, Error> {
         self.table.get(h).map(|r| r.data()).ok_or(Error::NotFound)
     }
@@ -98,7 +266,7 @@ impl<K: Eq + std::hash::Hash, R> ResourceTableGeneric<K, R> {
                 mem::forget(r);
             }
             entry::Entry::Vacant(e) => {
-                e.insert(Entry::new(r, 0));
+                e.insert(Entry::new(r, 0, self.next_generation()));
             }
         }
     }
@@ -111,7 +279,7 @@ impl<K: Eq + std::hash::Hash, R> ResourceTableGeneric<K, R> {
                 mem::forget(r);
             }
             entry::Entry::Vacant(e) => {
-                e.insert(Entry::new(r, 1));
+                e.insert(Entry::new(r, 1, self.next_generation()));
             }
         }
     }
@@ -135,6 +303,32 @@ impl<K: Eq + std::hash::Hash, R> ResourceTableGeneric<K, R> {
         }
         Ok(None)
     }
+
+    /// Like [`close_resource`], but fails with [`Error::Stale`] if the resource is no longer the
+    /// one `v` refers to.
+    ///
+    /// [`close_resource`]: Self::close_resource
+    pub fn close_resource_versioned(&self, v: &Versioned<K>) -> Result<Option<Arc<R>>, Error> {
+        // The check, the decrement and the removal are done under the lock of the shard, so the
+        // entry cannot be replaced in between and the new resource removed in its place.
+        let mut result = Err(Error::NotFound);
+        let removed = self
+            .table
+            .remove_if(&v.key, |_, entry| match entry.check(v.generation) {
+                Ok(entry) => {
+                    result = Ok(None);
+                    entry.close()
+                }
+                Err(e) => {
+                    result = Err(e);
+                    false
+                }
+            });
+        match removed {
+            Some((_, entry)) => Ok(Some(entry.data())),
+            None => result,
+        }
+    }
 }
 
 use crate::page_padded::PagePadded;
@@ -199,6 +393,14 @@ impl<R> ResourceSlab<R> {
         }
     }
 
+    /// Returns the keys of the resources in the slab, in no particular order.
+    pub fn keys(&self) -> Vec<usize> {
+        self.inverse_table
+            .iter()
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
     #[inline]
     pub fn get_handle_from_key(&self, key: usize) -> Result<Handle, Error> {
         let h = self.inverse_table.get(&key).ok_or(Error::NotFound)?;
@@ -265,3 +467,116 @@ impl<R> ResourceSlab<R> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versioned_get() {
+        let table = ResourceTable::default();
+        let v = table.insert_versioned(Handle(1), "a").unwrap();
+        assert_eq!(v.key, Handle(1));
+        assert_eq!(*table.get_versioned(&v).unwrap(), "a");
+        assert_eq!(table.versioned(&Handle(1)).unwrap(), v);
+        assert!(matches!(
+            table.insert_versioned(Handle(1), "b"),
+            Err(Error::Exists)
+        ));
+        let missing = Versioned {
+            key: Handle(2),
+            generation: v.generation,
+        };
+        assert!(matches!(
+            table.get_versioned(&missing),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn stale_after_reuse() {
+        let table = ResourceTable::default();
+        let old = table.insert_versioned(Handle(1), "old").unwrap();
+        assert_eq!(
+            *table.close_resource_versioned(&old).unwrap().unwrap(),
+            "old"
+        );
+        let new = table.insert_versioned(Handle(1), "new").unwrap();
+        assert_ne!(old.generation, new.generation);
+
+        assert!(matches!(table.get_versioned(&old), Err(Error::Stale)));
+        assert!(matches!(
+            table.close_resource_versioned(&old),
+            Err(Error::Stale)
+        ));
+        // the stale close leaves the new resource alone
+        assert_eq!(*table.get_versioned(&new).unwrap(), "new");
+        assert_eq!(*table.get(&Handle(1)).unwrap(), "new");
+    }
+
+    #[test]
+    fn versioned_close_counts_references() {
+        let table = ResourceTable::default();
+        let v = table.insert_versioned(Handle(1), 7).unwrap();
+        table.open_resource(&Handle(1)).unwrap();
+        assert!(table.close_resource_versioned(&v).unwrap().is_none());
+        assert_eq!(*table.close_resource_versioned(&v).unwrap().unwrap(), 7);
+        assert!(matches!(
+            table.close_resource_versioned(&v),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn pack_versioned_handle() {
+        let v = Versioned {
+            key: Handle(7),
+            generation: 3,
+        };
+        assert_eq!(Versioned::unpack(v.pack()), v);
+        assert_ne!(v.pack(), Handle(7));
+    }
+
+    #[test]
+    fn stale_slot_after_reuse() {
+        let mut slab = VersionedSlab::default();
+        let old = slab.insert("old");
+        assert_eq!(slab.get(old).copied().unwrap(), "old");
+        assert_eq!(slab.remove(old).unwrap(), "old");
+        assert!(matches!(slab.get(old), Err(Error::NotFound)));
+
+        // the slot is reused with the next generation
+        let new = slab.insert("new");
+        assert_eq!(Versioned::unpack(new).key, Versioned::unpack(old).key);
+        assert_ne!(new, old);
+        assert!(matches!(slab.get(old), Err(Error::Stale)));
+        assert!(matches!(slab.remove(old), Err(Error::Stale)));
+        assert_eq!(slab.get(new).copied().unwrap(), "new");
+        assert!(matches!(slab.get(Handle(9)), Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn concurrent_versioned_close() {
+        const THREADS: usize = 8;
+        for _ in 0..100 {
+            let table = Arc::new(ResourceTable::default());
+            let v = table.insert_versioned(Handle(1), ()).unwrap();
+            for _ in 1..THREADS {
+                table.open_resource(&Handle(1)).unwrap();
+            }
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let table = Arc::clone(&table);
+                    std::thread::spawn(move || table.close_resource_versioned(&v).unwrap())
+                })
+                .collect();
+            let last = handles
+                .into_iter()
+                .filter(|h| h.join().unwrap().is_some())
+                .count();
+            // exactly the last reference removes the resource
+            assert_eq!(last, 1);
+            assert!(table.get(&Handle(1)).is_err());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Round trips of engine states through the code generated by `#[derive(EngineState)]`.
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::EngineState;
+use phoenix_common::module::{ModuleCollection, Version};
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+use phoenix_common::PhoenixResult;
+
+#[derive(EngineState)]
+struct EngineV1 {
+    #[state(node)]
+    node: DataPathNode,
+    count: u64,
+    name: String,
+    #[state(skip)]
+    scratch: Vec<u8>,
+    #[state(skip, with = "count * 2")]
+    doubled: u64,
+}
+
+#[derive(EngineState)]
+#[state(version = 2)]
+struct EngineV2 {
+    #[state(node)]
+    node: DataPathNode,
+    count: u64,
+    name: String,
+    #[state(skip)]
+    scratch: Vec<u8>,
+    #[state(skip, with = "count * 2")]
+    doubled: u64,
+    #[state(since = 2, default = "prev_version.major as u32 + 7")]
+    added: u32,
+    #[state(since = 2)]
+    flags: Vec<u32>,
+}
+
+fn engine_v1() -> EngineV1 {
+    EngineV1 {
+        node: DataPathNode::new(),
+        count: 21,
+        name: "engine".to_owned(),
+        scratch: vec![1, 2, 3],
+        doubled: 0,
+    }
+}
+
+fn restore<E: EngineState>(dump: (ResourceCollection, DataPathNode)) -> PhoenixResult<E> {
+    let (local, node) = dump;
+    let mut shared = SharedStorage::new();
+    let mut global = ResourceCollection::new();
+    let plugged = ModuleCollection::new();
+    E::restore(
+        local,
+        &mut shared,
+        &mut global,
+        node,
+        &plugged,
+        Version::new(1, 0, 0),
+    )
+}
+
+#[test]
+fn round_trip() {
+    assert_eq!(EngineV1::STATE_VERSION, 1);
+    let engine: EngineV1 = restore(engine_v1().dump()).unwrap();
+    assert_eq!(engine.count, 21);
+    assert_eq!(engine.name, "engine");
+    assert!(engine.node.tx_inputs.is_empty());
+    // skipped fields are not dumped
+    assert!(engine.scratch.is_empty());
+    assert_eq!(engine.doubled, 42);
+}
+
+#[test]
+fn skipped_fields_not_dumped() {
+    let (local, _node) = engine_v1().dump();
+    let mut keys: Vec<_> = local.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, ["__state_version", "count", "name"]);
+}
+
+#[test]
+fn restore_from_older_version() {
+    assert_eq!(EngineV2::STATE_VERSION, 2);
+    let engine: EngineV2 = restore(engine_v1().dump()).unwrap();
+    assert_eq!(engine.count, 21);
+    assert_eq!(engine.name, "engine");
+    assert!(engine.scratch.is_empty());
+    assert_eq!(engine.doubled, 42);
+    assert_eq!(engine.added, 8);
+    assert!(engine.flags.is_empty());
+}
+
+#[test]
+fn restore_fields_since_current_version() {
+    let engine = EngineV2 {
+        node: DataPathNode::new(),
+        count: 1,
+        name: String::new(),
+        scratch: Vec::new(),
+        doubled: 0,
+        added: 3,
+        flags: vec![5],
+    };
+    let engine: EngineV2 = restore(engine.dump()).unwrap();
+    assert_eq!(engine.added, 3);
+    assert_eq!(engine.flags, [5]);
+}
+
+#[test]
+fn reject_newer_version() {
+    let engine = EngineV2 {
+        node: DataPathNode::new(),
+        count: 1,
+        name: String::new(),
+        scratch: Vec::new(),
+        doubled: 0,
+        added: 3,
+        flags: Vec::new(),
+    };
+    let err = restore::<EngineV1>(engine.dump()).err().unwrap();
+    assert!(err.to_string().contains("version 2"), "{err}");
+}
+
+#[test]
+fn reject_missing_field() {
+    let (mut local, node) = engine_v1().dump();
+    local.remove("name");
+    let err = restore::<EngineV1>((local, node)).err().unwrap();
+    assert!(err.to_string().contains("name"), "{err}");
+}
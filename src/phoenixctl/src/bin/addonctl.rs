@@ -6,8 +6,8 @@ use phoenix_api::engine::SchedulingMode;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use ipc::control::Request;
-use ipc::control::{pid_t, AddonRequest};
+use ipc::control::{pid_t, AddonRequest, PortDescriptor};
+use ipc::control::{Request, Response, ResponseKind};
 use ipc::unix::DomainSocket;
 
 const MAX_MSG_LEN: usize = 65536;
@@ -35,10 +35,20 @@ lazy_static::lazy_static! {
 struct Opts {
     #[arg(short, long)]
     config: PathBuf,
-    #[arg(long)]
-    pid: pid_t,
-    #[arg(long)]
-    sid: u64,
+    #[arg(long, required_unless_present = "selector")]
+    pid: Option<pid_t>,
+    #[arg(long, required_unless_present = "selector")]
+    sid: Option<u64>,
+    /// Target the subscriptions whose labels match this selector, e.g., `app=search,env!=dev`,
+    /// instead of the one of `pid` and `sid`
+    #[arg(long, conflicts_with_all = ["pid", "sid"])]
+    selector: Option<String>,
+    /// Wait for the addon to be attached or detached, printing its progress
+    #[arg(short, long)]
+    wait: bool,
+    /// Only check the request against the subscription and print what it would do
+    #[arg(long, conflicts_with = "wait")]
+    dry_run: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -62,6 +72,9 @@ struct Config {
     op: AddonOp,
     config_path: Option<PathBuf>,
     config_string: Option<String>,
+    /// Named ports of the addon engine
+    #[serde(default)]
+    edges: Vec<PortDescriptor>,
 }
 
 impl Config {
@@ -87,23 +100,64 @@ fn main() {
     let sock = DomainSocket::bind(sock_path).unwrap();
 
     let request = AddonRequest {
-        pid: opts.pid,
-        sid: opts.sid,
+        pid: opts.pid.unwrap_or_default(),
+        sid: opts.sid.unwrap_or_default(),
         addon_engine: config.addon_engine,
         tx_channels_replacements: config.tx_channels_replacements,
         rx_channels_replacements: config.rx_channels_replacements,
         group: config.group,
         config_path: config.config_path,
         config_string: config.config_string,
+        ports: config.edges,
+        dry_run: opts.dry_run,
+        selector: opts.selector,
     };
     let req = if config.op == AddonOp::Attach {
         Request::AttachAddon(SchedulingMode::Dedicate, request)
     } else {
         Request::DetachAddon(request)
     };
+    let req = if opts.wait {
+        Request::Streaming(Box::new(req))
+    } else {
+        req
+    };
     let buf = bincode::serialize(&req).unwrap();
     assert!(buf.len() < MAX_MSG_LEN);
 
     let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
     sock.send_to(&buf, &service_path).unwrap();
+
+    if !opts.wait && !opts.dry_run {
+        return;
+    }
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    loop {
+        let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+        assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+        let res: Response = bincode::deserialize(&buf).unwrap();
+        match res.0 {
+            Ok(ResponseKind::Progress(event)) => {
+                let engine = event.engine_type.as_deref().unwrap_or("-");
+                println!(
+                    "pid {} sid {} {}: {:?}",
+                    event.pid, event.sid, engine, event.stage
+                );
+            }
+            Ok(ResponseKind::Completed) => break,
+            Ok(ResponseKind::Plan(plan)) => {
+                for (i, step) in plan.iter().enumerate() {
+                    println!("{}. {}", i + 1, step);
+                }
+                break;
+            }
+            Ok(_) => panic!("invalid response"),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
@@ -0,0 +1,163 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[macro_use]
+extern crate prettytable;
+use clap::Parser;
+use prettytable::Table;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use ipc::control::{CanaryOutcome, CanaryRequest, PluginDescriptor};
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix module canary control")]
+struct Opts {
+    /// Load the modules of this config, in the format of an upgrade config, as canaries, or
+    /// change the share of their canaries. Without any action, the canaries are listed
+    #[arg(short, long, requires = "percent", conflicts_with_all = ["promote", "abort"])]
+    config: Option<PathBuf>,
+    /// The share of the new processes assigned to the canaries, in percent
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    percent: Option<u8>,
+    /// Only assign the processes whose labels match this selector, e.g., `env=staging`
+    #[arg(long, requires = "config")]
+    selector: Option<String>,
+    /// Only check that the canaries can be loaded and print what it would do
+    #[arg(long, requires = "config")]
+    dry_run: bool,
+    /// Upgrade this module to its canary version
+    #[arg(long, conflicts_with = "abort")]
+    promote: Option<String>,
+    /// Stop assigning processes to the canary of this module
+    #[arg(long)]
+    abort: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    modules: Vec<PluginDescriptor>,
+}
+
+impl Config {
+    fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let content = std::fs::read_to_string(path).unwrap();
+        toml::from_str(&content).unwrap()
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+
+    let send_req = |req: Request| {
+        let buf = bincode::serialize(&req).unwrap();
+        assert!(buf.len() < MAX_MSG_LEN);
+        sock.send_to(&buf, &service_path).unwrap();
+    };
+    let recv_res = || {
+        let mut buf = vec![0u8; MAX_MSG_LEN];
+        let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+        assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+        let res: Response = bincode::deserialize(&buf).unwrap();
+        match res.0 {
+            Ok(kind) => kind,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    if let Some(config) = opts.config.as_ref() {
+        let config = Config::from_path(config);
+        for module in config.modules {
+            send_req(Request::StartCanary(CanaryRequest {
+                plugin: module,
+                percent: opts.percent.unwrap(),
+                selector: opts.selector.clone(),
+                dry_run: opts.dry_run,
+            }));
+            if opts.dry_run {
+                match recv_res() {
+                    ResponseKind::Plan(plan) => {
+                        for (i, step) in plan.iter().enumerate() {
+                            println!("{}. {}", i + 1, step);
+                        }
+                    }
+                    _ => panic!("invalid response"),
+                }
+            }
+        }
+        return;
+    }
+
+    if let Some(name) = opts.promote {
+        send_req(Request::EndCanary(name, CanaryOutcome::Promote));
+        return;
+    }
+    if let Some(name) = opts.abort {
+        send_req(Request::EndCanary(name, CanaryOutcome::Abort));
+        return;
+    }
+
+    send_req(Request::ListCanaries);
+    match recv_res() {
+        ResponseKind::Canaries(canaries) => {
+            let mut table = Table::new();
+            table.add_row(row![bFc => "Module", "Library", "Share", "Selector", "State", "PIDs"]);
+            for canary in canaries {
+                table.add_row(row![
+                    canary.name,
+                    canary.lib_path.display(),
+                    format!("{}%", canary.percent),
+                    canary.selector.unwrap_or_default(),
+                    if canary.draining { "ending" } else { "active" },
+                    canary
+                        .processes
+                        .iter()
+                        .map(|pid| pid.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ]);
+            }
+            table.printstd();
+        }
+        _ => panic!("invalid response"),
+    }
+}
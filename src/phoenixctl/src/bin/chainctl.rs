@@ -0,0 +1,136 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use phoenix_api::engine::SchedulingMode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use ipc::control::{pid_t, ChainRequest, ChainedAddon};
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+#[command(name = "Phoenix addon chain manager")]
+struct Opts {
+    #[arg(short, long)]
+    config: PathBuf,
+    #[arg(long)]
+    pid: pid_t,
+    #[arg(long)]
+    sid: u64,
+    /// Wait for the addons to be attached, printing their progress
+    #[arg(short, long)]
+    wait: bool,
+    /// Only check the chain against the subscription and print what attaching it would do
+    #[arg(long, conflicts_with = "wait")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    upstream: String,
+    downstream: String,
+    /// In the order the tx messages go through them
+    addons: Vec<ChainedAddon>,
+    #[serde(default)]
+    group: Vec<String>,
+}
+
+impl Config {
+    fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let content = std::fs::read_to_string(path).unwrap();
+        toml::from_str(&content).unwrap()
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+    let config = Config::from_path(opts.config);
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let request = ChainRequest {
+        pid: opts.pid,
+        sid: opts.sid,
+        upstream: config.upstream,
+        downstream: config.downstream,
+        addons: config.addons,
+        group: config.group,
+        dry_run: opts.dry_run,
+    };
+    let req = Request::AttachChain(SchedulingMode::Dedicate, request);
+    let req = if opts.wait {
+        Request::Streaming(Box::new(req))
+    } else {
+        req
+    };
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    if !opts.wait && !opts.dry_run {
+        return;
+    }
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    loop {
+        let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+        assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+        let res: Response = bincode::deserialize(&buf).unwrap();
+        match res.0 {
+            Ok(ResponseKind::Progress(event)) => {
+                let engine = event.engine_type.as_deref().unwrap_or("-");
+                println!(
+                    "pid {} sid {} {}: {:?}",
+                    event.pid, event.sid, engine, event.stage
+                );
+            }
+            Ok(ResponseKind::Completed) => break,
+            Ok(ResponseKind::Plan(plan)) => {
+                for (i, step) in plan.iter().enumerate() {
+                    println!("{}. {}", i + 1, step);
+                }
+                break;
+            }
+            Ok(_) => panic!("invalid response"),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
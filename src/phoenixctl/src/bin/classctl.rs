@@ -0,0 +1,85 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use uuid::Uuid;
+
+use ipc::control::Request;
+use ipc::unix::DomainSocket;
+use phoenix_api::engine::SchedulingClass;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Class {
+    /// A dedicated runtime and core for each scheduling group, spinning
+    LatencyCritical,
+    /// Runtimes shared with other throughput subscriptions, spinning
+    Throughput,
+    /// Runtimes shared with other background subscriptions, sleeping when idle
+    Background,
+}
+
+impl From<Class> for SchedulingClass {
+    fn from(class: Class) -> Self {
+        match class {
+            Class::LatencyCritical => SchedulingClass::LatencyCritical,
+            Class::Throughput => SchedulingClass::Throughput,
+            Class::Background => SchedulingClass::Background,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix subscription scheduling class control")]
+struct Opts {
+    /// Process ID of the application
+    #[arg(short, long)]
+    pid: i32,
+    /// Service subscription ID
+    #[arg(short, long)]
+    sid: u64,
+    /// The new scheduling class
+    #[arg(short, long, value_enum)]
+    class: Class,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::SetSchedulingClass(opts.pid, opts.sid, opts.class.into());
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+}
@@ -0,0 +1,136 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[macro_use]
+extern crate prettytable;
+use clap::Parser;
+use prettytable::Table;
+use uuid::Uuid;
+
+use ipc::control::{EventInfo, Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix event bus viewer")]
+struct Opts {
+    /// List the events after this sequence number
+    #[arg(short, long, default_value_t = 0)]
+    after: u64,
+    /// Number of events to list, at most 100
+    #[arg(short, long, default_value_t = 100)]
+    limit: usize,
+    /// Only list the events of this kind, e.g., slo.burn_rate
+    #[arg(short, long)]
+    kind: Option<String>,
+    /// Keep polling for new events, one per line
+    #[arg(short, long)]
+    follow: bool,
+    /// Dump the events in JSON
+    #[arg(short, long)]
+    json: bool,
+}
+
+fn list_events(sock: &DomainSocket, after: u64, limit: usize) -> Vec<EventInfo> {
+    let req = Request::ListEvents(after, limit);
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    match res.0.unwrap() {
+        ResponseKind::Events(events) => events,
+        _ => panic!("invalid response"),
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let matches = |e: &EventInfo| opts.kind.as_ref().map_or(true, |kind| &e.kind == kind);
+
+    if !opts.follow {
+        let events: Vec<_> = list_events(&sock, opts.after, opts.limit)
+            .into_iter()
+            .filter(matches)
+            .collect();
+        if opts.json {
+            println!("{}", serde_json::to_string_pretty(&events).unwrap());
+            return;
+        }
+        let mut table = Table::new();
+        table.add_row(row![bFc => "Seq", "Time (ms)", "Severity", "Kind", "Source", "Message"]);
+        for e in events {
+            table.add_row(row![
+                e.seq,
+                e.timestamp_ms,
+                format!("{:?}", e.severity),
+                e.kind,
+                e.source,
+                e.message
+            ]);
+        }
+        table.printstd();
+        return;
+    }
+
+    let mut after = opts.after;
+    loop {
+        let events = list_events(&sock, after, opts.limit);
+        let caught_up = events.len() < opts.limit.min(100);
+        for e in events {
+            after = e.seq;
+            if !matches(&e) {
+                continue;
+            }
+            if opts.json {
+                println!("{}", serde_json::to_string(&e).unwrap());
+            } else {
+                println!(
+                    "{} {} {:?} [{}] {}: {}",
+                    e.seq, e.timestamp_ms, e.severity, e.kind, e.source, e.message
+                );
+            }
+        }
+        if caught_up {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
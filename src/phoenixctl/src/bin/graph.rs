@@ -0,0 +1,148 @@
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use uuid::Uuid;
+
+use ipc::control::{ChannelDirection, DataPathGraphInfo, Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Dot,
+    Json,
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix datapath graph viewer")]
+struct Opts {
+    /// Target user process, can be omitted if the subscription ID is unambiguous
+    #[arg(short, long)]
+    pid: Option<i32>,
+    /// Target service subscription
+    #[arg(short, long)]
+    sid: u64,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Dot)]
+    format: Format,
+}
+
+/// Renders the graph in Graphviz DOT language, e.g., `phoenixctl graph --sid 3 | dot -Tsvg`.
+/// Engines in the same scheduling group are clustered together.
+fn to_dot(graph: &DataPathGraphInfo) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph {{").unwrap();
+    writeln!(
+        dot,
+        "    label=\"{} (pid={}, sid={})\";",
+        graph.service, graph.pid, graph.sid
+    )
+    .unwrap();
+    writeln!(dot, "    node [shape=box];").unwrap();
+
+    let mut groups = graph.engines.iter().map(|e| e.gid).collect::<Vec<_>>();
+    groups.sort_unstable();
+    groups.dedup();
+    for gid in groups {
+        writeln!(dot, "    subgraph cluster_{} {{", gid).unwrap();
+        writeln!(dot, "        label=\"gid={}\";", gid).unwrap();
+        for engine in graph.engines.iter().filter(|e| e.gid == gid) {
+            let style = if graph.addons.contains(&engine.engine_type) {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            writeln!(
+                dot,
+                "        \"{}\" [label=\"{}\\neid={}, rid={}\"{}];",
+                engine.engine_type, engine.engine_type, engine.eid, engine.rid, style
+            )
+            .unwrap();
+        }
+        writeln!(dot, "    }}").unwrap();
+    }
+
+    for channel in graph.channels.iter() {
+        let (direction, color) = match channel.direction {
+            ChannelDirection::Tx => ("tx", "blue"),
+            ChannelDirection::Rx => ("rx", "red"),
+        };
+        let depth = channel
+            .queue_depth
+            .map_or_else(|| "?".to_string(), |d| d.to_string());
+        writeln!(
+            dot,
+            "    \"{}\" -> \"{}\" [label=\"{} {}->{}\\ndepth={}\", color={}];",
+            channel.sender,
+            channel.receiver,
+            direction,
+            channel.sender_index,
+            channel.receiver_index,
+            depth,
+            color
+        )
+        .unwrap();
+    }
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::DataPathGraph(opts.pid, opts.sid);
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    match res.0 {
+        Ok(ResponseKind::DataPathGraph(graph)) => match opts.format {
+            Format::Dot => print!("{}", to_dot(&graph)),
+            Format::Json => println!("{}", serde_json::to_string_pretty(&graph).unwrap()),
+        },
+        Ok(_) => panic!("invalid response"),
+        Err(e) => {
+            eprintln!("Failed to get the datapath graph: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -61,7 +61,7 @@ fn main() {
     let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
     sock.send_to(&buf, &service_path).unwrap();
 
-    let mut buf = vec![0u8; 4096];
+    let mut buf = vec![0u8; MAX_MSG_LEN];
     let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
     assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
 
@@ -77,21 +77,63 @@ fn main() {
                 let mut services = HashMap::with_capacity(subscriptions.len());
                 let mut engine_tables = HashMap::new();
                 for subscription in subscriptions {
+                    let cpu_share = subscription
+                        .cpu_share
+                        .map_or_else(|| "None".to_string(), |share| format!("{:.2}", share));
+                    let labels = if !subscription.labels.is_empty() {
+                        subscription
+                            .labels
+                            .iter()
+                            .map(|(key, value)| format!("{}={}", key, value))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    } else {
+                        "None".to_string()
+                    };
+                    // the modules of the subscription running on their canary version
+                    let service = if !subscription.canaries.is_empty() {
+                        format!(
+                            "{} (canary: {})",
+                            subscription.service,
+                            subscription.canaries.join(", ")
+                        )
+                    } else {
+                        subscription.service
+                    };
                     services.insert(
                         (subscription.pid, subscription.sid),
-                        (subscription.service, subscription.addons),
+                        (service, subscription.addons, cpu_share, labels),
                     );
                     let mut table = Table::new();
-                    table.add_row(row![bFc => "EngineId", "EngineType"]);
+                    table.add_row(
+                        row![bFc => "EngineId", "EngineType", "CPU Time (ms)", "Resumes", "Avg Quantum (ns)", "Fused Resumes", "Direct Calls", "Fused Saved (us)"],
+                    );
                     for (engine_id, engine_type) in subscription.engines {
-                        table.add_row(row![Fc => engine_id, engine_type]);
+                        let times = subscription
+                            .engine_times
+                            .iter()
+                            .find(|t| t.eid == engine_id);
+                        if let Some(t) = times {
+                            let cpu_time_ms = t.cpu_time_ns / 1_000_000;
+                            let fused_saved_us = t.fused_saved_ns / 1_000;
+                            table.add_row(row![Fc =>
+                                engine_id, engine_type, cpu_time_ms, t.resumes, t.avg_quantum_ns,
+                                t.fused_resumes, t.direct_calls, fused_saved_us
+                            ]);
+                        } else {
+                            table.add_row(
+                                row![Fc => engine_id, engine_type, "-", "-", "-", "-", "-", "-"],
+                            );
+                        }
                     }
                     engine_tables.insert((subscription.pid, subscription.sid), table);
                 }
 
                 let mut table = Table::new();
-                table.add_row(row![bFm => "PID", "SID", "Service", "Addons", "Engines"]);
-                for ((pid, sid), (service, addons)) in services.into_iter() {
+                table.add_row(
+                    row![bFm => "PID", "SID", "Service", "Addons", "CPU Share", "Labels", "Engines"],
+                );
+                for ((pid, sid), (service, addons, cpu_share, labels)) in services.into_iter() {
                     let engines = engine_tables.remove(&(pid, sid)).unwrap();
                     let addons = if !addons.is_empty() {
                         addons.join(", ")
@@ -99,9 +141,13 @@ fn main() {
                         "None".to_string()
                     };
                     if engines.len() > 1 {
-                        table.add_row(row![pid, sid, service, Fy->addons, Fb->engines]);
+                        table.add_row(
+                            row![pid, sid, service, Fy->addons, cpu_share, labels, Fb->engines],
+                        );
                     } else {
-                        table.add_row(row![pid, sid, service, Fy->addons, Fb->"None"]);
+                        table.add_row(
+                            row![pid, sid, service, Fy->addons, cpu_share, labels, Fb->"None"],
+                        );
                     }
                 }
                 table.printstd();
@@ -0,0 +1,98 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[macro_use]
+extern crate prettytable;
+use clap::Parser;
+use prettytable::Table;
+use uuid::Uuid;
+
+use ipc::control::{MetricsInfo, Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix metrics")]
+struct Opts {
+    /// Dump the counters in JSON
+    #[arg(short, long)]
+    json: bool,
+}
+
+fn list_metrics() -> MetricsInfo {
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::ListMetrics;
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+    let res: Response = bincode::deserialize(&buf).unwrap();
+
+    match res.0 {
+        Ok(ResponseKind::Metrics(metrics)) => metrics,
+        Ok(_) => panic!("invalid response"),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let metrics = list_metrics();
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&metrics).unwrap());
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row![bFc => "Counter", "Value"]);
+    table.add_row(row!["Unexpected errors", metrics.unexpected_errors]);
+    table.add_row(row!["Datapath allocations", metrics.datapath_allocations]);
+    table.add_row(row![
+        "Messages expired at hop limit",
+        metrics.expired_messages
+    ]);
+    table.add_row(row!["Link down events", metrics.link_down_events]);
+    table.add_row(row!["Link up events", metrics.link_up_events]);
+    table.add_row(row!["Ports down", metrics.ports_down]);
+    table.add_row(row!["Backend ejections", metrics.backend_ejections]);
+    table.add_row(row!["Backends ejected", metrics.backends_ejected]);
+    table.add_row(row!["Processes with leaked resources", metrics.leaks]);
+    table.printstd();
+}
@@ -0,0 +1,152 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[macro_use]
+extern crate prettytable;
+use clap::Parser;
+use prettytable::Table;
+use uuid::Uuid;
+
+use ipc::control::{PluginInfo, PluginManifest, Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix plugin list")]
+struct Opts {
+    /// List the manifests of the plugins deployed in this directory, e.g.,
+    /// `$PHOENIX_PREFIX/plugins`, instead of the plugins loaded by the daemon
+    #[arg(short, long)]
+    available: Option<PathBuf>,
+    /// Dump the plugins in JSON
+    #[arg(short, long)]
+    json: bool,
+}
+
+fn read_manifests(dir: &Path) -> Vec<(PathBuf, PluginManifest)> {
+    let mut manifests = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let is_manifest = path.extension().map_or(false, |ext| ext == "toml")
+            && path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("libphoenix_");
+        if !is_manifest {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        match toml::from_str(&content) {
+            Ok(manifest) => manifests.push((path, manifest)),
+            Err(e) => eprintln!("skipping invalid manifest {}: {}", path.display(), e),
+        }
+    }
+    manifests.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+    manifests
+}
+
+fn list_plugins() -> Vec<PluginInfo> {
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::ListPlugins;
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+    let res: Response = bincode::deserialize(&buf).unwrap();
+
+    match res.0 {
+        Ok(ResponseKind::Plugins(plugins)) => plugins,
+        Ok(_) => panic!("invalid response"),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    if let Some(dir) = opts.available.as_ref() {
+        let manifests = read_manifests(dir);
+        if opts.json {
+            let manifests: Vec<_> = manifests.into_iter().map(|(_, m)| m).collect();
+            println!("{}", serde_json::to_string_pretty(&manifests).unwrap());
+            return;
+        }
+        let mut table = Table::new();
+        table.add_row(
+            row![bFc => "Plugin", "Kind", "Version", "Engines", "Min Core Version", "Manifest"],
+        );
+        for (path, manifest) in manifests {
+            table.add_row(row![
+                manifest.name,
+                format!("{:?}", manifest.kind),
+                manifest.version,
+                manifest.engines.join(" "),
+                manifest.min_core_version.unwrap_or_default(),
+                path.display(),
+            ]);
+        }
+        table.printstd();
+        return;
+    }
+
+    let plugins = list_plugins();
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&plugins).unwrap());
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row![bFc => "Plugin", "Kind", "Version", "Engines", "Library", "Manifest"]);
+    for plugin in plugins {
+        table.add_row(row![
+            plugin.name,
+            format!("{:?}", plugin.kind),
+            plugin.version,
+            plugin.engines.join(" "),
+            plugin.lib_path.display(),
+            if plugin.manifest.is_some() {
+                "yes"
+            } else {
+                "no"
+            },
+        ]);
+    }
+    table.printstd();
+}
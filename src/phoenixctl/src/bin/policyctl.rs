@@ -0,0 +1,116 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use phoenix_api::engine::SchedulingMode;
+use uuid::Uuid;
+
+use ipc::control::{pid_t, PolicyRequest};
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+#[command(name = "Phoenix datapath policy manager")]
+struct Opts {
+    /// The policy, compiled by phoenixd into a chain of addons
+    #[arg(short, long)]
+    policy: PathBuf,
+    #[arg(long)]
+    pid: pid_t,
+    #[arg(long)]
+    sid: u64,
+    /// Wait for the addons to be attached, printing their progress
+    #[arg(short, long)]
+    wait: bool,
+    /// Only compile the policy, check its chain against the subscription and print what
+    /// applying it would do
+    #[arg(long, conflicts_with = "wait")]
+    dry_run: bool,
+}
+
+fn main() {
+    let opts = Opts::parse();
+    let policy = std::fs::read_to_string(opts.policy).unwrap();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let request = PolicyRequest {
+        pid: opts.pid,
+        sid: opts.sid,
+        policy,
+        dry_run: opts.dry_run,
+    };
+    let req = Request::ApplyPolicy(SchedulingMode::Dedicate, request);
+    let req = if opts.wait {
+        Request::Streaming(Box::new(req))
+    } else {
+        req
+    };
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    if !opts.wait && !opts.dry_run {
+        return;
+    }
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    loop {
+        let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+        assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+        let res: Response = bincode::deserialize(&buf).unwrap();
+        match res.0 {
+            Ok(ResponseKind::Progress(event)) => {
+                let engine = event.engine_type.as_deref().unwrap_or("-");
+                println!(
+                    "pid {} sid {} {}: {:?}",
+                    event.pid, event.sid, engine, event.stage
+                );
+            }
+            Ok(ResponseKind::Completed) => break,
+            Ok(ResponseKind::Plan(plan)) => {
+                for (i, step) in plan.iter().enumerate() {
+                    println!("{}. {}", i + 1, step);
+                }
+                break;
+            }
+            Ok(_) => panic!("invalid response"),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
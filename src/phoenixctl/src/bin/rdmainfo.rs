@@ -0,0 +1,192 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use prettytable::{row, Table};
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+use phoenix_api::transport::rdma::control_plane::{IntrospectInfo, Query, QueryResponse};
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+/// The engines that make RDMA connections, the transport itself or an mRPC adapter using it.
+const RDMA_ENGINES: &[&str] = &["RdmaTransportEngine", "RpcAdapterEngine"];
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix RDMA queue pair, completion queue, and SRQ viewer")]
+struct Opts {
+    /// Target user process
+    #[arg(short, long)]
+    pid: i32,
+    /// Target service subscription
+    #[arg(short, long)]
+    sid: u64,
+    /// Print the attributes in JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn request(sock: &DomainSocket, req: &Request) -> ResponseKind {
+    let buf = bincode::serialize(req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    match res.0 {
+        Ok(kind) => kind,
+        Err(e) => {
+            eprintln!("Request failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_info(info: &IntrospectInfo) {
+    let mut table = Table::new();
+    table.set_titles(row![
+        "CmId",
+        "Local",
+        "Peer",
+        "QPN",
+        "State",
+        "MTU",
+        "Timeout",
+        "Retry",
+        "RNR retry",
+        "Min RNR timer",
+        "RD atomic",
+        "Dest RD atomic",
+        "Send WR/SGE",
+        "Recv WR/SGE",
+        "Inline",
+        "Send CQE",
+        "Recv CQE"
+    ]);
+    for conn in info.connections.iter() {
+        let cmid = conn.cmid.0 .0;
+        let send_cqe = conn.send_cq.cqe;
+        let recv_cqe = conn.recv_cq.cqe;
+        match &conn.qp {
+            Some(qp) => table.add_row(row![
+                cmid,
+                conn.local_addr,
+                conn.peer_addr,
+                qp.qp_num,
+                qp.state,
+                qp.path_mtu,
+                qp.timeout,
+                qp.retry_cnt,
+                qp.rnr_retry,
+                qp.min_rnr_timer,
+                qp.max_rd_atomic,
+                qp.max_dest_rd_atomic,
+                format!("{}/{}", qp.cap.max_send_wr, qp.cap.max_send_sge),
+                format!("{}/{}", qp.cap.max_recv_wr, qp.cap.max_recv_sge),
+                qp.cap.max_inline_data,
+                send_cqe,
+                recv_cqe
+            ]),
+            None => table.add_row(row![
+                cmid,
+                conn.local_addr,
+                conn.peer_addr,
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                "?",
+                send_cqe,
+                recv_cqe
+            ]),
+        };
+    }
+    table.printstd();
+
+    if !info.srqs.is_empty() {
+        let mut table = Table::new();
+        table.set_titles(row!["DC target", "Max WR", "Max SGE", "Limit"]);
+        for srq in info.srqs.iter() {
+            table.add_row(row![srq.dct.0 .0, srq.max_wr, srq.max_sge, srq.srq_limit]);
+        }
+        table.printstd();
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    // find the engine making the connections of the subscription
+    let subscriptions = match request(&sock, &Request::ListSubscription) {
+        ResponseKind::ListSubscription(subscriptions) => subscriptions,
+        _ => panic!("invalid response"),
+    };
+    let eid = subscriptions
+        .iter()
+        .filter(|s| s.pid == opts.pid && s.sid == opts.sid)
+        .flat_map(|s| s.engines.iter())
+        .find_map(|(eid, ty)| RDMA_ENGINES.contains(&ty.as_str()).then_some(*eid));
+    let Some(eid) = eid else {
+        eprintln!(
+            "None of {:?} found in subscription pid={}, sid={}",
+            RDMA_ENGINES, opts.pid, opts.sid
+        );
+        std::process::exit(1);
+    };
+
+    let query = bincode::serialize(&Query::Introspect).unwrap();
+    let answer = match request(&sock, &Request::EngineQuery(eid, query)) {
+        ResponseKind::EngineQuery(answer) => answer,
+        _ => panic!("invalid response"),
+    };
+    let QueryResponse::Introspect(info) = bincode::deserialize(&answer).unwrap();
+
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+    } else {
+        print_info(&info);
+    }
+}
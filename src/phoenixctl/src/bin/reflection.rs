@@ -0,0 +1,139 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+use phoenix_api_mrpc::control_plane::{Query, QueryResponse, ReflectionInfo};
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+const MRPC_ENGINE: &str = "MrpcEngine";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix mRPC reflection viewer")]
+struct Opts {
+    /// Target user process
+    #[arg(short, long)]
+    pid: i32,
+    /// Target mRPC service subscription
+    #[arg(short, long)]
+    sid: u64,
+    /// Print the reflection in JSON, including the encoded FileDescriptorSets
+    #[arg(long)]
+    json: bool,
+}
+
+fn request(sock: &DomainSocket, req: &Request) -> ResponseKind {
+    let buf = bincode::serialize(req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    match res.0 {
+        Ok(kind) => kind,
+        Err(e) => {
+            eprintln!("Request failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_reflection(info: &ReflectionInfo) {
+    for service in info.services.iter() {
+        println!(
+            "service {} (service_id={})",
+            service.name, service.service_id
+        );
+        for method in service.methods.iter() {
+            println!(
+                "    rpc {}({}) returns ({}) (func_id={})",
+                method.name, method.input_type, method.output_type, method.func_id
+            );
+        }
+    }
+    for message in info.messages.iter() {
+        println!("message {}", message.name);
+        for field in message.fields.iter() {
+            println!(
+                "    {}{} {} = {}",
+                if field.repeated { "repeated " } else { "" },
+                field.type_name,
+                field.name,
+                field.number
+            );
+        }
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    // find the MrpcEngine of the subscription
+    let subscriptions = match request(&sock, &Request::ListSubscription) {
+        ResponseKind::ListSubscription(subscriptions) => subscriptions,
+        _ => panic!("invalid response"),
+    };
+    let eid = subscriptions
+        .iter()
+        .filter(|s| s.pid == opts.pid && s.sid == opts.sid)
+        .flat_map(|s| s.engines.iter())
+        .find_map(|(eid, ty)| (ty == MRPC_ENGINE).then_some(*eid));
+    let Some(eid) = eid else {
+        eprintln!(
+            "No {} found in subscription pid={}, sid={}",
+            MRPC_ENGINE, opts.pid, opts.sid
+        );
+        std::process::exit(1);
+    };
+
+    let query = bincode::serialize(&Query::Reflection).unwrap();
+    let answer = match request(&sock, &Request::EngineQuery(eid, query)) {
+        ResponseKind::EngineQuery(answer) => answer,
+        _ => panic!("invalid response"),
+    };
+    let QueryResponse::Reflection(info) = bincode::deserialize(&answer).unwrap();
+
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+    } else {
+        print_reflection(&info);
+    }
+}
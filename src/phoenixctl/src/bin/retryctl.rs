@@ -0,0 +1,112 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[macro_use]
+extern crate prettytable;
+use clap::Parser;
+use prettytable::Table;
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+use phoenix_api_policy_retry::control_plane::{Query, QueryResponse, Request as RetryRequest};
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix retry policy viewer")]
+struct Opts {
+    /// EngineId of the RetryEngine
+    #[arg(short, long)]
+    eid: u64,
+    /// Replace the retry policy with the one in this TOML file instead
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Dump the counters in JSON
+    #[arg(short, long)]
+    json: bool,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    if let Some(path) = opts.config {
+        let content = std::fs::read_to_string(path).unwrap();
+        let request = bincode::serialize(&RetryRequest::NewConfig(content)).unwrap();
+        let req = Request::EngineRequest(opts.eid, request);
+        let buf = bincode::serialize(&req).unwrap();
+        assert!(buf.len() < MAX_MSG_LEN);
+        sock.send_to(&buf, &service_path).unwrap();
+        return;
+    }
+
+    let query = bincode::serialize(&Query::Stats).unwrap();
+    let req = Request::EngineQuery(opts.eid, query);
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    let answer = match res.0 {
+        Ok(ResponseKind::EngineQuery(answer)) => answer,
+        Ok(_) => panic!("invalid response"),
+        Err(e) => {
+            eprintln!("Query failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let QueryResponse::Stats(stats) = bincode::deserialize(&answer).unwrap();
+
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row![bFc =>
+        "Calls", "Retries", "Recovered", "Budget exhausted", "Attempts exhausted",
+        "Hops exhausted", "Budget"
+    ]);
+    table.add_row(row![
+        stats.calls,
+        stats.retries,
+        stats.recovered,
+        stats.budget_exhausted,
+        stats.attempts_exhausted,
+        stats.hops_exhausted,
+        format!("{:.1}", stats.budget)
+    ]);
+    table.printstd();
+}
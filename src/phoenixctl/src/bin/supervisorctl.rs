@@ -0,0 +1,115 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[macro_use]
+extern crate prettytable;
+use clap::Parser;
+use prettytable::Table;
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind, SupervisorAction};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix engine failure viewer")]
+struct Opts {
+    /// Tear down the service subscription of this process ID instead, e.g., once its failed
+    /// engines kept for inspection have been inspected
+    #[arg(short, long, requires = "sid")]
+    pid: Option<i32>,
+    /// Service subscription ID to tear down
+    #[arg(short, long, requires = "pid")]
+    sid: Option<u64>,
+    /// Dump the failures in JSON
+    #[arg(short, long)]
+    json: bool,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = match (opts.pid, opts.sid) {
+        (Some(pid), Some(sid)) => Request::TearDownSubscription(pid, sid),
+        _ => Request::ListEngineFailures,
+    };
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    if let Request::TearDownSubscription(..) = req {
+        return;
+    }
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    let kind = res.0.unwrap();
+    match kind {
+        ResponseKind::EngineFailures(failures) => {
+            if opts.json {
+                println!("{}", serde_json::to_string_pretty(&failures).unwrap());
+                return;
+            }
+            let mut table = Table::new();
+            table.add_row(
+                row![bFc => "Time (ms)", "PID", "SID", "EngineId", "EngineType", "Restarts", "Action", "Error"],
+            );
+            for failure in failures {
+                let action = match failure.action {
+                    SupervisorAction::Restart(backoff_ms) => {
+                        format!("restart in {} ms", backoff_ms)
+                    }
+                    SupervisorAction::Teardown => "teardown".to_string(),
+                    SupervisorAction::KeepFailed => "keep failed".to_string(),
+                };
+                table.add_row(row![
+                    failure.timestamp_ms,
+                    failure.pid,
+                    failure.sid,
+                    failure.eid,
+                    failure.engine_type,
+                    failure.restarts,
+                    action,
+                    failure.error
+                ]);
+            }
+            table.printstd();
+        }
+        _ => panic!("invalid response"),
+    }
+}
@@ -5,8 +5,8 @@ use clap::Parser;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use ipc::control::Request;
 use ipc::control::{PluginDescriptor, PluginType, UpgradeRequest};
+use ipc::control::{Request, Response, ResponseKind};
 use ipc::unix::DomainSocket;
 
 const MAX_MSG_LEN: usize = 65536;
@@ -35,6 +35,13 @@ struct Opts {
     /// Phoenix config path
     #[arg(short, long)]
     config: PathBuf,
+    /// Only check that the plugins can be loaded and print what the upgrade would do
+    #[arg(long)]
+    dry_run: bool,
+    /// Only upgrade the engines of the subscriptions whose labels match this selector, e.g.,
+    /// `app=search`
+    #[arg(long)]
+    selector: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,7 +64,7 @@ impl Config {
 
 fn main() {
     let opts = Opts::parse();
-    let config = Config::from_path(opts.config);
+    let config = Config::from_path(&opts.config);
 
     let uuid = Uuid::new_v4();
     let arg0 = env::args().next().unwrap();
@@ -80,6 +87,26 @@ fn main() {
 
         let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
         sock.send_to(&buf, &service_path).unwrap();
+
+        if opts.dry_run {
+            let mut buf = vec![0u8; MAX_MSG_LEN];
+            let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+            assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+            let res: Response = bincode::deserialize(&buf).unwrap();
+            match res.0 {
+                Ok(ResponseKind::Plan(plan)) => {
+                    for (i, step) in plan.iter().enumerate() {
+                        println!("{}. {}", i + 1, step);
+                    }
+                }
+                Ok(_) => panic!("invalid response"),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
     };
 
     // handle modules
@@ -89,6 +116,8 @@ fn main() {
             ty: PluginType::Module,
             flush,
             detach_subscription,
+            dry_run: opts.dry_run,
+            selector: opts.selector.clone(),
         };
 
         send_req(upgrade_request);
@@ -101,6 +130,8 @@ fn main() {
             ty: PluginType::Addon,
             flush,
             detach_subscription,
+            dry_run: opts.dry_run,
+            selector: opts.selector.clone(),
         };
 
         send_req(upgrade_request);
@@ -0,0 +1,120 @@
+//! The audit log of the control plane.
+//!
+//! Each request received on the control socket is given an ID, and once dispatched, is appended
+//! to the audit file with the credentials of its sender and its outcome, one JSON object per
+//! line. The most recent entries are also kept in memory for `ListAuditLog`. The file is only
+//! ever appended to, and the IDs continue from its last entry when the daemon restarts.
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::net::UCred;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ipc::control::AuditEntry;
+
+use crate::config::AuditConfig;
+use crate::log;
+
+/// The requests and the errors are truncated to this many characters, e.g., the payload of an
+/// engine request, so that a list of entries fits in a message.
+const MAX_FIELD_LEN: usize = 200;
+
+/// The most entries listed at once.
+const MAX_LISTED: usize = 100;
+
+pub(crate) struct AuditLog {
+    /// `None` if the audit file is disabled or cannot be opened.
+    file: Option<File>,
+    /// The most recent entries, oldest first.
+    recent: VecDeque<AuditEntry>,
+    retained: usize,
+    next_id: u64,
+}
+
+impl AuditLog {
+    /// Opens the audit file, relative to `prefix` unless absolute.
+    pub(crate) fn new(config: &AuditConfig, prefix: &Path) -> Self {
+        let mut audit = AuditLog {
+            file: None,
+            recent: VecDeque::with_capacity(config.retained),
+            retained: config.retained,
+            next_id: 0,
+        };
+        if !config.enabled {
+            return audit;
+        }
+
+        let path = prefix.join(&config.path);
+        // resume from the entries of the previous daemons
+        if let Ok(content) = fs::read_to_string(&path) {
+            for line in content.lines() {
+                if let Ok(entry) = serde_json::from_str::<AuditEntry>(line) {
+                    audit.next_id = audit.next_id.max(entry.id + 1);
+                    audit.retain(entry);
+                }
+            }
+        }
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => audit.file = Some(file),
+            Err(e) => log::warn!("Failed to open the audit file {:?}: {}", path, e),
+        }
+        audit
+    }
+
+    fn retain(&mut self, entry: AuditEntry) {
+        if self.retained == 0 {
+            return;
+        }
+        if self.recent.len() >= self.retained {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(entry);
+    }
+
+    /// Gives the next request its ID.
+    pub(crate) fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Records a request, described by `request`, and its outcome.
+    pub(crate) fn record(
+        &mut self,
+        id: u64,
+        cred: &UCred,
+        request: &str,
+        outcome: &anyhow::Result<()>,
+    ) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let entry = AuditEntry {
+            id,
+            timestamp_ms,
+            pid: cred.pid,
+            uid: cred.uid,
+            gid: cred.gid,
+            request: request.chars().take(MAX_FIELD_LEN).collect(),
+            error: outcome
+                .as_ref()
+                .err()
+                .map(|e| e.to_string().chars().take(MAX_FIELD_LEN).collect()),
+        };
+        if let Some(file) = self.file.as_mut() {
+            let mut line = serde_json::to_string(&entry).expect("AuditEntry is serializable");
+            line.push('\n');
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                log::warn!("Failed to write request {} to the audit file: {}", id, e);
+            }
+        }
+        self.retain(entry);
+    }
+
+    /// The most recent `limit` entries, at most `MAX_LISTED`, oldest first.
+    pub(crate) fn list(&self, limit: usize) -> Vec<AuditEntry> {
+        let skip = self.recent.len().saturating_sub(limit.min(MAX_LISTED));
+        self.recent.iter().skip(skip).cloned().collect()
+    }
+}
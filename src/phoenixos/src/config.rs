@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -11,6 +12,31 @@ use ipc::control::PluginDescriptor;
 pub struct Control {
     pub prefix: PathBuf,
     pub path: PathBuf,
+    /// The requests each client may send per second on the control socket once its burst is
+    /// used up, see `throttle`. 0 disables the limit.
+    #[serde(default = "Control::default_requests_per_sec")]
+    pub requests_per_sec: f64,
+    /// The requests each client may send at once.
+    #[serde(default = "Control::default_burst")]
+    pub burst: f64,
+    /// Number of threads answering the requests that wait on an engine, e.g., the engine
+    /// queries, so that a slow engine does not hold the requests of the other clients.
+    #[serde(default = "Control::default_workers")]
+    pub workers: usize,
+}
+
+impl Control {
+    fn default_requests_per_sec() -> f64 {
+        100.0
+    }
+
+    fn default_burst() -> f64 {
+        200.0
+    }
+
+    fn default_workers() -> usize {
+        4
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,6 +44,10 @@ pub struct Control {
 pub struct LinkerConfig {
     #[serde(default = "LinkerConfig::default_workdir")]
     pub workdir: PathBuf,
+    /// Debug mode for plugin upgrades. Old libraries are made inaccessible instead of
+    /// unmapped, so that anything still referencing them faults on first use.
+    #[serde(default)]
+    pub leak_detect: bool,
 }
 
 impl LinkerConfig {
@@ -43,6 +73,238 @@ pub struct ProfilingConfig {
     pub duration_ms: u64,
 }
 
+/// The idle mode of the runtimes. When no engine in the daemon has done any work for
+/// `threshold_ms`, the runtimes stop spinning and wait on the doorbells of the engines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// `None` disables the idle mode.
+    pub threshold_ms: Option<u64>,
+    /// The longest time a runtime waits before checking its engines again.
+    pub max_park_ms: u64,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            threshold_ms: None,
+            max_park_ms: 100,
+        }
+    }
+}
+
+/// The channels between the engines on the data path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DataPathConfig {
+    /// The capacity of each channel, rounded up to a power of two between runtimes. An engine
+    /// leaves its input queued while the channel it forwards to is full, `send` still queues
+    /// past the capacity.
+    pub channel_capacity: usize,
+}
+
+impl Default for DataPathConfig {
+    fn default() -> Self {
+        DataPathConfig {
+            channel_capacity: ipc::channel::DEFAULT_CAPACITY,
+        }
+    }
+}
+
+/// A data path edge whose sender calls the receiver in place of the channel, or else resumes it
+/// right after itself, see `runtime::fusion`. The engines are given by their types.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FusionConfig {
+    pub sender: String,
+    pub receiver: String,
+}
+
+/// The sandbox of the runtime threads, see `runtime::sandbox`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    /// Paths the runtimes read besides those declared by the plugins.
+    pub read_paths: Vec<PathBuf>,
+    /// Paths the runtimes write besides those declared by the plugins and the prefix.
+    pub write_paths: Vec<PathBuf>,
+}
+
+/// The cgroup (v2) of each service subscription, see `runtime::cgroup`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CgroupConfig {
+    /// Where the cgroup2 filesystem is mounted.
+    #[serde(default = "CgroupConfig::default_mount")]
+    pub mount: PathBuf,
+    /// `cpu.max` of each subscription, e.g., "50000 100000" for half a core.
+    pub cpu_max: Option<String>,
+    /// `cpu.weight` of each subscription, in [1, 10000].
+    pub cpu_weight: Option<u32>,
+}
+
+impl CgroupConfig {
+    fn default_mount() -> PathBuf {
+        "/sys/fs/cgroup".into()
+    }
+}
+
+/// Automatic scaling of the runtimes by moving scheduling groups between them, see
+/// `runtime::scaling`. The engines are not sharded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScalingConfig {
+    /// How often the utilization of the runtimes is sampled.
+    pub interval_ms: u64,
+    /// A shared runtime busier than this fraction of a core moves its busiest scheduling group
+    /// to a runtime of its own.
+    pub scale_out_utilization: f64,
+    /// A moved scheduling group less busy than this goes back to a shared runtime.
+    pub scale_in_utilization: f64,
+    /// Number of consecutive samples over or under the thresholds before acting.
+    pub sustained_intervals: u32,
+}
+
+impl Default for ScalingConfig {
+    fn default() -> Self {
+        ScalingConfig {
+            interval_ms: 1000,
+            scale_out_utilization: 0.8,
+            scale_in_utilization: 0.1,
+            sustained_intervals: 5,
+        }
+    }
+}
+
+/// The cores of the runtimes, see `runtime::isolation`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoreConfig {
+    /// The cores the runtimes run on, as a cpulist, e.g., "2-7". `None` lets them run on all
+    /// the cores, or those of the NUMA node in the scheduling hint.
+    pub runtimes: Option<String>,
+    /// Warn about the cores of the runtimes that are not isolated from the kernel scheduler.
+    pub check_isolation: bool,
+    /// Steer the interrupts of the RDMA NICs to the cores other than those of the runtimes.
+    pub steer_irqs: bool,
+}
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        CoreConfig {
+            runtimes: None,
+            check_isolation: true,
+            steer_irqs: false,
+        }
+    }
+}
+
+/// The scheduling classes of the subscriptions, see `SchedulingClass`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClassConfig {
+    /// Cores left to the shared runtimes. Latency-critical subscriptions are only admitted as
+    /// long as their dedicated runtimes leave these cores.
+    pub reserved_cores: usize,
+    /// How long the engines of a background subscription may sleep when they have no work,
+    /// unless its scheduling hint has a latency budget.
+    pub background_latency_budget_us: u32,
+}
+
+impl Default for ClassConfig {
+    fn default() -> Self {
+        ClassConfig {
+            reserved_cores: 1,
+            background_latency_budget_us: 1000,
+        }
+    }
+}
+
+/// What the supervisor of a subscription does when one of its engines fails, i.e., panics or
+/// returns an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Restart the engine after a backoff, up to `max_restarts` times, then escalate.
+    Restart,
+    /// Shut down all the engines of the subscription.
+    Teardown,
+    /// Stop resuming the engine, but keep it for inspection with engine queries.
+    KeepFailed,
+}
+
+/// What the supervisor does once the engines of a subscription have been restarted
+/// `max_restarts` times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Escalation {
+    Teardown,
+    KeepFailed,
+}
+
+/// The supervisor policy of a subscription, see `runtime::supervisor`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorPolicy {
+    pub on_failure: FailurePolicy,
+    /// Restarts allowed over the lifetime of the subscription, for all its engines.
+    pub max_restarts: u32,
+    /// The backoff before the first restart, doubled for each of the next ones.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub escalation: Escalation,
+}
+
+impl Default for SupervisorPolicy {
+    fn default() -> Self {
+        SupervisorPolicy {
+            on_failure: FailurePolicy::Restart,
+            max_restarts: 3,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 1000,
+            escalation: Escalation::Teardown,
+        }
+    }
+}
+
+/// The supervision of the engines that fail.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub default: SupervisorPolicy,
+    /// The policies of the subscriptions to some services, by service name, e.g., "Mrpc".
+    pub services: HashMap<String, SupervisorPolicy>,
+}
+
+impl SupervisorConfig {
+    /// The policy of the subscriptions to a service.
+    pub fn policy(&self, service: &str) -> &SupervisorPolicy {
+        self.services.get(service).unwrap_or(&self.default)
+    }
+}
+
+/// The audit log of the control plane, see `audit`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// The audit file, relative to the prefix unless absolute.
+    pub path: PathBuf,
+    /// Number of the most recent requests kept in memory for listing.
+    pub retained: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: true,
+            path: "audit.log".into(),
+            retained: 1024,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Group {
@@ -70,6 +332,26 @@ pub struct Config {
     pub addons: Vec<PluginDescriptor>,
     #[serde(default)]
     pub scheduling: Vec<SchedulingPolicy>,
+    #[serde(default)]
+    pub idle: IdleConfig,
+    #[serde(default)]
+    pub datapath: DataPathConfig,
+    #[serde(default)]
+    pub fusion: Vec<FusionConfig>,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// `None` disables the per-subscription cgroups.
+    pub cgroup: Option<CgroupConfig>,
+    /// `None` disables the automatic scaling.
+    pub scaling: Option<ScalingConfig>,
+    #[serde(default)]
+    pub classes: ClassConfig,
+    #[serde(default)]
+    pub cores: CoreConfig,
+    #[serde(default)]
+    pub supervisor: SupervisorConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
 }
 
 impl Config {
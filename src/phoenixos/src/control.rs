@@ -3,44 +3,85 @@
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
-use std::os::unix::net::{SocketAddr, UCred};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::{SocketAddr, UCred, UnixDatagram};
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 use anyhow::{anyhow, bail};
+use futures::executor::{ThreadPool, ThreadPoolBuilder};
 use ipc::control::ResponseKind;
-use ipc::control::{PluginType, Response};
+use ipc::control::{CanaryOutcome, Labels, PluginType, Response};
 use itertools::Itertools;
 use nix::unistd::Pid;
 
-use ipc::control::ServiceSubscriptionInfo;
+use ipc::control::{
+    ChannelDirection, DataPathGraphInfo, EngineTimeInfo, GraphChannelInfo, GraphEngineInfo,
+    MetricsInfo, ServiceSubscriptionInfo,
+};
 use ipc::unix::DomainSocket;
-use phoenix_api::engine::{SchedulingHint, SchedulingMode};
+use phoenix_api::engine::{SchedulingClass, SchedulingHint, SchedulingMode};
 
-use phoenix_common::engine::datapath::{ChannelDescriptor, DataPathNode};
+use phoenix_common::engine::datapath::{ChannelDescriptor, DataPathNode, PortEndpoint};
 use phoenix_common::engine::EngineType;
+use phoenix_common::event;
+use phoenix_common::metrics;
 use phoenix_common::module::{NewEngineRequest, Service};
 use phoenix_common::storage::{ResourceCollection, SharedStorage, PHOENIX_PREFIX_KEY};
 
+use crate::audit::AuditLog;
 use crate::config::Config;
+use crate::labels::{self, Selector};
 use crate::plugin::{Plugin, PluginName};
 use crate::plugin_mgr::PluginManager;
+use crate::policy::Policy;
 use crate::runtime::graph::create_datapath_channels;
+use crate::runtime::group::GroupId;
 use crate::runtime::manager::{EngineId, ServiceSubscription, SubscriptionId};
+use crate::runtime::progress::Progress;
+use crate::runtime::sandbox::Sandbox;
+use crate::runtime::scaling::Autoscaler;
 use crate::runtime::{EngineContainer, EngineUpgrader, RuntimeManager};
+use crate::systemd::{self, Watchdog};
+use crate::throttle::Throttle;
 use crate::{log, tracing};
 
+/// How long a request targeting the subscriptions by a label selector waits for the request
+/// of the previous one to complete, when both belong to the same process.
+const SELECTED_WAIT: Duration = Duration::from_secs(10);
+
 pub struct Control {
     sock: DomainSocket,
     runtime_manager: Arc<RuntimeManager>,
     plugins: Arc<PluginManager>,
     upgrader: EngineUpgrader,
+    /// `None` if the automatic scaling is disabled.
+    autoscaler: Option<Autoscaler>,
+    /// `None` if not supervised by the systemd watchdog.
+    watchdog: Option<Watchdog>,
+    /// Whether the control socket has been handed over to a new daemon.
+    handed_off: bool,
     scheduling_override: HashMap<String, SchedulingMode>,
+    audit: AuditLog,
+    throttle: Throttle,
+    /// Answer the requests that wait on an engine.
+    workers: ThreadPool,
+    /// The control socket, for the workers to answer on.
+    replier: Arc<DomainSocket>,
+    /// The requests answered by the workers, to record in the audit log.
+    completed_tx: Sender<Completion>,
+    completed_rx: Receiver<Completion>,
     config: Config,
 }
 
+/// A request answered by a worker: its ID, the credentials of its sender, the request, and its
+/// outcome.
+type Completion = (u64, UCred, String, anyhow::Result<()>);
+
 impl Control {
     fn choose_transport(
         &mut self,
@@ -76,6 +117,7 @@ impl Control {
 }
 
 impl Control {
+    #[allow(clippy::too_many_arguments)]
     fn create_service(
         &mut self,
         service: Service,
@@ -84,11 +126,13 @@ impl Control {
         scheduling_hint: SchedulingHint,
         cred: &UCred,
         config_string: Option<String>,
+        labels: Labels,
     ) -> anyhow::Result<()> {
         let pid = Pid::from_raw(cred.pid.unwrap());
         if self.upgrader.is_upgrading(pid) {
             bail!("client {} still upgrading", pid);
         }
+        labels::check_labels(&labels)?;
 
         // NOTE(cjr): Specially handle here. A complete solution needs a large refactoring.
         // TODO(cjr): Need a complete refactoring.
@@ -113,6 +157,24 @@ impl Control {
             graph,
         };
 
+        // the modules whose canaries the engines run on
+        let modules = service_registry
+            .engines
+            .iter()
+            .filter_map(
+                |engine| match &self.plugins.engine_registry.get(engine)?.0 {
+                    PluginName::Module(name) => Some(name.clone()),
+                    PluginName::Addon(_) => None,
+                },
+            )
+            .collect::<Vec<_>>();
+        let canaried = self.plugins.assign_canaries(
+            &modules.iter().map(String::as_str).collect(),
+            pid,
+            &labels,
+            &self.runtime_manager,
+        );
+
         let mut shared = SharedStorage::new();
         let mut global = self
             .runtime_manager
@@ -139,7 +201,6 @@ impl Control {
                 }
             };
 
-            let mut module = self.plugins.modules.get_mut(module_name).unwrap();
             tracing::info!(
                 "Created engine {:?} of service {:?} for client pid={:?}",
                 aux_engine_type,
@@ -156,18 +217,26 @@ impl Control {
                 config_string: config_string.clone(),
             };
 
-            let engine = module.create_engine(
-                *aux_engine_type,
-                request,
-                &mut shared,
-                global.value_mut(),
-                node,
-                &self.plugins.modules,
+            let (engine, version, linked) = self.plugins.with_module(
+                module_name,
+                canaried.contains(module_name),
+                |module, linked| {
+                    module
+                        .create_engine(
+                            *aux_engine_type,
+                            request,
+                            &mut shared,
+                            global.value_mut(),
+                            node,
+                            &self.plugins.modules,
+                        )
+                        .map(|engine| (engine, module.version(), linked))
+                },
             )?;
 
             // submit auxiliary to runtime manager
             if let Some(engine) = engine {
-                let container = EngineContainer::new(engine, *aux_engine_type, module.version());
+                let container = EngineContainer::new(engine, *aux_engine_type, version, linked);
                 let representative = service_registry
                     .scheduling_groups
                     .find_representative(*aux_engine_type)
@@ -195,7 +264,6 @@ impl Control {
                 panic!("service engine {:?} is an addon", service_engine_type)
             }
         };
-        let mut module = self.plugins.modules.get_mut(module_name).unwrap();
         let specified_mode = specified_mode.unwrap_or(service_mode);
         let request = NewEngineRequest::Service {
             sock: &self.sock,
@@ -209,16 +277,25 @@ impl Control {
             .remove(service_engine_type)
             .unwrap_or_else(DataPathNode::new);
 
-        let engine = match module.create_engine(
-            *service_engine_type,
-            request,
-            &mut shared,
-            global.value_mut(),
-            node,
-            &self.plugins.modules,
-        ) {
-            Ok(Some(ret)) => ret,
-            Ok(None) => bail!(
+        let created = self.plugins.with_module(
+            module_name,
+            canaried.contains(module_name),
+            |module, linked| {
+                module
+                    .create_engine(
+                        *service_engine_type,
+                        request,
+                        &mut shared,
+                        global.value_mut(),
+                        node,
+                        &self.plugins.modules,
+                    )
+                    .map(|engine| (engine, module.version(), linked))
+            },
+        );
+        let (engine, version, linked) = match created {
+            Ok((Some(ret), version, linked)) => (ret, version, linked),
+            Ok((None, ..)) => bail!(
                 "service engine must always be created, engine_type={:?}",
                 service_engine_type
             ),
@@ -239,7 +316,7 @@ impl Control {
             pid
         );
         // Submit service engine to runtime manager
-        let container = EngineContainer::new(engine, *service_engine_type, module.version());
+        let container = EngineContainer::new(engine, *service_engine_type, version, linked);
         let representative = service_registry
             .scheduling_groups
             .find_representative(*service_engine_type)
@@ -265,6 +342,17 @@ impl Control {
             groups_to_submit.push((raw_containers, mode));
         }
         let sid = self.runtime_manager.new_subscription(pid, subscription);
+        if !canaried.is_empty() {
+            tracing::info!(
+                "Subscription pid={:?}, sid={:?} runs on the canaries of {:?}",
+                pid,
+                sid,
+                canaried
+            );
+        }
+        if !labels.is_empty() {
+            self.runtime_manager.labels.insert((pid, sid), labels);
+        }
         let engines_count = groups_to_submit.iter().map(|(c, _)| c.len()).sum();
         self.runtime_manager
             .service_subscriptions
@@ -272,15 +360,101 @@ impl Control {
             .unwrap()
             .1 = engines_count;
 
+        // the hints keep the mode of the subscription, which a scheduling class change replaces
+        let scheduling_hint = SchedulingHint {
+            mode: service_mode,
+            ..scheduling_hint
+        };
         for (containers, mode) in groups_to_submit {
             self.runtime_manager
-                .submit_group(pid, sid, containers, mode, scheduling_hint);
+                .submit_group(pid, sid, containers, mode, scheduling_hint)?;
         }
         Ok(())
     }
 
-    /// Create a `Control` instance.
-    pub fn new(runtime_manager: Arc<RuntimeManager>, config: Config) -> Self {
+    /// The number of scheduling groups of a service whose engines follow the scheduling mode of
+    /// the subscription, rather than a mode specified by their plugin.
+    fn num_subscription_groups(&self, service: &Service) -> usize {
+        let service_registry = match self.plugins.service_registry.get(service) {
+            Some(registry) => registry,
+            None => return 0,
+        };
+        let mut representatives = HashSet::new();
+        let mut singletons = 0;
+        for engine_type in service_registry.engines.iter() {
+            let specified_mode = self
+                .plugins
+                .engine_registry
+                .get(engine_type)
+                .and_then(|plugin| plugin.value().1);
+            if specified_mode.is_some() {
+                continue;
+            }
+            match service_registry
+                .scheduling_groups
+                .find_representative(*engine_type)
+            {
+                Some(representative) => {
+                    representatives.insert(representative);
+                }
+                None => singletons += 1,
+            }
+        }
+        representatives.len() + singletons
+    }
+
+    /// Changes the scheduling class of a service subscription and moves its scheduling groups
+    /// that follow the scheduling mode of the subscription to runtimes of the new class.
+    fn set_scheduling_class(
+        &mut self,
+        pid: Pid,
+        sid: SubscriptionId,
+        class: SchedulingClass,
+    ) -> anyhow::Result<()> {
+        let rm = &self.runtime_manager;
+        let current: HashMap<GroupId, SchedulingMode> = rm
+            .engine_subscriptions
+            .iter()
+            .filter(|info| info.pid == pid && info.sid == sid)
+            .map(|info| (info.gid, info.scheduling_mode))
+            .collect();
+        if current.is_empty() {
+            bail!("subscription pid={}, sid={} not found", pid, sid.0);
+        }
+
+        let mode = class.mode();
+        let mut gids = Vec::new();
+        let mut num_new_dedicated = 0;
+        let mut numa_node = None;
+        for (gid, group_mode) in current {
+            let Some(hint) = rm.group_hints.get(&(pid, sid, gid)) else {
+                continue;
+            };
+            if group_mode != hint.mode {
+                continue;
+            }
+            if group_mode != SchedulingMode::Dedicate {
+                num_new_dedicated += 1;
+            }
+            numa_node = hint.numa_node_affinity;
+            gids.push(gid);
+        }
+        if class == SchedulingClass::LatencyCritical {
+            rm.admit_latency_critical(numa_node, num_new_dedicated)?;
+        }
+
+        for gid in gids.iter() {
+            if let Some(mut hint) = rm.group_hints.get_mut(&(pid, sid, *gid)) {
+                hint.mode = mode;
+                hint.class = Some(class);
+            }
+        }
+        self.upgrader.migrate_groups(pid, sid, gids, mode)
+    }
+
+    /// Create a `Control` instance. With `takeover`, the control socket is taken over from the
+    /// daemon currently running.
+    pub fn new(runtime_manager: Arc<RuntimeManager>, config: Config, takeover: bool) -> Self {
         let config_clone = config.clone();
 
         // Create phoenix working directory if not existing
@@ -289,20 +463,6 @@ impl Control {
             panic!("Failed to create directory for {:?}: {}", phoenix_prefix, e)
         });
 
-        let phoenix_path = phoenix_prefix.join(&config.control.path);
-        if phoenix_path.exists() {
-            fs::remove_file(&phoenix_path).expect("remove_file");
-        }
-
-        // Create the control plane domain socket
-        let sock = DomainSocket::bind(&phoenix_path)
-            .unwrap_or_else(|e| panic!("Cannot bind domain socket at {:?}: {}", phoenix_path, e));
-
-        sock.set_read_timeout(Some(Duration::from_millis(1)))
-            .expect("set_read_timeout");
-        sock.set_write_timeout(Some(Duration::from_millis(1)))
-            .expect("set_write_timeout");
-
         // load all preset static modules and addons
         let plugins = Arc::new(
             PluginManager::new(phoenix_prefix, &config.linker)
@@ -318,7 +478,49 @@ impl Control {
                 .expect("failed to load preset addons");
         }
 
+        // the runtimes only get the capabilities of the preset plugins
+        if config.sandbox.enabled {
+            let caps = plugins.seal_sandbox();
+            runtime_manager.set_sandbox(Sandbox::new(&caps, &config));
+        }
+
         let upgrader = EngineUpgrader::new(Arc::clone(&runtime_manager), Arc::clone(&plugins));
+
+        // Take the control plane domain socket from the running daemon or from systemd if
+        // socket-activated, otherwise create it
+        let phoenix_path = phoenix_prefix.join(&config.control.path);
+        let sock = if takeover {
+            take_over_control_socket(phoenix_prefix, &phoenix_path).unwrap_or_else(|e| {
+                panic!(
+                    "Cannot take over the control socket at {:?}: {}",
+                    phoenix_path, e
+                )
+            })
+        } else {
+            match systemd::listen_fds().first() {
+                Some(&fd) => {
+                    log::info!("Using the control socket passed by systemd (fd={})", fd);
+                    let sock = unsafe { UnixDatagram::from_raw_fd(fd) };
+                    DomainSocket::from_bound(sock, false).unwrap_or_else(|e| {
+                        panic!("Cannot use the socket-activated fd {}: {}", fd, e)
+                    })
+                }
+                None => {
+                    if phoenix_path.exists() {
+                        fs::remove_file(&phoenix_path).expect("remove_file");
+                    }
+                    DomainSocket::bind(&phoenix_path).unwrap_or_else(|e| {
+                        panic!("Cannot bind domain socket at {:?}: {}", phoenix_path, e)
+                    })
+                }
+            }
+        };
+
+        sock.set_read_timeout(Some(Duration::from_millis(1)))
+            .expect("set_read_timeout");
+        sock.set_write_timeout(Some(Duration::from_millis(1)))
+            .expect("set_write_timeout");
+
         tracing::info!("Control plane initialized");
 
         let scheduling_override = config
@@ -327,19 +529,52 @@ impl Control {
             .map(|x| (x.service, x.mode.into()))
             .collect();
 
+        let autoscaler = config_clone.scaling.as_ref().map(Autoscaler::new);
+        let audit = AuditLog::new(&config_clone.audit, &config_clone.control.prefix);
+        let throttle = Throttle::new(&config_clone.control);
+        let workers = ThreadPoolBuilder::new()
+            .pool_size(config_clone.control.workers.max(1))
+            .name_prefix("control-worker-")
+            .create()
+            .expect("failed to create the control workers");
+        let replier = sock
+            .try_clone()
+            .and_then(|sock| DomainSocket::from_bound(sock, false))
+            .expect("failed to clone the control socket");
+        let (completed_tx, completed_rx) = mpsc::channel();
+
         Control {
             sock,
             runtime_manager: Arc::clone(&runtime_manager),
             plugins,
             upgrader,
+            autoscaler,
+            watchdog: Watchdog::from_env(),
+            handed_off: false,
             scheduling_override,
+            audit,
+            throttle,
+            workers,
+            replier: Arc::new(replier),
+            completed_tx,
+            completed_rx,
             config: config_clone,
         }
     }
 
     pub fn mainloop(&mut self, exit_flag: &AtomicBool) -> anyhow::Result<()> {
         let mut buf = vec![0u8; 65536];
+        systemd::notify_or_warn("READY=1");
         while !exit_flag.load(Ordering::Relaxed) {
+            if self.handed_off {
+                // the new daemon serves the control socket, exit after the clients are gone
+                if self.runtime_manager.engine_subscriptions.is_empty() {
+                    log::info!("All clients are gone after the handoff");
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
             match self.sock.recv_with_credential_from(buf.as_mut_slice()) {
                 Ok((size, sender, cred)) => {
                     log::debug!(
@@ -349,9 +584,7 @@ impl Control {
                         cred
                     );
                     if let Some(cred) = cred {
-                        if let Err(e) = self.dispatch(&mut buf[..size], &sender, &cred) {
-                            log::warn!("Control dispatch: {}", e);
-                        }
+                        self.dispatch_audited(&buf[..size], &sender, &cred);
                     } else {
                         log::warn!("received data without a credential, ignored");
                     }
@@ -364,41 +597,1075 @@ impl Control {
                     log::warn!("recv failed: {:?}", e)
                 }
             }
+            while let Ok((id, cred, request, outcome)) = self.completed_rx.try_recv() {
+                self.record(id, &cred, &request, outcome);
+            }
+            self.throttle.prune();
+            if let Some(autoscaler) = self.autoscaler.as_mut() {
+                autoscaler.tick(&self.runtime_manager, &mut self.upgrader);
+            }
+            if let Some(watchdog) = self.watchdog.as_mut() {
+                watchdog.tick(&self.runtime_manager);
+            }
         }
         log::info!("exiting...");
+        systemd::notify_or_warn("STOPPING=1");
+        Ok(())
+    }
+
+    /// Collects the engines and channels of a service subscription, along with the number of
+    /// messages waiting in each channel.
+    fn dump_datapath_graph(
+        &self,
+        pid: Option<Pid>,
+        sid: SubscriptionId,
+    ) -> anyhow::Result<DataPathGraphInfo> {
+        const QUEUE_DEPTH_TIMEOUT: Duration = Duration::from_millis(100);
+
+        let candidates = self
+            .runtime_manager
+            .service_subscriptions
+            .iter()
+            .map(|s| *s.key())
+            .filter(|(p, s)| *s == sid && pid.map_or(true, |pid| pid == *p))
+            .map(|(p, _)| p)
+            .collect::<Vec<_>>();
+        let pid = match candidates[..] {
+            [pid] => pid,
+            [] => bail!(
+                "service subscription (pid={:?}, sid={:?}) not found",
+                pid,
+                sid
+            ),
+            _ => bail!(
+                "sid={:?} is used by multiple clients {:?}, please specify the pid",
+                sid,
+                candidates
+            ),
+        };
+
+        // Copy everything out first, runtimes need to access the subscription when engines
+        // shutdown, do not block them while waiting for the queue depths.
+        let (service, addons, mut edges) = {
+            let subscription = self
+                .runtime_manager
+                .service_subscriptions
+                .get(&(pid, sid))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "service subscription (pid={:?}, sid={:?}) not found",
+                        pid,
+                        sid
+                    )
+                })?;
+            let (subscription, _) = subscription.value();
+            let graph = &subscription.graph;
+            let mut edges = Vec::new();
+            for (direction, inputs) in [
+                (ChannelDirection::Tx, &graph.tx_inputs),
+                (ChannelDirection::Rx, &graph.rx_inputs),
+            ] {
+                for (receiver, senders) in inputs.iter() {
+                    for (receiver_index, (sender, sender_index)) in senders.iter().enumerate() {
+                        edges.push((direction, *sender, *sender_index, *receiver, receiver_index));
+                    }
+                }
+            }
+            let service = subscription.service.0.to_string();
+            let addons = subscription
+                .addons
+                .iter()
+                .map(|x| x.0.to_string())
+                .collect();
+            (service, addons, edges)
+        };
+        edges.sort_by_key(|(direction, sender, sender_index, ..)| {
+            (*direction == ChannelDirection::Rx, sender.0, *sender_index)
+        });
+
+        let mut engines = Vec::new();
+        let mut engine_ids = HashMap::new();
+        for engine in self.runtime_manager.engine_subscriptions.iter() {
+            if engine.pid == pid && engine.sid == sid {
+                engine_ids.insert(engine.engine_type, (*engine.key(), engine.rid));
+                engines.push(GraphEngineInfo {
+                    eid: engine.key().0,
+                    engine_type: engine.engine_type.0.to_string(),
+                    rid: engine.rid.0,
+                    gid: engine.gid.0,
+                });
+            }
+        }
+        engines.sort_by_key(|x| x.eid);
+
+        let sampled = engine_ids.values().copied().collect::<Vec<_>>();
+        let depths = self
+            .runtime_manager
+            .sample_queue_depths(&sampled, QUEUE_DEPTH_TIMEOUT);
+
+        let channels = edges
+            .into_iter()
+            .map(
+                |(direction, sender, sender_index, receiver, receiver_index)| {
+                    let queue_depth = engine_ids
+                        .get(&receiver)
+                        .and_then(|(eid, _)| depths.get(eid))
+                        .and_then(|d| match direction {
+                            ChannelDirection::Tx => d.tx_inputs.get(receiver_index),
+                            ChannelDirection::Rx => d.rx_inputs.get(receiver_index),
+                        })
+                        .copied();
+                    GraphChannelInfo {
+                        direction,
+                        sender: sender.0.to_string(),
+                        sender_index,
+                        receiver: receiver.0.to_string(),
+                        receiver_index,
+                        queue_depth,
+                    }
+                },
+            )
+            .collect();
+
+        Ok(DataPathGraphInfo {
+            pid: pid.as_raw(),
+            sid: sid.0,
+            service,
+            addons,
+            engines,
+            channels,
+        })
+    }
+
+    /// Upgrades plugins, and the engines of the modules upgraded.
+    fn upgrade(
+        &mut self,
+        mut request: ipc::control::UpgradeRequest,
+        progress: Progress,
+    ) -> anyhow::Result<()> {
+        log::info!("Receive backend upgrade request: {:?}", request);
+        match request.ty {
+            PluginType::Module => {
+                let selected = self.select_upgraded(&request)?;
+                self.plugins
+                    .check_upgrade_with_canaries(&request.plugins, selected.is_some())?;
+                let engines_to_upgrade = self.plugins.load_or_upgrade_modules(&request.plugins)?;
+                self.upgrader.upgrade(
+                    engines_to_upgrade,
+                    request.flush,
+                    request.detach_subscription,
+                    progress,
+                    selected.as_ref(),
+                )?;
+                self.plugins.upgraded_with_canaries(&request.plugins);
+
+                self.config.modules.append(&mut request.plugins);
+            }
+            PluginType::Addon => {
+                for addon in &request.plugins {
+                    self.plugins.load_or_upgrade_addon(addon)?;
+                }
+            }
+        }
         Ok(())
     }
 
+    /// The subscriptions selected by the label selector of an upgrade, `None` for all of them.
+    fn select_upgraded(
+        &self,
+        request: &ipc::control::UpgradeRequest,
+    ) -> anyhow::Result<Option<HashSet<(Pid, SubscriptionId)>>> {
+        let Some(selector) = request.selector.as_deref() else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.select_subscriptions(selector)?.into_iter().collect(),
+        ))
+    }
+
+    /// The subscriptions whose labels match `selector`, ordered by pid and sid.
+    fn select_subscriptions(&self, selector: &str) -> anyhow::Result<Vec<(Pid, SubscriptionId)>> {
+        let selector: Selector = selector.parse()?;
+        let no_labels = ipc::control::Labels::new();
+        let selected = self
+            .runtime_manager
+            .service_subscriptions
+            .iter()
+            .map(|subscription| *subscription.key())
+            .filter(|key| match self.runtime_manager.labels.get(key) {
+                Some(labels) => selector.matches(&labels),
+                None => selector.matches(&no_labels),
+            })
+            .sorted_by_key(|(pid, sid)| (pid.as_raw(), sid.0))
+            .collect::<Vec<_>>();
+        if selected.is_empty() {
+            bail!(
+                "no subscription matches the selector {:?}",
+                selector.to_string()
+            );
+        }
+        Ok(selected)
+    }
+
+    /// Loads a canary version of a module, or changes the share of its canary.
+    fn start_canary(&mut self, request: ipc::control::CanaryRequest) -> anyhow::Result<()> {
+        log::info!("Receive canary request: {:?}", request);
+        let selector = request
+            .selector
+            .as_deref()
+            .map(str::parse::<Selector>)
+            .transpose()?;
+        self.plugins
+            .load_canary(&request.plugin, request.percent, selector)
+    }
+
+    fn plan_canary(&self, request: &ipc::control::CanaryRequest) -> anyhow::Result<Vec<String>> {
+        let selector = request
+            .selector
+            .as_deref()
+            .map(str::parse::<Selector>)
+            .transpose()?;
+        self.plugins
+            .plan_canary(&request.plugin, request.percent, selector.as_ref())
+    }
+
+    /// Ends the canary of a module. Promoting it upgrades the module to the canary version,
+    /// which moves the subscriptions of both versions to it. Either way, the canary is unloaded
+    /// once its engines are gone.
+    fn end_canary(
+        &mut self,
+        name: &str,
+        outcome: CanaryOutcome,
+        progress: Progress,
+    ) -> anyhow::Result<()> {
+        log::info!("Receive end of canary request: {}, {:?}", name, outcome);
+        let descriptor = self.plugins.end_canary(name)?;
+        if outcome == CanaryOutcome::Promote {
+            let request = ipc::control::UpgradeRequest {
+                plugins: vec![descriptor],
+                ty: PluginType::Module,
+                flush: false,
+                detach_subscription: false,
+                dry_run: false,
+                selector: None,
+            };
+            self.upgrade(request, progress)?;
+        }
+        // an aborted canary without processes can go right away
+        self.plugins.unload_ended_canaries(&self.runtime_manager);
+        Ok(())
+    }
+
+    /// Checks an upgrade without loading the plugins, and describes what it would do.
+    fn plan_upgrade(&self, request: &ipc::control::UpgradeRequest) -> anyhow::Result<Vec<String>> {
+        let mut plan = self.plugins.plan_plugins(&request.plugins, request.ty)?;
+        if let PluginType::Addon = request.ty {
+            return Ok(plan);
+        }
+        let selected = self.select_upgraded(request)?;
+        self.plugins
+            .check_upgrade_with_canaries(&request.plugins, selected.is_some())?;
+
+        let names = request
+            .plugins
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<HashSet<_>>();
+        let mut engines_to_upgrade = HashMap::new();
+        for engine in self.runtime_manager.engine_subscriptions.iter() {
+            if selected
+                .as_ref()
+                .map_or(false, |s| !s.contains(&(engine.pid, engine.sid)))
+            {
+                continue;
+            }
+            let upgraded = self
+                .plugins
+                .engine_registry
+                .get(&engine.engine_type)
+                .map_or(false, |e| match &e.value().0 {
+                    PluginName::Module(name) => names.contains(name.as_str()),
+                    PluginName::Addon(_) => false,
+                });
+            if upgraded {
+                engines_to_upgrade
+                    .entry((engine.pid.as_raw(), engine.sid.0))
+                    .or_insert_with(Vec::new)
+                    .push(engine.engine_type.0);
+            }
+        }
+        for ((pid, sid), engines) in engines_to_upgrade.into_iter().sorted() {
+            plan.push(format!(
+                "upgrade engines [{}] of subscription (pid={}, sid={}), suspending {}{}",
+                engines.into_iter().sorted().join(", "),
+                pid,
+                sid,
+                if request.detach_subscription {
+                    "the whole subscription"
+                } else {
+                    "only these engines"
+                },
+                if request.flush {
+                    " and flushing their queues"
+                } else {
+                    ""
+                },
+            ));
+        }
+        Ok(plan)
+    }
+
+    /// Looks up the addon engine and the channel replacements of an addon request.
+    fn resolve_addon_request(
+        &self,
+        request: &ipc::control::AddonRequest,
+    ) -> anyhow::Result<(EngineType, Vec<ChannelDescriptor>, Vec<ChannelDescriptor>)> {
+        let addon_engine = unsafe { transmute_engine_type_from_str(request.addon_engine.as_str()) };
+        let addon_engine = *self
+            .plugins
+            .engine_registry
+            .get(&addon_engine)
+            .ok_or_else(|| anyhow!("Addon engine type {:?} not found", request.addon_engine))?
+            .key();
+
+        let tx_edges_replacement =
+            self.refactor_channel_descriptors(&request.tx_channels_replacements)?;
+        let rx_edges_replacement =
+            self.refactor_channel_descriptors(&request.rx_channels_replacements)?;
+        Ok((addon_engine, tx_edges_replacement, rx_edges_replacement))
+    }
+
+    /// Looks up an engine type by its name.
+    fn resolve_engine_type(&self, engine: &str) -> anyhow::Result<EngineType> {
+        let engine_ty = unsafe { transmute_engine_type_from_str(engine) };
+        let engine_ty = *self
+            .plugins
+            .engine_registry
+            .get(&engine_ty)
+            .ok_or_else(|| anyhow!("Engine type {:?} not found", engine))?
+            .key();
+        Ok(engine_ty)
+    }
+
+    /// Looks up the engines of the scheduling group to attach an addon to.
+    fn resolve_group(&self, group: &[String]) -> anyhow::Result<HashSet<EngineType>> {
+        group
+            .iter()
+            .map(|engine| self.resolve_engine_type(engine))
+            .collect()
+    }
+
+    /// Looks up the engines of a chain request, and loads the configs of its addons.
+    #[allow(clippy::type_complexity)]
+    fn resolve_chain_request(
+        &self,
+        request: &ipc::control::ChainRequest,
+    ) -> anyhow::Result<(
+        EngineType,
+        EngineType,
+        Vec<(EngineType, bool, Option<String>)>,
+    )> {
+        let upstream = self.resolve_engine_type(&request.upstream)?;
+        let downstream = self.resolve_engine_type(&request.downstream)?;
+        if request.addons.is_empty() {
+            bail!("the chain has no addons");
+        }
+        let mut addons = Vec::with_capacity(request.addons.len());
+        for addon in request.addons.iter() {
+            let addon_engine = self.resolve_engine_type(&addon.addon_engine)?;
+            if let PluginName::Module(_) =
+                self.plugins.engine_registry.get(&addon_engine).unwrap().0
+            {
+                bail!("engine type {:?} is not an addon", addon_engine);
+            }
+            let config_string =
+                Plugin::load_config(addon.config_path.as_ref(), addon.config_string.as_ref())?;
+            addons.push((addon_engine, addon.tx_only, config_string));
+        }
+        Ok((upstream, downstream, addons))
+    }
+
+    /// Attaches a chain of addons to a service subscription.
+    fn attach_chain(
+        &mut self,
+        mode: SchedulingMode,
+        request: ipc::control::ChainRequest,
+        progress: Progress,
+    ) -> anyhow::Result<()> {
+        log::info!("Receive attach chain request from phoenixctl");
+        let (upstream, downstream, addons) = self.resolve_chain_request(&request)?;
+        let group = self.resolve_group(&request.group)?;
+
+        let pid = Pid::from_raw(request.pid);
+        let sid = SubscriptionId(request.sid);
+        self.upgrader.attach_chain(
+            pid, sid, upstream, downstream, addons, mode, group, progress,
+        )
+    }
+
+    /// Checks attaching a chain of addons without touching the subscription, and describes
+    /// what it would do.
+    fn plan_attach_chain(
+        &self,
+        mode: SchedulingMode,
+        request: &ipc::control::ChainRequest,
+    ) -> anyhow::Result<Vec<String>> {
+        let (upstream, downstream, addons) = self.resolve_chain_request(request)?;
+        let group = self.resolve_group(&request.group)?;
+        for (addon, _, config_string) in addons.iter() {
+            if let Some(config) = config_string.as_ref() {
+                if let Err(e) = config.parse::<toml::Value>() {
+                    bail!("invalid config of addon {:?}: {}", addon, e);
+                }
+            }
+        }
+
+        let pid = Pid::from_raw(request.pid);
+        let sid = SubscriptionId(request.sid);
+        let engines = self.subscription_engines(pid, sid);
+        if let Some(engine) = group.iter().find(|e| !engines.contains(*e)) {
+            bail!(
+                "engine {:?} of the scheduling group is not in subscription (pid={}, sid={})",
+                engine,
+                pid,
+                sid.0
+            );
+        }
+        let types = addons
+            .iter()
+            .map(|(addon, tx_only, _)| (*addon, *tx_only))
+            .collect::<Vec<_>>();
+        let replacements = {
+            let subscription = self
+                .runtime_manager
+                .service_subscriptions
+                .get(&(pid, sid))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "service subscription (pid={:?}, sid={:?}) not found",
+                        pid,
+                        sid
+                    )
+                })?;
+            let (subscription, _) = subscription.value();
+            subscription
+                .graph
+                .chain_replacements(upstream, downstream, &types)?
+        };
+
+        let mut plan = vec![format!(
+            "suspend the engines [{}] of subscription (pid={}, sid={})",
+            engines.iter().map(|e| e.0).sorted().join(", "),
+            pid,
+            sid.0
+        )];
+        for ((addon, _, config_string), (tx, rx)) in addons.iter().zip(replacements) {
+            plan.push(format!(
+                "create addon engine {}{}, replacing tx channels {:?} and rx channels {:?}",
+                addon.0,
+                if config_string.is_some() {
+                    " with the new config"
+                } else {
+                    ""
+                },
+                tx,
+                rx
+            ));
+        }
+        if group.is_empty() {
+            plan.push(format!(
+                "schedule the addons in a new {:?} scheduling group",
+                mode
+            ));
+        } else {
+            plan.push(format!(
+                "schedule the addons in the scheduling group of [{}]",
+                group.iter().map(|e| e.0).sorted().join(", ")
+            ));
+        }
+        plan.push("resume the engines".to_owned());
+        Ok(plan)
+    }
+
+    /// The engines to insert a policy between, unless given: the frontend engine of the
+    /// subscription, i.e., the only one sending tx messages without receiving any, and the
+    /// engine it sends them to.
+    fn policy_endpoints(
+        &self,
+        pid: Pid,
+        sid: SubscriptionId,
+        upstream: Option<String>,
+        downstream: Option<String>,
+    ) -> anyhow::Result<(String, String)> {
+        if let (Some(upstream), Some(downstream)) = (upstream.as_ref(), downstream.as_ref()) {
+            return Ok((upstream.clone(), downstream.clone()));
+        }
+        let subscription = self
+            .runtime_manager
+            .service_subscriptions
+            .get(&(pid, sid))
+            .ok_or_else(|| {
+                anyhow!(
+                    "service subscription (pid={:?}, sid={:?}) not found",
+                    pid,
+                    sid
+                )
+            })?;
+        let graph = &subscription.value().0.graph;
+        let upstream = match upstream {
+            Some(upstream) => self.resolve_engine_type(&upstream)?,
+            None => {
+                let frontends = graph
+                    .tx_outputs
+                    .iter()
+                    .filter(|(engine, outputs)| {
+                        !outputs.is_empty()
+                            && graph.tx_inputs.get(*engine).map_or(true, Vec::is_empty)
+                    })
+                    .map(|(engine, _)| *engine)
+                    .collect::<Vec<_>>();
+                match frontends[..] {
+                    [frontend] => frontend,
+                    _ => bail!(
+                        "cannot infer the upstream engine of the policy among {:?}",
+                        frontends
+                    ),
+                }
+            }
+        };
+        let downstream = match downstream {
+            Some(downstream) => downstream,
+            None => match graph.tx_outputs.get(&upstream).map(Vec::as_slice) {
+                Some([(next, _)]) => next.0.to_owned(),
+                _ => bail!(
+                    "cannot infer the downstream engine of the policy from {:?}",
+                    upstream
+                ),
+            },
+        };
+        Ok((upstream.0.to_owned(), downstream))
+    }
+
+    /// Compiles a policy into the request attaching its chain of addons.
+    fn compile_policy(
+        &self,
+        request: &ipc::control::PolicyRequest,
+    ) -> anyhow::Result<ipc::control::ChainRequest> {
+        let policy = Policy::parse(&request.policy)?;
+        let addons = policy.compile()?;
+        let (upstream, downstream) = self.policy_endpoints(
+            Pid::from_raw(request.pid),
+            SubscriptionId(request.sid),
+            policy.upstream,
+            policy.downstream,
+        )?;
+        Ok(ipc::control::ChainRequest {
+            pid: request.pid,
+            sid: request.sid,
+            upstream,
+            downstream,
+            addons,
+            group: policy.group,
+            dry_run: request.dry_run,
+        })
+    }
+
+    /// Compiles a policy and attaches its chain of addons to a service subscription.
+    fn apply_policy(
+        &mut self,
+        mode: SchedulingMode,
+        request: ipc::control::PolicyRequest,
+        progress: Progress,
+    ) -> anyhow::Result<()> {
+        log::info!("Receive apply policy request from phoenixctl");
+        let chain = self.compile_policy(&request)?;
+        self.attach_chain(mode, chain, progress)
+    }
+
+    /// Compiles a policy and checks attaching its chain of addons without touching the
+    /// subscription, and describes what it would do.
+    fn plan_apply_policy(
+        &self,
+        mode: SchedulingMode,
+        request: &ipc::control::PolicyRequest,
+    ) -> anyhow::Result<Vec<String>> {
+        let chain = self.compile_policy(request)?;
+        let mut plan = vec![format!(
+            "compile the policy into the chain [{}] between {} and {}",
+            chain
+                .addons
+                .iter()
+                .map(|a| a.addon_engine.as_str())
+                .join(", "),
+            chain.upstream,
+            chain.downstream
+        )];
+        plan.extend(self.plan_attach_chain(mode, &chain)?);
+        Ok(plan)
+    }
+
+    /// Attaches an addon to a service subscription.
+    fn attach_addon(
+        &mut self,
+        mode: SchedulingMode,
+        mut request: ipc::control::AddonRequest,
+        progress: Progress,
+    ) -> anyhow::Result<()> {
+        if let Some(selector) = request.selector.take() {
+            return self.for_each_selected(&selector, request, |this, request| {
+                this.attach_addon(mode, request, progress.clone())
+            });
+        }
+        log::info!("Receive attach addon request from phoenixctl");
+        let (addon_engine, tx_edges_replacement, rx_edges_replacement) =
+            self.resolve_addon_request(&request)?;
+        let group = self.resolve_group(&request.group)?;
+        // only the ports of the new engine can be declared, check them before touching
+        // the engines
+        check_addon_ports(
+            &request,
+            addon_engine,
+            &tx_edges_replacement,
+            &rx_edges_replacement,
+        )?;
+
+        let pid = Pid::from_raw(request.pid);
+        let gid = SubscriptionId(request.sid);
+        let config_string = Plugin::load_config(request.config_path, request.config_string)?;
+        self.upgrader.attach_addon(
+            pid,
+            gid,
+            addon_engine,
+            mode,
+            tx_edges_replacement,
+            rx_edges_replacement,
+            group,
+            request.ports,
+            config_string,
+            progress,
+        )?;
+        Ok(())
+    }
+
+    /// Checks attaching an addon without touching the subscription, and describes what it
+    /// would do.
+    fn plan_attach_addon(
+        &self,
+        mode: SchedulingMode,
+        request: &ipc::control::AddonRequest,
+    ) -> anyhow::Result<Vec<String>> {
+        if let Some(selector) = request.selector.as_deref() {
+            return self.plan_each_selected(selector, request, |this, request| {
+                this.plan_attach_addon(mode, request)
+            });
+        }
+        let (addon_engine, tx_edges_replacement, rx_edges_replacement) =
+            self.resolve_addon_request(request)?;
+        let group = self.resolve_group(&request.group)?;
+        check_addon_ports(
+            request,
+            addon_engine,
+            &tx_edges_replacement,
+            &rx_edges_replacement,
+        )?;
+        if let Some(entry) = self.plugins.engine_registry.get(&addon_engine) {
+            if let PluginName::Module(_) = entry.value().0 {
+                bail!("engine type {:?} is not an addon", addon_engine);
+            }
+        }
+        let config_string =
+            Plugin::load_config(request.config_path.as_ref(), request.config_string.as_ref())?;
+        if let Some(config) = config_string.as_ref() {
+            if let Err(e) = config.parse::<toml::Value>() {
+                bail!("invalid config of addon {:?}: {}", request.addon_engine, e);
+            }
+        }
+
+        let pid = Pid::from_raw(request.pid);
+        let sid = SubscriptionId(request.sid);
+        let engines = self.subscription_engines(pid, sid);
+        if let Some(engine) = group.iter().find(|e| !engines.contains(*e)) {
+            bail!(
+                "engine {:?} of the scheduling group is not in subscription (pid={}, sid={})",
+                engine,
+                pid,
+                sid.0
+            );
+        }
+        {
+            let subscription = self
+                .runtime_manager
+                .service_subscriptions
+                .get(&(pid, sid))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "service subscription (pid={:?}, sid={:?}) not found",
+                        pid,
+                        sid
+                    )
+                })?;
+            let (subscription, _) = subscription.value();
+            if subscription.addons.contains(&addon_engine) {
+                bail!("addon {:?} is already attached", addon_engine);
+            }
+            subscription.graph.check_attach_addon(
+                addon_engine,
+                &tx_edges_replacement,
+                &rx_edges_replacement,
+            )?;
+        }
+
+        let mut plan = vec![format!(
+            "suspend the engines [{}] of subscription (pid={}, sid={})",
+            engines.iter().map(|e| e.0).sorted().join(", "),
+            pid,
+            sid.0
+        )];
+        plan.extend(
+            tx_edges_replacement
+                .iter()
+                .map(|edge| format!("replace tx channel {:?}", edge)),
+        );
+        plan.extend(
+            rx_edges_replacement
+                .iter()
+                .map(|edge| format!("replace rx channel {:?}", edge)),
+        );
+        plan.extend(
+            request
+                .ports
+                .iter()
+                .map(|port| format!("declare port {:?} on {:?}", port.name, port.endpoint)),
+        );
+        plan.push(format!(
+            "create addon engine {}{}",
+            addon_engine.0,
+            if config_string.is_some() {
+                " with the new config"
+            } else {
+                ""
+            }
+        ));
+        if group.is_empty() {
+            plan.push(format!("schedule it in a new {:?} scheduling group", mode));
+        } else {
+            plan.push(format!(
+                "schedule it in the scheduling group of [{}]",
+                group.iter().map(|e| e.0).sorted().join(", ")
+            ));
+        }
+        plan.push("resume the engines".to_owned());
+        Ok(plan)
+    }
+
+    /// Detaches an addon from a service subscription.
+    fn detach_addon(
+        &mut self,
+        mut request: ipc::control::AddonRequest,
+        progress: Progress,
+    ) -> anyhow::Result<()> {
+        if let Some(selector) = request.selector.take() {
+            return self.for_each_selected(&selector, request, |this, request| {
+                this.detach_addon(request, progress.clone())
+            });
+        }
+        log::info!("Receive detach addon request from phoenixctl");
+        let (addon_engine, tx_edges_replacement, rx_edges_replacement) =
+            self.resolve_addon_request(&request)?;
+
+        let pid = Pid::from_raw(request.pid);
+        let gid = SubscriptionId(request.sid);
+        self.upgrader.detach_addon(
+            pid,
+            gid,
+            addon_engine,
+            tx_edges_replacement,
+            rx_edges_replacement,
+            progress,
+        )?;
+        Ok(())
+    }
+
+    /// Checks detaching an addon without touching the subscription, and describes what it
+    /// would do.
+    fn plan_detach_addon(
+        &self,
+        request: &ipc::control::AddonRequest,
+    ) -> anyhow::Result<Vec<String>> {
+        if let Some(selector) = request.selector.as_deref() {
+            return self.plan_each_selected(selector, request, |this, request| {
+                this.plan_detach_addon(request)
+            });
+        }
+        let (addon_engine, tx_edges_replacement, rx_edges_replacement) =
+            self.resolve_addon_request(request)?;
+
+        let pid = Pid::from_raw(request.pid);
+        let sid = SubscriptionId(request.sid);
+        let engines = self.subscription_engines(pid, sid);
+        {
+            let subscription = self
+                .runtime_manager
+                .service_subscriptions
+                .get(&(pid, sid))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "service subscription (pid={:?}, sid={:?}) not found",
+                        pid,
+                        sid
+                    )
+                })?;
+            let (subscription, _) = subscription.value();
+            if !subscription.addons.contains(&addon_engine) {
+                bail!("addon {:?} is not attached", addon_engine);
+            }
+            subscription.graph.check_detach_addon(
+                addon_engine,
+                &tx_edges_replacement,
+                &rx_edges_replacement,
+            )?;
+        }
+
+        let mut plan = vec![format!(
+            "suspend the engines [{}] of subscription (pid={}, sid={})",
+            engines.iter().map(|e| e.0).sorted().join(", "),
+            pid,
+            sid.0
+        )];
+        plan.extend(
+            tx_edges_replacement
+                .iter()
+                .map(|edge| format!("replace tx channel {:?}", edge)),
+        );
+        plan.extend(
+            rx_edges_replacement
+                .iter()
+                .map(|edge| format!("replace rx channel {:?}", edge)),
+        );
+        plan.push(format!("remove addon engine {}", addon_engine.0));
+        plan.push("resume the engines".to_owned());
+        Ok(plan)
+    }
+
+    /// The types of the engines of a service subscription.
+    /// Applies an addon request to each subscription selected by its label selector, one after
+    /// another. The subscriptions applied to before an error are left as they are.
+    fn for_each_selected(
+        &mut self,
+        selector: &str,
+        request: ipc::control::AddonRequest,
+        mut apply: impl FnMut(&mut Self, ipc::control::AddonRequest) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        for (pid, sid) in self.select_subscriptions(selector)? {
+            // the requests of a process are taken one at a time
+            self.upgrader.wait_idle(pid, SELECTED_WAIT)?;
+            let request = ipc::control::AddonRequest {
+                pid: pid.as_raw(),
+                sid: sid.0,
+                ..request.clone()
+            };
+            apply(self, request)
+                .map_err(|e| anyhow!("subscription (pid={}, sid={}): {}", pid, sid.0, e))?;
+        }
+        Ok(())
+    }
+
+    /// Plans an addon request for each subscription selected by its label selector.
+    fn plan_each_selected(
+        &self,
+        selector: &str,
+        request: &ipc::control::AddonRequest,
+        plan: impl Fn(&Self, &ipc::control::AddonRequest) -> anyhow::Result<Vec<String>>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut steps = Vec::new();
+        for (pid, sid) in self.select_subscriptions(selector)? {
+            let request = ipc::control::AddonRequest {
+                pid: pid.as_raw(),
+                sid: sid.0,
+                selector: None,
+                ..request.clone()
+            };
+            let subscription_steps = plan(self, &request)
+                .map_err(|e| anyhow!("subscription (pid={}, sid={}): {}", pid, sid.0, e))?;
+            steps.extend(
+                subscription_steps
+                    .into_iter()
+                    .map(|step| format!("subscription (pid={}, sid={}): {}", pid, sid.0, step)),
+            );
+        }
+        Ok(steps)
+    }
+
+    fn subscription_engines(&self, pid: Pid, sid: SubscriptionId) -> HashSet<EngineType> {
+        self.runtime_manager
+            .engine_subscriptions
+            .iter()
+            .filter(|e| e.pid == pid && e.sid == sid)
+            .map(|e| e.engine_type)
+            .collect()
+    }
+
+    /// Answers the sender of a request.
+    fn reply(&self, sender: &SocketAddr, response: Response) -> anyhow::Result<()> {
+        let client_path = sender
+            .as_pathname()
+            .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+        send_response(&self.sock, client_path, &response)
+    }
+
+    /// Answers a dry run with its plan, or with why it would fail.
+    fn reply_plan(
+        &self,
+        sender: &SocketAddr,
+        plan: anyhow::Result<Vec<String>>,
+    ) -> anyhow::Result<()> {
+        let result = match plan.as_ref() {
+            Ok(plan) => Ok(ResponseKind::Plan(plan.clone())),
+            Err(e) => Err(phoenix_api::Error::from_error(
+                phoenix_api::ErrorCode::InvalidArgument,
+                &**e,
+            )),
+        };
+        self.reply(sender, Response(result))?;
+        plan.map(|_| ())
+    }
+
+    /// Gives a request its ID, dispatches it, and records it with its outcome in the audit log.
+    /// The requests that wait on an engine are answered by the workers, and recorded once they
+    /// complete.
+    fn dispatch_audited(&mut self, buf: &[u8], sender: &SocketAddr, cred: &UCred) {
+        use ipc::control;
+        let id = self.audit.next_id();
+        let (request, outcome) = match bincode::deserialize::<control::Request>(buf) {
+            Ok(msg) => {
+                let request = format!("{:?}", msg);
+                log::debug!("Control request {}: {}", id, request);
+                if !self.throttle.admit(cred) {
+                    let outcome = self.refuse_throttled(sender, cred);
+                    (request, outcome)
+                } else if let control::Request::EngineQuery(..)
+                | control::Request::UpdateEngineConfig(..) = msg
+                {
+                    let Some(client_path) = sender.as_pathname() else {
+                        let outcome = Err(anyhow!("peer is unnamed, something is wrong"));
+                        self.record(id, cred, &request, outcome);
+                        return;
+                    };
+                    let rm = Arc::clone(&self.runtime_manager);
+                    let replier = Arc::clone(&self.replier);
+                    let completed = self.completed_tx.clone();
+                    let client_path = client_path.to_path_buf();
+                    let cred = *cred;
+                    self.workers.spawn_ok(async move {
+                        let outcome = answer_engine(&rm, &replier, msg, &client_path, cred);
+                        // the control plane is gone if the receiver is
+                        let _ = completed.send((id, cred, request, outcome));
+                    });
+                    return;
+                } else {
+                    (request, self.dispatch(msg, sender, cred))
+                }
+            }
+            Err(e) => (
+                "<malformed>".to_owned(),
+                Err(anyhow!("malformed request: {}", e)),
+            ),
+        };
+        self.record(id, cred, &request, outcome);
+    }
+
+    fn record(&mut self, id: u64, cred: &UCred, request: &str, outcome: anyhow::Result<()>) {
+        if let Err(e) = outcome.as_ref() {
+            log::warn!("Control dispatch: request {}: {}", id, e);
+        }
+        self.audit.record(id, cred, request, &outcome);
+    }
+
+    /// Answers a request refused by the throttle, if its sender is waiting for an answer.
+    fn refuse_throttled(&self, sender: &SocketAddr, cred: &UCred) -> anyhow::Result<()> {
+        let message = format!("too many requests from pid {:?}, retry later", cred.pid);
+        if let Some(client_path) = sender.as_pathname() {
+            let error =
+                phoenix_api::Error::new(phoenix_api::ErrorCode::ResourceExhausted, &message);
+            let buf = bincode::serialize(&Response(Err(error)))?;
+            // a client that is not waiting for an answer may not be there to take it
+            if let Err(e) = self.sock.send_to(&buf, client_path) {
+                log::debug!(
+                    "Fail to answer the throttled client {:?}: {}",
+                    client_path,
+                    e
+                );
+            }
+        }
+        Err(anyhow!(message))
+    }
+
+    /// Subscribes a client to a service.
+    fn new_client(
+        &mut self,
+        hint: SchedulingHint,
+        service_name: String,
+        config_str: Option<String>,
+        labels: Labels,
+        sender: &SocketAddr,
+        cred: &UCred,
+    ) -> anyhow::Result<()> {
+        let client_path = sender
+            .as_pathname()
+            .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+        let service = unsafe { transmute_service_from_str(service_name.as_str()) };
+        let service = *self
+            .plugins
+            .service_registry
+            .get(&service)
+            .ok_or_else(|| anyhow!("Service {:?} not found, requested by {:?}", service, sender))?
+            .key();
+        let desired_mode = hint.mode;
+        // a scheduling class takes precedence over the scheduling policies
+        let mode_override = match hint.class {
+            Some(class) => class.mode(),
+            None => self
+                .scheduling_override
+                .get(&service_name)
+                .copied()
+                .unwrap_or(desired_mode),
+        };
+        if hint.class == Some(SchedulingClass::LatencyCritical) {
+            let num_groups = self.num_subscription_groups(&service);
+            if let Err(e) = self
+                .runtime_manager
+                .admit_latency_critical(hint.numa_node_affinity, num_groups)
+            {
+                // answer the client, which is waiting for its engines
+                let buf = bincode::serialize(&Response(Err(e.clone())))?;
+                self.sock.send_to(&buf, client_path)?;
+                bail!("subscription to {:?} not admitted: {}", service, e);
+            }
+        }
+        self.create_service(
+            service,
+            client_path,
+            mode_override,
+            hint,
+            cred,
+            config_str,
+            labels,
+        )
+    }
+
     fn dispatch(
         &mut self,
-        buf: &mut [u8],
+        msg: ipc::control::Request,
         sender: &SocketAddr,
         cred: &UCred,
     ) -> anyhow::Result<()> {
         use ipc::control;
-        let msg: control::Request = bincode::deserialize(buf).unwrap();
         match msg {
             control::Request::NewClient(hint, service_name, config_str) => {
-                let client_path = sender
-                    .as_pathname()
-                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
-                let service = unsafe { transmute_service_from_str(service_name.as_str()) };
-                let service = *self
-                    .plugins
-                    .service_registry
-                    .get(&service)
-                    .ok_or_else(|| {
-                        anyhow!("Service {:?} not found, requested by {:?}", service, sender)
-                    })?
-                    .key();
-                let desired_mode = hint.mode;
-                let mode_override = self
-                    .scheduling_override
-                    .get(&service_name)
-                    .copied()
-                    .unwrap_or(desired_mode);
-                self.create_service(service, client_path, mode_override, hint, cred, config_str)?;
-                Ok(())
+                self.new_client(hint, service_name, config_str, Labels::new(), sender, cred)
+            }
+            control::Request::NewLabeledClient(hint, service_name, config_str, labels) => {
+                self.new_client(hint, service_name, config_str, labels, sender, cred)
             }
             control::Request::EngineRequest(eid, request) => {
                 log::info!("Receive engine request");
@@ -415,39 +1682,37 @@ impl Control {
                 }
                 Ok(())
             }
-            control::Request::Upgrade(mut request) => {
-                log::info!("Receive backend upgrade request: {:?}", request);
-                match request.ty {
-                    PluginType::Module => {
-                        let engines_to_upgrade =
-                            self.plugins.load_or_upgrade_modules(&request.plugins)?;
-                        self.upgrader.upgrade(
-                            engines_to_upgrade,
-                            request.flush,
-                            request.detach_subscription,
-                        )?;
-
-                        self.config.modules.append(&mut request.plugins);
-                    }
-                    PluginType::Addon => {
-                        for addon in &request.plugins {
-                            self.plugins.load_or_upgrade_addon(addon)?;
-                        }
-                    }
-                }
-                Ok(())
+            control::Request::EngineQuery(..) | control::Request::UpdateEngineConfig(..) => {
+                unreachable!("answered by the workers")
             }
+            control::Request::Upgrade(request) if request.dry_run => {
+                let plan = self.plan_upgrade(&request);
+                self.reply_plan(sender, plan)
+            }
+            control::Request::Upgrade(request) => self.upgrade(request, Progress::none()),
             control::Request::ListSubscription => {
-                let client_path = sender
-                    .as_pathname()
-                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
-
                 let mut engine_subscriptions = HashMap::new();
+                let mut engine_times = HashMap::new();
                 for engine in self.runtime_manager.engine_subscriptions.iter() {
                     let entry = engine_subscriptions
                         .entry((engine.pid, engine.sid))
                         .or_insert_with(Vec::new);
                     entry.push((engine.key().0, engine.engine_type.0.to_string()));
+
+                    let cpu_time_ns = engine.stats.cpu_time_ns();
+                    let resumes = engine.stats.resumes();
+                    let entry = engine_times
+                        .entry((engine.pid, engine.sid))
+                        .or_insert_with(Vec::new);
+                    entry.push(EngineTimeInfo {
+                        eid: engine.key().0,
+                        cpu_time_ns,
+                        resumes,
+                        avg_quantum_ns: cpu_time_ns.checked_div(resumes).unwrap_or(0),
+                        fused_resumes: engine.stats.fused_resumes(),
+                        fused_saved_ns: engine.stats.fused_saved_ns(),
+                        direct_calls: engine.stats.direct_calls(),
+                    });
                 }
                 let mut subscriptions_info =
                     Vec::with_capacity(self.runtime_manager.service_subscriptions.len());
@@ -462,6 +1727,22 @@ impl Control {
                     let engines = engine_subscriptions
                         .remove(&(subscription.key().0, subscription.key().1))
                         .unwrap_or_default();
+                    let engine_times = engine_times
+                        .remove(&(subscription.key().0, subscription.key().1))
+                        .unwrap_or_default();
+                    let cpu_share = self
+                        .runtime_manager
+                        .cpu_caps
+                        .get(subscription.key())
+                        .and_then(|cap| cap.share());
+                    let labels = self
+                        .runtime_manager
+                        .labels
+                        .get(subscription.key())
+                        .map(|labels| labels.clone())
+                        .unwrap_or_default();
+
+                    let canaries = self.plugins.canaries_of(subscription.key().0);
 
                     let info = ServiceSubscriptionInfo {
                         pid,
@@ -469,94 +1750,204 @@ impl Control {
                         engines,
                         service,
                         addons,
+                        engine_times,
+                        cpu_share,
+                        labels,
+                        canaries,
                     };
                     subscriptions_info.push(info);
                 }
                 let response = Response(Ok(ResponseKind::ListSubscription(subscriptions_info)));
-                let mut buf = bincode::serialize(&response)?;
-                let nbytes = self.sock.send_to(buf.as_mut_slice(), client_path)?;
-                assert_eq!(
-                    nbytes,
-                    buf.len(),
-                    "expect to send {} bytes, but only {} was sent",
-                    buf.len(),
-                    nbytes
-                );
+                self.reply(sender, response)?;
                 tracing::info!("List subscription request completed");
                 Ok(())
             }
-            control::Request::AttachAddon(mode, request) => {
-                log::info!("Receive attach addon request from phoenixctl");
-                let addon_engine =
-                    unsafe { transmute_engine_type_from_str(request.addon_engine.as_str()) };
-                let addon_engine = *self
-                    .plugins
-                    .engine_registry
-                    .get(&addon_engine)
-                    .ok_or_else(|| {
-                        anyhow!("Addon engine type {:?} not found", request.addon_engine)
-                    })?
-                    .key();
-
-                let tx_edges_replacement =
-                    self.refactor_channel_descriptors(request.tx_channels_replacements)?;
-                let rx_edges_replacement =
-                    self.refactor_channel_descriptors(request.rx_channels_replacements)?;
-                let mut group = HashSet::with_capacity(request.group.len());
-                for engine in request.group {
-                    let engine_ty = unsafe { transmute_engine_type_from_str(engine.as_str()) };
-                    let engine_ty = *self
-                        .plugins
-                        .engine_registry
-                        .get(&engine_ty)
-                        .ok_or_else(|| anyhow!("Engine type {:?} not found", engine))?
-                        .key();
-                    group.insert(engine_ty);
-                }
-
-                let pid = Pid::from_raw(request.pid);
-                let gid = SubscriptionId(request.sid);
-                let config_string =
-                    Plugin::load_config(request.config_path, request.config_string)?;
-                self.upgrader.attach_addon(
+            control::Request::SetCpuShare(pid, sid, share) => {
+                log::info!(
+                    "Receive CPU share request, pid={}, sid={}, share={:?}",
                     pid,
-                    gid,
-                    addon_engine,
-                    mode,
-                    tx_edges_replacement,
-                    rx_edges_replacement,
-                    group,
-                    config_string,
-                )?;
+                    sid,
+                    share
+                );
+                if share.map_or(false, |share| !share.is_finite() || share <= 0.0) {
+                    bail!("invalid CPU share: {:?}", share);
+                }
+                let key = (Pid::from_raw(pid), SubscriptionId(sid));
+                match self.runtime_manager.cpu_caps.get(&key) {
+                    Some(cap) => cap.set_share(share),
+                    None => bail!("subscription pid={}, sid={} not found", pid, sid),
+                }
                 Ok(())
             }
-            control::Request::DetachAddon(request) => {
-                log::info!("Receive detach addon request from phoenixctl");
-                let addon_engine =
-                    unsafe { transmute_engine_type_from_str(request.addon_engine.as_str()) };
-                let addon_engine = *self
-                    .plugins
-                    .engine_registry
-                    .get(&addon_engine)
-                    .ok_or_else(|| {
-                        anyhow!("Addon engine type {:?} not found", request.addon_engine)
-                    })?
-                    .key();
-
-                let tx_edges_replacement =
-                    self.refactor_channel_descriptors(request.tx_channels_replacements)?;
-                let rx_edges_replacement =
-                    self.refactor_channel_descriptors(request.rx_channels_replacements)?;
-
-                let pid = Pid::from_raw(request.pid);
-                let gid = SubscriptionId(request.sid);
-                self.upgrader.detach_addon(
+            control::Request::SetSchedulingClass(pid, sid, class) => {
+                log::info!(
+                    "Receive scheduling class request, pid={}, sid={}, class={:?}",
                     pid,
-                    gid,
-                    addon_engine,
-                    tx_edges_replacement,
-                    rx_edges_replacement,
-                )?;
+                    sid,
+                    class
+                );
+                self.set_scheduling_class(Pid::from_raw(pid), SubscriptionId(sid), class)
+            }
+            control::Request::ListEngineFailures => {
+                let failures = self.runtime_manager.failures.list();
+                self.reply(sender, Response(Ok(ResponseKind::EngineFailures(failures))))
+            }
+            control::Request::ListAuditLog(limit) => {
+                let response = Response(Ok(ResponseKind::AuditLog(self.audit.list(limit))));
+                self.reply(sender, response)
+            }
+            control::Request::ListEvents(after, limit) => {
+                let response = Response(Ok(ResponseKind::Events(event::list(after, limit))));
+                self.reply(sender, response)
+            }
+            control::Request::SetLabels(pid, sid, new_labels) => {
+                log::info!(
+                    "Receive labels request, pid={}, sid={}, labels={:?}",
+                    pid,
+                    sid,
+                    new_labels
+                );
+                labels::check_labels(&new_labels)?;
+                let key = (Pid::from_raw(pid), SubscriptionId(sid));
+                if !self
+                    .runtime_manager
+                    .service_subscriptions
+                    .contains_key(&key)
+                {
+                    bail!("subscription pid={}, sid={} not found", pid, sid);
+                }
+                if new_labels.is_empty() {
+                    self.runtime_manager.labels.remove(&key);
+                } else {
+                    self.runtime_manager.labels.insert(key, new_labels);
+                }
+                Ok(())
+            }
+            control::Request::TearDownSubscription(pid, sid) => {
+                log::info!("Receive teardown request, pid={}, sid={}", pid, sid);
+                if !self
+                    .runtime_manager
+                    .tear_down_subscription(Pid::from_raw(pid), SubscriptionId(sid))
+                {
+                    bail!("subscription pid={}, sid={} not found", pid, sid);
+                }
+                Ok(())
+            }
+            control::Request::DataPathGraph(pid, sid) => {
+                let result = self
+                    .dump_datapath_graph(pid.map(Pid::from_raw), SubscriptionId(sid))
+                    .map(ResponseKind::DataPathGraph)
+                    .map_err(|e| {
+                        phoenix_api::Error::from_error(phoenix_api::ErrorCode::Internal, &*e)
+                    });
+                self.reply(sender, Response(result))?;
+                tracing::info!("Datapath graph request completed");
+                Ok(())
+            }
+            control::Request::AttachAddon(mode, request) if request.dry_run => {
+                let plan = self.plan_attach_addon(mode, &request);
+                self.reply_plan(sender, plan)
+            }
+            control::Request::AttachAddon(mode, request) => {
+                self.attach_addon(mode, request, Progress::none())
+            }
+            control::Request::AttachChain(mode, request) if request.dry_run => {
+                let plan = self.plan_attach_chain(mode, &request);
+                self.reply_plan(sender, plan)
+            }
+            control::Request::AttachChain(mode, request) => {
+                self.attach_chain(mode, request, Progress::none())
+            }
+            control::Request::ApplyPolicy(mode, request) if request.dry_run => {
+                let plan = self.plan_apply_policy(mode, &request);
+                self.reply_plan(sender, plan)
+            }
+            control::Request::ApplyPolicy(mode, request) => {
+                self.apply_policy(mode, request, Progress::none())
+            }
+            control::Request::DetachAddon(request) if request.dry_run => {
+                let plan = self.plan_detach_addon(&request);
+                self.reply_plan(sender, plan)
+            }
+            control::Request::DetachAddon(request) => self.detach_addon(request, Progress::none()),
+            control::Request::StartCanary(request) if request.dry_run => {
+                let plan = self.plan_canary(&request);
+                self.reply_plan(sender, plan)
+            }
+            control::Request::StartCanary(request) => self.start_canary(request),
+            control::Request::EndCanary(name, outcome) => {
+                self.end_canary(&name, outcome, Progress::none())
+            }
+            control::Request::ListCanaries => {
+                let canaries = self.plugins.list_canaries(&self.runtime_manager);
+                self.reply(sender, Response(Ok(ResponseKind::Canaries(canaries))))
+            }
+            control::Request::ListPlugins => {
+                let plugins = self.plugins.list_plugins();
+                self.reply(sender, Response(Ok(ResponseKind::Plugins(plugins))))
+            }
+            control::Request::ListMetrics => {
+                let snapshot = MetricsInfo {
+                    unexpected_errors: metrics::unexpected_errors(),
+                    datapath_allocations: metrics::datapath_allocations(),
+                    expired_messages: metrics::expired_messages(),
+                    link_down_events: metrics::link_down_events(),
+                    link_up_events: metrics::link_up_events(),
+                    ports_down: metrics::ports_down(),
+                    backend_ejections: metrics::backend_ejections(),
+                    backends_ejected: metrics::backends_ejected(),
+                    leaks: metrics::leaks(),
+                };
+                self.reply(sender, Response(Ok(ResponseKind::Metrics(snapshot))))
+            }
+            control::Request::Streaming(request) => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+                let progress = Progress::stream(self.sock.try_clone()?, client_path.to_owned());
+                let result = match *request {
+                    control::Request::Upgrade(request) if !request.dry_run => {
+                        self.upgrade(request, progress.clone())
+                    }
+                    control::Request::AttachAddon(mode, request) if !request.dry_run => {
+                        self.attach_addon(mode, request, progress.clone())
+                    }
+                    control::Request::AttachChain(mode, request) if !request.dry_run => {
+                        self.attach_chain(mode, request, progress.clone())
+                    }
+                    control::Request::ApplyPolicy(mode, request) if !request.dry_run => {
+                        self.apply_policy(mode, request, progress.clone())
+                    }
+                    control::Request::DetachAddon(request) if !request.dry_run => {
+                        self.detach_addon(request, progress.clone())
+                    }
+                    control::Request::EndCanary(name, outcome) => {
+                        self.end_canary(&name, outcome, progress.clone())
+                    }
+                    request => Err(anyhow!("{:?} cannot be streamed", request)),
+                };
+                // the final result is sent once the last step is done
+                if let Err(e) = result.as_ref() {
+                    progress.fail(e);
+                }
+                result
+            }
+            control::Request::Handoff => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+                if cred.uid != nix::unistd::Uid::current().as_raw() {
+                    bail!("handoff requested by another user, uid={}", cred.uid);
+                }
+                self.sock.send_fd(client_path, &[self.sock.as_raw_fd()])?;
+                // the socket file now belongs to the new daemon
+                self.sock.release_path();
+                self.handed_off = true;
+                log::info!(
+                    "Control socket handed over to pid {:?}, draining {} engines",
+                    cred.pid,
+                    self.runtime_manager.engine_subscriptions.len(),
+                );
                 Ok(())
             }
         }
@@ -564,10 +1955,10 @@ impl Control {
 
     fn refactor_channel_descriptors(
         &self,
-        channels: Vec<(String, String, usize, usize)>,
+        channels: &[(String, String, usize, usize)],
     ) -> anyhow::Result<Vec<ChannelDescriptor>> {
         let mut edges = Vec::with_capacity(channels.len());
-        for (sender, receiver, sender_idx, recevier_idx) in channels.into_iter() {
+        for (sender, receiver, sender_idx, recevier_idx) in channels.iter() {
             let sender_engine = unsafe { transmute_engine_type_from_str(sender.as_str()) };
             let receiver_engine = unsafe { transmute_engine_type_from_str(receiver.as_str()) };
             let sender_engine = *self
@@ -585,14 +1976,142 @@ impl Control {
             edges.push(ChannelDescriptor(
                 sender_engine,
                 receiver_engine,
-                sender_idx,
-                recevier_idx,
+                *sender_idx,
+                *recevier_idx,
             ));
         }
         Ok(edges)
     }
 }
 
+/// Checks that the ports of an attach request are declared on the addon engine, and refer to
+/// its channels.
+fn check_addon_ports(
+    request: &ipc::control::AddonRequest,
+    addon_engine: EngineType,
+    tx_edges_replacement: &[ChannelDescriptor],
+    rx_edges_replacement: &[ChannelDescriptor],
+) -> anyhow::Result<()> {
+    for port in &request.ports {
+        if port.engine != request.addon_engine {
+            bail!(
+                "port {:?} is declared on {:?}, not on the addon engine",
+                port.name,
+                port.engine
+            );
+        }
+        let nchannels = match port.endpoint {
+            PortEndpoint::TxInput => tx_edges_replacement
+                .iter()
+                .filter(|e| e.1 == addon_engine)
+                .count(),
+            PortEndpoint::TxOutput => tx_edges_replacement
+                .iter()
+                .filter(|e| e.0 == addon_engine)
+                .count(),
+            PortEndpoint::RxInput => rx_edges_replacement
+                .iter()
+                .filter(|e| e.1 == addon_engine)
+                .count(),
+            PortEndpoint::RxOutput => rx_edges_replacement
+                .iter()
+                .filter(|e| e.0 == addon_engine)
+                .count(),
+        };
+        if let Some(c) = port.channels.iter().find(|&&c| c >= nchannels) {
+            bail!(
+                "port {:?} refers to channel {}, but {:?} has {} channels",
+                port.name,
+                c,
+                port.endpoint,
+                nchannels
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Asks the daemon listening on `control_path` to hand its control socket over.
+fn take_over_control_socket(prefix: &Path, control_path: &Path) -> anyhow::Result<DomainSocket> {
+    let sock_path = prefix.join(format!("phoenixd-takeover-{}.sock", std::process::id()));
+    if sock_path.exists() {
+        fs::remove_file(&sock_path)?;
+    }
+    let sock = DomainSocket::bind(&sock_path)?;
+    sock.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let buf = bincode::serialize(&ipc::control::Request::Handoff)?;
+    sock.send_to(&buf, control_path)?;
+    let (fds, cred) = sock.recv_fd()?;
+    let fd = *fds
+        .first()
+        .ok_or_else(|| anyhow!("no fd received from the running daemon"))?;
+    log::info!(
+        "Took over the control socket from pid {:?}",
+        cred.and_then(|c| c.pid)
+    );
+    let sock = unsafe { UnixDatagram::from_raw_fd(fd) };
+    Ok(DomainSocket::from_bound(sock, true)?)
+}
+
+/// Answers a request that waits on an engine, i.e., an EngineQuery or an UpdateEngineConfig.
+fn answer_engine(
+    rm: &RuntimeManager,
+    sock: &DomainSocket,
+    msg: ipc::control::Request,
+    client_path: &Path,
+    cred: UCred,
+) -> anyhow::Result<()> {
+    use ipc::control;
+    let (response, outcome) = match msg {
+        control::Request::EngineQuery(eid, query) => {
+            log::info!("Receive engine query");
+            let result = rm
+                .query_engine(EngineId(eid), query, cred, Duration::from_secs(1))
+                .map(ResponseKind::EngineQuery)
+                .map_err(|e| phoenix_api::Error::from_error(phoenix_api::ErrorCode::Internal, &*e));
+            (Response(result), Ok(()))
+        }
+        control::Request::UpdateEngineConfig(eid, config) => {
+            log::info!("Receive engine config update");
+            let result = rm.update_engine_config(EngineId(eid), config, Duration::from_secs(1));
+            let response = Response(
+                result
+                    .as_ref()
+                    .map(|previous| ResponseKind::EngineConfig(previous.clone()))
+                    .map_err(|e| {
+                        phoenix_api::Error::from_error(
+                            phoenix_api::ErrorCode::InvalidArgument,
+                            &**e,
+                        )
+                    }),
+            );
+            (response, result.map(|_| ()))
+        }
+        msg => bail!("{:?} does not wait on an engine", msg),
+    };
+    send_response(sock, client_path, &response)?;
+    outcome
+}
+
+/// Sends the response to a request to the client waiting for it at `client_path`.
+fn send_response(
+    sock: &DomainSocket,
+    client_path: &Path,
+    response: &Response,
+) -> anyhow::Result<()> {
+    let buf = bincode::serialize(response)?;
+    let nbytes = sock.send_to(&buf, client_path)?;
+    assert_eq!(
+        nbytes,
+        buf.len(),
+        "expect to send {} bytes, but only {} was sent",
+        buf.len(),
+        nbytes
+    );
+    Ok(())
+}
+
 unsafe fn transmute_engine_type_from_str(engine: &str) -> EngineType {
     let bytes = engine.as_bytes();
     let (ptr, len) = (bytes.as_ptr(), bytes.len());
@@ -0,0 +1,109 @@
+//! Label selectors, to target the service subscriptions by their labels rather than by their
+//! pid and sid.
+//!
+//! A selector is a comma-separated list of requirements, all of which a subscription must meet:
+//! `key=value`, `key!=value`, `key` for the subscriptions with the label, and `!key` for those
+//! without it, e.g., `app=search,env!=dev`. A subscription without a label does not equal any
+//! value, so it meets `key!=value`.
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use ipc::control::Labels;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::Equals(key, value) => write!(f, "{}={}", key, value),
+            Requirement::NotEquals(key, value) => write!(f, "{}!={}", key, value),
+            Requirement::Exists(key) => write!(f, "{}", key),
+            Requirement::NotExists(key) => write!(f, "!{}", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+impl Selector {
+    /// Whether a subscription with `labels` meets all the requirements.
+    pub(crate) fn matches(&self, labels: &Labels) -> bool {
+        self.requirements.iter().all(|r| r.matches(labels))
+    }
+}
+
+/// Checks a label key or value: non-empty, and without the characters of the selector syntax.
+fn check_token(token: &str) -> anyhow::Result<&str> {
+    let token = token.trim();
+    if token.is_empty()
+        || token.contains(|c: char| matches!(c, ',' | '=' | '!') || c.is_whitespace())
+    {
+        bail!("invalid label key or value {:?}", token);
+    }
+    Ok(token)
+}
+
+/// Checks the labels to attach to a subscription.
+pub(crate) fn check_labels(labels: &Labels) -> anyhow::Result<()> {
+    for (key, value) in labels {
+        check_token(key)?;
+        check_token(value)?;
+    }
+    Ok(())
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut requirements = Vec::new();
+        for requirement in s.split(',') {
+            let requirement = requirement.trim();
+            let parsed = if let Some((key, value)) = requirement.split_once("!=") {
+                Requirement::NotEquals(check_token(key)?.to_owned(), check_token(value)?.to_owned())
+            } else if let Some((key, value)) = requirement.split_once('=') {
+                Requirement::Equals(check_token(key)?.to_owned(), check_token(value)?.to_owned())
+            } else if let Some(key) = requirement.strip_prefix('!') {
+                Requirement::NotExists(check_token(key)?.to_owned())
+            } else {
+                Requirement::Exists(check_token(requirement)?.to_owned())
+            };
+            requirements.push(parsed);
+        }
+        Ok(Selector { requirements })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, requirement) in self.requirements.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", requirement)?;
+        }
+        Ok(())
+    }
+}
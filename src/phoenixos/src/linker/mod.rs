@@ -55,6 +55,8 @@ pub(crate) mod relocation;
 pub(crate) mod tls;
 use tls::PHOENIX_MOD_INIT_EXEC;
 
+pub(crate) mod unwind;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("IO: {0}")]
@@ -124,6 +126,16 @@ impl LoadedModules {
         None
     }
 
+    fn remove(&self, mod_id: usize) -> Option<LinkedModule> {
+        let mut inner = self.0.lock().unwrap();
+        let index = inner.iter().position(|x| x.mod_id() == mod_id)?;
+        Some(inner.remove(index))
+    }
+
+    fn snapshot(&self) -> Vec<LinkedModule> {
+        self.0.lock().unwrap().clone()
+    }
+
     pub(crate) fn contains(&self, path: &str) -> bool {
         let inner = self.0.lock().unwrap();
         let path = PathBuf::from(path);
@@ -147,11 +159,13 @@ pub(crate) struct Linker {
     workdir: PathBuf,
     // These crates are dependencies of phoenix itself, so no need to load them again.
     crates_to_skip: HashSet<String>,
+    /// Poison unloaded modules instead of unmapping them.
+    leak_detect: bool,
 }
 
 impl Linker {
     /// Load the binary of the phoenix itself.
-    pub(crate) fn new(workdir: PathBuf) -> Result<Self, Error> {
+    pub(crate) fn new(workdir: PathBuf, leak_detect: bool) -> Result<Self, Error> {
         // Load deps
         let mut dep_path = fs::read_link("/proc/self/exe")?;
         dep_path.set_extension("d");
@@ -204,6 +218,7 @@ impl Linker {
             global_sym_table,
             workdir,
             crates_to_skip,
+            leak_detect,
         })
     }
 
@@ -320,6 +335,55 @@ impl Linker {
         Ok(LOADED_MODULES.find_module_by_name(archive_path).unwrap())
     }
 
+    /// Returns why `linked` cannot be unloaded yet, or `None` if nothing references it any
+    /// more apart from the caller and the set of loaded modules.
+    pub(crate) fn find_reference(&self, linked: &LinkedModule) -> Option<String> {
+        // One reference is held by the caller, one by LOADED_MODULES.
+        let pinned = Arc::strong_count(linked).saturating_sub(2);
+        if pinned > 0 {
+            return Some(format!("pinned by {} engine(s) or in-flight users", pinned));
+        }
+
+        for other in LOADED_MODULES.snapshot() {
+            if Arc::ptr_eq(&other, linked) {
+                continue;
+            }
+            if let Some(name) = other.find_reference_to(linked, &self.global_sym_table) {
+                return Some(format!(
+                    "{} is linked against its symbol {}",
+                    other.path().display(),
+                    demangle(name)
+                ));
+            }
+        }
+        None
+    }
+
+    /// Unloads a module, typically the old version of an upgraded plugin.
+    ///
+    /// The caller must have checked with [`Linker::find_reference`] that no code, vtable,
+    /// or TLS variable of the module can be reached anymore. Its symbols are withdrawn from
+    /// the global symbol table, which then falls back to the definitions of the remaining
+    /// modules, and the image is unmapped (or poisoned in leak-detect mode) once `linked`
+    /// is dropped.
+    ///
+    /// Dependencies are shared between plugins and stay loaded.
+    pub(crate) fn unload(&mut self, linked: LinkedModule) {
+        linked.withdraw_global_symbols(&mut self.global_sym_table);
+        LOADED_MODULES.remove(linked.mod_id());
+        for module in LOADED_MODULES.snapshot() {
+            module.update_global_symbol_table(&mut self.global_sym_table);
+        }
+        if self.leak_detect {
+            linked.poison_on_drop();
+        }
+        log::info!(
+            "Unloading module {} (mod_id: {})",
+            linked.path().display(),
+            linked.mod_id()
+        );
+    }
+
     fn load_archive_inner(
         &mut self,
         archive_path: &Path,
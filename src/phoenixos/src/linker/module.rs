@@ -1,7 +1,8 @@
 use std::fs;
+use std::mem::ManuallyDrop;
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use object::elf::FileHeader64;
@@ -16,8 +17,9 @@ use phoenix_common::log;
 use super::initfini::InitFini;
 use super::relocation::do_relocation;
 use super::section::{CommonSection, ExtraSymbolSection, Section};
-use super::symbol::{SymbolLookupTable, SymbolTable};
+use super::symbol::{Symbol, SymbolLookupTable, SymbolTable};
 use super::tls::{TlsInitImage, PHOENIX_MOD_BASE};
+use super::unwind::{relocate_eh_frame, UnwindInfo};
 use super::Error;
 
 static MODULE_COUNTER: AtomicUsize = AtomicUsize::new(PHOENIX_MOD_BASE);
@@ -103,6 +105,11 @@ impl LoadableModule {
             );
         }
 
+        // Move the unwind tables to where they can be terminated and registered
+        for sec in sections.iter_mut().filter(|s| s.is_eh_frame()) {
+            relocate_eh_frame(sec, &image)?;
+        }
+
         // Create the TLS initialization image
         let tls_initimage = TlsInitImage::new(&mut sections)?;
 
@@ -160,15 +167,7 @@ impl LoadableModule {
 
     /// Insert symbol definition for global symbols from this module into global symbol table
     pub(crate) fn update_global_symbol_table(&self, sym_lookup_table: &mut SymbolLookupTable) {
-        for (_, sym) in self.symtab.iter() {
-            if sym.is_global
-                && (sym.is_definition
-                    || sym.is_common
-                    || (sym.kind == SymbolKind::Tls && !sym.is_undefined))
-            {
-                sym_lookup_table.insert(sym.name.clone(), sym.clone());
-            }
-        }
+        export_symbols(&self.symtab, sym_lookup_table)
     }
 
     /// Performa relocation
@@ -204,7 +203,17 @@ impl LoadableModule {
             &sym_lookup_table,
         );
 
+        // Register the unwind tables so that panics raised in this module can unwind.
+        // SAFETY: the section has been relocated and terminated, and it is owned by the module.
+        let unwind = self
+            .sections
+            .iter()
+            .find(|s| s.is_eh_frame())
+            .map(|s| unsafe { UnwindInfo::register(s.address as *const u8) });
+
         Ok(Arc::new(LinkedModuleInner {
+            unwind,
+            poison_on_drop: AtomicBool::new(false),
             mod_id: self.mod_id,
             sections: self.sections,
             init: self.init,
@@ -213,16 +222,37 @@ impl LoadableModule {
             common_section: self.common_section,
             extra_symbol_section,
             tls_initimage: self.tls_initimage,
-            _image: self.image,
+            _image: ManuallyDrop::new(self.image),
             path: self.path,
             _object: self.object,
         }))
     }
 }
 
+/// Whether a symbol is a definition that should be visible to other modules.
+#[inline]
+fn is_exported(sym: &Symbol) -> bool {
+    sym.is_global
+        && (sym.is_definition
+            || sym.is_common
+            || (sym.kind == SymbolKind::Tls && !sym.is_undefined))
+}
+
+fn export_symbols(symtab: &SymbolTable, sym_lookup_table: &mut SymbolLookupTable) {
+    for (_, sym) in symtab.iter() {
+        if is_exported(sym) {
+            sym_lookup_table.insert(sym.name.clone(), sym.clone());
+        }
+    }
+}
+
 pub(crate) type LinkedModule = Arc<LinkedModuleInner>;
 
 pub(crate) struct LinkedModuleInner {
+    /// Registered unwind tables, must be dropped before the image.
+    unwind: Option<UnwindInfo>,
+    /// Leak-detect mode, leave the image inaccessible instead of unmapping it.
+    poison_on_drop: AtomicBool,
     /// mod_id
     mod_id: usize,
     /// Sections of the module
@@ -243,8 +273,8 @@ pub(crate) struct LinkedModuleInner {
     extra_symbol_section: ExtraSymbolSection,
     /// TLS initialization image
     tls_initimage: TlsInitImage,
-    /// The memory map needs to be retained. Retained for RAII.
-    _image: Mmap,
+    /// The memory map needs to be retained. Dropped explicitly, see `Drop`.
+    _image: ManuallyDrop<Mmap>,
     /// Path to the binary
     path: PathBuf,
     /// The File must be the last to drop. Retained for RAII.
@@ -278,4 +308,99 @@ impl LinkedModuleInner {
     pub(crate) fn path(&self) -> &PathBuf {
         &self.path
     }
+
+    /// Whether `addr` points into the image of this module.
+    #[inline]
+    pub(crate) fn contains_addr(&self, addr: usize) -> bool {
+        let start = self._image.as_ptr().addr();
+        (start..start + self._image.len()).contains(&addr)
+    }
+
+    /// Insert symbol definition for global symbols from this module into global symbol table
+    pub(crate) fn update_global_symbol_table(&self, sym_lookup_table: &mut SymbolLookupTable) {
+        export_symbols(&self.symtab, sym_lookup_table)
+    }
+
+    /// Remove the global symbols that are currently resolved to this module.
+    pub(crate) fn withdraw_global_symbols(&self, sym_lookup_table: &mut SymbolLookupTable) {
+        sym_lookup_table
+            .table
+            .retain(|name, bound| !self.defines(name, bound));
+    }
+
+    /// Returns the name of a symbol through which this module was linked against `other`.
+    pub(crate) fn find_reference_to<'a>(
+        &'a self,
+        other: &LinkedModuleInner,
+        sym_lookup_table: &SymbolLookupTable,
+    ) -> Option<&'a str> {
+        // Relocations against global symbols are always resolved through the global table,
+        // even for symbols defined in this module itself, see `do_relocation`.
+        self.symtab
+            .iter()
+            .filter(|(_, sym)| sym.is_global && sym.kind != SymbolKind::Section)
+            .find(|(_, sym)| {
+                sym_lookup_table
+                    .table
+                    .get(&sym.name)
+                    .map_or(false, |bound| other.defines(&sym.name, bound))
+            })
+            .map(|(_, sym)| sym.name.as_str())
+    }
+
+    /// Whether `bound`, an entry of the global symbol table, is the definition of `name`
+    /// exported by this module.
+    fn defines(&self, name: &str, bound: &Symbol) -> bool {
+        self.symtab.symbol_by_name(name).map_or(false, |sym| {
+            is_exported(sym) && sym.address == bound.address && sym.mod_id == bound.mod_id
+        })
+    }
+
+    /// Instead of unmapping the image on drop, leave the address range reserved but
+    /// inaccessible. Any dangling reference into the module then faults right away.
+    pub(crate) fn poison_on_drop(&self) {
+        self.poison_on_drop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for LinkedModuleInner {
+    fn drop(&mut self) {
+        // The unwind tables must be gone before the memory they describe.
+        drop(self.unwind.take());
+
+        // SAFETY: `_image` is never used after this point.
+        let image = unsafe { ManuallyDrop::take(&mut self._image) };
+        if self.poison_on_drop.load(Ordering::Relaxed) {
+            let ret = unsafe {
+                libc::mprotect(
+                    image.as_ptr() as *mut libc::c_void,
+                    image.len(),
+                    libc::PROT_NONE,
+                )
+            };
+            if ret != 0 {
+                log::error!(
+                    "Failed to poison module {} (mod_id: {}): {}",
+                    self.path.display(),
+                    self.mod_id,
+                    std::io::Error::last_os_error()
+                );
+            }
+            log::warn!(
+                "Module {} (mod_id: {}) poisoned at: [0x{:0x}, 0x{:0x})",
+                self.path.display(),
+                self.mod_id,
+                image.as_ptr().addr(),
+                image.as_ptr().addr() + image.len()
+            );
+            std::mem::forget(image);
+        } else {
+            log::debug!(
+                "Module {} (mod_id: {}) unloaded",
+                self.path.display(),
+                self.mod_id
+            );
+            drop(image);
+        }
+    }
 }
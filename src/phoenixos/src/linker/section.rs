@@ -62,6 +62,10 @@ impl Section {
             return false;
         }
 
+        if self.is_eh_frame() {
+            return true;
+        }
+
         match self.kind {
             SectionKind::Text
             | SectionKind::Data
@@ -73,6 +77,12 @@ impl Section {
         }
     }
 
+    /// Whether this section holds the unwind tables of the module.
+    #[inline]
+    pub(crate) fn is_eh_frame(&self) -> bool {
+        self.name == ".eh_frame"
+    }
+
     /// Update runtime address for sections needed to load. Allocate memory for .bss sections
     /// if encountered.
     pub(crate) fn update_runtime_addr(&mut self, image_start: *const u8) -> Result<(), Error> {
@@ -0,0 +1,92 @@
+//! Unwind tables of loaded modules.
+//!
+//! The unwinder only knows about the `.eh_frame` of objects loaded by the system dynamic
+//! loader. Unless the tables of a plugin are registered with it, a panic raised in plugin
+//! code cannot unwind into the runtime and aborts the whole process instead.
+//!
+//! `__register_frame` walks the table until a zero-length entry, which a relocatable
+//! object does not have (it normally comes from `crtend.o`). So the section is moved into
+//! a dedicated mapping placed right behind the module image, which keeps its PC-relative
+//! references to `.text` in range, and left a terminator at its end.
+use mmap::{Mmap, MmapOptions};
+
+use super::section::Section;
+use super::Error;
+use crate::log;
+
+extern "C" {
+    fn __register_frame(begin: *const u8);
+    fn __deregister_frame(begin: *const u8);
+}
+
+/// Lengths in `.eh_frame` are 4 bytes, a zero one ends the table.
+const TERMINATOR_LEN: usize = 4;
+
+/// Relocates the `.eh_frame` section into its own mapping.
+///
+/// Must be called after the runtime address of `section` has been assigned and before
+/// relocations are performed.
+pub(crate) fn relocate_eh_frame(section: &mut Section, image: &Mmap) -> Result<(), Error> {
+    debug_assert!(section.is_eh_frame());
+    let page_size = page_size::get();
+    let len = (section.size as usize + TERMINATOR_LEN).next_multiple_of(page_size);
+    let target_addr = (image.as_ptr().addr() + image.len()).next_multiple_of(page_size);
+
+    // The anonymous mapping is zero-filled, the terminator comes for free.
+    let mmap = MmapOptions::new()
+        .len(len)
+        .anon(true)
+        .private(true)
+        .read(true)
+        .write(true)
+        .fixed_noreplace(target_addr)
+        .mmap()?;
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            section.address as *const u8,
+            mmap.as_mut_ptr(),
+            section.size as usize,
+        );
+    }
+
+    log::trace!(
+        "Section '{}' relocated to: [0x{:0x}, 0x{:0x} + {})",
+        section.name,
+        mmap.as_ptr().addr(),
+        mmap.as_ptr().addr(),
+        section.size
+    );
+
+    section.address = mmap.as_ptr().addr() as u64;
+    section.mmap = Some(mmap);
+    Ok(())
+}
+
+/// A registered unwind table, deregistered on drop.
+///
+/// It must be dropped before the memory of the module it describes is unmapped.
+pub(crate) struct UnwindInfo {
+    begin: usize,
+}
+
+impl UnwindInfo {
+    /// Registers the unwind table starting at `begin`.
+    ///
+    /// # Safety
+    ///
+    /// `begin` must point to a fully relocated `.eh_frame` ended by a terminator, and the
+    /// memory must outlive the returned object.
+    pub(crate) unsafe fn register(begin: *const u8) -> Self {
+        __register_frame(begin);
+        UnwindInfo {
+            begin: begin.addr(),
+        }
+    }
+}
+
+impl Drop for UnwindInfo {
+    fn drop(&mut self) {
+        unsafe { __deregister_frame(self.begin as *const u8) };
+    }
+}
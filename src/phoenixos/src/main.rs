@@ -16,13 +16,19 @@ use clap::Parser;
 pub use phoenix_common::tracing;
 pub use phoenix_common::tracing as log;
 
+pub(crate) mod audit;
 pub(crate) mod config;
 pub(crate) mod control;
+pub(crate) mod labels;
 pub(crate) mod linker;
 pub(crate) mod logging;
+pub(crate) mod manifest;
 pub(crate) mod plugin;
 pub(crate) mod plugin_mgr;
+pub(crate) mod policy;
 pub(crate) mod runtime;
+pub(crate) mod systemd;
+pub(crate) mod throttle;
 
 pub(crate) mod dependency;
 
@@ -38,6 +44,14 @@ struct Opts {
     config: PathBuf,
     #[arg(long)]
     no_ansi: bool,
+    /// Take over the control socket from the running phoenixd, which exits after its current
+    /// clients are gone
+    #[arg(long)]
+    takeover: bool,
+    /// Run the RDMA transport as on SoftRoCE (rxe) or soft-iWARP (siw), for developer machines
+    /// without an RDMA NIC, unless its config says otherwise
+    #[arg(long)]
+    soft_rdma: bool,
 }
 
 static TERMINATE: AtomicBool = AtomicBool::new(false);
@@ -50,6 +64,10 @@ extern "C" fn handle_sigint(sig: i32) {
 fn main() -> Result<()> {
     // load config
     let opts = Opts::parse();
+    if opts.soft_rdma {
+        // read by the RDMA transport, before any thread is spawned
+        std::env::set_var("PHOENIX_SOFT_RDMA", "1");
+    }
     let config = Config::from_path(opts.config)?;
 
     // init log setting from "PHOENIX_LOG", print messages with level lower than specified to stdout
@@ -70,6 +88,6 @@ fn main() -> Result<()> {
         .expect("failed to register sighandler");
 
     // the Control now takes over
-    let mut control = Control::new(runtime_manager, config);
+    let mut control = Control::new(runtime_manager, config, opts.takeover);
     control.mainloop(&TERMINATE)
 }
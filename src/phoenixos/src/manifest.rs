@@ -0,0 +1,302 @@
+//! The manifests of the plugins, see [`PluginManifest`].
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use itertools::Itertools;
+use semver::Version;
+
+use ipc::control::{ConfigType, PluginManifest, PluginType};
+use phoenix_common::engine::EngineType;
+
+/// The version of the daemon, which the `min_core_version` of the manifests is checked against.
+const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The path of the manifest of the plugin at `lib_path`.
+pub(crate) fn manifest_path(lib_path: &Path) -> PathBuf {
+    lib_path.with_extension("toml")
+}
+
+/// Reads the manifest of the plugin at `lib_path`. Returns `None` if it has none.
+pub(crate) fn read(lib_path: &Path) -> anyhow::Result<Option<PluginManifest>> {
+    let path = manifest_path(lib_path);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Fail to read manifest {}", path.display()))?;
+    let manifest: PluginManifest =
+        toml::from_str(&content).with_context(|| format!("invalid manifest {}", path.display()))?;
+    Version::parse(&manifest.version).with_context(|| {
+        format!(
+            "invalid version {:?} in manifest {}",
+            manifest.version,
+            path.display()
+        )
+    })?;
+    Ok(Some(manifest))
+}
+
+/// Checks, before loading a plugin, that it is the plugin `name` of `kind`, that it works with
+/// this daemon, and that it takes `config`.
+pub(crate) fn check(
+    manifest: &PluginManifest,
+    name: &str,
+    kind: PluginType,
+    config: Option<&str>,
+) -> anyhow::Result<()> {
+    if manifest.name != name {
+        bail!(
+            "plugin {}: its manifest describes plugin {} instead",
+            name,
+            manifest.name
+        );
+    }
+    if manifest.kind != kind {
+        bail!("plugin {} is a {:?}, not a {:?}", name, manifest.kind, kind);
+    }
+    if let Some(min_core_version) = manifest.min_core_version.as_deref() {
+        let min_core_version = Version::parse(min_core_version).with_context(|| {
+            format!(
+                "plugin {}: invalid min_core_version {:?}",
+                name, min_core_version
+            )
+        })?;
+        let core_version = Version::parse(CORE_VERSION).unwrap();
+        if core_version < min_core_version {
+            bail!(
+                "plugin {} needs phoenixos {} or later, this is {}",
+                name,
+                min_core_version,
+                core_version
+            );
+        }
+    }
+    check_config(manifest, config)
+}
+
+/// Checks `config` against the keys declared by the manifest. A manifest that declares no key
+/// leaves the config to the plugin.
+fn check_config(manifest: &PluginManifest, config: Option<&str>) -> anyhow::Result<()> {
+    if manifest.config.is_empty() {
+        return Ok(());
+    }
+    let table = match config {
+        Some(config) => match config.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => bail!("plugin {}: the config is not a table", manifest.name),
+            Err(e) => bail!("plugin {}: invalid config: {}", manifest.name, e),
+        },
+        None => Default::default(),
+    };
+    for (key, value) in table.iter() {
+        let Some(field) = manifest.config.get(key) else {
+            bail!(
+                "plugin {}: unknown key {:?} in the config, expected one of {}",
+                manifest.name,
+                key,
+                manifest.config.keys().join(", ")
+            );
+        };
+        let matched = match field.ty {
+            ConfigType::String => value.is_str(),
+            ConfigType::Integer => value.is_integer(),
+            // an integer is as good as a float
+            ConfigType::Float => value.is_float() || value.is_integer(),
+            ConfigType::Boolean => value.is_bool(),
+            ConfigType::Array => value.is_array(),
+            ConfigType::Table => value.is_table(),
+        };
+        if !matched {
+            bail!(
+                "plugin {}: key {:?} of the config must be a {:?}, got {}",
+                manifest.name,
+                key,
+                field.ty,
+                value.type_str()
+            );
+        }
+    }
+    if let Some((key, _)) = manifest
+        .config
+        .iter()
+        .find(|(key, field)| field.required && !table.contains_key(*key))
+    {
+        bail!(
+            "plugin {}: key {:?} is missing from the config",
+            manifest.name,
+            key
+        );
+    }
+    Ok(())
+}
+
+/// Checks, once a plugin is loaded, that it is the one its manifest describes.
+pub(crate) fn check_loaded(
+    manifest: &PluginManifest,
+    version: &Version,
+    engines: &[EngineType],
+) -> anyhow::Result<()> {
+    // the version is checked to parse when the manifest is read
+    if Version::parse(&manifest.version).unwrap() != *version {
+        bail!(
+            "plugin {} reports version {}, its manifest says {}",
+            manifest.name,
+            version,
+            manifest.version
+        );
+    }
+    let provided = engines.iter().map(|e| e.0).sorted().join(", ");
+    let declared = manifest.engines.iter().sorted().join(", ");
+    if provided != declared {
+        bail!(
+            "plugin {} provides engines [{}], its manifest declares [{}]",
+            manifest.name,
+            provided,
+            declared
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        name = "Mrpc"
+        kind = "Module"
+        version = "0.1.0"
+        engines = ["MrpcEngine"]
+
+        [config.prefetch]
+        type = "boolean"
+
+        [config.timeout_ms]
+        type = "integer"
+        required = true
+
+        [config.ratio]
+        type = "float"
+    "#;
+
+    fn manifest() -> PluginManifest {
+        toml::from_str(MANIFEST).unwrap()
+    }
+
+    /// Writes `manifest` next to a plugin of its own, and returns the path of the plugin.
+    fn plugin_with(name: &str, manifest: Option<&str>) -> PathBuf {
+        let lib_path = std::env::temp_dir().join(format!(
+            "phoenix-manifest-{}-{}.so",
+            std::process::id(),
+            name
+        ));
+        let path = manifest_path(&lib_path);
+        let _ = std::fs::remove_file(&path);
+        if let Some(manifest) = manifest {
+            std::fs::write(&path, manifest).unwrap();
+        }
+        lib_path
+    }
+
+    fn error(result: anyhow::Result<()>) -> String {
+        format!("{:#}", result.unwrap_err())
+    }
+
+    #[test]
+    fn read_manifest() {
+        let lib_path = plugin_with("valid", Some(MANIFEST));
+        assert_eq!(manifest_path(&lib_path).extension().unwrap(), "toml");
+        assert_eq!(read(&lib_path).unwrap(), Some(manifest()));
+
+        assert_eq!(read(&plugin_with("none", None)).unwrap(), None);
+
+        let invalid = MANIFEST.replace("0.1.0", "0.1");
+        let err = read(&plugin_with("version", Some(&invalid))).unwrap_err();
+        assert!(err.to_string().starts_with("invalid version"), "{}", err);
+
+        let unknown = format!("{}\n[extra]\n", MANIFEST);
+        let err = read(&plugin_with("unknown", Some(&unknown))).unwrap_err();
+        assert!(err.to_string().starts_with("invalid manifest"), "{}", err);
+    }
+
+    #[test]
+    fn check_plugin() {
+        let manifest = manifest();
+        let config = Some("timeout_ms = 10");
+        assert!(check(&manifest, "Mrpc", PluginType::Module, config).is_ok());
+
+        let err = error(check(&manifest, "RpcAdapter", PluginType::Module, config));
+        assert!(err.contains("describes plugin Mrpc instead"), "{}", err);
+        let err = error(check(&manifest, "Mrpc", PluginType::Addon, config));
+        assert!(err.contains("is a Module, not a Addon"), "{}", err);
+    }
+
+    #[test]
+    fn check_core_version() {
+        let mut manifest = manifest();
+        let config = Some("timeout_ms = 10");
+        manifest.min_core_version = Some(CORE_VERSION.to_owned());
+        assert!(check(&manifest, "Mrpc", PluginType::Module, config).is_ok());
+        manifest.min_core_version = Some("0.0.1".to_owned());
+        assert!(check(&manifest, "Mrpc", PluginType::Module, config).is_ok());
+
+        manifest.min_core_version = Some("999.0.0".to_owned());
+        let err = error(check(&manifest, "Mrpc", PluginType::Module, config));
+        assert!(err.contains("needs phoenixos 999.0.0 or later"), "{}", err);
+        manifest.min_core_version = Some("next".to_owned());
+        let err = error(check(&manifest, "Mrpc", PluginType::Module, config));
+        assert!(err.contains("invalid min_core_version"), "{}", err);
+    }
+
+    #[test]
+    fn check_config_keys() {
+        let manifest = manifest();
+        let check = |config: &str| check_config(&manifest, Some(config));
+        assert!(check("timeout_ms = 10\nprefetch = true\nratio = 0.5").is_ok());
+        // an integer is as good as a float
+        assert!(check("timeout_ms = 10\nratio = 1").is_ok());
+
+        let err = error(check("timeout_ms = 10\nbatch = 4"));
+        assert!(err.contains("unknown key \"batch\""), "{}", err);
+        assert!(err.contains("prefetch, ratio, timeout_ms"), "{}", err);
+        let err = error(check("timeout_ms = \"10\""));
+        assert!(err.contains("must be a Integer, got string"), "{}", err);
+        let err = error(check("timeout_ms = 10\nprefetch = 1"));
+        assert!(err.contains("must be a Boolean, got integer"), "{}", err);
+        let err = error(check("prefetch = true"));
+        assert!(err.contains("\"timeout_ms\" is missing"), "{}", err);
+        let err = error(check_config(&manifest, None));
+        assert!(err.contains("\"timeout_ms\" is missing"), "{}", err);
+        let err = error(check("timeout_ms ="));
+        assert!(err.contains("invalid config"), "{}", err);
+    }
+
+    #[test]
+    fn config_left_to_plugin() {
+        let mut manifest = manifest();
+        manifest.config.clear();
+        assert!(check_config(&manifest, Some("anything = [1, 2]")).is_ok());
+        assert!(check_config(&manifest, Some("not toml")).is_ok());
+        assert!(check_config(&manifest, None).is_ok());
+    }
+
+    #[test]
+    fn check_loaded_plugin() {
+        let mut manifest = manifest();
+        manifest.engines = vec!["MrpcEngine".to_owned(), "AuxEngine".to_owned()];
+        let version = Version::new(0, 1, 0);
+        let engines = [EngineType("AuxEngine"), EngineType("MrpcEngine")];
+        assert!(check_loaded(&manifest, &version, &engines).is_ok());
+
+        let err = error(check_loaded(&manifest, &Version::new(0, 2, 0), &engines));
+        assert!(err.contains("reports version 0.2.0"), "{}", err);
+        let err = error(check_loaded(&manifest, &version, &engines[1..]));
+        assert!(
+            err.contains(
+                "provides engines [MrpcEngine], its manifest declares [AuxEngine, MrpcEngine]"
+            ),
+            "{}",
+            err
+        );
+    }
+}
@@ -16,12 +16,23 @@ pub(crate) enum PluginName {
 
 pub(crate) struct Plugin {
     linked: LinkedModule,
-    _old: Option<LinkedModule>,
+    /// The library replaced by the last upgrade, kept mapped until it is safe to unload.
+    old: Option<LinkedModule>,
 }
 
 impl Plugin {
     pub(crate) fn new(linked: LinkedModule) -> Self {
-        Self { linked, _old: None }
+        Self { linked, old: None }
+    }
+
+    #[inline]
+    pub(crate) fn linked(&self) -> &LinkedModule {
+        &self.linked
+    }
+
+    #[inline]
+    pub(crate) fn old(&self) -> Option<&LinkedModule> {
+        self.old.as_ref()
     }
 
     pub(crate) fn init_module(
@@ -75,19 +86,28 @@ impl Plugin {
         let old = self.linked;
         Plugin {
             linked: new,
-            _old: Some(old),
+            old: Some(old),
         }
     }
 
+    /// The library, for the caller to unload. The library replaced by the last upgrade must have
+    /// been taken out.
+    #[inline]
+    pub(crate) fn into_linked(self) -> LinkedModule {
+        debug_assert!(self.old.is_none());
+        self.linked
+    }
+
+    /// Takes the old library out, the caller decides whether it can be unloaded.
     #[inline]
-    pub(crate) fn unload_old(&mut self) {
-        self._old = None
+    pub(crate) fn take_old(&mut self) -> Option<LinkedModule> {
+        self.old.take()
     }
 
     #[inline]
     pub(crate) fn rollback(&mut self) {
-        if self._old.is_some() {
-            self.linked = self._old.take().unwrap();
+        if self.old.is_some() {
+            self.linked = self.old.take().unwrap();
         }
     }
 
@@ -3,17 +3,19 @@ use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::mem::ManuallyDrop;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use anyhow::bail;
 use crc32fast::Hasher as Crc32Hasher;
 use dashmap::DashMap;
 use itertools::Itertools;
+use nix::unistd::Pid;
 use phoenix_api::engine::SchedulingMode;
 
-use ipc::control::PluginDescriptor;
+use ipc::control::{CanaryInfo, Labels, PluginDescriptor, PluginInfo, PluginManifest, PluginType};
 
 use phoenix_common::addon::PhoenixAddon;
+use phoenix_common::capability::Capabilities;
 use phoenix_common::engine::datapath::node::ChannelDescriptor;
 use phoenix_common::engine::EngineType;
 use phoenix_common::module::PhoenixModule;
@@ -21,9 +23,12 @@ use phoenix_common::module::Service;
 
 use crate::config::LinkerConfig;
 use crate::dependency::EngineGraph;
-use crate::linker::Linker;
+use crate::labels::Selector;
+use crate::linker::{LinkedModule, Linker};
+use crate::manifest;
 use crate::plugin::{Plugin, PluginName};
 use crate::runtime::group::GroupUnionFind;
+use crate::runtime::RuntimeManager;
 use crate::{log, tracing};
 
 pub(crate) struct ServiceRegistry {
@@ -33,17 +38,86 @@ pub(crate) struct ServiceRegistry {
     pub(crate) scheduling_groups: GroupUnionFind,
 }
 
+/// A second version of a module, loaded side by side with the one in `modules`, which the new
+/// subscriptions of a share of the processes run on, so that a release can be canaried rather than
+/// upgraded wholesale.
+///
+/// A process is assigned as a whole, when it subscribes for the first time, and stays on the
+/// version it is assigned to for as long as it has subscriptions, since its engines share their
+/// per-process states, which the two versions cannot.
+pub(crate) struct Canary {
+    descriptor: PluginDescriptor,
+    // NOTE: declared before the plugin, the module must be dropped before its library
+    module: Box<dyn PhoenixModule>,
+    // like `PluginManager::plugins`, the library is only unloaded explicitly
+    plugin: ManuallyDrop<Plugin>,
+    percent: u8,
+    selector: Option<Selector>,
+    /// No longer takes new processes, and is unloaded once its engines are gone.
+    draining: bool,
+    processes: HashSet<Pid>,
+    /// The processes decided so far, and those of them assigned to the canary.
+    decided: u64,
+    canaried: u64,
+}
+
+impl Canary {
+    /// Whether the new subscription of `pid` runs on the canary.
+    fn assign(&mut self, pid: Pid, labels: &Labels, subscribed: bool) -> bool {
+        if self.processes.contains(&pid) {
+            return true;
+        }
+        if subscribed || self.draining {
+            return false;
+        }
+        if !self.selector.as_ref().map_or(true, |s| s.matches(labels)) {
+            return false;
+        }
+        // assigns the exact share over the processes decided, rather than a random one
+        self.decided += 1;
+        if self.canaried * 100 < self.percent as u64 * self.decided {
+            self.canaried += 1;
+            self.processes.insert(pid);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn info(&self) -> CanaryInfo {
+        CanaryInfo {
+            name: self.descriptor.name.clone(),
+            lib_path: self.descriptor.lib_path.clone(),
+            percent: self.percent,
+            selector: self.selector.as_ref().map(|s| s.to_string()),
+            draining: self.draining,
+            processes: self
+                .processes
+                .iter()
+                .map(|pid| pid.as_raw())
+                .sorted()
+                .collect(),
+        }
+    }
+}
+
 // COMMENT(wyj): drop order matters
 pub struct PluginManager {
     default_prefix: PathBuf,
     pub(crate) modules: DashMap<String, Box<dyn PhoenixModule>>,
     pub(crate) addons: DashMap<String, Box<dyn PhoenixAddon>>,
+    /// The canary versions of the modules, by the name of the module
+    canaries: DashMap<String, Canary>,
+    /// The manifests of the plugins loaded, by the name of the plugin
+    manifests: DashMap<String, PluginManifest>,
     pub(crate) engine_registry: DashMap<EngineType, (PluginName, Option<SchedulingMode>)>,
     pub(crate) service_registry: DashMap<Service, ServiceRegistry>,
     dependency_graph: Mutex<EngineGraph>,
     /// signature of different scheduling groups
     /// that service engines (addon engines are excluded) will create
     scheduling_group_signatures: Mutex<HashMap<u32, String>>,
+    /// The capabilities of the sandbox of the runtimes once it is sealed
+    sandbox: Mutex<Option<Capabilities>>,
 
     plugins: ManuallyDrop<DashMap<PluginDescriptor, Plugin>>,
     rt_linker: Mutex<Linker>,
@@ -62,12 +136,15 @@ impl PluginManager {
             default_prefix: default_prefix.clone(),
             modules: DashMap::new(),
             addons: DashMap::new(),
+            canaries: DashMap::new(),
+            manifests: DashMap::new(),
             engine_registry: DashMap::new(),
             service_registry: DashMap::new(),
             dependency_graph: Mutex::new(EngineGraph::new()),
             scheduling_group_signatures: Mutex::new(HashMap::new()),
+            sandbox: Mutex::new(None),
             plugins: ManuallyDrop::new(DashMap::new()),
-            rt_linker: Mutex::new(Linker::new(rt_linker_workdir)?),
+            rt_linker: Mutex::new(Linker::new(rt_linker_workdir, linker_config.leak_detect)?),
         })
     }
 
@@ -86,6 +163,67 @@ impl PluginManager {
         }
     }
 
+    /// The capabilities declared by the plugins loaded.
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        let mut caps = Capabilities::default();
+        for module in self.modules.iter() {
+            caps.merge(&module.capabilities());
+        }
+        for addon in self.addons.iter() {
+            caps.merge(&addon.capabilities());
+        }
+        caps
+    }
+
+    /// Fixes the sandbox of the runtimes to the capabilities of the plugins loaded. The plugins
+    /// loaded from now on must not declare more.
+    ///
+    /// Called once at startup, before the first runtime starts. The runtimes install their
+    /// sandbox when they start and cannot widen it afterwards, so the capabilities are frozen
+    /// from then on: a plugin hot-loaded later, by an upgrade or an addon attach, cannot get new
+    /// system calls or paths, and is rejected by `check_sandbox` if it declares any. Such a
+    /// plugin must be added to the preset plugins and the daemon restarted.
+    pub(crate) fn seal_sandbox(&self) -> Capabilities {
+        let caps = self.capabilities();
+        *self.sandbox.lock().unwrap() = Some(caps.clone());
+        caps
+    }
+
+    fn check_sandbox(&self, name: &str, caps: &Capabilities) -> anyhow::Result<()> {
+        match self.sandbox.lock().unwrap().as_ref() {
+            Some(sandbox) if !sandbox.covers(caps) => bail!(
+                "plugin {} needs capabilities beyond the sandbox of the runtimes: {:?}",
+                name,
+                caps
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the manifest of a plugin, and checks the plugin against it before loading it.
+    fn check_manifest(
+        &self,
+        descriptor: &PluginDescriptor,
+        kind: PluginType,
+        config_string: Option<&str>,
+    ) -> anyhow::Result<Option<PluginManifest>> {
+        let (lib_path, _) = self.get_plugin_path(descriptor);
+        let manifest = manifest::read(&lib_path)?;
+        if let Some(manifest) = manifest.as_ref() {
+            manifest::check(manifest, &descriptor.name, kind, config_string)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Records the manifest a plugin is loaded with, or forgets the one of the library it
+    /// replaces.
+    fn record_manifest(&self, name: &str, manifest: Option<PluginManifest>) {
+        match manifest {
+            Some(manifest) => self.manifests.insert(name.to_owned(), manifest),
+            None => self.manifests.remove(name).map(|(_, m)| m),
+        };
+    }
+
     pub fn load_or_upgrade_addon(&self, addon: &PluginDescriptor) -> anyhow::Result<()> {
         // Get the library path and its dep file path
         let (lib_path, dep_path) = self.get_plugin_path(&addon);
@@ -95,6 +233,11 @@ impl PluginManager {
             dep_path.display()
         );
 
+        // Read config from path or string
+        let config_string =
+            Plugin::load_config(addon.config_path.as_ref(), addon.config_string.as_ref())?;
+        let manifest = self.check_manifest(addon, PluginType::Addon, config_string.as_deref())?;
+
         // RT linker load the rlib and its all transitive dependencies
         let linked = {
             let mut linker = self.rt_linker.lock().unwrap();
@@ -107,10 +250,6 @@ impl PluginManager {
             None => Plugin::new(linked),
         };
 
-        // Read config from path or string
-        let config_string =
-            Plugin::load_config(addon.config_path.as_ref(), addon.config_string.as_ref())?;
-
         // Init the addon
         let mut new_addon = new_plug.init_addon(config_string.as_deref())?;
 
@@ -123,6 +262,17 @@ impl PluginManager {
             self.plugins.get_mut(&addon).unwrap().rollback();
             bail!("new addon is not compatible with old version");
         }
+        let checked = self
+            .check_sandbox(&addon.name, &new_addon.capabilities())
+            .and_then(|_| match manifest.as_ref() {
+                Some(m) => manifest::check_loaded(m, &new_addon.version(), new_addon.engines()),
+                None => Ok(()),
+            });
+        if let Err(e) = checked {
+            self.plugins.get_mut(&addon).unwrap().rollback();
+            return Err(e);
+        }
+        self.record_manifest(&addon.name, manifest);
 
         if let Some((_, old_addon)) = self.addons.remove(&addon.name) {
             // migrate any states/resources from old module
@@ -132,6 +282,8 @@ impl PluginManager {
         // Update the registries
         let engines = new_addon.engines();
         for engine in engines {
+            // Re-insert the key as well, the old one points into the old library.
+            self.engine_registry.remove(engine);
             self.engine_registry
                 .insert(*engine, (PluginName::Addon(addon.name.clone()), None));
             tracing::info!("Registered addon engine {:?}", engine);
@@ -148,6 +300,66 @@ impl PluginManager {
         Ok(())
     }
 
+    /// Checks that the plugins can be loaded, that their configs parse, and that they match their
+    /// manifests, without loading them. Returns the steps that loading them would take.
+    pub(crate) fn plan_plugins(
+        &self,
+        descriptors: &[PluginDescriptor],
+        kind: PluginType,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut plan = Vec::with_capacity(descriptors.len());
+        for descriptor in descriptors.iter() {
+            let (lib_path, dep_path) = self.get_plugin_path(descriptor);
+            for path in [&lib_path, &dep_path] {
+                if !path.is_file() {
+                    bail!("plugin {}: {} not found", descriptor.name, path.display());
+                }
+            }
+            let config_string = Plugin::load_config(
+                descriptor.config_path.as_ref(),
+                descriptor.config_string.as_ref(),
+            )?;
+            if let Some(config) = config_string.as_ref() {
+                if let Err(e) = config.parse::<toml::Value>() {
+                    bail!("plugin {}: invalid config: {}", descriptor.name, e);
+                }
+            }
+            let version = self
+                .check_manifest(descriptor, kind, config_string.as_deref())?
+                .map(|m| format!(", version {}", m.version))
+                .unwrap_or_default();
+
+            let loaded = self.modules.contains_key(&descriptor.name)
+                || self.addons.contains_key(&descriptor.name);
+            let engines = self
+                .engine_registry
+                .iter()
+                .filter(|e| match &e.value().0 {
+                    PluginName::Module(name) | PluginName::Addon(name) => name == &descriptor.name,
+                })
+                .map(|e| e.key().0)
+                .sorted()
+                .join(", ");
+            if loaded {
+                plan.push(format!(
+                    "upgrade plugin {} from {}{}, engines: [{}]",
+                    descriptor.name,
+                    lib_path.display(),
+                    version,
+                    engines
+                ));
+            } else {
+                plan.push(format!(
+                    "load plugin {} from {}{}",
+                    descriptor.name,
+                    lib_path.display(),
+                    version
+                ));
+            }
+        }
+        Ok(plan)
+    }
+
     /// Load or upgrade plugins.
     /// Returns a set of affected engine types.
     pub fn load_or_upgrade_modules(
@@ -162,6 +374,18 @@ impl PluginManager {
             new_versions.insert(&module.key()[..], module.version());
         }
 
+        // check the manifests before loading any of the modules
+        let mut manifests = HashMap::with_capacity(descriptors.len());
+        for descriptor in descriptors.iter() {
+            let config_string = Plugin::load_config(
+                descriptor.config_path.as_ref(),
+                descriptor.config_string.as_ref(),
+            )?;
+            let manifest =
+                self.check_manifest(descriptor, PluginType::Module, config_string.as_deref())?;
+            manifests.insert(&descriptor.name, manifest);
+        }
+
         // load new moduels (plugins)
         for descriptor in descriptors.iter() {
             // Get the library path and its dep file path
@@ -221,6 +445,19 @@ impl PluginManager {
             }
             bail!("new modules are not compatible with existing ones");
         }
+        let sandboxed = new_modules.iter().try_for_each(|(name, module)| {
+            self.check_sandbox(name, &module.capabilities())?;
+            match manifests[*name].as_ref() {
+                Some(m) => manifest::check_loaded(m, &module.version(), module.engines()),
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = sandboxed {
+            for desc in descriptors.iter() {
+                self.plugins.get_mut(&desc).unwrap().rollback();
+            }
+            return Err(e);
+        }
 
         drop(modules_guard);
         // if compatible, finish upgrade
@@ -239,6 +476,8 @@ impl PluginManager {
             upgraded_engine_types.extend(engines.iter().copied());
             graph_guard.add_engines(engines.iter().copied());
             for engine in engines {
+                // Re-insert the key as well, the old one points into the old library.
+                self.engine_registry.remove(engine);
                 self.engine_registry
                     .insert(*engine, (PluginName::Module(plugin_name.clone()), None));
             }
@@ -254,6 +493,7 @@ impl PluginManager {
             let edges = module.dependencies();
             graph_guard.add_dependency(edges.iter().copied())?;
             self.modules.insert(name.to_string(), module);
+            self.record_manifest(name, manifests.remove(name).unwrap());
         }
 
         for descriptor in descriptors.iter() {
@@ -341,12 +581,433 @@ impl PluginManager {
         Ok(upgraded_engine_types)
     }
 
-    /// Finish upgrade of all engines, unload old plugins
-    pub(crate) fn upgrade_cleanup(&self) {
+    /// Returns the library that implements engines of type `engine_type`.
+    pub(crate) fn engine_module(&self, engine_type: EngineType) -> Option<LinkedModule> {
+        let registry = self.engine_registry.get(&engine_type)?;
+        let name = match &registry.value().0 {
+            PluginName::Module(name) | PluginName::Addon(name) => name,
+        };
+        self.plugins
+            .iter()
+            .find(|plugin| &plugin.key().name == name)
+            .map(|plugin| LinkedModule::clone(plugin.value().linked()))
+    }
+
+    /// Checks that a canary can be loaded, without loading it. Returns the steps that loading it
+    /// would take.
+    pub(crate) fn plan_canary(
+        &self,
+        descriptor: &PluginDescriptor,
+        percent: u8,
+        selector: Option<&Selector>,
+    ) -> anyhow::Result<Vec<String>> {
+        let share = match selector {
+            Some(selector) => format!("{}% of the new processes matching {}", percent, selector),
+            None => format!("{}% of the new processes", percent),
+        };
+        if let Some(canary) = self.check_canary(descriptor, percent)? {
+            return Ok(vec![format!(
+                "assign {} to the canary of module {}, instead of {}%",
+                share, descriptor.name, canary
+            )]);
+        }
+        let mut plan = self.plan_plugins(std::slice::from_ref(descriptor), PluginType::Module)?;
+        let (lib_path, _) = self.get_plugin_path(descriptor);
+        plan[0] = format!(
+            "load a canary of module {} from {}, side by side with the running one",
+            descriptor.name,
+            lib_path.display()
+        );
+        plan.push(format!("assign {} to the canary", share));
+        Ok(plan)
+    }
+
+    /// Checks a canary request. Returns the share of the canary it changes, if any.
+    fn check_canary(
+        &self,
+        descriptor: &PluginDescriptor,
+        percent: u8,
+    ) -> anyhow::Result<Option<u8>> {
+        if percent > 100 {
+            bail!("invalid share of a canary: {}%", percent);
+        }
+        if !self.modules.contains_key(&descriptor.name) {
+            bail!(
+                "module {} is not loaded, there is nothing to canary",
+                descriptor.name
+            );
+        }
+        match self.canaries.get(&descriptor.name) {
+            Some(canary) if canary.draining => {
+                bail!("the canary of module {} is ending", descriptor.name)
+            }
+            Some(canary) if canary.descriptor.lib_path != descriptor.lib_path => bail!(
+                "module {} already has a canary, from {}",
+                descriptor.name,
+                canary.descriptor.lib_path.display()
+            ),
+            Some(canary) => Ok(Some(canary.percent)),
+            None => Ok(None),
+        }
+    }
+
+    /// Loads a canary version of a module, side by side with the running one, and assigns it
+    /// `percent` of the new processes matching `selector`. Changes the share of the canary if it
+    /// is already loaded from the same library.
+    pub(crate) fn load_canary(
+        &self,
+        descriptor: &PluginDescriptor,
+        percent: u8,
+        selector: Option<Selector>,
+    ) -> anyhow::Result<()> {
+        if self.check_canary(descriptor, percent)?.is_some() {
+            let mut canary = self.canaries.get_mut(&descriptor.name).unwrap();
+            canary.percent = percent;
+            canary.selector = selector;
+            log::info!(
+                "Canary of module {} now takes {}% of the new processes",
+                descriptor.name,
+                percent
+            );
+            return Ok(());
+        }
+
+        let (lib_path, dep_path) = self.get_plugin_path(descriptor);
+        log::info!(
+            "load_canary: lib_path: {}, dep_path: {}",
+            lib_path.display(),
+            dep_path.display()
+        );
+        let config_string = Plugin::load_config(
+            descriptor.config_path.as_ref(),
+            descriptor.config_string.as_ref(),
+        )?;
+        let manifest =
+            self.check_manifest(descriptor, PluginType::Module, config_string.as_deref())?;
+        let linked = {
+            let mut linker = self.rt_linker.lock().unwrap();
+            linker.load_archive(lib_path, dep_path)?
+        };
+        let plugin = Plugin::new(linked);
+        let module = plugin
+            .init_module(config_string.as_deref())
+            .and_then(|module| {
+                self.check_canary_module(&descriptor.name, &*module)?;
+                if let Some(m) = manifest.as_ref() {
+                    manifest::check_loaded(m, &module.version(), module.engines())?;
+                }
+                Ok(module)
+            });
+        let module = match module {
+            Ok(module) => module,
+            Err(e) => {
+                self.rt_linker.lock().unwrap().unload(plugin.into_linked());
+                return Err(e);
+            }
+        };
+
+        log::info!(
+            "Loaded a canary of module {}, taking {}% of the new processes",
+            descriptor.name,
+            percent
+        );
+        self.canaries.insert(
+            descriptor.name.clone(),
+            Canary {
+                descriptor: descriptor.clone(),
+                module,
+                plugin: ManuallyDrop::new(plugin),
+                percent,
+                selector,
+                draining: false,
+                processes: HashSet::new(),
+                decided: 0,
+                canaried: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Checks that a canary can stand in for the running module in the subscriptions: it works
+    /// with the other modules, stays in the sandbox, and provides the same engines.
+    fn check_canary_module(&self, name: &str, canary: &dyn PhoenixModule) -> anyhow::Result<()> {
+        let engines = |m: &dyn PhoenixModule| -> String {
+            m.engines().iter().map(|e| e.0).sorted().join(", ")
+        };
+        let running = engines(&**self.modules.get(name).unwrap());
+        if engines(canary) != running {
+            bail!(
+                "the canary of module {} provides engines [{}] instead of [{}]",
+                name,
+                engines(canary),
+                running
+            );
+        }
+        let mut versions = self
+            .modules
+            .iter()
+            .map(|m| (m.key().clone(), m.version()))
+            .collect::<HashMap<_, _>>();
+        versions.insert(name.to_owned(), canary.version());
+        let versions = versions.iter().map(|(k, v)| (&k[..], v.clone())).collect();
+        if !canary.check_compatibility(None, &versions) {
+            bail!(
+                "the canary of module {} is not compatible with the other modules",
+                name
+            );
+        }
+        self.check_sandbox(name, &canary.capabilities())
+    }
+
+    /// Refuses to upgrade a module that is being canaried. Once its canary is ending, upgrading
+    /// the module moves the processes of the canary to the new version.
+    pub(crate) fn check_upgrade_with_canaries(
+        &self,
+        descriptors: &[PluginDescriptor],
+        selective: bool,
+    ) -> anyhow::Result<()> {
+        for descriptor in descriptors {
+            match self.canaries.get(&descriptor.name) {
+                Some(canary) if !canary.draining => bail!(
+                    "module {} is being canaried, end its canary first",
+                    descriptor.name
+                ),
+                Some(canary) if selective && !canary.processes.is_empty() => bail!(
+                    "the processes of the canary of module {} must be upgraded with the others",
+                    descriptor.name
+                ),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the upgrade of modules, which has moved the processes of their canaries.
+    pub(crate) fn upgraded_with_canaries(&self, descriptors: &[PluginDescriptor]) {
+        for descriptor in descriptors {
+            if let Some(mut canary) = self.canaries.get_mut(&descriptor.name) {
+                canary.processes.clear();
+            }
+        }
+    }
+
+    /// Stops assigning processes to the canary of a module. Returns its descriptor.
+    pub(crate) fn end_canary(&self, name: &str) -> anyhow::Result<PluginDescriptor> {
+        let mut canary = self
+            .canaries
+            .get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("module {} has no canary", name))?;
+        canary.draining = true;
+        Ok(canary.descriptor.clone())
+    }
+
+    /// Assigns a new subscription of `pid` to the canaries of `modules`. Returns the modules
+    /// whose canaries it runs on.
+    pub(crate) fn assign_canaries(
+        &self,
+        modules: &HashSet<&str>,
+        pid: Pid,
+        labels: &Labels,
+        rm: &RuntimeManager,
+    ) -> HashSet<String> {
+        if self.canaries.is_empty() {
+            return HashSet::new();
+        }
+        let subscribed = rm.service_subscriptions.iter().any(|s| s.key().0 == pid);
+        let mut assigned = HashSet::new();
+        for mut canary in self.canaries.iter_mut() {
+            if !subscribed {
+                // the process is gone, and the pid reused
+                canary.processes.remove(&pid);
+            }
+            if modules.contains(canary.key().as_str()) && canary.assign(pid, labels, subscribed) {
+                assigned.insert(canary.key().clone());
+            }
+        }
+        assigned
+    }
+
+    /// The modules whose canaries the subscriptions of `pid` run on.
+    pub(crate) fn canaries_of(&self, pid: Pid) -> Vec<String> {
+        self.canaries
+            .iter()
+            .filter(|c| c.processes.contains(&pid))
+            .map(|c| c.key().clone())
+            .sorted()
+            .collect()
+    }
+
+    /// Runs `f` on the module `name`, or on its canary, with the library it is loaded from.
+    pub(crate) fn with_module<R, F>(&self, name: &str, canary: bool, f: F) -> R
+    where
+        F: FnOnce(&mut dyn PhoenixModule, Option<LinkedModule>) -> R,
+    {
+        if canary {
+            let mut canary = self.canaries.get_mut(name).unwrap();
+            let linked = LinkedModule::clone(canary.plugin.linked());
+            f(&mut *canary.module, Some(linked))
+        } else {
+            let linked = self
+                .plugins
+                .iter()
+                .find(|plugin| plugin.key().name == name)
+                .map(|plugin| LinkedModule::clone(plugin.value().linked()));
+            let mut module = self.modules.get_mut(name).unwrap();
+            f(&mut **module, linked)
+        }
+    }
+
+    /// The canaries of the modules, once those that have ended are unloaded.
+    pub(crate) fn list_canaries(&self, rm: &RuntimeManager) -> Vec<CanaryInfo> {
+        self.unload_ended_canaries(rm);
+        self.canaries
+            .iter()
+            .map(|c| c.info())
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .collect()
+    }
+
+    /// The plugins loaded, with the manifests they were loaded with.
+    pub(crate) fn list_plugins(&self) -> Vec<PluginInfo> {
+        self.plugins
+            .iter()
+            .filter_map(|plugin| {
+                let descriptor = plugin.key();
+                let name = &descriptor.name;
+                // a plugin being loaded has no module or addon yet
+                let (kind, version) = match self.modules.get(name) {
+                    Some(module) => (PluginType::Module, module.version()),
+                    None => (PluginType::Addon, self.addons.get(name)?.version()),
+                };
+                let engines = self
+                    .engine_registry
+                    .iter()
+                    .filter(|e| match &e.value().0 {
+                        PluginName::Module(n) | PluginName::Addon(n) => n == name,
+                    })
+                    .map(|e| e.key().0.to_owned())
+                    .sorted()
+                    .collect();
+                Some(PluginInfo {
+                    name: name.clone(),
+                    kind,
+                    lib_path: self.get_plugin_path(descriptor).0,
+                    version: version.to_string(),
+                    engines,
+                    manifest: self.manifests.get(name).map(|m| m.value().clone()),
+                })
+            })
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .collect()
+    }
+
+    pub(crate) fn unload_ended_canaries(&self, rm: &RuntimeManager) {
+        self.canary_cleanup(&mut self.rt_linker.lock().unwrap(), rm);
+    }
+
+    /// Unloads the canaries that have ended once nothing can reach them anymore: no process is
+    /// assigned to them, and no engine container pins their library.
+    fn canary_cleanup(&self, linker: &mut Linker, rm: &RuntimeManager) {
+        for mut canary in self.canaries.iter_mut() {
+            canary
+                .processes
+                .retain(|pid| rm.service_subscriptions.iter().any(|s| s.key().0 == *pid));
+        }
+        let ended = self
+            .canaries
+            .iter()
+            .filter(|c| c.draining && c.processes.is_empty())
+            .map(|c| c.key().clone())
+            .collect_vec();
+        for name in ended {
+            let canary = self.canaries.get(&name).unwrap();
+            if let Some(reason) = linker.find_reference(canary.plugin.linked()) {
+                log::warn!("Keeping the canary of module {} loaded: {}", name, reason);
+                continue;
+            }
+            drop(canary);
+            let (_, canary) = self.canaries.remove(&name).unwrap();
+            let Canary { module, plugin, .. } = canary;
+            drop(module);
+            linker.unload(ManuallyDrop::into_inner(plugin).into_linked());
+            log::info!("Unloaded the canary of module {}", name);
+        }
+    }
+
+    /// Finish upgrade of all engines, unload old plugins.
+    ///
+    /// An old library is only unloaded once nothing can reach it anymore: no engine container
+    /// pins it, no other module is linked against it, and no `EngineType` known to the
+    /// registries or to the runtime manager points into it. Otherwise it stays mapped, and
+    /// the check is repeated after the next upgrade.
+    pub(crate) fn upgrade_cleanup(&self, rm: &RuntimeManager) {
         // NOTE, we drop the old library here. To work around the issue mentioned earlier in
-        // PluginManager, we do not exit thread/runtime actively.
+        // PluginManager, we do not exit thread/runtime actively. Hence TLS destructors that the
+        // old library may have registered on runtime threads never run.
+        let mut linker = self.rt_linker.lock().unwrap();
         for mut plugin in self.plugins.iter_mut() {
-            plugin.value_mut().unload_old()
+            let name = plugin.key().name.clone();
+            let plugin = plugin.value_mut();
+            let Some(old) = plugin.old() else {
+                continue;
+            };
+            if Arc::ptr_eq(old, plugin.linked()) {
+                // the same library was loaded again, there is nothing to unload
+                plugin.take_old();
+                continue;
+            }
+
+            let reason = self
+                .find_engine_type_in(old, rm)
+                .map(|ty| format!("engine type {:?} still points into it", ty))
+                .or_else(|| linker.find_reference(old));
+            match reason {
+                Some(reason) => {
+                    log::warn!(
+                        "Keeping the old library of plugin {} loaded: {}",
+                        name,
+                        reason
+                    );
+                }
+                None => linker.unload(plugin.take_old().unwrap()),
+            }
         }
+        self.canary_cleanup(&mut linker, rm);
+    }
+
+    /// Find an `EngineType` whose name is stored in the memory of `linked`.
+    fn find_engine_type_in(
+        &self,
+        linked: &LinkedModule,
+        rm: &RuntimeManager,
+    ) -> Option<EngineType> {
+        let in_linked = |ty: &EngineType| linked.contains_addr(ty.0.as_ptr().addr());
+        let registered = self.engine_registry.iter().map(|e| *e.key());
+        let services = self.service_registry.iter().flat_map(|s| {
+            let registry = s.value();
+            let channels = registry
+                .tx_channels
+                .iter()
+                .chain(registry.rx_channels.iter());
+            registry
+                .engines
+                .iter()
+                .copied()
+                .chain(channels.flat_map(|c| [c.0, c.1]))
+                .collect::<Vec<_>>()
+        });
+        let running = rm
+            .engine_subscriptions
+            .iter()
+            .map(|e| e.value().engine_type);
+        let addons = rm
+            .service_subscriptions
+            .iter()
+            .flat_map(|s| s.value().0.addons.clone());
+        registered
+            .chain(services)
+            .chain(running)
+            .chain(addons)
+            .find(in_linked)
     }
 }
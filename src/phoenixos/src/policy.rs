@@ -0,0 +1,345 @@
+//! Declarative datapath policies.
+//!
+//! A policy is a list of rules, each matching the RPCs of a service or one of its methods and
+//! taking an action on them. It is compiled into a chain of the standard policy addons, which
+//! is then attached like any other chain:
+//!
+//! - the `pass` and `reject` rules go to a `FilterEngine`, where the first matching rule decides;
+//! - the `rate_limit` rule goes to a `RateLimitEngine`;
+//! - the `mirror` rule goes to a `CaptureEngine`.
+//!
+//! The requests go through the addons in that order, e.g., the rejected requests are neither
+//! rate limited nor mirrored.
+use std::path::PathBuf;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+use ipc::control::ChainedAddon;
+
+const FILTER_ENGINE: &str = "FilterEngine";
+const RATE_LIMIT_ENGINE: &str = "RateLimitEngine";
+const CAPTURE_ENGINE: &str = "CaptureEngine";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Policy {
+    /// The engine to insert the chain after, inferred from the datapath if omitted.
+    #[serde(default)]
+    pub(crate) upstream: Option<String>,
+    /// The engine to insert the chain before, inferred from the datapath if omitted.
+    #[serde(default)]
+    pub(crate) downstream: Option<String>,
+    /// The scheduling group of the addons, a new group of their own if empty.
+    #[serde(default)]
+    pub(crate) group: Vec<String>,
+    #[serde(default)]
+    pub(crate) rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Rule {
+    #[serde(default, rename = "match")]
+    pub(crate) matches: Match,
+    pub(crate) action: Action,
+}
+
+/// The RPCs a rule applies to, all of them if empty.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Match {
+    /// The full name of the service, e.g., `rpc_hello.Greeter`.
+    #[serde(default)]
+    pub(crate) service: Option<String>,
+    /// The name of a method of the service, e.g., `SayHello`.
+    #[serde(default)]
+    pub(crate) method: Option<String>,
+    /// The remote end of the connection.
+    #[serde(default)]
+    pub(crate) peer: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Action {
+    Pass,
+    Reject,
+    RateLimit {
+        requests_per_sec: u64,
+        bucket_size: u64,
+    },
+    Mirror {
+        #[serde(default)]
+        dir: Option<PathBuf>,
+        #[serde(default)]
+        snap_len: usize,
+    },
+    Route {
+        to: String,
+    },
+}
+
+impl Policy {
+    pub(crate) fn parse(policy: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(policy)?)
+    }
+
+    /// Compiles the rules into the chain of addons, in the order the requests go through them.
+    pub(crate) fn compile(&self) -> anyhow::Result<Vec<ChainedAddon>> {
+        let mut filter_rules = Vec::new();
+        let mut rejects = false;
+        let mut rate_limit = None;
+        let mut mirror = None;
+        for (i, rule) in self.rules.iter().enumerate() {
+            let mut table = rule.matches.compile(i)?;
+            match &rule.action {
+                Action::Pass | Action::Reject => {
+                    let reject = matches!(rule.action, Action::Reject);
+                    rejects |= reject;
+                    let action = if reject { "reject" } else { "pass" };
+                    table.insert("action".to_string(), Value::from(action));
+                    filter_rules.push(Value::Table(table));
+                }
+                Action::RateLimit {
+                    requests_per_sec,
+                    bucket_size,
+                } => {
+                    if rate_limit.is_some() {
+                        bail!("rule {}: only one rate_limit rule is supported", i);
+                    }
+                    table.insert(
+                        "requests_per_sec".to_string(),
+                        Value::Integer(*requests_per_sec as i64),
+                    );
+                    table.insert(
+                        "bucket_size".to_string(),
+                        Value::Integer(*bucket_size as i64),
+                    );
+                    rate_limit = Some(table);
+                }
+                Action::Mirror { dir, snap_len } => {
+                    if mirror.is_some() {
+                        bail!("rule {}: only one mirror rule is supported", i);
+                    }
+                    if let Some(dir) = dir {
+                        table.insert(
+                            "dir".to_string(),
+                            Value::from(dir.to_string_lossy().into_owned()),
+                        );
+                    }
+                    table.insert("snap_len".to_string(), Value::Integer(*snap_len as i64));
+                    mirror = Some(table);
+                }
+                Action::Route { to } => {
+                    bail!(
+                        "rule {}: routing to {:?} is not supported by the standard addons",
+                        i,
+                        to
+                    );
+                }
+            }
+        }
+
+        let mut chain = Vec::new();
+        // a filter of only pass rules passes everything
+        if rejects {
+            let mut table = Table::new();
+            table.insert("rules".to_string(), Value::Array(filter_rules));
+            chain.push(chained_addon(FILTER_ENGINE, table, false));
+        }
+        if let Some(table) = rate_limit {
+            chain.push(chained_addon(RATE_LIMIT_ENGINE, table, true));
+        }
+        if let Some(table) = mirror {
+            chain.push(chained_addon(CAPTURE_ENGINE, table, false));
+        }
+        if chain.is_empty() {
+            bail!("the policy has no rules that change the datapath");
+        }
+        Ok(chain)
+    }
+}
+
+impl Match {
+    /// The service and function IDs to match, as in the configs of the addons.
+    fn compile(&self, rule: usize) -> anyhow::Result<Table> {
+        if let Some(peer) = self.peer.as_ref() {
+            bail!(
+                "rule {}: matching on the peer {:?} is not supported by the standard addons",
+                rule,
+                peer
+            );
+        }
+        let mut table = Table::new();
+        match (self.service.as_ref(), self.method.as_ref()) {
+            (Some(service), method) => {
+                table.insert(
+                    "service_id".to_string(),
+                    Value::Integer(crc32fast::hash(service.as_bytes()) as i64),
+                );
+                if let Some(method) = method {
+                    let path = format!("/{}/{}", service, method);
+                    table.insert(
+                        "func_id".to_string(),
+                        Value::Integer(crc32fast::hash(path.as_bytes()) as i64),
+                    );
+                }
+            }
+            (None, Some(method)) => {
+                bail!(
+                    "rule {}: method {:?} is given without its service",
+                    rule,
+                    method
+                );
+            }
+            (None, None) => {}
+        }
+        Ok(table)
+    }
+}
+
+fn chained_addon(engine: &str, config: Table, tx_only: bool) -> ChainedAddon {
+    ChainedAddon {
+        addon_engine: engine.to_string(),
+        config_path: None,
+        config_string: Some(toml::to_string(&config).expect("a table is serializable")),
+        tx_only,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+        [[rules]]
+        match = { service = "rpc_hello.Greeter", method = "SayHello" }
+        action = "pass"
+
+        [[rules]]
+        match = { service = "rpc_hello.Greeter" }
+        action = "reject"
+
+        [[rules]]
+        action = { mirror = { dir = "/tmp/capture", snap_len = 64 } }
+
+        [[rules]]
+        action = { rate_limit = { requests_per_sec = 1000, bucket_size = 10 } }
+    "#;
+
+    fn config(addon: &ChainedAddon) -> Table {
+        toml::from_str(addon.config_string.as_ref().unwrap()).unwrap()
+    }
+
+    fn compile(policy: &str) -> anyhow::Result<Vec<ChainedAddon>> {
+        Policy::parse(policy)?.compile()
+    }
+
+    fn error(policy: &str) -> String {
+        compile(policy).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn chain_in_order() {
+        let chain = compile(POLICY).unwrap();
+        let engines: Vec<_> = chain.iter().map(|a| a.addon_engine.as_str()).collect();
+        assert_eq!(engines, [FILTER_ENGINE, RATE_LIMIT_ENGINE, CAPTURE_ENGINE]);
+        let tx_only: Vec<_> = chain.iter().map(|a| a.tx_only).collect();
+        assert_eq!(tx_only, [false, true, false]);
+        assert!(chain.iter().all(|a| a.config_path.is_none()));
+
+        let service_id = crc32fast::hash(b"rpc_hello.Greeter") as i64;
+        let func_id = crc32fast::hash(b"/rpc_hello.Greeter/SayHello") as i64;
+        let filter = config(&chain[0]);
+        let rules = filter["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0]["service_id"].as_integer(), Some(service_id));
+        assert_eq!(rules[0]["func_id"].as_integer(), Some(func_id));
+        assert_eq!(rules[0]["action"].as_str(), Some("pass"));
+        assert_eq!(rules[1]["service_id"].as_integer(), Some(service_id));
+        assert!(rules[1].get("func_id").is_none());
+        assert_eq!(rules[1]["action"].as_str(), Some("reject"));
+
+        let rate_limit = config(&chain[1]);
+        assert_eq!(rate_limit["requests_per_sec"].as_integer(), Some(1000));
+        assert_eq!(rate_limit["bucket_size"].as_integer(), Some(10));
+        assert!(rate_limit.get("service_id").is_none());
+
+        let mirror = config(&chain[2]);
+        assert_eq!(mirror["dir"].as_str(), Some("/tmp/capture"));
+        assert_eq!(mirror["snap_len"].as_integer(), Some(64));
+    }
+
+    #[test]
+    fn parse_placement() {
+        let policy = Policy::parse(
+            r#"
+            upstream = "Mrpc"
+            downstream = "RpcAdapter"
+            group = ["Mrpc", "RpcAdapter"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policy.upstream.as_deref(), Some("Mrpc"));
+        assert_eq!(policy.downstream.as_deref(), Some("RpcAdapter"));
+        assert_eq!(policy.group, ["Mrpc", "RpcAdapter"]);
+        assert!(policy.rules.is_empty());
+        assert!(Policy::parse("unknown = 1").is_err());
+        assert!(Policy::parse("[[rules]]\naction = \"drop\"").is_err());
+    }
+
+    #[test]
+    fn only_pass_rules() {
+        let policy = r#"
+            [[rules]]
+            action = "pass"
+
+            [[rules]]
+            action = { mirror = {} }
+        "#;
+        let chain = compile(policy).unwrap();
+        assert_eq!(chain.len(), 1);
+        assert_eq!(chain[0].addon_engine, CAPTURE_ENGINE);
+        let mirror = config(&chain[0]);
+        assert!(mirror.get("dir").is_none());
+        assert_eq!(mirror["snap_len"].as_integer(), Some(0));
+
+        let err = error("[[rules]]\naction = \"pass\"");
+        assert!(err.contains("no rules that change the datapath"), "{}", err);
+        assert!(error("").contains("no rules"));
+    }
+
+    #[test]
+    fn reject_unsupported() {
+        let err = error("[[rules]]\naction = { route = { to = \"v2\" } }");
+        assert!(err.starts_with("rule 0: routing"), "{}", err);
+
+        let err = error("[[rules]]\nmatch = { peer = \"10.0.0.1\" }\naction = \"reject\"");
+        assert!(err.starts_with("rule 0: matching on the peer"), "{}", err);
+
+        let err = error("[[rules]]\nmatch = { method = \"SayHello\" }\naction = \"reject\"");
+        assert!(err.contains("without its service"), "{}", err);
+
+        let policy = r#"
+            [[rules]]
+            action = { rate_limit = { requests_per_sec = 1, bucket_size = 1 } }
+
+            [[rules]]
+            action = { rate_limit = { requests_per_sec = 2, bucket_size = 2 } }
+        "#;
+        let err = error(policy);
+        assert!(err.starts_with("rule 1: only one rate_limit"), "{}", err);
+
+        let policy = r#"
+            [[rules]]
+            action = { mirror = {} }
+
+            [[rules]]
+            action = { mirror = {} }
+        "#;
+        let err = error(policy);
+        assert!(err.starts_with("rule 1: only one mirror"), "{}", err);
+    }
+}
@@ -0,0 +1,186 @@
+//! CPU time accounting of the engines and CPU caps of the subscriptions.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use minstant::Instant;
+
+lazy_static::lazy_static! {
+    static ref EPOCH: Instant = Instant::now();
+}
+
+#[inline]
+fn now_ns() -> u64 {
+    (Instant::now() - *EPOCH).as_nanos() as u64
+}
+
+/// The period over which the CPU cap of a subscription is enforced.
+const CAP_PERIOD: Duration = Duration::from_millis(100);
+
+/// Time-slicing accounting of an engine. Only its runtime updates it.
+#[derive(Debug, Default)]
+pub(crate) struct EngineStats {
+    /// Total time the runtime spent in resuming the engine, in nanoseconds.
+    cpu_time_ns: AtomicU64,
+    /// Number of times the engine is resumed.
+    resumes: AtomicU64,
+    /// Time spent in the resumes that made progress, in nanoseconds.
+    busy_ns: AtomicU64,
+    /// Number of times the engine is resumed right after the sender of a fused edge.
+    fused_resumes: AtomicU64,
+    /// Latency saved by the fused resumes that made progress and by the direct calls, in
+    /// nanoseconds.
+    fused_saved_ns: AtomicU64,
+    /// Number of messages the engine took from direct calls on fused edges.
+    direct_calls: AtomicU64,
+    /// When the first direct call since the last regular resume was made, 0 if none was.
+    direct_at_ns: AtomicU64,
+}
+
+impl EngineStats {
+    #[inline]
+    pub(crate) fn record(&self, elapsed: Duration) {
+        // single writer, no need for atomic read-modify-write
+        let cpu_time = self.cpu_time_ns.load(Ordering::Relaxed);
+        self.cpu_time_ns
+            .store(cpu_time + elapsed.as_nanos() as u64, Ordering::Relaxed);
+        let resumes = self.resumes.load(Ordering::Relaxed);
+        self.resumes.store(resumes + 1, Ordering::Relaxed);
+    }
+
+    /// Accounts a resume recorded by `record` that made progress.
+    #[inline]
+    pub(crate) fn record_busy(&self, elapsed: Duration) {
+        let busy = self.busy_ns.load(Ordering::Relaxed);
+        self.busy_ns
+            .store(busy + elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Accounts a fused resume, also recorded by `record`.
+    #[inline]
+    pub(crate) fn record_fused(&self) {
+        let fused = self.fused_resumes.load(Ordering::Relaxed);
+        self.fused_resumes.store(fused + 1, Ordering::Relaxed);
+    }
+
+    /// Accounts a message taken by a direct call, the latency saved is accounted at the next
+    /// regular resume by `settle_direct`.
+    #[inline]
+    pub(crate) fn record_direct(&self) {
+        let calls = self.direct_calls.load(Ordering::Relaxed);
+        self.direct_calls.store(calls + 1, Ordering::Relaxed);
+        if self.direct_at_ns.load(Ordering::Relaxed) == 0 {
+            self.direct_at_ns.store(now_ns().max(1), Ordering::Relaxed);
+        }
+    }
+
+    /// Called before a regular resume, accounts the time since the first direct call after the
+    /// previous one.
+    #[inline]
+    pub(crate) fn settle_direct(&self) {
+        let direct_at = self.direct_at_ns.load(Ordering::Relaxed);
+        if direct_at != 0 {
+            self.direct_at_ns.store(0, Ordering::Relaxed);
+            self.record_saved(Duration::from_nanos(now_ns().saturating_sub(direct_at)));
+        }
+    }
+
+    /// Accounts the time from a fused resume or a direct call to the next regular resume.
+    #[inline]
+    pub(crate) fn record_saved(&self, saved: Duration) {
+        let saved_ns = self.fused_saved_ns.load(Ordering::Relaxed);
+        self.fused_saved_ns
+            .store(saved_ns + saved.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn cpu_time_ns(&self) -> u64 {
+        self.cpu_time_ns.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn resumes(&self) -> u64 {
+        self.resumes.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn busy_ns(&self) -> u64 {
+        self.busy_ns.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn fused_resumes(&self) -> u64 {
+        self.fused_resumes.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn fused_saved_ns(&self) -> u64 {
+        self.fused_saved_ns.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn direct_calls(&self) -> u64 {
+        self.direct_calls.load(Ordering::Relaxed)
+    }
+}
+
+/// Caps the CPU time used by the engines of a subscription, shared by the runtimes running them.
+///
+/// The engines are not resumed once the subscription has used up its quota in the current period.
+#[derive(Debug, Default)]
+pub(crate) struct CpuCap {
+    /// CPU time allowed in each period in nanoseconds, 0 for no cap.
+    quota_ns: AtomicU64,
+    /// CPU time used in the current period.
+    used_ns: AtomicU64,
+    /// Start of the current period.
+    period_start_ns: AtomicU64,
+}
+
+impl CpuCap {
+    /// Sets the cap in number of cores, e.g., 0.5 for half a core. `None` removes the cap.
+    pub(crate) fn set_share(&self, share: Option<f64>) {
+        let quota = share.map_or(0, |share| {
+            ((CAP_PERIOD.as_nanos() as f64 * share) as u64).max(1)
+        });
+        self.quota_ns.store(quota, Ordering::Relaxed);
+    }
+
+    /// The cap in number of cores, `None` if not capped.
+    pub(crate) fn share(&self) -> Option<f64> {
+        match self.quota_ns.load(Ordering::Relaxed) {
+            0 => None,
+            quota => Some(quota as f64 / CAP_PERIOD.as_nanos() as f64),
+        }
+    }
+
+    /// Returns true if the subscription has used up its quota in the current period.
+    #[inline]
+    pub(crate) fn is_exhausted(&self) -> bool {
+        let quota = self.quota_ns.load(Ordering::Relaxed);
+        if quota == 0 {
+            return false;
+        }
+        let now = now_ns();
+        let start = self.period_start_ns.load(Ordering::Relaxed);
+        if now.saturating_sub(start) >= CAP_PERIOD.as_nanos() as u64 {
+            // the first runtime to notice starts a new period
+            if self
+                .period_start_ns
+                .compare_exchange(start, now, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.used_ns.store(0, Ordering::Relaxed);
+            }
+            return false;
+        }
+        self.used_ns.load(Ordering::Relaxed) >= quota
+    }
+
+    #[inline]
+    pub(crate) fn charge(&self, elapsed: Duration) {
+        if self.quota_ns.load(Ordering::Relaxed) != 0 {
+            self.used_ns
+                .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+}
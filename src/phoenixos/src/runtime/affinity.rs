@@ -33,6 +33,36 @@ impl CoreMask {
         self.0.sched_set_affinity_for_current_thread()
     }
 
+    /// The cores of the mask that are also in `cores`. If none is, all of `cores`.
+    pub(crate) fn restrict_to(&self, cores: &[u16]) -> Self {
+        use libnuma::masks::indices::CpuIndex;
+        use libnuma::masks::Mask;
+        let cpu_mask = CpuMask::allocate();
+        let mut empty = true;
+        for &i in cores {
+            if self.0.is_set(CpuIndex::new(i)) {
+                cpu_mask.set(CpuIndex::new(i));
+                empty = false;
+            }
+        }
+        if empty {
+            for &i in cores {
+                cpu_mask.set(CpuIndex::new(i));
+            }
+        }
+        CoreMask(cpu_mask)
+    }
+
+    /// The number of cores in the mask.
+    pub(crate) fn count(&self) -> usize {
+        use libnuma::masks::indices::CpuIndex;
+        use libnuma::masks::Mask;
+        let all_cpus = CpuIndex::number_of_permitted_cpus();
+        (0..all_cpus)
+            .filter(|&i| self.0.is_set(CpuIndex::new(i as u16)))
+            .count()
+    }
+
     #[allow(unused)]
     pub(crate) fn is_set(&self, i: u16) -> bool {
         use libnuma::masks::indices::CpuIndex;
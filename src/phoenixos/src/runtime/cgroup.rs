@@ -0,0 +1,86 @@
+//! Per-subscription cgroups (v2) for the runtimes.
+//!
+//! The daemon's own cgroup becomes the root of a threaded subtree. Each service subscription gets
+//! a threaded child cgroup, and the runtimes dedicated to the subscription move their threads
+//! into it, so the CPU time they use is attributed to the subscription and limited by `cpu.max`
+//! and `cpu.weight`. Engines on a runtime shared by several subscriptions (the `Compact` and
+//! `GroupShared` modes) stay in the daemon's cgroup.
+//!
+//! The memory controller does not support threaded cgroups, the memory of a subscription is
+//! limited by the `max_heap_size` of Salloc instead.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::Pid;
+
+use super::manager::SubscriptionId;
+use crate::config::CgroupConfig;
+use crate::log;
+
+pub(crate) struct CgroupManager {
+    /// The cgroup of the daemon.
+    base: PathBuf,
+    config: CgroupConfig,
+}
+
+impl CgroupManager {
+    pub(crate) fn new(config: &CgroupConfig) -> io::Result<Self> {
+        // cgroup v2 has a single line of "0::<path>"
+        let content = fs::read_to_string("/proc/self/cgroup")?;
+        let path = content
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cgroup v2 is not mounted"))?;
+        let base = config.mount.join(path.trim_start_matches('/'));
+        // cpu is a threaded controller, it can be enabled while the daemon is in this cgroup
+        fs::write(base.join("cgroup.subtree_control"), "+cpu")?;
+        Ok(CgroupManager {
+            base,
+            config: config.clone(),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn base(&self) -> &Path {
+        &self.base
+    }
+
+    fn path(&self, pid: Pid, sid: SubscriptionId) -> PathBuf {
+        self.base.join(format!("sub-{}-{}", pid, sid.0))
+    }
+
+    /// Creates the cgroup of a subscription if it does not exist yet and returns its path.
+    pub(crate) fn create(&self, pid: Pid, sid: SubscriptionId) -> io::Result<PathBuf> {
+        let path = self.path(pid, sid);
+        match fs::create_dir(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(path),
+            Err(e) => return Err(e),
+        }
+        fs::write(path.join("cgroup.type"), "threaded")?;
+        if let Some(cpu_max) = &self.config.cpu_max {
+            fs::write(path.join("cpu.max"), cpu_max)?;
+        }
+        if let Some(cpu_weight) = self.config.cpu_weight {
+            fs::write(path.join("cpu.weight"), cpu_weight.to_string())?;
+        }
+        Ok(path)
+    }
+
+    /// Removes the cgroup of a subscription. The runtimes must have left it.
+    pub(crate) fn remove(&self, pid: Pid, sid: SubscriptionId) {
+        let path = self.path(pid, sid);
+        if let Err(e) = fs::remove_dir(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("Failed to remove cgroup {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Moves the calling thread to the cgroup at `path`.
+pub(crate) fn join(path: &Path) -> io::Result<()> {
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+    fs::write(path.join("cgroup.threads"), tid.to_string())
+}
@@ -1,12 +1,25 @@
 use std::future::Future;
+use std::os::unix::io::RawFd;
 use std::os::unix::ucred::UCred;
 use std::pin::Pin;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures::future::BoxFuture;
+use minstant::Instant;
 use semver::Version;
 
+use phoenix_common::engine::datapath::node::Vertex;
 use phoenix_common::engine::{Engine, EngineResult, EngineType};
 
+use super::accounting::{CpuCap, EngineStats};
+use super::executor::QueueDepths;
+use super::fusion::{self, DirectTarget, FusedEdges};
+use super::supervisor::Supervisor;
+
+use crate::linker::LinkedModule;
+
 /// A container that bundles a `Box<dyn Engine>` and its `Future` object so that the caller of this
 /// type can use both the methods provided by the `Engine` trait and poll the future.
 pub(crate) struct EngineContainer {
@@ -37,6 +50,34 @@ pub(crate) struct EngineContainer {
 
     /// The verion of the phoenix module that the engine belongs to.
     version: Version,
+
+    /// CPU time accounting, read by the control plane.
+    stats: Arc<EngineStats>,
+
+    /// The CPU cap of the subscription the engine belongs to.
+    cpu_cap: Option<Arc<CpuCap>>,
+
+    /// The supervisor of the subscription the engine belongs to.
+    supervisor: Option<Arc<Supervisor>>,
+
+    /// Set when the engine has failed and is not to be resumed, until the given time if it is
+    /// to be restarted then.
+    failed: Option<Option<Instant>>,
+
+    /// When the engine was last resumed by a fused edge with some work done, cleared by its
+    /// next regular resume.
+    fused_at: Option<Instant>,
+
+    /// The fused edges, set while the senders of those to the engine may call it in place of
+    /// its input channels.
+    direct: Option<Arc<FusedEdges>>,
+
+    /// The library that implements the engine. Keeps the code and the vtable of the engine
+    /// mapped while the container is alive, so it must be dropped last.
+    ///
+    /// NOTE: `detach` releases it while the engine is still around. This is fine because the
+    /// `PluginManager` does not unload the library until the upgrade has completed.
+    _module: Option<LinkedModule>,
 }
 
 /// Extending the future's lifetime from 'a to 'static.
@@ -51,7 +92,12 @@ unsafe fn extend_lifetime<'a>(
 }
 
 impl EngineContainer {
-    pub(crate) fn new(engine: Box<dyn Engine>, ty: EngineType, version: Version) -> Self {
+    pub(crate) fn new(
+        engine: Box<dyn Engine>,
+        ty: EngineType,
+        version: Version,
+        module: Option<LinkedModule>,
+    ) -> Self {
         let mut pinned = Pin::new(engine);
         let future = {
             let fut = pinned.as_mut().activate();
@@ -68,6 +114,13 @@ impl EngineContainer {
             engine: pinned,
             version,
             ty,
+            stats: Arc::new(EngineStats::default()),
+            cpu_cap: None,
+            supervisor: None,
+            failed: None,
+            fused_at: None,
+            direct: None,
+            _module: module,
         }
     }
 
@@ -100,11 +153,121 @@ impl EngineContainer {
         self.engine.handle_request(request, cred)
     }
 
+    pub(crate) fn handle_query(&mut self, query: Vec<u8>, cred: UCred) -> anyhow::Result<Vec<u8>> {
+        self.engine.handle_query(query, cred)
+    }
+
+    /// Updates the configuration of the engine, and rolls it back if the engine rejects it.
+    /// Returns the replaced configuration.
+    pub(crate) fn update_config(&mut self, config: &str) -> anyhow::Result<String> {
+        let previous = self.engine.update_config(config)?;
+        if let Err(e) = self.engine.check_config() {
+            self.engine.update_config(&previous).map_err(|rollback| {
+                anyhow::anyhow!(
+                    "{} rejected the configuration ({}), and failed to roll it back: {}",
+                    self.engine.as_ref().description(),
+                    e,
+                    rollback
+                )
+            })?;
+            anyhow::bail!("configuration rejected and rolled back: {}", e);
+        }
+        Ok(previous)
+    }
+
+    #[inline]
+    pub(crate) fn stats(&self) -> &Arc<EngineStats> {
+        &self.stats
+    }
+
+    #[inline]
+    pub(crate) fn set_cpu_cap(&mut self, cpu_cap: Arc<CpuCap>) {
+        self.cpu_cap = Some(cpu_cap);
+    }
+
+    /// Returns true if the engine must not be resumed because its subscription has used up its
+    /// CPU quota.
+    #[inline]
+    pub(crate) fn is_throttled(&self) -> bool {
+        self.cpu_cap
+            .as_ref()
+            .map_or(false, |cap| cap.is_exhausted())
+    }
+
+    #[inline]
+    pub(crate) fn supervisor(&self) -> Option<&Arc<Supervisor>> {
+        self.supervisor.as_ref()
+    }
+
+    #[inline]
+    pub(crate) fn set_supervisor(&mut self, supervisor: Arc<Supervisor>) {
+        self.supervisor = Some(supervisor);
+    }
+
+    /// Returns true if the engine must shut down because its subscription is torn down.
+    #[inline]
+    pub(crate) fn is_torn_down(&self) -> bool {
+        self.supervisor
+            .as_ref()
+            .map_or(false, |supervisor| supervisor.is_torn_down())
+    }
+
+    /// Stops resuming the failed engine, until `restart_at` if it is to be restarted.
+    #[inline]
+    pub(crate) fn set_failed(&mut self, restart_at: Option<Instant>) {
+        self.failed = Some(restart_at);
+        if self.direct.is_some() {
+            self.clear_direct_inputs();
+        }
+    }
+
+    /// Returns true if the engine has failed and must not be resumed. A failed engine whose
+    /// restart is due is restarted instead.
+    #[inline]
+    pub(crate) fn is_failed(&mut self) -> bool {
+        match self.failed {
+            None => false,
+            Some(Some(restart_at)) if Instant::now() >= restart_at => {
+                self.restart();
+                false
+            }
+            Some(_) => true,
+        }
+    }
+
+    /// Activates the engine again, dropping the future that has failed.
+    fn restart(&mut self) {
+        // the old future borrows the engine, drop it before the new one does
+        self.future = Box::pin(futures::future::pending());
+        let fut = self.engine.as_mut().activate();
+        // SAFETY: see `new`.
+        self.future = unsafe { extend_lifetime(fut) };
+        self.failed = None;
+        if let Some(fused) = self.direct.take() {
+            self.set_direct_calls(fused);
+        }
+    }
+
+    /// Accounts a resume of the engine that took `elapsed`.
+    #[inline]
+    pub(crate) fn account(&self, elapsed: Duration) {
+        self.stats.record(elapsed);
+        if let Some(cap) = self.cpu_cap.as_ref() {
+            cap.charge(elapsed);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn doorbells(&self) -> Option<Vec<RawFd>> {
+        self.engine.doorbells()
+    }
+
     /// Detach current engine in prepare for upgrade
     /// Some preparatory work is done during this step
     /// e.g., flush inter-engine shared queues
     /// There is no need to call this function if only moves
-    pub(crate) fn detach(self) -> Box<dyn Engine> {
+    pub(crate) fn detach(mut self) -> Box<dyn Engine> {
+        self.clear_direct_calls();
         drop(self.future);
         let engine = self.engine;
         unsafe { Pin::into_inner_unchecked(engine) }
@@ -113,4 +276,81 @@ impl EngineContainer {
     pub(crate) fn flush(&mut self) -> anyhow::Result<usize> {
         self.engine.flush()
     }
+
+    /// Returns true if any input queue of the engine has a message.
+    pub(crate) fn has_input(&mut self) -> bool {
+        let engine = self.engine.as_mut().get_mut();
+        engine.tx_inputs().iter().any(|q| !q.is_empty())
+            || engine.rx_inputs().iter().any(|q| !q.is_empty())
+    }
+
+    /// Records a resume by a fused edge started at `start` that made progress.
+    #[inline]
+    pub(crate) fn mark_fused(&mut self, start: Instant) {
+        self.fused_at.get_or_insert(start);
+    }
+
+    /// Called before a regular resume, accounts the latency saved by the last fused resumes and
+    /// direct calls.
+    #[inline]
+    pub(crate) fn settle_fused(&mut self) {
+        if let Some(start) = self.fused_at.take() {
+            self.stats.record_saved(start.elapsed());
+        }
+        self.stats.settle_direct();
+    }
+
+    /// The address of the engine object, which identifies it while it is pinned.
+    #[inline]
+    pub(crate) fn addr(&self) -> *const () {
+        &*self.engine as *const dyn Engine as *const ()
+    }
+
+    /// Lets the senders of the fused edges to the engine call it in place of its input channels,
+    /// see `fusion`. Must be called on the runtime of the engine, and undone by
+    /// `clear_direct_calls` before the engine leaves it.
+    pub(crate) fn set_direct_calls(&mut self, fused: Arc<FusedEdges>) {
+        if !fused.is_receiver(self.ty) {
+            return;
+        }
+        let engine = self.engine.as_mut().get_mut();
+        let target = DirectTarget {
+            fused: Arc::clone(&fused),
+            ty: self.ty,
+            engine: NonNull::from(&mut *engine),
+            stats: Arc::clone(&self.stats),
+            cpu_cap: self.cpu_cap.clone(),
+            supervisor: self.supervisor.clone(),
+        };
+        // a channel whose call is held by another runtime keeps queueing
+        for (index, queue) in engine.tx_inputs().iter().enumerate() {
+            queue.set_direct(fusion::direct_tx(target.clone(), index));
+        }
+        for (index, queue) in engine.rx_inputs().iter().enumerate() {
+            queue.set_direct(fusion::direct_rx(target.clone(), index));
+        }
+        self.direct = Some(fused);
+    }
+
+    /// Undoes `set_direct_calls`, the messages to the engine are queued again.
+    pub(crate) fn clear_direct_calls(&mut self) {
+        if self.direct.take().is_some() {
+            self.clear_direct_inputs();
+        }
+    }
+
+    fn clear_direct_inputs(&mut self) {
+        let engine = self.engine.as_mut().get_mut();
+        engine.tx_inputs().iter().for_each(|q| q.clear_direct());
+        engine.rx_inputs().iter().for_each(|q| q.clear_direct());
+    }
+
+    /// Returns the number of messages currently in each input queue of the engine.
+    pub(crate) fn queue_depths(&mut self) -> QueueDepths {
+        let engine = self.engine.as_mut().get_mut();
+        QueueDepths {
+            tx_inputs: engine.tx_inputs().iter().map(|q| q.len()).collect(),
+            rx_inputs: engine.rx_inputs().iter().map(|q| q.len()).collect(),
+        }
+    }
 }
@@ -1,9 +1,12 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashSet;
 use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::ucred::UCred;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
-use std::sync::Weak;
+use std::sync::{mpsc, Arc, Weak};
 use std::task::{Context, Poll};
 use std::thread;
 use std::time::Duration;
@@ -13,11 +16,16 @@ use minstant::Instant;
 use spin::Mutex;
 use thiserror::Error;
 
+use phoenix_common::engine::timer;
 use phoenix_common::engine::EngineResult;
 
 use super::affinity::CoreMask;
+use super::cgroup;
+use super::fusion::{FusedEdges, Running};
 use super::group::GroupId;
+use super::idle::{IdleDetector, Waker};
 use super::manager::{EngineId, RuntimeId, RuntimeManager};
+use super::supervisor::Verdict;
 use super::{EngineContainer, SchedulingGroup};
 use crate::{log, tracing};
 
@@ -29,6 +37,8 @@ pub enum Error {
     #[allow(dead_code)]
     #[error("Fail to set thread affinity")]
     SetAffinity(io::Error),
+    #[error("Fail to install the sandbox: {0}")]
+    Sandbox(io::Error),
 }
 
 /// # Safety
@@ -49,17 +59,37 @@ pub(crate) enum SuspendResult {
     NotFound,
 }
 
+/// Depths of the datapath input queues of an engine, in the order of its `DataPathNode`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct QueueDepths {
+    pub(crate) tx_inputs: Vec<usize>,
+    pub(crate) rx_inputs: Vec<usize>,
+}
+
+/// A request the runtime thread serves between two rounds, when none of its engines is running.
+type Task = Box<dyn FnOnce(&Runtime) + Send>;
+
 enum RuntimeSubmission {
     NewGroup(SchedulingGroup),
     AttachToGroup(GroupId, Vec<(EngineId, EngineContainer)>),
+    /// Drop a scheduling group whose engines have all been suspended and moved elsewhere.
+    RemoveGroup(GroupId),
 }
 
+/// The shortest and longest sleeps of a shared runtime that has no work. The sleep is doubled for
+/// each round without work, bounded by the latency budget of the scheduling groups. The sleeps of
+/// a background runtime are only bounded by the latency budget.
+const MIN_BACKOFF: Duration = Duration::from_micros(1);
+const MAX_BACKOFF: Duration = Duration::from_micros(100);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub(crate) enum RuntimeMode {
     Compact = 1,
     Dedicated = 2,
     GroupShared = 3,
+    /// Shared by the scheduling groups of the background subscriptions, sleeps when idle.
+    Background = 4,
 }
 
 pub(crate) struct Runtime {
@@ -93,11 +123,113 @@ pub(crate) struct Runtime {
     pub(crate) new_ctrl_request: AtomicBool,
     pub(crate) control_requests: Mutex<Vec<(EngineId, Vec<u8>, UCred)>>,
 
+    pub(crate) new_task: AtomicBool,
+    /// Requests served by the runtime thread between two rounds
+    tasks: Mutex<Vec<Task>>,
+
+    /// Whether the runtime thread is in the cgroup of a subscription. Only touched by the
+    /// runtime thread.
+    in_cgroup: Cell<bool>,
+
     pub(crate) runtime_manager: Weak<RuntimeManager>,
+
+    /// Wakes up the runtime when it waits on the doorbells of its engines.
+    waker: Arc<Waker>,
+    idle_detector: Arc<IdleDetector>,
+    /// The data path edges whose receivers are resumed right after their senders.
+    fused: Arc<FusedEdges>,
+}
+
+/// The outcome of resuming an engine once.
+enum Resumed {
+    /// The engine did no work, or was throttled.
+    Idle,
+    Busy,
+    /// The engine completed, or failed and is to be shut down.
+    Done,
+}
+
+/// Resumes an engine and accounts the time it took.
+fn resume(eid: EngineId, engine: &mut EngineContainer, cx: &mut Context<'_>) -> Resumed {
+    if engine.is_torn_down() {
+        return Resumed::Done;
+    }
+    if engine.is_failed() || engine.is_throttled() {
+        return Resumed::Idle;
+    }
+
+    // Set engine's local storage here before poll
+    engine.engine_mut().set_els();
+
+    // bind to a variable first (otherwise engine is borrowed in the match expression)
+    // A panic in the engine only takes down the engine, not the whole runtime.
+    let resumed = Instant::now();
+    // the senders of the fused edges check what is running before they call a receiver
+    let running = Running::enter(engine.engine_type(), engine.addr());
+    let ret = panic::catch_unwind(AssertUnwindSafe(|| engine.future().poll(cx)));
+    drop(running);
+    let elapsed = resumed.elapsed();
+    engine.account(elapsed);
+    let Ok(ret) = ret else {
+        log::error!("Engine [{}] panicked", engine.engine().description());
+        return supervise(eid, engine, "panicked");
+    };
+    match ret {
+        Poll::Pending => {
+            let tracker = engine.engine_mut().tracker();
+            let nwork = tracker.nwork();
+            tracker.set_nwork(0);
+            if nwork > 0 {
+                engine.stats().record_busy(elapsed);
+                Resumed::Busy
+            } else {
+                Resumed::Idle
+            }
+        }
+        Poll::Ready(EngineResult::Ok(())) => {
+            log::info!(
+                "Engine [{}] completed, shutting down...",
+                engine.engine().description()
+            );
+            Resumed::Done
+        }
+        Poll::Ready(EngineResult::Err(e)) => {
+            log::error!("Engine [{}] error: {}", engine.engine().description(), e);
+            supervise(eid, engine, &e.to_string())
+        }
+    }
+}
+
+/// Hands a failed engine to the supervisor of its subscription.
+fn supervise(eid: EngineId, engine: &mut EngineContainer, error: &str) -> Resumed {
+    let verdict = match engine.supervisor() {
+        Some(supervisor) => supervisor.on_failure(eid, engine.engine_type(), error),
+        None => Verdict::Teardown,
+    };
+    match verdict {
+        Verdict::Restart(backoff) => {
+            engine.set_failed(Some(Instant::now() + backoff));
+            Resumed::Idle
+        }
+        Verdict::Teardown => Resumed::Done,
+        Verdict::KeepFailed => {
+            engine.set_failed(None);
+            Resumed::Idle
+        }
+    }
 }
 
 impl Runtime {
-    pub(crate) fn new(id: RuntimeId, cores: CoreMask, rm: Weak<RuntimeManager>) -> Self {
+    pub(crate) fn new(
+        id: RuntimeId,
+        cores: CoreMask,
+        rm: Weak<RuntimeManager>,
+        idle_detector: Arc<IdleDetector>,
+        fused: Arc<FusedEdges>,
+    ) -> Self {
+        let waker =
+            Arc::new(Waker::new().unwrap_or_else(|e| panic!("failed to create the waker: {}", e)));
+        idle_detector.register(Arc::clone(&waker));
         Runtime {
             id,
             cores,
@@ -117,7 +249,16 @@ impl Runtime {
             new_ctrl_request: AtomicBool::new(false),
             control_requests: Mutex::new(Vec::new()),
 
+            new_task: AtomicBool::new(false),
+            tasks: Mutex::new(Vec::new()),
+
+            in_cgroup: Cell::new(false),
+
             runtime_manager: rm,
+
+            waker,
+            idle_detector,
+            fused,
         }
     }
 
@@ -176,7 +317,7 @@ impl Runtime {
             } else {
                 match mode {
                     RuntimeMode::Dedicated => false,
-                    RuntimeMode::Compact => {
+                    RuntimeMode::Compact | RuntimeMode::Background => {
                         if let Some(quota) = quota {
                             quota > scheduled_groups
                         } else {
@@ -205,6 +346,7 @@ impl Runtime {
         let submission = RuntimeSubmission::NewGroup(group);
         self.pending.lock().push(submission);
         self.new_pending.store(true, Ordering::Release);
+        self.waker.wake();
     }
 
     /// Attach an engine to a existing scheduling roup
@@ -216,21 +358,132 @@ impl Runtime {
         let submission = RuntimeSubmission::AttachToGroup(gid, engines);
         self.pending.lock().push(submission);
         self.new_pending.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// The cores the runtime affinitizes to.
+    #[inline]
+    pub(crate) fn cores(&self) -> &CoreMask {
+        &self.cores
+    }
+
+    /// Whether the runtime is dedicated to a scheduling group, which takes a core of its own.
+    pub(crate) fn is_dedicated(&self) -> bool {
+        self.mode.load(Ordering::Relaxed) == RuntimeMode::Dedicated as u8
+            && self.active_cnt.load(Ordering::Relaxed) + self.pending.lock().len() > 0
+    }
+
+    /// Remove an emptied scheduling group, e.g., after its engines are migrated to another
+    /// runtime. A group that still has engines is kept.
+    pub(crate) fn remove_group(&self, gid: GroupId) {
+        let submission = RuntimeSubmission::RemoveGroup(gid);
+        self.pending.lock().push(submission);
+        self.new_pending.store(true, Ordering::Release);
+        self.waker.wake();
     }
 
     /// Submit a request to a specified engine
     pub(crate) fn submit_engine_request(&self, eid: EngineId, request: Vec<u8>, cred: UCred) {
         self.control_requests.lock().push((eid, request, cred));
         self.new_ctrl_request.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    fn submit_task(&self, task: Task) {
+        self.tasks.lock().push(task);
+        self.new_task.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Run `f` on an engine of this runtime between two rounds. The outcome is sent to the
+    /// returned receiver, `None` if the engine is not found in this runtime. The caller may stop
+    /// waiting for it at any time.
+    pub(crate) fn call_engine<R, F>(&self, eid: EngineId, f: F) -> mpsc::Receiver<Option<R>>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut EngineContainer) -> R + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.submit_task(Box::new(move |runtime: &Runtime| {
+            let _ = tx.send(runtime.with_engine(eid, f));
+        }));
+        rx
+    }
+
+    fn with_engine<R>(
+        &self,
+        eid: EngineId,
+        f: impl FnOnce(&mut EngineContainer) -> R,
+    ) -> Option<R> {
+        for group in self.running.borrow().iter() {
+            let mut group = group.borrow_mut();
+            if let Some((_, engine)) = group.engines.iter_mut().find(|(id, _)| *id == eid) {
+                return Some(f(engine));
+            }
+        }
+        None
     }
 
     pub(crate) fn request_suspend(&self, eid: EngineId) {
         self.suspend_requests.lock().push(eid);
         self.new_suspend.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Calls off a suspension the runtime has not taken yet. Returns false if it has taken it,
+    /// the engine is then suspended soon, or found shut down.
+    pub(crate) fn cancel_suspend(&self, eid: EngineId) -> bool {
+        let mut requests = self.suspend_requests.lock();
+        match requests.iter().position(|x| *x == eid) {
+            Some(index) => {
+                requests.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ask the runtime to move its thread to the cgroup at `path`.
+    pub(crate) fn request_cgroup(&self, path: PathBuf) {
+        self.submit_task(Box::new(move |runtime: &Runtime| {
+            match cgroup::join(&path) {
+                Ok(()) => runtime.in_cgroup.set(true),
+                Err(e) => log::warn!("Runtime {:?} failed to join {:?}: {}", runtime.id, path, e),
+            }
+        }));
+    }
+
+    /// The longest a compact or background runtime may sleep between two rounds without work,
+    /// the smallest latency budget of the scheduling groups. `None` if the runtime must keep
+    /// spinning.
+    fn latency_budget(&self) -> Option<Duration> {
+        let mode = self.mode.load(Ordering::Relaxed);
+        if mode != RuntimeMode::Compact as u8 && mode != RuntimeMode::Background as u8 {
+            return None;
+        }
+        // a group without a budget is smaller than any group with one
+        self.running
+            .borrow()
+            .iter()
+            .map(|group| group.borrow().latency_budget)
+            .min()
+            .flatten()
+    }
+
+    /// The fds to wait on in the idle mode, `None` if any engine cannot be woken up by its
+    /// doorbells.
+    fn doorbells(&self) -> Option<Vec<RawFd>> {
+        let mut fds = vec![self.waker.as_raw_fd()];
+        for group in self.running.borrow().iter() {
+            for (_eid, engine) in group.borrow().engines.iter() {
+                fds.extend(engine.doorbells()?);
+            }
+        }
+        Some(fds)
     }
 
     #[inline]
-    fn save_energy_or_shutdown(&self, last_event_ts: Instant) {
+    fn save_energy_or_shutdown(&self, last_event_ts: Instant, idle: &mut bool) {
         // THRES:DURA = 20:1 will lose around 10% bandwidth which is unacceptable,
         // 200:1 looks good so far.
 
@@ -245,10 +498,45 @@ impl Runtime {
 
         let dura = Instant::now() - last_event_ts;
 
+        if let Some(threshold) = self.idle_detector.threshold() {
+            if dura > threshold {
+                match self.doorbells() {
+                    Some(fds) => {
+                        if !*idle {
+                            *idle = true;
+                            self.idle_detector.enter_idle();
+                        }
+                        // wait on the doorbells only when the whole daemon is idle
+                        if self.idle_detector.all_idle() {
+                            tracing::trace!("Runtime {:?} is waiting on doorbells", self.id);
+                            self.idle_detector.park(&fds, timer::next_expiry());
+                            return;
+                        }
+                    }
+                    None if *idle => {
+                        *idle = false;
+                        self.idle_detector.leave_idle();
+                    }
+                    None => {}
+                }
+            }
+        }
+
         // park the engine only then it's empty
         if dura > SHUTDOWN_THRESHOLD && self.is_empty() {
+            // a parked runtime must not keep the others out of the idle mode
+            if self.idle_detector.threshold().is_some() && !*idle {
+                *idle = true;
+                self.idle_detector.enter_idle();
+            }
             tracing::trace!("Runtime {:?} is shutting down", self.id);
-            thread::park();
+            // the engines moved away may still have timers on this runtime
+            match timer::next_expiry() {
+                Some(deadline) => thread::park_timeout(
+                    deadline.saturating_duration_since(std::time::Instant::now()),
+                ),
+                None => thread::park(),
+            }
             tracing::trace!("Runtime {:?} is restarted", self.id);
         } else if dura > DEEP_SLEEP_THRESHOLD {
             tracing::trace!("Runtime {:?} is going to deep sleep", self.id);
@@ -261,6 +549,60 @@ impl Runtime {
         }
     }
 
+    /// Lets the senders of the fused edges on this runtime call the engines in place of their
+    /// input channels, see `fusion`.
+    fn set_direct_calls(&self, engines: &mut [(EngineId, EngineContainer)]) {
+        if self.fused.is_empty() {
+            return;
+        }
+        for (_eid, engine) in engines.iter_mut() {
+            engine.set_direct_calls(Arc::clone(&self.fused));
+        }
+    }
+
+    /// Resumes the engines fused to the engine at `sender` that have messages, so that they
+    /// take what it has just sent in this pass, then the engines fused to those that made
+    /// progress, and so on. Each engine is resumed at most once.
+    fn resume_fused(
+        &self,
+        engines: &mut [(EngineId, EngineContainer)],
+        group_index: usize,
+        sender: usize,
+        cx: &mut Context<'_>,
+        shutdown: &mut Vec<(usize, usize)>,
+    ) {
+        let mut visited = vec![sender];
+        let mut senders = vec![sender];
+        while let Some(sender) = senders.pop() {
+            let sender_type = engines[sender].1.engine_type();
+            for receiver in 0..engines.len() {
+                if visited.contains(&receiver)
+                    || shutdown.contains(&(group_index, receiver))
+                    || !self
+                        .fused
+                        .is_fused(sender_type, engines[receiver].1.engine_type())
+                {
+                    continue;
+                }
+                let (eid, engine) = &mut engines[receiver];
+                if !engine.has_input() {
+                    continue;
+                }
+                visited.push(receiver);
+                engine.stats().record_fused();
+                let start = Instant::now();
+                match resume(*eid, engine, cx) {
+                    Resumed::Idle => {}
+                    Resumed::Busy => {
+                        engine.mark_fused(start);
+                        senders.push(receiver);
+                    }
+                    Resumed::Done => shutdown.push((group_index, receiver)),
+                }
+            }
+        }
+    }
+
     /// A spinning future executor.
     pub(crate) fn mainloop(&self) -> Result<(), Error> {
         let waker = futures::task::noop_waker();
@@ -269,47 +611,85 @@ impl Runtime {
         let mut shutdown = Vec::new();
 
         let mut last_event_ts = Instant::now();
+        // whether this runtime is counted as idle by the idle detector
+        let mut idle = false;
+        // updated whenever the scheduling groups change
+        let mut latency_budget = None;
+        let mut backoff = Duration::ZERO;
+
+        // the timers armed by the engines on this runtime
+        timer::install_wheel();
 
         loop {
             // TODO(cjr): if there's no active engine on this runtime, call `mwait` to put the CPU
             // into an optimized state. (the wakeup latency and whether it can be used in user mode
             // are two concerns)
-            self.save_energy_or_shutdown(last_event_ts);
+            self.save_energy_or_shutdown(last_event_ts, &mut idle);
+
+            // the engines take their expired timers when they are resumed
+            let nexpired = timer::advance_wheel(std::time::Instant::now());
+            if nexpired > 0 {
+                tracing::trace!("Runtime {:?}: {} timers expired", self.id, nexpired);
+            }
+
+            let mut has_work = false;
 
             // drive each engine
             for (group_index, group) in self.running.borrow().iter().enumerate() {
                 let mut group = group.borrow_mut();
+                let engines = &mut group.engines;
 
-                for (engine_index, (_eid, engine)) in group.engines.iter_mut().enumerate() {
-                    // Set engine's local storage here before poll
-                    engine.engine_mut().set_els();
-
-                    // bind to a variable first (otherwise engine is borrowed in the match expression)
-                    let ret = engine.future().poll(&mut cx);
-                    match ret {
-                        Poll::Pending => {
-                            let tracker = engine.engine_mut().tracker();
-                            // has_work += tracker.nwork();
-                            if tracker.nwork() > 0 {
-                                last_event_ts = Instant::now();
+                for engine_index in 0..engines.len() {
+                    // the engine may have been done in a fused resume of this pass
+                    if shutdown.contains(&(group_index, engine_index)) {
+                        continue;
+                    }
+                    let (eid, engine) = &mut engines[engine_index];
+                    engine.settle_fused();
+                    match resume(*eid, engine, &mut cx) {
+                        Resumed::Idle => {}
+                        Resumed::Busy => {
+                            has_work = true;
+                            last_event_ts = Instant::now();
+                            if !self.fused.is_empty() {
+                                self.resume_fused(
+                                    engines,
+                                    group_index,
+                                    engine_index,
+                                    &mut cx,
+                                    &mut shutdown,
+                                );
                             }
-                            tracker.set_nwork(0);
-                        }
-                        Poll::Ready(EngineResult::Ok(())) => {
-                            log::info!(
-                                "Engine [{}] completed, shutting down...",
-                                engine.engine().description()
-                            );
-                            shutdown.push((group_index, engine_index));
-                        }
-                        Poll::Ready(EngineResult::Err(e)) => {
-                            log::error!("Engine [{}] error: {}", engine.engine().description(), e);
-                            shutdown.push((group_index, engine_index));
                         }
+                        Resumed::Done => shutdown.push((group_index, engine_index)),
                     }
                 }
             }
 
+            if has_work && idle {
+                idle = false;
+                self.idle_detector.leave_idle();
+            }
+
+            if let Some(budget) = latency_budget {
+                if has_work {
+                    backoff = Duration::ZERO;
+                } else {
+                    let max_backoff =
+                        if self.mode.load(Ordering::Relaxed) == RuntimeMode::Background as u8 {
+                            budget
+                        } else {
+                            budget.min(MAX_BACKOFF)
+                        };
+                    backoff = (backoff * 2).max(MIN_BACKOFF).min(max_backoff);
+                    thread::park_timeout(backoff);
+                }
+            }
+
+            let groups_removed = !shutdown.is_empty();
+            // the fused resumes may have added them out of order
+            shutdown.sort_unstable();
+
             // garbage collect every several rounds, maybe move to another thread.
             for (group_index, engine_index) in shutdown.drain(..).rev() {
                 let mut running = self.running.borrow_mut();
@@ -320,13 +700,30 @@ impl Runtime {
                 let desc = engine.engine().description().to_owned();
                 // TODO: also remember to set els before dropping an engine
                 engine.engine_mut().set_els();
-                drop(engine);
-                log::info!("Engine [{}] shutdown successfully", desc);
+                if panic::catch_unwind(AssertUnwindSafe(|| drop(engine))).is_err() {
+                    log::error!("Engine [{}] panicked during shutdown", desc);
+                } else {
+                    log::info!("Engine [{}] shutdown successfully", desc);
+                }
                 if running[group_index].borrow_mut().engines.is_empty() {
                     // All engines in the scheduling group has shutdown
                     // NOTE(wyj): Relaxed ordering should be fine
                     self.active_cnt.fetch_sub(1, Ordering::Relaxed);
                     running.swap_remove(group_index);
+                    // leave the cgroup so that it can be removed with the subscription
+                    if self.in_cgroup.get() && running.is_empty() {
+                        let rm = self.runtime_manager.upgrade().unwrap();
+                        if let Some(cgroups) = rm.cgroups.as_ref() {
+                            if let Err(e) = cgroup::join(cgroups.base()) {
+                                log::warn!(
+                                    "Runtime {:?} failed to leave its cgroup: {}",
+                                    self.id,
+                                    e
+                                );
+                            }
+                        }
+                        self.in_cgroup.set(false);
+                    }
                 }
                 // This should be fine because runtime will be dropped later than RuntimeManager.
                 self.runtime_manager
@@ -334,6 +731,9 @@ impl Runtime {
                     .unwrap()
                     .register_engine_shutdown(eid);
             }
+            if groups_removed {
+                latency_budget = self.latency_budget();
+            }
 
             // move newly added runtime to the scheduling queue
             if Ok(true)
@@ -347,12 +747,14 @@ impl Runtime {
                 let mut running = self.running.borrow_mut();
                 for submission in self.pending.lock().drain(..) {
                     match submission {
-                        RuntimeSubmission::NewGroup(group) => {
+                        RuntimeSubmission::NewGroup(mut group) => {
+                            self.set_direct_calls(&mut group.engines);
                             // NOTE(wyj): Relaxed ordering should be fine
                             self.active_cnt.fetch_add(1, Ordering::Relaxed);
                             running.push(RefCell::new(group));
                         }
-                        RuntimeSubmission::AttachToGroup(group_id, engines) => {
+                        RuntimeSubmission::AttachToGroup(group_id, mut engines) => {
+                            self.set_direct_calls(&mut engines);
                             match running.iter_mut().find(|x| x.borrow().id == group_id) {
                                 Some(group) => {
                                     group.borrow_mut().engines.extend(engines);
@@ -360,12 +762,24 @@ impl Runtime {
                                 None => {
                                     // handle the case that all engines within the group already shutdowns
                                     let group = SchedulingGroup::new(group_id, engines);
+                                    self.active_cnt.fetch_add(1, Ordering::Relaxed);
                                     running.push(RefCell::new(group));
                                 }
                             }
                         }
+                        RuntimeSubmission::RemoveGroup(group_id) => {
+                            if let Some(index) = running.iter().position(|x| {
+                                let group = x.borrow();
+                                group.id == group_id && group.engines.is_empty()
+                            }) {
+                                self.active_cnt.fetch_sub(1, Ordering::Relaxed);
+                                running.swap_remove(index);
+                            }
+                        }
                     }
                 }
+                drop(running);
+                latency_budget = self.latency_budget();
             }
 
             if Ok(true)
@@ -391,6 +805,8 @@ impl Runtime {
                     engines.extend(group_engines_suspend);
                 }
                 for (engine_id, mut engine) in engines {
+                    // the engine may move to another runtime
+                    engine.clear_direct_calls();
                     if let Err(err) = engine.engine_mut().pre_detach() {
                         tracing::error!(
                             "Failed to detach engine {:?} from runtime, error={:?}",
@@ -443,6 +859,17 @@ impl Runtime {
                 }
             }
 
+            if Ok(true)
+                == self
+                    .new_task
+                    .compare_exchange(true, false, Ordering::Acquire, Ordering::Relaxed)
+            {
+                let tasks = self.tasks.lock().drain(..).collect::<Vec<_>>();
+                for task in tasks {
+                    task(self);
+                }
+            }
+
             // loop scope ends here
         }
     }
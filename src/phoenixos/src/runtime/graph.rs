@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use petgraph::graph::NodeIndex;
 use petgraph::visit::{Topo, Walker};
@@ -22,6 +22,10 @@ pub enum Error {
     InvalidReplacement(ChannelDescriptor),
     #[error("Addon engine {0:?} not found")]
     AddonNotFound(EngineType),
+    #[error("Addon engine {0:?} already attached")]
+    AddonAttached(EngineType),
+    #[error("No single channel from {0:?} to {1:?} to insert addons into")]
+    ChannelNotUnique(EngineType, EngineType),
     #[error("Channel to be replaced is not empty, receiver_engine={0:?}, endpoint=({1:?}, {2})")]
     ChannelNotEmpty(EngineType, EndpointType, usize),
     #[error("Engine {0:?}'s channels ({1:?}) and the graph descriptor mismatched")]
@@ -145,6 +149,207 @@ impl DataPathGraph {
 
         topo_order
     }
+
+    /// Checks that the channel replacements of attaching `addon` fit the graph, without
+    /// changing it.
+    pub(crate) fn check_attach_addon(
+        &self,
+        addon: EngineType,
+        tx_edges_replacement: &[ChannelDescriptor],
+        rx_edges_replacement: &[ChannelDescriptor],
+    ) -> Result<(), Error> {
+        if self.tx_inputs.contains_key(&addon) {
+            return Err(Error::AddonAttached(addon));
+        }
+        check_attach_edges(
+            &self.tx_inputs,
+            &self.tx_outputs,
+            addon,
+            tx_edges_replacement,
+        )?;
+        check_attach_edges(
+            &self.rx_inputs,
+            &self.rx_outputs,
+            addon,
+            rx_edges_replacement,
+        )
+    }
+
+    /// Checks that the channel replacements of detaching `addon` fit the graph, without
+    /// changing it.
+    pub(crate) fn check_detach_addon(
+        &self,
+        addon: EngineType,
+        tx_edges_replacement: &[ChannelDescriptor],
+        rx_edges_replacement: &[ChannelDescriptor],
+    ) -> Result<(), Error> {
+        if !self.tx_inputs.contains_key(&addon) {
+            return Err(Error::AddonNotFound(addon));
+        }
+        check_detach_edges(
+            &self.tx_inputs,
+            &self.tx_outputs,
+            addon,
+            tx_edges_replacement,
+        )?;
+        check_detach_edges(
+            &self.rx_inputs,
+            &self.rx_outputs,
+            addon,
+            rx_edges_replacement,
+        )
+    }
+
+    /// The channel replacements that insert `addons`, in order, on the tx channel from
+    /// `upstream` to `downstream` and on the rx channel back, if any, except for the tx-only
+    /// addons. Each addon is inserted between the previous ones and `downstream`, so the
+    /// replacements must be applied in order.
+    /// * addons: each addon, and whether it is tx-only
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn chain_replacements(
+        &self,
+        upstream: EngineType,
+        downstream: EngineType,
+        addons: &[(EngineType, bool)],
+    ) -> Result<Vec<(Vec<ChannelDescriptor>, Vec<ChannelDescriptor>)>, Error> {
+        let (tx_output, tx_input) = find_channel(&self.tx_outputs, upstream, downstream)?
+            .ok_or(Error::ChannelNotUnique(upstream, downstream))?;
+        let rx = find_channel(&self.rx_outputs, downstream, upstream)?;
+
+        let mut seen = HashSet::with_capacity(addons.len());
+        let mut replacements = Vec::with_capacity(addons.len());
+        // the engines before the next addon, with their tx output and rx input to replace
+        let mut prev_tx = (upstream, tx_output);
+        let mut prev_rx = (upstream, rx.map_or(0, |(_, rx_input)| rx_input));
+        for &(addon, tx_only) in addons {
+            if self.tx_inputs.contains_key(&addon) || !seen.insert(addon) {
+                return Err(Error::AddonAttached(addon));
+            }
+            let tx_edges = vec![
+                ChannelDescriptor(prev_tx.0, addon, prev_tx.1, 0),
+                ChannelDescriptor(addon, downstream, 0, tx_input),
+            ];
+            prev_tx = (addon, 0);
+            let rx_edges = match rx {
+                Some((rx_output, _)) if !tx_only => {
+                    let edges = vec![
+                        ChannelDescriptor(downstream, addon, rx_output, 0),
+                        ChannelDescriptor(addon, prev_rx.0, 0, prev_rx.1),
+                    ];
+                    prev_rx = (addon, 0);
+                    edges
+                }
+                _ => Vec::new(),
+            };
+            replacements.push((tx_edges, rx_edges));
+        }
+        Ok(replacements)
+    }
+}
+
+// the indices in the outputs of `sender` and in the inputs of `receiver` of the channel between
+// them, if any
+fn find_channel(
+    outputs: &HashMap<EngineType, Vec<(EngineType, usize)>>,
+    sender: EngineType,
+    receiver: EngineType,
+) -> Result<Option<(usize, usize)>, Error> {
+    let mut channels = outputs
+        .get(&sender)
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, (engine, _))| *engine == receiver)
+        .map(|(output, (_, input))| (output, *input));
+    match (channels.next(), channels.next()) {
+        (channel, None) => Ok(channel),
+        (_, Some(_)) => Err(Error::ChannelNotUnique(sender, receiver)),
+    }
+}
+
+// the checks of `refactor_channels_attach_addon` on the edges of one direction
+fn check_attach_edges(
+    inputs: &HashMap<EngineType, Vec<(EngineType, usize)>>,
+    outputs: &HashMap<EngineType, Vec<(EngineType, usize)>>,
+    addon: EngineType,
+    edges: &[ChannelDescriptor],
+) -> Result<(), Error> {
+    let mut senders_await_replace = HashSet::new();
+    let mut receivers_await_replace = HashSet::new();
+    let mut addon_inputs = Vec::new();
+    let mut addon_outputs = Vec::new();
+    for edge in edges.iter().copied() {
+        if edge.0 == addon {
+            let receiver_inputs = inputs.get(&edge.1).ok_or(Error::InvalidReplacement(edge))?;
+            if edge.3 >= receiver_inputs.len() {
+                return Err(Error::InvalidReplacement(edge));
+            }
+            if !receivers_await_replace.remove(&(edge.1, edge.3)) {
+                senders_await_replace.insert(receiver_inputs[edge.3]);
+            }
+            addon_outputs.push(edge.2);
+        } else if edge.1 == addon {
+            let sender_outputs = outputs
+                .get(&edge.0)
+                .ok_or(Error::InvalidReplacement(edge))?;
+            if edge.2 >= sender_outputs.len() {
+                return Err(Error::InvalidReplacement(edge));
+            }
+            if !senders_await_replace.remove(&(edge.0, edge.2)) {
+                receivers_await_replace.insert(sender_outputs[edge.2]);
+            }
+            addon_inputs.push(edge.3);
+        } else {
+            return Err(Error::InvalidReplacement(edge));
+        }
+    }
+    if !senders_await_replace.is_empty() || !receivers_await_replace.is_empty() {
+        return Err(Error::DanglingEndpoint);
+    }
+    // the endpoints of the addon are indexed from 0 without holes
+    for mut indices in [addon_inputs, addon_outputs] {
+        indices.sort_unstable();
+        let hole = indices
+            .iter()
+            .enumerate()
+            .find_map(|(i, &index)| (i != index).then_some(index));
+        if let Some(index) = hole {
+            return Err(Error::IndexNotContiguous(index));
+        }
+    }
+    Ok(())
+}
+
+// the checks of `refactor_channels_detach_addon` on the edges of one direction
+fn check_detach_edges(
+    inputs: &HashMap<EngineType, Vec<(EngineType, usize)>>,
+    outputs: &HashMap<EngineType, Vec<(EngineType, usize)>>,
+    addon: EngineType,
+    edges: &[ChannelDescriptor],
+) -> Result<(), Error> {
+    let mut inputs_await_replace = (0..inputs[&addon].len()).collect::<HashSet<_>>();
+    let mut outputs_await_replace = (0..outputs[&addon].len()).collect::<HashSet<_>>();
+    for edge in edges.iter().copied() {
+        if edge.0 == addon || edge.1 == addon {
+            return Err(Error::InvalidReplacement(edge));
+        }
+        let sender_outputs = outputs
+            .get(&edge.0)
+            .ok_or(Error::InvalidReplacement(edge))?;
+        if edge.2 >= sender_outputs.len() || sender_outputs[edge.2].0 != addon {
+            return Err(Error::InvalidReplacement(edge));
+        }
+        inputs_await_replace.remove(&sender_outputs[edge.2].1);
+        let receiver_inputs = inputs.get(&edge.1).ok_or(Error::InvalidReplacement(edge))?;
+        if edge.3 >= receiver_inputs.len() || receiver_inputs[edge.3].0 != addon {
+            return Err(Error::InvalidReplacement(edge));
+        }
+        outputs_await_replace.remove(&receiver_inputs[edge.3].1);
+    }
+    if !inputs_await_replace.is_empty() || !outputs_await_replace.is_empty() {
+        return Err(Error::DanglingEndpoint);
+    }
+    Ok(())
 }
 
 // create a set of `DataPathNode`s for a service engine group
@@ -299,6 +504,7 @@ impl EndpointCollection {
             tx_outputs: tx_senders,
             rx_inputs: rx_receivers,
             rx_outputs: rx_senders,
+            ports: Default::default(),
         };
 
         let endpoint_info = [
@@ -311,3 +517,189 @@ impl EndpointCollection {
         Ok((node, endpoint_info))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MRPC: EngineType = EngineType("MrpcEngine");
+    const ADAPTER: EngineType = EngineType("RpcAdapterEngine");
+    const A: EngineType = EngineType("AddonA");
+    const B: EngineType = EngineType("AddonB");
+
+    fn edge(sender: EngineType, receiver: EngineType) -> ChannelDescriptor {
+        ChannelDescriptor(sender, receiver, 0, 0)
+    }
+
+    fn build_graph(
+        tx_edges: Vec<ChannelDescriptor>,
+        rx_edges: Vec<ChannelDescriptor>,
+    ) -> DataPathGraph {
+        let groups = GroupUnionFind::new(Vec::new());
+        create_datapath_channels(tx_edges, rx_edges, &groups)
+            .unwrap()
+            .1
+    }
+
+    /// MrpcEngine and RpcAdapterEngine, with a channel each way.
+    fn pair() -> DataPathGraph {
+        build_graph(vec![edge(MRPC, ADAPTER)], vec![edge(ADAPTER, MRPC)])
+    }
+
+    #[test]
+    fn check_attach() {
+        let graph = pair();
+        let tx = [edge(MRPC, A), edge(A, ADAPTER)];
+        let rx = [edge(ADAPTER, A), edge(A, MRPC)];
+        graph.check_attach_addon(A, &tx, &rx).unwrap();
+        // tx only
+        graph.check_attach_addon(A, &tx, &[]).unwrap();
+
+        // an engine of the graph
+        assert!(matches!(
+            graph.check_attach_addon(MRPC, &tx, &rx),
+            Err(Error::AddonAttached(MRPC))
+        ));
+        // a channel left with one end
+        assert!(matches!(
+            graph.check_attach_addon(A, &tx[..1], &rx),
+            Err(Error::DanglingEndpoint)
+        ));
+        // an edge that does not involve the addon
+        assert!(matches!(
+            graph.check_attach_addon(A, &[edge(MRPC, ADAPTER)], &rx),
+            Err(Error::InvalidReplacement(_))
+        ));
+        // an endpoint out of the engine
+        let out_of_range = [ChannelDescriptor(MRPC, A, 1, 0), edge(A, ADAPTER)];
+        assert!(matches!(
+            graph.check_attach_addon(A, &out_of_range, &rx),
+            Err(Error::InvalidReplacement(_))
+        ));
+        // a hole in the endpoints of the addon
+        let hole = [ChannelDescriptor(MRPC, A, 0, 1), edge(A, ADAPTER)];
+        assert!(matches!(
+            graph.check_attach_addon(A, &hole, &rx),
+            Err(Error::IndexNotContiguous(1))
+        ));
+        // the check leaves the graph alone
+        assert_eq!(graph.tx_outputs[&MRPC], [(ADAPTER, 0)]);
+        assert!(!graph.tx_inputs.contains_key(&A));
+    }
+
+    #[test]
+    fn check_detach() {
+        let graph = build_graph(
+            vec![edge(MRPC, A), edge(A, ADAPTER)],
+            vec![edge(ADAPTER, A), edge(A, MRPC)],
+        );
+        let tx = [edge(MRPC, ADAPTER)];
+        let rx = [edge(ADAPTER, MRPC)];
+        graph.check_detach_addon(A, &tx, &rx).unwrap();
+
+        assert!(matches!(
+            graph.check_detach_addon(B, &tx, &rx),
+            Err(Error::AddonNotFound(B))
+        ));
+        // the rx channels of the addon are left
+        assert!(matches!(
+            graph.check_detach_addon(A, &tx, &[]),
+            Err(Error::DanglingEndpoint)
+        ));
+        // a replacement that keeps the addon
+        assert!(matches!(
+            graph.check_detach_addon(A, &[edge(MRPC, A)], &rx),
+            Err(Error::InvalidReplacement(_))
+        ));
+        // a channel that is not to the addon
+        assert!(matches!(
+            graph.check_detach_addon(A, &tx, &[edge(MRPC, ADAPTER)]),
+            Err(Error::InvalidReplacement(_))
+        ));
+        assert_eq!(graph.tx_outputs[&MRPC], [(A, 0)]);
+    }
+
+    /// Applies the replacements of attaching `addon` to the edges of one direction.
+    fn attach(
+        edges: &mut Vec<ChannelDescriptor>,
+        addon: EngineType,
+        replacement: &[ChannelDescriptor],
+    ) {
+        for new in replacement {
+            if new.0 == addon {
+                edges.retain(|e| !(e.1 == new.1 && e.3 == new.3));
+            } else {
+                edges.retain(|e| !(e.0 == new.0 && e.2 == new.2));
+            }
+        }
+        edges.extend_from_slice(replacement);
+    }
+
+    #[test]
+    fn chain() {
+        const C: EngineType = EngineType("AddonC");
+        let addons = [(A, false), (B, true), (C, false)];
+        let replacements = pair().chain_replacements(MRPC, ADAPTER, &addons).unwrap();
+        assert_eq!(replacements.len(), 3);
+        // each addon goes right before the downstream engine, the tx-only one only on tx
+        assert_eq!(replacements[1].0, [edge(A, B), edge(B, ADAPTER)]);
+        assert!(replacements[1].1.is_empty());
+        assert_eq!(replacements[2].1, [edge(ADAPTER, C), edge(C, A)]);
+
+        // applied in order, each replacement fits the graph left by the previous ones
+        let mut tx_edges = vec![edge(MRPC, ADAPTER)];
+        let mut rx_edges = vec![edge(ADAPTER, MRPC)];
+        for (&(addon, _), (tx, rx)) in addons.iter().zip(&replacements) {
+            let graph = build_graph(tx_edges.clone(), rx_edges.clone());
+            graph.check_attach_addon(addon, tx, rx).unwrap();
+            attach(&mut tx_edges, addon, tx);
+            attach(&mut rx_edges, addon, rx);
+        }
+        let graph = build_graph(tx_edges, rx_edges);
+        assert_eq!(graph.tx_outputs[&MRPC], [(A, 0)]);
+        assert_eq!(graph.tx_outputs[&A], [(B, 0)]);
+        assert_eq!(graph.tx_outputs[&B], [(C, 0)]);
+        assert_eq!(graph.tx_outputs[&C], [(ADAPTER, 0)]);
+        assert_eq!(graph.rx_outputs[&ADAPTER], [(C, 0)]);
+        assert_eq!(graph.rx_outputs[&C], [(A, 0)]);
+        assert_eq!(graph.rx_outputs[&A], [(MRPC, 0)]);
+        assert!(graph.rx_outputs[&B].is_empty());
+    }
+
+    #[test]
+    fn chain_without_rx() {
+        let graph = build_graph(vec![edge(MRPC, ADAPTER)], Vec::new());
+        let replacements = graph
+            .chain_replacements(MRPC, ADAPTER, &[(A, false)])
+            .unwrap();
+        assert_eq!(replacements[0].0, [edge(MRPC, A), edge(A, ADAPTER)]);
+        assert!(replacements[0].1.is_empty());
+    }
+
+    #[test]
+    fn chain_rejected() {
+        let graph = pair();
+        assert!(matches!(
+            graph.chain_replacements(MRPC, ADAPTER, &[(A, false), (A, true)]),
+            Err(Error::AddonAttached(A))
+        ));
+        assert!(matches!(
+            graph.chain_replacements(MRPC, ADAPTER, &[(MRPC, false)]),
+            Err(Error::AddonAttached(MRPC))
+        ));
+        // no channel that way
+        assert!(matches!(
+            graph.chain_replacements(ADAPTER, MRPC, &[(A, false)]),
+            Err(Error::ChannelNotUnique(ADAPTER, MRPC))
+        ));
+        // two channels, the addons would not know which one
+        let graph = build_graph(
+            vec![edge(MRPC, ADAPTER), ChannelDescriptor(MRPC, ADAPTER, 1, 1)],
+            Vec::new(),
+        );
+        assert!(matches!(
+            graph.chain_replacements(MRPC, ADAPTER, &[(A, false)]),
+            Err(Error::ChannelNotUnique(MRPC, ADAPTER))
+        ));
+    }
+}
@@ -2,6 +2,7 @@
 //! share the same scheduling policy.
 use std::collections::HashMap;
 use std::fmt;
+use std::time::Duration;
 
 use petgraph::unionfind::UnionFind;
 
@@ -19,12 +20,18 @@ pub(crate) struct SchedulingGroup {
     pub(crate) id: GroupId,
     /// The engines in this group.
     pub(crate) engines: Vec<(EngineId, EngineContainer)>,
+    /// How long the runtime may sleep when the group has no work, `None` to keep spinning.
+    pub(crate) latency_budget: Option<Duration>,
 }
 
 impl SchedulingGroup {
     /// Create a new scheduling group from a list of engines
     pub(crate) fn new(gid: GroupId, engines: Vec<(EngineId, EngineContainer)>) -> Self {
-        SchedulingGroup { id: gid, engines }
+        SchedulingGroup {
+            id: gid,
+            engines,
+            latency_budget: None,
+        }
     }
 }
 
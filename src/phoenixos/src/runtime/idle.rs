@@ -0,0 +1,127 @@
+//! Idle mode of the runtimes.
+//!
+//! A runtime that has done no work for the configured threshold and whose engines all have
+//! doorbells marks itself idle. When all runtimes are idle, each of them waits on the doorbells
+//! of its engines instead of spinning. Any runtime that finds work again wakes up the others.
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use spin::Mutex;
+
+use crate::config::IdleConfig;
+
+/// An eventfd to wake up a runtime waiting on its doorbells.
+pub(crate) struct Waker(File);
+
+impl Waker {
+    pub(crate) fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Waker(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    #[inline]
+    pub(crate) fn wake(&self) {
+        // This only fails if the counter overflows, in which case it is readable anyway.
+        let _ = (&self.0).write_all(&1u64.to_ne_bytes());
+    }
+}
+
+impl AsRawFd for Waker {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+pub(crate) struct IdleDetector {
+    threshold: Option<Duration>,
+    max_park: Duration,
+    /// Number of runtimes not in the idle mode.
+    busy: AtomicUsize,
+    wakers: Mutex<Vec<Arc<Waker>>>,
+}
+
+impl IdleDetector {
+    pub(crate) fn new(config: &IdleConfig) -> Self {
+        IdleDetector {
+            threshold: config.threshold_ms.map(Duration::from_millis),
+            max_park: Duration::from_millis(config.max_park_ms),
+            busy: AtomicUsize::new(0),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// How long a runtime must have done nothing before entering the idle mode, `None` if the
+    /// idle mode is disabled.
+    #[inline]
+    pub(crate) fn threshold(&self) -> Option<Duration> {
+        self.threshold
+    }
+
+    /// Registers a new runtime, which starts busy.
+    pub(crate) fn register(&self, waker: Arc<Waker>) {
+        self.busy.fetch_add(1, Ordering::AcqRel);
+        self.wakers.lock().push(waker);
+    }
+
+    #[inline]
+    pub(crate) fn enter_idle(&self) {
+        self.busy.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Leaves the idle mode and wakes up all the runtimes waiting on their doorbells.
+    pub(crate) fn leave_idle(&self) {
+        self.busy.fetch_add(1, Ordering::AcqRel);
+        for waker in self.wakers.lock().iter() {
+            waker.wake();
+        }
+    }
+
+    #[inline]
+    pub(crate) fn all_idle(&self) -> bool {
+        self.busy.load(Ordering::Acquire) == 0
+    }
+
+    /// Waits until any of `fds` becomes readable, or for at most `max_park_ms`, or until
+    /// `deadline`, the next expiry of the runtime's timers. The readable eventfds are drained.
+    pub(crate) fn park(&self, fds: &[RawFd], deadline: Option<Instant>) {
+        let mut pollfds: Vec<libc::pollfd> = fds
+            .iter()
+            .map(|&fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let timeout = match deadline {
+            // round up, so the timers are due when the runtime wakes up
+            Some(deadline) => {
+                let micros = deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_micros();
+                self.max_park
+                    .min(Duration::from_millis(((micros + 999) / 1000) as u64))
+            }
+            None => self.max_park,
+        };
+        let timeout = timeout.as_millis() as libc::c_int;
+        let ret = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as _, timeout) };
+        if ret <= 0 {
+            // timed out or interrupted
+            return;
+        }
+        for pollfd in pollfds.iter().filter(|p| p.revents & libc::POLLIN != 0) {
+            // Reading an epoll fd fails, it is drained by its engine.
+            let file = std::mem::ManuallyDrop::new(unsafe { File::from_raw_fd(pollfd.fd) });
+            let mut buf = [0u8; 8];
+            let _ = (&*file).read(&mut buf);
+        }
+    }
+}
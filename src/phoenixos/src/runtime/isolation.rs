@@ -0,0 +1,162 @@
+//! The cores of the runtimes and their isolation.
+//!
+//! The runtimes can be restricted to a set of cores. Those cores should be isolated from the
+//! kernel scheduler, e.g., with the `isolcpus` or `nohz_full` boot parameters, so that neither
+//! other threads nor the scheduler tick preempt the spinning runtimes. The daemon cannot isolate
+//! them itself, it only warns about the cores that are not isolated. It can also steer the
+//! interrupts of the RDMA NICs to the other cores.
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::log;
+
+/// Parses a cpulist of the kernel, e.g., "0-3,8,10-11".
+pub(crate) fn parse_cpu_list(list: &str) -> anyhow::Result<Vec<u16>> {
+    let mut cores = BTreeSet::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (first.trim().parse::<u16>()?, last.trim().parse::<u16>()?);
+                anyhow::ensure!(first <= last, "invalid range of cores: {}", range);
+                cores.extend(first..=last);
+            }
+            None => {
+                cores.insert(range.trim().parse::<u16>()?);
+            }
+        }
+    }
+    Ok(cores.into_iter().collect())
+}
+
+fn format_cpu_list(cores: &[u16]) -> String {
+    cores
+        .iter()
+        .map(|core| core.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn read_cpu_list<P: AsRef<Path>>(path: P) -> Vec<u16> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|list| parse_cpu_list(&list).ok())
+        .unwrap_or_default()
+}
+
+/// The cores given to a boot parameter in /proc/cmdline, e.g., `nohz_full=2-7`. The flags of
+/// `isolcpus`, e.g., `isolcpus=domain,managed_irq,2-7`, are skipped.
+fn boot_parameter_cores(cmdline: &str, name: &str) -> Vec<u16> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+        .flat_map(|value| {
+            let list = value
+                .split(',')
+                .skip_while(|part| part.starts_with(|c: char| c.is_ascii_alphabetic()))
+                .collect::<Vec<_>>()
+                .join(",");
+            parse_cpu_list(&list).unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Warns about the cores of the runtimes that are not isolated from the kernel scheduler, or
+/// that still have the scheduler tick. Returns whether all of them are isolated.
+pub(crate) fn check_isolation(cores: &[u16]) -> bool {
+    let cmdline = fs::read_to_string("/proc/cmdline").unwrap_or_default();
+    let mut isolated = read_cpu_list("/sys/devices/system/cpu/isolated");
+    isolated.extend(boot_parameter_cores(&cmdline, "isolcpus"));
+    let mut nohz_full = read_cpu_list("/sys/devices/system/cpu/nohz_full");
+    nohz_full.extend(boot_parameter_cores(&cmdline, "nohz_full"));
+
+    let not_isolated: Vec<u16> = cores
+        .iter()
+        .copied()
+        .filter(|core| !isolated.contains(core))
+        .collect();
+    let ticking: Vec<u16> = cores
+        .iter()
+        .copied()
+        .filter(|core| !nohz_full.contains(core))
+        .collect();
+    if !not_isolated.is_empty() {
+        log::warn!(
+            "Cores {} of the runtimes are not isolated from the kernel scheduler, add them to isolcpus",
+            format_cpu_list(&not_isolated)
+        );
+    }
+    if !ticking.is_empty() {
+        log::warn!(
+            "Cores {} of the runtimes still have the scheduler tick, add them to nohz_full",
+            format_cpu_list(&ticking)
+        );
+    }
+    not_isolated.is_empty() && ticking.is_empty()
+}
+
+/// The interrupts of the RDMA NICs, from their MSI-X vectors.
+fn rdma_irqs() -> io::Result<Vec<(String, u32)>> {
+    let mut irqs = Vec::new();
+    for device in fs::read_dir("/sys/class/infiniband")? {
+        let device = device?;
+        let name = device.file_name().to_string_lossy().into_owned();
+        let msi_irqs = match fs::read_dir(device.path().join("device/msi_irqs")) {
+            Ok(msi_irqs) => msi_irqs,
+            // e.g., a software RDMA device
+            Err(_) => continue,
+        };
+        for irq in msi_irqs {
+            if let Ok(irq) = irq?.file_name().to_string_lossy().parse() {
+                irqs.push((name.clone(), irq));
+            }
+        }
+    }
+    Ok(irqs)
+}
+
+/// Writes the affinity of the interrupts of the RDMA NICs, so that they land on the cores other
+/// than those of the runtimes. Returns the number of interrupts steered.
+///
+/// The affinity is only a hint, irqbalance may overwrite it, and managed interrupts do not
+/// accept it.
+pub(crate) fn steer_irqs(cores: &[u16]) -> usize {
+    let others: Vec<u16> = read_cpu_list("/sys/devices/system/cpu/online")
+        .into_iter()
+        .filter(|core| !cores.contains(core))
+        .collect();
+    if others.is_empty() {
+        log::warn!("No core is left for the interrupts of the RDMA NICs");
+        return 0;
+    }
+    let irqs = match rdma_irqs() {
+        Ok(irqs) => irqs,
+        Err(e) => {
+            log::warn!("Failed to find the interrupts of the RDMA NICs: {}", e);
+            return 0;
+        }
+    };
+
+    let affinity = format_cpu_list(&others);
+    let mut steered = 0;
+    for (device, irq) in irqs {
+        let path = format!("/proc/irq/{}/smp_affinity_list", irq);
+        match fs::write(&path, &affinity) {
+            Ok(()) => steered += 1,
+            Err(e) => log::warn!(
+                "Failed to steer interrupt {} of {} to cores {}: {}",
+                irq,
+                device,
+                affinity,
+                e
+            ),
+        }
+    }
+    log::info!(
+        "Steered {} interrupts of the RDMA NICs to cores {}",
+        steered,
+        affinity
+    );
+    steered
+}
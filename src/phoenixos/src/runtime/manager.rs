@@ -3,28 +3,42 @@
 //! among different runtimes, and even dynamically scale out/down the runtimes.
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::io;
+use std::os::unix::ucred::UCred;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crc32fast::Hasher as Crc32Hasher;
 use dashmap::DashMap;
 use nix::unistd::Pid;
 
-use phoenix_api::engine::{SchedulingHint, SchedulingMode};
+use ipc::control::Labels;
+use phoenix_api::engine::{SchedulingClass, SchedulingHint, SchedulingMode};
+use phoenix_common::engine::datapath::channel;
 use phoenix_common::engine::EngineType;
+use phoenix_common::ledger;
 use phoenix_common::module::Service;
 use phoenix_common::storage::ResourceCollection;
 
+use super::accounting::{CpuCap, EngineStats};
 use super::affinity::CoreMask;
+use super::cgroup::CgroupManager;
 use super::container::EngineContainer;
-use super::executor::{self, Runtime, RuntimeMode};
+use super::executor::{self, QueueDepths, Runtime, RuntimeMode};
+use super::fusion::FusedEdges;
 use super::graph::DataPathGraph;
 use super::group::GroupId;
+use super::idle::IdleDetector;
+use super::isolation;
+use super::sandbox::Sandbox;
+use super::supervisor::{FailureLog, Supervisor};
 use super::SchedulingGroup;
-use crate::config::Config;
+use crate::config::{Config, SupervisorConfig};
 use crate::{log, tracing};
 
 #[repr(transparent)]
@@ -39,7 +53,7 @@ pub(crate) struct RuntimeId(pub(crate) u64);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) struct SubscriptionId(pub(crate) u64);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub(crate) struct EngineInfo {
     /// Application process PID that this engine serves
     pub(crate) pid: Pid,
@@ -53,6 +67,8 @@ pub(crate) struct EngineInfo {
     pub(crate) engine_type: EngineType,
     /// Scheduling mode
     pub(crate) scheduling_mode: SchedulingMode,
+    /// CPU time accounting of the engine
+    pub(crate) stats: Arc<EngineStats>,
 }
 
 pub(crate) struct ServiceSubscription {
@@ -83,6 +99,8 @@ impl GlobalResourceManager {
         });
         if removed.is_some() {
             self.resource.remove(&pid);
+            // all the engines of the process are gone, and so should be its resources
+            ledger::check_released(pid);
         }
     }
 }
@@ -102,7 +120,33 @@ pub struct RuntimeManager {
     /// The service each engine group is running,
     /// and the number of active engines in that group
     pub(crate) service_subscriptions: DashMap<(Pid, SubscriptionId), (ServiceSubscription, usize)>,
+    /// The CPU cap of each service subscription
+    pub(crate) cpu_caps: DashMap<(Pid, SubscriptionId), Arc<CpuCap>>,
+    /// The labels of the service subscriptions that have any
+    pub(crate) labels: DashMap<(Pid, SubscriptionId), Labels>,
+    /// The supervisor of each service subscription
+    pub(crate) supervisors: DashMap<(Pid, SubscriptionId), Arc<Supervisor>>,
+    /// The recent failures of the engines, reported by the supervisors
+    pub(crate) failures: Arc<FailureLog>,
+    /// The supervisor policies of the subscriptions
+    supervisor_config: SupervisorConfig,
+    /// The scheduling hint each scheduling group was submitted with
+    pub(crate) group_hints: DashMap<(Pid, SubscriptionId, GroupId), SchedulingHint>,
     pub(crate) global_resource_mgr: GlobalResourceManager,
+    /// Shared by all the runtimes to decide when the daemon is idle.
+    pub(crate) idle_detector: Arc<IdleDetector>,
+    /// The fused data path edges, the same for all the runtimes.
+    pub(crate) fused: Arc<FusedEdges>,
+    /// Installed by each runtime thread when it starts, `None` if the sandbox is disabled.
+    sandbox: Mutex<Option<Arc<Sandbox>>>,
+    /// The cgroups of the subscriptions, `None` if disabled.
+    pub(crate) cgroups: Option<CgroupManager>,
+    /// Cores latency-critical subscriptions are not admitted to.
+    reserved_cores: usize,
+    /// The latency budget of the background subscriptions without one.
+    background_latency_budget: Duration,
+    /// The cores the runtimes are restricted to, `None` if they are not.
+    runtime_cores: Option<Vec<u16>>,
 }
 
 pub struct Inner {
@@ -117,20 +161,38 @@ impl Inner {
         &mut self,
         pid: Pid,
         sid: SubscriptionId,
-        group: SchedulingGroup,
+        mut group: SchedulingGroup,
         rm: &Arc<RuntimeManager>,
         mode: SchedulingMode,
         hint: SchedulingHint,
-    ) {
-        let (runtime_mode, quota) = match mode {
+    ) -> Result<(), executor::Error> {
+        let (mut runtime_mode, quota) = match mode {
             SchedulingMode::Dedicate => (RuntimeMode::Dedicated, None),
             SchedulingMode::Compact => (RuntimeMode::Compact, None),
             SchedulingMode::GroupShared(quota) => (RuntimeMode::GroupShared, Some(quota)),
             SchedulingMode::Spread => unimplemented!(),
         };
+        // background groups do not share runtimes with spinning ones
+        if runtime_mode == RuntimeMode::Compact && hint.class == Some(SchedulingClass::Background) {
+            runtime_mode = RuntimeMode::Background;
+        }
+
+        // only engines sharing a runtime sleep when idle, a dedicated runtime keeps spinning
+        let latency_budget = hint
+            .latency_budget_us
+            .map(|us| Duration::from_micros(us as u64));
+        match runtime_mode {
+            RuntimeMode::Compact if hint.class != Some(SchedulingClass::Throughput) => {
+                group.latency_budget = latency_budget;
+            }
+            RuntimeMode::Background => {
+                group.latency_budget = latency_budget.or(Some(rm.background_latency_budget));
+            }
+            _ => {}
+        }
 
         // choose cores to schedule
-        let cores = CoreMask::from_numa_node(hint.numa_node_affinity);
+        let cores = rm.core_mask(hint.numa_node_affinity);
         log::debug!(
             "group: {:?}, scheduling hint: {:?}, cores: {}",
             group,
@@ -151,10 +213,18 @@ impl Inner {
             r.try_acquire(runtime_mode, Some(group_signature), cores.clone(), quota)
         }) {
             Some((rid, _runtime)) => *rid,
-            None => self.start_runtime(cores, runtime_mode, Some(group_signature), Arc::clone(rm)),
+            None => {
+                self.start_runtime(cores, runtime_mode, Some(group_signature), Arc::clone(rm))?
+            }
         };
 
-        for (eid, engine) in group.engines.iter() {
+        let cpu_cap = rm.cpu_cap(pid, sid);
+        let supervisor = rm.supervisors.get(&(pid, sid)).map(|s| Arc::clone(&s));
+        for (eid, engine) in group.engines.iter_mut() {
+            engine.set_cpu_cap(Arc::clone(&cpu_cap));
+            if let Some(supervisor) = supervisor.as_ref() {
+                engine.set_supervisor(Arc::clone(supervisor));
+            }
             let engine_type = engine.engine_type();
             let engine_info = EngineInfo {
                 pid,
@@ -163,6 +233,7 @@ impl Inner {
                 gid: group.id,
                 scheduling_mode: mode,
                 engine_type,
+                stats: Arc::clone(engine.stats()),
             };
             tracing::info!(
                 "Submitting engine {:?} (pid={:?}, sid={:?}, gid={:?}) to runtime (rid={:?})",
@@ -176,15 +247,59 @@ impl Inner {
             assert!(prev.is_none(), "eid={:?} is already used", eid);
         }
 
+        // the runtime is dedicated to this subscription, attribute its CPU time to it
+        if runtime_mode == RuntimeMode::Dedicated {
+            if let Some(cgroups) = rm.cgroups.as_ref() {
+                match cgroups.create(pid, sid) {
+                    Ok(path) => self.runtimes[&rid].request_cgroup(path),
+                    Err(e) => log::warn!(
+                        "Failed to create the cgroup for pid={:?}, sid={:?}: {}",
+                        pid,
+                        sid,
+                        e
+                    ),
+                }
+            }
+        }
+
         self.runtimes[&rid].add_group(group);
         // a runtime will not be parked when having pending engines, so in theory, we can check
         // whether the runtime and only unpark it when it's in parked state.
         self.handles[&rid].thread().unpark();
+        Ok(())
     }
 }
 
 impl RuntimeManager {
-    pub fn new(_config: &Config) -> Self {
+    pub fn new(config: &Config) -> Self {
+        channel::set_default_capacity(config.datapath.channel_capacity);
+        let cgroups = config
+            .cgroup
+            .as_ref()
+            .and_then(|cgroup| match CgroupManager::new(cgroup) {
+                Ok(cgroups) => Some(cgroups),
+                Err(e) => {
+                    log::warn!("Per-subscription cgroups are disabled: {}", e);
+                    None
+                }
+            });
+        let core_list = config.cores.runtimes.as_deref();
+        let runtime_cores = match core_list.map(isolation::parse_cpu_list) {
+            Some(Ok(cores)) if !cores.is_empty() => Some(cores),
+            Some(Err(e)) => {
+                log::warn!("The runtimes are not restricted to cores: {}", e);
+                None
+            }
+            _ => None,
+        };
+        if let Some(cores) = runtime_cores.as_ref() {
+            if config.cores.check_isolation {
+                isolation::check_isolation(cores);
+            }
+            if config.cores.steer_irqs {
+                isolation::steer_irqs(cores);
+            }
+        }
         let inner = Inner {
             runtime_counter: 0,
             runtimes: HashMap::with_capacity(1),
@@ -197,7 +312,22 @@ impl RuntimeManager {
             subscription_counter: DashMap::new(),
             engine_subscriptions: DashMap::new(),
             service_subscriptions: DashMap::new(),
+            cpu_caps: DashMap::new(),
+            labels: DashMap::new(),
+            supervisors: DashMap::new(),
+            failures: Arc::new(FailureLog::default()),
+            supervisor_config: config.supervisor.clone(),
+            group_hints: DashMap::new(),
             global_resource_mgr: GlobalResourceManager::new(),
+            idle_detector: Arc::new(IdleDetector::new(&config.idle)),
+            fused: Arc::new(FusedEdges::new(&config.fusion)),
+            sandbox: Mutex::new(None),
+            cgroups,
+            reserved_cores: config.classes.reserved_cores,
+            background_latency_budget: Duration::from_micros(
+                config.classes.background_latency_budget_us as u64,
+            ),
+            runtime_cores,
         }
     }
 
@@ -212,8 +342,14 @@ impl RuntimeManager {
     ) {
         let inner = self.inner.lock().unwrap();
         let mut submission = Vec::with_capacity(engines.len());
-        for engine in engines {
+        let cpu_cap = self.cpu_cap(pid, sid);
+        let supervisor = self.supervisors.get(&(pid, sid)).map(|s| Arc::clone(&s));
+        for mut engine in engines {
             let eid = EngineId(self.engine_counter.fetch_add(1, Ordering::Relaxed));
+            engine.set_cpu_cap(Arc::clone(&cpu_cap));
+            if let Some(supervisor) = supervisor.as_ref() {
+                engine.set_supervisor(Arc::clone(supervisor));
+            }
             let engine_type = engine.engine_type();
             let engine_info = EngineInfo {
                 pid,
@@ -222,6 +358,7 @@ impl RuntimeManager {
                 gid,
                 scheduling_mode: mode,
                 engine_type,
+                stats: Arc::clone(engine.stats()),
             };
             tracing::info!(
                 "Attaching engine {:?} (pid={:?}, sid={:?}, gid={:?}) to runtime (rid={:?})",
@@ -245,20 +382,86 @@ impl RuntimeManager {
         engines: Vec<EngineContainer>,
         mode: SchedulingMode,
         hint: SchedulingHint,
-    ) {
+    ) -> Result<(), executor::Error> {
+        let gid = GroupId(
+            self.scheduling_group_counter
+                .fetch_add(1, Ordering::Relaxed),
+        );
+        self.group_hints.insert((pid, sid, gid), hint);
+        let ret = self.submit_group_with_id(pid, sid, gid, engines, mode, hint);
+        if ret.is_err() {
+            self.group_hints.remove(&(pid, sid, gid));
+        }
+        ret
+    }
+
+    /// Submit the engines of an existing scheduling group, e.g., one that was suspended from
+    /// its runtime, to a runtime chosen for `mode`. The engines are dropped if no runtime can be
+    /// started for them.
+    pub(crate) fn submit_group_with_id(
+        self: &Arc<Self>,
+        pid: Pid,
+        sid: SubscriptionId,
+        gid: GroupId,
+        engines: Vec<EngineContainer>,
+        mode: SchedulingMode,
+        hint: SchedulingHint,
+    ) -> Result<(), executor::Error> {
         let mut inner = self.inner.lock().unwrap();
         let mut submission = Vec::with_capacity(engines.len());
         for engine in engines {
             let eid = EngineId(self.engine_counter.fetch_add(1, Ordering::Relaxed));
             submission.push((eid, engine));
         }
-        let gid = GroupId(
-            self.scheduling_group_counter
-                .fetch_add(1, Ordering::Relaxed),
-        );
         let group = SchedulingGroup::new(gid, submission);
 
-        inner.schedule(pid, sid, group, self, mode, hint);
+        inner.schedule(pid, sid, group, self, mode, hint)
+    }
+
+    /// The cores a runtime for the groups with affinity to `numa_node` runs on.
+    pub(crate) fn core_mask(&self, numa_node: Option<u8>) -> CoreMask {
+        let cores = CoreMask::from_numa_node(numa_node);
+        match self.runtime_cores.as_ref() {
+            Some(runtime_cores) => cores.restrict_to(runtime_cores),
+            None => cores,
+        }
+    }
+
+    /// Admits `num_groups` more scheduling groups of latency-critical subscriptions on the cores
+    /// of `numa_node`. Each of them takes a dedicated runtime, and the dedicated runtimes must
+    /// leave the reserved cores.
+    pub(crate) fn admit_latency_critical(
+        &self,
+        numa_node: Option<u8>,
+        num_groups: usize,
+    ) -> Result<(), phoenix_api::Error> {
+        let cores = self.core_mask(numa_node);
+        let available = cores.count().saturating_sub(self.reserved_cores);
+        let inner = self.inner.lock().unwrap();
+        let in_use = inner
+            .runtimes
+            .values()
+            .filter(|r| r.cores() == &cores && r.is_dedicated())
+            .count();
+        if in_use + num_groups > available {
+            return Err(phoenix_api::Error::resource_exhausted(format!(
+                "{} dedicated cores requested, {} of {} left",
+                num_groups,
+                available.saturating_sub(in_use),
+                available
+            )));
+        }
+        Ok(())
+    }
+
+    /// The CPU cap of a service subscription.
+    pub(crate) fn cpu_cap(&self, pid: Pid, sid: SubscriptionId) -> Arc<CpuCap> {
+        Arc::clone(&self.cpu_caps.entry((pid, sid)).or_default())
+    }
+
+    /// Sandboxes the runtimes started from now on.
+    pub(crate) fn set_sandbox(&self, sandbox: Sandbox) {
+        *self.sandbox.lock().unwrap() = Some(Arc::new(sandbox));
     }
 
     /// Create a new engine group for service subscription
@@ -269,6 +472,12 @@ impl RuntimeManager {
     ) -> SubscriptionId {
         let mut counter = self.subscription_counter.entry(pid).or_insert(0);
         let sid = SubscriptionId(*counter);
+        let policy = self
+            .supervisor_config
+            .policy(subscription.service.0)
+            .clone();
+        let supervisor = Supervisor::new(pid, sid, policy, Arc::clone(&self.failures));
+        self.supervisors.insert((pid, sid), Arc::new(supervisor));
         self.service_subscriptions
             .insert((pid, sid), (subscription, 0));
         *self.global_resource_mgr.active_cnt.entry(pid).or_insert(0) += 1;
@@ -276,6 +485,109 @@ impl RuntimeManager {
         sid
     }
 
+    /// Runs `f` on an engine between two rounds of its runtime and waits for the outcome. The
+    /// lock is only held to submit the call, not while waiting for the runtime.
+    fn call_engine<R, F>(&self, eid: EngineId, timeout: Duration, f: F) -> anyhow::Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut EngineContainer) -> R + Send + 'static,
+    {
+        let rid = match self.engine_subscriptions.get(&eid) {
+            Some(info) => info.rid,
+            None => anyhow::bail!("engine eid={:?} not found", eid),
+        };
+
+        let outcome = {
+            let inner = self.inner.lock().unwrap();
+            let outcome = inner.runtimes[&rid].call_engine(eid, f);
+            inner.handles[&rid].thread().unpark();
+            outcome
+        };
+
+        match outcome.recv_timeout(timeout) {
+            Ok(Some(result)) => Ok(result),
+            Ok(None) => anyhow::bail!("engine eid={:?} not found in runtime", eid),
+            Err(_) => anyhow::bail!("engine eid={:?} did not respond in {:?}", eid, timeout),
+        }
+    }
+
+    /// Sample the queue depths of the given engines on the runtimes they are running on.
+    ///
+    /// Engines whose runtime does not respond within `timeout` are missing in the result.
+    pub(crate) fn sample_queue_depths(
+        &self,
+        engines: &[(EngineId, RuntimeId)],
+        timeout: Duration,
+    ) -> HashMap<EngineId, QueueDepths> {
+        // submit all the requests first, so that the runtimes sample in parallel
+        let outcomes = {
+            let inner = self.inner.lock().unwrap();
+            engines
+                .iter()
+                .map(|(eid, rid)| {
+                    let outcome =
+                        inner.runtimes[rid].call_engine(*eid, |engine| engine.queue_depths());
+                    inner.handles[rid].thread().unpark();
+                    (*eid, outcome)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut depths = HashMap::with_capacity(engines.len());
+        for (eid, outcome) in outcomes {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if let Ok(Some(result)) = outcome.recv_timeout(timeout) {
+                depths.insert(eid, result);
+            }
+        }
+        depths
+    }
+
+    /// Sends a query to an engine and waits for its answer.
+    pub(crate) fn query_engine(
+        &self,
+        eid: EngineId,
+        query: Vec<u8>,
+        cred: UCred,
+        timeout: Duration,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.call_engine(eid, timeout, move |engine| engine.handle_query(query, cred))?
+    }
+
+    /// Updates the configuration of an engine and waits for the outcome. Returns the replaced
+    /// configuration.
+    pub(crate) fn update_engine_config(
+        &self,
+        eid: EngineId,
+        config: String,
+        timeout: Duration,
+    ) -> anyhow::Result<String> {
+        // the engine is not running, so it sees either configuration as a whole
+        self.call_engine(eid, timeout, move |engine| engine.update_config(&config))?
+    }
+
+    /// Shuts down all the engines of a service subscription. Returns false if the subscription is
+    /// not found.
+    pub(crate) fn tear_down_subscription(&self, pid: Pid, sid: SubscriptionId) -> bool {
+        let Some(supervisor) = self.supervisors.get(&(pid, sid)) else {
+            return false;
+        };
+        supervisor.tear_down();
+        // the runtimes shut the engines down when they resume them
+        let inner = self.inner.lock().unwrap();
+        for handle in inner.handles.values() {
+            handle.thread().unpark();
+        }
+        true
+    }
+
+    /// Returns false if any runtime thread has exited.
+    pub(crate) fn is_alive(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.handles.values().all(|handle| !handle.is_finished())
+    }
+
     pub(crate) fn register_engine_shutdown(&self, engine_id: EngineId) {
         let info = self.engine_subscriptions.remove(&engine_id).unwrap().1;
         let removed =
@@ -287,27 +599,44 @@ impl RuntimeManager {
         if removed.is_some() {
             self.global_resource_mgr
                 .register_subscription_shutdown(info.pid);
+            self.cpu_caps.remove(&(info.pid, info.sid));
+            self.labels.remove(&(info.pid, info.sid));
+            self.supervisors.remove(&(info.pid, info.sid));
+            self.group_hints
+                .retain(|(pid, sid, _), _| *pid != info.pid || *sid != info.sid);
+            if let Some(cgroups) = self.cgroups.as_ref() {
+                cgroups.remove(info.pid, info.sid);
+            }
         }
     }
 }
 
 impl Inner {
+    /// Starts a runtime on `cores`. Fails if the runtime thread cannot install the sandbox, in
+    /// which case the runtime is not registered.
     fn start_runtime(
         &mut self,
         cores: CoreMask,
         mode: RuntimeMode,
         group_signature: Option<u32>,
         rm: Arc<RuntimeManager>,
-    ) -> RuntimeId {
+    ) -> Result<RuntimeId, executor::Error> {
         let runtime_id = RuntimeId(self.runtime_counter);
         self.runtime_counter = self.runtime_counter.checked_add(1).unwrap();
 
-        let runtime = Arc::new(Runtime::new(runtime_id, cores.clone(), Arc::downgrade(&rm)));
+        let runtime = Arc::new(Runtime::new(
+            runtime_id,
+            cores.clone(),
+            Arc::downgrade(&rm),
+            Arc::clone(&rm.idle_detector),
+            Arc::clone(&rm.fused),
+        ));
         let flag = runtime.try_acquire(mode, group_signature, cores.clone(), None);
         assert!(flag);
 
-        self.runtimes.insert(runtime_id, Arc::clone(&runtime));
-
+        let sandbox = rm.sandbox.lock().unwrap().clone();
+        let (installed_tx, installed_rx) = mpsc::sync_channel(1);
+        let thread_runtime = Arc::clone(&runtime);
         let handle = thread::Builder::new()
             .name(format!("Runtime {}, CpuSet: {:?}", runtime_id.0, cores))
             .spawn(move || {
@@ -316,11 +645,36 @@ impl Inner {
                 if !cores.sched_set_affinity_for_current_thread() {
                     log::warn!("Set affinity for {:?} failed", runtime_id);
                 }
-                runtime.mainloop()
+                if let Some(sandbox) = sandbox {
+                    if let Err(e) = sandbox.install() {
+                        log::error!("Failed to sandbox {:?}: {}", runtime_id, e);
+                        let _ = installed_tx.send(Err(e));
+                        return Ok(());
+                    }
+                }
+                let _ = installed_tx.send(Ok(()));
+                thread_runtime.mainloop()
             })
             .unwrap_or_else(|e| panic!("failed to spawn new threads: {}", e));
 
+        // the runtime starts running engines only once it is sandboxed
+        match installed_rx.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                let _ = handle.join();
+                return Err(executor::Error::Sandbox(e));
+            }
+            Err(_) => {
+                let _ = handle.join();
+                return Err(executor::Error::Sandbox(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the runtime thread exited before installing the sandbox",
+                )));
+            }
+        }
+
+        self.runtimes.insert(runtime_id, runtime);
         self.handles.insert(runtime_id, handle);
-        runtime_id
+        Ok(runtime_id)
     }
 }
@@ -14,6 +14,24 @@ pub(crate) mod graph;
 pub(crate) mod upgrade;
 pub(crate) use upgrade::EngineUpgrader;
 
+pub(crate) mod progress;
+
 pub(crate) mod affinity;
 
+pub(crate) mod isolation;
+
 pub(crate) mod lb;
+
+pub(crate) mod idle;
+
+pub(crate) mod cgroup;
+
+pub(crate) mod accounting;
+
+pub(crate) mod scaling;
+
+pub(crate) mod fusion;
+
+pub(crate) mod sandbox;
+
+pub(crate) mod supervisor;
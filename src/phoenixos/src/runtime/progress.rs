@@ -0,0 +1,195 @@
+//! Progress of the upgrades and the addon attaches and detaches, streamed to the client that
+//! asked for it with `Request::Streaming`.
+//!
+//! The steps are sent to the client as they happen. An upgrade may run for several clients at
+//! once, so the final result is only sent when the last of them is done, i.e., when the last
+//! clone of the `Progress` is dropped. It is an error if any of them has failed.
+use std::fmt;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use nix::unistd::Pid;
+
+use ipc::control::{ProgressEvent, ProgressStage, Response, ResponseKind};
+use phoenix_common::engine::EngineType;
+
+use super::manager::SubscriptionId;
+use crate::log;
+
+struct Stream {
+    /// A clone of the control socket, so that the client sees the responses coming from it.
+    sock: UnixDatagram,
+    client: PathBuf,
+    /// The first failure.
+    error: Mutex<Option<phoenix_api::Error>>,
+}
+
+impl Stream {
+    fn send(&self, response: &Response) {
+        let buf = bincode::serialize(response).expect("Response is serializable");
+        if let Err(e) = self.sock.send_to(&buf, &self.client) {
+            log::warn!("Failed to send the progress to {:?}: {}", self.client, e);
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let result = match self.error.get_mut().unwrap().take() {
+            Some(error) => Err(error),
+            None => Ok(ResponseKind::Completed),
+        };
+        self.send(&Response(result));
+    }
+}
+
+/// Where the progress of a long-running request goes, nowhere unless it is streamed.
+#[derive(Clone)]
+pub(crate) struct Progress(Option<Arc<Stream>>);
+
+impl Progress {
+    /// The progress of a request that is not streamed.
+    pub(crate) fn none() -> Self {
+        Progress(None)
+    }
+
+    /// Streams the progress to `client` through `sock`.
+    pub(crate) fn stream(sock: UnixDatagram, client: PathBuf) -> Self {
+        Progress(Some(Arc::new(Stream {
+            sock,
+            client,
+            error: Mutex::new(None),
+        })))
+    }
+
+    /// Reports a step about an engine of a subscription, or the whole subscription if
+    /// `engine_type` is `None`.
+    pub(crate) fn report(
+        &self,
+        pid: Pid,
+        sid: SubscriptionId,
+        engine_type: Option<EngineType>,
+        stage: ProgressStage,
+    ) {
+        if let Some(stream) = self.0.as_ref() {
+            let event = ProgressEvent {
+                pid: pid.as_raw(),
+                sid: sid.0,
+                engine_type: engine_type.map(|ty| ty.0.to_owned()),
+                stage,
+            };
+            stream.send(&Response(Ok(ResponseKind::Progress(event))));
+        }
+    }
+
+    /// Makes the request fail with `error`, unless it has already failed.
+    pub(crate) fn fail(&self, error: impl fmt::Display) {
+        if let Some(stream) = self.0.as_ref() {
+            stream
+                .error
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| phoenix_api::Error::internal(error.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const ENGINE: EngineType = EngineType("TestEngine");
+
+    /// A client bound to a socket of its own, and the progress streamed to it.
+    fn client(name: &str) -> (UnixDatagram, Progress) {
+        let path = std::env::temp_dir().join(format!(
+            "phoenix-progress-{}-{}.sock",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        let client = UnixDatagram::bind(&path).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let sock = UnixDatagram::unbound().unwrap();
+        (client, Progress::stream(sock, path))
+    }
+
+    fn recv(client: &UnixDatagram) -> Response {
+        let mut buf = vec![0; 65536];
+        let len = client.recv(&mut buf).unwrap();
+        bincode::deserialize(&buf[..len]).unwrap()
+    }
+
+    fn stage(response: Response) -> (Option<String>, ProgressStage) {
+        match response.0 {
+            Ok(ResponseKind::Progress(event)) => (event.engine_type, event.stage),
+            other => panic!("expected a progress event, got {:?}", other),
+        }
+    }
+
+    fn pending(client: &UnixDatagram) -> bool {
+        client.set_nonblocking(true).unwrap();
+        let mut buf = [0; 16];
+        let pending = client.recv(&mut buf).is_ok();
+        client.set_nonblocking(false).unwrap();
+        pending
+    }
+
+    #[test]
+    fn steps_then_completed() {
+        let (client, progress) = client("completed");
+        let (pid, sid) = (Pid::from_raw(1), SubscriptionId(1));
+        progress.report(pid, sid, Some(ENGINE), ProgressStage::Suspended);
+        progress.report(pid, sid, None, ProgressStage::Resubmitted);
+        assert_eq!(
+            stage(recv(&client)),
+            (Some("TestEngine".to_owned()), ProgressStage::Suspended)
+        );
+        assert_eq!(stage(recv(&client)), (None, ProgressStage::Resubmitted));
+        assert!(!pending(&client));
+
+        drop(progress);
+        assert!(matches!(recv(&client).0, Ok(ResponseKind::Completed)));
+    }
+
+    #[test]
+    fn result_after_last_clone() {
+        let (client, progress) = client("clones");
+        let other = progress.clone();
+        drop(progress);
+        assert!(!pending(&client));
+        drop(other);
+        assert!(matches!(recv(&client).0, Ok(ResponseKind::Completed)));
+    }
+
+    #[test]
+    fn first_failure_wins() {
+        let (client, progress) = client("failure");
+        let other = progress.clone();
+        other.fail("first");
+        progress.fail("second");
+        drop(other);
+        drop(progress);
+        match recv(&client).0 {
+            Err(e) => assert_eq!(e.message(), "first"),
+            Ok(_) => panic!("expected the failure"),
+        }
+    }
+
+    #[test]
+    fn not_streamed() {
+        let progress = Progress::none();
+        progress.report(
+            Pid::from_raw(1),
+            SubscriptionId(1),
+            None,
+            ProgressStage::Created,
+        );
+        progress.fail("ignored");
+    }
+}
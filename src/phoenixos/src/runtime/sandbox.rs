@@ -0,0 +1,440 @@
+//! Sandbox of the runtime threads.
+//!
+//! Each runtime thread restricts itself when it starts, before resuming any engine. Landlock
+//! limits the paths it can open to those declared by the plugins and the daemon, and a seccomp
+//! filter limits its system calls to those the runtime needs and those declared by the plugins.
+//! Other system calls fail with `EPERM`. The restrictions apply to the runtime thread only, the
+//! control thread keeps loading plugins and creating engines.
+//!
+//! The capabilities are those of the preset plugins, sealed at startup by
+//! `PluginManager::seal_sandbox`. A restriction installed cannot be lifted, so plugins loaded
+//! later cannot get new system calls or paths, and are refused if they declare any.
+//!
+//! Landlock is best effort: on a kernel without it, only the seccomp filter is installed.
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use phoenix_common::capability::Capabilities;
+
+use crate::config::Config;
+use crate::log;
+
+/// The system calls of the runtime itself: memory, futexes, parking, waiting on the doorbells,
+/// the clock, signals and exiting, and the I/O on the files and sockets of its engines.
+const RUNTIME_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_lseek,
+    libc::SYS_close,
+    libc::SYS_openat,
+    libc::SYS_newfstatat,
+    libc::SYS_fstat,
+    libc::SYS_statx,
+    libc::SYS_fcntl,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_unlinkat,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_setaffinity,
+    libc::SYS_getcpu,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_gettimeofday,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_tgkill,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    libc::SYS_epoll_pwait,
+    libc::SYS_epoll_ctl,
+    libc::SYS_ppoll,
+    libc::SYS_recvfrom,
+    libc::SYS_sendto,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmsg,
+    libc::SYS_getrandom,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+];
+
+/// The capabilities of every runtime thread.
+#[derive(Debug, Clone)]
+pub(crate) struct Sandbox {
+    caps: Capabilities,
+}
+
+impl Sandbox {
+    /// The sandbox for the plugins declaring `plugins`, plus what the daemon needs: its prefix,
+    /// the cgroups of the subscriptions and the paths given in the config.
+    pub(crate) fn new(plugins: &Capabilities, config: &Config) -> Self {
+        let mut caps = plugins.clone();
+        caps.read_paths
+            .extend(config.sandbox.read_paths.iter().cloned());
+        caps.write_paths.push(config.control.prefix.clone());
+        if let Some(cgroup) = config.cgroup.as_ref() {
+            caps.write_paths.push(cgroup.mount.clone());
+        }
+        caps.write_paths
+            .extend(config.sandbox.write_paths.iter().cloned());
+        caps.syscalls
+            .extend(RUNTIME_SYSCALLS.iter().map(|&nr| nr as i64));
+        Sandbox { caps }
+    }
+
+    /// Restricts the calling thread.
+    pub(crate) fn install(&self) -> io::Result<()> {
+        // required by both Landlock and seccomp without CAP_SYS_ADMIN
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        match landlock::restrict(&self.caps) {
+            Ok(()) => {}
+            Err(e) if landlock::is_unsupported(&e) => {
+                log::warn!(
+                    "Landlock is not supported, the paths are not restricted: {}",
+                    e
+                );
+            }
+            Err(e) => return Err(e),
+        }
+        let mut syscalls = self.caps.syscalls.clone();
+        if !self.caps.devices.is_empty() {
+            syscalls.push(libc::SYS_ioctl as i64);
+        }
+        seccomp::restrict(&syscalls)
+    }
+}
+
+fn open_path(path: &Path) -> io::Result<libc::c_int> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(fd)
+    }
+}
+
+mod landlock {
+    use super::*;
+
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    // the file system rights of the first ABI
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    const REMOVE_DIR: u64 = 1 << 4;
+    const REMOVE_FILE: u64 = 1 << 5;
+    const MAKE_DIR: u64 = 1 << 7;
+    const MAKE_REG: u64 = 1 << 8;
+    const MAKE_SOCK: u64 = 1 << 9;
+    const MAKE_FIFO: u64 = 1 << 10;
+    const MAKE_SYM: u64 = 1 << 12;
+
+    /// All the rights of the first ABI, including making character and block devices.
+    const HANDLED: u64 = (1 << 13) - 1;
+    /// The rights that apply to a file, the others only apply to a directory.
+    const FILE_RIGHTS: u64 = EXECUTE | WRITE_FILE | READ_FILE;
+    const READ: u64 = READ_FILE | READ_DIR;
+    const WRITE: u64 = READ
+        | WRITE_FILE
+        | REMOVE_DIR
+        | REMOVE_FILE
+        | MAKE_DIR
+        | MAKE_REG
+        | MAKE_SOCK
+        | MAKE_FIFO
+        | MAKE_SYM;
+    const DEVICE: u64 = READ | WRITE_FILE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    pub(super) fn is_unsupported(e: &io::Error) -> bool {
+        matches!(
+            e.raw_os_error(),
+            Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP)
+        )
+    }
+
+    fn check(ret: libc::c_long) -> io::Result<libc::c_long> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    fn add_rule(ruleset: libc::c_int, path: &Path, access: u64) -> io::Result<()> {
+        let fd = match open_path(path) {
+            Ok(fd) => fd,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::warn!("Sandbox path {:?} does not exist, skipped", path);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let access = if path.is_dir() {
+            access
+        } else {
+            access & FILE_RIGHTS
+        };
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: fd,
+        };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset,
+                RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        };
+        unsafe { libc::close(fd) };
+        check(ret).map(|_| ())
+    }
+
+    pub(super) fn restrict(caps: &Capabilities) -> io::Result<()> {
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        check(abi)?;
+
+        let attr = RulesetAttr {
+            handled_access_fs: HANDLED,
+        };
+        let ruleset = check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        })? as libc::c_int;
+
+        let rules = caps
+            .read_paths
+            .iter()
+            .map(|p| (p, READ))
+            .chain(caps.write_paths.iter().map(|p| (p, WRITE)))
+            .chain(caps.devices.iter().map(|p| (p, DEVICE)));
+        let mut ret = Ok(());
+        for (path, access) in rules {
+            ret = add_rule(ruleset, path, access);
+            if ret.is_err() {
+                break;
+            }
+        }
+        if ret.is_ok() {
+            ret = check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) })
+                .map(|_| ());
+        }
+        unsafe { libc::close(ruleset) };
+        ret
+    }
+}
+
+mod seccomp {
+    use super::*;
+
+    const SECCOMP_SET_MODE_FILTER: libc::c_long = 1;
+    pub(super) const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    pub(super) const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+
+    #[cfg(target_arch = "x86_64")]
+    pub(super) const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    pub(super) const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    // offsets in struct seccomp_data
+    pub(super) const NR_OFFSET: u32 = 0;
+    pub(super) const ARCH_OFFSET: u32 = 4;
+
+    // BPF_LD | BPF_W | BPF_ABS
+    pub(super) const BPF_LD_W_ABS: u16 = 0x20;
+    // BPF_JMP | BPF_JEQ | BPF_K
+    pub(super) const BPF_JEQ_K: u16 = 0x15;
+    // BPF_RET | BPF_K
+    pub(super) const BPF_RET_K: u16 = 0x06;
+
+    fn stmt(code: u16, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code, jt, jf, k }
+    }
+
+    /// Allows `syscalls` of the native architecture, other system calls fail with `EPERM`.
+    pub(super) fn filter(syscalls: &[i64]) -> Vec<libc::sock_filter> {
+        let mut syscalls = syscalls.to_vec();
+        syscalls.sort_unstable();
+        syscalls.dedup();
+        // the jumps to the final allow are at most 255 instructions long
+        assert!(syscalls.len() < 256, "too many system calls in the sandbox");
+
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let mut prog = vec![
+            stmt(BPF_LD_W_ABS, ARCH_OFFSET),
+            jump(BPF_JEQ_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET_K, deny),
+            stmt(BPF_LD_W_ABS, NR_OFFSET),
+        ];
+        let n = syscalls.len();
+        for (i, &nr) in syscalls.iter().enumerate() {
+            prog.push(jump(BPF_JEQ_K, nr as u32, (n - i) as u8, 0));
+        }
+        prog.push(stmt(BPF_RET_K, deny));
+        prog.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+        prog
+    }
+
+    pub(super) fn restrict(syscalls: &[i64]) -> io::Result<()> {
+        let mut prog = filter(syscalls);
+        let fprog = libc::sock_fprog {
+            len: prog.len() as libc::c_ushort,
+            filter: prog.as_mut_ptr(),
+        };
+        // without SECCOMP_FILTER_FLAG_TSYNC, only the calling thread is filtered
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                SECCOMP_SET_MODE_FILTER,
+                0,
+                &fprog as *const libc::sock_fprog,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::seccomp::*;
+    use super::*;
+
+    /// Runs `prog` on a system call `nr` of `arch`, with the instructions the filter uses.
+    fn run(prog: &[libc::sock_filter], nr: u32, arch: u32) -> u32 {
+        let mut acc = 0;
+        let mut pc = 0;
+        loop {
+            let insn = &prog[pc];
+            pc += 1;
+            match insn.code {
+                BPF_LD_W_ABS => {
+                    acc = match insn.k {
+                        NR_OFFSET => nr,
+                        ARCH_OFFSET => arch,
+                        k => panic!("load from offset {}", k),
+                    }
+                }
+                BPF_JEQ_K => {
+                    pc += if acc == insn.k {
+                        insn.jt as usize
+                    } else {
+                        insn.jf as usize
+                    };
+                }
+                BPF_RET_K => return insn.k,
+                code => panic!("unexpected instruction {:#x}", code),
+            }
+        }
+    }
+
+    #[test]
+    fn filter_allows_declared() {
+        let syscalls = [
+            libc::SYS_write,
+            libc::SYS_read,
+            libc::SYS_futex,
+            libc::SYS_read,
+        ];
+        let prog = filter(&syscalls);
+        // the duplicate is removed
+        assert_eq!(prog.len(), 4 + 3 + 2);
+
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        for nr in [libc::SYS_read, libc::SYS_write, libc::SYS_futex] {
+            assert_eq!(run(&prog, nr as u32, AUDIT_ARCH), SECCOMP_RET_ALLOW);
+        }
+        for nr in [libc::SYS_execve, libc::SYS_clone, libc::SYS_socket] {
+            assert_eq!(run(&prog, nr as u32, AUDIT_ARCH), deny);
+        }
+        // a system call of another architecture with an allowed number
+        assert_eq!(run(&prog, libc::SYS_read as u32, 0x4000_0003), deny);
+    }
+
+    #[test]
+    fn filter_runtime_syscalls() {
+        let syscalls: Vec<_> = RUNTIME_SYSCALLS.iter().map(|&nr| nr as i64).collect();
+        let prog = filter(&syscalls);
+        for &nr in RUNTIME_SYSCALLS {
+            assert_eq!(run(&prog, nr as u32, AUDIT_ARCH), SECCOMP_RET_ALLOW);
+        }
+        assert_ne!(
+            run(&prog, libc::SYS_execve as u32, AUDIT_ARCH),
+            SECCOMP_RET_ALLOW
+        );
+    }
+
+    #[test]
+    fn filter_empty() {
+        let prog = filter(&[]);
+        assert_ne!(
+            run(&prog, libc::SYS_read as u32, AUDIT_ARCH),
+            SECCOMP_RET_ALLOW
+        );
+    }
+}
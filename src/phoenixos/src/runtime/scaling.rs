@@ -0,0 +1,174 @@
+//! Automatic scaling of the runtimes.
+//!
+//! The utilization of a scheduling group is the fraction of time its engines spent in resumes
+//! that made progress. When a shared runtime stays busier than the scale-out threshold, its
+//! busiest group is moved to a runtime of its own. Such a group is moved back to a shared runtime
+//! after it stays below the scale-in threshold.
+//!
+//! An engine keeps the state of all its connections, so the unit of scaling is the scheduling
+//! group rather than the engine. The groups of background subscriptions are never scaled out.
+//!
+//! This does not shard an engine: no replica of an mRPC or transport engine is spawned, and no
+//! connection is re-hashed between replicas. A group that is busy on a runtime of its own stays
+//! as it is. Sharding needs a transport engine that can hand some of its connections to another
+//! one, and an mRPC engine that routes the calls of an app to several of them, which neither
+//! supports.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use nix::unistd::Pid;
+
+use phoenix_api::engine::{SchedulingClass, SchedulingMode};
+
+use super::group::GroupId;
+use super::manager::{RuntimeId, RuntimeManager, SubscriptionId};
+use super::EngineUpgrader;
+use crate::config::ScalingConfig;
+use crate::log;
+
+type GroupKey = (Pid, SubscriptionId, GroupId);
+
+#[derive(Debug, Default)]
+struct GroupLoad {
+    /// Busy time of the engines in the group at the last sample.
+    busy_ns: u64,
+    /// Utilization in the last interval.
+    utilization: f64,
+    /// Number of consecutive intervals under the scale-in threshold.
+    cold: u32,
+}
+
+pub(crate) struct Autoscaler {
+    interval: Duration,
+    scale_out_utilization: f64,
+    scale_in_utilization: f64,
+    sustained_intervals: u32,
+    last_sample: Instant,
+    groups: HashMap<GroupKey, GroupLoad>,
+    /// Number of consecutive intervals over the scale-out threshold of each runtime.
+    hot: HashMap<RuntimeId, u32>,
+    /// Groups moved to a runtime of their own, with the mode to go back to.
+    scaled_out: HashMap<GroupKey, SchedulingMode>,
+}
+
+impl Autoscaler {
+    pub(crate) fn new(config: &ScalingConfig) -> Self {
+        Autoscaler {
+            interval: Duration::from_millis(config.interval_ms),
+            scale_out_utilization: config.scale_out_utilization,
+            scale_in_utilization: config.scale_in_utilization,
+            sustained_intervals: config.sustained_intervals.max(1),
+            last_sample: Instant::now(),
+            groups: HashMap::new(),
+            hot: HashMap::new(),
+            scaled_out: HashMap::new(),
+        }
+    }
+
+    /// Samples the utilization once per interval and moves the scheduling groups accordingly.
+    pub(crate) fn tick(&mut self, rm: &RuntimeManager, upgrader: &mut EngineUpgrader) {
+        let elapsed = self.last_sample.elapsed();
+        if elapsed < self.interval {
+            return;
+        }
+        self.last_sample = Instant::now();
+
+        // the busy time, runtime and mode of each group
+        let mut current: HashMap<GroupKey, (u64, RuntimeId, SchedulingMode)> = HashMap::new();
+        for info in rm.engine_subscriptions.iter() {
+            let entry = current.entry((info.pid, info.sid, info.gid)).or_insert((
+                0,
+                info.rid,
+                info.scheduling_mode,
+            ));
+            entry.0 += info.stats.busy_ns();
+        }
+
+        self.groups.retain(|key, _| current.contains_key(key));
+        self.scaled_out.retain(|key, _| current.contains_key(key));
+
+        let mut runtime_load: HashMap<RuntimeId, (f64, Vec<GroupKey>)> = HashMap::new();
+        for (key, (busy_ns, rid, _mode)) in current.iter() {
+            let load = self.groups.entry(*key).or_insert_with(|| GroupLoad {
+                busy_ns: *busy_ns,
+                ..Default::default()
+            });
+            // engines are replaced across upgrades, which resets their stats
+            let delta = busy_ns.saturating_sub(load.busy_ns);
+            load.busy_ns = *busy_ns;
+            load.utilization = delta as f64 / elapsed.as_nanos() as f64;
+            if load.utilization < self.scale_in_utilization {
+                load.cold += 1;
+            } else {
+                load.cold = 0;
+            }
+            let runtime = runtime_load.entry(*rid).or_default();
+            runtime.0 += load.utilization;
+            runtime.1.push(*key);
+        }
+
+        self.hot.retain(|rid, _| runtime_load.contains_key(rid));
+        for (rid, (utilization, keys)) in runtime_load.iter() {
+            let hot = self.hot.entry(*rid).or_default();
+            // only a runtime shared by several groups can be relieved
+            if *utilization < self.scale_out_utilization || keys.len() < 2 {
+                *hot = 0;
+                continue;
+            }
+            *hot += 1;
+            if *hot < self.sustained_intervals {
+                continue;
+            }
+            *hot = 0;
+
+            let busiest = keys
+                .iter()
+                .filter(|key| !upgrader.is_upgrading(key.0))
+                // background groups are not worth a core of their own
+                .filter(|key| {
+                    rm.group_hints
+                        .get(key)
+                        .map_or(true, |hint| hint.class != Some(SchedulingClass::Background))
+                })
+                .max_by(|a, b| {
+                    self.groups[a]
+                        .utilization
+                        .total_cmp(&self.groups[b].utilization)
+                });
+            if let Some(key) = busiest {
+                let (_, _, mode) = current[key];
+                log::info!(
+                    "Runtime {:?} is {:.0}% busy, scaling out scheduling group {:?}",
+                    rid,
+                    utilization * 100.0,
+                    key,
+                );
+                match upgrader.migrate_group(key.0, key.1, key.2, SchedulingMode::Dedicate) {
+                    Ok(()) => {
+                        self.scaled_out.insert(*key, mode);
+                    }
+                    Err(e) => log::warn!("Failed to scale out {:?}: {}", key, e),
+                }
+            }
+        }
+
+        let scale_in = self
+            .scaled_out
+            .iter()
+            .filter(|(key, _)| self.groups[key].cold >= self.sustained_intervals)
+            .map(|(key, mode)| (*key, *mode))
+            .collect::<Vec<_>>();
+        for (key, mode) in scale_in {
+            if upgrader.is_upgrading(key.0) {
+                continue;
+            }
+            log::info!("Scaling in idle scheduling group {:?}", key);
+            match upgrader.migrate_group(key.0, key.1, key.2, mode) {
+                Ok(()) => {
+                    self.scaled_out.remove(&key);
+                }
+                Err(e) => log::warn!("Failed to scale in {:?}: {}", key, e),
+            }
+        }
+    }
+}
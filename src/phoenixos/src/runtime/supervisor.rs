@@ -0,0 +1,313 @@
+//! Supervision of the engines that fail.
+//!
+//! An engine fails when it panics or returns an error. Each subscription has a supervisor that
+//! decides, following its `SupervisorPolicy`, whether the failed engine is restarted after a
+//! backoff, the whole subscription is torn down, or the engine is kept without being resumed so
+//! that it can still be inspected with engine queries. A restarted engine keeps its state and its
+//! channels, only its future is activated again.
+//!
+//! The failures are logged and the most recent ones are kept in a `FailureLog`, which the control
+//! plane lists.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::unistd::Pid;
+
+use ipc::control::{EngineFailureInfo, SupervisorAction};
+use phoenix_common::engine::EngineType;
+
+use super::manager::{EngineId, SubscriptionId};
+use crate::config::{Escalation, FailurePolicy, SupervisorPolicy};
+use crate::log;
+
+/// The failures older than this many are forgotten.
+const MAX_FAILURES: usize = 128;
+/// The errors are truncated to this many characters, so that the list fits in a message.
+const MAX_ERROR_LEN: usize = 256;
+
+/// The most recent failures of the engines, shared by all the supervisors.
+#[derive(Debug, Default)]
+pub(crate) struct FailureLog {
+    failures: Mutex<VecDeque<EngineFailureInfo>>,
+}
+
+impl FailureLog {
+    fn record(&self, failure: EngineFailureInfo) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_FAILURES {
+            failures.pop_front();
+        }
+        failures.push_back(failure);
+    }
+
+    /// The recent failures, oldest first.
+    pub(crate) fn list(&self) -> Vec<EngineFailureInfo> {
+        self.failures.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// What to do with a failed engine.
+pub(crate) enum Verdict {
+    /// Resume the engine again after the backoff.
+    Restart(Duration),
+    /// Shut down the engine, the other engines of the subscription follow.
+    Teardown,
+    /// Stop resuming the engine.
+    KeepFailed,
+}
+
+/// The supervisor of a subscription, shared by the runtimes running its engines.
+#[derive(Debug)]
+pub(crate) struct Supervisor {
+    pid: Pid,
+    sid: SubscriptionId,
+    policy: SupervisorPolicy,
+    /// Number of restarts of the engines of the subscription so far.
+    restarts: AtomicU32,
+    /// Set when the subscription is torn down, its engines shut down on their next resume.
+    torn_down: AtomicBool,
+    failures: Arc<FailureLog>,
+}
+
+impl Supervisor {
+    pub(crate) fn new(
+        pid: Pid,
+        sid: SubscriptionId,
+        policy: SupervisorPolicy,
+        failures: Arc<FailureLog>,
+    ) -> Self {
+        Supervisor {
+            pid,
+            sid,
+            policy,
+            restarts: AtomicU32::new(0),
+            torn_down: AtomicBool::new(false),
+            failures,
+        }
+    }
+
+    /// Returns true if the engines of the subscription must shut down.
+    #[inline]
+    pub(crate) fn is_torn_down(&self) -> bool {
+        self.torn_down.load(Ordering::Relaxed)
+    }
+
+    /// Shuts down all the engines of the subscription on their next resume.
+    pub(crate) fn tear_down(&self) {
+        self.torn_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Decides what to do with an engine of the subscription that failed with `error`, and
+    /// reports the failure.
+    pub(crate) fn on_failure(
+        &self,
+        eid: EngineId,
+        engine_type: EngineType,
+        error: &str,
+    ) -> Verdict {
+        let restarts = self.restarts.load(Ordering::Relaxed);
+        let verdict = match self.policy.on_failure {
+            FailurePolicy::Restart if self.is_torn_down() => Verdict::Teardown,
+            FailurePolicy::Restart => {
+                let max_restarts = self.policy.max_restarts;
+                // the engines of a subscription may fail on several runtimes at once
+                let restarted =
+                    self.restarts
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                            (n < max_restarts).then_some(n + 1)
+                        });
+                match restarted {
+                    Ok(n) => Verdict::Restart(self.backoff(n)),
+                    Err(_) => match self.policy.escalation {
+                        Escalation::Teardown => Verdict::Teardown,
+                        Escalation::KeepFailed => Verdict::KeepFailed,
+                    },
+                }
+            }
+            FailurePolicy::Teardown => Verdict::Teardown,
+            FailurePolicy::KeepFailed => Verdict::KeepFailed,
+        };
+
+        let action = match verdict {
+            Verdict::Restart(backoff) => {
+                log::warn!(
+                    "Engine {:?} of pid={:?}, sid={:?} failed, restarting it in {:?}",
+                    engine_type,
+                    self.pid,
+                    self.sid,
+                    backoff
+                );
+                SupervisorAction::Restart(backoff.as_millis() as u64)
+            }
+            Verdict::Teardown => {
+                log::error!(
+                    "Engine {:?} of pid={:?}, sid={:?} failed, tearing down the subscription",
+                    engine_type,
+                    self.pid,
+                    self.sid
+                );
+                self.tear_down();
+                SupervisorAction::Teardown
+            }
+            Verdict::KeepFailed => {
+                log::error!(
+                    "Engine {:?} of pid={:?}, sid={:?} failed, keeping it for inspection",
+                    engine_type,
+                    self.pid,
+                    self.sid
+                );
+                SupervisorAction::KeepFailed
+            }
+        };
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        self.failures.record(EngineFailureInfo {
+            pid: self.pid.as_raw(),
+            sid: self.sid.0,
+            eid: eid.0,
+            engine_type: engine_type.0.to_owned(),
+            error: error.chars().take(MAX_ERROR_LEN).collect(),
+            restarts,
+            action,
+            timestamp_ms,
+        });
+        verdict
+    }
+
+    /// The backoff before the restart after `restarts` ones.
+    fn backoff(&self, restarts: u32) -> Duration {
+        let backoff_ms = self
+            .policy
+            .initial_backoff_ms
+            .saturating_mul(1u64.checked_shl(restarts).unwrap_or(u64::MAX))
+            .min(self.policy.max_backoff_ms);
+        Duration::from_millis(backoff_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENGINE: EngineType = EngineType("TestEngine");
+
+    fn supervisor(policy: SupervisorPolicy) -> Supervisor {
+        Supervisor::new(
+            Pid::from_raw(1),
+            SubscriptionId(1),
+            policy,
+            Arc::new(FailureLog::default()),
+        )
+    }
+
+    fn fail(supervisor: &Supervisor) -> Verdict {
+        supervisor.on_failure(EngineId(1), ENGINE, "failed")
+    }
+
+    fn restart_policy(escalation: Escalation) -> SupervisorPolicy {
+        SupervisorPolicy {
+            on_failure: FailurePolicy::Restart,
+            max_restarts: 3,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 25,
+            escalation,
+        }
+    }
+
+    #[test]
+    fn restart_with_backoff_then_escalate() {
+        let supervisor = supervisor(restart_policy(Escalation::Teardown));
+        let backoffs: Vec<_> = (0..3)
+            .map(|_| match fail(&supervisor) {
+                Verdict::Restart(backoff) => backoff.as_millis(),
+                _ => panic!("expected a restart"),
+            })
+            .collect();
+        // doubled, up to max_backoff_ms
+        assert_eq!(backoffs, [10, 20, 25]);
+        assert!(!supervisor.is_torn_down());
+
+        assert!(matches!(fail(&supervisor), Verdict::Teardown));
+        assert!(supervisor.is_torn_down());
+        // the other engines of the subscription follow
+        assert!(matches!(fail(&supervisor), Verdict::Teardown));
+    }
+
+    #[test]
+    fn escalate_to_keep_failed() {
+        let supervisor = supervisor(SupervisorPolicy {
+            max_restarts: 0,
+            ..restart_policy(Escalation::KeepFailed)
+        });
+        assert!(matches!(fail(&supervisor), Verdict::KeepFailed));
+        assert!(!supervisor.is_torn_down());
+    }
+
+    #[test]
+    fn fixed_policies() {
+        let teardown = supervisor(SupervisorPolicy {
+            on_failure: FailurePolicy::Teardown,
+            ..Default::default()
+        });
+        assert!(matches!(fail(&teardown), Verdict::Teardown));
+        assert!(teardown.is_torn_down());
+
+        let keep = supervisor(SupervisorPolicy {
+            on_failure: FailurePolicy::KeepFailed,
+            ..Default::default()
+        });
+        assert!(matches!(fail(&keep), Verdict::KeepFailed));
+        assert!(matches!(fail(&keep), Verdict::KeepFailed));
+        assert!(!keep.is_torn_down());
+    }
+
+    #[test]
+    fn backoff_saturates() {
+        let supervisor = supervisor(SupervisorPolicy {
+            initial_backoff_ms: u64::MAX / 2,
+            max_backoff_ms: u64::MAX,
+            ..Default::default()
+        });
+        assert_eq!(supervisor.backoff(3), Duration::from_millis(u64::MAX));
+        assert_eq!(supervisor.backoff(200), Duration::from_millis(u64::MAX));
+    }
+
+    #[test]
+    fn failures_recorded() {
+        let supervisor = supervisor(restart_policy(Escalation::Teardown));
+        let long_error = "e".repeat(2 * MAX_ERROR_LEN);
+        supervisor.on_failure(EngineId(2), ENGINE, &long_error);
+        fail(&supervisor);
+        let failures = supervisor.failures.list();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].eid, 2);
+        assert_eq!(failures[0].error.len(), MAX_ERROR_LEN);
+        assert_eq!(failures[0].restarts, 0);
+        assert_eq!(failures[0].action, SupervisorAction::Restart(10));
+        assert_eq!(failures[1].restarts, 1);
+        assert_eq!(failures[1].action, SupervisorAction::Restart(20));
+    }
+
+    #[test]
+    fn failure_log_bounded() {
+        let log = FailureLog::default();
+        let supervisor = Supervisor::new(
+            Pid::from_raw(1),
+            SubscriptionId(1),
+            SupervisorPolicy {
+                on_failure: FailurePolicy::KeepFailed,
+                ..Default::default()
+            },
+            Arc::new(log),
+        );
+        for eid in 0..MAX_FAILURES as u64 + 10 {
+            supervisor.on_failure(EngineId(eid), ENGINE, "failed");
+        }
+        let failures = supervisor.failures.list();
+        assert_eq!(failures.len(), MAX_FAILURES);
+        assert_eq!(failures[0].eid, 10);
+    }
+}
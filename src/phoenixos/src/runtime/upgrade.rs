@@ -2,6 +2,7 @@ use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::bail;
 use dashmap::DashSet;
@@ -9,6 +10,7 @@ use futures::executor::{ThreadPool, ThreadPoolBuilder};
 use nix::unistd::Pid;
 use semver::Version;
 
+use ipc::control::{PortDescriptor, ProgressStage};
 use phoenix_api::engine::{SchedulingHint, SchedulingMode};
 
 use phoenix_common::engine::datapath::{
@@ -22,12 +24,17 @@ use super::graph::DataPathGraph;
 use super::graph::{EndpointCollection, EndpointType, Error};
 use super::group::GroupId;
 use super::manager::{EngineId, EngineInfo, RuntimeId, RuntimeManager, SubscriptionId};
+use super::progress::Progress;
 use super::EngineContainer;
 
 use crate::plugin::PluginName;
 use crate::plugin_mgr::PluginManager;
 use crate::{log, tracing};
 
+/// How long the engines have to suspend before an upgrade gives up on them. An engine that
+/// never yields holds its runtime, which then never gets to suspend it.
+const SUSPEND_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub(crate) struct EngineUpgrader {
     runtime_manager: Arc<RuntimeManager>,
     plugins: Arc<PluginManager>,
@@ -44,29 +51,104 @@ struct EngineDumped {
     mode: SchedulingMode,
 }
 
-/// Attach an addon to a serivce subscription
-/// * group: the scheduling group to attach the addon to
+/// Waits for the runtimes to suspend the engines they have been requested to, and unregisters
+/// the engines suspended. The engines that have shut down meanwhile are left out.
+///
+/// If some engines are not suspended in [`SUSPEND_TIMEOUT`], their suspension is called off,
+/// the engines suspended so far are resumed in their scheduling groups, and an error is
+/// returned, so that one stuck engine does not hold the upgrades of all the others.
+fn wait_suspended(
+    rm: &Arc<RuntimeManager>,
+    mut engines: Vec<(EngineId, EngineInfo)>,
+    mut on_suspended: impl FnMut(&EngineInfo),
+) -> anyhow::Result<Vec<(EngineId, EngineContainer, EngineInfo)>> {
+    let start = Instant::now();
+    let mut stuck = Vec::new();
+    let mut suspended = Vec::with_capacity(engines.len());
+    while !engines.is_empty() {
+        let guard = rm.inner.lock().unwrap();
+        if stuck.is_empty() && start.elapsed() > SUSPEND_TIMEOUT {
+            // a request the runtime has taken is answered soon, so it is still waited for
+            engines.retain(|(eid, info)| {
+                let cancelled = guard.runtimes[&info.rid].cancel_suspend(*eid);
+                if cancelled {
+                    stuck.push(info.engine_type);
+                }
+                !cancelled
+            });
+        }
+        engines.retain(|(eid, info)| {
+            let runtime = guard.runtimes.get(&info.rid).unwrap();
+            if let Some((_, result)) = runtime.suspended.remove(eid) {
+                if let SuspendResult::Engine(container) = result {
+                    on_suspended(info);
+                    suspended.push((*eid, container, info.clone()));
+                    rm.engine_subscriptions.remove(eid);
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+    if stuck.is_empty() {
+        return Ok(suspended);
+    }
+
+    let mut groups: HashMap<GroupId, (EngineInfo, Vec<EngineContainer>)> = HashMap::new();
+    for (_, container, info) in suspended {
+        groups
+            .entry(info.gid)
+            .or_insert_with(|| (info.clone(), Vec::new()))
+            .1
+            .push(container);
+    }
+    for (gid, (info, containers)) in groups {
+        rm.attach_to_group(
+            info.pid,
+            info.sid,
+            gid,
+            info.rid,
+            containers,
+            info.scheduling_mode,
+        );
+    }
+    bail!(
+        "engines {:?} did not suspend in {:?}, the others are resumed",
+        stuck,
+        SUSPEND_TIMEOUT
+    )
+}
+
+/// An addon to attach, with the channel replacements that install it.
+pub(crate) struct AddonAttachment {
+    pub(crate) addon: EngineType,
+    pub(crate) tx_edges_replacement: Vec<ChannelDescriptor>,
+    pub(crate) rx_edges_replacement: Vec<ChannelDescriptor>,
+    pub(crate) ports: Vec<PortDescriptor>,
+    pub(crate) config_string: Option<String>,
+}
+
+/// Attach addons to a serivce subscription, one after another, while its engines are
+/// suspended. The channel replacements of an addon may refer to the addons attached before it.
+/// * group: the scheduling group to attach the addons to
 #[allow(clippy::too_many_arguments)]
-async fn attach_addon<I>(
+async fn attach_addons(
     rm: Arc<RuntimeManager>,
     plugins: Arc<PluginManager>,
     pid: Pid,
     sid: SubscriptionId,
-    addon: EngineType,
+    addons: Vec<AddonAttachment>,
     mode: SchedulingMode,
-    tx_edges_replacement: I,
-    rx_edges_replacement: I,
     group: HashSet<EngineType>,
-    config_string: Option<String>,
     indicator: Arc<DashSet<Pid>>,
-) where
-    I: IntoIterator<Item = ChannelDescriptor>,
-{
-    let mut subscription_engines = rm
+    progress: Progress,
+) {
+    let subscription_engines = rm
         .engine_subscriptions
         .iter()
         .filter(|e| e.pid == pid && e.sid == sid)
-        .map(|e| (*e.key(), *e.value()))
+        .map(|e| (*e.key(), e.value().clone()))
         .collect::<Vec<_>>();
 
     if subscription_engines.is_empty() {
@@ -75,6 +157,10 @@ async fn attach_addon<I>(
             pid,
             sid,
         );
+        progress.fail(format_args!(
+            "no engines exist for subscription (pid={:?}, sid={:?})",
+            pid, sid
+        ));
         indicator.remove(&pid);
         return;
     }
@@ -86,38 +172,45 @@ async fn attach_addon<I>(
     }
     drop(guard);
 
-    let (mut subscription, _) = rm.service_subscriptions.remove(&(pid, sid)).unwrap().1;
-    if subscription.addons.contains(&addon) {
+    let (mut subscription, prev_count) = rm.service_subscriptions.remove(&(pid, sid)).unwrap().1;
+    if let Some(addon) = addons
+        .iter()
+        .map(|a| a.addon)
+        .find(|addon| subscription.addons.contains(addon))
+    {
         log::error!(
             "Addon engine {:?} already exists in service subscription (pid={:?}, sid={:?})",
             addon,
             pid,
             sid,
         );
+        progress.fail(format_args!("addon engine {:?} already exists", addon));
         rm.global_resource_mgr.register_subscription_shutdown(pid);
         indicator.remove(&pid);
         return;
     }
-    let mut engine_containers = Vec::with_capacity(subscription_engines.len());
-    while !subscription_engines.is_empty() {
-        let guard = rm.inner.lock().unwrap();
-        subscription_engines.retain(|(eid, info)| {
-            let runtime = guard.runtimes.get(&info.rid).unwrap();
-            if let Some((_, result)) = runtime.suspended.remove(eid) {
-                if let SuspendResult::Engine(container) = result {
-                    engine_containers.push((container, *info));
-                    rm.engine_subscriptions.remove(eid);
-                }
-                false
-            } else {
-                true
-            }
-        });
-    }
+    let engine_containers = match wait_suspended(&rm, subscription_engines, |info| {
+        progress.report(pid, sid, Some(info.engine_type), ProgressStage::Suspended);
+    }) {
+        Ok(engine_containers) => engine_containers,
+        Err(err) => {
+            log::error!(
+                "Fail to suspend the engines of subscription (pid={:?}, sid={:?}): {:?}",
+                pid,
+                sid,
+                err,
+            );
+            progress.fail(format_args!("{}", err));
+            rm.service_subscriptions
+                .insert((pid, sid), (subscription, prev_count));
+            indicator.remove(&pid);
+            return;
+        }
+    };
 
     let mut detached_engines = HashMap::with_capacity(engine_containers.len());
     let mut detached_meta = HashMap::with_capacity(engine_containers.len());
-    for (container, info) in engine_containers {
+    for (_, container, info) in engine_containers {
         let engine_type = info.engine_type;
         let version = container.version();
         let engine = container.detach();
@@ -160,107 +253,151 @@ async fn attach_addon<I>(
             );
         }
     }
+    for engine_type in detached_engines.keys() {
+        progress.report(pid, sid, Some(*engine_type), ProgressStage::Flushed);
+    }
 
-    let node = match refactor_channels_attach_addon(
-        &mut detached_engines,
-        &mut subscription.graph,
-        addon,
-        tx_edges_replacement,
-        rx_edges_replacement,
-        &group,
-    ) {
-        Ok(node) => node,
-        Err(err) => {
-            log::error!(
-                "Fail to refactor data path channels in installing addon {:?} on subscription (pid={:?}, sid={:?}): {:?}",
-                addon,
-                pid,
-                sid,
-                err,
-            );
-            // discard the service subscription
-            // do not resubmit the engines
-            rm.global_resource_mgr.register_subscription_shutdown(pid);
-            indicator.remove(&pid);
-            return;
-        }
+    // get scheduling group ID and runtime ID for the addons
+    let (addon_gid, rid) = if !group.is_empty() {
+        let peer = *group.iter().next().unwrap();
+        let (info, _) = detached_meta.get(&peer).unwrap();
+        (info.gid, Some(info.rid))
+    } else {
+        let gid = GroupId(rm.scheduling_group_counter.fetch_add(1, Ordering::Relaxed));
+        (gid, None)
     };
 
-    // get the addon from the engine_registry
-    let mut plugin = match plugins.engine_registry.get_mut(&addon) {
-        Some(plugin) => match &plugin.value().0 {
-            PluginName::Module(_) => {
-                log::error!("Engine type {:?} is not an addon", addon);
+    // the addons attached so far are in the scheduling group as well
+    let mut members = group;
+    let mut attached = Vec::with_capacity(addons.len());
+    for attachment in addons {
+        let AddonAttachment {
+            addon,
+            tx_edges_replacement,
+            rx_edges_replacement,
+            ports,
+            config_string,
+        } = attachment;
+        let node = match refactor_channels_attach_addon(
+            &mut detached_engines,
+            &mut subscription.graph,
+            addon,
+            tx_edges_replacement,
+            rx_edges_replacement,
+            &members,
+        )
+        .map_err(anyhow::Error::from)
+        .and_then(|mut node| {
+            for port in ports {
+                node.declare_port(port.endpoint, port.name, port.channels, port.fan_out)?;
+            }
+            Ok(node)
+        }) {
+            Ok(node) => node,
+            Err(err) => {
+                log::error!(
+                    "Fail to refactor data path channels in installing addon {:?} on subscription (pid={:?}, sid={:?}): {:?}",
+                    addon,
+                    pid,
+                    sid,
+                    err,
+                );
+                progress.fail(format_args!(
+                    "failed to refactor data path channels: {}",
+                    err
+                ));
+                // discard the service subscription
+                // do not resubmit the engines
                 rm.global_resource_mgr.register_subscription_shutdown(pid);
+                indicator.remove(&pid);
                 return;
             }
-            PluginName::Addon(addon_name) => plugins.addons.get_mut(addon_name).unwrap(),
-        },
-        None => {
-            log::error!("Addon for engine type {:?} not found", addon);
-            rm.global_resource_mgr.register_subscription_shutdown(pid);
-            indicator.remove(&pid);
-            return;
-        }
-    };
+        };
 
-    // update config if found necessary
-    if let Some(config) = config_string {
-        if let Err(err) = plugin.update_config(&config) {
-            log::error!(
-                "Failed to update config for addon: {:?}, err: {:?}, attach aborted",
-                addon,
-                err
-            );
-            return;
-        }
-    }
+        // get the addon from the engine_registry
+        let mut plugin = match plugins.engine_registry.get_mut(&addon) {
+            Some(plugin) => match &plugin.value().0 {
+                PluginName::Module(_) => {
+                    log::error!("Engine type {:?} is not an addon", addon);
+                    progress.fail(format_args!("engine type {:?} is not an addon", addon));
+                    rm.global_resource_mgr.register_subscription_shutdown(pid);
+                    return;
+                }
+                PluginName::Addon(addon_name) => plugins.addons.get_mut(addon_name).unwrap(),
+            },
+            None => {
+                log::error!("Addon for engine type {:?} not found", addon);
+                progress.fail(format_args!("addon for engine type {:?} not found", addon));
+                rm.global_resource_mgr.register_subscription_shutdown(pid);
+                indicator.remove(&pid);
+                return;
+            }
+        };
 
-    // create engine from the module
-    let addon_engine = match plugin.value_mut().create_engine(addon, pid, node) {
-        Ok(engine) => engine,
-        Err(err) => {
-            log::error!(
-                "Failed to create addon engine {:?} for subscription (pid={:?}, sid={:?}), error: {:?}",
-                addon,
-                pid,
-                sid,
-                err,
-            );
-            rm.global_resource_mgr.register_subscription_shutdown(pid);
-            indicator.remove(&pid);
-            return;
+        // update config if found necessary
+        if let Some(config) = config_string {
+            if let Err(err) = plugin.update_config(&config) {
+                log::error!(
+                    "Failed to update config for addon: {:?}, err: {:?}, attach aborted",
+                    addon,
+                    err
+                );
+                progress.fail(format_args!("failed to update config: {}", err));
+                return;
+            }
         }
-    };
 
-    // create EngineContainer
-    let version = plugin.version();
-    let container = EngineContainer::new(addon_engine, addon, version);
+        // create engine from the module
+        let addon_engine = match plugin.value_mut().create_engine(addon, pid, node) {
+            Ok(engine) => engine,
+            Err(err) => {
+                log::error!(
+                    "Failed to create addon engine {:?} for subscription (pid={:?}, sid={:?}), error: {:?}",
+                    addon,
+                    pid,
+                    sid,
+                    err,
+                );
+                progress.fail(format_args!("failed to create addon engine: {}", err));
+                rm.global_resource_mgr.register_subscription_shutdown(pid);
+                indicator.remove(&pid);
+                return;
+            }
+        };
+        progress.report(pid, sid, Some(addon), ProgressStage::Created);
 
-    // get scheduling group ID and runtime ID for the addon
-    let (addon_gid, rid) = if !group.is_empty() {
-        let peer = *group.iter().next().unwrap();
-        let (info, _) = detached_meta.get(&peer).unwrap();
-        (info.gid, Some(info.rid))
-    } else {
-        let gid = GroupId(rm.scheduling_group_counter.fetch_add(1, Ordering::Relaxed));
-        (gid, None)
-    };
+        // the addons attached next may replace its channels
+        detached_engines.insert(addon, addon_engine);
+        attached.push((addon, plugin.version()));
+        members.insert(addon);
 
-    let mut containers_resubmit: HashMap<_, _> =
-        std::iter::once((addon_gid, (vec![container], mode, rid))).collect();
+        log::info!(
+            "Addon engine {:?} created, pid={:?}, sid={:?}, gid={:?}",
+            addon,
+            pid,
+            sid,
+            addon_gid,
+        );
+    }
 
-    log::info!(
-        "Addon engine {:?} created, pid={:?}, sid={:?}, gid={:?}",
-        addon,
-        pid,
-        sid,
-        addon_gid,
-    );
+    // create EngineContainers
+    let mut addon_containers = Vec::with_capacity(attached.len());
+    for (addon, version) in attached.iter() {
+        let addon_engine = detached_engines.remove(addon).unwrap();
+        addon_containers.push(EngineContainer::new(
+            addon_engine,
+            *addon,
+            version.clone(),
+            plugins.engine_module(*addon),
+        ));
+    }
+    let mut containers_resubmit: HashMap<_, _> =
+        std::iter::once((addon_gid, (addon_containers, mode, rid))).collect();
 
     for (ty, engine) in detached_engines.into_iter() {
         let (info, version) = detached_meta.remove(&ty).unwrap();
-        let container = EngineContainer::new(engine, ty, version);
+        let module = plugins.engine_module(ty);
+        let container = EngineContainer::new(engine, ty, version, module);
         let gid = info.gid;
         let rid = info.rid;
         containers_resubmit
@@ -274,21 +411,27 @@ async fn attach_addon<I>(
     let (addon_group_engines, ..) = containers_resubmit.get(&addon_gid).unwrap();
     for engine in addon_group_engines {
         let engine_type = engine.engine_type();
-        if (engine_type != addon) && !group.contains(&engine_type) {
+        if !members.contains(&engine_type) {
             log::error!(
-                "Scheduling group {:?} to attach addon {:?} to subscription (pid={:?}, sid={:?}) does not contain all engines in the group",
-                group,
-                addon,
+                "Scheduling group {:?} to attach addons {:?} to subscription (pid={:?}, sid={:?}) does not contain all engines in the group",
+                members,
+                attached,
                 pid,
                 sid,
             );
+            progress.fail(format_args!(
+                "scheduling group {:?} does not contain all engines in the group",
+                members
+            ));
             rm.global_resource_mgr.register_subscription_shutdown(pid);
             indicator.remove(&pid);
             return;
         }
     }
 
-    subscription.addons.push(addon);
+    subscription
+        .addons
+        .extend(attached.iter().map(|(addon, _)| *addon));
 
     let engines_count = containers_resubmit
         .iter()
@@ -300,38 +443,45 @@ async fn attach_addon<I>(
         if let Some(rid) = rid {
             rm.attach_to_group(pid, sid, group_id, rid, containers, mode);
         } else {
-            rm.submit_group(
-                pid,
-                sid,
-                containers,
+            let hint = SchedulingHint {
                 mode,
-                SchedulingHint {
-                    mode,
-                    numa_node_affinity: None,
-                },
-            );
+                numa_node_affinity: None,
+                latency_budget_us: None,
+                class: None,
+            };
+            if let Err(e) = rm.submit_group(pid, sid, containers, mode, hint) {
+                log::error!(
+                    "Failed to resubmit a scheduling group (pid={:?}, sid={:?}), its engines are dropped: {}",
+                    pid,
+                    sid,
+                    e
+                );
+            }
         }
     }
+    progress.report(pid, sid, None, ProgressStage::Resubmitted);
     indicator.remove(&pid);
 }
 
 /// Detach an addon from a service subscription
 async fn detach_addon<I>(
     rm: Arc<RuntimeManager>,
+    plugins: Arc<PluginManager>,
     pid: Pid,
     sid: SubscriptionId,
     addon: EngineType,
     tx_edges_replacement: I,
     rx_edges_replacement: I,
     indicator: Arc<DashSet<Pid>>,
+    progress: Progress,
 ) where
     I: IntoIterator<Item = ChannelDescriptor>,
 {
-    let mut subscription_engines = rm
+    let subscription_engines = rm
         .engine_subscriptions
         .iter()
         .filter(|e| e.pid == pid && e.sid == sid)
-        .map(|e| (*e.key(), *e.value()))
+        .map(|e| (*e.key(), e.value().clone()))
         .collect::<Vec<_>>();
 
     if subscription_engines.is_empty() {
@@ -340,6 +490,10 @@ async fn detach_addon<I>(
             pid,
             sid,
         );
+        progress.fail(format_args!(
+            "no engines exist for subscription (pid={:?}, sid={:?})",
+            pid, sid
+        ));
         indicator.remove(&pid);
         return;
     }
@@ -351,41 +505,47 @@ async fn detach_addon<I>(
     }
     drop(guard);
 
-    let (mut subscription, _) = rm.service_subscriptions.remove(&(pid, sid)).unwrap().1;
+    let (mut subscription, prev_count) = rm.service_subscriptions.remove(&(pid, sid)).unwrap().1;
 
-    if let Some(index) = subscription.addons.iter().position(|x| *x == addon) {
-        subscription.addons.remove(index);
-    } else {
-        log::error!(
-            "Addon engine {:?} not found in subscription (pid={:?}, gid={:?})",
-            addon,
-            pid,
-            sid,
-        );
-        rm.global_resource_mgr.register_subscription_shutdown(pid);
-        indicator.remove(&pid);
-        return;
-    }
-    let mut engine_containers = Vec::with_capacity(subscription_engines.len());
-    while !subscription_engines.is_empty() {
-        let guard = rm.inner.lock().unwrap();
-        subscription_engines.retain(|(eid, info)| {
-            let runtime = guard.runtimes.get(&info.rid).unwrap();
-            if let Some((_, result)) = runtime.suspended.remove(eid) {
-                if let SuspendResult::Engine(container) = result {
-                    engine_containers.push((container, *info));
-                    rm.engine_subscriptions.remove(eid);
-                }
-                false
-            } else {
-                true
-            }
-        });
-    }
+    let index = match subscription.addons.iter().position(|x| *x == addon) {
+        Some(index) => index,
+        None => {
+            log::error!(
+                "Addon engine {:?} not found in subscription (pid={:?}, gid={:?})",
+                addon,
+                pid,
+                sid,
+            );
+            progress.fail(format_args!("addon engine {:?} not found", addon));
+            rm.global_resource_mgr.register_subscription_shutdown(pid);
+            indicator.remove(&pid);
+            return;
+        }
+    };
+    subscription.addons.remove(index);
+    let engine_containers = match wait_suspended(&rm, subscription_engines, |info| {
+        progress.report(pid, sid, Some(info.engine_type), ProgressStage::Suspended);
+    }) {
+        Ok(engine_containers) => engine_containers,
+        Err(err) => {
+            log::error!(
+                "Fail to suspend the engines of subscription (pid={:?}, sid={:?}): {:?}",
+                pid,
+                sid,
+                err,
+            );
+            progress.fail(format_args!("{}", err));
+            subscription.addons.insert(index, addon);
+            rm.service_subscriptions
+                .insert((pid, sid), (subscription, prev_count));
+            indicator.remove(&pid);
+            return;
+        }
+    };
 
     let mut detached_engines = HashMap::with_capacity(engine_containers.len());
     let mut detached_meta = HashMap::with_capacity(engine_containers.len());
-    for (container, info) in engine_containers {
+    for (_, container, info) in engine_containers {
         let engine_type = info.engine_type;
         let version = container.version();
         let engine = container.detach();
@@ -429,6 +589,9 @@ async fn detach_addon<I>(
             );
         }
     }
+    for engine_type in detached_engines.keys() {
+        progress.report(pid, sid, Some(*engine_type), ProgressStage::Flushed);
+    }
 
     let result = refactor_channels_detach_addon(
         &mut detached_engines,
@@ -446,15 +609,21 @@ async fn detach_addon<I>(
             sid,
             err,
         );
+        progress.fail(format_args!(
+            "failed to refactor data path channels: {}",
+            err
+        ));
         rm.global_resource_mgr.register_subscription_shutdown(pid);
         indicator.remove(&pid);
         return;
     }
+    progress.report(pid, sid, Some(addon), ProgressStage::Removed);
 
     let mut containers_resubmit = HashMap::new();
     for (ty, (engine, _)) in detached_engines.into_iter() {
         let (info, version) = detached_meta.remove(&ty).unwrap();
-        let container = EngineContainer::new(engine, ty, version);
+        let module = plugins.engine_module(ty);
+        let container = EngineContainer::new(engine, ty, version, module);
         let gid = info.gid;
         let rid = info.rid;
         let entry =
@@ -473,6 +642,7 @@ async fn detach_addon<I>(
     for (group_id, (containers, mode, rid)) in containers_resubmit {
         rm.attach_to_group(pid, sid, group_id, rid, containers, mode);
     }
+    progress.report(pid, sid, None, ProgressStage::Resubmitted);
     indicator.remove(&pid);
 }
 
@@ -493,10 +663,11 @@ async fn upgrade_client(
     rm: Arc<RuntimeManager>,
     plugins: Arc<PluginManager>,
     pid: Pid,
-    mut to_upgrade: Vec<(EngineId, EngineInfo)>,
-    mut to_suspend: Vec<(EngineId, EngineInfo)>,
+    to_upgrade: Vec<(EngineId, EngineInfo)>,
+    to_suspend: Vec<(EngineId, EngineInfo)>,
     flush: bool,
     indicator: Arc<DashSet<Pid>>,
+    progress: Progress,
 ) {
     let guard = rm.inner.lock().unwrap();
     for (engine_id, info) in to_upgrade.iter().chain(to_suspend.iter()) {
@@ -505,53 +676,60 @@ async fn upgrade_client(
     }
     drop(guard);
 
+    let upgrade_ids = to_upgrade
+        .iter()
+        .map(|(eid, _)| *eid)
+        .collect::<HashSet<_>>();
+    // the engines are removed from the engine subscriptions, but the reference count of their
+    // service subscription is not decreased. If all engines within the group have already shut
+    // down, the entry from `rm.service_subscriptions` should have already been removed, and if
+    // the group is the last active group for pid, so has the entry in
+    // `rm.global_resource_mgr`.
+    let suspended = match wait_suspended(
+        &rm,
+        to_upgrade.into_iter().chain(to_suspend).collect(),
+        |info| {
+            progress.report(
+                pid,
+                info.sid,
+                Some(info.engine_type),
+                ProgressStage::Suspended,
+            );
+        },
+    ) {
+        Ok(suspended) => suspended,
+        Err(err) => {
+            log::error!(
+                "Fail to suspend the engines of client pid={:?}: {:?}",
+                pid,
+                err
+            );
+            progress.fail(format_args!("{}", err));
+            indicator.remove(&pid);
+            return;
+        }
+    };
+
     // EngineContainers suspended from runtimes, awaiting for upgrade
     let mut engines_to_upgrade = HashMap::new();
     // EngineContainers for engines in the same engine subscription
     // that do not need update, but need to suspend from runtimes,
     let mut containers_suspended = HashMap::new();
-    while !to_upgrade.is_empty() || !to_suspend.is_empty() {
-        let guard = rm.inner.lock().unwrap();
-        to_upgrade.retain(|(eid, info)| {
-            let runtime = guard.runtimes.get(&info.rid).unwrap();
-            if let Some((_, result)) = runtime.suspended.remove(eid) {
-                if let SuspendResult::Engine(container) = result {
-                    let subscription = engines_to_upgrade
-                        .entry(info.sid)
-                        .or_insert_with(HashMap::new);
-                    let engine_type = container.engine_type();
-                    let version = container.version();
-                    let engine = container.detach();
-                    subscription.insert(engine_type, (engine, *info, version));
-                    // remove the engine from subscriptions
-                    // but we don't decrease the reference count
-                    // of the corresponding service subscription
-                    rm.engine_subscriptions.remove(eid);
-                }
-                // if all engines with in the group is already shutdown
-                // the entry from `rm.service_subscriptions` should have already been removed
-                // if the group is the last active group for pid,
-                // the entry in `rm.global_resource_mgr` has also been removed.
-                false
-            } else {
-                true
-            }
-        });
-        to_suspend.retain(|(eid, info)| {
-            let runtime = guard.runtimes.get(&info.rid).unwrap();
-            if let Some((_, result)) = runtime.suspended.remove(eid) {
-                if let SuspendResult::Engine(container) = result {
-                    let subscription = containers_suspended
-                        .entry(info.sid)
-                        .or_insert_with(Vec::new);
-                    subscription.push((container, *info));
-                    rm.engine_subscriptions.remove(eid);
-                }
-                false
-            } else {
-                true
-            }
-        });
+    for (eid, container, info) in suspended {
+        if upgrade_ids.contains(&eid) {
+            let subscription = engines_to_upgrade
+                .entry(info.sid)
+                .or_insert_with(HashMap::new);
+            let engine_type = container.engine_type();
+            let version = container.version();
+            let engine = container.detach();
+            subscription.insert(engine_type, (engine, info, version));
+        } else {
+            let subscription = containers_suspended
+                .entry(info.sid)
+                .or_insert_with(Vec::new);
+            subscription.push((container, info));
+        }
     }
     let subscribed = engines_to_upgrade
         .keys()
@@ -611,6 +789,7 @@ async fn upgrade_client(
                     sid,
                     engine_type,
                 );
+                progress.report(pid, *sid, Some(engine_type), ProgressStage::Flushed);
             }
         }
     }
@@ -627,6 +806,7 @@ async fn upgrade_client(
                 sid,
                 engine_type,
             );
+            progress.report(pid, sid, Some(engine_type), ProgressStage::Dumped);
             let entry = local_states.entry(sid).or_insert_with(HashMap::new);
             let dumped = EngineDumped {
                 local_states: state,
@@ -767,8 +947,13 @@ async fn upgrade_client(
                     };
                     match engine {
                         Ok(engine) => {
-                            let container =
-                                EngineContainer::new(engine, *subscribed_engine_ty, new_version);
+                            let module = plugins.engine_module(*subscribed_engine_ty);
+                            let container = EngineContainer::new(
+                                engine,
+                                *subscribed_engine_ty,
+                                new_version,
+                                module,
+                            );
                             let gid = dumped.gid;
                             let rid = dumped.rid;
                             let entry = containers_resubmit.entry(gid).or_insert((
@@ -783,6 +968,12 @@ async fn upgrade_client(
                                 sid,
                                 subscribed_engine_ty,
                             );
+                            progress.report(
+                                pid,
+                                sid,
+                                Some(*subscribed_engine_ty),
+                                ProgressStage::Restored,
+                            );
                         }
                         Err(err) => {
                             log::error!(
@@ -792,6 +983,10 @@ async fn upgrade_client(
                                 subscribed_engine_ty,
                                 err,
                             );
+                            progress.fail(format_args!(
+                                "failed to restore engine {:?}: {}",
+                                subscribed_engine_ty, err
+                            ));
                             resubmit = false;
                             break;
                         }
@@ -805,6 +1000,7 @@ async fn upgrade_client(
             for (group_id, (containers, mode, rid)) in containers_resubmit {
                 rm.attach_to_group(pid, sid, group_id, rid, containers, mode);
             }
+            progress.report(pid, sid, None, ProgressStage::Resubmitted);
         } else {
             // error has occurred, rollback
             // cancel all pending submission
@@ -822,7 +1018,108 @@ async fn upgrade_client(
 
     indicator.remove(&pid);
     if indicator.is_empty() {
-        plugins.upgrade_cleanup();
+        plugins.upgrade_cleanup(&rm);
+    }
+}
+
+/// Move scheduling groups to runtimes chosen for `mode`, one after another.
+async fn migrate_groups(
+    rm: Arc<RuntimeManager>,
+    pid: Pid,
+    sid: SubscriptionId,
+    gids: Vec<GroupId>,
+    mode: SchedulingMode,
+    indicator: Arc<DashSet<Pid>>,
+) {
+    for gid in gids {
+        migrate_group(&rm, pid, sid, gid, mode);
+    }
+    indicator.remove(&pid);
+}
+
+/// Move a scheduling group to a runtime chosen for `mode`, without detaching its engines.
+fn migrate_group(
+    rm: &Arc<RuntimeManager>,
+    pid: Pid,
+    sid: SubscriptionId,
+    gid: GroupId,
+    mode: SchedulingMode,
+) {
+    let group_engines = rm
+        .engine_subscriptions
+        .iter()
+        .filter(|e| e.pid == pid && e.sid == sid && e.gid == gid)
+        .map(|e| (*e.key(), e.value().clone()))
+        .collect::<Vec<_>>();
+
+    if group_engines.is_empty() {
+        log::warn!(
+            "No engines exist for scheduling group (pid={:?}, sid={:?}, gid={:?})",
+            pid,
+            sid,
+            gid,
+        );
+        return;
+    }
+    let prev_rid = group_engines[0].1.rid;
+
+    let guard = rm.inner.lock().unwrap();
+    for (engine_id, info) in group_engines.iter() {
+        let runtime = guard.runtimes.get(&info.rid).unwrap();
+        runtime.request_suspend(*engine_id);
+    }
+    drop(guard);
+
+    // an engine that is not found has already shut down and been unregistered
+    let containers = match wait_suspended(rm, group_engines, |_| {}) {
+        Ok(suspended) => suspended
+            .into_iter()
+            .map(|(_, container, _)| container)
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            log::error!(
+                "Fail to migrate scheduling group (pid={:?}, sid={:?}, gid={:?}): {:?}",
+                pid,
+                sid,
+                gid,
+                err,
+            );
+            return;
+        }
+    };
+
+    rm.inner.lock().unwrap().runtimes[&prev_rid].remove_group(gid);
+
+    if !containers.is_empty() {
+        let hint = rm
+            .group_hints
+            .get(&(pid, sid, gid))
+            .map(|hint| *hint)
+            .unwrap_or_default();
+        log::info!(
+            "Migrating scheduling group (pid={:?}, sid={:?}, gid={:?}) from runtime {:?} to a {:?} runtime",
+            pid,
+            sid,
+            gid,
+            prev_rid,
+            mode,
+        );
+        if let Err(e) = rm.submit_group_with_id(
+            pid,
+            sid,
+            gid,
+            containers,
+            mode,
+            SchedulingHint { mode, ..hint },
+        ) {
+            log::error!(
+                "Failed to migrate scheduling group (pid={:?}, sid={:?}, gid={:?}), its engines are dropped: {}",
+                pid,
+                sid,
+                gid,
+                e
+            );
+        }
     }
 }
 
@@ -848,7 +1145,9 @@ impl EngineUpgrader {
         tx_edges_replacement: I,
         rx_edges_replacement: I,
         group: HashSet<EngineType>,
+        ports: Vec<PortDescriptor>,
         config_string: Option<String>,
+        progress: Progress,
     ) -> anyhow::Result<()>
     where
         I: IntoIterator<Item = ChannelDescriptor> + Send + 'static,
@@ -860,18 +1159,88 @@ impl EngineUpgrader {
             )
         }
         self.upgrade_indicator.insert(pid);
-        let fut = attach_addon(
+        let attachment = AddonAttachment {
+            addon,
+            tx_edges_replacement: tx_edges_replacement.into_iter().collect(),
+            rx_edges_replacement: rx_edges_replacement.into_iter().collect(),
+            ports,
+            config_string,
+        };
+        let fut = attach_addons(
             self.runtime_manager.clone(),
             self.plugins.clone(),
             pid,
             gid,
-            addon,
+            vec![attachment],
+            mode,
+            group,
+            Arc::clone(&self.upgrade_indicator),
+            progress,
+        );
+        self.executor.spawn_ok(fut);
+        Ok(())
+    }
+
+    /// Attach a chain of addons, in order, on the tx channel from `upstream` to `downstream`
+    /// of a service subscription, and on the rx channel back. The data path only resumes once
+    /// all of them are attached.
+    /// * addons: the addons, each with whether it is tx-only and its new config
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    pub(crate) fn attach_chain(
+        &mut self,
+        pid: Pid,
+        sid: SubscriptionId,
+        upstream: EngineType,
+        downstream: EngineType,
+        addons: Vec<(EngineType, bool, Option<String>)>,
+        mode: SchedulingMode,
+        group: HashSet<EngineType>,
+        progress: Progress,
+    ) -> anyhow::Result<()> {
+        if self.upgrade_indicator.contains(&pid) {
+            bail!(
+                "there is already an ongoing upgrade for client pid={:?}",
+                pid
+            )
+        }
+        let types = addons
+            .iter()
+            .map(|(addon, tx_only, _)| (*addon, *tx_only))
+            .collect::<Vec<_>>();
+        let replacements = match self.runtime_manager.service_subscriptions.get(&(pid, sid)) {
+            Some(subscription) => subscription
+                .0
+                .graph
+                .chain_replacements(upstream, downstream, &types)?,
+            None => bail!(
+                "service subscription (pid={:?}, sid={:?}) not found",
+                pid,
+                sid
+            ),
+        };
+        let attachments = addons
+            .into_iter()
+            .zip(replacements)
+            .map(|((addon, _, config_string), (tx, rx))| AddonAttachment {
+                addon,
+                tx_edges_replacement: tx,
+                rx_edges_replacement: rx,
+                ports: Vec::new(),
+                config_string,
+            })
+            .collect();
+
+        self.upgrade_indicator.insert(pid);
+        let fut = attach_addons(
+            self.runtime_manager.clone(),
+            self.plugins.clone(),
+            pid,
+            sid,
+            attachments,
             mode,
-            tx_edges_replacement,
-            rx_edges_replacement,
             group,
-            config_string,
             Arc::clone(&self.upgrade_indicator),
+            progress,
         );
         self.executor.spawn_ok(fut);
         Ok(())
@@ -885,6 +1254,7 @@ impl EngineUpgrader {
         addon: EngineType,
         tx_edges_replacement: I,
         rx_edges_replacement: I,
+        progress: Progress,
     ) -> anyhow::Result<()>
     where
         I: IntoIterator<Item = ChannelDescriptor> + Send + 'static,
@@ -898,27 +1268,72 @@ impl EngineUpgrader {
         self.upgrade_indicator.insert(pid);
         let fut = detach_addon(
             self.runtime_manager.clone(),
+            self.plugins.clone(),
             pid,
             gid,
             addon,
             tx_edges_replacement,
             rx_edges_replacement,
             Arc::clone(&self.upgrade_indicator),
+            progress,
+        );
+        self.executor.spawn_ok(fut);
+        Ok(())
+    }
+
+    /// Move a scheduling group of a service subscription to a runtime chosen for `mode`.
+    pub(crate) fn migrate_group(
+        &mut self,
+        pid: Pid,
+        sid: SubscriptionId,
+        gid: GroupId,
+        mode: SchedulingMode,
+    ) -> anyhow::Result<()> {
+        self.migrate_groups(pid, sid, vec![gid], mode)
+    }
+
+    /// Move scheduling groups of a service subscription to runtimes chosen for `mode`.
+    pub(crate) fn migrate_groups(
+        &mut self,
+        pid: Pid,
+        sid: SubscriptionId,
+        gids: Vec<GroupId>,
+        mode: SchedulingMode,
+    ) -> anyhow::Result<()> {
+        if self.upgrade_indicator.contains(&pid) {
+            bail!(
+                "there is already an ongoing upgrade for client pid={:?}",
+                pid
+            )
+        }
+        self.upgrade_indicator.insert(pid);
+        let fut = migrate_groups(
+            self.runtime_manager.clone(),
+            pid,
+            sid,
+            gids,
+            mode,
+            Arc::clone(&self.upgrade_indicator),
         );
         self.executor.spawn_ok(fut);
         Ok(())
     }
+
     /// Live upgrade existing clients
     /// Arguments:
     /// * engine_types: engines that need to be upgraded
     /// * flush: whether to flush the queues for the engines to be upgraded
     /// * detach_subscription: whether to suspend/detach all engines in each service subscription,
     ///     even the engine does not need upgrade, this is generally required to flush queues
+    /// * progress: where the steps of the upgrades of all the clients go
+    /// * subscriptions: only upgrade the engines of these subscriptions, all if `None`
     pub(crate) fn upgrade(
         &mut self,
         engine_types: HashSet<EngineType>,
         flush: bool,
         detach_subscription: bool,
+        progress: Progress,
+        subscriptions: Option<&HashSet<(Pid, SubscriptionId)>>,
     ) -> anyhow::Result<()> {
         if !self.upgrade_indicator.is_empty() {
             bail!("there is already an ongoing upgrade")
@@ -939,12 +1354,15 @@ impl EngineUpgrader {
             .runtime_manager
             .engine_subscriptions
             .iter()
-            .filter(|e| engine_types.contains(&e.engine_type))
+            .filter(|e| {
+                engine_types.contains(&e.engine_type)
+                    && subscriptions.map_or(true, |s| s.contains(&(e.pid, e.sid)))
+            })
         {
             let client = engines_to_upgrade
                 .entry(engine.pid)
                 .or_insert_with(Vec::new);
-            client.push((*engine.key(), *engine.value()));
+            client.push((*engine.key(), engine.value().clone()));
             subscriptions_to_upgrade.insert((engine.pid, engine.sid));
         }
 
@@ -959,7 +1377,7 @@ impl EngineUpgrader {
                 })
             {
                 let client = engines_to_detach.entry(engine.pid).or_insert_with(Vec::new);
-                client.push((*engine.key(), *engine.value()));
+                client.push((*engine.key(), engine.value().clone()));
             }
         }
 
@@ -983,6 +1401,7 @@ impl EngineUpgrader {
                 to_detach,
                 flush,
                 Arc::clone(&self.upgrade_indicator),
+                progress.clone(),
             );
             self.executor.spawn_ok(fut);
         }
@@ -990,6 +1409,22 @@ impl EngineUpgrader {
         Ok(())
     }
 
+    /// Waits for the ongoing upgrade of an application process, if any, to complete.
+    pub(crate) fn wait_idle(&self, pid: Pid, timeout: Duration) -> anyhow::Result<()> {
+        let start = Instant::now();
+        while self.upgrade_indicator.contains(&pid) {
+            if start.elapsed() > timeout {
+                bail!(
+                    "the upgrade for client pid={:?} did not complete in {:?}",
+                    pid,
+                    timeout
+                );
+            }
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+
     /// Check whether engines for an application process is still upgrading,
     /// returns true if still upgrading
     pub(crate) fn is_upgrading(&self, pid: Pid) -> bool {
@@ -0,0 +1,131 @@
+//! Integration with systemd: socket activation of the control socket, and readiness and
+//! watchdog notifications.
+//!
+//! See sd_listen_fds(3) and sd_notify(3). Everything here is a no-op when the daemon is not
+//! started by systemd.
+use std::env;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+use crate::log;
+use crate::runtime::RuntimeManager;
+
+/// The first fd passed by socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the fds passed by socket activation. The environment variables are unset so that they
+/// are not inherited by child processes.
+pub(crate) fn listen_fds() -> Vec<RawFd> {
+    let pid = env::var("LISTEN_PID").ok();
+    let nfds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // the fds are meant for another process
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Vec::new();
+    }
+    let nfds = nfds.and_then(|n| n.parse::<RawFd>().ok()).unwrap_or(0);
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + nfds)
+        .inspect(|&fd| unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        })
+        .collect()
+}
+
+/// Sends `state` to the service manager, e.g., "READY=1". Returns false if the daemon is not
+/// supervised by systemd.
+pub(crate) fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let path = path.as_bytes();
+
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    if path.is_empty() || path.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid NOTIFY_SOCKET",
+        ));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(path.iter()) {
+        *dst = *src as libc::c_char;
+    }
+    // an abstract socket
+    if path[0] == b'@' {
+        addr.sun_path[0] = 0;
+    }
+    let addr_len = mem::size_of::<libc::sa_family_t>() + path.len();
+
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ret = unsafe {
+        libc::sendto(
+            fd,
+            state.as_ptr() as *const libc::c_void,
+            state.len(),
+            libc::MSG_NOSIGNAL,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            addr_len as libc::socklen_t,
+        )
+    };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if ret < 0 {
+        return Err(err);
+    }
+    Ok(true)
+}
+
+/// Sends `state` to the service manager, logging the errors.
+pub(crate) fn notify_or_warn(state: &str) {
+    if let Err(e) = notify(state) {
+        log::warn!("Failed to notify systemd of {:?}: {}", state, e);
+    }
+}
+
+/// Pings the systemd watchdog as long as the runtimes are alive.
+pub(crate) struct Watchdog {
+    interval: Duration,
+    last_ping: Instant,
+}
+
+impl Watchdog {
+    /// Returns `None` if the watchdog is not enabled for the daemon.
+    pub(crate) fn from_env() -> Option<Self> {
+        if let Ok(pid) = env::var("WATCHDOG_PID") {
+            if pid.parse::<u32>().ok() != Some(std::process::id()) {
+                return None;
+            }
+        }
+        let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+        if usec == 0 {
+            return None;
+        }
+        // ping twice per timeout, as recommended by sd_watchdog_enabled(3)
+        Some(Watchdog {
+            interval: Duration::from_micros(usec) / 2,
+            last_ping: Instant::now(),
+        })
+    }
+
+    pub(crate) fn tick(&mut self, rm: &RuntimeManager) {
+        if self.last_ping.elapsed() < self.interval {
+            return;
+        }
+        self.last_ping = Instant::now();
+        if rm.is_alive() {
+            notify_or_warn("WATCHDOG=1");
+        } else {
+            log::error!("A runtime has exited, stop pinging the watchdog");
+        }
+    }
+}
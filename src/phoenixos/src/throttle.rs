@@ -0,0 +1,72 @@
+//! Per-client rate limits on the control socket.
+//!
+//! Each client, identified by the pid of the sender, has a bucket of `burst` requests refilled
+//! at `requests_per_sec`. A request beyond it is refused, so that a client looping on the
+//! control socket cannot starve the others.
+use std::collections::HashMap;
+use std::os::unix::net::UCred;
+use std::time::{Duration, Instant};
+
+use crate::config::Control as ControlConfig;
+
+/// How often the buckets of the idle clients are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    requests_per_sec: f64,
+    burst: f64,
+    buckets: HashMap<Option<libc::pid_t>, Bucket>,
+    last_prune: Instant,
+}
+
+impl Throttle {
+    pub(crate) fn new(config: &ControlConfig) -> Self {
+        Throttle {
+            requests_per_sec: config.requests_per_sec,
+            burst: config.burst.max(1.0),
+            buckets: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    /// Takes a request from the bucket of its sender. Returns false if the bucket is empty.
+    pub(crate) fn admit(&mut self, cred: &UCred) -> bool {
+        if self.requests_per_sec <= 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let bucket = self.buckets.entry(cred.pid).or_insert(Bucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.requests_per_sec;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.last_refill = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Forgets the clients whose bucket would be full again, i.e., those that have been idle.
+    pub(crate) fn prune(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_prune) < PRUNE_INTERVAL {
+            return;
+        }
+        self.last_prune = now;
+        let (requests_per_sec, burst) = (self.requests_per_sec, self.burst);
+        self.buckets.retain(|_, bucket| {
+            let idle = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens + idle * requests_per_sec < burst
+        });
+    }
+}
@@ -0,0 +1,67 @@
+//! Buffers shared with the app, the collective operations read and write them in place.
+use std::os::unix::io::AsRawFd;
+
+use memfd::{Memfd, MemfdOptions};
+use memmap2::{MmapOptions, MmapRaw};
+
+use phoenix_api::buf::Range;
+use phoenix_api::{AsHandle, Handle};
+
+#[derive(Debug)]
+pub struct SharedBuffer {
+    mmap: MmapRaw,
+    memfd: Memfd,
+}
+
+impl AsHandle for SharedBuffer {
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        Handle(self.memfd.as_raw_fd() as _)
+    }
+}
+
+impl SharedBuffer {
+    pub fn new(nbytes: usize) -> Result<Self, crate::ControlPathError> {
+        if nbytes == 0 {
+            return Err(crate::ControlPathError::InvalidArgument("empty buffer"));
+        }
+        let opts = MemfdOptions::default()
+            .allow_sealing(true)
+            .close_on_exec(false);
+        let name = format!("collective-buffer-{}", nbytes);
+        let memfd = opts.create(name)?;
+        memfd.as_file().set_len(nbytes as u64)?;
+        let mmap = MmapOptions::new().map_raw(memfd.as_file())?;
+        Ok(SharedBuffer { mmap, memfd })
+    }
+
+    #[inline]
+    pub fn memfd(&self) -> &Memfd {
+        &self.memfd
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.mmap.len() == 0
+    }
+
+    /// The address of the buffer in the backend.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.mmap.as_mut_ptr() as usize
+    }
+
+    /// Whether `range` lies in the buffer.
+    #[inline]
+    pub fn contains(&self, range: Range) -> bool {
+        range
+            .offset
+            .checked_add(range.len)
+            .map_or(false, |end| end <= self.len() as u64)
+    }
+}
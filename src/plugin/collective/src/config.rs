@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectiveConfig {
+    pub prefix: Option<PathBuf>,
+    pub engine_basename: String,
+    /// The size of the pieces a buffer is split into and pipelined along the members, in bytes.
+    pub chunk_size: usize,
+    /// How long the creation of a group waits for the other members to show up, in milliseconds.
+    pub connect_timeout_ms: u64,
+}
+
+impl CollectiveConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config = toml::from_str(config.unwrap_or(""))?;
+        Ok(config)
+    }
+}
+
+impl Default for CollectiveConfig {
+    fn default() -> Self {
+        CollectiveConfig {
+            prefix: None,
+            engine_basename: "collective-engine".to_owned(),
+            chunk_size: 1024 * 1024,
+            connect_timeout_ms: 30000,
+        }
+    }
+}
@@ -0,0 +1,560 @@
+use std::collections::VecDeque;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap as HashMap;
+use futures::future::BoxFuture;
+
+use phoenix_api::buf::Range;
+use phoenix_api::collective::cmd::{self, DataType, ReduceOp};
+use phoenix_api::net::{WcOpcode, WcStatus};
+use phoenix_api::transport::tcp::dp;
+use phoenix_api::{AsHandle, Handle};
+use transport_tcp::ops::Ops;
+
+use super::module::CustomerType;
+use super::{ControlPathError, ResourceError};
+use crate::buffer::SharedBuffer;
+use crate::config::CollectiveConfig;
+use crate::group::{self, Group, PendingGroup};
+use crate::reduce;
+use crate::schedule::{self, Action, Step};
+
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::future;
+use phoenix_common::engine::{Decompose, Engine, EngineResult, Indicator};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::module::{ModuleCollection, Version};
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+use phoenix_common::tracing;
+
+pub struct CollectiveEngine {
+    pub(crate) customer: CustomerType,
+    pub(crate) indicator: Indicator,
+    pub(crate) node: DataPathNode,
+    pub(crate) ops: Ops,
+    pub(crate) config: CollectiveConfig,
+    pub(crate) groups: HashMap<Handle, Group>,
+    pub(crate) buffers: HashMap<Handle, SharedBuffer>,
+    // the group being created, the client waits for its completion
+    pub(crate) pending_group: Option<PendingGroup>,
+    // the operation in progress, the client waits for its completion
+    pub(crate) running: Option<Running>,
+    pub(crate) next_group: u64,
+}
+
+/// A broadcast or all-reduce in progress.
+#[derive(Debug)]
+pub(crate) struct Running {
+    group: Handle,
+    completion: cmd::CompletionKind,
+    /// The address of the buffer in the backend.
+    base: usize,
+    reduce: Option<(DataType, ReduceOp)>,
+    steps: VecDeque<Step>,
+    /// The step in progress, and whether each of its actions is done.
+    step: Step,
+    done: Vec<bool>,
+    /// Where the data to reduce is received, aligned for any element type.
+    scratch: Vec<u64>,
+    scratch_offsets: Vec<usize>,
+}
+
+impl_vertex_for_engine!(CollectiveEngine, node);
+
+impl Decompose for CollectiveEngine {
+    #[inline]
+    fn flush(&mut self) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+        let mut collections = ResourceCollection::with_capacity(8);
+        tracing::trace!("dumping Collective engine states...");
+        collections.insert("customer".to_string(), Box::new(engine.customer));
+        collections.insert("ops".to_string(), Box::new(engine.ops));
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("groups".to_string(), Box::new(engine.groups));
+        collections.insert("buffers".to_string(), Box::new(engine.buffers));
+        collections.insert("pending_group".to_string(), Box::new(engine.pending_group));
+        collections.insert("running".to_string(), Box::new(engine.running));
+        collections.insert("next_group".to_string(), Box::new(engine.next_group));
+        (collections, engine.node)
+    }
+}
+
+impl CollectiveEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+        node: DataPathNode,
+        _plugged: &ModuleCollection,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        tracing::trace!("restoring Collective engine");
+        let customer = *local
+            .remove("customer")
+            .unwrap()
+            .downcast::<CustomerType>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let ops = *local
+            .remove("ops")
+            .unwrap()
+            .downcast::<Ops>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<CollectiveConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let groups = *local
+            .remove("groups")
+            .unwrap()
+            .downcast::<HashMap<Handle, Group>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let buffers = *local
+            .remove("buffers")
+            .unwrap()
+            .downcast::<HashMap<Handle, SharedBuffer>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let pending_group = *local
+            .remove("pending_group")
+            .unwrap()
+            .downcast::<Option<PendingGroup>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let running = *local
+            .remove("running")
+            .unwrap()
+            .downcast::<Option<Running>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let next_group = *local
+            .remove("next_group")
+            .unwrap()
+            .downcast::<u64>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = CollectiveEngine {
+            customer,
+            indicator: Default::default(),
+            node,
+            ops,
+            config,
+            groups,
+            buffers,
+            pending_group,
+            running,
+            next_group,
+        };
+        Ok(engine)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for CollectiveEngine {
+    fn description(self: Pin<&Self>) -> String {
+        "CollectiveEngine".to_owned()
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn doorbells(&self) -> Option<Vec<RawFd>> {
+        // the sockets are polled while a group is created or an operation runs, and a group
+        // being created retries its connections by a timer
+        if self.pending_group.is_some() || self.running.is_some() {
+            return None;
+        }
+        Some(self.customer.doorbells().to_vec())
+    }
+}
+
+impl CollectiveEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut nwork = 0;
+            nwork += self.check_transport()?;
+            match self.check_cmd()? {
+                Progress(n) => nwork += n,
+                Status::Disconnected => return Ok(()),
+            }
+            self.indicator.set_nwork(nwork);
+            future::yield_now().await;
+        }
+    }
+}
+
+impl CollectiveEngine {
+    fn check_cmd(&mut self) -> Result<Status, ControlPathError> {
+        // The client waits for the completion of the pending command, it sends nothing else in
+        // the meantime.
+        if self.pending_group.is_some() || self.running.is_some() {
+            return Ok(Progress(0));
+        }
+
+        match self.customer.try_recv_cmd() {
+            Ok(req) => {
+                let result = self.process_cmd(req);
+                match result {
+                    Ok(Some(res)) => self.customer.send_comp(cmd::Completion(Ok(res)))?,
+                    Ok(None) => {}
+                    Err(e) => self.customer.send_comp(cmd::Completion(Err(e.into())))?,
+                }
+                Ok(Progress(1))
+            }
+            Err(ipc::TryRecvError::Empty) => {
+                // do nothing
+                Ok(Progress(0))
+            }
+            Err(ipc::TryRecvError::Disconnected) => Ok(Status::Disconnected),
+            Err(ipc::TryRecvError::Other(_e)) => Err(ControlPathError::IpcTryRecv),
+        }
+    }
+
+    /// Returns `None` if the completion is deferred.
+    fn process_cmd(
+        &mut self,
+        req: cmd::Command,
+    ) -> Result<Option<cmd::CompletionKind>, ControlPathError> {
+        use cmd::Command;
+        match req {
+            Command::CreateGroup(members, rank) => {
+                tracing::trace!("CreateGroup, rank {} of {:?}", rank, members);
+                let timeout = Duration::from_millis(self.config.connect_timeout_ms);
+                let pending = PendingGroup::new(&self.ops, members, rank, timeout)?;
+                if pending.is_complete() {
+                    return Ok(Some(self.finish_group(pending)));
+                }
+                self.pending_group = Some(pending);
+                Ok(None)
+            }
+            Command::DestroyGroup(handle) => {
+                let mut group = self.groups.remove(&handle).ok_or(ResourceError::NotFound)?;
+                group.close(&self.ops);
+                Ok(Some(cmd::CompletionKind::DestroyGroup))
+            }
+            Command::RegBuffer(nbytes) => {
+                let buffer = match SharedBuffer::new(nbytes) {
+                    Ok(buffer) => buffer,
+                    Err(e) => {
+                        // the client waits for the fd before the completion
+                        self.customer.send_fd(&[])?;
+                        return Err(e);
+                    }
+                };
+                self.customer.send_fd(&[buffer.memfd().as_raw_fd()][..])?;
+                let handle = buffer.as_handle();
+                let len = buffer.len();
+                self.buffers.insert(handle, buffer);
+                Ok(Some(cmd::CompletionKind::RegBuffer(handle, len)))
+            }
+            Command::DeregBuffer(handle) => {
+                self.buffers
+                    .remove(&handle)
+                    .ok_or(ResourceError::NotFound)?;
+                Ok(Some(cmd::CompletionKind::DeregBuffer))
+            }
+            Command::Broadcast(group, buffer, range, root, algorithm) => {
+                let (rank, size, base) = self.prepare(group, buffer, range)?;
+                if root >= size {
+                    return Err(ControlPathError::InvalidArgument("root out of the group"));
+                }
+                let steps =
+                    schedule::broadcast(rank, size, root, range, algorithm, self.config.chunk_size);
+                self.start(group, base, None, steps, cmd::CompletionKind::Broadcast)
+            }
+            Command::AllReduce(group, buffer, range, dtype, op, algorithm) => {
+                let (rank, size, base) = self.prepare(group, buffer, range)?;
+                let steps = schedule::all_reduce(
+                    rank,
+                    size,
+                    range,
+                    dtype.size(),
+                    algorithm,
+                    self.config.chunk_size,
+                )?;
+                self.start(
+                    group,
+                    base,
+                    Some((dtype, op)),
+                    steps,
+                    cmd::CompletionKind::AllReduce,
+                )
+            }
+        }
+    }
+
+    fn finish_group(&mut self, pending: PendingGroup) -> cmd::CompletionKind {
+        let group = pending.finish(&self.ops);
+        let handle = Handle(self.next_group);
+        self.next_group += 1;
+        let size = group.size();
+        self.groups.insert(handle, group);
+        cmd::CompletionKind::CreateGroup(handle, size)
+    }
+
+    /// Checks the arguments of an operation. Returns the rank of this member, the size of the
+    /// group and the address of the buffer.
+    fn prepare(
+        &self,
+        group: Handle,
+        buffer: Handle,
+        range: Range,
+    ) -> Result<(usize, usize, usize), ControlPathError> {
+        let group_ref = self.groups.get(&group).ok_or(ResourceError::NotFound)?;
+        if group_ref.broken {
+            return Err(ControlPathError::GroupBroken(group));
+        }
+        let buffer = self.buffers.get(&buffer).ok_or(ResourceError::NotFound)?;
+        if !buffer.contains(range) {
+            return Err(ControlPathError::InvalidArgument("range out of the buffer"));
+        }
+        Ok((group_ref.rank, group_ref.size(), buffer.addr()))
+    }
+
+    fn start(
+        &mut self,
+        group: Handle,
+        base: usize,
+        reduce: Option<(DataType, ReduceOp)>,
+        steps: Vec<Step>,
+        completion: cmd::CompletionKind,
+    ) -> Result<Option<cmd::CompletionKind>, ControlPathError> {
+        let group_ref = self.groups.get_mut(&group).unwrap();
+        group_ref.seq = group_ref.seq.wrapping_add(1);
+        self.running = Some(Running {
+            group,
+            completion,
+            base,
+            reduce,
+            steps: steps.into(),
+            step: Vec::new(),
+            done: Vec::new(),
+            scratch: Vec::new(),
+            scratch_offsets: Vec::new(),
+        });
+        match self.advance() {
+            Ok(true) => Ok(Some(self.running.take().unwrap().completion)),
+            Ok(false) => Ok(None),
+            Err(e) => {
+                self.fail_running();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl CollectiveEngine {
+    fn check_transport(&mut self) -> Result<usize, ControlPathError> {
+        if self.pending_group.is_none() && self.running.is_none() {
+            return Ok(0);
+        }
+
+        let (conns, wcs) = self.ops.poll_io(Duration::from_micros(0))?;
+        let nwork = conns.len() + wcs.len();
+
+        if let Some(pending) = self.pending_group.as_mut() {
+            let result = (|| {
+                for conn in conns {
+                    pending.on_accepted(&self.ops, conn)?;
+                }
+                for wc in &wcs {
+                    pending.on_completion(&self.ops, wc)?;
+                }
+                pending.check_retries(&self.ops)
+            })();
+            match result {
+                Ok(()) if pending.is_complete() => {
+                    let pending = self.pending_group.take().unwrap();
+                    let res = self.finish_group(pending);
+                    self.customer.send_comp(cmd::Completion(Ok(res)))?;
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    let pending = self.pending_group.take().unwrap();
+                    pending.abort(&self.ops);
+                    self.customer.send_comp(cmd::Completion(Err(e.into())))?;
+                }
+            }
+            return Ok(nwork);
+        }
+
+        // no one is expected to connect
+        for conn in conns {
+            group::close(&self.ops, conn);
+        }
+        let result = (|| {
+            for wc in &wcs {
+                self.on_completion(wc)?;
+            }
+            self.advance()
+        })();
+        match result {
+            Ok(true) => {
+                let running = self.running.take().unwrap();
+                self.customer
+                    .send_comp(cmd::Completion(Ok(running.completion)))?;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                self.fail_running();
+                self.customer.send_comp(cmd::Completion(Err(e.into())))?;
+            }
+        }
+        Ok(nwork)
+    }
+
+    /// Posts the next steps of the running operation once the current one is done. Returns
+    /// true if the operation is done.
+    fn advance(&mut self) -> Result<bool, ControlPathError> {
+        let running = self.running.as_mut().unwrap();
+        if running.done.iter().any(|done| !done) {
+            return Ok(false);
+        }
+        let step = match running.steps.pop_front() {
+            Some(step) => step,
+            None => return Ok(true),
+        };
+        let group = &self.groups[&running.group];
+
+        // place the data to reduce in the scratch memory, each piece aligned to 8 bytes
+        let mut scratch_len = 0;
+        running.scratch_offsets.clear();
+        for action in &step {
+            running.scratch_offsets.push(scratch_len);
+            if let Action::Recv {
+                range,
+                reduce: true,
+                ..
+            } = action
+            {
+                scratch_len += (range.len as usize + 7) / 8 * 8;
+            }
+        }
+        running.scratch.resize(scratch_len / 8, 0);
+
+        for (i, action) in step.iter().enumerate() {
+            match *action {
+                Action::Send { peer, range } => {
+                    let conn = group.conns[peer].unwrap();
+                    let buf = Range {
+                        offset: running.base as u64 + range.offset,
+                        len: range.len,
+                    };
+                    self.ops.post_send(conn, i as u64, buf, group.seq)?;
+                }
+                Action::Recv {
+                    peer,
+                    range,
+                    reduce,
+                } => {
+                    let conn = group.conns[peer].unwrap();
+                    let offset = if reduce {
+                        running.scratch.as_ptr() as u64 + running.scratch_offsets[i] as u64
+                    } else {
+                        running.base as u64 + range.offset
+                    };
+                    let buf = Range {
+                        offset,
+                        len: range.len,
+                    };
+                    self.ops.post_recv(conn, i as u64, buf)?;
+                }
+            }
+        }
+        running.done = vec![false; step.len()];
+        running.step = step;
+        Ok(false)
+    }
+
+    fn on_completion(&mut self, wc: &dp::Completion) -> Result<(), ControlPathError> {
+        let running = self.running.as_mut().unwrap();
+        let group = &self.groups[&running.group];
+        let i = wc.wr_id as usize;
+        let expected = match running.step.get(i) {
+            Some(Action::Send { peer, .. }) => Some((*peer, WcOpcode::Send)),
+            Some(Action::Recv { peer, .. }) => Some((*peer, WcOpcode::Recv)),
+            None => None,
+        };
+        let peer = match expected {
+            Some((peer, opcode))
+                if group.conns[peer] == Some(Handle(wc.conn_id))
+                    && wc.opcode == opcode
+                    && !running.done[i] =>
+            {
+                peer
+            }
+            _ => {
+                tracing::warn!("Unexpected completion: {:?}", wc);
+                return Ok(());
+            }
+        };
+        if let WcStatus::Error(code) = wc.status {
+            return Err(ControlPathError::Peer(
+                peer,
+                format!("{:?} failed, error code: {}", wc.opcode, code),
+            ));
+        }
+
+        if let Action::Recv { range, reduce, .. } = running.step[i] {
+            if wc.imm != group.seq || wc.byte_len as u64 != range.len {
+                return Err(ControlPathError::Peer(
+                    peer,
+                    "message of another operation".to_owned(),
+                ));
+            }
+            if reduce {
+                let (dtype, op) = running.reduce.unwrap();
+                let len = range.len as usize;
+                // SAFETY: the range has been checked to lie in the buffer, which the client does
+                // not touch while the operation runs
+                let dst = unsafe {
+                    std::slice::from_raw_parts_mut(
+                        (running.base + range.offset as usize) as *mut u8,
+                        len,
+                    )
+                };
+                let src = unsafe {
+                    std::slice::from_raw_parts(
+                        (running.scratch.as_ptr() as *const u8).add(running.scratch_offsets[i]),
+                        len,
+                    )
+                };
+                reduce::reduce(dtype, op, dst, src);
+            }
+        }
+        running.done[i] = true;
+        Ok(())
+    }
+
+    /// Gives up the running operation. The messages in flight cannot be told apart from those
+    /// of the next operation, so the group is closed.
+    fn fail_running(&mut self) {
+        if let Some(running) = self.running.take() {
+            if let Some(group) = self.groups.get_mut(&running.group) {
+                group.close(&self.ops);
+            }
+        }
+    }
+}
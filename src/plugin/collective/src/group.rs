@@ -0,0 +1,214 @@
+//! The groups of members and the connections between them.
+//!
+//! The members of a group are fully connected. Each member listens on its own address and
+//! connects to the members of lower ranks, then says hello with its rank, so the accepting side
+//! learns which member a connection is from.
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api::buf::Range;
+use phoenix_api::net::{MappedAddrStatus, WcOpcode, WcStatus};
+use phoenix_api::transport::tcp::dp;
+use phoenix_api::Handle;
+use phoenix_common::tracing;
+use transport_tcp::ops::Ops;
+
+use crate::ControlPathError;
+
+/// How long to wait before connecting again to a member that is not listening yet.
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+const HELLO_BYTES: usize = std::mem::size_of::<u64>();
+
+/// Closes the connection `conn` and drops the work posted on it.
+pub(crate) fn close(ops: &Ops, conn: Handle) {
+    // a connection already closed is fine
+    let _ = ops.close(conn);
+}
+
+#[derive(Debug)]
+pub(crate) struct Group {
+    pub(crate) rank: usize,
+    /// The connection to each member, `None` for the member itself.
+    pub(crate) conns: Vec<Option<Handle>>,
+    /// The number of operations run on the group, the messages of an operation carry it as the
+    /// immediate value.
+    pub(crate) seq: u32,
+    /// Set when an operation fails, the connections are closed then.
+    pub(crate) broken: bool,
+}
+
+impl Group {
+    #[inline]
+    pub(crate) fn size(&self) -> usize {
+        self.conns.len()
+    }
+
+    pub(crate) fn close(&mut self, ops: &Ops) {
+        for conn in self.conns.iter().flatten() {
+            close(ops, *conn);
+        }
+        self.broken = true;
+    }
+}
+
+/// A group waiting for all its members to be connected.
+#[derive(Debug)]
+pub(crate) struct PendingGroup {
+    members: Vec<SocketAddr>,
+    rank: usize,
+    listener: Handle,
+    conns: Vec<Option<Handle>>,
+    /// The connections to the members of lower ranks whose hello is being sent.
+    connecting: HashMap<Handle, usize>,
+    /// The members of lower ranks to connect to again, and when.
+    retries: Vec<(usize, Instant)>,
+    /// The accepted connections whose hello is being received, and where it goes.
+    accepted: HashMap<Handle, Box<[u8; HELLO_BYTES]>>,
+    /// The hello sent by this member.
+    hello: Box<[u8; HELLO_BYTES]>,
+    deadline: Instant,
+}
+
+impl PendingGroup {
+    pub(crate) fn new(
+        ops: &Ops,
+        members: Vec<SocketAddr>,
+        rank: usize,
+        timeout: Duration,
+    ) -> Result<Self, ControlPathError> {
+        if rank >= members.len() {
+            return Err(ControlPathError::InvalidArgument("rank out of the group"));
+        }
+        let listener = ops.bind(&members[rank])?;
+        let mut group = PendingGroup {
+            conns: vec![None; members.len()],
+            members,
+            rank,
+            listener,
+            connecting: HashMap::default(),
+            retries: Vec::new(),
+            accepted: HashMap::default(),
+            hello: Box::new((rank as u64).to_le_bytes()),
+            deadline: Instant::now() + timeout,
+        };
+        for peer in 0..rank {
+            group.connect(ops, peer)?;
+        }
+        Ok(group)
+    }
+
+    fn connect(&mut self, ops: &Ops, peer: usize) -> Result<(), ControlPathError> {
+        let conn = ops.connect(&self.members[peer])?;
+        let range = Range {
+            offset: self.hello.as_ptr() as u64,
+            len: HELLO_BYTES as u64,
+        };
+        ops.post_send(conn, 0, range, 0)?;
+        self.connecting.insert(conn, peer);
+        Ok(())
+    }
+
+    /// Takes a connection accepted by the listener.
+    pub(crate) fn on_accepted(&mut self, ops: &Ops, conn: Handle) -> Result<(), ControlPathError> {
+        if let Some((_, status)) = ops.state.sock_table.borrow_mut().get_mut(&conn) {
+            *status = MappedAddrStatus::Mapped;
+        }
+        let hello = Box::new([0u8; HELLO_BYTES]);
+        let range = Range {
+            offset: hello.as_ptr() as u64,
+            len: HELLO_BYTES as u64,
+        };
+        ops.post_recv(conn, 0, range)?;
+        self.accepted.insert(conn, hello);
+        Ok(())
+    }
+
+    /// Takes a completion on the connections of the group. Returns false if the completion is
+    /// not of this group.
+    pub(crate) fn on_completion(
+        &mut self,
+        ops: &Ops,
+        wc: &dp::Completion,
+    ) -> Result<bool, ControlPathError> {
+        let conn = Handle(wc.conn_id);
+        match wc.opcode {
+            WcOpcode::Send if self.connecting.contains_key(&conn) => {
+                let peer = self.connecting.remove(&conn).unwrap();
+                if wc.status == WcStatus::Success {
+                    self.conns[peer] = Some(conn);
+                } else {
+                    // the member is not listening yet
+                    close(ops, conn);
+                    self.retries.push((peer, Instant::now() + RETRY_INTERVAL));
+                }
+                Ok(true)
+            }
+            WcOpcode::Recv if self.accepted.contains_key(&conn) => {
+                let hello = self.accepted.remove(&conn).unwrap();
+                let peer = u64::from_le_bytes(*hello) as usize;
+                if wc.status != WcStatus::Success
+                    || wc.byte_len != HELLO_BYTES
+                    || peer <= self.rank
+                    || peer >= self.conns.len()
+                    || self.conns[peer].is_some()
+                {
+                    tracing::warn!("Dropping connection {:?}, invalid hello", conn);
+                    close(ops, conn);
+                } else {
+                    self.conns[peer] = Some(conn);
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Connects again to the members due. Fails if the group is not complete by the deadline.
+    pub(crate) fn check_retries(&mut self, ops: &Ops) -> Result<(), ControlPathError> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(ControlPathError::Timeout);
+        }
+        let (due, waiting): (Vec<_>, Vec<_>) =
+            self.retries.drain(..).partition(|(_, at)| *at <= now);
+        self.retries = waiting;
+        for (peer, _) in due {
+            self.connect(ops, peer)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn is_complete(&self) -> bool {
+        self.conns
+            .iter()
+            .enumerate()
+            .all(|(peer, conn)| peer == self.rank || conn.is_some())
+    }
+
+    /// Stops listening, and returns the group.
+    pub(crate) fn finish(self, ops: &Ops) -> Group {
+        ops.state.listener_table.borrow_mut().remove(&self.listener);
+        Group {
+            rank: self.rank,
+            conns: self.conns,
+            seq: 0,
+            broken: false,
+        }
+    }
+
+    /// Closes the listener and all the connections.
+    pub(crate) fn abort(self, ops: &Ops) {
+        ops.state.listener_table.borrow_mut().remove(&self.listener);
+        let conns = self.conns.iter().flatten().copied();
+        for conn in conns
+            .chain(self.connecting.keys().copied())
+            .chain(self.accepted.keys().copied())
+        {
+            close(ops, conn);
+        }
+    }
+}
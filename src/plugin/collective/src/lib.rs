@@ -0,0 +1,76 @@
+#![feature(peer_credentials_unix_socket)]
+
+use std::io;
+
+use thiserror::Error;
+
+use phoenix_api::Handle;
+use phoenix_common::resource::Error as ResourceError;
+use phoenix_common::{InitFnResult, PhoenixModule};
+
+pub mod buffer;
+pub mod config;
+pub(crate) mod engine;
+pub(crate) mod group;
+pub mod module;
+pub(crate) mod reduce;
+pub(crate) mod schedule;
+
+#[derive(Error, Debug)]
+pub enum ControlPathError {
+    // Below are errors that return to the user.
+    #[error("Resource error: {0}")]
+    Resource(#[from] ResourceError),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Memfd: {0}")]
+    Memfd(#[from] memfd::Error),
+    #[error("TCP transport: {0}")]
+    TcpApi(#[from] transport_tcp::ApiError),
+    #[error("TCP transport: {0}")]
+    Transport(#[from] transport_tcp::TransportError),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(&'static str),
+    #[error("Timed out waiting for the members of the group")]
+    Timeout,
+    #[error("Rank {0} failed: {1}")]
+    Peer(usize, String),
+    #[error("Group {0:?} is broken by a failed operation")]
+    GroupBroken(Handle),
+    // Below are errors that does not return to the user.
+    #[error("Ipc-channel TryRecvError")]
+    IpcTryRecv,
+    #[error("Send command error")]
+    SendCommand,
+    #[error("Service error: {0}")]
+    Service(#[from] ipc::Error),
+}
+
+impl From<ControlPathError> for phoenix_api::Error {
+    fn from(other: ControlPathError) -> Self {
+        let code = match &other {
+            ControlPathError::Resource(e) => e.code(),
+            ControlPathError::InvalidArgument(_) => phoenix_api::ErrorCode::InvalidArgument,
+            ControlPathError::Timeout => phoenix_api::ErrorCode::Timeout,
+            _ => phoenix_api::ErrorCode::Internal,
+        };
+        phoenix_api::Error::from_error(code, &other)
+    }
+}
+
+use std::sync::mpsc::SendError;
+impl<T> From<SendError<T>> for ControlPathError {
+    fn from(_other: SendError<T>) -> Self {
+        Self::SendCommand
+    }
+}
+
+use crate::config::CollectiveConfig;
+use crate::module::CollectiveModule;
+
+#[no_mangle]
+pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
+    let config = CollectiveConfig::new(config_string)?;
+    let module = CollectiveModule::new(config);
+    Ok(Box::new(module))
+}
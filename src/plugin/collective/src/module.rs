@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use nix::unistd::Pid;
+use uuid::Uuid;
+
+use ipc::customer::ShmCustomer;
+use phoenix_api::collective::{cmd, dp};
+use phoenix_api::engine::SchedulingMode;
+use transport_tcp::module::TcpTransportModule;
+use transport_tcp::ops::Ops;
+
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{Engine, EnginePair, EngineType};
+use phoenix_common::module::{
+    ModuleCollection, ModuleDowncast, NewEngineRequest, PhoenixModule, Service, ServiceInfo,
+    Version,
+};
+use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
+
+use super::engine::CollectiveEngine;
+use crate::config::CollectiveConfig;
+
+pub(crate) type CustomerType =
+    ShmCustomer<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>;
+
+pub(crate) struct CollectiveEngineBuilder {
+    customer: CustomerType,
+    _client_pid: Pid,
+    _mode: SchedulingMode,
+    node: DataPathNode,
+    ops: Ops,
+    config: CollectiveConfig,
+}
+
+impl CollectiveEngineBuilder {
+    fn new(
+        customer: CustomerType,
+        client_pid: Pid,
+        mode: SchedulingMode,
+        node: DataPathNode,
+        ops: Ops,
+        config: CollectiveConfig,
+    ) -> Self {
+        CollectiveEngineBuilder {
+            customer,
+            _client_pid: client_pid,
+            _mode: mode,
+            node,
+            ops,
+            config,
+        }
+    }
+
+    fn build(self) -> Result<CollectiveEngine> {
+        Ok(CollectiveEngine {
+            customer: self.customer,
+            indicator: Default::default(),
+            node: self.node,
+            ops: self.ops,
+            config: self.config,
+            groups: Default::default(),
+            buffers: Default::default(),
+            pending_group: None,
+            running: None,
+            next_group: 0,
+        })
+    }
+}
+
+pub struct CollectiveModule {
+    config: CollectiveConfig,
+}
+
+impl CollectiveModule {
+    pub const COLLECTIVE_ENGINE: EngineType = EngineType("CollectiveEngine");
+    pub const ENGINES: &'static [EngineType] = &[CollectiveModule::COLLECTIVE_ENGINE];
+
+    pub const SERVICE: Service = Service("Collective");
+}
+
+impl CollectiveModule {
+    pub fn new(config: CollectiveConfig) -> Self {
+        CollectiveModule { config }
+    }
+}
+
+impl PhoenixModule for CollectiveModule {
+    fn service(&self) -> Option<ServiceInfo> {
+        let service = ServiceInfo {
+            service: CollectiveModule::SERVICE,
+            engine: CollectiveModule::COLLECTIVE_ENGINE,
+            tx_channels: &[],
+            rx_channels: &[],
+            scheduling_groups: vec![],
+        };
+        Some(service)
+    }
+
+    fn engines(&self) -> &[EngineType] {
+        CollectiveModule::ENGINES
+    }
+
+    fn dependencies(&self) -> &[EnginePair] {
+        &[]
+    }
+
+    fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let module = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(module.config));
+        collections
+    }
+
+    fn migrate(&mut self, _prev_module: Box<dyn PhoenixModule>) {}
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        request: NewEngineRequest,
+        _shared: &mut SharedStorage,
+        global: &mut ResourceCollection,
+        node: DataPathNode,
+        plugged: &ModuleCollection,
+    ) -> Result<Option<Box<dyn Engine>>> {
+        if ty != CollectiveModule::COLLECTIVE_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+        if let NewEngineRequest::Service {
+            sock,
+            client_path,
+            mode,
+            cred,
+            config_string: _,
+        } = request
+        {
+            // 1. generate a path and bind a unix domain socket to it
+            let uuid = Uuid::new_v4();
+            let instance_name = format!("{}-{}.sock", self.config.engine_basename, uuid);
+
+            // use the phoenix_prefix if not otherwise specified
+            let phoenix_prefix = get_default_prefix(global)?;
+            let engine_prefix = self.config.prefix.as_ref().unwrap_or(phoenix_prefix);
+            let engine_path = engine_prefix.join(instance_name);
+
+            // 2. create customer stub
+            let customer = ShmCustomer::accept(sock, client_path, mode, engine_path)?;
+
+            // 3. the members are connected by the sockets of the TCP transport, the engine owns
+            // its own set of sockets
+            let client_pid = Pid::from_raw(cred.pid.unwrap());
+            let mut tcp_transport_module = plugged
+                .get_mut("TcpTransport")
+                .ok_or_else(|| anyhow!("fail to get TcpTransport module"))?;
+            let tcp_transport = tcp_transport_module
+                .downcast_mut::<TcpTransportModule>()
+                .ok_or_else(|| anyhow!("fail to downcast TcpTransport module"))?;
+            let ops = tcp_transport.create_ops(client_pid)?;
+
+            let builder = CollectiveEngineBuilder::new(
+                customer,
+                client_pid,
+                mode,
+                node,
+                ops,
+                self.config.clone(),
+            );
+            let engine = builder.build()?;
+
+            Ok(Some(Box::new(engine)))
+        } else {
+            bail!("invalid request type");
+        }
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        shared: &mut SharedStorage,
+        global: &mut ResourceCollection,
+        node: DataPathNode,
+        plugged: &ModuleCollection,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != CollectiveModule::COLLECTIVE_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+        let engine = CollectiveEngine::restore(local, shared, global, node, plugged, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
@@ -0,0 +1,113 @@
+//! Element-wise reduction of the received data into a buffer.
+use phoenix_api::collective::cmd::{DataType, ReduceOp};
+
+trait Element: Copy + PartialOrd {
+    const SIZE: usize;
+
+    fn load(bytes: &[u8]) -> Self;
+    fn store(self, bytes: &mut [u8]);
+    fn sum(self, other: Self) -> Self;
+    fn prod(self, other: Self) -> Self;
+}
+
+macro_rules! impl_element_int {
+    ($($ty:ty),*) => {$(
+        impl Element for $ty {
+            const SIZE: usize = std::mem::size_of::<$ty>();
+
+            #[inline]
+            fn load(bytes: &[u8]) -> Self {
+                <$ty>::from_ne_bytes(bytes.try_into().unwrap())
+            }
+
+            #[inline]
+            fn store(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_ne_bytes());
+            }
+
+            // integers wrap around like in the other collective libraries
+            #[inline]
+            fn sum(self, other: Self) -> Self {
+                self.wrapping_add(other)
+            }
+
+            #[inline]
+            fn prod(self, other: Self) -> Self {
+                self.wrapping_mul(other)
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_element_float {
+    ($($ty:ty),*) => {$(
+        impl Element for $ty {
+            const SIZE: usize = std::mem::size_of::<$ty>();
+
+            #[inline]
+            fn load(bytes: &[u8]) -> Self {
+                <$ty>::from_ne_bytes(bytes.try_into().unwrap())
+            }
+
+            #[inline]
+            fn store(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_ne_bytes());
+            }
+
+            #[inline]
+            fn sum(self, other: Self) -> Self {
+                self + other
+            }
+
+            #[inline]
+            fn prod(self, other: Self) -> Self {
+                self * other
+            }
+        }
+    )*};
+}
+
+impl_element_int!(i32, i64, u32, u64);
+impl_element_float!(f32, f64);
+
+fn reduce_as<T: Element>(op: ReduceOp, dst: &mut [u8], src: &[u8]) {
+    for (d, s) in dst.chunks_exact_mut(T::SIZE).zip(src.chunks_exact(T::SIZE)) {
+        let a = T::load(d);
+        let b = T::load(s);
+        let r = match op {
+            ReduceOp::Sum => a.sum(b),
+            ReduceOp::Prod => a.prod(b),
+            // NaN is taken only if it is already in `dst`
+            ReduceOp::Min => {
+                if b < a {
+                    b
+                } else {
+                    a
+                }
+            }
+            ReduceOp::Max => {
+                if b > a {
+                    b
+                } else {
+                    a
+                }
+            }
+        };
+        r.store(d);
+    }
+}
+
+/// Combines the elements of `src` into those of `dst`. Both are whole elements of `dtype`, which
+/// need not be aligned.
+pub fn reduce(dtype: DataType, op: ReduceOp, dst: &mut [u8], src: &[u8]) {
+    debug_assert_eq!(dst.len(), src.len());
+    debug_assert_eq!(dst.len() % dtype.size(), 0);
+    match dtype {
+        DataType::I32 => reduce_as::<i32>(op, dst, src),
+        DataType::I64 => reduce_as::<i64>(op, dst, src),
+        DataType::U32 => reduce_as::<u32>(op, dst, src),
+        DataType::U64 => reduce_as::<u64>(op, dst, src),
+        DataType::F32 => reduce_as::<f32>(op, dst, src),
+        DataType::F64 => reduce_as::<f64>(op, dst, src),
+    }
+}
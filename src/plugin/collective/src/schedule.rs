@@ -0,0 +1,411 @@
+//! The steps each member takes in a collective operation.
+//!
+//! An operation runs as a sequence of steps. The sends and receives of a step are posted at
+//! once, and the next step starts when all of them have completed. Every member derives its steps
+//! from the same arguments, so the messages between each pair of members are sent and received
+//! in the same order and of the same sizes.
+use phoenix_api::buf::Range;
+use phoenix_api::collective::cmd::Algorithm;
+
+use crate::ControlPathError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Sends the bytes of `range` in the buffer to the member `peer`.
+    Send { peer: usize, range: Range },
+    /// Receives the bytes of `range` from the member `peer`. They are combined with the bytes in
+    /// the buffer if `reduce` is set, otherwise they overwrite them.
+    Recv {
+        peer: usize,
+        range: Range,
+        reduce: bool,
+    },
+}
+
+pub type Step = Vec<Action>;
+
+/// Splits `range` into pieces of at most `chunk_size` bytes, each of whole elements of
+/// `elem_size` bytes.
+fn split(range: Range, chunk_size: usize, elem_size: usize) -> Vec<Range> {
+    let chunk = (chunk_size / elem_size).max(1) as u64 * elem_size as u64;
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    while offset < range.len {
+        let len = chunk.min(range.len - offset);
+        chunks.push(Range {
+            offset: range.offset + offset,
+            len,
+        });
+        offset += len;
+    }
+    chunks
+}
+
+/// The number of elements of `elem_size` bytes in `range`, which must hold whole elements.
+fn elems(range: Range, elem_size: usize) -> Result<usize, ControlPathError> {
+    if elem_size == 0 || range.len % elem_size as u64 != 0 {
+        return Err(ControlPathError::InvalidArgument(
+            "range not of whole elements",
+        ));
+    }
+    Ok(range.len as usize / elem_size)
+}
+
+/// Splits `range` of `nelems` elements into `n` segments that differ by at most one element.
+fn segments(range: Range, n: usize, elem_size: usize) -> Result<Vec<Range>, ControlPathError> {
+    let nelems = elems(range, elem_size)?;
+    Ok((0..n)
+        .map(|i| {
+            let start = nelems * i / n;
+            let end = nelems * (i + 1) / n;
+            Range {
+                offset: range.offset + (start * elem_size) as u64,
+                len: ((end - start) * elem_size) as u64,
+            }
+        })
+        .collect())
+}
+
+/// Moves the data from the members `from` to the members `to` chunk by chunk. A chunk is
+/// forwarded in the step after it is received, so the chunks flow along a chain or tree of
+/// members in a pipeline. The data is sent right away if `from` is empty.
+fn pipeline(from: &[usize], to: &[usize], chunks: &[Range], reduce: bool) -> Vec<Step> {
+    let mut steps = Vec::new();
+    if from.is_empty() {
+        for &chunk in chunks {
+            steps.push(
+                to.iter()
+                    .map(|&peer| Action::Send { peer, range: chunk })
+                    .collect(),
+            );
+        }
+    } else {
+        for c in 0..=chunks.len() {
+            let mut step = Vec::new();
+            if c < chunks.len() {
+                step.extend(from.iter().map(|&peer| Action::Recv {
+                    peer,
+                    range: chunks[c],
+                    reduce,
+                }));
+            }
+            if c > 0 {
+                step.extend(to.iter().map(|&peer| Action::Send {
+                    peer,
+                    range: chunks[c - 1],
+                }));
+            }
+            steps.push(step);
+        }
+    }
+    steps.retain(|step| !step.is_empty());
+    steps
+}
+
+/// The parent and children of the virtual rank `v` in the binary tree of `n` members rooted at
+/// virtual rank 0. The virtual rank of `rank` is its distance from `root` on the ring.
+fn tree(rank: usize, root: usize, n: usize) -> (Option<usize>, Vec<usize>) {
+    let v = (rank + n - root) % n;
+    let real = |v: usize| (v + root) % n;
+    let parent = if v == 0 {
+        None
+    } else {
+        Some(real((v - 1) / 2))
+    };
+    let children = [2 * v + 1, 2 * v + 2]
+        .into_iter()
+        .filter(|&c| c < n)
+        .map(real)
+        .collect();
+    (parent, children)
+}
+
+/// The predecessor and successor of `rank` in the chain of `n` members starting at `root`.
+fn chain(rank: usize, root: usize, n: usize) -> (Option<usize>, Vec<usize>) {
+    let v = (rank + n - root) % n;
+    let parent = if v == 0 {
+        None
+    } else {
+        Some((rank + n - 1) % n)
+    };
+    let children = if v + 1 < n {
+        vec![(rank + 1) % n]
+    } else {
+        vec![]
+    };
+    (parent, children)
+}
+
+/// Broadcasts `range` from the member `root` to all members.
+pub fn broadcast(
+    rank: usize,
+    n: usize,
+    root: usize,
+    range: Range,
+    algorithm: Algorithm,
+    chunk_size: usize,
+) -> Vec<Step> {
+    if n < 2 || range.len == 0 {
+        return Vec::new();
+    }
+    let (parent, children) = match algorithm {
+        Algorithm::Ring => chain(rank, root, n),
+        Algorithm::Tree => tree(rank, root, n),
+    };
+    let from: Vec<usize> = parent.into_iter().collect();
+    pipeline(&from, &children, &split(range, chunk_size, 1), false)
+}
+
+/// Reduces `range`, elements of `elem_size` bytes, of all members, and leaves the result in
+/// `range` of every member. Fails if `range` is not of whole elements.
+pub fn all_reduce(
+    rank: usize,
+    n: usize,
+    range: Range,
+    elem_size: usize,
+    algorithm: Algorithm,
+    chunk_size: usize,
+) -> Result<Vec<Step>, ControlPathError> {
+    elems(range, elem_size)?;
+    if n < 2 || range.len == 0 {
+        return Ok(Vec::new());
+    }
+    match algorithm {
+        Algorithm::Ring => ring_all_reduce(rank, n, range, elem_size, chunk_size),
+        Algorithm::Tree => {
+            // reduce towards member 0, then broadcast from it
+            let chunks = split(range, chunk_size, elem_size);
+            let (parent, children) = tree(rank, 0, n);
+            let parent: Vec<usize> = parent.into_iter().collect();
+            let mut steps = pipeline(&children, &parent, &chunks, true);
+            steps.extend(pipeline(&parent, &children, &chunks, false));
+            Ok(steps)
+        }
+    }
+}
+
+/// Reduce-scatter followed by allgather on the ring. The range is cut into one segment per
+/// member, in each of the `2 * (n - 1)` rounds every member sends a segment to its successor
+/// and receives another from its predecessor.
+fn ring_all_reduce(
+    rank: usize,
+    n: usize,
+    range: Range,
+    elem_size: usize,
+    chunk_size: usize,
+) -> Result<Vec<Step>, ControlPathError> {
+    let segs = segments(range, n, elem_size)?;
+    let next = (rank + 1) % n;
+    let prev = (rank + n - 1) % n;
+    let mut steps = Vec::new();
+    let mut round = |send: usize, recv: usize, reduce: bool| {
+        // the segment received from the predecessor is as long as the one it sends
+        let sends = split(segs[send], chunk_size, elem_size);
+        let recvs = split(segs[recv], chunk_size, elem_size);
+        for c in 0..sends.len().max(recvs.len()) {
+            let mut step = Vec::new();
+            if let Some(&range) = sends.get(c) {
+                step.push(Action::Send { peer: next, range });
+            }
+            if let Some(&range) = recvs.get(c) {
+                step.push(Action::Recv {
+                    peer: prev,
+                    range,
+                    reduce,
+                });
+            }
+            steps.push(step);
+        }
+    };
+    // after round k of reduce-scatter, segment (rank - k - 1) holds the sum of k + 2 members
+    for k in 0..n - 1 {
+        round((rank + n - k) % n, (rank + 2 * n - k - 1) % n, true);
+    }
+    // segment (rank + 1) is fully reduced, pass the reduced segments around
+    for k in 0..n - 1 {
+        round((rank + 1 + n - k) % n, (rank + n - k) % n, false);
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 2] = [Algorithm::Ring, Algorithm::Tree];
+
+    fn range(offset: u64, len: u64) -> Range {
+        Range { offset, len }
+    }
+
+    /// Runs the steps of every member on its buffer. The sends of a step are posted at once and
+    /// the step completes when its receives have arrived. The bytes are reduced by a wrapping
+    /// add. Panics if a message is received with a different range than sent, or if the members
+    /// wait on each other.
+    fn run(steps: Vec<Vec<Step>>, mut buffers: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let n = steps.len();
+        // queues[from][to]
+        let mut queues = vec![vec![VecDeque::<(Range, Vec<u8>)>::new(); n]; n];
+        let mut next = vec![0; n];
+        let mut posted = vec![false; n];
+        loop {
+            let mut progress = false;
+            for rank in 0..n {
+                let Some(step) = steps[rank].get(next[rank]) else {
+                    continue;
+                };
+                if !posted[rank] {
+                    for action in step {
+                        if let Action::Send { peer, range } = *action {
+                            let bytes = &buffers[rank]
+                                [range.offset as usize..(range.offset + range.len) as usize];
+                            queues[rank][peer].push_back((range, bytes.to_vec()));
+                        }
+                    }
+                    posted[rank] = true;
+                    progress = true;
+                }
+                // the receives of a step from the same peer arrive in order
+                let mut wanted = vec![0; n];
+                for action in step {
+                    if let Action::Recv { peer, .. } = *action {
+                        wanted[peer] += 1;
+                    }
+                }
+                if (0..n).any(|peer| queues[peer][rank].len() < wanted[peer]) {
+                    continue;
+                }
+                for action in step {
+                    if let Action::Recv {
+                        peer,
+                        range,
+                        reduce,
+                    } = *action
+                    {
+                        let (sent, bytes) = queues[peer][rank].pop_front().unwrap();
+                        assert_eq!(sent.len, range.len, "{peer} -> {rank}");
+                        let dst = &mut buffers[rank]
+                            [range.offset as usize..(range.offset + range.len) as usize];
+                        for (d, s) in dst.iter_mut().zip(bytes) {
+                            *d = if reduce { d.wrapping_add(s) } else { s };
+                        }
+                    }
+                }
+                next[rank] += 1;
+                posted[rank] = false;
+                progress = true;
+            }
+            if (0..n).all(|rank| next[rank] == steps[rank].len()) {
+                break;
+            }
+            assert!(progress, "members wait on each other");
+        }
+        assert!(queues.iter().flatten().all(|q| q.is_empty()));
+        buffers
+    }
+
+    fn buffer(rank: usize, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (rank * 31 + i * 7) as u8).collect()
+    }
+
+    #[test]
+    fn split_whole_elements() {
+        let chunks = split(range(4, 20), 7, 4);
+        assert_eq!(
+            chunks,
+            [
+                range(4, 4),
+                range(8, 4),
+                range(12, 4),
+                range(16, 4),
+                range(20, 4)
+            ]
+        );
+        let chunks = split(range(0, 20), 8, 4);
+        assert_eq!(chunks, [range(0, 8), range(8, 8), range(16, 4)]);
+    }
+
+    #[test]
+    fn segments_whole_elements() {
+        let segs = segments(range(8, 40), 3, 4).unwrap();
+        assert_eq!(segs, [range(8, 12), range(20, 12), range(32, 16)]);
+        assert_eq!(segs.iter().map(|s| s.len).sum::<u64>(), 40);
+        assert!(segments(range(0, 10), 3, 4).is_err());
+        assert!(all_reduce(0, 3, range(0, 10), 4, Algorithm::Ring, 64).is_err());
+        assert!(all_reduce(0, 3, range(0, 10), 4, Algorithm::Tree, 64).is_err());
+    }
+
+    #[test]
+    fn pipeline_forwards_next_step() {
+        let chunks = [range(0, 4), range(4, 4)];
+        let steps = pipeline(&[0], &[2, 3], &chunks, false);
+        let recv = |range| Action::Recv {
+            peer: 0,
+            range,
+            reduce: false,
+        };
+        let send = |peer, range| Action::Send { peer, range };
+        assert_eq!(
+            steps,
+            [
+                vec![recv(chunks[0])],
+                vec![recv(chunks[1]), send(2, chunks[0]), send(3, chunks[0])],
+                vec![send(2, chunks[1]), send(3, chunks[1])],
+            ]
+        );
+
+        // the root sends right away, a leaf only receives
+        assert_eq!(pipeline(&[], &[1], &chunks, false).len(), 2);
+        assert_eq!(pipeline(&[0], &[], &chunks, false).len(), 2);
+    }
+
+    #[test]
+    fn broadcast_reaches_all() {
+        for algorithm in ALGORITHMS {
+            for n in 1..=7 {
+                for root in 0..n {
+                    let r = range(3, 50);
+                    let steps = (0..n)
+                        .map(|rank| broadcast(rank, n, root, r, algorithm, 16))
+                        .collect();
+                    let buffers = run(steps, (0..n).map(|rank| buffer(rank, 64)).collect());
+                    for (rank, buf) in buffers.iter().enumerate() {
+                        let mut expected = buffer(rank, 64);
+                        expected[3..53].copy_from_slice(&buffer(root, 64)[3..53]);
+                        assert_eq!(buf, &expected, "{algorithm:?} n={n} root={root}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn all_reduce_sums() {
+        for algorithm in ALGORITHMS {
+            for n in 1..=7 {
+                // fewer elements than members leaves some ring segments empty
+                for len in [8, 4 * 13] {
+                    let r = range(4, len);
+                    let steps = (0..n)
+                        .map(|rank| all_reduce(rank, n, r, 4, algorithm, 12).unwrap())
+                        .collect();
+                    let buffers = run(steps, (0..n).map(|rank| buffer(rank, 64)).collect());
+                    let end = 4 + len as usize;
+                    let mut sum = vec![0u8; 64];
+                    for rank in 0..n {
+                        for (s, b) in sum.iter_mut().zip(buffer(rank, 64)) {
+                            *s = s.wrapping_add(b);
+                        }
+                    }
+                    for (rank, buf) in buffers.iter().enumerate() {
+                        let mut expected = buffer(rank, 64);
+                        expected[4..end].copy_from_slice(&sum[4..end]);
+                        assert_eq!(buf, &expected, "{algorithm:?} n={n} len={len}");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -7,6 +7,8 @@ use serde::{Deserialize, Serialize};
 pub struct SallocConfig {
     pub prefix: Option<PathBuf>,
     pub engine_basename: String,
+    /// The most shared memory a process can allocate, in bytes. Unlimited if not set.
+    pub max_heap_size: Option<usize>,
 }
 
 impl SallocConfig {
@@ -21,6 +23,7 @@ impl Default for SallocConfig {
         SallocConfig {
             prefix: None,
             engine_basename: "salloc-engine".to_owned(),
+            max_heap_size: None,
         }
     }
 }
@@ -1,31 +1,45 @@
 use std::alloc::Layout;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::pin::Pin;
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use futures::future::BoxFuture;
 
 use phoenix_api::salloc::cmd;
+use phoenix_api::salloc::control_plane::{HeapFullPolicy, Setting};
 
 use super::module::CustomerType;
-use super::region::SharedRegion;
-use super::state::State as SallocState;
-use super::{ControlPathError, ResourceError};
+use super::region::ShmRegion;
+use super::state::{Resource, State as SallocState};
+use super::ControlPathError;
 
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::future;
-use phoenix_common::engine::{Decompose, Engine, EngineResult, Indicator};
-use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::engine::{Decompose, Engine, EngineResult, EngineState, Indicator};
 use phoenix_common::impl_vertex_for_engine;
-use phoenix_common::module::{ModuleCollection, Version};
+use phoenix_common::resource::Versioned;
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 use phoenix_common::tracing;
 
+#[derive(EngineState)]
 pub struct SallocEngine {
     pub(crate) customer: CustomerType,
+    #[state(skip)]
     pub(crate) indicator: Indicator,
+    #[state(node)]
     pub(crate) node: DataPathNode,
     pub(crate) state: SallocState,
+    pub(crate) setting: Setting,
+    pub(crate) max_heap_size: Option<usize>,
+    // an AllocShm held back by HeapFullPolicy::Block
+    pub(crate) pending_alloc: Option<PendingAlloc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PendingAlloc {
+    layout: Layout,
+    deadline: Instant,
 }
 
 impl_vertex_for_engine!(SallocEngine, node);
@@ -51,49 +65,7 @@ impl Decompose for SallocEngine {
         // needs to be upgraded at the same time
         // the last engine to detach & unload will decompose the Arc in shared
         // and put the shared resource into global resources (`global`)
-        let engine = *self;
-        let mut collections = ResourceCollection::with_capacity(2);
-        tracing::trace!("dumping Salloc engine states...");
-        collections.insert("customer".to_string(), Box::new(engine.customer));
-        // NOTE(wyj): to upgrade state, do the following instead
-        // if Arc::strong_count(&engine.state.shared) == 1 {
-        //     let shared = Arc::try_unwrap(engine.state.shared).unwrap();
-        //     collections.insert("shared-pid".to_string(), Box::new(shared.pid));
-        //     collections.insert("shared-resource-mr_table".to_string(), Box::new(shared.resource.mr_table));
-        // }
-        collections.insert("state".to_string(), Box::new(engine.state));
-        (collections, engine.node)
-    }
-}
-
-impl SallocEngine {
-    pub(crate) fn restore(
-        mut local: ResourceCollection,
-        _shared: &mut SharedStorage,
-        _global: &mut ResourceCollection,
-        node: DataPathNode,
-        _plugged: &ModuleCollection,
-        _prev_version: Version,
-    ) -> Result<Self> {
-        tracing::trace!("restoring Salloc engine");
-        let customer = *local
-            .remove("customer")
-            .unwrap()
-            .downcast::<CustomerType>()
-            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
-        let state = *local
-            .remove("state")
-            .unwrap()
-            .downcast::<SallocState>()
-            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
-
-        let engine = SallocEngine {
-            customer,
-            indicator: Default::default(),
-            node,
-            state,
-        };
-        Ok(engine)
+        EngineState::dump(*self)
     }
 }
 
@@ -118,6 +90,14 @@ impl Engine for SallocEngine {
     fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
         Box::pin(async move { self.get_mut().mainloop().await })
     }
+
+    fn doorbells(&self) -> Option<Vec<RawFd>> {
+        // a blocked allocation must be retried before its deadline
+        if self.pending_alloc.is_some() {
+            return None;
+        }
+        Some(self.customer.doorbells().to_vec())
+    }
 }
 
 impl SallocEngine {
@@ -136,11 +116,18 @@ impl SallocEngine {
 
 impl SallocEngine {
     fn check_cmd(&mut self) -> Result<Status, ControlPathError> {
+        // The client waits for the completion of the pending allocation, it sends nothing else
+        // in the meantime.
+        if let Some(pending) = self.pending_alloc {
+            return self.check_pending_alloc(pending);
+        }
+
         match self.customer.try_recv_cmd() {
             Ok(req) => {
                 let result = self.process_cmd(req);
                 match result {
-                    Ok(res) => self.customer.send_comp(cmd::Completion(Ok(res)))?,
+                    Ok(Some(res)) => self.customer.send_comp(cmd::Completion(Ok(res)))?,
+                    Ok(None) => {}
                     Err(e) => self.customer.send_comp(cmd::Completion(Err(e.into())))?,
                 }
                 Ok(Progress(1))
@@ -154,39 +141,102 @@ impl SallocEngine {
         }
     }
 
-    fn process_cmd(&mut self, req: cmd::Command) -> Result<cmd::CompletionKind, ControlPathError> {
+    fn check_pending_alloc(&mut self, pending: PendingAlloc) -> Result<Status, ControlPathError> {
+        let result = match self.alloc_shm(pending.layout) {
+            Err(ControlPathError::HeapFull { .. }) if Instant::now() < pending.deadline => {
+                return Ok(Progress(0));
+            }
+            result => result,
+        };
+        self.pending_alloc = None;
+        match result {
+            Ok(res) => self.customer.send_comp(cmd::Completion(Ok(res)))?,
+            Err(e) => self.customer.send_comp(cmd::Completion(Err(e.into())))?,
+        }
+        Ok(Progress(1))
+    }
+
+    fn alloc_shm(&self, layout: Layout) -> Result<cmd::CompletionKind, ControlPathError> {
+        let resource = self.state.resource();
+        resource
+            .try_reserve(layout.size(), self.max_heap_size)
+            .map_err(|used| ControlPathError::HeapFull {
+                requested: layout.size(),
+                used,
+                limit: self.max_heap_size.unwrap(),
+            })?;
+        let region = match ShmRegion::new(layout, &self.state.addr_mediator) {
+            Ok(region) => region,
+            Err(e) => {
+                resource.unreserve(layout.size());
+                return Err(e.into());
+            }
+        };
+        // mr's addr on backend side
+        let local_addr = region.as_ptr().expose_addr();
+        let file_off = 0;
+
+        // send fd
+        self.customer.send_fd(&[region.memfd().as_raw_fd()][..])?;
+
+        let usage = Resource::usage_of(&region);
+        let versioned = resource.mr_table.insert_versioned(local_addr, region)?;
+        resource.account.acquire(usage);
+        Ok(cmd::CompletionKind::AllocShm(
+            local_addr,
+            file_off,
+            versioned.generation,
+        ))
+    }
+
+    /// Returns `None` if the completion is deferred.
+    fn process_cmd(
+        &mut self,
+        req: cmd::Command,
+    ) -> Result<Option<cmd::CompletionKind>, ControlPathError> {
         use cmd::Command;
         match req {
             Command::AllocShm(size, align) => {
                 // TODO(wyj): implement backend heap allocator to properly handle align
                 tracing::trace!("AllocShm, size: {}", size);
                 let layout = Layout::from_size_align(size, align)?;
-                let region = SharedRegion::new(layout, &self.state.addr_mediator)?;
-                // mr's addr on backend side
-                let local_addr = region.as_ptr().expose_addr();
-                let file_off = 0;
-
-                // send fd
-                self.customer.send_fd(&[region.memfd().as_raw_fd()][..])?;
-
-                self.state
-                    .resource()
-                    .mr_table
-                    .lock()
-                    .insert(local_addr, region)
-                    .map_or_else(|| Ok(()), |_| Err(ResourceError::Exists))?;
-                Ok(cmd::CompletionKind::AllocShm(local_addr, file_off))
+                match self.alloc_shm(layout) {
+                    Err(ControlPathError::HeapFull { .. })
+                        if self.setting.heap_full_policy == HeapFullPolicy::Block =>
+                    {
+                        let timeout = Duration::from_millis(self.setting.block_timeout_ms);
+                        self.pending_alloc = Some(PendingAlloc {
+                            layout,
+                            deadline: Instant::now() + timeout,
+                        });
+                        Ok(None)
+                    }
+                    // Reclaim is done by the client on getting the error.
+                    result => result.map(Some),
+                }
             }
-            Command::DeallocShm(addr) => {
+            Command::DeallocShm(addr, generation) => {
                 // TODO(wyj): will shm dealloc when app exits?
                 // app may not dealloc all the created shm regions due to lazy_static and potential misbehave
-                self.state
+                let key = Versioned {
+                    key: addr,
+                    generation,
+                };
+                // a stale address, e.g., a region deallocated twice, does not free the region
+                // allocated at the same address since
+                let region = self
+                    .state
                     .resource()
                     .mr_table
-                    .lock()
-                    .remove(&addr)
-                    .map_or_else(|| Err(ResourceError::NotFound), |_| Ok(()))?;
-                Ok(cmd::CompletionKind::DeallocShm)
+                    .close_resource_versioned(&key)?;
+                if let Some(region) = region {
+                    self.state.resource().unreserve(region.len());
+                    self.state
+                        .resource()
+                        .account
+                        .release(Resource::usage_of(&region));
+                }
+                Ok(Some(cmd::CompletionKind::DeallocShm))
             }
         }
     }
@@ -25,6 +25,12 @@ pub enum ControlPathError {
     Layout(#[from] LayoutError),
     #[error("SharedRegion allocate error: {0}")]
     SharedRegion(#[from] region::Error),
+    #[error("Heap full: requested {requested} bytes, {used} of {limit} bytes in use")]
+    HeapFull {
+        requested: usize,
+        used: usize,
+        limit: usize,
+    },
     // Below are errors that does not return to the user.
     #[error("Ipc-channel TryRecvError")]
     IpcTryRecv,
@@ -36,7 +42,12 @@ pub enum ControlPathError {
 
 impl From<ControlPathError> for phoenix_api::Error {
     fn from(other: ControlPathError) -> Self {
-        phoenix_api::Error::Generic(other.to_string())
+        let code = match &other {
+            ControlPathError::HeapFull { .. } => phoenix_api::ErrorCode::ResourceExhausted,
+            ControlPathError::Resource(e) => e.code(),
+            _ => phoenix_api::ErrorCode::Internal,
+        };
+        phoenix_api::Error::from_error(code, &other)
     }
 }
 
@@ -7,16 +7,19 @@ use uuid::Uuid;
 
 use ipc::customer::ShmCustomer;
 use phoenix_api::engine::SchedulingMode;
+use phoenix_api::salloc::control_plane::Setting;
 use phoenix_api::salloc::{cmd, dp};
 
+use phoenix_common::capability::Capabilities;
 use phoenix_common::engine::datapath::node::DataPathNode;
-use phoenix_common::engine::{Engine, EnginePair, EngineType};
+use phoenix_common::engine::{Engine, EnginePair, EngineState, EngineType};
 use phoenix_common::module::{
     ModuleCollection, ModuleDowncast, NewEngineRequest, PhoenixModule, Service, ServiceInfo,
     Version,
 };
 use phoenix_common::state_mgr::SharedStateManager;
 use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
+use phoenix_common::tracing;
 
 use super::engine::SallocEngine;
 use super::state::{Shared, State};
@@ -33,9 +36,12 @@ pub(crate) struct SallocEngineBuilder {
     node: DataPathNode,
     shared: Arc<Shared>,
     addr_mediator: Arc<AddressMediator>,
+    setting: Setting,
+    max_heap_size: Option<usize>,
 }
 
 impl SallocEngineBuilder {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         customer: CustomerType,
         client_pid: Pid,
@@ -43,6 +49,8 @@ impl SallocEngineBuilder {
         node: DataPathNode,
         shared: Arc<Shared>,
         addr_mediator: Arc<AddressMediator>,
+        setting: Setting,
+        max_heap_size: Option<usize>,
     ) -> Self {
         SallocEngineBuilder {
             customer,
@@ -51,6 +59,8 @@ impl SallocEngineBuilder {
             node,
             shared,
             addr_mediator,
+            setting,
+            max_heap_size,
         }
     }
 
@@ -63,6 +73,9 @@ impl SallocEngineBuilder {
             indicator: Default::default(),
             node: self.node,
             state: salloc_state,
+            setting: self.setting,
+            max_heap_size: self.max_heap_size,
+            pending_alloc: None,
         })
     }
 }
@@ -117,6 +130,14 @@ impl PhoenixModule for SallocModule {
         &[]
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // the regions are memfds shared with the applications
+        Capabilities {
+            syscalls: vec![libc::SYS_memfd_create, libc::SYS_ftruncate],
+            ..Default::default()
+        }
+    }
+
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
@@ -158,7 +179,7 @@ impl PhoenixModule for SallocModule {
             client_path,
             mode,
             cred,
-            config_string: _config_string,
+            config_string,
         } = request
         {
             // 1. generate a path and bind a unix domain socket to it
@@ -179,6 +200,13 @@ impl PhoenixModule for SallocModule {
             // the transport module is responsible for initializing and starting the transport engines
             let client_pid = Pid::from_raw(cred.pid.unwrap());
 
+            let setting = if let Some(config_string) = config_string {
+                serde_json::from_str(&config_string)?
+            } else {
+                Setting::default()
+            };
+            tracing::debug!("Salloc service setting: {:?}", setting);
+
             let shared = self.state_mgr.get_or_create(client_pid)?;
             let builder = SallocEngineBuilder::new(
                 customer,
@@ -187,6 +215,8 @@ impl PhoenixModule for SallocModule {
                 node,
                 shared,
                 Arc::clone(&self.addr_mediator),
+                setting,
+                self.config.max_heap_size,
             );
 
             let engine = builder.build()?;
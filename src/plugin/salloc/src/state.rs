@@ -1,12 +1,15 @@
-use std::collections::BTreeMap;
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use nix::unistd::Pid;
 
 use crate::region::AddressMediator;
 
-use super::region::SharedRegion;
+use phoenix_common::ledger::{Account, Usage};
+use phoenix_common::resource::ResourceTableGeneric;
+
+use super::region::ShmRegion;
 use phoenix_common::state_mgr::ProcessShared;
 
 pub struct State {
@@ -41,21 +44,59 @@ impl ProcessShared for Shared {
     fn new(pid: Pid) -> io::Result<Self> {
         let shared = Shared {
             pid,
-            resource: Resource::new(),
+            resource: Resource::new(pid),
         };
         Ok(shared)
     }
 }
 
 pub struct Resource {
-    // TODO(wyj): apply the alignment trick and replace the BTreeMap here.
-    pub(crate) mr_table: spin::Mutex<BTreeMap<usize, SharedRegion>>,
+    // Regions by their address on the backend side. An address is handed out again once its
+    // region is deallocated, so the application refers to a region by the versioned address.
+    pub(crate) mr_table: ResourceTableGeneric<usize, ShmRegion>,
+    // bytes of shared memory allocated by the process
+    heap_size: AtomicUsize,
+    // the regions in mr_table, checked to be released when the process is torn down
+    pub(crate) account: Account,
 }
 
 impl Resource {
-    fn new() -> Self {
+    fn new(pid: Pid) -> Self {
         Self {
-            mr_table: spin::Mutex::new(BTreeMap::default()),
+            mr_table: ResourceTableGeneric::default(),
+            heap_size: AtomicUsize::new(0),
+            account: Account::new(pid),
         }
     }
+
+    /// What a region in `mr_table` holds.
+    #[inline]
+    pub(crate) fn usage_of(region: &ShmRegion) -> Usage {
+        Usage {
+            memory_regions: 0,
+            memfds: 1,
+            heap_bytes: region.len() as u64,
+        }
+    }
+
+    #[inline]
+    pub fn heap_size(&self) -> usize {
+        self.heap_size.load(Ordering::Acquire)
+    }
+
+    /// Accounts `size` more bytes to the heap, unless that exceeds `limit`. Returns the bytes in
+    /// use on failure.
+    pub(crate) fn try_reserve(&self, size: usize, limit: Option<usize>) -> Result<(), usize> {
+        self.heap_size
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| match limit {
+                Some(limit) if used + size > limit => None,
+                _ => Some(used + size),
+            })
+            .map(|_| ())
+    }
+
+    #[inline]
+    pub(crate) fn unreserve(&self, size: usize) {
+        self.heap_size.fetch_sub(size, Ordering::AcqRel);
+    }
 }
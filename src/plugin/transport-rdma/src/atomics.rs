@@ -0,0 +1,163 @@
+//! The atomics a device does not do natively, done by the engine as a sequence of
+//! compare-and-swaps, see [`ExtAtomic`].
+//!
+//! The compare-and-swaps of an operation are posted with the `wr_id` of the work request and
+//! always signaled. Their completions are held back from the application until a guess is right
+//! or the comparison of a masked compare-and-swap fails, and the last one is returned as the
+//! completion of the work request if it was signaled. Errors are returned at once.
+use std::mem;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api::net;
+use phoenix_api::Handle;
+use rdma::ffi::{self, ibv_wc_status};
+use rdma::ibv::ExtAtomic;
+use rdma::rdmacm;
+
+use super::ops::Ops;
+use super::recovery::Posted;
+use super::DatapathError;
+
+#[derive(Debug)]
+struct Emulated {
+    cmid: Handle,
+    op: ExtAtomic,
+    /// The compare-and-swap in flight.
+    posted: Posted,
+    /// The remote value it guessed.
+    guess: u64,
+    signaled: bool,
+}
+
+/// The extended atomics in flight, by `wr_id`.
+#[derive(Debug, Default)]
+pub(crate) struct Atomics {
+    pending: AtomicUsize,
+    inner: spin::Mutex<HashMap<u64, Vec<Emulated>>>,
+}
+
+impl Ops {
+    /// Posts the first compare-and-swap of an extended atomic.
+    ///
+    /// # Safety
+    ///
+    /// See [`Ops::post_send`].
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) unsafe fn post_ext_atomic(
+        &self,
+        cmid_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        buf: &[u8],
+        wr_id: u64,
+        remote_addr: u64,
+        rkey: u32,
+        send_flags: net::SendFlags,
+        op: ExtAtomic,
+    ) -> Result<(), DatapathError> {
+        let guess = op.first_guess();
+        // the guess is picked for the operation to succeed on
+        let swap = op.apply(guess).unwrap();
+        let flags: rdma::ibv::SendFlags = (send_flags | net::SendFlags::SIGNALED).into();
+        let posted =
+            Posted::compare_and_swap(wr_id, mr, buf, flags.0, remote_addr, rkey, guess, swap);
+
+        let atomics = &self.state.shared.atomics;
+        let mut inner = atomics.inner.lock();
+        self.post_tracked(cmid_handle, posted)?;
+        inner.entry(wr_id).or_default().push(Emulated {
+            cmid: cmid_handle,
+            op,
+            posted,
+            guess,
+            signaled: send_flags.contains(net::SendFlags::SIGNALED),
+        });
+        atomics.pending.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Looks at a work completion. Returns true if it is a compare-and-swap of an extended atomic
+    /// held back from the application.
+    pub(crate) fn advance_ext_atomic(&self, wc: &mut ffi::ibv_wc) -> bool {
+        let atomics = &self.state.shared.atomics;
+        if atomics.pending.load(Ordering::Relaxed) == 0 {
+            return false;
+        }
+        let mut inner = atomics.inner.lock();
+        let emulated = match inner.get_mut(&wc.wr_id()) {
+            Some(emulated) => emulated,
+            None => return false,
+        };
+        let pos = match emulated
+            .iter()
+            .position(|e| self.current_qp_num(e.cmid) == Some(wc.qp_num))
+        {
+            Some(pos) => pos,
+            None => return false,
+        };
+
+        let e = &mut emulated[pos];
+        let next = if wc.is_valid() {
+            // SAFETY: the buffer is valid until the work request completes
+            let current = unsafe { e.posted.fetched() };
+            if current == e.guess {
+                None
+            } else {
+                e.op.apply(current).map(|swap| (current, swap))
+            }
+        } else {
+            None
+        };
+        if let Some((guess, swap)) = next {
+            // guess again from the value found
+            let posted = e.posted.with_operands(guess, swap);
+            // SAFETY: the same buffer as the work request
+            match unsafe { self.post_tracked(e.cmid, posted) } {
+                Ok(()) => {
+                    e.posted = posted;
+                    e.guess = guess;
+                    return true;
+                }
+                Err(err) => {
+                    let failed = net::WorkCompletion::new_vendor_err(
+                        wc.wr_id(),
+                        net::WcStatus::Error(
+                            NonZeroU32::new(ibv_wc_status::IBV_WC_GENERAL_ERR).unwrap(),
+                        ),
+                        err.into_vendor_err(),
+                    );
+                    // SAFETY: the ibv_wc and net::WorkCompletion have the same layout
+                    *wc = unsafe { mem::transmute(failed) };
+                }
+            }
+        }
+
+        let e = emulated.swap_remove(pos);
+        if emulated.is_empty() {
+            inner.remove(&wc.wr_id());
+        }
+        atomics.pending.fetch_sub(1, Ordering::Relaxed);
+        if !wc.is_valid() {
+            return false;
+        }
+        if let ExtAtomic::FetchAndAdd { .. } = e.op {
+            // SAFETY: the ibv_wc and net::WorkCompletion have the same layout
+            let wc: &mut net::WorkCompletion = unsafe { mem::transmute(wc) };
+            wc.opcode = net::WcOpcode::FetchAdd;
+        }
+        !e.signaled
+    }
+
+    fn current_qp_num(&self, cmid_handle: Handle) -> Option<u32> {
+        let recovery = &self.state.shared.recovery;
+        let key = if recovery.is_enabled() {
+            recovery.lock().current(cmid_handle.0 as usize)
+        } else {
+            cmid_handle.0 as usize
+        };
+        let cmid = self.resource().cmid_table.get_dp(key).ok()?;
+        cmid.qp().map(|qp| qp.qp_num())
+    }
+}
@@ -4,6 +4,8 @@ use std::sync::Arc;
 use anyhow::{anyhow, Result};
 use futures::future::BoxFuture;
 
+use super::super::link;
+use super::super::ops::Ops;
 use super::super::state::State;
 use super::super::ApiError;
 
@@ -105,6 +107,10 @@ impl CmEngine {
             let mut nwork = 0;
             let Progress(n) = self.check_cm_event()?;
             nwork += n;
+            let Progress(n) = self.check_links();
+            nwork += n;
+            let Progress(n) = self.check_recovery();
+            nwork += n;
             if Arc::strong_count(&self.state.shared) == 1 {
                 // cm_engine is the last active engine
                 return Ok(());
@@ -142,6 +148,27 @@ impl CmEngine {
             .shared
             .cm_manager
             .blocking_lock()
-            .poll_cm_event_once(&self.state.resource().event_channel_table)
+            .poll_cm_event_once(
+                &self.state.resource().event_channel_table,
+                self.state.shared.recovery.is_enabled(),
+            )
+    }
+
+    fn check_links(&mut self) -> Status {
+        Progress(link::poll_async_events())
+    }
+
+    fn check_recovery(&mut self) -> Status {
+        if !self.state.shared.recovery.is_enabled() {
+            return Progress(0);
+        }
+        let ops = Ops::new(State::new(Arc::clone(&self.state.shared)));
+        match ops.check_recovery() {
+            Ok(n) => Progress(n),
+            Err(e) => {
+                tracing::warn!("check_recovery: {}", e);
+                Progress(0)
+            }
+        }
     }
 }
@@ -1,24 +1,38 @@
-// use fnv::FnvHashMap as HashMap;
 use std::collections::VecDeque;
 use std::io;
 use std::os::unix::io::AsRawFd;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
+use fnv::FnvHashMap as HashMap;
 use phoenix_api::Handle;
 use rdma::rdmacm;
 
 use phoenix_common::log;
-use phoenix_common::resource::{Error as ResourceError, ResourceTable};
+use phoenix_common::resource::{Error as ResourceError, ResourceTable, Versioned};
 
+use super::recovery;
 use super::state::EventChannel;
 use crate::ApiError;
 
 pub(crate) mod engine;
 
+/// The number of registered event channels below which the closed ones are not pruned.
+const MIN_PRUNE_AT: usize = 64;
+
 pub(crate) struct CmEventManager {
     pub(crate) poll: mio::Poll,
     pub(crate) err_buffer: VecDeque<ApiError>,
+    /// The event channels registered, by the token of their registration. The token is the
+    /// generation of the channel in the table, so the events of a closed channel are never taken
+    /// for the events of a new channel with the same handle.
+    channels: HashMap<usize, Versioned<Handle>>,
+    /// The number of entries in `channels` to prune the closed channels at.
+    prune_at: usize,
+    /// The event channels of the connections disconnected by the peers, for the recovery.
+    pub(crate) disconnected: Vec<Versioned<Handle>>,
+    /// The connect requests that re-establish a connection, taken by the recovery.
+    pub(crate) reconnect_requests: Vec<rdmacm::CmEvent>,
 }
 
 impl CmEventManager {
@@ -26,26 +40,44 @@ impl CmEventManager {
         Ok(CmEventManager {
             poll: mio::Poll::new()?,
             err_buffer: VecDeque::new(),
+            channels: HashMap::default(),
+            prune_at: MIN_PRUNE_AT,
+            disconnected: Vec::new(),
+            reconnect_requests: Vec::new(),
         })
     }
 
     /// Add the event channel to the PollSet.
     pub(crate) fn register_event_channel(
-        &self,
-        channel_handle: Handle,
+        &mut self,
+        ec: Versioned<Handle>,
         channel: &rdmacm::EventChannel,
+        event_channel_table: &ResourceTable<EventChannel>,
     ) -> Result<(), ApiError> {
+        let token = ec.generation as usize;
         self.poll
             .registry()
             .register(
                 &mut mio::unix::SourceFd(&channel.as_raw_fd()),
-                mio::Token(channel_handle.0 as _),
+                mio::Token(token),
                 mio::Interest::READABLE,
             )
             .map_err(ApiError::Mio)?;
+        self.channels.insert(token, ec);
+        self.prune(event_channel_table);
         Ok(())
     }
 
+    /// Forgets the channels closed since, once the registered channels have doubled.
+    fn prune(&mut self, event_channel_table: &ResourceTable<EventChannel>) {
+        if self.channels.len() < self.prune_at {
+            return;
+        }
+        self.channels
+            .retain(|_, ec| event_channel_table.get_versioned(ec).is_ok());
+        self.prune_at = (self.channels.len() * 2).max(MIN_PRUNE_AT);
+    }
+
     pub(crate) fn first_error(&mut self) -> Option<ApiError> {
         self.err_buffer.pop_front()
     }
@@ -53,6 +85,7 @@ impl CmEventManager {
     pub(crate) fn poll_cm_event_once(
         &mut self,
         event_channel_table: &ResourceTable<EventChannel>,
+        recover: bool,
     ) -> Result<(), ApiError> {
         let mut events = mio::Events::with_capacity(1);
         self.poll
@@ -60,12 +93,16 @@ impl CmEventManager {
             .map_err(ApiError::Mio)?;
 
         if let Some(io_event) = events.iter().next() {
-            let handle = Handle(io_event.token().0 as _);
-            let event_channel = match event_channel_table.get(&handle) {
+            let Some(&ec) = self.channels.get(&io_event.token().0) else {
+                log::warn!("unknown event channel token {:?}", io_event.token());
+                return Ok(());
+            };
+            let event_channel = match event_channel_table.get_versioned(&ec) {
                 Ok(ec) => ec,
-                Err(ResourceError::NotFound) => {
+                Err(ResourceError::NotFound | ResourceError::Stale) => {
                     // skip the error and only leave a warning message
-                    log::warn!("event_channel_handle {:?} not found in the table", handle);
+                    log::warn!("event channel {:?} not found in the table", ec);
+                    self.channels.remove(&io_event.token().0);
                     return Ok(());
                 }
                 Err(e) => {
@@ -85,6 +122,9 @@ impl CmEventManager {
                     Ordering::SeqCst,
                 ) {
                     log::debug!("passively call disconnect");
+                    if recover {
+                        self.disconnected.push(ec);
+                    }
                     cm_event.id().disconnect().map_err(ApiError::RdmaCm)?;
                 }
 
@@ -102,6 +142,14 @@ impl CmEventManager {
                 )
                 .map_err(ApiError::Mio)?;
 
+            if cm_event.event() == rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_CONNECT_REQUEST
+                && recover
+                && recovery::is_reconnect(cm_event.private_data())
+            {
+                self.reconnect_requests.push(cm_event);
+                return Ok(());
+            }
+
             // Add CmEvent to event channel buffer
             event_channel.add_event(cm_event);
 
@@ -1,7 +1,19 @@
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use phoenix_api::net::AccessFlags;
+
+/// A kind of access to a memory region by the peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteAccess {
+    Read,
+    Write,
+    Atomic,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct RdmaTransportConfig {
@@ -10,6 +22,83 @@ pub struct RdmaTransportConfig {
     pub datapath_wq_depth: usize,
     pub datapath_cq_depth: usize,
     pub command_max_interval_ms: u32,
+    /// The remote access the applications may grant to the memory regions they register.
+    pub remote_access: Vec<RemoteAccess>,
+    pub recovery: RecoveryConfig,
+    /// Used only when built with the `dc` feature.
+    pub dc: DcConfig,
+    pub soft_rdma: SoftRdmaConfig,
+}
+
+/// Re-establishing the connections whose queue pair went into the error state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecoveryConfig {
+    /// Every work request is kept until its completion to be posted again, which costs a lock on
+    /// the data path.
+    pub enabled: bool,
+    /// The reconnection attempts before a connection is given up.
+    pub max_attempts: u32,
+    /// The delay before the first attempt, doubled at each of the next ones.
+    pub backoff_ms: u64,
+    pub resolve_timeout_ms: i32,
+    /// Local addresses on other ports, for multi-path. A connection whose port is down is
+    /// re-established from each of them in turn, and from any address, until an attempt
+    /// succeeds.
+    pub failover_addrs: Vec<IpAddr>,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        RecoveryConfig {
+            enabled: false,
+            max_attempts: 5,
+            backoff_ms: 100,
+            resolve_timeout_ms: 2000,
+            failover_addrs: Vec::new(),
+        }
+    }
+}
+
+/// The dynamically connected transport.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DcConfig {
+    /// The GID the DC targets and initiators use for the address vectors, which must be a RoCE v2
+    /// GID on RoCE.
+    pub gid_index: u8,
+}
+
+/// When to run as on software RDMA, i.e., SoftRoCE (rxe) or soft-iWARP (siw).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SoftRdmaMode {
+    /// When every device is a software one, or when phoenixd runs with `--soft-rdma`.
+    Auto,
+    Always,
+    Never,
+}
+
+/// Relaxing the timeouts and the inline data for software RDMA.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoftRdmaConfig {
+    pub mode: SoftRdmaMode,
+    /// The inline data the queue pairs are created with at most. The sends larger than it are
+    /// posted from their memory regions.
+    pub max_inline_data: u32,
+    /// The least timeout to resolve an address or a route with.
+    pub min_timeout_ms: i32,
+}
+
+impl Default for SoftRdmaConfig {
+    fn default() -> Self {
+        SoftRdmaConfig {
+            mode: SoftRdmaMode::Auto,
+            max_inline_data: 64,
+            min_timeout_ms: 10000,
+        }
+    }
 }
 
 impl Default for RdmaTransportConfig {
@@ -20,6 +109,14 @@ impl Default for RdmaTransportConfig {
             datapath_wq_depth: 32,
             datapath_cq_depth: 32,
             command_max_interval_ms: 1000,
+            remote_access: vec![
+                RemoteAccess::Read,
+                RemoteAccess::Write,
+                RemoteAccess::Atomic,
+            ],
+            recovery: RecoveryConfig::default(),
+            dc: DcConfig::default(),
+            soft_rdma: SoftRdmaConfig::default(),
         }
     }
 }
@@ -29,4 +126,15 @@ impl RdmaTransportConfig {
         let config = toml::from_str(config.unwrap_or(""))?;
         Ok(config)
     }
+
+    /// Returns the remote access flags the memory regions may be registered with.
+    pub fn permitted_access(&self) -> AccessFlags {
+        self.remote_access
+            .iter()
+            .fold(AccessFlags::empty(), |acc, access| match access {
+                RemoteAccess::Read => acc | AccessFlags::REMOTE_READ,
+                RemoteAccess::Write => acc | AccessFlags::REMOTE_WRITE,
+                RemoteAccess::Atomic => acc | AccessFlags::REMOTE_ATOMIC,
+            })
+    }
 }
@@ -0,0 +1,299 @@
+//! Dynamically connected transport, see [`rdma::dc`].
+//!
+//! The peers exchange their DC targets through the connection manager. A DC target set on a
+//! `CmId` is advertised in the private data of its connect or accept, after a token, and the DC
+//! target the other end advertised is kept with the `CmId`. A DC peer is created from it for a
+//! DC initiator, and the `CmId` can be destroyed then, so a process keeps one connection
+//! established at a time however many peers it reaches.
+use std::slice;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api::net;
+use phoenix_api::transport::rdma::control_plane::SrqInfo;
+use phoenix_api::Handle;
+use rdma::dc::{DcEndpoint, DcInitiator, DcPeer, DcTarget};
+use rdma::ibv;
+use rdma::rdmacm;
+
+use phoenix_common::log;
+use phoenix_common::resource::ResourceSlab;
+
+use super::config::DcConfig;
+use super::ops::{Ops, Result};
+use super::{ApiError, DatapathError};
+
+/// Prefixes the DC target in the private data of a connect or accept.
+const TOKEN_MAGIC: &[u8; 8] = b"phxdctgt";
+
+/// Puts the token of `endpoint` before the private data of the application.
+pub(crate) fn with_endpoint(
+    conn_param: Option<&net::ConnParam>,
+    endpoint: &DcEndpoint,
+) -> net::ConnParam {
+    let mut conn_param = conn_param.cloned().unwrap_or(net::ConnParam {
+        private_data: None,
+        responder_resources: 1,
+        initiator_depth: 1,
+        flow_control: 0,
+        retry_count: 7,
+        rnr_retry_count: 7,
+        srq: 0,
+        qp_num: 0,
+    });
+    let mut data = TOKEN_MAGIC.to_vec();
+    data.extend_from_slice(&endpoint.encode());
+    if let Some(app_data) = conn_param.private_data.take() {
+        data.extend_from_slice(&app_data);
+    }
+    conn_param.private_data = Some(data);
+    conn_param
+}
+
+/// Finds the DC target advertised in the private data of a connect request or an accept.
+pub(crate) fn decode_endpoint(private_data: &[u8]) -> Option<DcEndpoint> {
+    private_data
+        .strip_prefix(TOKEN_MAGIC)
+        .and_then(DcEndpoint::decode)
+}
+
+#[derive(Default)]
+pub(crate) struct DcState {
+    gid_index: AtomicU8,
+    targets: ResourceSlab<DcTarget<'static>>,
+    initiators: ResourceSlab<DcInitiator<'static>>,
+    peers: ResourceSlab<DcPeer<'static>>,
+    /// The DC target advertised on each `CmId`, by the key of the `CmId`.
+    advertised: spin::Mutex<HashMap<usize, DcEndpoint>>,
+    /// The DC target the other end of each `CmId` advertised.
+    remote: spin::Mutex<HashMap<usize, DcEndpoint>>,
+}
+
+impl DcState {
+    pub(crate) fn configure(&self, config: &DcConfig) {
+        self.gid_index.store(config.gid_index, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn gid_index(&self) -> u8 {
+        self.gid_index.load(Ordering::Relaxed)
+    }
+
+    /// The DC target to advertise on the `CmId` of `key`.
+    #[inline]
+    pub(crate) fn advertised(&self, key: usize) -> Option<DcEndpoint> {
+        self.advertised.lock().get(&key).copied()
+    }
+
+    /// Keeps the DC target the other end of the `CmId` of `key` advertised.
+    pub(crate) fn set_remote(&self, key: usize, private_data: &[u8]) {
+        if let Some(endpoint) = decode_endpoint(private_data) {
+            self.remote.lock().insert(key, endpoint);
+        }
+    }
+
+    pub(crate) fn forget(&self, key: usize) {
+        self.advertised.lock().remove(&key);
+        self.remote.lock().remove(&key);
+    }
+
+    /// The limits of the shared receive queues of the DC targets.
+    pub(crate) fn srq_infos(&self) -> Vec<SrqInfo> {
+        let mut keys = self.targets.keys();
+        keys.sort_unstable();
+        keys.into_iter()
+            .filter_map(|key| {
+                let dct = self.targets.get(key).ok()?;
+                match dct.query_srq() {
+                    Ok(attr) => Some(SrqInfo {
+                        dct: net::DcTarget(Handle(key as u64)),
+                        max_wr: attr.max_wr,
+                        max_sge: attr.max_sge,
+                        srq_limit: attr.srq_limit,
+                    }),
+                    Err(e) => {
+                        log::warn!("querying the SRQ of DC target {}: {}", key, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl Ops {
+    #[inline]
+    pub(crate) fn dc(&self) -> &DcState {
+        &self.state.shared.dc
+    }
+
+    /// The connection parameters that advertise the DC target set on the `CmId` of `key`, if
+    /// any.
+    pub(crate) fn dc_conn_param(
+        &self,
+        key: usize,
+        conn_param: Option<&net::ConnParam>,
+    ) -> Option<net::ConnParam> {
+        self.dc()
+            .advertised(key)
+            .map(|endpoint| with_endpoint(conn_param, &endpoint))
+    }
+
+    pub fn create_dc_target(
+        &self,
+        pd: &net::ProtectionDomain,
+        cq: &net::CompletionQueue,
+        max_recv_wr: u32,
+    ) -> Result<net::DcTarget> {
+        log::debug!(
+            "CreateDcTarget, pd: {:?}, cq: {:?}, max_recv_wr: {}",
+            pd,
+            cq,
+            max_recv_wr
+        );
+
+        let pd = self.resource().pd_table.get(&pd.0)?;
+        let cq = self.resource().cq_table.get(cq.0 .0 as usize)?;
+        // the key only keeps out the initiators of the peers not connected to
+        let key = uuid::Uuid::new_v4().as_u128() as u64;
+        let dct = DcTarget::create(&pd, &cq, max_recv_wr, key, self.dc().gid_index())
+            .map_err(ApiError::Ibv)?;
+        let key = self.dc().targets.insert(dct)?;
+        Ok(net::DcTarget(Handle(key as u64)))
+    }
+
+    pub fn create_dc_initiator(
+        &self,
+        pd: &net::ProtectionDomain,
+        cq: &net::CompletionQueue,
+        max_send_wr: u32,
+    ) -> Result<net::DcInitiator> {
+        log::debug!(
+            "CreateDcInitiator, pd: {:?}, cq: {:?}, max_send_wr: {}",
+            pd,
+            cq,
+            max_send_wr
+        );
+
+        let pd = self.resource().pd_table.get(&pd.0)?;
+        let cq = self.resource().cq_table.get(cq.0 .0 as usize)?;
+        let dci = DcInitiator::create(&pd, &cq, max_send_wr, self.dc().gid_index())
+            .map_err(ApiError::Ibv)?;
+        let key = self.dc().initiators.insert(dci)?;
+        Ok(net::DcInitiator(Handle(key as u64)))
+    }
+
+    pub fn set_dc_target(&self, cmid: &net::CmId, dct: &net::DcTarget) -> Result<()> {
+        log::debug!("SetDcTarget, cmid: {:?}, dct: {:?}", cmid, dct);
+
+        // the CmId must exist
+        self.resource().cmid_table.get(cmid.0 .0 as usize)?;
+        let endpoint = self.dc().targets.get(dct.0 .0 as usize)?.endpoint();
+        self.dc()
+            .advertised
+            .lock()
+            .insert(cmid.0 .0 as usize, endpoint);
+        Ok(())
+    }
+
+    pub fn create_dc_peer(&self, dci: &net::DcInitiator, cmid: &net::CmId) -> Result<net::DcPeer> {
+        log::debug!("CreateDcPeer, dci: {:?}, cmid: {:?}", dci, cmid);
+
+        let remote = self
+            .dc()
+            .remote
+            .lock()
+            .get(&(cmid.0 .0 as usize))
+            .copied()
+            .ok_or(ApiError::NotFound)?;
+        let dci = self.dc().initiators.get(dci.0 .0 as usize)?;
+        let peer = dci.create_peer(&remote).map_err(ApiError::Ibv)?;
+        let key = self.dc().peers.insert(peer)?;
+        Ok(net::DcPeer(Handle(key as u64)))
+    }
+
+    pub fn destroy_dc_target(&self, dct: &net::DcTarget) -> Result<()> {
+        log::debug!("DestroyDcTarget, dct: {:?}", dct);
+        self.dc().targets.close_resource_by_key(dct.0 .0 as usize)?;
+        Ok(())
+    }
+
+    pub fn destroy_dc_initiator(&self, dci: &net::DcInitiator) -> Result<()> {
+        log::debug!("DestroyDcInitiator, dci: {:?}", dci);
+        self.dc()
+            .initiators
+            .close_resource_by_key(dci.0 .0 as usize)?;
+        Ok(())
+    }
+
+    pub fn destroy_dc_peer(&self, peer: &net::DcPeer) -> Result<()> {
+        log::debug!("DestroyDcPeer, peer: {:?}", peer);
+        self.dc().peers.close_resource_by_key(peer.0 .0 as usize)?;
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// See [`Ops::post_recv`].
+    #[inline]
+    pub unsafe fn post_dc_recv(
+        &self,
+        dct_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+    ) -> std::result::Result<(), DatapathError> {
+        let dct = self.dc().targets.get_dp(dct_handle.0 as usize)?;
+        let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
+        // the application owns the buffer, see `Ops::post_recv`
+        let buf = slice::from_raw_parts_mut(buf.as_ptr() as *mut u8, buf.len());
+        dct.post_recv(wr_id, buf, mr).map_err(DatapathError::Ibv)
+    }
+
+    /// # Safety
+    ///
+    /// See [`Ops::post_send`].
+    #[inline]
+    pub unsafe fn post_dc_send(
+        &self,
+        dci_handle: Handle,
+        peer_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+        send_flags: net::SendFlags,
+    ) -> std::result::Result<(), DatapathError> {
+        let dci = self.dc().initiators.get_dp(dci_handle.0 as usize)?;
+        let peer = self.dc().peers.get_dp(peer_handle.0 as usize)?;
+        let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
+        let flags: ibv::SendFlags = send_flags.into();
+        dci.post_send(&peer, wr_id, buf, mr, flags)
+            .map_err(DatapathError::Ibv)
+    }
+
+    /// # Safety
+    ///
+    /// See [`Ops::post_write`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_dc_write(
+        &self,
+        dci_handle: Handle,
+        peer_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+        send_flags: net::SendFlags,
+    ) -> std::result::Result<(), DatapathError> {
+        let dci = self.dc().initiators.get_dp(dci_handle.0 as usize)?;
+        let peer = self.dc().peers.get_dp(peer_handle.0 as usize)?;
+        let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
+        let remote_addr = rkey.addr + remote_offset;
+        let flags: ibv::SendFlags = send_flags.into();
+        dci.post_write(&peer, wr_id, buf, mr, flags, remote_addr, rkey.rkey)
+            .map_err(DatapathError::Ibv)
+    }
+}
@@ -2,8 +2,10 @@ use std::collections::VecDeque;
 use std::io;
 use std::mem;
 use std::os::unix::io::AsRawFd;
+use std::os::unix::ucred::UCred;
 use std::pin::Pin;
 use std::slice;
+use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use futures::future::BoxFuture;
@@ -11,7 +13,7 @@ use futures::future::BoxFuture;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::net;
 use phoenix_api::net::returned;
-use phoenix_api::transport::rdma::{cmd, dp};
+use phoenix_api::transport::rdma::{cmd, control_plane, dp};
 use phoenix_api::{AsHandle, Handle};
 
 // use rdma::ibv;
@@ -19,6 +21,7 @@ use rdma::rdmacm;
 
 use super::module::CustomerType;
 use super::ops::Ops;
+use super::state::Resource;
 use super::{ApiError, DatapathError, Error};
 
 use phoenix_common::engine::datapath::node::DataPathNode;
@@ -36,6 +39,8 @@ pub(crate) struct TransportEngine {
 
     pub(crate) node: DataPathNode,
     pub(crate) ops: Ops,
+    // The remote access the memory regions may be registered with.
+    pub(crate) permitted_access: net::AccessFlags,
     pub(crate) cq_err_buffer: VecDeque<dp::Completion>, // TODO(cjr): limit the length of the queue
     pub(crate) wr_read_buffer: Vec<dp::WorkRequest>,
 }
@@ -54,11 +59,15 @@ impl Decompose for TransportEngine {
         _global: &mut ResourceCollection,
     ) -> (ResourceCollection, DataPathNode) {
         let engine = *self;
-        let mut collections = ResourceCollection::with_capacity(5);
+        let mut collections = ResourceCollection::with_capacity(6);
         tracing::trace!("dumping RdmaTransport-TransportEngine states...");
         collections.insert("customer".to_string(), Box::new(engine.customer));
         collections.insert("mode".to_string(), Box::new(engine._mode));
         collections.insert("ops".to_string(), Box::new(engine.ops));
+        collections.insert(
+            "permitted_access".to_string(),
+            Box::new(engine.permitted_access),
+        );
         collections.insert("cq_err_buffer".to_string(), Box::new(engine.cq_err_buffer));
         collections.insert(
             "wr_read_buffer".to_string(),
@@ -93,6 +102,11 @@ impl TransportEngine {
             .unwrap()
             .downcast::<Ops>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let permitted_access = *local
+            .remove("permitted_access")
+            .unwrap()
+            .downcast::<net::AccessFlags>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
         let cq_err_buffer = *local
             .remove("cq_err_buffer")
             .unwrap()
@@ -110,6 +124,7 @@ impl TransportEngine {
             _mode: mode,
             node,
             ops,
+            permitted_access,
             cq_err_buffer,
             wr_read_buffer,
         };
@@ -141,6 +156,17 @@ impl Engine for TransportEngine {
     fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
         &mut self.get_mut().indicator
     }
+
+    fn handle_query(&mut self, query: Vec<u8>, _cred: UCred) -> Result<Vec<u8>> {
+        let query: control_plane::Query = bincode::deserialize(&query[..])?;
+
+        let response = match query {
+            control_plane::Query::Introspect => {
+                control_plane::QueryResponse::Introspect(self.ops.introspect())
+            }
+        };
+        Ok(bincode::serialize(&response)?)
+    }
 }
 
 impl TransportEngine {
@@ -349,7 +375,10 @@ impl TransportEngine {
                     (net::CompletionQueue(Handle::INVALID), *wr_id)
                 }
             }
-            WorkRequest::PostRead(cmid_handle, _, wr_id, ..) => {
+            WorkRequest::PostRead(cmid_handle, _, wr_id, ..)
+            | WorkRequest::PostCompareAndSwap(cmid_handle, _, wr_id, ..)
+            | WorkRequest::PostFetchAndAdd(cmid_handle, _, wr_id, ..)
+            | WorkRequest::PostMaskedCompareAndSwap(cmid_handle, _, wr_id, ..) => {
                 if let Ok(cmid) = self
                     .ops
                     .resource()
@@ -365,6 +394,12 @@ impl TransportEngine {
                     (net::CompletionQueue(Handle::INVALID), *wr_id)
                 }
             }
+            // the DC work requests do not know their completion queue
+            WorkRequest::PostDcRecv(_, wr_id, ..)
+            | WorkRequest::PostDcSend(_, _, wr_id, ..)
+            | WorkRequest::PostDcWrite(_, _, wr_id, ..) => {
+                (net::CompletionQueue(Handle::INVALID), *wr_id)
+            }
         }
     }
 
@@ -430,6 +465,9 @@ impl TransportEngine {
             match cq.poll(&mut wc) {
                 Ok(completions) if !completions.is_empty() => {
                     for w in completions {
+                        if self.ops.hold_back(*cq_handle, w) {
+                            continue;
+                        }
                         self.cq_err_buffer.push_back(dp::Completion {
                             cq_handle: *cq_handle,
                             _padding: Default::default(),
@@ -559,8 +597,174 @@ impl TransportEngine {
                 }
                 Ok(())
             }
+            WorkRequest::PostCompareAndSwap(
+                cmid_handle,
+                mr_handle,
+                wr_id,
+                range,
+                remote_offset,
+                rkey,
+                send_flags,
+                compare,
+                swap,
+            ) => {
+                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
+                unsafe {
+                    self.ops.post_compare_and_swap(
+                        *cmid_handle,
+                        &rdma_mr,
+                        *range,
+                        *wr_id,
+                        *rkey,
+                        *remote_offset,
+                        *send_flags,
+                        *compare,
+                        *swap,
+                    )?;
+                }
+                Ok(())
+            }
+            WorkRequest::PostFetchAndAdd(
+                cmid_handle,
+                mr_handle,
+                wr_id,
+                range,
+                remote_offset,
+                rkey,
+                send_flags,
+                add,
+                mask,
+            ) => {
+                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
+                unsafe {
+                    self.ops.post_fetch_and_add(
+                        *cmid_handle,
+                        &rdma_mr,
+                        *range,
+                        *wr_id,
+                        *rkey,
+                        *remote_offset,
+                        *send_flags,
+                        *add,
+                        *mask,
+                    )?;
+                }
+                Ok(())
+            }
+            WorkRequest::PostMaskedCompareAndSwap(
+                cmid_handle,
+                mr_handle,
+                wr_id,
+                range,
+                remote_offset,
+                rkey,
+                send_flags,
+                compare,
+                compare_mask,
+                swap,
+                swap_mask,
+            ) => {
+                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
+                unsafe {
+                    self.ops.post_masked_compare_and_swap(
+                        *cmid_handle,
+                        &rdma_mr,
+                        *range,
+                        *wr_id,
+                        *rkey,
+                        *remote_offset,
+                        *send_flags,
+                        *compare,
+                        *compare_mask,
+                        *swap,
+                        *swap_mask,
+                    )?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "dc")]
+            WorkRequest::PostDcRecv(dct_handle, wr_id, range, mr_handle) => {
+                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
+                unsafe {
+                    self.ops
+                        .post_dc_recv(*dct_handle, &rdma_mr, *range, *wr_id)?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "dc")]
+            WorkRequest::PostDcSend(
+                dci_handle,
+                peer_handle,
+                wr_id,
+                range,
+                mr_handle,
+                send_flags,
+            ) => {
+                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
+                unsafe {
+                    self.ops.post_dc_send(
+                        *dci_handle,
+                        *peer_handle,
+                        &rdma_mr,
+                        *range,
+                        *wr_id,
+                        *send_flags,
+                    )?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "dc")]
+            WorkRequest::PostDcWrite(
+                dci_handle,
+                peer_handle,
+                wr_id,
+                range,
+                mr_handle,
+                remote_offset,
+                rkey,
+                send_flags,
+            ) => {
+                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
+                unsafe {
+                    self.ops.post_dc_write(
+                        *dci_handle,
+                        *peer_handle,
+                        &rdma_mr,
+                        *range,
+                        *wr_id,
+                        *rkey,
+                        *remote_offset,
+                        *send_flags,
+                    )?;
+                }
+                Ok(())
+            }
+            #[cfg(not(feature = "dc"))]
+            WorkRequest::PostDcRecv(..)
+            | WorkRequest::PostDcSend(..)
+            | WorkRequest::PostDcWrite(..) => Err(DatapathError::Ibv(io::Error::from(
+                nix::errno::Errno::EOPNOTSUPP,
+            ))),
             WorkRequest::PollCq(cq_handle) => {
                 // trace!("cq_handle: {:?}", cq_handle);
+                let recovery = Arc::clone(&self.ops.state.shared.recovery);
+                if recovery.is_enabled() {
+                    // the work requests of the connections given up
+                    let cq_err_buffer = &mut self.cq_err_buffer;
+                    recovery.lock().drain_failed(*cq_handle, usize::MAX, |wc| {
+                        cq_err_buffer.push_back(dp::Completion {
+                            cq_handle: *cq_handle,
+                            _padding: Default::default(),
+                            wc,
+                        });
+                    });
+                }
                 self.try_flush_cq_err_buffer()?;
 
                 // Poll the completions and put them directly into the shared memory queue.
@@ -609,7 +813,13 @@ impl TransportEngine {
                                 1,
                             );
                             match cq.poll(wc) {
-                                Ok(completions) if !completions.is_empty() => cnt += 1,
+                                Ok(completions) if !completions.is_empty() => {
+                                    // the slot is reused if the completion is held back for a
+                                    // connection being recovered or an atomic done by the engine
+                                    if !self.ops.hold_back(*cq_handle, &mut completions[0]) {
+                                        cnt += 1;
+                                    }
+                                }
                                 Ok(_) => {
                                     wc.as_mut_ptr()
                                         .cast::<net::WorkCompletion>()
@@ -693,6 +903,18 @@ impl TransportEngine {
                 Ok(CompletionKind::CmCreateQp(ret_qp))
             }
             Command::RegMr(pd, nbytes, access) => {
+                let remote = net::AccessFlags::REMOTE_READ
+                    | net::AccessFlags::REMOTE_WRITE
+                    | net::AccessFlags::REMOTE_ATOMIC;
+                let denied = (*access & remote) - self.permitted_access;
+                if !denied.is_empty() {
+                    // An empty set of fds tells the customer to receive the error.
+                    self.customer
+                        .send_fd(&[])
+                        .map_err(ApiError::SendFd)
+                        .unwrap();
+                    return Err(ApiError::AccessDenied(denied).into());
+                }
                 let mr = self.ops.reg_mr(pd, *nbytes, *access)?;
 
                 // TODO(cjr): If there is an error above, the customer will be confused.
@@ -709,12 +931,14 @@ impl TransportEngine {
                 let raw_mr_handle = mr.as_handle();
                 let file_off = mr.file_off() as u64;
                 let pd_handle = mr.pd().as_handle();
+                let usage = Resource::usage_of(&mr);
                 let key = self
                     .ops
                     .resource()
                     .mr_table
                     .occupy_or_create_resource(raw_mr_handle, mr)
                     .map_err(ApiError::from)?;
+                self.ops.resource().account.acquire(usage);
                 let new_mr_handle = Handle(key as u64);
 
                 let ret_mr = returned::MemoryRegion {
@@ -730,11 +954,14 @@ impl TransportEngine {
             }
             Command::DeregMr(mr) => {
                 tracing::trace!("DeregMr, mr: {:?}", mr);
-                self.ops
-                    .resource()
+                let resource = self.ops.resource();
+                let closed = resource
                     .mr_table
                     .close_resource_by_key(mr.0 .0 as usize)
                     .map_err(ApiError::from)?;
+                if let Some(mr) = closed {
+                    resource.account.release(Resource::usage_of(&mr));
+                }
                 Ok(CompletionKind::DeregMr)
             }
             Command::DeallocPd(pd) => {
@@ -757,6 +984,10 @@ impl TransportEngine {
                 self.ops.destroy_id(cmid)?;
                 Ok(CompletionKind::DestroyId)
             }
+            Command::TryGetConnEvent(cmid) => {
+                let event = self.ops.try_get_conn_event(cmid)?;
+                Ok(CompletionKind::TryGetConnEvent(event))
+            }
 
             Command::OpenPd(pd) => {
                 self.ops.open_pd(pd)?;
@@ -782,6 +1013,51 @@ impl TransportEngine {
                 let ret_cq = self.ops.create_cq(ctx, *min_cq_entries, *cq_context)?;
                 Ok(CompletionKind::CreateCq(ret_cq))
             }
+            #[cfg(feature = "dc")]
+            Command::CreateDcTarget(pd, cq, max_recv_wr) => {
+                let dct = self.ops.create_dc_target(pd, cq, *max_recv_wr)?;
+                Ok(CompletionKind::CreateDcTarget(dct))
+            }
+            #[cfg(feature = "dc")]
+            Command::CreateDcInitiator(pd, cq, max_send_wr) => {
+                let dci = self.ops.create_dc_initiator(pd, cq, *max_send_wr)?;
+                Ok(CompletionKind::CreateDcInitiator(dci))
+            }
+            #[cfg(feature = "dc")]
+            Command::SetDcTarget(cmid, dct) => {
+                self.ops.set_dc_target(cmid, dct)?;
+                Ok(CompletionKind::SetDcTarget)
+            }
+            #[cfg(feature = "dc")]
+            Command::CreateDcPeer(dci, cmid) => {
+                let peer = self.ops.create_dc_peer(dci, cmid)?;
+                Ok(CompletionKind::CreateDcPeer(peer))
+            }
+            #[cfg(feature = "dc")]
+            Command::DestroyDcTarget(dct) => {
+                self.ops.destroy_dc_target(dct)?;
+                Ok(CompletionKind::DestroyDcTarget)
+            }
+            #[cfg(feature = "dc")]
+            Command::DestroyDcInitiator(dci) => {
+                self.ops.destroy_dc_initiator(dci)?;
+                Ok(CompletionKind::DestroyDcInitiator)
+            }
+            #[cfg(feature = "dc")]
+            Command::DestroyDcPeer(peer) => {
+                self.ops.destroy_dc_peer(peer)?;
+                Ok(CompletionKind::DestroyDcPeer)
+            }
+            #[cfg(not(feature = "dc"))]
+            Command::CreateDcTarget(..)
+            | Command::CreateDcInitiator(..)
+            | Command::SetDcTarget(..)
+            | Command::CreateDcPeer(..)
+            | Command::DestroyDcTarget(..)
+            | Command::DestroyDcInitiator(..)
+            | Command::DestroyDcPeer(..) => Err(ApiError::Ibv(io::Error::from(
+                nix::errno::Errno::EOPNOTSUPP,
+            ))),
         }
     }
 }
@@ -0,0 +1,95 @@
+//! Dumping the attributes of the queue pairs, completion queues, and shared receive queues of a
+//! process, as the devices negotiated them, for diagnosing the performance of its connections.
+//! Answers [`Query::Introspect`], see `phoenixctl rdmainfo`.
+//!
+//! [`Query::Introspect`]: phoenix_api::transport::rdma::control_plane::Query::Introspect
+use phoenix_api::net;
+use phoenix_api::transport::rdma::control_plane::{ConnectionInfo, CqInfo, IntrospectInfo, QpInfo};
+use phoenix_api::{AsHandle, Handle};
+use rdma::ffi;
+use rdma::ibv;
+
+use phoenix_common::log;
+
+use super::ops::Ops;
+
+fn qp_state_name(state: ffi::ibv_qp_state::Type) -> &'static str {
+    use ffi::ibv_qp_state::*;
+    match state {
+        IBV_QPS_RESET => "RESET",
+        IBV_QPS_INIT => "INIT",
+        IBV_QPS_RTR => "RTR",
+        IBV_QPS_RTS => "RTS",
+        IBV_QPS_SQD => "SQD",
+        IBV_QPS_SQE => "SQE",
+        IBV_QPS_ERR => "ERR",
+        _ => "UNKNOWN",
+    }
+}
+
+fn qp_info(qp: &ibv::QueuePair<'_>) -> Option<QpInfo> {
+    let (attr, init_attr) = match qp.query() {
+        Ok(attrs) => attrs,
+        Err(e) => {
+            log::warn!("querying queue pair {}: {}", qp.qp_num(), e);
+            return None;
+        }
+    };
+    Some(QpInfo {
+        qp_num: qp.qp_num(),
+        state: qp_state_name(attr.qp_state).to_owned(),
+        path_mtu: ibv::mtu_bytes(attr.path_mtu).unwrap_or(0),
+        timeout: attr.timeout,
+        retry_cnt: attr.retry_cnt,
+        rnr_retry: attr.rnr_retry,
+        min_rnr_timer: attr.min_rnr_timer,
+        max_rd_atomic: attr.max_rd_atomic,
+        max_dest_rd_atomic: attr.max_dest_rd_atomic,
+        cap: net::QpCapability {
+            max_send_wr: init_attr.cap.max_send_wr,
+            max_recv_wr: init_attr.cap.max_recv_wr,
+            max_send_sge: init_attr.cap.max_send_sge,
+            max_recv_sge: init_attr.cap.max_recv_sge,
+            max_inline_data: init_attr.cap.max_inline_data,
+        },
+    })
+}
+
+fn cq_info(cq: &ibv::CompletionQueue<'_>) -> CqInfo {
+    CqInfo {
+        handle: net::CompletionQueue(cq.as_handle()),
+        cqe: cq.capacity(),
+    }
+}
+
+impl Ops {
+    /// Returns the attributes of the connections of the process, i.e., the `CmId`s with a queue
+    /// pair, and of the shared receive queues.
+    pub fn introspect(&self) -> IntrospectInfo {
+        let mut keys = self.resource().cmid_table.keys();
+        keys.sort_unstable();
+        let connections = keys
+            .into_iter()
+            .filter_map(|key| {
+                let cmid = self.resource().cmid_table.get(key).ok()?;
+                let qp = cmid.qp()?;
+                Some(ConnectionInfo {
+                    cmid: net::CmId(Handle(key as u64)),
+                    local_addr: cmid.get_local_addr(),
+                    peer_addr: cmid.get_peer_addr(),
+                    qp: qp_info(qp),
+                    send_cq: cq_info(qp.send_cq()),
+                    recv_cq: cq_info(qp.recv_cq()),
+                })
+            })
+            .collect();
+
+        IntrospectInfo {
+            connections,
+            #[cfg(feature = "dc")]
+            srqs: self.dc().srq_infos(),
+            #[cfg(not(feature = "dc"))]
+            srqs: Vec::new(),
+        }
+    }
+}
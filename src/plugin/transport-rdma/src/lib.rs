@@ -8,13 +8,20 @@ use thiserror::Error;
 use phoenix_common::resource::Error as ResourceError;
 pub use phoenix_common::{InitFnResult, PhoenixModule};
 
+pub(crate) mod atomics;
 pub(crate) mod cm;
 pub mod config;
+#[cfg(feature = "dc")]
+pub(crate) mod dc;
 pub(crate) mod engine;
+pub(crate) mod introspect;
+pub(crate) mod link;
 pub mod module;
 
 #[allow(clippy::too_many_arguments)]
 pub mod ops;
+pub(crate) mod recovery;
+pub(crate) mod soft;
 pub mod state;
 
 #[derive(Debug, Error)]
@@ -42,6 +49,8 @@ pub enum ApiError {
     NoCmEvent,
     #[error("Transport specific error: {0}")]
     Transport(i32),
+    #[error("Remote access not permitted: {0:?}")]
+    AccessDenied(phoenix_api::net::AccessFlags),
 }
 
 /// Control path error.
@@ -63,14 +72,14 @@ pub enum Error {
 
 impl From<Error> for phoenix_api::Error {
     fn from(other: Error) -> Self {
-        phoenix_api::Error::Generic(other.to_string())
+        phoenix_api::Error::from_error(phoenix_api::ErrorCode::Internal, &other)
     }
 }
 
 impl From<ResourceError> for ApiError {
     fn from(other: ResourceError) -> Self {
         match other {
-            ResourceError::NotFound => ApiError::NotFound,
+            ResourceError::NotFound | ResourceError::Stale => ApiError::NotFound,
             ResourceError::Exists => ApiError::Exists,
             ResourceError::SlabFull => ApiError::SlabFull,
         }
@@ -85,18 +94,27 @@ pub enum DatapathError {
     ShmIpc(#[from] ipc::shmem_ipc::ShmIpcError),
     #[error("Shared memory queue ringbuf error: {0}.")]
     ShmRingbuf(#[from] ipc::shmem_ipc::ShmRingbufError),
+    #[error("Shared memory ring error: {0}.")]
+    ShmRing(#[from] ipc::ring::Error),
     #[error("rdmacm internal error: {0}.")]
     RdmaCm(io::Error),
     #[error("ibv internal error: {0}.")]
     Ibv(io::Error),
+    #[error("Invalid work request: {0}.")]
+    InvalidWorkRequest(&'static str),
+    #[error("Unexpected error: {0}")]
+    Other(String),
 }
 
 impl From<ResourceError> for DatapathError {
     fn from(other: ResourceError) -> Self {
         match other {
-            ResourceError::NotFound => DatapathError::NotFound,
-            ResourceError::Exists => panic!(),
-            ResourceError::SlabFull => panic!(),
+            ResourceError::NotFound | ResourceError::Stale => DatapathError::NotFound,
+            other => {
+                let context = format!("resource error on the datapath: {}", other);
+                phoenix_common::metrics::record_unexpected_error(&context);
+                DatapathError::Other(context)
+            }
         }
     }
 }
@@ -106,7 +124,12 @@ impl From<ipc::Error> for DatapathError {
         match other {
             ipc::Error::ShmIpc(e) => DatapathError::ShmIpc(e),
             ipc::Error::ShmRingbuf(e) => DatapathError::ShmRingbuf(e),
-            _ => panic!(),
+            ipc::Error::ShmRing(e) => DatapathError::ShmRing(e),
+            other => {
+                let context = format!("IPC error on the datapath: {}", other);
+                phoenix_common::metrics::record_unexpected_error(&context);
+                DatapathError::Other(context)
+            }
         }
     }
 }
@@ -116,9 +139,11 @@ impl DatapathError {
         match self {
             Self::NotFound => 1024,
             Self::ShmIpc(_) => 1025,
-            Self::ShmRingbuf(_) => 1026,
+            Self::ShmRingbuf(_) | Self::ShmRing(_) => 1026,
             Self::RdmaCm(e) => e.raw_os_error().unwrap() as u32,
             Self::Ibv(e) => e.raw_os_error().unwrap() as u32,
+            Self::Other(_) => 1027,
+            Self::InvalidWorkRequest(_) => 1028,
         }
     }
 }
@@ -0,0 +1,129 @@
+//! Following the links of the devices through their asynchronous events.
+//!
+//! The events are read by the `CmEngine` of any process. A queue pair that fails is reported to
+//! the recovery of the process that owns it. When a port goes down, or its device fails, the
+//! connections on it are degraded in the processes that recover their connections, and the
+//! active sides re-establish them from the configured failover addresses, see
+//! [`RecoveryConfig::failover_addrs`]. The changes are counted in [`phoenix_common::metrics`].
+//! When the GID table of a device changes, or its port comes back up, the table is read again
+//! for the default protection domains to be found by the new GIDs.
+//!
+//! The devices are opened once, when the module is loaded, so a device plugged in later is not
+//! used until the daemon restarts.
+//!
+//! [`RecoveryConfig::failover_addrs`]: super::config::RecoveryConfig::failover_addrs
+use std::collections::HashSet;
+use std::io;
+
+use lazy_static::lazy_static;
+
+use phoenix_api::{AsHandle, Handle};
+use rdma::ffi;
+
+use phoenix_common::{log, metrics};
+
+use super::recovery;
+use super::state::{DefaultContext, DEFAULT_CTXS};
+
+#[derive(Debug, Default)]
+struct Links {
+    /// By the verbs context and the port number.
+    down_ports: HashSet<(Handle, u8)>,
+    failed_devices: HashSet<Handle>,
+}
+
+lazy_static! {
+    static ref LINKS: spin::Mutex<Links> = spin::Mutex::new(Links::default());
+}
+
+/// Whether a port of a device is up, as far as the events of the device tell.
+pub(crate) fn is_port_up(ctx: Handle, port_num: u8) -> bool {
+    let links = LINKS.lock();
+    !links.failed_devices.contains(&ctx) && !links.down_ports.contains(&(ctx, port_num))
+}
+
+fn describe(ctx: &DefaultContext, port_num: Option<u8>) -> String {
+    let name = ctx.pinned_ctx.verbs.device_name().map_or_else(
+        || "unknown".to_owned(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    match port_num {
+        Some(port_num) => format!("device {}, port {}", name, port_num),
+        None => format!("device {}", name),
+    }
+}
+
+fn port_down(ctx: &DefaultContext, port_num: u8) {
+    let handle = ctx.pinned_ctx.verbs.as_handle();
+    if LINKS.lock().down_ports.insert((handle, port_num)) {
+        metrics::record_link_down(&describe(ctx, Some(port_num)));
+        recovery::report_link_down(handle, Some(port_num));
+    }
+}
+
+fn port_up(ctx: &DefaultContext, port_num: u8) {
+    let handle = ctx.pinned_ctx.verbs.as_handle();
+    if LINKS.lock().down_ports.remove(&(handle, port_num)) {
+        metrics::record_link_up(&describe(ctx, Some(port_num)));
+    }
+    // the addresses may have changed while the port was down
+    refresh_gid_table(ctx);
+}
+
+fn device_failed(ctx: &DefaultContext) {
+    let handle = ctx.pinned_ctx.verbs.as_handle();
+    if LINKS.lock().failed_devices.insert(handle) {
+        metrics::record_link_down(&describe(ctx, None));
+        recovery::report_link_down(handle, None);
+    }
+}
+
+fn refresh_gid_table(ctx: &DefaultContext) {
+    if let Err(e) = ctx.refresh_gid_table() {
+        log::warn!("reading the GID table of {}: {}", describe(ctx, None), e);
+    }
+}
+
+/// Reads the asynchronous events of the devices. Returns the number of events.
+pub(crate) fn poll_async_events() -> usize {
+    let mut fatal = Vec::new();
+    let mut nevents = 0;
+    for ctx in DEFAULT_CTXS.iter() {
+        if LINKS
+            .lock()
+            .failed_devices
+            .contains(&ctx.pinned_ctx.verbs.as_handle())
+        {
+            continue;
+        }
+        loop {
+            let event = match ctx.pinned_ctx.verbs.get_async_event() {
+                Ok(event) => event,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("get_async_event: {}", e);
+                    break;
+                }
+            };
+            nevents += 1;
+            use ffi::ibv_event_type::*;
+            match (event.event_type(), event.qp_num(), event.port_num()) {
+                (
+                    IBV_EVENT_QP_FATAL | IBV_EVENT_QP_REQ_ERR | IBV_EVENT_QP_ACCESS_ERR,
+                    Some(qp_num),
+                    _,
+                ) => {
+                    log::warn!("queue pair failed: {:?}", event);
+                    fatal.push(qp_num);
+                }
+                (IBV_EVENT_PORT_ERR, _, Some(port_num)) => port_down(ctx, port_num),
+                (IBV_EVENT_PORT_ACTIVE, _, Some(port_num)) => port_up(ctx, port_num),
+                (IBV_EVENT_GID_CHANGE, _, Some(_)) => refresh_gid_table(ctx),
+                (IBV_EVENT_DEVICE_FATAL, ..) => device_failed(ctx),
+                _ => log::debug!("async event: {:?}", event),
+            }
+        }
+    }
+    recovery::report_fatal_qps(fatal);
+    nevents
+}
@@ -10,8 +10,10 @@ use uuid::Uuid;
 use ipc::customer::ShmCustomer;
 use ipc::unix::DomainSocket;
 use phoenix_api::engine::SchedulingMode;
+use phoenix_api::net::AccessFlags;
 use phoenix_api::transport::rdma::{cmd, dp};
 
+use phoenix_common::capability::Capabilities;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
 use phoenix_common::module::{
@@ -57,15 +59,23 @@ pub(crate) struct TransportEngineBuilder {
     node: DataPathNode,
     mode: SchedulingMode,
     ops: Ops,
+    permitted_access: AccessFlags,
 }
 
 impl TransportEngineBuilder {
-    fn new(customer: CustomerType, node: DataPathNode, mode: SchedulingMode, ops: Ops) -> Self {
+    fn new(
+        customer: CustomerType,
+        node: DataPathNode,
+        mode: SchedulingMode,
+        ops: Ops,
+        permitted_access: AccessFlags,
+    ) -> Self {
         TransportEngineBuilder {
             customer,
             node,
             mode,
             ops,
+            permitted_access,
         }
     }
 
@@ -78,6 +88,7 @@ impl TransportEngineBuilder {
             _mode: self.mode,
             node: self.node,
             ops: self.ops,
+            permitted_access: self.permitted_access,
             cq_err_buffer: VecDeque::new(),
             wr_read_buffer: Vec::with_capacity(BUF_LEN),
         })
@@ -140,6 +151,15 @@ impl PhoenixModule for RdmaTransportModule {
         Self::DEPENDENCIES
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // the verbs and the connection manager talk to the devices with ioctl and write
+        Capabilities {
+            read_paths: vec!["/sys/class/infiniband".into(), "/sys/devices".into()],
+            devices: vec!["/dev/infiniband".into()],
+            ..Default::default()
+        }
+    }
+
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
@@ -264,7 +284,8 @@ impl RdmaTransportModule {
         let ops = self.create_ops(client_pid)?;
 
         // 4. create the engine
-        let builder = TransportEngineBuilder::new(customer, node, mode, ops);
+        let builder =
+            TransportEngineBuilder::new(customer, node, mode, ops, self.config.permitted_access());
         let engine = builder.build()?;
         Ok(engine)
     }
@@ -276,6 +297,10 @@ impl RdmaTransportModule {
         _config_string: Option<String>,
     ) -> Result<Option<CmEngine>> {
         let shared = self.state_mgr.get_or_create(client_pid)?;
+        shared.recovery.configure(&self.config.recovery);
+        #[cfg(feature = "dc")]
+        shared.dc.configure(&self.config.dc);
+        shared.soft_rdma.configure(&self.config.soft_rdma);
 
         // only create one cm_engine for a client process
         // if refcnt > 1, then there is already a CmEngine running
@@ -1,6 +1,7 @@
 //! Providing the API implemention for both TransportEngine and RpcAdapter.
 //! The API design requires a bit finesse.
 use std::io;
+use std::mem;
 use std::net::SocketAddr;
 use std::slice;
 use std::sync::atomic::Ordering;
@@ -8,7 +9,9 @@ use std::sync::Arc;
 
 use phoenix_api::net;
 use phoenix_api::net::returned;
+use phoenix_api::transport::rdma::cmd::ConnEvent;
 use phoenix_api::{AsHandle, Handle};
+use rdma::ffi;
 use rdma::ibv;
 use rdma::mr::MemoryRegion;
 use rdma::rdmacm;
@@ -16,7 +19,9 @@ use rdma::rdmacm::CmId;
 
 use phoenix_common::engine::future;
 use phoenix_common::log;
+use phoenix_common::resource::Versioned;
 
+use super::recovery::{self, Posted};
 use super::state::{EventChannel, Resource, State};
 use super::{ApiError, DatapathError};
 
@@ -67,18 +72,13 @@ impl Ops {
         //     user_buf,
         //     mr_handle
         // );
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
-
         // since post_recv itself is already unsafe, it is the user's responsibility to
         // make sure the received data is valid. The user must avoid post_recv a same
         // buffer multiple times (e.g. from a single thread or from multiple threads)
         // without any synchronization.
         // let rdma_mr = rdmacm::MemoryRegion::from(mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
-        let buf_mut = slice::from_raw_parts_mut(buf.as_ptr() as _, buf.len());
-        cmid.post_recv(wr_id, buf_mut, mr)
-            .map_err(DatapathError::RdmaCm)?;
-        Ok(())
+        self.post_tracked(cmid_handle, Posted::recv(wr_id, mr, buf))
     }
 
     /// # Safety
@@ -103,15 +103,11 @@ impl Ops {
         //     mr_handle,
         //     send_flags,
         // );
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
-
         // let rdma_mr = rdmacm::MemoryRegion::from(mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
 
-        let flags: ibv::SendFlags = send_flags.into();
-        cmid.post_send(wr_id, buf, mr, flags.0)
-            .map_err(DatapathError::RdmaCm)?;
-        Ok(())
+        let flags: ibv::SendFlags = self.soft_rdma().send_flags(send_flags, buf.len()).into();
+        self.post_tracked(cmid_handle, Posted::send(wr_id, mr, buf, flags.0, None))
     }
 
     /// # Safety
@@ -129,15 +125,14 @@ impl Ops {
         send_flags: net::SendFlags,
         imm: u32,
     ) -> std::result::Result<(), DatapathError> {
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
-
         // let rdma_mr = rdmacm::MemoryRegion::from(&mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
 
-        let flags: ibv::SendFlags = send_flags.into();
-        cmid.post_send_with_imm(wr_id, buf, mr, flags.0, imm)
-            .map_err(DatapathError::RdmaCm)?;
-        Ok(())
+        let flags: ibv::SendFlags = self.soft_rdma().send_flags(send_flags, buf.len()).into();
+        self.post_tracked(
+            cmid_handle,
+            Posted::send(wr_id, mr, buf, flags.0, Some(imm)),
+        )
     }
 
     /// # Safety
@@ -156,17 +151,13 @@ impl Ops {
         remote_offset: u64,
         send_flags: net::SendFlags,
     ) -> std::result::Result<(), DatapathError> {
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
-
         // let rdma_mr = rdmacm::MemoryRegion::from(mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
         let remote_addr = rkey.addr + remote_offset;
 
-        let flags: ibv::SendFlags = send_flags.into();
-        cmid.post_write(wr_id, buf, mr, flags.0, remote_addr, rkey.rkey)
-            .map_err(DatapathError::RdmaCm)?;
-
-        Ok(())
+        let flags: ibv::SendFlags = self.soft_rdma().send_flags(send_flags, buf.len()).into();
+        let posted = Posted::write(wr_id, mr, buf, flags.0, remote_addr, rkey.rkey);
+        self.post_tracked(cmid_handle, posted)
     }
 
     /// # Safety
@@ -185,19 +176,183 @@ impl Ops {
         remote_offset: u64,
         send_flags: net::SendFlags,
     ) -> std::result::Result<(), DatapathError> {
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
-
         let remote_addr = rkey.addr + remote_offset;
         let flags: ibv::SendFlags = send_flags.into();
 
         // let rdma_mr = rdmacm::MemoryRegion::from(mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
-        let buf_mut = slice::from_raw_parts_mut(buf.as_ptr() as _, buf.len());
-        cmid.post_read(wr_id, buf_mut, mr, flags.0, remote_addr, rkey.rkey)
-            .map_err(DatapathError::RdmaCm)?;
+        let posted = Posted::read(wr_id, mr, buf, flags.0, remote_addr, rkey.rkey);
+        self.post_tracked(cmid_handle, posted)
+    }
+
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `Ops::poll_cq` returns a completion for this receive).
+    #[inline]
+    pub unsafe fn post_compare_and_swap(
+        &self,
+        cmid_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+        send_flags: net::SendFlags,
+        compare: u64,
+        swap: u64,
+    ) -> std::result::Result<(), DatapathError> {
+        let (buf, remote_addr) = atomic_target(mr, range, rkey, remote_offset)?;
+        let flags: ibv::SendFlags = send_flags.into();
+
+        let posted = Posted::compare_and_swap(
+            wr_id,
+            mr,
+            buf,
+            flags.0,
+            remote_addr,
+            rkey.rkey,
+            compare,
+            swap,
+        );
+        self.post_tracked(cmid_handle, posted)
+    }
+
+    /// Adds `add` to the field of `mask` of the remote 8 bytes, see [`ibv::ExtAtomic`]. Only a
+    /// full mask is native, others are done as compare-and-swaps.
+    ///
+    /// # Safety
+    ///
+    /// See [`Ops::post_compare_and_swap`].
+    #[inline]
+    pub unsafe fn post_fetch_and_add(
+        &self,
+        cmid_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+        send_flags: net::SendFlags,
+        add: u64,
+        mask: u64,
+    ) -> std::result::Result<(), DatapathError> {
+        let (buf, remote_addr) = atomic_target(mr, range, rkey, remote_offset)?;
+        let op = ibv::ExtAtomic::FetchAndAdd { add, mask };
+        if !op.is_valid() {
+            return Err(DatapathError::InvalidWorkRequest(
+                "the mask of a fetch-and-add must be a contiguous run of bits",
+            ));
+        }
+        if !op.is_native() {
+            return self.post_ext_atomic(
+                cmid_handle,
+                mr,
+                buf,
+                wr_id,
+                remote_addr,
+                rkey.rkey,
+                send_flags,
+                op,
+            );
+        }
+        let flags: ibv::SendFlags = send_flags.into();
+        let posted = Posted::fetch_and_add(wr_id, mr, buf, flags.0, remote_addr, rkey.rkey, add);
+        self.post_tracked(cmid_handle, posted)
+    }
+
+    /// Compares the bits of `compare_mask` of the remote 8 bytes with `compare` and, if equal,
+    /// replaces the bits of `swap_mask` with `swap`, see [`ibv::ExtAtomic`]. Only full masks are
+    /// native, others are done as a sequence of compare-and-swaps.
+    ///
+    /// # Safety
+    ///
+    /// See [`Ops::post_compare_and_swap`].
+    #[inline]
+    pub unsafe fn post_masked_compare_and_swap(
+        &self,
+        cmid_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+        rkey: net::RemoteKey,
+        remote_offset: u64,
+        send_flags: net::SendFlags,
+        compare: u64,
+        compare_mask: u64,
+        swap: u64,
+        swap_mask: u64,
+    ) -> std::result::Result<(), DatapathError> {
+        let op = ibv::ExtAtomic::MaskedCompareAndSwap {
+            compare,
+            compare_mask,
+            swap,
+            swap_mask,
+        };
+        if op.is_native() {
+            return self.post_compare_and_swap(
+                cmid_handle,
+                mr,
+                range,
+                wr_id,
+                rkey,
+                remote_offset,
+                send_flags,
+                compare,
+                swap,
+            );
+        }
+        let (buf, remote_addr) = atomic_target(mr, range, rkey, remote_offset)?;
+        self.post_ext_atomic(
+            cmid_handle,
+            mr,
+            buf,
+            wr_id,
+            remote_addr,
+            rkey.rkey,
+            send_flags,
+            op,
+        )
+    }
+
+    /// Posts a work request to the connection of `cmid_handle`, which may have been re-established
+    /// on another `CmId`, and keeps it until its completion for a recovery.
+    ///
+    /// # Safety
+    ///
+    /// See [`Ops::post_send`].
+    #[inline]
+    pub(crate) unsafe fn post_tracked(
+        &self,
+        cmid_handle: Handle,
+        posted: Posted,
+    ) -> std::result::Result<(), DatapathError> {
+        let key = cmid_handle.0 as usize;
+        let recovery = &self.state.shared.recovery;
+        if !recovery.is_enabled() {
+            let cmid = self.resource().cmid_table.get_dp(key)?;
+            return posted.post_on(&cmid).map_err(DatapathError::RdmaCm);
+        }
+        // the connection must not be switched over in between
+        let mut inner = recovery.lock();
+        let cmid = self.resource().cmid_table.get_dp(inner.current(key))?;
+        posted.post_on(&cmid).map_err(DatapathError::RdmaCm)?;
+        inner.track(key, posted);
         Ok(())
     }
 
+    /// Looks at a work completion polled from `cq`. Returns true if it is held back from the
+    /// application, for a connection being recovered or an atomic done by the engine.
+    #[inline]
+    pub(crate) fn hold_back(&self, cq: net::CompletionQueue, wc: &mut ffi::ibv_wc) -> bool {
+        let recovery = &self.state.shared.recovery;
+        if recovery.is_enabled() && recovery.lock().absorb(cq, wc, self.resource()) {
+            return true;
+        }
+        self.advance_ext_atomic(wc)
+    }
+
     #[inline]
     pub fn poll_cq(
         &self,
@@ -213,7 +368,24 @@ impl Ops {
         let wc_slice = unsafe { slice::from_raw_parts_mut(wc.as_mut_ptr().cast(), wc.capacity()) };
         match cq.poll(wc_slice) {
             Ok(completions) => {
-                unsafe { wc.set_len(completions.len()) };
+                let mut n = 0;
+                for i in 0..completions.len() {
+                    if !self.hold_back(*cq_handle, &mut wc_slice[i]) {
+                        wc_slice.swap(n, i);
+                        n += 1;
+                    }
+                }
+                let recovery = &self.state.shared.recovery;
+                if recovery.is_enabled() {
+                    recovery
+                        .lock()
+                        .drain_failed(*cq_handle, wc.capacity() - n, |failed| {
+                            // SAFETY: the ibv_wc and net::WorkCompletion have the same layout
+                            wc_slice[n] = unsafe { mem::transmute(failed) };
+                            n += 1;
+                        });
+                }
+                unsafe { wc.set_len(n) };
                 Ok(())
             }
             Err(rdma::ibv::PollCqError) => {
@@ -224,6 +396,35 @@ impl Ops {
     }
 }
 
+/// The local buffer and the remote address of an atomic.
+///
+/// Rejects what the NIC would fail obscurely, or what would bring down the engine.
+fn atomic_target<'a>(
+    mr: &'a rdmacm::MemoryRegion,
+    range: phoenix_api::buf::Range,
+    rkey: net::RemoteKey,
+    remote_offset: u64,
+) -> std::result::Result<(&'a [u8], u64), DatapathError> {
+    if range.len != mem::size_of::<u64>() as u64 {
+        return Err(DatapathError::InvalidWorkRequest(
+            "the local buffer of an atomic must be 8 bytes",
+        ));
+    }
+    if range.offset > (mr.len() as u64).saturating_sub(range.len) {
+        return Err(DatapathError::InvalidWorkRequest(
+            "the local buffer is out of the memory region",
+        ));
+    }
+    let remote_addr = rkey.addr + remote_offset;
+    if remote_addr % mem::size_of::<u64>() as u64 != 0 {
+        return Err(DatapathError::InvalidWorkRequest(
+            "the remote address of an atomic must be 8-byte aligned",
+        ));
+    }
+    let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
+    Ok((buf, remote_addr))
+}
+
 // Control path APIs
 impl Ops {
     pub fn get_sgid(&self, cmid_handle: Handle) -> Result<ibv::Gid> {
@@ -333,8 +534,9 @@ impl Ops {
         ))
     }
 
-    // Helper function
-    fn create_and_register_event_channel(&self) -> Result<(Handle, rdmacm::EventChannel)> {
+    // Helper function. Creates an event channel, inserts it in the table and registers it to
+    // the IO reactor.
+    fn create_and_register_event_channel(&self) -> Result<(Versioned<Handle>, Arc<EventChannel>)> {
         // create a new event channel for each cmid
         let channel = rdmacm::EventChannel::create_event_channel().map_err(ApiError::RdmaCm)?;
 
@@ -342,9 +544,26 @@ impl Ops {
         channel.set_nonblocking(true).map_err(ApiError::RdmaCm)?;
         let channel_handle = channel.as_handle();
 
-        self.register_event_channel(channel_handle, &channel)?;
+        let ec = self
+            .resource()
+            .event_channel_table
+            .insert_versioned(channel_handle, EventChannel::new(channel))?;
+        let channel = self.resource().event_channel_table.get_versioned(&ec)?;
+        if let Err(e) = self.register_event_channel(ec, &channel) {
+            drop(channel);
+            self.discard_event_channel(&ec);
+            return Err(e);
+        }
 
-        Ok((channel_handle, channel))
+        Ok((ec, channel))
+    }
+
+    // Helper function. Removes an event channel no CmId has been created on or migrated to.
+    fn discard_event_channel(&self, ec: &Versioned<Handle>) {
+        let _ = self
+            .resource()
+            .event_channel_table
+            .close_resource_versioned(ec);
     }
 
     pub fn create_id(
@@ -353,28 +572,30 @@ impl Ops {
     ) -> Result<returned::CmId> {
         log::debug!("CreateId, port_space: {:?}", port_space);
 
-        // prepare an event channel
-        let (channel_handle, channel) = self.create_and_register_event_channel()?;
+        // prepare an event channel, it is in the table from now on
+        let (ec, channel) = self.create_and_register_event_channel()?;
 
         // TODO(cjr): this is safe because event_channel will be stored in the
         // ResourceTable
         let ps: rdmacm::PortSpace = port_space.into();
-        let cmid = unsafe { CmId::create_id(Some(&channel), 0, ps.0) }.map_err(ApiError::RdmaCm)?;
+        let cmid = match unsafe { CmId::create_id(Some(&**channel), 0, ps.0) } {
+            Ok(cmid) => cmid,
+            Err(e) => {
+                drop(channel);
+                self.discard_event_channel(&ec);
+                return Err(ApiError::RdmaCm(e));
+            }
+        };
 
-        // insert event_channel
         // TODO(cjr): think over it. What if any exception happen in between any of these
         // operations? How to safely/correctly rollback?
-        self.resource()
-            .event_channel_table
-            .insert(channel_handle, EventChannel::new(channel))?;
-
         // insert cmid after event_channel is inserted
         let new_cmid_handle = self.resource().insert_cmid(cmid)?;
 
         log::debug!(
             "CreateId, returned CmId Handle: {:?}, EventChannel Handle: {:?}",
             new_cmid_handle,
-            channel_handle
+            ec.key
         );
 
         Ok(returned::CmId {
@@ -396,14 +617,18 @@ impl Ops {
     }
 
     // Helper function.
-    fn handle_connect_request(&self, event: rdmacm::CmEvent) -> Result<returned::CmId> {
+    pub(crate) fn handle_connect_request(&self, event: rdmacm::CmEvent) -> Result<returned::CmId> {
         log::debug!("handle_connect_request");
         let (new_cmid, new_qp) = event.get_request();
 
         // Create event channel for the new_cmid and migrate
-        let (channel_handle, channel) = self.create_and_register_event_channel()?;
+        let (ec, channel) = self.create_and_register_event_channel()?;
 
-        new_cmid.migrate_id(&channel).map_err(ApiError::RdmaCm)?;
+        if let Err(e) = new_cmid.migrate_id(&channel) {
+            drop(channel);
+            self.discard_event_channel(&ec);
+            return Err(ApiError::RdmaCm(e));
+        }
 
         let ret_qp = if let Some(qp) = new_qp {
             let handles = self.resource().insert_qp(qp)?;
@@ -412,18 +637,17 @@ impl Ops {
             None
         };
 
-        // insert event_channel
-        self.resource()
-            .event_channel_table
-            .insert(channel_handle, EventChannel::new(channel))?;
-
         // insert cmid
         let new_cmid_handle = self.resource().insert_cmid(new_cmid)?;
+        #[cfg(feature = "dc")]
+        self.dc()
+            .set_remote(new_cmid_handle.0 as usize, event.private_data());
+        self.save_peer_private_data(new_cmid_handle.0 as usize, event.private_data());
 
         log::debug!(
             "(Try)GetRequest, returned CmId Handle: {:?}, EventChannel Handle: {:?}",
             new_cmid_handle,
-            channel_handle
+            ec.key
         );
 
         Ok(returned::CmId {
@@ -489,15 +713,41 @@ impl Ops {
             conn_param
         );
 
+        #[cfg(feature = "dc")]
+        let dc_param = self.dc_conn_param(cmid_handle.0 as usize, conn_param);
+        #[cfg(feature = "dc")]
+        let conn_param = dc_param.as_ref().or(conn_param);
+
+        // the secret the active side re-establishes the connection with
+        let secret = self
+            .state
+            .shared
+            .recovery
+            .is_enabled()
+            .then(recovery::new_secret);
+        let secret_param = secret.and_then(|secret| recovery::with_secret(conn_param, &secret));
+        if secret.is_some() && secret_param.is_none() {
+            log::warn!(
+                "connection {:?} is not recoverable, its private data leaves no room for the secret",
+                cmid_handle
+            );
+        }
+
         let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
-        cmid.accept(self.get_conn_param(conn_param).as_ref())
-            .map_err(ApiError::RdmaCm)?;
+        cmid.accept(
+            self.get_conn_param(secret_param.as_ref().or(conn_param))
+                .as_ref(),
+        )
+        .map_err(ApiError::RdmaCm)?;
 
         // wait until the accept is done
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_ESTABLISHED;
         let ec_handle = cmid.event_channel().as_handle();
         let _event = self.wait_cm_event(&ec_handle, event_type).await?;
 
+        if let (Some(secret), Some(_)) = (secret, secret_param) {
+            self.recover_accepted(cmid_handle.0 as usize, conn_param, secret);
+        }
         Ok(())
     }
 
@@ -512,6 +762,11 @@ impl Ops {
             conn_param
         );
 
+        #[cfg(feature = "dc")]
+        let dc_param = self.dc_conn_param(cmid_handle.0 as usize, conn_param);
+        #[cfg(feature = "dc")]
+        let conn_param = dc_param.as_ref().or(conn_param);
+
         let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
         cmid.connect(self.get_conn_param(conn_param).as_ref())
             .map_err(ApiError::RdmaCm)?;
@@ -519,11 +774,39 @@ impl Ops {
         // wait until the accept is done
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_ESTABLISHED;
         let ec_handle = cmid.event_channel().as_handle();
-        let _event = self.wait_cm_event(&ec_handle, event_type).await?;
-
+        let event = self.wait_cm_event(&ec_handle, event_type).await?;
+        let (secret, private_data) = recovery::split_secret(event.private_data());
+        // the DC target the passive side advertised
+        #[cfg(feature = "dc")]
+        self.dc().set_remote(cmid_handle.0 as usize, private_data);
+        self.save_peer_private_data(cmid_handle.0 as usize, private_data);
+
+        if self.state.shared.recovery.is_enabled() {
+            match secret {
+                Some(secret) => self.recover_connected(cmid_handle.0 as usize, conn_param, secret),
+                None => log::warn!(
+                    "connection {:?} is not recoverable, the peer sent no secret",
+                    cmid_handle
+                ),
+            }
+        }
         Ok(())
     }
 
+    fn save_peer_private_data(&self, key: usize, private_data: &[u8]) {
+        if !private_data.is_empty() {
+            let mut table = self.state.shared.peer_private_data.lock();
+            table.insert(key, private_data.to_vec());
+        }
+    }
+
+    /// Takes the private data the peer sent with the connection request, or with the accept of
+    /// a connection this side has connected. The private data may be padded by the transport.
+    pub fn take_peer_private_data(&self, cmid_handle: Handle) -> Option<Vec<u8>> {
+        let mut table = self.state.shared.peer_private_data.lock();
+        table.remove(&(cmid_handle.0 as usize))
+    }
+
     pub fn bind_addr(&self, cmid_handle: Handle, sockaddr: &SocketAddr) -> Result<()> {
         log::debug!(
             "BindAddr, cmid_handle: {:?}, sockaddr: {:?}",
@@ -544,7 +827,9 @@ impl Ops {
         );
 
         let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
-        cmid.resolve_addr(sockaddr).map_err(ApiError::RdmaCm)?;
+        let timeout_ms = self.soft_rdma().timeout_ms(rdmacm::RESOLVE_ADDR_TIMEOUT_MS);
+        cmid.resolve_addr_from(None, sockaddr, timeout_ms)
+            .map_err(ApiError::RdmaCm)?;
 
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_ADDR_RESOLVED;
         let ec_handle = cmid.event_channel().as_handle();
@@ -561,7 +846,8 @@ impl Ops {
         );
 
         let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
-        cmid.resolve_route(timeout_ms).map_err(ApiError::RdmaCm)?;
+        cmid.resolve_route(self.soft_rdma().timeout_ms(timeout_ms))
+            .map_err(ApiError::RdmaCm)?;
 
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_ROUTE_RESOLVED;
         let ec_handle = cmid.event_channel().as_handle();
@@ -595,12 +881,18 @@ impl Ops {
             )
         });
 
-        let (pd, qp_init_attr) = self.get_qp_params(pd.as_ref(), Some(qp_init_attr))?;
+        let (pd_obj, attr) = self.get_qp_params(pd.as_ref(), Some(qp_init_attr))?;
         let qp = cmid
-            .create_qp(pd.as_deref(), qp_init_attr.as_ref())
+            .create_qp(pd_obj.as_deref(), attr.as_ref())
             .map_err(ApiError::RdmaCm)?;
+        let qp_num = qp.qp_num();
         let handles = self.resource().insert_qp(qp)?;
-        Ok(prepare_returned_qp(handles))
+        let ret_qp = prepare_returned_qp(handles);
+        if self.state.shared.recovery.is_enabled() {
+            let key = cmid_handle.0 as usize;
+            self.prepare_recovery(key, pd, qp_init_attr, &ret_qp, qp_num);
+        }
+        Ok(ret_qp)
     }
 
     /// Must be set before resolve_addr
@@ -709,6 +1001,7 @@ impl Ops {
         log::debug!("Disconnect, cmid: {:?}", cmid);
 
         let cmid_handle = cmid.0;
+        self.forget_recovery(cmid_handle.0 as usize);
         let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
         // use the context to distinguish if the connection is disconnected
         if let Ok(0) =
@@ -729,6 +1022,14 @@ impl Ops {
 
     pub fn destroy_id(&self, cmid: &net::CmId) -> Result<()> {
         log::debug!("DestroyId, cmid: {:?}", cmid);
+        self.forget_recovery(cmid.0 .0 as usize);
+        #[cfg(feature = "dc")]
+        self.dc().forget(cmid.0 .0 as usize);
+        self.state
+            .shared
+            .peer_private_data
+            .lock()
+            .remove(&(cmid.0 .0 as usize));
         // NOTE(cjr): Must drop the buffer in event_channel first to rdma_ack_cm_event. Otherwise,
         // the dropping of CmId will be blocked. This will block multiple engines including
         // rpc_adapter::AcceptorEngine and CmEngine.
@@ -768,6 +1069,14 @@ impl Ops {
         Ok(())
     }
 
+    pub fn try_get_conn_event(&self, cmid: &net::CmId) -> Result<Option<ConnEvent>> {
+        let recovery = &self.state.shared.recovery;
+        if !recovery.is_enabled() {
+            return Ok(None);
+        }
+        Ok(recovery.lock().pop_event(cmid.0 .0 as usize))
+    }
+
     pub fn open_pd(&self, pd: &net::ProtectionDomain) -> Result<()> {
         log::trace!("OpenPd, pd: {:?}", pd);
         self.resource().pd_table.open_resource(&pd.0)?;
@@ -795,7 +1104,7 @@ impl Ops {
             .resource()
             .default_pds
             .iter()
-            .map(|(pd, _gids)| returned::ProtectionDomain { handle: *pd })
+            .map(|(pd, _index)| returned::ProtectionDomain { handle: *pd })
             .collect();
         Ok(pds)
     }
@@ -821,7 +1130,7 @@ impl Ops {
         rdmacm::MemoryRegion::new_on_demand_paging(pd.pd()).map_err(ApiError::Ibv)
     }
 
-    fn get_qp_params(
+    pub(crate) fn get_qp_params(
         &self,
         pd_handle: Option<&net::ProtectionDomain>,
         qp_init_attr: Option<&net::QpInitAttr>,
@@ -845,12 +1154,14 @@ impl Ops {
             } else {
                 None
             };
+            let mut cap = a.cap;
+            cap.max_inline_data = self.soft_rdma().max_inline_data(cap.max_inline_data);
             use std::ops::Deref;
             let attr = ibv::QpInitAttr {
                 qp_context: 0,
                 send_cq: send_cq.as_ref().map(|x| x.deref().deref()),
                 recv_cq: recv_cq.as_ref().map(|x| x.deref().deref()),
-                cap: a.cap.into(),
+                cap: cap.into(),
                 qp_type: a.qp_type.into(),
                 sq_sig_all: a.sq_sig_all,
             };
@@ -861,7 +1172,7 @@ impl Ops {
         Ok((pd, qp_init_attr))
     }
 
-    fn get_conn_param(
+    pub(crate) fn get_conn_param(
         &self,
         conn_param: Option<&net::ConnParam>,
     ) -> Option<rdma::ffi::rdma_conn_param> {
@@ -885,14 +1196,14 @@ impl Ops {
     // Helper function. TODO(cjr): this function may block up to 1 milliseconds.
     fn register_event_channel(
         &self,
-        channel_handle: Handle,
+        ec: Versioned<Handle>,
         channel: &rdmacm::EventChannel,
     ) -> Result<()> {
         self.state
             .shared
             .cm_manager
             .blocking_lock()
-            .register_event_channel(channel_handle, channel)?;
+            .register_event_channel(ec, channel, &self.resource().event_channel_table)?;
         Ok(())
     }
 
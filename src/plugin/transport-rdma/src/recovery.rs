@@ -0,0 +1,1335 @@
+//! Recovering the connections whose queue pair went into the error state, e.g., on a cable flap
+//! or a reset of the peer, without the application doing anything.
+//!
+//! A connection is tracked from the time it is established. Every work request posted to it is
+//! kept until its completion. When the queue pair fails, which shows as an asynchronous
+//! `IBV_EVENT_QP_FATAL`, a disconnect from the peer, an error completion of a transport
+//! failure, or its port going down, see [`super::link`], the connection is degraded. The error
+//! and flush completions of the kept work requests are held back from the application. Once
+//! they have all been flushed out of the old queue pair, the active side creates a new `CmId`
+//! and a new queue pair with the same attributes and connects to the same peer again, with a
+//! token in the private data. The token carries a random secret the passive side sent in the
+//! private data of its accept when the connection was established. The passive side accepts the
+//! connect request on behalf of the application if the secret matches, and rejects it
+//! otherwise. The kept work requests are then posted on the new queue pair, receives first, in
+//! their original order, and the `CmId` the application holds refers to the new one from then on.
+//!
+//! The work requests that completed on the remote side but not yet locally are posted again, so a
+//! send may be delivered twice. The application sees the changes through
+//! [`ConnEvent`]s, and a connection that could not be re-established in a few attempts completes
+//! its kept work requests with flush errors.
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap as HashMap;
+use lazy_static::lazy_static;
+
+use phoenix_api::addrinfo::PortSpace;
+use phoenix_api::net;
+use phoenix_api::net::returned;
+use phoenix_api::transport::rdma::cmd::ConnEvent;
+use phoenix_api::{AsHandle, Handle};
+use rdma::ffi::{self, ibv_wc_opcode, ibv_wc_status, rdma_cm_event_type};
+use rdma::rdmacm;
+use rdma::rdmacm::CmId;
+
+use phoenix_common::log;
+
+use super::config::RecoveryConfig;
+use super::link;
+use super::ops::Ops;
+use super::state::Resource;
+use super::ApiError;
+
+/// Prefixes the private data of a connect request that re-establishes a connection, followed by
+/// the original address of the active side and the secret of the connection.
+const TOKEN_MAGIC: &[u8; 8] = b"phxrecon";
+const TOKEN_LEN: usize = TOKEN_MAGIC.len() + 16 + 2 + SECRET_LEN;
+
+/// Prefixes the secret of a connection in the private data of the accept that establishes it.
+const SECRET_MAGIC: &[u8; 8] = b"phxrsecr";
+const SECRET_LEN: usize = 16;
+/// The private data an accept carries at most.
+const MAX_ACCEPT_PRIVATE_DATA: usize = 196;
+
+/// A random secret the passive side sends to the active side when a connection is established.
+/// Only the active side can re-establish the connection then, not anyone who knows its address.
+pub(crate) type Secret = [u8; SECRET_LEN];
+
+/// A secret for a connection being accepted.
+#[inline]
+pub(crate) fn new_secret() -> Secret {
+    *uuid::Uuid::new_v4().as_bytes()
+}
+
+/// Puts the secret before the private data of the accept, `None` if it does not fit.
+pub(crate) fn with_secret(
+    conn_param: Option<&net::ConnParam>,
+    secret: &Secret,
+) -> Option<net::ConnParam> {
+    let mut conn_param = conn_param.cloned().unwrap_or(net::ConnParam {
+        private_data: None,
+        responder_resources: 1,
+        initiator_depth: 1,
+        flow_control: 0,
+        retry_count: 7,
+        rnr_retry_count: 7,
+        srq: 0,
+        qp_num: 0,
+    });
+    let mut data = SECRET_MAGIC.to_vec();
+    data.extend_from_slice(secret);
+    if let Some(app_data) = conn_param.private_data.take() {
+        data.extend_from_slice(&app_data);
+    }
+    if data.len() > MAX_ACCEPT_PRIVATE_DATA {
+        return None;
+    }
+    conn_param.private_data = Some(data);
+    Some(conn_param)
+}
+
+/// Splits the secret off the private data of an accept. Returns the secret, if any, and the
+/// private data after it.
+pub(crate) fn split_secret(private_data: &[u8]) -> (Option<Secret>, &[u8]) {
+    match private_data.strip_prefix(SECRET_MAGIC) {
+        Some(rest) if rest.len() >= SECRET_LEN => {
+            let (secret, rest) = rest.split_at(SECRET_LEN);
+            (Some(secret.try_into().unwrap()), rest)
+        }
+        _ => (None, private_data),
+    }
+}
+
+/// Compares the secrets in constant time.
+fn secret_eq(a: &Secret, b: &Secret) -> bool {
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn encode_token(origin: &SocketAddr, secret: &Secret) -> Vec<u8> {
+    let ip = match origin.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let mut token = Vec::with_capacity(TOKEN_LEN);
+    token.extend_from_slice(TOKEN_MAGIC);
+    token.extend_from_slice(&ip.octets());
+    token.extend_from_slice(&origin.port().to_be_bytes());
+    token.extend_from_slice(secret);
+    token
+}
+
+fn decode_token(data: &[u8]) -> Option<(SocketAddr, Secret)> {
+    // the private data of a connect request may be padded
+    if data.len() < TOKEN_LEN || !data.starts_with(TOKEN_MAGIC) {
+        return None;
+    }
+    let data = &data[TOKEN_MAGIC.len()..];
+    let octets: [u8; 16] = data[..16].try_into().unwrap();
+    let ip = Ipv6Addr::from(octets);
+    let ip = ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4);
+    let port = u16::from_be_bytes([data[16], data[17]]);
+    let secret = data[18..18 + SECRET_LEN].try_into().unwrap();
+    Some((SocketAddr::new(ip, port), secret))
+}
+
+/// Whether a connect request re-establishes a connection.
+#[inline]
+pub(crate) fn is_reconnect(private_data: &[u8]) -> bool {
+    decode_token(private_data).is_some()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PostedOp {
+    Recv,
+    Send(ffi::ibv_send_flags),
+    SendWithImm(ffi::ibv_send_flags, u32),
+    Write(ffi::ibv_send_flags, u64, u32),
+    Read(ffi::ibv_send_flags, u64, u32),
+    CompareAndSwap(ffi::ibv_send_flags, u64, u32, u64, u64),
+    FetchAndAdd(ffi::ibv_send_flags, u64, u32, u64),
+}
+
+/// A work request posted to a connection.
+#[derive(Debug, Clone, Copy)]
+pub struct Posted {
+    wr_id: u64,
+    mr: *mut ffi::ibv_mr,
+    buf: *mut u8,
+    len: usize,
+    op: PostedOp,
+    /// Completed with an error on a queue pair that failed.
+    flushed: bool,
+}
+
+// SAFETY: the buffer and the memory region stay valid until the work request completes, see
+// `Ops::post_send`, and they are only accessed to post the work request.
+unsafe impl Send for Posted {}
+
+impl Posted {
+    fn new(wr_id: u64, mr: &rdmacm::MemoryRegion, buf: &[u8], op: PostedOp) -> Self {
+        Posted {
+            wr_id,
+            mr: mr.0,
+            buf: buf.as_ptr() as *mut u8,
+            len: buf.len(),
+            op,
+            flushed: false,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn recv(wr_id: u64, mr: &rdmacm::MemoryRegion, buf: &[u8]) -> Self {
+        Self::new(wr_id, mr, buf, PostedOp::Recv)
+    }
+
+    #[inline]
+    pub(crate) fn send(
+        wr_id: u64,
+        mr: &rdmacm::MemoryRegion,
+        buf: &[u8],
+        flags: ffi::ibv_send_flags,
+        imm: Option<u32>,
+    ) -> Self {
+        let op = match imm {
+            Some(imm) => PostedOp::SendWithImm(flags, imm),
+            None => PostedOp::Send(flags),
+        };
+        Self::new(wr_id, mr, buf, op)
+    }
+
+    #[inline]
+    pub(crate) fn write(
+        wr_id: u64,
+        mr: &rdmacm::MemoryRegion,
+        buf: &[u8],
+        flags: ffi::ibv_send_flags,
+        remote_addr: u64,
+        rkey: u32,
+    ) -> Self {
+        Self::new(wr_id, mr, buf, PostedOp::Write(flags, remote_addr, rkey))
+    }
+
+    #[inline]
+    pub(crate) fn read(
+        wr_id: u64,
+        mr: &rdmacm::MemoryRegion,
+        buf: &[u8],
+        flags: ffi::ibv_send_flags,
+        remote_addr: u64,
+        rkey: u32,
+    ) -> Self {
+        Self::new(wr_id, mr, buf, PostedOp::Read(flags, remote_addr, rkey))
+    }
+
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn compare_and_swap(
+        wr_id: u64,
+        mr: &rdmacm::MemoryRegion,
+        buf: &[u8],
+        flags: ffi::ibv_send_flags,
+        remote_addr: u64,
+        rkey: u32,
+        compare: u64,
+        swap: u64,
+    ) -> Self {
+        let op = PostedOp::CompareAndSwap(flags, remote_addr, rkey, compare, swap);
+        Self::new(wr_id, mr, buf, op)
+    }
+
+    #[inline]
+    pub(crate) fn fetch_and_add(
+        wr_id: u64,
+        mr: &rdmacm::MemoryRegion,
+        buf: &[u8],
+        flags: ffi::ibv_send_flags,
+        remote_addr: u64,
+        rkey: u32,
+        add: u64,
+    ) -> Self {
+        let op = PostedOp::FetchAndAdd(flags, remote_addr, rkey, add);
+        Self::new(wr_id, mr, buf, op)
+    }
+
+    /// The same compare-and-swap with other operands.
+    #[inline]
+    pub(crate) fn with_operands(&self, compare: u64, swap: u64) -> Self {
+        let mut posted = *self;
+        if let PostedOp::CompareAndSwap(flags, remote_addr, rkey, ..) = self.op {
+            posted.op = PostedOp::CompareAndSwap(flags, remote_addr, rkey, compare, swap);
+        }
+        posted
+    }
+
+    /// The original remote value an atomic has written to its buffer.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must still be valid.
+    #[inline]
+    pub(crate) unsafe fn fetched(&self) -> u64 {
+        debug_assert_eq!(self.len, mem::size_of::<u64>());
+        ptr::read_unaligned(self.buf as *const u64)
+    }
+
+    #[inline]
+    fn is_recv(&self) -> bool {
+        matches!(self.op, PostedOp::Recv)
+    }
+
+    /// Posts the work request to the queue pair of `cmid`.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must stay valid until the work request completes.
+    pub(crate) unsafe fn post_on(&self, cmid: &CmId) -> io::Result<()> {
+        let mr = rdmacm::MemoryRegion::from_raw(self.mr);
+        let buf = slice::from_raw_parts_mut(self.buf, self.len);
+        match self.op {
+            PostedOp::Recv => cmid.post_recv(self.wr_id, buf, &mr),
+            PostedOp::Send(flags) => cmid.post_send(self.wr_id, buf, &mr, flags),
+            PostedOp::SendWithImm(flags, imm) => {
+                cmid.post_send_with_imm(self.wr_id, buf, &mr, flags, imm)
+            }
+            PostedOp::Write(flags, remote_addr, rkey) => {
+                cmid.post_write(self.wr_id, buf, &mr, flags, remote_addr, rkey)
+            }
+            PostedOp::Read(flags, remote_addr, rkey) => {
+                cmid.post_read(self.wr_id, buf, &mr, flags, remote_addr, rkey)
+            }
+            PostedOp::CompareAndSwap(flags, remote_addr, rkey, compare, swap) => cmid
+                .post_atomic_cmp_and_swp(
+                    self.wr_id,
+                    buf,
+                    &mr,
+                    flags,
+                    remote_addr,
+                    rkey,
+                    compare,
+                    swap,
+                ),
+            PostedOp::FetchAndAdd(flags, remote_addr, rkey, add) => {
+                cmid.post_atomic_fetch_and_add(self.wr_id, buf, &mr, flags, remote_addr, rkey, add)
+            }
+        }
+    }
+}
+
+/// Whether an error completion is a failure of the transport rather than of the work request.
+fn is_transport_error(status: ibv_wc_status::Type) -> bool {
+    use ibv_wc_status::*;
+    matches!(
+        status,
+        IBV_WC_WR_FLUSH_ERR
+            | IBV_WC_RETRY_EXC_ERR
+            | IBV_WC_RNR_RETRY_EXC_ERR
+            | IBV_WC_FATAL_ERR
+            | IBV_WC_RESP_TIMEOUT_ERR
+            | IBV_WC_GENERAL_ERR
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Role {
+    /// Connected to `peer` from `origin`.
+    Active {
+        peer: SocketAddr,
+        origin: SocketAddr,
+        secret: Secret,
+    },
+    /// Accepted the connection from `origin`.
+    Passive { origin: SocketAddr, secret: Secret },
+}
+
+/// The stages of a connection. A stage of an attempt carries the key of its new `CmId`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Connected,
+    Degraded,
+    Resolving(usize),
+    Routing(usize),
+    Connecting(usize),
+    Accepting(usize),
+    Failed,
+}
+
+impl Phase {
+    fn attempt(self) -> Option<usize> {
+        match self {
+            Phase::Resolving(k)
+            | Phase::Routing(k)
+            | Phase::Connecting(k)
+            | Phase::Accepting(k) => Some(k),
+            _ => None,
+        }
+    }
+}
+
+/// What creates the same queue pair again.
+#[derive(Debug, Clone)]
+struct QpParams {
+    pd: Option<net::ProtectionDomain>,
+    attr: net::QpInitAttr,
+    send_cq: net::CompletionQueue,
+    recv_cq: net::CompletionQueue,
+    qp_num: u32,
+}
+
+#[derive(Debug)]
+struct Connection {
+    role: Option<Role>,
+    phase: Phase,
+    qp: QpParams,
+    conn_param: Option<net::ConnParam>,
+    /// The work requests without a completion, in the order they are posted.
+    sends: VecDeque<Posted>,
+    recvs: VecDeque<Posted>,
+    events: VecDeque<ConnEvent>,
+    attempts: u32,
+    /// When the current phase began.
+    since: Instant,
+}
+
+impl Connection {
+    #[inline]
+    fn is_drained(&self) -> bool {
+        self.sends.iter().chain(&self.recvs).all(|p| p.flushed)
+    }
+
+    /// Marks the first kept work request with `wr_id` flushed. The sends before it have
+    /// completed, unsignaled, since a send queue completes in order.
+    fn flush(&mut self, wr_id: u64, cq: net::CompletionQueue) {
+        let shared_cq = self.qp.send_cq == self.qp.recv_cq;
+        if cq == self.qp.send_cq || shared_cq {
+            if let Some(pos) = self
+                .sends
+                .iter()
+                .position(|p| !p.flushed && p.wr_id == wr_id)
+            {
+                let mut i = 0;
+                self.sends.retain(|p| {
+                    i += 1;
+                    p.flushed || i > pos
+                });
+                self.sends.iter_mut().find(|p| !p.flushed).unwrap().flushed = true;
+                return;
+            }
+        }
+        if cq == self.qp.recv_cq || shared_cq {
+            if let Some(p) = self
+                .recvs
+                .iter_mut()
+                .find(|p| !p.flushed && p.wr_id == wr_id)
+            {
+                p.flushed = true;
+            }
+        }
+    }
+
+    /// Drops the kept work requests up to the one with `wr_id`, which completed successfully.
+    fn complete(&mut self, wr_id: u64, is_recv: bool) {
+        let queue = if is_recv {
+            &mut self.recvs
+        } else {
+            &mut self.sends
+        };
+        if let Some(pos) = queue.iter().position(|p| p.wr_id == wr_id) {
+            queue.drain(..=pos);
+        }
+    }
+
+    #[inline]
+    fn enter(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.since = Instant::now();
+    }
+}
+
+lazy_static! {
+    /// The recoveries tracking each queue pair, to dispatch the asynchronous events of the
+    /// devices, which are shared by all processes.
+    // TODO(cjr): Different devices can have queue pairs of the same number.
+    static ref QP_OWNERS: spin::Mutex<HashMap<u32, Weak<Recovery>>> =
+        spin::Mutex::new(HashMap::default());
+}
+
+/// Reports the queue pairs that failed to the recoveries tracking them.
+pub(crate) fn report_fatal_qps(fatal: Vec<u32>) {
+    // do not hold the owners while locking a recovery
+    let owners: Vec<_> = {
+        let owners = QP_OWNERS.lock();
+        fatal
+            .into_iter()
+            .filter_map(|qp_num| owners.get(&qp_num).map(|o| (qp_num, o.clone())))
+            .collect()
+    };
+    for (qp_num, owner) in owners {
+        if let Some(recovery) = owner.upgrade() {
+            recovery.lock().fatal_qps.push(qp_num);
+        }
+    }
+}
+
+/// Reports a port that went down, or all the ports of a device if `port_num` is `None`, to the
+/// recoveries tracking any connection.
+pub(crate) fn report_link_down(ctx: Handle, port_num: Option<u8>) {
+    let mut recoveries: Vec<Arc<Recovery>> = Vec::new();
+    for owner in QP_OWNERS.lock().values() {
+        if let Some(recovery) = owner.upgrade() {
+            if !recoveries.iter().any(|r| Arc::ptr_eq(r, &recovery)) {
+                recoveries.push(recovery);
+            }
+        }
+    }
+    for recovery in recoveries {
+        recovery.lock().down_links.push((ctx, port_num));
+    }
+}
+
+/// The recovery of the connections of a process.
+pub(crate) struct Recovery {
+    enabled: AtomicBool,
+    inner: spin::Mutex<Inner>,
+}
+
+impl Recovery {
+    pub(crate) fn new() -> Self {
+        Recovery {
+            enabled: AtomicBool::new(false),
+            inner: spin::Mutex::new(Inner::default()),
+        }
+    }
+
+    pub(crate) fn configure(&self, config: &RecoveryConfig) {
+        self.inner.lock().config = config.clone();
+        self.enabled.store(config.enabled, Ordering::Release);
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn lock(&self) -> spin::MutexGuard<'_, Inner> {
+        self.inner.lock()
+    }
+
+    /// Starts tracking a connection from the time it is established.
+    fn track_connection(
+        self: &Arc<Self>,
+        key: usize,
+        role: Role,
+        conn_param: Option<net::ConnParam>,
+    ) {
+        let mut inner = self.lock();
+        let qp_num = match inner.conns.get_mut(&key) {
+            Some(conn) => {
+                conn.role = Some(role);
+                conn.conn_param = conn_param;
+                conn.enter(Phase::Connected);
+                conn.qp.qp_num
+            }
+            None => {
+                log::debug!("connection {} has no queue pair created by phoenix", key);
+                return;
+            }
+        };
+        inner.by_qp_num.insert(qp_num, key);
+        QP_OWNERS.lock().insert(qp_num, Arc::downgrade(self));
+    }
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Inner {
+    config: RecoveryConfig,
+    /// By the key of the `CmId` the application holds.
+    conns: HashMap<usize, Connection>,
+    /// The key of the `CmId` each restored connection is established on.
+    successors: HashMap<usize, usize>,
+    by_qp_num: HashMap<u32, usize>,
+    /// Queue pairs that failed, reported by the asynchronous events.
+    fatal_qps: Vec<u32>,
+    /// Ports that went down, by the verbs context, all of them if the port is `None`.
+    down_links: Vec<(Handle, Option<u8>)>,
+    /// Connect requests re-establishing a connection, until the connection is drained.
+    requests: VecDeque<rdmacm::CmEvent>,
+    /// Completions of the work requests of the connections given up, by their CQ.
+    failed: VecDeque<(net::CompletionQueue, net::WorkCompletion)>,
+}
+
+impl Inner {
+    /// The key of the `CmId` the connection is established on now.
+    #[inline]
+    pub(crate) fn current(&self, key: usize) -> usize {
+        self.successors.get(&key).copied().unwrap_or(key)
+    }
+
+    /// Keeps a work request posted to the connection until it completes.
+    #[inline]
+    pub(crate) fn track(&mut self, key: usize, posted: Posted) {
+        if let Some(conn) = self.conns.get_mut(&key) {
+            if conn.phase == Phase::Failed {
+                return;
+            }
+            if posted.is_recv() {
+                conn.recvs.push_back(posted);
+            } else {
+                conn.sends.push_back(posted);
+            }
+        }
+    }
+
+    /// Looks at a work completion from `cq`. Returns true if it is held back from the
+    /// application.
+    pub(crate) fn absorb(
+        &mut self,
+        cq: net::CompletionQueue,
+        wc: &ffi::ibv_wc,
+        resource: &Resource,
+    ) -> bool {
+        let key = match self.by_qp_num.get(&wc.qp_num) {
+            Some(&key) => key,
+            None => return false,
+        };
+        let conn = self.conns.get_mut(&key).unwrap();
+        match wc.error() {
+            None => {
+                let is_recv = wc.opcode() & ibv_wc_opcode::IBV_WC_RECV != 0;
+                conn.complete(wc.wr_id(), is_recv);
+                false
+            }
+            Some((status, _)) if is_transport_error(status) => {
+                conn.flush(wc.wr_id(), cq);
+                if conn.phase == Phase::Connected {
+                    log::warn!(
+                        "connection {} degraded by a work completion with status {}",
+                        key,
+                        status
+                    );
+                    self.degrade(key, resource);
+                }
+                true
+            }
+            Some((status, _)) => {
+                // the work request itself is wrong, posting it again would fail the same way
+                log::warn!(
+                    "connection {} cannot be recovered from a work completion with status {}",
+                    key,
+                    status
+                );
+                self.give_up(key, resource);
+                false
+            }
+        }
+    }
+
+    /// Takes the completions of the work requests of the connections given up on `cq`.
+    pub(crate) fn drain_failed<F>(&mut self, cq: net::CompletionQueue, max: usize, mut f: F)
+    where
+        F: FnMut(net::WorkCompletion),
+    {
+        if self.failed.is_empty() {
+            return;
+        }
+        let mut n = 0;
+        self.failed.retain(|(c, wc)| {
+            if n < max && *c == cq {
+                f(*wc);
+                n += 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    #[inline]
+    pub(crate) fn pop_event(&mut self, key: usize) -> Option<ConnEvent> {
+        self.conns.get_mut(&key).and_then(|c| c.events.pop_front())
+    }
+
+    /// Stops tracking a connection the application disconnects. Returns the `CmId`s created for
+    /// the connection.
+    pub(crate) fn forget(&mut self, key: usize) -> Vec<usize> {
+        let mut created = Vec::new();
+        if let Some(conn) = self.conns.remove(&key) {
+            self.by_qp_num.remove(&conn.qp.qp_num);
+            QP_OWNERS.lock().remove(&conn.qp.qp_num);
+            created.extend(conn.phase.attempt());
+        }
+        created.extend(self.successors.remove(&key));
+        created
+    }
+
+    /// Disconnects the failed queue pair, which flushes its work requests.
+    fn degrade(&mut self, key: usize, resource: &Resource) {
+        let current = self.current(key);
+        let conn = self.conns.get_mut(&key).unwrap();
+        conn.enter(Phase::Degraded);
+        conn.events.push_back(ConnEvent::Degraded);
+        if let Ok(cmid) = resource.cmid_table.get_dp(current) {
+            // the disconnected event this triggers is not taken for a new failure
+            if let Ok(0) = unsafe { &*cmid.context() }.compare_exchange(
+                0,
+                1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                if let Err(e) = cmid.disconnect() {
+                    log::debug!("disconnecting the failed connection {}: {}", key, e);
+                }
+            }
+        }
+    }
+
+    /// Completes the kept work requests with flush errors. The ones not flushed yet complete
+    /// from the queue pair as usual.
+    fn give_up(&mut self, key: usize, resource: &Resource) {
+        let conn = self.conns.get_mut(&key).unwrap();
+        if conn.phase == Phase::Failed {
+            return;
+        }
+        let attempt = conn.phase.attempt();
+        conn.enter(Phase::Failed);
+        conn.events.push_back(ConnEvent::Failed);
+        let status = flush_status();
+        for p in conn.sends.drain(..).filter(|p| p.flushed) {
+            let wc = net::WorkCompletion::new_vendor_err(p.wr_id, status, 0);
+            self.failed.push_back((conn.qp.send_cq, wc));
+        }
+        for p in conn.recvs.drain(..).filter(|p| p.flushed) {
+            let wc = net::WorkCompletion::new_vendor_err(p.wr_id, status, 0);
+            self.failed.push_back((conn.qp.recv_cq, wc));
+        }
+        self.by_qp_num.remove(&conn.qp.qp_num);
+        QP_OWNERS.lock().remove(&conn.qp.qp_num);
+        if let Some(k) = attempt {
+            destroy_cmid(resource, k);
+        }
+    }
+}
+
+#[inline]
+fn flush_status() -> net::WcStatus {
+    net::WcStatus::Error(NonZeroU32::new(ibv_wc_status::IBV_WC_WR_FLUSH_ERR).unwrap())
+}
+
+/// Destroys a `CmId` created for a recovery, see `Ops::destroy_id`.
+fn destroy_cmid(resource: &Resource, key: usize) {
+    let cmid = match resource.cmid_table.close_resource_by_key(key) {
+        Ok(Some(cmid)) => cmid,
+        _ => return,
+    };
+    let ec_handle = cmid.event_channel().as_handle();
+    let ec = resource.event_channel_table.close_resource(&ec_handle);
+    if let Ok(Some(ec)) = ec.as_ref() {
+        ec.clear_event_queue();
+    }
+    drop(cmid);
+    drop(ec);
+}
+
+/// How an attempt went in this round.
+enum Step {
+    Pending,
+    Next(Phase),
+    Established,
+    Failed,
+}
+
+impl Ops {
+    /// Records the queue pair created for a `CmId` to create it again.
+    pub(crate) fn prepare_recovery(
+        &self,
+        key: usize,
+        pd: Option<net::ProtectionDomain>,
+        attr: &net::QpInitAttr,
+        qp: &returned::QueuePair,
+        qp_num: u32,
+    ) {
+        let conn = Connection {
+            role: None,
+            phase: Phase::Degraded,
+            qp: QpParams {
+                pd,
+                attr: attr.clone(),
+                send_cq: qp.send_cq.handle,
+                recv_cq: qp.recv_cq.handle,
+                qp_num,
+            },
+            conn_param: None,
+            sends: VecDeque::new(),
+            recvs: VecDeque::new(),
+            events: VecDeque::new(),
+            attempts: 0,
+            since: Instant::now(),
+        };
+        self.state.shared.recovery.lock().conns.insert(key, conn);
+    }
+
+    /// Tracks a connection this side established, with the secret the passive side sent.
+    pub(crate) fn recover_connected(
+        &self,
+        key: usize,
+        conn_param: Option<&net::ConnParam>,
+        secret: Secret,
+    ) {
+        let cmid = match self.resource().cmid_table.get(key) {
+            Ok(cmid) => cmid,
+            Err(_) => return,
+        };
+        let role = Role::Active {
+            peer: cmid.get_peer_addr(),
+            origin: cmid.get_local_addr(),
+            secret,
+        };
+        self.state
+            .shared
+            .recovery
+            .track_connection(key, role, conn_param.cloned());
+    }
+
+    /// Tracks a connection this side accepted, with the secret it sent to the active side.
+    pub(crate) fn recover_accepted(
+        &self,
+        key: usize,
+        conn_param: Option<&net::ConnParam>,
+        secret: Secret,
+    ) {
+        let cmid = match self.resource().cmid_table.get(key) {
+            Ok(cmid) => cmid,
+            Err(_) => return,
+        };
+        let role = Role::Passive {
+            origin: cmid.get_peer_addr(),
+            secret,
+        };
+        self.state
+            .shared
+            .recovery
+            .track_connection(key, role, conn_param.cloned());
+    }
+
+    /// Stops tracking a connection the application disconnects, and destroys the `CmId`s
+    /// created for it.
+    pub(crate) fn forget_recovery(&self, key: usize) {
+        let recovery = &self.state.shared.recovery;
+        if !recovery.is_enabled() {
+            return;
+        }
+        let created = recovery.lock().forget(key);
+        for k in created {
+            destroy_cmid(self.resource(), k);
+        }
+    }
+
+    /// Advances the recovery of the connections. Returns the amount of work done.
+    pub(crate) fn check_recovery(&self) -> Result<usize, ApiError> {
+        let recovery = &self.state.shared.recovery;
+        if !recovery.is_enabled() {
+            return Ok(0);
+        }
+        let mut nwork = 0;
+
+        let (disconnected, requests) = {
+            let mut manager = self.state.shared.cm_manager.blocking_lock();
+            (
+                mem::take(&mut manager.disconnected),
+                mem::take(&mut manager.reconnect_requests),
+            )
+        };
+        nwork += disconnected.len() + requests.len();
+
+        let mut inner = recovery.lock();
+        let inner = &mut *inner;
+
+        // the connections that failed
+        let fatal_qps = mem::take(&mut inner.fatal_qps);
+        for qp_num in fatal_qps {
+            if let Some(&key) = inner.by_qp_num.get(&qp_num) {
+                if inner.conns[&key].phase == Phase::Connected {
+                    inner.degrade(key, self.resource());
+                }
+            }
+        }
+        // the connections on the links that went down
+        let down_links = mem::take(&mut inner.down_links);
+        if !down_links.is_empty() {
+            let keys: Vec<usize> = inner
+                .conns
+                .iter()
+                .filter(|(_, conn)| conn.phase == Phase::Connected)
+                .map(|(&key, _)| key)
+                .collect();
+            for key in keys {
+                let port = self
+                    .resource()
+                    .cmid_table
+                    .get_dp(inner.current(key))
+                    .ok()
+                    .and_then(|cmid| {
+                        cmid.port()
+                            .map(|(ctx, port_num)| (ctx.as_handle(), port_num))
+                    });
+                let down = port.map_or(false, |(ctx, port_num)| {
+                    down_links
+                        .iter()
+                        .any(|&(c, p)| c == ctx && p.map_or(true, |p| p == port_num))
+                });
+                if down {
+                    log::warn!("connection {} degraded by its link going down", key);
+                    inner.degrade(key, self.resource());
+                }
+            }
+        }
+        for ec in disconnected {
+            // the channel has been closed, and maybe its handle taken by another one, since
+            if self
+                .resource()
+                .event_channel_table
+                .get_versioned(&ec)
+                .is_err()
+            {
+                continue;
+            }
+            let ec_handle = ec.key;
+            let key = inner.conns.iter().find_map(|(&key, conn)| {
+                let current = inner.successors.get(&key).copied().unwrap_or(key);
+                let cmid = self.resource().cmid_table.get_dp(current).ok()?;
+                (conn.phase == Phase::Connected && cmid.event_channel().as_handle() == ec_handle)
+                    .then_some(key)
+            });
+            if let Some(key) = key {
+                log::warn!("connection {} degraded by a disconnect of the peer", key);
+                inner.degrade(key, self.resource());
+            }
+        }
+        inner.requests.extend(requests);
+
+        // the passive sides accept the connect requests of their peers
+        let requests = mem::take(&mut inner.requests);
+        for event in requests {
+            if let Some(event) = self.accept_reconnect(inner, event) {
+                inner.requests.push_back(event);
+            }
+        }
+
+        // the active sides connect again, or give up
+        let keys: Vec<usize> = inner.conns.keys().copied().collect();
+        for key in keys {
+            nwork += self.advance(inner, key);
+        }
+
+        Ok(nwork)
+    }
+
+    /// Accepts a connect request re-establishing a connection. Returns the request back if the
+    /// connection is not drained yet.
+    fn accept_reconnect(
+        &self,
+        inner: &mut Inner,
+        event: rdmacm::CmEvent,
+    ) -> Option<rdmacm::CmEvent> {
+        let (origin, secret) = decode_token(event.private_data()).unwrap();
+        // the connection is found by the address, and only its active side knows the secret
+        let key = inner.conns.iter().find_map(|(&key, conn)| match conn.role {
+            Some(Role::Passive {
+                origin: o,
+                secret: s,
+            }) if o == origin && secret_eq(&s, &secret) && conn.phase != Phase::Failed => Some(key),
+            _ => None,
+        });
+        let key = match key {
+            Some(key) => key,
+            None => {
+                log::debug!("no connection from {} to re-establish", origin);
+                let (cmid, _) = event.get_request();
+                if let Err(e) = cmid.reject() {
+                    log::debug!("rejecting the connect request from {}: {}", origin, e);
+                }
+                // the event must be acknowledged before the CmId is destroyed
+                drop(event);
+                drop(cmid);
+                return None;
+            }
+        };
+        let conn = inner.conns.get_mut(&key).unwrap();
+        match conn.phase {
+            Phase::Connected => {
+                // the peer noticed first
+                inner.degrade(key, self.resource());
+                return Some(event);
+            }
+            Phase::Degraded if !conn.is_drained() => return Some(event),
+            Phase::Accepting(k) => {
+                // the peer gave up the previous attempt
+                destroy_cmid(self.resource(), k);
+            }
+            _ => {}
+        }
+
+        let result = (|| {
+            let ret_cmid = self.handle_connect_request(event)?;
+            let new_key = ret_cmid.handle.0 .0 as usize;
+            let attempt = (|| {
+                let qp_num = self.create_qp_for(inner, key, new_key)?;
+                let cmid = self.resource().cmid_table.get(new_key)?;
+                let conn_param = inner.conns[&key].conn_param.clone();
+                cmid.accept(self.get_conn_param(conn_param.as_ref()).as_ref())
+                    .map_err(ApiError::RdmaCm)?;
+                Ok::<_, ApiError>(qp_num)
+            })();
+            match attempt {
+                Ok(qp_num) => Ok((new_key, qp_num)),
+                Err(e) => {
+                    destroy_cmid(self.resource(), new_key);
+                    Err(e)
+                }
+            }
+        })();
+        let conn = inner.conns.get_mut(&key).unwrap();
+        match result {
+            Ok((new_key, _)) => conn.enter(Phase::Accepting(new_key)),
+            Err(e) => {
+                log::warn!("accepting to re-establish connection {}: {}", key, e);
+                conn.enter(Phase::Degraded);
+            }
+        }
+        None
+    }
+
+    /// Creates the queue pair of a connection again on the `CmId` of `new_key`. Returns the
+    /// number of the queue pair.
+    fn create_qp_for(&self, inner: &Inner, key: usize, new_key: usize) -> Result<u32, ApiError> {
+        let params = &inner.conns[&key].qp;
+        let cmid = self.resource().cmid_table.get(new_key)?;
+        let (pd, qp_init_attr) = self.get_qp_params(params.pd.as_ref(), Some(&params.attr))?;
+        let qp = cmid
+            .create_qp(pd.as_deref(), qp_init_attr.as_ref())
+            .map_err(ApiError::RdmaCm)?;
+        let qp_num = qp.qp_num();
+        self.resource().insert_qp(qp)?;
+        Ok(qp_num)
+    }
+
+    fn advance(&self, inner: &mut Inner, key: usize) -> usize {
+        let config = inner.config.clone();
+        let conn = &inner.conns[&key];
+        let elapsed = conn.since.elapsed();
+        let backoff = Duration::from_millis(config.backoff_ms << conn.attempts.min(16));
+        let resolve_timeout_ms = self.soft_rdma().timeout_ms(config.resolve_timeout_ms);
+        let timeout = Duration::from_millis(resolve_timeout_ms.max(0) as u64) * 2;
+
+        match (conn.phase, conn.role) {
+            (Phase::Connected | Phase::Failed, _) | (_, None) => 0,
+            (Phase::Degraded, Some(Role::Active { peer, origin, .. })) => {
+                // wait for the old queue pair to flush its work requests, which is quick
+                if elapsed < backoff || (!conn.is_drained() && elapsed < backoff + timeout) {
+                    return 0;
+                }
+                if conn.attempts >= config.max_attempts {
+                    log::warn!("giving up re-establishing connection {}", key);
+                    inner.give_up(key, self.resource());
+                    return 1;
+                }
+                let src = self.source_addr(inner, key, conn.attempts + 1);
+                let conn = inner.conns.get_mut(&key).unwrap();
+                conn.attempts += 1;
+                log::info!(
+                    "re-establishing connection {} to {} from {}, attempt {}",
+                    key,
+                    peer,
+                    src.unwrap_or(origin),
+                    conn.attempts
+                );
+                let attempt = (|| {
+                    let ret_cmid = self.create_id(PortSpace::TCP)?;
+                    let new_key = ret_cmid.handle.0 .0 as usize;
+                    let cmid = self.resource().cmid_table.get(new_key)?;
+                    if let Err(e) = cmid.resolve_addr_from(src.as_ref(), &peer, resolve_timeout_ms)
+                    {
+                        drop(cmid);
+                        destroy_cmid(self.resource(), new_key);
+                        return Err(ApiError::RdmaCm(e));
+                    }
+                    Ok(new_key)
+                })();
+                match attempt {
+                    Ok(new_key) => conn.enter(Phase::Resolving(new_key)),
+                    Err(e) => {
+                        log::warn!("re-establishing connection {}: {}", key, e);
+                        conn.enter(Phase::Degraded);
+                    }
+                }
+                1
+            }
+            (Phase::Degraded, Some(Role::Passive { .. })) => {
+                // the peer connects again within its attempts
+                let deadline =
+                    Duration::from_millis(config.backoff_ms << config.max_attempts.min(16))
+                        + timeout * config.max_attempts;
+                if elapsed > deadline {
+                    log::warn!("connection {} was not re-established by the peer", key);
+                    inner.give_up(key, self.resource());
+                    return 1;
+                }
+                0
+            }
+            (phase, _) => {
+                let new_key = phase.attempt().unwrap();
+                let step = self.step(inner, key, phase, new_key);
+                let step = match step {
+                    Step::Pending if elapsed > timeout => {
+                        log::warn!(
+                            "re-establishing connection {} timed out in {:?}",
+                            key,
+                            phase
+                        );
+                        Step::Failed
+                    }
+                    step => step,
+                };
+                match step {
+                    Step::Pending => 0,
+                    Step::Next(next) => {
+                        inner.conns.get_mut(&key).unwrap().enter(next);
+                        1
+                    }
+                    Step::Established => {
+                        if let Err(e) = self.restore(inner, key, new_key) {
+                            log::warn!("restoring connection {}: {}", key, e);
+                            inner.degrade(key, self.resource());
+                        }
+                        1
+                    }
+                    Step::Failed => {
+                        destroy_cmid(self.resource(), new_key);
+                        inner.conns.get_mut(&key).unwrap().enter(Phase::Degraded);
+                        1
+                    }
+                }
+            }
+        }
+    }
+
+    /// The local address to re-establish a connection from in an attempt. It is any address
+    /// unless the port of the connection is down, and then the failover addresses and any address
+    /// are tried in turn.
+    fn source_addr(&self, inner: &Inner, key: usize, attempt: u32) -> Option<SocketAddr> {
+        let failover = &inner.config.failover_addrs;
+        if failover.is_empty() {
+            return None;
+        }
+        let cmid = self.resource().cmid_table.get_dp(inner.current(key)).ok()?;
+        let (ctx, port_num) = cmid.port()?;
+        if link::is_port_up(ctx.as_handle(), port_num) {
+            return None;
+        }
+        let i = (attempt as usize).saturating_sub(1) % (failover.len() + 1);
+        failover.get(i).map(|&ip| SocketAddr::new(ip, 0))
+    }
+
+    /// Takes the next event of an attempt, and goes on.
+    fn step(&self, inner: &Inner, key: usize, phase: Phase, new_key: usize) -> Step {
+        let cmid = match self.resource().cmid_table.get(new_key) {
+            Ok(cmid) => cmid,
+            Err(_) => return Step::Failed,
+        };
+        let ec_handle = cmid.event_channel().as_handle();
+        let event = match self.resource().event_channel_table.get(&ec_handle) {
+            Ok(ec) => match ec.pop_cm_event() {
+                Some(event) => event,
+                None => return Step::Pending,
+            },
+            Err(_) => return Step::Failed,
+        };
+        if event.status() != 0 {
+            log::warn!(
+                "re-establishing connection {}: {} with status {}",
+                key,
+                event,
+                event.status()
+            );
+            return Step::Failed;
+        }
+
+        let config = &inner.config;
+        let result = match (phase, event.event()) {
+            (Phase::Resolving(_), rdma_cm_event_type::RDMA_CM_EVENT_ADDR_RESOLVED) => cmid
+                .resolve_route(self.soft_rdma().timeout_ms(config.resolve_timeout_ms))
+                .map(|_| Step::Next(Phase::Routing(new_key)))
+                .map_err(ApiError::RdmaCm),
+            (Phase::Routing(_), rdma_cm_event_type::RDMA_CM_EVENT_ROUTE_RESOLVED) => {
+                (|| {
+                    self.create_qp_for(inner, key, new_key)?;
+                    let conn = &inner.conns[&key];
+                    let (origin, secret) = match conn.role {
+                        Some(Role::Active { origin, secret, .. }) => (origin, secret),
+                        _ => unreachable!(),
+                    };
+                    // the passive side finds the connection by the token, and checks its secret
+                    let mut conn_param =
+                        conn.conn_param.clone().unwrap_or_else(|| net::ConnParam {
+                            private_data: None,
+                            responder_resources: 1,
+                            initiator_depth: 1,
+                            flow_control: 0,
+                            retry_count: 7,
+                            rnr_retry_count: 7,
+                            srq: 0,
+                            qp_num: 0,
+                        });
+                    conn_param.private_data = Some(encode_token(&origin, &secret));
+                    cmid.connect(self.get_conn_param(Some(&conn_param)).as_ref())
+                        .map_err(ApiError::RdmaCm)?;
+                    Ok::<_, ApiError>(Step::Next(Phase::Connecting(new_key)))
+                })()
+            }
+            (
+                Phase::Connecting(_) | Phase::Accepting(_),
+                rdma_cm_event_type::RDMA_CM_EVENT_ESTABLISHED,
+            ) => Ok(Step::Established),
+            _ => {
+                log::warn!("re-establishing connection {}: unexpected {}", key, event);
+                Ok(Step::Failed)
+            }
+        };
+        result.unwrap_or_else(|e| {
+            log::warn!("re-establishing connection {}: {}", key, e);
+            Step::Failed
+        })
+    }
+
+    /// Switches a connection over to the `CmId` of `new_key`, and posts the kept work requests
+    /// again.
+    fn restore(&self, inner: &mut Inner, key: usize, new_key: usize) -> Result<(), ApiError> {
+        let cmid = self.resource().cmid_table.get(new_key)?;
+        let qp_num = cmid.qp().map(|qp| qp.qp_num()).unwrap();
+
+        let previous = inner.successors.insert(key, new_key);
+        let conn = inner.conns.get_mut(&key).unwrap();
+        let old_qp_num = mem::replace(&mut conn.qp.qp_num, qp_num);
+        inner.by_qp_num.remove(&old_qp_num);
+        inner.by_qp_num.insert(qp_num, key);
+        {
+            let mut owners = QP_OWNERS.lock();
+            if let Some(owner) = owners.remove(&old_qp_num) {
+                owners.insert(qp_num, owner);
+            }
+        }
+        if let Some(k) = previous {
+            destroy_cmid(self.resource(), k);
+        }
+
+        conn.enter(Phase::Connected);
+        conn.attempts = 0;
+        for p in conn.recvs.iter_mut().chain(conn.sends.iter_mut()) {
+            p.flushed = false;
+            // SAFETY: the work request has not completed
+            unsafe { p.post_on(&cmid) }.map_err(ApiError::RdmaCm)?;
+        }
+        log::info!(
+            "connection {} restored, {} sends and {} receives posted again",
+            key,
+            conn.sends.len(),
+            conn.recvs.len()
+        );
+        conn.events.push_back(ConnEvent::Restored);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_round_trip() {
+        let secret = new_secret();
+        for origin in [
+            "192.168.1.2:40000".parse().unwrap(),
+            "[fe80::1]:1".parse().unwrap(),
+            "0.0.0.0:0".parse().unwrap(),
+        ] {
+            let token = encode_token(&origin, &secret);
+            assert_eq!(token.len(), TOKEN_LEN);
+            assert_eq!(decode_token(&token), Some((origin, secret)));
+            assert!(is_reconnect(&token));
+
+            // padded by the transport
+            let mut padded = token.clone();
+            padded.resize(56, 0);
+            assert_eq!(decode_token(&padded), Some((origin, secret)));
+
+            assert_eq!(decode_token(&token[..TOKEN_LEN - 1]), None);
+        }
+        assert_eq!(decode_token(&[0u8; 56]), None);
+        assert!(!is_reconnect(b"phxrsecr"));
+    }
+
+    #[test]
+    fn secret_in_accept() {
+        let secret = new_secret();
+        assert_ne!(secret, new_secret());
+
+        let conn_param = with_secret(None, &secret).unwrap();
+        let data = conn_param.private_data.unwrap();
+        assert_eq!(split_secret(&data), (Some(secret), &[][..]));
+
+        // the private data of the application follows the secret
+        let app = net::ConnParam {
+            private_data: Some(b"app".to_vec()),
+            responder_resources: 2,
+            initiator_depth: 3,
+            flow_control: 0,
+            retry_count: 7,
+            rnr_retry_count: 7,
+            srq: 0,
+            qp_num: 0,
+        };
+        let conn_param = with_secret(Some(&app), &secret).unwrap();
+        assert_eq!(conn_param.responder_resources, 2);
+        assert_eq!(conn_param.initiator_depth, 3);
+        let mut data = conn_param.private_data.unwrap();
+        data.resize(MAX_ACCEPT_PRIVATE_DATA, 0);
+        let (found, rest) = split_secret(&data);
+        assert_eq!(found, Some(secret));
+        assert!(rest.starts_with(b"app"));
+
+        // an accept without the secret is left as it is
+        assert_eq!(split_secret(b"app"), (None, &b"app"[..]));
+        assert_eq!(split_secret(SECRET_MAGIC), (None, &SECRET_MAGIC[..]));
+
+        // no room for the secret
+        let full = net::ConnParam {
+            private_data: Some(vec![0; MAX_ACCEPT_PRIVATE_DATA - SECRET_LEN]),
+            ..app
+        };
+        assert!(with_secret(Some(&full), &secret).is_none());
+    }
+
+    #[test]
+    fn secret_compare() {
+        let secret = new_secret();
+        assert!(secret_eq(&secret, &secret));
+        let mut other = secret;
+        other[SECRET_LEN - 1] ^= 1;
+        assert!(!secret_eq(&secret, &other));
+    }
+}
@@ -0,0 +1,117 @@
+//! Running on software RDMA, i.e., SoftRoCE (rxe) or soft-iWARP (siw), e.g., in a developer VM
+//! without an RDMA NIC.
+//!
+//! The kernel takes much longer to resolve the addresses and routes of a software device, and
+//! the software devices take little inline data. So the timeouts of the connection manager are
+//! raised to [`SoftRdmaConfig::min_timeout_ms`], and the queue pairs are created with at most
+//! [`SoftRdmaConfig::max_inline_data`], the sends larger than it being posted from their memory
+//! regions instead. This applies when every device is a software one, or when phoenixd runs with
+//! `--soft-rdma`, which sets [`SOFT_RDMA_ENV`], unless the configuration says otherwise.
+//!
+//! [`SoftRdmaConfig::min_timeout_ms`]: super::config::SoftRdmaConfig::min_timeout_ms
+//! [`SoftRdmaConfig::max_inline_data`]: super::config::SoftRdmaConfig::max_inline_data
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+
+use lazy_static::lazy_static;
+
+use phoenix_api::net;
+
+use phoenix_common::log;
+
+use super::config::{SoftRdmaConfig, SoftRdmaMode};
+use super::ops::Ops;
+use super::state::DEFAULT_CTXS;
+
+/// Asks for software RDMA when set to anything but `0`.
+pub const SOFT_RDMA_ENV: &str = "PHOENIX_SOFT_RDMA";
+
+lazy_static! {
+    /// Whether there are devices and all of them are software ones.
+    static ref ONLY_SOFTWARE: bool = {
+        let only_software = !DEFAULT_CTXS.is_empty()
+            && DEFAULT_CTXS
+                .iter()
+                .all(|ctx| match ctx.pinned_ctx.verbs.is_software() {
+                    Ok(is_software) => is_software,
+                    Err(e) => {
+                        log::warn!("querying device: {}", e);
+                        false
+                    }
+                });
+        if only_software {
+            log::info!("only software RDMA devices found, relaxing timeouts and inline data");
+        }
+        only_software
+    };
+}
+
+fn requested_by_env() -> bool {
+    env::var_os(SOFT_RDMA_ENV).map_or(false, |v| v != "0")
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SoftRdma {
+    enabled: AtomicBool,
+    max_inline_data: AtomicU32,
+    min_timeout_ms: AtomicI32,
+}
+
+impl SoftRdma {
+    pub(crate) fn configure(&self, config: &SoftRdmaConfig) {
+        let enabled = match config.mode {
+            SoftRdmaMode::Auto => requested_by_env() || *ONLY_SOFTWARE,
+            SoftRdmaMode::Always => true,
+            SoftRdmaMode::Never => false,
+        };
+        self.max_inline_data
+            .store(config.max_inline_data, Ordering::Relaxed);
+        self.min_timeout_ms
+            .store(config.min_timeout_ms, Ordering::Relaxed);
+        self.enabled.store(enabled, Ordering::Release);
+    }
+
+    #[inline]
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// The inline data to create a queue pair with, given the one asked for.
+    #[inline]
+    pub(crate) fn max_inline_data(&self, max_inline_data: u32) -> u32 {
+        if self.enabled() {
+            max_inline_data.min(self.max_inline_data.load(Ordering::Relaxed))
+        } else {
+            max_inline_data
+        }
+    }
+
+    /// Leaves out `INLINE` for a send of `len` bytes larger than the queue pairs take inline.
+    #[inline]
+    pub(crate) fn send_flags(&self, mut flags: net::SendFlags, len: usize) -> net::SendFlags {
+        if flags.contains(net::SendFlags::INLINE)
+            && self.enabled()
+            && len > self.max_inline_data.load(Ordering::Relaxed) as usize
+        {
+            flags.remove(net::SendFlags::INLINE);
+        }
+        flags
+    }
+
+    /// The timeout to resolve an address or a route with, given the one asked for.
+    #[inline]
+    pub(crate) fn timeout_ms(&self, timeout_ms: i32) -> i32 {
+        if self.enabled() {
+            timeout_ms.max(self.min_timeout_ms.load(Ordering::Relaxed))
+        } else {
+            timeout_ms
+        }
+    }
+}
+
+impl Ops {
+    #[inline]
+    pub(crate) fn soft_rdma(&self) -> &SoftRdma {
+        &self.state.shared.soft_rdma
+    }
+}
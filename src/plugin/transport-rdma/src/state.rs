@@ -6,6 +6,7 @@ use std::pin::Pin;
 // use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 
+use fnv::FnvHashMap as HashMap;
 use lazy_static::lazy_static;
 use nix::unistd::Pid;
 
@@ -15,11 +16,17 @@ use rdma::ibv;
 use rdma::rdmacm;
 use rdma::rdmacm::CmId;
 
+use phoenix_common::ledger::{Account, Usage};
 use phoenix_common::resource::{ResourceSlab, ResourceTable};
 use phoenix_common::state_mgr::ProcessShared;
 use phoenix_common::tracing;
 
+use super::atomics::Atomics;
 use super::cm::CmEventManager;
+#[cfg(feature = "dc")]
+use super::dc::DcState;
+use super::recovery::Recovery;
+use super::soft::SoftRdma;
 use super::ApiError;
 
 // TODO(cjr): Make this global lock more fine-grained.
@@ -46,6 +53,17 @@ pub struct Shared {
     pub(crate) cm_manager: tokio::sync::Mutex<CmEventManager>,
     // Pid as the identifier of this process
     pub pid: Pid,
+    // Connections being recovered, before the resources they refer to
+    pub(crate) recovery: Arc<Recovery>,
+    // Atomics done by the engine
+    pub(crate) atomics: Atomics,
+    // DC targets, initiators and peers, dropped before the resources they are created from
+    #[cfg(feature = "dc")]
+    pub(crate) dc: DcState,
+    // Timeouts and inline data relaxed for software RDMA
+    pub(crate) soft_rdma: SoftRdma,
+    // Private data received from the peers, until the connections are set up
+    pub(crate) peer_private_data: spin::Mutex<HashMap<usize, Vec<u8>>>,
     // Resources
     pub resource: Resource,
     // Other shared states include L4 policies, buffers, configurations, etc.
@@ -60,7 +78,13 @@ impl ProcessShared for Shared {
         let shared = Shared {
             cm_manager,
             pid,
-            resource: Resource::new()?,
+            recovery: Arc::new(Recovery::new()),
+            atomics: Atomics::default(),
+            #[cfg(feature = "dc")]
+            dc: DcState::default(),
+            soft_rdma: SoftRdma::default(),
+            peer_private_data: spin::Mutex::new(HashMap::default()),
+            resource: Resource::new(pid)?,
             _other: spin::Mutex::new(()),
         };
         Ok(shared)
@@ -91,7 +115,24 @@ impl PinnedContext {
 
 pub(crate) struct DefaultContext {
     pub(crate) pinned_ctx: Pin<Box<PinnedContext>>,
-    gid_table: Vec<ibv::Gid>,
+    // Read again when the GID table of the device changes
+    gid_table: spin::RwLock<Vec<ibv::Gid>>,
+}
+
+impl DefaultContext {
+    #[inline]
+    pub(crate) fn has_gid(&self, gid: &ibv::Gid) -> bool {
+        self.gid_table.read().contains(gid)
+    }
+
+    /// Reads the GID table of the device again.
+    pub(crate) fn refresh_gid_table(&self) -> io::Result<()> {
+        let verbs = &self.pinned_ctx.verbs;
+        let max_index = verbs.port_attr()?.gid_tbl_len as usize;
+        let gid_table: io::Result<_> = (0..max_index).map(|index| verbs.gid(index)).collect();
+        *self.gid_table.write() = gid_table?;
+        Ok(())
+    }
 }
 
 /// Open default verbs contexts
@@ -101,6 +142,8 @@ fn open_default_verbs() -> io::Result<Vec<DefaultContext>> {
     for ctx in ctx_list.into_iter() {
         let result: io::Result<_> = (|| {
             let max_index = ctx.port_attr()?.gid_tbl_len as usize;
+            // the asynchronous events are polled by the CmEngine
+            ctx.set_async_nonblocking(true)?;
             let gid_table: io::Result<_> = (0..max_index).map(|index| ctx.gid(index)).collect();
             Ok((ctx, gid_table?))
         })();
@@ -108,7 +151,7 @@ fn open_default_verbs() -> io::Result<Vec<DefaultContext>> {
             Ok((ctx, gid_table)) => {
                 default_ctxs.push(DefaultContext {
                     pinned_ctx: Box::pin(PinnedContext::new(ctx)),
-                    gid_table,
+                    gid_table: spin::RwLock::new(gid_table),
                 });
             }
             Err(e) => {
@@ -168,6 +211,11 @@ impl EventChannel {
         }
     }
 
+    /// Takes the first event in the order they occur.
+    pub(crate) fn pop_cm_event(&self) -> Option<rdmacm::CmEvent> {
+        self.event_queue.lock().pop_front()
+    }
+
     pub(crate) fn add_event(&self, cm_event: rdmacm::CmEvent) {
         self.event_queue.lock().push_back(cm_event);
     }
@@ -180,7 +228,8 @@ impl EventChannel {
 /// A variety of tables where each maps a `Handle` to a kind of RNIC resource.
 pub struct Resource {
     // pub default_pds: spin::Mutex<Vec<(net::ProtectionDomain, Vec<ibv::Gid>)>>,
-    pub default_pds: Vec<(net::ProtectionDomain, Vec<ibv::Gid>)>,
+    // The default PD of each device, with the index of the device in DEFAULT_CTXS
+    pub default_pds: Vec<(net::ProtectionDomain, usize)>,
     // NOTE(cjr): Do NOT change the order of the following fields. A wrong drop order may cause
     // failures in the underlying library.
     pub cmid_table: ResourceSlab<CmId<'static>>,
@@ -190,18 +239,16 @@ pub struct Resource {
     pub mr_table: ResourceSlab<rdma::mr::MemoryRegion>,
     pub cq_table: ResourceSlab<ibv::CompletionQueue<'static>>,
     pub pd_table: ResourceTable<ibv::ProtectionDomain<'static>>,
+    // The memory regions in mr_table, checked to be deregistered when the process is torn down
+    pub account: Account,
 }
 
 impl Resource {
-    pub fn new() -> io::Result<Self> {
+    pub fn new(pid: Pid) -> io::Result<Self> {
         let mut default_pds = Vec::new();
         let pd_table = ResourceTable::default();
-        for DefaultContext {
-            pinned_ctx: ctx,
-            gid_table,
-        } in DEFAULT_CTXS.iter()
-        {
-            let pd = match ctx.verbs.alloc_pd() {
+        for (index, default_ctx) in DEFAULT_CTXS.iter().enumerate() {
+            let pd = match default_ctx.pinned_ctx.verbs.alloc_pd() {
                 Ok(pd) => pd,
                 Err(_) => continue,
             };
@@ -209,7 +256,7 @@ impl Resource {
             // e.g. when SR-IOV is enabled, the program will panic here.
             let pd_handle = pd.as_handle();
             pd_table.insert(pd_handle, pd).unwrap();
-            default_pds.push((net::ProtectionDomain(pd_handle), gid_table.clone()));
+            default_pds.push((net::ProtectionDomain(pd_handle), index));
         }
         Ok(Resource {
             // default_pds: spin::Mutex::new(default_pds),
@@ -220,12 +267,23 @@ impl Resource {
             mr_table: ResourceSlab::default(),
             cq_table: ResourceSlab::default(),
             pd_table,
+            account: Account::new(pid),
         })
     }
 
+    /// What a memory region in `mr_table` holds, the registration and its memfd.
+    #[inline]
+    pub fn usage_of(mr: &rdma::mr::MemoryRegion) -> Usage {
+        Usage {
+            memory_regions: 1,
+            memfds: 1,
+            heap_bytes: mr.len() as u64,
+        }
+    }
+
     pub fn default_verbs_context(&self, gid: &ibv::Gid) -> Option<net::VerbsContext> {
         DEFAULT_CTXS.iter().find_map(|c| {
-            if c.gid_table.contains(gid) {
+            if c.has_gid(gid) {
                 Some(net::VerbsContext(c.pinned_ctx.verbs.as_handle()))
             } else {
                 None
@@ -234,9 +292,13 @@ impl Resource {
     }
 
     pub fn default_pd(&self, gid: &ibv::Gid) -> Option<net::ProtectionDomain> {
-        self.default_pds
-            .iter()
-            .find_map(|(pd, gids)| if gids.contains(gid) { Some(*pd) } else { None })
+        self.default_pds.iter().find_map(|(pd, index)| {
+            if DEFAULT_CTXS[*index].has_gid(gid) {
+                Some(*pd)
+            } else {
+                None
+            }
+        })
     }
 
     pub fn insert_qp(
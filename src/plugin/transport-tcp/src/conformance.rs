@@ -0,0 +1,89 @@
+//! Runs the conformance suite of the transports on [`Ops`] over the loopback device.
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nix::unistd::Pid;
+
+use phoenix_api::buf::Range;
+use phoenix_api::net::MappedAddrStatus;
+use phoenix_api::Handle;
+use phoenix_common::state_mgr::ProcessShared;
+use transport_conformance::{Completion, Config, Event, Transport};
+
+use crate::ops::Ops;
+use crate::state::{Shared, State};
+
+struct Loopback {
+    ops: Ops,
+}
+
+impl Loopback {
+    fn new() -> Self {
+        let shared = Arc::new(Shared::new(Pid::this()).unwrap());
+        Loopback {
+            ops: Ops::new(State::new(shared)),
+        }
+    }
+}
+
+impl Transport for Loopback {
+    type Error = anyhow::Error;
+
+    fn bind(&mut self, addr: &SocketAddr) -> anyhow::Result<Handle> {
+        Ok(self.ops.bind(addr)?)
+    }
+
+    fn local_addr(&self, listener: Handle) -> anyhow::Result<SocketAddr> {
+        Ok(self.ops.local_addr(listener)?)
+    }
+
+    fn connect(&mut self, addr: &SocketAddr) -> anyhow::Result<Handle> {
+        Ok(self.ops.connect(addr)?)
+    }
+
+    fn close(&mut self, handle: Handle) -> anyhow::Result<()> {
+        Ok(self.ops.close(handle)?)
+    }
+
+    unsafe fn post_send(
+        &mut self,
+        conn: Handle,
+        wr_id: u64,
+        buf: Range,
+        imm: u32,
+    ) -> anyhow::Result<()> {
+        Ok(self.ops.post_send(conn, wr_id, buf, imm)?)
+    }
+
+    unsafe fn post_recv(&mut self, conn: Handle, wr_id: u64, buf: Range) -> anyhow::Result<()> {
+        Ok(self.ops.post_recv(conn, wr_id, buf)?)
+    }
+
+    fn poll(&mut self, timeout: Duration, events: &mut Vec<Event>) -> anyhow::Result<()> {
+        let (conns, wcs) = self.ops.poll_io(timeout)?;
+        for conn in conns {
+            // as the collective engine does, read from the accepted connections right away
+            if let Some((_, status)) = self.ops.state.sock_table.borrow_mut().get_mut(&conn) {
+                *status = MappedAddrStatus::Mapped;
+            }
+            events.push(Event::Accepted(conn));
+        }
+        events.extend(wcs.into_iter().map(|wc| {
+            Event::Completion(Completion {
+                conn: Handle(wc.conn_id),
+                wr_id: wc.wr_id,
+                opcode: wc.opcode,
+                status: wc.status,
+                byte_len: wc.byte_len,
+                imm: wc.imm,
+            })
+        }));
+        Ok(())
+    }
+}
+
+#[test]
+fn tcp_conforms() {
+    transport_conformance::assert_conforms(Loopback::new, &Config::default());
+}
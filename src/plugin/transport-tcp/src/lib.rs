@@ -8,6 +8,8 @@ use phoenix_common::resource::Error as ResourceError;
 pub use phoenix_common::{InitFnResult, PhoenixModule};
 
 pub mod config;
+#[cfg(test)]
+mod conformance;
 pub mod engine;
 pub mod module;
 pub mod ops;
@@ -20,6 +22,10 @@ pub enum ApiError {
     Socket(#[from] io::Error),
     #[error("Resource not found in table")]
     NotFound,
+    #[error("Resource exists in table")]
+    Exists,
+    #[error("ResourceSlab is full")]
+    SlabFull,
     // #[error("Fail to create MemoryRegion: {0}")]
     // MemoryRegion(mr::Error),
 }
@@ -39,16 +45,16 @@ pub(crate) enum Error {
 
 impl From<Error> for phoenix_api::Error {
     fn from(other: Error) -> Self {
-        phoenix_api::Error::Generic(other.to_string())
+        phoenix_api::Error::from_error(phoenix_api::ErrorCode::Internal, &other)
     }
 }
 
 impl From<ResourceError> for ApiError {
     fn from(other: ResourceError) -> Self {
         match other {
-            ResourceError::NotFound => ApiError::NotFound,
-            ResourceError::Exists => panic!(),
-            ResourceError::SlabFull => todo!(),
+            ResourceError::NotFound | ResourceError::Stale => ApiError::NotFound,
+            ResourceError::Exists => ApiError::Exists,
+            ResourceError::SlabFull => ApiError::SlabFull,
         }
     }
 }
@@ -61,6 +67,8 @@ pub enum TransportError {
     ShmIpc(#[from] ipc::shmem_ipc::ShmIpcError),
     #[error("Shared memory queue ringbuf error: {0}.")]
     ShmRingbuf(#[from] ipc::shmem_ipc::ShmRingbufError),
+    #[error("Shared memory ring error: {0}.")]
+    ShmRing(#[from] ipc::ring::Error),
     #[error("Socket internal error: {0}.")]
     Socket(#[from] io::Error),
     #[error("Disconnected")]
@@ -72,9 +80,12 @@ pub enum TransportError {
 impl From<ResourceError> for TransportError {
     fn from(other: ResourceError) -> Self {
         match other {
-            ResourceError::NotFound => TransportError::NotFound,
-            ResourceError::Exists => panic!(),
-            ResourceError::SlabFull => panic!(),
+            ResourceError::NotFound | ResourceError::Stale => TransportError::NotFound,
+            other => {
+                let context = format!("resource error on the datapath: {}", other);
+                phoenix_common::metrics::record_unexpected_error(&context);
+                TransportError::General(context)
+            }
         }
     }
 }
@@ -84,7 +95,12 @@ impl From<ipc::Error> for TransportError {
         match other {
             ipc::Error::ShmIpc(e) => TransportError::ShmIpc(e),
             ipc::Error::ShmRingbuf(e) => TransportError::ShmRingbuf(e),
-            _ => panic!(),
+            ipc::Error::ShmRing(e) => TransportError::ShmRing(e),
+            other => {
+                let context = format!("IPC error on the datapath: {}", other);
+                phoenix_common::metrics::record_unexpected_error(&context);
+                TransportError::General(context)
+            }
         }
     }
 }
@@ -94,7 +110,7 @@ impl TransportError {
         match self {
             Self::NotFound => 1024,
             Self::ShmIpc(_) => 1025,
-            Self::ShmRingbuf(_) => 1026,
+            Self::ShmRingbuf(_) | Self::ShmRing(_) => 1026,
             Self::Disconnected => 1027,
             Self::General(_) => 2048,
             Self::Socket(e) => e.raw_os_error().unwrap() as u32,
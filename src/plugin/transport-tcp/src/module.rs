@@ -11,6 +11,7 @@ use ipc::unix::DomainSocket;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::transport::tcp::{cmd, dp};
 
+use phoenix_common::capability::Capabilities;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
 use phoenix_common::module::{
@@ -99,6 +100,24 @@ impl PhoenixModule for TcpTransportModule {
         Self::DEPENDENCIES
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            syscalls: vec![
+                libc::SYS_socket,
+                libc::SYS_bind,
+                libc::SYS_listen,
+                libc::SYS_accept4,
+                libc::SYS_connect,
+                libc::SYS_setsockopt,
+                libc::SYS_getsockopt,
+                libc::SYS_getsockname,
+                libc::SYS_getpeername,
+                libc::SYS_shutdown,
+            ],
+            ..Default::default()
+        }
+    }
+
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
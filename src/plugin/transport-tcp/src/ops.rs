@@ -3,7 +3,7 @@ use std::collections::VecDeque;
 use std::io::{IoSlice, Read, Write};
 use std::net::SocketAddr;
 use std::num::NonZeroU32;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::Duration;
 
 use mio::net::{TcpListener, TcpStream};
@@ -29,6 +29,11 @@ impl Ops {
         self.state.poll.borrow()
     }
 
+    /// The epoll fd of the sockets, readable when any of them has an event.
+    pub fn poll_fd(&self) -> RawFd {
+        self.poll().as_raw_fd()
+    }
+
     fn poll_mut(&self) -> RefMut<Poll> {
         self.state.poll.borrow_mut()
     }
@@ -109,6 +114,57 @@ impl Ops {
     pub fn accept(&self, _handle: Handle) -> Result<Handle, ApiError> {
         unimplemented!("accept");
     }
+
+    /// The address the listener is bound to.
+    pub fn local_addr(&self, listener_handle: Handle) -> Result<SocketAddr, ApiError> {
+        let table = self.state.listener_table.borrow();
+        let listener = table.get(&listener_handle).ok_or(ApiError::NotFound)?;
+        Ok(listener.local_addr()?)
+    }
+
+    /// Closes a listener or a connection, and drops the work posted on it.
+    pub fn close(&self, handle: Handle) -> Result<(), ApiError> {
+        if self
+            .state
+            .listener_table
+            .borrow_mut()
+            .remove(&handle)
+            .is_some()
+        {
+            return Ok(());
+        }
+        self.state.cq_table.borrow_mut().remove(&handle);
+        self.state
+            .sock_table
+            .borrow_mut()
+            .remove(&handle)
+            .map(|_| ())
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// The segments the kernel has retransmitted on the socket.
+    pub fn retransmits(&self, handle: Handle) -> Result<u64, ApiError> {
+        let table = self.state.sock_table.borrow();
+        let (sock, _status) = table.get(&handle).ok_or(ApiError::NotFound)?;
+        let mut info = std::mem::MaybeUninit::<libc::tcp_info>::zeroed();
+        let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+        // SAFETY: the kernel writes at most len bytes to info
+        let ret = unsafe {
+            libc::getsockopt(
+                sock.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                info.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // SAFETY: zeroed is a valid tcp_info, the fields not written stay zero
+        let info = unsafe { info.assume_init() };
+        Ok(info.tcpi_total_retrans as u64)
+    }
 }
 
 const MAGIC_BYTES: usize = std::mem::size_of::<u32>();
@@ -136,6 +192,8 @@ impl Ops {
     /// Format:
     /// | magic | imm | len |      buf    |
     /// |   4   |  4  |  8  |   range.len |
+    ///
+    /// The fields of the header are little-endian.
     pub fn post_send(
         &self,
         sock_handle: Handle,
@@ -205,8 +263,9 @@ impl Ops {
                 .borrow()
                 .contains_key(&Handle(handle as _))
             {
+                // the events are edge-triggered, take all the pending connections
                 let listener_handle = handle;
-                if let Ok(handle) = self.try_accept(Handle(listener_handle as _)) {
+                while let Ok(handle) = self.try_accept(Handle(listener_handle as _)) {
                     conns.push(handle);
                 }
             } else {
@@ -282,7 +341,9 @@ impl Task {
     ) -> Self {
         let expected = if opcode == WcOpcode::Recv {
             if offset >= HEADER_BYTES {
-                let len = unsafe { std::ptr::read_unaligned((buf.offset as *const u64).offset(1)) };
+                let len = u64::from_le(unsafe {
+                    std::ptr::read_unaligned((buf.offset as *const u64).offset(1))
+                });
                 HEADER_BYTES + len as usize
             } else {
                 0
@@ -305,12 +366,10 @@ impl Task {
 
     pub(crate) fn get_meta(imm: u32, len: u64) -> [u8; HEADER_BYTES] {
         let mut meta: [u8; HEADER_BYTES] = [0; HEADER_BYTES];
-        unsafe {
-            let magic: u32 = 2563;
-            std::ptr::write(meta.as_mut_ptr() as *mut u32, magic);
-            std::ptr::write(meta.as_mut_ptr().offset(4) as *mut u32, imm);
-            std::ptr::write(meta.as_mut_ptr().offset(8) as *mut u64, len);
-        }
+        let magic: u32 = 2563;
+        meta[..4].copy_from_slice(&magic.to_le_bytes());
+        meta[4..8].copy_from_slice(&imm.to_le_bytes());
+        meta[8..].copy_from_slice(&len.to_le_bytes());
         meta
     }
 
@@ -341,7 +400,8 @@ impl Task {
                 Err(e) => WcStatus::Error(NonZeroU32::new(e.as_vendor_err()).unwrap()),
             },
             buf: self.buf,
-            byte_len: self.offset - HEADER_BYTES,
+            // a receive may fail before its header is read
+            byte_len: self.offset.saturating_sub(HEADER_BYTES),
             imm: self.imm,
         }
     }
@@ -424,17 +484,10 @@ impl CompletionQueue {
                             task.offset += n;
                             if task.offset == HEADER_BYTES {
                                 // TODO(lsh): Can check magic number here
-                                let len = unsafe {
-                                    std::ptr::read_unaligned(
-                                        (task.meta.as_ptr().offset(8)) as *const u64,
-                                    )
-                                } as usize;
+                                let len =
+                                    u64::from_le_bytes(task.meta[8..].try_into().unwrap()) as usize;
                                 task.expected = HEADER_BYTES + len;
-                                task.imm = unsafe {
-                                    std::ptr::read_unaligned(
-                                        (task.meta.as_ptr().offset(4)) as *const u32,
-                                    )
-                                };
+                                task.imm = u32::from_le_bytes(task.meta[4..8].try_into().unwrap());
                                 if len > task.buf.len as _ {
                                     task.error = Err(TransportError::General(
                                         "Insufficient recving buffer!".to_string(),
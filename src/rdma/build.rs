@@ -44,7 +44,27 @@ fn main() {
         .file("src/rdma_verbs_wrapper.c")
         .compile("rdma_verbs_wrapper");
 
-    let bindings = bindgen::Builder::default()
+    // Dynamically connected transport goes through the mlx5 direct verbs.
+    let dc = env::var_os("CARGO_FEATURE_DC").is_some();
+    if dc {
+        println!("cargo:rerun-if-changed=src/mlx5dv_wrapper.c");
+        cc::Build::new()
+            .warnings(true)
+            .opt_level(3)
+            .cargo_metadata(false)
+            .pic(true)
+            .use_plt(false)
+            .shared_flag(true)
+            .static_flag(true)
+            .file("src/mlx5dv_wrapper.c")
+            .compile("mlx5dv_wrapper");
+    }
+
+    let mut builder = bindgen::Builder::default();
+    if dc {
+        builder = builder.clang_arg("-DPHOENIX_DC");
+    }
+    let bindings = builder
         .header("src/rdma_verbs_wrapper.h")
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
         .blocklist_type("max_align_t")
@@ -60,6 +80,12 @@ fn main() {
         .constified_enum_module("ibv_wc_opcode")
         .constified_enum_module("ibv_wr_opcode")
         .constified_enum_module("ibv_wc_status")
+        .constified_enum_module("ibv_event_type")
+        .constified_enum_module("ibv_mw_type")
+        .constified_enum_module("ibv_qp_init_attr_mask")
+        .constified_enum_module("ibv_qp_create_send_ops_flags")
+        .constified_enum_module("mlx5dv_qp_init_attr_mask")
+        .constified_enum_module("mlx5dv_dc_type")
         .constified_enum_module("rdma_port_space")
         .constified_enum_module("rdma_cm_event_type")
         .derive_default(true)
@@ -74,6 +100,9 @@ fn main() {
 
     println!("cargo:rustc-link-search=native={}", out_path.display());
     println!("cargo:rustc-link-lib=static=rdma_verbs_wrapper");
+    if dc {
+        println!("cargo:rustc-link-lib=static=mlx5dv_wrapper");
+    }
     // println!("cargo:rustc-link-lib=dylib=rdma_verbs_wrapper");
     // println!("cargo:rustc-link-arg=-Wl,-rpath={}", out_path.display());
     // println!(
@@ -83,4 +112,7 @@ fn main() {
 
     println!("cargo:rustc-link-lib=ibverbs");
     println!("cargo:rustc-link-lib=rdmacm");
+    if dc {
+        println!("cargo:rustc-link-lib=mlx5");
+    }
 }
@@ -59,6 +59,10 @@ mod sa {
         AccessFlags::REMOTE_ATOMIC.bits(),
         ffi::ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC.0
     );
+    const_assert_eq!(
+        AccessFlags::MW_BIND.bits(),
+        ffi::ibv_access_flags::IBV_ACCESS_MW_BIND.0
+    );
 }
 
 impl From<phoenix_api::addrinfo::PortSpace> for rdmacm::PortSpace {
@@ -216,6 +220,8 @@ impl From<ffi::ibv_wc> for phoenix_api::net::WorkCompletion {
                 ibv_wc_opcode::IBV_WC_SEND => WcOpcode::Send,
                 ibv_wc_opcode::IBV_WC_RDMA_WRITE => WcOpcode::RdmaWrite,
                 ibv_wc_opcode::IBV_WC_RDMA_READ => WcOpcode::RdmaRead,
+                ibv_wc_opcode::IBV_WC_COMP_SWAP => WcOpcode::CompSwap,
+                ibv_wc_opcode::IBV_WC_FETCH_ADD => WcOpcode::FetchAdd,
                 ibv_wc_opcode::IBV_WC_RECV => WcOpcode::Recv,
                 ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM => WcOpcode::RecvRdmaWithImm,
                 code => panic!("unimplemented opcode: {:?}, wc: {:?}", code, other),
@@ -0,0 +1,508 @@
+//! Dynamically connected (DC) transport of ConnectX devices, through the mlx5 direct verbs.
+//!
+//! A DC target (DCT) receives, into a shared receive queue, from any DC initiator (DCI) that
+//! presents its access key. A DCI sends to any DCT, each work request carrying an address handle
+//! of the peer, the number of its DCT, and the key. The device connects a DCI to a DCT on demand,
+//! so the state of a process stays the same however many peers it reaches.
+use std::io;
+use std::marker::PhantomData;
+use std::ptr;
+
+use crate::ffi;
+use crate::ibv::{CompletionQueue, Gid, ProtectionDomain, SendFlags, PORT_NUM};
+use crate::rdmacm;
+
+/// What a DCI needs to reach a DCT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DcEndpoint {
+    /// The number of the DCT.
+    pub dctn: u32,
+    /// The access key of the DCT.
+    pub key: u64,
+    /// The LID of the port of the DCT.
+    pub lid: u16,
+    /// The GID of the port of the DCT.
+    pub gid: Gid,
+}
+
+impl DcEndpoint {
+    /// The length of the encoded endpoint.
+    pub const ENCODED_LEN: usize = 4 + 8 + 2 + 16;
+
+    /// Encodes the endpoint to exchange it with a peer.
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0; Self::ENCODED_LEN];
+        buf[..4].copy_from_slice(&self.dctn.to_be_bytes());
+        buf[4..12].copy_from_slice(&self.key.to_be_bytes());
+        buf[12..14].copy_from_slice(&self.lid.to_be_bytes());
+        let gid: ffi::ibv_gid = self.gid.into();
+        buf[14..].copy_from_slice(unsafe { &gid.raw });
+        buf
+    }
+
+    /// Decodes an endpoint encoded by [`DcEndpoint::encode`].
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::ENCODED_LEN {
+            return None;
+        }
+        let raw: [u8; 16] = buf[14..Self::ENCODED_LEN].try_into().unwrap();
+        Some(DcEndpoint {
+            dctn: u32::from_be_bytes(buf[..4].try_into().unwrap()),
+            key: u64::from_be_bytes(buf[4..12].try_into().unwrap()),
+            lid: u16::from_be_bytes(buf[12..14].try_into().unwrap()),
+            gid: ffi::ibv_gid { raw }.into(),
+        })
+    }
+}
+
+/// Creates a queue pair of the mlx5 driver with the DC attributes.
+fn create_dc_qp(
+    pd: &ProtectionDomain<'_>,
+    attr: &mut ffi::ibv_qp_init_attr_ex,
+    dc_type: ffi::mlx5dv_dc_type::Type,
+    key: u64,
+) -> io::Result<*mut ffi::ibv_qp> {
+    let mut dv_attr = ffi::mlx5dv_qp_init_attr {
+        comp_mask: ffi::mlx5dv_qp_init_attr_mask::MLX5DV_QP_INIT_ATTR_MASK_DC as u64,
+        dc_init_attr: ffi::mlx5dv_dc_init_attr {
+            dc_type,
+            __bindgen_anon_1: ffi::mlx5dv_dc_init_attr__bindgen_ty_1 {
+                dct_access_key: key,
+            },
+        },
+        ..Default::default()
+    };
+    attr.qp_type = ffi::ibv_qp_type::IBV_QPT_DRIVER;
+    attr.pd = pd.pd;
+    attr.comp_mask |= ffi::ibv_qp_init_attr_mask::IBV_QP_INIT_ATTR_PD;
+    let qp = unsafe { ffi::mlx5dv_create_qp((&*pd.pd).context, attr, &mut dv_attr) };
+    if qp.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(qp)
+    }
+}
+
+fn modify_qp(
+    qp: *mut ffi::ibv_qp,
+    attr: &mut ffi::ibv_qp_attr,
+    mask: ffi::ibv_qp_attr_mask,
+) -> io::Result<()> {
+    let errno = unsafe { ffi::ibv_modify_qp(qp, attr, mask.0 as i32) };
+    if errno != 0 {
+        return Err(io::Error::from_raw_os_error(errno));
+    }
+    Ok(())
+}
+
+/// The address vector of the port of `gid_index` reaching `remote`, or the local port if `None`.
+fn ah_attr(gid_index: u8, remote: Option<&DcEndpoint>) -> ffi::ibv_ah_attr {
+    let mut ah_attr = ffi::ibv_ah_attr {
+        is_global: 1,
+        port_num: PORT_NUM,
+        ..Default::default()
+    };
+    ah_attr.grh.sgid_index = gid_index;
+    ah_attr.grh.hop_limit = 0xff;
+    if let Some(remote) = remote {
+        ah_attr.dlid = remote.lid;
+        ah_attr.grh.dgid = remote.gid.into();
+    }
+    ah_attr
+}
+
+/// A DC target, the receiving side of the DC transport, with its shared receive queue.
+pub struct DcTarget<'res> {
+    _phantom: PhantomData<&'res ()>,
+    qp: *mut ffi::ibv_qp,
+    srq: *mut ffi::ibv_srq,
+    endpoint: DcEndpoint,
+}
+
+unsafe impl<'res> Send for DcTarget<'res> {}
+unsafe impl<'res> Sync for DcTarget<'res> {}
+
+impl<'res> DcTarget<'res> {
+    /// Creates a DCT whose receives complete on `cq`, accepting the DCIs that present `key`, and
+    /// brings it to `IBV_QPS_RTR` on the port of `gid_index`.
+    ///
+    /// # Errors
+    ///
+    ///  - `EOPNOTSUPP`: The device does not support DC.
+    ///  - `EINVAL`: Invalid value provided in the attributes.
+    ///  - `ENOMEM`: Not enough resources to complete this operation.
+    pub fn create(
+        pd: &ProtectionDomain<'res>,
+        cq: &CompletionQueue<'res>,
+        max_recv_wr: u32,
+        key: u64,
+        gid_index: u8,
+    ) -> io::Result<Self> {
+        let port_attr = pd.context().port_attr()?;
+        let gid = pd.context().gid(gid_index as usize)?;
+        let mut srq_attr = ffi::ibv_srq_init_attr {
+            attr: ffi::ibv_srq_attr {
+                max_wr: max_recv_wr,
+                max_sge: 1,
+                srq_limit: 0,
+            },
+            ..Default::default()
+        };
+        let srq = unsafe { ffi::ibv_create_srq(pd.pd, &mut srq_attr) };
+        if srq.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let mut attr = ffi::ibv_qp_init_attr_ex {
+            send_cq: cq.cq,
+            recv_cq: cq.cq,
+            srq,
+            ..Default::default()
+        };
+        let qp = match create_dc_qp(pd, &mut attr, ffi::mlx5dv_dc_type::MLX5DV_DCTYPE_DCT, key) {
+            Ok(qp) => qp,
+            Err(e) => {
+                unsafe { ffi::ibv_destroy_srq(srq) };
+                return Err(e);
+            }
+        };
+        let dct = DcTarget {
+            _phantom: PhantomData,
+            qp,
+            srq,
+            endpoint: DcEndpoint {
+                dctn: unsafe { &*qp }.qp_num,
+                key,
+                lid: port_attr.lid,
+                gid,
+            },
+        };
+
+        let mut attr = ffi::ibv_qp_attr {
+            qp_state: ffi::ibv_qp_state::IBV_QPS_INIT,
+            pkey_index: 0,
+            port_num: PORT_NUM,
+            qp_access_flags: (ffi::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE
+                | ffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ
+                | ffi::ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC)
+                .0,
+            ..Default::default()
+        };
+        let mask = ffi::ibv_qp_attr_mask::IBV_QP_STATE
+            | ffi::ibv_qp_attr_mask::IBV_QP_PKEY_INDEX
+            | ffi::ibv_qp_attr_mask::IBV_QP_PORT
+            | ffi::ibv_qp_attr_mask::IBV_QP_ACCESS_FLAGS;
+        modify_qp(qp, &mut attr, mask)?;
+
+        let mut attr = ffi::ibv_qp_attr {
+            qp_state: ffi::ibv_qp_state::IBV_QPS_RTR,
+            path_mtu: port_attr.active_mtu,
+            min_rnr_timer: 16,
+            ah_attr: ah_attr(gid_index, None),
+            ..Default::default()
+        };
+        let mask = ffi::ibv_qp_attr_mask::IBV_QP_STATE
+            | ffi::ibv_qp_attr_mask::IBV_QP_AV
+            | ffi::ibv_qp_attr_mask::IBV_QP_PATH_MTU
+            | ffi::ibv_qp_attr_mask::IBV_QP_MIN_RNR_TIMER;
+        modify_qp(qp, &mut attr, mask)?;
+        Ok(dct)
+    }
+
+    /// What a DCI needs to reach this DCT.
+    #[inline]
+    pub fn endpoint(&self) -> DcEndpoint {
+        self.endpoint
+    }
+
+    /// Queries the limits of the shared receive queue of the DCT, which may be more than asked
+    /// for at its creation.
+    pub fn query_srq(&self) -> io::Result<ffi::ibv_srq_attr> {
+        let mut attr = ffi::ibv_srq_attr::default();
+        let errno = unsafe { ffi::ibv_query_srq(self.srq, &mut attr) };
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        Ok(attr)
+    }
+
+    /// Posts a receive to the shared receive queue of the DCT.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue.
+    #[inline]
+    pub unsafe fn post_recv(
+        &self,
+        wr_id: u64,
+        buf: &mut [u8],
+        mr: &rdmacm::MemoryRegion<'_>,
+    ) -> io::Result<()> {
+        let mut sge = ffi::ibv_sge {
+            addr: buf.as_mut_ptr() as u64,
+            length: buf.len() as u32,
+            lkey: (&*mr.0).lkey,
+        };
+        let mut wr = ffi::ibv_recv_wr {
+            wr_id,
+            next: ptr::null_mut(),
+            sg_list: &mut sge,
+            num_sge: 1,
+        };
+        let mut bad_wr = ptr::null_mut();
+        let errno = ffi::ibv_post_srq_recv_real(self.srq, &mut wr, &mut bad_wr);
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        Ok(())
+    }
+}
+
+impl<'res> Drop for DcTarget<'res> {
+    fn drop(&mut self) {
+        let errno = unsafe { ffi::ibv_destroy_qp(self.qp) };
+        if errno != 0 {
+            let e = io::Error::from_raw_os_error(errno);
+            panic!("{}", e);
+        }
+        let errno = unsafe { ffi::ibv_destroy_srq(self.srq) };
+        if errno != 0 {
+            let e = io::Error::from_raw_os_error(errno);
+            panic!("{}", e);
+        }
+    }
+}
+
+/// A peer a DCI sends to, i.e., an address handle and the DCT at that address.
+pub struct DcPeer<'res> {
+    _phantom: PhantomData<&'res ()>,
+    ah: *mut ffi::ibv_ah,
+    dctn: u32,
+    key: u64,
+}
+
+unsafe impl<'res> Send for DcPeer<'res> {}
+unsafe impl<'res> Sync for DcPeer<'res> {}
+
+impl<'res> Drop for DcPeer<'res> {
+    fn drop(&mut self) {
+        let errno = unsafe { ffi::ibv_destroy_ah(self.ah) };
+        if errno != 0 {
+            let e = io::Error::from_raw_os_error(errno);
+            panic!("{}", e);
+        }
+    }
+}
+
+/// A DC initiator, the sending side of the DC transport.
+pub struct DcInitiator<'res> {
+    _phantom: PhantomData<&'res ()>,
+    qp: *mut ffi::ibv_qp,
+    qpx: *mut ffi::ibv_qp_ex,
+    mqpx: *mut ffi::mlx5dv_qp_ex,
+    pd: *mut ffi::ibv_pd,
+    gid_index: u8,
+}
+
+unsafe impl<'res> Send for DcInitiator<'res> {}
+unsafe impl<'res> Sync for DcInitiator<'res> {}
+
+impl<'res> DcInitiator<'res> {
+    /// Creates a DCI whose sends complete on `cq` and brings it to `IBV_QPS_RTS` on the port of
+    /// `gid_index`.
+    ///
+    /// # Errors
+    ///
+    ///  - `EOPNOTSUPP`: The device does not support DC.
+    ///  - `EINVAL`: Invalid value provided in the attributes.
+    ///  - `ENOMEM`: Not enough resources to complete this operation.
+    pub fn create(
+        pd: &ProtectionDomain<'res>,
+        cq: &CompletionQueue<'res>,
+        max_send_wr: u32,
+        gid_index: u8,
+    ) -> io::Result<Self> {
+        use ffi::ibv_qp_create_send_ops_flags::*;
+        let mut attr = ffi::ibv_qp_init_attr_ex {
+            send_cq: cq.cq,
+            recv_cq: cq.cq,
+            cap: ffi::ibv_qp_cap {
+                max_send_wr,
+                max_send_sge: 1,
+                ..Default::default()
+            },
+            comp_mask: ffi::ibv_qp_init_attr_mask::IBV_QP_INIT_ATTR_SEND_OPS_FLAGS,
+            send_ops_flags: (IBV_QP_EX_WITH_SEND | IBV_QP_EX_WITH_RDMA_WRITE) as u64,
+            ..Default::default()
+        };
+        let qp = create_dc_qp(pd, &mut attr, ffi::mlx5dv_dc_type::MLX5DV_DCTYPE_DCI, 0)?;
+        let qpx = unsafe { ffi::ibv_qp_to_qp_ex(qp) };
+        let dci = DcInitiator {
+            _phantom: PhantomData,
+            qp,
+            qpx,
+            mqpx: unsafe { ffi::mlx5dv_qp_ex_from_ibv_qp_ex(qpx) },
+            pd: pd.pd,
+            gid_index,
+        };
+        let port_attr = pd.context().port_attr()?;
+
+        let mut attr = ffi::ibv_qp_attr {
+            qp_state: ffi::ibv_qp_state::IBV_QPS_INIT,
+            pkey_index: 0,
+            port_num: PORT_NUM,
+            ..Default::default()
+        };
+        let mask = ffi::ibv_qp_attr_mask::IBV_QP_STATE
+            | ffi::ibv_qp_attr_mask::IBV_QP_PKEY_INDEX
+            | ffi::ibv_qp_attr_mask::IBV_QP_PORT;
+        modify_qp(qp, &mut attr, mask)?;
+
+        let mut attr = ffi::ibv_qp_attr {
+            qp_state: ffi::ibv_qp_state::IBV_QPS_RTR,
+            path_mtu: port_attr.active_mtu,
+            ah_attr: ah_attr(gid_index, None),
+            ..Default::default()
+        };
+        let mask = ffi::ibv_qp_attr_mask::IBV_QP_STATE
+            | ffi::ibv_qp_attr_mask::IBV_QP_AV
+            | ffi::ibv_qp_attr_mask::IBV_QP_PATH_MTU;
+        modify_qp(qp, &mut attr, mask)?;
+
+        let mut attr = ffi::ibv_qp_attr {
+            qp_state: ffi::ibv_qp_state::IBV_QPS_RTS,
+            timeout: 14,
+            retry_cnt: 7,
+            rnr_retry: 7,
+            sq_psn: 0,
+            max_rd_atomic: 1,
+            ..Default::default()
+        };
+        let mask = ffi::ibv_qp_attr_mask::IBV_QP_STATE
+            | ffi::ibv_qp_attr_mask::IBV_QP_TIMEOUT
+            | ffi::ibv_qp_attr_mask::IBV_QP_RETRY_CNT
+            | ffi::ibv_qp_attr_mask::IBV_QP_RNR_RETRY
+            | ffi::ibv_qp_attr_mask::IBV_QP_SQ_PSN
+            | ffi::ibv_qp_attr_mask::IBV_QP_MAX_QP_RD_ATOMIC;
+        modify_qp(qp, &mut attr, mask)?;
+        Ok(dci)
+    }
+
+    /// Creates the address handle to reach `remote`.
+    pub fn create_peer(&self, remote: &DcEndpoint) -> io::Result<DcPeer<'res>> {
+        let mut attr = ah_attr(self.gid_index, Some(remote));
+        let ah = unsafe { ffi::ibv_create_ah(self.pd, &mut attr) };
+        if ah.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(DcPeer {
+            _phantom: PhantomData,
+            ah,
+            dctn: remote.dctn,
+            key: remote.key,
+        })
+    }
+
+    /// Posts a send to `peer`, received by a receive posted to its DCT.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue.
+    #[inline]
+    pub unsafe fn post_send(
+        &self,
+        peer: &DcPeer<'_>,
+        wr_id: u64,
+        buf: &[u8],
+        mr: &rdmacm::MemoryRegion<'_>,
+        flags: SendFlags,
+    ) -> io::Result<()> {
+        self.post_with(peer, wr_id, buf, mr, flags, |qpx| {
+            ffi::ibv_wr_send_real(qpx)
+        })
+    }
+
+    /// Posts an RDMA write of `buf` to `remote_addr` of `peer`.
+    ///
+    /// # Safety
+    ///
+    /// See [`DcInitiator::post_send`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_write(
+        &self,
+        peer: &DcPeer<'_>,
+        wr_id: u64,
+        buf: &[u8],
+        mr: &rdmacm::MemoryRegion<'_>,
+        flags: SendFlags,
+        remote_addr: u64,
+        rkey: u32,
+    ) -> io::Result<()> {
+        self.post_with(peer, wr_id, buf, mr, flags, |qpx| {
+            ffi::ibv_wr_rdma_write_real(qpx, rkey, remote_addr)
+        })
+    }
+
+    unsafe fn post_with<F>(
+        &self,
+        peer: &DcPeer<'_>,
+        wr_id: u64,
+        buf: &[u8],
+        mr: &rdmacm::MemoryRegion<'_>,
+        flags: SendFlags,
+        op: F,
+    ) -> io::Result<()>
+    where
+        F: FnOnce(*mut ffi::ibv_qp_ex),
+    {
+        ffi::ibv_wr_start_real(self.qpx);
+        (*self.qpx).wr_id = wr_id;
+        (*self.qpx).wr_flags = flags.0 .0;
+        op(self.qpx);
+        ffi::mlx5dv_wr_set_dc_addr_real(self.mqpx, peer.ah, peer.dctn, peer.key);
+        ffi::ibv_wr_set_sge_real(
+            self.qpx,
+            (&*mr.0).lkey,
+            buf.as_ptr() as u64,
+            buf.len() as u32,
+        );
+        let errno = ffi::ibv_wr_complete_real(self.qpx);
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        Ok(())
+    }
+}
+
+impl<'res> Drop for DcInitiator<'res> {
+    fn drop(&mut self) {
+        let errno = unsafe { ffi::ibv_destroy_qp(self.qp) };
+        if errno != 0 {
+            let e = io::Error::from_raw_os_error(errno);
+            panic!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_roundtrip() {
+        let endpoint = DcEndpoint {
+            dctn: 0x12345,
+            key: 0xdead_beef_cafe,
+            lid: 7,
+            gid: ffi::ibv_gid { raw: [3; 16] }.into(),
+        };
+        let buf = endpoint.encode();
+        assert_eq!(DcEndpoint::decode(&buf), Some(endpoint));
+        assert_eq!(
+            DcEndpoint::decode(&buf[..DcEndpoint::ENCODED_LEN - 1]),
+            None
+        );
+    }
+}
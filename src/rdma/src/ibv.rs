@@ -1,14 +1,17 @@
 use std::convert::TryInto;
 use std::ffi::CStr;
+use std::fmt;
 use std::io;
 use std::marker::PhantomData;
 use std::mem;
+use std::mem::MaybeUninit;
 use std::os::raw::c_void;
 use std::ptr;
 
-const PORT_NUM: u8 = 1;
+pub(crate) const PORT_NUM: u8 = 1;
 
 use crate::ffi;
+use crate::rdmacm;
 pub use ffi::ibv_qp_type;
 pub use ffi::ibv_wc;
 pub use ffi::ibv_wc_opcode;
@@ -207,6 +210,16 @@ impl<'devlist> Device<'devlist> {
     }
 }
 
+/// The vendor ID the SoftRoCE driver (rxe) reports.
+const RXE_VENDOR_ID: u32 = 0xff_ffff;
+/// The vendor ID the soft-iWARP driver (siw) reports, "bmt" in ASCII.
+const SIW_VENDOR_ID: u32 = 0x62_6d74;
+
+#[inline]
+fn is_software_vendor(vendor_id: u32) -> bool {
+    matches!(vendor_id, RXE_VENDOR_ID | SIW_VENDOR_ID)
+}
+
 /// An RDMA context bound to a device.
 #[repr(transparent)]
 pub struct Context {
@@ -253,6 +266,32 @@ impl Context {
         Ok(Context { ctx })
     }
 
+    /// Returns the name of the device of this context, see [`Device::name`].
+    pub fn device_name(&self) -> Option<&CStr> {
+        let name_ptr = unsafe { ffi::ibv_get_device_name((*self.ctx).device) };
+        if name_ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(name_ptr) })
+        }
+    }
+
+    /// Returns the attributes of the device of this context.
+    pub fn device_attr(&self) -> io::Result<ffi::ibv_device_attr> {
+        let mut attr = ffi::ibv_device_attr::default();
+        let errno = unsafe { ffi::ibv_query_device(self.ctx, &mut attr) };
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        Ok(attr)
+    }
+
+    /// Whether the device runs RDMA in software on an Ethernet NIC, i.e., SoftRoCE (rxe) or
+    /// soft-iWARP (siw).
+    pub fn is_software(&self) -> io::Result<bool> {
+        Ok(is_software_vendor(self.device_attr()?.vendor_id))
+    }
+
     /// Returns the port_attr for the given context.
     pub fn port_attr(&self) -> io::Result<ffi::ibv_port_attr> {
         // TODO: from http://www.rdmamojo.com/2012/07/21/ibv_query_port/
@@ -363,6 +402,110 @@ impl Context {
     }
 }
 
+impl Context {
+    /// Sets whether `get_async_event` blocks until there is an event.
+    pub fn set_async_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let fd = unsafe { &*self.ctx }.async_fd;
+        let mut flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if nonblocking {
+            flags |= libc::O_NONBLOCK;
+        } else {
+            flags &= !libc::O_NONBLOCK;
+        }
+        let rc = unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+        if rc == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Gets the next asynchronous event of the device, e.g., a QP entering the error state.
+    ///
+    /// # Errors
+    ///
+    ///  - `EAGAIN`: No event, and the context is set nonblocking.
+    pub fn get_async_event(&self) -> io::Result<AsyncEvent<'_>> {
+        let mut event = MaybeUninit::<ffi::ibv_async_event>::uninit();
+        let rc = unsafe { ffi::ibv_get_async_event(self.ctx, event.as_mut_ptr()) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(AsyncEvent {
+            _phantom: PhantomData,
+            event: unsafe { event.assume_init() },
+        })
+    }
+}
+
+/// An asynchronous event of a device. The event is acknowledged on drop.
+pub struct AsyncEvent<'ctx> {
+    _phantom: PhantomData<&'ctx ()>,
+    event: ffi::ibv_async_event,
+}
+
+unsafe impl<'ctx> Send for AsyncEvent<'ctx> {}
+
+impl<'ctx> Drop for AsyncEvent<'ctx> {
+    fn drop(&mut self) {
+        unsafe { ffi::ibv_ack_async_event(&mut self.event) };
+    }
+}
+
+impl<'ctx> fmt::Debug for AsyncEvent<'ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncEvent")
+            .field("event_type", &self.event_type())
+            .field("qp_num", &self.qp_num())
+            .field("port_num", &self.port_num())
+            .finish()
+    }
+}
+
+impl<'ctx> AsyncEvent<'ctx> {
+    #[inline]
+    pub fn event_type(&self) -> ffi::ibv_event_type::Type {
+        self.event.event_type
+    }
+
+    /// Returns the number of the QP that the event is about, for the events of a QP.
+    pub fn qp_num(&self) -> Option<u32> {
+        use ffi::ibv_event_type::*;
+        match self.event.event_type {
+            IBV_EVENT_QP_FATAL
+            | IBV_EVENT_QP_REQ_ERR
+            | IBV_EVENT_QP_ACCESS_ERR
+            | IBV_EVENT_COMM_EST
+            | IBV_EVENT_SQ_DRAINED
+            | IBV_EVENT_PATH_MIG
+            | IBV_EVENT_PATH_MIG_ERR
+            | IBV_EVENT_QP_LAST_WQE_REACHED => {
+                let qp = unsafe { self.event.element.qp };
+                assert!(!qp.is_null());
+                Some(unsafe { &*qp }.qp_num)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the port that the event is about, for the events of a port.
+    pub fn port_num(&self) -> Option<u8> {
+        use ffi::ibv_event_type::*;
+        match self.event.event_type {
+            IBV_EVENT_PORT_ACTIVE
+            | IBV_EVENT_PORT_ERR
+            | IBV_EVENT_LID_CHANGE
+            | IBV_EVENT_PKEY_CHANGE
+            | IBV_EVENT_GID_CHANGE
+            | IBV_EVENT_SM_CHANGE
+            | IBV_EVENT_CLIENT_REREGISTER => Some(unsafe { self.event.element.port_num } as u8),
+            _ => None,
+        }
+    }
+}
+
 /// Error on allocating a protection domain (PD).
 #[derive(Debug)]
 pub struct AllocPdError;
@@ -378,7 +521,7 @@ impl Drop for Context {
 #[repr(transparent)]
 pub struct CompletionQueue<'ctx> {
     _phantom: PhantomData<&'ctx ()>,
-    cq: *mut ffi::ibv_cq,
+    pub(crate) cq: *mut ffi::ibv_cq,
 }
 
 unsafe impl<'a> Send for CompletionQueue<'a> {}
@@ -1087,6 +1230,184 @@ impl<T> Drop for MemoryRegion<T> {
 #[derive(Debug, Clone, Copy)]
 pub struct AccessFlags(pub ffi::ibv_access_flags);
 
+/// An atomic operation on the 8 bytes at a remote address, beyond the compare-and-swap and the
+/// fetch-and-add the verbs define.
+///
+/// A device only does the full 8-byte operations natively. Others are done by the requester as a
+/// sequence of native compare-and-swaps, each guessing the remote value from the previous one
+/// and swapping in what the operation makes of it, until a guess is right. They are atomic with
+/// respect to other atomics on the same 8 bytes, but not to RDMA writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtAtomic {
+    /// Compares the bits of `compare_mask` with `compare` and, if equal, replaces the bits of
+    /// `swap_mask` with `swap`.
+    MaskedCompareAndSwap {
+        /// The expected value.
+        compare: u64,
+        /// The bits to compare.
+        compare_mask: u64,
+        /// The new value.
+        swap: u64,
+        /// The bits to replace.
+        swap_mask: u64,
+    },
+    /// Adds `add` to the field of `mask`, a contiguous run of bits, e.g., `0xffff_ffff` for the
+    /// low half. Carries out of the field are dropped, so fields of 1, 2, or 4 bytes are
+    /// operands of that size.
+    FetchAndAdd {
+        /// The value to add to the field.
+        add: u64,
+        /// The bits of the field.
+        mask: u64,
+    },
+}
+
+impl ExtAtomic {
+    /// Whether the masks are well formed.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            ExtAtomic::MaskedCompareAndSwap { .. } => true,
+            ExtAtomic::FetchAndAdd { mask, .. } => {
+                let field = mask.checked_shr(mask.trailing_zeros()).unwrap_or(0);
+                field != 0 && field & field.wrapping_add(1) == 0
+            }
+        }
+    }
+
+    /// Whether the operation is a native compare-and-swap or fetch-and-add of the full 8 bytes.
+    pub fn is_native(&self) -> bool {
+        match *self {
+            ExtAtomic::MaskedCompareAndSwap {
+                compare_mask,
+                swap_mask,
+                ..
+            } => compare_mask == u64::MAX && swap_mask == u64::MAX,
+            ExtAtomic::FetchAndAdd { mask, .. } => mask == u64::MAX,
+        }
+    }
+
+    /// Returns the value the operation leaves when it finds `current`, or `None` if the
+    /// comparison fails.
+    pub fn apply(&self, current: u64) -> Option<u64> {
+        match *self {
+            ExtAtomic::MaskedCompareAndSwap {
+                compare,
+                compare_mask,
+                swap,
+                swap_mask,
+            } => {
+                if (current ^ compare) & compare_mask != 0 {
+                    return None;
+                }
+                Some((current & !swap_mask) | (swap & swap_mask))
+            }
+            ExtAtomic::FetchAndAdd { add, mask } => {
+                let shift = mask.trailing_zeros();
+                let field = (current & mask) >> shift;
+                let sum = field.wrapping_add(add).checked_shl(shift).unwrap_or(0);
+                Some((current & !mask) | (sum & mask))
+            }
+        }
+    }
+
+    /// The remote value to guess first, the one the operation succeeds on if nothing else
+    /// matters.
+    pub fn first_guess(&self) -> u64 {
+        match *self {
+            ExtAtomic::MaskedCompareAndSwap {
+                compare,
+                compare_mask,
+                ..
+            } => compare & compare_mask,
+            ExtAtomic::FetchAndAdd { .. } => 0,
+        }
+    }
+}
+
+/// Returns the next rkey of a memory window, which only differs from `rkey` in the low 8 bits (the
+/// key tag), as `ibv_inc_rkey` does.
+#[inline]
+pub fn inc_rkey(rkey: u32) -> u32 {
+    const MASK: u32 = 0x0000_00ff;
+    let tag = rkey.wrapping_add(1) & MASK;
+    (rkey & !MASK) | tag
+}
+
+/// A memory window, which grants remote access to a part of a memory region under its own rkey.
+///
+/// A window is bound to a range of a memory region registered with `IBV_ACCESS_MW_BIND` by
+/// posting a bind to a queue pair. Binding it again, to an empty range for type 1 windows, or
+/// invalidating its rkey for type 2 windows revokes the access through the previous rkey, while
+/// the memory region and the rkeys of the other windows stay valid. A type 2 window is only
+/// usable by the queue pair it is bound through, so its rkey can be granted to a single peer.
+///
+/// See also [RDMAmojo's `ibv_alloc_mw` documentation][1].
+///
+/// [1]: https://www.rdmamojo.com/2016/02/16/ibv_alloc_mw/
+pub struct MemoryWindow<'pd> {
+    _phantom: PhantomData<&'pd ()>,
+    mw: *mut ffi::ibv_mw,
+}
+
+unsafe impl<'pd> Send for MemoryWindow<'pd> {}
+unsafe impl<'pd> Sync for MemoryWindow<'pd> {}
+
+#[cfg(feature = "phoenix")]
+impl<'pd> AsHandle for MemoryWindow<'pd> {
+    /// Returns the inner handle of this memory window.
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        assert!(!self.mw.is_null());
+        let mw = unsafe { &*self.mw };
+        let ctx_handle = (&mw.context).as_ref().as_handle();
+        let mw_handle = mw.handle;
+        Handle(ctx_handle.0 << 32 | mw_handle as u64)
+    }
+}
+
+impl<'pd> MemoryWindow<'pd> {
+    /// Returns the rkey the peers use to access the memory through this window.
+    ///
+    /// For a type 2 window, this is the rkey of the last bind posted through
+    /// [`QueuePair::post_bind_mw`], which takes effect once the bind completes.
+    #[inline]
+    pub fn rkey(&self) -> u32 {
+        assert!(!self.mw.is_null());
+        unsafe { &*self.mw }.rkey
+    }
+
+    /// Returns the type of this window, `IBV_MW_TYPE_1` or `IBV_MW_TYPE_2`.
+    #[inline]
+    pub fn mw_type(&self) -> ffi::ibv_mw_type::Type {
+        assert!(!self.mw.is_null());
+        unsafe { &*self.mw }.type_
+    }
+}
+
+impl<'pd> fmt::Debug for MemoryWindow<'pd> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryWindow")
+            .field("rkey", &self.rkey())
+            .field("mw_type", &self.mw_type())
+            .finish()
+    }
+}
+
+impl<'pd> Drop for MemoryWindow<'pd> {
+    fn drop(&mut self) {
+        // SAFETY: the context outlives the window
+        let errno = unsafe {
+            let ctx = (&*self.mw).context;
+            let ops = &mut (&mut *ctx).ops;
+            ops.dealloc_mw.as_mut().unwrap()(self.mw)
+        };
+        if errno != 0 {
+            let e = io::Error::from_raw_os_error(errno);
+            panic!("{}", e);
+        }
+    }
+}
+
 /// A protection domain for a device's context.
 #[repr(transparent)]
 pub struct ProtectionDomain<'ctx> {
@@ -1235,6 +1556,35 @@ impl<'ctx> ProtectionDomain<'ctx> {
             Ok(MemoryRegion { mr, data })
         }
     }
+
+    /// Allocates a memory window of `mw_type`, `IBV_MW_TYPE_1` or `IBV_MW_TYPE_2`, associated
+    /// with this `ProtectionDomain`.
+    ///
+    /// The window grants no access until it is bound to a memory region, see
+    /// [`QueuePair::bind_mw`] and [`QueuePair::post_bind_mw`].
+    ///
+    /// # Errors
+    ///
+    ///  - `EINVAL`: Invalid `mw_type`.
+    ///  - `ENOMEM`: Not enough resources to complete this operation.
+    ///  - `EOPNOTSUPP`: The device does not support memory windows.
+    pub fn alloc_mw(&self, mw_type: ffi::ibv_mw_type::Type) -> io::Result<MemoryWindow<'_>> {
+        // ibv_alloc_mw is an inline function in verbs.h
+        let ops = &mut unsafe { &mut *(&*self.pd).context }.ops;
+        let alloc_mw = match ops.alloc_mw.as_mut() {
+            Some(alloc_mw) => alloc_mw,
+            None => return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP)),
+        };
+        let mw = unsafe { alloc_mw(self.pd, mw_type) };
+        if mw.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(MemoryWindow {
+                _phantom: PhantomData,
+                mw,
+            })
+        }
+    }
 }
 
 impl<'a> Drop for ProtectionDomain<'a> {
@@ -1321,6 +1671,13 @@ impl<'res> QueuePair<'res> {
         (pd_ret, send_cq_ret, recv_cq_ret)
     }
 
+    /// Returns the number of this QP, which its work completions carry.
+    #[inline]
+    pub fn qp_num(&self) -> u32 {
+        assert!(!self.qp.is_null());
+        unsafe { &*self.qp }.qp_num
+    }
+
     /// Returns the protection domain of this QP.
     #[inline]
     pub fn pd(&self) -> &ProtectionDomain<'res> {
@@ -1344,6 +1701,41 @@ impl<'res> QueuePair<'res> {
         let cq = &unsafe { &*self.qp }.recv_cq;
         cq.as_ref()
     }
+
+    /// Queries the current attributes of this QP, e.g., its state, the path MTU, the timeouts and
+    /// the retry counts it was brought up with, and the capabilities the device granted at its
+    /// creation, which may be more than asked for.
+    ///
+    /// See also [RDMAmojo's `ibv_query_qp` documentation][1].
+    ///
+    /// [1]: https://www.rdmamojo.com/2013/01/19/ibv_query_qp/
+    pub fn query(&self) -> io::Result<(ffi::ibv_qp_attr, ffi::ibv_qp_init_attr)> {
+        assert!(!self.qp.is_null());
+        let mut attr = ffi::ibv_qp_attr::default();
+        let mut init_attr = ffi::ibv_qp_init_attr::default();
+        let mask = ffi::ibv_qp_attr_mask::IBV_QP_STATE
+            | ffi::ibv_qp_attr_mask::IBV_QP_PATH_MTU
+            | ffi::ibv_qp_attr_mask::IBV_QP_TIMEOUT
+            | ffi::ibv_qp_attr_mask::IBV_QP_RETRY_CNT
+            | ffi::ibv_qp_attr_mask::IBV_QP_RNR_RETRY
+            | ffi::ibv_qp_attr_mask::IBV_QP_MIN_RNR_TIMER
+            | ffi::ibv_qp_attr_mask::IBV_QP_MAX_QP_RD_ATOMIC
+            | ffi::ibv_qp_attr_mask::IBV_QP_MAX_DEST_RD_ATOMIC
+            | ffi::ibv_qp_attr_mask::IBV_QP_CAP;
+        let errno = unsafe { ffi::ibv_query_qp(self.qp, &mut attr, mask.0 as i32, &mut init_attr) };
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        Ok((attr, init_attr))
+    }
+}
+
+/// Returns the number of bytes of `mtu`, or `None` if it is not a valid MTU.
+pub fn mtu_bytes(mtu: ffi::ibv_mtu) -> Option<u32> {
+    match mtu {
+        1..=5 => Some(256 << (mtu - 1)),
+        _ => None,
+    }
 }
 
 impl<'res> QueuePair<'res> {
@@ -1509,6 +1901,181 @@ impl<'res> QueuePair<'res> {
     }
 }
 
+impl<'res> QueuePair<'res> {
+    /// Binds a type 1 memory window to `buf`, which must lie in `mr`, for the remote `access`,
+    /// e.g., `IBV_ACCESS_REMOTE_READ`. Returns the new rkey of the window, and the rkey it had is
+    /// no longer valid. An empty `buf` unbinds the window, which revokes the remote access through
+    /// it.
+    ///
+    /// The bind is a work request on the Send Queue of this QP. A work completion with `wr_id`
+    /// is generated if `send_flags` has `IBV_SEND_SIGNALED`, or if the QP signals all send
+    /// requests.
+    ///
+    /// See also [RDMAmojo's `ibv_bind_mw` documentation][1].
+    ///
+    /// # Errors
+    ///
+    ///  - `EINVAL`: The window is not of type 1, or `buf` is out of `mr`.
+    ///  - `EPERM`: The window and `mr` are not in the same protection domain.
+    ///  - `ENOMEM`: Send Queue is full or not enough resources to complete this operation.
+    ///
+    /// [1]: https://www.rdmamojo.com/2016/02/16/ibv_bind_mw/
+    pub fn bind_mw(
+        &self,
+        mw: &mut MemoryWindow<'_>,
+        mr: &rdmacm::MemoryRegion<'_>,
+        buf: &[u8],
+        access: ffi::ibv_access_flags,
+        wr_id: u64,
+        send_flags: ffi::ibv_send_flags,
+    ) -> io::Result<u32> {
+        assert!(!self.qp.is_null());
+        assert!(!mw.mw.is_null());
+        if mw.mw_type() != ffi::ibv_mw_type::IBV_MW_TYPE_1 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let mut mw_bind = ffi::ibv_mw_bind {
+            wr_id,
+            send_flags: send_flags.0,
+            bind_info: bind_info(mr, buf, access)?,
+        };
+        // SAFETY: the window and the memory region are valid, and the memory is only accessed by
+        // the peers with the rkey the caller grants
+        let errno = unsafe {
+            if (&*mw.mw).pd != (&*mr.0).pd {
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+            // ibv_bind_mw is an inline function in verbs.h
+            let ctx = (&*mw.mw).context;
+            let ops = &mut (&mut *ctx).ops;
+            ops.bind_mw.as_mut().unwrap()(self.qp, mw.mw, &mut mw_bind as *mut _)
+        };
+        if errno != 0 {
+            Err(io::Error::from_raw_os_error(errno))
+        } else {
+            Ok(mw.rkey())
+        }
+    }
+
+    /// Posts a work request that binds a type 2 memory window to `buf`, which must lie in `mr`,
+    /// for the remote `access`. Returns the new rkey of the window, which is only valid for the
+    /// peer of this QP once the bind completes.
+    ///
+    /// The window must be invalidated, see [`QueuePair::post_local_inv`], before it is bound
+    /// again.
+    ///
+    /// # Safety
+    ///
+    /// The memory in `buf` is accessible by the peer until the rkey is invalidated. The caller
+    /// must not reuse or drop the memory region before.
+    ///
+    /// # Errors
+    ///
+    ///  - `EINVAL`: The window is not of type 2, or `buf` is out of `mr`.
+    ///  - `ENOMEM`: Send Queue is full or not enough resources to complete this operation.
+    pub unsafe fn post_bind_mw(
+        &self,
+        mw: &mut MemoryWindow<'_>,
+        mr: &rdmacm::MemoryRegion<'_>,
+        buf: &[u8],
+        access: ffi::ibv_access_flags,
+        wr_id: u64,
+        send_flags: ffi::ibv_send_flags,
+    ) -> io::Result<u32> {
+        assert!(!self.qp.is_null());
+        assert!(!mw.mw.is_null());
+        if mw.mw_type() != ffi::ibv_mw_type::IBV_MW_TYPE_2 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let rkey = inc_rkey(mw.rkey());
+        let mut wr = ffi::ibv_send_wr {
+            wr_id,
+            next: ptr::null_mut(),
+            sg_list: ptr::null_mut(),
+            num_sge: 0,
+            opcode: ffi::ibv_wr_opcode::IBV_WR_BIND_MW,
+            send_flags: send_flags.0,
+            wr: Default::default(),
+            qp_type: Default::default(),
+            __bindgen_anon_1: Default::default(),
+            __bindgen_anon_2: Default::default(),
+        };
+        wr.__bindgen_anon_2.bind_mw.mw = mw.mw;
+        wr.__bindgen_anon_2.bind_mw.rkey = rkey;
+        wr.__bindgen_anon_2.bind_mw.bind_info = bind_info(mr, buf, access)?;
+        self.post_send_wr(&mut wr)?;
+        // the rkey of a type 2 window is chosen by the consumer
+        (&mut *mw.mw).rkey = rkey;
+        Ok(rkey)
+    }
+
+    /// Posts a work request that invalidates `rkey`, the rkey of a type 2 memory window bound
+    /// through this QP, which revokes the remote access through it. Accesses by the peer that
+    /// arrive later fail with a remote access error.
+    ///
+    /// # Errors
+    ///
+    ///  - `EINVAL`: Invalid value provided in the Work Request.
+    ///  - `ENOMEM`: Send Queue is full or not enough resources to complete this operation.
+    pub fn post_local_inv(
+        &self,
+        rkey: u32,
+        wr_id: u64,
+        send_flags: ffi::ibv_send_flags,
+    ) -> io::Result<()> {
+        assert!(!self.qp.is_null());
+        let mut wr = ffi::ibv_send_wr {
+            wr_id,
+            next: ptr::null_mut(),
+            sg_list: ptr::null_mut(),
+            num_sge: 0,
+            opcode: ffi::ibv_wr_opcode::IBV_WR_LOCAL_INV,
+            send_flags: send_flags.0,
+            wr: Default::default(),
+            qp_type: Default::default(),
+            __bindgen_anon_1: Default::default(),
+            __bindgen_anon_2: Default::default(),
+        };
+        wr.__bindgen_anon_1.invalidate_rkey = rkey;
+        // SAFETY: the work request refers to no memory
+        unsafe { self.post_send_wr(&mut wr) }
+    }
+
+    unsafe fn post_send_wr(&self, wr: &mut ffi::ibv_send_wr) -> io::Result<()> {
+        let mut bad_wr: *mut ffi::ibv_send_wr = ptr::null_mut();
+        let ctx = (&*self.qp).context;
+        let ops = &mut (&mut *ctx).ops;
+        let errno = ops.post_send.as_mut().unwrap()(self.qp, wr as *mut _, &mut bad_wr as *mut _);
+        if errno != 0 {
+            Err(io::Error::from_raw_os_error(errno))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Describes the range `buf` of `mr` a memory window is bound to.
+fn bind_info(
+    mr: &rdmacm::MemoryRegion<'_>,
+    buf: &[u8],
+    access: ffi::ibv_access_flags,
+) -> io::Result<ffi::ibv_mw_bind_info> {
+    assert!(!mr.0.is_null());
+    let region = unsafe { &*mr.0 };
+    let start = region.addr as usize;
+    let end = start + region.length as usize;
+    let addr = buf.as_ptr() as usize;
+    if !buf.is_empty() && (addr < start || addr + buf.len() > end) {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    Ok(ffi::ibv_mw_bind_info {
+        mr: mr.0,
+        addr: addr as u64,
+        length: buf.len() as u64,
+        mw_access_flags: access.0,
+    })
+}
+
 impl<'a> Drop for QueuePair<'a> {
     fn drop(&mut self) {
         // TODO: ibv_destroy_qp() fails if the QP is attached to a multicast group.
@@ -1520,6 +2087,80 @@ impl<'a> Drop for QueuePair<'a> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inc_rkey_only_changes_the_tag() {
+        assert_eq!(inc_rkey(0x1234_5600), 0x1234_5601);
+        assert_eq!(inc_rkey(0x1234_56fe), 0x1234_56ff);
+        assert_eq!(inc_rkey(0x1234_56ff), 0x1234_5600);
+        assert_eq!(inc_rkey(u32::MAX), 0xffff_ff00);
+    }
+
+    #[test]
+    fn mtu_in_bytes() {
+        assert_eq!(mtu_bytes(0), None);
+        assert_eq!(mtu_bytes(1), Some(256));
+        assert_eq!(mtu_bytes(3), Some(1024));
+        assert_eq!(mtu_bytes(5), Some(4096));
+        assert_eq!(mtu_bytes(6), None);
+    }
+
+    #[test]
+    fn software_vendors() {
+        assert!(is_software_vendor(0xff_ffff));
+        assert!(is_software_vendor(u32::from_be_bytes([
+            0, b'b', b'm', b't'
+        ])));
+        // Mellanox
+        assert!(!is_software_vendor(0x02c9));
+    }
+
+    #[test]
+    fn masked_compare_and_swap() {
+        let op = ExtAtomic::MaskedCompareAndSwap {
+            compare: 0x0000_0001_0000_0000,
+            compare_mask: 0xffff_ffff_0000_0000,
+            swap: 0x0000_0000_0000_0007,
+            swap_mask: 0x0000_0000_ffff_ffff,
+        };
+        assert!(op.is_valid() && !op.is_native());
+        assert_eq!(op.apply(0x0000_0001_1234_5678), Some(0x0000_0001_0000_0007));
+        assert_eq!(op.apply(0x0000_0002_1234_5678), None);
+        assert_eq!(op.apply(op.first_guess()), Some(0x0000_0001_0000_0007));
+    }
+
+    #[test]
+    fn fetch_and_add_stays_in_the_field() {
+        let op = ExtAtomic::FetchAndAdd {
+            add: 1,
+            mask: 0xffff_ffff_0000_0000,
+        };
+        assert!(op.is_valid());
+        assert_eq!(op.apply(0x0000_0001_ffff_ffff), Some(0x0000_0002_ffff_ffff));
+        assert_eq!(op.apply(0xffff_ffff_0000_0005), Some(0x0000_0000_0000_0005));
+        let op = ExtAtomic::FetchAndAdd {
+            add: 0x80,
+            mask: 0x0000_ff00,
+        };
+        assert_eq!(op.apply(0x1234_8012), Some(0x1234_0012));
+        let op = ExtAtomic::FetchAndAdd {
+            add: 1,
+            mask: u64::MAX,
+        };
+        assert!(op.is_native());
+        assert_eq!(op.apply(u64::MAX), Some(0));
+        assert!(!ExtAtomic::FetchAndAdd { add: 1, mask: 0 }.is_valid());
+        assert!(!ExtAtomic::FetchAndAdd {
+            add: 1,
+            mask: 0b101
+        }
+        .is_valid());
+    }
+}
+
 #[cfg(all(test, feature = "serde"))]
 mod test_serde {
     use super::*;
@@ -82,6 +82,10 @@ pub(crate) mod sliceindex;
 #[allow(clippy::needless_borrow)]
 pub mod ibv;
 
+/// Dynamically connected transport of ConnectX devices.
+#[cfg(feature = "dc")]
+pub mod dc;
+
 /// rdmacm API.
 #[allow(clippy::needless_borrow)]
 #[allow(missing_docs)]
@@ -0,0 +1,33 @@
+#include "mlx5dv_wrapper.h"
+
+int ibv_post_srq_recv_real(struct ibv_srq *srq, struct ibv_recv_wr *recv_wr,
+                           struct ibv_recv_wr **bad_recv_wr) {
+        return ibv_post_srq_recv(srq, recv_wr, bad_recv_wr);
+}
+
+void ibv_wr_start_real(struct ibv_qp_ex *qp) {
+        ibv_wr_start(qp);
+}
+
+int ibv_wr_complete_real(struct ibv_qp_ex *qp) {
+        return ibv_wr_complete(qp);
+}
+
+void ibv_wr_send_real(struct ibv_qp_ex *qp) {
+        ibv_wr_send(qp);
+}
+
+void ibv_wr_rdma_write_real(struct ibv_qp_ex *qp, uint32_t rkey,
+                            uint64_t remote_addr) {
+        ibv_wr_rdma_write(qp, rkey, remote_addr);
+}
+
+void ibv_wr_set_sge_real(struct ibv_qp_ex *qp, uint32_t lkey, uint64_t addr,
+                         uint32_t length) {
+        ibv_wr_set_sge(qp, lkey, addr, length);
+}
+
+void mlx5dv_wr_set_dc_addr_real(struct mlx5dv_qp_ex *mqp, struct ibv_ah *ah,
+                                uint32_t remote_dctn, uint64_t remote_dc_key) {
+        mlx5dv_wr_set_dc_addr(mqp, ah, remote_dctn, remote_dc_key);
+}
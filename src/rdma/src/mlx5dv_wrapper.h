@@ -0,0 +1,32 @@
+#ifndef MLX5DV_WRAPPER_H
+#define MLX5DV_WRAPPER_H
+
+#include <infiniband/mlx5dv.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+int ibv_post_srq_recv_real(struct ibv_srq* srq, struct ibv_recv_wr* recv_wr,
+                           struct ibv_recv_wr** bad_recv_wr);
+
+void ibv_wr_start_real(struct ibv_qp_ex* qp);
+
+int ibv_wr_complete_real(struct ibv_qp_ex* qp);
+
+void ibv_wr_send_real(struct ibv_qp_ex* qp);
+
+void ibv_wr_rdma_write_real(struct ibv_qp_ex* qp, uint32_t rkey,
+                            uint64_t remote_addr);
+
+void ibv_wr_set_sge_real(struct ibv_qp_ex* qp, uint32_t lkey, uint64_t addr,
+                         uint32_t length);
+
+void mlx5dv_wr_set_dc_addr_real(struct mlx5dv_qp_ex* mqp, struct ibv_ah* ah,
+                                uint32_t remote_dctn, uint64_t remote_dc_key);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif  // MLX5DV_WRAPPER_H
@@ -2,6 +2,10 @@
 
 #include <rdma/rdma_verbs.h>
 
+#ifdef PHOENIX_DC
+#include "mlx5dv_wrapper.h"
+#endif
+
 #ifdef __cplusplus
 extern "C" {
 #endif
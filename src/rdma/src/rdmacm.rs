@@ -25,6 +25,9 @@ use crate::ffi;
 use crate::ibv;
 use crate::net::IntoInner;
 
+/// The timeout of [`CmId::resolve_addr`].
+pub const RESOLVE_ADDR_TIMEOUT_MS: i32 = 1500;
+
 #[derive(Debug, Clone, Copy)]
 pub struct AddrInfoHints {
     pub(crate) flags: i32,
@@ -367,6 +370,20 @@ impl CmEvent {
         }
     }
 
+    /// Returns the private data the peer sent along, only valid for the connection events.
+    #[inline]
+    pub fn private_data(&self) -> &[u8] {
+        assert!(!self.0.is_null());
+        let conn = unsafe { &(*self.0).param.conn };
+        if conn.private_data.is_null() {
+            &[]
+        } else {
+            unsafe {
+                slice::from_raw_parts(conn.private_data.cast(), conn.private_data_len as usize)
+            }
+        }
+    }
+
     /// Returns a reference to the assocated rdma_cm_id.
     #[inline]
     pub fn id<'a>(&self) -> &'a CmId<'a> {
@@ -497,6 +514,15 @@ impl<'a> AsHandle for MemoryRegion<'a> {
 }
 
 impl<'a> MemoryRegion<'a> {
+    /// # Safety
+    ///
+    /// `mr` must be a valid ibv_mr, and must outlive the returned object.
+    #[inline]
+    pub unsafe fn from_raw(mr: *mut ffi::ibv_mr) -> Self {
+        assert!(!mr.is_null());
+        Self(mr, PhantomData)
+    }
+
     #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn new_on_demand_paging(pd: *mut ffi::ibv_pd) -> io::Result<Self> {
         let access = ffi::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
@@ -716,10 +742,23 @@ impl<'res> CmId<'res> {
     }
 
     pub fn resolve_addr(&self, sockaddr: &SocketAddr) -> io::Result<()> {
+        self.resolve_addr_from(None, sockaddr, RESOLVE_ADDR_TIMEOUT_MS)
+    }
+
+    /// Resolves `sockaddr` from the local address `src`, which binds the `CmId` to the device and
+    /// port of `src`. Any local address is used if `src` is `None`.
+    pub fn resolve_addr_from(
+        &self,
+        src: Option<&SocketAddr>,
+        sockaddr: &SocketAddr,
+        timeout_ms: i32,
+    ) -> io::Result<()> {
         let id = self.0;
-        let src_addr = ptr::null_mut();
+        let mut src_addr = src.map(|src| src.into_inner().0);
+        let src_addr = src_addr
+            .as_mut()
+            .map_or(ptr::null_mut(), |src_addr| src_addr.as_mut_ptr());
         let (mut dst_addr, _socklen) = sockaddr.into_inner();
-        let timeout_ms = 1500;
 
         let rc = unsafe { ffi::rdma_resolve_addr(id, src_addr, dst_addr.as_mut_ptr(), timeout_ms) };
         if rc != 0 {
@@ -832,6 +871,16 @@ impl<'res> CmId<'res> {
         Ok(MemoryRegion(mr, PhantomData))
     }
 
+    /// Rejects a connect request.
+    pub fn reject(&self) -> io::Result<()> {
+        let id = self.0;
+        let rc = unsafe { ffi::rdma_reject(id, ptr::null(), 0) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
     pub fn disconnect(&self) -> io::Result<()> {
         let id = self.0;
         let rc = unsafe { ffi::rdma_disconnect(id) };
@@ -1038,6 +1087,129 @@ impl<'res> CmId<'res> {
         Ok(())
     }
 
+    /// Posts an `IBV_WR_ATOMIC_CMP_AND_SWP`. The 8 bytes at `remote_addr` are compared with
+    /// `compare` and replaced by `swap` if equal, the original value is written to `buf`.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `CompletionQueue::poll` returns a completion for this send).
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_atomic_cmp_and_swp<'a>(
+        &self,
+        wr_id: u64,
+        buf: &mut [u8],
+        mr: &MemoryRegion<'a>,
+        flags: ffi::ibv_send_flags,
+        remote_addr: u64,
+        rkey: u32,
+        compare: u64,
+        swap: u64,
+    ) -> io::Result<()> {
+        self.post_atomic(
+            wr_id,
+            buf,
+            mr,
+            flags,
+            ffi::ibv_wr_opcode::IBV_WR_ATOMIC_CMP_AND_SWP,
+            remote_addr,
+            rkey,
+            compare,
+            swap,
+        )
+    }
+
+    /// Posts an `IBV_WR_ATOMIC_FETCH_AND_ADD`. `add` is added to the 8 bytes at `remote_addr`,
+    /// wrapping around, and the original value is written to `buf`.
+    ///
+    /// # Safety
+    ///
+    /// See [`CmId::post_atomic_cmp_and_swp`].
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_atomic_fetch_and_add<'a>(
+        &self,
+        wr_id: u64,
+        buf: &mut [u8],
+        mr: &MemoryRegion<'a>,
+        flags: ffi::ibv_send_flags,
+        remote_addr: u64,
+        rkey: u32,
+        add: u64,
+    ) -> io::Result<()> {
+        self.post_atomic(
+            wr_id,
+            buf,
+            mr,
+            flags,
+            ffi::ibv_wr_opcode::IBV_WR_ATOMIC_FETCH_AND_ADD,
+            remote_addr,
+            rkey,
+            add,
+            0,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn post_atomic<'a>(
+        &self,
+        wr_id: u64,
+        buf: &mut [u8],
+        mr: &MemoryRegion<'a>,
+        flags: ffi::ibv_send_flags,
+        opcode: ffi::ibv_wr_opcode::Type,
+        remote_addr: u64,
+        rkey: u32,
+        compare_add: u64,
+        swap: u64,
+    ) -> io::Result<()> {
+        let qp = (&*self.0).qp;
+        let addr = buf.as_ptr();
+        let length = buf.len();
+        assert_eq!(length, mem::size_of::<u64>());
+
+        let mr = mr.0;
+        assert!(!mr.is_null());
+        assert!(
+            (&*mr).addr as *const _ <= addr
+                && addr.add(length) <= (&*mr).addr.add((&*mr).length as usize) as *const _
+        );
+        let mut sge = ffi::ibv_sge {
+            addr: addr as u64,
+            length: length as u32,
+            lkey: (&*mr).lkey,
+        };
+        let mut wr = ffi::ibv_send_wr {
+            wr_id,
+            next: ptr::null_mut(),
+            sg_list: &mut sge as *mut _,
+            num_sge: 1,
+            opcode,
+            send_flags: flags.0,
+            __bindgen_anon_1: Default::default(),
+            wr: ffi::ibv_send_wr__bindgen_ty_2 {
+                atomic: ffi::ibv_send_wr__bindgen_ty_2__bindgen_ty_2 {
+                    remote_addr,
+                    compare_add,
+                    swap,
+                    rkey,
+                },
+            },
+            qp_type: Default::default(),
+            __bindgen_anon_2: Default::default(),
+        };
+        let mut bad_wr = ptr::null_mut();
+        let ctx = (&*self.0).verbs;
+        let ops = &mut (&mut *ctx).ops;
+        let rc = ops.post_send.as_mut().unwrap()(qp, &mut wr, &mut bad_wr);
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn get_send_comp(&self) -> io::Result<ffi::ibv_wc> {
         let id = self.0;
@@ -1072,6 +1244,17 @@ impl<'res> CmId<'res> {
         unsafe { ffi::rdma_get_dst_port(id) }
     }
 
+    /// Returns the device and the port the `CmId` is bound to, after its address is resolved or
+    /// it is bound to the address of a device.
+    #[inline]
+    pub fn port(&self) -> Option<(&ibv::Context, u8)> {
+        let id = unsafe { &*self.0 };
+        if id.verbs.is_null() {
+            return None;
+        }
+        Some((AsRef::<ibv::Context>::as_ref(&id.verbs), id.port_num))
+    }
+
     #[inline]
     pub fn get_local_addr(&self) -> SocketAddr {
         let id = self.0;
@@ -1,34 +1,100 @@
+use std::cell::{Ref, RefCell};
 use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
 
 use thiserror::Error;
 
 use ipc::service::ShmService;
 use phoenix_api::engine::SchedulingHint;
+use phoenix_api::salloc::control_plane::Setting;
 use phoenix_api::salloc::{cmd, dp};
 
 use phoenix_syscalls::{PHOENIX_CONTROL_SOCK, PHOENIX_PREFIX};
 
+/// Returns the salloc [`Setting`] of the current thread.
+pub fn current_setting() -> Setting {
+    SETTING.with_borrow(|s| *s)
+}
+
+/// Update the salloc [`Setting`] of the current thread, e.g., what to do when the heap is full.
+///
+/// # Note
+///
+/// Each thread has its own subscription to the backend. This API must be called before the
+/// thread allocates any shared memory to make it effective.
+pub fn set_setting(setting: &Setting) {
+    SETTING.with_borrow_mut(|s| *s = *setting);
+}
+
 thread_local! {
+    static SETTING: RefCell<Setting> = RefCell::new(Setting::default());
     /// Initialization is dynamically performed on the first call to with within a thread.
     #[doc(hidden)]
-    pub static SA_CTX: SAContext = SAContext::register().expect("phoenix salloc register failed");
+    pub static SA_CTX: SAContext = SAContext::register(current_setting()).expect("phoenix salloc register failed");
 }
 
+type SallocService =
+    ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>;
+
+/// The pid of the backend the shared memory heap was obtained from.
+static BACKEND_PID: AtomicI32 = AtomicI32::new(0);
+
 pub struct SAContext {
-    pub(crate) service:
-        ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>,
+    pub(crate) setting: Setting,
+    service: RefCell<SallocService>,
 }
 
 impl SAContext {
-    fn register() -> Result<SAContext, Error> {
+    fn register(setting: Setting) -> Result<SAContext, Error> {
+        let service = Self::register_service(&setting)?;
+        if let Some(pid) = service.peer_pid() {
+            let _ = BACKEND_PID.compare_exchange(0, pid, Ordering::AcqRel, Ordering::Acquire);
+        }
+        Ok(Self {
+            setting,
+            service: RefCell::new(service),
+        })
+    }
+
+    fn register_service(setting: &Setting) -> Result<SallocService, Error> {
+        let setting_str = serde_json::to_string(setting)?;
         let service = ShmService::register(
             &*PHOENIX_PREFIX,
             &*PHOENIX_CONTROL_SOCK,
             "Salloc".to_string(),
             SchedulingHint::default(),
-            None,
+            Some(&setting_str),
         )?;
-        Ok(Self { service })
+        Ok(service)
+    }
+
+    #[inline]
+    pub(crate) fn service(&self) -> Ref<'_, SallocService> {
+        self.service.borrow()
+    }
+
+    /// Registers with the backend again if it has exited, e.g., phoenixd has restarted.
+    ///
+    /// The new backend knows nothing about the shared memory obtained from the old one, so the
+    /// heap of the thread is abandoned: the memory stays mapped and the objects on it remain
+    /// valid, but it is not reused for new objects. Use [`is_backed`] to tell whether an object
+    /// can still be sent to the backend.
+    ///
+    /// [`is_backed`]: crate::wheap::is_backed
+    pub fn reconnect(&self) -> Result<(), Error> {
+        if self.service().is_peer_alive() {
+            return Ok(());
+        }
+        let service = Self::register_service(&self.setting)?;
+        let old_pid = self.service().peer_pid().unwrap_or(0);
+        let new_pid = service.peer_pid().unwrap_or(0);
+        *self.service.borrow_mut() = service;
+        // the heap is shared by the threads, only the first thread to notice abandons it
+        let first = BACKEND_PID
+            .compare_exchange(old_pid, new_pid, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        crate::wheap::abandon_heap(first);
+        Ok(())
     }
 }
 
@@ -40,4 +106,14 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("Interface error {0}: {1}")]
     Interface(&'static str, phoenix_api::Error),
+    #[error("Serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+impl Error {
+    /// Whether the backend failed the request because the heap of the process is full.
+    #[inline]
+    pub fn is_heap_full(&self) -> bool {
+        matches!(self, Error::Interface(_, e) if e.code() == phoenix_api::ErrorCode::ResourceExhausted)
+    }
 }
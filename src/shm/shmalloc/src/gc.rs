@@ -1,12 +1,21 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use lazy_static::lazy_static;
 
 use slabmalloc::GLOBAL_PAGE_POOL;
 
+use crate::wheap::SHARED_HEAP_REGIONS;
+
 lazy_static! {
     pub(crate) static ref PAGE_RECLAIMER_CTX: PageReclaimerContext =
         PageReclaimerContext::initialize();
 }
 
+// Pages pre-allocated by the warm-up, the reclaimer keeps at least these many in the pool.
+pub(crate) static WARM_SMALL_PAGES: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static WARM_LARGE_PAGES: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static WARM_HUGE_PAGES: AtomicUsize = AtomicUsize::new(0);
+
 pub(crate) struct PageReclaimerContext;
 
 impl PageReclaimerContext {
@@ -20,17 +29,20 @@ impl PageReclaimerContext {
 
     async fn reclaim_task() {
         loop {
+            let warm = WARM_SMALL_PAGES.load(Ordering::Relaxed);
             GLOBAL_PAGE_POOL.release_small_pages(
-                Self::SMALL_PAGE_RELEASE_THRESHOLD,
-                Self::SMALL_PAGE_RELEASE_RESERVE,
+                Self::SMALL_PAGE_RELEASE_THRESHOLD.max(warm),
+                Self::SMALL_PAGE_RELEASE_RESERVE.max(warm),
             );
+            let warm = WARM_LARGE_PAGES.load(Ordering::Relaxed);
             GLOBAL_PAGE_POOL.release_large_pages(
-                Self::LARGE_PAGE_RELEASE_THRESHOLD,
-                Self::LARGE_PAGE_RELEASE_RESERVE,
+                Self::LARGE_PAGE_RELEASE_THRESHOLD.max(warm),
+                Self::LARGE_PAGE_RELEASE_RESERVE.max(warm),
             );
+            let warm = WARM_HUGE_PAGES.load(Ordering::Relaxed);
             GLOBAL_PAGE_POOL.release_huge_pages(
-                Self::HUGE_PAGE_RELEASE_THRESHOLD,
-                Self::HUGE_PAGE_RELEASE_RESERVE,
+                Self::HUGE_PAGE_RELEASE_THRESHOLD.max(warm),
+                Self::HUGE_PAGE_RELEASE_RESERVE.max(warm),
             );
             smol::Timer::after(std::time::Duration::from_millis(Self::RELEASE_INTERVAL_MS)).await;
         }
@@ -43,3 +55,27 @@ impl PageReclaimerContext {
         PageReclaimerContext
     }
 }
+
+/// Returns all the empty pages in the global pool to the backend, used when the heap is full.
+/// Returns the number of bytes released.
+pub(crate) fn reclaim_empty_pages() -> usize {
+    let mut addrs = Vec::new();
+    if let Some(pages) = GLOBAL_PAGE_POOL.release_small_pages(0, 0) {
+        addrs.extend(pages.iter().map(|p| p.as_ptr().addr()));
+    }
+    if let Some(pages) = GLOBAL_PAGE_POOL.release_large_pages(0, 0) {
+        addrs.extend(pages.iter().map(|p| p.as_ptr().addr()));
+    }
+    if let Some(pages) = GLOBAL_PAGE_POOL.release_huge_pages(0, 0) {
+        addrs.extend(pages.iter().map(|p| p.as_ptr().addr()));
+    }
+
+    let regions: Vec<_> = {
+        let mut guard = SHARED_HEAP_REGIONS.lock();
+        addrs.iter().filter_map(|addr| guard.remove(addr)).collect()
+    };
+    let nbytes = regions.iter().map(|r| r.len()).sum();
+    // dropping a region deallocates it in the backend
+    drop(regions);
+    nbytes
+}
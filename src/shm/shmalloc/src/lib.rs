@@ -1,8 +1,11 @@
 #![feature(allocator_api)]
 #![feature(strict_provenance)]
+#![feature(ptr_internals)]
+#![feature(local_key_cell_methods)]
+#![feature(int_roundings)]
 
 pub mod wheap;
-pub use wheap::SharedHeapAllocator;
+pub use wheap::{is_backed, is_local_heap, use_local_heap, warm_up, SharedHeapAllocator};
 
 pub mod backend;
 pub(crate) mod gc;
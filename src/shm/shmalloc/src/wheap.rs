@@ -5,17 +5,21 @@ use std::io;
 use std::mem;
 use std::num::NonZeroUsize;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // use fnv::FnvHashMap as HashMap;
 use lazy_static::lazy_static;
-use memfd::Memfd;
+use memfd::{Memfd, MemfdOptions};
 use slabmalloc::GLOBAL_PAGE_POOL;
 use slabmalloc::{AllocablePage, HugeObjectPage, LargeObjectPage, ObjectPage, ZoneAllocator};
 
 use phoenix_api::salloc::cmd;
+use phoenix_api::salloc::control_plane::{HeapFullPolicy, HeapWarmup};
 use shm::ptr::ShmNonNull;
+use shm::region::AddressMediator;
 
 use super::backend::{Error, SA_CTX};
+use super::gc::{WARM_HUGE_PAGES, WARM_LARGE_PAGES, WARM_SMALL_PAGES};
 use region::WriteRegion;
 use shm::alloc::ShmAllocator;
 
@@ -43,30 +47,67 @@ impl WriteHeap {
     }
 
     fn allocate_shm(&self, len: usize) -> Result<WriteRegion, Error> {
+        match Self::request_shm(len) {
+            Err(e)
+                if e.is_heap_full()
+                    && SA_CTX.with(|ctx| ctx.setting.heap_full_policy)
+                        == HeapFullPolicy::Reclaim =>
+            {
+                // give the cached empty pages back and try again
+                if super::gc::reclaim_empty_pages() > 0 {
+                    Self::request_shm(len)
+                } else {
+                    Err(e)
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn request_shm(len: usize) -> Result<WriteRegion, Error> {
         assert!(len > 0);
+        if LOCAL_HEAP.load(Ordering::Acquire) {
+            return Self::create_local_shm(len);
+        }
         SA_CTX.with(|ctx| {
             // TODO(cjr): use a correct align
             let align = len;
             let req = cmd::Command::AllocShm(len, align);
-            ctx.service.send_cmd(req)?;
-            let fds = ctx.service.recv_fd()?;
+            ctx.service().send_cmd(req)?;
+
+            // The fd is only sent on success, so check the completion first.
+            let (remote_addr, file_off, generation) = match ctx.service().recv_comp()?.0 {
+                Ok(cmd::CompletionKind::AllocShm(remote_addr, file_off, generation)) => {
+                    (remote_addr, file_off, generation)
+                }
+                Err(e) => return Err(Error::Interface("AllocShm", e)),
+                otherwise => panic!("Expect AllocShm, found {:?}", otherwise),
+            };
 
+            let fds = ctx.service().recv_fd()?;
             assert_eq!(fds.len(), 1);
 
             let memfd = Memfd::try_from_fd(fds[0]).map_err(|_| io::Error::last_os_error())?;
             let file_len = memfd.as_file().metadata()?.len() as usize;
             assert!(file_len >= len);
 
-            match ctx.service.recv_comp().unwrap().0 {
-                Ok(cmd::CompletionKind::AllocShm(remote_addr, file_off)) => {
-                    Ok(WriteRegion::new(remote_addr, len, align, file_off, memfd).unwrap())
-                }
-                Err(e) => Err(Error::Interface("AllocShm", e)),
-                otherwise => panic!("Expect AllocShm, found {:?}", otherwise),
-            }
+            Ok(WriteRegion::new(remote_addr, generation, len, align, file_off, memfd).unwrap())
         })
     }
 
+    fn create_local_shm(len: usize) -> Result<WriteRegion, Error> {
+        let align = len;
+        let memfd = MemfdOptions::default()
+            .close_on_exec(true)
+            .create(format!("local-heap-{}", len))
+            .map_err(|_| io::Error::last_os_error())?;
+        memfd.as_file().set_len(len as u64)?;
+
+        let layout = Layout::from_size_align(len, align).unwrap();
+        let addr = LOCAL_ADDRESS_MEDIATOR.allocate(layout);
+        WriteRegion::new_local(addr, len, align, memfd)
+    }
+
     #[inline]
     fn allocate_huge_page(&mut self) -> Option<&'static mut HugeObjectPage<'static>> {
         // take from global pool first
@@ -147,6 +188,111 @@ impl WriteHeap {
     }
 }
 
+/// Allocates a region of `len` bytes and touches each page of it, returns its address.
+fn prefault_shm(len: usize) -> Result<usize, Error> {
+    let sr = WriteHeap::request_shm(len)?;
+    let addr = sr.as_ptr().addr();
+    for off in (0..len).step_by(ObjectPage::SIZE) {
+        // SAFETY: the region is mapped, writable and not handed out yet.
+        unsafe { sr.as_mut_ptr().add(off).write_volatile(0) };
+    }
+    SHARED_HEAP_REGIONS
+        .lock()
+        .insert(addr, sr)
+        .ok_or(())
+        .unwrap_err();
+    Ok(addr)
+}
+
+/// Pre-faults pages of type `P` for `size` bytes. Returns the pages allocated even on failure.
+fn prefault_pages<P: AllocablePage + 'static>(
+    size: usize,
+) -> (Vec<&'static mut P>, Result<(), Error>) {
+    let n = size.div_ceil(P::SIZE);
+    let mut pages = Vec::with_capacity(n);
+    for _ in 0..n {
+        match prefault_shm(P::SIZE) {
+            Ok(addr) => pages.push(unsafe { &mut *(addr as *mut P) }),
+            Err(e) => return (pages, Err(e)),
+        }
+    }
+    (pages, Ok(()))
+}
+
+static WARMED_UP: AtomicBool = AtomicBool::new(false);
+
+static LOCAL_HEAP: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref LOCAL_ADDRESS_MEDIATOR: AddressMediator = AddressMediator::new();
+}
+
+/// Backs the heap with shared memory the process creates itself instead of obtaining it from the
+/// backend, so that messages can be allocated without phoenixd, e.g., in unit tests. Such messages
+/// cannot be sent to a backend. This must be called before the process allocates anything on the
+/// heap to make it effective.
+pub fn use_local_heap() {
+    LOCAL_HEAP.store(true, Ordering::Release);
+}
+
+/// Returns true if the heap is backed by shared memory of the process itself, see
+/// [`use_local_heap`].
+pub fn is_local_heap() -> bool {
+    LOCAL_HEAP.load(Ordering::Acquire)
+}
+
+/// Pre-allocates the heap pages given by `warmup` into the global page pool, so that the first
+/// allocations do not pay for creating and mapping the shared memory, nor for the page faults.
+/// The pages are kept in the pool thereafter. Only the first call in the process takes effect.
+///
+/// The RDMA transport uses an on-demand paging memory region over the whole address space, so
+/// there is nothing to register here.
+pub fn warm_up(warmup: &HeapWarmup) -> Result<(), Error> {
+    if warmup.is_empty() || WARMED_UP.swap(true, Ordering::AcqRel) {
+        return Ok(());
+    }
+
+    let (pages, small) = prefault_pages::<ObjectPage<'static>>(warmup.small);
+    WARM_SMALL_PAGES.fetch_add(pages.len(), Ordering::Relaxed);
+    GLOBAL_PAGE_POOL.recycle_small_pages(pages, None, true);
+    small?;
+
+    let (pages, large) = prefault_pages::<LargeObjectPage<'static>>(warmup.large);
+    WARM_LARGE_PAGES.fetch_add(pages.len(), Ordering::Relaxed);
+    GLOBAL_PAGE_POOL.recycle_large_pages(pages, None, true);
+    large?;
+
+    let (pages, huge) = prefault_pages::<HugeObjectPage<'static>>(warmup.huge);
+    WARM_HUGE_PAGES.fetch_add(pages.len(), Ordering::Relaxed);
+    GLOBAL_PAGE_POOL.recycle_huge_pages(pages, None, true);
+    huge
+}
+
+/// Stops allocating from the shared memory obtained from a backend that has exited. The pages of
+/// the thread are dropped without being freed. With `global`, so are the pages cached for the
+/// whole process and the regions, which are never unmapped.
+pub(crate) fn abandon_heap(global: bool) {
+    TL_SHARED_HEAP.with(|shared_heap| {
+        let old = mem::replace(&mut *shared_heap.borrow_mut(), WriteHeap::new());
+        mem::forget(old);
+    });
+    if global {
+        GLOBAL_PAGE_POOL.clear();
+        // dropping a region would deallocate it in the new backend, which never allocated it
+        mem::forget(mem::take(&mut *SHARED_HEAP_REGIONS.lock()));
+    }
+}
+
+/// Returns true if `addr` is on the shared memory obtained from the current backend, i.e., an
+/// object at `addr` can be sent to the backend.
+pub fn is_backed(addr: usize) -> bool {
+    let guard = SHARED_HEAP_REGIONS.lock();
+    match guard.range(..=addr).next_back() {
+        Some((start, region)) => addr < start + region.len(),
+        None => false,
+    }
+}
+
 impl Default for WriteHeap {
     fn default() -> Self {
         WriteHeap::new()
@@ -365,8 +511,13 @@ mod region {
     pub(crate) struct WriteRegion {
         mmap: MmapFixed,
         remote_addr: usize,
+        // the generation of the region in the backend, it tells this region from the regions
+        // allocated at the same address once this one is deallocated
+        generation: u64,
         align: usize,
         _memfd: Memfd,
+        /// Created by the process itself, unknown to the backend.
+        local: bool,
     }
 
     impl Deref for WriteRegion {
@@ -384,12 +535,15 @@ mod region {
 
     impl Drop for WriteRegion {
         fn drop(&mut self) {
+            if self.local {
+                return;
+            }
             (|| {
                 SA_CTX.with(|ctx| {
-                    let req = Command::DeallocShm(self.remote_addr);
-                    ctx.service.send_cmd(req)?;
+                    let req = Command::DeallocShm(self.remote_addr, self.generation);
+                    ctx.service().send_cmd(req)?;
                     // TODO(wyj): do we really need to wait for completion here?
-                    rx_recv_impl!(ctx.service, CompletionKind::DeallocShm)
+                    rx_recv_impl!(ctx.service(), CompletionKind::DeallocShm)
                 })
             })()
             .unwrap_or_else(|e| eprintln!("Dropping WriteRegion: {}", e));
@@ -399,6 +553,7 @@ mod region {
     impl WriteRegion {
         pub(crate) fn new(
             remote_addr: usize,
+            generation: u64,
             nbytes: usize,
             align: usize,
             file_off: i64,
@@ -412,8 +567,28 @@ mod region {
             Ok(WriteRegion {
                 mmap,
                 remote_addr,
+                generation,
+                align,
+                _memfd: memfd,
+                local: false,
+            })
+        }
+
+        /// Maps a region the process has created itself at `addr`.
+        pub(crate) fn new_local(
+            addr: usize,
+            nbytes: usize,
+            align: usize,
+            memfd: Memfd,
+        ) -> Result<Self, Error> {
+            let mmap = MmapFixed::new(addr, nbytes, 0, memfd.as_file())?;
+            Ok(WriteRegion {
+                mmap,
+                remote_addr: addr,
+                generation: 0,
                 align,
                 _memfd: memfd,
+                local: true,
             })
         }
 
@@ -3,6 +3,7 @@
 
 // boxed.rs & shmptr.rs
 #![feature(strict_provenance)]
+#![feature(int_roundings)]
 #![feature(allocator_api)]
 #![feature(negative_impls)]
 #![feature(peer_credentials_unix_socket)]
@@ -66,6 +67,12 @@ pub mod vec;
 /// [`alloc::collections`]: https://doc.rust-lang.org/nightly/alloc/collections/index.html
 pub mod collections;
 
+/// Shared memory regions, their registrations with the transports, and the buffers in them.
+pub mod region;
+
 /// Shared-memory version of [`std::string::String`].
 #[allow(clippy::partialeq_ne_impl)]
 pub mod string;
+
+#[cfg(feature = "serde")]
+mod serde_impls;
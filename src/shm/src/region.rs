@@ -0,0 +1,458 @@
+//! Regions of shared memory, and the slices of them handed out as buffers.
+//!
+//! A [`ShmRegion`] is a memfd mapped into the backend. The application maps the same file, at
+//! the same address under the single-address-space approach of [`AddressMediator`], or at the
+//! address it reports otherwise, which is kept as the application's view of the region. A
+//! transport that registers the region with a device records the [`Registration`] on it. A
+//! [`BufferSlice`] is a segment of a region, holding a reference to it.
+//!
+//! With the `sanitize` feature, a region is a heap allocation instead, which Miri and the
+//! sanitizers know about, and the application is simulated to map it at
+//! [`SIMULATED_APP_OFFSET`] bytes from the backend, see [`ShmRegion::simulated_app_view`]. The
+//! marshalling and the engines then run as usual in a test, and a backend that reads an address
+//! of the application in place of its own reads memory that is not allocated, which the tools
+//! report. Such a region has no memfd to hand to an application.
+use std::alloc::Layout;
+use std::io;
+use std::ops::{Deref, DerefMut};
+#[cfg(not(feature = "sanitize"))]
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use memfd::Memfd;
+#[cfg(not(feature = "sanitize"))]
+use memfd::MemfdOptions;
+#[cfg(not(feature = "sanitize"))]
+use mmap::MmapFixed;
+use thiserror::Error;
+
+use phoenix_api::{AsHandle, Handle};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Memfd: {0}.")]
+    Memfd(#[from] memfd::Error),
+    #[error("IO: {0}.")]
+    Io(#[from] io::Error),
+}
+
+/// The registration of a region with a transport, e.g., as an RDMA memory region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registration {
+    /// The handle the transport knows the registration by.
+    pub handle: Handle,
+    pub lkey: u32,
+    pub rkey: u32,
+}
+
+/// A region, or a part of it, as the application sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionView {
+    pub ptr: usize,
+    pub len: usize,
+    pub align: usize,
+}
+
+/// How far from the backend the application is simulated to map the regions with the `sanitize`
+/// feature, far enough for an address of one to not fall in an allocation of the other.
+pub const SIMULATED_APP_OFFSET: usize = 0x1000_0000_0000;
+
+#[derive(Debug)]
+pub struct ShmRegion {
+    #[cfg(not(feature = "sanitize"))]
+    mmap: MmapFixed,
+    #[cfg(not(feature = "sanitize"))]
+    memfd: Memfd,
+    #[cfg(feature = "sanitize")]
+    mmap: heap::HeapRegion,
+    align: usize,
+    file_off: usize,
+    /// Where the application has mapped the region, 0 until it tells.
+    app_addr: AtomicUsize,
+    registration: spin::Mutex<Option<Registration>>,
+}
+
+impl Deref for ShmRegion {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.mmap[..]
+    }
+}
+
+impl DerefMut for ShmRegion {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.mmap[..]
+    }
+}
+
+impl AsHandle for ShmRegion {
+    #[cfg(not(feature = "sanitize"))]
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        Handle(self.memfd.as_raw_fd() as _)
+    }
+
+    #[cfg(feature = "sanitize")]
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        Handle(self.mmap.id() as _)
+    }
+}
+
+impl AsRef<ShmRegion> for ShmRegion {
+    fn as_ref(&self) -> &ShmRegion {
+        self
+    }
+}
+
+impl ShmRegion {
+    #[cfg(not(feature = "sanitize"))]
+    pub fn new(layout: Layout, addr_mediator: &AddressMediator) -> Result<Self, Error> {
+        let nbytes = layout.size();
+        let align = layout.align().max(page_size());
+        let hugetlb_size = None;
+
+        let opts = MemfdOptions::default()
+            .allow_sealing(true)
+            .close_on_exec(false)
+            .hugetlb(hugetlb_size);
+
+        let name = format!("shared-mr-{}", nbytes);
+        let memfd = opts.create(name)?;
+        memfd.as_file().set_len(nbytes as u64)?;
+
+        let target_addr = addr_mediator.allocate(layout);
+        let mmap = MmapFixed::new(target_addr, nbytes, 0, memfd.as_file())?;
+        Ok(Self {
+            mmap,
+            memfd,
+            align,
+            file_off: 0,
+            app_addr: AtomicUsize::new(0),
+            registration: spin::Mutex::new(None),
+        })
+    }
+
+    /// Allocates the region on the heap, wherever the allocator puts it.
+    #[cfg(feature = "sanitize")]
+    pub fn new(layout: Layout, _addr_mediator: &AddressMediator) -> Result<Self, Error> {
+        let align = layout.align().max(page_size());
+        let layout = Layout::from_size_align(layout.size(), align)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self {
+            mmap: heap::HeapRegion::new(layout)?,
+            align,
+            file_off: 0,
+            app_addr: AtomicUsize::new(0),
+            registration: spin::Mutex::new(None),
+        })
+    }
+
+    #[cfg(not(feature = "sanitize"))]
+    #[inline]
+    pub fn memfd(&self) -> &Memfd {
+        &self.memfd
+    }
+
+    /// # Panics
+    ///
+    /// Always panics, a region on the heap has no memfd to hand to an application.
+    #[cfg(feature = "sanitize")]
+    pub fn memfd(&self) -> &Memfd {
+        panic!("a region allocated with the `sanitize` feature has no memfd")
+    }
+
+    /// Simulates that the application has mapped the region at [`SIMULATED_APP_OFFSET`] bytes
+    /// from the backend, and returns its view of it.
+    #[cfg(feature = "sanitize")]
+    pub fn simulated_app_view(&self) -> RegionView {
+        self.map_app(self.addr() + SIMULATED_APP_OFFSET)
+    }
+
+    #[inline]
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    #[inline]
+    pub fn file_off(&self) -> usize {
+        self.file_off
+    }
+
+    /// The address of the region in the backend.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.mmap.as_ptr().expose_addr()
+    }
+
+    /// Records where the application has mapped the region, and returns its view of it.
+    pub fn map_app(&self, app_addr: usize) -> RegionView {
+        self.app_addr.store(app_addr, Ordering::Release);
+        RegionView {
+            ptr: app_addr,
+            len: self.len(),
+            align: self.align,
+        }
+    }
+
+    /// The application's view of the region, once it has mapped it.
+    pub fn app_view(&self) -> Option<RegionView> {
+        match self.app_addr.load(Ordering::Acquire) {
+            0 => None,
+            ptr => Some(RegionView {
+                ptr,
+                len: self.len(),
+                align: self.align,
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn registration(&self) -> Option<Registration> {
+        *self.registration.lock()
+    }
+
+    /// Records that a transport has registered the region.
+    #[inline]
+    pub fn set_registration(&self, registration: Registration) {
+        *self.registration.lock() = Some(registration);
+    }
+
+    #[inline]
+    pub fn clear_registration(&self) -> Option<Registration> {
+        self.registration.lock().take()
+    }
+}
+
+/// A segment of a [`ShmRegion`]. The region stays mapped as long as any of its slices is alive.
+/// Slices handed out from the same region by a pool should not overlap.
+#[derive(Debug, Clone)]
+pub struct BufferSlice {
+    offset: usize,
+    len: usize,
+    align: usize,
+    region: Arc<ShmRegion>,
+}
+
+impl AsHandle for BufferSlice {
+    /// The handle of the region in the high 16 bits, and the index of the slice in it in the
+    /// low 16 bits, for the slices of the same length.
+    fn as_handle(&self) -> Handle {
+        let high = self.region.as_handle().0;
+        let low = self.offset / self.len;
+        assert!(high < (1 << 16), "Please consider reduce the number of underlying storage or widen the Handle type to 64-bit");
+        assert!(low < (1 << 16), "Please consider reduce the number of recv buffers inside a slab or widen the Handle type to 64-bit");
+        Handle(((high * (1 << 16)) as u32 + low as u32) as u64)
+    }
+}
+
+impl BufferSlice {
+    pub fn new(region: Arc<ShmRegion>, offset: usize, len: usize, align: usize) -> Self {
+        assert!(
+            offset + len <= region.len(),
+            "slice {offset}+{len} out of a region of {}",
+            region.len()
+        );
+        BufferSlice {
+            offset,
+            len,
+            align,
+            region,
+        }
+    }
+
+    /// The address of the slice in the backend.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.region.addr() + self.offset
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// The offset of the slice in its region.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    #[inline]
+    pub fn region(&self) -> &Arc<ShmRegion> {
+        &self.region
+    }
+
+    /// The application's view of the slice, once it has mapped the region.
+    pub fn app_view(&self) -> Option<RegionView> {
+        self.region.app_view().map(|view| RegionView {
+            ptr: view.ptr + self.offset,
+            len: self.len,
+            align: self.align,
+        })
+    }
+
+    #[inline]
+    pub fn registration(&self) -> Option<Registration> {
+        self.region.registration()
+    }
+}
+
+/// The backend and user applications are forced to mmap the shared memory to the same location.
+/// This single-address-space approach avoids the problem of invalid pointers on shared memory.
+///
+/// `AddressMediator` is used to find an unused address in both address space. In this prototype,
+/// since 48bit virtual address space is embrassingly large, we just take the address starting from
+/// 0x600000000000 and bump it on each allocation.
+///
+/// Similar to memory allocation, it takes an `Layout` as input and returns an address that follows
+/// the alignment requirement.
+pub struct AddressMediator {
+    current: spin::Mutex<usize>,
+}
+
+impl Default for AddressMediator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AddressMediator {
+    const STARTING_ADDRESS: usize = 0x600000000000;
+
+    pub fn new() -> Self {
+        Self {
+            current: spin::Mutex::new(Self::STARTING_ADDRESS),
+        }
+    }
+
+    pub fn allocate(&self, layout: Layout) -> usize {
+        let mut current = self.current.lock();
+        let next = current.next_multiple_of(layout.align());
+        *current = next + layout.size();
+        next
+    }
+}
+
+#[cfg(feature = "sanitize")]
+mod heap {
+    use std::alloc::{self, Layout};
+    use std::io;
+    use std::ops::{Deref, DerefMut};
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A zeroed heap allocation in place of a mapping.
+    #[derive(Debug)]
+    pub(super) struct HeapRegion {
+        ptr: NonNull<u8>,
+        layout: Layout,
+        id: usize,
+    }
+
+    // SAFETY: the allocation is owned by the region, as the mapping would be
+    unsafe impl Send for HeapRegion {}
+    unsafe impl Sync for HeapRegion {}
+
+    impl HeapRegion {
+        pub(super) fn new(layout: Layout) -> io::Result<Self> {
+            static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+            if layout.size() == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty region"));
+            }
+            // SAFETY: the layout is not empty
+            let ptr = unsafe { alloc::alloc_zeroed(layout) };
+            let ptr =
+                NonNull::new(ptr).ok_or_else(|| io::Error::from(io::ErrorKind::OutOfMemory))?;
+            Ok(HeapRegion {
+                ptr,
+                layout,
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            })
+        }
+
+        /// Tells the regions apart, as the fd of the memfd does.
+        pub(super) fn id(&self) -> usize {
+            self.id
+        }
+
+        pub(super) fn as_ptr(&self) -> *const u8 {
+            self.ptr.as_ptr()
+        }
+    }
+
+    impl Deref for HeapRegion {
+        type Target = [u8];
+        fn deref(&self) -> &Self::Target {
+            // SAFETY: the allocation is of `layout.size()` bytes, initialized to zeros
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+        }
+    }
+
+    impl DerefMut for HeapRegion {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            // SAFETY: as in `deref`, and the region is borrowed mutably
+            unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+        }
+    }
+
+    impl Drop for HeapRegion {
+        fn drop(&mut self) {
+            // SAFETY: allocated with the same layout in `new`
+            unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+        }
+    }
+}
+
+pub fn page_size() -> usize {
+    use nix::unistd::{sysconf, SysconfVar};
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    match PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            let page_size = sysconf(SysconfVar::PAGE_SIZE)
+                .ok()
+                .flatten()
+                .map_or(4096, |n| n as usize);
+
+            PAGE_SIZE.store(page_size, Ordering::Relaxed);
+
+            page_size
+        }
+        page_size => page_size,
+    }
+}
+
+#[cfg(all(test, feature = "sanitize"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_region_slices() {
+        let layout = Layout::from_size_align(4 * 4096, 4096).unwrap();
+        let mut region = ShmRegion::new(layout, &AddressMediator::new()).unwrap();
+        assert_eq!(region.len(), layout.size());
+        assert!(region.iter().all(|&b| b == 0));
+        region[4096] = 42;
+
+        let view = region.simulated_app_view();
+        assert_eq!(view.ptr, region.addr() + SIMULATED_APP_OFFSET);
+
+        let region = Arc::new(region);
+        let slice = BufferSlice::new(Arc::clone(&region), 4096, 4096, 4096);
+        assert_eq!(slice.addr(), region.addr() + 4096);
+        assert_eq!(slice.app_view().unwrap().ptr, view.ptr + 4096);
+        assert_eq!(region[slice.offset()], 42);
+    }
+}
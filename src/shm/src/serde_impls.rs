@@ -0,0 +1,109 @@
+//! Implementations of [`serde`] traits for the shared memory collections.
+//!
+//! They follow what serde provides for their counterparts in [`std`]. Deserialized values are
+//! allocated with the default allocator of `A`.
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::alloc::ShmAllocator;
+use crate::boxed::Box;
+use crate::string::String;
+use crate::vec::Vec;
+
+impl<T: Serialize, A: ShmAllocator> Serialize for Vec<T, A> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T, A> Deserialize<'de> for Vec<T, A>
+where
+    T: Deserialize<'de>,
+    A: ShmAllocator + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VecVisitor<T, A>(PhantomData<(T, A)>);
+
+        impl<'de, T, A> Visitor<'de> for VecVisitor<T, A>
+        where
+            T: Deserialize<'de>,
+            A: ShmAllocator + Default,
+        {
+            type Value = Vec<T, A>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a sequence")
+            }
+
+            fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+                // do not trust the size hint more than serde does
+                let capacity = seq.size_hint().unwrap_or(0).min(4096);
+                let mut values = Vec::with_capacity(capacity);
+                while let Some(value) = seq.next_element()? {
+                    values.push(value);
+                }
+                Ok(values)
+            }
+
+            // bytes are commonly serialized this way, accept them for Vec<u8>
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                let mut values = Vec::with_capacity(v.len());
+                for &b in v {
+                    values.push(T::deserialize(serde::de::value::U8Deserializer::new(b))?);
+                }
+                Ok(values)
+            }
+        }
+
+        deserializer.deserialize_seq(VecVisitor(PhantomData))
+    }
+}
+
+impl<A: ShmAllocator> Serialize for String<A> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de, A: ShmAllocator + Default> Deserialize<'de> for String<A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StringVisitor<A>(PhantomData<A>);
+
+        impl<'de, A: ShmAllocator + Default> Visitor<'de> for StringVisitor<A> {
+            type Value = String<A>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(String::from(v))
+            }
+        }
+
+        deserializer.deserialize_str(StringVisitor(PhantomData))
+    }
+}
+
+impl<T: Serialize, A: ShmAllocator> Serialize for Box<T, A> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+impl<'de, T, A> Deserialize<'de> for Box<T, A>
+where
+    T: Deserialize<'de>,
+    A: ShmAllocator + Default,
+{
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Box::new)
+    }
+}
@@ -22,6 +22,11 @@ impl<P> PageBuffer<P> {
             used: LinkedList::new(),
         }
     }
+
+    fn clear(&mut self) {
+        self.empty.clear();
+        self.used.clear();
+    }
 }
 
 pub struct GlobalPagePool<'a> {
@@ -39,6 +44,14 @@ impl<'a> GlobalPagePool<'a> {
         }
     }
 
+    /// Forgets all the pages in the pool without releasing them, e.g., when the memory backing
+    /// them can no longer be used.
+    pub fn clear(&self) {
+        self.small_pages.lock().clear();
+        self.large_pages.lock().clear();
+        self.huge_pages.lock().clear();
+    }
+
     pub fn acquire_small_page(&self) -> Option<&'a mut ObjectPage<'a>> {
         let mut guard = self.small_pages.lock();
         let buf = guard.deref_mut();
@@ -84,7 +97,7 @@ impl<'a> GlobalPagePool<'a> {
             .map(|page| unsafe { &mut *page.as_ptr() })
     }
 
-    pub(crate) fn recycle_small_pages<I: IntoIterator<Item = &'a mut ObjectPage<'a>>>(
+    pub fn recycle_small_pages<I: IntoIterator<Item = &'a mut ObjectPage<'a>>>(
         &self,
         pages: I,
         obj_per_page: Option<usize>,
@@ -107,7 +120,7 @@ impl<'a> GlobalPagePool<'a> {
         }
     }
 
-    pub(crate) fn recycle_large_pages<I: IntoIterator<Item = &'a mut LargeObjectPage<'a>>>(
+    pub fn recycle_large_pages<I: IntoIterator<Item = &'a mut LargeObjectPage<'a>>>(
         &self,
         pages: I,
         obj_per_page: Option<usize>,
@@ -130,7 +143,7 @@ impl<'a> GlobalPagePool<'a> {
         }
     }
 
-    pub(crate) fn recycle_huge_pages<I: IntoIterator<Item = &'a mut HugeObjectPage<'a>>>(
+    pub fn recycle_huge_pages<I: IntoIterator<Item = &'a mut HugeObjectPage<'a>>>(
         &self,
         pages: I,
         obj_per_page: Option<usize>,
@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use phoenix_api::buf::Range;
+use phoenix_api::net::WcOpcode;
+use phoenix_api::Handle;
+
+use crate::{Completion, Config, Error, Event, Transport};
+
+/// How long a single poll of the transport blocks.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A transport under test, with the connections it accepted and the completions it reported
+/// that a scenario has not taken yet.
+pub struct Harness<T: Transport> {
+    // NOTE: declared before the buffers, so the transport and the work posted on it are dropped
+    // before the buffers the work points to
+    transport: T,
+    config: Config,
+    events: Vec<Event>,
+    accepted: VecDeque<Handle>,
+    completions: VecDeque<Completion>,
+    buffers: Vec<Box<[u8]>>,
+}
+
+fn transport_err<E: std::fmt::Display>(e: E) -> Error {
+    Error::Transport(e.to_string())
+}
+
+impl<T: Transport> Harness<T> {
+    pub fn new(transport: T, config: Config) -> Self {
+        Harness {
+            transport,
+            config,
+            events: Vec::new(),
+            accepted: VecDeque::new(),
+            completions: VecDeque::new(),
+            buffers: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    #[inline]
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Allocates a buffer of `len` bytes filled with `fill`, which lives as long as the harness.
+    /// Returns its index.
+    pub fn alloc(&mut self, len: usize, fill: u8) -> usize {
+        self.buffers.push(vec![fill; len].into_boxed_slice());
+        self.buffers.len() - 1
+    }
+
+    pub fn range(&mut self, buf: usize) -> Range {
+        let buf = &mut self.buffers[buf];
+        Range {
+            offset: buf.as_mut_ptr() as u64,
+            len: buf.len() as u64,
+        }
+    }
+
+    /// The bytes of a buffer, only to be read once the work posted on it has completed.
+    pub fn bytes(&self, buf: usize) -> &[u8] {
+        &self.buffers[buf]
+    }
+
+    /// Binds a listener on the address of the config. Returns it with the address it is bound
+    /// to.
+    pub fn bind(&mut self) -> Result<(Handle, SocketAddr), Error> {
+        let listener = self
+            .transport
+            .bind(&self.config.addr)
+            .map_err(transport_err)?;
+        let addr = self.transport.local_addr(listener).map_err(transport_err)?;
+        Ok((listener, addr))
+    }
+
+    pub fn connect(&mut self, addr: &SocketAddr) -> Result<Handle, Error> {
+        self.transport.connect(addr).map_err(transport_err)
+    }
+
+    pub fn close(&mut self, handle: Handle) -> Result<(), Error> {
+        self.transport.close(handle).map_err(transport_err)
+    }
+
+    pub fn post_send(
+        &mut self,
+        conn: Handle,
+        wr_id: u64,
+        buf: usize,
+        imm: u32,
+    ) -> Result<(), Error> {
+        let range = self.range(buf);
+        // SAFETY: the buffer lives as long as the harness, which drops the transport first
+        unsafe { self.transport.post_send(conn, wr_id, range, imm) }.map_err(transport_err)
+    }
+
+    pub fn post_recv(&mut self, conn: Handle, wr_id: u64, buf: usize) -> Result<(), Error> {
+        let range = self.range(buf);
+        // SAFETY: the buffer lives as long as the harness, which drops the transport first
+        unsafe { self.transport.post_recv(conn, wr_id, range) }.map_err(transport_err)
+    }
+
+    /// Polls the transport once, and keeps what it reports.
+    pub fn poll_once(&mut self) -> Result<(), Error> {
+        self.transport
+            .poll(POLL_INTERVAL, &mut self.events)
+            .map_err(transport_err)?;
+        for event in self.events.drain(..) {
+            match event {
+                Event::Accepted(conn) => self.accepted.push_back(conn),
+                Event::Completion(wc) => self.completions.push_back(wc),
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls the transport until `f` returns something, or the timeout of the config expires.
+    pub fn wait_for<R, F>(&mut self, what: &str, mut f: F) -> Result<R, Error>
+    where
+        F: FnMut(&mut Self) -> Option<R>,
+    {
+        let deadline = Instant::now() + self.config.timeout;
+        loop {
+            if let Some(r) = f(self) {
+                return Ok(r);
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(what.to_string()));
+            }
+            self.poll_once()?;
+        }
+    }
+
+    /// Waits for the next connection accepted by any listener.
+    pub fn accept(&mut self) -> Result<Handle, Error> {
+        self.wait_for("a connection to be accepted", |h| h.accepted.pop_front())
+    }
+
+    /// Takes the connections accepted so far.
+    pub fn take_accepted(&mut self) -> Vec<Handle> {
+        self.accepted.drain(..).collect()
+    }
+
+    /// Waits for the next completion of `opcode` on `conn`, in the order they are reported.
+    pub fn next_completion(&mut self, conn: Handle, opcode: WcOpcode) -> Result<Completion, Error> {
+        let what = format!("a completion of {:?} on {:?}", opcode, conn);
+        self.wait_for(&what, |h| {
+            let pos = h
+                .completions
+                .iter()
+                .position(|wc| wc.conn == conn && wc.opcode == opcode)?;
+            h.completions.remove(pos)
+        })
+    }
+}
@@ -0,0 +1,239 @@
+//! The conformance suite of the transports.
+//!
+//! A transport plugin, e.g., TCP or RDMA, implements [`Transport`] over its operations, and runs
+//! the suite over the loopback device with [`run_suite`] or [`assert_conforms`], usually from a
+//! test of the plugin:
+//!
+//! ```ignore
+//! #[test]
+//! fn conforms() {
+//!     transport_conformance::assert_conforms(MyTransport::new, &Config::default());
+//! }
+//! ```
+//!
+//! Each scenario starts from a fresh transport, both ends of its connections are on the same
+//! transport, and everything is driven from the calling thread by [`Transport::poll`]. The
+//! scenarios cover:
+//!
+//! - the lifecycle of the listeners and the connections: bind, connect, accept, close, and bind
+//!   again on the same address;
+//! - a message far larger than the socket buffers, which is split and reassembled;
+//! - a connection closed while a message is in flight, after which the peer gets a completion
+//!   for each of its receives instead of waiting forever;
+//! - a sender far ahead of its receiver, after which every message arrives intact and in order;
+//! - running out of file descriptors, which surfaces as an error rather than a panic, after
+//!   which the transport accepts and carries new connections again.
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use phoenix_api::buf::Range;
+use phoenix_api::net::{WcOpcode, WcStatus};
+use phoenix_api::Handle;
+
+mod harness;
+mod scenarios;
+
+pub use harness::Harness;
+
+/// The operations of a transport exercised by the suite.
+pub trait Transport {
+    type Error: fmt::Display;
+
+    /// Listens on `addr`, and returns the handle of the listener.
+    fn bind(&mut self, addr: &SocketAddr) -> Result<Handle, Self::Error>;
+
+    /// The address the listener is bound to, e.g., to learn the port of `127.0.0.1:0`.
+    fn local_addr(&self, listener: Handle) -> Result<SocketAddr, Self::Error>;
+
+    /// Connects to `addr`, and returns the handle of the connection. The connection may still
+    /// be establishing when it returns, the work posted on it is carried once it is up.
+    fn connect(&mut self, addr: &SocketAddr) -> Result<Handle, Self::Error>;
+
+    /// Closes a listener or a connection, and drops the work posted on it.
+    fn close(&mut self, handle: Handle) -> Result<(), Self::Error>;
+
+    /// Sends the bytes of `buf` on `conn` with the immediate value `imm`.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must stay valid until its completion, or until `conn` is closed.
+    unsafe fn post_send(
+        &mut self,
+        conn: Handle,
+        wr_id: u64,
+        buf: Range,
+        imm: u32,
+    ) -> Result<(), Self::Error>;
+
+    /// Receives the next message on `conn` in `buf`.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must stay valid and not be accessed until its completion, or until `conn` is
+    /// closed.
+    unsafe fn post_recv(&mut self, conn: Handle, wr_id: u64, buf: Range)
+        -> Result<(), Self::Error>;
+
+    /// Makes progress for up to `timeout`, and appends what happened to `events`.
+    fn poll(&mut self, timeout: Duration, events: &mut Vec<Event>) -> Result<(), Self::Error>;
+}
+
+/// What a [`Transport::poll`] reports.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    /// A connection is accepted by a listener.
+    Accepted(Handle),
+    /// A work request is completed.
+    Completion(Completion),
+}
+
+/// The completion of a send or a receive.
+#[derive(Debug, Clone, Copy)]
+pub struct Completion {
+    pub conn: Handle,
+    pub wr_id: u64,
+    pub opcode: WcOpcode,
+    pub status: WcStatus,
+    /// The bytes received, for a receive.
+    pub byte_len: usize,
+    /// The immediate value, for a receive.
+    pub imm: u32,
+}
+
+/// The knobs of the suite.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The address to listen on, a port of 0 picks any free port.
+    pub addr: SocketAddr,
+    /// How long to wait for a connection or a completion before failing a scenario.
+    pub timeout: Duration,
+    /// The number of connections opened at once by the lifecycle scenario.
+    pub connections: usize,
+    /// The size of the message of the large message scenario.
+    pub large_message_bytes: usize,
+    /// The number and the size of the messages sent ahead by the flow control scenario.
+    pub pressure_messages: usize,
+    pub pressure_message_bytes: usize,
+    /// Whether to run the scenario of the file descriptor exhaustion. It lowers the limit of the
+    /// open files of the whole process for its duration, so only enable it where nothing else
+    /// runs in the process at the same time.
+    pub fd_exhaustion: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            timeout: Duration::from_secs(10),
+            connections: 8,
+            large_message_bytes: 16 * 1024 * 1024,
+            pressure_messages: 256,
+            pressure_message_bytes: 64 * 1024,
+            fd_exhaustion: true,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("transport error: {0}")]
+    Transport(String),
+    #[error("timed out waiting for {0}")]
+    Timeout(String),
+    #[error("{0}")]
+    Violation(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A scenario of the suite, run on a fresh transport.
+pub struct Scenario<T: Transport> {
+    pub name: &'static str,
+    pub run: fn(&mut Harness<T>) -> Result<(), Error>,
+}
+
+/// The scenarios enabled by `config`, in the order they run.
+pub fn scenarios<T: Transport>(config: &Config) -> Vec<Scenario<T>> {
+    let mut scenarios = vec![
+        Scenario {
+            name: "connect_bind_lifecycle",
+            run: scenarios::connect_bind_lifecycle,
+        },
+        Scenario {
+            name: "large_message",
+            run: scenarios::large_message,
+        },
+        Scenario {
+            name: "disconnect_inflight",
+            run: scenarios::disconnect_inflight,
+        },
+        Scenario {
+            name: "flow_control_pressure",
+            run: scenarios::flow_control_pressure,
+        },
+    ];
+    if config.fd_exhaustion {
+        scenarios.push(Scenario {
+            name: "fd_exhaustion",
+            run: scenarios::fd_exhaustion,
+        });
+    }
+    scenarios
+}
+
+/// The outcome of each scenario.
+#[derive(Debug)]
+pub struct Report {
+    pub results: Vec<(&'static str, Result<(), Error>)>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, r)| r.is_ok())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "{}: ok", name)?,
+                Err(e) => writeln!(f, "{}: FAILED, {}", name, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every scenario, each on a transport from `new_transport`.
+pub fn run_suite<T, F>(mut new_transport: F, config: &Config) -> Report
+where
+    T: Transport,
+    F: FnMut() -> T,
+{
+    let results = scenarios(config)
+        .into_iter()
+        .map(|scenario| {
+            let mut harness = Harness::new(new_transport(), config.clone());
+            (scenario.name, (scenario.run)(&mut harness))
+        })
+        .collect();
+    Report { results }
+}
+
+/// Runs every scenario, and panics with the report if any of them fails.
+pub fn assert_conforms<T, F>(new_transport: F, config: &Config)
+where
+    T: Transport,
+    F: FnMut() -> T,
+{
+    let report = run_suite(new_transport, config);
+    assert!(
+        report.passed(),
+        "the transport does not conform:\n{}",
+        report
+    );
+}
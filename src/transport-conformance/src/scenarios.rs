@@ -0,0 +1,358 @@
+use phoenix_api::net::{WcOpcode, WcStatus};
+use phoenix_api::Handle;
+
+use crate::{Error, Harness, Transport};
+
+const SMALL_MESSAGE_BYTES: usize = 64;
+
+/// The byte the `i`-th message is filled with.
+#[inline]
+fn pattern(i: usize) -> u8 {
+    (i % 251) as u8 + 1
+}
+
+fn check_recv<T: Transport>(
+    h: &Harness<T>,
+    buf: usize,
+    wc: &crate::Completion,
+    len: usize,
+    fill: u8,
+) -> Result<(), Error> {
+    if wc.status != WcStatus::Success {
+        return Err(Error::Violation(format!("receive failed: {:?}", wc)));
+    }
+    if wc.byte_len != len {
+        return Err(Error::Violation(format!(
+            "received {} bytes, expected {}",
+            wc.byte_len, len
+        )));
+    }
+    if let Some(pos) = h.bytes(buf)[..len].iter().position(|&b| b != fill) {
+        return Err(Error::Violation(format!(
+            "received byte {} is {:#x}, expected {:#x}",
+            pos,
+            h.bytes(buf)[pos],
+            fill
+        )));
+    }
+    Ok(())
+}
+
+fn check_send(wc: &crate::Completion) -> Result<(), Error> {
+    if wc.status != WcStatus::Success {
+        return Err(Error::Violation(format!("send failed: {:?}", wc)));
+    }
+    Ok(())
+}
+
+/// Connects to `addr` and waits for the other end to be accepted.
+fn connect_pair<T: Transport>(
+    h: &mut Harness<T>,
+    addr: &std::net::SocketAddr,
+) -> Result<(Handle, Handle), Error> {
+    let client = h.connect(addr)?;
+    let server = h.accept()?;
+    Ok((client, server))
+}
+
+/// Sends a small message from `from` to `to` and back.
+fn ping_pong<T: Transport>(h: &mut Harness<T>, from: Handle, to: Handle) -> Result<(), Error> {
+    let ping = h.alloc(SMALL_MESSAGE_BYTES, 0);
+    let pong = h.alloc(SMALL_MESSAGE_BYTES, 0);
+    h.post_recv(to, 0, ping)?;
+    h.post_recv(from, 0, pong)?;
+
+    let msg = h.alloc(SMALL_MESSAGE_BYTES, pattern(1));
+    h.post_send(from, 0, msg, 1)?;
+    let wc = h.next_completion(to, WcOpcode::Recv)?;
+    check_recv(h, ping, &wc, SMALL_MESSAGE_BYTES, pattern(1))?;
+
+    let msg = h.alloc(SMALL_MESSAGE_BYTES, pattern(2));
+    h.post_send(to, 0, msg, 2)?;
+    let wc = h.next_completion(from, WcOpcode::Recv)?;
+    check_recv(h, pong, &wc, SMALL_MESSAGE_BYTES, pattern(2))?;
+
+    check_send(&h.next_completion(from, WcOpcode::Send)?)?;
+    check_send(&h.next_completion(to, WcOpcode::Send)?)
+}
+
+/// Opens several connections at once, checks each carries messages both ways with the
+/// immediate values, closes everything, and binds the same address again.
+pub(crate) fn connect_bind_lifecycle<T: Transport>(h: &mut Harness<T>) -> Result<(), Error> {
+    let (listener, addr) = h.bind()?;
+    let n = h.config().connections;
+
+    let clients = (0..n)
+        .map(|_| h.connect(&addr))
+        .collect::<Result<Vec<_>, _>>()?;
+    let servers = (0..n).map(|_| h.accept()).collect::<Result<Vec<_>, _>>()?;
+
+    // the connections are accepted in any order, each client tells its index in the immediate
+    // value, and the server echoes it back
+    let mut server_bufs = Vec::with_capacity(n);
+    for &server in &servers {
+        let buf = h.alloc(SMALL_MESSAGE_BYTES, 0);
+        h.post_recv(server, 0, buf)?;
+        server_bufs.push(buf);
+    }
+    let mut client_bufs = Vec::with_capacity(n);
+    for (i, &client) in clients.iter().enumerate() {
+        let buf = h.alloc(SMALL_MESSAGE_BYTES, 0);
+        h.post_recv(client, 0, buf)?;
+        client_bufs.push(buf);
+        let msg = h.alloc(SMALL_MESSAGE_BYTES, pattern(i));
+        h.post_send(client, 0, msg, i as u32)?;
+    }
+
+    let mut seen = vec![false; n];
+    for (&server, &buf) in servers.iter().zip(&server_bufs) {
+        let wc = h.next_completion(server, WcOpcode::Recv)?;
+        let i = wc.imm as usize;
+        if i >= n || seen[i] {
+            return Err(Error::Violation(format!(
+                "unexpected immediate value {} on {:?}",
+                wc.imm, server
+            )));
+        }
+        seen[i] = true;
+        check_recv(h, buf, &wc, SMALL_MESSAGE_BYTES, pattern(i))?;
+        let msg = h.alloc(SMALL_MESSAGE_BYTES, pattern(i));
+        h.post_send(server, 0, msg, wc.imm)?;
+    }
+
+    for (i, (&client, &buf)) in clients.iter().zip(&client_bufs).enumerate() {
+        let wc = h.next_completion(client, WcOpcode::Recv)?;
+        if wc.imm as usize != i {
+            return Err(Error::Violation(format!(
+                "connection {} got the reply of connection {}",
+                i, wc.imm
+            )));
+        }
+        check_recv(h, buf, &wc, SMALL_MESSAGE_BYTES, pattern(i))?;
+    }
+    for &conn in clients.iter().chain(&servers) {
+        check_send(&h.next_completion(conn, WcOpcode::Send)?)?;
+    }
+
+    for conn in clients.into_iter().chain(servers) {
+        h.close(conn)?;
+    }
+    h.close(listener)?;
+
+    // the address is free again once the listener is closed
+    let mut config_addr = h.config().addr;
+    config_addr.set_port(addr.port());
+    let listener = h
+        .transport_mut()
+        .bind(&config_addr)
+        .map_err(|e| Error::Violation(format!("bind again on {}: {}", config_addr, e)))?;
+    let (client, server) = connect_pair(h, &config_addr)?;
+    ping_pong(h, client, server)?;
+    h.close(client)?;
+    h.close(server)?;
+    h.close(listener)
+}
+
+/// Sends a message far larger than the socket buffers.
+pub(crate) fn large_message<T: Transport>(h: &mut Harness<T>) -> Result<(), Error> {
+    let (listener, addr) = h.bind()?;
+    let (client, server) = connect_pair(h, &addr)?;
+    let len = h.config().large_message_bytes;
+
+    let buf = h.alloc(len, 0);
+    h.post_recv(server, 0, buf)?;
+    let msg = h.alloc(len, pattern(7));
+    h.post_send(client, 0, msg, 7)?;
+
+    let wc = h.next_completion(server, WcOpcode::Recv)?;
+    check_recv(h, buf, &wc, len, pattern(7))?;
+    if wc.imm != 7 {
+        return Err(Error::Violation(format!(
+            "immediate value {}, expected 7",
+            wc.imm
+        )));
+    }
+    check_send(&h.next_completion(client, WcOpcode::Send)?)?;
+
+    // a smaller message after a large one lands in a larger buffer
+    let buf = h.alloc(len, 0);
+    h.post_recv(server, 1, buf)?;
+    let msg = h.alloc(SMALL_MESSAGE_BYTES, pattern(8));
+    h.post_send(client, 1, msg, 8)?;
+    let wc = h.next_completion(server, WcOpcode::Recv)?;
+    check_recv(h, buf, &wc, SMALL_MESSAGE_BYTES, pattern(8))?;
+
+    h.close(client)?;
+    h.close(server)?;
+    h.close(listener)
+}
+
+/// Closes a connection while a large message is in flight on it. The receives posted by the
+/// peer must all complete, the ones that succeed with the whole message, and the closed
+/// connection must refuse new work.
+pub(crate) fn disconnect_inflight<T: Transport>(h: &mut Harness<T>) -> Result<(), Error> {
+    const INFLIGHT: usize = 2;
+
+    let (listener, addr) = h.bind()?;
+    let (client, server) = connect_pair(h, &addr)?;
+    let len = h.config().large_message_bytes;
+
+    let mut bufs = Vec::with_capacity(INFLIGHT);
+    for i in 0..INFLIGHT {
+        let buf = h.alloc(len, 0);
+        h.post_recv(server, i as u64, buf)?;
+        bufs.push(buf);
+    }
+    for i in 0..INFLIGHT {
+        let msg = h.alloc(len, pattern(i));
+        h.post_send(client, i as u64, msg, i as u32)?;
+    }
+    // let a part of the messages go
+    h.poll_once()?;
+    h.close(client)?;
+
+    for (i, &buf) in bufs.iter().enumerate() {
+        let wc = h.next_completion(server, WcOpcode::Recv)?;
+        if wc.status == WcStatus::Success {
+            // a message is either received whole or not at all
+            check_recv(h, buf, &wc, len, pattern(i))?;
+        }
+    }
+
+    let msg = h.alloc(SMALL_MESSAGE_BYTES, 0);
+    if h.post_send(client, INFLIGHT as u64, msg, 0).is_ok() {
+        return Err(Error::Violation(
+            "a send is posted on a closed connection".to_string(),
+        ));
+    }
+
+    h.close(server)?;
+    h.close(listener)
+}
+
+/// Queues many more messages than the receiver takes at once, the receiver keeping a single
+/// receive posted. Every message must arrive whole and in order, and every send must complete.
+pub(crate) fn flow_control_pressure<T: Transport>(h: &mut Harness<T>) -> Result<(), Error> {
+    let (listener, addr) = h.bind()?;
+    let (client, server) = connect_pair(h, &addr)?;
+    let n = h.config().pressure_messages;
+    let len = h.config().pressure_message_bytes;
+
+    let buf = h.alloc(len, 0);
+    h.post_recv(server, 0, buf)?;
+    for i in 0..n {
+        let msg = h.alloc(len, pattern(i));
+        h.post_send(client, i as u64, msg, i as u32)?;
+    }
+
+    for i in 0..n {
+        let wc = h.next_completion(server, WcOpcode::Recv)?;
+        if wc.imm as usize != i {
+            return Err(Error::Violation(format!(
+                "message {} is received in place of message {}",
+                wc.imm, i
+            )));
+        }
+        check_recv(h, buf, &wc, len, pattern(i))?;
+        if i + 1 < n {
+            h.post_recv(server, i as u64 + 1, buf)?;
+        }
+    }
+
+    for i in 0..n {
+        let wc = h.next_completion(client, WcOpcode::Send)?;
+        check_send(&wc)?;
+        if wc.wr_id != i as u64 {
+            return Err(Error::Violation(format!(
+                "send {} completes in place of send {}",
+                wc.wr_id, i
+            )));
+        }
+    }
+
+    h.close(client)?;
+    h.close(server)?;
+    h.close(listener)
+}
+
+/// Lowers the soft limit of the open files of the process for as long as it lives.
+struct FileLimit {
+    saved: libc::rlimit,
+}
+
+impl FileLimit {
+    fn lower(extra: u64) -> Result<Self, Error> {
+        let open = std::fs::read_dir("/proc/self/fd")?.count() as u64;
+        let mut saved = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: getrlimit writes the limit to a valid rlimit
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut saved) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let lowered = libc::rlimit {
+            rlim_cur: (open + extra).min(saved.rlim_max),
+            rlim_max: saved.rlim_max,
+        };
+        // SAFETY: setrlimit reads a valid rlimit
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &lowered) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(FileLimit { saved })
+    }
+}
+
+impl Drop for FileLimit {
+    fn drop(&mut self) {
+        // SAFETY: setrlimit reads a valid rlimit
+        unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &self.saved) };
+    }
+}
+
+/// Opens connections until the process runs out of file descriptors, which must surface as an
+/// error. Once the connections are closed, the transport must bind, connect and carry messages
+/// again.
+pub(crate) fn fd_exhaustion<T: Transport>(h: &mut Harness<T>) -> Result<(), Error> {
+    const EXTRA_FILES: u64 = 32;
+    const MAX_CONNECTIONS: usize = 4096;
+
+    let (listener, addr) = h.bind()?;
+    let mut conns = Vec::new();
+    let mut exhausted = false;
+    {
+        let _limit = FileLimit::lower(EXTRA_FILES)?;
+        for _ in 0..MAX_CONNECTIONS {
+            match h.connect(&addr) {
+                Ok(conn) => conns.push(conn),
+                Err(_) => {
+                    exhausted = true;
+                    break;
+                }
+            }
+            h.poll_once()?;
+        }
+        h.poll_once()?;
+    }
+    if !exhausted {
+        return Err(Error::Violation(format!(
+            "no error after {} connections under a limit of {} more files",
+            conns.len(),
+            EXTRA_FILES
+        )));
+    }
+
+    conns.extend(h.take_accepted());
+    for conn in conns {
+        h.close(conn)?;
+    }
+    // connections left in the backlog of the old listener are not accepted anymore
+    h.close(listener)?;
+
+    let (listener, addr) = h.bind()?;
+    let (client, server) = connect_pair(h, &addr)?;
+    ping_pong(h, client, server)?;
+    h.close(client)?;
+    h.close(server)?;
+    h.close(listener)
+}